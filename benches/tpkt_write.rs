@@ -0,0 +1,124 @@
+//! Transport writes and time spent per TPKT frame
+//!
+//! Each write to a TCP socket is a syscall, and may be a segment
+//! on the wire, so a frame is expected to take a single write.
+//! The separate writes of the header and of the payload
+//! are measured as the reference
+//!
+//! Run with `cargo bench --bench tpkt_write`
+
+use rdp::core::tpkt::base::{Action, TpktHeader};
+use rdp::core::tpkt::client::TpktClient;
+use rdp::model::data::Message;
+use std::hint::black_box;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Frames written by each measure
+const ITERATIONS: usize = 10_000;
+
+/// Payload sizes, from an input event to a large channel chunk
+const PAYLOAD_SIZES: [usize; 3] = [16, 1_600, 16_000];
+
+/// Transport which drops its data and counts the writes
+#[derive(Default)]
+struct CountingTransport {
+    writes: usize,
+}
+
+impl AsyncRead for CountingTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for CountingTransport {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.writes += 1;
+        black_box(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn report(name: &str, size: usize, writes: usize, start: Instant) {
+    println!(
+        "{:<24} {:>6} bytes {:>4} writes/frame {:>8} ns/frame",
+        name,
+        size,
+        writes / ITERATIONS,
+        start.elapsed().as_nanos() / ITERATIONS as u128
+    );
+}
+
+/// Header then payload, straight to the transport
+async fn separate_writes(size: usize) {
+    let mut transport = CountingTransport::default();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let payload = vec![0u8; size];
+        let header = TpktHeader {
+            action: Action::FastPathActionX224 as u8,
+            flag: 0,
+            size: (payload.len() + 4) as u16,
+        };
+        header.write_to(&mut transport).await.unwrap();
+        payload.write_to(&mut transport).await.unwrap();
+    }
+    report("separate writes", size, transport.writes, start);
+}
+
+async fn tpkt_write(size: usize) {
+    let mut transport = CountingTransport::default();
+    let start = Instant::now();
+    {
+        let mut client = TpktClient::new(&mut transport);
+        for _ in 0..ITERATIONS {
+            client.write(vec![0u8; size]).await.unwrap();
+        }
+    }
+    report("TpktClient::write", size, transport.writes, start);
+}
+
+async fn fast_path_write(size: usize) {
+    let mut transport = CountingTransport::default();
+    let start = Instant::now();
+    {
+        let mut client = TpktClient::new(&mut transport);
+        for _ in 0..ITERATIONS {
+            client.write_fast_path(0, vec![0u8; size]).await.unwrap();
+        }
+    }
+    report("write_fast_path", size, transport.writes, start);
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        for size in PAYLOAD_SIZES {
+            separate_writes(size).await;
+            tpkt_write(size).await;
+            fast_path_write(size).await;
+        }
+    });
+}
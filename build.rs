@@ -0,0 +1,19 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Header of the C interface, see src/ffi.rs
+    // The copy of the source tree is only refreshed on demand
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-env-changed=RDP_UPDATE_HEADER");
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let bindings = cbindgen::generate(&crate_dir).expect("Unable to generate the C header");
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        bindings.write_to_file(format!("{}/rdp.h", out_dir));
+        if std::env::var_os("RDP_UPDATE_HEADER").is_some() {
+            bindings.write_to_file(format!("{}/include/rdp.h", crate_dir));
+        }
+    }
+}
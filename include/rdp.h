@@ -0,0 +1,201 @@
+#ifndef RDP_H
+#define RDP_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The call succeeded
+ */
+#define RDP_OK 0
+
+/**
+ * The call failed, the reason is given by rdp_last_error
+ */
+#define RDP_ERROR -1
+
+/**
+ * Kind of a polled event
+ */
+typedef enum RdpEventKind {
+  /**
+   * Pixels to draw on the desktop
+   */
+  RDP_EVENT_KIND_BITMAP = 0,
+  /**
+   * The user is logged on
+   */
+  RDP_EVENT_KIND_LOGGED_ON = 1,
+  /**
+   * New size of the desktop
+   */
+  RDP_EVENT_KIND_RESIZED = 2,
+  /**
+   * The server ends the session
+   */
+  RDP_EVENT_KIND_TERMINATED = 3,
+  /**
+   * Events not exposed to C
+   */
+  RDP_EVENT_KIND_OTHER = 4,
+} RdpEventKind;
+
+/**
+ * Settings of a connection, opaque to C
+ */
+typedef struct RdpConfig RdpConfig;
+
+/**
+ * Connected session, opaque to C
+ */
+typedef struct RdpSession RdpSession;
+
+/**
+ * Event filled by rdp_poll_event
+ */
+typedef struct RdpCEvent {
+  enum RdpEventKind kind;
+  /**
+   * Destination of a bitmap on the desktop, right and bottom included
+   */
+  uint16_t left;
+  uint16_t top;
+  uint16_t right;
+  uint16_t bottom;
+  /**
+   * Size of the bitmap pixels, or of the resized desktop
+   */
+  uint16_t width;
+  uint16_t height;
+  /**
+   * Bits per pixel of the bitmap pixels
+   */
+  uint16_t bpp;
+  /**
+   * RGBA bitmap pixels, valid until the next poll
+   */
+  const uint8_t *data;
+  size_t data_len;
+} RdpCEvent;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Message of the last error of this thread
+ * Null if no call failed, valid until the next failure
+ */
+const char *rdp_last_error(void);
+
+/**
+ * New configuration, freed by rdp_config_free
+ */
+struct RdpConfig *rdp_config_new(void);
+
+/**
+ * Free a configuration
+ *
+ * # Safety
+ * The config comes from rdp_config_new and is not used afterwards
+ */
+void rdp_config_free(struct RdpConfig *config);
+
+/**
+ * Host name or address with an optional port
+ *
+ * # Safety
+ * The config comes from rdp_config_new,
+ * the target is a null terminated string
+ */
+int rdp_config_set_target(struct RdpConfig *config, const char *target);
+
+/**
+ * Credentials of the logon
+ *
+ * # Safety
+ * The config comes from rdp_config_new,
+ * the strings are null terminated
+ */
+int rdp_config_set_credentials(struct RdpConfig *config,
+                               const char *domain,
+                               const char *username,
+                               const char *password);
+
+/**
+ * Size of the desktop
+ *
+ * # Safety
+ * The config comes from rdp_config_new
+ */
+int rdp_config_set_screen(struct RdpConfig *config, uint16_t width, uint16_t height);
+
+/**
+ * Run the whole connection sequence
+ * Null on failure, freed by rdp_disconnect
+ * The config can be freed or reused afterwards
+ *
+ * # Safety
+ * The config comes from rdp_config_new
+ */
+struct RdpSession *rdp_connect(const struct RdpConfig *config);
+
+/**
+ * Wait up to timeout_ms for the next event
+ * Returns 1 once the event is filled, 0 if none came in time
+ * and RDP_ERROR once the session is closed
+ *
+ * # Safety
+ * The session comes from rdp_connect,
+ * the event points to a writable RdpCEvent
+ */
+int rdp_poll_event(struct RdpSession *session, struct RdpCEvent *event, uint32_t timeout_ms);
+
+/**
+ * Press or release a key
+ *
+ * # Safety
+ * The session comes from rdp_connect
+ */
+int rdp_send_key(struct RdpSession *session, uint8_t scancode, bool extended, bool down);
+
+/**
+ * Move the mouse
+ *
+ * # Safety
+ * The session comes from rdp_connect
+ */
+int rdp_send_mouse_move(struct RdpSession *session, uint16_t x, uint16_t y);
+
+/**
+ * Press or release a mouse button
+ * 1 is the left button, 2 the right one, 3 the middle one,
+ * 4 and 5 the extended ones
+ *
+ * # Safety
+ * The session comes from rdp_connect
+ */
+int rdp_send_mouse_button(struct RdpSession *session,
+                          uint8_t button,
+                          bool down,
+                          uint16_t x,
+                          uint16_t y);
+
+/**
+ * Close the session and free it
+ *
+ * # Safety
+ * The session comes from rdp_connect and is not used afterwards
+ */
+void rdp_disconnect(struct RdpSession *session);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* RDP_H */
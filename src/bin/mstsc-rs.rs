@@ -14,7 +14,6 @@ use std::io::{Read, Write};
 use std::time::{Instant};
 use std::ptr;
 use std::mem;
-use std::mem::{size_of, forget};
 use rdp::core::client::{RdpClient, Connector};
 #[cfg(target_os = "windows")]
 use winapi::um::winsock2::{select, fd_set};
@@ -63,16 +62,6 @@ fn wait_for_fd(fd: usize) -> bool {
     }
 }
 
-/// Transmute is use to convert Vec<u8> -> Vec<u32>
-/// To accelerate data convert
-pub unsafe fn transmute_vec<S, T>(mut vec: Vec<S>) -> Vec<T> {
-    let ptr = vec.as_mut_ptr();
-    let capacity = vec.capacity() * size_of::<S>() / size_of::<T>();
-    let len = vec.len() * size_of::<S>() / size_of::<T>();
-    forget(vec);
-    Vec::from_raw_parts(ptr as *mut T, len, capacity)
-}
-
 /// Copy a bitmap event into the buffer
 /// This function use unsafe copy
 /// to accelerate data transfer
@@ -88,7 +77,8 @@ fn fast_bitmap_transfer(buffer: &mut Vec<u32>, width: usize, bitmap: BitmapEvent
     // Use some unsafe method to faster
     // data transfer between buffers
     unsafe {
-        let data_aligned :Vec<u32> = transmute_vec(data);
+        // minifb pixels are 0RGB
+        let data_aligned :Vec<u32> = data.chunks_exact(4).map(|rgba| u32::from_be_bytes([0, rgba[0], rgba[1], rgba[2]])).collect();
         for i in 0..(bitmap_dest_bottom - bitmap_dest_top + 1) {
             let dest_i = (i + bitmap_dest_top) * width + bitmap_dest_left;
             let src_i = i * bitmap_width;
@@ -0,0 +1,296 @@
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BufMut, BytesMut};
+use std::io::{Error, ErrorKind, Result};
+
+/// Size of an AUDIO_FORMAT without its extra data
+const AUDIO_FORMAT_SIZE: usize = 18;
+
+/// Tags of the audio formats
+/// MS-RDPEA 2.2.2.1.1 Audio Format (AUDIO_FORMAT)
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub const WAVE_FORMAT_ADPCM: u16 = 0x0002;
+pub const WAVE_FORMAT_ALAW: u16 = 0x0006;
+pub const WAVE_FORMAT_MULAW: u16 = 0x0007;
+pub const WAVE_FORMAT_DVI_ADPCM: u16 = 0x0011;
+pub const WAVE_FORMAT_GSM610: u16 = 0x0031;
+pub const WAVE_FORMAT_MPEGLAYER3: u16 = 0x0055;
+pub const WAVE_FORMAT_AAC_MS: u16 = 0xA106;
+pub const WAVE_FORMAT_OPUS: u16 = 0x704F;
+
+/// Step sizes of IMA ADPCM
+const IMA_STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+/// Step index changes of IMA ADPCM, by the magnitude of a nibble
+const IMA_INDEX_TABLE: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+
+/// Description of an audio format, as a WAVEFORMATEX
+/// MS-RDPEA 2.2.2.1.1 Audio Format (AUDIO_FORMAT)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub format_tag: u16,
+    pub channels: u16,
+    pub samples_per_sec: u32,
+    pub avg_bytes_per_sec: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+    /// Extra data of the format
+    pub data: Vec<u8>,
+}
+
+impl AudioFormat {
+    /// Uncompressed format
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::codec::audio::AudioFormat;
+    /// let format = AudioFormat::pcm(2, 44100, 16);
+    /// assert_eq!(format.avg_bytes_per_sec, 176400);
+    /// ```
+    pub fn pcm(channels: u16, samples_per_sec: u32, bits_per_sample: u16) -> Self {
+        let block_align = channels * bits_per_sample / 8;
+        AudioFormat {
+            format_tag: WAVE_FORMAT_PCM,
+            channels,
+            samples_per_sec,
+            avg_bytes_per_sec: samples_per_sec * block_align as u32,
+            block_align,
+            bits_per_sample,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn read(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, AUDIO_FORMAT_SIZE, "AUDIO: format")?;
+        let format_tag = buffer.get_u16_le();
+        let channels = buffer.get_u16_le();
+        let samples_per_sec = buffer.get_u32_le();
+        let avg_bytes_per_sec = buffer.get_u32_le();
+        let block_align = buffer.get_u16_le();
+        let bits_per_sample = buffer.get_u16_le();
+        let length = buffer.get_u16_le() as usize;
+        check_remaining(buffer, length, "AUDIO: format data")?;
+        Ok(AudioFormat {
+            format_tag,
+            channels,
+            samples_per_sec,
+            avg_bytes_per_sec,
+            block_align,
+            bits_per_sample,
+            data: buffer.split_to(length).to_vec(),
+        })
+    }
+
+    pub fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.put_u16_le(self.format_tag);
+        buffer.put_u16_le(self.channels);
+        buffer.put_u32_le(self.samples_per_sec);
+        buffer.put_u32_le(self.avg_bytes_per_sec);
+        buffer.put_u16_le(self.block_align);
+        buffer.put_u16_le(self.bits_per_sample);
+        buffer.put_u16_le(self.data.len() as u16);
+        buffer.extend_from_slice(&self.data);
+    }
+
+    pub fn length(&self) -> usize {
+        AUDIO_FORMAT_SIZE + self.data.len()
+    }
+}
+
+/// Decoder of an audio format to 16 bits PCM
+/// PCM, G.711 and IMA ADPCM are decoded by `builtin_decoder`,
+/// other decoders like AAC or Opus can be plugged in
+pub trait AudioDecoder {
+    /// Interleaved samples of the encoded data
+    fn decode(&mut self, format: &AudioFormat, data: &[u8]) -> Result<Vec<i16>>;
+}
+
+/// PCM with 8 or 16 bits per sample
+pub struct PcmDecoder;
+
+impl AudioDecoder for PcmDecoder {
+    fn decode(&mut self, format: &AudioFormat, data: &[u8]) -> Result<Vec<i16>> {
+        match format.bits_per_sample {
+            8 => Ok(data.iter().map(|s| (*s as i16 - 128) << 8).collect()),
+            16 => Ok(data
+                .chunks_exact(2)
+                .map(|s| i16::from_le_bytes([s[0], s[1]]))
+                .collect()),
+            bits => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("AUDIO: unsupported PCM with {} bits per sample", bits),
+            )),
+        }
+    }
+}
+
+/// G.711 A-law and mu-law
+pub struct G711Decoder;
+
+fn alaw_to_linear(value: u8) -> i16 {
+    let value = value ^ 0x55;
+    let exponent = (value >> 4) & 0x07;
+    let mantissa = (value & 0x0F) as i16;
+    let magnitude = if exponent == 0 {
+        (mantissa << 4) + 8
+    } else {
+        ((mantissa << 4) + 0x108) << (exponent - 1)
+    };
+    if value & 0x80 != 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
+}
+
+fn mulaw_to_linear(value: u8) -> i16 {
+    let value = !value;
+    let exponent = (value >> 4) & 0x07;
+    let mantissa = (value & 0x0F) as i16;
+    let magnitude = (((mantissa << 3) + 0x84) << exponent) - 0x84;
+    if value & 0x80 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+impl AudioDecoder for G711Decoder {
+    fn decode(&mut self, format: &AudioFormat, data: &[u8]) -> Result<Vec<i16>> {
+        let expand = if format.format_tag == WAVE_FORMAT_ALAW {
+            alaw_to_linear
+        } else {
+            mulaw_to_linear
+        };
+        Ok(data.iter().map(|s| expand(*s)).collect())
+    }
+}
+
+/// IMA ADPCM, each block starts with the state of each channel
+pub struct ImaAdpcmDecoder;
+
+/// State of a channel of IMA ADPCM
+struct ImaChannel {
+    predictor: i32,
+    index: i32,
+}
+
+impl ImaChannel {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let step = IMA_STEP_TABLE[self.index as usize];
+        let mut difference = step >> 3;
+        if nibble & 0x04 != 0 {
+            difference += step;
+        }
+        if nibble & 0x02 != 0 {
+            difference += step >> 1;
+        }
+        if nibble & 0x01 != 0 {
+            difference += step >> 2;
+        }
+        if nibble & 0x08 != 0 {
+            self.predictor -= difference;
+        } else {
+            self.predictor += difference;
+        }
+        self.predictor = self.predictor.clamp(i16::MIN as i32, i16::MAX as i32);
+        self.index = (self.index + IMA_INDEX_TABLE[(nibble & 0x07) as usize]).clamp(0, 88);
+        self.predictor as i16
+    }
+}
+
+impl AudioDecoder for ImaAdpcmDecoder {
+    fn decode(&mut self, format: &AudioFormat, data: &[u8]) -> Result<Vec<i16>> {
+        let channels = format.channels.max(1) as usize;
+        let block_align = format.block_align as usize;
+        if block_align < 4 * channels || (block_align - 4 * channels) % (4 * channels) != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "AUDIO: invalid IMA ADPCM block alignment",
+            ));
+        }
+        let mut samples = Vec::new();
+        for block in data.chunks_exact(block_align) {
+            let mut state: Vec<ImaChannel> = block
+                .chunks_exact(4)
+                .take(channels)
+                .map(|header| ImaChannel {
+                    predictor: i16::from_le_bytes([header[0], header[1]]) as i32,
+                    index: (header[2] as i32).min(88),
+                })
+                .collect();
+            samples.extend(state.iter().map(|channel| channel.predictor as i16));
+
+            // Each word of a channel holds 8 samples
+            let words = &block[4 * channels..];
+            for group in words.chunks_exact(4 * channels) {
+                let mut decoded = vec![0; 8 * channels];
+                for (channel, word) in group.chunks_exact(4).enumerate() {
+                    for (i, byte) in word.iter().enumerate() {
+                        decoded[(2 * i) * channels + channel] = state[channel].decode(byte & 0x0F);
+                        decoded[(2 * i + 1) * channels + channel] =
+                            state[channel].decode(byte >> 4);
+                    }
+                }
+                samples.extend_from_slice(&decoded);
+            }
+        }
+        Ok(samples)
+    }
+}
+
+/// Decoder of the formats supported without any other library
+pub fn builtin_decoder(format: &AudioFormat) -> Option<Box<dyn AudioDecoder + Send>> {
+    match (format.format_tag, format.bits_per_sample) {
+        (WAVE_FORMAT_PCM, 8) | (WAVE_FORMAT_PCM, 16) => Some(Box::new(PcmDecoder)),
+        (WAVE_FORMAT_ALAW, 8) | (WAVE_FORMAT_MULAW, 8) => Some(Box::new(G711Decoder)),
+        (WAVE_FORMAT_DVI_ADPCM, 4) => Some(Box::new(ImaAdpcmDecoder)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Formats are written and read back with their extra data
+    #[test]
+    fn test_audio_format() {
+        let mut format = AudioFormat::pcm(2, 22050, 16);
+        format.data = vec![1, 2];
+        let mut data = Vec::new();
+        format.write(&mut data);
+        assert_eq!(data.len(), format.length());
+        assert_eq!(
+            AudioFormat::read(&mut BytesMut::from(&data[..])).unwrap(),
+            format
+        );
+    }
+
+    /// G.711 silence and the first IMA ADPCM sample of a block
+    #[test]
+    fn test_builtin_decoders() {
+        let mut format = AudioFormat::pcm(1, 8000, 8);
+        format.format_tag = WAVE_FORMAT_MULAW;
+        let mut decoder = builtin_decoder(&format).unwrap();
+        assert_eq!(decoder.decode(&format, &[0xFF, 0x7F]).unwrap(), [0, 0]);
+        format.format_tag = WAVE_FORMAT_ALAW;
+        assert_eq!(decoder.decode(&format, &[0xD5]).unwrap(), [8]);
+
+        format.format_tag = WAVE_FORMAT_DVI_ADPCM;
+        format.bits_per_sample = 4;
+        format.block_align = 8;
+        let samples = builtin_decoder(&format)
+            .unwrap()
+            .decode(&format, &[0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])
+            .unwrap();
+        assert_eq!(samples.len(), 9);
+        assert_eq!(samples[0], 16);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::codec::mppc::MppcDecompressor;
+use crate::codec::xcrush::XcrushDecompressor;
+
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+
+/// Flags of compressed data, the compression type is in the low bits
+/// MS-RDPBCGR 2.2.8.1.1.1.2 Share Data Header (TS_SHAREDATAHEADER)
+pub const COMPRESSION_TYPE_MASK: u8 = 0x0F;
+pub const PACKET_COMPRESSED: u8 = 0x20;
+pub const PACKET_AT_FRONT: u8 = 0x40;
+pub const PACKET_FLUSHED: u8 = 0x80;
+
+/// Bulk compression algorithms
+/// The client announces the highest one it supports in the info packet
+/// MS-RDPBCGR 3.1.8 Bulk Data Compression
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum CompressionType {
+    /// RDP 4.0, MPPC with an 8 KB history
+    PacketComprType8k = 0x0,
+    /// RDP 5.0, MPPC with a 64 KB history
+    PacketComprType64k = 0x1,
+    /// RDP 6.0, NCrush isn't supported so the client never announces it
+    PacketComprTypeRdp6 = 0x2,
+    /// RDP 6.1, MPPC over long matches of a 2 MB history
+    /// The server may still use a lower level
+    PacketComprTypeRdp61 = 0x3,
+}
+
+/// Decompress the data sent by the server
+/// The same history is used for slow path and fast path data
+///
+/// # Example
+/// ```
+/// use rdp::codec::bulk::{BulkDecompressor, PACKET_COMPRESSED};
+/// let mut bulk = BulkDecompressor::default();
+/// // RDP 4.0 literals "ab"
+/// let data = bulk.decompress(&[0x61, 0x62], PACKET_COMPRESSED).unwrap();
+/// assert_eq!(data, b"ab");
+/// ```
+#[derive(Default)]
+pub struct BulkDecompressor {
+    mppc: MppcDecompressor,
+    xcrush: XcrushDecompressor,
+}
+
+impl BulkDecompressor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Data is returned as is when the compressed flag isn't set,
+    /// the history is still reset if requested
+    pub fn decompress(&mut self, data: &[u8], flags: u8) -> Result<Vec<u8>> {
+        let compression_type = flags & COMPRESSION_TYPE_MASK;
+        match CompressionType::try_from(compression_type) {
+            Ok(CompressionType::PacketComprType8k) | Ok(CompressionType::PacketComprType64k) => {
+                self.mppc.decompress(data, flags)
+            }
+            Ok(CompressionType::PacketComprTypeRdp61) if flags & PACKET_COMPRESSED != 0 => {
+                self.xcrush.decompress(data, flags)
+            }
+            Ok(CompressionType::PacketComprTypeRdp61) => Ok(data.to_vec()),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("BULK: unsupported compression type {}", compression_type),
+            )),
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use crate::codec::nsc::nsc_decompress;
+
+use std::io::{Error, ErrorKind, Result};
+
+/// Flags of the bitmap stream
+/// MS-RDPEGFX 2.2.4.1 CLEARCODEC_BITMAP_STREAM
+const CLEARCODEC_FLAG_GLYPH_INDEX: u8 = 0x01;
+const CLEARCODEC_FLAG_GLYPH_HIT: u8 = 0x02;
+const CLEARCODEC_FLAG_CACHE_RESET: u8 = 0x04;
+
+/// Sizes of the caches kept between bitmaps
+const GLYPH_CACHE_SIZE: usize = 4000;
+const VBAR_CACHE_SIZE: usize = 32768;
+const SHORT_VBAR_CACHE_SIZE: usize = 16384;
+
+/// Glyphs are small bitmaps only
+const MAX_GLYPH_PIXELS: usize = 1024;
+/// Bands are at most 52 pixels high
+const MAX_VBAR_HEIGHT: usize = 52;
+
+/// Subcodecs of the subcodec layer
+/// MS-RDPEGFX 2.2.4.1.1.3.1 CLEARCODEC_SUBCODEC
+const SUBCODEC_UNCOMPRESSED: u8 = 0;
+const SUBCODEC_NSCODEC: u8 = 1;
+const SUBCODEC_RLEX: u8 = 2;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Read side of a layer
+struct Source<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Source<'a> {
+    fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    fn read(&mut self, size: usize) -> Result<&'a [u8]> {
+        if self.input.len() < size {
+            return Err(invalid("CLEARCODEC: truncated stream"));
+        }
+        let (data, rest) = self.input.split_at(size);
+        self.input = rest;
+        Ok(data)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        let data = self.read(2)?;
+        Ok(u16::from_le_bytes([data[0], data[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let data = self.read(4)?;
+        Ok(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+    }
+
+    fn read_bgr(&mut self) -> Result<[u8; 3]> {
+        let data = self.read(3)?;
+        Ok([data[0], data[1], data[2]])
+    }
+
+    /// Run length on 1, 2 or 4 bytes
+    /// MS-RDPEGFX 2.2.4.1.1.1.1 CLEARCODEC_RGB_RUN_SEGMENT
+    fn read_run_length(&mut self) -> Result<usize> {
+        Ok(match self.read_u8()? {
+            0xFF => match self.read_u16()? {
+                0xFFFF => self.read_u32()? as usize,
+                length => length as usize,
+            },
+            length => length as usize,
+        })
+    }
+}
+
+/// BGRA image being decoded
+struct Surface {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl Surface {
+    fn put(&mut self, x: usize, y: usize, bgr: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let offset = (y * self.width + x) * 4;
+            self.data[offset..offset + 4].copy_from_slice(&[bgr[0], bgr[1], bgr[2], 0xFF]);
+        }
+    }
+}
+
+/// Cached glyph
+struct Glyph {
+    width: u16,
+    height: u16,
+    data: Vec<u8>,
+}
+
+/// Decoder of the ClearCodec
+/// MS-RDPEGFX 3.3.8.1 ClearCodec
+///
+/// The glyph and vertical bar caches are shared by
+/// all the bitmaps of a session, so a single decoder
+/// must be used for all of them
+///
+/// Pixels are BGRA, rows are top down
+pub struct ClearCodec {
+    glyphs: Vec<Option<Glyph>>,
+    vbars: Vec<Vec<[u8; 3]>>,
+    vbar_cursor: usize,
+    short_vbars: Vec<Vec<[u8; 3]>>,
+    short_vbar_cursor: usize,
+}
+
+impl Default for ClearCodec {
+    fn default() -> Self {
+        ClearCodec::new()
+    }
+}
+
+impl ClearCodec {
+    /// Decoder with empty caches
+    pub fn new() -> Self {
+        ClearCodec {
+            glyphs: (0..GLYPH_CACHE_SIZE).map(|_| None).collect(),
+            vbars: vec![Vec::new(); VBAR_CACHE_SIZE],
+            vbar_cursor: 0,
+            short_vbars: vec![Vec::new(); SHORT_VBAR_CACHE_SIZE],
+            short_vbar_cursor: 0,
+        }
+    }
+
+    /// Decompress a bitmap
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::codec::clearcodec::ClearCodec;
+    /// let mut codec = ClearCodec::new();
+    /// // Residual layer only, 2 red pixels
+    /// let data = codec.decompress(&[
+    ///     0x00, 0x00,
+    ///     0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ///     0x00, 0x00, 0xFF, 0x02
+    /// ], 2, 1).unwrap();
+    /// assert_eq!(data, vec![0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF]);
+    /// ```
+    pub fn decompress(&mut self, input: &[u8], width: u16, height: u16) -> Result<Vec<u8>> {
+        let mut source = Source { input };
+        let flags = source.read_u8()?;
+        // The sequence number is only checked by the server
+        source.read_u8()?;
+
+        if flags & CLEARCODEC_FLAG_CACHE_RESET != 0 {
+            self.vbar_cursor = 0;
+            self.short_vbar_cursor = 0;
+        }
+
+        let glyph_index = if flags & CLEARCODEC_FLAG_GLYPH_INDEX != 0 {
+            let index = source.read_u16()? as usize;
+            if index >= GLYPH_CACHE_SIZE {
+                return Err(invalid("CLEARCODEC: invalid glyph index"));
+            }
+            if width as usize * height as usize > MAX_GLYPH_PIXELS {
+                return Err(invalid("CLEARCODEC: glyph is too large"));
+            }
+            Some(index)
+        } else {
+            None
+        };
+
+        if flags & CLEARCODEC_FLAG_GLYPH_HIT != 0 {
+            let glyph = glyph_index
+                .and_then(|index| self.glyphs[index].as_ref())
+                .ok_or_else(|| invalid("CLEARCODEC: glyph is not cached"))?;
+            if (glyph.width, glyph.height) != (width, height) {
+                return Err(invalid("CLEARCODEC: glyph size mismatch"));
+            }
+            return Ok(glyph.data.clone());
+        }
+
+        let mut surface = Surface {
+            width: width as usize,
+            height: height as usize,
+            data: vec![0; width as usize * height as usize * 4],
+        };
+
+        // MS-RDPEGFX 2.2.4.1.1 CLEARCODEC_COMPOSITE_PAYLOAD
+        let residual_size = source.read_u32()? as usize;
+        let bands_size = source.read_u32()? as usize;
+        let subcodec_size = source.read_u32()? as usize;
+        decode_residual(
+            &mut Source {
+                input: source.read(residual_size)?,
+            },
+            &mut surface,
+        )?;
+        self.decode_bands(
+            &mut Source {
+                input: source.read(bands_size)?,
+            },
+            &mut surface,
+        )?;
+        decode_subcodecs(
+            &mut Source {
+                input: source.read(subcodec_size)?,
+            },
+            &mut surface,
+        )?;
+
+        if let Some(index) = glyph_index {
+            self.glyphs[index] = Some(Glyph {
+                width,
+                height,
+                data: surface.data.clone(),
+            });
+        }
+        Ok(surface.data)
+    }
+
+    /// Bands of vertical bars over a background color
+    /// MS-RDPEGFX 2.2.4.1.1.2 CLEARCODEC_BANDS_DATA
+    fn decode_bands(&mut self, source: &mut Source, surface: &mut Surface) -> Result<()> {
+        while !source.is_empty() {
+            let x_start = source.read_u16()? as usize;
+            let x_end = source.read_u16()? as usize;
+            let y_start = source.read_u16()? as usize;
+            let y_end = source.read_u16()? as usize;
+            let background = source.read_bgr()?;
+            if x_end < x_start || y_end < y_start || y_end - y_start >= MAX_VBAR_HEIGHT {
+                return Err(invalid("CLEARCODEC: invalid band"));
+            }
+            let vbar_height = y_end - y_start + 1;
+
+            for x in x_start..=x_end {
+                let index = self.decode_vbar(source, background, vbar_height)?;
+                for (y, bgr) in self.vbars[index].iter().take(vbar_height).enumerate() {
+                    surface.put(x, y_start + y, *bgr);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Find or build a vertical bar, returns its index in the cache
+    /// MS-RDPEGFX 2.2.4.1.1.2.1.1 CLEARCODEC_VBAR
+    fn decode_vbar(
+        &mut self,
+        source: &mut Source,
+        background: [u8; 3],
+        vbar_height: usize,
+    ) -> Result<usize> {
+        let header = source.read_u16()?;
+        let (short_index, y_on) = match header & 0xC000 {
+            // VBAR_CACHE_HIT
+            0x8000 | 0xC000 => return Ok((header & 0x7FFF) as usize),
+            // SHORT_VBAR_CACHE_HIT
+            0x4000 => ((header & 0x3FFF) as usize, source.read_u8()? as usize),
+            // SHORT_VBAR_CACHE_MISS
+            _ => {
+                let y_on = (header & 0xFF) as usize;
+                let y_off = ((header >> 8) & 0x3F) as usize;
+                if y_off < y_on || y_off - y_on > MAX_VBAR_HEIGHT {
+                    return Err(invalid("CLEARCODEC: invalid short vertical bar"));
+                }
+                let pixels = (y_on..y_off)
+                    .map(|_| source.read_bgr())
+                    .collect::<Result<Vec<_>>>()?;
+                let index = self.short_vbar_cursor;
+                self.short_vbars[index] = pixels;
+                self.short_vbar_cursor = (index + 1) % SHORT_VBAR_CACHE_SIZE;
+                (index, y_on)
+            }
+        };
+
+        // The short bar is drawn over the background of the band
+        let short_vbar = &self.short_vbars[short_index];
+        let mut vbar = vec![background; vbar_height];
+        for (y, bgr) in short_vbar.iter().enumerate() {
+            if let Some(pixel) = vbar.get_mut(y_on + y) {
+                *pixel = *bgr;
+            }
+        }
+        let index = self.vbar_cursor;
+        self.vbars[index] = vbar;
+        self.vbar_cursor = (index + 1) % VBAR_CACHE_SIZE;
+        Ok(index)
+    }
+}
+
+/// Base layer, runs of colors over the whole bitmap
+/// MS-RDPEGFX 2.2.4.1.1.1 CLEARCODEC_RESIDUAL_DATA
+fn decode_residual(source: &mut Source, surface: &mut Surface) -> Result<()> {
+    if source.is_empty() {
+        return Ok(());
+    }
+    let pixel_count = surface.width * surface.height;
+    let mut index = 0;
+    while !source.is_empty() {
+        let bgr = source.read_bgr()?;
+        let run_length = source.read_run_length()?;
+        if index + run_length > pixel_count {
+            return Err(invalid("CLEARCODEC: residual run goes past the bitmap"));
+        }
+        for pixel in index..index + run_length {
+            surface.put(pixel % surface.width, pixel / surface.width, bgr);
+        }
+        index += run_length;
+    }
+    if index != pixel_count {
+        return Err(invalid(
+            "CLEARCODEC: residual layer doesn't cover the bitmap",
+        ));
+    }
+    Ok(())
+}
+
+/// Regions encoded by another codec
+/// MS-RDPEGFX 2.2.4.1.1.3 CLEARCODEC_SUBCODECS_DATA
+fn decode_subcodecs(source: &mut Source, surface: &mut Surface) -> Result<()> {
+    while !source.is_empty() {
+        let x_start = source.read_u16()? as usize;
+        let y_start = source.read_u16()? as usize;
+        let width = source.read_u16()?;
+        let height = source.read_u16()?;
+        let size = source.read_u32()? as usize;
+        let codec = source.read_u8()?;
+        let data = source.read(size)?;
+        if x_start + width as usize > surface.width || y_start + height as usize > surface.height {
+            return Err(invalid(
+                "CLEARCODEC: subcodec region is outside of the bitmap",
+            ));
+        }
+
+        let pixel_count = width as usize * height as usize;
+        let pixels: Vec<[u8; 3]> = match codec {
+            SUBCODEC_UNCOMPRESSED => {
+                if data.len() < pixel_count * 3 {
+                    return Err(invalid("CLEARCODEC: truncated uncompressed region"));
+                }
+                data.chunks_exact(3)
+                    .take(pixel_count)
+                    .map(|bgr| [bgr[0], bgr[1], bgr[2]])
+                    .collect()
+            }
+            SUBCODEC_NSCODEC => nsc_decompress(data, width, height)?
+                .chunks_exact(4)
+                .map(|bgra| [bgra[0], bgra[1], bgra[2]])
+                .collect(),
+            SUBCODEC_RLEX => decode_rlex(&mut Source { input: data }, pixel_count)?,
+            _ => return Err(invalid("CLEARCODEC: unknown subcodec")),
+        };
+
+        for (index, bgr) in pixels.into_iter().enumerate() {
+            let x = x_start + index % width as usize;
+            let y = y_start + index / width as usize;
+            surface.put(x, y, bgr);
+        }
+    }
+    Ok(())
+}
+
+/// Palette runs, each one is a run of a color
+/// followed by a suite of consecutive palette entries
+/// MS-RDPEGFX 2.2.4.1.1.3.1.1 CLEARCODEC_SUBCODEC_RLEX
+fn decode_rlex(source: &mut Source, pixel_count: usize) -> Result<Vec<[u8; 3]>> {
+    let palette_count = source.read_u8()? as usize;
+    if palette_count == 0 || palette_count > 127 {
+        return Err(invalid("CLEARCODEC: invalid RLEX palette"));
+    }
+    let palette = (0..palette_count)
+        .map(|_| source.read_bgr())
+        .collect::<Result<Vec<_>>>()?;
+    // Bits needed for the highest palette index
+    let index_bits = (usize::BITS - (palette_count - 1).leading_zeros()).max(1);
+    let index_mask = (1 << index_bits) - 1;
+
+    let mut pixels = Vec::with_capacity(pixel_count);
+    while !source.is_empty() {
+        let header = source.read_u8()? as usize;
+        let stop_index = header & index_mask;
+        let suite_depth = header >> index_bits;
+        let run_length = source.read_run_length()?;
+        if suite_depth > stop_index || stop_index >= palette_count {
+            return Err(invalid("CLEARCODEC: invalid RLEX segment"));
+        }
+        let start_index = stop_index - suite_depth;
+        if pixels.len() + run_length + suite_depth + 1 > pixel_count {
+            return Err(invalid("CLEARCODEC: RLEX segment goes past the region"));
+        }
+        pixels.resize(pixels.len() + run_length, palette[start_index]);
+        pixels.extend_from_slice(&palette[start_index..=stop_index]);
+    }
+    if pixels.len() != pixel_count {
+        return Err(invalid("CLEARCODEC: RLEX doesn't cover the region"));
+    }
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn composite(residual: &[u8], bands: &[u8], subcodecs: &[u8]) -> Vec<u8> {
+        let mut payload = vec![];
+        for layer in [residual, bands, subcodecs] {
+            payload.extend_from_slice(&(layer.len() as u32).to_le_bytes());
+        }
+        for layer in [residual, bands, subcodecs] {
+            payload.extend_from_slice(layer);
+        }
+        payload
+    }
+
+    /// A band of one new short bar, then a band using the cached bar
+    #[test]
+    fn test_clearcodec_bands() {
+        let mut codec = ClearCodec::new();
+        let bands = [
+            // x 0 to 0, y 0 to 2, gray background
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x80, 0x80, 0x80,
+            // Short bar miss, y from 1 to 2, one blue pixel
+            0x01, 0x02, 0xFF, 0x00, 0x00, // x 1 to 1, y 0 to 2, vertical bar cache hit
+            0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+        ];
+        let mut input = vec![0, 0];
+        input.extend(composite(&[], &bands, &[]));
+        let data = codec.decompress(&input, 2, 3).unwrap();
+        let column = |x: usize| -> Vec<&[u8]> {
+            (0..3)
+                .map(|y| &data[(y * 2 + x) * 4..(y * 2 + x) * 4 + 4])
+                .collect()
+        };
+        let gray = &[0x80, 0x80, 0x80, 0xFF][..];
+        let blue = &[0xFF, 0x00, 0x00, 0xFF][..];
+        assert_eq!(column(0), vec![gray, blue, gray]);
+        assert_eq!(column(1), vec![gray, blue, gray]);
+    }
+
+    /// RLEX run then suite, in a glyph reused by a later bitmap
+    #[test]
+    fn test_clearcodec_rlex_glyph() {
+        let mut codec = ClearCodec::new();
+        let rlex = [
+            // 3 colors
+            0x03, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01, 0x02, 0x02, 0x02,
+            // Run of 1 of color 0 then suite 0 to 2
+            0x0A, 0x01,
+        ];
+        let mut subcodec = vec![0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x01, 0x00];
+        subcodec.extend_from_slice(&(rlex.len() as u32).to_le_bytes());
+        subcodec.push(SUBCODEC_RLEX);
+        subcodec.extend_from_slice(&rlex);
+
+        // Glyph 7
+        let mut input = vec![CLEARCODEC_FLAG_GLYPH_INDEX, 0, 0x07, 0x00];
+        input.extend(composite(&[], &[], &subcodec));
+        let data = codec.decompress(&input, 4, 1).unwrap();
+        assert_eq!(
+            data,
+            vec![0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 1, 1, 1, 0xFF, 2, 2, 2, 0xFF]
+        );
+
+        let hit = [
+            CLEARCODEC_FLAG_GLYPH_INDEX | CLEARCODEC_FLAG_GLYPH_HIT,
+            1,
+            0x07,
+            0x00,
+        ];
+        assert_eq!(codec.decompress(&hit, 4, 1).unwrap(), data);
+        assert!(codec.decompress(&hit, 2, 2).is_err());
+    }
+
+    /// The residual layer must cover the bitmap exactly
+    #[test]
+    fn test_clearcodec_residual() {
+        let mut codec = ClearCodec::new();
+        let mut input = vec![0, 0];
+        input.extend(composite(&[0x01, 0x02, 0x03, 0xFF, 0x03, 0x00], &[], &[]));
+        let data = codec.decompress(&input, 3, 1).unwrap();
+        assert_eq!(&data[8..], &[0x01, 0x02, 0x03, 0xFF]);
+
+        let mut input = vec![0, 0];
+        input.extend(composite(&[0x01, 0x02, 0x03, 0x02], &[], &[]));
+        assert!(codec.decompress(&input, 3, 1).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+// Conversion of bitmap pixels to RGBA
+//
+// Each converter handles as many pixels as both the input
+// and the output can hold, the output is 4 bytes per pixel
+//
+// With the `simd` feature, SSE2 or NEON are used
+// when the CPU supports them
+
+/// Expand a 5 bits channel to 8 bits, rounded to the nearest
+#[inline]
+fn expand_5(value: u16) -> u8 {
+    ((value * 527 + 23) >> 6) as u8
+}
+
+/// Expand a 6 bits channel to 8 bits, rounded to the nearest
+#[inline]
+fn expand_6(value: u16) -> u8 {
+    ((value * 259 + 33) >> 6) as u8
+}
+
+/// 8 bpp palette indexes
+/// Indexes outside of the palette are black
+///
+/// # Example
+/// ```
+/// use rdp::codec::color::palette_to_rgba;
+/// let mut output = [0; 8];
+/// palette_to_rgba(&[1, 2], &[[0, 0, 0], [1, 2, 3]], &mut output);
+/// assert_eq!(output, [1, 2, 3, 0xFF, 0, 0, 0, 0xFF]);
+/// ```
+pub fn palette_to_rgba(input: &[u8], palette: &[[u8; 3]], output: &mut [u8]) {
+    for (index, rgba) in input.iter().zip(output.chunks_exact_mut(4)) {
+        let [r, g, b] = palette.get(*index as usize).copied().unwrap_or_default();
+        rgba.copy_from_slice(&[r, g, b, 0xFF]);
+    }
+}
+
+/// 15 bpp little endian RGB555
+///
+/// # Example
+/// ```
+/// use rdp::codec::color::rgb555_to_rgba;
+/// let mut output = [0; 4];
+/// rgb555_to_rgba(&[0x1F, 0x7C], &mut output);
+/// assert_eq!(output, [0xFF, 0, 0xFF, 0xFF]);
+/// ```
+pub fn rgb555_to_rgba(input: &[u8], output: &mut [u8]) {
+    let done = simd::rgb555_to_rgba(input, output);
+    rgb555_to_rgba_scalar(&input[done * 2..], &mut output[done * 4..]);
+}
+
+fn rgb555_to_rgba_scalar(input: &[u8], output: &mut [u8]) {
+    for (pixel, rgba) in input.chunks_exact(2).zip(output.chunks_exact_mut(4)) {
+        let value = u16::from_le_bytes([pixel[0], pixel[1]]);
+        rgba.copy_from_slice(&[
+            expand_5((value >> 10) & 0x1F),
+            expand_5((value >> 5) & 0x1F),
+            expand_5(value & 0x1F),
+            0xFF,
+        ]);
+    }
+}
+
+/// 16 bpp little endian RGB565
+///
+/// # Example
+/// ```
+/// use rdp::codec::color::rgb565_to_rgba;
+/// let mut output = [0; 4];
+/// rgb565_to_rgba(&[0xE0, 0x07], &mut output);
+/// assert_eq!(output, [0, 0xFF, 0, 0xFF]);
+/// ```
+pub fn rgb565_to_rgba(input: &[u8], output: &mut [u8]) {
+    let done = simd::rgb565_to_rgba(input, output);
+    rgb565_to_rgba_scalar(&input[done * 2..], &mut output[done * 4..]);
+}
+
+fn rgb565_to_rgba_scalar(input: &[u8], output: &mut [u8]) {
+    for (pixel, rgba) in input.chunks_exact(2).zip(output.chunks_exact_mut(4)) {
+        let value = u16::from_le_bytes([pixel[0], pixel[1]]);
+        rgba.copy_from_slice(&[
+            expand_5(value >> 11),
+            expand_6((value >> 5) & 0x3F),
+            expand_5(value & 0x1F),
+            0xFF,
+        ]);
+    }
+}
+
+/// 24 bpp pixels, stored in the BGR order
+pub fn bgr24_to_rgba(input: &[u8], output: &mut [u8]) {
+    for (pixel, rgba) in input.chunks_exact(3).zip(output.chunks_exact_mut(4)) {
+        rgba.copy_from_slice(&[pixel[2], pixel[1], pixel[0], 0xFF]);
+    }
+}
+
+/// 32 bpp pixels, stored in the BGRX order
+/// The fourth byte is ignored
+///
+/// # Example
+/// ```
+/// use rdp::codec::color::bgrx_to_rgba;
+/// let mut output = [0; 4];
+/// bgrx_to_rgba(&[1, 2, 3, 0], &mut output);
+/// assert_eq!(output, [3, 2, 1, 0xFF]);
+/// ```
+pub fn bgrx_to_rgba(input: &[u8], output: &mut [u8]) {
+    let done = simd::bgrx_to_rgba(input, output);
+    bgrx_to_rgba_scalar(&input[done * 4..], &mut output[done * 4..]);
+}
+
+fn bgrx_to_rgba_scalar(input: &[u8], output: &mut [u8]) {
+    for (pixel, rgba) in input.chunks_exact(4).zip(output.chunks_exact_mut(4)) {
+        rgba.copy_from_slice(&[pixel[2], pixel[1], pixel[0], 0xFF]);
+    }
+}
+
+/// Runtime selection of the vector implementation
+/// Each function returns the number of converted pixels,
+/// the caller converts the remaining ones
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+mod simd {
+    pub fn bgrx_to_rgba(input: &[u8], output: &mut [u8]) -> usize {
+        if is_x86_feature_detected!("sse2") {
+            // Safety: SSE2 is available
+            unsafe { super::sse2::bgrx_to_rgba(input, output) }
+        } else {
+            0
+        }
+    }
+
+    pub fn rgb565_to_rgba(input: &[u8], output: &mut [u8]) -> usize {
+        if is_x86_feature_detected!("sse2") {
+            // Safety: SSE2 is available
+            unsafe { super::sse2::rgb16_to_rgba::<false>(input, output) }
+        } else {
+            0
+        }
+    }
+
+    pub fn rgb555_to_rgba(input: &[u8], output: &mut [u8]) -> usize {
+        if is_x86_feature_detected!("sse2") {
+            // Safety: SSE2 is available
+            unsafe { super::sse2::rgb16_to_rgba::<true>(input, output) }
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+mod simd {
+    pub fn bgrx_to_rgba(input: &[u8], output: &mut [u8]) -> usize {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // Safety: NEON is available
+            unsafe { super::neon::bgrx_to_rgba(input, output) }
+        } else {
+            0
+        }
+    }
+
+    pub fn rgb565_to_rgba(input: &[u8], output: &mut [u8]) -> usize {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // Safety: NEON is available
+            unsafe { super::neon::rgb16_to_rgba::<false>(input, output) }
+        } else {
+            0
+        }
+    }
+
+    pub fn rgb555_to_rgba(input: &[u8], output: &mut [u8]) -> usize {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // Safety: NEON is available
+            unsafe { super::neon::rgb16_to_rgba::<true>(input, output) }
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(not(all(
+    feature = "simd",
+    any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")
+)))]
+mod simd {
+    pub fn bgrx_to_rgba(_input: &[u8], _output: &mut [u8]) -> usize {
+        0
+    }
+
+    pub fn rgb565_to_rgba(_input: &[u8], _output: &mut [u8]) -> usize {
+        0
+    }
+
+    pub fn rgb555_to_rgba(_input: &[u8], _output: &mut [u8]) -> usize {
+        0
+    }
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+mod sse2 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    /// 4 pixels at a time, red and blue are swapped with shifts
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn bgrx_to_rgba(input: &[u8], output: &mut [u8]) -> usize {
+        let blocks = (input.len() / 4).min(output.len() / 4) / 4;
+        let low = _mm_set1_epi32(0xFF);
+        let green = _mm_set1_epi32(0xFF00);
+        let alpha = _mm_set1_epi32(0xFF00_0000u32 as i32);
+        for block in 0..blocks {
+            let pixels = _mm_loadu_si128(input.as_ptr().add(block * 16) as *const __m128i);
+            let red = _mm_and_si128(_mm_srli_epi32::<16>(pixels), low);
+            let blue = _mm_slli_epi32::<16>(_mm_and_si128(pixels, low));
+            let result = _mm_or_si128(
+                _mm_or_si128(red, blue),
+                _mm_or_si128(_mm_and_si128(pixels, green), alpha),
+            );
+            _mm_storeu_si128(output.as_mut_ptr().add(block * 16) as *mut __m128i, result);
+        }
+        blocks * 4
+    }
+
+    /// 8 pixels at a time, RGB555 when IS_555 else RGB565
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn rgb16_to_rgba<const IS_555: bool>(input: &[u8], output: &mut [u8]) -> usize {
+        let blocks = (input.len() / 2).min(output.len() / 4) / 8;
+        let mask_5 = _mm_set1_epi16(0x1F);
+        let mask_6 = _mm_set1_epi16(0x3F);
+        let alpha = _mm_set1_epi16(0xFF00u16 as i16);
+        // Same rounding as expand_5 and expand_6
+        let expand_5 = |value| {
+            let value = _mm_add_epi16(
+                _mm_mullo_epi16(value, _mm_set1_epi16(527)),
+                _mm_set1_epi16(23),
+            );
+            _mm_srli_epi16::<6>(value)
+        };
+        let expand_6 = |value| {
+            let value = _mm_add_epi16(
+                _mm_mullo_epi16(value, _mm_set1_epi16(259)),
+                _mm_set1_epi16(33),
+            );
+            _mm_srli_epi16::<6>(value)
+        };
+        for block in 0..blocks {
+            let pixels = _mm_loadu_si128(input.as_ptr().add(block * 16) as *const __m128i);
+            let blue = expand_5(_mm_and_si128(pixels, mask_5));
+            let (red, green) = if IS_555 {
+                (
+                    expand_5(_mm_and_si128(_mm_srli_epi16::<10>(pixels), mask_5)),
+                    expand_5(_mm_and_si128(_mm_srli_epi16::<5>(pixels), mask_5)),
+                )
+            } else {
+                (
+                    expand_5(_mm_srli_epi16::<11>(pixels)),
+                    expand_6(_mm_and_si128(_mm_srli_epi16::<5>(pixels), mask_6)),
+                )
+            };
+            // Channels are 8 bits wide, pair them before interleaving
+            let red_green = _mm_or_si128(red, _mm_slli_epi16::<8>(green));
+            let blue_alpha = _mm_or_si128(blue, alpha);
+            let result = output.as_mut_ptr().add(block * 32) as *mut __m128i;
+            _mm_storeu_si128(result, _mm_unpacklo_epi16(red_green, blue_alpha));
+            _mm_storeu_si128(result.add(1), _mm_unpackhi_epi16(red_green, blue_alpha));
+        }
+        blocks * 8
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+mod neon {
+    use std::arch::aarch64::*;
+
+    /// 16 pixels at a time, channels are deinterleaved by the load
+    #[target_feature(enable = "neon")]
+    pub unsafe fn bgrx_to_rgba(input: &[u8], output: &mut [u8]) -> usize {
+        let blocks = (input.len() / 4).min(output.len() / 4) / 16;
+        let alpha = vdupq_n_u8(0xFF);
+        for block in 0..blocks {
+            let bgrx = vld4q_u8(input.as_ptr().add(block * 64));
+            let rgba = uint8x16x4_t(bgrx.2, bgrx.1, bgrx.0, alpha);
+            vst4q_u8(output.as_mut_ptr().add(block * 64), rgba);
+        }
+        blocks * 16
+    }
+
+    /// 8 pixels at a time, RGB555 when IS_555 else RGB565
+    #[target_feature(enable = "neon")]
+    pub unsafe fn rgb16_to_rgba<const IS_555: bool>(input: &[u8], output: &mut [u8]) -> usize {
+        let blocks = (input.len() / 2).min(output.len() / 4) / 8;
+        let mask_5 = vdupq_n_u16(0x1F);
+        let mask_6 = vdupq_n_u16(0x3F);
+        let alpha = vdup_n_u8(0xFF);
+        // Same rounding as expand_5 and expand_6
+        let expand_5 = |value| vshrq_n_u16::<6>(vmlaq_n_u16(vdupq_n_u16(23), value, 527));
+        let expand_6 = |value| vshrq_n_u16::<6>(vmlaq_n_u16(vdupq_n_u16(33), value, 259));
+        for block in 0..blocks {
+            let pixels = vld1q_u16(input.as_ptr().add(block * 16) as *const u16);
+            let blue = expand_5(vandq_u16(pixels, mask_5));
+            let (red, green) = if IS_555 {
+                (
+                    expand_5(vandq_u16(vshrq_n_u16::<10>(pixels), mask_5)),
+                    expand_5(vandq_u16(vshrq_n_u16::<5>(pixels), mask_5)),
+                )
+            } else {
+                (
+                    expand_5(vshrq_n_u16::<11>(pixels)),
+                    expand_6(vandq_u16(vshrq_n_u16::<5>(pixels), mask_6)),
+                )
+            };
+            let rgba = uint8x8x4_t(vmovn_u16(red), vmovn_u16(green), vmovn_u16(blue), alpha);
+            vst4_u8(output.as_mut_ptr().add(block * 32), rgba);
+        }
+        blocks * 8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::rle::rgb565torgb32;
+
+    /// Pixels of all the values of a 16 bits pixel
+    fn all_16bpp_pixels() -> Vec<u8> {
+        (0..=u16::MAX).flat_map(u16::to_le_bytes).collect()
+    }
+
+    /// Same result as the BGRA conversion of the rle module
+    #[test]
+    fn test_rgb565_to_rgba() {
+        let input = all_16bpp_pixels();
+        let mut output = vec![0; input.len() * 2];
+        rgb565_to_rgba(&input, &mut output);
+
+        let values: Vec<u16> = (0..=u16::MAX).collect();
+        let expected = rgb565torgb32(&values, values.len(), 1);
+        for (rgba, bgra) in output.chunks_exact(4).zip(expected.chunks_exact(4)) {
+            assert_eq!(rgba, [bgra[2], bgra[1], bgra[0], bgra[3]]);
+        }
+    }
+
+    /// Vector and scalar paths give the same result,
+    /// lengths are not a multiple of the vector size
+    #[test]
+    fn test_simd_matches_scalar() {
+        let input: Vec<u8> = (0..4 * 37).map(|index| (index * 7) as u8).collect();
+
+        let mut vector = vec![0; 4 * 37];
+        let mut scalar = vec![0; 4 * 37];
+        bgrx_to_rgba(&input, &mut vector);
+        bgrx_to_rgba_scalar(&input, &mut scalar);
+        assert_eq!(vector, scalar);
+
+        let input = &input[..2 * 37];
+        rgb555_to_rgba(input, &mut vector);
+        rgb555_to_rgba_scalar(input, &mut scalar);
+        assert_eq!(vector, scalar);
+
+        rgb565_to_rgba(input, &mut vector);
+        rgb565_to_rgba_scalar(input, &mut scalar);
+        assert_eq!(vector, scalar);
+    }
+
+    /// The shortest of the input and output gives the pixel count
+    #[test]
+    fn test_bgr24_to_rgba() {
+        let mut output = [0; 8];
+        bgr24_to_rgba(&[1, 2, 3, 4, 5, 6, 7], &mut output);
+        assert_eq!(output, [3, 2, 1, 0xFF, 6, 5, 4, 0xFF]);
+    }
+}
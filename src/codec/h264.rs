@@ -0,0 +1,559 @@
+use crate::core::framebuffer::Rectangle;
+
+use std::io::{Error, ErrorKind, Result};
+
+/// Below this difference the main view chroma
+/// is kept as is when rebuilding full chroma
+const CHROMA_FILTER_THRESHOLD: u8 = 30;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_u16(input: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([input[offset], input[offset + 1]])
+}
+
+fn read_u32(input: &[u8]) -> Result<u32> {
+    match input {
+        [a, b, c, d, ..] => Ok(u32::from_le_bytes([*a, *b, *c, *d])),
+        _ => Err(invalid("H264: truncated stream")),
+    }
+}
+
+/// Encoding quality of a region
+/// MS-RDPEGFX 2.2.4.4.2 RDPGFX_H264_QUANT_QUALITY
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AvcQuality {
+    /// Quantization parameter
+    pub qp: u8,
+    /// The region will be refined by later frames
+    pub progressive: bool,
+    /// Quality level from 0 to 100
+    pub quality: u8,
+}
+
+/// H.264 bitstream and the regions it updates
+/// MS-RDPEGFX 2.2.4.4 RFX_AVC420_BITMAP_STREAM
+#[derive(Debug, Eq, PartialEq)]
+pub struct Avc420Stream<'a> {
+    pub regions: Vec<Rectangle>,
+    pub quality: Vec<AvcQuality>,
+    pub bitstream: &'a [u8],
+}
+
+/// Parse the metablock in front of an AVC420 bitstream
+/// MS-RDPEGFX 2.2.4.4.1 RFX_AVC420_METABLOCK
+pub fn parse_avc420(input: &[u8]) -> Result<Avc420Stream<'_>> {
+    let count = read_u32(input)? as usize;
+    let size = count
+        .checked_mul(10)
+        .and_then(|size| size.checked_add(4))
+        .filter(|size| *size <= input.len())
+        .ok_or_else(|| invalid("H264: truncated metablock"))?;
+
+    let mut regions = Vec::with_capacity(count);
+    for index in 0..count {
+        let offset = 4 + index * 8;
+        let region = Rectangle {
+            left: read_u16(input, offset),
+            top: read_u16(input, offset + 2),
+            right: read_u16(input, offset + 4),
+            bottom: read_u16(input, offset + 6),
+        };
+        if region.right < region.left || region.bottom < region.top {
+            return Err(invalid("H264: invalid region"));
+        }
+        regions.push(region);
+    }
+    let quality = input[4 + count * 8..size]
+        .chunks_exact(2)
+        .map(|value| AvcQuality {
+            qp: value[0] & 0x3F,
+            progressive: value[0] & 0x80 != 0,
+            quality: value[1],
+        })
+        .collect();
+
+    Ok(Avc420Stream {
+        regions,
+        quality,
+        bitstream: &input[size..],
+    })
+}
+
+/// Streams of an AVC444 bitmap
+/// The luma stream holds the main YUV 4:2:0 view, the
+/// chroma stream the auxiliary view with the missing chroma
+/// MS-RDPEGFX 2.2.4.5 RFX_AVC444_BITMAP_STREAM
+#[derive(Debug, Eq, PartialEq)]
+pub enum Avc444Stream<'a> {
+    Both {
+        luma: Avc420Stream<'a>,
+        chroma: Avc420Stream<'a>,
+    },
+    Luma(Avc420Stream<'a>),
+    Chroma(Avc420Stream<'a>),
+}
+
+/// Split an AVC444 bitmap into its streams
+pub fn parse_avc444(input: &[u8]) -> Result<Avc444Stream<'_>> {
+    let info = read_u32(input)?;
+    let size = (info & 0x3FFF_FFFF) as usize;
+    let input = &input[4..];
+    if size > input.len() {
+        return Err(invalid("H264: truncated AVC444 stream"));
+    }
+    let (first, second) = input.split_at(size);
+    match info >> 30 {
+        0 => Ok(Avc444Stream::Both {
+            luma: parse_avc420(first)?,
+            chroma: parse_avc420(second)?,
+        }),
+        1 => Ok(Avc444Stream::Luma(parse_avc420(first)?)),
+        2 => Ok(Avc444Stream::Chroma(parse_avc420(first)?)),
+        _ => Err(invalid("H264: invalid AVC444 stream content")),
+    }
+}
+
+/// Layout of the auxiliary view
+/// MS-RDPEGFX 3.3.8.3.2 and 3.3.8.3.3
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Avc444Version {
+    /// RDPGFX_CODECID_AVC444
+    V1,
+    /// RDPGFX_CODECID_AVC444v2
+    V2,
+}
+
+/// YUV 4:2:0 picture given by an H.264 decoder
+/// Planes are packed, chroma planes have half the width and height
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Yuv420Frame {
+    pub width: usize,
+    pub height: usize,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+}
+
+impl Yuv420Frame {
+    fn chroma_width(&self) -> usize {
+        (self.width + 1) / 2
+    }
+
+    fn chroma_height(&self) -> usize {
+        (self.height + 1) / 2
+    }
+
+    /// Build a frame from planes with a row stride
+    pub fn from_strides(
+        width: usize,
+        height: usize,
+        (y, y_stride): (&[u8], usize),
+        (u, u_stride): (&[u8], usize),
+        (v, v_stride): (&[u8], usize),
+    ) -> Result<Self> {
+        let pack = |plane: &[u8], stride: usize, width: usize, height: usize| {
+            if height > 0 && plane.len() < stride * (height - 1) + width {
+                return Err(invalid("H264: plane is too short"));
+            }
+            Ok((0..height)
+                .flat_map(|row| &plane[row * stride..row * stride + width])
+                .copied()
+                .collect())
+        };
+        let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+        Ok(Yuv420Frame {
+            width,
+            height,
+            y: pack(y, y_stride, width, height)?,
+            u: pack(u, u_stride, chroma_width, chroma_height)?,
+            v: pack(v, v_stride, chroma_width, chroma_height)?,
+        })
+    }
+}
+
+/// H.264 decoder used by the AVC codecs
+/// `OpenH264Decoder` is available with the `h264` feature,
+/// other decoders like ffmpeg can be plugged in
+pub trait H264Decoder {
+    /// Decode the NAL units of a bitstream
+    /// None until the decoder has a complete picture
+    fn decode(&mut self, bitstream: &[u8]) -> Result<Option<Yuv420Frame>>;
+}
+
+/// H.264 decoder of the openh264 library
+#[cfg(feature = "h264")]
+pub struct OpenH264Decoder {
+    decoder: openh264::decoder::Decoder,
+}
+
+#[cfg(feature = "h264")]
+impl OpenH264Decoder {
+    pub fn new() -> Result<Self> {
+        let decoder = openh264::decoder::Decoder::new()
+            .map_err(|e| Error::new(ErrorKind::Other, format!("H264: {}", e)))?;
+        Ok(OpenH264Decoder { decoder })
+    }
+}
+
+#[cfg(feature = "h264")]
+impl H264Decoder for OpenH264Decoder {
+    fn decode(&mut self, bitstream: &[u8]) -> Result<Option<Yuv420Frame>> {
+        let yuv = match self
+            .decoder
+            .decode(bitstream)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("H264: {}", e)))?
+        {
+            Some(yuv) => yuv,
+            None => return Ok(None),
+        };
+        let (width, height) = yuv.dimension_rgb();
+        let (y_stride, u_stride, v_stride) = yuv.strides_yuv();
+        Yuv420Frame::from_strides(
+            width,
+            height,
+            (yuv.y_with_stride(), y_stride),
+            (yuv.u_with_stride(), u_stride),
+            (yuv.v_with_stride(), v_stride),
+        )
+        .map(Some)
+    }
+}
+
+/// BT.709 full range, as used by the graphics pipeline
+fn yuv_to_bgra(y: u8, u: u8, v: u8) -> [u8; 4] {
+    let luma = y as i32 * 256;
+    let u = u as i32 - 128;
+    let v = v as i32 - 128;
+    let clamp = |value: i32| (value >> 8).clamp(0, 0xFF) as u8;
+    [
+        clamp(luma + 475 * u),
+        clamp(luma - 48 * u - 120 * v),
+        clamp(luma + 403 * v),
+        0xFF,
+    ]
+}
+
+/// Crop a frame and convert it to BGRA
+fn yuv420_to_bgra(frame: &Yuv420Frame, width: usize, height: usize) -> Vec<u8> {
+    let chroma_width = frame.chroma_width();
+    let mut output = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let chroma = (y / 2) * chroma_width + x / 2;
+            output.extend_from_slice(&yuv_to_bgra(
+                frame.y[y * frame.width + x],
+                frame.u[chroma],
+                frame.v[chroma],
+            ));
+        }
+    }
+    output
+}
+
+/// Rebuild full chroma planes from the main and auxiliary views
+/// Main view chroma was averaged over 2x2 pixels by the encoder
+fn combine_yuv444(
+    main: &Yuv420Frame,
+    auxiliary: &Yuv420Frame,
+    version: Avc444Version,
+) -> (Vec<u8>, Vec<u8>) {
+    let (width, height) = (main.width, main.height);
+    let chroma_width = main.chroma_width();
+    let mut u = vec![0; width * height];
+    let mut v = vec![0; width * height];
+
+    // Even rows and columns from the main view
+    for y in (0..height).step_by(2) {
+        for x in (0..width).step_by(2) {
+            let chroma = (y / 2) * chroma_width + x / 2;
+            u[y * width + x] = main.u[chroma];
+            v[y * width + x] = main.v[chroma];
+        }
+    }
+
+    match version {
+        Avc444Version::V1 => {
+            // Blocks of 16 luma lines, 8 odd lines of U then 8 of V
+            for line in 0..height {
+                let (plane, row) = match line % 16 {
+                    offset if offset < 8 => (&mut u, line - offset + 2 * offset + 1),
+                    offset => (&mut v, line - offset + 2 * (offset - 8) + 1),
+                };
+                if row < height {
+                    plane[row * width..(row + 1) * width]
+                        .copy_from_slice(&auxiliary.y[line * width..(line + 1) * width]);
+                }
+            }
+            // Odd columns of even rows
+            for y in 0..main.chroma_height() {
+                for x in (0..chroma_width).filter(|x| 2 * x + 1 < width) {
+                    let chroma = y * chroma_width + x;
+                    u[2 * y * width + 2 * x + 1] = auxiliary.u[chroma];
+                    v[2 * y * width + 2 * x + 1] = auxiliary.v[chroma];
+                }
+            }
+        }
+        Avc444Version::V2 => {
+            // Odd columns, U on the left half of the luma and V on the right
+            let half = width / 2;
+            for y in 0..height {
+                for x in (0..half).filter(|x| 2 * x + 1 < width) {
+                    u[y * width + 2 * x + 1] = auxiliary.y[y * width + x];
+                    v[y * width + 2 * x + 1] = auxiliary.y[y * width + half + x];
+                }
+            }
+            // Even columns of odd rows, one column out of two in each chroma plane
+            let quarter = width / 4;
+            for y in (0..main.chroma_height()).filter(|y| 2 * y + 1 < height) {
+                let row = (2 * y + 1) * width;
+                for x in (0..quarter).filter(|x| 4 * x + 2 < width) {
+                    let chroma = y * chroma_width + x;
+                    u[row + 4 * x] = auxiliary.u[chroma];
+                    v[row + 4 * x] = auxiliary.u[chroma + quarter];
+                    u[row + 4 * x + 2] = auxiliary.v[chroma];
+                    v[row + 4 * x + 2] = auxiliary.v[chroma + quarter];
+                }
+            }
+        }
+    }
+
+    // Undo the average when it's far from the original value
+    for plane in [&mut u, &mut v] {
+        for y in (0..height.saturating_sub(1)).step_by(2) {
+            for x in (0..width.saturating_sub(1)).step_by(2) {
+                let index = y * width + x;
+                let original = plane[index];
+                let value = 4 * original as i32
+                    - plane[index + 1] as i32
+                    - plane[index + width] as i32
+                    - plane[index + width + 1] as i32;
+                let value = value.clamp(0, 0xFF) as u8;
+                if value.abs_diff(original) >= CHROMA_FILTER_THRESHOLD {
+                    plane[index] = value;
+                }
+            }
+        }
+    }
+    (u, v)
+}
+
+/// Picture decoded from an AVC bitmap
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AvcFrame {
+    /// Regions which changed, other pixels must be kept
+    pub regions: Vec<Rectangle>,
+    /// BGRA pixels of the whole bitmap, rows are top down
+    pub data: Vec<u8>,
+}
+
+/// Decoder of the AVC420 and AVC444 codecs of a surface
+/// MS-RDPEGFX 3.3.8.3 H.264 Codecs
+///
+/// AVC444 uses two H.264 streams, the last picture of
+/// each one is kept as the server may update only one
+///
+/// # Example
+/// ```rust, ignore
+/// let mut decoder = AvcDecoder::new(OpenH264Decoder::new()?, OpenH264Decoder::new()?);
+/// if let Some(frame) = decoder.decode_avc420(&bitmap, width, height)? {
+///     for region in frame.regions {
+///         // copy the region from frame.data
+///     }
+/// }
+/// ```
+pub struct AvcDecoder<D: H264Decoder> {
+    main: D,
+    auxiliary: D,
+    last_main: Option<Yuv420Frame>,
+    last_auxiliary: Option<Yuv420Frame>,
+}
+
+impl<D: H264Decoder> AvcDecoder<D> {
+    /// The auxiliary decoder is only used by AVC444
+    pub fn new(main: D, auxiliary: D) -> Self {
+        AvcDecoder {
+            main,
+            auxiliary,
+            last_main: None,
+            last_auxiliary: None,
+        }
+    }
+
+    /// Decode a RFX_AVC420_BITMAP_STREAM
+    pub fn decode_avc420(
+        &mut self,
+        input: &[u8],
+        width: u16,
+        height: u16,
+    ) -> Result<Option<AvcFrame>> {
+        let stream = parse_avc420(input)?;
+        let frame = match self.main.decode(stream.bitstream)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let (width, height) = checked_size(&frame, width, height)?;
+        Ok(Some(AvcFrame {
+            regions: stream.regions,
+            data: yuv420_to_bgra(&frame, width, height),
+        }))
+    }
+
+    /// Decode a RFX_AVC444_BITMAP_STREAM
+    pub fn decode_avc444(
+        &mut self,
+        input: &[u8],
+        width: u16,
+        height: u16,
+        version: Avc444Version,
+    ) -> Result<Option<AvcFrame>> {
+        let (luma, chroma) = match parse_avc444(input)? {
+            Avc444Stream::Both { luma, chroma } => (Some(luma), Some(chroma)),
+            Avc444Stream::Luma(luma) => (Some(luma), None),
+            Avc444Stream::Chroma(chroma) => (None, Some(chroma)),
+        };
+
+        let mut regions = Vec::new();
+        if let Some(luma) = luma {
+            if let Some(frame) = self.main.decode(luma.bitstream)? {
+                self.last_main = Some(frame);
+            }
+            regions = luma.regions;
+        }
+        if let Some(chroma) = chroma {
+            if let Some(frame) = self.auxiliary.decode(chroma.bitstream)? {
+                self.last_auxiliary = Some(frame);
+            }
+            if regions.is_empty() {
+                regions = chroma.regions;
+            }
+        }
+
+        let (main, auxiliary) = match (&self.last_main, &self.last_auxiliary) {
+            (Some(main), Some(auxiliary)) => (main, auxiliary),
+            _ => return Ok(None),
+        };
+        if (main.width, main.height) != (auxiliary.width, auxiliary.height) {
+            return Err(invalid("H264: main and auxiliary views differ in size"));
+        }
+        let (width, height) = checked_size(main, width, height)?;
+        let (u, v) = combine_yuv444(main, auxiliary, version);
+        let mut data = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * main.width + x;
+                data.extend_from_slice(&yuv_to_bgra(main.y[index], u[index], v[index]));
+            }
+        }
+        Ok(Some(AvcFrame { regions, data }))
+    }
+}
+
+/// Decoded pictures are aligned on macroblocks
+/// and may be larger than the bitmap
+fn checked_size(frame: &Yuv420Frame, width: u16, height: u16) -> Result<(usize, usize)> {
+    let (width, height) = (width as usize, height as usize);
+    if width > frame.width || height > frame.height {
+        return Err(invalid("H264: picture is smaller than the bitmap"));
+    }
+    Ok((width, height))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Gives the same picture for each bitstream
+    struct Still(Yuv420Frame);
+
+    impl H264Decoder for Still {
+        fn decode(&mut self, _bitstream: &[u8]) -> Result<Option<Yuv420Frame>> {
+            Ok(Some(self.0.clone()))
+        }
+    }
+
+    fn frame(width: usize, height: usize, y: u8, u: u8, v: u8) -> Yuv420Frame {
+        let chroma_size = ((width + 1) / 2) * ((height + 1) / 2);
+        Yuv420Frame {
+            width,
+            height,
+            y: vec![y; width * height],
+            u: vec![u; chroma_size],
+            v: vec![v; chroma_size],
+        }
+    }
+
+    /// Metablock regions and quality, then the raw bitstream
+    #[test]
+    fn test_parse_avc444() {
+        let mut luma = vec![1, 0, 0, 0, 1, 0, 2, 0, 17, 0, 18, 0, 0x96, 80];
+        luma.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+        let mut input = (luma.len() as u32 | 1 << 30).to_le_bytes().to_vec();
+        input.extend_from_slice(&luma);
+
+        match parse_avc444(&input).unwrap() {
+            Avc444Stream::Luma(stream) => {
+                assert_eq!(
+                    stream.regions,
+                    vec![Rectangle {
+                        left: 1,
+                        top: 2,
+                        right: 17,
+                        bottom: 18
+                    }]
+                );
+                assert_eq!(
+                    stream.quality,
+                    vec![AvcQuality {
+                        qp: 22,
+                        progressive: true,
+                        quality: 80
+                    }]
+                );
+                assert_eq!(stream.bitstream, &[0x00, 0x00, 0x00, 0x01]);
+            }
+            _ => panic!("expected a luma stream"),
+        }
+        assert!(parse_avc420(&[2, 0, 0, 0, 0]).is_err());
+    }
+
+    /// Cropped to the bitmap, BT.709 colors
+    #[test]
+    fn test_decode_avc420() {
+        let mut decoder = AvcDecoder::new(
+            Still(frame(16, 16, 0x80, 0x80, 0x80)),
+            Still(frame(16, 16, 0, 0, 0)),
+        );
+        let input = [0, 0, 0, 0];
+        let frame = decoder.decode_avc420(&input, 2, 1).unwrap().unwrap();
+        assert_eq!(
+            frame.data,
+            vec![0x80, 0x80, 0x80, 0xFF, 0x80, 0x80, 0x80, 0xFF]
+        );
+        assert!(decoder.decode_avc420(&input, 32, 1).is_err());
+        assert_eq!(yuv_to_bgra(0x80, 0x80, 0xFF), [0x80, 0x44, 0xFF, 0xFF]);
+    }
+
+    /// Each chroma sample comes from the right view
+    #[test]
+    fn test_combine_yuv444() {
+        let main = frame(4, 2, 0, 10, 5);
+        let mut auxiliary = frame(4, 2, 0, 0, 0);
+        auxiliary.y = vec![1, 1, 1, 1, 2, 2, 2, 2];
+        auxiliary.u = vec![3, 3];
+        auxiliary.v = vec![4, 4];
+
+        let (u, v) = combine_yuv444(&main, &auxiliary, Avc444Version::V1);
+        // Line 0 of the auxiliary luma is the odd line of U,
+        // line 8 would be the one of V
+        assert_eq!(u, vec![10, 3, 10, 3, 1, 1, 1, 1]);
+        assert_eq!(v, vec![5, 4, 5, 4, 0, 0, 0, 0]);
+
+        auxiliary.y = vec![1, 2, 5, 6, 1, 2, 5, 6];
+        let (u, v) = combine_yuv444(&main, &auxiliary, Avc444Version::V2);
+        assert_eq!(u, vec![10, 1, 10, 2, 3, 1, 4, 2]);
+        assert_eq!(v, vec![5, 5, 5, 6, 3, 5, 4, 6]);
+    }
+}
@@ -0,0 +1,389 @@
+use std::io::{Error, ErrorKind, Result};
+
+/// Codes of the compression orders
+/// MS-RDPBCGR 2.2.9.1.1.3.1.2.4 RLE Compressed Bitmap Stream (RLE_BITMAP_STREAM)
+const REGULAR_BG_RUN: u8 = 0x0;
+const REGULAR_FG_RUN: u8 = 0x1;
+const REGULAR_FGBG_IMAGE: u8 = 0x2;
+const REGULAR_COLOR_RUN: u8 = 0x3;
+const REGULAR_COLOR_IMAGE: u8 = 0x4;
+const LITE_SET_FG_FG_RUN: u8 = 0xC;
+const LITE_SET_FG_FGBG_IMAGE: u8 = 0xD;
+const LITE_DITHERED_RUN: u8 = 0xE;
+const MEGA_MEGA_BG_RUN: u8 = 0xF0;
+const MEGA_MEGA_FG_RUN: u8 = 0xF1;
+const MEGA_MEGA_FGBG_IMAGE: u8 = 0xF2;
+const MEGA_MEGA_COLOR_RUN: u8 = 0xF3;
+const MEGA_MEGA_COLOR_IMAGE: u8 = 0xF4;
+const MEGA_MEGA_SET_FG_RUN: u8 = 0xF6;
+const MEGA_MEGA_SET_FGBG_IMAGE: u8 = 0xF7;
+const MEGA_MEGA_DITHERED_RUN: u8 = 0xF8;
+const SPECIAL_FGBG_1: u8 = 0xF9;
+const SPECIAL_FGBG_2: u8 = 0xFA;
+const SPECIAL_WHITE: u8 = 0xFD;
+const SPECIAL_BLACK: u8 = 0xFE;
+
+/// Bitmasks of the special foreground background images
+const SPECIAL_FGBG_1_MASK: u8 = 0x03;
+const SPECIAL_FGBG_2_MASK: u8 = 0x05;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Read side of the compressed stream
+struct Source<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Source<'a> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.input.len()
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        let byte = *self
+            .input
+            .get(self.position)
+            .ok_or_else(|| invalid("RLE: truncated stream"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        Ok(self.read_u8()? as u16 | (self.read_u8()? as u16) << 8)
+    }
+
+    fn read_pixel(&mut self, pixel_size: usize) -> Result<u32> {
+        let mut pixel = 0;
+        for index in 0..pixel_size {
+            pixel |= (self.read_u8()? as u32) << (8 * index);
+        }
+        Ok(pixel)
+    }
+}
+
+/// Write side, scanlines are written from the bottom one
+/// so the previous line is the one below
+struct Destination {
+    data: Vec<u8>,
+    position: usize,
+    pixel_size: usize,
+    row_size: usize,
+}
+
+impl Destination {
+    fn is_first_line(&self) -> bool {
+        self.position < self.row_size
+    }
+
+    /// Pixel of the previous line at the current position
+    fn previous(&self) -> u32 {
+        let offset = self.position - self.row_size;
+        let mut pixel = 0;
+        for index in 0..self.pixel_size {
+            pixel |= (self.data[offset + index] as u32) << (8 * index);
+        }
+        pixel
+    }
+
+    fn write(&mut self, pixel: u32) -> Result<()> {
+        if self.position + self.pixel_size > self.data.len() {
+            return Err(invalid("RLE: too many pixels"));
+        }
+        for index in 0..self.pixel_size {
+            self.data[self.position + index] = (pixel >> (8 * index)) as u8;
+        }
+        self.position += self.pixel_size;
+        Ok(())
+    }
+
+    /// Foreground background image, a set bit is a foreground pixel
+    /// Black and the foreground color on the first line, otherwise
+    /// the previous line and its mix with the foreground color
+    fn write_fgbg(
+        &mut self,
+        mask: u8,
+        count: usize,
+        foreground: u32,
+        first_line: bool,
+    ) -> Result<()> {
+        for bit in 0..count {
+            let set = mask & (1 << bit) != 0;
+            let pixel = match (first_line, set) {
+                (true, true) => foreground,
+                (true, false) => 0,
+                (false, true) => self.previous() ^ foreground,
+                (false, false) => self.previous(),
+            };
+            self.write(pixel)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decompress a bitmap compressed with the interleaved RLE
+/// MS-RDPBCGR 3.1.9 Interleaved RLE-Based Bitmap Compression
+///
+/// Pixels keep their color depth, 8 bpp are palette indexes,
+/// 15 and 16 bpp are little endian RGB555 and RGB565 and 24 bpp are BGR
+/// Rows are bottom up as in uncompressed bitmaps
+///
+/// # Example
+/// ```
+/// use rdp::codec::interleaved::interleaved_rle_decompress;
+/// // Color run of 4 pixels of color 7 then black pixels
+/// let data = interleaved_rle_decompress(&[0x64, 0x07, 0xFE, 0xFE, 0xFE, 0xFE], 4, 2, 8).unwrap();
+/// assert_eq!(data, vec![7, 7, 7, 7, 0, 0, 0, 0]);
+/// ```
+pub fn interleaved_rle_decompress(
+    input: &[u8],
+    width: u16,
+    height: u16,
+    bpp: u16,
+) -> Result<Vec<u8>> {
+    let (pixel_size, white) = match bpp {
+        8 => (1, 0xFF),
+        15 => (2, 0x7FFF),
+        16 => (2, 0xFFFF),
+        24 => (3, 0xFF_FFFF),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("RLE: interleaved RLE is not defined for {} bpp", bpp),
+            ))
+        }
+    };
+    let row_size = width as usize * pixel_size;
+    let mut source = Source { input, position: 0 };
+    let mut destination = Destination {
+        data: vec![0; row_size * height as usize],
+        position: 0,
+        pixel_size,
+        row_size,
+    };
+
+    let mut foreground = white;
+    let mut insert_foreground = false;
+    let mut first_line = true;
+
+    while !source.is_empty() {
+        // Once past the first line the previous line is always used
+        if first_line && !destination.is_first_line() {
+            first_line = false;
+            insert_foreground = false;
+        }
+
+        let header = source.read_u8()?;
+        let code = order_code(header);
+        let run_length = run_length(header, code, &mut source)?;
+
+        if code == REGULAR_BG_RUN || code == MEGA_MEGA_BG_RUN {
+            let mut count = run_length;
+            // Two background runs in a row are split by a foreground pixel
+            if insert_foreground && count > 0 {
+                let pixel = if first_line {
+                    foreground
+                } else {
+                    destination.previous() ^ foreground
+                };
+                destination.write(pixel)?;
+                count -= 1;
+            }
+            for _ in 0..count {
+                let pixel = if first_line {
+                    0
+                } else {
+                    destination.previous()
+                };
+                destination.write(pixel)?;
+            }
+            insert_foreground = true;
+            continue;
+        }
+        insert_foreground = false;
+
+        match code {
+            REGULAR_FG_RUN | MEGA_MEGA_FG_RUN | LITE_SET_FG_FG_RUN | MEGA_MEGA_SET_FG_RUN => {
+                if code == LITE_SET_FG_FG_RUN || code == MEGA_MEGA_SET_FG_RUN {
+                    foreground = source.read_pixel(pixel_size)?;
+                }
+                for _ in 0..run_length {
+                    let pixel = if first_line {
+                        foreground
+                    } else {
+                        destination.previous() ^ foreground
+                    };
+                    destination.write(pixel)?;
+                }
+            }
+            LITE_DITHERED_RUN | MEGA_MEGA_DITHERED_RUN => {
+                let first = source.read_pixel(pixel_size)?;
+                let second = source.read_pixel(pixel_size)?;
+                for _ in 0..run_length {
+                    destination.write(first)?;
+                    destination.write(second)?;
+                }
+            }
+            REGULAR_COLOR_RUN | MEGA_MEGA_COLOR_RUN => {
+                let pixel = source.read_pixel(pixel_size)?;
+                for _ in 0..run_length {
+                    destination.write(pixel)?;
+                }
+            }
+            REGULAR_FGBG_IMAGE
+            | MEGA_MEGA_FGBG_IMAGE
+            | LITE_SET_FG_FGBG_IMAGE
+            | MEGA_MEGA_SET_FGBG_IMAGE => {
+                if code == LITE_SET_FG_FGBG_IMAGE || code == MEGA_MEGA_SET_FGBG_IMAGE {
+                    foreground = source.read_pixel(pixel_size)?;
+                }
+                let mut remaining = run_length;
+                while remaining > 0 {
+                    let count = remaining.min(8);
+                    let mask = source.read_u8()?;
+                    destination.write_fgbg(mask, count, foreground, first_line)?;
+                    remaining -= count;
+                }
+            }
+            REGULAR_COLOR_IMAGE | MEGA_MEGA_COLOR_IMAGE => {
+                for _ in 0..run_length {
+                    let pixel = source.read_pixel(pixel_size)?;
+                    destination.write(pixel)?;
+                }
+            }
+            SPECIAL_FGBG_1 => {
+                destination.write_fgbg(SPECIAL_FGBG_1_MASK, 8, foreground, first_line)?
+            }
+            SPECIAL_FGBG_2 => {
+                destination.write_fgbg(SPECIAL_FGBG_2_MASK, 8, foreground, first_line)?
+            }
+            SPECIAL_WHITE => destination.write(white)?,
+            SPECIAL_BLACK => destination.write(0)?,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("RLE: invalid order header 0x{:02X}", header),
+                ))
+            }
+        }
+    }
+
+    Ok(destination.data)
+}
+
+/// Regular orders use the 3 high bits, lite orders the
+/// 4 high bits and the other ones the whole byte
+fn order_code(header: u8) -> u8 {
+    match header >> 4 {
+        0xC..=0xE => header >> 4,
+        0xF => header,
+        _ => header >> 5,
+    }
+}
+
+/// Run length of an order, some orders read it after the header
+fn run_length(header: u8, code: u8, source: &mut Source) -> Result<usize> {
+    Ok(match code {
+        REGULAR_FGBG_IMAGE => match header & 0x1F {
+            0 => source.read_u8()? as usize + 1,
+            length => length as usize * 8,
+        },
+        LITE_SET_FG_FGBG_IMAGE => match header & 0x0F {
+            0 => source.read_u8()? as usize + 1,
+            length => length as usize * 8,
+        },
+        REGULAR_BG_RUN | REGULAR_FG_RUN | REGULAR_COLOR_RUN | REGULAR_COLOR_IMAGE => {
+            match header & 0x1F {
+                0 => source.read_u8()? as usize + 32,
+                length => length as usize,
+            }
+        }
+        LITE_SET_FG_FG_RUN | LITE_DITHERED_RUN => match header & 0x0F {
+            0 => source.read_u8()? as usize + 16,
+            length => length as usize,
+        },
+        MEGA_MEGA_BG_RUN
+        | MEGA_MEGA_FG_RUN
+        | MEGA_MEGA_FGBG_IMAGE
+        | MEGA_MEGA_COLOR_RUN
+        | MEGA_MEGA_COLOR_IMAGE
+        | MEGA_MEGA_SET_FG_RUN
+        | MEGA_MEGA_SET_FGBG_IMAGE
+        | MEGA_MEGA_DITHERED_RUN => source.read_u16()? as usize,
+        _ => 0,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Background runs copy the previous line, two runs
+    /// in a row are split by a foreground pixel
+    #[test]
+    fn test_background_run() {
+        let input = [
+            0x83, 1, 2, 3,    // color image of the first line
+            0x02, // background run, previous line
+            0x01, // background run, foreground pixel inserted
+        ];
+        let data = interleaved_rle_decompress(&input, 3, 2, 8).unwrap();
+        assert_eq!(data, vec![1, 2, 3, 1, 2, 3 ^ 0xFF]);
+    }
+
+    /// The foreground color is mixed with the previous line
+    #[test]
+    fn test_set_foreground_run_16bpp() {
+        let input = [
+            0xC2, 0x0F, 0x00, // lite set foreground run, first line
+            0xF6, 0x02, 0x00, 0xF0, 0x00, // mega mega set foreground run
+        ];
+        let data = interleaved_rle_decompress(&input, 2, 2, 16).unwrap();
+        assert_eq!(data, vec![0x0F, 0x00, 0x0F, 0x00, 0xFF, 0x00, 0xFF, 0x00]);
+    }
+
+    /// Foreground background bitmasks are read from the low bit
+    #[test]
+    fn test_fgbg_image() {
+        let input = [
+            0xFA, // special 2, 8 pixels of the first line
+            0x41, 0x0F, // regular image of 8 pixels
+        ];
+        let data = interleaved_rle_decompress(&input, 8, 2, 8).unwrap();
+        assert_eq!(
+            data,
+            vec![
+                0xFF, 0, 0xFF, 0, 0, 0, 0, 0, //
+                0, 0xFF, 0, 0xFF, 0, 0, 0, 0
+            ]
+        );
+    }
+
+    /// Dithered runs alternate two 24 bpp colors
+    #[test]
+    fn test_dithered_run_24bpp() {
+        let input = [0xE2, 1, 2, 3, 4, 5, 6];
+        let data = interleaved_rle_decompress(&input, 4, 1, 24).unwrap();
+        assert_eq!(data, vec![1, 2, 3, 4, 5, 6, 1, 2, 3, 4, 5, 6]);
+    }
+
+    /// Run lengths stored after the header
+    #[test]
+    fn test_extended_run_length() {
+        let input = [0x60, 0x00, 0x7F, 0x00, 0xFD];
+        let data = interleaved_rle_decompress(&input, 33, 1, 15).unwrap();
+        assert_eq!(&data[..2], [0x7F, 0x00]);
+        assert_eq!(&data[62..], [0x7F, 0x00, 0xFF, 0x7F]);
+    }
+
+    /// Streams which write outside the bitmap are rejected
+    #[test]
+    fn test_invalid_stream() {
+        assert!(interleaved_rle_decompress(&[0x65, 0x01], 2, 2, 8).is_err());
+        assert!(interleaved_rle_decompress(&[0x83, 0x01], 2, 2, 8).is_err());
+        assert!(interleaved_rle_decompress(&[0xF5], 2, 2, 8).is_err());
+        assert!(interleaved_rle_decompress(&[], 2, 2, 32).is_err());
+    }
+}
@@ -1 +1,13 @@
-pub mod rle;
\ No newline at end of file
+pub mod rle;
+pub mod interleaved;
+pub mod color;
+pub mod planar;
+pub mod nsc;
+pub mod clearcodec;
+pub mod h264;
+pub mod zgfx;
+pub mod bulk;
+pub mod mppc;
+pub mod xcrush;
+pub mod audio;
+pub mod pool;
@@ -0,0 +1,389 @@
+use crate::codec::bulk::{
+    CompressionType, COMPRESSION_TYPE_MASK, PACKET_AT_FRONT, PACKET_COMPRESSED, PACKET_FLUSHED,
+};
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+/// History sizes of RDP 4.0 and RDP 5.0
+const HISTORY_SIZE_8K: usize = 8192;
+const HISTORY_SIZE_64K: usize = 65536;
+
+/// Matches are at least this long
+const MIN_MATCH_LENGTH: usize = 3;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Both MPPC variants use the same encoding,
+/// RDP 5.0 has longer offsets and lengths
+fn is_large(flags: u8) -> Result<bool> {
+    match CompressionType::try_from(flags & COMPRESSION_TYPE_MASK) {
+        Ok(CompressionType::PacketComprType8k) => Ok(false),
+        Ok(CompressionType::PacketComprType64k) => Ok(true),
+        _ => Err(Error::new(
+            ErrorKind::Unsupported,
+            "MPPC: unsupported compression type",
+        )),
+    }
+}
+
+fn history_size(large: bool) -> usize {
+    if large {
+        HISTORY_SIZE_64K
+    } else {
+        HISTORY_SIZE_8K
+    }
+}
+
+/// Bits of compressed data, most significant first
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Bits<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.position
+    }
+
+    fn read(&mut self, count: u32) -> Result<usize> {
+        if count as usize > self.remaining() {
+            return Err(invalid("MPPC: truncated data"));
+        }
+        let mut value = 0;
+        for _ in 0..count {
+            let bit = (self.data[self.position / 8] >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as usize;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    /// Number of set bits before the next clear one,
+    /// the clear bit isn't read after the maximum
+    fn read_ones(&mut self, max: u32) -> Result<u32> {
+        let mut count = 0;
+        while count < max && self.read(1)? == 1 {
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Decompressor of MPPC data
+/// MS-RDPBCGR 3.1.8.4 Decompressing Data
+#[derive(Clone)]
+pub struct MppcDecompressor {
+    history: Vec<u8>,
+    /// Next position written in the history
+    index: usize,
+}
+
+impl Default for MppcDecompressor {
+    fn default() -> Self {
+        MppcDecompressor {
+            history: vec![0; HISTORY_SIZE_64K],
+            index: 0,
+        }
+    }
+}
+
+impl MppcDecompressor {
+    /// Decompress data with the flags of its header
+    /// Data is returned as is when the compressed flag isn't set
+    pub fn decompress(&mut self, data: &[u8], flags: u8) -> Result<Vec<u8>> {
+        let large = is_large(flags)?;
+        if flags & PACKET_AT_FRONT != 0 {
+            self.index = 0;
+        }
+        if flags & PACKET_FLUSHED != 0 {
+            self.history.fill(0);
+            self.index = 0;
+        }
+        if flags & PACKET_COMPRESSED == 0 {
+            return Ok(data.to_vec());
+        }
+
+        let size = history_size(large);
+        let start = self.index;
+        let mut bits = Bits { data, position: 0 };
+        // Less than a byte left is padding
+        while bits.remaining() >= 8 {
+            let literal = match bits.read(2)? {
+                0b00 | 0b01 => {
+                    bits.position -= 1;
+                    Some(bits.read(7)? as u8)
+                }
+                0b10 => Some(bits.read(7)? as u8 | 0x80),
+                _ => None,
+            };
+            if let Some(literal) = literal {
+                if self.index == size {
+                    return Err(invalid("MPPC: history is full"));
+                }
+                self.history[self.index] = literal;
+                self.index += 1;
+                continue;
+            }
+
+            let offset = match (large, bits.read_ones(if large { 3 } else { 2 })?) {
+                (true, 3) => bits.read(6)?,
+                (true, 2) => bits.read(8)? + 64,
+                (true, 1) => bits.read(11)? + 320,
+                (true, _) => bits.read(16)? + 2368,
+                (false, 2) => bits.read(6)?,
+                (false, 1) => bits.read(8)? + 64,
+                (false, _) => bits.read(13)? + 320,
+            };
+            let max_ones = if large { 14 } else { 11 };
+            let length = match bits.read_ones(max_ones + 1)? {
+                0 => MIN_MATCH_LENGTH,
+                ones if ones <= max_ones => (1 << (ones + 1)) + bits.read(ones + 1)?,
+                _ => return Err(invalid("MPPC: invalid length of match")),
+            };
+
+            if offset == 0 || offset > self.index || self.index + length > size {
+                return Err(invalid("MPPC: invalid match"));
+            }
+            for _ in 0..length {
+                self.history[self.index] = self.history[self.index - offset];
+                self.index += 1;
+            }
+        }
+        Ok(self.history[start..self.index].to_vec())
+    }
+}
+
+/// Bits of compressed data, most significant first
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    count: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: usize, count: u32) {
+        for i in (0..count).rev() {
+            if self.count % 8 == 0 {
+                self.data.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.data.last_mut().unwrap() |= bit << (7 - self.count % 8);
+            self.count += 1;
+        }
+    }
+}
+
+/// Compressor of MPPC data, for the server side
+/// MS-RDPBCGR 3.1.8.1 Abstract Data Model
+///
+/// # Example
+/// ```
+/// use rdp::codec::bulk::CompressionType;
+/// use rdp::codec::mppc::{MppcCompressor, MppcDecompressor};
+/// let mut compressor = MppcCompressor::new(CompressionType::PacketComprType64k).unwrap();
+/// let mut decompressor = MppcDecompressor::default();
+/// let data = b"abcabcabcabcabcabcabcabc";
+/// let (flags, compressed) = compressor.compress(data);
+/// assert!(compressed.len() < data.len());
+/// assert_eq!(decompressor.decompress(&compressed, flags).unwrap(), data);
+/// ```
+pub struct MppcCompressor {
+    compression_type: CompressionType,
+    history: Vec<u8>,
+    index: usize,
+    /// Last position of each sequence of 3 bytes
+    positions: HashMap<[u8; 3], usize>,
+}
+
+impl MppcCompressor {
+    /// Only the MPPC compression types are accepted
+    pub fn new(compression_type: CompressionType) -> Result<Self> {
+        let large = is_large(compression_type as u8)?;
+        Ok(MppcCompressor {
+            compression_type,
+            history: vec![0; history_size(large)],
+            index: 0,
+            positions: HashMap::new(),
+        })
+    }
+
+    fn reset(&mut self) {
+        self.history.fill(0);
+        self.index = 0;
+        self.positions.clear();
+    }
+
+    /// Compress data, return the flags of its header with the data to send
+    /// Data which doesn't shrink is sent as is and the history is flushed
+    pub fn compress(&mut self, data: &[u8]) -> (u8, Vec<u8>) {
+        let large = self.compression_type == CompressionType::PacketComprType64k;
+        let size = self.history.len();
+        let mut flags = self.compression_type as u8;
+        if data.len() > size {
+            self.reset();
+            return (flags | PACKET_FLUSHED, data.to_vec());
+        }
+        if self.index + data.len() > size {
+            self.index = 0;
+            flags |= PACKET_AT_FRONT;
+        }
+        let start = self.index;
+        let end = start + data.len();
+        self.history[start..end].copy_from_slice(data);
+
+        let (max_offset, max_length) = if large {
+            (HISTORY_SIZE_64K - 1, HISTORY_SIZE_64K - 1)
+        } else {
+            (HISTORY_SIZE_8K - 1, HISTORY_SIZE_8K - 1)
+        };
+        let mut writer = BitWriter::default();
+        let mut position = start;
+        while position < end {
+            let (offset, length) = self.find_match(position, end, max_offset, max_length);
+            if length < MIN_MATCH_LENGTH {
+                let literal = self.history[position] as usize;
+                if literal < 0x80 {
+                    writer.write(literal, 8);
+                } else {
+                    writer.write(0b10, 2);
+                    writer.write(literal & 0x7F, 7);
+                }
+                self.remember(position, end);
+                position += 1;
+                continue;
+            }
+
+            match (large, offset) {
+                (true, 0..=63) => writer.write((0b11111 << 6) | offset, 11),
+                (true, 64..=319) => writer.write((0b11110 << 8) | (offset - 64), 13),
+                (true, 320..=2367) => writer.write((0b1110 << 11) | (offset - 320), 15),
+                (true, _) => writer.write((0b110 << 16) | (offset - 2368), 19),
+                (false, 0..=63) => writer.write((0b1111 << 6) | offset, 10),
+                (false, 64..=319) => writer.write((0b1110 << 8) | (offset - 64), 12),
+                (false, _) => writer.write((0b110 << 13) | (offset - 320), 16),
+            }
+            if length == MIN_MATCH_LENGTH {
+                writer.write(0, 1);
+            } else {
+                // Ones, a zero, then the bits below the highest one
+                let ones = usize::BITS - length.leading_zeros() - 2;
+                writer.write(((1 << ones) - 1) << 1, ones + 1);
+                writer.write(length - (1 << (ones + 1)), ones + 1);
+            }
+            for position in position..position + length {
+                self.remember(position, end);
+            }
+            position += length;
+        }
+
+        if writer.data.len() >= data.len() {
+            self.reset();
+            return (flags | PACKET_FLUSHED, data.to_vec());
+        }
+        self.index = end;
+        (flags | PACKET_COMPRESSED, writer.data)
+    }
+
+    fn remember(&mut self, position: usize, end: usize) {
+        if position + MIN_MATCH_LENGTH <= end {
+            let key = [
+                self.history[position],
+                self.history[position + 1],
+                self.history[position + 2],
+            ];
+            self.positions.insert(key, position);
+        }
+    }
+
+    /// Longest match with the last occurrence of the next 3 bytes
+    fn find_match(
+        &self,
+        position: usize,
+        end: usize,
+        max_offset: usize,
+        max_length: usize,
+    ) -> (usize, usize) {
+        if position + MIN_MATCH_LENGTH > end {
+            return (0, 0);
+        }
+        let key = [
+            self.history[position],
+            self.history[position + 1],
+            self.history[position + 2],
+        ];
+        let candidate = match self.positions.get(&key) {
+            Some(&candidate) if candidate < position && position - candidate <= max_offset => {
+                candidate
+            }
+            _ => return (0, 0),
+        };
+        // Positions may be stale after the history moved to the front
+        let mut length = 0;
+        while position + length < end
+            && length < max_length
+            && self.history[candidate + length] == self.history[position + length]
+        {
+            length += 1;
+        }
+        (position - candidate, length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Literals and a match of both history sizes
+    #[test]
+    fn test_mppc_decompress() {
+        let mut mppc = MppcDecompressor::default();
+        let data = [0x61, 0x62, 0x63, 0xF0, 0xD6, 0x90];
+        assert_eq!(
+            mppc.decompress(&data, PACKET_COMPRESSED).unwrap(),
+            b"abcabc\xE9"
+        );
+
+        let flags = PACKET_COMPRESSED | PACKET_FLUSHED | 0x1;
+        let data = [0x61, 0x62, 0x63, 0xF8, 0x78, 0x80];
+        assert_eq!(mppc.decompress(&data, flags).unwrap(), b"abcabcabcabc");
+
+        // Match before the start of the history
+        let data = [0xF0, 0xD0];
+        assert!(mppc
+            .decompress(&data, PACKET_COMPRESSED | PACKET_FLUSHED)
+            .is_err());
+    }
+
+    /// Compressed data of many packets, with history resets
+    #[test]
+    fn test_mppc_round_trip() {
+        for compression_type in [
+            CompressionType::PacketComprType8k,
+            CompressionType::PacketComprType64k,
+        ] {
+            let mut compressor = MppcCompressor::new(compression_type).unwrap();
+            let mut decompressor = MppcDecompressor::default();
+            let mut compressed = false;
+            for i in 0..64u32 {
+                let data: Vec<u8> = if i % 10 == 9 {
+                    // Noise which doesn't compress
+                    (0..1000u32)
+                        .map(|j| (j.wrapping_mul(2654435761) >> 13) as u8)
+                        .collect()
+                } else {
+                    (0..1000u32)
+                        .map(|j| ((j * (i % 5 + 1)) % 251) as u8 ^ (((i as u8) << 2) & 0x80))
+                        .collect()
+                };
+                let (flags, output) = compressor.compress(&data);
+                compressed |= flags & PACKET_COMPRESSED != 0;
+                assert_eq!(decompressor.decompress(&output, flags).unwrap(), data);
+            }
+            assert!(compressed);
+        }
+        assert!(MppcCompressor::new(CompressionType::PacketComprTypeRdp6).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::codec::planar::ycocg_to_rgb;
+
+use std::io::{Error, ErrorKind, Result};
+
+/// Size of the NSCodec header
+/// MS-RDPNSC 2.2.1 NSCodec Bitmap Stream (NSCODEC_BITMAP_STREAM)
+const HEADER_SIZE: usize = 20;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// One plane, run length encoded
+/// A byte repeated twice starts a run, the last 4 bytes are raw
+/// MS-RDPNSC 2.2.2 NSCodec RLE Compressed Plane (NSCODEC_RLE_DECOMPRESSION)
+fn decode_rle_plane(input: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut source = input;
+    let read_u8 = |source: &mut &[u8]| {
+        let (byte, rest) = source
+            .split_first()
+            .ok_or_else(|| invalid("NSCODEC: truncated plane"))?;
+        *source = rest;
+        Ok::<u8, Error>(*byte)
+    };
+
+    let mut plane = Vec::with_capacity(size);
+    while plane.len() + 4 < size {
+        let value = read_u8(&mut source)?;
+        if plane.len() + 5 == size || source.first() != Some(&value) {
+            plane.push(value);
+            continue;
+        }
+        read_u8(&mut source)?;
+        let run_length = match read_u8(&mut source)? {
+            0xFF => {
+                let mut length = [0; 4];
+                for byte in length.iter_mut() {
+                    *byte = read_u8(&mut source)?;
+                }
+                u32::from_le_bytes(length) as usize
+            }
+            length => length as usize + 2,
+        };
+        if plane.len() + run_length > size {
+            return Err(invalid("NSCODEC: run goes past the plane"));
+        }
+        plane.resize(plane.len() + run_length, value);
+    }
+    while plane.len() < size {
+        plane.push(read_u8(&mut source)?);
+    }
+    Ok(plane)
+}
+
+/// Decompress a bitmap of NSCodec
+/// MS-RDPNSC 3.1.8 Decoding
+///
+/// Planes are luma, orange chroma, green chroma and alpha,
+/// chroma planes may be subsampled
+///
+/// Pixels are BGRA, rows are top down
+pub fn nsc_decompress(input: &[u8], width: u16, height: u16) -> Result<Vec<u8>> {
+    if input.len() < HEADER_SIZE {
+        return Err(invalid("NSCODEC: truncated header"));
+    }
+    let mut plane_byte_counts = [0; 4];
+    for (index, count) in plane_byte_counts.iter_mut().enumerate() {
+        let bytes = &input[index * 4..index * 4 + 4];
+        *count = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    }
+    let color_loss = input[16];
+    let subsampling = input[17] != 0;
+    if !(1..=7).contains(&color_loss) {
+        return Err(invalid("NSCODEC: invalid color loss level"));
+    }
+
+    let width = width as usize;
+    let height = height as usize;
+    // Subsampled luma lines are padded to 8 pixels
+    // and chroma planes have half of the padded size
+    let (luma_width, chroma_width, chroma_height) = if subsampling {
+        let luma_width = (width + 7) / 8 * 8;
+        (luma_width, luma_width / 2, (height + 1) / 2)
+    } else {
+        (width, width, height)
+    };
+    let plane_sizes = [
+        luma_width * height,
+        chroma_width * chroma_height,
+        chroma_width * chroma_height,
+        width * height,
+    ];
+
+    let mut planes = Vec::with_capacity(4);
+    let mut source = &input[HEADER_SIZE..];
+    for (byte_count, size) in plane_byte_counts.into_iter().zip(plane_sizes) {
+        if source.len() < byte_count {
+            return Err(invalid("NSCODEC: truncated plane"));
+        }
+        let (data, rest) = source.split_at(byte_count);
+        source = rest;
+        planes.push(if byte_count == 0 {
+            vec![0xFF; size]
+        } else if byte_count < size {
+            decode_rle_plane(data, size)?
+        } else {
+            data[..size].to_vec()
+        });
+    }
+
+    let mut output = vec![0; width * height * 4];
+    for (index, pixel) in output.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (index % width, index / width);
+        let chroma = if subsampling {
+            (y / 2) * chroma_width + x / 2
+        } else {
+            index
+        };
+        let [red, green, blue] = ycocg_to_rgb(
+            planes[0][y * luma_width + x],
+            planes[1][chroma],
+            planes[2][chroma],
+            color_loss,
+        );
+        pixel.copy_from_slice(&[blue, green, red, planes[3][index]]);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Runs are 2 equal bytes followed by the length
+    #[test]
+    fn test_decode_rle_plane() {
+        let data = [0x10, 0x10, 0x03, 0x20, 0x01, 0x02, 0x03, 0x04];
+        assert_eq!(
+            decode_rle_plane(&data, 10).unwrap(),
+            vec![0x10, 0x10, 0x10, 0x10, 0x10, 0x20, 0x01, 0x02, 0x03, 0x04]
+        );
+        assert!(decode_rle_plane(&data[..6], 10).is_err());
+    }
+
+    /// Raw planes, missing alpha plane is opaque
+    #[test]
+    fn test_nsc_decompress() {
+        let mut input = vec![];
+        for count in [2u32, 2, 2, 0] {
+            input.extend_from_slice(&count.to_le_bytes());
+        }
+        input.extend_from_slice(&[1, 0, 0, 0]);
+        input.extend_from_slice(&[0x40, 0x80, 0x02, 0x00, 0x00, 0x00]);
+        let data = nsc_decompress(&input, 2, 1).unwrap();
+        assert_eq!(data, vec![0x3E, 0x40, 0x42, 0xFF, 0x80, 0x80, 0x80, 0xFF]);
+    }
+}
@@ -0,0 +1,210 @@
+use std::io::{Error, ErrorKind, Result};
+
+/// Fields of the format header
+/// MS-RDPEGDI 2.2.2.5.1 RDP 6.0 Bitmap Compressed Bitmap Stream (RDP6_BITMAP_STREAM)
+const FORMAT_HEADER_CLL_MASK: u8 = 0x07;
+const FORMAT_HEADER_CS: u8 = 0x08;
+const FORMAT_HEADER_RLE: u8 = 0x10;
+const FORMAT_HEADER_NA: u8 = 0x20;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_u8(source: &mut &[u8]) -> Result<u8> {
+    let (byte, rest) = source
+        .split_first()
+        .ok_or_else(|| invalid("PLANAR: truncated stream"))?;
+    *source = rest;
+    Ok(*byte)
+}
+
+/// YCoCg to RGB
+/// Chroma values were shifted right by the color loss level,
+/// the shift by one less builds in the halving of the chroma
+/// MS-RDPEGDI 3.1.9.1.2 Color Loss Reduction
+pub(crate) fn ycocg_to_rgb(luma: u8, co: u8, cg: u8, color_loss: u8) -> [u8; 3] {
+    let shift = color_loss.saturating_sub(1);
+    let co = (co << shift) as i8 as i16;
+    let cg = (cg << shift) as i8 as i16;
+    let luma = luma as i16;
+    let clamp = |value: i16| value.clamp(0, 0xFF) as u8;
+    [
+        clamp(luma - cg + co),
+        clamp(luma + cg),
+        clamp(luma - cg - co),
+    ]
+}
+
+/// One color plane, run length encoded
+/// The first scanline holds values, the others deltas from
+/// the previous scanline, stored as sign and magnitude
+/// MS-RDPEGDI 2.2.2.5.1.1 RDP 6.0 Run Length Encoded Color Plane (RDP6_RLE_PLANE)
+fn decode_rle_plane(source: &mut &[u8], width: usize, height: usize) -> Result<Vec<u8>> {
+    let mut plane = vec![0; width * height];
+    for y in 0..height {
+        let mut x = 0;
+        let mut value = 0u8;
+        let mut delta = 0u8;
+        while x < width {
+            let control = read_u8(source)?;
+            let raw_bytes = (control >> 4) as usize;
+            // Long runs without raw bytes
+            let (raw_bytes, run_length) = match control & 0x0F {
+                1 => (0, 16 + raw_bytes),
+                2 => (0, 32 + raw_bytes),
+                run_length => (raw_bytes, run_length as usize),
+            };
+            if x + raw_bytes + run_length > width {
+                return Err(invalid("PLANAR: segment goes past the scanline"));
+            }
+
+            for index in 0..raw_bytes + run_length {
+                if index < raw_bytes {
+                    let byte = read_u8(source)?;
+                    if y == 0 {
+                        value = byte;
+                    } else if byte & 1 != 0 {
+                        delta = ((byte >> 1) + 1).wrapping_neg();
+                    } else {
+                        delta = byte >> 1;
+                    }
+                }
+                let offset = y * width + x;
+                plane[offset] = if y == 0 {
+                    value
+                } else {
+                    plane[offset - width].wrapping_add(delta)
+                };
+                x += 1;
+            }
+        }
+    }
+    Ok(plane)
+}
+
+/// One color plane, stored as is
+fn decode_raw_plane(source: &mut &[u8], width: usize, height: usize) -> Result<Vec<u8>> {
+    if source.len() < width * height {
+        return Err(invalid("PLANAR: truncated raw plane"));
+    }
+    let (plane, rest) = source.split_at(width * height);
+    *source = rest;
+    Ok(plane.to_vec())
+}
+
+/// Decompress a bitmap of the planar codec
+/// MS-RDPEGDI 3.1.9 RDP 6.0 Bitmap Compression
+///
+/// Planes are ARGB or, with a color loss level, AYCoCg
+/// with optionally subsampled chroma planes
+///
+/// Pixels are BGRA, rows keep the order of the stream,
+/// bottom up for bitmap updates
+///
+/// # Example
+/// ```
+/// use rdp::codec::planar::planar_decompress;
+/// // Raw planes without alpha, one red pixel
+/// let data = planar_decompress(&[0x20, 0xFF, 0x00, 0x00, 0x00], 1, 1).unwrap();
+/// assert_eq!(data, vec![0x00, 0x00, 0xFF, 0xFF]);
+/// ```
+pub fn planar_decompress(input: &[u8], width: u16, height: u16) -> Result<Vec<u8>> {
+    let mut source = input;
+    let header = read_u8(&mut source)?;
+    let color_loss = header & FORMAT_HEADER_CLL_MASK;
+    let subsampling = header & FORMAT_HEADER_CS != 0;
+    if subsampling && color_loss == 0 {
+        return Err(invalid("PLANAR: chroma subsampling without YCoCg"));
+    }
+
+    let width = width as usize;
+    let height = height as usize;
+    let (chroma_width, chroma_height) = if subsampling {
+        ((width + 1) / 2, (height + 1) / 2)
+    } else {
+        (width, height)
+    };
+    let decode_plane = if header & FORMAT_HEADER_RLE != 0 {
+        decode_rle_plane
+    } else {
+        decode_raw_plane
+    };
+
+    let alpha = if header & FORMAT_HEADER_NA == 0 {
+        Some(decode_plane(&mut source, width, height)?)
+    } else {
+        None
+    };
+    let first = decode_plane(&mut source, width, height)?;
+    let second = decode_plane(&mut source, chroma_width, chroma_height)?;
+    let third = decode_plane(&mut source, chroma_width, chroma_height)?;
+    // A pad byte may follow raw planes, it's ignored
+
+    let mut output = vec![0; width * height * 4];
+    for (index, pixel) in output.chunks_exact_mut(4).enumerate() {
+        let [red, green, blue] = if color_loss == 0 {
+            [first[index], second[index], third[index]]
+        } else {
+            let chroma = if subsampling {
+                (index / width / 2) * chroma_width + (index % width) / 2
+            } else {
+                index
+            };
+            ycocg_to_rgb(first[index], second[chroma], third[chroma], color_loss)
+        };
+        let alpha = alpha.as_ref().map_or(0xFF, |alpha| alpha[index]);
+        pixel.copy_from_slice(&[blue, green, red, alpha]);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Runs repeat the last value or delta
+    #[test]
+    fn test_decode_rle_plane() {
+        // A raw byte then a run of 3, the second line is +2
+        let data = [0x13, 0x10, 0x13, 0x04];
+        let plane = decode_rle_plane(&mut &data[..], 4, 2).unwrap();
+        assert_eq!(plane, vec![0x10, 0x10, 0x10, 0x10, 0x12, 0x12, 0x12, 0x12]);
+
+        // A run of 16 is longer than the line
+        let data = [0x10, 0x07, 0x01];
+        assert!(decode_rle_plane(&mut &data[..], 4, 2).is_err());
+    }
+
+    /// First line of 20 pixels as a single run of a raw value
+    /// then a line of negative deltas
+    #[test]
+    fn test_planar_rle_long_run() {
+        let mut input = vec![0x30];
+        for value in [0x80u8, 0x40, 0x00] {
+            // 1 raw byte then a run of 3 + 16 on the first line
+            input.extend_from_slice(&[0x10, value, 0x31]);
+            // -1 for the whole second line
+            input.extend_from_slice(&[0x10, 0x01, 0x31]);
+        }
+        let data = planar_decompress(&input, 20, 2).unwrap();
+        assert_eq!(&data[..4], &[0x00, 0x40, 0x80, 0xFF]);
+        assert_eq!(&data[76..80], &[0x00, 0x40, 0x80, 0xFF]);
+        assert_eq!(&data[80..84], &[0xFF, 0x3F, 0x7F, 0xFF]);
+        assert_eq!(&data[156..160], &[0xFF, 0x3F, 0x7F, 0xFF]);
+    }
+
+    /// Subsampled YCoCg planes, chroma is shared by 2x2 pixels
+    #[test]
+    fn test_planar_ycocg_subsampled() {
+        // Color loss level 1, subsampling, raw, no alpha
+        // then the luma, orange chroma and green chroma planes
+        let input = [0x29, 0x10, 0x20, 0x30, 0x40, 0x04, 0xFE];
+        let data = planar_decompress(&input, 2, 2).unwrap();
+        // R = Y - Cg + Co, G = Y + Cg, B = Y - Cg - Co
+        assert_eq!(&data[..4], &[0x0E, 0x0E, 0x16, 0xFF]);
+        assert_eq!(&data[12..], &[0x3E, 0x3E, 0x46, 0xFF]);
+        assert_eq!(ycocg_to_rgb(0x80, 0x00, 0x00, 3), [0x80, 0x80, 0x80]);
+        assert!(planar_decompress(&[0x28, 0x00], 1, 1).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::Result;
+#[cfg(feature = "parallel")]
+use std::io::{Error, ErrorKind};
+
+/// Workers decoding the independent tiles of a frame
+/// The results are given back in the order of the tiles,
+/// so they are drawn as if they were decoded one after the other
+///
+/// Without the parallel feature, or with a single thread,
+/// the tiles are decoded on the calling thread
+///
+/// # Example
+/// ```
+/// use rdp::codec::pool::DecodePool;
+/// let pool = DecodePool::new(4).unwrap();
+/// let decoded = pool.decode(vec![1, 2, 3], |tile| tile * 2);
+/// assert_eq!(decoded, vec![2, 4, 6]);
+/// ```
+#[derive(Default)]
+pub struct DecodePool {
+    #[cfg(feature = "parallel")]
+    pool: Option<rayon::ThreadPool>,
+}
+
+impl DecodePool {
+    /// Decode on the calling thread
+    pub fn sequential() -> Self {
+        DecodePool::default()
+    }
+
+    /// Pool of `threads` workers, 0 for one by CPU
+    #[cfg(feature = "parallel")]
+    pub fn new(threads: usize) -> Result<Self> {
+        if threads == 1 {
+            return Ok(DecodePool::sequential());
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("rdp-decode-{}", index))
+            .build()
+            .map_err(|e| Error::new(ErrorKind::Other, format!("DECODE: {}", e)))?;
+        Ok(DecodePool { pool: Some(pool) })
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    pub fn new(_threads: usize) -> Result<Self> {
+        Ok(DecodePool::sequential())
+    }
+
+    /// Number of tiles decoded at the same time
+    #[cfg(feature = "parallel")]
+    pub fn threads(&self) -> usize {
+        self.pool
+            .as_ref()
+            .map_or(1, rayon::ThreadPool::current_num_threads)
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    pub fn threads(&self) -> usize {
+        1
+    }
+
+    /// Decode all the tiles, the results are in the order of the tiles
+    pub fn decode<T, R, F>(&self, tiles: Vec<T>, decode: F) -> Vec<R>
+    where
+        T: Send,
+        R: Send,
+        F: Fn(T) -> R + Send + Sync,
+    {
+        #[cfg(feature = "parallel")]
+        if let Some(pool) = &self.pool {
+            use rayon::prelude::*;
+            if tiles.len() > 1 {
+                return pool.install(|| tiles.into_par_iter().map(decode).collect());
+            }
+        }
+        tiles.into_iter().map(decode).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Results keep the order of the tiles whatever the decode time
+    #[test]
+    fn test_decode_pool_order() {
+        let pool = DecodePool::new(0).unwrap();
+        let tiles = (0..64u64).collect::<Vec<_>>();
+        let decoded = pool.decode(tiles, |tile| {
+            std::thread::sleep(std::time::Duration::from_micros((64 - tile) * 10));
+            tile * tile
+        });
+        assert_eq!(
+            decoded,
+            (0..64u64).map(|tile| tile * tile).collect::<Vec<_>>()
+        );
+        assert!(pool.threads() >= 1);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::codec::bulk::{
+    CompressionType, COMPRESSION_TYPE_MASK, PACKET_COMPRESSED, PACKET_FLUSHED,
+};
+use crate::codec::mppc::MppcDecompressor;
+
+use std::io::{Error, ErrorKind, Result};
+
+/// Flags of the level 1 compressor
+/// MS-RDPEGDI 2.2.2.4.1 RDP61_COMPRESSED_DATA
+pub const L1_COMPRESSED: u8 = 0x01;
+pub const L1_NO_COMPRESSION: u8 = 0x02;
+pub const L1_PACKET_AT_FRONT: u8 = 0x04;
+pub const L1_INNER_COMPRESSION: u8 = 0x10;
+
+/// Size of the level 1 history
+const HISTORY_SIZE: usize = 2_000_000;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Decompressor of the RDP 6.1 bulk compressor
+/// Level 1 copies long matches of a large history,
+/// its output may be compressed again with MPPC by level 2
+/// MS-RDPEGDI 3.1.8.2 RDP 6.1 Bulk Compression
+///
+/// # Example
+/// ```
+/// use rdp::codec::xcrush::{XcrushDecompressor, L1_COMPRESSED};
+/// let mut xcrush = XcrushDecompressor::default();
+/// // A match of 3 bytes at the start of the history, after the literals "abc"
+/// let data = [L1_COMPRESSED, 0x00, 1, 0, 3, 0, 3, 0, 0, 0, 0, 0, b'a', b'b', b'c'];
+/// assert_eq!(xcrush.decompress(&data, 0).unwrap(), b"abcabc");
+/// ```
+pub struct XcrushDecompressor {
+    history: Vec<u8>,
+    /// Next position written in the history
+    index: usize,
+    mppc: MppcDecompressor,
+}
+
+impl Default for XcrushDecompressor {
+    fn default() -> Self {
+        XcrushDecompressor {
+            history: vec![0; HISTORY_SIZE],
+            index: 0,
+            mppc: MppcDecompressor::default(),
+        }
+    }
+}
+
+impl XcrushDecompressor {
+    /// Decompress data starting with both levels flags,
+    /// the flags of the bulk header are only used to reset the history
+    pub fn decompress(&mut self, data: &[u8], flags: u8) -> Result<Vec<u8>> {
+        if flags & PACKET_FLUSHED != 0 {
+            self.history.fill(0);
+            self.index = 0;
+        }
+        if data.len() < 2 {
+            return Err(invalid("XCRUSH: truncated header"));
+        }
+        let (level1_flags, level2_flags) = (data[0], data[1]);
+        let data = &data[2..];
+
+        // Level 2 is always MPPC with a 64 KB history
+        let data = if level2_flags & PACKET_COMPRESSED != 0 {
+            let level2_flags =
+                (level2_flags & !COMPRESSION_TYPE_MASK) | CompressionType::PacketComprType64k as u8;
+            self.mppc.decompress(data, level2_flags)?
+        } else {
+            data.to_vec()
+        };
+
+        if level1_flags & L1_COMPRESSED == 0 {
+            return Ok(data);
+        }
+        if level1_flags & L1_PACKET_AT_FRONT != 0 {
+            self.index = 0;
+        }
+        self.decompress_level1(&data)
+    }
+
+    /// Literals are copied between the matches
+    /// MS-RDPEGDI 2.2.2.4.1.1 RDP61_MATCH_DETAILS
+    fn decompress_level1(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < 2 {
+            return Err(invalid("XCRUSH: truncated match count"));
+        }
+        let match_count = u16::from_le_bytes([data[0], data[1]]) as usize;
+        if data.len() < 2 + match_count * 8 {
+            return Err(invalid("XCRUSH: truncated match details"));
+        }
+        let (details, literals) = data[2..].split_at(match_count * 8);
+
+        let start = self.index;
+        let mut literals = literals.iter();
+        for detail in details.chunks_exact(8) {
+            let length = u16::from_le_bytes([detail[0], detail[1]]) as usize;
+            let output_offset = u16::from_le_bytes([detail[2], detail[3]]) as usize;
+            let history_offset =
+                u32::from_le_bytes([detail[4], detail[5], detail[6], detail[7]]) as usize;
+
+            let written = self.index - start;
+            if output_offset < written {
+                return Err(invalid("XCRUSH: invalid match output offset"));
+            }
+            for _ in written..output_offset {
+                let literal = *literals
+                    .next()
+                    .ok_or_else(|| invalid("XCRUSH: truncated literals"))?;
+                self.push(literal)?;
+            }
+            if history_offset + length > HISTORY_SIZE {
+                return Err(invalid("XCRUSH: invalid match history offset"));
+            }
+            // The copy may overlap what it writes
+            for source in history_offset..history_offset + length {
+                let byte = self.history[source];
+                self.push(byte)?;
+            }
+        }
+        for literal in literals {
+            self.push(*literal)?;
+        }
+        Ok(self.history[start..self.index].to_vec())
+    }
+
+    fn push(&mut self, byte: u8) -> Result<()> {
+        if self.index == HISTORY_SIZE {
+            return Err(invalid("XCRUSH: history is full"));
+        }
+        self.history[self.index] = byte;
+        self.index += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Matches in the current and a previous packet
+    #[test]
+    fn test_xcrush_decompress_level1() {
+        let mut xcrush = XcrushDecompressor::default();
+        let mut data = vec![L1_COMPRESSED, 0x00, 2, 0];
+        // Overlapping match of "ab" at the start, then "ab" from the history
+        data.extend_from_slice(&[4, 0, 2, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&[2, 0, 7, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(b"abx");
+        assert_eq!(xcrush.decompress(&data, 0).unwrap(), b"abababxab");
+
+        let data = [L1_COMPRESSED, 0x00, 1, 0, 3, 0, 1, 0, 6, 0, 0, 0, b'-'];
+        assert_eq!(xcrush.decompress(&data, 0).unwrap(), b"-xab");
+        // A flushed history is written from its start
+        let data = [L1_COMPRESSED, 0x00, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            xcrush.decompress(&data, PACKET_FLUSHED).unwrap(),
+            vec![0, 0]
+        );
+    }
+
+    /// Level 2 output is decompressed by level 1, invalid matches are rejected
+    #[test]
+    fn test_xcrush_decompress_level2() {
+        let mut xcrush = XcrushDecompressor::default();
+        // MPPC literals of an uncompressed level 1
+        let data = [L1_NO_COMPRESSION, PACKET_COMPRESSED, 0x61, 0x62];
+        assert_eq!(xcrush.decompress(&data, 0).unwrap(), b"ab");
+
+        let data = [L1_COMPRESSED, 0x00, 1, 0, 2, 0, 1, 0, 0, 0, 0, 0];
+        assert!(xcrush.decompress(&data, 0).is_err());
+        let data = [L1_COMPRESSED, 0x00, 1, 0, 2, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0];
+        assert!(xcrush.decompress(&data, 0).is_err());
+        assert!(xcrush.decompress(&[L1_COMPRESSED], 0).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::io::{Error, ErrorKind, Result};
+
+/// Flags of the bulk header, the compression type is in the low bits
+/// MS-RDPEGFX 2.2.5.3 RDP8_BULK_ENCODED_DATA
+pub const PACKET_COMPR_TYPE_RDP8: u8 = 0x04;
+pub const PACKET_COMPRESSED: u8 = 0x20;
+const COMPRESSION_TYPE_MASK: u8 = 0x0F;
+
+/// Size of the history buffer shared by all segments
+const HISTORY_SIZE: usize = 2_500_000;
+/// A segment never decompresses to more than this
+const MAX_SEGMENT_SIZE: usize = 65535;
+
+/// Tokens of the compressed stream, sorted by prefix length
+/// (prefix length, prefix code, value bits, is a match, value base)
+/// Literals are the base plus the value, matches give the distance
+/// MS-RDPEGFX 3.1.9.1.1 Decompressing Data
+#[rustfmt::skip]
+const TOKENS: [(u32, u32, u32, bool, u32); 40] = [
+    (1, 0b0, 8, false, 0x00),
+    (5, 0b10001, 5, true, 0),
+    (5, 0b10010, 7, true, 32),
+    (5, 0b10011, 9, true, 160),
+    (5, 0b10100, 10, true, 672),
+    (5, 0b10101, 12, true, 1696),
+    (5, 0b11000, 0, false, 0x00),
+    (5, 0b11001, 0, false, 0x01),
+    (6, 0b101100, 14, true, 5792),
+    (6, 0b101101, 15, true, 22176),
+    (6, 0b110100, 0, false, 0x02),
+    (6, 0b110101, 0, false, 0x03),
+    (6, 0b110110, 0, false, 0xFF),
+    (7, 0b1011100, 18, true, 54944),
+    (7, 0b1011101, 20, true, 317088),
+    (7, 0b1101110, 0, false, 0x04),
+    (7, 0b1101111, 0, false, 0x05),
+    (7, 0b1110000, 0, false, 0x06),
+    (7, 0b1110001, 0, false, 0x07),
+    (7, 0b1110010, 0, false, 0x08),
+    (7, 0b1110011, 0, false, 0x09),
+    (7, 0b1110100, 0, false, 0x0A),
+    (7, 0b1110101, 0, false, 0x0B),
+    (7, 0b1110110, 0, false, 0x3A),
+    (7, 0b1110111, 0, false, 0x3B),
+    (7, 0b1111000, 0, false, 0x3C),
+    (7, 0b1111001, 0, false, 0x3D),
+    (7, 0b1111010, 0, false, 0x3E),
+    (7, 0b1111011, 0, false, 0x3F),
+    (7, 0b1111100, 0, false, 0x40),
+    (7, 0b1111101, 0, false, 0x80),
+    (8, 0b10111100, 20, true, 1365664),
+    (8, 0b10111101, 21, true, 2414240),
+    (8, 0b11111100, 0, false, 0x0C),
+    (8, 0b11111101, 0, false, 0x38),
+    (8, 0b11111110, 0, false, 0x39),
+    (8, 0b11111111, 0, false, 0x66),
+    (9, 0b101111100, 22, true, 4511392),
+    (9, 0b101111101, 23, true, 8705696),
+    (9, 0b101111110, 24, true, 17094304),
+];
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Bits of the compressed stream, most significant first
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+    /// The last byte gives the number of unused bits before it
+    end: usize,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Result<Self> {
+        let (padding, data) = data
+            .split_last()
+            .ok_or_else(|| invalid("ZGFX: empty segment"))?;
+        let end = (data.len() * 8)
+            .checked_sub(*padding as usize)
+            .ok_or_else(|| invalid("ZGFX: invalid padding"))?;
+        Ok(Bits {
+            data,
+            position: 0,
+            end,
+        })
+    }
+
+    fn has_remaining(&self) -> bool {
+        self.position < self.end
+    }
+
+    fn read(&mut self, count: u32) -> Result<u32> {
+        if self.position + count as usize > self.end {
+            return Err(invalid("ZGFX: truncated segment"));
+        }
+        let mut value = 0;
+        for _ in 0..count {
+            let bit = (self.data[self.position / 8] >> (7 - self.position % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    /// Skip the unused bits of the current byte
+    fn align(&mut self) {
+        self.position = (self.position + 7) & !7;
+    }
+}
+
+/// Decompressor of the RDP 8.0 bulk compressor
+/// The history is kept between segments,
+/// they must be decompressed in the order of the stream
+///
+/// # Example
+/// ```
+/// use rdp::codec::zgfx::Zgfx;
+/// let mut zgfx = Zgfx::new();
+/// // Uncompressed segment
+/// assert_eq!(zgfx.decompress(&[0x04, 1, 2, 3]).unwrap(), vec![1, 2, 3]);
+/// ```
+pub struct Zgfx {
+    history: Vec<u8>,
+    /// Next position written in the history
+    index: usize,
+}
+
+impl Default for Zgfx {
+    fn default() -> Self {
+        Zgfx::new()
+    }
+}
+
+impl Zgfx {
+    pub fn new() -> Self {
+        Zgfx {
+            history: vec![0; HISTORY_SIZE],
+            index: 0,
+        }
+    }
+
+    fn push(&mut self, output: &mut Vec<u8>, byte: u8) -> Result<()> {
+        if output.len() == MAX_SEGMENT_SIZE {
+            return Err(invalid("ZGFX: segment is too large"));
+        }
+        output.push(byte);
+        self.history[self.index] = byte;
+        self.index = (self.index + 1) % HISTORY_SIZE;
+        Ok(())
+    }
+
+    /// Decompress a segment, starting with its header
+    /// MS-RDPEGFX 2.2.5.3 RDP8_BULK_ENCODED_DATA
+    pub fn decompress(&mut self, segment: &[u8]) -> Result<Vec<u8>> {
+        let (flags, data) = segment
+            .split_first()
+            .ok_or_else(|| invalid("ZGFX: empty segment"))?;
+        let mut output = Vec::with_capacity(data.len());
+        if flags & PACKET_COMPRESSED == 0 {
+            for byte in data {
+                self.push(&mut output, *byte)?;
+            }
+            return Ok(output);
+        }
+        if flags & COMPRESSION_TYPE_MASK != PACKET_COMPR_TYPE_RDP8 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("ZGFX: unsupported compression type {}", flags),
+            ));
+        }
+
+        let mut bits = Bits::new(data)?;
+        while bits.has_remaining() {
+            let (mut length, mut prefix) = (0, 0);
+            let token = TOKENS.iter().find_map(|token| {
+                while length < token.0 {
+                    prefix = (prefix << 1) | bits.read(1).ok()?;
+                    length += 1;
+                }
+                Some(token).filter(|token| token.1 == prefix)
+            });
+            let (_, _, value_bits, is_match, value_base) =
+                *token.ok_or_else(|| invalid("ZGFX: invalid token"))?;
+            let value = value_base + bits.read(value_bits)?;
+            if !is_match {
+                self.push(&mut output, value as u8)?;
+            } else if value != 0 {
+                self.copy_match(&mut bits, &mut output, value as usize)?;
+            } else {
+                // Bytes which are not encoded, from the next whole byte
+                let count = bits.read(15)?;
+                bits.align();
+                for _ in 0..count {
+                    let byte = bits.read(8)? as u8;
+                    self.push(&mut output, byte)?;
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Copy bytes of the history, the copy may overlap what it writes
+    fn copy_match(&mut self, bits: &mut Bits, output: &mut Vec<u8>, distance: usize) -> Result<()> {
+        if distance > HISTORY_SIZE {
+            return Err(invalid("ZGFX: invalid match distance"));
+        }
+        let count = if bits.read(1)? == 0 {
+            3
+        } else {
+            let (mut count, mut extra) = (4usize, 2);
+            while bits.read(1)? == 1 {
+                count *= 2;
+                extra += 1;
+                if count > MAX_SEGMENT_SIZE {
+                    return Err(invalid("ZGFX: invalid match length"));
+                }
+            }
+            count + bits.read(extra)? as usize
+        };
+        let mut source = (self.index + HISTORY_SIZE - distance) % HISTORY_SIZE;
+        for _ in 0..count {
+            let byte = self.history[source];
+            self.push(output, byte)?;
+            source = (source + 1) % HISTORY_SIZE;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Literals and a match, then a match in the previous segment
+    /// and bytes which are not encoded
+    #[test]
+    fn test_zgfx_decompress() {
+        let mut zgfx = Zgfx::new();
+        assert_eq!(
+            zgfx.decompress(&[0x24, 0x30, 0x98, 0x8C, 0x71, 0x1D, 0x60, 0x02])
+                .unwrap(),
+            b"abcabcabc\x00".to_vec()
+        );
+        assert_eq!(
+            zgfx.decompress(&[0x24, 0x8A, 0x91, 0x00, 0x00, 0x20, 0x78, 0x79, 0x00])
+                .unwrap(),
+            b"abcxy".to_vec()
+        );
+    }
+
+    /// Uncompressed segments are kept in the history
+    #[test]
+    fn test_zgfx_uncompressed_history() {
+        let mut zgfx = Zgfx::new();
+        assert_eq!(zgfx.decompress(&[0x04, b'a', b'b', b'c']).unwrap(), b"abc");
+        // Match of distance 3 and length 3, 5 unused bits
+        assert_eq!(zgfx.decompress(&[0x24, 0x88, 0xC0, 0x05]).unwrap(), b"abc");
+        // The segment ends in the middle of the match
+        assert!(zgfx.decompress(&[0x24, 0x88, 0xC0, 0x06]).is_err());
+        assert!(zgfx.decompress(&[0x21, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use crate::codec::audio::AudioFormat;
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the dynamic virtual channel of the audio input
+pub const AUDIN_CHANNEL_NAME: &str = "AUDIO_INPUT";
+
+/// Version of the client, format changes are supported from version 2
+/// MS-RDPEAI 2.2.2.1 Version PDU (MSG_SNDIN_VERSION)
+pub const SNDIN_VERSION: u32 = 0x00000002;
+
+/// Size of the open PDU without its format
+/// MS-RDPEAI 2.2.2.3 Open PDU (MSG_SNDIN_OPEN)
+const OPEN_SIZE: usize = 8;
+
+/// Id of an audio input PDU
+/// MS-RDPEAI 2.2.1 SNDIN_PDU Header (SNDIN_PDU)
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum SndinMessageId {
+    MsgSndinVersion = 0x01,
+    MsgSndinFormats = 0x02,
+    MsgSndinOpen = 0x03,
+    MsgSndinOpenReply = 0x04,
+    MsgSndinDataIncoming = 0x05,
+    MsgSndinData = 0x06,
+    MsgSndinFormatchange = 0x07,
+}
+
+/// PDU of the audio input channel, sent by both sides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudinPdu {
+    /// MS-RDPEAI 2.2.2.1 Version PDU (MSG_SNDIN_VERSION)
+    Version(u32),
+    /// Formats of the server, or the ones the client can capture
+    /// MS-RDPEAI 2.2.2.2 Sound Formats PDU (MSG_SNDIN_FORMATS)
+    Formats(Vec<AudioFormat>),
+    /// Index of the initial format in the formats of the client
+    /// MS-RDPEAI 2.2.2.3 Open PDU (MSG_SNDIN_OPEN)
+    Open {
+        frames_per_packet: u32,
+        initial_format: u32,
+        format: AudioFormat,
+    },
+    /// HRESULT of the open, zero on success
+    /// MS-RDPEAI 2.2.2.4 Open Reply PDU (MSG_SNDIN_OPEN_REPLY)
+    OpenReply(u32),
+    /// Sent before each data PDU
+    /// MS-RDPEAI 2.2.3.1 Incoming Data PDU (MSG_SNDIN_DATA_INCOMING)
+    DataIncoming,
+    /// Captured audio in the current format
+    /// MS-RDPEAI 2.2.3.2 Data PDU (MSG_SNDIN_DATA)
+    Data(Vec<u8>),
+    /// Index of the new format in the formats of the client
+    /// MS-RDPEAI 2.2.4.1 Format Change PDU (MSG_SNDIN_FORMATCHANGE)
+    FormatChange(u32),
+}
+
+impl AudinPdu {
+    fn message_id(&self) -> SndinMessageId {
+        match self {
+            AudinPdu::Version(_) => SndinMessageId::MsgSndinVersion,
+            AudinPdu::Formats(_) => SndinMessageId::MsgSndinFormats,
+            AudinPdu::Open { .. } => SndinMessageId::MsgSndinOpen,
+            AudinPdu::OpenReply(_) => SndinMessageId::MsgSndinOpenReply,
+            AudinPdu::DataIncoming => SndinMessageId::MsgSndinDataIncoming,
+            AudinPdu::Data(_) => SndinMessageId::MsgSndinData,
+            AudinPdu::FormatChange(_) => SndinMessageId::MsgSndinFormatchange,
+        }
+    }
+
+    fn data_length(&self) -> usize {
+        match self {
+            AudinPdu::Version(_) | AudinPdu::OpenReply(_) | AudinPdu::FormatChange(_) => 4,
+            AudinPdu::Formats(formats) => {
+                8 + formats.iter().map(AudioFormat::length).sum::<usize>()
+            }
+            AudinPdu::Open { format, .. } => OPEN_SIZE + format.length(),
+            AudinPdu::DataIncoming => 0,
+            AudinPdu::Data(data) => data.len(),
+        }
+    }
+}
+
+/// Read an audio input PDU, a whole message of the channel
+pub fn read_audin_pdu(buffer: &mut BytesMut) -> Result<AudinPdu> {
+    check_remaining(buffer, 1, "AUDIN: header")?;
+    let message_id = buffer.get_u8();
+    let pdu = match SndinMessageId::try_from(message_id) {
+        Ok(SndinMessageId::MsgSndinVersion) => {
+            check_remaining(buffer, 4, "AUDIN: version")?;
+            AudinPdu::Version(buffer.get_u32_le())
+        }
+        Ok(SndinMessageId::MsgSndinFormats) => {
+            check_remaining(buffer, 8, "AUDIN: formats")?;
+            let count = buffer.get_u32_le();
+            let _packet_size = buffer.get_u32_le();
+            let mut formats = Vec::new();
+            for _ in 0..count {
+                formats.push(AudioFormat::read(buffer)?);
+            }
+            AudinPdu::Formats(formats)
+        }
+        Ok(SndinMessageId::MsgSndinOpen) => {
+            check_remaining(buffer, OPEN_SIZE, "AUDIN: open")?;
+            AudinPdu::Open {
+                frames_per_packet: buffer.get_u32_le(),
+                initial_format: buffer.get_u32_le(),
+                format: AudioFormat::read(buffer)?,
+            }
+        }
+        Ok(SndinMessageId::MsgSndinOpenReply) => {
+            check_remaining(buffer, 4, "AUDIN: open reply")?;
+            AudinPdu::OpenReply(buffer.get_u32_le())
+        }
+        Ok(SndinMessageId::MsgSndinDataIncoming) => AudinPdu::DataIncoming,
+        Ok(SndinMessageId::MsgSndinData) => AudinPdu::Data(buffer.to_vec()),
+        Ok(SndinMessageId::MsgSndinFormatchange) => {
+            check_remaining(buffer, 4, "AUDIN: format change")?;
+            AudinPdu::FormatChange(buffer.get_u32_le())
+        }
+        Err(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("AUDIN: unexpected message id {}", message_id),
+            ))
+        }
+    };
+    // Extra data of the server formats is ignored
+    buffer.clear();
+    Ok(pdu)
+}
+
+#[async_trait]
+impl Message for AudinPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u8(self.message_id() as u8).await?;
+        match self {
+            AudinPdu::Version(value)
+            | AudinPdu::OpenReply(value)
+            | AudinPdu::FormatChange(value) => {
+                writer.write_u32_le(*value).await?;
+            }
+            AudinPdu::Formats(formats) => {
+                writer.write_u32_le(formats.len() as u32).await?;
+                writer.write_u32_le(self.length() as u32).await?;
+                let mut data = Vec::new();
+                for format in formats {
+                    format.write(&mut data);
+                }
+                writer.write_all(&data).await?;
+            }
+            AudinPdu::Open {
+                frames_per_packet,
+                initial_format,
+                format,
+            } => {
+                writer.write_u32_le(*frames_per_packet).await?;
+                writer.write_u32_le(*initial_format).await?;
+                let mut data = Vec::new();
+                format.write(&mut data);
+                writer.write_all(&data).await?;
+            }
+            AudinPdu::DataIncoming => (),
+            AudinPdu::Data(data) => writer.write_all(data).await?,
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        *self = read_audin_pdu(&mut BytesMut::from(&data[..]))?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        1 + self.data_length()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Check the encoding of a PDU both ways
+    async fn check_pdu(pdu: AudinPdu, data: &[u8]) {
+        assert_eq!(pdu.length(), data.len());
+        assert_eq!(to_vec(&pdu).await.unwrap(), data);
+        assert_eq!(read_audin_pdu(&mut BytesMut::from(data)).unwrap(), pdu);
+    }
+
+    /// Formats of the client and the open of the server
+    /// MS-RDPEAI 2.2.2 Initialization Sequence
+    #[tokio::test]
+    async fn test_audin_initialization_pdu() {
+        check_pdu(
+            AudinPdu::Version(SNDIN_VERSION),
+            &[
+                0x01, // MessageId MSG_SNDIN_VERSION
+                0x02, 0x00, 0x00, 0x00, // Version
+            ],
+        )
+        .await;
+        check_pdu(
+            AudinPdu::Formats(vec![AudioFormat::pcm(2, 44100, 16)]),
+            &[
+                0x02, // MessageId MSG_SNDIN_FORMATS
+                0x01, 0x00, 0x00, 0x00, // NumFormats
+                0x1B, 0x00, 0x00, 0x00, // cbSizeFormatsPacket
+                0x01, 0x00, // wFormatTag WAVE_FORMAT_PCM
+                0x02, 0x00, // nChannels
+                0x44, 0xAC, 0x00, 0x00, // nSamplesPerSec
+                0x10, 0xB1, 0x02, 0x00, // nAvgBytesPerSec
+                0x04, 0x00, // nBlockAlign
+                0x10, 0x00, // wBitsPerSample
+                0x00, 0x00, // cbSize
+            ],
+        )
+        .await;
+        check_pdu(
+            AudinPdu::Open {
+                frames_per_packet: 1024,
+                initial_format: 0,
+                format: AudioFormat::pcm(1, 22050, 16),
+            },
+            &[
+                0x03, // MessageId MSG_SNDIN_OPEN
+                0x00, 0x04, 0x00, 0x00, // FramesPerPacket
+                0x00, 0x00, 0x00, 0x00, // initialFormat
+                0x01, 0x00, // wFormatTag WAVE_FORMAT_PCM
+                0x01, 0x00, // nChannels
+                0x22, 0x56, 0x00, 0x00, // nSamplesPerSec
+                0x44, 0xAC, 0x00, 0x00, // nAvgBytesPerSec
+                0x02, 0x00, // nBlockAlign
+                0x10, 0x00, // wBitsPerSample
+                0x00, 0x00, // cbSize
+            ],
+        )
+        .await;
+        check_pdu(
+            AudinPdu::OpenReply(0),
+            &[
+                0x04, // MessageId MSG_SNDIN_OPEN_REPLY
+                0x00, 0x00, 0x00, 0x00, // Result S_OK
+            ],
+        )
+        .await;
+    }
+
+    /// Captured data and format changes
+    /// MS-RDPEAI 2.2.3 Data Transfer Sequence
+    #[tokio::test]
+    async fn test_audin_data_pdu() {
+        check_pdu(AudinPdu::DataIncoming, &[0x05]).await;
+        check_pdu(AudinPdu::Data(vec![1, 2, 3, 4]), &[0x06, 1, 2, 3, 4]).await;
+        check_pdu(
+            AudinPdu::FormatChange(1),
+            &[
+                0x07, // MessageId MSG_SNDIN_FORMATCHANGE
+                0x01, 0x00, 0x00, 0x00, // NewFormat
+            ],
+        )
+        .await;
+    }
+}
@@ -0,0 +1,258 @@
+use crate::codec::audio::{AudioFormat, WAVE_FORMAT_PCM};
+use crate::core::audin::base::{read_audin_pdu, AudinPdu, AUDIN_CHANNEL_NAME, SNDIN_VERSION};
+use crate::core::drdynvc::client::{to_messages, DynamicChannelHandler};
+use crate::core::event::{DynamicChannelEvent, RdpEvent};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use std::any::Any;
+use std::io::{Error, ErrorKind, Result};
+
+/// HRESULT of an open the source couldn't start
+const E_FAIL: u32 = 0x80004005;
+
+/// Microphone of the client
+/// Captures audio in one of the formats offered by the server
+pub trait AudioSource {
+    /// The source can capture in the format, 16 bits PCM by default
+    fn supports(&self, format: &AudioFormat) -> bool {
+        format.format_tag == WAVE_FORMAT_PCM && format.bits_per_sample == 16
+    }
+    /// Start capturing in a format
+    fn start(&mut self, format: &AudioFormat) -> Result<()>;
+    /// Audio captured since the last read, encoded in the format
+    fn read(&mut self) -> Result<Vec<u8>>;
+    /// Stop capturing until the next start
+    fn stop(&mut self) {}
+}
+
+/// Event of the audio input channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudinEvent {
+    /// The server started or changed the capture
+    Opened(AudioFormat),
+    /// The source couldn't start
+    OpenFailed,
+}
+
+/// Client of the audio input dynamic virtual channel
+/// Offers the formats of the server the source can capture,
+/// then streams the captured audio once the server opens it
+///
+/// Captured audio is sent by `poll`, in packets of the size asked by the server
+///
+/// # Example
+/// ```
+/// use rdp::codec::audio::AudioFormat;
+/// use rdp::core::audin::client::{AudinClient, AudioSource};
+/// struct Silence;
+/// impl AudioSource for Silence {
+///     fn start(&mut self, _: &AudioFormat) -> std::io::Result<()> { Ok(()) }
+///     fn read(&mut self) -> std::io::Result<Vec<u8>> { Ok(vec![0; 4]) }
+/// }
+/// let mut client = AudinClient::new(Box::new(Silence));
+/// // Version of the server
+/// let mut data = bytes::BytesMut::from(&[1, 2, 0, 0, 0][..]);
+/// let responses = client.process(&mut data, &mut |_| {}).unwrap();
+/// // Version of the client
+/// assert_eq!(responses.len(), 1);
+/// ```
+pub struct AudinClient {
+    source: Box<dyn AudioSource + Send>,
+    /// Formats offered to the server, indexed by the open and format change PDUs
+    formats: Vec<AudioFormat>,
+    /// Format of the capture once opened
+    format: Option<AudioFormat>,
+    frames_per_packet: u32,
+    /// Captured audio not sent yet
+    captured: Vec<u8>,
+}
+
+impl AudinClient {
+    pub fn new(source: Box<dyn AudioSource + Send>) -> Self {
+        AudinClient {
+            source,
+            formats: Vec::new(),
+            format: None,
+            frames_per_packet: 0,
+            captured: Vec::new(),
+        }
+    }
+
+    /// Restart the source in a format of the client
+    fn start(&mut self, index: u32) -> Result<AudioFormat> {
+        let format = self
+            .formats
+            .get(index as usize)
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "AUDIN: unknown format"))?;
+        if self.format.is_some() {
+            self.source.stop();
+        }
+        self.format = None;
+        self.captured.clear();
+        self.source.start(&format)?;
+        self.format = Some(format.clone());
+        Ok(format)
+    }
+
+    /// Stop the capture, once the channel is closed
+    pub fn close(&mut self) {
+        if self.format.take().is_some() {
+            self.source.stop();
+        }
+        self.captured.clear();
+    }
+
+    /// Process a message of the channel
+    /// and build the PDUs expected by the server
+    pub fn process<T>(&mut self, buffer: &mut BytesMut, callback: &mut T) -> Result<Vec<AudinPdu>>
+    where
+        T: FnMut(AudinEvent),
+    {
+        let mut responses = Vec::new();
+        match read_audin_pdu(buffer)? {
+            AudinPdu::Version(_) => responses.push(AudinPdu::Version(SNDIN_VERSION)),
+            AudinPdu::Formats(formats) => {
+                self.formats = formats
+                    .into_iter()
+                    .filter(|format| self.source.supports(format))
+                    .collect();
+                responses.push(AudinPdu::Formats(self.formats.clone()));
+            }
+            AudinPdu::Open {
+                frames_per_packet,
+                initial_format,
+                ..
+            } => {
+                self.frames_per_packet = frames_per_packet;
+                match self.start(initial_format) {
+                    Ok(format) => {
+                        responses.push(AudinPdu::FormatChange(initial_format));
+                        responses.push(AudinPdu::OpenReply(0));
+                        callback(AudinEvent::Opened(format));
+                    }
+                    Err(_) => {
+                        responses.push(AudinPdu::OpenReply(E_FAIL));
+                        callback(AudinEvent::OpenFailed);
+                    }
+                }
+            }
+            AudinPdu::FormatChange(index) => match self.start(index) {
+                Ok(format) => {
+                    responses.push(AudinPdu::FormatChange(index));
+                    callback(AudinEvent::Opened(format));
+                }
+                Err(_) => callback(AudinEvent::OpenFailed),
+            },
+            _ => (),
+        }
+        Ok(responses)
+    }
+
+    /// Send the audio captured by the source
+    /// Each packet is announced by an incoming data PDU
+    pub fn poll(&mut self) -> Result<Vec<AudinPdu>> {
+        let format = match &self.format {
+            Some(format) => format,
+            None => return Ok(Vec::new()),
+        };
+        let packet_size = (self.frames_per_packet as usize * format.block_align as usize).max(1);
+        self.captured.extend(self.source.read()?);
+        let mut responses = Vec::new();
+        while self.captured.len() >= packet_size {
+            let rest = self.captured.split_off(packet_size);
+            responses.push(AudinPdu::DataIncoming);
+            responses.push(AudinPdu::Data(std::mem::replace(&mut self.captured, rest)));
+        }
+        Ok(responses)
+    }
+}
+
+/// The captured audio is sent by `DynamicChannelClient::poll`
+#[async_trait]
+impl DynamicChannelHandler for AudinClient {
+    fn channel_names(&self) -> Vec<&'static str> {
+        vec![AUDIN_CHANNEL_NAME]
+    }
+
+    async fn process(
+        &mut self,
+        _channel_name: &str,
+        _channel_id: u32,
+        buffer: &mut BytesMut,
+        callback: &mut (dyn FnMut(RdpEvent) + Send),
+    ) -> Result<Vec<Vec<u8>>> {
+        let responses = AudinClient::process(self, buffer, &mut |event| {
+            callback(RdpEvent::Dynamic(DynamicChannelEvent::AudioInput(event)))
+        })?;
+        to_messages(responses).await
+    }
+
+    async fn poll(&mut self, _channel_name: &str, _channel_id: u32) -> Result<Vec<Vec<u8>>> {
+        let pdus = AudinClient::poll(self)?;
+        to_messages(pdus).await
+    }
+
+    fn close(&mut self, _channel_id: u32) {
+        AudinClient::close(self)
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::audio::WAVE_FORMAT_ALAW;
+
+    /// Captures an increasing counter
+    struct Counter(u8);
+
+    impl AudioSource for Counter {
+        fn start(&mut self, format: &AudioFormat) -> Result<()> {
+            assert_eq!(format.samples_per_sec, 8000);
+            Ok(())
+        }
+
+        fn read(&mut self) -> Result<Vec<u8>> {
+            self.0 += 1;
+            Ok(vec![self.0; 6])
+        }
+    }
+
+    /// The capture is opened in a supported format and sent in packets
+    #[test]
+    fn test_capture() {
+        let mut client = AudinClient::new(Box::new(Counter(0)));
+        let mut alaw = AudioFormat::pcm(1, 8000, 8);
+        alaw.format_tag = WAVE_FORMAT_ALAW;
+        client.formats = vec![alaw, AudioFormat::pcm(1, 8000, 16)]
+            .into_iter()
+            .filter(|format| client.source.supports(format))
+            .collect();
+        assert!(client.poll().unwrap().is_empty());
+
+        // Open with 2 frames per packet in the first format of the client
+        let mut buffer = BytesMut::from(&[3, 2, 0, 0, 0, 0, 0, 0, 0][..]);
+        let mut format = Vec::new();
+        AudioFormat::pcm(1, 8000, 16).write(&mut format);
+        buffer.extend_from_slice(&format);
+        let responses = client.process(&mut buffer, &mut |_| {}).unwrap();
+        assert_eq!(
+            responses,
+            vec![AudinPdu::FormatChange(0), AudinPdu::OpenReply(0)]
+        );
+
+        let responses = client.poll().unwrap();
+        assert_eq!(
+            responses,
+            vec![AudinPdu::DataIncoming, AudinPdu::Data(vec![1; 4]),]
+        );
+        let responses = client.poll().unwrap();
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[3], AudinPdu::Data(vec![2; 4]));
+    }
+}
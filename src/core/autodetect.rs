@@ -0,0 +1,353 @@
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Header type of a request
+const TYPE_ID_AUTODETECT_REQUEST: u8 = 0x00;
+/// Header type of a response
+const TYPE_ID_AUTODETECT_RESPONSE: u8 = 0x01;
+
+/// Type of auto detect request sent by the server
+/// MS-RDPBCGR 2.2.14.1 Auto-Detect Request PDU Data Structures
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum AutoDetectRequestType {
+    RdpRttRequestTypeContinuous = 0x0001,
+    RdpRttRequestTypeConnecttime = 0x1001,
+    RdpBwStartRequestTypeContinuous = 0x0014,
+    RdpBwStartRequestTypeTunnel = 0x0114,
+    RdpBwStartRequestTypeConnecttime = 0x1014,
+    RdpBwPayloadRequestTypeConnecttime = 0x0002,
+    RdpBwStopRequestTypeConnecttime = 0x002B,
+    RdpBwStopRequestTypeContinuous = 0x0429,
+    RdpBwStopRequestTypeTunnel = 0x0629,
+    RdpNetcharResultBaseRttAverageRtt = 0x0840,
+    RdpNetcharResultBandwidthAverageRtt = 0x0880,
+    RdpNetcharResultAll = 0x08C0,
+}
+
+/// Type of auto detect response sent by the client
+/// MS-RDPBCGR 2.2.14.2 Auto-Detect Response PDU Data Structures
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum AutoDetectResponseType {
+    RdpRttResponseType = 0x0000,
+    RdpBwResultsResponseTypeConnecttime = 0x0003,
+    RdpBwResultsResponseTypeContinuous = 0x000B,
+}
+
+/// Network characteristics computed by the server
+/// Fields are None until the server sends them
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct NetworkCharacteristics {
+    /// Lowest round trip time in milliseconds
+    pub base_rtt: Option<u32>,
+    /// Average round trip time in milliseconds
+    pub average_rtt: Option<u32>,
+    /// Bandwidth in kilobits per second
+    pub bandwidth: Option<u32>,
+}
+
+/// Request sent by the server
+#[derive(Debug, Eq, PartialEq)]
+pub enum AutoDetectRequest {
+    /// Must be answered as soon as possible
+    Rtt { sequence_number: u16 },
+    BandwidthStart,
+    /// Size of the payload used to measure the bandwidth
+    BandwidthPayload(usize),
+    BandwidthStop {
+        sequence_number: u16,
+        continuous: bool,
+        payload_length: usize,
+    },
+    NetworkCharacteristics(NetworkCharacteristics),
+    Unknown(u16),
+}
+
+/// Read an optional payload prefixed by its length
+fn read_payload_length(buffer: &mut BytesMut) -> Result<usize> {
+    check_remaining(buffer, 2, "AUTODETECT: payload length")?;
+    let payload_length = buffer.get_u16_le() as usize;
+    check_remaining(buffer, payload_length, "AUTODETECT: payload")?;
+    buffer.advance(payload_length);
+    Ok(payload_length)
+}
+
+/// Read a field of the network characteristics results
+fn read_u32(buffer: &mut BytesMut) -> Result<u32> {
+    check_remaining(buffer, 4, "AUTODETECT: network characteristics")?;
+    Ok(buffer.get_u32_le())
+}
+
+/// Parse the data of an auto detect request PDU
+/// The security header must be removed
+/// MS-RDPBCGR 2.2.14.3 Auto-Detect Request PDU (AUTODETECT_REQ_PDU)
+pub fn read_auto_detect_request(buffer: &mut BytesMut) -> Result<AutoDetectRequest> {
+    check_remaining(buffer, 6, "AUTODETECT: request header")?;
+    let _header_length = buffer.get_u8();
+    if buffer.get_u8() != TYPE_ID_AUTODETECT_REQUEST {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "AUTODETECT: invalid request header type",
+        ));
+    }
+    let sequence_number = buffer.get_u16_le();
+    let request_type = buffer.get_u16_le();
+
+    Ok(match AutoDetectRequestType::try_from(request_type) {
+        Ok(AutoDetectRequestType::RdpRttRequestTypeContinuous)
+        | Ok(AutoDetectRequestType::RdpRttRequestTypeConnecttime) => {
+            AutoDetectRequest::Rtt { sequence_number }
+        }
+        Ok(AutoDetectRequestType::RdpBwStartRequestTypeContinuous)
+        | Ok(AutoDetectRequestType::RdpBwStartRequestTypeTunnel)
+        | Ok(AutoDetectRequestType::RdpBwStartRequestTypeConnecttime) => {
+            AutoDetectRequest::BandwidthStart
+        }
+        Ok(AutoDetectRequestType::RdpBwPayloadRequestTypeConnecttime) => {
+            AutoDetectRequest::BandwidthPayload(read_payload_length(buffer)?)
+        }
+        Ok(AutoDetectRequestType::RdpBwStopRequestTypeConnecttime) => {
+            AutoDetectRequest::BandwidthStop {
+                sequence_number,
+                continuous: false,
+                payload_length: read_payload_length(buffer)?,
+            }
+        }
+        Ok(AutoDetectRequestType::RdpBwStopRequestTypeContinuous)
+        | Ok(AutoDetectRequestType::RdpBwStopRequestTypeTunnel) => {
+            AutoDetectRequest::BandwidthStop {
+                sequence_number,
+                continuous: true,
+                payload_length: 0,
+            }
+        }
+        Ok(AutoDetectRequestType::RdpNetcharResultBaseRttAverageRtt) => {
+            AutoDetectRequest::NetworkCharacteristics(NetworkCharacteristics {
+                base_rtt: Some(read_u32(buffer)?),
+                average_rtt: Some(read_u32(buffer)?),
+                bandwidth: None,
+            })
+        }
+        Ok(AutoDetectRequestType::RdpNetcharResultBandwidthAverageRtt) => {
+            AutoDetectRequest::NetworkCharacteristics(NetworkCharacteristics {
+                base_rtt: None,
+                bandwidth: Some(read_u32(buffer)?),
+                average_rtt: Some(read_u32(buffer)?),
+            })
+        }
+        Ok(AutoDetectRequestType::RdpNetcharResultAll) => {
+            AutoDetectRequest::NetworkCharacteristics(NetworkCharacteristics {
+                base_rtt: Some(read_u32(buffer)?),
+                bandwidth: Some(read_u32(buffer)?),
+                average_rtt: Some(read_u32(buffer)?),
+            })
+        }
+        Err(_) => AutoDetectRequest::Unknown(request_type),
+    })
+}
+
+/// Response sent back to the server
+/// MS-RDPBCGR 2.2.14.4 Auto-Detect Response PDU (AUTODETECT_RSP_PDU)
+#[derive(Debug, Eq, PartialEq)]
+pub enum AutoDetectResponse {
+    /// MS-RDPBCGR 2.2.14.2.1 RTT Measure Response (RDP_RTT_RESPONSE)
+    Rtt { sequence_number: u16 },
+    /// MS-RDPBCGR 2.2.14.2.2 Bandwidth Measure Results (RDP_BW_RESULTS)
+    BandwidthResults {
+        sequence_number: u16,
+        response_type: AutoDetectResponseType,
+        /// Milliseconds between start and stop
+        time_delta: u32,
+        byte_count: u32,
+    },
+}
+
+#[async_trait]
+impl Message for AutoDetectResponse {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u8(self.length() as u8).await?;
+        writer.write_u8(TYPE_ID_AUTODETECT_RESPONSE).await?;
+        match self {
+            AutoDetectResponse::Rtt { sequence_number } => {
+                writer.write_u16_le(*sequence_number).await?;
+                writer.write_u16_le(AutoDetectResponseType::RdpRttResponseType as u16).await?;
+            }
+            AutoDetectResponse::BandwidthResults {
+                sequence_number,
+                response_type,
+                time_delta,
+                byte_count,
+            } => {
+                writer.write_u16_le(*sequence_number).await?;
+                writer.write_u16_le(*response_type as u16).await?;
+                writer.write_u32_le(*time_delta).await?;
+                writer.write_u32_le(*byte_count).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let _header_length = reader.read_u8().await?;
+        let _header_type_id = reader.read_u8().await?;
+        let sequence_number = reader.read_u16_le().await?;
+        let response_type = AutoDetectResponseType::try_from(reader.read_u16_le().await?)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "AUTODETECT: unknown response type"))?;
+
+        *self = match response_type {
+            AutoDetectResponseType::RdpRttResponseType => {
+                AutoDetectResponse::Rtt { sequence_number }
+            }
+            _ => AutoDetectResponse::BandwidthResults {
+                sequence_number,
+                response_type,
+                time_delta: reader.read_u32_le().await?,
+                byte_count: reader.read_u32_le().await?,
+            },
+        };
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        match self {
+            AutoDetectResponse::Rtt { .. } => 6,
+            AutoDetectResponse::BandwidthResults { .. } => 14,
+        }
+    }
+}
+
+/// Client side of the network auto detection
+/// Answer the server requests and keep its results
+///
+/// # Example
+/// ```
+/// use rdp::core::autodetect::{AutoDetectRequest, AutoDetectResponse, AutoDetector};
+/// use std::time::Instant;
+/// let mut detector = AutoDetector::default();
+/// let request = AutoDetectRequest::Rtt { sequence_number: 1 };
+/// let response = detector.process(request, Instant::now());
+/// assert_eq!(response, Some(AutoDetectResponse::Rtt { sequence_number: 1 }));
+/// ```
+#[derive(Default)]
+pub struct AutoDetector {
+    /// Start of the current bandwidth measure
+    bandwidth_start: Option<Instant>,
+    /// Payload bytes received since the start
+    byte_count: usize,
+    /// Last results sent by the server
+    characteristics: NetworkCharacteristics,
+}
+
+impl AutoDetector {
+    /// Update the measures with a request
+    /// and build the response expected by the server
+    pub fn process(
+        &mut self,
+        request: AutoDetectRequest,
+        now: Instant,
+    ) -> Option<AutoDetectResponse> {
+        match request {
+            AutoDetectRequest::Rtt { sequence_number } => {
+                Some(AutoDetectResponse::Rtt { sequence_number })
+            }
+            AutoDetectRequest::BandwidthStart => {
+                self.bandwidth_start = Some(now);
+                self.byte_count = 0;
+                None
+            }
+            AutoDetectRequest::BandwidthPayload(length) => {
+                self.byte_count += length;
+                None
+            }
+            AutoDetectRequest::BandwidthStop {
+                sequence_number,
+                continuous,
+                payload_length,
+            } => {
+                let start = self.bandwidth_start.take().unwrap_or(now);
+                let byte_count = self.byte_count + payload_length;
+                Some(AutoDetectResponse::BandwidthResults {
+                    sequence_number,
+                    response_type: if continuous {
+                        AutoDetectResponseType::RdpBwResultsResponseTypeContinuous
+                    } else {
+                        AutoDetectResponseType::RdpBwResultsResponseTypeConnecttime
+                    },
+                    time_delta: now.saturating_duration_since(start).as_millis() as u32,
+                    byte_count: byte_count as u32,
+                })
+            }
+            AutoDetectRequest::NetworkCharacteristics(characteristics) => {
+                self.characteristics = characteristics;
+                None
+            }
+            AutoDetectRequest::Unknown(_) => None,
+        }
+    }
+
+    /// Last network characteristics sent by the server
+    pub fn get_network_characteristics(&self) -> NetworkCharacteristics {
+        self.characteristics
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+    use std::time::Duration;
+
+    /// Test of a connect time RTT request
+    #[test]
+    fn test_read_rtt_request() {
+        let mut buffer = BytesMut::from(&[6u8, 0, 3, 0, 1, 0x10][..]);
+        assert_eq!(
+            read_auto_detect_request(&mut buffer).unwrap(),
+            AutoDetectRequest::Rtt { sequence_number: 3 }
+        );
+    }
+
+    /// Test of the network characteristics results
+    #[test]
+    fn test_read_network_characteristics() {
+        let mut buffer = BytesMut::from(
+            &[14u8, 0, 1, 0, 0xC0, 0x08, 10, 0, 0, 0, 0, 1, 0, 0, 20, 0, 0, 0][..],
+        );
+        let expected = NetworkCharacteristics {
+            base_rtt: Some(10),
+            bandwidth: Some(256),
+            average_rtt: Some(20),
+        };
+        assert_eq!(
+            read_auto_detect_request(&mut buffer).unwrap(),
+            AutoDetectRequest::NetworkCharacteristics(expected)
+        );
+    }
+
+    /// Payload bytes are counted from start to stop
+    #[tokio::test]
+    async fn test_bandwidth_measure() {
+        let start = Instant::now();
+        let mut detector = AutoDetector::default();
+        assert!(detector.process(AutoDetectRequest::BandwidthStart, start).is_none());
+        assert!(detector.process(AutoDetectRequest::BandwidthPayload(100), start).is_none());
+
+        let stop = AutoDetectRequest::BandwidthStop {
+            sequence_number: 2,
+            continuous: false,
+            payload_length: 50,
+        };
+        let response = detector.process(stop, start + Duration::from_millis(40)).unwrap();
+        assert_eq!(
+            to_vec(&response).await.unwrap(),
+            vec![14, 1, 2, 0, 3, 0, 40, 0, 0, 0, 150, 0, 0, 0]
+        );
+    }
+}
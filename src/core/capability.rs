@@ -1,556 +1,2025 @@
-// use crate::core::gcc::{KeyboardLayout, KeyboardType};
-// use crate::model::data::{
-//     to_vec, Check, Component, DataType, DynOption, Message, MessageOption, Trame, U16, U32,
-// };
-// use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
-
-// use num_enum::TryFromPrimitive;
-// use std::convert::TryFrom;
-// use std::io::Cursor;
-
-// /// All capabilities that can be negotiated
-// /// between client and server
-// /// This is done by the global channel
-// #[repr(u16)]
-// #[derive(Eq, PartialEq, Hash, Debug, TryFromPrimitive)]
-// pub enum CapabilitySetType {
-//     CapstypeGeneral = 0x0001,
-//     CapstypeBitmap = 0x0002,
-//     CapstypeOrder = 0x0003,
-//     CapstypeBitmapcache = 0x0004,
-//     CapstypeControl = 0x0005,
-//     CapstypeActivation = 0x0007,
-//     CapstypePointer = 0x0008,
-//     CapstypeShare = 0x0009,
-//     CapstypeColorcache = 0x000A,
-//     CapstypeSound = 0x000C,
-//     CapstypeInput = 0x000D,
-//     CapstypeFont = 0x000E,
-//     CapstypeBrush = 0x000F,
-//     CapstypeGlyphcache = 0x0010,
-//     CapstypeOffscreencache = 0x0011,
-//     CapstypeBitmapcacheHostsupport = 0x0012,
-//     CapstypeBitmapcacheRev2 = 0x0013,
-//     CapstypeVirtualchannel = 0x0014,
-//     CapstypeDrawninegridcache = 0x0015,
-//     CapstypeDrawgdiplus = 0x0016,
-//     CapstypeRail = 0x0017,
-//     CapstypeWindow = 0x0018,
-//     CapsettypeCompdesk = 0x0019,
-//     CapsettypeMultifragmentupdate = 0x001A,
-//     CapsettypeLargePointer = 0x001B,
-//     CapsettypeSurfaceCommands = 0x001C,
-//     CapsettypeBitmapCodecs = 0x001D,
-//     CapssettypeFrameAcknowledge = 0x001E,
-// }
-
-// /// A capability
-// /// Composed by a type and structured
-// /// component
-// ///
-// /// # Example
-// /// ```rust, ignore
-// /// use rdp::core::capability::{Capability, CapabilitySetType};
-// /// let capability = Capability {
-// ///     cap_type: CapabilitySetType::CapstypePointer,
-// ///     message: component![
-// ///         "colorPointerFlag" => U16::LE(0),
-// ///         "colorPointerCacheSize" => U16::LE(20)
-// ///     ]
-// /// };
-// /// ```
-// pub struct Capability {
-//     pub cap_type: CapabilitySetType,
-//     pub message: Component,
-// }
-
-// impl Capability {
-//     /// Parse the capability from a parent capability_set
-//     ///
-//     /// # Example
-//     /// ```
-//     /// #[macro_use]
-//     /// # extern crate rdp;
-//     /// # use rdp::model::data::{DataType};
-//     /// # use rdp::model::error::{Error, RdpError, RdpResult, RdpErrorKind};
-//     /// # use rdp::core::capability::{capability_set, ts_general_capability_set, Capability};
-//     /// # fn main() {
-//     ///     let example = capability_set(Some(ts_general_capability_set(Some(4))));
-//     ///     let general_capability = Capability::from_capability_set(&example).unwrap();
-//     ///     assert_eq!(cast!(DataType::U16, general_capability.message["extraFlags"]).unwrap(), 4)
-//     /// # }
-//     /// ```
-//     pub fn from_capability_set(capability_set: &Component) -> RdpResult<Capability> {
-//         let cap_type = CapabilitySetType::try_from(cast!(
-//             DataType::U16,
-//             capability_set["capabilitySetType"]
-//         )?)?;
-//         let mut capability = match cap_type {
-//             CapabilitySetType::CapstypeGeneral => ts_general_capability_set(None),
-//             CapabilitySetType::CapstypeBitmap => ts_bitmap_capability_set(None, None, None),
-//             CapabilitySetType::CapstypeOrder => ts_order_capability_set(None),
-//             CapabilitySetType::CapstypeBitmapcache => ts_bitmap_cache_capability_set(),
-//             CapabilitySetType::CapstypePointer => ts_pointer_capability_set(),
-//             CapabilitySetType::CapstypeInput => ts_input_capability_set(None, None),
-//             CapabilitySetType::CapstypeBrush => ts_brush_capability_set(),
-//             CapabilitySetType::CapstypeGlyphcache => ts_glyph_capability_set(),
-//             CapabilitySetType::CapstypeOffscreencache => ts_offscreen_capability_set(),
-//             CapabilitySetType::CapstypeVirtualchannel => ts_virtualchannel_capability_set(),
-//             CapabilitySetType::CapstypeSound => ts_sound_capability_set(),
-//             CapabilitySetType::CapsettypeMultifragmentupdate => {
-//                 ts_multifragment_update_capability_ts()
-//             }
-//             _ => {
-//                 return Err(Error::RdpError(RdpError::new(
-//                     RdpErrorKind::Unknown,
-//                     &format!("CAPABILITY: Unknown capability {:?}", cap_type),
-//                 )))
-//             }
-//         };
-//         capability.message.read(&mut Cursor::new(cast!(
-//             DataType::Slice,
-//             capability_set["capabilitySet"]
-//         )?))?;
-//         Ok(capability)
-//     }
-// }
-
-// /// General capability payload
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/d705c3b6-a392-4b32-9610-391f6af62323
-// ///
-// /// # Example
-// /// ```
-// /// #[macro_use]
-// /// # extern crate rdp;
-// /// # use rdp::model::data::DataType;
-// /// # use rdp::model::error::{Error, RdpError, RdpResult, RdpErrorKind};
-// /// fn main() {
-// ///     use rdp::core::capability::{capability_set, ts_general_capability_set, CapabilitySetType};
-// ///     let capability_set = capability_set(Some(ts_general_capability_set(Some(2))));
-// ///     assert_eq!(cast!(DataType::U16, capability_set["capabilitySetType"]).unwrap(), CapabilitySetType::CapstypeGeneral as u16)
-// /// }
-// /// ```
-// pub fn capability_set(capability: Option<Capability>) -> Component {
-//     let default_capability = capability.unwrap_or(Capability {
-//         cap_type: CapabilitySetType::CapstypeGeneral,
-//         message: component![],
-//     });
-//     component![
-//         "capabilitySetType" => U16::LE(default_capability.cap_type as u16),
-//         "lengthCapability" => DynOption::new(U16::LE(default_capability.message.length() as u16 + 4), |length| MessageOption::Size("capabilitySet".to_string(), length.inner() as usize - 4)),
-//         "capabilitySet" => to_vec(&default_capability.message)
-//     ]
-// }
-
-// #[repr(u16)]
-// #[allow(dead_code)]
-// enum MajorType {
-//     OsmajortypeUnspecified = 0x0000,
-//     OsmajortypeWindows = 0x0001,
-//     OsmajortypeOs2 = 0x0002,
-//     OsmajortypeMacintosh = 0x0003,
-//     OsmajortypeUnix = 0x0004,
-//     OsmajortypeIos = 0x0005,
-//     OsmajortypeOsx = 0x0006,
-//     OsmajortypeAndroid = 0x0007,
-// }
-
-// #[allow(dead_code)]
-// enum MinorType {
-//     OsminortypeUnspecified = 0x0000,
-//     OsminortypeWindows31x = 0x0001,
-//     OsminortypeWindows95 = 0x0002,
-//     OsminortypeWindowsNt = 0x0003,
-//     OsminortypeOs2V21 = 0x0004,
-//     OsminortypePowerPc = 0x0005,
-//     OsminortypeMacintosh = 0x0006,
-//     OsminortypeNativeXserver = 0x0007,
-//     OsminortypePseudoXserver = 0x0008,
-//     OsminortypeWindowsRt = 0x0009,
-// }
-
-// #[repr(u16)]
-// pub enum GeneralExtraFlag {
-//     FastpathOutputSupported = 0x0001,
-//     NoBitmapCompressionHdr = 0x0400,
-//     LongCredentialsSupported = 0x0004,
-//     AutoreconnectSupported = 0x0008,
-//     EncSaltedChecksum = 0x0010,
-// }
-
-// /// General capability
-// /// This capability is send by both side
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/41dc6845-07dc-4af6-bc14-d8281acd4877
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_general_capability_set};
-// /// use rdp::model::data::to_vec;
-// /// let capability_set = capability_set(Some(ts_general_capability_set(Some(8))));
-// /// assert_eq!(to_vec(&capability_set), [1, 0, 24, 0, 1, 0, 3, 0, 0, 2, 0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0])
-// /// ```
-// pub fn ts_general_capability_set(extra_flags: Option<u16>) -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypeGeneral,
-//         message: component![
-//             "osMajorType" => U16::LE(MajorType::OsmajortypeWindows as u16),
-//             "osMinorType" => U16::LE(MinorType::OsminortypeWindowsNt as u16),
-//             "protocolVersion" => Check::new(U16::LE(0x0200)),
-//             "pad2octetsA" => U16::LE(0),
-//             "generalCompressionTypes" => Check::new(U16::LE(0)),
-//             "extraFlags" => U16::LE(extra_flags.unwrap_or(0)),
-//             "updateCapabilityFlag" => Check::new(U16::LE(0)),
-//             "remoteUnshareFlag" => Check::new(U16::LE(0)),
-//             "generalCompressionLevel" => Check::new(U16::LE(0)),
-//             "refreshRectSupport" => 0 as u8,
-//             "suppressOutputSupport" => 0 as u8
-//         ],
-//     }
-// }
-
-// /// Bitmap capability
-// /// Here we can set Bit per pixel
-// /// Screen Size
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/76670547-e35c-4b95-a242-5729a21b83f6
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_bitmap_capability_set};
-// /// use rdp::model::data::to_vec;
-// /// let capability_set = capability_set(Some(ts_bitmap_capability_set(Some(24), Some(800), Some(600))));
-// /// assert_eq!(to_vec(&capability_set), [2, 0, 28, 0, 24, 0, 1, 0, 1, 0, 1, 0, 32, 3, 88, 2, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0])
-// /// ```
-// pub fn ts_bitmap_capability_set(
-//     preferred_bits_per_pixel: Option<u16>,
-//     desktop_width: Option<u16>,
-//     desktop_height: Option<u16>,
-// ) -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypeBitmap,
-//         message: component![
-//             "preferredBitsPerPixel" => U16::LE(preferred_bits_per_pixel.unwrap_or(0)),
-//             "receive1BitPerPixel" => Check::new(U16::LE(0x0001)),
-//             "receive4BitsPerPixel" => Check::new(U16::LE(0x0001)),
-//             "receive8BitsPerPixel" => Check::new(U16::LE(0x0001)),
-//             "desktopWidth" => U16::LE(desktop_width.unwrap_or(0)),
-//             "desktopHeight" => U16::LE(desktop_height.unwrap_or(0)),
-//             "pad2octets" => U16::LE(0),
-//             "desktopResizeFlag" => U16::LE(0),
-//             "bitmapCompressionFlag" => Check::new(U16::LE(0x0001)),
-//             "highColorFlags" => Check::new(0 as u8),
-//             "drawingFlags" => 0 as u8,
-//             "multipleRectangleSupport" => Check::new(U16::LE(0x0001)),
-//             "pad2octetsB" => U16::LE(0)
-//         ],
-//     }
-// }
-
-// #[repr(u16)]
-// #[allow(dead_code)]
-// pub enum OrderFlag {
-//     NEGOTIATEORDERSUPPORT = 0x0002,
-//     ZEROBOUNDSDELTASSUPPORT = 0x0008,
-//     COLORINDEXSUPPORT = 0x0020,
-//     SOLIDPATTERNBRUSHONLY = 0x0040,
-//     OrderflagsExtraFlags = 0x0080,
-// }
-
-// /// Order capability
-// /// Some graphical orders options
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/9f409c29-480c-4751-9665-510b8ffff294
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_order_capability_set};
-// /// use rdp::model::data::to_vec;
-// /// let capability_set = capability_set(Some(ts_order_capability_set(Some(24))));
-// /// assert_eq!(to_vec(&capability_set), vec![3, 0, 88, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 20, 0, 0, 0, 1, 0, 0, 0, 24, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 132, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0])
-// /// ```
-// pub fn ts_order_capability_set(order_flags: Option<u16>) -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypeOrder,
-//         message: component![
-//             "terminalDescriptor" => vec![0 as u8; 16],
-//             "pad4octetsA" => U32::LE(0),
-//             "desktopSaveXGranularity" => U16::LE(1),
-//             "desktopSaveYGranularity" => U16::LE(20),
-//             "pad2octetsA" => U16::LE(0),
-//             "maximumOrderLevel" => U16::LE(1),
-//             "numberFonts" => U16::LE(0),
-//             "orderFlags" => U16::LE(order_flags.unwrap_or(OrderFlag::NEGOTIATEORDERSUPPORT as u16)),
-//             "orderSupport" => vec![0 as u8; 32],
-//             "textFlags" => U16::LE(0),
-//             "orderSupportExFlags" => U16::LE(0),
-//             "pad4octetsB" => U32::LE(0),
-//             "desktopSaveSize" => U32::LE(480*480),
-//             "pad2octetsC" => U16::LE(0),
-//             "pad2octetsD" => U16::LE(0),
-//             "textANSICodePage" => U16::LE(0),
-//             "pad2octetsE" => U16::LE(0)
-//         ],
-//     }
-// }
-
-// /// Bitmap cache is use as an optimization
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/101d40a7-56c0-40e1-bcb9-1475ff63cb9d
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_bitmap_cache_capability_set};
-// /// use rdp::model::data::to_vec;
-// /// let capability_set = capability_set(Some(ts_bitmap_cache_capability_set()));
-// /// assert_eq!(to_vec(&capability_set), vec![4, 0, 40, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
-// /// ```
-// pub fn ts_bitmap_cache_capability_set() -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypeBitmapcache,
-//         message: component![
-//             "pad1" => U32::LE(0),
-//             "pad2" => U32::LE(0),
-//             "pad3" => U32::LE(0),
-//             "pad4" => U32::LE(0),
-//             "pad5" => U32::LE(0),
-//             "pad6" => U32::LE(0),
-//             "cache0Entries" => U16::LE(0),
-//             "cache0MaximumCellSize" => U16::LE(0),
-//             "cache1Entries" => U16::LE(0),
-//             "cache1MaximumCellSize" => U16::LE(0),
-//             "cache2Entries" => U16::LE(0),
-//             "cache2MaximumCellSize" => U16::LE(0)
-//         ],
-//     }
-// }
-
-// /// Pointer capability
-// /// send by both client and server
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/925e2c05-c13f-44b1-aa20-23082051fef9
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_pointer_capability_set};
-// /// use rdp::model::data::to_vec;
-// /// let capability_set = capability_set(Some(ts_pointer_capability_set()));
-// /// assert_eq!(to_vec(&capability_set), vec![8, 0, 8, 0, 0, 0, 20, 0])
-// /// ```
-// pub fn ts_pointer_capability_set() -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypePointer,
-//         message: component![
-//             "colorPointerFlag" => U16::LE(0),
-//             "colorPointerCacheSize" => U16::LE(20)
-//         ],
-//     }
-// }
-
-// #[repr(u16)]
-// pub enum InputFlags {
-//     /// Raw Keyboard scancode
-//     /// This is the most convenient way to send keyboard event
-//     /// This fearture is supported by rdp-rs
-//     InputFlagScancodes = 0x0001,
-//     /// This is the extended mouse event
-//     /// with more button code
-//     /// This feature is supported by rdp-rs
-//     InputFlagMousex = 0x0004,
-//     /// The capability to send fastpath input
-//     /// This feature is NOT supported by rdp-rs
-//     InputFlagFastpathInput = 0x0008,
-//     /// In order to send keyboard scancode
-//     /// We can send directly UNICODE code of char
-//     /// Usefull if we want to send script
-//     /// This feature is supported by rdp-rs
-//     InputFlagUnicode = 0x0010,
-//     InputFlagFastpathInput2 = 0x0020,
-//     InputFlagUnused1 = 0x0040,
-//     InputFlagUnused2 = 0x0080,
-//     /// Support of the mouse wheel
-//     /// This feature is supported by rdp-rs
-//     TsInputFlagMouseHwheel = 0x0100,
-// }
-
-// /// Send input capability
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/b3bc76ae-9ee5-454f-b197-ede845ca69cc
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_input_capability_set, InputFlags};
-// /// use rdp::model::data::to_vec;
-// /// use rdp::core::gcc::KeyboardLayout;
-// /// let capability_set = capability_set(Some(ts_input_capability_set(Some(InputFlags::InputFlagScancodes as u16), Some(KeyboardLayout::French))));
-// /// assert_eq!(to_vec(&capability_set), vec![13, 0, 88, 0, 1, 0, 0, 0, 12, 4, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
-// /// ```
-// pub fn ts_input_capability_set(
-//     input_flags: Option<u16>,
-//     keyboard_layout: Option<KeyboardLayout>,
-// ) -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypeInput,
-//         message: component![
-//             "inputFlags" => U16::LE(input_flags.unwrap_or(0)),
-//             "pad2octetsA" => U16::LE(0),
-//             "keyboardLayout" => U32::LE(keyboard_layout.unwrap_or(KeyboardLayout::French) as u32),
-//             "keyboardType" => U32::LE(KeyboardType::Ibm101102Keys as u32),
-//             "keyboardSubType" => U32::LE(0),
-//             "keyboardFunctionKey" => U32::LE(12),
-//             "imeFileName" => vec![0 as u8; 64]
-//         ],
-//     }
-// }
-
-// /// Brush capability
-// /// send from client to server
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/8b6a830f-3dde-4a84-9250-21ffa7d2e342
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_brush_capability_set};
-// /// use rdp::model::data::to_vec;
-// /// use rdp::core::gcc::KeyboardLayout;
-// /// let capability_set = capability_set(Some(ts_brush_capability_set()));
-// /// assert_eq!(to_vec(&capability_set), vec![15, 0, 8, 0, 0, 0, 0, 0])
-// /// ```
-// pub fn ts_brush_capability_set() -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypeBrush,
-//         message: component![
-//             "brushSupportLevel" => U32::LE(0)
-//         ],
-//     }
-// }
-
-// /// Glyph cache entry
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/cae26830-263c-4c1e-97c2-b561faded3d9
-// fn cache_entry() -> Component {
-//     component![
-//         "cacheEntries" => U16::LE(0),
-//         "cacheMaximumCellSize" => U16::LE(0)
-//     ]
-// }
-
-// /// Glyph capability set
-// /// send from client to server
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/8e292483-9b0f-43b9-be14-dc6cd07e1615
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_glyph_capability_set};
-// /// use rdp::model::data::to_vec;
-// /// use rdp::core::gcc::KeyboardLayout;
-// /// let capability_set = capability_set(Some(ts_glyph_capability_set()));
-// /// assert_eq!(to_vec(&capability_set), vec![16, 0, 52, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
-// /// ```
-// pub fn ts_glyph_capability_set() -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypeGlyphcache,
-//         message: component![
-//             "glyphCache" => trame![
-//                 cache_entry(), cache_entry(), cache_entry(), cache_entry(), cache_entry(),
-//                 cache_entry(), cache_entry(), cache_entry(), cache_entry(), cache_entry()
-//             ],
-//             "fragCache" => U32::LE(0),
-//             "glyphSupportLevel" => U16::LE(0),
-//             "pad2octets" => U16::LE(0)
-//         ],
-//     }
-// }
-
-// /// Offscreen capability
-// /// send from client to server
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/412fa921-2faa-4f1b-ab5f-242cdabc04f9
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_offscreen_capability_set};
-// /// use rdp::model::data::to_vec;
-// /// use rdp::core::gcc::KeyboardLayout;
-// /// let capability_set = capability_set(Some(ts_offscreen_capability_set()));
-// /// assert_eq!(to_vec(&capability_set), vec![17, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0])
-// /// ```
-// pub fn ts_offscreen_capability_set() -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypeOffscreencache,
-//         message: component![
-//             "offscreenSupportLevel" => U32::LE(0),
-//             "offscreenCacheSize" => U16::LE(0),
-//             "offscreenCacheEntries" => U16::LE(0)
-//         ],
-//     }
-// }
-
-// /// Virtual channel capability
-// /// send by both side (client server)
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/a8593178-80c0-4b80-876c-cb77e62cecfc
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_virtualchannel_capability_set};
-// /// use rdp::model::data::to_vec;
-// /// use rdp::core::gcc::KeyboardLayout;
-// /// let capability_set = capability_set(Some(ts_virtualchannel_capability_set()));
-// /// assert_eq!(to_vec(&capability_set), vec![20, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0])
-// /// ```
-// pub fn ts_virtualchannel_capability_set() -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypeVirtualchannel,
-//         message: component![
-//             "flags" => U32::LE(0),
-//             "VCChunkSize" => Some(U32::LE(0))
-//         ],
-//     }
-// }
-
-// /// Sound capability
-// /// send from client server
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/fadb6a2c-18fa-4fa7-a155-e970d9b1ac59
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_sound_capability_set};
-// /// use rdp::model::data::to_vec;
-// /// use rdp::core::gcc::KeyboardLayout;
-// /// let capability_set = capability_set(Some(ts_sound_capability_set()));
-// /// assert_eq!(to_vec(&capability_set), vec![12, 0, 8, 0, 0, 0, 0, 0])
-// /// ```
-// pub fn ts_sound_capability_set() -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapstypeSound,
-//         message: component![
-//             "soundFlags" => U16::LE(0),
-//             "pad2octetsA" => U16::LE(0)
-//         ],
-//     }
-// }
-
-// /// Multi fragment capability
-// /// send by both side (client, server)
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/01717954-716a-424d-af35-28fb2b86df89
-// ///
-// /// /// # Example
-// /// ```
-// /// use rdp::core::capability::{capability_set, ts_multifragment_update_capability_ts};
-// /// use rdp::model::data::to_vec;
-// /// use rdp::core::gcc::KeyboardLayout;
-// /// let capability_set = capability_set(Some(ts_multifragment_update_capability_ts()));
-// /// assert_eq!(to_vec(&capability_set), vec![26, 0, 8, 0, 0, 0, 0, 0])
-// /// ```
-// pub fn ts_multifragment_update_capability_ts() -> Capability {
-//     Capability {
-//         cap_type: CapabilitySetType::CapsettypeMultifragmentupdate,
-//         message: component![
-//             "MaxRequestSize" => U32::LE(0)
-//         ],
-//     }
-// }
+use crate::core::gcc::{KeyboardLayout, KeyboardType};
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// All capabilities that can be negotiated
+/// between client and server
+/// This is done by the global channel
+#[repr(u16)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, TryFromPrimitive)]
+pub enum CapabilitySetType {
+    CapstypeGeneral = 0x0001,
+    CapstypeBitmap = 0x0002,
+    CapstypeOrder = 0x0003,
+    CapstypeBitmapcache = 0x0004,
+    CapstypeControl = 0x0005,
+    CapstypeActivation = 0x0007,
+    CapstypePointer = 0x0008,
+    CapstypeShare = 0x0009,
+    CapstypeColorcache = 0x000A,
+    CapstypeSound = 0x000C,
+    CapstypeInput = 0x000D,
+    CapstypeFont = 0x000E,
+    CapstypeBrush = 0x000F,
+    CapstypeGlyphcache = 0x0010,
+    CapstypeOffscreencache = 0x0011,
+    CapstypeBitmapcacheHostsupport = 0x0012,
+    CapstypeBitmapcacheRev2 = 0x0013,
+    CapstypeVirtualchannel = 0x0014,
+    CapstypeDrawninegridcache = 0x0015,
+    CapstypeDrawgdiplus = 0x0016,
+    CapstypeRail = 0x0017,
+    CapstypeWindow = 0x0018,
+    CapsettypeCompdesk = 0x0019,
+    CapsettypeMultifragmentupdate = 0x001A,
+    CapsettypeLargePointer = 0x001B,
+    CapsettypeSurfaceCommands = 0x001C,
+    CapsettypeBitmapCodecs = 0x001D,
+    CapssettypeFrameAcknowledge = 0x001E,
+}
+
+#[repr(u16)]
+#[allow(dead_code)]
+pub enum MajorType {
+    OsmajortypeUnspecified = 0x0000,
+    OsmajortypeWindows = 0x0001,
+    OsmajortypeOs2 = 0x0002,
+    OsmajortypeMacintosh = 0x0003,
+    OsmajortypeUnix = 0x0004,
+    OsmajortypeIos = 0x0005,
+    OsmajortypeOsx = 0x0006,
+    OsmajortypeAndroid = 0x0007,
+}
+
+#[repr(u16)]
+#[allow(dead_code)]
+pub enum MinorType {
+    OsminortypeUnspecified = 0x0000,
+    OsminortypeWindows31x = 0x0001,
+    OsminortypeWindows95 = 0x0002,
+    OsminortypeWindowsNt = 0x0003,
+    OsminortypeOs2V21 = 0x0004,
+    OsminortypePowerPc = 0x0005,
+    OsminortypeMacintosh = 0x0006,
+    OsminortypeNativeXserver = 0x0007,
+    OsminortypePseudoXserver = 0x0008,
+    OsminortypeWindowsRt = 0x0009,
+}
+
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GeneralExtraFlag {
+    FastpathOutputSupported = 0x0001,
+    NoBitmapCompressionHdr = 0x0400,
+    LongCredentialsSupported = 0x0004,
+    AutoreconnectSupported = 0x0008,
+    EncSaltedChecksum = 0x0010,
+}
+
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OrderFlag {
+    NEGOTIATEORDERSUPPORT = 0x0002,
+    ZEROBOUNDSDELTASSUPPORT = 0x0008,
+    COLORINDEXSUPPORT = 0x0020,
+    SOLIDPATTERNBRUSHONLY = 0x0040,
+    OrderflagsExtraFlags = 0x0080,
+}
+
+/// Extra flags of the order capability
+/// Only read when OrderflagsExtraFlags is set
+/// MS-RDPBCGR 2.2.7.1.3 Order Capability Set (TS_ORDER_CAPABILITYSET)
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OrderFlagEx {
+    CacheBitmapRev3Support = 0x0002,
+    AltsecFrameMarkerSupport = 0x0004,
+}
+
+/// Index of a drawing order in the order support array
+/// MS-RDPBCGR 2.2.7.1.3 Order Capability Set (TS_ORDER_CAPABILITYSET)
+#[repr(usize)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OrderSupportIndex {
+    Dstblt = 0x00,
+    Patblt = 0x01,
+    Scrblt = 0x02,
+    Memblt = 0x03,
+    Mem3blt = 0x04,
+    Drawninegrid = 0x07,
+    Lineto = 0x08,
+    MultiDrawninegrid = 0x09,
+    Opaquerect = 0x0A,
+    Savebitmap = 0x0B,
+    Multidstblt = 0x0F,
+    Multipatblt = 0x10,
+    Multiscrblt = 0x11,
+    Multiopaquerect = 0x12,
+    Fastindex = 0x13,
+    PolygonSc = 0x14,
+    PolygonCb = 0x15,
+    Polyline = 0x16,
+    Fastglyph = 0x18,
+    EllipseSc = 0x19,
+    EllipseCb = 0x1A,
+    Glyphindex = 0x1B,
+}
+
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InputFlags {
+    /// Raw Keyboard scancode
+    /// This is the most convenient way to send keyboard event
+    /// This fearture is supported by rdp-rs
+    InputFlagScancodes = 0x0001,
+    /// This is the extended mouse event
+    /// with more button code
+    /// This feature is supported by rdp-rs
+    InputFlagMousex = 0x0004,
+    /// The capability to send fastpath input
+    /// This feature is supported by rdp-rs
+    InputFlagFastpathInput = 0x0008,
+    /// In order to send keyboard scancode
+    /// We can send directly UNICODE code of char
+    /// Usefull if we want to send script
+    /// This feature is supported by rdp-rs
+    InputFlagUnicode = 0x0010,
+    InputFlagFastpathInput2 = 0x0020,
+    InputFlagUnused1 = 0x0040,
+    InputFlagUnused2 = 0x0080,
+    /// Support of the mouse wheel
+    /// This feature is supported by rdp-rs
+    TsInputFlagMouseHwheel = 0x0100,
+}
+
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SoundFlag {
+    SoundBeepsFlag = 0x0001,
+}
+
+/// Options of the planar codec allowed in bitmap updates
+/// MS-RDPBCGR 2.2.7.1.2 Bitmap Capability Set (TS_BITMAP_CAPABILITYSET)
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DrawingFlag {
+    DrawAllowDynamicColorFidelity = 0x02,
+    DrawAllowColorSubsampling = 0x04,
+    DrawAllowSkipAlpha = 0x08,
+}
+
+/// Brushes supported by the client
+/// MS-RDPBCGR 2.2.7.1.7 Brush Capability Set (TS_BRUSH_CAPABILITYSET)
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BrushSupportLevel {
+    BrushDefault = 0x00000000,
+    BrushColor8x8 = 0x00000001,
+    BrushColorFull = 0x00000002,
+}
+
+/// Glyph orders supported by the client
+/// MS-RDPBCGR 2.2.7.1.8 Glyph Cache Capability Set (TS_GLYPHCACHE_CAPABILITYSET)
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GlyphSupportLevel {
+    GlyphSupportNone = 0x0000,
+    GlyphSupportPartial = 0x0001,
+    GlyphSupportFull = 0x0002,
+    GlyphSupportEncode = 0x0003,
+}
+
+/// General capability
+/// This capability is send by both side
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/41dc6845-07dc-4af6-bc14-d8281acd4877
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GeneralCapability {
+    pub os_major_type: u16,
+    pub os_minor_type: u16,
+    pub protocol_version: u16,
+    pub general_compression_types: u16,
+    pub extra_flags: u16,
+    pub update_capability_flag: u16,
+    pub remote_unshare_flag: u16,
+    pub general_compression_level: u16,
+    pub refresh_rect_support: u8,
+    pub suppress_output_support: u8,
+}
+
+impl GeneralCapability {
+    pub fn new(extra_flags: u16) -> Self {
+        GeneralCapability {
+            os_major_type: MajorType::OsmajortypeWindows as u16,
+            os_minor_type: MinorType::OsminortypeWindowsNt as u16,
+            protocol_version: 0x0200,
+            general_compression_types: 0,
+            extra_flags,
+            update_capability_flag: 0,
+            remote_unshare_flag: 0,
+            general_compression_level: 0,
+            refresh_rect_support: 0,
+            suppress_output_support: 0,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 20, "CAPABILITY: general")?;
+        self.os_major_type = buffer.get_u16_le();
+        self.os_minor_type = buffer.get_u16_le();
+        self.protocol_version = buffer.get_u16_le();
+        buffer.advance(2);
+        self.general_compression_types = buffer.get_u16_le();
+        self.extra_flags = buffer.get_u16_le();
+        self.update_capability_flag = buffer.get_u16_le();
+        self.remote_unshare_flag = buffer.get_u16_le();
+        self.general_compression_level = buffer.get_u16_le();
+        self.refresh_rect_support = buffer.get_u8();
+        self.suppress_output_support = buffer.get_u8();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for GeneralCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.os_major_type).await?;
+        writer.write_u16_le(self.os_minor_type).await?;
+        writer.write_u16_le(self.protocol_version).await?;
+        writer.write_u16_le(0).await?;
+        writer.write_u16_le(self.general_compression_types).await?;
+        writer.write_u16_le(self.extra_flags).await?;
+        writer.write_u16_le(self.update_capability_flag).await?;
+        writer.write_u16_le(self.remote_unshare_flag).await?;
+        writer.write_u16_le(self.general_compression_level).await?;
+        writer.write_u8(self.refresh_rect_support).await?;
+        writer.write_u8(self.suppress_output_support).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.os_major_type = reader.read_u16_le().await?;
+        self.os_minor_type = reader.read_u16_le().await?;
+        self.protocol_version = reader.read_u16_le().await?;
+        reader.read_u16_le().await?;
+        self.general_compression_types = reader.read_u16_le().await?;
+        self.extra_flags = reader.read_u16_le().await?;
+        self.update_capability_flag = reader.read_u16_le().await?;
+        self.remote_unshare_flag = reader.read_u16_le().await?;
+        self.general_compression_level = reader.read_u16_le().await?;
+        self.refresh_rect_support = reader.read_u8().await?;
+        self.suppress_output_support = reader.read_u8().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        20
+    }
+}
+
+/// Bitmap capability
+/// Here we can set Bit per pixel
+/// Screen Size
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/76670547-e35c-4b95-a242-5729a21b83f6
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BitmapCapability {
+    pub preferred_bits_per_pixel: u16,
+    pub receive_1_bit_per_pixel: u16,
+    pub receive_4_bits_per_pixel: u16,
+    pub receive_8_bits_per_pixel: u16,
+    pub desktop_width: u16,
+    pub desktop_height: u16,
+    pub desktop_resize_flag: u16,
+    pub bitmap_compression_flag: u16,
+    pub high_color_flags: u8,
+    pub drawing_flags: u8,
+    pub multiple_rectangle_support: u16,
+}
+
+impl BitmapCapability {
+    pub fn new(preferred_bits_per_pixel: u16, desktop_width: u16, desktop_height: u16) -> Self {
+        BitmapCapability {
+            preferred_bits_per_pixel,
+            receive_1_bit_per_pixel: 0x0001,
+            receive_4_bits_per_pixel: 0x0001,
+            receive_8_bits_per_pixel: 0x0001,
+            desktop_width,
+            desktop_height,
+            desktop_resize_flag: 0,
+            bitmap_compression_flag: 0x0001,
+            high_color_flags: 0,
+            drawing_flags: 0,
+            multiple_rectangle_support: 0x0001,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 24, "CAPABILITY: bitmap")?;
+        self.preferred_bits_per_pixel = buffer.get_u16_le();
+        self.receive_1_bit_per_pixel = buffer.get_u16_le();
+        self.receive_4_bits_per_pixel = buffer.get_u16_le();
+        self.receive_8_bits_per_pixel = buffer.get_u16_le();
+        self.desktop_width = buffer.get_u16_le();
+        self.desktop_height = buffer.get_u16_le();
+        buffer.advance(2);
+        self.desktop_resize_flag = buffer.get_u16_le();
+        self.bitmap_compression_flag = buffer.get_u16_le();
+        self.high_color_flags = buffer.get_u8();
+        self.drawing_flags = buffer.get_u8();
+        self.multiple_rectangle_support = buffer.get_u16_le();
+        buffer.advance(2);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for BitmapCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.preferred_bits_per_pixel).await?;
+        writer.write_u16_le(self.receive_1_bit_per_pixel).await?;
+        writer.write_u16_le(self.receive_4_bits_per_pixel).await?;
+        writer.write_u16_le(self.receive_8_bits_per_pixel).await?;
+        writer.write_u16_le(self.desktop_width).await?;
+        writer.write_u16_le(self.desktop_height).await?;
+        writer.write_u16_le(0).await?;
+        writer.write_u16_le(self.desktop_resize_flag).await?;
+        writer.write_u16_le(self.bitmap_compression_flag).await?;
+        writer.write_u8(self.high_color_flags).await?;
+        writer.write_u8(self.drawing_flags).await?;
+        writer.write_u16_le(self.multiple_rectangle_support).await?;
+        writer.write_u16_le(0).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.preferred_bits_per_pixel = reader.read_u16_le().await?;
+        self.receive_1_bit_per_pixel = reader.read_u16_le().await?;
+        self.receive_4_bits_per_pixel = reader.read_u16_le().await?;
+        self.receive_8_bits_per_pixel = reader.read_u16_le().await?;
+        self.desktop_width = reader.read_u16_le().await?;
+        self.desktop_height = reader.read_u16_le().await?;
+        reader.read_u16_le().await?;
+        self.desktop_resize_flag = reader.read_u16_le().await?;
+        self.bitmap_compression_flag = reader.read_u16_le().await?;
+        self.high_color_flags = reader.read_u8().await?;
+        self.drawing_flags = reader.read_u8().await?;
+        self.multiple_rectangle_support = reader.read_u16_le().await?;
+        reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        24
+    }
+}
+
+/// Order capability
+/// Some graphical orders options
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/9f409c29-480c-4751-9665-510b8ffff294
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OrderCapability {
+    pub terminal_descriptor: [u8; 16],
+    pub desktop_save_x_granularity: u16,
+    pub desktop_save_y_granularity: u16,
+    pub maximum_order_level: u16,
+    pub number_fonts: u16,
+    pub order_flags: u16,
+    pub order_support: [u8; 32],
+    pub text_flags: u16,
+    pub order_support_ex_flags: u16,
+    pub desktop_save_size: u32,
+    pub text_ansi_code_page: u16,
+}
+
+impl OrderCapability {
+    /// No drawing order are enabled by default
+    pub fn new(order_flags: u16) -> Self {
+        OrderCapability {
+            terminal_descriptor: [0; 16],
+            desktop_save_x_granularity: 1,
+            desktop_save_y_granularity: 20,
+            maximum_order_level: 1,
+            number_fonts: 0,
+            order_flags,
+            order_support: [0; 32],
+            text_flags: 0,
+            order_support_ex_flags: 0,
+            desktop_save_size: 480 * 480,
+            text_ansi_code_page: 0,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 84, "CAPABILITY: order")?;
+        buffer.copy_to_slice(&mut self.terminal_descriptor);
+        buffer.advance(4);
+        self.desktop_save_x_granularity = buffer.get_u16_le();
+        self.desktop_save_y_granularity = buffer.get_u16_le();
+        buffer.advance(2);
+        self.maximum_order_level = buffer.get_u16_le();
+        self.number_fonts = buffer.get_u16_le();
+        self.order_flags = buffer.get_u16_le();
+        buffer.copy_to_slice(&mut self.order_support);
+        self.text_flags = buffer.get_u16_le();
+        self.order_support_ex_flags = buffer.get_u16_le();
+        buffer.advance(4);
+        self.desktop_save_size = buffer.get_u32_le();
+        buffer.advance(4);
+        self.text_ansi_code_page = buffer.get_u16_le();
+        buffer.advance(2);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for OrderCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_all(&self.terminal_descriptor).await?;
+        writer.write_u32_le(0).await?;
+        writer.write_u16_le(self.desktop_save_x_granularity).await?;
+        writer.write_u16_le(self.desktop_save_y_granularity).await?;
+        writer.write_u16_le(0).await?;
+        writer.write_u16_le(self.maximum_order_level).await?;
+        writer.write_u16_le(self.number_fonts).await?;
+        writer.write_u16_le(self.order_flags).await?;
+        writer.write_all(&self.order_support).await?;
+        writer.write_u16_le(self.text_flags).await?;
+        writer.write_u16_le(self.order_support_ex_flags).await?;
+        writer.write_u32_le(0).await?;
+        writer.write_u32_le(self.desktop_save_size).await?;
+        writer.write_u32_le(0).await?;
+        writer.write_u16_le(self.text_ansi_code_page).await?;
+        writer.write_u16_le(0).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        reader.read_exact(&mut self.terminal_descriptor).await?;
+        reader.read_u32_le().await?;
+        self.desktop_save_x_granularity = reader.read_u16_le().await?;
+        self.desktop_save_y_granularity = reader.read_u16_le().await?;
+        reader.read_u16_le().await?;
+        self.maximum_order_level = reader.read_u16_le().await?;
+        self.number_fonts = reader.read_u16_le().await?;
+        self.order_flags = reader.read_u16_le().await?;
+        reader.read_exact(&mut self.order_support).await?;
+        self.text_flags = reader.read_u16_le().await?;
+        self.order_support_ex_flags = reader.read_u16_le().await?;
+        reader.read_u32_le().await?;
+        self.desktop_save_size = reader.read_u32_le().await?;
+        reader.read_u32_le().await?;
+        self.text_ansi_code_page = reader.read_u16_le().await?;
+        reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        84
+    }
+}
+
+/// Bitmap cache capability, revision 2
+/// send from client to server
+///
+/// Each cell cache is a number of entries,
+/// the high bit marks a persistent cache
+///
+/// MS-RDPBCGR 2.2.7.1.4.2 Revision 2 (TS_BITMAPCACHE_CAPABILITYSET_REV2)
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct BitmapCacheRev2Capability {
+    pub cache_flags: u16,
+    pub cell_info: Vec<u32>,
+}
+
+impl BitmapCacheRev2Capability {
+    /// At most 5 caches are available
+    pub fn new(cell_info: &[u32]) -> Self {
+        BitmapCacheRev2Capability {
+            cache_flags: 0,
+            cell_info: cell_info.iter().take(5).copied().collect(),
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 36, "CAPABILITY: bitmap cache rev2")?;
+        self.cache_flags = buffer.get_u16_le();
+        buffer.advance(1);
+        let number_cell_caches = buffer.get_u8().min(5) as usize;
+        let cell_info: Vec<u32> = (0..5).map(|_| buffer.get_u32_le()).collect();
+        self.cell_info = cell_info[..number_cell_caches].to_vec();
+        buffer.advance(12);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for BitmapCacheRev2Capability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.cache_flags).await?;
+        writer.write_u8(0).await?;
+        writer.write_u8(self.cell_info.len() as u8).await?;
+        for index in 0..5 {
+            writer
+                .write_u32_le(self.cell_info.get(index).copied().unwrap_or(0))
+                .await?;
+        }
+        writer.write_all(&[0; 12]).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut buffer = vec![0; 36];
+        reader.read_exact(&mut buffer).await?;
+        self.read_from_buffer(&mut BytesMut::from(&buffer[..]))
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        36
+    }
+}
+
+/// Offscreen bitmap cache capability
+/// send from client to server
+///
+/// The cache size is in kilobytes
+///
+/// MS-RDPBCGR 2.2.7.1.9 Offscreen Bitmap Cache Capability Set (TS_OFFSCREEN_CAPABILITYSET)
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct OffscreenBitmapCacheCapability {
+    pub offscreen_support_level: u32,
+    pub offscreen_cache_size: u16,
+    pub offscreen_cache_entries: u16,
+}
+
+impl OffscreenBitmapCacheCapability {
+    pub fn new(offscreen_cache_size: u16, offscreen_cache_entries: u16) -> Self {
+        OffscreenBitmapCacheCapability {
+            offscreen_support_level: 1,
+            offscreen_cache_size,
+            offscreen_cache_entries,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 8, "CAPABILITY: offscreen bitmap cache")?;
+        self.offscreen_support_level = buffer.get_u32_le();
+        self.offscreen_cache_size = buffer.get_u16_le();
+        self.offscreen_cache_entries = buffer.get_u16_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for OffscreenBitmapCacheCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.offscreen_support_level).await?;
+        writer.write_u16_le(self.offscreen_cache_size).await?;
+        writer.write_u16_le(self.offscreen_cache_entries).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut buffer = vec![0; 8];
+        reader.read_exact(&mut buffer).await?;
+        self.read_from_buffer(&mut BytesMut::from(&buffer[..]))
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        8
+    }
+}
+
+/// Pointer capability
+/// send by both client and server
+///
+/// Pointer cache size is optional
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/925e2c05-c13f-44b1-aa20-23082051fef9
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PointerCapability {
+    pub color_pointer_flag: u16,
+    pub color_pointer_cache_size: u16,
+    pub pointer_cache_size: Option<u16>,
+}
+
+impl PointerCapability {
+    pub fn new(pointer_cache_size: u16) -> Self {
+        PointerCapability {
+            color_pointer_flag: 1,
+            color_pointer_cache_size: pointer_cache_size,
+            pointer_cache_size: Some(pointer_cache_size),
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "CAPABILITY: pointer")?;
+        self.color_pointer_flag = buffer.get_u16_le();
+        self.color_pointer_cache_size = buffer.get_u16_le();
+        self.pointer_cache_size = if buffer.remaining() >= 2 {
+            Some(buffer.get_u16_le())
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for PointerCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.color_pointer_flag).await?;
+        writer.write_u16_le(self.color_pointer_cache_size).await?;
+        if let Some(pointer_cache_size) = self.pointer_cache_size {
+            writer.write_u16_le(pointer_cache_size).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.color_pointer_flag = reader.read_u16_le().await?;
+        self.color_pointer_cache_size = reader.read_u16_le().await?;
+        if self.pointer_cache_size.is_some() {
+            self.pointer_cache_size = Some(reader.read_u16_le().await?);
+        }
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        4 + self.pointer_cache_size.map_or(0, |_| 2)
+    }
+}
+
+/// Send input capability
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/b3bc76ae-9ee5-454f-b197-ede845ca69cc
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InputCapability {
+    pub input_flags: u16,
+    pub keyboard_layout: u32,
+    pub keyboard_type: u32,
+    pub keyboard_sub_type: u32,
+    pub keyboard_function_key: u32,
+    pub ime_file_name: [u8; 64],
+}
+
+impl InputCapability {
+    pub fn new(input_flags: u16, keyboard_layout: KeyboardLayout) -> Self {
+        InputCapability {
+            input_flags,
+            keyboard_layout: keyboard_layout as u32,
+            keyboard_type: KeyboardType::Ibm101102Keys as u32,
+            keyboard_sub_type: 0,
+            keyboard_function_key: 12,
+            ime_file_name: [0; 64],
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 84, "CAPABILITY: input")?;
+        self.input_flags = buffer.get_u16_le();
+        buffer.advance(2);
+        self.keyboard_layout = buffer.get_u32_le();
+        self.keyboard_type = buffer.get_u32_le();
+        self.keyboard_sub_type = buffer.get_u32_le();
+        self.keyboard_function_key = buffer.get_u32_le();
+        buffer.copy_to_slice(&mut self.ime_file_name);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for InputCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.input_flags).await?;
+        writer.write_u16_le(0).await?;
+        writer.write_u32_le(self.keyboard_layout).await?;
+        writer.write_u32_le(self.keyboard_type).await?;
+        writer.write_u32_le(self.keyboard_sub_type).await?;
+        writer.write_u32_le(self.keyboard_function_key).await?;
+        writer.write_all(&self.ime_file_name).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.input_flags = reader.read_u16_le().await?;
+        reader.read_u16_le().await?;
+        self.keyboard_layout = reader.read_u32_le().await?;
+        self.keyboard_type = reader.read_u32_le().await?;
+        self.keyboard_sub_type = reader.read_u32_le().await?;
+        self.keyboard_function_key = reader.read_u32_le().await?;
+        reader.read_exact(&mut self.ime_file_name).await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        84
+    }
+}
+
+/// Brush capability
+/// send from client to server
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/8b6a830f-3dde-4a84-9250-21ffa7d2e342
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct BrushCapability {
+    pub brush_support_level: u32,
+}
+
+impl BrushCapability {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "CAPABILITY: brush")?;
+        self.brush_support_level = buffer.get_u32_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for BrushCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.brush_support_level).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.brush_support_level = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4
+    }
+}
+
+/// Glyph cache entry
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/cae26830-263c-4c1e-97c2-b561faded3d9
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct CacheDefinition {
+    pub cache_entries: u16,
+    pub cache_maximum_cell_size: u16,
+}
+
+/// Glyph capability set
+/// send from client to server
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/8e292483-9b0f-43b9-be14-dc6cd07e1615
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct GlyphCacheCapability {
+    pub glyph_cache: [CacheDefinition; 10],
+    pub frag_cache: u32,
+    pub glyph_support_level: u16,
+}
+
+impl GlyphCacheCapability {
+    /// Usual sizes of the glyph caches, from 4 to 2048 bytes per glyph,
+    /// and 256 fragments of up to 256 bytes
+    pub fn new(glyph_support_level: GlyphSupportLevel) -> Self {
+        let mut glyph_cache = [CacheDefinition::default(); 10];
+        for (cache, cell_size) in glyph_cache
+            .iter_mut()
+            .zip([4, 4, 8, 8, 16, 32, 64, 128, 256, 2048])
+        {
+            *cache = CacheDefinition {
+                cache_entries: if cell_size == 2048 { 64 } else { 254 },
+                cache_maximum_cell_size: cell_size,
+            };
+        }
+        GlyphCacheCapability {
+            glyph_cache,
+            frag_cache: 0x0100_0100,
+            glyph_support_level: glyph_support_level as u16,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 48, "CAPABILITY: glyph cache")?;
+        for cache in self.glyph_cache.iter_mut() {
+            cache.cache_entries = buffer.get_u16_le();
+            cache.cache_maximum_cell_size = buffer.get_u16_le();
+        }
+        self.frag_cache = buffer.get_u32_le();
+        self.glyph_support_level = buffer.get_u16_le();
+        buffer.advance(2);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for GlyphCacheCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        for cache in self.glyph_cache.iter() {
+            writer.write_u16_le(cache.cache_entries).await?;
+            writer.write_u16_le(cache.cache_maximum_cell_size).await?;
+        }
+        writer.write_u32_le(self.frag_cache).await?;
+        writer.write_u16_le(self.glyph_support_level).await?;
+        writer.write_u16_le(0).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        for cache in self.glyph_cache.iter_mut() {
+            cache.cache_entries = reader.read_u16_le().await?;
+            cache.cache_maximum_cell_size = reader.read_u16_le().await?;
+        }
+        self.frag_cache = reader.read_u32_le().await?;
+        self.glyph_support_level = reader.read_u16_le().await?;
+        reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        48
+    }
+}
+
+/// Compression of the virtual channel data
+/// MS-RDPBCGR 2.2.7.1.10 Virtual Channel Capability Set (TS_VIRTUALCHANNEL_CAPABILITYSET)
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VirtualChannelFlag {
+    VccapsNoCompr = 0x00000000,
+    VccapsComprSc = 0x00000001,
+    VccapsComprCs8k = 0x00000002,
+}
+
+/// Virtual channel capability
+/// send by both side (client server)
+///
+/// Chunk size is only sent by the server
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/a8593178-80c0-4b80-876c-cb77e62cecfc
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct VirtualChannelCapability {
+    pub flags: u32,
+    pub vc_chunk_size: Option<u32>,
+}
+
+impl VirtualChannelCapability {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "CAPABILITY: virtual channel")?;
+        self.flags = buffer.get_u32_le();
+        self.vc_chunk_size = if buffer.remaining() >= 4 {
+            Some(buffer.get_u32_le())
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for VirtualChannelCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.flags).await?;
+        if let Some(vc_chunk_size) = self.vc_chunk_size {
+            writer.write_u32_le(vc_chunk_size).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.flags = reader.read_u32_le().await?;
+        if self.vc_chunk_size.is_some() {
+            self.vc_chunk_size = Some(reader.read_u32_le().await?);
+        }
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        4 + self.vc_chunk_size.map_or(0, |_| 4)
+    }
+}
+
+/// Sound capability
+/// send from client server
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/fadb6a2c-18fa-4fa7-a155-e970d9b1ac59
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SoundCapability {
+    pub sound_flags: u16,
+}
+
+impl SoundCapability {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "CAPABILITY: sound")?;
+        self.sound_flags = buffer.get_u16_le();
+        buffer.advance(2);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for SoundCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.sound_flags).await?;
+        writer.write_u16_le(0).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.sound_flags = reader.read_u16_le().await?;
+        reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4
+    }
+}
+
+/// Multi fragment capability
+/// send by both side (client, server)
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/01717954-716a-424d-af35-28fb2b86df89
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MultiFragmentUpdateCapability {
+    pub max_request_size: u32,
+}
+
+impl MultiFragmentUpdateCapability {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "CAPABILITY: multi fragment update")?;
+        self.max_request_size = buffer.get_u32_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for MultiFragmentUpdateCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.max_request_size).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.max_request_size = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4
+    }
+}
+
+/// Surface commands supported by the client
+/// MS-RDPBCGR 2.2.7.2.9 Surface Commands Capability Set (TS_SURFCMDS_CAPABILITYSET)
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SurfaceCommandFlag {
+    SurfcmdsSetSurfaceBits = 0x0002,
+    SurfcmdsFrameMarker = 0x0010,
+    SurfcmdsStreamSurfaceBits = 0x0040,
+}
+
+/// Surface commands capability
+/// send by both side (client, server)
+///
+/// MS-RDPBCGR 2.2.7.2.9 Surface Commands Capability Set (TS_SURFCMDS_CAPABILITYSET)
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SurfaceCommandsCapability {
+    pub cmd_flags: u32,
+}
+
+impl SurfaceCommandsCapability {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 8, "CAPABILITY: surface commands")?;
+        self.cmd_flags = buffer.get_u32_le();
+        buffer.advance(4);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for SurfaceCommandsCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.cmd_flags).await?;
+        writer.write_u32_le(0).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut buffer = vec![0; 8];
+        reader.read_exact(&mut buffer).await?;
+        self.read_from_buffer(&mut BytesMut::from(&buffer[..]))
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        8
+    }
+}
+
+/// Level of RemoteApp support of the client
+/// MS-RDPERP 2.2.1.1.1 Remote Programs Capability Set (TS_RAIL_CAPABILITYSET)
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RailSupportLevel {
+    RailLevelSupported = 0x00000001,
+    RailLevelDockedLangbarSupported = 0x00000002,
+    RailLevelShellIntegrationSupported = 0x00000004,
+    RailLevelLanguageImeSyncSupported = 0x00000008,
+    RailLevelServerToClientImeSyncSupported = 0x00000010,
+    RailLevelHideMinimizedAppsSupported = 0x00000020,
+    RailLevelWindowCloakingSupported = 0x00000040,
+    RailLevelHandshakeExSupported = 0x00000080,
+}
+
+/// RemoteApp capability
+/// send by both side (client, server)
+///
+/// MS-RDPERP 2.2.1.1.1 Remote Programs Capability Set (TS_RAIL_CAPABILITYSET)
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RailCapability {
+    pub rail_support_level: u32,
+}
+
+impl RailCapability {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "CAPABILITY: rail")?;
+        self.rail_support_level = buffer.get_u32_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for RailCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.rail_support_level).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.rail_support_level = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4
+    }
+}
+
+/// Windowing orders supported by the client
+/// MS-RDPERP 2.2.1.1.2 Window List Capability Set (TS_WINDOW_CAPABILITYSET)
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WindowSupportLevel {
+    WindowLevelNotSupported = 0x00000000,
+    WindowLevelSupported = 0x00000001,
+    WindowLevelSupportedEx = 0x00000002,
+}
+
+/// Window list capability, with the icon caches of the client
+/// send by the client
+///
+/// MS-RDPERP 2.2.1.1.2 Window List Capability Set (TS_WINDOW_CAPABILITYSET)
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct WindowListCapability {
+    pub wnd_support_level: u32,
+    pub num_icon_caches: u8,
+    pub num_icon_cache_entries: u16,
+}
+
+impl WindowListCapability {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 7, "CAPABILITY: window list")?;
+        self.wnd_support_level = buffer.get_u32_le();
+        self.num_icon_caches = buffer.get_u8();
+        self.num_icon_cache_entries = buffer.get_u16_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for WindowListCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.wnd_support_level).await?;
+        writer.write_u8(self.num_icon_caches).await?;
+        writer.write_u16_le(self.num_icon_cache_entries).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut buffer = vec![0; 7];
+        reader.read_exact(&mut buffer).await?;
+        self.read_from_buffer(&mut BytesMut::from(&buffer[..]))
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        7
+    }
+}
+
+/// Codecs of surface bits commands
+/// MS-RDPBCGR 2.2.7.2.10.1.1 Bitmap Codec (TS_BITMAPCODEC)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BitmapCodecType {
+    NsCodec,
+    RemoteFx,
+    ImageRemoteFx,
+}
+
+impl BitmapCodecType {
+    /// Codecs with a decoder in rdp-rs
+    pub const DECODERS: [BitmapCodecType; 1] = [BitmapCodecType::NsCodec];
+
+    /// GUID of the codec, as sent on the wire
+    pub fn guid(&self) -> [u8; 16] {
+        match self {
+            // CA8D1BB9-000F-154F-589F-AE2D1A87E2D6
+            BitmapCodecType::NsCodec => [
+                0xB9, 0x1B, 0x8D, 0xCA, 0x0F, 0x00, 0x4F, 0x15, 0x58, 0x9F, 0xAE, 0x2D, 0x1A, 0x87,
+                0xE2, 0xD6,
+            ],
+            // 76772F12-BD72-4463-AFB3-B73C9C6F7886
+            BitmapCodecType::RemoteFx => [
+                0x12, 0x2F, 0x77, 0x76, 0x72, 0xBD, 0x63, 0x44, 0xAF, 0xB3, 0xB7, 0x3C, 0x9C, 0x6F,
+                0x78, 0x86,
+            ],
+            // 2744CCD4-9D8A-4E74-803C-0ECBEEA19C54
+            BitmapCodecType::ImageRemoteFx => [
+                0xD4, 0xCC, 0x44, 0x27, 0x8A, 0x9D, 0x74, 0x4E, 0x80, 0x3C, 0x0E, 0xCB, 0xEE, 0xA1,
+                0x9C, 0x54,
+            ],
+        }
+    }
+
+    pub fn from_guid(guid: &[u8; 16]) -> Option<Self> {
+        [
+            BitmapCodecType::NsCodec,
+            BitmapCodecType::RemoteFx,
+            BitmapCodecType::ImageRemoteFx,
+        ]
+        .into_iter()
+        .find(|codec| &codec.guid() == guid)
+    }
+
+    /// Properties sent by the client for a decoder
+    fn client_properties(&self) -> Vec<u8> {
+        match self {
+            // Dynamic fidelity, subsampling and the highest color loss level
+            // MS-RDPNSC 2.2.1 NSCodec Capability Set (TS_NSCODEC_CAPABILITYSET)
+            BitmapCodecType::NsCodec => vec![1, 1, 7],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A codec and the id used by surface bits commands
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct BitmapCodec {
+    pub guid: [u8; 16],
+    pub codec_id: u8,
+    /// Codec specific properties
+    pub properties: Vec<u8>,
+}
+
+/// Bitmap codecs capability
+/// send by both side (client, server)
+///
+/// The client chooses the id of each codec
+///
+/// MS-RDPBCGR 2.2.7.2.10 Bitmap Codecs Capability Set (TS_BITMAPCODECS_CAPABILITYSET)
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct BitmapCodecsCapability {
+    pub codecs: Vec<BitmapCodec>,
+}
+
+impl BitmapCodecsCapability {
+    /// One entry for each codec with a decoder, ids start at 1
+    pub fn new(codecs: &[BitmapCodecType]) -> Self {
+        BitmapCodecsCapability {
+            codecs: codecs
+                .iter()
+                .filter(|codec| BitmapCodecType::DECODERS.contains(codec))
+                .enumerate()
+                .map(|(index, codec)| BitmapCodec {
+                    guid: codec.guid(),
+                    codec_id: index as u8 + 1,
+                    properties: codec.client_properties(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Codec of an id chosen by the client
+    pub fn codec(&self, codec_id: u8) -> Option<BitmapCodecType> {
+        self.codecs
+            .iter()
+            .find(|codec| codec.codec_id == codec_id)
+            .and_then(|codec| BitmapCodecType::from_guid(&codec.guid))
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 1, "CAPABILITY: bitmap codecs")?;
+        let count = buffer.get_u8();
+        self.codecs = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            check_remaining(buffer, 19, "CAPABILITY: bitmap codec")?;
+            let mut guid = [0; 16];
+            buffer.copy_to_slice(&mut guid);
+            let codec_id = buffer.get_u8();
+            let length = buffer.get_u16_le() as usize;
+            check_remaining(buffer, length, "CAPABILITY: bitmap codec properties")?;
+            self.codecs.push(BitmapCodec {
+                guid,
+                codec_id,
+                properties: buffer.split_to(length).to_vec(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for BitmapCodecsCapability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u8(self.codecs.len() as u8).await?;
+        for codec in self.codecs.iter() {
+            writer.write_all(&codec.guid).await?;
+            writer.write_u8(codec.codec_id).await?;
+            writer.write_u16_le(codec.properties.len() as u16).await?;
+            writer.write_all(&codec.properties).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let count = reader.read_u8().await?;
+        self.codecs = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut codec = BitmapCodec::default();
+            reader.read_exact(&mut codec.guid).await?;
+            codec.codec_id = reader.read_u8().await?;
+            codec.properties = vec![0; reader.read_u16_le().await? as usize];
+            reader.read_exact(&mut codec.properties).await?;
+            self.codecs.push(codec);
+        }
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        1 + self
+            .codecs
+            .iter()
+            .map(|codec| 19 + codec.properties.len())
+            .sum::<usize>()
+    }
+}
+
+/// A capability set
+/// Capabilities not handled by rdp-rs are kept raw
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/d705c3b6-a392-4b32-9610-391f6af62323
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Capability {
+    General(GeneralCapability),
+    Bitmap(BitmapCapability),
+    Order(OrderCapability),
+    Pointer(PointerCapability),
+    Input(InputCapability),
+    Brush(BrushCapability),
+    GlyphCache(GlyphCacheCapability),
+    VirtualChannel(VirtualChannelCapability),
+    Sound(SoundCapability),
+    MultiFragmentUpdate(MultiFragmentUpdateCapability),
+    BitmapCacheRev2(BitmapCacheRev2Capability),
+    OffscreenBitmapCache(OffscreenBitmapCacheCapability),
+    SurfaceCommands(SurfaceCommandsCapability),
+    BitmapCodecs(BitmapCodecsCapability),
+    Rail(RailCapability),
+    WindowList(WindowListCapability),
+    Unknown(u16, Vec<u8>),
+}
+
+impl Capability {
+    /// Value of the capabilitySetType field
+    pub fn cap_type(&self) -> u16 {
+        match self {
+            Capability::General(_) => CapabilitySetType::CapstypeGeneral as u16,
+            Capability::Bitmap(_) => CapabilitySetType::CapstypeBitmap as u16,
+            Capability::Order(_) => CapabilitySetType::CapstypeOrder as u16,
+            Capability::Pointer(_) => CapabilitySetType::CapstypePointer as u16,
+            Capability::Input(_) => CapabilitySetType::CapstypeInput as u16,
+            Capability::Brush(_) => CapabilitySetType::CapstypeBrush as u16,
+            Capability::GlyphCache(_) => CapabilitySetType::CapstypeGlyphcache as u16,
+            Capability::VirtualChannel(_) => CapabilitySetType::CapstypeVirtualchannel as u16,
+            Capability::Sound(_) => CapabilitySetType::CapstypeSound as u16,
+            Capability::MultiFragmentUpdate(_) => {
+                CapabilitySetType::CapsettypeMultifragmentupdate as u16
+            }
+            Capability::BitmapCacheRev2(_) => CapabilitySetType::CapstypeBitmapcacheRev2 as u16,
+            Capability::OffscreenBitmapCache(_) => CapabilitySetType::CapstypeOffscreencache as u16,
+            Capability::SurfaceCommands(_) => CapabilitySetType::CapsettypeSurfaceCommands as u16,
+            Capability::BitmapCodecs(_) => CapabilitySetType::CapsettypeBitmapCodecs as u16,
+            Capability::Rail(_) => CapabilitySetType::CapstypeRail as u16,
+            Capability::WindowList(_) => CapabilitySetType::CapstypeWindow as u16,
+            Capability::Unknown(cap_type, _) => *cap_type,
+        }
+    }
+
+    /// Size of the capability without the header
+    fn body_length(&self) -> usize {
+        match self {
+            Capability::General(capability) => capability.length(),
+            Capability::Bitmap(capability) => capability.length(),
+            Capability::Order(capability) => capability.length(),
+            Capability::Pointer(capability) => capability.length(),
+            Capability::Input(capability) => capability.length(),
+            Capability::Brush(capability) => capability.length(),
+            Capability::GlyphCache(capability) => capability.length(),
+            Capability::VirtualChannel(capability) => capability.length(),
+            Capability::Sound(capability) => capability.length(),
+            Capability::MultiFragmentUpdate(capability) => capability.length(),
+            Capability::BitmapCacheRev2(capability) => capability.length(),
+            Capability::OffscreenBitmapCache(capability) => capability.length(),
+            Capability::SurfaceCommands(capability) => capability.length(),
+            Capability::BitmapCodecs(capability) => capability.length(),
+            Capability::Rail(capability) => capability.length(),
+            Capability::WindowList(capability) => capability.length(),
+            Capability::Unknown(_, data) => data.len(),
+        }
+    }
+}
+
+#[async_trait]
+impl Message for Capability {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.cap_type()).await?;
+        writer.write_u16_le(self.length() as u16).await?;
+        match self {
+            Capability::General(capability) => capability.write_to(writer).await,
+            Capability::Bitmap(capability) => capability.write_to(writer).await,
+            Capability::Order(capability) => capability.write_to(writer).await,
+            Capability::Pointer(capability) => capability.write_to(writer).await,
+            Capability::Input(capability) => capability.write_to(writer).await,
+            Capability::Brush(capability) => capability.write_to(writer).await,
+            Capability::GlyphCache(capability) => capability.write_to(writer).await,
+            Capability::VirtualChannel(capability) => capability.write_to(writer).await,
+            Capability::Sound(capability) => capability.write_to(writer).await,
+            Capability::MultiFragmentUpdate(capability) => capability.write_to(writer).await,
+            Capability::BitmapCacheRev2(capability) => capability.write_to(writer).await,
+            Capability::OffscreenBitmapCache(capability) => capability.write_to(writer).await,
+            Capability::SurfaceCommands(capability) => capability.write_to(writer).await,
+            Capability::BitmapCodecs(capability) => capability.write_to(writer).await,
+            Capability::Rail(capability) => capability.write_to(writer).await,
+            Capability::WindowList(capability) => capability.write_to(writer).await,
+            Capability::Unknown(_, data) => writer.write_all(data).await,
+        }
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let cap_type = reader.read_u16_le().await?;
+        let length = reader.read_u16_le().await? as usize;
+        let mut buffer = vec![0; length.saturating_sub(4)];
+        reader.read_exact(&mut buffer).await?;
+        *self = read_capability_body(cap_type, BytesMut::from(&buffer[..]))?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        4 + self.body_length()
+    }
+}
+
+/// Parse the body of a capability set
+fn read_capability_body(cap_type: u16, mut buffer: BytesMut) -> Result<Capability> {
+    Ok(match CapabilitySetType::try_from(cap_type) {
+        Ok(CapabilitySetType::CapstypeGeneral) => {
+            let mut capability = GeneralCapability::new(0);
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::General(capability)
+        }
+        Ok(CapabilitySetType::CapstypeBitmap) => {
+            let mut capability = BitmapCapability::new(0, 0, 0);
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::Bitmap(capability)
+        }
+        Ok(CapabilitySetType::CapstypeOrder) => {
+            let mut capability = OrderCapability::new(0);
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::Order(capability)
+        }
+        Ok(CapabilitySetType::CapstypePointer) => {
+            let mut capability = PointerCapability::new(0);
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::Pointer(capability)
+        }
+        Ok(CapabilitySetType::CapstypeInput) => {
+            let mut capability = InputCapability::new(0, KeyboardLayout::US);
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::Input(capability)
+        }
+        Ok(CapabilitySetType::CapstypeBrush) => {
+            let mut capability = BrushCapability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::Brush(capability)
+        }
+        Ok(CapabilitySetType::CapstypeGlyphcache) => {
+            let mut capability = GlyphCacheCapability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::GlyphCache(capability)
+        }
+        Ok(CapabilitySetType::CapstypeVirtualchannel) => {
+            let mut capability = VirtualChannelCapability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::VirtualChannel(capability)
+        }
+        Ok(CapabilitySetType::CapstypeSound) => {
+            let mut capability = SoundCapability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::Sound(capability)
+        }
+        Ok(CapabilitySetType::CapsettypeMultifragmentupdate) => {
+            let mut capability = MultiFragmentUpdateCapability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::MultiFragmentUpdate(capability)
+        }
+        Ok(CapabilitySetType::CapstypeBitmapcacheRev2) => {
+            let mut capability = BitmapCacheRev2Capability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::BitmapCacheRev2(capability)
+        }
+        Ok(CapabilitySetType::CapstypeOffscreencache) => {
+            let mut capability = OffscreenBitmapCacheCapability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::OffscreenBitmapCache(capability)
+        }
+        Ok(CapabilitySetType::CapsettypeSurfaceCommands) => {
+            let mut capability = SurfaceCommandsCapability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::SurfaceCommands(capability)
+        }
+        Ok(CapabilitySetType::CapsettypeBitmapCodecs) => {
+            let mut capability = BitmapCodecsCapability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::BitmapCodecs(capability)
+        }
+        Ok(CapabilitySetType::CapstypeRail) => {
+            let mut capability = RailCapability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::Rail(capability)
+        }
+        Ok(CapabilitySetType::CapstypeWindow) => {
+            let mut capability = WindowListCapability::default();
+            capability.read_from_buffer(&mut buffer)?;
+            Capability::WindowList(capability)
+        }
+        _ => Capability::Unknown(cap_type, buffer.to_vec()),
+    })
+}
+
+/// Read a capability set with its header
+pub fn read_capability_set(buffer: &mut BytesMut) -> Result<Capability> {
+    check_remaining(buffer, 4, "CAPABILITY: capability set header")?;
+    let cap_type = buffer.get_u16_le();
+    let length = buffer.get_u16_le() as usize;
+    if length < 4 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "CAPABILITY: invalid capability set length",
+        ));
+    }
+    check_remaining(buffer, length - 4, "CAPABILITY: capability set")?;
+    read_capability_body(cap_type, buffer.split_to(length - 4))
+}
+
+/// Icon caches announced with RemoteApp
+const ICON_CACHES: u8 = 3;
+const ICON_CACHE_ENTRIES: u16 = 12;
+
+/// Offscreen bitmap cache announced with drawing orders,
+/// the size is in kilobytes
+const OFFSCREEN_CACHE_SIZE: u16 = 7680;
+const OFFSCREEN_CACHE_ENTRIES: u16 = 100;
+
+/// Typed configuration of the capabilities
+/// announced by the client in the confirm active PDU
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::CapabilitiesConfig;
+/// let config = CapabilitiesConfig {
+///     desktop_width: 1280,
+///     desktop_height: 1024,
+///     ..Default::default()
+/// };
+/// assert_eq!(config.capability_sets().len(), 10);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CapabilitiesConfig {
+    pub desktop_width: u16,
+    pub desktop_height: u16,
+    /// Preferred bits per pixel of bitmap updates
+    pub color_depth: u16,
+    pub keyboard_layout: KeyboardLayout,
+    /// Ask the server to send graphic updates as fast path
+    pub fast_path_output: bool,
+    /// Allow the server to change the desktop size
+    pub desktop_resize: bool,
+    /// Send keyboard events as unicode characters
+    pub unicode_input: bool,
+    /// Support of the horizontal mouse wheel
+    pub mouse_horizontal_wheel: bool,
+    /// Play beeps sent by the server
+    pub sound_beeps: bool,
+    /// Number of pointers cached by the client
+    pub pointer_cache_size: u16,
+    /// Largest fast path update the client is able to reassemble
+    pub multifragment_max_request_size: u32,
+    /// Entries of each bitmap cache, up to 5 caches
+    /// Bitmap caches and MemBlt orders are enabled when not empty
+    pub bitmap_cache_entries: Vec<u32>,
+    /// Ask the server for drawing orders,
+    /// they are drawn by the software GDI of the client
+    ///
+    /// Frame markers and offscreen bitmaps are enabled too
+    pub drawing_orders: bool,
+    /// Accept surface bits and frame marker commands
+    pub surface_commands: bool,
+    /// Codecs allowed in surface bits commands,
+    /// codecs without a decoder are never announced
+    pub bitmap_codecs: Vec<BitmapCodecType>,
+    /// Run RemoteApps instead of a full desktop
+    /// Windows are sent as windowing orders, the apps are started on the rail channel
+    pub remote_app: bool,
+}
+
+impl Default for CapabilitiesConfig {
+    fn default() -> Self {
+        CapabilitiesConfig {
+            desktop_width: 800,
+            desktop_height: 600,
+            color_depth: 24,
+            keyboard_layout: KeyboardLayout::US,
+            fast_path_output: true,
+            desktop_resize: false,
+            unicode_input: true,
+            mouse_horizontal_wheel: false,
+            sound_beeps: false,
+            pointer_cache_size: 20,
+            multifragment_max_request_size: 0xFFFF,
+            bitmap_cache_entries: vec![],
+            drawing_orders: false,
+            surface_commands: false,
+            bitmap_codecs: BitmapCodecType::DECODERS.to_vec(),
+            remote_app: false,
+        }
+    }
+}
+
+impl CapabilitiesConfig {
+    /// The server may send drawing orders
+    pub fn orders_enabled(&self) -> bool {
+        self.drawing_orders || !self.bitmap_cache_entries.is_empty()
+    }
+
+    /// Glyph caches are only used by text drawing orders
+    pub fn glyph_cache(&self) -> GlyphCacheCapability {
+        if self.drawing_orders {
+            GlyphCacheCapability::new(GlyphSupportLevel::GlyphSupportFull)
+        } else {
+            GlyphCacheCapability::default()
+        }
+    }
+
+    /// Codecs announced with surface commands
+    /// Surface bits commands are decoded with it
+    pub fn bitmap_codecs_capability(&self) -> BitmapCodecsCapability {
+        if self.surface_commands {
+            BitmapCodecsCapability::new(&self.bitmap_codecs)
+        } else {
+            BitmapCodecsCapability::default()
+        }
+    }
+
+    /// All capability sets sent by the client
+    pub fn capability_sets(&self) -> Vec<Capability> {
+        let mut extra_flags = GeneralExtraFlag::LongCredentialsSupported as u16
+            | GeneralExtraFlag::NoBitmapCompressionHdr as u16
+            | GeneralExtraFlag::EncSaltedChecksum as u16;
+        if self.fast_path_output {
+            extra_flags |= GeneralExtraFlag::FastpathOutputSupported as u16;
+        }
+
+        let mut bitmap =
+            BitmapCapability::new(self.color_depth, self.desktop_width, self.desktop_height);
+        bitmap.desktop_resize_flag = self.desktop_resize as u16;
+        // The planar decoder handles all options of 32 bpp bitmaps
+        if self.color_depth == 32 {
+            bitmap.drawing_flags = DrawingFlag::DrawAllowDynamicColorFidelity as u8
+                | DrawingFlag::DrawAllowColorSubsampling as u8
+                | DrawingFlag::DrawAllowSkipAlpha as u8;
+        }
+
+        let mut input_flags =
+            InputFlags::InputFlagScancodes as u16 | InputFlags::InputFlagMousex as u16;
+        if self.unicode_input {
+            input_flags |= InputFlags::InputFlagUnicode as u16;
+        }
+        if self.mouse_horizontal_wheel {
+            input_flags |= InputFlags::TsInputFlagMouseHwheel as u16;
+        }
+
+        let mut sound = SoundCapability::default();
+        if self.sound_beeps {
+            sound.sound_flags |= SoundFlag::SoundBeepsFlag as u16;
+        }
+
+        let mut order = OrderCapability::new(
+            OrderFlag::NEGOTIATEORDERSUPPORT as u16 | OrderFlag::ZEROBOUNDSDELTASSUPPORT as u16,
+        );
+        if !self.bitmap_cache_entries.is_empty() {
+            order.order_support[OrderSupportIndex::Memblt as usize] = 1;
+        }
+        if self.drawing_orders {
+            for index in [
+                OrderSupportIndex::Dstblt,
+                OrderSupportIndex::Patblt,
+                OrderSupportIndex::Scrblt,
+                OrderSupportIndex::Opaquerect,
+                OrderSupportIndex::Lineto,
+                OrderSupportIndex::Polyline,
+                OrderSupportIndex::Glyphindex,
+                OrderSupportIndex::Fastglyph,
+            ] {
+                order.order_support[index as usize] = 1;
+            }
+            order.order_flags |= OrderFlag::OrderflagsExtraFlags as u16;
+            order.order_support_ex_flags |= OrderFlagEx::AltsecFrameMarkerSupport as u16;
+        }
+
+        // Cached brushes are 8x8, monochrome or in color
+        let mut brush = BrushCapability::default();
+        if self.drawing_orders {
+            brush.brush_support_level = BrushSupportLevel::BrushColor8x8 as u32;
+        }
+
+        let mut capabilities = vec![
+            Capability::General(GeneralCapability::new(extra_flags)),
+            Capability::Bitmap(bitmap),
+            Capability::Order(order),
+            Capability::Pointer(PointerCapability::new(self.pointer_cache_size)),
+            Capability::Input(InputCapability::new(input_flags, self.keyboard_layout)),
+            Capability::Brush(brush),
+            Capability::GlyphCache(self.glyph_cache()),
+            // Channel data is sent uncompressed, the bulk compression
+            // of the other data is requested in the info packet
+            Capability::VirtualChannel(VirtualChannelCapability::default()),
+            Capability::Sound(sound),
+            Capability::MultiFragmentUpdate(MultiFragmentUpdateCapability {
+                max_request_size: self.multifragment_max_request_size,
+            }),
+        ];
+        if !self.bitmap_cache_entries.is_empty() {
+            capabilities.push(Capability::BitmapCacheRev2(BitmapCacheRev2Capability::new(
+                &self.bitmap_cache_entries,
+            )));
+        }
+        if self.drawing_orders {
+            capabilities.push(Capability::OffscreenBitmapCache(
+                OffscreenBitmapCacheCapability::new(OFFSCREEN_CACHE_SIZE, OFFSCREEN_CACHE_ENTRIES),
+            ));
+        }
+        if self.surface_commands {
+            capabilities.push(Capability::SurfaceCommands(SurfaceCommandsCapability {
+                cmd_flags: SurfaceCommandFlag::SurfcmdsSetSurfaceBits as u32
+                    | SurfaceCommandFlag::SurfcmdsFrameMarker as u32
+                    | SurfaceCommandFlag::SurfcmdsStreamSurfaceBits as u32,
+            }));
+            capabilities.push(Capability::BitmapCodecs(self.bitmap_codecs_capability()));
+        }
+        if self.remote_app {
+            capabilities.push(Capability::Rail(RailCapability {
+                rail_support_level: RailSupportLevel::RailLevelSupported as u32
+                    | RailSupportLevel::RailLevelHandshakeExSupported as u32,
+            }));
+            capabilities.push(Capability::WindowList(WindowListCapability {
+                wnd_support_level: WindowSupportLevel::WindowLevelSupportedEx as u32,
+                num_icon_caches: ICON_CACHES,
+                num_icon_cache_entries: ICON_CACHE_ENTRIES,
+            }));
+        }
+        capabilities
+    }
+}
+
+/// Demand Active PDU
+/// First PDU send from server to client
+/// This payload include all capabilities
+/// of the target server
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/bd612af5-cb54-43a2-9646-438bc3ecf5db
+#[derive(Debug, Default)]
+pub struct DemandActivePdu {
+    pub share_id: u32,
+    pub source_descriptor: Vec<u8>,
+    pub capability_sets: Vec<Capability>,
+    pub session_id: u32,
+}
+
+impl DemandActivePdu {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 8, "CAPABILITY: demand active")?;
+        self.share_id = buffer.get_u32_le();
+        let length_source_descriptor = buffer.get_u16_le() as usize;
+        let length_combined_capabilities = buffer.get_u16_le() as usize;
+        check_remaining(
+            buffer,
+            length_source_descriptor + length_combined_capabilities,
+            "CAPABILITY: demand active",
+        )?;
+        self.source_descriptor = buffer.split_to(length_source_descriptor).to_vec();
+
+        let mut capabilities = buffer.split_to(length_combined_capabilities);
+        check_remaining(&capabilities, 4, "CAPABILITY: demand active")?;
+        let number_capabilities = capabilities.get_u16_le();
+        capabilities.advance(2);
+        self.capability_sets = (0..number_capabilities)
+            .map(|_| read_capability_set(&mut capabilities))
+            .collect::<Result<Vec<Capability>>>()?;
+
+        // Session id is not sent by old servers
+        self.session_id = if buffer.remaining() >= 4 {
+            buffer.get_u32_le()
+        } else {
+            0
+        };
+        Ok(())
+    }
+
+    /// Desktop size announced in the bitmap capability
+    pub fn desktop_size(&self) -> Option<(u16, u16)> {
+        self.capability_sets
+            .iter()
+            .find_map(|capability| match capability {
+                Capability::Bitmap(bitmap) => Some((bitmap.desktop_width, bitmap.desktop_height)),
+                _ => None,
+            })
+    }
+}
+
+/// First PDU send from client to server
+/// This PDU declare capabilities for the client
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/4e9722c3-ad83-43f5-af5a-529f73d88b48
+#[derive(Debug)]
+pub struct ConfirmActivePdu {
+    pub share_id: u32,
+    pub originator_id: u16,
+    pub source_descriptor: Vec<u8>,
+    pub capability_sets: Vec<Capability>,
+}
+
+impl ConfirmActivePdu {
+    pub fn new(share_id: u32, source_descriptor: &[u8], capability_sets: Vec<Capability>) -> Self {
+        ConfirmActivePdu {
+            share_id,
+            originator_id: 0x03EA,
+            source_descriptor: source_descriptor.to_vec(),
+            capability_sets,
+        }
+    }
+
+    fn combined_capabilities_length(&self) -> usize {
+        4 + self.capability_sets.iter().map(|c| c.length()).sum::<usize>()
+    }
+}
+
+#[async_trait]
+impl Message for ConfirmActivePdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.share_id).await?;
+        writer.write_u16_le(self.originator_id).await?;
+        writer.write_u16_le(self.source_descriptor.len() as u16).await?;
+        writer.write_u16_le(self.combined_capabilities_length() as u16).await?;
+        writer.write_all(&self.source_descriptor).await?;
+        writer.write_u16_le(self.capability_sets.len() as u16).await?;
+        writer.write_u16_le(0).await?;
+        for capability in self.capability_sets.iter() {
+            capability.write_to(writer).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.share_id = reader.read_u32_le().await?;
+        self.originator_id = reader.read_u16_le().await?;
+        let length_source_descriptor = reader.read_u16_le().await? as usize;
+        reader.read_u16_le().await?;
+        self.source_descriptor = vec![0; length_source_descriptor];
+        reader.read_exact(&mut self.source_descriptor).await?;
+        let number_capabilities = reader.read_u16_le().await?;
+        reader.read_u16_le().await?;
+        self.capability_sets.clear();
+        for _ in 0..number_capabilities {
+            let mut capability = Capability::Unknown(0, Vec::new());
+            capability.read_from(reader).await?;
+            self.capability_sets.push(capability);
+        }
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        10 + self.source_descriptor.len() + self.combined_capabilities_length()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Test format message of demand active pdu
+    #[test]
+    fn test_demand_active_pdu() {
+        let mut buffer = BytesMut::from(
+            &[
+                234, 3, 1, 0, 4, 0, 179, 1, 82, 68, 80, 0, 17, 0, 0, 0, 9, 0, 8, 0, 234, 3, 0, 0, 1,
+                0, 24, 0, 1, 0, 3, 0, 0, 2, 0, 0, 0, 0, 29, 4, 0, 0, 0, 0, 0, 0, 1, 1, 20, 0, 12, 0,
+                2, 0, 0, 0, 64, 6, 0, 0, 10, 0, 8, 0, 6, 0, 0, 0, 8, 0, 10, 0, 1, 0, 25, 0, 25, 0,
+                27, 0, 6, 0, 3, 0, 14, 0, 8, 0, 1, 0, 0, 0, 2, 0, 28, 0, 32, 0, 1, 0, 1, 0, 1, 0,
+                32, 3, 88, 2, 0, 0, 1, 0, 1, 0, 0, 30, 1, 0, 0, 0, 29, 0, 96, 0, 4, 185, 27, 141,
+                202, 15, 0, 79, 21, 88, 159, 174, 45, 26, 135, 226, 214, 0, 3, 0, 1, 1, 3, 18, 47,
+                119, 118, 114, 189, 99, 68, 175, 179, 183, 60, 156, 111, 120, 134, 0, 4, 0, 0, 0, 0,
+                0, 166, 81, 67, 156, 53, 53, 174, 66, 145, 12, 205, 252, 229, 118, 11, 88, 0, 4, 0,
+                0, 0, 0, 0, 212, 204, 68, 39, 138, 157, 116, 78, 128, 60, 14, 203, 238, 161, 156,
+                84, 0, 4, 0, 0, 0, 0, 0, 3, 0, 88, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 64, 66, 15, 0, 1, 0, 20, 0, 0, 0, 1, 0, 0, 0, 170, 0, 1, 1, 1, 1, 1, 0, 0, 0, 1,
+                0, 0, 1, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 0, 1, 1, 1, 1, 0, 0, 0, 0, 161, 6, 6, 0,
+                64, 66, 15, 0, 64, 66, 15, 0, 1, 0, 0, 0, 0, 0, 0, 0, 18, 0, 8, 0, 1, 0, 0, 0, 13,
+                0, 88, 0, 117, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0, 23, 0, 8, 0, 255, 0, 0, 0, 24, 0, 11, 0, 2, 0, 0, 0, 3, 12, 0, 26, 0, 8,
+                0, 43, 72, 9, 0, 28, 0, 12, 0, 82, 0, 0, 0, 0, 0, 0, 0, 30, 0, 8, 0, 0, 0, 0, 0, 0,
+                0, 0, 0,
+            ][..],
+        );
+        let mut pdu = DemandActivePdu::default();
+        pdu.read_from_buffer(&mut buffer).unwrap();
+        assert_eq!(pdu.share_id, 0x103ea);
+        assert_eq!(pdu.source_descriptor, b"RDP\0");
+        assert_eq!(pdu.capability_sets.len(), 17);
+        assert_eq!(pdu.desktop_size(), Some((800, 600)));
+    }
+
+    /// Test confirm active PDU format
+    #[tokio::test]
+    async fn test_confirm_active_pdu() {
+        let pdu = ConfirmActivePdu::new(
+            4,
+            b"rdp-rs",
+            vec![Capability::Brush(BrushCapability::default())],
+        );
+        let buffer = to_vec(&pdu).await.unwrap();
+        assert_eq!(buffer.len(), pdu.length());
+        assert_eq!(
+            buffer,
+            [
+                4, 0, 0, 0, 234, 3, 6, 0, 12, 0, 114, 100, 112, 45, 114, 115, 1, 0, 0, 0, 15, 0, 8,
+                0, 0, 0, 0, 0
+            ]
+        );
+    }
+
+    /// Capability sets built from the config must be valid round trip
+    #[tokio::test]
+    async fn test_capabilities_config() {
+        let config = CapabilitiesConfig {
+            desktop_width: 1024,
+            desktop_height: 768,
+            ..Default::default()
+        };
+        for capability in config.capability_sets() {
+            let mut buffer = BytesMut::from(&to_vec(&capability).await.unwrap()[..]);
+            assert_eq!(buffer.len(), capability.length());
+            assert_eq!(read_capability_set(&mut buffer).unwrap(), capability);
+        }
+    }
+
+    /// Bitmap caches add the rev2 capability and MemBlt support
+    #[tokio::test]
+    async fn test_capabilities_config_bitmap_cache() {
+        let config = CapabilitiesConfig {
+            bitmap_cache_entries: vec![600, 600, 2048],
+            ..Default::default()
+        };
+        let capabilities = config.capability_sets();
+        assert_eq!(capabilities.len(), 11);
+        match &capabilities[2] {
+            Capability::Order(order) => {
+                assert_eq!(order.order_support[OrderSupportIndex::Memblt as usize], 1)
+            }
+            _ => panic!("expected order capability"),
+        }
+        let capability = capabilities.last().unwrap();
+        let mut buffer = BytesMut::from(&to_vec(capability).await.unwrap()[..]);
+        assert_eq!(buffer.len(), 40);
+        assert_eq!(&read_capability_set(&mut buffer).unwrap(), capability);
+    }
+
+    /// Surface commands are only advertised when enabled
+    #[test]
+    fn test_capabilities_config_surface_commands() {
+        let config = CapabilitiesConfig {
+            surface_commands: true,
+            ..Default::default()
+        };
+        let capabilities = config.capability_sets();
+        assert_eq!(capabilities.len(), 12);
+        assert_eq!(
+            capabilities[10].cap_type(),
+            CapabilitySetType::CapsettypeSurfaceCommands as u16
+        );
+        assert_eq!(CapabilitiesConfig::default().capability_sets().len(), 10);
+    }
+
+    /// RemoteApp adds the rail and window list capabilities
+    #[tokio::test]
+    async fn test_capabilities_config_remote_app() {
+        let config = CapabilitiesConfig {
+            remote_app: true,
+            ..Default::default()
+        };
+        let capabilities = config.capability_sets();
+        assert_eq!(capabilities.len(), 12);
+        for capability in &capabilities[10..] {
+            let mut buffer = BytesMut::from(&to_vec(capability).await.unwrap()[..]);
+            assert_eq!(buffer.len(), capability.length());
+            assert_eq!(&read_capability_set(&mut buffer).unwrap(), capability);
+        }
+        assert_eq!(capabilities[11].length(), 11);
+    }
+
+    /// Only codecs with a decoder get an id
+    #[tokio::test]
+    async fn test_bitmap_codecs_capability() {
+        let capability =
+            BitmapCodecsCapability::new(&[BitmapCodecType::RemoteFx, BitmapCodecType::NsCodec]);
+        assert_eq!(capability.codecs.len(), 1);
+        assert_eq!(capability.codec(1), Some(BitmapCodecType::NsCodec));
+        assert_eq!(capability.codec(2), None);
+
+        let capability = Capability::BitmapCodecs(capability);
+        let mut buffer = BytesMut::from(&to_vec(&capability).await.unwrap()[..]);
+        assert_eq!(buffer.len(), 4 + 1 + 19 + 3);
+        assert_eq!(read_capability_set(&mut buffer).unwrap(), capability);
+    }
+
+    /// Drawing orders are advertised in the order capability
+    #[test]
+    fn test_capabilities_config_drawing_orders() {
+        let config = CapabilitiesConfig {
+            drawing_orders: true,
+            ..Default::default()
+        };
+        assert!(config.orders_enabled());
+        match &config.capability_sets()[2] {
+            Capability::Order(order) => {
+                assert_eq!(
+                    order.order_support[OrderSupportIndex::Opaquerect as usize],
+                    1
+                );
+                assert_eq!(order.order_support[OrderSupportIndex::Memblt as usize], 0);
+            }
+            _ => panic!("expected order capability"),
+        }
+        match &config.capability_sets()[6] {
+            Capability::GlyphCache(glyph_cache) => assert_eq!(
+                glyph_cache.glyph_support_level,
+                GlyphSupportLevel::GlyphSupportFull as u16
+            ),
+            _ => panic!("expected glyph cache capability"),
+        }
+        assert!(config.capability_sets().iter().any(|capability| matches!(
+            capability,
+            Capability::OffscreenBitmapCache(offscreen) if offscreen.offscreen_cache_entries > 0
+        )));
+    }
+}
@@ -0,0 +1,339 @@
+use crate::core::proxy::Direction;
+
+use std::io::{Result, Write};
+use std::net::SocketAddrV4;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Section header block
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D0D0A;
+/// Interface description block
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+/// Enhanced packet block
+const BLOCK_ENHANCED_PACKET: u32 = 0x00000006;
+/// Byte order magic of the section header
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+/// Packets start with the IPv4 header
+const LINKTYPE_RAW: u16 = 101;
+
+/// Size of the synthetic IPv4 header
+const IP_HEADER_SIZE: usize = 20;
+/// Size of the synthetic TCP header
+const TCP_HEADER_SIZE: usize = 20;
+/// Largest payload of a single packet
+const SEGMENT_MAX_SIZE: usize = u16::MAX as usize - IP_HEADER_SIZE - TCP_HEADER_SIZE;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// Internet checksum over a list of slices
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for byte in parts.iter().flat_map(|part| part.iter()).enumerate() {
+        sum += match byte.0 % 2 {
+            0 => (*byte.1 as u32) << 8,
+            _ => *byte.1 as u32,
+        };
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Write the decrypted TPKT and fast path stream
+/// in a pcapng file readable by Wireshark
+///
+/// Each segment is wrapped in synthetic IPv4 and TCP headers
+/// so the RDP dissector can follow the conversation
+///
+/// # Example
+/// ```rust, ignore
+/// let file = std::fs::File::create("session.pcapng")?;
+/// let capture = PcapngWriter::new(
+///     file,
+///     "10.0.0.2:50000".parse().unwrap(),
+///     "10.0.0.1:3389".parse().unwrap(),
+/// )?;
+/// let tpkt = TpktClient::new(CaptureStream::new(stream, capture));
+/// ```
+pub struct PcapngWriter<W> {
+    writer: W,
+    client: SocketAddrV4,
+    server: SocketAddrV4,
+    /// Next sequence number sent by the client
+    client_seq: u32,
+    /// Next sequence number sent by the server
+    server_seq: u32,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Write the file header and a synthetic TCP handshake
+    pub fn new(writer: W, client: SocketAddrV4, server: SocketAddrV4) -> Result<Self> {
+        let mut capture = PcapngWriter {
+            writer,
+            client,
+            server,
+            client_seq: 0,
+            server_seq: 0,
+        };
+
+        // Version 1.0 and an unknown section length
+        let mut section = Vec::new();
+        section.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend_from_slice(&1u16.to_le_bytes());
+        section.extend_from_slice(&0u16.to_le_bytes());
+        section.extend_from_slice(&u64::MAX.to_le_bytes());
+        capture.write_block(BLOCK_SECTION_HEADER, &section)?;
+
+        // No snap length
+        let mut interface = Vec::new();
+        interface.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        interface.extend_from_slice(&0u16.to_le_bytes());
+        interface.extend_from_slice(&0u32.to_le_bytes());
+        capture.write_block(BLOCK_INTERFACE_DESCRIPTION, &interface)?;
+
+        capture.write_packet(Direction::ClientToServer, TCP_SYN, &[])?;
+        capture.client_seq += 1;
+        capture.write_packet(Direction::ServerToClient, TCP_SYN | TCP_ACK, &[])?;
+        capture.server_seq += 1;
+        capture.write_packet(Direction::ClientToServer, TCP_ACK, &[])?;
+        Ok(capture)
+    }
+
+    /// Write a block padded on 32 bits
+    /// with its total length on both sides
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> Result<()> {
+        let padding = (4 - body.len() % 4) % 4;
+        let length = (12 + body.len() + padding) as u32;
+        let mut block = Vec::with_capacity(length as usize);
+        block.extend_from_slice(&block_type.to_le_bytes());
+        block.extend_from_slice(&length.to_le_bytes());
+        block.extend_from_slice(body);
+        block.resize(block.len() + padding, 0);
+        block.extend_from_slice(&length.to_le_bytes());
+        self.writer.write_all(&block)
+    }
+
+    /// Write a single TCP packet
+    fn write_packet(&mut self, direction: Direction, flags: u8, payload: &[u8]) -> Result<()> {
+        let (source, destination, seq, ack) = match direction {
+            Direction::ClientToServer => {
+                (self.client, self.server, self.client_seq, self.server_seq)
+            }
+            Direction::ServerToClient => {
+                (self.server, self.client, self.server_seq, self.client_seq)
+            }
+        };
+
+        let mut tcp = Vec::with_capacity(TCP_HEADER_SIZE + payload.len());
+        tcp.extend_from_slice(&source.port().to_be_bytes());
+        tcp.extend_from_slice(&destination.port().to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&if flags & TCP_ACK != 0 { ack } else { 0 }.to_be_bytes());
+        tcp.push(((TCP_HEADER_SIZE / 4) as u8) << 4);
+        tcp.push(flags);
+        tcp.extend_from_slice(&u16::MAX.to_be_bytes());
+        tcp.extend_from_slice(&[0, 0, 0, 0]);
+        tcp.extend_from_slice(payload);
+
+        let mut pseudo_header = Vec::with_capacity(12);
+        pseudo_header.extend_from_slice(&source.ip().octets());
+        pseudo_header.extend_from_slice(&destination.ip().octets());
+        pseudo_header.extend_from_slice(&[0, 6]);
+        pseudo_header.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+        let tcp_checksum = checksum(&[&pseudo_header, &tcp]);
+        tcp[16..18].copy_from_slice(&tcp_checksum.to_be_bytes());
+
+        // Version 4, no option, don't fragment, TTL 128, TCP
+        let mut ip = Vec::with_capacity(IP_HEADER_SIZE + tcp.len());
+        ip.extend_from_slice(&[0x45, 0]);
+        ip.extend_from_slice(&((IP_HEADER_SIZE + tcp.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0, 0x40, 0, 128, 6, 0, 0]);
+        ip.extend_from_slice(&source.ip().octets());
+        ip.extend_from_slice(&destination.ip().octets());
+        let ip_checksum = checksum(&[&ip]);
+        ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+        ip.extend_from_slice(&tcp);
+
+        // Timestamp in microseconds, the default resolution
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_micros() as u64);
+        let mut packet = Vec::with_capacity(20 + ip.len());
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        packet.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        packet.extend_from_slice(&(ip.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&(ip.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&ip);
+        self.write_block(BLOCK_ENHANCED_PACKET, &packet)
+    }
+
+    /// Write a part of the stream sent in one direction
+    /// Large segments are split over several packets
+    pub fn write_segment(&mut self, direction: Direction, payload: &[u8]) -> Result<()> {
+        for chunk in payload.chunks(SEGMENT_MAX_SIZE) {
+            self.write_packet(direction, TCP_PSH | TCP_ACK, chunk)?;
+            match direction {
+                Direction::ClientToServer => {
+                    self.client_seq = self.client_seq.wrapping_add(chunk.len() as u32)
+                }
+                Direction::ServerToClient => {
+                    self.server_seq = self.server_seq.wrapping_add(chunk.len() as u32)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the end of the connection
+    /// and give back the writer
+    pub fn finish(mut self) -> Result<W> {
+        self.write_packet(Direction::ClientToServer, TCP_FIN | TCP_ACK, &[])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Transport of a client which copies
+/// all sent and received bytes in a capture
+///
+/// It must wrap the decrypted stream
+/// to be readable by the RDP dissector
+pub struct CaptureStream<S, W> {
+    stream: S,
+    capture: PcapngWriter<W>,
+}
+
+impl<S, W: Write> CaptureStream<S, W> {
+    pub fn new(stream: S, capture: PcapngWriter<W>) -> Self {
+        CaptureStream { stream, capture }
+    }
+
+    /// Give back the stream and the capture
+    pub fn into_inner(self) -> (S, PcapngWriter<W>) {
+        (self.stream, self.capture)
+    }
+}
+
+impl<S: AsyncRead + Unpin, W: Write + Unpin> AsyncRead for CaptureStream<S, W> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        match Pin::new(&mut this.stream).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                let received = &buf.filled()[filled..];
+                Poll::Ready(match received.is_empty() {
+                    true => Ok(()),
+                    false => this
+                        .capture
+                        .write_segment(Direction::ServerToClient, received),
+                })
+            }
+            poll => poll,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin, W: Write + Unpin> AsyncWrite for CaptureStream<S, W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.stream).poll_write(cx, buf) {
+            Poll::Ready(Ok(size)) => Poll::Ready(
+                this.capture
+                    .write_segment(Direction::ClientToServer, &buf[..size])
+                    .map(|_| size),
+            ),
+            poll => poll,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn new_capture() -> PcapngWriter<Vec<u8>> {
+        PcapngWriter::new(
+            Vec::new(),
+            "10.0.0.2:50000".parse().unwrap(),
+            "10.0.0.1:3389".parse().unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// Split the blocks of a capture
+    fn read_blocks(mut capture: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut blocks = Vec::new();
+        while !capture.is_empty() {
+            let block_type = u32::from_le_bytes(capture[0..4].try_into().unwrap());
+            let length = u32::from_le_bytes(capture[4..8].try_into().unwrap()) as usize;
+            assert_eq!(capture[length - 4..length], capture[4..8]);
+            blocks.push((block_type, capture[8..length - 4].to_vec()));
+            capture = &capture[length..];
+        }
+        blocks
+    }
+
+    /// Test of the blocks and headers of a segment
+    #[test]
+    fn test_write_segment() {
+        let mut capture = new_capture();
+        capture
+            .write_segment(Direction::ClientToServer, &[3, 0, 0, 5, 1])
+            .unwrap();
+        let blocks = read_blocks(&capture.writer);
+
+        assert_eq!(blocks[0].0, BLOCK_SECTION_HEADER);
+        assert_eq!(blocks[1].0, BLOCK_INTERFACE_DESCRIPTION);
+        // Handshake then data
+        assert_eq!(blocks.len(), 6);
+
+        let (block_type, packet) = &blocks[5];
+        assert_eq!(*block_type, BLOCK_ENHANCED_PACKET);
+        let ip = &packet[20..20 + 45];
+        assert_eq!(checksum(&[&ip[..IP_HEADER_SIZE]]), 0);
+        assert_eq!(ip[12..16], [10, 0, 0, 2]);
+        let tcp = &ip[IP_HEADER_SIZE..];
+        assert_eq!(u16::from_be_bytes([tcp[2], tcp[3]]), 3389);
+        assert_eq!(u32::from_be_bytes(tcp[4..8].try_into().unwrap()), 1);
+        assert_eq!(tcp[13], TCP_PSH | TCP_ACK);
+        assert_eq!(tcp[TCP_HEADER_SIZE..], [3, 0, 0, 5, 1]);
+    }
+
+    /// Both directions of a stream are captured
+    #[tokio::test]
+    async fn test_capture_stream() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut stream = CaptureStream::new(client, new_capture());
+
+        stream.write_all(&[1, 2]).await.unwrap();
+        server.write_all(&[3]).await.unwrap();
+        let mut buffer = [0; 1];
+        stream.read_exact(&mut buffer).await.unwrap();
+
+        let (_, capture) = stream.into_inner();
+        assert_eq!(capture.client_seq, 3);
+        assert_eq!(capture.server_seq, 2);
+        let blocks = read_blocks(&capture.finish().unwrap());
+        assert_eq!(blocks.len(), 8);
+    }
+}
@@ -0,0 +1,361 @@
+use crate::core::limits::PduLimits;
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use tokio::sync::mpsc;
+
+/// Size of the chunks when the server doesn't give one
+/// MS-RDPBCGR 3.1.5.2.2 Reassembly of Chunked Virtual Channel Data
+pub const CHANNEL_CHUNK_LENGTH: usize = 1600;
+
+/// Flags of the channel PDU header
+/// MS-RDPBCGR 2.2.6.1.1 Channel PDU Header (CHANNEL_PDU_HEADER)
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChannelFlag {
+    ChannelFlagFirst = 0x00000001,
+    ChannelFlagLast = 0x00000002,
+    ChannelFlagShowProtocol = 0x00000010,
+    ChannelFlagSuspend = 0x00000020,
+    ChannelFlagResume = 0x00000040,
+    ChannelFlagShadowPersistent = 0x00000080,
+    ChannelPacketCompressed = 0x00200000,
+    ChannelPacketAtFront = 0x00400000,
+    ChannelPacketFlushed = 0x00800000,
+}
+
+/// Header of each chunk of a channel message
+/// MS-RDPBCGR 2.2.6.1.1 Channel PDU Header (CHANNEL_PDU_HEADER)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChannelPduHeader {
+    /// Length of the whole message, not of the chunk
+    pub length: u32,
+    pub flags: u32,
+}
+
+impl ChannelPduHeader {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 8, "CHANNEL: channel PDU header")?;
+        self.length = buffer.get_u32_le();
+        self.flags = buffer.get_u32_le();
+        Ok(())
+    }
+}
+
+/// Split a message in chunks starting with their header
+/// The flags are added to the first and last flags of each chunk
+///
+/// # Example
+/// ```
+/// use rdp::core::channel::split_chunks;
+/// let chunks = split_chunks(&[0; 2000], 0, 1600);
+/// assert_eq!(chunks.len(), 2);
+/// assert_eq!(chunks[1].len(), 8 + 400);
+/// ```
+pub fn split_chunks(data: &[u8], flags: u32, chunk_length: usize) -> Vec<Vec<u8>> {
+    let mut chunks: Vec<&[u8]> = data.chunks(chunk_length).collect();
+    // Empty messages are still sent
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    let count = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut chunk_flags = flags;
+            if index == 0 {
+                chunk_flags |= ChannelFlag::ChannelFlagFirst as u32;
+            }
+            if index == count - 1 {
+                chunk_flags |= ChannelFlag::ChannelFlagLast as u32;
+            }
+            let mut buffer = Vec::with_capacity(8 + chunk.len());
+            buffer.put_u32_le(data.len() as u32);
+            buffer.put_u32_le(chunk_flags);
+            buffer.extend_from_slice(chunk);
+            buffer
+        })
+        .collect()
+}
+
+/// Reassembly state of a channel
+#[derive(Default)]
+struct ChannelState {
+    /// Chunks of the current message
+    data: BytesMut,
+    /// Length of the whole current message,
+    /// None between two messages
+    length: Option<usize>,
+    /// Open handle of the channel, messages are events without it
+    sender: Option<mpsc::UnboundedSender<Bytes>>,
+}
+
+/// Reassemble the chunks of all static virtual channels
+/// and give the messages to their open handle
+///
+/// A message sent in a single chunk is a slice of the
+/// received PDU, only the chunked ones are copied
+///
+/// # Example
+/// ```
+/// use bytes::{Bytes, BytesMut};
+/// use rdp::core::channel::{split_chunks, StaticChannels};
+/// let mut channels = StaticChannels::default();
+/// let chunks = split_chunks(b"hello", 0, 3);
+/// assert_eq!(channels.read("echo", BytesMut::from(&chunks[0][..])).unwrap(), None);
+/// let message = channels.read("echo", BytesMut::from(&chunks[1][..])).unwrap();
+/// assert_eq!(message, Some(Bytes::from_static(b"hello")));
+/// ```
+#[derive(Default)]
+pub struct StaticChannels {
+    channels: HashMap<String, ChannelState>,
+    /// The length announced by a first chunk is checked
+    /// against the largest reassembled size
+    limits: PduLimits,
+}
+
+impl StaticChannels {
+    pub fn new(limits: PduLimits) -> Self {
+        StaticChannels {
+            channels: HashMap::new(),
+            limits,
+        }
+    }
+
+    /// Send the next messages of the channel to the handle
+    pub fn open(&mut self, channel_name: &str, sender: mpsc::UnboundedSender<Bytes>) {
+        self.channels
+            .entry(channel_name.to_string())
+            .or_default()
+            .sender = Some(sender);
+    }
+
+    /// Read a chunk of a channel
+    /// Complete messages of channels without an open handle are returned
+    pub fn read(&mut self, channel_name: &str, mut payload: BytesMut) -> Result<Option<Bytes>> {
+        let mut header = ChannelPduHeader::default();
+        header.read_from_buffer(&mut payload)?;
+        if header.flags & ChannelFlag::ChannelPacketCompressed as u32 != 0 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "CHANNEL: compressed channel data",
+            ));
+        }
+        // Flow control of the server, without data
+        let flow_control =
+            ChannelFlag::ChannelFlagSuspend as u32 | ChannelFlag::ChannelFlagResume as u32;
+        if header.flags & flow_control != 0 {
+            return Ok(None);
+        }
+
+        let state = self.channels.entry(channel_name.to_string()).or_default();
+        if header.flags & ChannelFlag::ChannelFlagFirst as u32 != 0 {
+            // Nothing is kept from a message announced too long
+            state.length = None;
+            self.limits.check_reassembled_size(header.length as usize)?;
+            state.data.clear();
+            state.length = Some(header.length as usize);
+        }
+        let length = state.length.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "CHANNEL: chunk without a first chunk",
+            )
+        })?;
+        if state.data.len() + payload.len() > length {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "CHANNEL: chunks longer than the message",
+            ));
+        }
+        let last = header.flags & ChannelFlag::ChannelFlagLast as u32 != 0;
+        let data = if last && state.data.is_empty() {
+            payload.freeze()
+        } else {
+            state.data.extend_from_slice(&payload);
+            if !last {
+                return Ok(None);
+            }
+            std::mem::take(&mut state.data).freeze()
+        };
+
+        state.length = None;
+        match &state.sender {
+            Some(sender) => match sender.send(data) {
+                Ok(()) => Ok(None),
+                // The handle is dropped
+                Err(mpsc::error::SendError(data)) => {
+                    state.sender = None;
+                    Ok(Some(data))
+                }
+            },
+            None => Ok(Some(data)),
+        }
+    }
+}
+
+/// Message written by a channel handle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelData {
+    pub name: String,
+    /// Added to the header of each chunk
+    pub flags: u32,
+    pub data: Vec<u8>,
+}
+
+/// Handle of a static virtual channel
+/// Reads the messages of the channel and writes new ones,
+/// the session does the chunking
+///
+/// # Example
+/// ```rust, ignore
+/// let mut channel = client.open_static_channel("MYCHAN")?;
+/// tokio::spawn(async move {
+///     while let Some(message) = channel.read().await {
+///         channel.write(message.to_vec()).await?;
+///     }
+///     Ok::<(), std::io::Error>(())
+/// });
+/// ```
+pub struct StaticChannel {
+    name: String,
+    /// Added to the header of each chunk written
+    flags: u32,
+    incoming: mpsc::UnboundedReceiver<Bytes>,
+    outgoing: mpsc::Sender<ChannelData>,
+}
+
+impl StaticChannel {
+    pub fn new(
+        name: &str,
+        incoming: mpsc::UnboundedReceiver<Bytes>,
+        outgoing: mpsc::Sender<ChannelData>,
+    ) -> Self {
+        StaticChannel {
+            name: name.to_string(),
+            flags: 0,
+            incoming,
+            outgoing,
+        }
+    }
+
+    /// Name of the channel
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Flags added to the header of each chunk written,
+    /// like ChannelFlagShowProtocol
+    pub fn set_flags(&mut self, flags: u32) {
+        self.flags = flags;
+    }
+
+    /// Next message sent by the server
+    /// None once the session is closed
+    pub async fn read(&mut self) -> Option<Bytes> {
+        self.incoming.recv().await
+    }
+
+    /// Send a message to the server
+    /// It is written by the next read of the session
+    pub async fn write(&self, data: Vec<u8>) -> Result<()> {
+        let message = ChannelData {
+            name: self.name.clone(),
+            flags: self.flags,
+            data,
+        };
+        self.outgoing
+            .send(message)
+            .await
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "CHANNEL: session closed"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::limits::is_too_large;
+
+    /// Chunks carry the length of the whole message and their flags
+    #[test]
+    fn test_split_chunks() {
+        let chunks = split_chunks(&[1, 2, 3, 4, 5], 0x10, 2);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0], [5, 0, 0, 0, 0x11, 0, 0, 0, 1, 2]);
+        assert_eq!(chunks[1], [5, 0, 0, 0, 0x10, 0, 0, 0, 3, 4]);
+        assert_eq!(chunks[2], [5, 0, 0, 0, 0x12, 0, 0, 0, 5]);
+        assert_eq!(split_chunks(&[], 0, 2), vec![vec![0, 0, 0, 0, 3, 0, 0, 0]]);
+    }
+
+    /// Messages go to the open handle once complete
+    #[test]
+    fn test_static_channels_read() {
+        let mut channels = StaticChannels::default();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        channels.open("echo", sender);
+        for chunk in split_chunks(b"hello", 0, 2) {
+            let message = channels.read("echo", BytesMut::from(&chunk[..])).unwrap();
+            assert_eq!(message, None);
+        }
+        assert_eq!(receiver.try_recv().unwrap(), &b"hello"[..]);
+
+        // Messages are returned once the handle is dropped
+        drop(receiver);
+        let chunk = BytesMut::from(&split_chunks(b"hi", 0, 2)[0][..]);
+        assert_eq!(
+            channels.read("echo", chunk).unwrap(),
+            Some(Bytes::from_static(b"hi"))
+        );
+    }
+
+    /// A message in a single chunk is not copied
+    #[test]
+    fn test_static_channels_read_single_chunk() {
+        let mut channels = StaticChannels::default();
+        let chunk = BytesMut::from(&split_chunks(b"hello", 0, 8)[0][..]);
+        let address = chunk[8..].as_ptr();
+        let message = channels.read("echo", chunk).unwrap().unwrap();
+        assert_eq!(message, &b"hello"[..]);
+        assert_eq!(message.as_ptr(), address);
+    }
+
+    /// Chunks out of order or too long are rejected
+    #[test]
+    fn test_static_channels_invalid_chunks() {
+        let mut channels = StaticChannels::default();
+        let chunks = split_chunks(b"hello", 0, 2);
+        assert!(channels
+            .read("echo", BytesMut::from(&chunks[1][..]))
+            .is_err());
+        let mut chunk = BytesMut::from(&chunks[0][..]);
+        chunk[0] = 1;
+        assert!(channels.read("echo", chunk).is_err());
+    }
+
+    /// The length of the first chunk is checked against the limits
+    #[test]
+    fn test_static_channels_too_large() {
+        let mut channels = StaticChannels::new(PduLimits {
+            max_reassembled_size: 4,
+            ..Default::default()
+        });
+        let chunks = split_chunks(b"hello", 0, 2);
+        let error = channels
+            .read("echo", BytesMut::from(&chunks[0][..]))
+            .unwrap_err();
+        assert!(is_too_large(&error));
+        assert!(channels
+            .read("echo", BytesMut::from(&chunks[1][..]))
+            .is_err());
+
+        let chunk = BytesMut::from(&split_chunks(b"hi", 0, 2)[0][..]);
+        assert_eq!(
+            channels.read("echo", chunk).unwrap(),
+            Some(Bytes::from_static(b"hi"))
+        );
+    }
+}
@@ -1,367 +1,1659 @@
-// use crate::core::event::{PointerButton, RdpEvent};
-// use crate::core::gcc::KeyboardLayout;
-// use crate::core::global;
-// use crate::core::global::{ts_keyboard_event, ts_pointer_event, KeyboardFlag, PointerFlag};
-// use crate::core::mcs;
-// use crate::core::sec;
-// use crate::core::tpkt;
-// use crate::core::x224;
-// use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
-// use crate::model::link::{Link, Stream};
-// use crate::nla::ntlm::Ntlm;
-
-// use std::io::{Read, Write};
-// use tokio::io::AsyncRead;
-// use tokio::io::AsyncWrite;
-
-// impl From<&str> for KeyboardLayout {
-//     fn from(e: &str) -> Self {
-//         match e {
-//             "us" => KeyboardLayout::US,
-//             "fr" => KeyboardLayout::French,
-//             _ => KeyboardLayout::US,
-//         }
-//     }
-// }
-
-// pub struct RdpClient<S> {
-//     /// Multi channel
-//     /// This is the main switch layer of the protocol
-//     mcs: mcs::Client<S>,
-//     /// Global channel that implement the basic layer
-//     global: global::Client,
-// }
-
-// impl<S: AsyncRead + AsyncWrite> RdpClient<S> {
-//     /// Read a payload from the server
-//     /// RDpClient use a callback pattern that can be called more than once
-//     /// during a read call
-//     ///
-//     /// # Example
-//     /// ```no_run
-//     /// use std::net::{SocketAddr, TcpStream};
-//     /// use rdp::core::client::Connector;
-//     /// use rdp::core::event::RdpEvent;
-//     /// let addr = "127.0.0.1:3389".parse::<SocketAddr>().unwrap();
-//     /// let tcp = TcpStream::connect(&addr).unwrap();
-//     /// let mut connector = Connector::new()
-//     ///     .screen(800, 600)
-//     ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
-//     /// let mut client = connector.connect(tcp).unwrap();
-//     /// client.read(|rdp_event| {
-//     ///     match rdp_event {
-//     ///         RdpEvent::Bitmap(bitmap) => {
-//     ///             // do something with bitmap
-//     ///         }
-//     ///          _ => println!("Unhandled event")
-//     ///     }
-//     /// }).unwrap()
-//     /// ```
-//     pub fn read<T>(&mut self, callback: T) -> RdpResult<()>
-//     where
-//         T: FnMut(RdpEvent),
-//     {
-//         let (channel_name, message) = self.mcs.read()?;
-//         match channel_name.as_str() {
-//             "global" => self.global.read(message, &mut self.mcs, callback),
-//             _ => Err(Error::RdpError(RdpError::new(
-//                 RdpErrorKind::UnexpectedType,
-//                 &format!("Invalid channel name {:?}", channel_name),
-//             ))),
-//         }
-//     }
-
-//     /// Write an event to the server
-//     /// Typically is all about input event like mouse and keyboard
-//     ///
-//     /// # Example
-//     /// ```no_run
-//     /// use std::net::{SocketAddr, TcpStream};
-//     /// use rdp::core::client::Connector;
-//     /// use rdp::core::event::{RdpEvent, PointerEvent, PointerButton};
-//     /// let addr = "127.0.0.1:3389".parse::<SocketAddr>().unwrap();
-//     /// let tcp = TcpStream::connect(&addr).unwrap();
-//     /// let mut connector = Connector::new()
-//     ///     .screen(800, 600)
-//     ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
-//     /// let mut client = connector.connect(tcp).unwrap();
-//     /// client.write(RdpEvent::Pointer(
-//     ///     // Send a mouse click down at 100x100
-//     ///     PointerEvent {
-//     ///         x: 100 as u16,
-//     ///         y: 100 as u16,
-//     ///         button: PointerButton::Left,
-//     ///         down: true
-//     ///     }
-//     /// )).unwrap()
-//     /// ```
-//     pub fn write(&mut self, event: RdpEvent) -> RdpResult<()> {
-//         match event {
-//             // Pointer event
-//             // Mouse position an d button position
-//             RdpEvent::Pointer(pointer) => {
-//                 // Pointer are sent to global channel
-//                 // Compute flags
-//                 let mut flags: u16 = 0;
-//                 match pointer.button {
-//                     PointerButton::Left => flags |= PointerFlag::PtrflagsButton1 as u16,
-//                     PointerButton::Right => flags |= PointerFlag::PtrflagsButton2 as u16,
-//                     PointerButton::Middle => flags |= PointerFlag::PtrflagsButton3 as u16,
-//                     _ => flags |= PointerFlag::PtrflagsMove as u16,
-//                 }
-
-//                 if pointer.down {
-//                     flags |= PointerFlag::PtrflagsDown as u16;
-//                 }
-
-//                 self.global.write_input_event(
-//                     ts_pointer_event(Some(flags), Some(pointer.x), Some(pointer.y)),
-//                     &mut self.mcs,
-//                 )
-//             }
-//             // Raw keyboard input
-//             RdpEvent::Key(key) => {
-//                 let mut flags: u16 = 0;
-//                 if !key.down {
-//                     flags |= KeyboardFlag::KbdflagsRelease as u16;
-//                 }
-//                 self.global.write_input_event(
-//                     ts_keyboard_event(Some(flags), Some(key.code)),
-//                     &mut self.mcs,
-//                 )
-//             }
-//             _ => Err(Error::RdpError(RdpError::new(
-//                 RdpErrorKind::UnexpectedType,
-//                 "RDPCLIENT: This event can't be sent",
-//             ))),
-//         }
-//     }
-
-//     /// This function will ignore input event
-//     /// once the global channel is not connected
-//     /// This will disable InvalidAutomata error in case
-//     /// if you sent input before end of the sync process
-//     pub fn try_write(&mut self, event: RdpEvent) -> RdpResult<()> {
-//         let result = self.write(event);
-//         match result {
-//             Err(Error::RdpError(e)) => match e.kind() {
-//                 RdpErrorKind::InvalidAutomata => Ok(()),
-//                 _ => Err(Error::RdpError(e)),
-//             },
-//             _ => result,
-//         }
-//     }
-
-//     /// Close client is indeed close the switch layer
-//     pub fn shutdown(&mut self) -> RdpResult<()> {
-//         self.mcs.shutdown()
-//     }
-// }
-
-// pub struct Connector {
-//     /// Screen width
-//     width: u16,
-//     /// Screen height
-//     height: u16,
-//     /// Keyboard layout
-//     layout: KeyboardLayout,
-//     /// Restricted admin mode
-//     /// This mode protect against credential forward
-//     restricted_admin_mode: bool,
-//     /// Microsoft Domain
-//     /// If you don't care keep empty
-//     domain: String,
-//     /// Username
-//     username: String,
-//     /// Password
-//     password: String,
-//     /// When you only want to pass the hash
-//     password_hash: Option<Vec<u8>>,
-//     /// Set auto logon flags during security logon
-//     auto_logon: bool,
-//     /// Do not send creds to CredSSP
-//     blank_creds: bool,
-//     /// When using SSL check or not
-//     /// the certificate during SSL handshake
-//     check_certificate: bool,
-//     /// Client name exposed to the server
-//     name: String,
-//     /// Use network level authentication
-//     /// default TRUE
-//     use_nla: bool,
-// }
-
-// impl Connector {
-//     /// Create a new RDP client
-//     /// You can configure your client
-//     ///
-//     /// # Example
-//     /// ```no_run
-//     /// use rdp::core::client::Connector;
-//     /// let mut connector = Connector::new()
-//     ///     .screen(800, 600)
-//     ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
-//     /// ```
-//     pub fn new() -> Self {
-//         Connector {
-//             width: 800,
-//             height: 600,
-//             layout: KeyboardLayout::US,
-//             restricted_admin_mode: false,
-//             domain: "".to_string(),
-//             username: "".to_string(),
-//             password: "".to_string(),
-//             password_hash: None,
-//             auto_logon: false,
-//             blank_creds: false,
-//             check_certificate: false,
-//             name: "rdp-rs".to_string(),
-//             use_nla: true,
-//         }
-//     }
-
-//     /// Connect to a target server
-//     /// This function will produce a RdpClient object
-//     /// use to interact with server
-//     ///
-//     /// # Example
-//     /// ```no_run
-//     /// use std::net::{SocketAddr, TcpStream};
-//     /// use rdp::core::client::Connector;
-//     /// let addr = "127.0.0.1:3389".parse::<SocketAddr>().unwrap();
-//     /// let tcp = TcpStream::connect(&addr).unwrap();
-//     /// let mut connector = Connector::new()
-//     ///     .screen(800, 600)
-//     ///     .credentials("domain".to_string(), "username".to_string(), "password".to_string());
-//     /// let mut client = connector.connect(tcp).unwrap();
-//     /// ```
-//     pub fn connect<S: Read + Write>(&mut self, stream: S) -> RdpResult<RdpClient<S>> {
-//         // Create a wrapper around the stream
-//         let tcp = Link::new(Stream::Raw(stream));
-
-//         // Compute authentication method
-//         let mut authentication = if let Some(hash) = &self.password_hash {
-//             Ntlm::from_hash(self.domain.clone(), self.username.clone(), hash)
-//         } else {
-//             Ntlm::new(
-//                 self.domain.clone(),
-//                 self.username.clone(),
-//                 self.password.clone(),
-//             )
-//         };
-//         // Create the x224 layer
-//         // With all negotiated security stuff and credentials
-//         let mut protocols = x224::Protocols::ProtocolSSL as u32;
-//         if self.use_nla {
-//             protocols |= x224::Protocols::ProtocolHybrid as u32
-//         }
-
-//         let x224 = x224::Client::connect(
-//             tpkt::Client::new(tcp),
-//             protocols,
-//             self.check_certificate,
-//             Some(&mut authentication),
-//             self.restricted_admin_mode,
-//             self.blank_creds,
-//         )?;
-
-//         // Create MCS layer and connect it
-//         let mut mcs = mcs::Client::new(x224);
-//         mcs.connect(self.name.clone(), self.width, self.height, self.layout)?;
-//         // state less connection for old secure layer
-//         if self.restricted_admin_mode {
-//             sec::connect(
-//                 &mut mcs,
-//                 &"".to_string(),
-//                 &"".to_string(),
-//                 &"".to_string(),
-//                 self.auto_logon,
-//             )?;
-//         } else {
-//             sec::connect(
-//                 &mut mcs,
-//                 &self.domain,
-//                 &self.username,
-//                 &self.password,
-//                 self.auto_logon,
-//             )?;
-//         }
-
-//         // Now the global channel
-//         let global = global::Client::new(
-//             mcs.get_user_id(),
-//             mcs.get_global_channel_id(),
-//             self.width,
-//             self.height,
-//             self.layout,
-//             &self.name,
-//         );
-
-//         Ok(RdpClient { mcs, global })
-//     }
-
-//     /// Configure the screen size of the session
-//     /// You need to set a power of two definition
-//     pub fn screen(mut self, width: u16, height: u16) -> Self {
-//         self.width = width;
-//         self.height = height;
-//         self
-//     }
-
-//     /// Configure credentials for the session
-//     /// Credentials use to logon on server
-//     pub fn credentials(mut self, domain: String, username: String, password: String) -> Self {
-//         self.domain = domain;
-//         self.username = username;
-//         self.password = password;
-//         self
-//     }
-
-//     /// Enable or disable restricted admin mode
-//     pub fn set_restricted_admin_mode(mut self, state: bool) -> Self {
-//         self.restricted_admin_mode = state;
-//         self
-//     }
-
-//     /// Try authenticate using NTLM hashes and restricted admin mode
-//     pub fn set_password_hash(mut self, password_hash: Vec<u8>) -> Self {
-//         self.password_hash = Some(password_hash);
-//         self
-//     }
-
-//     /// Set the keyboard layout
-//     pub fn layout(mut self, layout: KeyboardLayout) -> Self {
-//         self.layout = layout;
-//         self
-//     }
-
-//     /// Switch on the AutoLogon flag
-//     pub fn auto_logon(mut self, auto_logon: bool) -> Self {
-//         self.auto_logon = auto_logon;
-//         self
-//     }
-
-//     /// Send blank creds at the end of CRedSSP
-//     pub fn blank_creds(mut self, blank_creds: bool) -> Self {
-//         self.blank_creds = blank_creds;
-//         self
-//     }
-
-//     /// Enable or not the check of SSL certificate
-//     pub fn check_certificate(mut self, check_certificate: bool) -> Self {
-//         self.check_certificate = check_certificate;
-//         self
-//     }
-
-//     /// Set the default name send to server
-//     pub fn name(mut self, name: String) -> Self {
-//         self.name = name;
-//         self
-//     }
-
-//     /// Enable or disable Network Level Authentication
-//     pub fn use_nla(mut self, use_nla: bool) -> Self {
-//         self.use_nla = use_nla;
-//         self
-//     }
-// }
+use crate::codec::bulk::CompressionType;
+use crate::core::capability::CapabilitiesConfig;
+use crate::core::channel::{ChannelData, StaticChannel};
+use crate::core::config::{ConnectionConfig, TcpOptions, Timeouts};
+use crate::core::connector::ProxyConfig;
+use crate::core::event::RdpEvent;
+use crate::core::framebuffer::{FramePacer, Framebuffer};
+use crate::core::gcc::KeyboardLayout;
+use crate::core::global::base::InclusiveRectangle;
+use crate::core::global::client::GlobalClient;
+use crate::core::handler::RdpEventHandler;
+use crate::core::input::{InputEvent, InputQueue};
+use crate::core::keyboard::KeyboardHook;
+use crate::core::mcs::client::McsClient;
+use crate::core::preconnection::{PreconnectionPdu, VMCONNECT_PORT};
+use crate::core::rail::base::RAIL_CHANNEL_NAME;
+use crate::core::runtime::{self, default_timer, Timer};
+use crate::core::sec::base::{ClientInfoPdu, InfoFlag, PerformanceFlags};
+use crate::core::sec::client::SecClient;
+use crate::core::tpkt::base::Payload;
+use crate::core::tpkt::client::TpktClient;
+use crate::core::x224::base::{ConnectionRequestOptions, Protocols};
+use crate::core::x224::client::X224Client;
+use crate::model::data::Message;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::nla::ntlm::Ntlm;
+
+use std::future::Future;
+use std::io::{Error, ErrorKind, Result};
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::net::{lookup_host, TcpSocket, TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
+
+/// Default port of RDP servers
+pub const DEFAULT_PORT: u16 = 3389;
+
+/// Events or inputs waiting in the queues of a split session
+const SESSION_QUEUE_SIZE: usize = 64;
+
+/// Time without bitmap updates after which a frame is complete
+const FRAME_QUIET_TIME: Duration = Duration::from_millis(100);
+
+/// Active RDP session
+/// Built by the RdpClientBuilder once the whole
+/// connection sequence is done
+pub struct RdpClient<S> {
+    /// Global channel, owns all the lower layers
+    global: GlobalClient<S>,
+    /// Longest time without any data from the server
+    idle_read: Option<Duration>,
+    /// Shared with the builder, stops the session once cancelled
+    cancel: CancellationToken,
+    /// Messages written by the channel handles
+    channel_writes: mpsc::Receiver<ChannelData>,
+    /// Cloned into each channel handle
+    channel_sender: mpsc::Sender<ChannelData>,
+    /// Timer of the read timeouts
+    timer: Arc<dyn Timer>,
+    /// Inputs waiting to be sent in a single PDU
+    inputs: InputQueue,
+    /// Most frames per second sent by a split session
+    frame_rate: Option<u32>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RdpClient<TcpStream> {
+    /// Start the configuration of a new connection
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let mut client = RdpClient::builder()
+    ///     .target("192.168.0.1")
+    ///     .credentials("domain", "username", "password")
+    ///     .security(Protocols::ProtocolRDP as u32)
+    ///     .connect()
+    ///     .await?;
+    /// ```
+    pub fn builder() -> RdpClientBuilder {
+        RdpClientBuilder::new()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> RdpClient<S> {
+    /// Read the next payload from the server
+    /// The callback can be called more than once
+    /// during a read call
+    ///
+    /// Once the cancel token is triggered the session
+    /// is closed and an Interrupted error is returned
+    ///
+    /// Messages of the channel handles and the queued inputs
+    /// are written meanwhile
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// client.read(|rdp_event| match rdp_event {
+    ///     RdpEvent::Bitmap(bitmap) => {
+    ///         // do something with bitmap
+    ///     }
+    ///     _ => println!("Unhandled event"),
+    /// }).await?;
+    /// ```
+    pub async fn read<T>(&mut self, mut callback: T) -> Result<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        loop {
+            let wakeup = self.wait(&mut callback).await?;
+            if self.handle(wakeup, &mut callback).await? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Wait for the next payload or the next write
+    /// Cancel safe, nothing is written meanwhile
+    async fn wait<T>(&mut self, callback: &mut T) -> Result<Wakeup>
+    where
+        T: FnMut(RdpEvent),
+    {
+        tokio::select! {
+            result = with_timeout(
+                self.timer.as_ref(),
+                self.idle_read,
+                "idle read",
+                self.global.receive(&mut *callback),
+            ) => result.map(|(channel_name, payload)| Wakeup::Payload(channel_name, payload)),
+            Some(message) = self.channel_writes.recv() => Ok(Wakeup::ChannelWrite(message)),
+            _ = sleep_until(self.timer.as_ref(), self.inputs.deadline()) => {
+                Ok(Wakeup::InputDeadline)
+            }
+            _ = self.cancel.cancelled() => Ok(Wakeup::Cancelled),
+        }
+    }
+
+    /// Handle the result of `wait` until the end,
+    /// a reactivation is never interrupted by a write
+    ///
+    /// Returns true once a payload is handled
+    async fn handle<T>(&mut self, wakeup: Wakeup, callback: &mut T) -> Result<bool>
+    where
+        T: FnMut(RdpEvent),
+    {
+        match wakeup {
+            Wakeup::Payload(channel_name, payload) => {
+                self.global.process(channel_name, payload, callback).await?;
+                return Ok(true);
+            }
+            Wakeup::ChannelWrite(message) => {
+                self.global
+                    .write_channel(&message.name, message.flags, &message.data)
+                    .await?
+            }
+            Wakeup::InputDeadline => self.flush_input().await?,
+            Wakeup::Cancelled => {
+                self.global.shutdown().await?;
+                return Err(Error::new(
+                    ErrorKind::Interrupted,
+                    "RDPCLIENT: session cancelled",
+                ));
+            }
+        }
+        Ok(false)
+    }
+
+    /// Send a mouse or keyboard event to the server
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// // Send a mouse click down at 100x100
+    /// client.write(RdpEvent::Pointer(PointerEvent {
+    ///     x: 100,
+    ///     y: 100,
+    ///     button: PointerButton::Left,
+    ///     down: true,
+    /// })).await?;
+    /// ```
+    pub async fn write(&mut self, event: RdpEvent) -> Result<()> {
+        self.write_input(&[to_input_event(event)?]).await
+    }
+
+    /// Send several input events at once
+    ///
+    /// With an input latency they are queued, and sent by a later
+    /// read or write once the oldest one waited long enough
+    /// See `RdpClientBuilder::input_latency`
+    pub async fn write_input(&mut self, events: &[InputEvent]) -> Result<()> {
+        self.inputs.extend(events);
+        if self.inputs.is_due() {
+            self.flush_input().await?;
+        }
+        Ok(())
+    }
+
+    /// Send the queued input events now
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// // Button events are not delayed
+    /// client.write_input(&[InputEvent::mouse_button(PointerButton::Left, true, 10, 10)]).await?;
+    /// client.flush_input().await?;
+    /// ```
+    pub async fn flush_input(&mut self) -> Result<()> {
+        if self.inputs.is_empty() {
+            return Ok(());
+        }
+        let events = self.inputs.take();
+        self.global.write_input(&events).await
+    }
+
+    /// Handle to read and write the messages of a static virtual channel
+    /// The channel must be requested with `RdpClientBuilder::channels`
+    /// and opened before the session is split
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let mut channel = client.open_static_channel("MYCHAN")?;
+    /// channel.write(b"ping".to_vec()).await?;
+    /// ```
+    pub fn open_static_channel(&mut self, channel_name: &str) -> Result<StaticChannel> {
+        if !self.global.get_sec().get_mcs().has_channel(channel_name) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("RDPCLIENT: channel {} is not joined", channel_name),
+            ));
+        }
+        let (sender, incoming) = mpsc::unbounded_channel();
+        self.global.open_channel(channel_name, sender);
+        Ok(StaticChannel::new(
+            channel_name,
+            incoming,
+            self.channel_sender.clone(),
+        ))
+    }
+
+    /// Getter of the global channel
+    pub fn get_global(&self) -> &GlobalClient<S> {
+        &self.global
+    }
+
+    /// Token which stops the session once cancelled
+    pub fn get_cancel_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    /// Mutable access to the global channel
+    /// for refresh, suppress output or unicode input
+    pub fn get_global_mut(&mut self) -> &mut GlobalClient<S> {
+        &mut self.global
+    }
+
+    /// Ask the server to log off the user
+    /// The end of the session is reported by `read`
+    pub async fn logoff(&mut self) -> Result<()> {
+        self.global.logoff().await
+    }
+
+    /// Send a close event to server
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.global.shutdown().await
+    }
+
+    /// Ask a repaint of the whole desktop and compose it
+    ///
+    /// The frame is complete once the server sent
+    /// no bitmap update for a short time
+    /// Other events received meanwhile are dropped
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let frame = client.capture_frame().await?;
+    /// let pixel = frame.pixel(10, 10);
+    /// ```
+    pub async fn capture_frame(&mut self) -> Result<Framebuffer> {
+        let config = self.global.get_config();
+        let (width, height) = (config.desktop_width, config.desktop_height);
+        match self
+            .global
+            .refresh(&[InclusiveRectangle::desktop(width, height)])
+            .await
+        {
+            // Wait for the next updates instead
+            Err(e) if e.kind() == ErrorKind::Unsupported => (),
+            result => result?,
+        }
+
+        let timer = self.timer.clone();
+        let mut framebuffer = Framebuffer::new(width, height);
+        let mut updated = false;
+        let mut error = None;
+        loop {
+            // The first update can take a while
+            let quiet_time = if updated {
+                Some(FRAME_QUIET_TIME)
+            } else {
+                None
+            };
+            let mut apply = |event| {
+                updated |= matches!(event, RdpEvent::Bitmap(_));
+                if let Err(e) = framebuffer.apply(event) {
+                    error.get_or_insert(e);
+                }
+            };
+            // Only the wait is timed out, a payload received
+            // or a reactivation is always handled until the end
+            let wait = self.wait(&mut apply);
+            let wakeup = match quiet_time {
+                Some(quiet_time) => {
+                    match runtime::timeout(timer.as_ref(), quiet_time, wait).await {
+                        Some(wakeup) => wakeup?,
+                        None => break,
+                    }
+                }
+                None => wait.await?,
+            };
+            self.handle(wakeup, &mut apply).await?;
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(framebuffer),
+        }
+    }
+
+    /// Wait for the next complete frame of the desktop
+    /// See `capture_frame`
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let image = client.screenshot().await?;
+    /// let pixel = image.get_pixel(10, 10);
+    /// ```
+    #[cfg(feature = "image")]
+    pub async fn screenshot(&mut self) -> Result<image::RgbaImage> {
+        Ok(self.capture_frame().await?.to_image())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> RdpClient<S> {
+    /// Write the next PDUs from a task of their own, so a slow
+    /// network doesn't block the reads of the session
+    ///
+    /// Input events go before the other PDUs, like acknowledgements,
+    /// refreshes and virtual channel data, except with legacy
+    /// security where all PDUs are sent in order
+    /// Each priority holds at most `capacity` PDUs, then the writes wait
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// client.start_write_queue(64);
+    /// let metrics = client.get_global().get_sec().get_mcs().get_metrics().clone();
+    /// let (reader, writer) = client.split();
+    /// println!("{} PDUs to send", metrics.snapshot().write_queue_depth);
+    /// ```
+    pub fn start_write_queue(&mut self, capacity: usize) {
+        self.global.start_write_queue(capacity);
+    }
+
+    /// Move the session into a task and split it into
+    /// an event reader and an input writer
+    ///
+    /// Both halves can be used from different tasks,
+    /// the writer can be cloned
+    /// The session is closed once the reader is dropped
+    /// or the cancel token is triggered
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let (mut reader, writer) = client.split();
+    /// tokio::spawn(async move {
+    ///     writer.write_input(&[InputEvent::mouse_move(100, 100)]).await
+    /// });
+    /// while let Some(event) = reader.next().await {
+    ///     match event? {
+    ///         RdpEvent::Bitmap(bitmap) => {
+    ///             // do something with bitmap
+    ///         }
+    ///         _ => (),
+    ///     }
+    /// }
+    /// ```
+    pub fn split(self) -> (SessionReader, SessionWriter) {
+        let (event_sender, events) = mpsc::channel(SESSION_QUEUE_SIZE);
+        let (input_sender, inputs) = mpsc::channel(SESSION_QUEUE_SIZE);
+        tokio::spawn(run_session(self, inputs, event_sender));
+        (
+            SessionReader { events },
+            SessionWriter {
+                inputs: input_sender,
+            },
+        )
+    }
+
+    /// Move the session into a task and read it as a stream
+    /// of events, for sessions which never send inputs
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// use tokio_stream::StreamExt;
+    /// let mut bitmaps = client.into_stream().filter_map(|event| match event {
+    ///     Ok(RdpEvent::Bitmap(bitmap)) => Some(bitmap),
+    ///     _ => None,
+    /// });
+    /// while let Some(bitmap) = bitmaps.next().await {
+    ///     // do something with bitmap
+    /// }
+    /// ```
+    pub fn into_stream(self) -> SessionReader {
+        self.split().0
+    }
+
+    /// Move the session into a task and give
+    /// each event to the handler
+    ///
+    /// The task returns the handler once the session is closed
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let (task, writer) = client.run(Viewer::default());
+    /// writer.write_input(&[InputEvent::mouse_move(100, 100)]).await?;
+    /// let viewer = task.await?;
+    /// ```
+    pub fn run<H>(self, handler: H) -> (JoinHandle<H>, SessionWriter)
+    where
+        H: RdpEventHandler + 'static,
+    {
+        let (reader, writer) = self.split();
+        (tokio::spawn(reader.run(handler)), writer)
+    }
+}
+
+/// What woke up a session waiting for the server
+enum Wakeup {
+    /// Payload to process
+    Payload(String, Payload),
+    /// Message of a channel handle to write
+    ChannelWrite(ChannelData),
+    /// The oldest queued input waited long enough
+    InputDeadline,
+    /// The cancel token was triggered
+    Cancelled,
+}
+
+/// Drive a split session
+/// Only the waits for the server are raced against the inputs,
+/// so a received payload is always processed until the end
+async fn run_session<S>(
+    mut client: RdpClient<S>,
+    mut inputs: mpsc::Receiver<Vec<InputEvent>>,
+    events: mpsc::Sender<Result<RdpEvent>>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let timer = client.timer.clone();
+    let mut pacer = client.frame_rate.map(|frame_rate| {
+        let config = client.global.get_config();
+        FramePacer::new(config.desktop_width, config.desktop_height, frame_rate)
+    });
+    let mut decoded = Vec::new();
+    let mut inputs_open = true;
+    loop {
+        let mut push = |event: RdpEvent| decoded.push(event);
+        let mut result = tokio::select! {
+            input = inputs.recv(), if inputs_open => match input {
+                Some(mut input) => {
+                    // Inputs sent meanwhile go in the same PDU,
+                    // empty ones are sent by SessionWriter::flush
+                    let mut flush = input.is_empty();
+                    while let Ok(more) = inputs.try_recv() {
+                        flush |= more.is_empty();
+                        input.extend(more);
+                    }
+                    match client.write_input(&input).await {
+                        Ok(()) if flush => client.flush_input().await,
+                        result => result,
+                    }
+                }
+                None => {
+                    inputs_open = false;
+                    Ok(())
+                }
+            },
+            wakeup = client.wait(&mut push) => match wakeup {
+                Ok(wakeup) => client.handle(wakeup, &mut push).await.map(|_| ()),
+                Err(e) => Err(e),
+            },
+            _ = sleep_until(timer.as_ref(), pacer.as_ref().and_then(FramePacer::deadline)) => {
+                let frame = pacer.as_mut().and_then(FramePacer::take_frame);
+                decoded.extend(frame.map(RdpEvent::Damage));
+                Ok(())
+            }
+            _ = events.closed() => {
+                let _ = client.shutdown().await;
+                return;
+            }
+        };
+
+        for event in decoded.drain(..) {
+            // Bitmaps wait for the next paced frame
+            let event = match &mut pacer {
+                Some(pacer) => match pacer.apply(event) {
+                    Ok(Some(event)) => event,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        result = result.and(Err(e));
+                        break;
+                    }
+                },
+                None => event,
+            };
+            // The reader is gone
+            if events.send(Ok(event)).await.is_err() {
+                let _ = client.shutdown().await;
+                return;
+            }
+        }
+        if let Err(e) = result {
+            let _ = events.send(Err(e)).await;
+            return;
+        }
+    }
+}
+
+/// Events half of a split session
+///
+/// Also a stream of events, so tokio_stream or futures
+/// combinators and select! loops can consume it
+/// The stream ends after the error which closed the session
+pub struct SessionReader {
+    events: mpsc::Receiver<Result<RdpEvent>>,
+}
+
+impl SessionReader {
+    /// Next event sent by the server
+    /// The last item is the error which ended the session
+    /// then None is returned
+    pub async fn next(&mut self) -> Option<Result<RdpEvent>> {
+        self.events.recv().await
+    }
+
+    /// Give all the events to the handler
+    /// until the session is closed
+    pub async fn run<H: RdpEventHandler>(mut self, mut handler: H) -> H {
+        while let Some(event) = self.next().await {
+            match event {
+                Ok(event) => handler.on_event(event),
+                Err(e) => handler.on_disconnect(e),
+            }
+        }
+        handler
+    }
+}
+
+impl Stream for SessionReader {
+    type Item = Result<RdpEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// Input half of a split session
+#[derive(Clone)]
+pub struct SessionWriter {
+    inputs: mpsc::Sender<Vec<InputEvent>>,
+}
+
+impl SessionWriter {
+    /// Send a mouse or keyboard event to the server
+    pub async fn write(&self, event: RdpEvent) -> Result<()> {
+        self.write_input(&[to_input_event(event)?]).await
+    }
+
+    /// Send several input events at once
+    pub async fn write_input(&self, events: &[InputEvent]) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.send(events.to_vec()).await
+    }
+
+    /// Send the input events queued by the session now
+    /// See `RdpClient::flush_input`
+    pub async fn flush(&self) -> Result<()> {
+        self.send(Vec::new()).await
+    }
+
+    async fn send(&self, events: Vec<InputEvent>) -> Result<()> {
+        self.inputs
+            .send(events)
+            .await
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "RDPCLIENT: session closed"))
+    }
+}
+
+/// Completed at the deadline, like the one of the queued
+/// inputs or of the next frame, never without one
+async fn sleep_until(timer: &dyn Timer, deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => {
+            timer
+                .sleep(deadline.saturating_duration_since(Instant::now()))
+                .await
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Only mouse and keyboard events can be sent
+fn to_input_event(event: RdpEvent) -> Result<InputEvent> {
+    match event {
+        RdpEvent::Pointer(pointer) => Ok(InputEvent::from(pointer)),
+        RdpEvent::Key(key) => Ok(InputEvent::from(key)),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "RDPCLIENT: this event can't be sent",
+        )),
+    }
+}
+
+/// Steps of the connection sequence
+/// Reported once each step is done
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ConnectionPhase {
+    TcpConnected,
+    /// Security protocol selected by the server
+    NegotiationDone(Protocols),
+    /// TLS started over the connection
+    TlsEstablished,
+    /// Credentials accepted by the server through CredSSP
+    NlaDone,
+    /// Channels joined
+    McsConnected,
+    /// Client info sent and licensing done
+    Licensed,
+    /// Capabilities exchanged and connection finalized
+    Activated,
+}
+
+/// Callback of the connection progress
+/// Shared by the clones of the builder
+type ProgressCallback = Arc<dyn Fn(ConnectionPhase) + Send + Sync>;
+
+/// Configuration of a new RDP connection
+///
+/// TLS is started when the server selects SSL, then NLA
+/// authenticates the credentials with NTLM when it selects Hybrid
+#[derive(Clone)]
+pub struct RdpClientBuilder {
+    /// Host name or address with an optional port
+    target: Option<String>,
+    /// Port used when the target has none
+    default_port: u16,
+    /// Microsoft Domain
+    /// If you don't care keep empty
+    domain: String,
+    username: String,
+    password: String,
+    /// Set auto logon flags during security logon
+    auto_logon: bool,
+    /// Security protocols requested during negotiation
+    security: u32,
+    /// Check the certificate of TLS servers
+    check_certificate: bool,
+    /// RD gateway, refused here, the tunnel is opened
+    /// by an RdgConnector and given to connect_with
+    gateway: Option<String>,
+    /// SOCKS5 or HTTP proxy to reach the target
+    proxy: Option<ProxyConfig>,
+    /// Options of the TCP connection to the target or to the proxy
+    tcp_options: TcpOptions,
+    /// Client name exposed to the server
+    name: String,
+    /// Cookie and correlation info of the connection request
+    options: ConnectionRequestOptions,
+    /// Capabilities sent to the server
+    /// Also carries the screen size and the keyboard layout
+    config: CapabilitiesConfig,
+    /// Side which handles the system shortcuts
+    keyboard_hook: KeyboardHook,
+    /// Visual effects, server defaults when not set
+    performance_flags: Option<PerformanceFlags>,
+    /// Bulk compression of the server data, none when not set
+    compression: Option<CompressionType>,
+    /// Static virtual channels to join
+    channels: Vec<String>,
+    /// Time limits of the connection phases
+    timeouts: Timeouts,
+    /// Abort the connection and then stop the session
+    cancel: CancellationToken,
+    /// Called after each step of the connection
+    progress: Option<ProgressCallback>,
+    /// Logon id and random of the session to reconnect to
+    auto_reconnect: Option<(u32, [u8; 16])>,
+    /// Sent before the connection request to route the connection
+    preconnection: Option<PreconnectionPdu>,
+    /// Timer of the timeouts and of the heartbeats
+    timer: Arc<dyn Timer>,
+    /// Longest wait of a queued input event
+    input_latency: Duration,
+    /// Most frames per second of a split session, unlimited when not set
+    frame_rate: Option<u32>,
+}
+
+impl Default for RdpClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RdpClientBuilder {
+    pub fn new() -> Self {
+        RdpClientBuilder {
+            target: None,
+            default_port: DEFAULT_PORT,
+            domain: String::new(),
+            username: String::new(),
+            password: String::new(),
+            auto_logon: false,
+            security: Protocols::ProtocolRDP as u32,
+            check_certificate: false,
+            gateway: None,
+            proxy: None,
+            tcp_options: TcpOptions::default(),
+            name: "rdp-rs".to_string(),
+            options: ConnectionRequestOptions::default(),
+            config: CapabilitiesConfig::default(),
+            keyboard_hook: KeyboardHook::default(),
+            performance_flags: None,
+            compression: None,
+            channels: Vec::new(),
+            timeouts: Timeouts::default(),
+            cancel: CancellationToken::new(),
+            progress: None,
+            auto_reconnect: None,
+            preconnection: None,
+            timer: default_timer(),
+            input_latency: Duration::ZERO,
+            frame_rate: None,
+        }
+    }
+
+    /// Apply a connection profile
+    /// Capabilities not covered by the profile are reset
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let config: ConnectionConfig = toml::from_str(&profile)?;
+    /// let client = RdpClient::builder().config(config).connect().await?;
+    /// ```
+    pub fn config(mut self, config: ConnectionConfig) -> Self {
+        self.security = config.security_protocols();
+        self.config = config.capabilities();
+        self.check_certificate = config.check_certificate;
+        self.gateway = config.gateway;
+        self.target = config.target;
+        self.domain = config.domain;
+        self.username = config.username;
+        self.password = config.password;
+        self.auto_logon = config.auto_logon;
+        self.name = config.client_name;
+        self.keyboard_hook = config.keyboard_hook;
+        self.performance_flags = Some(config.performance_flags);
+        self.channels = config.channels;
+        self.timeouts = config.timeouts;
+        self.tcp_options = config.tcp;
+        self.preconnection = config.preconnection;
+        self
+    }
+
+    /// Server to connect to, the port is 3389 by default
+    /// `host`, `host:port`, `ip:port` and `[ipv6]:port` are accepted
+    pub fn target(mut self, target: &str) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    /// Credentials used to logon on server
+    pub fn credentials(mut self, domain: &str, username: &str, password: &str) -> Self {
+        self.domain = domain.to_string();
+        self.username = username.to_string();
+        self.password = password.to_string();
+        self
+    }
+
+    /// Security protocols requested to the server
+    /// A combination of `Protocols` flags
+    pub fn security(mut self, protocols: u32) -> Self {
+        self.security = protocols;
+        self
+    }
+
+    /// Enable or not the check of the TLS certificate
+    pub fn check_certificate(mut self, check_certificate: bool) -> Self {
+        self.check_certificate = check_certificate;
+        self
+    }
+
+    /// Switch on the AutoLogon flag
+    pub fn auto_logon(mut self, auto_logon: bool) -> Self {
+        self.auto_logon = auto_logon;
+        self
+    }
+
+    /// Configure the screen size of the session
+    pub fn screen(mut self, width: u16, height: u16) -> Self {
+        self.config.desktop_width = width;
+        self.config.desktop_height = height;
+        self
+    }
+
+    /// Set the keyboard layout
+    pub fn layout(mut self, layout: KeyboardLayout) -> Self {
+        self.config.keyboard_layout = layout;
+        self
+    }
+
+    /// Choose whether the Windows key and Alt+Tab shortcuts act on the remote session
+    /// Filter the input with a `ShortcutFilter` of the same hook
+    pub fn keyboard_hook(mut self, hook: KeyboardHook) -> Self {
+        self.keyboard_hook = hook;
+        self
+    }
+
+    /// Open the TCP connection through a proxy
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let client = RdpClient::builder()
+    ///     .target("10.0.0.1")
+    ///     .proxy(ProxyConfig::HttpConnect {
+    ///         address: "proxy.example.com:8080".to_string(),
+    ///         credentials: None,
+    ///     })
+    ///     .connect()
+    ///     .await?;
+    /// ```
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Set the name sent to server
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Disable visual effects of the remote session
+    pub fn performance_flags(mut self, flags: PerformanceFlags) -> Self {
+        self.performance_flags = Some(flags);
+        self
+    }
+
+    /// Let the server compress its data, which lowers the bandwidth
+    /// at the cost of some processing
+    /// RDP 6.0 isn't supported, the connection is refused with it
+    pub fn compression(mut self, compression_type: CompressionType) -> Self {
+        self.compression = Some(compression_type);
+        self
+    }
+
+    /// Static virtual channels to join, like `cliprdr` or `rdpdr`
+    pub fn channels(mut self, channels: &[&str]) -> Self {
+        self.channels = channels.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Time limits of the connection phases and of the session reads
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let client = RdpClient::builder()
+    ///     .target("192.168.0.1")
+    ///     .timeouts(Timeouts {
+    ///         tcp_connect: Some(Duration::from_secs(5)),
+    ///         total: Some(Duration::from_secs(30)),
+    ///         ..Default::default()
+    ///     })
+    ///     .connect()
+    ///     .await?;
+    /// ```
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Options of the TCP connection opened by connect,
+    /// like TCP_NODELAY, the keepalive and the buffer sizes
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let client = RdpClient::builder()
+    ///     .target("192.168.0.1")
+    ///     .tcp_options(TcpOptions {
+    ///         keepalive: Some(Duration::from_secs(60)),
+    ///         recv_buffer_size: Some(0x100000),
+    ///         ..Default::default()
+    ///     })
+    ///     .connect()
+    ///     .await?;
+    /// ```
+    pub fn tcp_options(mut self, options: TcpOptions) -> Self {
+        self.tcp_options = options;
+        self
+    }
+
+    /// Timers of the timeouts and of the heartbeats,
+    /// for the runtimes without the tokio timers like wasm32
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let client = RdpClientBuilder::new()
+    ///     .timer(Arc::new(BrowserTimer))
+    ///     .connect_with(websocket)
+    ///     .await?;
+    /// ```
+    pub fn timer(mut self, timer: Arc<dyn Timer>) -> Self {
+        self.timer = timer;
+        self
+    }
+
+    /// Longest time an input event waits in the queue, so the events
+    /// of a tick are sent in a single PDU and the mouse moves in
+    /// between are merged
+    /// Zero by default, the events are sent at once
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let client = RdpClient::builder()
+    ///     .target("192.168.0.1")
+    ///     .input_latency(Duration::from_millis(8))
+    ///     .connect()
+    ///     .await?;
+    /// ```
+    pub fn input_latency(mut self, latency: Duration) -> Self {
+        self.input_latency = latency;
+        self
+    }
+
+    /// Most frames per second sent by a split session
+    ///
+    /// The bitmaps are drawn by the session, which sends a single
+    /// RdpEvent::Damage with the merged updated regions per frame,
+    /// so the frontend redraws at its own pace whatever the rate
+    /// of the updates. Frame markers are dropped
+    /// Zero, the default, sends the bitmaps as they come
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let client = RdpClient::builder()
+    ///     .target("192.168.0.1")
+    ///     .max_frame_rate(30)
+    ///     .connect()
+    ///     .await?;
+    /// let (mut reader, writer) = client.split();
+    /// while let Some(event) = reader.next().await {
+    ///     if let RdpEvent::Damage(damage) = event? {
+    ///         // redraw damage.regions
+    ///     }
+    /// }
+    /// ```
+    pub fn max_frame_rate(mut self, frames_per_second: u32) -> Self {
+        self.frame_rate = Some(frames_per_second).filter(|rate| *rate > 0);
+        self
+    }
+
+    /// Token to abort the connection at any phase
+    /// The same token closes the session once connected
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let cancel = CancellationToken::new();
+    /// // cancel.cancel() from the Cancel button
+    /// let client = RdpClient::builder()
+    ///     .target("192.168.0.1")
+    ///     .cancel_token(cancel.clone())
+    ///     .connect()
+    ///     .await?;
+    /// ```
+    pub fn cancel_token(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Follow the steps of the connection sequence
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let (sender, receiver) = tokio::sync::watch::channel(None);
+    /// let client = RdpClient::builder()
+    ///     .target("192.168.0.1")
+    ///     .progress(move |phase| {
+    ///         let _ = sender.send(Some(phase));
+    ///     })
+    ///     .connect()
+    ///     .await?;
+    /// ```
+    pub fn progress(mut self, callback: impl Fn(ConnectionPhase) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Reconnect to the session of a previous connection
+    /// without a new logon
+    /// The cookie is the one of the `AutoReconnectCookie` event
+    pub fn auto_reconnect_cookie(mut self, logon_id: u32, random: [u8; 16]) -> Self {
+        self.auto_reconnect = Some((logon_id, random));
+        self
+    }
+
+    /// Send a preconnection PDU before the connection request,
+    /// for the brokers which route the connections by id or token
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let client = RdpClient::builder()
+    ///     .target("broker.example.com")
+    ///     .preconnection(PreconnectionPdu::with_blob(0, "lab-vm-42"))
+    ///     .connect()
+    ///     .await?;
+    /// ```
+    pub fn preconnection(mut self, pdu: PreconnectionPdu) -> Self {
+        self.preconnection = Some(pdu);
+        self
+    }
+
+    /// Connect to a Hyper-V VM through the VMConnect service of its host
+    /// The target is the host, on port 2179 by default
+    ///
+    /// The basic mode shows the console of the VM, the enhanced mode
+    /// connects to the RDP server of the guest, which needs TLS
+    /// or NLA on current Hyper-V versions
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let client = RdpClient::builder()
+    ///     .target("hyperv-host")
+    ///     .vmconnect("39418f90-6d03-468e-b796-91c60e430220", false)
+    ///     .connect()
+    ///     .await?;
+    /// ```
+    pub fn vmconnect(mut self, vm_id: &str, enhanced_mode: bool) -> Self {
+        self.default_port = VMCONNECT_PORT;
+        self.preconnection = Some(PreconnectionPdu::vmconnect(vm_id, enhanced_mode));
+        self
+    }
+
+    /// Routing cookie and correlation info of the connection request
+    pub fn options(mut self, options: ConnectionRequestOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Replace all the capabilities sent to the server
+    pub fn capabilities(mut self, config: CapabilitiesConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Open a TCP connection to the target
+    /// and run the whole connection sequence
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn connect(self) -> Result<RdpClient<TcpStream>> {
+        let cancel = self.cancel.clone();
+        let timer = self.timer.clone();
+        let total = self.timeouts.total;
+        until_cancelled(
+            &cancel,
+            with_timeout(timer.as_ref(), total, "connection", self.connect_tcp(None)),
+        )
+        .await
+    }
+
+    /// Same as connect, over a socket configured by the application,
+    /// like one bound to an interface
+    /// The socket is connected to the first address of the target,
+    /// or of the proxy, of its family. The TCP options aren't applied
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let socket = TcpSocket::new_v4()?;
+    /// socket.bind("10.0.0.2:0".parse()?)?;
+    /// let client = RdpClient::builder()
+    ///     .target("192.168.0.1")
+    ///     .connect_socket(socket)
+    ///     .await?;
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn connect_socket(self, socket: TcpSocket) -> Result<RdpClient<TcpStream>> {
+        let cancel = self.cancel.clone();
+        let timer = self.timer.clone();
+        let total = self.timeouts.total;
+        until_cancelled(
+            &cancel,
+            with_timeout(
+                timer.as_ref(),
+                total,
+                "connection",
+                self.connect_tcp(Some(socket)),
+            ),
+        )
+        .await
+    }
+
+    /// Run the whole connection sequence over an opened transport
+    /// The target is ignored
+    pub async fn connect_with<S>(self, transport: S) -> Result<RdpClient<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let cancel = self.cancel.clone();
+        let timer = self.timer.clone();
+        let total = self.timeouts.total;
+        until_cancelled(
+            &cancel,
+            with_timeout(timer.as_ref(), total, "connection", self.run(transport)),
+        )
+        .await
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn connect_tcp(self, socket: Option<TcpSocket>) -> Result<RdpClient<TcpStream>> {
+        if self.gateway.is_some() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "RDPCLIENT: RD gateways need an RdgConnector and connect_with",
+            ));
+        }
+        let target = match &self.target {
+            Some(target) => target,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "RDPCLIENT: no target to connect to",
+                ))
+            }
+        };
+        let (host, port) = split_target(target, self.default_port);
+        let stream = with_timeout(
+            self.timer.as_ref(),
+            self.timeouts.tcp_connect,
+            "TCP connect",
+            self.open_stream(host, port, socket),
+        )
+        .await?;
+        if let Some(callback) = &self.progress {
+            callback(ConnectionPhase::TcpConnected);
+        }
+        self.run(stream).await
+    }
+
+    /// TCP connection to the target, or to the proxy
+    /// whose handshake counts as the TCP connection
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn open_stream(
+        &self,
+        host: &str,
+        port: u16,
+        socket: Option<TcpSocket>,
+    ) -> Result<TcpStream> {
+        match &self.proxy {
+            Some(proxy) => {
+                let mut stream = self.open_tcp(proxy.address(), socket).await?;
+                proxy.handshake(&mut stream, host, port).await?;
+                Ok(stream)
+            }
+            None => self.open_tcp((host, port), socket).await,
+        }
+    }
+
+    /// Connect the socket of the application,
+    /// or a new one with the TCP options
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn open_tcp(
+        &self,
+        address: impl ToSocketAddrs,
+        socket: Option<TcpSocket>,
+    ) -> Result<TcpStream> {
+        match socket {
+            Some(socket) => {
+                let address = socket_address(&socket, address).await?;
+                socket.connect(address).await
+            }
+            None => connect_with_options(address, &self.tcp_options).await,
+        }
+    }
+
+    /// Connection sequence, each phase with its own time limit
+    async fn run<S>(mut self, mut transport: S) -> Result<RdpClient<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        // The server could pick NCrush, which can't be decompressed
+        if self.compression == Some(CompressionType::PacketComprTypeRdp6) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "RDPCLIENT: RDP 6.0 compression isn't supported",
+            ));
+        }
+        let timeouts = self.timeouts;
+        let timer = self.timer.clone();
+        let progress = self.progress.take();
+        // RemoteApps are started on the rail channel
+        if self.config.remote_app && !self.channels.iter().any(|name| name == RAIL_CHANNEL_NAME) {
+            self.channels.push(RAIL_CHANNEL_NAME.to_string());
+        }
+        let notify = move |phase| {
+            if let Some(callback) = &progress {
+                callback(phase)
+            }
+        };
+
+        if let Some(preconnection) = &self.preconnection {
+            preconnection.write_to(&mut transport).await?;
+        }
+        let mut x224 = with_timeout(
+            timer.as_ref(),
+            timeouts.negotiation,
+            "negotiation",
+            X224Client::negotiate(
+                TpktClient::new(transport),
+                self.security,
+                false,
+                self.options,
+            ),
+        )
+        .await?;
+        let selected_protocol = x224.get_selected_protocols();
+        notify(ConnectionPhase::NegotiationDone(selected_protocol));
+
+        match selected_protocol {
+            Protocols::ProtocolRDP => (),
+            #[cfg(not(target_arch = "wasm32"))]
+            Protocols::ProtocolSSL | Protocols::ProtocolHybrid => {
+                // Name of the target for SNI
+                let server_name = self
+                    .target
+                    .as_deref()
+                    .map_or("", |target| split_target(target, self.default_port).0);
+                with_timeout(
+                    timer.as_ref(),
+                    timeouts.tls_handshake,
+                    "TLS handshake",
+                    x224.start_ssl(self.check_certificate, server_name),
+                )
+                .await?;
+                notify(ConnectionPhase::TlsEstablished);
+                if selected_protocol == Protocols::ProtocolHybrid {
+                    let mut ntlm = Ntlm::new(
+                        self.domain.clone(),
+                        self.username.clone(),
+                        self.password.clone(),
+                    );
+                    with_timeout(
+                        timer.as_ref(),
+                        timeouts.nla,
+                        "NLA",
+                        x224.start_nla(&mut ntlm, false),
+                    )
+                    .await?;
+                    notify(ConnectionPhase::NlaDone);
+                }
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "RDPCLIENT: security protocol not handled",
+                ))
+            }
+        }
+
+        let mcs = with_timeout(
+            timer.as_ref(),
+            timeouts.channel_connection,
+            "channel connection",
+            McsClient::connect_with_channels(
+                x224,
+                &self.name,
+                self.config.desktop_width,
+                self.config.desktop_height,
+                self.config.keyboard_layout,
+                &self.channels,
+            ),
+        )
+        .await?;
+        notify(ConnectionPhase::McsConnected);
+
+        let mut info = ClientInfoPdu::new(
+            &self.domain,
+            &self.username,
+            &self.password,
+            self.auto_logon,
+        );
+        if let Some(flags) = self.performance_flags {
+            info.set_performance_flags(flags);
+        }
+        if let Some(compression_type) = self.compression {
+            info.set_compression(compression_type);
+        }
+        if let Some((logon_id, random)) = self.auto_reconnect {
+            info.set_auto_reconnect_cookie(logon_id, random);
+        }
+        info.set_flag(InfoFlag::InfoRail, self.config.remote_app);
+        info.set_flag(
+            InfoFlag::InfoEnablewindowskey,
+            self.keyboard_hook != KeyboardHook::Local,
+        );
+        let sec = with_timeout(
+            timer.as_ref(),
+            timeouts.logon,
+            "logon",
+            SecClient::connect(mcs, info),
+        )
+        .await?;
+        notify(ConnectionPhase::Licensed);
+
+        let mut global = with_timeout(
+            timer.as_ref(),
+            timeouts.capabilities,
+            "capabilities exchange",
+            GlobalClient::connect(sec, self.config),
+        )
+        .await?;
+        global.set_timer(timer.clone());
+        notify(ConnectionPhase::Activated);
+        let (channel_sender, channel_writes) = mpsc::channel(SESSION_QUEUE_SIZE);
+        Ok(RdpClient {
+            global,
+            idle_read: timeouts.idle_read,
+            cancel: self.cancel,
+            channel_writes,
+            channel_sender,
+            timer,
+            inputs: InputQueue::new(self.input_latency),
+            frame_rate: self.frame_rate,
+        })
+    }
+}
+
+/// Abort a connection phase once the token is cancelled
+pub(crate) async fn until_cancelled<T>(
+    cancel: &CancellationToken,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::select! {
+        result = future => result,
+        _ = cancel.cancelled() => Err(Error::new(
+            ErrorKind::Interrupted,
+            "RDPCLIENT: connection cancelled",
+        )),
+    }
+}
+
+/// Connect to the first address which answers,
+/// like TcpStream::connect with the options set on each socket
+#[cfg(not(target_arch = "wasm32"))]
+async fn connect_with_options(
+    address: impl ToSocketAddrs,
+    options: &TcpOptions,
+) -> Result<TcpStream> {
+    let mut last_error = None;
+    for address in lookup_host(address).await? {
+        let result = match tcp_socket(&address, options) {
+            Ok(socket) => socket.connect(address).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(stream) => {
+                stream.set_nodelay(options.nodelay)?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            "RDPCLIENT: no address found for the target",
+        )
+    }))
+}
+
+/// New socket with the buffer sizes and the keepalive of the options
+#[cfg(not(target_arch = "wasm32"))]
+fn tcp_socket(address: &SocketAddr, options: &TcpOptions) -> Result<TcpSocket> {
+    let socket = match address {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(time) = options.keepalive {
+        let mut keepalive = socket2::TcpKeepalive::new().with_time(time);
+        if let Some(interval) = options.keepalive_interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        socket2::SockRef::from(&socket).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(socket)
+}
+
+/// First address of the target with the family of the socket
+/// Any address when the family is unknown until the socket is bound
+#[cfg(not(target_arch = "wasm32"))]
+async fn socket_address(socket: &TcpSocket, address: impl ToSocketAddrs) -> Result<SocketAddr> {
+    let ipv6 = socket.local_addr().map(|local| local.is_ipv6()).ok();
+    lookup_host(address)
+        .await?
+        .find(|address| match ipv6 {
+            Some(ipv6) => address.is_ipv6() == ipv6,
+            None => true,
+        })
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                "RDPCLIENT: no address of the socket family found for the target",
+            )
+        })
+}
+
+/// Run a future with an optional time limit
+async fn with_timeout<T>(
+    timer: &dyn Timer,
+    timeout: Option<Duration>,
+    phase: &str,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => runtime::timeout(timer, timeout, future)
+            .await
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::TimedOut,
+                    format!("RDPCLIENT: {} timed out after {:?}", phase, timeout),
+                )
+            })?,
+        None => future.await,
+    }
+}
+
+/// Split a target into a host and a port
+/// Brackets of IPv6 addresses are removed
+pub(crate) fn split_target(target: &str, default_port: u16) -> (&str, u16) {
+    if let Some((host, port)) = target.rsplit_once(':') {
+        // A bare IPv6 address contains colons without a port
+        let is_ipv6 = host.contains(':');
+        if !is_ipv6 || host.ends_with(']') {
+            if let Ok(port) = port.parse::<u16>() {
+                return (host.trim_start_matches('[').trim_end_matches(']'), port);
+            }
+        }
+    }
+    (
+        target.trim_start_matches('[').trim_end_matches(']'),
+        default_port,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::event::SessionEvent;
+
+    /// Test the split of host and port
+    #[test]
+    fn test_split_target() {
+        assert_eq!(split_target("server", DEFAULT_PORT), ("server", 3389));
+        assert_eq!(split_target("server:3390", DEFAULT_PORT), ("server", 3390));
+        assert_eq!(
+            split_target("192.168.0.1:3390", DEFAULT_PORT),
+            ("192.168.0.1", 3390)
+        );
+        assert_eq!(split_target("::1", DEFAULT_PORT), ("::1", 3389));
+        assert_eq!(split_target("[::1]", DEFAULT_PORT), ("::1", 3389));
+        assert_eq!(split_target("[::1]:3390", DEFAULT_PORT), ("::1", 3390));
+        assert_eq!(split_target("host", VMCONNECT_PORT), ("host", 2179));
+    }
+
+    /// A server which never answers stalls the negotiation
+    #[tokio::test]
+    async fn test_connect_negotiation_timeout() {
+        let (client, _server) = tokio::io::duplex(1024);
+        let result = RdpClientBuilder::new()
+            .timeouts(Timeouts {
+                negotiation: Some(Duration::from_millis(10)),
+                ..Default::default()
+            })
+            .connect_with(client)
+            .await;
+        assert_eq!(result.err().map(|e| e.kind()), Some(ErrorKind::TimedOut));
+    }
+
+    /// A server which selects SSL and stalls the handshake
+    /// is stopped by the TLS handshake limit
+    #[tokio::test]
+    async fn test_connect_tls_handshake_timeout() {
+        use crate::core::tpkt::server::TpktServer;
+        use crate::core::x224::base::SecurityPolicy;
+        use crate::core::x224::server::X224Server;
+
+        let (client, server) = tokio::io::duplex(1024);
+        let policy = SecurityPolicy {
+            accepted: vec![Protocols::ProtocolSSL],
+        };
+        let connection = RdpClientBuilder::new()
+            .security(Protocols::ProtocolSSL as u32)
+            .timeouts(Timeouts {
+                tls_handshake: Some(Duration::from_millis(10)),
+                ..Default::default()
+            })
+            .connect_with(client);
+        let (server, result) = tokio::join!(
+            X224Server::accept(TpktServer::new(server), &policy),
+            connection
+        );
+        assert_eq!(
+            server.unwrap().get_selected_protocols(),
+            Protocols::ProtocolSSL
+        );
+        let error = result.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert!(error.to_string().contains("TLS handshake"));
+    }
+
+    /// The preconnection PDU comes before the connection request
+    #[tokio::test]
+    async fn test_connect_preconnection() {
+        use tokio::io::AsyncReadExt;
+
+        let (client, mut server) = tokio::io::duplex(1024);
+        let connection = RdpClientBuilder::new()
+            .preconnection(PreconnectionPdu::new(5))
+            .timeouts(Timeouts {
+                negotiation: Some(Duration::from_millis(10)),
+                ..Default::default()
+            })
+            .connect_with(client);
+        assert!(connection.await.is_err());
+
+        let mut pdu = [0u8; 17];
+        server.read_exact(&mut pdu).await.unwrap();
+        assert_eq!(pdu[..16], [16, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 5, 0, 0, 0]);
+        // TPKT version of the connection request
+        assert_eq!(pdu[16], 3);
+    }
+
+    /// A cancelled token aborts the connection
+    #[tokio::test]
+    async fn test_connect_cancelled() {
+        let (client, _server) = tokio::io::duplex(1024);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let result = RdpClientBuilder::new()
+            .cancel_token(cancel)
+            .connect_with(client)
+            .await;
+        assert_eq!(result.err().map(|e| e.kind()), Some(ErrorKind::Interrupted));
+    }
+
+    /// The progress stops at the failing step
+    #[tokio::test]
+    async fn test_connect_progress() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { listener.accept().await });
+
+        let phases = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = phases.clone();
+        let result = RdpClient::builder()
+            .target(&target)
+            .progress(move |phase| recorded.lock().unwrap().push(phase))
+            .connect()
+            .await;
+        assert!(result.is_err());
+        assert_eq!(*phases.lock().unwrap(), vec![ConnectionPhase::TcpConnected]);
+    }
+
+    /// The options are set on the socket before the connection
+    #[tokio::test]
+    async fn test_connect_with_options() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap();
+        tokio::spawn(async move { listener.accept().await });
+
+        let options = TcpOptions {
+            nodelay: false,
+            keepalive: Some(Duration::from_secs(60)),
+            keepalive_interval: Some(Duration::from_secs(10)),
+            send_buffer_size: Some(0x10000),
+            ..Default::default()
+        };
+        let stream = connect_with_options(target, &options).await.unwrap();
+        assert!(!stream.nodelay().unwrap());
+        assert!(socket2::SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    /// The stream gives the events then the closing error
+    #[tokio::test]
+    async fn test_session_reader_stream() {
+        use tokio_stream::StreamExt;
+
+        let (sender, events) = mpsc::channel(SESSION_QUEUE_SIZE);
+        let reader = SessionReader { events };
+        tokio::spawn(async move {
+            let logon = RdpEvent::Session(SessionEvent::LogonNotify);
+            sender.send(Ok(logon)).await.unwrap();
+            let error = Error::new(ErrorKind::UnexpectedEof, "closed");
+            sender.send(Err(error)).await.unwrap();
+        });
+
+        let results: Vec<_> = reader.collect().await;
+        assert!(matches!(
+            results[..],
+            [Ok(RdpEvent::Session(SessionEvent::LogonNotify)), Err(_)]
+        ));
+    }
+
+    /// A target is required to open the TCP connection
+    #[tokio::test]
+    async fn test_connect_without_target() {
+        let result = RdpClient::builder()
+            .credentials("", "user", "password")
+            .connect()
+            .await;
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+    }
+
+    /// RDP 6.0 isn't announced as the server could use NCrush
+    #[tokio::test]
+    async fn test_connect_with_rdp6_compression() {
+        let (client, _server) = tokio::io::duplex(64);
+        let result = RdpClient::builder()
+            .compression(CompressionType::PacketComprTypeRdp6)
+            .connect_with(client)
+            .await;
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+    }
+}
@@ -0,0 +1,513 @@
+use crate::model::data::{check_remaining, Message};
+use crate::model::unicode::{from_unicode, Unicode};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the static virtual channel of the clipboard
+pub const CLIPRDR_CHANNEL_NAME: &str = "cliprdr";
+
+/// Size of the CLIPRDR_HEADER
+const HEADER_SIZE: usize = 8;
+
+/// Size of a format name without long format names
+/// MS-RDPECLIP 2.2.3.1.1.1 Short Format Name (CLIPRDR_SHORT_FORMAT_NAME)
+const SHORT_FORMAT_NAME_SIZE: usize = 32;
+
+/// General capability set
+/// MS-RDPECLIP 2.2.2.1.1.1 General Capability Set (CLIPRDR_GENERAL_CAPABILITY)
+const CB_CAPSTYPE_GENERAL: u16 = 0x0001;
+const CB_CAPS_VERSION_2: u32 = 0x00000002;
+const GENERAL_CAPABILITY_SIZE: usize = 12;
+
+/// Standard clipboard formats
+/// The ids of the other formats are given with their name in the format lists
+pub const CF_TEXT: u32 = 1;
+pub const CF_BITMAP: u32 = 2;
+pub const CF_DIB: u32 = 8;
+pub const CF_UNICODETEXT: u32 = 13;
+pub const CF_DIBV5: u32 = 17;
+
+/// Names of the registered formats
+pub const HTML_FORMAT_NAME: &str = "HTML Format";
+pub const PNG_FORMAT_NAME: &str = "PNG";
+pub const FILE_GROUP_DESCRIPTOR_NAME: &str = "FileGroupDescriptorW";
+
+/// Size of a file contents request without the clip data id
+/// MS-RDPECLIP 2.2.5.3 File Contents Request PDU (CLIPRDR_FILECONTENTS_REQUEST)
+const FILE_CONTENTS_REQUEST_SIZE: usize = 24;
+
+/// Type of a clipboard PDU
+/// MS-RDPECLIP 2.2.1 Clipboard PDU Header (CLIPRDR_HEADER)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum ClipboardMessageType {
+    CbMonitorReady = 0x0001,
+    CbFormatList = 0x0002,
+    CbFormatListResponse = 0x0003,
+    CbFormatDataRequest = 0x0004,
+    CbFormatDataResponse = 0x0005,
+    CbTempDirectory = 0x0006,
+    CbClipCaps = 0x0007,
+    CbFilecontentsRequest = 0x0008,
+    CbFilecontentsResponse = 0x0009,
+    CbLockClipdata = 0x000A,
+    CbUnlockClipdata = 0x000B,
+}
+
+/// Flags of the clipboard PDU header
+/// MS-RDPECLIP 2.2.1 Clipboard PDU Header (CLIPRDR_HEADER)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClipboardMessageFlag {
+    CbResponseOk = 0x0001,
+    CbResponseFail = 0x0002,
+    CbAsciiNames = 0x0004,
+}
+
+/// Flags of the general capability set
+/// MS-RDPECLIP 2.2.2.1.1.1 General Capability Set (CLIPRDR_GENERAL_CAPABILITY)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GeneralFlag {
+    CbUseLongFormatNames = 0x00000002,
+    CbStreamFileclipEnabled = 0x00000004,
+    CbFileclipNoFilePaths = 0x00000008,
+    CbCanLockClipdata = 0x00000010,
+    CbHugeFileSupportEnabled = 0x00000020,
+}
+
+/// Data asked by a file contents request
+/// MS-RDPECLIP 2.2.5.3 File Contents Request PDU (CLIPRDR_FILECONTENTS_REQUEST)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FileContentsFlag {
+    FilecontentsSize = 0x00000001,
+    FilecontentsRange = 0x00000002,
+}
+
+/// Request of the size or of a range of a file
+/// The file is an item of the last file list
+/// MS-RDPECLIP 2.2.5.3 File Contents Request PDU (CLIPRDR_FILECONTENTS_REQUEST)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileContentsRequest {
+    /// Given back in the response
+    pub stream_id: u32,
+    /// Index of the file in the file list
+    pub index: u32,
+    pub flags: u32,
+    pub position: u64,
+    /// Bytes asked, 8 for a size request
+    pub requested: u32,
+    /// Only sent when the clipboard data can be locked
+    pub clip_data_id: Option<u32>,
+}
+
+impl FileContentsRequest {
+    /// Ask the size of a file
+    pub fn size(stream_id: u32, index: u32) -> Self {
+        FileContentsRequest {
+            stream_id,
+            index,
+            flags: FileContentsFlag::FilecontentsSize as u32,
+            position: 0,
+            requested: 8,
+            clip_data_id: None,
+        }
+    }
+
+    /// Ask a range of a file
+    pub fn range(stream_id: u32, index: u32, position: u64, requested: u32) -> Self {
+        FileContentsRequest {
+            stream_id,
+            index,
+            flags: FileContentsFlag::FilecontentsRange as u32,
+            position,
+            requested,
+            clip_data_id: None,
+        }
+    }
+
+    pub fn is_size(&self) -> bool {
+        self.flags & FileContentsFlag::FilecontentsSize as u32 != 0
+    }
+}
+
+/// A format of a format list
+/// Standard formats have no name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardFormat {
+    pub id: u32,
+    pub name: String,
+}
+
+impl ClipboardFormat {
+    pub fn new(id: u32, name: &str) -> Self {
+        ClipboardFormat {
+            id,
+            name: name.to_string(),
+        }
+    }
+
+    fn name_length(&self, long_names: bool) -> usize {
+        if long_names {
+            self.name.to_unicode().len() + 2
+        } else {
+            SHORT_FORMAT_NAME_SIZE
+        }
+    }
+}
+
+/// PDU of the clipboard channel, sent by both sides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardPdu {
+    /// MS-RDPECLIP 2.2.2.2 Server Monitor Ready PDU (CLIPRDR_MONITOR_READY)
+    MonitorReady,
+    /// Flags of the general capability set
+    /// MS-RDPECLIP 2.2.2.1 Clipboard Capabilities PDU (CLIPRDR_CAPS)
+    Capabilities(u32),
+    /// Names are long when both sides announce long format names
+    /// MS-RDPECLIP 2.2.3.1 Format List PDU (CLIPRDR_FORMAT_LIST)
+    FormatList {
+        formats: Vec<ClipboardFormat>,
+        long_names: bool,
+    },
+    /// MS-RDPECLIP 2.2.3.2 Format List Response PDU (FORMAT_LIST_RESPONSE)
+    FormatListResponse(bool),
+    /// Id of the requested format
+    /// MS-RDPECLIP 2.2.5.1 Format Data Request PDU (CLIPRDR_FORMAT_DATA_REQUEST)
+    FormatDataRequest(u32),
+    /// None when the data can't be given
+    /// MS-RDPECLIP 2.2.5.2 Format Data Response PDU (CLIPRDR_FORMAT_DATA_RESPONSE)
+    FormatDataResponse(Option<Vec<u8>>),
+    FileContentsRequest(FileContentsRequest),
+    /// Size or range of a file, None when it can't be read
+    /// MS-RDPECLIP 2.2.5.4 File Contents Response PDU (CLIPRDR_FILECONTENTS_RESPONSE)
+    FileContentsResponse {
+        stream_id: u32,
+        data: Option<Vec<u8>>,
+    },
+}
+
+impl ClipboardPdu {
+    fn message_type(&self) -> ClipboardMessageType {
+        match self {
+            ClipboardPdu::MonitorReady => ClipboardMessageType::CbMonitorReady,
+            ClipboardPdu::Capabilities(_) => ClipboardMessageType::CbClipCaps,
+            ClipboardPdu::FormatList { .. } => ClipboardMessageType::CbFormatList,
+            ClipboardPdu::FormatListResponse(_) => ClipboardMessageType::CbFormatListResponse,
+            ClipboardPdu::FormatDataRequest(_) => ClipboardMessageType::CbFormatDataRequest,
+            ClipboardPdu::FormatDataResponse(_) => ClipboardMessageType::CbFormatDataResponse,
+            ClipboardPdu::FileContentsRequest(_) => ClipboardMessageType::CbFilecontentsRequest,
+            ClipboardPdu::FileContentsResponse { .. } => {
+                ClipboardMessageType::CbFilecontentsResponse
+            }
+        }
+    }
+
+    fn flags(&self) -> u16 {
+        match self {
+            ClipboardPdu::FormatListResponse(true)
+            | ClipboardPdu::FormatDataResponse(Some(_))
+            | ClipboardPdu::FileContentsResponse { data: Some(_), .. } => {
+                ClipboardMessageFlag::CbResponseOk as u16
+            }
+            ClipboardPdu::FormatListResponse(false)
+            | ClipboardPdu::FormatDataResponse(None)
+            | ClipboardPdu::FileContentsResponse { data: None, .. } => {
+                ClipboardMessageFlag::CbResponseFail as u16
+            }
+            _ => 0,
+        }
+    }
+
+    fn data_length(&self) -> usize {
+        match self {
+            ClipboardPdu::MonitorReady | ClipboardPdu::FormatListResponse(_) => 0,
+            ClipboardPdu::Capabilities(_) => 4 + GENERAL_CAPABILITY_SIZE,
+            ClipboardPdu::FormatList {
+                formats,
+                long_names,
+            } => formats
+                .iter()
+                .map(|format| 4 + format.name_length(*long_names))
+                .sum(),
+            ClipboardPdu::FormatDataRequest(_) => 4,
+            ClipboardPdu::FormatDataResponse(data) => data.as_ref().map_or(0, Vec::len),
+            ClipboardPdu::FileContentsRequest(request) => {
+                FILE_CONTENTS_REQUEST_SIZE + request.clip_data_id.map_or(0, |_| 4)
+            }
+            ClipboardPdu::FileContentsResponse { data, .. } => {
+                4 + data.as_ref().map_or(0, Vec::len)
+            }
+        }
+    }
+}
+
+/// Read the formats of a format list
+fn read_formats(
+    buffer: &mut BytesMut,
+    long_names: bool,
+    ascii: bool,
+) -> Result<Vec<ClipboardFormat>> {
+    let mut formats = Vec::new();
+    while buffer.has_remaining() {
+        check_remaining(buffer, 4, "CLIPRDR: format id")?;
+        let id = buffer.get_u32_le();
+        let name = if long_names {
+            // Null terminated UTF-16 name
+            let end = buffer
+                .chunks_exact(2)
+                .position(|c| c == [0, 0])
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidData, "CLIPRDR: invalid format name")
+                })?;
+            from_unicode(&buffer.split_to(end * 2 + 2))
+        } else {
+            check_remaining(buffer, SHORT_FORMAT_NAME_SIZE, "CLIPRDR: short format name")?;
+            let name = buffer.split_to(SHORT_FORMAT_NAME_SIZE);
+            if ascii {
+                let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+                String::from_utf8_lossy(&name[..end]).to_string()
+            } else {
+                from_unicode(&name)
+            }
+        };
+        formats.push(ClipboardFormat { id, name });
+    }
+    Ok(formats)
+}
+
+/// Read a clipboard PDU
+/// Format lists are read with long names when both sides announced them
+pub fn read_clipboard_pdu(buffer: &mut BytesMut, long_names: bool) -> Result<ClipboardPdu> {
+    check_remaining(buffer, HEADER_SIZE, "CLIPRDR: header")?;
+    let message_type = buffer.get_u16_le();
+    let flags = buffer.get_u16_le();
+    let length = buffer.get_u32_le() as usize;
+    check_remaining(buffer, length, "CLIPRDR: PDU")?;
+    let mut body = buffer.split_to(length);
+    let body = &mut body;
+    let ok = flags & ClipboardMessageFlag::CbResponseOk as u16 != 0;
+
+    Ok(match ClipboardMessageType::try_from(message_type) {
+        Ok(ClipboardMessageType::CbMonitorReady) => ClipboardPdu::MonitorReady,
+        Ok(ClipboardMessageType::CbClipCaps) => {
+            check_remaining(body, 4, "CLIPRDR: capabilities")?;
+            let count = body.get_u16_le();
+            let _pad = body.get_u16_le();
+            let mut general_flags = 0;
+            for _ in 0..count {
+                check_remaining(body, 4, "CLIPRDR: capability set")?;
+                let capability_type = body.get_u16_le();
+                let capability_length = (body.get_u16_le() as usize).saturating_sub(4);
+                check_remaining(body, capability_length, "CLIPRDR: capability set")?;
+                let mut capability = body.split_to(capability_length);
+                if capability_type == CB_CAPSTYPE_GENERAL {
+                    check_remaining(&capability, 8, "CLIPRDR: general capability")?;
+                    let _version = capability.get_u32_le();
+                    general_flags = capability.get_u32_le();
+                }
+            }
+            ClipboardPdu::Capabilities(general_flags)
+        }
+        Ok(ClipboardMessageType::CbFormatList) => {
+            let ascii = flags & ClipboardMessageFlag::CbAsciiNames as u16 != 0;
+            ClipboardPdu::FormatList {
+                formats: read_formats(body, long_names, ascii)?,
+                long_names,
+            }
+        }
+        Ok(ClipboardMessageType::CbFormatListResponse) => ClipboardPdu::FormatListResponse(ok),
+        Ok(ClipboardMessageType::CbFormatDataRequest) => {
+            check_remaining(body, 4, "CLIPRDR: format data request")?;
+            ClipboardPdu::FormatDataRequest(body.get_u32_le())
+        }
+        Ok(ClipboardMessageType::CbFormatDataResponse) => {
+            ClipboardPdu::FormatDataResponse(Some(body.to_vec()).filter(|_| ok))
+        }
+        Ok(ClipboardMessageType::CbFilecontentsRequest) => {
+            check_remaining(
+                body,
+                FILE_CONTENTS_REQUEST_SIZE,
+                "CLIPRDR: file contents request",
+            )?;
+            let stream_id = body.get_u32_le();
+            let index = body.get_u32_le();
+            let flags = body.get_u32_le();
+            let position = body.get_u64_le();
+            let requested = body.get_u32_le();
+            let clip_data_id = (body.remaining() >= 4).then(|| body.get_u32_le());
+            ClipboardPdu::FileContentsRequest(FileContentsRequest {
+                stream_id,
+                index,
+                flags,
+                position,
+                requested,
+                clip_data_id,
+            })
+        }
+        Ok(ClipboardMessageType::CbFilecontentsResponse) => {
+            check_remaining(body, 4, "CLIPRDR: file contents response")?;
+            ClipboardPdu::FileContentsResponse {
+                stream_id: body.get_u32_le(),
+                data: Some(body.to_vec()).filter(|_| ok),
+            }
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("CLIPRDR: unexpected message type {}", message_type),
+            ))
+        }
+    })
+}
+
+#[async_trait]
+impl Message for ClipboardPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.message_type() as u16).await?;
+        writer.write_u16_le(self.flags()).await?;
+        writer.write_u32_le(self.data_length() as u32).await?;
+        match self {
+            ClipboardPdu::MonitorReady | ClipboardPdu::FormatListResponse(_) => (),
+            ClipboardPdu::Capabilities(general_flags) => {
+                writer.write_u16_le(1).await?;
+                writer.write_u16_le(0).await?;
+                writer.write_u16_le(CB_CAPSTYPE_GENERAL).await?;
+                writer.write_u16_le(GENERAL_CAPABILITY_SIZE as u16).await?;
+                writer.write_u32_le(CB_CAPS_VERSION_2).await?;
+                writer.write_u32_le(*general_flags).await?;
+            }
+            ClipboardPdu::FormatList {
+                formats,
+                long_names,
+            } => {
+                for format in formats {
+                    writer.write_u32_le(format.id).await?;
+                    let mut name = format.name.to_unicode();
+                    if *long_names {
+                        name.extend_from_slice(&[0, 0]);
+                    } else {
+                        // Truncated with room for the null terminator
+                        name.resize(SHORT_FORMAT_NAME_SIZE - 2, 0);
+                        name.resize(SHORT_FORMAT_NAME_SIZE, 0);
+                    }
+                    writer.write_all(&name).await?;
+                }
+            }
+            ClipboardPdu::FormatDataRequest(format_id) => writer.write_u32_le(*format_id).await?,
+            ClipboardPdu::FormatDataResponse(data) => {
+                if let Some(data) = data {
+                    writer.write_all(data).await?;
+                }
+            }
+            ClipboardPdu::FileContentsRequest(request) => {
+                writer.write_u32_le(request.stream_id).await?;
+                writer.write_u32_le(request.index).await?;
+                writer.write_u32_le(request.flags).await?;
+                writer.write_u64_le(request.position).await?;
+                writer.write_u32_le(request.requested).await?;
+                if let Some(clip_data_id) = request.clip_data_id {
+                    writer.write_u32_le(clip_data_id).await?;
+                }
+            }
+            ClipboardPdu::FileContentsResponse { stream_id, data } => {
+                writer.write_u32_le(*stream_id).await?;
+                if let Some(data) = data {
+                    writer.write_all(data).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Format lists are read with the names of the current value
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let long_names = match self {
+            ClipboardPdu::FormatList { long_names, .. } => *long_names,
+            _ => true,
+        };
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header).await?;
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let mut buffer = BytesMut::from(&header[..]);
+        buffer.resize(HEADER_SIZE + length as usize, 0);
+        reader.read_exact(&mut buffer[HEADER_SIZE..]).await?;
+        *self = read_clipboard_pdu(&mut buffer, long_names)?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        HEADER_SIZE + self.data_length()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Format lists are written and read with both kinds of names
+    #[tokio::test]
+    async fn test_format_list() {
+        for long_names in [true, false] {
+            let pdu = ClipboardPdu::FormatList {
+                formats: vec![
+                    ClipboardFormat::new(CF_UNICODETEXT, ""),
+                    ClipboardFormat::new(0xC001, HTML_FORMAT_NAME),
+                ],
+                long_names,
+            };
+            let data = to_vec(&pdu).await.unwrap();
+            assert_eq!(data.len(), pdu.length());
+            let mut buffer = BytesMut::from(&data[..]);
+            assert_eq!(read_clipboard_pdu(&mut buffer, long_names).unwrap(), pdu);
+        }
+    }
+
+    /// Capabilities of the server and a failed data response
+    #[test]
+    fn test_read_clipboard_pdu() {
+        let mut buffer = BytesMut::from(
+            &[
+                0x07, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00,
+                0x0C, 0x00, 0x02, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x05, 0x00, 0x02, 0x00,
+                0x00, 0x00, 0x00, 0x00,
+            ][..],
+        );
+        assert_eq!(
+            read_clipboard_pdu(&mut buffer, false).unwrap(),
+            ClipboardPdu::Capabilities(0x06)
+        );
+        assert_eq!(
+            read_clipboard_pdu(&mut buffer, false).unwrap(),
+            ClipboardPdu::FormatDataResponse(None)
+        );
+        assert!(buffer.is_empty());
+    }
+
+    /// Range requests and their response are written and read back
+    #[tokio::test]
+    async fn test_file_contents() {
+        for pdu in [
+            ClipboardPdu::FileContentsRequest(FileContentsRequest::range(3, 1, 1 << 32, 4096)),
+            ClipboardPdu::FileContentsResponse {
+                stream_id: 3,
+                data: Some(vec![1, 2, 3]),
+            },
+            ClipboardPdu::FileContentsResponse {
+                stream_id: 3,
+                data: None,
+            },
+        ] {
+            let data = to_vec(&pdu).await.unwrap();
+            assert_eq!(data.len(), pdu.length());
+            let mut buffer = BytesMut::from(&data[..]);
+            assert_eq!(read_clipboard_pdu(&mut buffer, true).unwrap(), pdu);
+        }
+    }
+}
@@ -0,0 +1,652 @@
+use crate::core::cliprdr::base::{
+    read_clipboard_pdu, ClipboardFormat, ClipboardPdu, FileContentsRequest, GeneralFlag, CF_DIB,
+    CF_DIBV5, CF_TEXT, CF_UNICODETEXT, FILE_GROUP_DESCRIPTOR_NAME, HTML_FORMAT_NAME,
+    PNG_FORMAT_NAME,
+};
+use crate::core::cliprdr::format::{
+    bmp_to_dib, clipboard_to_html, clipboard_to_text, dib_header_size, dib_to_bmp,
+    html_to_clipboard, read_file_group_descriptor, text_to_clipboard, write_file_group_descriptor,
+    FileDescriptor,
+};
+
+use bytes::{Buf, BytesMut};
+use std::collections::HashMap;
+use std::io::Result;
+
+/// Ids of the registered formats offered by the client
+const HTML_FORMAT_ID: u32 = 0xC001;
+const PNG_FORMAT_ID: u32 = 0xC002;
+const FILE_GROUP_DESCRIPTOR_ID: u32 = 0xC003;
+
+/// Bytes asked by each range request of a download
+const FILE_CHUNK_SIZE: u64 = 65536;
+
+/// Size of a BITMAPV5HEADER, CF_DIBV5 data starts with it
+const BITMAP_V5_HEADER_SIZE: usize = 124;
+
+/// Kind of clipboard content, several formats can hold it
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClipboardKind {
+    Text,
+    Html,
+    Image,
+    Files,
+}
+
+/// Clipboard content in its local representation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardContent {
+    Text(String),
+    /// HTML fragment
+    Html(String),
+    /// BMP file, sent as CF_DIB or CF_DIBV5
+    Bitmap(Vec<u8>),
+    /// PNG file
+    Png(Vec<u8>),
+    /// Files, their contents are read by the file delegate
+    Files(Vec<FileDescriptor>),
+}
+
+impl ClipboardContent {
+    fn kind(&self) -> ClipboardKind {
+        match self {
+            ClipboardContent::Text(_) => ClipboardKind::Text,
+            ClipboardContent::Html(_) => ClipboardKind::Html,
+            ClipboardContent::Bitmap(_) | ClipboardContent::Png(_) => ClipboardKind::Image,
+            ClipboardContent::Files(_) => ClipboardKind::Files,
+        }
+    }
+
+    /// Formats announced for the content
+    fn formats(&self) -> Vec<ClipboardFormat> {
+        match self {
+            ClipboardContent::Text(_) => vec![ClipboardFormat::new(CF_UNICODETEXT, "")],
+            ClipboardContent::Html(_) => {
+                vec![ClipboardFormat::new(HTML_FORMAT_ID, HTML_FORMAT_NAME)]
+            }
+            ClipboardContent::Bitmap(bmp) => {
+                let mut formats = vec![ClipboardFormat::new(CF_DIB, "")];
+                let header_size = bmp_to_dib(bmp).ok().and_then(|dib| dib_header_size(&dib));
+                if header_size == Some(BITMAP_V5_HEADER_SIZE) {
+                    formats.push(ClipboardFormat::new(CF_DIBV5, ""));
+                }
+                formats
+            }
+            ClipboardContent::Png(_) => vec![ClipboardFormat::new(PNG_FORMAT_ID, PNG_FORMAT_NAME)],
+            ClipboardContent::Files(_) => vec![ClipboardFormat::new(
+                FILE_GROUP_DESCRIPTOR_ID,
+                FILE_GROUP_DESCRIPTOR_NAME,
+            )],
+        }
+    }
+
+    /// Data of one of its formats
+    fn to_clipboard(&self) -> Result<Vec<u8>> {
+        match self {
+            ClipboardContent::Text(text) => Ok(text_to_clipboard(text)),
+            ClipboardContent::Html(html) => Ok(html_to_clipboard(html)),
+            ClipboardContent::Bitmap(bmp) => bmp_to_dib(bmp),
+            ClipboardContent::Png(png) => Ok(png.clone()),
+            ClipboardContent::Files(files) => Ok(write_file_group_descriptor(files)),
+        }
+    }
+}
+
+/// Event of the clipboard channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardEvent {
+    /// The server clipboard changed, with the kinds it holds
+    Changed(Vec<ClipboardKind>),
+    /// Content requested to the server
+    Content(ClipboardContent),
+    /// The server couldn't give the requested content
+    RequestFailed,
+    /// All the contents of a server file are given to the delegate,
+    /// with the index of the file
+    FileReceived(u32),
+    /// The download of a server file stopped
+    FileFailed(u32),
+}
+
+/// Local filesystem of the clipboard files
+/// Reads the files offered to the server and stores the ones downloaded from it
+pub trait ClipboardFileDelegate {
+    /// Size of a file offered to the server, by its index in the file list
+    fn size(&mut self, index: u32) -> Result<u64>;
+    /// Read a range of a file offered to the server
+    fn read(&mut self, index: u32, position: u64, length: u32) -> Result<Vec<u8>>;
+    /// Store a range of a file downloaded from the server
+    fn write(&mut self, file: &FileDescriptor, position: u64, data: &[u8]) -> Result<()>;
+}
+
+/// Download of a server file, one range at a time
+struct Download {
+    index: u32,
+    /// Bytes already stored
+    position: u64,
+    /// Asked first when the file list doesn't give it
+    size: Option<u64>,
+}
+
+impl Download {
+    fn request(&self, stream_id: u32) -> ClipboardPdu {
+        ClipboardPdu::FileContentsRequest(match self.size {
+            None => FileContentsRequest::size(stream_id, self.index),
+            Some(size) => FileContentsRequest::range(
+                stream_id,
+                self.index,
+                self.position,
+                (size - self.position).min(FILE_CHUNK_SIZE) as u32,
+            ),
+        })
+    }
+}
+
+/// Client of the clipboard static virtual channel
+/// Formats are negotiated for text, HTML and images,
+/// their data is converted from and to the local representation
+///
+/// PDUs are sent on the channel handle with ChannelFlagShowProtocol
+/// Files are only copied once a file delegate is set
+///
+/// # Example
+/// ```
+/// use rdp::core::cliprdr::client::{ClipboardClient, ClipboardContent};
+/// let mut client = ClipboardClient::new();
+/// let _format_list = client.set_content(vec![ClipboardContent::Text("hello".to_string())]);
+/// // Monitor ready sent by the server
+/// let mut data = bytes::BytesMut::from(&[1, 0, 0, 0, 0, 0, 0, 0][..]);
+/// let responses = client.process(&mut data, &mut |_| {}).unwrap();
+/// // Capabilities and format list
+/// assert_eq!(responses.len(), 2);
+/// ```
+#[derive(Default)]
+pub struct ClipboardClient {
+    /// Reads and stores the contents of the files
+    files: Option<Box<dyn ClipboardFileDelegate + Send>>,
+    /// Files offered by the server
+    remote_files: Vec<FileDescriptor>,
+    /// Downloads of server files by stream id
+    downloads: HashMap<u32, Download>,
+    next_stream_id: u32,
+    /// Content offered to the server
+    local: Vec<ClipboardContent>,
+    /// Formats offered by the server
+    remote: Vec<ClipboardFormat>,
+    /// Both sides announced long format names
+    long_names: bool,
+    /// Format of the pending data request
+    requested: Option<u32>,
+}
+
+impl ClipboardClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy files through the clipboard with the delegate
+    /// Must be set before the capabilities are sent
+    pub fn set_file_delegate(&mut self, files: Box<dyn ClipboardFileDelegate + Send>) {
+        self.files = Some(files);
+    }
+
+    /// Flags of the client general capability set
+    /// Files are streamed without their local path
+    pub fn capabilities(&self) -> ClipboardPdu {
+        let mut general_flags = GeneralFlag::CbUseLongFormatNames as u32;
+        if self.files.is_some() {
+            general_flags |= GeneralFlag::CbStreamFileclipEnabled as u32
+                | GeneralFlag::CbFileclipNoFilePaths as u32
+                | GeneralFlag::CbHugeFileSupportEnabled as u32;
+        }
+        ClipboardPdu::Capabilities(general_flags)
+    }
+
+    fn format_list(&self) -> ClipboardPdu {
+        ClipboardPdu::FormatList {
+            formats: self
+                .local
+                .iter()
+                .flat_map(ClipboardContent::formats)
+                .collect(),
+            long_names: self.long_names,
+        }
+    }
+
+    /// Offer new content to the server
+    /// The returned format list must be sent once the channel is ready
+    pub fn set_content(&mut self, content: Vec<ClipboardContent>) -> ClipboardPdu {
+        self.local = content;
+        self.format_list()
+    }
+
+    /// Kinds of content offered by the server
+    pub fn get_remote_kinds(&self) -> Vec<ClipboardKind> {
+        let mut kinds = Vec::new();
+        for kind in [
+            ClipboardKind::Text,
+            ClipboardKind::Html,
+            ClipboardKind::Image,
+            ClipboardKind::Files,
+        ] {
+            if self.remote_format(kind).is_some() {
+                kinds.push(kind);
+            }
+        }
+        kinds
+    }
+
+    /// Best format of the server for a kind of content
+    /// PNG and CF_DIBV5 keep the transparency of images
+    fn remote_format(&self, kind: ClipboardKind) -> Option<u32> {
+        let find = |id: u32, name: &str| {
+            self.remote
+                .iter()
+                .find(|format| {
+                    if name.is_empty() {
+                        format.id == id
+                    } else {
+                        format.name == name
+                    }
+                })
+                .map(|format| format.id)
+        };
+        match kind {
+            ClipboardKind::Text => find(CF_UNICODETEXT, "").or_else(|| find(CF_TEXT, "")),
+            ClipboardKind::Html => find(0, HTML_FORMAT_NAME),
+            ClipboardKind::Image => find(0, PNG_FORMAT_NAME)
+                .or_else(|| find(CF_DIBV5, ""))
+                .or_else(|| find(CF_DIB, "")),
+            ClipboardKind::Files => find(0, FILE_GROUP_DESCRIPTOR_NAME),
+        }
+    }
+
+    /// Ask the server for its content of a kind
+    /// None when the server doesn't offer it
+    pub fn request(&mut self, kind: ClipboardKind) -> Option<ClipboardPdu> {
+        let format_id = self.remote_format(kind)?;
+        self.requested = Some(format_id);
+        Some(ClipboardPdu::FormatDataRequest(format_id))
+    }
+
+    /// Convert the data of a server format
+    fn read_content(&self, format_id: u32, data: &[u8]) -> Result<ClipboardContent> {
+        let name = self
+            .remote
+            .iter()
+            .find(|format| format.id == format_id)
+            .map(|format| format.name.as_str());
+        Ok(match (format_id, name) {
+            (_, Some(HTML_FORMAT_NAME)) => ClipboardContent::Html(clipboard_to_html(data)?),
+            (_, Some(PNG_FORMAT_NAME)) => ClipboardContent::Png(data.to_vec()),
+            (_, Some(FILE_GROUP_DESCRIPTOR_NAME)) => {
+                ClipboardContent::Files(read_file_group_descriptor(data)?)
+            }
+            (CF_DIB, _) | (CF_DIBV5, _) => ClipboardContent::Bitmap(dib_to_bmp(data)?),
+            (CF_TEXT, _) => {
+                let end = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+                ClipboardContent::Text(String::from_utf8_lossy(&data[..end]).to_string())
+            }
+            _ => ClipboardContent::Text(clipboard_to_text(data)),
+        })
+    }
+
+    /// Data of a format offered by the client
+    fn write_content(&self, format_id: u32) -> Option<Vec<u8>> {
+        self.local
+            .iter()
+            .find(|content| {
+                content
+                    .formats()
+                    .iter()
+                    .any(|format| format.id == format_id)
+            })
+            .and_then(|content| content.to_clipboard().ok())
+    }
+
+    /// Start the download of a file of the last file list of the server
+    /// None without a file delegate or for directories
+    pub fn download(&mut self, index: u32) -> Option<ClipboardPdu> {
+        let file = self.remote_files.get(index as usize)?;
+        if self.files.is_none() || file.directory {
+            return None;
+        }
+        let download = Download {
+            index,
+            position: 0,
+            size: file.size,
+        };
+        let stream_id = self.next_stream_id;
+        self.next_stream_id = self.next_stream_id.wrapping_add(1);
+        let request = download.request(stream_id);
+        self.downloads.insert(stream_id, download);
+        Some(request)
+    }
+
+    /// Answer a file contents request with the delegate
+    fn read_file(&mut self, request: FileContentsRequest) -> ClipboardPdu {
+        let data = self.files.as_mut().and_then(|files| {
+            let data = if request.is_size() {
+                files
+                    .size(request.index)
+                    .map(|size| size.to_le_bytes().to_vec())
+            } else {
+                files.read(request.index, request.position, request.requested)
+            };
+            data.ok()
+        });
+        ClipboardPdu::FileContentsResponse {
+            stream_id: request.stream_id,
+            data,
+        }
+    }
+
+    /// Store a range of a download and ask the next one
+    /// None once the download is done or failed
+    fn write_file<T>(
+        &mut self,
+        stream_id: u32,
+        data: Option<Vec<u8>>,
+        callback: &mut T,
+    ) -> Option<ClipboardPdu>
+    where
+        T: FnMut(ClipboardEvent),
+    {
+        let mut download = self.downloads.remove(&stream_id)?;
+        let index = download.index;
+        // Empty files are still written once
+        let done = match (download.size, data, self.files.as_mut()) {
+            (None, Some(data), _) if data.len() >= 8 => {
+                download.size = Some(u64::from_le_bytes(data[..8].try_into().unwrap()));
+                Some(false)
+            }
+            (Some(size), Some(data), Some(files)) if !data.is_empty() || size == 0 => {
+                let file = &self.remote_files[index as usize];
+                let position = download.position;
+                download.position += data.len() as u64;
+                files
+                    .write(file, position, &data)
+                    .ok()
+                    .map(|_| download.position >= size)
+            }
+            _ => None,
+        };
+        match done {
+            None => {
+                callback(ClipboardEvent::FileFailed(index));
+                return None;
+            }
+            Some(true) => {
+                callback(ClipboardEvent::FileReceived(index));
+                return None;
+            }
+            Some(false) => (),
+        }
+        let request = download.request(stream_id);
+        self.downloads.insert(stream_id, download);
+        Some(request)
+    }
+
+    /// Process a message of the channel
+    /// and build the PDUs expected by the server
+    pub fn process<T>(
+        &mut self,
+        buffer: &mut BytesMut,
+        callback: &mut T,
+    ) -> Result<Vec<ClipboardPdu>>
+    where
+        T: FnMut(ClipboardEvent),
+    {
+        let mut responses = Vec::new();
+        while buffer.has_remaining() {
+            match read_clipboard_pdu(buffer, self.long_names)? {
+                ClipboardPdu::Capabilities(general_flags) => {
+                    self.long_names = general_flags & GeneralFlag::CbUseLongFormatNames as u32 != 0;
+                }
+                ClipboardPdu::MonitorReady => {
+                    responses.push(self.capabilities());
+                    responses.push(self.format_list());
+                }
+                ClipboardPdu::FormatList { formats, .. } => {
+                    self.remote = formats;
+                    self.requested = None;
+                    responses.push(ClipboardPdu::FormatListResponse(true));
+                    callback(ClipboardEvent::Changed(self.get_remote_kinds()));
+                }
+                ClipboardPdu::FormatDataRequest(format_id) => {
+                    responses.push(ClipboardPdu::FormatDataResponse(
+                        self.write_content(format_id),
+                    ));
+                }
+                ClipboardPdu::FormatDataResponse(data) => {
+                    let content = match (self.requested.take(), data) {
+                        (Some(format_id), Some(data)) => self.read_content(format_id, &data).ok(),
+                        _ => None,
+                    };
+                    if let Some(ClipboardContent::Files(files)) = &content {
+                        self.remote_files = files.clone();
+                        self.downloads.clear();
+                    }
+                    callback(match content {
+                        Some(content) => ClipboardEvent::Content(content),
+                        None => ClipboardEvent::RequestFailed,
+                    });
+                }
+                ClipboardPdu::FileContentsRequest(request) => {
+                    responses.push(self.read_file(request));
+                }
+                ClipboardPdu::FileContentsResponse { stream_id, data } => {
+                    responses.extend(self.write_file(stream_id, data, callback));
+                }
+                ClipboardPdu::FormatListResponse(_) => (),
+            }
+        }
+        Ok(responses)
+    }
+
+    /// Kinds of content offered by the client
+    pub fn get_local_kinds(&self) -> Vec<ClipboardKind> {
+        self.local.iter().map(ClipboardContent::kind).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Read the PDUs written by the server
+    async fn server_data(pdus: &[ClipboardPdu]) -> BytesMut {
+        let mut buffer = BytesMut::new();
+        for pdu in pdus {
+            buffer.extend_from_slice(&to_vec(pdu).await.unwrap());
+        }
+        buffer
+    }
+
+    /// The best image format of the server is requested and converted
+    #[tokio::test]
+    async fn test_request_image() {
+        let mut client = ClipboardClient::new();
+        let mut buffer = server_data(&[
+            ClipboardPdu::Capabilities(GeneralFlag::CbUseLongFormatNames as u32),
+            ClipboardPdu::FormatList {
+                formats: vec![
+                    ClipboardFormat::new(CF_DIB, ""),
+                    ClipboardFormat::new(0xC0AB, HTML_FORMAT_NAME),
+                ],
+                long_names: true,
+            },
+        ])
+        .await;
+        let mut events = Vec::new();
+        let responses = client
+            .process(&mut buffer, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!(responses, vec![ClipboardPdu::FormatListResponse(true)]);
+        assert_eq!(
+            events,
+            vec![ClipboardEvent::Changed(vec![
+                ClipboardKind::Html,
+                ClipboardKind::Image
+            ])]
+        );
+
+        assert_eq!(client.request(ClipboardKind::Text), None);
+        assert_eq!(
+            client.request(ClipboardKind::Image),
+            Some(ClipboardPdu::FormatDataRequest(CF_DIB))
+        );
+        let mut dib = vec![0; 44];
+        dib[0] = 40;
+        dib[14] = 32;
+        let mut buffer = server_data(&[ClipboardPdu::FormatDataResponse(Some(dib.clone()))]).await;
+        events.clear();
+        client
+            .process(&mut buffer, &mut |event| events.push(event))
+            .unwrap();
+        let bmp = dib_to_bmp(&dib).unwrap();
+        assert_eq!(
+            events,
+            vec![ClipboardEvent::Content(ClipboardContent::Bitmap(bmp))]
+        );
+    }
+
+    /// Files are kept in memory, the offered file is "abc"
+    #[derive(Default)]
+    struct MemoryFiles {
+        written: std::sync::Arc<std::sync::Mutex<Vec<u8>>>,
+    }
+
+    impl ClipboardFileDelegate for MemoryFiles {
+        fn size(&mut self, _index: u32) -> Result<u64> {
+            Ok(3)
+        }
+
+        fn read(&mut self, _index: u32, position: u64, length: u32) -> Result<Vec<u8>> {
+            let end = (position + length as u64).min(3) as usize;
+            Ok(b"abc"[position as usize..end].to_vec())
+        }
+
+        fn write(&mut self, _file: &FileDescriptor, position: u64, data: &[u8]) -> Result<()> {
+            let mut written = self.written.lock().unwrap();
+            assert_eq!(written.len() as u64, position);
+            written.extend_from_slice(data);
+            Ok(())
+        }
+    }
+
+    /// A server file without size is downloaded after a size request
+    #[tokio::test]
+    async fn test_download_file() {
+        let files = MemoryFiles::default();
+        let written = files.written.clone();
+        let mut client = ClipboardClient::new();
+        client.set_file_delegate(Box::new(files));
+        client.remote = vec![ClipboardFormat::new(0xC0F0, FILE_GROUP_DESCRIPTOR_NAME)];
+        assert_eq!(
+            client.request(ClipboardKind::Files),
+            Some(ClipboardPdu::FormatDataRequest(0xC0F0))
+        );
+        let mut file = FileDescriptor::new("a.txt", 0);
+        file.size = None;
+        let list = write_file_group_descriptor(&[file]);
+        let mut buffer = server_data(&[ClipboardPdu::FormatDataResponse(Some(list))]).await;
+        client.process(&mut buffer, &mut |_| {}).unwrap();
+
+        assert_eq!(
+            client.download(0),
+            Some(ClipboardPdu::FileContentsRequest(
+                FileContentsRequest::size(0, 0)
+            ))
+        );
+        let mut events = Vec::new();
+        let mut buffer = server_data(&[ClipboardPdu::FileContentsResponse {
+            stream_id: 0,
+            data: Some(5u64.to_le_bytes().to_vec()),
+        }])
+        .await;
+        let responses = client
+            .process(&mut buffer, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!(
+            responses,
+            vec![ClipboardPdu::FileContentsRequest(
+                FileContentsRequest::range(0, 0, 0, 5)
+            )]
+        );
+        let mut buffer = server_data(&[
+            ClipboardPdu::FileContentsResponse {
+                stream_id: 0,
+                data: Some(b"hel".to_vec()),
+            },
+            ClipboardPdu::FileContentsResponse {
+                stream_id: 0,
+                data: Some(b"lo".to_vec()),
+            },
+        ])
+        .await;
+        let responses = client
+            .process(&mut buffer, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!(
+            responses,
+            vec![ClipboardPdu::FileContentsRequest(
+                FileContentsRequest::range(0, 0, 3, 2)
+            )]
+        );
+        assert_eq!(events, vec![ClipboardEvent::FileReceived(0)]);
+        assert_eq!(&written.lock().unwrap()[..], b"hello");
+    }
+
+    /// Size and range requests of the server are read by the delegate
+    #[tokio::test]
+    async fn test_file_contents_request() {
+        let mut client = ClipboardClient::new();
+        client.set_file_delegate(Box::new(MemoryFiles::default()));
+        let mut buffer = server_data(&[
+            ClipboardPdu::FileContentsRequest(FileContentsRequest::size(7, 0)),
+            ClipboardPdu::FileContentsRequest(FileContentsRequest::range(8, 0, 1, 10)),
+        ])
+        .await;
+        let responses = client.process(&mut buffer, &mut |_| {}).unwrap();
+        assert_eq!(
+            responses,
+            vec![
+                ClipboardPdu::FileContentsResponse {
+                    stream_id: 7,
+                    data: Some(3u64.to_le_bytes().to_vec()),
+                },
+                ClipboardPdu::FileContentsResponse {
+                    stream_id: 8,
+                    data: Some(b"bc".to_vec()),
+                },
+            ]
+        );
+    }
+
+    /// Requests of the server get the data of the local content
+    #[tokio::test]
+    async fn test_format_data_request() {
+        let mut client = ClipboardClient::new();
+        client.set_content(vec![
+            ClipboardContent::Text("hi".to_string()),
+            ClipboardContent::Html("<b>hi</b>".to_string()),
+        ]);
+        let mut buffer = server_data(&[
+            ClipboardPdu::FormatDataRequest(CF_UNICODETEXT),
+            ClipboardPdu::FormatDataRequest(HTML_FORMAT_ID),
+            ClipboardPdu::FormatDataRequest(CF_DIB),
+        ])
+        .await;
+        let responses = client.process(&mut buffer, &mut |_| {}).unwrap();
+        assert_eq!(
+            responses,
+            vec![
+                ClipboardPdu::FormatDataResponse(Some(vec![b'h', 0, b'i', 0, 0, 0])),
+                ClipboardPdu::FormatDataResponse(Some(html_to_clipboard("<b>hi</b>"))),
+                ClipboardPdu::FormatDataResponse(None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use crate::model::unicode::{from_unicode, Unicode};
+
+use std::io::{Error, ErrorKind, Result};
+
+/// Size of the BITMAPFILEHEADER before a device independent bitmap
+const BITMAP_FILE_HEADER_SIZE: usize = 14;
+/// Smallest BITMAPINFOHEADER
+const BITMAP_INFO_HEADER_SIZE: usize = 40;
+/// Color masks follow a BITMAPINFOHEADER with this compression
+const BI_BITFIELDS: u32 = 3;
+
+/// Comments around the fragment of the HTML clipboard format
+const START_FRAGMENT: &str = "<html>\r\n<body>\r\n<!--StartFragment-->";
+const END_FRAGMENT: &str = "<!--EndFragment-->\r\n</body>\r\n</html>";
+
+/// Size of a FILEDESCRIPTORW and of its file name
+/// MS-RDPECLIP 2.2.5.2.3.1 File Descriptor (CLIPRDR_FILEDESCRIPTOR)
+const FILE_DESCRIPTOR_SIZE: usize = 592;
+const FILE_NAME_SIZE: usize = 520;
+
+/// Fields of a file descriptor which are set
+/// MS-RDPECLIP 2.2.5.2.3.1 File Descriptor (CLIPRDR_FILEDESCRIPTOR)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FileDescriptorFlag {
+    FdAttributes = 0x00000004,
+    FdWritestime = 0x00000020,
+    FdFilesize = 0x00000040,
+    FdShowprogressui = 0x00004000,
+}
+
+/// Attributes of a file descriptor
+const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x00000010;
+const FILE_ATTRIBUTE_NORMAL: u32 = 0x00000080;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Build a BMP file from a CF_DIB or CF_DIBV5 clipboard data
+/// The file header gives the offset of the pixels,
+/// after the info header, the color masks and the palette
+///
+/// # Example
+/// ```
+/// use rdp::core::cliprdr::format::{bmp_to_dib, dib_to_bmp};
+/// let mut dib = vec![0; 44];
+/// dib[0] = 40; // header size
+/// dib[14] = 32; // bits per pixel
+/// let bmp = dib_to_bmp(&dib).unwrap();
+/// assert_eq!(&bmp[..2], b"BM");
+/// assert_eq!(bmp_to_dib(&bmp).unwrap(), dib);
+/// ```
+pub fn dib_to_bmp(dib: &[u8]) -> Result<Vec<u8>> {
+    if dib.len() < BITMAP_INFO_HEADER_SIZE {
+        return Err(invalid("CLIPRDR: truncated bitmap header"));
+    }
+    let header_size = read_u32(dib, 0) as usize;
+    let bit_count = u16::from_le_bytes([dib[14], dib[15]]);
+    let compression = read_u32(dib, 16);
+    let colors_used = read_u32(dib, 32) as usize;
+
+    let masks_size = if header_size == BITMAP_INFO_HEADER_SIZE && compression == BI_BITFIELDS {
+        12
+    } else {
+        0
+    };
+    let palette_size = match colors_used {
+        0 if bit_count <= 8 => (1 << bit_count) * 4,
+        colors_used => colors_used * 4,
+    };
+    let offset = BITMAP_FILE_HEADER_SIZE + header_size + masks_size + palette_size;
+    if header_size < BITMAP_INFO_HEADER_SIZE || offset > BITMAP_FILE_HEADER_SIZE + dib.len() {
+        return Err(invalid("CLIPRDR: invalid bitmap header"));
+    }
+
+    let mut bmp = Vec::with_capacity(BITMAP_FILE_HEADER_SIZE + dib.len());
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&((BITMAP_FILE_HEADER_SIZE + dib.len()) as u32).to_le_bytes());
+    bmp.extend_from_slice(&[0; 4]);
+    bmp.extend_from_slice(&(offset as u32).to_le_bytes());
+    bmp.extend_from_slice(dib);
+    Ok(bmp)
+}
+
+/// Remove the file header of a BMP file
+/// The rest is the CF_DIB or CF_DIBV5 data, depending on its header size
+pub fn bmp_to_dib(bmp: &[u8]) -> Result<Vec<u8>> {
+    if bmp.len() < BITMAP_FILE_HEADER_SIZE + BITMAP_INFO_HEADER_SIZE || &bmp[..2] != b"BM" {
+        return Err(invalid("CLIPRDR: invalid BMP file"));
+    }
+    Ok(bmp[BITMAP_FILE_HEADER_SIZE..].to_vec())
+}
+
+/// Size of the info header of a CF_DIB or CF_DIBV5 data
+pub fn dib_header_size(dib: &[u8]) -> Option<usize> {
+    (dib.len() >= 4).then(|| read_u32(dib, 0) as usize)
+}
+
+/// Wrap an HTML fragment in the HTML clipboard format
+/// The header gives the byte offsets of the document and the fragment
+///
+/// # Example
+/// ```
+/// use rdp::core::cliprdr::format::{clipboard_to_html, html_to_clipboard};
+/// let data = html_to_clipboard("<b>bold</b>");
+/// assert_eq!(clipboard_to_html(&data).unwrap(), "<b>bold</b>");
+/// ```
+pub fn html_to_clipboard(fragment: &str) -> Vec<u8> {
+    let header = |start_html: usize, end_html: usize, start: usize, end: usize| {
+        format!(
+            "Version:0.9\r\nStartHTML:{:010}\r\nEndHTML:{:010}\r\nStartFragment:{:010}\r\nEndFragment:{:010}\r\n",
+            start_html, end_html, start, end
+        )
+    };
+    let start_html = header(0, 0, 0, 0).len();
+    let start = start_html + START_FRAGMENT.len();
+    let end = start + fragment.len();
+    let end_html = end + END_FRAGMENT.len();
+
+    let mut data = header(start_html, end_html, start, end).into_bytes();
+    data.extend_from_slice(START_FRAGMENT.as_bytes());
+    data.extend_from_slice(fragment.as_bytes());
+    data.extend_from_slice(END_FRAGMENT.as_bytes());
+    // The clipboard data is null terminated
+    data.push(0);
+    data
+}
+
+/// Offset of a field of the HTML clipboard format header
+fn read_html_offset(header: &str, field: &str) -> Option<usize> {
+    header
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Extract the fragment of the HTML clipboard format
+/// The whole document is returned when the fragment isn't marked
+pub fn clipboard_to_html(data: &[u8]) -> Result<String> {
+    let end = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+    let data = &data[..end];
+    let header = String::from_utf8_lossy(&data[..data.len().min(256)]);
+    let (start, end) = match (
+        read_html_offset(&header, "StartFragment"),
+        read_html_offset(&header, "EndFragment"),
+    ) {
+        (Some(start), Some(end)) => (start, end),
+        _ => (
+            read_html_offset(&header, "StartHTML")
+                .ok_or_else(|| invalid("CLIPRDR: invalid HTML header"))?,
+            read_html_offset(&header, "EndHTML").unwrap_or(data.len()),
+        ),
+    };
+    if start > end || end > data.len() {
+        return Err(invalid("CLIPRDR: invalid HTML offsets"));
+    }
+    Ok(String::from_utf8_lossy(&data[start..end]).to_string())
+}
+
+/// Null terminated UTF-16 text of the CF_UNICODETEXT format
+pub fn text_to_clipboard(text: &str) -> Vec<u8> {
+    let mut data = text.to_unicode();
+    data.extend_from_slice(&[0, 0]);
+    data
+}
+
+/// Text of the CF_UNICODETEXT format
+pub fn clipboard_to_text(data: &[u8]) -> String {
+    from_unicode(data)
+}
+
+/// File of a clipboard file list
+/// Files in directories have a relative path with backslashes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDescriptor {
+    pub name: String,
+    /// None when the size is not given
+    pub size: Option<u64>,
+    pub directory: bool,
+    /// Last write time as a FILETIME, if given
+    pub last_write_time: Option<u64>,
+}
+
+impl FileDescriptor {
+    pub fn new(name: &str, size: u64) -> Self {
+        FileDescriptor {
+            name: name.to_string(),
+            size: Some(size),
+            directory: false,
+            last_write_time: None,
+        }
+    }
+}
+
+/// Read the file list of the FileGroupDescriptorW format
+/// MS-RDPECLIP 2.2.5.2.3 Packed File List (CLIPRDR_FILELIST)
+pub fn read_file_group_descriptor(data: &[u8]) -> Result<Vec<FileDescriptor>> {
+    if data.len() < 4 {
+        return Err(invalid("CLIPRDR: truncated file list"));
+    }
+    let count = read_u32(data, 0) as usize;
+    let descriptors = &data[4..];
+    if descriptors.len() / FILE_DESCRIPTOR_SIZE < count {
+        return Err(invalid("CLIPRDR: truncated file list"));
+    }
+    Ok(descriptors
+        .chunks_exact(FILE_DESCRIPTOR_SIZE)
+        .take(count)
+        .map(|descriptor| {
+            let flags = read_u32(descriptor, 0);
+            let attributes = read_u32(descriptor, 36);
+            let last_write_time =
+                read_u32(descriptor, 56) as u64 | ((read_u32(descriptor, 60) as u64) << 32);
+            let size = ((read_u32(descriptor, 64) as u64) << 32) | read_u32(descriptor, 68) as u64;
+            FileDescriptor {
+                name: from_unicode(&descriptor[72..]),
+                size: (flags & FileDescriptorFlag::FdFilesize as u32 != 0).then_some(size),
+                directory: flags & FileDescriptorFlag::FdAttributes as u32 != 0
+                    && attributes & FILE_ATTRIBUTE_DIRECTORY != 0,
+                last_write_time: (flags & FileDescriptorFlag::FdWritestime as u32 != 0)
+                    .then_some(last_write_time),
+            }
+        })
+        .collect())
+}
+
+/// Write a file list in the FileGroupDescriptorW format
+/// Names longer than the descriptor field are truncated
+pub fn write_file_group_descriptor(files: &[FileDescriptor]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(4 + files.len() * FILE_DESCRIPTOR_SIZE);
+    data.extend_from_slice(&(files.len() as u32).to_le_bytes());
+    for file in files {
+        let mut flags =
+            FileDescriptorFlag::FdAttributes as u32 | FileDescriptorFlag::FdShowprogressui as u32;
+        if file.size.is_some() {
+            flags |= FileDescriptorFlag::FdFilesize as u32;
+        }
+        if file.last_write_time.is_some() {
+            flags |= FileDescriptorFlag::FdWritestime as u32;
+        }
+        let attributes = if file.directory {
+            FILE_ATTRIBUTE_DIRECTORY
+        } else {
+            FILE_ATTRIBUTE_NORMAL
+        };
+        let last_write_time = file.last_write_time.unwrap_or(0);
+        let size = file.size.unwrap_or(0);
+
+        data.extend_from_slice(&flags.to_le_bytes());
+        // Class id, size and position of the icon
+        data.extend_from_slice(&[0; 32]);
+        data.extend_from_slice(&attributes.to_le_bytes());
+        // Creation and last access times
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&last_write_time.to_le_bytes());
+        data.extend_from_slice(&((size >> 32) as u32).to_le_bytes());
+        data.extend_from_slice(&(size as u32).to_le_bytes());
+        let mut name = file.name.to_unicode();
+        name.resize(FILE_NAME_SIZE - 2, 0);
+        name.resize(FILE_NAME_SIZE, 0);
+        data.extend_from_slice(&name);
+    }
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The offset of the pixels counts the color masks and the palette
+    #[test]
+    fn test_dib_to_bmp() {
+        let mut dib = vec![0; 40 + 12 + 4];
+        dib[0] = 40;
+        dib[14] = 32;
+        dib[16] = BI_BITFIELDS as u8;
+        let bmp = dib_to_bmp(&dib).unwrap();
+        assert_eq!(read_u32(&bmp, 2), 14 + 56);
+        assert_eq!(read_u32(&bmp, 10), 14 + 40 + 12);
+
+        // 8 bpp with a full palette
+        let mut dib = vec![0; 40 + 1024 + 4];
+        dib[0] = 40;
+        dib[14] = 8;
+        assert_eq!(read_u32(&dib_to_bmp(&dib).unwrap(), 10), 14 + 40 + 1024);
+        dib.truncate(40 + 1000);
+        assert!(dib_to_bmp(&dib).is_err());
+    }
+
+    /// Offsets of the header match the fragment and the document
+    #[test]
+    fn test_html_to_clipboard() {
+        let data = html_to_clipboard("<p>été</p>");
+        let header = String::from_utf8_lossy(&data);
+        let start_html = read_html_offset(&header, "StartHTML").unwrap();
+        let end_html = read_html_offset(&header, "EndHTML").unwrap();
+        assert!(data[start_html..].starts_with(b"<html>"));
+        assert_eq!(end_html, data.len() - 1);
+        assert_eq!(clipboard_to_html(&data).unwrap(), "<p>été</p>");
+
+        let data = b"Version:0.9\r\nStartHTML:0000000300\r\n<b>x</b>\0";
+        assert!(clipboard_to_html(data).is_err());
+    }
+
+    /// File lists keep the names, sizes and directories
+    #[test]
+    fn test_file_group_descriptor() {
+        let files = vec![
+            FileDescriptor {
+                name: "docs".to_string(),
+                size: None,
+                directory: true,
+                last_write_time: Some(0x01D0_0000_0000_0000),
+            },
+            FileDescriptor::new("docs\\big.bin", 5 << 32),
+        ];
+        let data = write_file_group_descriptor(&files);
+        assert_eq!(data.len(), 4 + 2 * FILE_DESCRIPTOR_SIZE);
+        assert_eq!(read_file_group_descriptor(&data).unwrap(), files);
+        assert!(read_file_group_descriptor(&data[..600]).is_err());
+    }
+}
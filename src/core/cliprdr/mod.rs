@@ -0,0 +1,3 @@
+pub mod base;
+pub mod client;
+pub mod format;
\ No newline at end of file
@@ -0,0 +1,59 @@
+//! Transports of the runtimes based on the futures-io traits,
+//! like async-std or smol
+//!
+//! The layers are written with the tokio traits, a futures-io
+//! stream is wrapped by `compat` and given to `connect_with`
+//! The timers are given to the builder since the tokio ones
+//! only run in a tokio runtime, and the sessions are read with
+//! `read` and `write_input` since `split` and `run` spawn tokio tasks
+//!
+//! # Example
+//! ```rust, ignore
+//! use rdp::core::compat::FuturesAsyncReadCompatExt;
+//!
+//! struct AsyncIoTimer;
+//!
+//! impl Timer for AsyncIoTimer {
+//!     fn sleep(&self, duration: Duration) -> Sleep {
+//!         Box::pin(async move {
+//!             async_io::Timer::after(duration).await;
+//!         })
+//!     }
+//! }
+//!
+//! let stream = async_std::net::TcpStream::connect("192.168.0.1:3389").await?;
+//! let mut client = RdpClient::builder()
+//!     .credentials("domain", "username", "password")
+//!     .timer(Arc::new(AsyncIoTimer))
+//!     .connect_with(stream.compat())
+//!     .await?;
+//! ```
+pub use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::client::RdpClientBuilder;
+    use crate::core::config::Timeouts;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    /// The connection runs over a futures-io stream
+    #[tokio::test]
+    async fn test_connect_futures_io() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        // Same stream as the ones of async-std or smol
+        let stream = TokioAsyncReadCompatExt::compat(client);
+        let connection = RdpClientBuilder::new()
+            .timeouts(Timeouts {
+                negotiation: Some(Duration::from_millis(10)),
+                ..Default::default()
+            })
+            .connect_with(FuturesAsyncReadCompatExt::compat(stream));
+        assert!(connection.await.is_err());
+
+        // TPKT version of the connection request
+        assert_eq!(server.read_u8().await.unwrap(), 3);
+    }
+}
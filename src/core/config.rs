@@ -0,0 +1,226 @@
+use crate::core::capability::CapabilitiesConfig;
+use crate::core::gcc::KeyboardLayout;
+use crate::core::keyboard::KeyboardHook;
+use crate::core::preconnection::PreconnectionPdu;
+use crate::core::sec::base::PerformanceFlags;
+use crate::core::x224::base::Protocols;
+
+use std::time::Duration;
+
+/// All the settings of a connection
+///
+/// With the serde feature profiles can be loaded from
+/// TOML or JSON, missing fields take their default value
+///
+/// # Example
+/// ```
+/// use rdp::core::config::ConnectionConfig;
+/// use rdp::core::x224::base::Protocols;
+/// let config = ConnectionConfig {
+///     target: Some("192.168.0.1".to_string()),
+///     username: "user".to_string(),
+///     desktop_width: 1920,
+///     desktop_height: 1080,
+///     ..Default::default()
+/// };
+/// assert_eq!(config.security_protocols(), Protocols::ProtocolRDP as u32);
+/// assert_eq!(config.capabilities().desktop_width, 1920);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct ConnectionConfig {
+    /// Host name or address with an optional port
+    pub target: Option<String>,
+    pub domain: String,
+    pub username: String,
+    pub password: String,
+    /// Set auto logon flags during security logon
+    pub auto_logon: bool,
+    /// Client name exposed to the server
+    pub client_name: String,
+    pub desktop_width: u16,
+    pub desktop_height: u16,
+    /// Preferred bits per pixel of bitmap updates
+    pub color_depth: u16,
+    pub keyboard_layout: KeyboardLayout,
+    /// Side which handles the Windows key and Alt+Tab shortcuts
+    pub keyboard_hook: KeyboardHook,
+    /// Security protocols requested during negotiation
+    pub security: Vec<Protocols>,
+    /// Check the certificate of TLS servers
+    pub check_certificate: bool,
+    /// RD gateway to go through, not supported by the connection
+    pub gateway: Option<String>,
+    /// Sent before the connection request, for the brokers
+    /// which route the connections
+    pub preconnection: Option<PreconnectionPdu>,
+    /// Display the session in full screen
+    /// Left to the application
+    pub fullscreen: bool,
+    /// Visual effects of the remote session
+    pub performance_flags: PerformanceFlags,
+    /// Static virtual channels to join
+    pub channels: Vec<String>,
+    /// Time limits of the connection phases
+    pub timeouts: Timeouts,
+    /// Options of the TCP connection
+    pub tcp: TcpOptions,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        let capabilities = CapabilitiesConfig::default();
+        ConnectionConfig {
+            target: None,
+            domain: String::new(),
+            username: String::new(),
+            password: String::new(),
+            auto_logon: false,
+            client_name: "rdp-rs".to_string(),
+            desktop_width: capabilities.desktop_width,
+            desktop_height: capabilities.desktop_height,
+            color_depth: capabilities.color_depth,
+            keyboard_layout: capabilities.keyboard_layout,
+            keyboard_hook: KeyboardHook::default(),
+            security: vec![Protocols::ProtocolRDP],
+            check_certificate: false,
+            gateway: None,
+            preconnection: None,
+            fullscreen: false,
+            performance_flags: PerformanceFlags::default(),
+            channels: Vec::new(),
+            timeouts: Timeouts::default(),
+            tcp: TcpOptions::default(),
+        }
+    }
+}
+
+impl ConnectionConfig {
+    /// Security protocols as expected by the negotiation request
+    pub fn security_protocols(&self) -> u32 {
+        self.security
+            .iter()
+            .fold(0, |protocols, protocol| protocols | *protocol as u32)
+    }
+
+    /// Capabilities sent to the server
+    /// Settings not covered by the config keep their default value
+    pub fn capabilities(&self) -> CapabilitiesConfig {
+        CapabilitiesConfig {
+            desktop_width: self.desktop_width,
+            desktop_height: self.desktop_height,
+            color_depth: self.color_depth,
+            keyboard_layout: self.keyboard_layout,
+            ..Default::default()
+        }
+    }
+}
+
+/// Time limits of a connection
+/// A phase without limit can wait forever for the server
+///
+/// # Example
+/// ```
+/// use rdp::core::config::Timeouts;
+/// use std::time::Duration;
+/// let timeouts = Timeouts {
+///     tcp_connect: Some(Duration::from_secs(5)),
+///     total: Some(Duration::from_secs(30)),
+///     ..Default::default()
+/// };
+/// assert_eq!(timeouts.idle_read, None);
+/// ```
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Timeouts {
+    /// Opening of the TCP connection
+    pub tcp_connect: Option<Duration>,
+    /// Negotiation of the security protocol
+    pub negotiation: Option<Duration>,
+    /// TLS handshake, once the server selected SSL or Hybrid
+    pub tls_handshake: Option<Duration>,
+    /// CredSSP exchange, once the server selected Hybrid
+    pub nla: Option<Duration>,
+    /// Basic settings exchange and channel joins
+    pub channel_connection: Option<Duration>,
+    /// Client info and licensing
+    pub logon: Option<Duration>,
+    /// Capabilities exchange and connection finalization
+    pub capabilities: Option<Duration>,
+    /// Deadline of the whole connection sequence
+    pub total: Option<Duration>,
+    /// Longest time without any data from the server
+    /// once the session is active
+    pub idle_read: Option<Duration>,
+}
+
+/// Options of the TCP connections opened by the builder
+/// Transports given to connect_with are used as they are
+///
+/// # Example
+/// ```
+/// use rdp::core::config::TcpOptions;
+/// use std::time::Duration;
+/// let options = TcpOptions {
+///     keepalive: Some(Duration::from_secs(60)),
+///     keepalive_interval: Some(Duration::from_secs(10)),
+///     ..Default::default()
+/// };
+/// assert!(options.nodelay);
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct TcpOptions {
+    /// Disable the Nagle algorithm, so the input PDUs
+    /// are sent at once, true by default
+    pub nodelay: bool,
+    /// Idle time before the first keepalive probe
+    /// No probe is sent when not set
+    pub keepalive: Option<Duration>,
+    /// Time between two keepalive probes, system default when not set
+    pub keepalive_interval: Option<Duration>,
+    /// Size of the send buffer, system default when not set
+    pub send_buffer_size: Option<u32>,
+    /// Size of the receive buffer, system default when not set
+    /// Set before the connection so the TCP window can use it
+    pub recv_buffer_size: Option<u32>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        TcpOptions {
+            nodelay: true,
+            keepalive: None,
+            keepalive_interval: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Requested protocols are combined as flags
+    #[test]
+    fn test_security_protocols() {
+        let config = ConnectionConfig {
+            security: vec![Protocols::ProtocolSSL, Protocols::ProtocolHybrid],
+            ..Default::default()
+        };
+        assert_eq!(config.security_protocols(), 0x03);
+    }
+}
@@ -0,0 +1,400 @@
+use crate::model::base64::base64_encode;
+
+use std::io::{Error, ErrorKind, Result};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::net::TcpStream;
+
+/// Version of the SOCKS protocol
+/// RFC 1928 3. Procedure for TCP-based clients
+const SOCKS_VERSION: u8 = 0x05;
+/// Version of the username and password negotiation
+/// RFC 1929 2. Initial negotiation
+const SOCKS_AUTH_VERSION: u8 = 0x01;
+const SOCKS_CMD_CONNECT: u8 = 0x01;
+/// Reply of a successful request
+const SOCKS_SUCCEEDED: u8 = 0x00;
+
+/// Authentication methods of the SOCKS handshake
+/// RFC 1928 3. Procedure for TCP-based clients
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SocksMethod {
+    NoAuthenticationRequired = 0x00,
+    UsernamePassword = 0x02,
+    NoAcceptableMethods = 0xFF,
+}
+
+/// Type of the address of a SOCKS request
+/// RFC 1928 5. Addressing
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SocksAddressType {
+    Ipv4 = 0x01,
+    DomainName = 0x03,
+    Ipv6 = 0x04,
+}
+
+/// Largest response header of an HTTP proxy
+const HTTP_HEADER_MAX_SIZE: usize = 0x4000;
+
+/// Credentials of a proxy
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProxyCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Proxy between the client and the RDP server
+/// The name of the target is resolved by the proxy
+///
+/// # Example
+/// ```rust, ignore
+/// let proxy = ProxyConfig::Socks5 {
+///     address: "proxy.example.com:1080".to_string(),
+///     credentials: Some(ProxyCredentials {
+///         username: "user".to_string(),
+///         password: "password".to_string(),
+///     }),
+/// };
+/// let stream = proxy.connect("10.0.0.1:3389").await?;
+/// let client = RdpClient::builder().connect_with(stream).await?;
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProxyConfig {
+    /// RFC 1928, with the username and password authentication of RFC 1929
+    Socks5 {
+        /// `host:port` of the proxy
+        address: String,
+        credentials: Option<ProxyCredentials>,
+    },
+    /// Tunnel opened with the CONNECT method, with a Basic authentication
+    /// RFC 7231 4.3.6 CONNECT
+    HttpConnect {
+        /// `host:port` of the proxy
+        address: String,
+        credentials: Option<ProxyCredentials>,
+    },
+}
+
+impl ProxyConfig {
+    /// Address of the proxy
+    pub fn address(&self) -> &str {
+        match self {
+            ProxyConfig::Socks5 { address, .. } | ProxyConfig::HttpConnect { address, .. } => {
+                address
+            }
+        }
+    }
+
+    /// Open a TCP connection to the proxy,
+    /// then a tunnel to the `host:port` target
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn connect(&self, target: &str) -> Result<TcpStream> {
+        let (host, port) = split_host_port(target)?;
+        let mut stream = TcpStream::connect(self.address()).await?;
+        stream.set_nodelay(true)?;
+        self.handshake(&mut stream, host, port).await?;
+        Ok(stream)
+    }
+
+    /// Open the tunnel over a connection to the proxy
+    /// Once done the stream carries the data of the target
+    pub async fn handshake<S>(&self, stream: &mut S, host: &str, port: u16) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        match self {
+            ProxyConfig::Socks5 { credentials, .. } => {
+                socks5_handshake(stream, host, port, credentials.as_ref()).await
+            }
+            ProxyConfig::HttpConnect { credentials, .. } => {
+                http_connect_handshake(stream, host, port, credentials.as_ref()).await
+            }
+        }
+    }
+}
+
+/// Split a `host:port` target, the port is mandatory
+/// Brackets of IPv6 addresses are removed
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+fn split_host_port(target: &str) -> Result<(&str, u16)> {
+    target
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+        .map(|(host, port)| (host.trim_start_matches('[').trim_end_matches(']'), port))
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("PROXY: no port in target {}", target),
+            )
+        })
+}
+
+/// Negotiate the method, authenticate and ask a connection to the target
+/// RFC 1928 SOCKS Protocol Version 5
+pub async fn socks5_handshake<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    credentials: Option<&ProxyCredentials>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let method = match credentials {
+        Some(_) => SocksMethod::UsernamePassword,
+        None => SocksMethod::NoAuthenticationRequired,
+    };
+    stream.write_all(&[SOCKS_VERSION, 1, method as u8]).await?;
+    let mut answer = [0u8; 2];
+    stream.read_exact(&mut answer).await?;
+    if answer[0] != SOCKS_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("PROXY: unexpected SOCKS version {}", answer[0]),
+        ));
+    }
+    if answer[1] != method as u8 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "PROXY: no acceptable SOCKS authentication method",
+        ));
+    }
+
+    // RFC 1929 Username/Password Authentication for SOCKS V5
+    if let Some(credentials) = credentials {
+        let (username, password) = (
+            credentials.username.as_bytes(),
+            credentials.password.as_bytes(),
+        );
+        if username.len() > 255 || password.len() > 255 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "PROXY: SOCKS credentials are too long",
+            ));
+        }
+        let mut request = vec![SOCKS_AUTH_VERSION, username.len() as u8];
+        request.extend_from_slice(username);
+        request.push(password.len() as u8);
+        request.extend_from_slice(password);
+        stream.write_all(&request).await?;
+        stream.read_exact(&mut answer).await?;
+        if answer[1] != SOCKS_SUCCEEDED {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "PROXY: SOCKS credentials refused",
+            ));
+        }
+    }
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CMD_CONNECT, 0];
+    if let Ok(ip) = host.parse::<Ipv4Addr>() {
+        request.push(SocksAddressType::Ipv4 as u8);
+        request.extend_from_slice(&ip.octets());
+    } else if let Ok(ip) = host.parse::<Ipv6Addr>() {
+        request.push(SocksAddressType::Ipv6 as u8);
+        request.extend_from_slice(&ip.octets());
+    } else {
+        if host.len() > 255 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "PROXY: SOCKS host name is too long",
+            ));
+        }
+        request.push(SocksAddressType::DomainName as u8);
+        request.push(host.len() as u8);
+        request.extend_from_slice(host.as_bytes());
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != SOCKS_SUCCEEDED {
+        return Err(Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("PROXY: SOCKS request failed with reply {}", reply[1]),
+        ));
+    }
+    // Skip the bound address and port
+    let size = match reply[3] {
+        atyp if atyp == SocksAddressType::Ipv4 as u8 => 4,
+        atyp if atyp == SocksAddressType::Ipv6 as u8 => 16,
+        atyp if atyp == SocksAddressType::DomainName as u8 => stream.read_u8().await? as usize,
+        atyp => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("PROXY: unexpected SOCKS address type {}", atyp),
+            ))
+        }
+    };
+    let mut bound = vec![0u8; size + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// Ask the proxy a tunnel with the CONNECT method
+/// The response is read byte by byte to leave the data of the target
+pub async fn http_connect_handshake<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    credentials: Option<&ProxyCredentials>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let authority = match host.contains(':') {
+        true => format!("[{}]:{}", host, port),
+        false => format!("{}:{}", host, port),
+    };
+    let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", authority, authority);
+    if let Some(credentials) = credentials {
+        let token = format!("{}:{}", credentials.username, credentials.password);
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64_encode(token.as_bytes())
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > HTTP_HEADER_MAX_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "PROXY: HTTP response header is too long",
+            ));
+        }
+        response.push(stream.read_u8().await?);
+    }
+    let status = String::from_utf8_lossy(&response)
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(()),
+        Some(407) => Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "PROXY: HTTP proxy authentication required",
+        )),
+        Some(status) => Err(Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("PROXY: HTTP proxy answered with status {}", status),
+        )),
+        None => Err(Error::new(
+            ErrorKind::InvalidData,
+            "PROXY: invalid HTTP status line",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn credentials() -> ProxyCredentials {
+        ProxyCredentials {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        }
+    }
+
+    /// Test of the split of the targets
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("server:3389").unwrap(), ("server", 3389));
+        assert_eq!(split_host_port("[::1]:3390").unwrap(), ("::1", 3390));
+        assert!(split_host_port("server").is_err());
+    }
+
+    /// Authenticated SOCKS handshake to a host name
+    #[tokio::test]
+    async fn test_socks5_handshake() {
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 2]);
+            proxy.write_all(&[5, 2]).await.unwrap();
+
+            let mut auth = [0u8; 11];
+            proxy.read_exact(&mut auth).await.unwrap();
+            assert_eq!(auth, *b"\x01\x04user\x04pass");
+            proxy.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0u8; 13];
+            proxy.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, *b"\x05\x01\x00\x03\x06server\x0d\x3d");
+            proxy
+                .write_all(&[5, 0, 0, 1, 10, 0, 0, 1, 0x0d, 0x3d, 3])
+                .await
+                .unwrap();
+        });
+
+        socks5_handshake(&mut client, "server", 3389, Some(&credentials()))
+            .await
+            .unwrap();
+        server.await.unwrap();
+        // The data of the target follows the reply
+        assert_eq!(client.read_u8().await.unwrap(), 3);
+    }
+
+    /// A refused SOCKS request fails the connection
+    #[tokio::test]
+    async fn test_socks5_refused() {
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.unwrap();
+            proxy.write_all(&[5, 0]).await.unwrap();
+            let mut request = [0u8; 10];
+            proxy.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[3], SocksAddressType::Ipv4 as u8);
+            // Connection refused
+            proxy.write_all(&[5, 5, 0, 1]).await.unwrap();
+            proxy
+        });
+
+        let result = socks5_handshake(&mut client, "10.0.0.1", 3389, None).await;
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(ErrorKind::ConnectionRefused)
+        );
+    }
+
+    /// CONNECT request with a Basic authentication
+    #[tokio::test]
+    async fn test_http_connect_handshake() {
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        let server = tokio::spawn(async move {
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(proxy.read_u8().await.unwrap());
+            }
+            assert_eq!(
+                String::from_utf8(request).unwrap(),
+                "CONNECT server:3389 HTTP/1.1\r\n\
+                 Host: server:3389\r\n\
+                 Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+            );
+            proxy
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n\x03")
+                .await
+                .unwrap();
+        });
+
+        ProxyConfig::HttpConnect {
+            address: "proxy:8080".to_string(),
+            credentials: Some(credentials()),
+        }
+        .handshake(&mut client, "server", 3389)
+        .await
+        .unwrap();
+        server.await.unwrap();
+        assert_eq!(client.read_u8().await.unwrap(), 3);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the static virtual channel which carries
+/// the dynamic virtual channels
+/// MS-RDPEDYC 2.1 Transport
+pub const DRDYNVC_CHANNEL_NAME: &str = "drdynvc";
+
+/// Command of a dynamic virtual channel PDU
+/// MS-RDPEDYC 2.2 Message Syntax
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum DynvcCommand {
+    Create = 0x01,
+    DataFirst = 0x02,
+    Data = 0x03,
+    Close = 0x04,
+    Capability = 0x05,
+    DataFirstCompressed = 0x06,
+    DataCompressed = 0x07,
+    SoftSyncRequest = 0x08,
+    SoftSyncResponse = 0x09,
+}
+
+/// PDU of the drdynvc static channel, sent by both sides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DynvcPdu {
+    /// Version of the server with its priority charges from version 2,
+    /// or the version of the client without them
+    /// MS-RDPEDYC 2.2.1.1 DVC Capabilities Request PDU
+    /// MS-RDPEDYC 2.2.1.2 DVC Capabilities Response PDU
+    Capabilities {
+        version: u16,
+        priority_charges: Option<[u16; 4]>,
+    },
+    /// MS-RDPEDYC 2.2.2.1 DVC Create Request PDU (DYNVC_CREATE_REQ)
+    CreateRequest {
+        priority: u8,
+        channel_id: u32,
+        channel_name: String,
+    },
+    /// HRESULT of the creation, negative on failure
+    /// MS-RDPEDYC 2.2.2.2 DVC Create Response PDU (DYNVC_CREATE_RSP)
+    CreateResponse { channel_id: u32, status: u32 },
+    /// First block of a message longer than a PDU
+    /// MS-RDPEDYC 2.2.3.1 DVC Data First PDU (DYNVC_DATA_FIRST)
+    DataFirst {
+        channel_id: u32,
+        length: u32,
+        data: Vec<u8>,
+    },
+    /// Whole message or its next block
+    /// MS-RDPEDYC 2.2.3.2 DVC Data PDU (DYNVC_DATA)
+    Data { channel_id: u32, data: Vec<u8> },
+    /// MS-RDPEDYC 2.2.4 Closing a DVC (DYNVC_CLOSE)
+    Close(u32),
+}
+
+/// Size code of the channel id and length fields
+/// 0 for one byte, 1 for two bytes and 2 for four bytes
+fn size_code(value: u32) -> u8 {
+    if value <= 0xFF {
+        0
+    } else if value <= 0xFFFF {
+        1
+    } else {
+        2
+    }
+}
+
+fn size_of_code(code: u8) -> usize {
+    match code {
+        0 => 1,
+        1 => 2,
+        _ => 4,
+    }
+}
+
+fn read_variable(buffer: &mut BytesMut, code: u8, context: &str) -> Result<u32> {
+    check_remaining(buffer, size_of_code(code), context)?;
+    Ok(match code {
+        0 => buffer.get_u8() as u32,
+        1 => buffer.get_u16_le() as u32,
+        _ => buffer.get_u32_le(),
+    })
+}
+
+async fn write_variable(
+    writer: &mut (impl AsyncWrite + Unpin + Send),
+    code: u8,
+    value: u32,
+) -> Result<()> {
+    match code {
+        0 => writer.write_u8(value as u8).await,
+        1 => writer.write_u16_le(value as u16).await,
+        _ => writer.write_u32_le(value).await,
+    }
+}
+
+impl DynvcPdu {
+    fn command(&self) -> DynvcCommand {
+        match self {
+            DynvcPdu::Capabilities { .. } => DynvcCommand::Capability,
+            DynvcPdu::CreateRequest { .. } | DynvcPdu::CreateResponse { .. } => {
+                DynvcCommand::Create
+            }
+            DynvcPdu::DataFirst { .. } => DynvcCommand::DataFirst,
+            DynvcPdu::Data { .. } => DynvcCommand::Data,
+            DynvcPdu::Close(_) => DynvcCommand::Close,
+        }
+    }
+
+    fn channel_id(&self) -> u32 {
+        match self {
+            DynvcPdu::Capabilities { .. } => 0,
+            DynvcPdu::CreateRequest { channel_id, .. }
+            | DynvcPdu::CreateResponse { channel_id, .. }
+            | DynvcPdu::DataFirst { channel_id, .. }
+            | DynvcPdu::Data { channel_id, .. }
+            | DynvcPdu::Close(channel_id) => *channel_id,
+        }
+    }
+
+    /// Middle bits of the header, the priority of a create request
+    /// or the size code of the length of a data first PDU
+    fn sp(&self) -> u8 {
+        match self {
+            DynvcPdu::CreateRequest { priority, .. } => *priority,
+            DynvcPdu::DataFirst { length, .. } => size_code(*length),
+            _ => 0,
+        }
+    }
+
+    fn data_length(&self) -> usize {
+        match self {
+            DynvcPdu::Capabilities {
+                priority_charges, ..
+            } => 3 + priority_charges.map_or(0, |_| 8),
+            DynvcPdu::CreateRequest { channel_name, .. } => channel_name.len() + 1,
+            DynvcPdu::CreateResponse { .. } => 4,
+            DynvcPdu::DataFirst { length, data, .. } => {
+                size_of_code(size_code(*length)) + data.len()
+            }
+            DynvcPdu::Data { data, .. } => data.len(),
+            DynvcPdu::Close(_) => 0,
+        }
+    }
+}
+
+/// Overhead of a data PDU, its header and the channel id
+pub fn data_header_length(channel_id: u32) -> usize {
+    1 + size_of_code(size_code(channel_id))
+}
+
+/// Read a PDU sent by the server, a whole message of the drdynvc channel
+///
+/// # Example
+/// ```
+/// use bytes::BytesMut;
+/// use rdp::core::drdynvc::base::{read_dynvc_pdu, DynvcPdu};
+/// let mut data = BytesMut::from(&[0x10, 0x03, b'e', b'c', b'h', b'o', 0][..]);
+/// assert_eq!(
+///     read_dynvc_pdu(&mut data).unwrap(),
+///     DynvcPdu::CreateRequest {
+///         priority: 0,
+///         channel_id: 3,
+///         channel_name: "echo".to_string(),
+///     }
+/// );
+/// ```
+pub fn read_dynvc_pdu(buffer: &mut BytesMut) -> Result<DynvcPdu> {
+    check_remaining(buffer, 1, "DRDYNVC: header")?;
+    let header = buffer.get_u8();
+    let (command, sp, cb_id) = (header >> 4, (header >> 2) & 0x03, header & 0x03);
+    let pdu = match DynvcCommand::try_from(command) {
+        Ok(DynvcCommand::Capability) => {
+            check_remaining(buffer, 3, "DRDYNVC: capabilities")?;
+            buffer.advance(1);
+            let version = buffer.get_u16_le();
+            let priority_charges = if version >= 2 {
+                check_remaining(buffer, 8, "DRDYNVC: priority charges")?;
+                Some([
+                    buffer.get_u16_le(),
+                    buffer.get_u16_le(),
+                    buffer.get_u16_le(),
+                    buffer.get_u16_le(),
+                ])
+            } else {
+                None
+            };
+            DynvcPdu::Capabilities {
+                version,
+                priority_charges,
+            }
+        }
+        Ok(DynvcCommand::Create) => {
+            let channel_id = read_variable(buffer, cb_id, "DRDYNVC: channel id")?;
+            let end = buffer.iter().position(|c| *c == 0).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "DRDYNVC: channel name without terminator",
+                )
+            })?;
+            let channel_name = String::from_utf8_lossy(&buffer.split_to(end)).to_string();
+            DynvcPdu::CreateRequest {
+                priority: sp,
+                channel_id,
+                channel_name,
+            }
+        }
+        Ok(DynvcCommand::DataFirst) => DynvcPdu::DataFirst {
+            channel_id: read_variable(buffer, cb_id, "DRDYNVC: channel id")?,
+            length: read_variable(buffer, sp, "DRDYNVC: length")?,
+            data: buffer.to_vec(),
+        },
+        Ok(DynvcCommand::Data) => DynvcPdu::Data {
+            channel_id: read_variable(buffer, cb_id, "DRDYNVC: channel id")?,
+            data: buffer.to_vec(),
+        },
+        Ok(DynvcCommand::Close) => {
+            DynvcPdu::Close(read_variable(buffer, cb_id, "DRDYNVC: channel id")?)
+        }
+        // Only sent to clients of version 3
+        Ok(command) => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("DRDYNVC: unexpected command {:?}", command),
+            ))
+        }
+        Err(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("DRDYNVC: unknown command {}", command),
+            ))
+        }
+    };
+    buffer.clear();
+    Ok(pdu)
+}
+
+#[async_trait]
+impl Message for DynvcPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        let cb_id = size_code(self.channel_id());
+        writer
+            .write_u8(((self.command() as u8) << 4) | ((self.sp() & 0x03) << 2) | cb_id)
+            .await?;
+        match self {
+            DynvcPdu::Capabilities {
+                version,
+                priority_charges,
+            } => {
+                writer.write_u8(0).await?;
+                writer.write_u16_le(*version).await?;
+                for charge in priority_charges.iter().flatten() {
+                    writer.write_u16_le(*charge).await?;
+                }
+            }
+            DynvcPdu::CreateRequest {
+                channel_id,
+                channel_name,
+                ..
+            } => {
+                write_variable(writer, cb_id, *channel_id).await?;
+                writer.write_all(channel_name.as_bytes()).await?;
+                writer.write_u8(0).await?;
+            }
+            DynvcPdu::CreateResponse { channel_id, status } => {
+                write_variable(writer, cb_id, *channel_id).await?;
+                writer.write_u32_le(*status).await?;
+            }
+            DynvcPdu::DataFirst {
+                channel_id,
+                length,
+                data,
+            } => {
+                write_variable(writer, cb_id, *channel_id).await?;
+                write_variable(writer, size_code(*length), *length).await?;
+                writer.write_all(data).await?;
+            }
+            DynvcPdu::Data { channel_id, data } => {
+                write_variable(writer, cb_id, *channel_id).await?;
+                writer.write_all(data).await?;
+            }
+            DynvcPdu::Close(channel_id) => write_variable(writer, cb_id, *channel_id).await?,
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        *self = read_dynvc_pdu(&mut BytesMut::from(&data[..]))?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        match self {
+            // Without a channel id
+            DynvcPdu::Capabilities { .. } => 1 + self.data_length(),
+            _ => data_header_length(self.channel_id()) + self.data_length(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// PDUs of the server are written and read back
+    #[tokio::test]
+    async fn test_dynvc_pdu() {
+        for pdu in [
+            DynvcPdu::Capabilities {
+                version: 3,
+                priority_charges: Some([936, 3276, 9362, 21845]),
+            },
+            DynvcPdu::CreateRequest {
+                priority: 0,
+                channel_id: 0x1234,
+                channel_name: "Microsoft::Windows::RDS::Graphics".to_string(),
+            },
+            DynvcPdu::DataFirst {
+                channel_id: 7,
+                length: 0x10000,
+                data: vec![1, 2, 3],
+            },
+            DynvcPdu::Data {
+                channel_id: 0x12345678,
+                data: vec![4, 5],
+            },
+            DynvcPdu::Close(7),
+        ] {
+            let data = to_vec(&pdu).await.unwrap();
+            assert_eq!(data.len(), pdu.length());
+            assert_eq!(read_dynvc_pdu(&mut BytesMut::from(&data[..])).unwrap(), pdu);
+        }
+    }
+
+    /// The capabilities response of the client has no priority charges
+    #[tokio::test]
+    async fn test_capabilities_response() {
+        let pdu = DynvcPdu::Capabilities {
+            version: 2,
+            priority_charges: None,
+        };
+        assert_eq!(to_vec(&pdu).await.unwrap(), [0x50, 0x00, 0x02, 0x00]);
+        let pdu = DynvcPdu::CreateResponse {
+            channel_id: 3,
+            status: 0,
+        };
+        assert_eq!(to_vec(&pdu).await.unwrap(), [0x10, 0x03, 0, 0, 0, 0]);
+    }
+}
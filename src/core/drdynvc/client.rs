@@ -0,0 +1,475 @@
+use crate::core::channel::CHANNEL_CHUNK_LENGTH;
+use crate::core::drdynvc::base::{data_header_length, read_dynvc_pdu, DynvcPdu};
+use crate::core::event::RdpEvent;
+use crate::model::data::{to_vec, Message};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+/// Version of the client, the compression
+/// and the soft sync of version 3 are not supported
+/// MS-RDPEDYC 2.2.1.2 DVC Capabilities Response PDU
+pub const DYNVC_VERSION: u16 = 2;
+
+/// HRESULT of a create request without a handler for the channel
+const E_FAIL: u32 = 0x80004005;
+
+/// Client of one or several dynamic virtual channels
+///
+/// Messages are given whole to the handler, and the messages
+/// it returns are sent on the same channel
+#[async_trait]
+pub trait DynamicChannelHandler: Send {
+    /// Names of the channels the handler accepts
+    fn channel_names(&self) -> Vec<&'static str>;
+
+    /// Messages sent first once the server created a channel
+    async fn open(&mut self, _channel_name: &str, _channel_id: u32) -> Result<Vec<Vec<u8>>> {
+        Ok(Vec::new())
+    }
+
+    /// Process a message of a channel and build the responses
+    async fn process(
+        &mut self,
+        channel_name: &str,
+        channel_id: u32,
+        buffer: &mut BytesMut,
+        callback: &mut (dyn FnMut(RdpEvent) + Send),
+    ) -> Result<Vec<Vec<u8>>>;
+
+    /// Messages which are not responses, like captured audio
+    async fn poll(&mut self, _channel_name: &str, _channel_id: u32) -> Result<Vec<Vec<u8>>> {
+        Ok(Vec::new())
+    }
+
+    /// The server closed a channel
+    fn close(&mut self, _channel_id: u32) {}
+
+    /// Concrete client, see `DynamicChannelClient::handler_mut`
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Write the PDUs of a handler, one message each
+pub(crate) async fn to_messages(pdus: Vec<impl Message>) -> Result<Vec<Vec<u8>>> {
+    let mut messages = Vec::with_capacity(pdus.len());
+    for pdu in pdus {
+        messages.push(to_vec(&pdu).await?);
+    }
+    Ok(messages)
+}
+
+/// Channel created by the server
+struct DynamicChannel {
+    name: String,
+    /// Index of the handler in the registered ones
+    handler: usize,
+    /// Blocks of the current message
+    data: BytesMut,
+    /// Length of the current message announced by a data first PDU
+    length: Option<usize>,
+}
+
+/// Manager of the dynamic virtual channels, on the drdynvc static channel
+///
+/// The server creates the channels by name, a create request is accepted
+/// when a registered handler serves the name. The blocks of each message
+/// are reassembled for the handler, and its messages are split in blocks
+///
+/// # Example
+/// ```rust, ignore
+/// let mut dynamic = DynamicChannelClient::new();
+/// dynamic.register(Box::new(GraphicsClient::new()));
+/// let mut channel = client.open_static_channel(DRDYNVC_CHANNEL_NAME)?;
+/// while let Some(message) = channel.read().await {
+///     let mut message = BytesMut::from(&message[..]);
+///     for pdu in dynamic.process(&mut message, &mut |event| events.push(event)).await? {
+///         channel.write(to_vec(&pdu).await?).await?;
+///     }
+/// }
+/// ```
+#[derive(Default)]
+pub struct DynamicChannelClient {
+    handlers: Vec<Box<dyn DynamicChannelHandler>>,
+    /// Open channels by id
+    channels: HashMap<u32, DynamicChannel>,
+    /// Version chosen with the server
+    version: Option<u16>,
+}
+
+impl DynamicChannelClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept the channels of a handler
+    /// A later handler of the same name is never used
+    pub fn register(&mut self, handler: Box<dyn DynamicChannelHandler>) {
+        self.handlers.push(handler);
+    }
+
+    /// First registered handler of a type, to call
+    /// the methods of the concrete client
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let client = dynamic.handler_mut::<RdpeiClient>().unwrap();
+    /// if let Some(touch) = client.touch(contacts) {
+    ///     let channel_id = dynamic.channel_id(RDPEI_CHANNEL_NAME).unwrap();
+    ///     for pdu in dynamic.write(channel_id, to_vec(&touch).await?)? {
+    ///         channel.write(to_vec(&pdu).await?).await?;
+    ///     }
+    /// }
+    /// ```
+    pub fn handler_mut<H: DynamicChannelHandler + 'static>(&mut self) -> Option<&mut H> {
+        self.handlers
+            .iter_mut()
+            .find_map(|handler| handler.as_any_mut().downcast_mut::<H>())
+    }
+
+    /// Version chosen with the server, None
+    /// until the capabilities are exchanged
+    pub fn get_version(&self) -> Option<u16> {
+        self.version
+    }
+
+    /// Id of the first open channel of a name
+    pub fn channel_id(&self, channel_name: &str) -> Option<u32> {
+        self.channels
+            .iter()
+            .find(|(_, channel)| channel.name == channel_name)
+            .map(|(channel_id, _)| *channel_id)
+    }
+
+    /// Split a message in blocks, each one fits in a single chunk
+    /// of the static channel
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::drdynvc::base::DynvcPdu;
+    /// use rdp::core::drdynvc::client::DynamicChannelClient;
+    /// let pdus = DynamicChannelClient::split(3, vec![0; 2000]);
+    /// assert_eq!(pdus.len(), 2);
+    /// assert!(matches!(pdus[0], DynvcPdu::DataFirst { length: 2000, .. }));
+    /// ```
+    pub fn split(channel_id: u32, message: Vec<u8>) -> Vec<DynvcPdu> {
+        let block_length = CHANNEL_CHUNK_LENGTH - data_header_length(channel_id);
+        if message.len() <= block_length {
+            return vec![DynvcPdu::Data {
+                channel_id,
+                data: message,
+            }];
+        }
+        // The length of the data first PDU takes at most 4 bytes
+        let mut blocks = message.chunks(block_length - 4);
+        let mut pdus = vec![DynvcPdu::DataFirst {
+            channel_id,
+            length: message.len() as u32,
+            data: blocks.next().unwrap_or_default().to_vec(),
+        }];
+        pdus.extend(blocks.map(|block| DynvcPdu::Data {
+            channel_id,
+            data: block.to_vec(),
+        }));
+        pdus
+    }
+
+    /// Send a message on an open channel
+    pub fn write(&self, channel_id: u32, message: Vec<u8>) -> Result<Vec<DynvcPdu>> {
+        if !self.channels.contains_key(&channel_id) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("DRDYNVC: channel {} is not open", channel_id),
+            ));
+        }
+        Ok(Self::split(channel_id, message))
+    }
+
+    /// Messages of the handlers which are not responses
+    /// Called regularly, like for the captured audio
+    pub async fn poll(&mut self) -> Result<Vec<DynvcPdu>> {
+        let mut pdus = Vec::new();
+        for (channel_id, channel) in &self.channels {
+            let handler = &mut self.handlers[channel.handler];
+            for message in handler.poll(&channel.name, *channel_id).await? {
+                pdus.extend(Self::split(*channel_id, message));
+            }
+        }
+        Ok(pdus)
+    }
+
+    /// Process a message of the drdynvc channel
+    /// and build the PDUs expected by the server
+    pub async fn process<T>(
+        &mut self,
+        buffer: &mut BytesMut,
+        callback: &mut T,
+    ) -> Result<Vec<DynvcPdu>>
+    where
+        T: FnMut(RdpEvent) + Send,
+    {
+        let mut responses = Vec::new();
+        match read_dynvc_pdu(buffer)? {
+            DynvcPdu::Capabilities { version, .. } => {
+                let version = version.min(DYNVC_VERSION);
+                self.version = Some(version);
+                responses.push(DynvcPdu::Capabilities {
+                    version,
+                    priority_charges: None,
+                });
+            }
+            DynvcPdu::CreateRequest {
+                channel_id,
+                channel_name,
+                ..
+            } => {
+                let handler = self
+                    .handlers
+                    .iter()
+                    .position(|handler| handler.channel_names().contains(&&channel_name[..]));
+                let handler = match handler {
+                    Some(handler) => handler,
+                    None => {
+                        responses.push(DynvcPdu::CreateResponse {
+                            channel_id,
+                            status: E_FAIL,
+                        });
+                        return Ok(responses);
+                    }
+                };
+                responses.push(DynvcPdu::CreateResponse {
+                    channel_id,
+                    status: 0,
+                });
+                let messages = self.handlers[handler]
+                    .open(&channel_name, channel_id)
+                    .await?;
+                for message in messages {
+                    responses.extend(Self::split(channel_id, message));
+                }
+                self.channels.insert(
+                    channel_id,
+                    DynamicChannel {
+                        name: channel_name,
+                        handler,
+                        data: BytesMut::new(),
+                        length: None,
+                    },
+                );
+            }
+            DynvcPdu::DataFirst {
+                channel_id,
+                length,
+                data,
+            } => {
+                let channel = self.channel(channel_id)?;
+                channel.data.clear();
+                channel.data.extend_from_slice(&data);
+                channel.length = Some(length as usize);
+                responses.extend(self.dispatch(channel_id, callback).await?);
+            }
+            DynvcPdu::Data { channel_id, data } => {
+                let channel = self.channel(channel_id)?;
+                // A data PDU alone is a whole message
+                if channel.length.is_none() {
+                    channel.data.clear();
+                    channel.length = Some(data.len());
+                }
+                channel.data.extend_from_slice(&data);
+                responses.extend(self.dispatch(channel_id, callback).await?);
+            }
+            DynvcPdu::Close(channel_id) => {
+                if let Some(channel) = self.channels.remove(&channel_id) {
+                    self.handlers[channel.handler].close(channel_id);
+                    responses.push(DynvcPdu::Close(channel_id));
+                }
+            }
+            DynvcPdu::CreateResponse { .. } => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "DRDYNVC: unexpected create response",
+                ))
+            }
+        }
+        Ok(responses)
+    }
+
+    fn channel(&mut self, channel_id: u32) -> Result<&mut DynamicChannel> {
+        self.channels.get_mut(&channel_id).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("DRDYNVC: data on the unknown channel {}", channel_id),
+            )
+        })
+    }
+
+    /// Give the message to the handler once all its blocks are read
+    async fn dispatch<T>(&mut self, channel_id: u32, callback: &mut T) -> Result<Vec<DynvcPdu>>
+    where
+        T: FnMut(RdpEvent) + Send,
+    {
+        let channel = self.channel(channel_id)?;
+        let length = channel.length.unwrap_or_default();
+        if channel.data.len() > length {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "DRDYNVC: blocks longer than the message",
+            ));
+        }
+        if channel.data.len() < length {
+            return Ok(Vec::new());
+        }
+
+        channel.length = None;
+        let mut message = channel.data.split();
+        let (name, handler) = (channel.name.clone(), channel.handler);
+        let messages = self.handlers[handler]
+            .process(&name, channel_id, &mut message, callback)
+            .await?;
+        Ok(messages
+            .into_iter()
+            .flat_map(|message| Self::split(channel_id, message))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::egfx::base::GRAPHICS_CHANNEL_NAME;
+    use crate::core::egfx::client::GraphicsClient;
+
+    /// Answers each message with its length
+    struct Counter;
+
+    #[async_trait]
+    impl DynamicChannelHandler for Counter {
+        fn channel_names(&self) -> Vec<&'static str> {
+            vec!["counter"]
+        }
+
+        async fn open(&mut self, _: &str, _: u32) -> Result<Vec<Vec<u8>>> {
+            Ok(vec![b"hello".to_vec()])
+        }
+
+        async fn process(
+            &mut self,
+            _: &str,
+            _: u32,
+            buffer: &mut BytesMut,
+            _: &mut (dyn FnMut(RdpEvent) + Send),
+        ) -> Result<Vec<Vec<u8>>> {
+            Ok(vec![(buffer.len() as u32).to_le_bytes().to_vec()])
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    async fn process(client: &mut DynamicChannelClient, pdu: DynvcPdu) -> Vec<DynvcPdu> {
+        let data = to_vec(&pdu).await.unwrap();
+        client
+            .process(&mut BytesMut::from(&data[..]), &mut |_| {})
+            .await
+            .unwrap()
+    }
+
+    /// Channels of registered names are opened, the others refused
+    #[tokio::test]
+    async fn test_create_request() {
+        let mut client = DynamicChannelClient::new();
+        client.register(Box::new(Counter));
+        let create = |channel_id, name: &str| DynvcPdu::CreateRequest {
+            priority: 0,
+            channel_id,
+            channel_name: name.to_string(),
+        };
+
+        assert_eq!(
+            process(&mut client, create(3, "counter")).await,
+            [
+                DynvcPdu::CreateResponse {
+                    channel_id: 3,
+                    status: 0
+                },
+                DynvcPdu::Data {
+                    channel_id: 3,
+                    data: b"hello".to_vec()
+                }
+            ]
+        );
+        assert_eq!(
+            process(&mut client, create(4, "unknown")).await,
+            [DynvcPdu::CreateResponse {
+                channel_id: 4,
+                status: E_FAIL
+            }]
+        );
+        assert_eq!(client.channel_id("counter"), Some(3));
+        assert!(client.handler_mut::<Counter>().is_some());
+
+        assert_eq!(
+            process(&mut client, DynvcPdu::Close(3)).await,
+            [DynvcPdu::Close(3)]
+        );
+        assert_eq!(client.channel_id("counter"), None);
+    }
+
+    /// The graphics pipeline advertises its capabilities once created
+    #[tokio::test]
+    async fn test_graphics_channel() {
+        let mut client = DynamicChannelClient::new();
+        client.register(Box::new(GraphicsClient::new()));
+        let responses = process(
+            &mut client,
+            DynvcPdu::CreateRequest {
+                priority: 0,
+                channel_id: 5,
+                channel_name: GRAPHICS_CHANNEL_NAME.to_string(),
+            },
+        )
+        .await;
+        let advertise = to_vec(&GraphicsClient::new().capabilities_advertise())
+            .await
+            .unwrap();
+        assert_eq!(
+            responses[1],
+            DynvcPdu::Data {
+                channel_id: 5,
+                data: advertise
+            }
+        );
+        assert!(client.handler_mut::<GraphicsClient>().is_some());
+    }
+
+    /// The blocks of a message are given at once to the handler
+    #[tokio::test]
+    async fn test_reassembly() {
+        let mut client = DynamicChannelClient::new();
+        client.register(Box::new(Counter));
+        process(
+            &mut client,
+            DynvcPdu::CreateRequest {
+                priority: 0,
+                channel_id: 3,
+                channel_name: "counter".to_string(),
+            },
+        )
+        .await;
+
+        let mut responses = Vec::new();
+        for pdu in DynamicChannelClient::split(3, vec![0; 4000]) {
+            responses.extend(process(&mut client, pdu).await);
+        }
+        assert_eq!(
+            responses,
+            [DynvcPdu::Data {
+                channel_id: 3,
+                data: 4000u32.to_le_bytes().to_vec()
+            }]
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use crate::core::drdynvc::client::DynamicChannelHandler;
+use crate::core::event::RdpEvent;
+use crate::core::metrics::Metrics;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::Result;
+use std::time::{Duration, Instant};
+
+/// Name of the dynamic virtual channel used to
+/// measure the round trip time
+/// MS-RDPEECO 2.1 Transport
+pub const ECHO_CHANNEL_NAME: &str = "Microsoft::Windows::RDS::Echo";
+
+/// Answer the echo requests of the server
+///
+/// Only the server sends requests, the client
+/// returns each payload unchanged as soon as possible
+/// MS-RDPEECO 2.2.1 Echo Request PDU (ECHO_REQUEST_PDU)
+/// MS-RDPEECO 2.2.2 Echo Response PDU (ECHO_RESPONSE_PDU)
+///
+/// # Example
+/// ```
+/// use bytes::BytesMut;
+/// use rdp::core::echo::EchoClient;
+/// let mut echo = EchoClient::default();
+/// let response = echo.process(&mut BytesMut::from(&b"ping"[..]));
+/// assert_eq!(response, b"ping");
+/// assert_eq!(echo.get_request_count(), 1);
+/// ```
+#[derive(Default)]
+pub struct EchoClient {
+    /// Requests answered since the channel was opened
+    requests: u64,
+    /// Reception time of the last request
+    last_request: Option<Instant>,
+}
+
+impl EchoClient {
+    /// Build the response of a request read on the channel
+    pub fn process(&mut self, buffer: &mut BytesMut) -> Vec<u8> {
+        self.requests += 1;
+        self.last_request = Some(Instant::now());
+        buffer.split().to_vec()
+    }
+
+    /// Requests answered since the channel was opened
+    pub fn get_request_count(&self) -> u64 {
+        self.requests
+    }
+
+    /// Reception time of the last request
+    pub fn get_last_request(&self) -> Option<Instant> {
+        self.last_request
+    }
+}
+
+/// Round trip times over the sample window, in milliseconds
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LatencyStats {
+    /// Last measure
+    pub last: u32,
+    pub min: u32,
+    pub max: u32,
+    pub average: u32,
+    /// Average difference between two consecutive measures
+    pub jitter: u32,
+}
+
+/// State of the link after a new measure
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LatencyStatus {
+    Good,
+    /// The average round trip time is above the threshold
+    Degraded(u32),
+}
+
+/// Sample the round trip time measured by the auto
+/// detection and flag the degraded links
+///
+/// The server measures the round trip time with the
+/// auto detect or the echo requests, the monitor only
+/// keeps the last measures of a sliding window
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use rdp::core::echo::{LatencyMonitor, LatencyStatus};
+/// let mut monitor = LatencyMonitor::new(Duration::from_secs(1), 4, 150);
+/// assert_eq!(monitor.record(100), LatencyStatus::Good);
+/// assert_eq!(monitor.record(300), LatencyStatus::Degraded(200));
+/// assert_eq!(monitor.stats().unwrap().jitter, 200);
+/// ```
+pub struct LatencyMonitor {
+    /// Time between two samples
+    period: Duration,
+    /// Number of samples kept
+    window: usize,
+    /// Average round trip time in milliseconds above which the link is degraded
+    threshold: u32,
+    /// Last samples, oldest first
+    samples: VecDeque<u32>,
+    /// Time of the last sample
+    last_sample: Option<Instant>,
+}
+
+impl LatencyMonitor {
+    pub fn new(period: Duration, window: usize, threshold: u32) -> Self {
+        LatencyMonitor {
+            period,
+            window: window.max(1),
+            threshold,
+            samples: VecDeque::new(),
+            last_sample: None,
+        }
+    }
+
+    /// Time between two samples
+    pub fn get_period(&self) -> Duration {
+        self.period
+    }
+
+    /// Add a measure in milliseconds
+    pub fn record(&mut self, rtt: u32) -> LatencyStatus {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt);
+        self.status()
+    }
+
+    /// Sample the round trip time of the connection
+    /// None before the end of the period or before the first measure
+    pub fn sample(&mut self, now: Instant, metrics: &Metrics) -> Option<LatencyStatus> {
+        if let Some(last_sample) = self.last_sample {
+            if now.saturating_duration_since(last_sample) < self.period {
+                return None;
+            }
+        }
+        let rtt = metrics.snapshot().rtt?;
+        self.last_sample = Some(now);
+        Some(self.record(rtt))
+    }
+
+    /// State of the link according to the current window
+    pub fn status(&self) -> LatencyStatus {
+        match self.stats() {
+            Some(stats) if stats.average > self.threshold => LatencyStatus::Degraded(stats.average),
+            _ => LatencyStatus::Good,
+        }
+    }
+
+    /// Statistics of the current window
+    /// None before the first measure
+    pub fn stats(&self) -> Option<LatencyStats> {
+        let last = *self.samples.back()?;
+        let count = self.samples.len() as u64;
+        let sum: u64 = self.samples.iter().map(|rtt| *rtt as u64).sum();
+        let deltas: u64 = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .map(|(previous, next)| previous.abs_diff(*next) as u64)
+            .sum();
+        Some(LatencyStats {
+            last,
+            min: *self.samples.iter().min()?,
+            max: *self.samples.iter().max()?,
+            average: (sum / count) as u32,
+            jitter: if count > 1 {
+                (deltas / (count - 1)) as u32
+            } else {
+                0
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl DynamicChannelHandler for EchoClient {
+    fn channel_names(&self) -> Vec<&'static str> {
+        vec![ECHO_CHANNEL_NAME]
+    }
+
+    async fn process(
+        &mut self,
+        _channel_name: &str,
+        _channel_id: u32,
+        buffer: &mut BytesMut,
+        _callback: &mut (dyn FnMut(RdpEvent) + Send),
+    ) -> Result<Vec<Vec<u8>>> {
+        Ok(vec![EchoClient::process(self, buffer)])
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test the payload is sent back unchanged
+    #[test]
+    fn test_echo_response() {
+        let mut echo = EchoClient::default();
+        let mut buffer = BytesMut::from(&[1u8, 2, 3, 4][..]);
+        assert_eq!(echo.process(&mut buffer), vec![1, 2, 3, 4]);
+        assert!(buffer.is_empty());
+        assert_eq!(echo.get_request_count(), 1);
+        assert!(echo.get_last_request().is_some());
+    }
+
+    /// Old samples leave the window
+    #[test]
+    fn test_latency_window() {
+        let mut monitor = LatencyMonitor::new(Duration::from_secs(1), 3, 100);
+        for rtt in [500, 20, 40, 30] {
+            monitor.record(rtt);
+        }
+        assert_eq!(
+            monitor.stats(),
+            Some(LatencyStats {
+                last: 30,
+                min: 20,
+                max: 40,
+                average: 30,
+                jitter: 15,
+            })
+        );
+        assert_eq!(monitor.status(), LatencyStatus::Good);
+    }
+
+    /// The metrics are only sampled once per period
+    #[test]
+    fn test_latency_sample() {
+        let metrics = Metrics::default();
+        let mut monitor = LatencyMonitor::new(Duration::from_secs(1), 4, 100);
+        let now = Instant::now();
+        assert_eq!(monitor.sample(now, &metrics), None);
+
+        metrics.set_rtt(250);
+        assert_eq!(
+            monitor.sample(now, &metrics),
+            Some(LatencyStatus::Degraded(250))
+        );
+        assert_eq!(
+            monitor.sample(now + Duration::from_millis(500), &metrics),
+            None
+        );
+
+        metrics.set_rtt(50);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            monitor.sample(later, &metrics),
+            Some(LatencyStatus::Degraded(150))
+        );
+        assert_eq!(monitor.stats().unwrap().min, 50);
+    }
+}
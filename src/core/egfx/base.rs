@@ -0,0 +1,731 @@
+use crate::codec::zgfx::Zgfx;
+use crate::core::gcc::Monitor;
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the dynamic virtual channel of the graphics pipeline
+pub const GRAPHICS_CHANNEL_NAME: &str = "Microsoft::Windows::RDS::Graphics";
+
+/// Size of the RDPGFX_HEADER
+const HEADER_SIZE: usize = 8;
+
+/// Most monitors of a reset graphics PDU
+/// MS-RDPEGFX 2.2.2.14 RDPGFX_RESET_GRAPHICS_PDU
+const MONITOR_MAX_COUNT: usize = 16;
+
+/// Segmented data descriptors
+/// MS-RDPEGFX 2.2.5.1 RDP_SEGMENTED_DATA
+const DEBLOCK_SINGLE: u8 = 0xE0;
+const DEBLOCK_MULTIPART: u8 = 0xE1;
+
+/// Command of a graphics PDU
+/// MS-RDPEGFX 2.2.1.5 RDPGFX_HEADER
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum GraphicsCommand {
+    RdpgfxCmdidWiretosurface1 = 0x0001,
+    RdpgfxCmdidWiretosurface2 = 0x0002,
+    RdpgfxCmdidDeleteencodingcontext = 0x0003,
+    RdpgfxCmdidSolidfill = 0x0004,
+    RdpgfxCmdidSurfacetosurface = 0x0005,
+    RdpgfxCmdidSurfacetocache = 0x0006,
+    RdpgfxCmdidCachetosurface = 0x0007,
+    RdpgfxCmdidEvictcacheentry = 0x0008,
+    RdpgfxCmdidCreatesurface = 0x0009,
+    RdpgfxCmdidDeletesurface = 0x000A,
+    RdpgfxCmdidStartframe = 0x000B,
+    RdpgfxCmdidEndframe = 0x000C,
+    RdpgfxCmdidFrameacknowledge = 0x000D,
+    RdpgfxCmdidResetgraphics = 0x000E,
+    RdpgfxCmdidMapsurfacetooutput = 0x000F,
+    RdpgfxCmdidCacheimportoffer = 0x0010,
+    RdpgfxCmdidCacheimportreply = 0x0011,
+    RdpgfxCmdidCapsadvertise = 0x0012,
+    RdpgfxCmdidCapsconfirm = 0x0013,
+    RdpgfxCmdidMapsurfacetowindow = 0x0015,
+    RdpgfxCmdidQoeframeacknowledge = 0x0016,
+    RdpgfxCmdidMapsurfacetoscaledoutput = 0x0017,
+    RdpgfxCmdidMapsurfacetoscaledwindow = 0x0018,
+}
+
+/// Codec of a wire to surface PDU
+/// MS-RDPEGFX 2.2.2.1 RDPGFX_WIRE_TO_SURFACE_PDU_1
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum GraphicsCodec {
+    RdpgfxCodecidUncompressed = 0x0000,
+    RdpgfxCodecidCavideo = 0x0003,
+    RdpgfxCodecidClearcodec = 0x0008,
+    RdpgfxCodecidCaprogressive = 0x0009,
+    RdpgfxCodecidPlanar = 0x000A,
+    RdpgfxCodecidAvc420 = 0x000B,
+    RdpgfxCodecidAlpha = 0x000C,
+    RdpgfxCodecidAvc444 = 0x000E,
+    RdpgfxCodecidAvc444v2 = 0x000F,
+}
+
+/// Pixel format of a surface
+/// MS-RDPEGFX 2.2.1.4 RDPGFX_PIXELFORMAT
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum PixelFormat {
+    GfxPixelFormatXrgb8888 = 0x20,
+    GfxPixelFormatArgb8888 = 0x21,
+}
+
+/// Versions of the capability sets
+/// MS-RDPEGFX 2.2.3 Capability Sets
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum CapabilityVersion {
+    RdpgfxCapversion8 = 0x00080004,
+    RdpgfxCapversion81 = 0x00080105,
+    RdpgfxCapversion10 = 0x000A0002,
+    RdpgfxCapversion101 = 0x000A0100,
+    RdpgfxCapversion102 = 0x000A0200,
+    RdpgfxCapversion103 = 0x000A0301,
+    RdpgfxCapversion104 = 0x000A0400,
+    RdpgfxCapversion105 = 0x000A0502,
+    RdpgfxCapversion106 = 0x000A0600,
+    RdpgfxCapversion107 = 0x000A0701,
+}
+
+/// Flags of the capability sets
+/// MS-RDPEGFX 2.2.3 Capability Sets
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CapabilityFlag {
+    RdpgfxCapsFlagThinclient = 0x01,
+    RdpgfxCapsFlagSmallcache = 0x02,
+    RdpgfxCapsFlagAvc420Enabled = 0x10,
+    RdpgfxCapsFlagAvcDisabled = 0x20,
+    RdpgfxCapsFlagAvcThinclient = 0x40,
+    RdpgfxCapsFlagScaledmapDisable = 0x80,
+}
+
+/// A capability set, only the flags are kept
+/// MS-RDPEGFX 2.2.1.6 RDPGFX_CAPSET
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CapabilitySet {
+    pub version: u32,
+    pub flags: u32,
+}
+
+impl CapabilitySet {
+    pub fn new(version: CapabilityVersion, flags: u32) -> Self {
+        CapabilitySet {
+            version: version as u32,
+            flags,
+        }
+    }
+
+    /// Version 10.1 has no flags
+    fn data_length(&self) -> usize {
+        if self.version == CapabilityVersion::RdpgfxCapversion101 as u32 {
+            16
+        } else {
+            4
+        }
+    }
+
+    /// Flags are the first field of the data
+    fn read_flags(version: u32, mut data: &[u8]) -> u32 {
+        if data.len() >= 4 && version != CapabilityVersion::RdpgfxCapversion101 as u32 {
+            data.get_u32_le()
+        } else {
+            0
+        }
+    }
+
+    fn read_from_buffer(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 8, "EGFX: capability set")?;
+        let version = buffer.get_u32_le();
+        let length = buffer.get_u32_le() as usize;
+        check_remaining(buffer, length, "EGFX: capability data")?;
+        let flags = CapabilitySet::read_flags(version, &buffer.split_to(length));
+        Ok(CapabilitySet { version, flags })
+    }
+}
+
+/// Rectangle of a surface, right and bottom are exclusive
+/// MS-RDPEGFX 2.2.1.2 RDPGFX_RECT16
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Rect16 {
+    pub left: u16,
+    pub top: u16,
+    pub right: u16,
+    pub bottom: u16,
+}
+
+impl Rect16 {
+    fn read_from_buffer(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 8, "EGFX: rectangle")?;
+        let rect = Rect16 {
+            left: buffer.get_u16_le(),
+            top: buffer.get_u16_le(),
+            right: buffer.get_u16_le(),
+            bottom: buffer.get_u16_le(),
+        };
+        if rect.left > rect.right || rect.top > rect.bottom {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "EGFX: invalid rectangle",
+            ));
+        }
+        Ok(rect)
+    }
+
+    pub fn width(&self) -> u16 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> u16 {
+        self.bottom - self.top
+    }
+}
+
+/// Read a count followed by the points
+/// MS-RDPEGFX 2.2.1.1 RDPGFX_POINT16
+fn read_points(buffer: &mut BytesMut) -> Result<Vec<(u16, u16)>> {
+    check_remaining(buffer, 2, "EGFX: point count")?;
+    let count = buffer.get_u16_le() as usize;
+    check_remaining(buffer, count * 4, "EGFX: points")?;
+    Ok((0..count)
+        .map(|_| (buffer.get_u16_le(), buffer.get_u16_le()))
+        .collect())
+}
+
+fn read_pixel_format(buffer: &mut BytesMut) -> Result<PixelFormat> {
+    check_remaining(buffer, 1, "EGFX: pixel format")?;
+    let format = buffer.get_u8();
+    PixelFormat::try_from(format).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("EGFX: invalid pixel format {}", format),
+        )
+    })
+}
+
+/// Read a length prefixed bitmap
+fn read_bitmap_data(buffer: &mut BytesMut) -> Result<Vec<u8>> {
+    check_remaining(buffer, 4, "EGFX: bitmap data length")?;
+    let length = buffer.get_u32_le() as usize;
+    check_remaining(buffer, length, "EGFX: bitmap data")?;
+    Ok(buffer.split_to(length).to_vec())
+}
+
+/// PDU sent by the server on the graphics channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphicsPdu {
+    /// MS-RDPEGFX 2.2.2.1 RDPGFX_WIRE_TO_SURFACE_PDU_1
+    WireToSurface1 {
+        surface_id: u16,
+        codec_id: u16,
+        pixel_format: PixelFormat,
+        dest: Rect16,
+        data: Vec<u8>,
+    },
+    /// Only used by the progressive codec
+    /// MS-RDPEGFX 2.2.2.2 RDPGFX_WIRE_TO_SURFACE_PDU_2
+    WireToSurface2 {
+        surface_id: u16,
+        codec_id: u16,
+        codec_context_id: u32,
+        pixel_format: PixelFormat,
+        data: Vec<u8>,
+    },
+    DeleteEncodingContext {
+        surface_id: u16,
+        codec_context_id: u32,
+    },
+    /// Color is 0xAARRGGBB
+    /// MS-RDPEGFX 2.2.2.4 RDPGFX_SOLIDFILL_PDU
+    SolidFill {
+        surface_id: u16,
+        color: u32,
+        rects: Vec<Rect16>,
+    },
+    SurfaceToSurface {
+        source_id: u16,
+        dest_id: u16,
+        source: Rect16,
+        points: Vec<(u16, u16)>,
+    },
+    SurfaceToCache {
+        surface_id: u16,
+        cache_key: u64,
+        cache_slot: u16,
+        source: Rect16,
+    },
+    CacheToSurface {
+        cache_slot: u16,
+        surface_id: u16,
+        points: Vec<(u16, u16)>,
+    },
+    EvictCacheEntry {
+        cache_slot: u16,
+    },
+    CreateSurface {
+        surface_id: u16,
+        width: u16,
+        height: u16,
+        pixel_format: PixelFormat,
+    },
+    DeleteSurface {
+        surface_id: u16,
+    },
+    StartFrame {
+        timestamp: u32,
+        frame_id: u32,
+    },
+    EndFrame {
+        frame_id: u32,
+    },
+    /// MS-RDPEGFX 2.2.2.14 RDPGFX_RESET_GRAPHICS_PDU
+    ResetGraphics {
+        width: u32,
+        height: u32,
+        monitors: Vec<Monitor>,
+    },
+    MapSurfaceToOutput {
+        surface_id: u16,
+        x: u32,
+        y: u32,
+    },
+    CacheImportReply {
+        cache_slots: Vec<u16>,
+    },
+    CapsConfirm(CapabilitySet),
+    /// Commands for remote applications and unknown commands
+    Unknown(u16),
+}
+
+/// Read a graphics PDU with its header
+/// MS-RDPEGFX 2.2.1.5 RDPGFX_HEADER
+pub fn read_graphics_pdu(buffer: &mut BytesMut) -> Result<GraphicsPdu> {
+    check_remaining(buffer, HEADER_SIZE, "EGFX: header")?;
+    let command = buffer.get_u16_le();
+    let _flags = buffer.get_u16_le();
+    let length = buffer.get_u32_le() as usize;
+    if length < HEADER_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "EGFX: invalid PDU length",
+        ));
+    }
+    check_remaining(buffer, length - HEADER_SIZE, "EGFX: PDU")?;
+    let mut body = buffer.split_to(length - HEADER_SIZE);
+    let body = &mut body;
+
+    Ok(match GraphicsCommand::try_from(command) {
+        Ok(GraphicsCommand::RdpgfxCmdidWiretosurface1) => {
+            check_remaining(body, 4, "EGFX: wire to surface")?;
+            let surface_id = body.get_u16_le();
+            let codec_id = body.get_u16_le();
+            let pixel_format = read_pixel_format(body)?;
+            let dest = Rect16::read_from_buffer(body)?;
+            GraphicsPdu::WireToSurface1 {
+                surface_id,
+                codec_id,
+                pixel_format,
+                dest,
+                data: read_bitmap_data(body)?,
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidWiretosurface2) => {
+            check_remaining(body, 8, "EGFX: wire to surface")?;
+            let surface_id = body.get_u16_le();
+            let codec_id = body.get_u16_le();
+            let codec_context_id = body.get_u32_le();
+            let pixel_format = read_pixel_format(body)?;
+            GraphicsPdu::WireToSurface2 {
+                surface_id,
+                codec_id,
+                codec_context_id,
+                pixel_format,
+                data: read_bitmap_data(body)?,
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidDeleteencodingcontext) => {
+            check_remaining(body, 6, "EGFX: delete encoding context")?;
+            GraphicsPdu::DeleteEncodingContext {
+                surface_id: body.get_u16_le(),
+                codec_context_id: body.get_u32_le(),
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidSolidfill) => {
+            check_remaining(body, 8, "EGFX: solid fill")?;
+            let surface_id = body.get_u16_le();
+            let mut color = [0; 4];
+            body.copy_to_slice(&mut color);
+            let count = body.get_u16_le();
+            GraphicsPdu::SolidFill {
+                surface_id,
+                color: u32::from_le_bytes(color),
+                rects: (0..count)
+                    .map(|_| Rect16::read_from_buffer(body))
+                    .collect::<Result<_>>()?,
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidSurfacetosurface) => {
+            check_remaining(body, 4, "EGFX: surface to surface")?;
+            let source_id = body.get_u16_le();
+            let dest_id = body.get_u16_le();
+            let source = Rect16::read_from_buffer(body)?;
+            GraphicsPdu::SurfaceToSurface {
+                source_id,
+                dest_id,
+                source,
+                points: read_points(body)?,
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidSurfacetocache) => {
+            check_remaining(body, 12, "EGFX: surface to cache")?;
+            let surface_id = body.get_u16_le();
+            let cache_key = body.get_u64_le();
+            let cache_slot = body.get_u16_le();
+            GraphicsPdu::SurfaceToCache {
+                surface_id,
+                cache_key,
+                cache_slot,
+                source: Rect16::read_from_buffer(body)?,
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidCachetosurface) => {
+            check_remaining(body, 4, "EGFX: cache to surface")?;
+            let cache_slot = body.get_u16_le();
+            let surface_id = body.get_u16_le();
+            GraphicsPdu::CacheToSurface {
+                cache_slot,
+                surface_id,
+                points: read_points(body)?,
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidEvictcacheentry) => {
+            check_remaining(body, 2, "EGFX: evict cache entry")?;
+            GraphicsPdu::EvictCacheEntry {
+                cache_slot: body.get_u16_le(),
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidCreatesurface) => {
+            check_remaining(body, 6, "EGFX: create surface")?;
+            let surface_id = body.get_u16_le();
+            let width = body.get_u16_le();
+            let height = body.get_u16_le();
+            GraphicsPdu::CreateSurface {
+                surface_id,
+                width,
+                height,
+                pixel_format: read_pixel_format(body)?,
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidDeletesurface) => {
+            check_remaining(body, 2, "EGFX: delete surface")?;
+            GraphicsPdu::DeleteSurface {
+                surface_id: body.get_u16_le(),
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidStartframe) => {
+            check_remaining(body, 8, "EGFX: start frame")?;
+            GraphicsPdu::StartFrame {
+                timestamp: body.get_u32_le(),
+                frame_id: body.get_u32_le(),
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidEndframe) => {
+            check_remaining(body, 4, "EGFX: end frame")?;
+            GraphicsPdu::EndFrame {
+                frame_id: body.get_u32_le(),
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidResetgraphics) => {
+            check_remaining(body, 12, "EGFX: reset graphics")?;
+            let width = body.get_u32_le();
+            let height = body.get_u32_le();
+            let count = body.get_u32_le() as usize;
+            if count > MONITOR_MAX_COUNT {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("EGFX: invalid number of monitors {}", count),
+                ));
+            }
+            // The PDU is padded to 340 bytes
+            GraphicsPdu::ResetGraphics {
+                width,
+                height,
+                monitors: (0..count)
+                    .map(|_| {
+                        let mut monitor = Monitor::default();
+                        monitor.read_from_buffer(body)?;
+                        Ok(monitor)
+                    })
+                    .collect::<Result<_>>()?,
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidMapsurfacetooutput) => {
+            check_remaining(body, 12, "EGFX: map surface to output")?;
+            let surface_id = body.get_u16_le();
+            body.advance(2);
+            GraphicsPdu::MapSurfaceToOutput {
+                surface_id,
+                x: body.get_u32_le(),
+                y: body.get_u32_le(),
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidCacheimportreply) => {
+            check_remaining(body, 2, "EGFX: cache import reply")?;
+            let count = body.get_u16_le() as usize;
+            check_remaining(body, count * 2, "EGFX: cache slots")?;
+            GraphicsPdu::CacheImportReply {
+                cache_slots: (0..count).map(|_| body.get_u16_le()).collect(),
+            }
+        }
+        Ok(GraphicsCommand::RdpgfxCmdidCapsconfirm) => {
+            GraphicsPdu::CapsConfirm(CapabilitySet::read_from_buffer(body)?)
+        }
+        _ => GraphicsPdu::Unknown(command),
+    })
+}
+
+/// Reassemble the segments of a message of the server
+/// MS-RDPEGFX 2.2.5.1 RDP_SEGMENTED_DATA
+///
+/// Segments are decompressed with the history of the channel
+pub fn read_segmented_data(buffer: &mut BytesMut, zgfx: &mut Zgfx) -> Result<BytesMut> {
+    check_remaining(buffer, 1, "EGFX: segment descriptor")?;
+    match buffer.get_u8() {
+        DEBLOCK_SINGLE => read_bulk_data(buffer, buffer.len(), zgfx),
+        DEBLOCK_MULTIPART => {
+            check_remaining(buffer, 6, "EGFX: multipart header")?;
+            let count = buffer.get_u16_le();
+            let size = buffer.get_u32_le() as usize;
+            let mut data = BytesMut::new();
+            for _ in 0..count {
+                check_remaining(buffer, 4, "EGFX: segment size")?;
+                let length = buffer.get_u32_le() as usize;
+                data.unsplit(read_bulk_data(buffer, length, zgfx)?);
+            }
+            if data.len() != size {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "EGFX: invalid uncompressed size",
+                ));
+            }
+            Ok(data)
+        }
+        descriptor => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("EGFX: invalid segment descriptor {}", descriptor),
+        )),
+    }
+}
+
+/// Decompressed data of a segment
+/// MS-RDPEGFX 2.2.5.3 RDP8_BULK_ENCODED_DATA
+fn read_bulk_data(buffer: &mut BytesMut, length: usize, zgfx: &mut Zgfx) -> Result<BytesMut> {
+    check_remaining(buffer, length, "EGFX: segment")?;
+    let segment = buffer.split_to(length);
+    Ok(BytesMut::from(&zgfx.decompress(&segment)?[..]))
+}
+
+/// PDU sent by the client on the graphics channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphicsClientPdu {
+    /// MS-RDPEGFX 2.2.2.18 RDPGFX_CAPS_ADVERTISE_PDU
+    CapsAdvertise(Vec<CapabilitySet>),
+    /// MS-RDPEGFX 2.2.2.12 RDPGFX_FRAME_ACKNOWLEDGE_PDU
+    FrameAcknowledge {
+        queue_depth: u32,
+        frame_id: u32,
+        total_frames_decoded: u32,
+    },
+}
+
+#[async_trait]
+impl Message for GraphicsClientPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        let command = match self {
+            GraphicsClientPdu::CapsAdvertise(_) => GraphicsCommand::RdpgfxCmdidCapsadvertise,
+            GraphicsClientPdu::FrameAcknowledge { .. } => {
+                GraphicsCommand::RdpgfxCmdidFrameacknowledge
+            }
+        };
+        writer.write_u16_le(command as u16).await?;
+        writer.write_u16_le(0).await?;
+        writer.write_u32_le(self.length() as u32).await?;
+        match self {
+            GraphicsClientPdu::CapsAdvertise(capabilities) => {
+                writer.write_u16_le(capabilities.len() as u16).await?;
+                for capability in capabilities {
+                    writer.write_u32_le(capability.version).await?;
+                    writer.write_u32_le(capability.data_length() as u32).await?;
+                    if capability.data_length() == 4 {
+                        writer.write_u32_le(capability.flags).await?;
+                    } else {
+                        writer.write_all(&[0; 16]).await?;
+                    }
+                }
+            }
+            GraphicsClientPdu::FrameAcknowledge {
+                queue_depth,
+                frame_id,
+                total_frames_decoded,
+            } => {
+                writer.write_u32_le(*queue_depth).await?;
+                writer.write_u32_le(*frame_id).await?;
+                writer.write_u32_le(*total_frames_decoded).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let command = reader.read_u16_le().await?;
+        let _flags = reader.read_u16_le().await?;
+        let _length = reader.read_u32_le().await?;
+        *self = match GraphicsCommand::try_from(command) {
+            Ok(GraphicsCommand::RdpgfxCmdidCapsadvertise) => {
+                let count = reader.read_u16_le().await?;
+                let mut capabilities = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let version = reader.read_u32_le().await?;
+                    let mut data = vec![0; reader.read_u32_le().await? as usize];
+                    reader.read_exact(&mut data).await?;
+                    let capability = CapabilitySet {
+                        version,
+                        flags: CapabilitySet::read_flags(version, &data),
+                    };
+                    capabilities.push(capability);
+                }
+                GraphicsClientPdu::CapsAdvertise(capabilities)
+            }
+            Ok(GraphicsCommand::RdpgfxCmdidFrameacknowledge) => {
+                GraphicsClientPdu::FrameAcknowledge {
+                    queue_depth: reader.read_u32_le().await?,
+                    frame_id: reader.read_u32_le().await?,
+                    total_frames_decoded: reader.read_u32_le().await?,
+                }
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("EGFX: unexpected client command {}", command),
+                ))
+            }
+        };
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        HEADER_SIZE
+            + match self {
+                GraphicsClientPdu::CapsAdvertise(capabilities) => {
+                    2 + capabilities
+                        .iter()
+                        .map(|capability| 8 + capability.data_length())
+                        .sum::<usize>()
+                }
+                GraphicsClientPdu::FrameAcknowledge { .. } => 12,
+            }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+    use bytes::BufMut;
+
+    /// Write a graphics PDU header before its body
+    fn put_pdu(buffer: &mut BytesMut, command: GraphicsCommand, body: &[u8]) {
+        buffer.put_u16_le(command as u16);
+        buffer.put_u16_le(0);
+        buffer.put_u32_le((HEADER_SIZE + body.len()) as u32);
+        buffer.put_slice(body);
+    }
+
+    /// A message with a solid fill and an end frame
+    #[test]
+    fn test_read_graphics_pdu() {
+        let mut buffer = BytesMut::new();
+        put_pdu(
+            &mut buffer,
+            GraphicsCommand::RdpgfxCmdidSolidfill,
+            &[1, 0, 0x30, 0x20, 0x10, 0xFF, 1, 0, 0, 0, 0, 0, 4, 0, 2, 0],
+        );
+        put_pdu(
+            &mut buffer,
+            GraphicsCommand::RdpgfxCmdidEndframe,
+            &[7, 0, 0, 0],
+        );
+        assert_eq!(
+            read_graphics_pdu(&mut buffer).unwrap(),
+            GraphicsPdu::SolidFill {
+                surface_id: 1,
+                color: 0xFF102030,
+                rects: vec![Rect16 {
+                    left: 0,
+                    top: 0,
+                    right: 4,
+                    bottom: 2
+                }],
+            }
+        );
+        assert_eq!(
+            read_graphics_pdu(&mut buffer).unwrap(),
+            GraphicsPdu::EndFrame { frame_id: 7 }
+        );
+        assert!(buffer.is_empty());
+    }
+
+    /// Segments are concatenated after their decompression
+    #[test]
+    fn test_read_segmented_data() {
+        let mut zgfx = Zgfx::new();
+        let mut buffer = BytesMut::from(&[DEBLOCK_SINGLE, 0x04, 1, 2][..]);
+        let data = read_segmented_data(&mut buffer, &mut zgfx).unwrap();
+        assert_eq!(&data[..], &[1, 2]);
+
+        let mut buffer = BytesMut::new();
+        buffer.put_u8(DEBLOCK_MULTIPART);
+        buffer.put_u16_le(2);
+        buffer.put_u32_le(4);
+        buffer.put_u32_le(2);
+        buffer.put_slice(&[0x04, 3]);
+        // Match of distance 3 and length 3
+        buffer.put_u32_le(4);
+        buffer.put_slice(&[0x24, 0x88, 0xC0, 0x05]);
+        let data = read_segmented_data(&mut buffer, &mut zgfx).unwrap();
+        assert_eq!(&data[..], &[3, 1, 2, 3]);
+
+        let mut buffer = BytesMut::from(&[DEBLOCK_SINGLE, 0x24, 0x88, 0xC0, 0x06][..]);
+        assert!(read_segmented_data(&mut buffer, &mut zgfx).is_err());
+    }
+
+    /// Version 10.1 has reserved bytes instead of flags
+    #[tokio::test]
+    async fn test_caps_advertise() {
+        let pdu = GraphicsClientPdu::CapsAdvertise(vec![
+            CapabilitySet::new(CapabilityVersion::RdpgfxCapversion101, 0),
+            CapabilitySet::new(
+                CapabilityVersion::RdpgfxCapversion8,
+                CapabilityFlag::RdpgfxCapsFlagSmallcache as u32,
+            ),
+        ]);
+        let data = to_vec(&pdu).await.unwrap();
+        assert_eq!(data.len(), 8 + 2 + 24 + 12);
+        assert_eq!(&data[..2], &[0x12, 0x00]);
+        let mut result = GraphicsClientPdu::FrameAcknowledge {
+            queue_depth: 0,
+            frame_id: 0,
+            total_frames_decoded: 0,
+        };
+        result.read_from(&mut &data[..]).await.unwrap();
+        assert_eq!(result, pdu);
+    }
+}
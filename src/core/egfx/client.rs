@@ -0,0 +1,686 @@
+use crate::codec::clearcodec::ClearCodec;
+use crate::codec::planar::planar_decompress;
+use crate::codec::pool::DecodePool;
+use crate::codec::zgfx::Zgfx;
+use crate::core::drdynvc::client::{to_messages, DynamicChannelHandler};
+use crate::core::egfx::base::{
+    read_graphics_pdu, read_segmented_data, CapabilityFlag, CapabilitySet, CapabilityVersion,
+    GraphicsClientPdu, GraphicsCodec, GraphicsPdu, PixelFormat, Rect16, GRAPHICS_CHANNEL_NAME,
+};
+use crate::core::event::{FrameAction, RdpEvent, SessionEvent};
+use crate::core::order::gdi::{Area, Surface};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+
+/// Cache slots of the server, without and with the small cache flag
+/// MS-RDPEGFX 2.2.2.6 RDPGFX_SURFACE_TO_CACHE_PDU
+const MAX_CACHE_SLOTS: u16 = 25600;
+const MAX_CACHE_SLOTS_SMALL: u16 = 4096;
+
+/// Signature of the alpha codec
+/// MS-RDPEGFX 2.2.4.3 RDPGFX_ALPHA_CODEC_HEADER
+const ALPHA_SIGNATURE: u16 = 0x414C;
+
+/// Surface created by the server
+struct GraphicsSurface {
+    surface: Surface,
+    /// Alpha is only kept for ARGB surfaces
+    alpha: bool,
+    /// Position on the desktop once mapped to the output
+    output: Option<(u32, u32)>,
+}
+
+/// Pixels copied by a surface to cache PDU
+struct CacheEntry {
+    width: usize,
+    height: usize,
+    pixels: Vec<u32>,
+}
+
+fn invalid_surface(surface_id: u16) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("EGFX: no surface {}", surface_id),
+    )
+}
+
+fn area(rect: &Rect16) -> Area {
+    Area {
+        left: rect.left as i32,
+        top: rect.top as i32,
+        right: rect.right as i32,
+        bottom: rect.bottom as i32,
+    }
+}
+
+/// Pixels of BGRA data, rows from the top
+fn bgra_pixels(data: &[u8], width: usize, height: usize) -> Result<Vec<u32>> {
+    if data.len() < width * height * 4 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "EGFX: bitmap data is too short",
+        ));
+    }
+    Ok(data
+        .chunks_exact(4)
+        .take(width * height)
+        .map(|pixel| u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+        .collect())
+}
+
+/// Pixels of a bitmap decoded without the state of the client,
+/// so the bitmaps of a message can be decoded at the same time
+/// None for the codecs decoded in order, like ClearCodec and its caches
+fn decode_tile(codec_id: u16, dest: &Rect16, data: &[u8]) -> Option<Result<Vec<u32>>> {
+    let (width, height) = (dest.width(), dest.height());
+    match GraphicsCodec::try_from(codec_id) {
+        Ok(GraphicsCodec::RdpgfxCodecidUncompressed) => {
+            Some(bgra_pixels(data, width as usize, height as usize))
+        }
+        Ok(GraphicsCodec::RdpgfxCodecidPlanar) => Some(
+            planar_decompress(data, width, height)
+                .and_then(|pixels| bgra_pixels(&pixels, width as usize, height as usize)),
+        ),
+        _ => None,
+    }
+}
+
+/// Alpha values of the alpha codec, raw or run length encoded
+/// MS-RDPEGFX 2.2.4.3 RDPGFX_ALPHA_CODEC_HEADER
+fn alpha_values(data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let too_short = || Error::new(ErrorKind::InvalidData, "EGFX: alpha data is too short");
+    let mut buffer = data;
+    if buffer.remaining() < 4 || buffer.get_u16_le() != ALPHA_SIGNATURE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "EGFX: invalid alpha codec signature",
+        ));
+    }
+    if buffer.get_u16_le() == 0 {
+        return buffer.get(..size).map(<[u8]>::to_vec).ok_or_else(too_short);
+    }
+    let mut values = Vec::with_capacity(size);
+    while values.len() < size {
+        if buffer.remaining() < 2 {
+            return Err(too_short());
+        }
+        let value = buffer.get_u8();
+        let mut count = buffer.get_u8() as usize;
+        if count == 0xFF {
+            if buffer.remaining() < 2 {
+                return Err(too_short());
+            }
+            count = buffer.get_u16_le() as usize;
+            if count == 0xFFFF {
+                if buffer.remaining() < 4 {
+                    return Err(too_short());
+                }
+                count = buffer.get_u32_le() as usize;
+            }
+        }
+        values.resize(size.min(values.len() + count), value);
+    }
+    Ok(values)
+}
+
+/// Client of the graphics pipeline
+/// Messages of the dynamic virtual channel are decoded on surfaces,
+/// the regions of the surfaces mapped to the output are given back as bitmaps
+///
+/// # Example
+/// ```
+/// use rdp::core::egfx::client::GraphicsClient;
+/// use rdp::core::event::RdpEvent;
+/// let mut client = GraphicsClient::new();
+/// let _advertise = client.capabilities_advertise();
+/// // A single uncompressed segment with an end frame PDU
+/// let mut data = bytes::BytesMut::from(
+///     &[0xE0, 0x04, 0x0C, 0x00, 0x00, 0x00, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00][..],
+/// );
+/// let responses = client.process(&mut data, &mut |_: RdpEvent| {}).unwrap();
+/// assert_eq!(responses.len(), 1);
+/// ```
+pub struct GraphicsClient {
+    surfaces: HashMap<u16, GraphicsSurface>,
+    cache: HashMap<u16, CacheEntry>,
+    clear_codec: ClearCodec,
+    /// Decompressor of the channel stream
+    zgfx: Zgfx,
+    /// Capability set chosen by the server
+    capabilities: Option<CapabilitySet>,
+    frames_decoded: u32,
+    /// Workers of the stateless codecs
+    pool: Arc<DecodePool>,
+}
+
+impl Default for GraphicsClient {
+    fn default() -> Self {
+        GraphicsClient::new()
+    }
+}
+
+impl GraphicsClient {
+    pub fn new() -> Self {
+        GraphicsClient {
+            surfaces: HashMap::new(),
+            cache: HashMap::new(),
+            clear_codec: ClearCodec::new(),
+            zgfx: Zgfx::new(),
+            capabilities: None,
+            frames_decoded: 0,
+            pool: Arc::new(DecodePool::sequential()),
+        }
+    }
+
+    /// Decode the bitmaps of each message on a pool of threads,
+    /// which can be shared by several clients
+    /// Planar and uncompressed bitmaps are decoded at the same time,
+    /// ClearCodec keeps caches between bitmaps and stays in order
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let mut client = GraphicsClient::new();
+    /// client.set_decode_pool(Arc::new(DecodePool::new(4)?));
+    /// ```
+    pub fn set_decode_pool(&mut self, pool: Arc<DecodePool>) {
+        self.pool = pool;
+    }
+
+    /// First PDU sent once the channel is opened
+    /// AVC codecs are not supported
+    pub fn capabilities_advertise(&self) -> GraphicsClientPdu {
+        GraphicsClientPdu::CapsAdvertise(vec![
+            CapabilitySet::new(
+                CapabilityVersion::RdpgfxCapversion10,
+                CapabilityFlag::RdpgfxCapsFlagAvcDisabled as u32,
+            ),
+            CapabilitySet::new(CapabilityVersion::RdpgfxCapversion81, 0),
+            CapabilitySet::new(CapabilityVersion::RdpgfxCapversion8, 0),
+        ])
+    }
+
+    /// Capability set confirmed by the server
+    pub fn capabilities(&self) -> Option<CapabilitySet> {
+        self.capabilities
+    }
+
+    fn max_cache_slots(&self) -> u16 {
+        match self.capabilities {
+            Some(capabilities)
+                if capabilities.flags & CapabilityFlag::RdpgfxCapsFlagSmallcache as u32 != 0 =>
+            {
+                MAX_CACHE_SLOTS_SMALL
+            }
+            _ => MAX_CACHE_SLOTS,
+        }
+    }
+
+    fn surface(&mut self, surface_id: u16) -> Result<&mut GraphicsSurface> {
+        self.surfaces
+            .get_mut(&surface_id)
+            .ok_or_else(|| invalid_surface(surface_id))
+    }
+
+    /// Process a message of the channel
+    /// and build the PDUs expected by the server
+    pub fn process<T>(
+        &mut self,
+        buffer: &mut BytesMut,
+        callback: &mut T,
+    ) -> Result<Vec<GraphicsClientPdu>>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let mut data = read_segmented_data(buffer, &mut self.zgfx)?;
+        let mut pdus = Vec::new();
+        while data.has_remaining() {
+            pdus.push(read_graphics_pdu(&mut data)?);
+        }
+
+        // The bitmaps are decoded first, then drawn in the order of the PDUs
+        let tiles = pdus
+            .iter()
+            .map(|pdu| match pdu {
+                GraphicsPdu::WireToSurface1 {
+                    codec_id,
+                    dest,
+                    data,
+                    ..
+                } => Some((*codec_id, dest, &data[..])),
+                _ => None,
+            })
+            .collect();
+        let decoded = self.pool.decode(tiles, |tile| {
+            tile.and_then(|(codec_id, dest, data)| decode_tile(codec_id, dest, data))
+        });
+
+        let mut responses = Vec::new();
+        for (pdu, pixels) in pdus.into_iter().zip(decoded) {
+            if let Some(response) = self.apply(pdu, pixels, callback)? {
+                responses.push(response);
+            }
+        }
+        Ok(responses)
+    }
+
+    /// Pixels are given for the bitmaps already decoded
+    fn apply<T>(
+        &mut self,
+        pdu: GraphicsPdu,
+        pixels: Option<Result<Vec<u32>>>,
+        callback: &mut T,
+    ) -> Result<Option<GraphicsClientPdu>>
+    where
+        T: FnMut(RdpEvent),
+    {
+        match pdu {
+            GraphicsPdu::CapsConfirm(capabilities) => self.capabilities = Some(capabilities),
+            GraphicsPdu::ResetGraphics { width, height, .. } => {
+                self.surfaces.clear();
+                self.cache.clear();
+                callback(RdpEvent::Session(SessionEvent::Reactivated {
+                    width: width as u16,
+                    height: height as u16,
+                }));
+            }
+            GraphicsPdu::CreateSurface {
+                surface_id,
+                width,
+                height,
+                pixel_format,
+            } => {
+                self.surfaces.insert(
+                    surface_id,
+                    GraphicsSurface {
+                        surface: Surface::new(width, height),
+                        alpha: pixel_format == PixelFormat::GfxPixelFormatArgb8888,
+                        output: None,
+                    },
+                );
+            }
+            GraphicsPdu::DeleteSurface { surface_id } => {
+                self.surfaces.remove(&surface_id);
+            }
+            GraphicsPdu::MapSurfaceToOutput { surface_id, x, y } => {
+                let surface = self.surface(surface_id)?;
+                surface.output = Some((x, y));
+                let area = surface.surface.area();
+                self.refresh(surface_id, area, callback);
+            }
+            GraphicsPdu::StartFrame { .. } => {
+                callback(RdpEvent::Session(SessionEvent::Frame(FrameAction::Start)))
+            }
+            GraphicsPdu::EndFrame { frame_id } => {
+                self.frames_decoded = self.frames_decoded.wrapping_add(1);
+                callback(RdpEvent::Session(SessionEvent::Frame(FrameAction::End)));
+                return Ok(Some(GraphicsClientPdu::FrameAcknowledge {
+                    queue_depth: 0,
+                    frame_id,
+                    total_frames_decoded: self.frames_decoded,
+                }));
+            }
+            GraphicsPdu::WireToSurface1 {
+                surface_id,
+                codec_id,
+                dest,
+                data,
+                ..
+            } => {
+                let area = self.wire_to_surface(surface_id, codec_id, &dest, &data, pixels)?;
+                self.refresh(surface_id, area, callback);
+            }
+            GraphicsPdu::WireToSurface2 { codec_id, .. } => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("EGFX: unsupported codec {}", codec_id),
+                ))
+            }
+            GraphicsPdu::SolidFill {
+                surface_id,
+                color,
+                rects,
+            } => {
+                let surface = self.surface(surface_id)?;
+                let color = if surface.alpha {
+                    color
+                } else {
+                    color | 0xFF000000
+                };
+                let mut updated = Area::default();
+                for rect in &rects {
+                    let drawn = surface.surface.copy(area(rect), &|_, _| color);
+                    updated = updated.union(&drawn);
+                }
+                self.refresh(surface_id, updated, callback);
+            }
+            GraphicsPdu::SurfaceToSurface {
+                source_id,
+                dest_id,
+                source,
+                points,
+            } => {
+                let pixels = self.surface(source_id)?.surface.copy_area(area(&source));
+                let width = source.width() as i32;
+                let surface = self.surface(dest_id)?;
+                let mut updated = Area::default();
+                for (x, y) in points {
+                    let dest = Area::new(
+                        x as i16,
+                        y as i16,
+                        source.width() as i16,
+                        source.height() as i16,
+                    );
+                    let drawn = surface.surface.copy(dest, &|px, py| {
+                        pixels[((py - dest.top) * width + px - dest.left) as usize]
+                    });
+                    updated = updated.union(&drawn);
+                }
+                self.refresh(dest_id, updated, callback);
+            }
+            GraphicsPdu::SurfaceToCache {
+                surface_id,
+                cache_slot,
+                source,
+                ..
+            } => {
+                self.check_cache_slot(cache_slot)?;
+                let pixels = self.surface(surface_id)?.surface.copy_area(area(&source));
+                self.cache.insert(
+                    cache_slot,
+                    CacheEntry {
+                        width: source.width() as usize,
+                        height: source.height() as usize,
+                        pixels,
+                    },
+                );
+            }
+            GraphicsPdu::CacheToSurface {
+                cache_slot,
+                surface_id,
+                points,
+            } => {
+                let entry = self.cache.get(&cache_slot).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("EGFX: empty cache slot {}", cache_slot),
+                    )
+                })?;
+                let surface = self
+                    .surfaces
+                    .get_mut(&surface_id)
+                    .ok_or_else(|| invalid_surface(surface_id))?;
+                let mut updated = Area::default();
+                for (x, y) in points {
+                    let dest =
+                        Area::new(x as i16, y as i16, entry.width as i16, entry.height as i16);
+                    let drawn = surface.surface.copy(dest, &|px, py| {
+                        entry.pixels
+                            [(py - dest.top) as usize * entry.width + (px - dest.left) as usize]
+                    });
+                    updated = updated.union(&drawn);
+                }
+                self.refresh(surface_id, updated, callback);
+            }
+            GraphicsPdu::EvictCacheEntry { cache_slot } => {
+                self.cache.remove(&cache_slot);
+            }
+            // No cache import offer is sent, codec contexts are only used by progressive
+            GraphicsPdu::CacheImportReply { .. }
+            | GraphicsPdu::DeleteEncodingContext { .. }
+            | GraphicsPdu::Unknown(_) => (),
+        }
+        Ok(None)
+    }
+
+    fn check_cache_slot(&self, cache_slot: u16) -> Result<()> {
+        if cache_slot == 0 || cache_slot > self.max_cache_slots() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("EGFX: invalid cache slot {}", cache_slot),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Decode a bitmap on a surface, return the area drawn
+    /// MS-RDPEGFX 2.2.2.1 RDPGFX_WIRE_TO_SURFACE_PDU_1
+    fn wire_to_surface(
+        &mut self,
+        surface_id: u16,
+        codec_id: u16,
+        dest: &Rect16,
+        data: &[u8],
+        decoded: Option<Result<Vec<u32>>>,
+    ) -> Result<Area> {
+        let (width, height) = (dest.width(), dest.height());
+        let size = width as usize * height as usize;
+        let surface = self
+            .surfaces
+            .get_mut(&surface_id)
+            .ok_or_else(|| invalid_surface(surface_id))?;
+        let pixels = match (decoded, GraphicsCodec::try_from(codec_id)) {
+            (Some(pixels), _) => pixels?,
+            (None, Ok(GraphicsCodec::RdpgfxCodecidClearcodec)) => bgra_pixels(
+                &self.clear_codec.decompress(data, width, height)?,
+                width as usize,
+                height as usize,
+            )?,
+            // Only the alpha of the surface is changed
+            (None, Ok(GraphicsCodec::RdpgfxCodecidAlpha)) => {
+                let alpha = alpha_values(data, size)?;
+                let mut pixels = surface.surface.copy_area(area(dest));
+                for (pixel, value) in pixels.iter_mut().zip(alpha) {
+                    *pixel = (*pixel & 0x00FFFFFF) | ((value as u32) << 24);
+                }
+                pixels
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("EGFX: unsupported codec {}", codec_id),
+                ))
+            }
+        };
+        let alpha_mask = if surface.alpha { 0 } else { 0xFF000000 };
+        let width = width as i32;
+        Ok(surface.surface.copy(area(dest), &|x, y| {
+            pixels[((y - dest.top as i32) * width + x - dest.left as i32) as usize] | alpha_mask
+        }))
+    }
+
+    /// Give back an area of a surface mapped to the output
+    fn refresh<T>(&self, surface_id: u16, area: Area, callback: &mut T)
+    where
+        T: FnMut(RdpEvent),
+    {
+        let surface = match self.surfaces.get(&surface_id) {
+            Some(surface) => surface,
+            None => return,
+        };
+        let (x, y) = match surface.output {
+            Some(output) => output,
+            None => return,
+        };
+        let area = area.intersect(&surface.surface.area());
+        if area.is_empty() {
+            return;
+        }
+        let mut bitmap = surface.surface.bitmap(area);
+        bitmap.dest_left = bitmap.dest_left.wrapping_add(x as u16);
+        bitmap.dest_right = bitmap.dest_right.wrapping_add(x as u16);
+        bitmap.dest_top = bitmap.dest_top.wrapping_add(y as u16);
+        bitmap.dest_bottom = bitmap.dest_bottom.wrapping_add(y as u16);
+        callback(RdpEvent::Bitmap(bitmap));
+    }
+}
+
+/// The capabilities are advertised once the channel is created,
+/// decoded frames are acknowledged
+#[async_trait]
+impl DynamicChannelHandler for GraphicsClient {
+    fn channel_names(&self) -> Vec<&'static str> {
+        vec![GRAPHICS_CHANNEL_NAME]
+    }
+
+    async fn open(&mut self, _channel_name: &str, _channel_id: u32) -> Result<Vec<Vec<u8>>> {
+        to_messages(vec![self.capabilities_advertise()]).await
+    }
+
+    async fn process(
+        &mut self,
+        _channel_name: &str,
+        _channel_id: u32,
+        buffer: &mut BytesMut,
+        callback: &mut (dyn FnMut(RdpEvent) + Send),
+    ) -> Result<Vec<Vec<u8>>> {
+        let responses = GraphicsClient::process(self, buffer, &mut |event| callback(event))?;
+        to_messages(responses).await
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::egfx::base::GraphicsCommand;
+    use bytes::BufMut;
+
+    /// Single uncompressed segment of PDUs
+    fn segment(pdus: &[(GraphicsCommand, Vec<u8>)]) -> BytesMut {
+        let mut buffer = BytesMut::new();
+        buffer.put_slice(&[0xE0, 0x04]);
+        for (command, body) in pdus {
+            buffer.put_u16_le(*command as u16);
+            buffer.put_u16_le(0);
+            buffer.put_u32_le(8 + body.len() as u32);
+            buffer.put_slice(body);
+        }
+        buffer
+    }
+
+    /// A filled surface is given back once mapped, frames are acknowledged
+    #[test]
+    fn test_graphics_client() {
+        let mut client = GraphicsClient::new();
+        let mut data = segment(&[
+            (
+                GraphicsCommand::RdpgfxCmdidCreatesurface,
+                vec![1, 0, 4, 0, 2, 0, PixelFormat::GfxPixelFormatXrgb8888 as u8],
+            ),
+            (
+                GraphicsCommand::RdpgfxCmdidMapsurfacetooutput,
+                vec![1, 0, 0, 0, 10, 0, 0, 0, 20, 0, 0, 0],
+            ),
+            (
+                GraphicsCommand::RdpgfxCmdidStartframe,
+                vec![0, 0, 0, 0, 3, 0, 0, 0],
+            ),
+            (
+                GraphicsCommand::RdpgfxCmdidSolidfill,
+                vec![1, 0, 0x30, 0x20, 0x10, 0, 1, 0, 1, 0, 0, 0, 3, 0, 1, 0],
+            ),
+            (GraphicsCommand::RdpgfxCmdidEndframe, vec![3, 0, 0, 0]),
+        ]);
+        let mut bitmaps = Vec::new();
+        let mut frames = 0;
+        let responses = client
+            .process(&mut data, &mut |event| match event {
+                RdpEvent::Bitmap(bitmap) => bitmaps.push(bitmap),
+                RdpEvent::Session(SessionEvent::Frame(_)) => frames += 1,
+                _ => (),
+            })
+            .unwrap();
+        assert_eq!(
+            responses,
+            vec![GraphicsClientPdu::FrameAcknowledge {
+                queue_depth: 0,
+                frame_id: 3,
+                total_frames_decoded: 1
+            }]
+        );
+        assert_eq!(frames, 2);
+        // The whole surface when mapped, then the filled area
+        assert_eq!(bitmaps.len(), 2);
+        let bitmap = &bitmaps[1];
+        assert_eq!((bitmap.dest_left, bitmap.dest_top), (11, 20));
+        assert_eq!((bitmap.dest_right, bitmap.dest_bottom), (12, 20));
+        assert_eq!(&bitmap.data[..4], &[0x30, 0x20, 0x10, 0xFF]);
+    }
+
+    /// Cached pixels are copied back at each point
+    #[test]
+    fn test_graphics_cache() {
+        let mut client = GraphicsClient::new();
+        let mut data = segment(&[
+            (
+                GraphicsCommand::RdpgfxCmdidCreatesurface,
+                vec![1, 0, 4, 0, 1, 0, PixelFormat::GfxPixelFormatArgb8888 as u8],
+            ),
+            (
+                GraphicsCommand::RdpgfxCmdidWiretosurface1,
+                vec![
+                    1, 0, 0, 0, 0x21, 0, 0, 0, 0, 1, 0, 1, 0, 4, 0, 0, 0, 1, 2, 3, 4,
+                ],
+            ),
+            (
+                GraphicsCommand::RdpgfxCmdidSurfacetocache,
+                vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 1, 0, 1, 0],
+            ),
+            (
+                GraphicsCommand::RdpgfxCmdidCachetosurface,
+                vec![5, 0, 1, 0, 2, 0, 2, 0, 0, 0, 3, 0, 0, 0],
+            ),
+        ]);
+        client.process(&mut data, &mut |_| {}).unwrap();
+        let surface = &client.surfaces[&1].surface;
+        assert_eq!(surface.pixel(0, 0), Some(0x04030201));
+        assert_eq!(surface.pixel(1, 0), Some(0xFF000000));
+        assert_eq!(surface.pixel(2, 0), Some(0x04030201));
+        assert_eq!(surface.pixel(3, 0), Some(0x04030201));
+
+        let mut data = segment(&[(
+            GraphicsCommand::RdpgfxCmdidCachetosurface,
+            vec![6, 0, 1, 0, 0, 0],
+        )]);
+        assert!(client.process(&mut data, &mut |_| {}).is_err());
+    }
+
+    /// Bitmaps decoded together are drawn in the order of the PDUs
+    #[test]
+    fn test_graphics_decode_pool() {
+        let mut client = GraphicsClient::new();
+        client.set_decode_pool(Arc::new(DecodePool::new(2).unwrap()));
+        let bitmap = |pixel: u8| {
+            vec![
+                1, 0, 0, 0, 0x21, 0, 0, 0, 0, 1, 0, 1, 0, 4, 0, 0, 0, pixel, pixel, pixel, pixel,
+            ]
+        };
+        let mut data = segment(&[
+            (
+                GraphicsCommand::RdpgfxCmdidCreatesurface,
+                vec![1, 0, 1, 0, 1, 0, PixelFormat::GfxPixelFormatArgb8888 as u8],
+            ),
+            (GraphicsCommand::RdpgfxCmdidWiretosurface1, bitmap(1)),
+            (GraphicsCommand::RdpgfxCmdidWiretosurface1, bitmap(2)),
+            (GraphicsCommand::RdpgfxCmdidWiretosurface1, bitmap(3)),
+        ]);
+        client.process(&mut data, &mut |_| {}).unwrap();
+        assert_eq!(client.surfaces[&1].surface.pixel(0, 0), Some(0x03030303));
+    }
+
+    /// Run length encoded alpha values
+    #[test]
+    fn test_alpha_values() {
+        let data = [0x4C, 0x41, 1, 0, 0x80, 2, 0x40, 0xFF, 1, 0];
+        assert_eq!(alpha_values(&data, 3).unwrap(), vec![0x80, 0x80, 0x40]);
+        assert!(alpha_values(&data[..4], 1).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+
+/// Declare the error info codes with their reason
+/// Unknown codes are kept as is
+macro_rules! error_info {
+    ($($name:ident = $code:expr => $reason:expr,)*) => {
+        /// Reason sent by the server before it closes the session
+        /// MS-RDPBCGR 2.2.5.1.1 Set Error Info PDU Data (TS_SET_ERROR_INFO_PDU)
+        #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+        pub enum ErrorInfo {
+            $($name,)*
+            Unknown(u32),
+        }
+
+        impl From<u32> for ErrorInfo {
+            fn from(code: u32) -> Self {
+                match code {
+                    $($code => ErrorInfo::$name,)*
+                    _ => ErrorInfo::Unknown(code),
+                }
+            }
+        }
+
+        impl ErrorInfo {
+            /// Code as sent on the wire
+            pub fn code(&self) -> u32 {
+                match self {
+                    $(ErrorInfo::$name => $code,)*
+                    ErrorInfo::Unknown(code) => *code,
+                }
+            }
+
+            /// Human readable reason
+            pub fn reason(&self) -> &'static str {
+                match self {
+                    $(ErrorInfo::$name => $reason,)*
+                    ErrorInfo::Unknown(_) => "unknown error",
+                }
+            }
+        }
+    };
+}
+
+error_info! {
+    // Protocol-independent codes
+    None = 0x0000_0000 => "no error",
+    RpcInitiatedDisconnect = 0x0000_0001 =>
+        "the disconnection was initiated by an administrative tool on the server",
+    RpcInitiatedLogoff = 0x0000_0002 =>
+        "the disconnection was due to a forced logoff initiated by an administrative tool",
+    IdleTimeout = 0x0000_0003 => "the idle session limit timer on the server has elapsed",
+    LogonTimeout = 0x0000_0004 => "the active session limit timer on the server has elapsed",
+    DisconnectedByOtherConnection = 0x0000_0005 =>
+        "another user connected to the server, forcing the disconnection",
+    OutOfMemory = 0x0000_0006 => "the server ran out of available memory resources",
+    ServerDeniedConnection = 0x0000_0007 => "the server denied the connection",
+    ServerInsufficientPrivileges = 0x0000_0009 =>
+        "the user cannot connect to the server due to insufficient access privileges",
+    ServerFreshCredentialsRequired = 0x0000_000A =>
+        "the server does not accept saved user credentials",
+    RpcInitiatedDisconnectByUser = 0x0000_000B =>
+        "the disconnection was initiated by the user on the server",
+    LogoffByUser = 0x0000_000C => "the disconnection was initiated by the user logging off",
+    CloseStackOnDriverNotReady = 0x0000_000F => "the display driver was not ready in time",
+    ServerDwmCrash = 0x0000_0010 => "the desktop window manager crashed on the server",
+    CloseStackOnDriverFailure = 0x0000_0011 => "the display driver failed to start",
+    CloseStackOnDriverIfaceFailure = 0x0000_0012 =>
+        "the display driver interface could not be created",
+    ServerWinlogonCrash = 0x0000_0017 => "the winlogon process crashed on the server",
+    ServerCsrssCrash = 0x0000_0018 => "the csrss process crashed on the server",
+    ServerShutdown = 0x0000_0019 => "the server is shutting down",
+    ServerReboot = 0x0000_001A => "the server is rebooting",
+
+    // Licensing codes
+    LicenseInternal = 0x0000_0100 => "an internal error occurred in the licensing protocol",
+    LicenseNoLicenseServer = 0x0000_0101 => "no license server was available",
+    LicenseNoLicense = 0x0000_0102 => "no valid software license was available",
+    LicenseBadClientMsg = 0x0000_0103 => "the server received an invalid licensing message",
+    LicenseHwidDoesntMatchLicense = 0x0000_0104 =>
+        "the client license has been modified and does not match the hardware id",
+    LicenseBadClientLicense = 0x0000_0105 => "the client license is in an invalid format",
+    LicenseCantFinishProtocol = 0x0000_0106 =>
+        "network problems have caused the licensing protocol to be terminated",
+    LicenseClientEndedProtocol = 0x0000_0107 =>
+        "the client prematurely ended the licensing protocol",
+    LicenseBadClientEncryption = 0x0000_0108 => "a licensing message was incorrectly encrypted",
+    LicenseCantUpgradeLicense = 0x0000_0109 =>
+        "the client license could not be upgraded or renewed",
+    LicenseNoRemoteConnections = 0x0000_010A =>
+        "the server is not licensed to accept remote connections",
+
+    // Connection broker codes
+    CbDestinationNotFound = 0x0000_0400 => "the target endpoint could not be found",
+    CbLoadingDestination = 0x0000_0402 => "the target endpoint is disconnecting from the broker",
+    CbRedirectingToDestination = 0x0000_0404 =>
+        "an error occurred while the connection was being redirected to the target endpoint",
+    CbSessionOnlineVmWake = 0x0000_0405 => "an error occurred while waking up the virtual machine",
+    CbSessionOnlineVmBoot = 0x0000_0406 => "an error occurred while starting the virtual machine",
+    CbSessionOnlineVmNoDns = 0x0000_0407 => "the IP address of the virtual machine is unknown",
+    CbDestinationPoolNotFree = 0x0000_0408 => "no free endpoint is available in the pool",
+    CbConnectionCancelled = 0x0000_0409 => "the connection processing was cancelled",
+    CbConnectionErrorInvalidSettings = 0x0000_0410 =>
+        "the settings contained in the routing token are invalid",
+    CbSessionOnlineVmBootTimeout = 0x0000_0411 =>
+        "a time-out occurred while the virtual machine was starting",
+    CbSessionOnlineVmSessmonFailed = 0x0000_0412 => "a session monitoring error occurred",
+
+    // RDP protocol codes
+    UnknownPduType2 = 0x0000_10C9 => "unknown data PDU type",
+    UnknownPduType = 0x0000_10CA => "unknown PDU type",
+    DataPduSequence = 0x0000_10CB => "the data PDU was sent out of sequence",
+    ControlPduSequence = 0x0000_10CD => "the control PDU was sent out of sequence",
+    InvalidControlPduAction = 0x0000_10CE => "invalid control PDU action",
+    InvalidInputPduType = 0x0000_10CF => "invalid input event type",
+    InvalidInputPduMouse = 0x0000_10D0 => "invalid mouse event flags",
+    InvalidRefreshRectPdu = 0x0000_10D1 => "invalid refresh rect PDU",
+    CreateUserDataFailed = 0x0000_10D2 => "the server failed to build the GCC conference response",
+    ConnectFailed = 0x0000_10D3 => "the server failed to complete the connection",
+    ConfirmActiveWrongShareId = 0x0000_10D4 => "the confirm active PDU has a wrong share id",
+    ConfirmActiveWrongOriginator = 0x0000_10D5 =>
+        "the confirm active PDU has a wrong originator id",
+    SecurityDataTooShort = 0x0000_10E0 => "there is not enough data to read the security header",
+    VChannelDataTooShort = 0x0000_10E1 => "there is not enough data to read a virtual channel PDU",
+    ShareDataTooShort = 0x0000_10E2 => "there is not enough data to read a share data header",
+    ConfirmActivePduTooShort = 0x0000_10E5 => "the confirm active PDU is too short",
+    CapabilitySetTooSmall = 0x0000_10E7 => "a capability set is too small",
+    CapabilitySetTooLarge = 0x0000_10E8 => "a capability set is too large",
+    NoCursorCache = 0x0000_10E9 => "the pointer cache capability is missing",
+    BadCapabilities = 0x0000_10EA => "the server received invalid capabilities",
+    InvalidChannelId = 0x0000_10EF => "an invalid channel id was received",
+    VChannelsTooMany = 0x0000_10F0 => "too many virtual channels were requested",
+    RemoteAppsNotEnabled = 0x0000_10F3 => "remote applications are not enabled on the server",
+    UpdateSessionKeyFailed = 0x0000_1191 => "the server failed to update the session keys",
+    DecryptFailed = 0x0000_1192 => "the server failed to decrypt a PDU",
+    EncryptFailed = 0x0000_1193 => "the server failed to encrypt a PDU",
+    EncPkgMismatch = 0x0000_1194 => "the encryption package does not match",
+    DecryptFailed2 = 0x0000_1195 => "the server failed to decrypt a PDU",
+}
+
+impl ErrorInfo {
+    /// The session is still alive on the server
+    /// and a new connection can reach it
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            ErrorInfo::None
+                | ErrorInfo::CloseStackOnDriverNotReady
+                | ErrorInfo::ServerDwmCrash
+                | ErrorInfo::CloseStackOnDriverFailure
+                | ErrorInfo::CloseStackOnDriverIfaceFailure
+                | ErrorInfo::ServerWinlogonCrash
+                | ErrorInfo::ServerCsrssCrash
+        )
+    }
+}
+
+impl fmt::Display for ErrorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (0x{:08X})", self.reason(), self.code())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Codes are decoded and encoded back
+    #[test]
+    fn test_error_info_code() {
+        assert_eq!(ErrorInfo::from(0x0C), ErrorInfo::LogoffByUser);
+        assert_eq!(ErrorInfo::from(0x400), ErrorInfo::CbDestinationNotFound);
+        assert_eq!(ErrorInfo::from(0xDEAD), ErrorInfo::Unknown(0xDEAD));
+        assert_eq!(ErrorInfo::LicenseNoLicense.code(), 0x102);
+        assert_eq!(ErrorInfo::Unknown(0xDEAD).code(), 0xDEAD);
+    }
+
+    /// Display shows the reason and the code
+    #[test]
+    fn test_error_info_display() {
+        assert_eq!(
+            ErrorInfo::RpcInitiatedDisconnect.to_string(),
+            "the disconnection was initiated by an administrative tool on the server (0x00000001)"
+        );
+        assert_eq!(ErrorInfo::Unknown(0xDEAD).to_string(), "unknown error (0x0000DEAD)");
+    }
+}
@@ -1,5 +1,16 @@
-use crate::codec::rle::{rgb565torgb32, rle_16_decompress, rle_32_decompress};
+use crate::codec::color::{bgr24_to_rgba, bgrx_to_rgba, rgb555_to_rgba, rgb565_to_rgba};
+use crate::codec::interleaved::interleaved_rle_decompress;
+use crate::codec::planar::planar_decompress;
+use crate::core::audin::client::AudinEvent;
+use crate::core::error_info::ErrorInfo;
+use crate::core::framebuffer::Rectangle;
+use crate::core::gcc::Monitor;
+use crate::core::order::window::WindowOrder;
+use crate::core::rdpei::client::RdpeiEvent;
+use crate::core::rdpevor::client::VideoEvent;
+use crate::core::urbdrc::client::UrbdrcEvent;
 use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
+use bytes::Bytes;
 use num_enum::TryFromPrimitive;
 
 /// A bitmap event is used
@@ -33,6 +44,7 @@ pub struct BitmapEvent {
 
 impl BitmapEvent {
     /// Decompress a bitmap which has been encoded by the RLE algorithm
+    /// Pixels are returned as RGBA whatever the bpp
     ///
     /// # Example
     /// ```no_run
@@ -60,51 +72,63 @@ impl BitmapEvent {
     /// }).unwrap()
     /// ```
     pub fn decompress(self) -> RdpResult<Vec<u8>> {
-        // actually only handle 32 bpp
+        // 8 bpp bitmaps are converted by Palette::apply
+        let width = self.width as usize;
+        let height = self.height as usize;
         match self.bpp {
             32 => {
                 // 32 bpp is straight forward
-                Ok(if self.is_compress {
-                    let mut result = vec![0 as u8; self.width as usize * self.height as usize * 4];
-                    rle_32_decompress(
-                        &self.data,
-                        self.width as u32,
-                        self.height as u32,
-                        &mut result,
-                    )?;
+                let data = if self.is_compress {
+                    // Planar rows are bottom up, compressed bitmaps are given top down
+                    let result = planar_decompress(&self.data, self.width, self.height)?;
+                    let row_size = width * 4;
+                    if row_size == 0 {
+                        return Ok(result);
+                    }
                     result
+                        .chunks_exact(row_size)
+                        .rev()
+                        .flatten()
+                        .copied()
+                        .collect()
                 } else {
                     self.data
-                })
+                };
+                let mut result = vec![0; data.len() / 4 * 4];
+                bgrx_to_rgba(&data, &mut result);
+                Ok(result)
             }
-            16 => {
-                // 16 bpp is more consumer
-                let result_16bpp = if self.is_compress {
-                    let mut result = vec![0 as u16; self.width as usize * self.height as usize * 2];
-                    rle_16_decompress(
-                        &self.data,
-                        self.width as usize,
-                        self.height as usize,
-                        &mut result,
-                    )?;
-                    result
+            15 | 16 | 24 => {
+                // Interleaved RLE gives the same bottom up rows
+                // as uncompressed bitmaps
+                let data = if self.is_compress {
+                    interleaved_rle_decompress(&self.data, self.width, self.height, self.bpp)?
                 } else {
-                    let mut result = vec![0 as u16; self.width as usize * self.height as usize];
-                    for i in 0..self.height {
-                        for j in 0..self.width {
-                            let src = (((self.height - i - 1) * self.width + j) * 2) as usize;
-                            result[(i * self.width + j) as usize] =
-                                (self.data[src + 1] as u16) << 8 | self.data[src] as u16;
-                        }
-                    }
-                    result
+                    self.data
                 };
-
-                Ok(rgb565torgb32(
-                    &result_16bpp,
-                    self.width as usize,
-                    self.height as usize,
-                ))
+                let row_size = width * (self.bpp as usize).div_ceil(8);
+                if row_size == 0 {
+                    return Ok(Vec::new());
+                }
+                if data.len() < row_size * height {
+                    return Err(Error::RdpError(RdpError::new(
+                        RdpErrorKind::InvalidSize,
+                        "Bitmap data is too short",
+                    )));
+                }
+                let mut result = vec![0; width * height * 4];
+                for (row, rgba) in data[..row_size * height]
+                    .chunks_exact(row_size)
+                    .rev()
+                    .zip(result.chunks_exact_mut(width * 4))
+                {
+                    match self.bpp {
+                        15 => rgb555_to_rgba(row, rgba),
+                        16 => rgb565_to_rgba(row, rgba),
+                        _ => bgr24_to_rgba(row, rgba),
+                    }
+                }
+                Ok(result)
             }
             _ => Err(Error::RdpError(RdpError::new(
                 RdpErrorKind::NotImplemented,
@@ -128,6 +152,10 @@ pub enum PointerButton {
     Right = 2,
     /// Wheel mouse button
     Middle = 3,
+    /// First extended button
+    X1 = 4,
+    /// Second extended button
+    X2 = 5,
 }
 
 /// A mouse pointer event
@@ -152,6 +180,137 @@ pub struct KeyboardEvent {
     pub down: bool,
 }
 
+/// Shape of the mouse pointer
+/// Pixels are RGBA from the top left corner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointerShape {
+    /// Index of the shape in the pointer cache
+    pub cache_index: u16,
+    pub hotspot_x: u16,
+    pub hotspot_y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub rgba: Vec<u8>,
+}
+
+/// Pointer updates sent by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CursorEvent {
+    /// The pointer must be hidden
+    Hidden,
+    /// Use the default system pointer
+    Default,
+    /// The server moved the pointer
+    Position { x: u16, y: u16 },
+    /// New pointer shape
+    Shape(PointerShape),
+}
+
+/// Boundary of a frame sent by the server
+/// Updates between the start and the end are parts of the same frame
+/// MS-RDPEGDI 2.2.2.2.1.3.7 Frame Marker (FRAME_MARKER)
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+pub enum FrameAction {
+    Start = 0x00000000,
+    End = 0x00000001,
+}
+
+/// Session state notifications sent by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// The user is logged on, the desktop is ready
+    LoggedOn {
+        domain: String,
+        user: String,
+        session_id: u32,
+    },
+    /// Logon notification without any information
+    LogonNotify,
+    /// Cookie used to reconnect to the same session
+    AutoReconnectCookie { logon_id: u32, random: [u8; 16] },
+    /// Logon error or warning
+    /// error_type is a LogonNotificationType or a LogonErrorType
+    LogonError { error_type: u32, error_data: u32 },
+    /// The server ran a deactivation reactivation sequence
+    /// The desktop size may have changed
+    Reactivated { width: u16, height: u16 },
+    /// The server is about to close the session
+    Terminated(ErrorInfo),
+    /// Monitors of the remote session
+    MonitorLayout(Vec<Monitor>),
+    /// Number of heartbeats missed since the last message
+    HeartbeatMissed(u8),
+    /// The server refused the logoff request
+    /// The user is asked to confirm it in the remote session
+    LogoffDenied,
+    /// Last event of a session
+    Ended(SessionEnd),
+    /// The connection was lost, a new one is attempted
+    /// Only sent by the reconnecting client
+    Reconnecting { attempt: u32 },
+    /// The session is back after a reconnection
+    Reconnected,
+    /// Frame boundary, sent with drawing orders or surface commands
+    Frame(FrameAction),
+}
+
+/// Why a session is over
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SessionEnd {
+    /// The user logged off, from the client or in the session
+    LoggedOff,
+    /// The server closed the connection
+    /// with the last error info it sent
+    Disconnected(ErrorInfo),
+    /// The connection was lost without any notice
+    Dropped,
+}
+
+/// Message received on a static virtual channel
+/// like `cliprdr` or `rdpdr`, once all its chunks are read
+///
+/// Channels opened with a handle don't send events
+/// MS-RDPBCGR 3.1.5.2.2 Reassembly of Chunked Virtual Channel Data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelEvent {
+    /// Name of the channel as requested by the client
+    pub name: String,
+    /// Slice of the received PDU when the message is not chunked
+    pub data: Bytes,
+}
+
+/// Event of a client of a dynamic virtual channel
+/// Graphics pipeline updates are bitmap and session events instead
+/// See `DynamicChannelClient`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DynamicChannelEvent {
+    AudioInput(AudinEvent),
+    Touch(RdpeiEvent),
+    Usb(UrbdrcEvent),
+    Video(VideoEvent),
+}
+
+/// Pixels of an updated region of the desktop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DamagedRegion {
+    pub rectangle: Rectangle,
+    /// RGBA pixels of the region, row by row
+    pub rgba: Vec<u8>,
+}
+
+/// Regions of the desktop updated since the previous frame
+/// Sent instead of the bitmaps once the frame rate is limited
+/// See `RdpClientBuilder::max_frame_rate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DamageEvent {
+    /// Size of the desktop
+    pub width: u16,
+    pub height: u16,
+    /// Merged regions, at least one
+    pub regions: Vec<DamagedRegion>,
+}
+
 /// All event handle by RDP protocol implemented by rdp-rs
 pub enum RdpEvent {
     /// Classic bitmap event
@@ -160,4 +319,16 @@ pub enum RdpEvent {
     Pointer(PointerEvent),
     /// Keyboard event
     Key(KeyboardEvent),
+    /// Session state event
+    Session(SessionEvent),
+    /// Pointer shape or position sent by the server
+    Cursor(CursorEvent),
+    /// Static virtual channel data
+    Channel(ChannelEvent),
+    /// Window, notification icon or desktop of a RemoteApp session
+    Window(WindowOrder),
+    /// Updated regions of a paced frame
+    Damage(DamageEvent),
+    /// Event of a dynamic virtual channel client
+    Dynamic(DynamicChannelEvent),
 }
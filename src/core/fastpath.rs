@@ -0,0 +1,316 @@
+use crate::codec::bulk::BulkDecompressor;
+use crate::core::event::BitmapEvent;
+use crate::core::limits::check_limit;
+use crate::core::metrics::Metrics;
+use crate::core::trace::trace_pdu;
+use crate::core::update::{read_bitmap_update, read_palette_update, Palette};
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, Bytes, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+
+/// Fast path update code
+/// MS-RDPBCGR 2.2.9.1.2.1 Fast-Path Update (TS_FP_UPDATE)
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum FastPathUpdateType {
+    FastpathUpdatetypeOrders = 0x0,
+    FastpathUpdatetypeBitmap = 0x1,
+    FastpathUpdatetypePalette = 0x2,
+    FastpathUpdatetypeSynchronize = 0x3,
+    FastpathUpdatetypeSurfcmds = 0x4,
+    FastpathUpdatetypePtrNull = 0x5,
+    FastpathUpdatetypePtrDefault = 0x6,
+    FastpathUpdatetypePtrPosition = 0x8,
+    FastpathUpdatetypeColor = 0x9,
+    FastpathUpdatetypeCached = 0xA,
+    FastpathUpdatetypePointer = 0xB,
+    FastpathUpdatetypeLargePointer = 0xC,
+}
+
+/// Fragmentation of a fast path update
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum Fragmentation {
+    FastpathFragmentSingle = 0x0,
+    FastpathFragmentLast = 0x1,
+    FastpathFragmentFirst = 0x2,
+    FastpathFragmentNext = 0x3,
+}
+
+/// The compression flags field is present
+const FASTPATH_OUTPUT_COMPRESSION_USED: u8 = 0x2;
+
+/// Default MaxRequestSize of the multifragment update capability
+const DEFAULT_MAX_UPDATE_SIZE: usize = 0xFFFF;
+
+/// A reassembled fast path update
+pub enum FastPathUpdate {
+    Orders(BytesMut),
+    Bitmap(Vec<BitmapEvent>),
+    Palette(Palette),
+    Synchronize,
+    SurfaceCommands(BytesMut),
+    /// Pointer updates keep their own code
+    Pointer(FastPathUpdateType, BytesMut),
+    Unknown(u8, BytesMut),
+}
+
+/// Dispatch a complete update on its code
+fn read_update_data(update_code: u8, mut data: BytesMut) -> Result<FastPathUpdate> {
+    Ok(match FastPathUpdateType::try_from(update_code) {
+        Ok(FastPathUpdateType::FastpathUpdatetypeOrders) => FastPathUpdate::Orders(data),
+        Ok(FastPathUpdateType::FastpathUpdatetypeBitmap) => {
+            FastPathUpdate::Bitmap(read_bitmap_update(&mut data)?)
+        }
+        Ok(FastPathUpdateType::FastpathUpdatetypePalette) => {
+            FastPathUpdate::Palette(read_palette_update(&mut data)?)
+        }
+        Ok(FastPathUpdateType::FastpathUpdatetypeSynchronize) => FastPathUpdate::Synchronize,
+        Ok(FastPathUpdateType::FastpathUpdatetypeSurfcmds) => {
+            FastPathUpdate::SurfaceCommands(data)
+        }
+        Ok(pointer_type) => FastPathUpdate::Pointer(pointer_type, data),
+        Err(_) => FastPathUpdate::Unknown(update_code, data),
+    })
+}
+
+/// Fast path output parser
+/// Keep the fragments of an update
+/// until the last one is received
+///
+/// # Example
+/// ```rust, ignore
+/// let mut reader = FastPathReader::default();
+/// let mut bulk = BulkDecompressor::default();
+/// for update in reader.read(&mut payload, &mut bulk)? {
+///     if let FastPathUpdate::Bitmap(bitmaps) = update {
+///         // draw bitmaps
+///     }
+/// }
+/// ```
+pub struct FastPathReader {
+    /// Update code and data of the pending fragments
+    fragments: Option<(u8, BytesMut)>,
+    /// Largest reassembled update, the MaxRequestSize
+    /// announced in the multifragment update capability
+    max_update_size: usize,
+    /// Count updates by type and parse errors
+    metrics: Metrics,
+}
+
+impl Default for FastPathReader {
+    fn default() -> Self {
+        FastPathReader::new(Metrics::default(), DEFAULT_MAX_UPDATE_SIZE)
+    }
+}
+
+impl FastPathReader {
+    pub fn new(metrics: Metrics, max_update_size: usize) -> Self {
+        FastPathReader {
+            fragments: None,
+            max_update_size,
+            metrics,
+        }
+    }
+
+    /// Parse all updates of a fast path output PDU
+    /// The payload must be decrypted, bulk compressed updates
+    /// are decompressed with the history of the session
+    /// MS-RDPBCGR 2.2.9.1.2 Server Fast-Path Update PDU (TS_FP_UPDATE_PDU)
+    pub fn read(
+        &mut self,
+        payload: &mut BytesMut,
+        bulk: &mut BulkDecompressor,
+    ) -> Result<Vec<FastPathUpdate>> {
+        let result = self.read_updates(payload, bulk);
+        if result.is_err() {
+            self.metrics.add_decode_error();
+        }
+        result
+    }
+
+    fn read_updates(
+        &mut self,
+        payload: &mut BytesMut,
+        bulk: &mut BulkDecompressor,
+    ) -> Result<Vec<FastPathUpdate>> {
+        let mut result = Vec::new();
+        while payload.has_remaining() {
+            let update_header = payload.get_u8();
+            let update_code = update_header & 0xF;
+            let fragmentation = (update_header >> 4) & 0x3;
+            let compression = (update_header >> 6) & 0x3;
+
+            let mut compression_flags = 0;
+            if compression & FASTPATH_OUTPUT_COMPRESSION_USED != 0 {
+                check_remaining(payload, 1, "FASTPATH: compression flags")?;
+                compression_flags = payload.get_u8();
+            }
+
+            check_remaining(payload, 2, "FASTPATH: update size")?;
+            let size = payload.get_u16_le() as usize;
+            check_remaining(payload, size, "FASTPATH: update data")?;
+            let mut data = payload.split_to(size);
+            if compression_flags != 0 {
+                // Takes the decompressed vector without copying it
+                data = Bytes::from(bulk.decompress(&data, compression_flags)?).into();
+            }
+            trace_pdu!(update_code, fragmentation, size, "FASTPATH: received update");
+
+            if let Some(data) = self.reassemble(update_code, fragmentation, data)? {
+                match FastPathUpdateType::try_from(update_code) {
+                    Ok(update_type) => self.metrics.add_pdu(&format!("{:?}", update_type)),
+                    Err(_) => self.metrics.add_pdu("FastpathUpdatetypeUnknown"),
+                }
+                result.push(read_update_data(update_code, data)?);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Return the update data once all fragments are received
+    /// Fragments are dropped when the update grows past the MaxRequestSize
+    fn reassemble(
+        &mut self,
+        update_code: u8,
+        fragmentation: u8,
+        data: BytesMut,
+    ) -> Result<Option<BytesMut>> {
+        match (Fragmentation::try_from(fragmentation), self.fragments.take()) {
+            (Ok(Fragmentation::FastpathFragmentSingle), None) => Ok(Some(data)),
+            (Ok(Fragmentation::FastpathFragmentFirst), None) => {
+                self.fragments = Some((update_code, data));
+                Ok(None)
+            }
+            (Ok(Fragmentation::FastpathFragmentNext), Some((code, mut fragments)))
+                if code == update_code =>
+            {
+                self.check_update_size(fragments.len() + data.len())?;
+                fragments.unsplit(data);
+                self.fragments = Some((code, fragments));
+                Ok(None)
+            }
+            (Ok(Fragmentation::FastpathFragmentLast), Some((code, mut fragments)))
+                if code == update_code =>
+            {
+                self.check_update_size(fragments.len() + data.len())?;
+                fragments.unsplit(data);
+                Ok(Some(fragments))
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                "FASTPATH: unexpected update fragment",
+            )),
+        }
+    }
+
+    fn check_update_size(&self, size: usize) -> Result<()> {
+        check_limit(size, self.max_update_size, "FASTPATH: reassembled update size")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::limits::is_too_large;
+    use bytes::BufMut;
+
+    /// Write an update header without compression
+    fn put_update(buffer: &mut BytesMut, code: u8, fragmentation: u8, data: &[u8]) {
+        buffer.put_u8(code | (fragmentation << 4));
+        buffer.put_u16_le(data.len() as u16);
+        buffer.put_slice(data);
+    }
+
+    /// Test of a single synchronize update
+    #[test]
+    fn test_read_single_update() {
+        let mut buffer = BytesMut::new();
+        put_update(&mut buffer, FastPathUpdateType::FastpathUpdatetypeSynchronize as u8, 0, &[]);
+        put_update(&mut buffer, FastPathUpdateType::FastpathUpdatetypePtrNull as u8, 0, &[]);
+
+        let mut bulk = BulkDecompressor::default();
+        let mut reader = FastPathReader::default();
+        let updates = reader.read(&mut buffer, &mut bulk).unwrap();
+        assert_eq!(updates.len(), 2);
+        assert!(matches!(updates[0], FastPathUpdate::Synchronize));
+        assert!(matches!(
+            updates[1],
+            FastPathUpdate::Pointer(FastPathUpdateType::FastpathUpdatetypePtrNull, _)
+        ));
+    }
+
+    /// Test of an update split over three fragments
+    #[test]
+    fn test_read_fragmented_update() {
+        let code = FastPathUpdateType::FastpathUpdatetypeBitmap as u8;
+        let mut reader = FastPathReader::default();
+        let mut bulk = BulkDecompressor::default();
+
+        let mut first = BytesMut::new();
+        put_update(&mut first, code, Fragmentation::FastpathFragmentFirst as u8, &[1, 0]);
+        assert!(reader.read(&mut first, &mut bulk).unwrap().is_empty());
+
+        let mut next = BytesMut::new();
+        put_update(&mut next, code, Fragmentation::FastpathFragmentNext as u8, &[0]);
+        assert!(reader.read(&mut next, &mut bulk).unwrap().is_empty());
+
+        let mut last = BytesMut::new();
+        put_update(&mut last, code, Fragmentation::FastpathFragmentLast as u8, &[0]);
+        let updates = reader.read(&mut last, &mut bulk).unwrap();
+        assert!(matches!(&updates[..], [FastPathUpdate::Bitmap(bitmaps)] if bitmaps.is_empty()));
+    }
+
+    /// A last fragment without a first one is an error
+    #[test]
+    fn test_read_orphan_fragment() {
+        let mut buffer = BytesMut::new();
+        put_update(&mut buffer, 0x1, Fragmentation::FastpathFragmentLast as u8, &[1, 0, 0, 0]);
+        let mut bulk = BulkDecompressor::default();
+        let mut reader = FastPathReader::default();
+        assert!(reader.read(&mut buffer, &mut bulk).is_err());
+    }
+
+    /// Fragments past the MaxRequestSize are a TooLarge error
+    #[test]
+    fn test_read_oversized_update() {
+        let code = FastPathUpdateType::FastpathUpdatetypeSurfcmds as u8;
+        let mut reader = FastPathReader::new(Metrics::default(), 0x1000);
+        let mut bulk = BulkDecompressor::default();
+
+        let mut first = BytesMut::new();
+        put_update(&mut first, code, Fragmentation::FastpathFragmentFirst as u8, &[0; 0x800]);
+        assert!(reader.read(&mut first, &mut bulk).unwrap().is_empty());
+
+        let mut next = BytesMut::new();
+        put_update(&mut next, code, Fragmentation::FastpathFragmentNext as u8, &[0; 0x800]);
+        assert!(reader.read(&mut next, &mut bulk).unwrap().is_empty());
+
+        let mut next = BytesMut::new();
+        put_update(&mut next, code, Fragmentation::FastpathFragmentNext as u8, &[0; 1]);
+        let error = reader.read(&mut next, &mut bulk).err().unwrap();
+        assert!(is_too_large(&error));
+
+        // The pending fragments are dropped with the error
+        let mut last = BytesMut::new();
+        put_update(&mut last, code, Fragmentation::FastpathFragmentLast as u8, &[0]);
+        assert!(reader.read(&mut last, &mut bulk).is_err());
+    }
+
+    /// Compressed updates are decompressed before their parsing
+    #[test]
+    fn test_read_compressed_update() {
+        let mut bulk = BulkDecompressor::default();
+        // Synchronize update of a single padding byte
+        let mut buffer = BytesMut::from(&[0x83, 0x21, 1, 0, 0][..]);
+        let mut reader = FastPathReader::default();
+        let updates = reader.read(&mut buffer, &mut bulk).unwrap();
+        assert!(matches!(updates[..], [FastPathUpdate::Synchronize]));
+
+        // Compression types of RDP 6.0 and later aren't supported
+        let mut buffer = BytesMut::from(&[0x83, 0x22, 1, 0, 0][..]);
+        assert!(reader.read(&mut buffer, &mut bulk).is_err());
+    }
+}
@@ -0,0 +1,513 @@
+use crate::core::event::{BitmapEvent, DamageEvent, DamagedRegion, RdpEvent, SessionEvent};
+
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, Instant};
+
+/// Above this number of damaged regions
+/// they are merged into their bounding box
+const MAX_DIRTY_RECTANGLES: usize = 64;
+
+/// Region of the framebuffer
+/// Right and bottom are exclusive
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Rectangle {
+    pub left: u16,
+    pub top: u16,
+    pub right: u16,
+    pub bottom: u16,
+}
+
+impl Rectangle {
+    pub fn width(&self) -> u16 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> u16 {
+        self.bottom - self.top
+    }
+
+    fn area(&self) -> u32 {
+        self.width() as u32 * self.height() as u32
+    }
+
+    /// Smallest rectangle which contains both
+    fn union(&self, other: &Rectangle) -> Rectangle {
+        Rectangle {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    fn intersection_area(&self, other: &Rectangle) -> u32 {
+        let width = self
+            .right
+            .min(other.right)
+            .saturating_sub(self.left.max(other.left));
+        let height = self
+            .bottom
+            .min(other.bottom)
+            .saturating_sub(self.top.max(other.top));
+        width as u32 * height as u32
+    }
+}
+
+/// Image of the remote desktop built from the updates
+/// Pixels are RGBA from the top left corner
+///
+/// Each update marks its region as damaged, the regions
+/// are merged when it doesn't add any undamaged pixel
+///
+/// # Example
+/// ```
+/// use rdp::core::event::BitmapEvent;
+/// use rdp::core::framebuffer::{Framebuffer, Rectangle};
+/// let mut framebuffer = Framebuffer::new(4, 4);
+/// framebuffer.apply_bitmap(BitmapEvent {
+///     dest_left: 1,
+///     dest_top: 1,
+///     dest_right: 1,
+///     dest_bottom: 1,
+///     width: 1,
+///     height: 1,
+///     bpp: 32,
+///     is_compress: false,
+///     data: vec![0x30, 0x20, 0x10, 0xFF],
+/// }).unwrap();
+/// assert_eq!(framebuffer.pixel(1, 1), Some([0x10, 0x20, 0x30, 0xFF]));
+/// assert_eq!(
+///     framebuffer.take_dirty(),
+///     vec![Rectangle { left: 1, top: 1, right: 2, bottom: 2 }]
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    width: u16,
+    height: u16,
+    data: Vec<u8>,
+    /// Regions updated since the last take_dirty
+    dirty: Vec<Rectangle>,
+}
+
+impl Framebuffer {
+    /// Black framebuffer
+    pub fn new(width: u16, height: u16) -> Self {
+        Framebuffer {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 4],
+            dirty: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// RGBA pixels, row by row
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Color of a pixel
+    pub fn pixel(&self, x: u16, y: u16) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.data[offset..offset + 4]);
+        Some(pixel)
+    }
+
+    /// Copy of the desktop as an image
+    #[cfg(feature = "image")]
+    pub fn to_image(&self) -> image::RgbaImage {
+        image::RgbaImage::from_raw(self.width as u32, self.height as u32, self.data.clone())
+            .expect("the buffer always holds the whole desktop")
+    }
+
+    /// Change the size of the desktop
+    /// The content is lost and the whole framebuffer is damaged
+    pub fn resize(&mut self, width: u16, height: u16) {
+        *self = Framebuffer::new(width, height);
+        self.damage(Rectangle {
+            left: 0,
+            top: 0,
+            right: width,
+            bottom: height,
+        });
+    }
+
+    /// Apply an event given by the session
+    /// Events which don't change the desktop are ignored
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let mut framebuffer = Framebuffer::new(800, 600);
+    /// client.read(|event| {
+    ///     if let Err(e) = framebuffer.apply(event) {
+    ///         println!("Invalid update: {}", e);
+    ///     }
+    /// }).await?;
+    /// for rectangle in framebuffer.take_dirty() {
+    ///     // redraw the rectangle
+    /// }
+    /// ```
+    pub fn apply(&mut self, event: RdpEvent) -> Result<()> {
+        match event {
+            RdpEvent::Bitmap(bitmap) => self.apply_bitmap(bitmap),
+            RdpEvent::Session(SessionEvent::Reactivated { width, height })
+                if (width, height) != (self.width, self.height) =>
+            {
+                self.resize(width, height);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Draw a bitmap update, clipped to the framebuffer
+    pub fn apply_bitmap(&mut self, bitmap: BitmapEvent) -> Result<()> {
+        let bitmap_width = bitmap.width as usize;
+        let bitmap_height = bitmap.height as usize;
+        // Uncompressed bitmaps are bottom up
+        let bottom_up = !bitmap.is_compress && bitmap.bpp == 32;
+        let destination = Rectangle {
+            left: bitmap.dest_left,
+            top: bitmap.dest_top,
+            right: bitmap.dest_right.saturating_add(1).min(self.width),
+            bottom: bitmap.dest_bottom.saturating_add(1).min(self.height),
+        };
+        if destination.left >= destination.right || destination.top >= destination.bottom {
+            return Ok(());
+        }
+
+        let data = bitmap.decompress().map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("FRAMEBUFFER: invalid bitmap {:?}", e),
+            )
+        })?;
+        if data.len() < bitmap_width * bitmap_height * 4 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "FRAMEBUFFER: bitmap data is too short",
+            ));
+        }
+
+        let width = (destination.width() as usize).min(bitmap_width);
+        let height = (destination.height() as usize).min(bitmap_height);
+        for row in 0..height {
+            let source_row = if bottom_up {
+                bitmap_height - row - 1
+            } else {
+                row
+            };
+            let source = &data[source_row * bitmap_width * 4..][..width * 4];
+            let offset = ((destination.top as usize + row) * self.width as usize
+                + destination.left as usize)
+                * 4;
+            self.data[offset..offset + width * 4].copy_from_slice(source);
+        }
+
+        self.damage(Rectangle {
+            right: destination.left + width as u16,
+            bottom: destination.top + height as u16,
+            ..destination
+        });
+        Ok(())
+    }
+
+    /// Mark a region as updated, clipped to the framebuffer
+    pub fn damage(&mut self, rectangle: Rectangle) {
+        let mut rectangle = Rectangle {
+            right: rectangle.right.min(self.width),
+            bottom: rectangle.bottom.min(self.height),
+            ..rectangle
+        };
+        if rectangle.left >= rectangle.right || rectangle.top >= rectangle.bottom {
+            return;
+        }
+        // Merge as long as no undamaged pixel is added
+        while let Some(index) = self.dirty.iter().position(|other| {
+            rectangle.union(other).area() + rectangle.intersection_area(other)
+                <= rectangle.area() + other.area()
+        }) {
+            rectangle = rectangle.union(&self.dirty.swap_remove(index));
+        }
+        self.dirty.push(rectangle);
+
+        if self.dirty.len() > MAX_DIRTY_RECTANGLES {
+            let bounds = self
+                .dirty
+                .iter()
+                .skip(1)
+                .fold(self.dirty[0], |bounds, other| bounds.union(other));
+            self.dirty = vec![bounds];
+        }
+    }
+
+    /// Regions updated since the last call
+    pub fn take_dirty(&mut self) -> Vec<Rectangle> {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// True once a region is updated, until the next take
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// RGBA pixels of a region inside the framebuffer, row by row
+    pub fn region(&self, rectangle: &Rectangle) -> Vec<u8> {
+        let row_size = rectangle.width() as usize * 4;
+        let mut rgba = Vec::with_capacity(row_size * rectangle.height() as usize);
+        for y in rectangle.top..rectangle.bottom {
+            let offset = (y as usize * self.width as usize + rectangle.left as usize) * 4;
+            rgba.extend_from_slice(&self.data[offset..offset + row_size]);
+        }
+        rgba
+    }
+
+    /// Regions updated since the last call with their pixels
+    pub fn take_damage(&mut self) -> DamageEvent {
+        let regions = self
+            .take_dirty()
+            .into_iter()
+            .map(|rectangle| DamagedRegion {
+                rgba: self.region(&rectangle),
+                rectangle,
+            })
+            .collect();
+        DamageEvent {
+            width: self.width,
+            height: self.height,
+            regions,
+        }
+    }
+}
+
+/// Coalesce the bitmap updates of a session into at most
+/// a number of frames per second, so frontends redraw
+/// at their own pace whatever the rate of the updates
+///
+/// Bitmaps are drawn on a framebuffer, each frame
+/// gives the regions updated since the previous one
+///
+/// # Example
+/// ```rust, ignore
+/// let mut pacer = FramePacer::new(800, 600, 30);
+/// client.read(|event| match pacer.apply(event) {
+///     Ok(Some(event)) => {
+///         // cursor, session or channel event
+///     }
+///     Ok(None) => (),
+///     Err(e) => println!("Invalid update: {}", e),
+/// }).await?;
+/// if pacer.deadline().map_or(false, |deadline| deadline <= Instant::now()) {
+///     let frame = pacer.take_frame();
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct FramePacer {
+    framebuffer: Framebuffer,
+    /// Shortest time between two frames
+    interval: Duration,
+    /// When the previous frame was taken
+    last_frame: Option<Instant>,
+}
+
+impl FramePacer {
+    /// At most `frames_per_second` frames, at least one
+    pub fn new(width: u16, height: u16, frames_per_second: u32) -> Self {
+        FramePacer {
+            framebuffer: Framebuffer::new(width, height),
+            interval: Duration::from_secs(1) / frames_per_second.max(1),
+            last_frame: None,
+        }
+    }
+
+    /// Desktop built from the updates
+    pub fn get_framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// Draw the bitmaps, and resize the framebuffer on reactivation
+    ///
+    /// Other events are given back to be sent at once
+    /// Frame markers are dropped since the frames are paced
+    pub fn apply(&mut self, event: RdpEvent) -> Result<Option<RdpEvent>> {
+        match event {
+            RdpEvent::Bitmap(bitmap) => {
+                self.framebuffer.apply_bitmap(bitmap)?;
+                Ok(None)
+            }
+            RdpEvent::Session(SessionEvent::Frame(_)) => Ok(None),
+            RdpEvent::Session(SessionEvent::Reactivated { width, height }) => {
+                if (width, height) != (self.framebuffer.width, self.framebuffer.height) {
+                    self.framebuffer.resize(width, height);
+                }
+                Ok(Some(event))
+            }
+            event => Ok(Some(event)),
+        }
+    }
+
+    /// When the next frame is due, None while nothing changed
+    pub fn deadline(&self) -> Option<Instant> {
+        if !self.framebuffer.is_dirty() {
+            return None;
+        }
+        Some(match self.last_frame {
+            Some(last_frame) => last_frame + self.interval,
+            None => Instant::now(),
+        })
+    }
+
+    /// Regions updated since the previous frame
+    /// None while nothing changed
+    pub fn take_frame(&mut self) -> Option<DamageEvent> {
+        if !self.framebuffer.is_dirty() {
+            return None;
+        }
+        self.last_frame = Some(Instant::now());
+        Some(self.framebuffer.take_damage())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::event::{CursorEvent, FrameAction};
+
+    fn bitmap(left: u16, top: u16, width: u16, height: u16) -> BitmapEvent {
+        BitmapEvent {
+            dest_left: left,
+            dest_top: top,
+            dest_right: left + width - 1,
+            dest_bottom: top + height - 1,
+            width,
+            height,
+            bpp: 32,
+            is_compress: false,
+            data: (0..width as usize * height as usize)
+                .flat_map(|index| [index as u8, 0, 0, 0xFF])
+                .collect(),
+        }
+    }
+
+    /// Uncompressed rows are drawn bottom up
+    /// and bitmaps are clipped to the framebuffer
+    #[test]
+    fn test_apply_bitmap() {
+        let mut framebuffer = Framebuffer::new(3, 3);
+        framebuffer.apply_bitmap(bitmap(1, 1, 2, 3)).unwrap();
+        assert_eq!(framebuffer.pixel(1, 1), Some([0, 0, 4, 0xFF]));
+        assert_eq!(framebuffer.pixel(2, 2), Some([0, 0, 3, 0xFF]));
+        assert_eq!(framebuffer.pixel(0, 0), Some([0, 0, 0, 0]));
+        assert_eq!(
+            framebuffer.take_dirty(),
+            vec![Rectangle {
+                left: 1,
+                top: 1,
+                right: 3,
+                bottom: 3
+            }]
+        );
+        assert!(framebuffer.take_dirty().is_empty());
+    }
+
+    /// Adjacent regions are merged, distant ones are kept apart
+    #[test]
+    fn test_damage_coalesced() {
+        let mut framebuffer = Framebuffer::new(100, 100);
+        for left in [0, 10, 20] {
+            framebuffer.damage(Rectangle {
+                left,
+                top: 0,
+                right: left + 10,
+                bottom: 10,
+            });
+        }
+        framebuffer.damage(Rectangle {
+            left: 50,
+            top: 50,
+            right: 60,
+            bottom: 60,
+        });
+        assert_eq!(
+            framebuffer.take_dirty(),
+            vec![
+                Rectangle {
+                    left: 0,
+                    top: 0,
+                    right: 30,
+                    bottom: 10
+                },
+                Rectangle {
+                    left: 50,
+                    top: 50,
+                    right: 60,
+                    bottom: 60
+                }
+            ]
+        );
+    }
+
+    /// A new desktop size damages the whole framebuffer
+    #[test]
+    fn test_apply_reactivated() {
+        let mut framebuffer = Framebuffer::new(10, 10);
+        let event = RdpEvent::Session(SessionEvent::Reactivated {
+            width: 20,
+            height: 5,
+        });
+        framebuffer.apply(event).unwrap();
+        assert_eq!((framebuffer.width(), framebuffer.height()), (20, 5));
+        assert_eq!(framebuffer.data().len(), 400);
+        assert_eq!(framebuffer.take_dirty()[0].area(), 100);
+    }
+
+    /// Bitmaps between two frames are merged into a single damage
+    #[test]
+    fn test_frame_pacer() {
+        let mut pacer = FramePacer::new(4, 4, 10);
+        assert!(pacer
+            .apply(RdpEvent::Bitmap(bitmap(0, 0, 2, 1)))
+            .unwrap()
+            .is_none());
+        assert!(pacer
+            .apply(RdpEvent::Bitmap(bitmap(2, 0, 2, 1)))
+            .unwrap()
+            .is_none());
+        let frame_end = RdpEvent::Session(SessionEvent::Frame(FrameAction::End));
+        assert!(pacer.apply(frame_end).unwrap().is_none());
+        let cursor = RdpEvent::Cursor(CursorEvent::Hidden);
+        assert!(pacer.apply(cursor).unwrap().is_some());
+        assert!(pacer.deadline().unwrap() <= Instant::now());
+
+        let frame = pacer.take_frame().unwrap();
+        assert_eq!(
+            frame.regions,
+            vec![DamagedRegion {
+                rectangle: Rectangle {
+                    left: 0,
+                    top: 0,
+                    right: 4,
+                    bottom: 1
+                },
+                rgba: vec![0, 0, 0, 0xFF, 0, 0, 1, 0xFF, 0, 0, 0, 0xFF, 0, 0, 1, 0xFF],
+            }]
+        );
+        assert!(pacer.take_frame().is_none());
+
+        // The next frame waits for the interval
+        pacer.apply(RdpEvent::Bitmap(bitmap(0, 1, 1, 1))).unwrap();
+        assert!(pacer.deadline().unwrap() > Instant::now());
+    }
+}
@@ -1,399 +1,1400 @@
-// use crate::core::per;
-// use crate::model::data::{
-//     to_vec, Array, Check, Component, DataType, DynOption, Message, MessageOption, Trame, U16, U32,
-// };
-// use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
-// use crate::model::unicode::Unicode;
-
-// use std::collections::HashMap;
-// use std::io::{Cursor, Read};
-
-// const T124_02_98_OID: [u8; 6] = [0, 0, 20, 124, 0, 1];
-// const H221_CS_KEY: [u8; 4] = *b"Duca";
-// const H221_SC_KEY: [u8; 4] = *b"McDn";
-
-// /// RDP protocol version
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
-// #[repr(u32)]
-// #[allow(dead_code)]
-// #[derive(Copy, Clone, Eq, PartialEq)]
-// pub enum Version {
-//     RdpVersion = 0x00080001,
-//     RdpVersion5plus = 0x00080004,
-//     Unknown,
-// }
-
-// impl From<u32> for Version {
-//     fn from(e: u32) -> Self {
-//         match e {
-//             0x00080001 => Version::RdpVersion5plus,
-//             0x00080004 => Version::RdpVersion,
-//             _ => Version::Unknown,
-//         }
-//     }
-// }
-
-// /// Color depth
-// /// This flag is deprecated
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
-// #[repr(u16)]
-// #[allow(dead_code)]
-// enum ColorDepth {
-//     RnsUdColor8BPP = 0xCA01,
-//     RnsUdColor16BPP555 = 0xCA02,
-//     RnsUdColor16BPP565 = 0xCA03,
-//     RnsUdColor24BPP = 0xCA04,
-// }
-
-// #[repr(u16)]
-// enum Sequence {
-//     RnsUdSasDel = 0xAA03,
-// }
-
-// /// Keyboard layout
-// /// https://docs.microsoft.com/en-us/previous-versions/windows/it-pro/windows-vista/cc766503(v=ws.10)?redirectedfrom=MSDN
-// #[repr(u32)]
-// #[derive(Copy, Clone)]
-// pub enum KeyboardLayout {
-//     Arabic = 0x00000401,
-//     Bulgarian = 0x00000402,
-//     ChineseUsKeyboard = 0x00000404,
-//     Czech = 0x00000405,
-//     Danish = 0x00000406,
-//     German = 0x00000407,
-//     Greek = 0x00000408,
-//     US = 0x00000409,
-//     Spanish = 0x0000040a,
-//     Finnish = 0x0000040b,
-//     French = 0x0000040c,
-//     Hebrew = 0x0000040d,
-//     Hungarian = 0x0000040e,
-//     Icelandic = 0x0000040f,
-//     Italian = 0x00000410,
-//     Japanese = 0x00000411,
-//     Korean = 0x00000412,
-//     Dutch = 0x00000413,
-//     Norwegian = 0x00000414,
-// }
-
-// /// Keyboard type
-// /// Ibm101102Keys is the most common keyboard type
-// #[repr(u32)]
-// #[allow(dead_code)]
-// pub enum KeyboardType {
-//     IbmPcXt83Key = 0x00000001,
-//     Olivetti = 0x00000002,
-//     IbmPcAt84Key = 0x00000003,
-//     Ibm101102Keys = 0x00000004,
-//     Nokia1050 = 0x00000005,
-//     Nokia9140 = 0x00000006,
-//     Japanese = 0x00000007,
-// }
-
-// #[repr(u16)]
-// #[allow(dead_code)]
-// enum HighColor {
-//     HighColor4BPP = 0x0004,
-//     HighColor8BPP = 0x0008,
-//     HighColor15BPP = 0x000f,
-//     HighColor16BPP = 0x0010,
-//     HighColor24BPP = 0x0018,
-// }
-
-// /// Supported color depth
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
-// #[repr(u16)]
-// #[allow(dead_code)]
-// enum Support {
-//     RnsUd24BPPSupport = 0x0001,
-//     RnsUd16BPPSupport = 0x0002,
-//     RnsUd15BPPSupport = 0x0004,
-//     RnsUd32BPPSupport = 0x0008,
-// }
-
-// /// Negotiation of some capability for pdu layer
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
-// #[repr(u16)]
-// #[allow(dead_code)]
-// enum CapabilityFlag {
-//     RnsUdCsSupportErrinfoPDU = 0x0001,
-//     RnsUdCsWant32BPPSession = 0x0002,
-//     RnsUdCsSupportStatusInfoPdu = 0x0004,
-//     RnsUdCsStrongAsymmetricKeys = 0x0008,
-//     RnsUdCsUnused = 0x0010,
-//     RnsUdCsValidConnectionType = 0x0020,
-//     RnsUdCsSupportMonitorLayoutPDU = 0x0040,
-//     RnsUdCsSupportNetcharAutodetect = 0x0080,
-//     RnsUdCsSupportDynvcGFXProtocol = 0x0100,
-//     RnsUdCsSupportDynamicTimezone = 0x0200,
-//     RnsUdCsSupportHeartbeatPDU = 0x0400,
-// }
-
-// /// Supported encryption method
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/6b58e11e-a32b-4903-b736-339f3cfe46ec?redirectedfrom=MSDN
-// #[repr(u32)]
-// #[allow(dead_code)]
-// enum EncryptionMethod {
-//     EncryptionFlag40bit = 0x00000001,
-//     EncryptionFlag128bit = 0x00000002,
-//     EncryptionFlag56bit = 0x00000008,
-//     FipsEncryptionFlag = 0x00000010,
-// }
-
-// /// Encryption level
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/3e86b68d-3e2e-4433-b486-878875778f4b?redirectedfrom=MSDN
-// #[allow(dead_code)]
-// enum EncryptionLevel {
-//     None = 0x00000000,
-//     Low = 0x00000001,
-//     ClientCompatible = 0x00000002,
-//     High = 0x00000003,
-//     Fips = 0x00000004,
-// }
-
-// #[repr(u16)]
-// #[derive(Eq, PartialEq, Hash)]
-// pub enum MessageType {
-//     //server -> client
-//     ScCore = 0x0C01,
-//     ScSecurity = 0x0C02,
-//     ScNet = 0x0C03,
-//     //client -> server
-//     CsCore = 0xC001,
-//     CsSecurity = 0xC002,
-//     CsNet = 0xC003,
-//     CsCluster = 0xC004,
-//     CsMonitor = 0xC005,
-//     Unknown = 0,
-// }
-
-// impl From<u16> for MessageType {
-//     fn from(e: u16) -> Self {
-//         match e {
-//             0x0C01 => MessageType::ScCore,
-//             0x0C02 => MessageType::ScSecurity,
-//             0x0C03 => MessageType::ScNet,
-//             0xC001 => MessageType::CsCore,
-//             0xC002 => MessageType::CsSecurity,
-//             0xC003 => MessageType::CsNet,
-//             0xC004 => MessageType::CsCluster,
-//             0xC005 => MessageType::CsMonitor,
-//             _ => MessageType::Unknown,
-//         }
-//     }
-// }
-
-// /// In case of client
-// /// This is all mandatory fields need by client core data
-// #[derive(Clone)]
-// pub struct ClientData {
-//     pub width: u16,
-//     pub height: u16,
-//     pub layout: KeyboardLayout,
-//     pub server_selected_protocol: u32,
-//     pub rdp_version: Version,
-//     pub name: String,
-// }
-
-// /// This is the first client specific data
-// ///
-// /// This field are obsolete and for modern
-// /// RDP they are not use
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
-// pub fn client_core_data(parameter: Option<ClientData>) -> Component {
-//     let client_parameter = parameter.unwrap_or(ClientData {
-//         width: 0,
-//         height: 0,
-//         layout: KeyboardLayout::French,
-//         server_selected_protocol: 0,
-//         rdp_version: Version::RdpVersion5plus,
-//         name: "".to_string(),
-//     });
-
-//     let client_name = if client_parameter.name.len() >= 16 {
-//         (&client_parameter.name[0..16]).to_string()
-//     } else {
-//         client_parameter.name.clone() + &"\x00".repeat(16 - client_parameter.name.len())
-//     };
-
-//     component![
-//         "version" => U32::LE(client_parameter.rdp_version as u32),
-//         "desktopWidth" => U16::LE(client_parameter.width),
-//         "desktopHeight" => U16::LE(client_parameter.height),
-//         "colorDepth" => U16::LE(ColorDepth::RnsUdColor8BPP as u16),
-//         "sasSequence" => U16::LE(Sequence::RnsUdSasDel as u16),
-//         "kbdLayout" => U32::LE(client_parameter.layout as u32),
-//         "clientBuild" => U32::LE(3790),
-//         "clientName" => client_name.to_string().to_unicode(),
-//         "keyboardType" => U32::LE(KeyboardType::Ibm101102Keys as u32),
-//         "keyboardSubType" => U32::LE(0),
-//         "keyboardFnKeys" => U32::LE(12),
-//         "imeFileName" => vec![0 as u8; 64],
-//         "postBeta2ColorDepth" => U16::LE(ColorDepth::RnsUdColor8BPP as u16),
-//         "clientProductId" => U16::LE(1),
-//         "serialNumber" => U32::LE(0),
-//         "highColorDepth" => U16::LE(HighColor::HighColor24BPP as u16),
-//         "supportedColorDepths" => U16::LE(
-//             //Support::RnsUd15BPPSupport as u16 |
-//             Support::RnsUd16BPPSupport as u16 |
-//             //Support::RnsUd24BPPSupport as u16 |
-//             Support::RnsUd32BPPSupport as u16
-//             ),
-//         "earlyCapabilityFlags" => U16::LE(CapabilityFlag::RnsUdCsSupportErrinfoPDU as u16),
-//         "clientDigProductId" => vec![0; 64],
-//         "connectionType" => 0 as u8,
-//         "pad1octet" => 0 as u8,
-//         "serverSelectedProtocol" => U32::LE(client_parameter.server_selected_protocol)
-//     ]
-// }
-
-// pub fn server_core_data() -> Component {
-//     component![
-//         "rdpVersion" => U32::LE(0),
-//         "clientRequestedProtocol" => Some(U32::LE(0)),
-//         "earlyCapabilityFlags" => Some(U32::LE(0))
-//     ]
-// }
-
-// /// Client security releated to deprecated RDP security layer
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/6b58e11e-a32b-4903-b736-339f3cfe46ec?redirectedfrom=MSDN
-// pub fn client_security_data() -> Component {
-//     component![
-//         "encryptionMethods" => U32::LE(
-//             EncryptionMethod::EncryptionFlag40bit as u32 |
-//             EncryptionMethod::EncryptionFlag56bit as u32 |
-//             EncryptionMethod::EncryptionFlag128bit as u32
-//          ),
-//         "extEncryptionMethods" => U32::LE(0)
-//     ]
-// }
-
-// /// In case of non ssl security layer
-// /// we need to check data in this packet
-// pub fn server_security_data() -> Component {
-//     component![
-//         "encryptionMethod" => U32::LE(0),
-//         "encryptionLevel" => U32::LE(0)
-//     ]
-// }
-
-// /// Actually we have no more classic channel
-// pub fn channel_def(name: &String, options: u32) -> Component {
-//     component![
-//         "name"=> name.as_bytes().to_vec(),
-//         "options" => U32::LE(options)
-//     ]
-// }
-
-// /// Actually we have no more channel than the classic one
-// pub fn client_network_data(channel_def_array: Trame) -> Component {
-//     component![
-//         "channelCount" => U32::LE(channel_def_array.len() as u32),
-//         "channelDefArray" => to_vec(&channel_def_array)
-//     ]
-// }
-
-// pub fn server_network_data() -> Component {
-//     component![
-//         "MCSChannelId" => Check::new(U16::LE(1003)),
-//         "channelCount" => DynOption::new(U16::LE(0), |count| MessageOption::Size("channelIdArray".to_string(), count.inner() as usize * 2)),
-//         "channelIdArray" => Array::new(|| U16::LE(0))
-//     ]
-// }
-
-// pub fn block_header(data_type: Option<MessageType>, length: Option<u16>) -> Component {
-//     component![
-//         "type" => U16::LE(data_type.unwrap_or(MessageType::CsCore) as u16),
-//         "length" => U16::LE(length.unwrap_or(0) as u16 + 4)
-//     ]
-// }
-
-// pub fn write_conference_create_request(user_data: &[u8]) -> RdpResult<Vec<u8>> {
-//     let mut result = Cursor::new(vec![]);
-//     per::write_choice(0, &mut result)?;
-//     per::write_object_identifier(&T124_02_98_OID, &mut result)?;
-//     per::write_length(user_data.len() as u16 + 14)?.write(&mut result)?;
-//     per::write_choice(0, &mut result)?;
-//     per::write_selection(0x08, &mut result)?;
-//     per::write_numeric_string(b"1", 1, &mut result)?;
-//     per::write_padding(1, &mut result)?;
-//     per::write_number_of_set(1, &mut result)?;
-//     per::write_choice(0xc0, &mut result)?;
-//     per::write_octet_stream(&H221_CS_KEY, 4, &mut result)?;
-//     per::write_octet_stream(user_data, 0, &mut result)?;
-//     Ok(result.into_inner())
-// }
-
-// pub struct ServerData {
-//     pub channel_ids: Vec<u16>,
-//     pub rdp_version: Version,
-// }
-
-// /// Read conference create response
-// pub fn read_conference_create_response(cc_response: &mut dyn Read) -> RdpResult<ServerData> {
-//     per::read_choice(cc_response)?;
-//     per::read_object_identifier(&T124_02_98_OID, cc_response)?;
-//     per::read_length(cc_response)?;
-//     per::read_choice(cc_response)?;
-//     per::read_integer_16(1001, cc_response)?;
-//     per::read_integer(cc_response)?;
-//     per::read_enumerates(cc_response)?;
-//     per::read_number_of_set(cc_response)?;
-//     per::read_choice(cc_response)?;
-//     per::read_octet_stream(&H221_SC_KEY, 4, cc_response)?;
-
-//     let length = per::read_length(cc_response)?;
-//     let mut result = HashMap::new();
-//     let mut sub = cc_response.take(length as u64);
-//     loop {
-//         let mut header = block_header(None, None);
-//         // No more blocks to read
-//         if header.read(&mut sub).is_err() {
-//             break;
-//         }
-
-//         let mut buffer = vec![
-//             0 as u8;
-//             (cast!(DataType::U16, header["length"])? - header.length() as u16)
-//                 as usize
-//         ];
-//         sub.read_exact(&mut buffer)?;
-
-//         match MessageType::from(cast!(DataType::U16, header["type"])?) {
-//             MessageType::ScCore => {
-//                 let mut server_core = server_core_data();
-//                 server_core.read(&mut Cursor::new(buffer))?;
-//                 result.insert(MessageType::ScCore, server_core);
-//             }
-//             MessageType::ScSecurity => {
-//                 let mut server_security = server_security_data();
-//                 server_security.read(&mut Cursor::new(buffer))?;
-//                 result.insert(MessageType::ScSecurity, server_security);
-//             }
-//             MessageType::ScNet => {
-//                 let mut server_net = server_network_data();
-//                 server_net.read(&mut Cursor::new(buffer))?;
-//                 result.insert(MessageType::ScNet, server_net);
-//             }
-//             _ => println!(
-//                 "GCC: Unknown server block {:?}",
-//                 cast!(DataType::U16, header["type"])?
-//             ),
-//         }
-//     }
-
-//     // All section are important
-//     Ok(ServerData {
-//         channel_ids: cast!(
-//             DataType::Trame,
-//             result[&MessageType::ScNet]["channelIdArray"]
-//         )?
-//         .into_iter()
-//         .map(|x| cast!(DataType::U16, x).unwrap())
-//         .collect(),
-//         rdp_version: Version::from(cast!(
-//             DataType::U32,
-//             result[&MessageType::ScCore]["rdpVersion"]
-//         )?),
-//     })
-// }
+use crate::core::per;
+use crate::core::trace::trace_pdu;
+use crate::model::data::{check_remaining, Message};
+use crate::model::unicode::{from_unicode, Unicode};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const T124_02_98_OID: [u8; 6] = [0, 0, 20, 124, 0, 1];
+const H221_CS_KEY: [u8; 4] = *b"Duca";
+const H221_SC_KEY: [u8; 4] = *b"McDn";
+
+/// RDP protocol version
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Version {
+    RdpVersion = 0x00080001,
+    RdpVersion5plus = 0x00080004,
+    Unknown,
+}
+
+impl From<u32> for Version {
+    fn from(e: u32) -> Self {
+        match e {
+            0x00080001 => Version::RdpVersion,
+            0x00080004 => Version::RdpVersion5plus,
+            _ => Version::Unknown,
+        }
+    }
+}
+
+/// Color depth
+/// This flag is deprecated
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
+#[repr(u16)]
+#[allow(dead_code)]
+enum ColorDepth {
+    RnsUdColor8BPP = 0xCA01,
+    RnsUdColor16BPP555 = 0xCA02,
+    RnsUdColor16BPP565 = 0xCA03,
+    RnsUdColor24BPP = 0xCA04,
+}
+
+#[repr(u16)]
+enum Sequence {
+    RnsUdSasDel = 0xAA03,
+}
+
+/// Keyboard layout
+/// https://docs.microsoft.com/en-us/previous-versions/windows/it-pro/windows-vista/cc766503(v=ws.10)?redirectedfrom=MSDN
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KeyboardLayout {
+    Arabic = 0x00000401,
+    Bulgarian = 0x00000402,
+    ChineseUsKeyboard = 0x00000404,
+    Czech = 0x00000405,
+    Danish = 0x00000406,
+    German = 0x00000407,
+    Greek = 0x00000408,
+    US = 0x00000409,
+    Spanish = 0x0000040a,
+    Finnish = 0x0000040b,
+    French = 0x0000040c,
+    Hebrew = 0x0000040d,
+    Hungarian = 0x0000040e,
+    Icelandic = 0x0000040f,
+    Italian = 0x00000410,
+    Japanese = 0x00000411,
+    Korean = 0x00000412,
+    Dutch = 0x00000413,
+    Norwegian = 0x00000414,
+    UnitedKingdom = 0x00000809,
+}
+
+/// Keyboard type
+/// Ibm101102Keys is the most common keyboard type
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+pub enum KeyboardType {
+    IbmPcXt83Key = 0x00000001,
+    Olivetti = 0x00000002,
+    IbmPcAt84Key = 0x00000003,
+    Ibm101102Keys = 0x00000004,
+    Nokia1050 = 0x00000005,
+    Nokia9140 = 0x00000006,
+    Japanese = 0x00000007,
+}
+
+#[repr(u16)]
+#[allow(dead_code)]
+enum HighColor {
+    HighColor4BPP = 0x0004,
+    HighColor8BPP = 0x0008,
+    HighColor15BPP = 0x000f,
+    HighColor16BPP = 0x0010,
+    HighColor24BPP = 0x0018,
+}
+
+/// Supported color depth
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
+#[repr(u16)]
+#[allow(dead_code)]
+enum Support {
+    RnsUd24BPPSupport = 0x0001,
+    RnsUd16BPPSupport = 0x0002,
+    RnsUd15BPPSupport = 0x0004,
+    RnsUd32BPPSupport = 0x0008,
+}
+
+/// Negotiation of some capability for pdu layer
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
+#[repr(u16)]
+#[allow(dead_code)]
+pub enum CapabilityFlag {
+    RnsUdCsSupportErrinfoPDU = 0x0001,
+    RnsUdCsWant32BPPSession = 0x0002,
+    RnsUdCsSupportStatusInfoPdu = 0x0004,
+    RnsUdCsStrongAsymmetricKeys = 0x0008,
+    RnsUdCsUnused = 0x0010,
+    RnsUdCsValidConnectionType = 0x0020,
+    RnsUdCsSupportMonitorLayoutPDU = 0x0040,
+    RnsUdCsSupportNetcharAutodetect = 0x0080,
+    RnsUdCsSupportDynvcGFXProtocol = 0x0100,
+    RnsUdCsSupportDynamicTimezone = 0x0200,
+    RnsUdCsSupportHeartbeatPDU = 0x0400,
+}
+
+/// Connection type hint of the client core data
+/// MS-RDPBCGR 2.2.1.3.2 Client Core Data (TS_UD_CS_CORE)
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ConnectionType {
+    ConnectionTypeModem = 0x01,
+    ConnectionTypeBroadbandLow = 0x02,
+    ConnectionTypeSatellite = 0x03,
+    ConnectionTypeBroadbandHigh = 0x04,
+    ConnectionTypeWan = 0x05,
+    ConnectionTypeLan = 0x06,
+    /// The server measures the network with auto detect PDUs
+    ConnectionTypeAutodetect = 0x07,
+}
+
+/// Supported encryption method
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/6b58e11e-a32b-4903-b736-339f3cfe46ec?redirectedfrom=MSDN
+#[repr(u32)]
+#[allow(dead_code)]
+pub enum EncryptionMethod {
+    EncryptionFlag40bit = 0x00000001,
+    EncryptionFlag128bit = 0x00000002,
+    EncryptionFlag56bit = 0x00000008,
+    FipsEncryptionFlag = 0x00000010,
+}
+
+/// Encryption level
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/3e86b68d-3e2e-4433-b486-878875778f4b?redirectedfrom=MSDN
+#[repr(u32)]
+#[allow(dead_code)]
+pub enum EncryptionLevel {
+    None = 0x00000000,
+    Low = 0x00000001,
+    ClientCompatible = 0x00000002,
+    High = 0x00000003,
+    Fips = 0x00000004,
+}
+
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum MessageType {
+    //server -> client
+    ScCore = 0x0C01,
+    ScSecurity = 0x0C02,
+    ScNet = 0x0C03,
+    ScMcsMsgchannel = 0x0C04,
+    ScMultitransport = 0x0C08,
+    //client -> server
+    CsCore = 0xC001,
+    CsSecurity = 0xC002,
+    CsNet = 0xC003,
+    CsCluster = 0xC004,
+    CsMonitor = 0xC005,
+    CsMcsMsgchannel = 0xC006,
+    CsMultitransport = 0xC00A,
+    Unknown = 0,
+}
+
+impl From<u16> for MessageType {
+    fn from(e: u16) -> Self {
+        match e {
+            0x0C01 => MessageType::ScCore,
+            0x0C02 => MessageType::ScSecurity,
+            0x0C03 => MessageType::ScNet,
+            0x0C04 => MessageType::ScMcsMsgchannel,
+            0x0C08 => MessageType::ScMultitransport,
+            0xC001 => MessageType::CsCore,
+            0xC002 => MessageType::CsSecurity,
+            0xC003 => MessageType::CsNet,
+            0xC004 => MessageType::CsCluster,
+            0xC005 => MessageType::CsMonitor,
+            0xC006 => MessageType::CsMcsMsgchannel,
+            0xC00A => MessageType::CsMultitransport,
+            _ => MessageType::Unknown,
+        }
+    }
+}
+
+/// In case of client
+/// This is all mandatory fields need by client core data
+#[derive(Clone)]
+pub struct ClientData {
+    pub width: u16,
+    pub height: u16,
+    pub layout: KeyboardLayout,
+    pub server_selected_protocol: u32,
+    pub rdp_version: Version,
+    pub name: String,
+}
+
+/// Header of all user data blocks
+/// MS-RDPBCGR 2.2.1.3.1 User Data Header (TS_UD_HEADER)
+pub struct BlockHeader {
+    pub block_type: u16,
+    pub length: u16,
+}
+
+impl BlockHeader {
+    pub fn new(block_type: MessageType, length: u16) -> Self {
+        BlockHeader {
+            block_type: block_type as u16,
+            length: length + 4,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "GCC: block header")?;
+        self.block_type = buffer.get_u16_le();
+        self.length = buffer.get_u16_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for BlockHeader {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.block_type).await?;
+        writer.write_u16_le(self.length).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.block_type = reader.read_u16_le().await?;
+        self.length = reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4
+    }
+}
+
+/// This is the first client specific data
+///
+/// This field are obsolete and for modern
+/// RDP they are not use
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/00f1da4a-ee9c-421a-852f-c19f92343d73?redirectedfrom=MSDN
+pub struct ClientCoreData {
+    pub version: u32,
+    pub desktop_width: u16,
+    pub desktop_height: u16,
+    pub color_depth: u16,
+    pub sas_sequence: u16,
+    pub keyboard_layout: u32,
+    pub client_build: u32,
+    pub client_name: [u8; 32],
+    pub keyboard_type: u32,
+    pub keyboard_sub_type: u32,
+    pub keyboard_fn_keys: u32,
+    pub ime_file_name: [u8; 64],
+    pub post_beta2_color_depth: u16,
+    pub client_product_id: u16,
+    pub serial_number: u32,
+    pub high_color_depth: u16,
+    pub supported_color_depths: u16,
+    pub early_capability_flags: u16,
+    pub client_dig_product_id: [u8; 64],
+    pub connection_type: u8,
+    pub pad1octet: u8,
+    pub server_selected_protocol: u32,
+}
+
+impl ClientCoreData {
+    pub fn new(parameter: &ClientData) -> Self {
+        // Client name is a 16 characters unicode string
+        let mut client_name = [0u8; 32];
+        let name = parameter.name.to_unicode();
+        let length = name.len().min(30);
+        client_name[..length].copy_from_slice(&name[..length]);
+
+        ClientCoreData {
+            version: parameter.rdp_version as u32,
+            desktop_width: parameter.width,
+            desktop_height: parameter.height,
+            color_depth: ColorDepth::RnsUdColor8BPP as u16,
+            sas_sequence: Sequence::RnsUdSasDel as u16,
+            keyboard_layout: parameter.layout as u32,
+            client_build: 3790,
+            client_name,
+            keyboard_type: KeyboardType::Ibm101102Keys as u32,
+            keyboard_sub_type: 0,
+            keyboard_fn_keys: 12,
+            ime_file_name: [0; 64],
+            post_beta2_color_depth: ColorDepth::RnsUdColor8BPP as u16,
+            client_product_id: 1,
+            serial_number: 0,
+            high_color_depth: HighColor::HighColor24BPP as u16,
+            supported_color_depths: Support::RnsUd16BPPSupport as u16
+                | Support::RnsUd32BPPSupport as u16,
+            early_capability_flags: CapabilityFlag::RnsUdCsSupportErrinfoPDU as u16
+                | CapabilityFlag::RnsUdCsSupportMonitorLayoutPDU as u16
+                | CapabilityFlag::RnsUdCsSupportHeartbeatPDU as u16
+                | CapabilityFlag::RnsUdCsValidConnectionType as u16
+                | CapabilityFlag::RnsUdCsSupportNetcharAutodetect as u16,
+            client_dig_product_id: [0; 64],
+            connection_type: ConnectionType::ConnectionTypeAutodetect as u8,
+            pad1octet: 0,
+            server_selected_protocol: parameter.server_selected_protocol,
+        }
+    }
+}
+
+impl Default for ClientCoreData {
+    fn default() -> Self {
+        ClientCoreData {
+            version: 0,
+            desktop_width: 0,
+            desktop_height: 0,
+            color_depth: 0,
+            sas_sequence: 0,
+            keyboard_layout: 0,
+            client_build: 0,
+            client_name: [0; 32],
+            keyboard_type: 0,
+            keyboard_sub_type: 0,
+            keyboard_fn_keys: 0,
+            ime_file_name: [0; 64],
+            post_beta2_color_depth: 0,
+            client_product_id: 0,
+            serial_number: 0,
+            high_color_depth: 0,
+            supported_color_depths: 0,
+            early_capability_flags: 0,
+            client_dig_product_id: [0; 64],
+            connection_type: 0,
+            pad1octet: 0,
+            server_selected_protocol: 0,
+        }
+    }
+}
+
+impl ClientCoreData {
+    /// Fields after the IME file name are optional
+    /// missing ones are set to zero
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 128, "GCC: client core data")?;
+        self.version = buffer.get_u32_le();
+        self.desktop_width = buffer.get_u16_le();
+        self.desktop_height = buffer.get_u16_le();
+        self.color_depth = buffer.get_u16_le();
+        self.sas_sequence = buffer.get_u16_le();
+        self.keyboard_layout = buffer.get_u32_le();
+        self.client_build = buffer.get_u32_le();
+        buffer.copy_to_slice(&mut self.client_name);
+        self.keyboard_type = buffer.get_u32_le();
+        self.keyboard_sub_type = buffer.get_u32_le();
+        self.keyboard_fn_keys = buffer.get_u32_le();
+        buffer.copy_to_slice(&mut self.ime_file_name);
+
+        let optional_u16 = |buffer: &mut BytesMut| match buffer.remaining() {
+            0..=1 => 0,
+            _ => buffer.get_u16_le(),
+        };
+        self.post_beta2_color_depth = optional_u16(buffer);
+        self.client_product_id = optional_u16(buffer);
+        self.serial_number = match buffer.remaining() {
+            0..=3 => 0,
+            _ => buffer.get_u32_le(),
+        };
+        self.high_color_depth = optional_u16(buffer);
+        self.supported_color_depths = optional_u16(buffer);
+        self.early_capability_flags = optional_u16(buffer);
+        if buffer.remaining() >= 64 {
+            buffer.copy_to_slice(&mut self.client_dig_product_id);
+        }
+        if buffer.remaining() >= 2 {
+            self.connection_type = buffer.get_u8();
+            self.pad1octet = buffer.get_u8();
+        }
+        if buffer.remaining() >= 4 {
+            self.server_selected_protocol = buffer.get_u32_le();
+        }
+        Ok(())
+    }
+
+    /// Name of the client without the null terminator
+    pub fn get_client_name(&self) -> String {
+        from_unicode(&self.client_name)
+    }
+}
+
+#[async_trait]
+impl Message for ClientCoreData {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.version).await?;
+        writer.write_u16_le(self.desktop_width).await?;
+        writer.write_u16_le(self.desktop_height).await?;
+        writer.write_u16_le(self.color_depth).await?;
+        writer.write_u16_le(self.sas_sequence).await?;
+        writer.write_u32_le(self.keyboard_layout).await?;
+        writer.write_u32_le(self.client_build).await?;
+        writer.write_all(&self.client_name).await?;
+        writer.write_u32_le(self.keyboard_type).await?;
+        writer.write_u32_le(self.keyboard_sub_type).await?;
+        writer.write_u32_le(self.keyboard_fn_keys).await?;
+        writer.write_all(&self.ime_file_name).await?;
+        writer.write_u16_le(self.post_beta2_color_depth).await?;
+        writer.write_u16_le(self.client_product_id).await?;
+        writer.write_u32_le(self.serial_number).await?;
+        writer.write_u16_le(self.high_color_depth).await?;
+        writer.write_u16_le(self.supported_color_depths).await?;
+        writer.write_u16_le(self.early_capability_flags).await?;
+        writer.write_all(&self.client_dig_product_id).await?;
+        writer.write_u8(self.connection_type).await?;
+        writer.write_u8(self.pad1octet).await?;
+        writer.write_u32_le(self.server_selected_protocol).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.version = reader.read_u32_le().await?;
+        self.desktop_width = reader.read_u16_le().await?;
+        self.desktop_height = reader.read_u16_le().await?;
+        self.color_depth = reader.read_u16_le().await?;
+        self.sas_sequence = reader.read_u16_le().await?;
+        self.keyboard_layout = reader.read_u32_le().await?;
+        self.client_build = reader.read_u32_le().await?;
+        reader.read_exact(&mut self.client_name).await?;
+        self.keyboard_type = reader.read_u32_le().await?;
+        self.keyboard_sub_type = reader.read_u32_le().await?;
+        self.keyboard_fn_keys = reader.read_u32_le().await?;
+        reader.read_exact(&mut self.ime_file_name).await?;
+        self.post_beta2_color_depth = reader.read_u16_le().await?;
+        self.client_product_id = reader.read_u16_le().await?;
+        self.serial_number = reader.read_u32_le().await?;
+        self.high_color_depth = reader.read_u16_le().await?;
+        self.supported_color_depths = reader.read_u16_le().await?;
+        self.early_capability_flags = reader.read_u16_le().await?;
+        reader.read_exact(&mut self.client_dig_product_id).await?;
+        self.connection_type = reader.read_u8().await?;
+        self.pad1octet = reader.read_u8().await?;
+        self.server_selected_protocol = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        212
+    }
+}
+
+/// Client security releated to deprecated RDP security layer
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/6b58e11e-a32b-4903-b736-339f3cfe46ec?redirectedfrom=MSDN
+pub struct ClientSecurityData {
+    pub encryption_methods: u32,
+    pub ext_encryption_methods: u32,
+}
+
+impl ClientSecurityData {
+    pub fn new() -> Self {
+        ClientSecurityData {
+            encryption_methods: EncryptionMethod::EncryptionFlag40bit as u32
+                | EncryptionMethod::EncryptionFlag56bit as u32
+                | EncryptionMethod::EncryptionFlag128bit as u32,
+            ext_encryption_methods: 0,
+        }
+    }
+}
+
+impl ClientSecurityData {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 8, "GCC: client security data")?;
+        self.encryption_methods = buffer.get_u32_le();
+        self.ext_encryption_methods = buffer.get_u32_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for ClientSecurityData {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.encryption_methods).await?;
+        writer.write_u32_le(self.ext_encryption_methods).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.encryption_methods = reader.read_u32_le().await?;
+        self.ext_encryption_methods = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        8
+    }
+}
+
+/// Options of a static virtual channel
+/// MS-RDPBCGR 2.2.1.3.4.1 Channel Definition Structure (CHANNEL_DEF)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChannelOption {
+    ChannelOptionInitialized = 0x80000000,
+    ChannelOptionEncryptRdp = 0x40000000,
+    ChannelOptionCompressRdp = 0x00800000,
+}
+
+/// Static virtual channel definition
+/// MS-RDPBCGR 2.2.1.3.4.1 Channel Definition Structure (CHANNEL_DEF)
+pub struct ChannelDef {
+    pub name: [u8; 8],
+    pub options: u32,
+}
+
+impl ChannelDef {
+    pub fn new(name: &str, options: u32) -> Self {
+        // name is a 7 ANSI characters null terminated string
+        let mut buffer = [0u8; 8];
+        let length = name.len().min(7);
+        buffer[..length].copy_from_slice(&name.as_bytes()[..length]);
+        ChannelDef {
+            name: buffer,
+            options,
+        }
+    }
+}
+
+impl ChannelDef {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 12, "GCC: channel definition")?;
+        buffer.copy_to_slice(&mut self.name);
+        self.options = buffer.get_u32_le();
+        Ok(())
+    }
+
+    /// Name of the channel without the null terminator
+    pub fn get_name(&self) -> String {
+        let length = self.name.iter().position(|c| *c == 0).unwrap_or(self.name.len());
+        String::from_utf8_lossy(&self.name[..length]).to_string()
+    }
+}
+
+#[async_trait]
+impl Message for ChannelDef {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_all(&self.name).await?;
+        writer.write_u32_le(self.options).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        reader.read_exact(&mut self.name).await?;
+        self.options = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        12
+    }
+}
+
+/// List of static virtual channel requested by the client
+/// MS-RDPBCGR 2.2.1.3.4 Client Network Data (TS_UD_CS_NET)
+pub struct ClientNetworkData {
+    pub channel_def_array: Vec<ChannelDef>,
+}
+
+impl ClientNetworkData {
+    pub fn new(channel_def_array: Vec<ChannelDef>) -> Self {
+        ClientNetworkData { channel_def_array }
+    }
+}
+
+impl ClientNetworkData {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "GCC: client network data")?;
+        let count = buffer.get_u32_le() as usize;
+        check_remaining(buffer, count * 12, "GCC: client network data")?;
+        self.channel_def_array = (0..count)
+            .map(|_| {
+                let mut channel_def = ChannelDef::new("", 0);
+                channel_def.read_from_buffer(buffer).map(|_| channel_def)
+            })
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for ClientNetworkData {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer
+            .write_u32_le(self.channel_def_array.len() as u32)
+            .await?;
+        for channel_def in &self.channel_def_array {
+            channel_def.write_to(writer).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let count = reader.read_u32_le().await?;
+        self.channel_def_array.clear();
+        for _ in 0..count {
+            let mut channel_def = ChannelDef::new("", 0);
+            channel_def.read_from(reader).await?;
+            self.channel_def_array.push(channel_def);
+        }
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        4 + 12 * self.channel_def_array.len()
+    }
+}
+
+/// Ask the server for the MCS message channel
+/// MS-RDPBCGR 2.2.1.3.7 Client Message Channel Data (TS_UD_CS_MCS_MSGCHANNEL)
+#[derive(Default)]
+pub struct ClientMessageChannelData {
+    pub flags: u32,
+}
+
+impl ClientMessageChannelData {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "GCC: client message channel data")?;
+        self.flags = buffer.get_u32_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for ClientMessageChannelData {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.flags).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.flags = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4
+    }
+}
+
+/// The monitor is the primary one
+pub const TS_MONITOR_PRIMARY: u32 = 0x0000_0001;
+
+/// Largest number of monitors of a layout
+pub const MONITOR_MAX_COUNT: usize = 16;
+
+/// Position of a monitor on the virtual desktop
+/// Bounds are inclusive
+/// MS-RDPBCGR 2.2.1.3.6.1 Monitor Definition (TS_MONITOR_DEF)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Monitor {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub flags: u32,
+}
+
+impl Monitor {
+    /// Monitor from its origin and size
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::gcc::Monitor;
+    /// let monitor = Monitor::new(1920, 0, 1280, 1024, false);
+    /// assert_eq!(monitor.right, 3199);
+    /// ```
+    pub fn new(left: i32, top: i32, width: u32, height: u32, primary: bool) -> Self {
+        Monitor {
+            left,
+            top,
+            right: left + width as i32 - 1,
+            bottom: top + height as i32 - 1,
+            flags: if primary { TS_MONITOR_PRIMARY } else { 0 },
+        }
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.flags & TS_MONITOR_PRIMARY != 0
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 20, "GCC: monitor definition")?;
+        self.left = buffer.get_i32_le();
+        self.top = buffer.get_i32_le();
+        self.right = buffer.get_i32_le();
+        self.bottom = buffer.get_i32_le();
+        self.flags = buffer.get_u32_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for Monitor {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_i32_le(self.left).await?;
+        writer.write_i32_le(self.top).await?;
+        writer.write_i32_le(self.right).await?;
+        writer.write_i32_le(self.bottom).await?;
+        writer.write_u32_le(self.flags).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.left = reader.read_i32_le().await?;
+        self.top = reader.read_i32_le().await?;
+        self.right = reader.read_i32_le().await?;
+        self.bottom = reader.read_i32_le().await?;
+        self.flags = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        20
+    }
+}
+
+/// Check the rules of a monitor layout
+/// At most 16 monitors and a single primary
+/// monitor with its origin at (0, 0)
+pub fn check_monitor_layout(monitors: &[Monitor]) -> Result<()> {
+    if monitors.is_empty() || monitors.len() > MONITOR_MAX_COUNT {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("GCC: invalid number of monitors {}", monitors.len()),
+        ));
+    }
+
+    let mut primaries = monitors.iter().filter(|monitor| monitor.is_primary());
+    match (primaries.next(), primaries.next()) {
+        (Some(primary), None) if primary.left == 0 && primary.top == 0 => Ok(()),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "GCC: layout needs a single primary monitor at origin",
+        )),
+    }
+}
+
+/// Server core data block
+/// MS-RDPBCGR 2.2.1.4.2 Server Core Data (TS_UD_SC_CORE)
+#[derive(Default)]
+pub struct ServerCoreData {
+    pub rdp_version: u32,
+    pub client_requested_protocols: Option<u32>,
+    pub early_capability_flags: Option<u32>,
+}
+
+impl ServerCoreData {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "GCC: server core data")?;
+        self.rdp_version = buffer.get_u32_le();
+        self.client_requested_protocols = if buffer.remaining() >= 4 {
+            Some(buffer.get_u32_le())
+        } else {
+            None
+        };
+        self.early_capability_flags = if buffer.remaining() >= 4 {
+            Some(buffer.get_u32_le())
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for ServerCoreData {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.rdp_version).await?;
+        if let Some(client_requested_protocols) = self.client_requested_protocols {
+            writer.write_u32_le(client_requested_protocols).await?;
+            if let Some(early_capability_flags) = self.early_capability_flags {
+                writer.write_u32_le(early_capability_flags).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.rdp_version = reader.read_u32_le().await?;
+        if self.client_requested_protocols.is_some() {
+            self.client_requested_protocols = Some(reader.read_u32_le().await?);
+            if self.early_capability_flags.is_some() {
+                self.early_capability_flags = Some(reader.read_u32_le().await?);
+            }
+        }
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        match (self.client_requested_protocols, self.early_capability_flags) {
+            (None, _) => 4,
+            (Some(_), None) => 8,
+            (Some(_), Some(_)) => 12,
+        }
+    }
+}
+
+/// In case of non ssl security layer
+/// we need to check data in this packet
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/3e86b68d-3e2e-4433-b486-878875778f4b
+#[derive(Default)]
+pub struct ServerSecurityData {
+    pub encryption_method: u32,
+    pub encryption_level: u32,
+    pub server_random: Vec<u8>,
+    pub server_certificate: Vec<u8>,
+}
+
+impl ServerSecurityData {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 8, "GCC: server security data")?;
+        self.encryption_method = buffer.get_u32_le();
+        self.encryption_level = buffer.get_u32_le();
+
+        // Random and certificate are only present
+        // when standard RDP security is used
+        if buffer.remaining() >= 8 {
+            let random_length = buffer.get_u32_le() as usize;
+            let certificate_length = buffer.get_u32_le() as usize;
+            check_remaining(
+                buffer,
+                random_length + certificate_length,
+                "GCC: server security data",
+            )?;
+            self.server_random = buffer.split_to(random_length).to_vec();
+            self.server_certificate = buffer.split_to(certificate_length).to_vec();
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for ServerSecurityData {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.encryption_method).await?;
+        writer.write_u32_le(self.encryption_level).await?;
+        if !self.server_random.is_empty() {
+            writer.write_u32_le(self.server_random.len() as u32).await?;
+            writer.write_u32_le(self.server_certificate.len() as u32).await?;
+            writer.write_all(&self.server_random).await?;
+            writer.write_all(&self.server_certificate).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.encryption_method = reader.read_u32_le().await?;
+        self.encryption_level = reader.read_u32_le().await?;
+        if self.encryption_method != 0 {
+            self.server_random = vec![0; reader.read_u32_le().await? as usize];
+            self.server_certificate = vec![0; reader.read_u32_le().await? as usize];
+            reader.read_exact(&mut self.server_random).await?;
+            reader.read_exact(&mut self.server_certificate).await?;
+        }
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        match self.server_random.len() {
+            0 => 8,
+            random_length => 16 + random_length + self.server_certificate.len(),
+        }
+    }
+}
+
+/// Server network data block
+/// Contains the MCS channel id of all requested channels
+/// MS-RDPBCGR 2.2.1.4.4 Server Network Data (TS_UD_SC_NET)
+#[derive(Default)]
+pub struct ServerNetworkData {
+    pub mcs_channel_id: u16,
+    pub channel_id_array: Vec<u16>,
+}
+
+impl ServerNetworkData {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "GCC: server network data")?;
+        self.mcs_channel_id = buffer.get_u16_le();
+        let count = buffer.get_u16_le() as usize;
+        check_remaining(buffer, count * 2, "GCC: server network data")?;
+        self.channel_id_array = (0..count).map(|_| buffer.get_u16_le()).collect();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for ServerNetworkData {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.mcs_channel_id).await?;
+        writer.write_u16_le(self.channel_id_array.len() as u16).await?;
+        for channel_id in &self.channel_id_array {
+            writer.write_u16_le(*channel_id).await?;
+        }
+        // The block length is a multiple of four
+        if self.channel_id_array.len() % 2 == 1 {
+            writer.write_u16_le(0).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.mcs_channel_id = reader.read_u16_le().await?;
+        let count = reader.read_u16_le().await?;
+        self.channel_id_array.clear();
+        for _ in 0..count {
+            self.channel_id_array.push(reader.read_u16_le().await?);
+        }
+        if count % 2 == 1 {
+            reader.read_u16_le().await?;
+        }
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        4 + 2 * (self.channel_id_array.len() + self.channel_id_array.len() % 2)
+    }
+}
+
+/// Server message channel data block
+/// Only sent when the client asked for it
+/// MS-RDPBCGR 2.2.1.4.5 Server Message Channel Data (TS_UD_SC_MCS_MSGCHANNEL)
+#[derive(Default)]
+pub struct ServerMessageChannelData {
+    pub mcs_channel_id: u16,
+}
+
+impl ServerMessageChannelData {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 2, "GCC: server message channel data")?;
+        self.mcs_channel_id = buffer.get_u16_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for ServerMessageChannelData {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.mcs_channel_id).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.mcs_channel_id = reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        2
+    }
+}
+
+/// Transports of the side channels
+/// MS-RDPBCGR 2.2.1.3.8 Client Multitransport Channel Data (TS_UD_CS_MULTITRANSPORT)
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MultitransportFlag {
+    TransporttypeUdpfecr = 0x00000001,
+    TransporttypeUdpfecl = 0x00000004,
+    TransporttypeUdpPreferred = 0x00000100,
+    SoftsyncTcpToUdp = 0x00000200,
+}
+
+/// Multitransport channel data block
+/// The same for the client and the server, the server
+/// keeps the transports it supports among the client ones
+/// MS-RDPBCGR 2.2.1.4.6 Server Multitransport Channel Data (TS_UD_SC_MULTITRANSPORT)
+#[derive(Default)]
+pub struct MultitransportChannelData {
+    /// MultitransportFlag of the transports
+    pub flags: u32,
+}
+
+impl MultitransportChannelData {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "GCC: multitransport channel data")?;
+        self.flags = buffer.get_u32_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for MultitransportChannelData {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.flags).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.flags = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4
+    }
+}
+
+/// All server data blocks received
+/// in the conference create response
+#[derive(Default)]
+pub struct ServerData {
+    pub core: ServerCoreData,
+    pub security: ServerSecurityData,
+    pub network: ServerNetworkData,
+    pub message_channel: Option<ServerMessageChannelData>,
+    /// Only sent when the client supports a side channel
+    pub multitransport: Option<MultitransportChannelData>,
+}
+
+impl ServerData {
+    /// Version of the protocol used by the server
+    pub fn rdp_version(&self) -> Version {
+        Version::from(self.core.rdp_version)
+    }
+}
+
+/// Serialize a list of client data blocks
+/// Each block is prefixed with its header
+async fn write_block(
+    buffer: &mut Vec<u8>,
+    block_type: MessageType,
+    block: &impl Message,
+) -> Result<()> {
+    BlockHeader::new(block_type, block.length() as u16)
+        .write_to(buffer)
+        .await?;
+    block.write_to(buffer).await
+}
+
+/// Build the client user data
+/// which is embedded in the conference create request
+pub async fn client_user_data(
+    core: &ClientCoreData,
+    security: &ClientSecurityData,
+    network: &ClientNetworkData,
+) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    write_block(&mut buffer, MessageType::CsCore, core).await?;
+    write_block(&mut buffer, MessageType::CsSecurity, security).await?;
+    write_block(&mut buffer, MessageType::CsNet, network).await?;
+    write_block(
+        &mut buffer,
+        MessageType::CsMcsMsgchannel,
+        &ClientMessageChannelData::default(),
+    )
+    .await?;
+    Ok(buffer)
+}
+
+/// Build the server user data
+/// which is embedded in the conference create response
+pub async fn server_user_data(server_data: &ServerData) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    write_block(&mut buffer, MessageType::ScCore, &server_data.core).await?;
+    write_block(&mut buffer, MessageType::ScNet, &server_data.network).await?;
+    write_block(&mut buffer, MessageType::ScSecurity, &server_data.security).await?;
+    if let Some(message_channel) = &server_data.message_channel {
+        write_block(&mut buffer, MessageType::ScMcsMsgchannel, message_channel).await?;
+    }
+    if let Some(multitransport) = &server_data.multitransport {
+        write_block(&mut buffer, MessageType::ScMultitransport, multitransport).await?;
+    }
+    Ok(buffer)
+}
+
+/// All client data blocks received
+/// in the conference create request
+#[derive(Default)]
+pub struct ClientUserData {
+    pub core: ClientCoreData,
+    pub security: Option<ClientSecurityData>,
+    pub network: Option<ClientNetworkData>,
+    pub message_channel: Option<ClientMessageChannelData>,
+}
+
+/// T.124 conference create request
+/// used to transport client user data
+pub fn write_conference_create_request(user_data: &[u8]) -> Result<BytesMut> {
+    let mut result = BytesMut::new();
+    per::write_choice(0, &mut result);
+    per::write_object_identifier(&T124_02_98_OID, &mut result)?;
+    per::write_length(user_data.len() as u16 + 14, &mut result);
+    per::write_choice(0, &mut result);
+    per::write_selection(0x08, &mut result);
+    per::write_numeric_string(b"1", 1, &mut result);
+    per::write_padding(1, &mut result);
+    per::write_number_of_set(1, &mut result);
+    per::write_choice(0xc0, &mut result);
+    per::write_octet_stream(&H221_CS_KEY, 4, &mut result);
+    per::write_octet_stream(user_data, 0, &mut result);
+    Ok(result)
+}
+
+/// Read conference create request
+/// The client core data is mandatory
+pub fn read_conference_create_request(cc_request: &mut BytesMut) -> Result<ClientUserData> {
+    per::read_choice(cc_request)?;
+    if !per::read_object_identifier(&T124_02_98_OID, cc_request)? {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "GCC: Invalid conference create request object identifier",
+        ));
+    }
+    per::read_length(cc_request)?;
+    per::read_choice(cc_request)?;
+    per::read_selection(cc_request)?;
+    per::read_numeric_string(1, cc_request)?;
+    per::read_padding(1, cc_request)?;
+    per::read_number_of_set(cc_request)?;
+    per::read_choice(cc_request)?;
+    per::read_octet_stream(&H221_CS_KEY, 4, cc_request)?;
+
+    let length = per::read_length(cc_request)? as usize;
+    check_remaining(cc_request, length, "GCC: client user data")?;
+    let mut sub = cc_request.split_to(length);
+
+    let mut result = ClientUserData::default();
+    let mut has_core = false;
+    while sub.remaining() >= 4 {
+        let mut header = BlockHeader::new(MessageType::Unknown, 0);
+        header.read_from_buffer(&mut sub)?;
+
+        if (header.length as usize) < 4 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "GCC: Invalid client block length",
+            ));
+        }
+        let block_length = header.length as usize - 4;
+        check_remaining(&sub, block_length, "GCC: client block")?;
+        let mut block = sub.split_to(block_length);
+
+        match MessageType::from(header.block_type) {
+            MessageType::CsCore => {
+                result.core.read_from_buffer(&mut block)?;
+                has_core = true;
+            }
+            MessageType::CsSecurity => {
+                let mut security = ClientSecurityData::new();
+                security.read_from_buffer(&mut block)?;
+                result.security = Some(security);
+            }
+            MessageType::CsNet => {
+                let mut network = ClientNetworkData::new(Vec::new());
+                network.read_from_buffer(&mut block)?;
+                result.network = Some(network);
+            }
+            MessageType::CsMcsMsgchannel => {
+                let mut message_channel = ClientMessageChannelData::default();
+                message_channel.read_from_buffer(&mut block)?;
+                result.message_channel = Some(message_channel);
+            }
+            // Blocks of newer clients are skipped
+            _ => {
+                trace_pdu!(block_type = header.block_type, "GCC: unknown client block");
+            }
+        }
+    }
+
+    if !has_core {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "GCC: Missing client core data",
+        ));
+    }
+    Ok(result)
+}
+
+/// T.124 conference create response
+/// used to transport server user data
+pub fn write_conference_create_response(user_data: &[u8]) -> Result<BytesMut> {
+    let mut result = BytesMut::new();
+    per::write_choice(0, &mut result);
+    per::write_object_identifier(&T124_02_98_OID, &mut result)?;
+    per::write_length(user_data.len() as u16 + 14, &mut result);
+    per::write_choice(0x14, &mut result);
+    per::write_integer_16(0x79F3, 1001, &mut result);
+    per::write_integer(1, &mut result);
+    per::write_enumerates(0, &mut result);
+    per::write_number_of_set(1, &mut result);
+    per::write_choice(0xc0, &mut result);
+    per::write_octet_stream(&H221_SC_KEY, 4, &mut result);
+    per::write_octet_stream(user_data, 0, &mut result);
+    Ok(result)
+}
+
+/// Read conference create response
+pub fn read_conference_create_response(cc_response: &mut BytesMut) -> Result<ServerData> {
+    per::read_choice(cc_response)?;
+    if !per::read_object_identifier(&T124_02_98_OID, cc_response)? {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "GCC: Invalid conference create response object identifier",
+        ));
+    }
+    per::read_length(cc_response)?;
+    per::read_choice(cc_response)?;
+    per::read_integer_16(1001, cc_response)?;
+    per::read_integer(cc_response)?;
+    per::read_enumerates(cc_response)?;
+    per::read_number_of_set(cc_response)?;
+    per::read_choice(cc_response)?;
+    per::read_octet_stream(&H221_SC_KEY, 4, cc_response)?;
+
+    let length = per::read_length(cc_response)? as usize;
+    check_remaining(cc_response, length, "GCC: server user data")?;
+    let mut sub = cc_response.split_to(length);
+
+    let mut result = ServerData::default();
+    while sub.remaining() >= 4 {
+        let mut header = BlockHeader::new(MessageType::Unknown, 0);
+        header.read_from_buffer(&mut sub)?;
+
+        if (header.length as usize) < 4 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "GCC: Invalid server block length",
+            ));
+        }
+        let block_length = header.length as usize - 4;
+        check_remaining(&sub, block_length, "GCC: server block")?;
+        let mut block = sub.split_to(block_length);
+
+        match MessageType::from(header.block_type) {
+            MessageType::ScCore => result.core.read_from_buffer(&mut block)?,
+            MessageType::ScSecurity => result.security.read_from_buffer(&mut block)?,
+            MessageType::ScNet => result.network.read_from_buffer(&mut block)?,
+            MessageType::ScMcsMsgchannel => {
+                let mut message_channel = ServerMessageChannelData::default();
+                message_channel.read_from_buffer(&mut block)?;
+                result.message_channel = Some(message_channel);
+            }
+            MessageType::ScMultitransport => {
+                let mut multitransport = MultitransportChannelData::default();
+                multitransport.read_from_buffer(&mut block)?;
+                result.multitransport = Some(multitransport);
+            }
+            // Blocks of newer servers are skipped
+            _ => {
+                trace_pdu!(block_type = header.block_type, "GCC: unknown server block");
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Test of the client core data format
+    #[tokio::test]
+    async fn test_client_core_data() {
+        let core = ClientCoreData::new(&ClientData {
+            width: 800,
+            height: 600,
+            layout: KeyboardLayout::US,
+            server_selected_protocol: 0,
+            rdp_version: Version::RdpVersion5plus,
+            name: "rdp-rs".to_string(),
+        });
+        let buffer = to_vec(&core).await.unwrap();
+        assert_eq!(buffer.len(), core.length());
+        assert_eq!(&buffer[0..8], [4, 0, 8, 0, 32, 3, 88, 2]);
+        assert_eq!(&buffer[20..26], [114, 0, 100, 0, 112, 0]);
+    }
+
+    /// Test of the conference create request header
+    #[test]
+    fn test_write_conference_create_request() {
+        let result = write_conference_create_request(&[1, 2, 3]).unwrap();
+        assert_eq!(
+            &result[..],
+            [
+                0, 5, 0, 20, 124, 0, 1, 17, 0, 8, 0, 16, 0, 1, 192, 0, 68, 117, 99, 97, 3, 1,
+                2, 3
+            ]
+        );
+    }
+
+    /// Test reading of server network block
+    #[test]
+    fn test_read_server_network_data() {
+        let mut buffer = BytesMut::from(&[235u8, 3, 2, 0, 236, 3, 237, 3][..]);
+        let mut network = ServerNetworkData::default();
+        network.read_from_buffer(&mut buffer).unwrap();
+        assert_eq!(network.mcs_channel_id, 1003);
+        assert_eq!(network.channel_id_array, vec![1004, 1005]);
+    }
+
+    /// The conference create request is read back by the server
+    #[tokio::test]
+    async fn test_read_conference_create_request() {
+        let core = ClientCoreData::new(&ClientData {
+            width: 1024,
+            height: 768,
+            layout: KeyboardLayout::US,
+            server_selected_protocol: 0,
+            rdp_version: Version::RdpVersion5plus,
+            name: "rdp-rs".to_string(),
+        });
+        let network = ClientNetworkData::new(vec![ChannelDef::new("rdpdr", 0)]);
+        let user_data = client_user_data(&core, &ClientSecurityData::new(), &network)
+            .await
+            .unwrap();
+        let mut request = write_conference_create_request(&user_data).unwrap();
+
+        let result = read_conference_create_request(&mut request).unwrap();
+        assert_eq!(result.core.desktop_width, 1024);
+        assert_eq!(result.core.get_client_name(), "rdp-rs");
+        assert_eq!(result.network.unwrap().channel_def_array[0].get_name(), "rdpdr");
+        assert!(result.message_channel.is_some());
+    }
+
+    /// The conference create response is read back by the client
+    #[tokio::test]
+    async fn test_write_conference_create_response() {
+        let server_data = ServerData {
+            core: ServerCoreData {
+                rdp_version: Version::RdpVersion5plus as u32,
+                client_requested_protocols: Some(0),
+                early_capability_flags: None,
+            },
+            network: ServerNetworkData {
+                mcs_channel_id: 1003,
+                channel_id_array: vec![1004],
+            },
+            message_channel: Some(ServerMessageChannelData {
+                mcs_channel_id: 1005,
+            }),
+            multitransport: Some(MultitransportChannelData {
+                flags: MultitransportFlag::TransporttypeUdpfecr as u32,
+            }),
+            ..Default::default()
+        };
+        let user_data = server_user_data(&server_data).await.unwrap();
+        let mut response = write_conference_create_response(&user_data).unwrap();
+
+        let result = read_conference_create_response(&mut response).unwrap();
+        assert_eq!(result.rdp_version(), Version::RdpVersion5plus);
+        assert_eq!(result.network.channel_id_array, vec![1004]);
+        assert_eq!(result.message_channel.unwrap().mcs_channel_id, 1005);
+        assert_eq!(result.multitransport.unwrap().flags, 0x01);
+        assert_eq!(result.security.encryption_method, 0);
+    }
+
+    /// A layout needs exactly one primary monitor at origin
+    #[test]
+    fn test_check_monitor_layout() {
+        let primary = Monitor::new(0, 0, 1920, 1080, true);
+        let left = Monitor::new(-1280, 0, 1280, 1024, false);
+        assert!(check_monitor_layout(&[primary, left]).is_ok());
+        assert!(check_monitor_layout(&[left]).is_err());
+        assert!(check_monitor_layout(&[primary, primary]).is_err());
+        assert!(check_monitor_layout(&[]).is_err());
+        assert!(check_monitor_layout(&[Monitor::new(10, 0, 800, 600, true)]).is_err());
+    }
+}
@@ -0,0 +1,798 @@
+use crate::codec::bulk::BulkDecompressor;
+use crate::core::capability::DemandActivePdu;
+use crate::core::error_info::ErrorInfo;
+use crate::core::event::SessionEvent;
+use crate::core::gcc::{Monitor, MONITOR_MAX_COUNT};
+use crate::core::session::read_save_session_info;
+use crate::core::trace::trace_pdu;
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Raw PDU type use by the protocol
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, TryFromPrimitive)]
+pub enum PDUType {
+    PdutypeDemandactivepdu = 0x11,
+    PdutypeConfirmactivepdu = 0x13,
+    PdutypeDeactivateallpdu = 0x16,
+    PdutypeDatapdu = 0x17,
+    PdutypeServerRedirPkt = 0x1A,
+}
+
+/// Type of PDU sent in a data PDU
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, TryFromPrimitive)]
+pub enum PDUType2 {
+    Pdutype2Update = 0x02,
+    Pdutype2Control = 0x14,
+    Pdutype2Pointer = 0x1B,
+    Pdutype2Input = 0x1C,
+    Pdutype2Synchronize = 0x1F,
+    Pdutype2RefreshRect = 0x21,
+    Pdutype2PlaySound = 0x22,
+    Pdutype2SuppressOutput = 0x23,
+    Pdutype2ShutdownRequest = 0x24,
+    Pdutype2ShutdownDenied = 0x25,
+    Pdutype2SaveSessionInfo = 0x26,
+    Pdutype2Fontlist = 0x27,
+    Pdutype2Fontmap = 0x28,
+    Pdutype2SetKeyboardIndicators = 0x29,
+    Pdutype2BitmapcachePersistentList = 0x2B,
+    Pdutype2BitmapcacheErrorPdu = 0x2C,
+    Pdutype2SetKeyboardImeStatus = 0x2D,
+    Pdutype2OffscrcacheErrorPdu = 0x2E,
+    Pdutype2SetErrorInfoPdu = 0x2F,
+    Pdutype2DrawninegridErrorPdu = 0x30,
+    Pdutype2DrawgdiplusErrorPdu = 0x31,
+    Pdutype2ArcStatusPdu = 0x32,
+    Pdutype2StatusInfoPdu = 0x36,
+    Pdutype2MonitorLayoutPdu = 0x37,
+}
+
+/// Priority of a data PDU
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StreamId {
+    StreamUndefined = 0x00,
+    StreamLow = 0x01,
+    StreamMed = 0x02,
+    StreamHi = 0x04,
+}
+
+/// Bulk compression flags of a data PDU
+/// The low bits carry the compression type
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CompressionFlag {
+    CompressionTypeMask = 0x0F,
+    PacketCompressed = 0x20,
+    PacketAtFront = 0x40,
+    PacketFlushed = 0x80,
+}
+
+/// This is the main PDU header
+/// It use the share control header to dispatch between all PDU
+///
+/// MS-RDPBCGR 2.2.8.1.1.1.1 Share Control Header (TS_SHARECONTROLHEADER)
+#[derive(Debug, Default)]
+pub struct ShareControlHeader {
+    pub total_length: u16,
+    pub pdu_type: u16,
+    pub pdu_source: u16,
+}
+
+impl ShareControlHeader {
+    pub fn new(pdu_type: PDUType, pdu_source: u16, length: usize) -> Self {
+        ShareControlHeader {
+            total_length: length as u16 + 6,
+            pdu_type: pdu_type as u16,
+            pdu_source,
+        }
+    }
+
+    /// Type of the PDU without the protocol version
+    pub fn get_pdu_type(&self) -> Option<PDUType> {
+        PDUType::try_from(self.pdu_type & 0x000F | 0x0010).ok()
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 6, "GLOBAL: share control header")?;
+        self.total_length = buffer.get_u16_le();
+        self.pdu_type = buffer.get_u16_le();
+        self.pdu_source = buffer.get_u16_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for ShareControlHeader {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.total_length).await?;
+        writer.write_u16_le(self.pdu_type).await?;
+        writer.write_u16_le(self.pdu_source).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.total_length = reader.read_u16_le().await?;
+        self.pdu_type = reader.read_u16_le().await?;
+        self.pdu_source = reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        6
+    }
+}
+
+/// All Data PDU share the same layout
+///
+/// MS-RDPBCGR 2.2.8.1.1.1.2 Share Data Header (TS_SHAREDATAHEADER)
+#[derive(Debug, Default)]
+pub struct ShareDataHeader {
+    pub share_id: u32,
+    pub stream_id: u8,
+    pub uncompressed_length: u16,
+    pub pdu_type_2: u8,
+    pub compressed_type: u8,
+    pub compressed_length: u16,
+}
+
+impl ShareDataHeader {
+    pub fn new(share_id: u32, pdu_type_2: PDUType2, length: usize) -> Self {
+        ShareDataHeader {
+            share_id,
+            stream_id: StreamId::StreamLow as u8,
+            uncompressed_length: length as u16 + 18,
+            pdu_type_2: pdu_type_2 as u8,
+            compressed_type: 0,
+            compressed_length: 0,
+        }
+    }
+
+    /// True if the payload use bulk compression
+    pub fn is_compressed(&self) -> bool {
+        self.compressed_type & CompressionFlag::PacketCompressed as u8 != 0
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 12, "GLOBAL: share data header")?;
+        self.share_id = buffer.get_u32_le();
+        buffer.advance(1);
+        self.stream_id = buffer.get_u8();
+        self.uncompressed_length = buffer.get_u16_le();
+        self.pdu_type_2 = buffer.get_u8();
+        self.compressed_type = buffer.get_u8();
+        self.compressed_length = buffer.get_u16_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for ShareDataHeader {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.share_id).await?;
+        writer.write_u8(0).await?;
+        writer.write_u8(self.stream_id).await?;
+        writer.write_u16_le(self.uncompressed_length).await?;
+        writer.write_u8(self.pdu_type_2).await?;
+        writer.write_u8(self.compressed_type).await?;
+        writer.write_u16_le(self.compressed_length).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.share_id = reader.read_u32_le().await?;
+        reader.read_u8().await?;
+        self.stream_id = reader.read_u8().await?;
+        self.uncompressed_length = reader.read_u16_le().await?;
+        self.pdu_type_2 = reader.read_u8().await?;
+        self.compressed_type = reader.read_u8().await?;
+        self.compressed_length = reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        12
+    }
+}
+
+/// Synchronize payload send by both side (client, server)
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/3fb4c95e-ad2d-43d1-a46f-5bd49418da49
+#[derive(Debug, Default)]
+pub struct SynchronizePdu {
+    pub message_type: u16,
+    pub target_user: u16,
+}
+
+impl SynchronizePdu {
+    pub fn new(target_user: u16) -> Self {
+        SynchronizePdu {
+            message_type: 1,
+            target_user,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "GLOBAL: synchronize")?;
+        self.message_type = buffer.get_u16_le();
+        self.target_user = buffer.get_u16_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for SynchronizePdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.message_type).await?;
+        writer.write_u16_le(self.target_user).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.message_type = reader.read_u16_le().await?;
+        self.target_user = reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4
+    }
+}
+
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Action {
+    CtrlactionRequestControl = 0x0001,
+    CtrlactionGrantedControl = 0x0002,
+    CtrlactionDetach = 0x0003,
+    CtrlactionCooperate = 0x0004,
+}
+
+/// Control payload send during pdu handshake
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/0448f397-aa11-455d-81b1-f1265085239d
+#[derive(Debug, Default)]
+pub struct ControlPdu {
+    pub action: u16,
+    pub grant_id: u16,
+    pub control_id: u32,
+}
+
+impl ControlPdu {
+    pub fn new(action: Action) -> Self {
+        ControlPdu {
+            action: action as u16,
+            grant_id: 0,
+            control_id: 0,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 8, "GLOBAL: control")?;
+        self.action = buffer.get_u16_le();
+        self.grant_id = buffer.get_u16_le();
+        self.control_id = buffer.get_u32_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for ControlPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.action).await?;
+        writer.write_u16_le(self.grant_id).await?;
+        writer.write_u32_le(self.control_id).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.action = reader.read_u16_le().await?;
+        self.grant_id = reader.read_u16_le().await?;
+        self.control_id = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        8
+    }
+}
+
+/// Font list PDU
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/e373575a-01e2-43a7-a6d8-e1952b83e787
+#[derive(Debug)]
+pub struct FontListPdu {
+    pub number_fonts: u16,
+    pub total_num_fonts: u16,
+    pub list_flags: u16,
+    pub entry_size: u16,
+}
+
+impl Default for FontListPdu {
+    fn default() -> Self {
+        FontListPdu {
+            number_fonts: 0,
+            total_num_fonts: 0,
+            list_flags: 0x0003,
+            entry_size: 0x0032,
+        }
+    }
+}
+
+#[async_trait]
+impl Message for FontListPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.number_fonts).await?;
+        writer.write_u16_le(self.total_num_fonts).await?;
+        writer.write_u16_le(self.list_flags).await?;
+        writer.write_u16_le(self.entry_size).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.number_fonts = reader.read_u16_le().await?;
+        self.total_num_fonts = reader.read_u16_le().await?;
+        self.list_flags = reader.read_u16_le().await?;
+        self.entry_size = reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        8
+    }
+}
+
+/// Font details send from server to client
+///
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/b4e557f3-7540-46fc-815d-0c12299cf1ee
+#[derive(Debug, Default)]
+pub struct FontMapPdu {
+    pub number_entries: u16,
+    pub total_num_entries: u16,
+    pub map_flags: u16,
+    pub entry_size: u16,
+}
+
+impl FontMapPdu {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 8, "GLOBAL: font map")?;
+        self.number_entries = buffer.get_u16_le();
+        self.total_num_entries = buffer.get_u16_le();
+        self.map_flags = buffer.get_u16_le();
+        self.entry_size = buffer.get_u16_le();
+        Ok(())
+    }
+}
+
+/// Rectangle with inclusive bounds
+/// MS-RDPBCGR 2.2.11.1 Inclusive Rectangle (TS_RECTANGLE16)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct InclusiveRectangle {
+    pub left: u16,
+    pub top: u16,
+    pub right: u16,
+    pub bottom: u16,
+}
+
+impl InclusiveRectangle {
+    /// Rectangle covering a whole desktop
+    pub fn desktop(width: u16, height: u16) -> Self {
+        InclusiveRectangle {
+            left: 0,
+            top: 0,
+            right: width.saturating_sub(1),
+            bottom: height.saturating_sub(1),
+        }
+    }
+}
+
+#[async_trait]
+impl Message for InclusiveRectangle {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.left).await?;
+        writer.write_u16_le(self.top).await?;
+        writer.write_u16_le(self.right).await?;
+        writer.write_u16_le(self.bottom).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.left = reader.read_u16_le().await?;
+        self.top = reader.read_u16_le().await?;
+        self.right = reader.read_u16_le().await?;
+        self.bottom = reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        8
+    }
+}
+
+/// Ask the server to send again some areas of the desktop
+/// MS-RDPBCGR 2.2.11.2.1 Refresh Rect PDU Data (TS_REFRESH_RECT_PDU)
+#[derive(Debug, Default)]
+pub struct RefreshRectPdu {
+    pub areas: Vec<InclusiveRectangle>,
+}
+
+#[async_trait]
+impl Message for RefreshRectPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u8(self.areas.len() as u8).await?;
+        writer.write_all(&[0; 3]).await?;
+        for area in &self.areas {
+            area.write_to(writer).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let number_of_areas = reader.read_u8().await?;
+        let mut pad = [0; 3];
+        reader.read_exact(&mut pad).await?;
+        self.areas = vec![InclusiveRectangle::default(); number_of_areas as usize];
+        for area in self.areas.iter_mut() {
+            area.read_from(reader).await?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4 + self.areas.len() * 8
+    }
+}
+
+/// Turn on or off the graphics updates sent by the server
+/// The desktop rectangle is only sent when updates are allowed
+/// MS-RDPBCGR 2.2.11.3.1 Suppress Output PDU Data (TS_SUPPRESS_OUTPUT_PDU)
+#[derive(Debug, Default)]
+pub struct SuppressOutputPdu {
+    pub desktop_rect: Option<InclusiveRectangle>,
+}
+
+#[async_trait]
+impl Message for SuppressOutputPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u8(self.desktop_rect.is_some() as u8).await?;
+        writer.write_all(&[0; 3]).await?;
+        if let Some(rect) = &self.desktop_rect {
+            rect.write_to(writer).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let allow_display_updates = reader.read_u8().await?;
+        let mut pad = [0; 3];
+        reader.read_exact(&mut pad).await?;
+        self.desktop_rect = None;
+        if allow_display_updates != 0 {
+            let mut rect = InclusiveRectangle::default();
+            rect.read_from(reader).await?;
+            self.desktop_rect = Some(rect);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4 + self.desktop_rect.map_or(0, |rect| rect.length())
+    }
+}
+
+/// All data PDUs handled by the global channel
+/// Payloads which are not decoded are kept raw
+#[derive(Debug)]
+pub enum DataPdu {
+    Update(BytesMut),
+    Pointer(BytesMut),
+    Synchronize(SynchronizePdu),
+    Control(ControlPdu),
+    FontMap(FontMapPdu),
+    SetErrorInfo(ErrorInfo),
+    SaveSessionInfo(Vec<SessionEvent>),
+    ShutdownDenied,
+    MonitorLayout(Vec<Monitor>),
+    Unknown(u8, BytesMut),
+}
+
+/// Read the monitors of the session
+/// MS-RDPBCGR 2.2.12.1 Monitor Layout PDU (TS_MONITOR_LAYOUT_PDU)
+pub fn read_monitor_layout(buffer: &mut BytesMut) -> Result<Vec<Monitor>> {
+    check_remaining(buffer, 4, "GLOBAL: monitor layout")?;
+    let monitor_count = buffer.get_u32_le() as usize;
+    if monitor_count > MONITOR_MAX_COUNT {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("GLOBAL: invalid number of monitors {}", monitor_count),
+        ));
+    }
+
+    (0..monitor_count)
+        .map(|_| {
+            let mut monitor = Monitor::default();
+            monitor.read_from_buffer(buffer)?;
+            Ok(monitor)
+        })
+        .collect()
+}
+
+/// Parse a data PDU and dispatch it on its type
+/// Bulk compressed data is decompressed first
+pub fn read_data_pdu(
+    buffer: &mut BytesMut,
+    bulk: &mut BulkDecompressor,
+) -> Result<(ShareDataHeader, DataPdu)> {
+    let mut header = ShareDataHeader::default();
+    header.read_from_buffer(buffer)?;
+    trace_pdu!(pdu_type_2 = header.pdu_type_2, "GLOBAL: received data PDU");
+
+    if header.compressed_type != 0 {
+        let data = bulk.decompress(&buffer.split(), header.compressed_type)?;
+        *buffer = BytesMut::from(&data[..]);
+    }
+
+    let pdu = match PDUType2::try_from(header.pdu_type_2) {
+        Ok(PDUType2::Pdutype2Update) => DataPdu::Update(buffer.split()),
+        Ok(PDUType2::Pdutype2Pointer) => DataPdu::Pointer(buffer.split()),
+        Ok(PDUType2::Pdutype2Synchronize) => {
+            let mut pdu = SynchronizePdu::default();
+            pdu.read_from_buffer(buffer)?;
+            DataPdu::Synchronize(pdu)
+        }
+        Ok(PDUType2::Pdutype2Control) => {
+            let mut pdu = ControlPdu::default();
+            pdu.read_from_buffer(buffer)?;
+            DataPdu::Control(pdu)
+        }
+        Ok(PDUType2::Pdutype2Fontmap) => {
+            let mut pdu = FontMapPdu::default();
+            pdu.read_from_buffer(buffer)?;
+            DataPdu::FontMap(pdu)
+        }
+        Ok(PDUType2::Pdutype2SetErrorInfoPdu) => {
+            check_remaining(buffer, 4, "GLOBAL: set error info")?;
+            DataPdu::SetErrorInfo(ErrorInfo::from(buffer.get_u32_le()))
+        }
+        Ok(PDUType2::Pdutype2SaveSessionInfo) => {
+            DataPdu::SaveSessionInfo(read_save_session_info(buffer)?)
+        }
+        Ok(PDUType2::Pdutype2ShutdownDenied) => DataPdu::ShutdownDenied,
+        Ok(PDUType2::Pdutype2MonitorLayoutPdu) => {
+            DataPdu::MonitorLayout(read_monitor_layout(buffer)?)
+        }
+        _ => DataPdu::Unknown(header.pdu_type_2, buffer.split()),
+    };
+    Ok((header, pdu))
+}
+
+/// All PDUs sent by the server on the global channel
+#[derive(Debug)]
+pub enum Pdu {
+    DemandActive(DemandActivePdu),
+    DeactivateAll,
+    Data(DataPdu),
+    Unknown(u16, BytesMut),
+}
+
+/// Parse a share control PDU and dispatch it on its type
+pub fn read_pdu(buffer: &mut BytesMut, bulk: &mut BulkDecompressor) -> Result<Pdu> {
+    let mut header = ShareControlHeader::default();
+    header.read_from_buffer(buffer)?;
+    let length = (header.total_length as usize).saturating_sub(6);
+    check_remaining(buffer, length, "GLOBAL: share control PDU")?;
+    let mut body = buffer.split_to(length);
+    trace_pdu!(pdu_type = header.pdu_type, size = length, "GLOBAL: received");
+
+    Ok(match header.get_pdu_type() {
+        Some(PDUType::PdutypeDemandactivepdu) => {
+            let mut pdu = DemandActivePdu::default();
+            pdu.read_from_buffer(&mut body)?;
+            Pdu::DemandActive(pdu)
+        }
+        Some(PDUType::PdutypeDeactivateallpdu) => Pdu::DeactivateAll,
+        Some(PDUType::PdutypeDatapdu) => Pdu::Data(read_data_pdu(&mut body, bulk)?.1),
+        _ => Pdu::Unknown(header.pdu_type, body),
+    })
+}
+
+/// A single payload can carry several PDUs
+pub fn read_pdus(buffer: &mut BytesMut, bulk: &mut BulkDecompressor) -> Result<Vec<Pdu>> {
+    let mut result = Vec::new();
+    while buffer.has_remaining() {
+        result.push(read_pdu(buffer, bulk)?);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::capability::{BrushCapability, Capability, ConfirmActivePdu};
+    use crate::model::data::to_vec;
+
+    #[tokio::test]
+    async fn test_share_control_header() {
+        let pdu = ConfirmActivePdu::new(
+            4,
+            b"rdp-rs",
+            vec![Capability::Brush(BrushCapability::default())],
+        );
+        let mut buffer = to_vec(&ShareControlHeader::new(
+            PDUType::PdutypeConfirmactivepdu,
+            12,
+            pdu.length(),
+        ))
+        .await
+        .unwrap();
+        buffer.extend(to_vec(&pdu).await.unwrap());
+
+        assert_eq!(
+            buffer,
+            vec![
+                34, 0, 19, 0, 12, 0, 4, 0, 0, 0, 234, 3, 6, 0, 12, 0, 114, 100, 112, 45, 114, 115,
+                1, 0, 0, 0, 15, 0, 8, 0, 0, 0, 0, 0
+            ]
+        )
+    }
+
+    #[test]
+    fn test_read_synchronize_pdu() {
+        let mut buffer = BytesMut::from(
+            &[
+                22, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 22, 0, 31, 0, 0, 0, 1, 0, 0, 0,
+            ][..],
+        );
+        let mut control = ShareControlHeader::default();
+        control.read_from_buffer(&mut buffer).unwrap();
+        assert_eq!(control.pdu_type, PDUType::PdutypeDatapdu as u16);
+
+        let mut data = ShareDataHeader::default();
+        data.read_from_buffer(&mut buffer).unwrap();
+        assert_eq!(data.pdu_type_2, PDUType2::Pdutype2Synchronize as u8);
+
+        let mut synchronize = SynchronizePdu::default();
+        synchronize.read_from_buffer(&mut buffer).unwrap();
+        assert_eq!(synchronize.message_type, 1);
+    }
+
+    #[test]
+    fn test_read_control_granted_pdu() {
+        let mut buffer = BytesMut::from(
+            &[
+                26, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 26, 0, 20, 0, 0, 0, 2, 0, 236, 3, 234, 3,
+                0, 0,
+            ][..],
+        );
+        ShareControlHeader::default()
+            .read_from_buffer(&mut buffer)
+            .unwrap();
+        let mut data = ShareDataHeader::default();
+        data.read_from_buffer(&mut buffer).unwrap();
+        assert_eq!(data.pdu_type_2, PDUType2::Pdutype2Control as u8);
+
+        let mut control = ControlPdu::default();
+        control.read_from_buffer(&mut buffer).unwrap();
+        assert_eq!(control.action, Action::CtrlactionGrantedControl as u16);
+        assert_eq!(control.grant_id, 1004);
+    }
+
+    /// Several PDUs can be sent in the same payload
+    #[test]
+    fn test_read_pdus() {
+        let mut buffer = BytesMut::from(
+            &[
+                22, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 22, 0, 31, 0, 0, 0, 1, 0, 0, 0, 22, 0,
+                23, 0, 234, 3, 234, 3, 1, 0, 0, 1, 22, 0, 47, 0, 0, 0, 12, 0, 0, 0,
+            ][..],
+        );
+        let pdus = read_pdus(&mut buffer, &mut BulkDecompressor::default()).unwrap();
+        assert_eq!(pdus.len(), 2);
+        assert!(matches!(pdus[0], Pdu::Data(DataPdu::Synchronize(_))));
+        assert!(matches!(pdus[1], Pdu::Data(DataPdu::SetErrorInfo(ErrorInfo::LogoffByUser))));
+    }
+
+    /// Data of a PDU can be bulk compressed
+    #[test]
+    fn test_read_compressed_data_pdu() {
+        // Literals of a 64 KB history are the bytes themselves
+        let mut buffer = BytesMut::from(
+            &[
+                22, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 1, 22, 0, 47, 0x21, 4, 0, 12, 0, 0, 0,
+            ][..],
+        );
+        let pdu = read_pdu(&mut buffer, &mut BulkDecompressor::default()).unwrap();
+        assert!(matches!(
+            pdu,
+            Pdu::Data(DataPdu::SetErrorInfo(ErrorInfo::LogoffByUser))
+        ));
+    }
+
+    #[test]
+    fn test_read_font_map_pdu() {
+        let mut buffer = BytesMut::from(
+            &[
+                26, 0, 23, 0, 234, 3, 234, 3, 1, 0, 0, 2, 26, 0, 40, 0, 0, 0, 0, 0, 0, 0, 3, 0, 4,
+                0,
+            ][..],
+        );
+        ShareControlHeader::default()
+            .read_from_buffer(&mut buffer)
+            .unwrap();
+        let mut data = ShareDataHeader::default();
+        data.read_from_buffer(&mut buffer).unwrap();
+        assert_eq!(data.pdu_type_2, PDUType2::Pdutype2Fontmap as u8);
+
+        let mut font_map = FontMapPdu::default();
+        font_map.read_from_buffer(&mut buffer).unwrap();
+        assert_eq!(font_map.map_flags, 3);
+        assert_eq!(font_map.entry_size, 4);
+    }
+
+    /// Areas follow the number of areas and the padding
+    #[tokio::test]
+    async fn test_write_refresh_rect_pdu() {
+        let pdu = RefreshRectPdu {
+            areas: vec![InclusiveRectangle::desktop(1024, 768)],
+        };
+        assert_eq!(pdu.length(), 12);
+        assert_eq!(
+            to_vec(&pdu).await.unwrap(),
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 255, 3, 255, 2]
+        );
+    }
+
+    /// The desktop rectangle is omitted when updates are suppressed
+    #[tokio::test]
+    async fn test_write_suppress_output_pdu() {
+        let suppress = SuppressOutputPdu { desktop_rect: None };
+        assert_eq!(to_vec(&suppress).await.unwrap(), vec![0, 0, 0, 0]);
+
+        let allow = SuppressOutputPdu {
+            desktop_rect: Some(InclusiveRectangle::desktop(800, 600)),
+        };
+        assert_eq!(
+            to_vec(&allow).await.unwrap(),
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 31, 3, 87, 2]
+        );
+    }
+
+    /// Test of a two monitors layout
+    #[test]
+    fn test_read_monitor_layout() {
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(&2u32.to_le_bytes());
+        for field in [0u32, 0, 1919, 1079, 1, 1920, 0, 3199, 1023, 0] {
+            buffer.extend_from_slice(&field.to_le_bytes());
+        }
+
+        let monitors = read_monitor_layout(&mut buffer).unwrap();
+        assert_eq!(
+            monitors,
+            vec![
+                Monitor::new(0, 0, 1920, 1080, true),
+                Monitor::new(1920, 0, 1280, 1024, false)
+            ]
+        );
+    }
+}
@@ -0,0 +1,888 @@
+use crate::codec::bulk::BulkDecompressor;
+use crate::core::capability::{
+    BitmapCodecsCapability, CapabilitiesConfig, Capability, ConfirmActivePdu, GeneralCapability,
+    InputFlags,
+};
+use crate::core::channel::{split_chunks, StaticChannels, CHANNEL_CHUNK_LENGTH};
+use crate::core::error_info::ErrorInfo;
+use crate::core::event::{BitmapEvent, ChannelEvent, RdpEvent, SessionEnd, SessionEvent};
+use crate::core::fastpath::{FastPathReader, FastPathUpdate};
+use crate::core::gcc::Monitor;
+use crate::core::global::base::{
+    read_pdus, Action, ControlPdu, DataPdu, FontListPdu, InclusiveRectangle, PDUType, PDUType2,
+    Pdu, RefreshRectPdu, ShareControlHeader, ShareDataHeader, SuppressOutputPdu, SynchronizePdu,
+};
+use crate::core::heartbeat::{HeartbeatMonitor, HeartbeatPdu, HeartbeatStatus};
+use crate::core::input::{
+    FastPathInputPdu, InputEvent, SlowPathInputPdu, FASTPATH_INPUT_MAX_EVENTS,
+};
+use crate::core::keyboard::type_text;
+use crate::core::order::altsec::AltSecondaryOrder;
+use crate::core::order::base::{DrawingOrder, OrderReader};
+use crate::core::order::cache::OrderCache;
+use crate::core::order::gdi::{decode_bitmap, Gdi};
+use crate::core::pointer::{read_fast_path_pointer, read_pointer_pdu, PointerCache};
+use crate::core::runtime::{default_timer, Timer};
+use crate::core::sec::base::{SecurityFlag, SecurityHeader};
+use crate::core::sec::client::SecClient;
+use crate::core::surface::{read_surface_commands, SurfaceCommand};
+use crate::core::tpkt::base::Payload;
+use crate::core::tpkt::queue::WritePriority;
+use crate::core::trace::trace_pdu;
+use crate::core::update::{read_update, Palette, Update};
+use crate::model::data::{to_vec, Message};
+
+use bytes::{Bytes, BytesMut};
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+
+/// Global channel client
+/// Handle the capability exchange and the
+/// connection finalization sequence
+pub struct GlobalClient<S> {
+    /// Security transport layer
+    sec: SecClient<S>,
+    /// Share id negotiated by global
+    /// channel during connection sequence
+    share_id: u32,
+    /// Capabilities sent by the client
+    config: CapabilitiesConfig,
+    /// Keep tracing of server capabilities
+    server_capabilities: Vec<Capability>,
+    /// Last error info sent by the server
+    termination_reason: Option<ErrorInfo>,
+    /// Reassemble fast path updates
+    fast_path: FastPathReader,
+    /// History of the bulk compressed data,
+    /// shared by slow path and fast path
+    bulk: BulkDecompressor,
+    /// Shapes of the pointers sent by the server
+    pointer_cache: PointerCache,
+    /// Current palette of 8 bpp sessions
+    palette: Palette,
+    /// State of the drawing orders
+    orders: OrderReader,
+    /// Bitmaps, glyphs and brushes used by drawing orders
+    caches: OrderCache,
+    /// Draws the orders, only when the server may send them
+    gdi: Option<Gdi>,
+    /// Codec of each id used by surface bits commands
+    codecs: BitmapCodecsCapability,
+    /// Last state of the toggle keys
+    /// Sent again after each activation
+    toggle_keys: InputEvent,
+    /// Monitors sent by the server
+    monitor_layout: Vec<Monitor>,
+    /// Missed heartbeats of the message channel
+    heartbeat: HeartbeatMonitor,
+    /// Close the connection once too many heartbeats are missed
+    heartbeat_watchdog: bool,
+    /// Wakes up once per heartbeat period
+    timer: Arc<dyn Timer>,
+    /// End of the current heartbeat period,
+    /// kept when a receive is cancelled
+    heartbeat_deadline: Option<Instant>,
+    /// A shutdown request was sent and not denied
+    logoff_requested: bool,
+    /// Chunks of the static virtual channels
+    channels: StaticChannels,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> GlobalClient<S> {
+    /// Exchange capabilities with the server
+    /// and run the connection finalization
+    ///
+    /// Once connected graphics and input can flow
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let config = CapabilitiesConfig {
+    ///     desktop_width: 1024,
+    ///     desktop_height: 768,
+    ///     ..Default::default()
+    /// };
+    /// let global = GlobalClient::connect(sec, config).await?;
+    /// ```
+    #[cfg_attr(
+        feature = "trace",
+        tracing::instrument(name = "capabilities", skip_all)
+    )]
+    pub async fn connect(sec: SecClient<S>, config: CapabilitiesConfig) -> Result<GlobalClient<S>> {
+        let pointer_cache = PointerCache::new(config.pointer_cache_size);
+        let caches = OrderCache::new(&config.bitmap_cache_entries, &config.glyph_cache());
+        let codecs = config.bitmap_codecs_capability();
+        let limits = sec.get_mcs().get_limits();
+        let fast_path = FastPathReader::new(
+            sec.get_mcs().get_metrics().clone(),
+            limits
+                .max_reassembled_size
+                .min(config.multifragment_max_request_size as usize),
+        );
+        let mut client = GlobalClient {
+            sec,
+            share_id: 0,
+            config,
+            server_capabilities: Vec::new(),
+            termination_reason: None,
+            fast_path,
+            bulk: BulkDecompressor::default(),
+            pointer_cache,
+            palette: Palette::default(),
+            orders: OrderReader::default(),
+            caches,
+            gdi: None,
+            codecs,
+            toggle_keys: InputEvent::sync(false, false, false, false),
+            monitor_layout: Vec::new(),
+            heartbeat: HeartbeatMonitor::default(),
+            heartbeat_watchdog: false,
+            timer: default_timer(),
+            heartbeat_deadline: None,
+            logoff_requested: false,
+            channels: StaticChannels::new(limits),
+        };
+        client.activate().await?;
+        Ok(client)
+    }
+
+    /// Capability exchange followed by the finalization sequence
+    /// Used on connection and after a deactivate all PDU
+    async fn activate(&mut self) -> Result<()> {
+        // Primary orders restart from their default state
+        self.orders = OrderReader::default();
+        self.read_demand_active().await?;
+        // Orders draw over the new desktop
+        self.gdi = if self.config.orders_enabled() {
+            Some(Gdi::new(
+                self.config.desktop_width,
+                self.config.desktop_height,
+                self.config.color_depth,
+            ))
+        } else {
+            None
+        };
+        self.write_confirm_active().await?;
+        self.write_client_finalize().await?;
+        self.read_server_finalize().await?;
+        self.write_input(&[self.toggle_keys]).await
+    }
+
+    /// Read the next PDUs sent on the global channel
+    async fn read_pdus(&mut self) -> Result<Vec<Pdu>> {
+        loop {
+            match self.sec.read().await? {
+                (channel_name, Payload::Raw(mut payload)) if channel_name == "global" => {
+                    return read_pdus(&mut payload, &mut self.bulk)
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    /// Read demand Active payload
+    /// This message is sent from server to client
+    /// and inform about server capabilities
+    async fn read_demand_active(&mut self) -> Result<()> {
+        loop {
+            for pdu in self.read_pdus().await? {
+                if let Pdu::DemandActive(pdu) = pdu {
+                    // The client must use the desktop size of the server
+                    if let Some((width, height)) = pdu.desktop_size() {
+                        self.config.desktop_width = width;
+                        self.config.desktop_height = height;
+                    }
+                    self.share_id = pdu.share_id;
+                    self.server_capabilities = pdu.capability_sets;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Read the server side of the finalization sequence
+    /// Synchronize, control cooperate, control granted
+    /// and font map are expected in this order
+    async fn read_server_finalize(&mut self) -> Result<()> {
+        let mut step = 0;
+        while step < 4 {
+            for pdu in self.read_pdus().await? {
+                match (step, pdu) {
+                    (0, Pdu::Data(DataPdu::Synchronize(_))) => step += 1,
+                    (1, Pdu::Data(DataPdu::Control(control)))
+                        if control.action == Action::CtrlactionCooperate as u16 =>
+                    {
+                        step += 1
+                    }
+                    (2, Pdu::Data(DataPdu::Control(control)))
+                        if control.action == Action::CtrlactionGrantedControl as u16 =>
+                    {
+                        step += 1
+                    }
+                    (3, Pdu::Data(DataPdu::FontMap(_))) => step += 1,
+                    // May be sent before the end of the finalization
+                    (_, Pdu::Data(DataPdu::MonitorLayout(monitors))) => {
+                        self.monitor_layout = monitors
+                    }
+                    (_, Pdu::Data(DataPdu::Control(_))) => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            "GLOBAL: unexpected control action",
+                        ))
+                    }
+                    _ => continue,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write confirm active pdu
+    /// This PDU include all client capabilities
+    async fn write_confirm_active(&mut self) -> Result<()> {
+        let pdu = ConfirmActivePdu::new(
+            self.share_id,
+            self.sec.get_mcs().get_client_name().as_bytes(),
+            self.config.capability_sets(),
+        );
+        self.write_pdu(PDUType::PdutypeConfirmactivepdu, &pdu, WritePriority::Low)
+            .await
+    }
+
+    /// This is the finalize connection sequence
+    /// sent from client to server
+    async fn write_client_finalize(&mut self) -> Result<()> {
+        let channel_id = self.sec.get_mcs().get_global_channel_id();
+        self.write_data_pdu(
+            PDUType2::Pdutype2Synchronize,
+            &SynchronizePdu::new(channel_id),
+        )
+        .await?;
+        self.write_data_pdu(
+            PDUType2::Pdutype2Control,
+            &ControlPdu::new(Action::CtrlactionCooperate),
+        )
+        .await?;
+        self.write_data_pdu(
+            PDUType2::Pdutype2Control,
+            &ControlPdu::new(Action::CtrlactionRequestControl),
+        )
+        .await?;
+        self.write_data_pdu(PDUType2::Pdutype2Fontlist, &FontListPdu::default())
+            .await
+    }
+
+    /// Send a classic PDU to the global channel
+    async fn write_pdu(
+        &mut self,
+        pdu_type: PDUType,
+        message: &impl Message,
+        priority: WritePriority,
+    ) -> Result<()> {
+        let user_id = self.sec.get_mcs().get_user_id();
+        trace_pdu!(pdu_type = ?pdu_type, size = message.length(), "GLOBAL: sent");
+        let header = ShareControlHeader::new(pdu_type, user_id, message.length());
+        let mut buffer = to_vec(&header).await?;
+        buffer.extend(to_vec(message).await?);
+        self.sec
+            .write_with_priority("global", 0, buffer, priority)
+            .await
+    }
+
+    /// Send Data pdu
+    pub async fn write_data_pdu(
+        &mut self,
+        pdu_type_2: PDUType2,
+        message: &impl Message,
+    ) -> Result<()> {
+        trace_pdu!(pdu_type_2 = ?pdu_type_2, "GLOBAL: sent data PDU");
+        // Slow path input goes before the acknowledgements and refreshes
+        let priority = match pdu_type_2 {
+            PDUType2::Pdutype2Input => WritePriority::High,
+            _ => WritePriority::Low,
+        };
+        let mut buffer = to_vec(&ShareDataHeader::new(
+            self.share_id,
+            pdu_type_2,
+            message.length(),
+        ))
+        .await?;
+        buffer.extend(to_vec(message).await?);
+        self.write_pdu(PDUType::PdutypeDatapdu, &buffer, priority)
+            .await
+    }
+
+    /// Send input events to the server
+    /// All events are batched in a single PDU
+    ///
+    /// Fast path is used when the server supports it
+    /// otherwise events are sent as a slow path data PDU
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// global.write_input(&[
+    ///     InputEvent::mouse_move(100, 100),
+    ///     InputEvent::mouse_button(PointerButton::Left, true, 100, 100),
+    /// ]).await?;
+    /// ```
+    pub async fn write_input(&mut self, events: &[InputEvent]) -> Result<()> {
+        if !self.is_fast_path_input() {
+            return self
+                .write_data_pdu(
+                    PDUType2::Pdutype2Input,
+                    &SlowPathInputPdu::new(events.to_vec()),
+                )
+                .await;
+        }
+
+        for chunk in events.chunks(FASTPATH_INPUT_MAX_EVENTS) {
+            self.sec
+                .write_fast_path(FastPathInputPdu::new(chunk.to_vec())?)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Synchronize the lock keys of the remote session
+    /// with the state of the client keyboard
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// global.sync_toggle_keys(true, false, false, false).await?;
+    /// ```
+    pub async fn sync_toggle_keys(
+        &mut self,
+        num_lock: bool,
+        caps_lock: bool,
+        scroll_lock: bool,
+        kana: bool,
+    ) -> Result<()> {
+        self.toggle_keys = InputEvent::sync(num_lock, caps_lock, scroll_lock, kana);
+        self.write_input(&[self.toggle_keys]).await
+    }
+
+    /// Type a text without any keyboard layout mapping
+    /// Each character is sent as unicode keyboard events
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// global.send_unicode("hello world").await?;
+    /// global.send_unicode(&'\n'.to_string()).await?;
+    /// ```
+    pub async fn send_unicode(&mut self, text: &str) -> Result<()> {
+        let unicode_supported = self.server_capabilities.iter().any(|capability| {
+            matches!(capability, Capability::Input(input)
+                if input.input_flags & InputFlags::InputFlagUnicode as u16 != 0)
+        });
+        if !unicode_supported {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "GLOBAL: server does not support unicode input",
+            ));
+        }
+        self.write_input(&InputEvent::unicode(text)).await
+    }
+
+    /// Type a text as scancodes of the negotiated keyboard layout
+    /// Unlike `send_unicode` the remote session sees real key strokes
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// global.type_text("dir C:\\\n").await?;
+    /// ```
+    pub async fn type_text(&mut self, text: &str) -> Result<()> {
+        let events = type_text(self.config.keyboard_layout, text)?;
+        self.write_input(&events).await
+    }
+
+    /// Ask the server to repaint some areas of the desktop
+    /// Useful to redraw the whole screen after attaching a new view
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// global.refresh(&[InclusiveRectangle::desktop(1024, 768)]).await?;
+    /// ```
+    pub async fn refresh(&mut self, rects: &[InclusiveRectangle]) -> Result<()> {
+        if !self.is_server_general_flag(|general| general.refresh_rect_support) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "GLOBAL: server does not support refresh rect",
+            ));
+        }
+
+        // numberOfAreas is a single byte
+        for chunk in rects.chunks(u8::MAX as usize) {
+            let pdu = RefreshRectPdu {
+                areas: chunk.to_vec(),
+            };
+            self.write_data_pdu(PDUType2::Pdutype2RefreshRect, &pdu)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Stop or resume the graphics updates sent by the server
+    /// When enabled the server stops sending graphics,
+    /// when disabled updates resume for the rectangle
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// // window is minimized
+    /// global.suppress_output(true, InclusiveRectangle::desktop(1024, 768)).await?;
+    /// // window is restored
+    /// global.suppress_output(false, InclusiveRectangle::desktop(1024, 768)).await?;
+    /// ```
+    pub async fn suppress_output(&mut self, enabled: bool, rect: InclusiveRectangle) -> Result<()> {
+        if !self.is_server_general_flag(|general| general.suppress_output_support) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "GLOBAL: server does not support suppress output",
+            ));
+        }
+
+        let pdu = SuppressOutputPdu {
+            desktop_rect: if enabled { None } else { Some(rect) },
+        };
+        self.write_data_pdu(PDUType2::Pdutype2SuppressOutput, &pdu)
+            .await
+    }
+
+    /// Read payload on global channel
+    ///
+    /// A deactivate all PDU restarts the capability exchange
+    /// and the finalization sequence without breaking the connection
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// global.read(|event| match event {
+    ///     RdpEvent::Session(SessionEvent::Reactivated { width, height }) => {
+    ///         // resize the frame buffer
+    ///     }
+    ///     _ => (),
+    /// }).await?;
+    /// ```
+    pub async fn read<T>(&mut self, mut callback: T) -> Result<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let (channel_name, payload) = self.receive(&mut callback).await?;
+        self.process(channel_name, payload, callback).await
+    }
+
+    /// Wait for the next payload of any channel
+    ///
+    /// Cancel safe, nothing is written to the server
+    /// until the payload is given to `process`
+    pub async fn receive<T>(&mut self, mut callback: T) -> Result<(String, Payload)>
+    where
+        T: FnMut(RdpEvent),
+    {
+        self.receive_payload(&mut callback)
+            .await
+            .map_err(|e| self.end_session(e, &mut callback))
+    }
+
+    /// Handle a payload given by `receive`
+    ///
+    /// A deactivate all PDU is handled until the end of the reactivation,
+    /// so the future must not be cancelled
+    pub async fn process<T>(
+        &mut self,
+        channel_name: String,
+        payload: Payload,
+        mut callback: T,
+    ) -> Result<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let payload = match self.sec.process(channel_name, payload).await {
+            Ok(payload) => payload,
+            Err(e) => return Err(self.end_session(e, &mut callback)),
+        };
+        let mut payload = match payload {
+            (channel_name, Payload::Raw(payload)) if channel_name == "global" => payload,
+            (channel_name, Payload::Raw(payload)) if channel_name == "message" => {
+                return self.read_message(payload)
+            }
+            (_, Payload::FastPath(_, mut payload)) => {
+                for update in self.fast_path.read(&mut payload, &mut self.bulk)? {
+                    match update {
+                        FastPathUpdate::Bitmap(bitmaps) => {
+                            for bitmap in bitmaps {
+                                callback(RdpEvent::Bitmap(self.draw_bitmap(bitmap)?));
+                            }
+                        }
+                        FastPathUpdate::Palette(palette) => self.palette = palette,
+                        FastPathUpdate::Orders(mut data) => {
+                            let orders = self.orders.read_fast_path(&mut data)?;
+                            self.draw_orders(orders, &mut callback)?;
+                        }
+                        FastPathUpdate::SurfaceCommands(mut data) => {
+                            for command in read_surface_commands(&mut data)? {
+                                match command {
+                                    SurfaceCommand::SurfaceBits(bits) => {
+                                        let bitmap = bits.into_bitmap(&self.codecs)?;
+                                        let bitmap = self.draw_bitmap(bitmap)?;
+                                        callback(RdpEvent::Bitmap(bitmap));
+                                    }
+                                    SurfaceCommand::FrameMarker { action, .. } => {
+                                        callback(RdpEvent::Session(SessionEvent::Frame(action)))
+                                    }
+                                }
+                            }
+                        }
+                        FastPathUpdate::Pointer(update_type, mut data) => {
+                            let update = read_fast_path_pointer(update_type, &mut data)?;
+                            callback(RdpEvent::Cursor(self.pointer_cache.update(update)?));
+                        }
+                        _ => (),
+                    }
+                }
+                return Ok(());
+            }
+            (channel_name, Payload::Raw(payload)) => {
+                if let Some(data) = self.channels.read(&channel_name, payload)? {
+                    callback(RdpEvent::Channel(ChannelEvent {
+                        name: channel_name,
+                        data,
+                    }));
+                }
+                return Ok(());
+            }
+        };
+
+        for pdu in read_pdus(&mut payload, &mut self.bulk)? {
+            match pdu {
+                Pdu::DeactivateAll => {
+                    self.activate().await?;
+                    callback(RdpEvent::Session(SessionEvent::Reactivated {
+                        width: self.config.desktop_width,
+                        height: self.config.desktop_height,
+                    }));
+                }
+                Pdu::Data(DataPdu::Update(mut payload)) => match read_update(&mut payload)? {
+                    Update::Bitmap(bitmaps) => {
+                        for bitmap in bitmaps {
+                            callback(RdpEvent::Bitmap(self.draw_bitmap(bitmap)?));
+                        }
+                    }
+                    Update::Palette(palette) => self.palette = palette,
+                    Update::Orders(number_orders, mut data) => {
+                        let orders = self.orders.read(number_orders, &mut data)?;
+                        self.draw_orders(orders, &mut callback)?;
+                    }
+                    _ => (),
+                },
+                Pdu::Data(DataPdu::Pointer(mut payload)) => {
+                    let update = read_pointer_pdu(&mut payload)?;
+                    callback(RdpEvent::Cursor(self.pointer_cache.update(update)?));
+                }
+                // Code zero is sent to clear a previous error
+                Pdu::Data(DataPdu::SetErrorInfo(ErrorInfo::None)) => {
+                    self.termination_reason = None;
+                }
+                Pdu::Data(DataPdu::SetErrorInfo(reason)) => {
+                    self.termination_reason = Some(reason);
+                    callback(RdpEvent::Session(SessionEvent::Terminated(reason)));
+                }
+                Pdu::Data(DataPdu::SaveSessionInfo(events)) => {
+                    for event in events {
+                        callback(RdpEvent::Session(event));
+                    }
+                }
+                Pdu::Data(DataPdu::MonitorLayout(monitors)) => {
+                    self.monitor_layout = monitors.clone();
+                    callback(RdpEvent::Session(SessionEvent::MonitorLayout(monitors)));
+                }
+                Pdu::Data(DataPdu::ShutdownDenied) => {
+                    self.logoff_requested = false;
+                    callback(RdpEvent::Session(SessionEvent::LogoffDenied));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Give the messages of a static virtual channel to a handle
+    /// instead of channel events
+    pub fn open_channel(&mut self, channel_name: &str, sender: mpsc::UnboundedSender<Bytes>) {
+        self.channels.open(channel_name, sender);
+    }
+
+    /// Send a message on a static virtual channel
+    /// It is split in chunks of the size accepted by the server
+    pub async fn write_channel(
+        &mut self,
+        channel_name: &str,
+        flags: u32,
+        data: &[u8],
+    ) -> Result<()> {
+        let chunk_length = self
+            .server_capabilities
+            .iter()
+            .find_map(|capability| match capability {
+                Capability::VirtualChannel(virtual_channel) => virtual_channel.vc_chunk_size,
+                _ => None,
+            })
+            .map_or(CHANNEL_CHUNK_LENGTH, |size| size as usize);
+        for chunk in split_chunks(data, flags, chunk_length) {
+            self.sec.write_with_flags(channel_name, 0, chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Convert a bitmap update with the palette
+    /// When orders are drawn, the bitmap is decoded and drawn by the GDI too
+    fn draw_bitmap(&mut self, bitmap: BitmapEvent) -> Result<BitmapEvent> {
+        let bitmap = self.palette.apply(bitmap);
+        match &mut self.gdi {
+            Some(gdi) => {
+                let bitmap = decode_bitmap(bitmap)?;
+                gdi.apply_bitmap(&bitmap);
+                Ok(bitmap)
+            }
+            None => Ok(bitmap),
+        }
+    }
+
+    /// Apply drawing orders to the caches and the GDI
+    /// Regions drawn by primary orders are sent as bitmap events,
+    /// frame markers as session events and windowing orders as window events
+    fn draw_orders<T>(&mut self, orders: Vec<DrawingOrder>, callback: &mut T) -> Result<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        for order in orders {
+            match order {
+                DrawingOrder::Secondary(order) => self.caches.insert(order, &self.palette)?,
+                DrawingOrder::AltSecondary(AltSecondaryOrder::FrameMarker(action)) => {
+                    callback(RdpEvent::Session(SessionEvent::Frame(action)))
+                }
+                DrawingOrder::AltSecondary(AltSecondaryOrder::Window(order)) => {
+                    callback(RdpEvent::Window(order))
+                }
+                DrawingOrder::AltSecondary(order) => {
+                    if let Some(gdi) = &mut self.gdi {
+                        gdi.apply_alt_secondary(&order)?;
+                    }
+                }
+                DrawingOrder::Primary { bounds, order } => {
+                    let gdi = match &mut self.gdi {
+                        Some(gdi) => gdi,
+                        None => continue,
+                    };
+                    if let Some(bitmap) =
+                        gdi.draw(&order, bounds, &self.palette, &mut self.caches)?
+                    {
+                        callback(RdpEvent::Bitmap(bitmap));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for the next payload of any channel
+    /// Each heartbeat period without any message
+    /// is reported as a missed heartbeat
+    async fn receive_payload<T>(&mut self, callback: &mut T) -> Result<(String, Payload)>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let period = match self.heartbeat.get_period() {
+            Some(period) => period,
+            None => return self.sec.receive().await,
+        };
+
+        loop {
+            let deadline = *self
+                .heartbeat_deadline
+                .get_or_insert_with(|| Instant::now() + period);
+            tokio::select! {
+                result = self.sec.receive() => {
+                    self.heartbeat.reset();
+                    self.heartbeat_deadline = None;
+                    return result;
+                }
+                _ = self.timer.sleep(deadline.saturating_duration_since(Instant::now())) => {
+                    self.heartbeat_deadline = Some(deadline + period);
+                    let missed = match self.heartbeat.miss() {
+                        HeartbeatStatus::Alive => continue,
+                        HeartbeatStatus::Warning(missed) => missed,
+                        HeartbeatStatus::Lost(_) if self.heartbeat_watchdog => {
+                            return Err(Error::new(
+                                ErrorKind::TimedOut,
+                                "GLOBAL: too many missed heartbeats",
+                            ));
+                        }
+                        HeartbeatStatus::Lost(missed) => missed,
+                    };
+                    callback(RdpEvent::Session(SessionEvent::HeartbeatMissed(missed)));
+                }
+            }
+        }
+    }
+
+    /// Report the end of the session before giving back the error
+    fn end_session<T>(&self, error: Error, callback: &mut T) -> Error
+    where
+        T: FnMut(RdpEvent),
+    {
+        callback(RdpEvent::Session(SessionEvent::Ended(
+            self.session_end(&error),
+        )));
+        error
+    }
+
+    /// Reason of the end of the session once reading failed
+    fn session_end(&self, error: &Error) -> SessionEnd {
+        let logged_off = matches!(
+            self.termination_reason,
+            Some(ErrorInfo::LogoffByUser) | Some(ErrorInfo::RpcInitiatedLogoff)
+        );
+        match self.termination_reason {
+            _ if logged_off || self.logoff_requested => SessionEnd::LoggedOff,
+            Some(reason) => SessionEnd::Disconnected(reason),
+            None if error.kind() == ErrorKind::ConnectionAborted => {
+                SessionEnd::Disconnected(ErrorInfo::None)
+            }
+            None => SessionEnd::Dropped,
+        }
+    }
+
+    /// Handle a message channel PDU
+    /// The security header gives its type
+    fn read_message(&mut self, mut payload: BytesMut) -> Result<()> {
+        let mut header = SecurityHeader::default();
+        header.read_from_buffer(&mut payload)?;
+
+        if header.flags & SecurityFlag::SecHeartbeat as u16 != 0 {
+            let mut heartbeat = HeartbeatPdu::default();
+            heartbeat.read_from_buffer(&mut payload)?;
+            self.heartbeat.update(heartbeat);
+        }
+        Ok(())
+    }
+
+    /// Return an error from `read` once the server
+    /// reconnect count of missed heartbeats is reached
+    /// so the caller can drop a silently dead connection
+    ///
+    /// Missed heartbeats are always reported as session events
+    /// until then
+    pub fn set_heartbeat_watchdog(&mut self, enabled: bool) {
+        self.heartbeat_watchdog = enabled;
+    }
+
+    /// Timer of the heartbeat periods
+    pub fn set_timer(&mut self, timer: Arc<dyn Timer>) {
+        self.timer = timer;
+    }
+
+    /// Heartbeat settings sent by the server
+    /// The period is zero if the server did not send any
+    pub fn get_heartbeat(&self) -> HeartbeatPdu {
+        self.heartbeat.get_settings()
+    }
+
+    /// Check a support field of the server general capability
+    fn is_server_general_flag(&self, flag: impl Fn(&GeneralCapability) -> u8) -> bool {
+        self.server_capabilities
+            .iter()
+            .any(|capability| match capability {
+                Capability::General(general) => flag(general) != 0,
+                _ => false,
+            })
+    }
+
+    /// Check if the server accepts fast path input
+    pub fn is_fast_path_input(&self) -> bool {
+        let fast_path_flags =
+            InputFlags::InputFlagFastpathInput as u16 | InputFlags::InputFlagFastpathInput2 as u16;
+        self.server_capabilities
+            .iter()
+            .any(|capability| match capability {
+                Capability::Input(input) => input.input_flags & fast_path_flags != 0,
+                _ => false,
+            })
+    }
+
+    /// Capabilities sent by the client
+    /// Desktop size is updated on reactivation
+    pub fn get_config(&self) -> &CapabilitiesConfig {
+        &self.config
+    }
+
+    /// Share id negotiated during the capability exchange
+    pub fn get_share_id(&self) -> u32 {
+        self.share_id
+    }
+
+    /// Capabilities announced by the server
+    pub fn get_server_capabilities(&self) -> &[Capability] {
+        &self.server_capabilities
+    }
+
+    /// Reason sent by the server before closing the session
+    /// None if the server did not send any
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// if let Some(reason) = global.get_termination_reason() {
+    ///     println!("session closed: {}", reason);
+    /// }
+    /// ```
+    pub fn get_termination_reason(&self) -> Option<ErrorInfo> {
+        self.termination_reason
+    }
+
+    /// Last monitor layout sent by the server
+    /// Empty if the server did not send any
+    pub fn get_monitor_layout(&self) -> &[Monitor] {
+        &self.monitor_layout
+    }
+
+    /// Getter of the underlying security layer
+    pub fn get_sec(&self) -> &SecClient<S> {
+        &self.sec
+    }
+
+    /// Ask the server to log off the user
+    /// MS-RDPBCGR 2.2.2.1 Shutdown Request PDU
+    ///
+    /// The server either closes the session, reported as
+    /// a LoggedOff session end, or denies the request
+    /// and asks the user to confirm in the remote session
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// global.logoff().await?;
+    /// loop {
+    ///     global.read(|event| match event {
+    ///         RdpEvent::Session(SessionEvent::LogoffDenied) => println!("confirm the logoff"),
+    ///         RdpEvent::Session(SessionEvent::Ended(end)) => println!("{:?}", end),
+    ///         _ => (),
+    ///     }).await?;
+    /// }
+    /// ```
+    pub async fn logoff(&mut self) -> Result<()> {
+        self.logoff_requested = true;
+        self.write_data_pdu(PDUType2::Pdutype2ShutdownRequest, &Vec::<u8>::new())
+            .await
+    }
+
+    /// Send a close event to server
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.sec.shutdown().await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> GlobalClient<S> {
+    /// Write the next PDUs from a task of their own
+    pub fn start_write_queue(&mut self, capacity: usize) {
+        self.sec.start_write_queue(capacity);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::core::event::{
+    BitmapEvent, ChannelEvent, CursorEvent, DamageEvent, DynamicChannelEvent, RdpEvent,
+    SessionEvent,
+};
+use crate::core::order::window::WindowOrder;
+
+use std::io::Error;
+
+/// Receiver of the events of a session
+/// driven by `RdpClient::run` or `SessionReader::run`
+///
+/// All methods do nothing by default
+/// Inputs are sent through the SessionWriter given by run
+///
+/// Static channel data, like the clipboard, is given raw to on_channel
+///
+/// # Example
+/// ```rust, ignore
+/// struct Viewer;
+///
+/// impl RdpEventHandler for Viewer {
+///     fn on_bitmap(&mut self, bitmap: BitmapEvent) {
+///         // do something with bitmap
+///     }
+///
+///     fn on_disconnect(&mut self, error: std::io::Error) {
+///         println!("Session closed: {}", error);
+///     }
+/// }
+///
+/// let (task, writer) = client.run(Viewer);
+/// ```
+pub trait RdpEventHandler: Send {
+    /// New bitmap of the remote desktop
+    fn on_bitmap(&mut self, _bitmap: BitmapEvent) {}
+
+    /// Pointer shape or position sent by the server
+    fn on_pointer(&mut self, _cursor: CursorEvent) {}
+
+    /// Session state notifications
+    /// The last one is SessionEvent::Ended
+    fn on_session(&mut self, _event: SessionEvent) {}
+
+    /// Data of a static virtual channel
+    fn on_channel(&mut self, _channel: ChannelEvent) {}
+
+    /// Window of a RemoteApp session
+    fn on_window(&mut self, _order: WindowOrder) {}
+
+    /// Updated regions of the desktop, instead of
+    /// the bitmaps once the frame rate is limited
+    fn on_damage(&mut self, _damage: DamageEvent) {}
+
+    /// Event of a client of a dynamic virtual channel
+    fn on_dynamic_channel(&mut self, _event: DynamicChannelEvent) {}
+
+    /// Error which closed the session
+    /// No other method is called after this one
+    fn on_disconnect(&mut self, _error: Error) {}
+
+    /// Give an event to the method of its kind
+    /// Input events are never sent by the server and are ignored
+    fn on_event(&mut self, event: RdpEvent) {
+        match event {
+            RdpEvent::Bitmap(bitmap) => self.on_bitmap(bitmap),
+            RdpEvent::Cursor(cursor) => self.on_pointer(cursor),
+            RdpEvent::Session(event) => self.on_session(event),
+            RdpEvent::Channel(channel) => self.on_channel(channel),
+            RdpEvent::Window(order) => self.on_window(order),
+            RdpEvent::Damage(damage) => self.on_damage(damage),
+            RdpEvent::Dynamic(event) => self.on_dynamic_channel(event),
+            RdpEvent::Pointer(_) | RdpEvent::Key(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Bytes;
+
+    #[derive(Default)]
+    struct Recorder {
+        cursors: Vec<CursorEvent>,
+        channels: Vec<String>,
+    }
+
+    impl RdpEventHandler for Recorder {
+        fn on_pointer(&mut self, cursor: CursorEvent) {
+            self.cursors.push(cursor);
+        }
+
+        fn on_channel(&mut self, channel: ChannelEvent) {
+            self.channels.push(channel.name);
+        }
+    }
+
+    /// Events reach the method of their kind
+    #[test]
+    fn test_handler_on_event() {
+        let mut handler = Recorder::default();
+        handler.on_event(RdpEvent::Cursor(CursorEvent::Hidden));
+        handler.on_event(RdpEvent::Session(SessionEvent::LogonNotify));
+        handler.on_event(RdpEvent::Channel(ChannelEvent {
+            name: "cliprdr".to_string(),
+            data: Bytes::new(),
+        }));
+        assert_eq!(handler.cursors, vec![CursorEvent::Hidden]);
+        assert_eq!(handler.channels, vec!["cliprdr"]);
+    }
+}
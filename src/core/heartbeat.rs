@@ -0,0 +1,142 @@
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BytesMut};
+use std::io::Result;
+use std::time::Duration;
+
+/// Heartbeat settings sent by the server
+/// on the message channel
+/// MS-RDPBCGR 2.2.16.1 Server Heartbeat PDU (SERVER_HEARTBEAT_PDU)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct HeartbeatPdu {
+    /// Seconds between two heartbeats
+    /// Zero disables the heartbeats
+    pub period: u8,
+    /// Missed heartbeats before a warning
+    pub warning_count: u8,
+    /// Missed heartbeats before a reconnection
+    pub reconnect_count: u8,
+}
+
+impl HeartbeatPdu {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "HEARTBEAT: heartbeat")?;
+        let _reserved = buffer.get_u8();
+        self.period = buffer.get_u8();
+        self.warning_count = buffer.get_u8();
+        self.reconnect_count = buffer.get_u8();
+        Ok(())
+    }
+}
+
+/// State of the link after a missed heartbeat
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HeartbeatStatus {
+    Alive,
+    /// The warning count is reached
+    Warning(u8),
+    /// The reconnect count is reached
+    Lost(u8),
+}
+
+/// Count the heartbeats missed since the last message
+///
+/// The server only sends a heartbeat when it has
+/// nothing else to send, so any message resets the count
+///
+/// # Example
+/// ```
+/// use rdp::core::heartbeat::{HeartbeatMonitor, HeartbeatPdu, HeartbeatStatus};
+/// let mut monitor = HeartbeatMonitor::default();
+/// monitor.update(HeartbeatPdu {
+///     period: 5,
+///     warning_count: 1,
+///     reconnect_count: 2,
+/// });
+/// assert_eq!(monitor.miss(), HeartbeatStatus::Warning(1));
+/// assert_eq!(monitor.miss(), HeartbeatStatus::Lost(2));
+/// monitor.reset();
+/// ```
+#[derive(Default)]
+pub struct HeartbeatMonitor {
+    /// Last settings sent by the server
+    settings: HeartbeatPdu,
+    /// Heartbeats missed since the last message
+    missed: u8,
+}
+
+impl HeartbeatMonitor {
+    /// Apply new settings sent by the server
+    pub fn update(&mut self, settings: HeartbeatPdu) {
+        self.settings = settings;
+        self.missed = 0;
+    }
+
+    /// Time without any message after which a heartbeat is missed
+    /// None while heartbeats are disabled
+    pub fn get_period(&self) -> Option<Duration> {
+        match self.settings.period {
+            0 => None,
+            period => Some(Duration::from_secs(period as u64)),
+        }
+    }
+
+    /// A message was received
+    pub fn reset(&mut self) {
+        self.missed = 0;
+    }
+
+    /// A whole period elapsed without any message
+    pub fn miss(&mut self) -> HeartbeatStatus {
+        self.missed = self.missed.saturating_add(1);
+        let reached = |count: u8| count != 0 && self.missed >= count;
+        if reached(self.settings.reconnect_count) {
+            HeartbeatStatus::Lost(self.missed)
+        } else if reached(self.settings.warning_count) {
+            HeartbeatStatus::Warning(self.missed)
+        } else {
+            HeartbeatStatus::Alive
+        }
+    }
+
+    /// Last settings sent by the server
+    pub fn get_settings(&self) -> HeartbeatPdu {
+        self.settings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test of the heartbeat format
+    #[test]
+    fn test_read_heartbeat() {
+        let mut buffer = BytesMut::from(&[0u8, 5, 2, 3][..]);
+        let mut heartbeat = HeartbeatPdu::default();
+        heartbeat.read_from_buffer(&mut buffer).unwrap();
+        assert_eq!(heartbeat.period, 5);
+        assert_eq!(heartbeat.warning_count, 2);
+        assert_eq!(heartbeat.reconnect_count, 3);
+    }
+
+    /// Any message resets the missed heartbeats
+    #[test]
+    fn test_heartbeat_monitor() {
+        let mut monitor = HeartbeatMonitor::default();
+        assert_eq!(monitor.get_period(), None);
+
+        monitor.update(HeartbeatPdu {
+            period: 5,
+            warning_count: 2,
+            reconnect_count: 3,
+        });
+        assert_eq!(monitor.get_period(), Some(Duration::from_secs(5)));
+        assert_eq!(monitor.miss(), HeartbeatStatus::Alive);
+        assert_eq!(monitor.miss(), HeartbeatStatus::Warning(2));
+        monitor.reset();
+        assert_eq!(monitor.miss(), HeartbeatStatus::Alive);
+        monitor.miss();
+        assert_eq!(monitor.miss(), HeartbeatStatus::Lost(3));
+    }
+}
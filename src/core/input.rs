@@ -0,0 +1,701 @@
+use crate::core::event::{KeyboardEvent, PointerButton, PointerEvent};
+use crate::model::data::Message;
+
+use async_trait::async_trait;
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+/// Flags of a pointer event
+/// MS-RDPBCGR 2.2.8.1.1.3.1.1.3 Mouse Event (TS_POINTER_EVENT)
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PointerFlag {
+    PtrflagsHwheel = 0x0400,
+    PtrflagsWheel = 0x0200,
+    PtrflagsWheelNegative = 0x0100,
+    WheelRotationMask = 0x01FF,
+    PtrflagsMove = 0x0800,
+    PtrflagsDown = 0x8000,
+    PtrflagsButton1 = 0x1000,
+    PtrflagsButton2 = 0x2000,
+    PtrflagsButton3 = 0x4000,
+}
+
+/// Flags of an extended pointer event
+/// MS-RDPBCGR 2.2.8.1.1.3.1.1.4 Extended Mouse Event (TS_POINTER_X_EVENT)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PointerXFlag {
+    PtrxflagsDown = 0x8000,
+    PtrxflagsButton1 = 0x0001,
+    PtrxflagsButton2 = 0x0002,
+}
+
+/// State of the toggle keys in a synchronize event
+/// MS-RDPBCGR 2.2.8.1.1.3.1.1.5 Synchronize Event (TS_SYNC_EVENT)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ToggleFlag {
+    TsSyncScrollLock = 0x00000001,
+    TsSyncNumLock = 0x00000002,
+    TsSyncCapsLock = 0x00000004,
+    TsSyncKanaLock = 0x00000008,
+}
+
+/// Event code of a fast path input event
+/// MS-RDPBCGR 2.2.8.1.2.2 Fast-Path Input Event (TS_FP_INPUT_EVENT)
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FastPathInputEventCode {
+    FastpathInputEventScancode = 0x0,
+    FastpathInputEventMouse = 0x1,
+    FastpathInputEventMousex = 0x2,
+    FastpathInputEventSync = 0x3,
+    FastpathInputEventUnicode = 0x4,
+}
+
+/// Keyboard flags of a fast path input event
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FastPathKeyboardFlag {
+    FastpathInputKbdflagsRelease = 0x01,
+    FastpathInputKbdflagsExtended = 0x02,
+    FastpathInputKbdflagsExtended1 = 0x04,
+}
+
+/// Message type of a slow path input event
+/// MS-RDPBCGR 2.2.8.1.1.3.1.1 Slow-Path Input Event (TS_INPUT_EVENT)
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InputEventType {
+    InputEventSync = 0x0000,
+    InputEventUnused = 0x0002,
+    InputEventScancode = 0x0004,
+    InputEventUnicode = 0x0005,
+    InputEventMouse = 0x8001,
+    InputEventMousex = 0x8002,
+}
+
+/// Keyboard flags of a slow path input event
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum KeyboardFlag {
+    KbdflagsExtended = 0x0100,
+    KbdflagsExtended1 = 0x0200,
+    KbdflagsDown = 0x4000,
+    KbdflagsRelease = 0x8000,
+}
+
+/// Size of a slow path input event
+const SLOW_PATH_INPUT_EVENT_SIZE: usize = 12;
+
+/// Largest number of events in a single fast path PDU
+pub const FASTPATH_INPUT_MAX_EVENTS: usize = 255;
+
+/// Input event sent by the client
+/// The same event can be encoded as fast path or slow path
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InputEvent {
+    /// Raw keyboard scancode
+    Scancode { code: u8, extended: bool, down: bool },
+    /// Keyboard event using an UTF-16 code unit
+    Unicode { code: u16, down: bool },
+    /// Mouse move, button or wheel
+    Mouse { flags: u16, x: u16, y: u16 },
+    /// Extended mouse buttons
+    MouseX { flags: u16, x: u16, y: u16 },
+    /// State of the toggle keys
+    Sync { flags: u32 },
+}
+
+impl InputEvent {
+    /// Mouse move without any button
+    pub fn mouse_move(x: u16, y: u16) -> Self {
+        InputEvent::Mouse {
+            flags: PointerFlag::PtrflagsMove as u16,
+            x,
+            y,
+        }
+    }
+
+    /// Press or release a mouse button
+    /// Extended buttons need the server to support extended mouse events
+    pub fn mouse_button(button: PointerButton, down: bool, x: u16, y: u16) -> Self {
+        let mut flags = match button {
+            PointerButton::Left => PointerFlag::PtrflagsButton1 as u16,
+            PointerButton::Right => PointerFlag::PtrflagsButton2 as u16,
+            PointerButton::Middle => PointerFlag::PtrflagsButton3 as u16,
+            PointerButton::None => PointerFlag::PtrflagsMove as u16,
+            PointerButton::X1 => {
+                return InputEvent::mouse_x_button(PointerXFlag::PtrxflagsButton1, down, x, y)
+            }
+            PointerButton::X2 => {
+                return InputEvent::mouse_x_button(PointerXFlag::PtrxflagsButton2, down, x, y)
+            }
+        };
+        if down {
+            flags |= PointerFlag::PtrflagsDown as u16;
+        }
+        InputEvent::Mouse { flags, x, y }
+    }
+
+    /// Press or release an extended mouse button
+    fn mouse_x_button(button: PointerXFlag, down: bool, x: u16, y: u16) -> Self {
+        let mut flags = button as u16;
+        if down {
+            flags |= PointerXFlag::PtrxflagsDown as u16;
+        }
+        InputEvent::MouseX { flags, x, y }
+    }
+
+    /// Vertical wheel rotation, positive is forward
+    /// The rotation is a 9 bits signed value
+    pub fn mouse_wheel(rotation: i16, x: u16, y: u16) -> Self {
+        InputEvent::Mouse {
+            flags: PointerFlag::PtrflagsWheel as u16 | wheel_rotation(rotation),
+            x,
+            y,
+        }
+    }
+
+    /// Horizontal wheel rotation, positive is right
+    /// Needs the server to support horizontal wheel
+    pub fn mouse_horizontal_wheel(rotation: i16, x: u16, y: u16) -> Self {
+        InputEvent::Mouse {
+            flags: PointerFlag::PtrflagsHwheel as u16 | wheel_rotation(rotation),
+            x,
+            y,
+        }
+    }
+
+    /// Synchronize the state of the toggle keys
+    pub fn sync(num_lock: bool, caps_lock: bool, scroll_lock: bool, kana: bool) -> Self {
+        let toggles = [
+            (num_lock, ToggleFlag::TsSyncNumLock),
+            (caps_lock, ToggleFlag::TsSyncCapsLock),
+            (scroll_lock, ToggleFlag::TsSyncScrollLock),
+            (kana, ToggleFlag::TsSyncKanaLock),
+        ];
+        InputEvent::Sync {
+            flags: toggles
+                .iter()
+                .filter(|(enabled, _)| *enabled)
+                .fold(0, |flags, (_, flag)| flags | *flag as u32),
+        }
+    }
+
+    /// Press and release events for each UTF-16 code unit of a text
+    /// Characters outside of the BMP are sent as surrogate pairs
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::input::InputEvent;
+    /// assert_eq!(InputEvent::unicode("a").len(), 2);
+    /// assert_eq!(InputEvent::unicode("\u{1F600}").len(), 4);
+    /// ```
+    pub fn unicode(text: &str) -> Vec<InputEvent> {
+        text.encode_utf16()
+            .flat_map(|code| {
+                [
+                    InputEvent::Unicode { code, down: true },
+                    InputEvent::Unicode { code, down: false },
+                ]
+            })
+            .collect()
+    }
+
+    /// Mouse move without any button or wheel
+    fn is_mouse_move(&self) -> bool {
+        matches!(self, InputEvent::Mouse { flags, .. } if *flags == PointerFlag::PtrflagsMove as u16)
+    }
+
+    /// Write the event in the fast path format
+    async fn write_fast_path(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        match *self {
+            InputEvent::Scancode {
+                code,
+                extended,
+                down,
+            } => {
+                let mut flags = 0;
+                if !down {
+                    flags |= FastPathKeyboardFlag::FastpathInputKbdflagsRelease as u8;
+                }
+                if extended {
+                    flags |= FastPathKeyboardFlag::FastpathInputKbdflagsExtended as u8;
+                }
+                writer
+                    .write_u8(fast_path_header(
+                        FastPathInputEventCode::FastpathInputEventScancode,
+                        flags,
+                    ))
+                    .await?;
+                writer.write_u8(code).await
+            }
+            InputEvent::Unicode { code, down } => {
+                let flags = if down {
+                    0
+                } else {
+                    FastPathKeyboardFlag::FastpathInputKbdflagsRelease as u8
+                };
+                writer
+                    .write_u8(fast_path_header(
+                        FastPathInputEventCode::FastpathInputEventUnicode,
+                        flags,
+                    ))
+                    .await?;
+                writer.write_u16_le(code).await
+            }
+            InputEvent::Mouse { flags, x, y } | InputEvent::MouseX { flags, x, y } => {
+                let code = match self {
+                    InputEvent::Mouse { .. } => FastPathInputEventCode::FastpathInputEventMouse,
+                    _ => FastPathInputEventCode::FastpathInputEventMousex,
+                };
+                writer.write_u8(fast_path_header(code, 0)).await?;
+                writer.write_u16_le(flags).await?;
+                writer.write_u16_le(x).await?;
+                writer.write_u16_le(y).await
+            }
+            InputEvent::Sync { flags } => {
+                writer
+                    .write_u8(fast_path_header(
+                        FastPathInputEventCode::FastpathInputEventSync,
+                        flags as u8,
+                    ))
+                    .await
+            }
+        }
+    }
+
+    /// Write the event in the slow path format
+    /// All events have the same size
+    async fn write_slow_path(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        // Event time is ignored by the server
+        writer.write_u32_le(0).await?;
+        match *self {
+            InputEvent::Scancode {
+                code,
+                extended,
+                down,
+            } => {
+                let mut flags = if down {
+                    KeyboardFlag::KbdflagsDown as u16
+                } else {
+                    KeyboardFlag::KbdflagsRelease as u16
+                };
+                if extended {
+                    flags |= KeyboardFlag::KbdflagsExtended as u16;
+                }
+                writer.write_u16_le(InputEventType::InputEventScancode as u16).await?;
+                writer.write_u16_le(flags).await?;
+                writer.write_u16_le(code as u16).await?;
+                writer.write_u16_le(0).await
+            }
+            InputEvent::Unicode { code, down } => {
+                let flags = if down {
+                    0
+                } else {
+                    KeyboardFlag::KbdflagsRelease as u16
+                };
+                writer.write_u16_le(InputEventType::InputEventUnicode as u16).await?;
+                writer.write_u16_le(flags).await?;
+                writer.write_u16_le(code).await?;
+                writer.write_u16_le(0).await
+            }
+            InputEvent::Mouse { flags, x, y } | InputEvent::MouseX { flags, x, y } => {
+                let message_type = match self {
+                    InputEvent::Mouse { .. } => InputEventType::InputEventMouse,
+                    _ => InputEventType::InputEventMousex,
+                };
+                writer.write_u16_le(message_type as u16).await?;
+                writer.write_u16_le(flags).await?;
+                writer.write_u16_le(x).await?;
+                writer.write_u16_le(y).await
+            }
+            InputEvent::Sync { flags } => {
+                writer.write_u16_le(InputEventType::InputEventSync as u16).await?;
+                writer.write_u16_le(0).await?;
+                writer.write_u32_le(flags).await
+            }
+        }
+    }
+
+    /// Size of the event in the fast path format
+    fn fast_path_length(&self) -> usize {
+        match self {
+            InputEvent::Scancode { .. } => 2,
+            InputEvent::Unicode { .. } => 3,
+            InputEvent::Mouse { .. } | InputEvent::MouseX { .. } => 7,
+            InputEvent::Sync { .. } => 1,
+        }
+    }
+}
+
+/// Encode a wheel rotation on 9 bits
+/// The high bit is the PTRFLAGS_WHEEL_NEGATIVE flag
+fn wheel_rotation(rotation: i16) -> u16 {
+    rotation.clamp(-256, 255) as u16 & PointerFlag::WheelRotationMask as u16
+}
+
+/// Event header of a fast path input event
+/// Flags use the five low bits
+fn fast_path_header(code: FastPathInputEventCode, flags: u8) -> u8 {
+    ((code as u8) << 5) | (flags & 0x1F)
+}
+
+/// Keyboard events use the high byte
+/// of the code to mark extended scancodes
+impl From<KeyboardEvent> for InputEvent {
+    fn from(event: KeyboardEvent) -> Self {
+        InputEvent::Scancode {
+            code: event.code as u8,
+            extended: event.code > 0xFF,
+            down: event.down,
+        }
+    }
+}
+
+impl From<PointerEvent> for InputEvent {
+    fn from(event: PointerEvent) -> Self {
+        InputEvent::mouse_button(event.button, event.down, event.x, event.y)
+    }
+}
+
+/// Fast path input events batched in a single PDU
+/// The number of events is always sent
+/// in the optional numEvents field
+/// MS-RDPBCGR 2.2.8.1.2 Client Fast-Path Input Event PDU (TS_FP_INPUT_PDU)
+///
+/// # Example
+/// ```
+/// use rdp::core::input::{FastPathInputPdu, InputEvent};
+/// let pdu = FastPathInputPdu::new(vec![InputEvent::mouse_move(10, 20)]).unwrap();
+/// ```
+pub struct FastPathInputPdu {
+    events: Vec<InputEvent>,
+}
+
+impl FastPathInputPdu {
+    pub fn new(events: Vec<InputEvent>) -> Result<Self> {
+        if events.len() > FASTPATH_INPUT_MAX_EVENTS {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "INPUT: too many events for a fast path PDU",
+            ));
+        }
+        Ok(FastPathInputPdu { events })
+    }
+}
+
+#[async_trait]
+impl Message for FastPathInputPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u8(self.events.len() as u8).await?;
+        for event in &self.events {
+            event.write_fast_path(writer).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, _reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "INPUT: fast path input is only sent by the client",
+        ))
+    }
+
+    fn length(&self) -> usize {
+        1 + self.events.iter().map(|e| e.fast_path_length()).sum::<usize>()
+    }
+}
+
+/// Slow path input events batched in a single data PDU
+/// Used when the server does not support fast path input
+/// MS-RDPBCGR 2.2.8.1.1.3.1 Client Input Event PDU Data (TS_INPUT_PDU_DATA)
+pub struct SlowPathInputPdu {
+    events: Vec<InputEvent>,
+}
+
+impl SlowPathInputPdu {
+    pub fn new(events: Vec<InputEvent>) -> Self {
+        SlowPathInputPdu { events }
+    }
+}
+
+#[async_trait]
+impl Message for SlowPathInputPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.events.len() as u16).await?;
+        writer.write_u16_le(0).await?;
+        for event in &self.events {
+            event.write_slow_path(writer).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, _reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "INPUT: slow path input is only sent by the client",
+        ))
+    }
+
+    fn length(&self) -> usize {
+        4 + self.events.len() * SLOW_PATH_INPUT_EVENT_SIZE
+    }
+}
+
+/// Input events waiting to be sent together
+///
+/// Consecutive mouse moves are merged since only the last position
+/// matters, and the events are sent in a single PDU once the oldest
+/// one waited for the max latency
+///
+/// # Example
+/// ```
+/// use rdp::core::input::{InputEvent, InputQueue};
+/// use std::time::Duration;
+/// let mut queue = InputQueue::new(Duration::from_millis(16));
+/// queue.push(InputEvent::mouse_move(10, 10));
+/// queue.push(InputEvent::mouse_move(20, 20));
+/// assert!(!queue.is_due());
+/// assert_eq!(queue.take(), vec![InputEvent::mouse_move(20, 20)]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct InputQueue {
+    events: Vec<InputEvent>,
+    /// Longest wait of an event, zero sends them at once
+    max_latency: Duration,
+    /// Push time of the oldest event, only kept with a latency
+    oldest: Option<Instant>,
+}
+
+impl InputQueue {
+    pub fn new(max_latency: Duration) -> Self {
+        InputQueue {
+            events: Vec::new(),
+            max_latency,
+            oldest: None,
+        }
+    }
+
+    /// Queue an event, a mouse move replaces the move queued just before
+    pub fn push(&mut self, event: InputEvent) {
+        match self.events.last_mut() {
+            Some(last) if last.is_mouse_move() && event.is_mouse_move() => *last = event,
+            _ => self.events.push(event),
+        }
+        if !self.max_latency.is_zero() {
+            self.oldest.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Queue several events
+    pub fn extend(&mut self, events: &[InputEvent]) {
+        for event in events {
+            self.push(*event);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Time at which the queued events must be sent
+    /// None when the queue is empty or without latency
+    pub fn deadline(&self) -> Option<Instant> {
+        self.oldest.map(|oldest| oldest + self.max_latency)
+    }
+
+    /// The queued events must be sent now, because the oldest
+    /// one waited long enough or they fill a fast path PDU
+    pub fn is_due(&self) -> bool {
+        if self.events.len() >= FASTPATH_INPUT_MAX_EVENTS {
+            return true;
+        }
+        match self.deadline() {
+            Some(deadline) => deadline <= Instant::now(),
+            None => !self.events.is_empty(),
+        }
+    }
+
+    /// Take all the queued events
+    pub fn take(&mut self) -> Vec<InputEvent> {
+        self.oldest = None;
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Test the encoding of all kind of events
+    #[tokio::test]
+    async fn test_write_fast_path_input() {
+        let pdu = FastPathInputPdu::new(vec![
+            InputEvent::Scancode {
+                code: 0x1C,
+                extended: true,
+                down: false,
+            },
+            InputEvent::Unicode {
+                code: 0x41,
+                down: true,
+            },
+            InputEvent::mouse_button(PointerButton::Left, true, 10, 20),
+            InputEvent::Sync { flags: 0x4 },
+        ])
+        .unwrap();
+
+        let buffer = to_vec(&pdu).await.unwrap();
+        assert_eq!(buffer.len(), pdu.length());
+        assert_eq!(
+            buffer,
+            vec![4, 0x03, 0x1C, 0x80, 0x41, 0x00, 0x20, 0x00, 0x90, 10, 0, 20, 0, 0x64]
+        );
+    }
+
+    /// Test the slow path encoding of a key press
+    #[tokio::test]
+    async fn test_write_slow_path_input() {
+        let pdu = SlowPathInputPdu::new(vec![
+            InputEvent::Scancode {
+                code: 0x1C,
+                extended: false,
+                down: true,
+            },
+            InputEvent::Sync { flags: 0x2 },
+        ]);
+
+        let buffer = to_vec(&pdu).await.unwrap();
+        assert_eq!(buffer.len(), pdu.length());
+        assert_eq!(
+            buffer,
+            vec![
+                2, 0, 0, 0, 0, 0, 0, 0, 0x04, 0x00, 0x00, 0x40, 0x1C, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 2, 0, 0, 0
+            ]
+        );
+    }
+
+    /// Each code unit is pressed then released
+    #[test]
+    fn test_unicode() {
+        assert_eq!(
+            InputEvent::unicode("\u{E9}"),
+            vec![
+                InputEvent::Unicode {
+                    code: 0xE9,
+                    down: true
+                },
+                InputEvent::Unicode {
+                    code: 0xE9,
+                    down: false
+                }
+            ]
+        );
+    }
+
+    /// Toggle keys are combined in the sync flags
+    #[test]
+    fn test_sync() {
+        assert_eq!(InputEvent::sync(true, true, false, false), InputEvent::Sync { flags: 0x6 });
+        assert_eq!(InputEvent::sync(false, false, false, false), InputEvent::Sync { flags: 0 });
+    }
+
+    /// Extended buttons are sent as extended mouse events
+    #[test]
+    fn test_mouse_x_button() {
+        assert_eq!(
+            InputEvent::mouse_button(PointerButton::X2, true, 1, 2),
+            InputEvent::MouseX {
+                flags: 0x8002,
+                x: 1,
+                y: 2
+            }
+        );
+        assert_eq!(
+            InputEvent::mouse_horizontal_wheel(-1, 0, 0),
+            InputEvent::Mouse {
+                flags: 0x05FF,
+                x: 0,
+                y: 0
+            }
+        );
+    }
+
+    /// Negative rotations set the negative flag
+    #[test]
+    fn test_mouse_wheel() {
+        assert_eq!(
+            InputEvent::mouse_wheel(-120, 0, 0),
+            InputEvent::Mouse {
+                flags: 0x0388,
+                x: 0,
+                y: 0
+            }
+        );
+        assert_eq!(
+            InputEvent::mouse_wheel(120, 0, 0),
+            InputEvent::Mouse {
+                flags: 0x0278,
+                x: 0,
+                y: 0
+            }
+        );
+    }
+
+    /// Only consecutive mouse moves are merged
+    #[test]
+    fn test_input_queue_coalesce() {
+        let mut queue = InputQueue::new(Duration::ZERO);
+        queue.push(InputEvent::mouse_move(1, 1));
+        queue.push(InputEvent::mouse_move(2, 2));
+        queue.push(InputEvent::mouse_button(PointerButton::Left, true, 2, 2));
+        queue.push(InputEvent::mouse_move(3, 3));
+        queue.push(InputEvent::mouse_wheel(120, 3, 3));
+        queue.push(InputEvent::mouse_move(4, 4));
+        queue.push(InputEvent::mouse_move(5, 5));
+        assert!(queue.is_due());
+        assert_eq!(
+            queue.take(),
+            vec![
+                InputEvent::mouse_move(2, 2),
+                InputEvent::mouse_button(PointerButton::Left, true, 2, 2),
+                InputEvent::mouse_move(3, 3),
+                InputEvent::mouse_wheel(120, 3, 3),
+                InputEvent::mouse_move(5, 5),
+            ]
+        );
+        assert!(queue.is_empty());
+        assert!(!queue.is_due());
+    }
+
+    /// Events wait for the latency unless they fill a PDU
+    #[test]
+    fn test_input_queue_deadline() {
+        let mut queue = InputQueue::new(Duration::from_secs(60));
+        assert_eq!(queue.deadline(), None);
+        queue.push(InputEvent::Sync { flags: 0 });
+        assert!(queue.deadline().is_some());
+        assert!(!queue.is_due());
+        for code in 0..FASTPATH_INPUT_MAX_EVENTS as u16 {
+            queue.push(InputEvent::Unicode { code, down: true });
+        }
+        assert!(queue.is_due());
+        queue.take();
+        assert_eq!(queue.deadline(), None);
+    }
+}
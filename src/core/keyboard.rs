@@ -0,0 +1,441 @@
+use crate::core::gcc::KeyboardLayout;
+use crate::core::input::InputEvent;
+
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+
+/// Named keys with their set 1 scancode
+/// Extended keys are prefixed by 0xE0
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Key {
+    Escape,
+    Backspace,
+    Tab,
+    Enter,
+    Space,
+    CapsLock,
+    LeftShift,
+    RightShift,
+    LeftCtrl,
+    RightCtrl,
+    LeftAlt,
+    /// Also known as AltGr
+    RightAlt,
+    LeftWin,
+    RightWin,
+    Menu,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    NumLock,
+    ScrollLock,
+    PrintScreen,
+    /// Function key from F1 to F24
+    F(u8),
+}
+
+impl Key {
+    /// Set 1 scancode and extended flag
+    /// Function keys out of range are mapped on F1
+    pub fn scancode(self) -> (u8, bool) {
+        match self {
+            Key::Escape => (0x01, false),
+            Key::Backspace => (0x0E, false),
+            Key::Tab => (0x0F, false),
+            Key::Enter => (0x1C, false),
+            Key::Space => (0x39, false),
+            Key::CapsLock => (0x3A, false),
+            Key::LeftShift => (0x2A, false),
+            Key::RightShift => (0x36, false),
+            Key::LeftCtrl => (0x1D, false),
+            Key::RightCtrl => (0x1D, true),
+            Key::LeftAlt => (0x38, false),
+            Key::RightAlt => (0x38, true),
+            Key::LeftWin => (0x5B, true),
+            Key::RightWin => (0x5C, true),
+            Key::Menu => (0x5D, true),
+            Key::Insert => (0x52, true),
+            Key::Delete => (0x53, true),
+            Key::Home => (0x47, true),
+            Key::End => (0x4F, true),
+            Key::PageUp => (0x49, true),
+            Key::PageDown => (0x51, true),
+            Key::Up => (0x48, true),
+            Key::Down => (0x50, true),
+            Key::Left => (0x4B, true),
+            Key::Right => (0x4D, true),
+            Key::NumLock => (0x45, false),
+            Key::ScrollLock => (0x46, false),
+            Key::PrintScreen => (0x37, true),
+            Key::F(n @ 1..=10) => (0x3A + n, false),
+            Key::F(11) => (0x57, false),
+            Key::F(12) => (0x58, false),
+            Key::F(n @ 13..=23) => (0x64 + n - 13, false),
+            Key::F(24) => (0x76, false),
+            Key::F(_) => (0x3B, false),
+        }
+    }
+
+    /// Press or release event of the key
+    pub fn event(self, down: bool) -> InputEvent {
+        let (code, extended) = self.scancode();
+        InputEvent::Scancode {
+            code,
+            extended,
+            down,
+        }
+    }
+}
+
+/// Scancodes of the character keys
+/// Row by row from the top left of an ISO keyboard
+/// 0x56 is the key between left shift and the first letter
+const CHARACTER_SCANCODES: [u8; 48] = [
+    0x29, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, // digits
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x1B, 0x2B, // top
+    0x1E, 0x1F, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, // middle
+    0x56, 0x2C, 0x2D, 0x2E, 0x2F, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35, // bottom
+];
+
+/// Characters produced by each key of `CHARACTER_SCANCODES`
+/// A space means that no character is produced
+struct LayoutMap {
+    normal: &'static str,
+    shift: &'static str,
+    altgr: &'static str,
+    /// Keys which need a space to produce their character
+    dead_keys: &'static str,
+}
+
+const US_MAP: LayoutMap = LayoutMap {
+    normal: "`1234567890-=qwertyuiop[]\\asdfghjkl;' zxcvbnm,./",
+    shift: "~!@#$%^&*()_+QWERTYUIOP{}|ASDFGHJKL:\" ZXCVBNM<>?",
+    altgr: "",
+    dead_keys: "",
+};
+
+const UK_MAP: LayoutMap = LayoutMap {
+    normal: "`1234567890-=qwertyuiop[]#asdfghjkl;'\\zxcvbnm,./",
+    shift: "¬!\"£$%^&*()_+QWERTYUIOP{}~ASDFGHJKL:@|ZXCVBNM<>?",
+    altgr: "¦   €",
+    dead_keys: "",
+};
+
+const GERMAN_MAP: LayoutMap = LayoutMap {
+    normal: "^1234567890ß´qwertzuiopü+#asdfghjklöä<yxcvbnm,.-",
+    shift: "°!\"§$%&/()=?`QWERTZUIOPÜ*'ASDFGHJKLÖÄ>YXCVBNM;:_",
+    altgr: "  ²³   {[]}\\ @ €        ~            |      µ",
+    dead_keys: "^´`",
+};
+
+const FRENCH_MAP: LayoutMap = LayoutMap {
+    normal: "²&é\"'(-è_çà)=azertyuiop^$*qsdfghjklmù<wxcvbn,;:!",
+    shift: " 1234567890°+AZERTYUIOP¨£µQSDFGHJKLM%>WXCVBN?./§",
+    altgr: "  ~#{[|`\\^@]}  €        ¤",
+    dead_keys: "^¨~`",
+};
+
+impl LayoutMap {
+    /// Layouts supported by the text typing helper
+    fn from_layout(layout: KeyboardLayout) -> Option<&'static LayoutMap> {
+        match layout {
+            KeyboardLayout::US => Some(&US_MAP),
+            KeyboardLayout::UnitedKingdom => Some(&UK_MAP),
+            KeyboardLayout::German => Some(&GERMAN_MAP),
+            KeyboardLayout::French => Some(&FRENCH_MAP),
+            _ => None,
+        }
+    }
+
+    /// Find the key and the modifier producing a character
+    fn find(&self, c: char) -> Option<(u8, Option<Key>)> {
+        let modifiers = [
+            (self.normal, None),
+            (self.shift, Some(Key::LeftShift)),
+            (self.altgr, Some(Key::RightAlt)),
+        ];
+        modifiers.iter().find_map(|(chars, modifier)| {
+            chars
+                .chars()
+                .position(|x| x == c && x != ' ')
+                .map(|index| (CHARACTER_SCANCODES[index], *modifier))
+        })
+    }
+}
+
+/// Press and release a key wrapped by an optional modifier
+fn push_key(events: &mut Vec<InputEvent>, code: u8, modifier: Option<Key>) {
+    if let Some(modifier) = modifier {
+        events.push(modifier.event(true));
+    }
+    for down in [true, false] {
+        events.push(InputEvent::Scancode {
+            code,
+            extended: false,
+            down,
+        });
+    }
+    if let Some(modifier) = modifier {
+        events.push(modifier.event(false));
+    }
+}
+
+/// Convert a text into scancode events for a keyboard layout
+/// Shift and AltGr are pressed when needed
+/// and dead keys are followed by a space
+///
+/// Only US, UK, German and French layouts are supported
+///
+/// # Example
+/// ```
+/// use rdp::core::gcc::KeyboardLayout;
+/// use rdp::core::keyboard::type_text;
+/// let events = type_text(KeyboardLayout::US, "Hi\n").unwrap();
+/// assert_eq!(events.len(), 8);
+/// ```
+pub fn type_text(layout: KeyboardLayout, text: &str) -> Result<Vec<InputEvent>> {
+    let map = LayoutMap::from_layout(layout).ok_or_else(|| {
+        Error::new(
+            ErrorKind::Unsupported,
+            format!("KEYBOARD: no scancode map for layout {:?}", layout),
+        )
+    })?;
+
+    let mut events = Vec::new();
+    for c in text.chars() {
+        let named = match c {
+            '\n' => Some(Key::Enter),
+            '\t' => Some(Key::Tab),
+            ' ' => Some(Key::Space),
+            _ => None,
+        };
+        if let Some(key) = named {
+            events.push(key.event(true));
+            events.push(key.event(false));
+            continue;
+        }
+
+        let (code, modifier) = map.find(c).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("KEYBOARD: character {:?} is not available on {:?}", c, layout),
+            )
+        })?;
+        push_key(&mut events, code, modifier);
+
+        if map.dead_keys.contains(c) {
+            events.push(Key::Space.event(true));
+            events.push(Key::Space.event(false));
+        }
+    }
+    Ok(events)
+}
+
+/// Side which handles the system shortcuts,
+/// the Windows key combinations, Alt+Tab, Alt+Esc and Ctrl+Esc
+/// Values of the keyboardhook setting of connection files
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KeyboardHook {
+    /// Shortcuts act on the local computer
+    Local = 0,
+    /// Shortcuts are sent to the remote session
+    Remote = 1,
+    /// Shortcuts are sent only while the session is in full screen
+    FullScreen = 2,
+}
+
+// A #[default] variant would also be the fallback of try_from
+#[allow(clippy::derivable_impls)]
+impl Default for KeyboardHook {
+    fn default() -> Self {
+        KeyboardHook::FullScreen
+    }
+}
+
+impl KeyboardHook {
+    /// Shortcuts go to the remote session
+    pub fn is_remote(self, fullscreen: bool) -> bool {
+        match self {
+            KeyboardHook::Local => false,
+            KeyboardHook::Remote => true,
+            KeyboardHook::FullScreen => fullscreen,
+        }
+    }
+}
+
+/// Drop the shortcuts handled by the local computer
+/// from the keyboard events sent to the server
+///
+/// The application still has to grab the keys from the local system
+/// when the shortcuts go to the remote session
+///
+/// # Example
+/// ```
+/// use rdp::core::keyboard::{Key, KeyboardHook, ShortcutFilter};
+/// let mut filter = ShortcutFilter::new(KeyboardHook::FullScreen, false);
+/// assert!(filter.forward(&Key::LeftAlt.event(true)));
+/// assert!(!filter.forward(&Key::Tab.event(true)));
+/// filter.set_fullscreen(true);
+/// assert!(filter.forward(&Key::Tab.event(true)));
+/// ```
+pub struct ShortcutFilter {
+    hook: KeyboardHook,
+    fullscreen: bool,
+    alt: bool,
+    ctrl: bool,
+}
+
+impl ShortcutFilter {
+    pub fn new(hook: KeyboardHook, fullscreen: bool) -> Self {
+        ShortcutFilter {
+            hook,
+            fullscreen,
+            alt: false,
+            ctrl: false,
+        }
+    }
+
+    /// Follow the display mode of the session
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        self.fullscreen = fullscreen;
+    }
+
+    /// False when the event is part of a shortcut handled locally
+    /// Modifiers are always forwarded to keep the server state in sync
+    pub fn forward(&mut self, event: &InputEvent) -> bool {
+        let key = match *event {
+            InputEvent::Scancode {
+                code,
+                extended,
+                down,
+            } => {
+                let key = (code, extended);
+                if key == Key::LeftAlt.scancode() || key == Key::RightAlt.scancode() {
+                    self.alt = down;
+                } else if key == Key::LeftCtrl.scancode() || key == Key::RightCtrl.scancode() {
+                    self.ctrl = down;
+                }
+                key
+            }
+            _ => return true,
+        };
+        if self.hook.is_remote(self.fullscreen) {
+            return true;
+        }
+
+        let escape = key == Key::Escape.scancode();
+        !(key == Key::LeftWin.scancode()
+            || key == Key::RightWin.scancode()
+            || (self.alt && (escape || key == Key::Tab.scancode()))
+            || (self.ctrl && escape))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Every map must be aligned with the scancode table
+    #[test]
+    fn test_layout_map_size() {
+        for map in [&US_MAP, &UK_MAP, &GERMAN_MAP, &FRENCH_MAP] {
+            assert_eq!(map.normal.chars().count(), CHARACTER_SCANCODES.len());
+            assert_eq!(map.shift.chars().count(), CHARACTER_SCANCODES.len());
+            assert!(map.altgr.chars().count() <= CHARACTER_SCANCODES.len());
+        }
+    }
+
+    /// Shift is pressed around upper case characters
+    #[test]
+    fn test_type_text_shift() {
+        let events = type_text(KeyboardLayout::US, "A").unwrap();
+        assert_eq!(
+            events,
+            vec![
+                Key::LeftShift.event(true),
+                press(0x1E),
+                InputEvent::Scancode {
+                    code: 0x1E,
+                    extended: false,
+                    down: false
+                },
+                Key::LeftShift.event(false),
+            ]
+        );
+    }
+
+    /// Press event of a character key
+    fn press(code: u8) -> InputEvent {
+        InputEvent::Scancode {
+            code,
+            extended: false,
+            down: true,
+        }
+    }
+
+    /// Layouts place characters on different keys
+    #[test]
+    fn test_type_text_layout() {
+        let german = type_text(KeyboardLayout::German, "z").unwrap();
+        assert_eq!(german[0], press(0x15));
+
+        let french = type_text(KeyboardLayout::French, "@").unwrap();
+        assert_eq!(french[0], Key::RightAlt.event(true));
+        assert_eq!(french[1], press(0x0B));
+
+        let uk = type_text(KeyboardLayout::UnitedKingdom, "\"").unwrap();
+        assert_eq!(uk[1], press(0x03));
+    }
+
+    /// Dead keys are followed by a space
+    #[test]
+    fn test_type_text_dead_key() {
+        let events = type_text(KeyboardLayout::German, "^").unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[2], Key::Space.event(true));
+    }
+
+    /// Function keys use two ranges of scancodes
+    #[test]
+    fn test_function_keys() {
+        assert_eq!(Key::F(1).scancode(), (0x3B, false));
+        assert_eq!(Key::F(10).scancode(), (0x44, false));
+        assert_eq!(Key::F(12).scancode(), (0x58, false));
+        assert_eq!(Key::F(13).scancode(), (0x64, false));
+        assert_eq!(Key::F(24).scancode(), (0x76, false));
+    }
+
+    /// Unknown characters are rejected
+    #[test]
+    fn test_type_text_unknown() {
+        assert!(type_text(KeyboardLayout::US, "é").is_err());
+        assert!(type_text(KeyboardLayout::Japanese, "a").is_err());
+    }
+
+    /// Local shortcuts are dropped, their modifiers are still sent
+    #[test]
+    fn test_shortcut_filter_local() {
+        let mut filter = ShortcutFilter::new(KeyboardHook::Local, true);
+        assert!(!filter.forward(&Key::LeftWin.event(true)));
+        assert!(filter.forward(&Key::Escape.event(true)));
+        assert!(filter.forward(&Key::LeftCtrl.event(true)));
+        assert!(!filter.forward(&Key::Escape.event(true)));
+        assert!(filter.forward(&Key::LeftCtrl.event(false)));
+        assert!(filter.forward(&Key::RightAlt.event(true)));
+        assert!(!filter.forward(&Key::Tab.event(false)));
+        assert!(filter.forward(&Key::RightAlt.event(false)));
+        assert!(filter.forward(&Key::Tab.event(true)));
+
+        let mut filter = ShortcutFilter::new(KeyboardHook::Remote, false);
+        assert!(filter.forward(&Key::RightWin.event(true)));
+    }
+}
@@ -1,138 +1,638 @@
-// use crate::model::data::{Component, Check, DynOption, U16, MessageOption, U32, DataType, Message};
-// use crate::model::error::{RdpResult, Error, RdpError, RdpErrorKind};
-
-// use std::io::{Cursor, Read};
-// use num_enum::TryFromPrimitive;
-// use std::convert::TryFrom;
-
-// pub enum LicenseMessage {
-//     NewLicense,
-//     ErrorAlert(Component)
-// }
-
-// /// License preambule
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/73170ca2-5f82-4a2d-9d1b-b439f3d8dadc
-// #[repr(u8)]
-// #[allow(dead_code)]
-// enum Preambule {
-//     PreambleVersion20 = 0x2,
-//     PreambleVersion30 = 0x3,
-//     ExtendedErrorMsgSupported = 0x80
-// }
-
-// /// All type of message
-// /// which can follow a license preamble
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/73170ca2-5f82-4a2d-9d1b-b439f3d8dadc
-// #[repr(u8)]
-// #[derive(TryFromPrimitive)]
-// pub enum MessageType {
-//     LicenseRequest = 0x01,
-//     PlatformChallenge = 0x02,
-//     NewLicense = 0x03,
-//     UpgradeLicense = 0x04,
-//     LicenseInfo = 0x12,
-//     NewLicenseRequest = 0x13,
-//     PlatformChallengeResponse = 0x15,
-//     ErrorAlert = 0xFF
-// }
-
-// /// Error code of the license automata
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/f18b6c9f-f3d8-4a0e-8398-f9b153233dca?redirectedfrom=MSDN
-// #[repr(u32)]
-// #[derive(PartialEq, Eq, TryFromPrimitive)]
-// pub enum ErrorCode {
-//     ErrInvalidServerCertificate = 0x00000001,
-//     ErrNoLicense = 0x00000002,
-//     ErrInvalidScope = 0x00000004,
-//     ErrNoLicenseServer = 0x00000006,
-//     StatusValidClient = 0x00000007,
-//     ErrInvalidClient = 0x00000008,
-//     ErrInvalidProductid = 0x0000000B,
-//     ErrInvalidMessageLen = 0x0000000C,
-//     ErrInvalidMac = 0x00000003
-// }
-
-// /// All valid state transition available
-// /// for license automata
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/f18b6c9f-f3d8-4a0e-8398-f9b153233dca
-// #[repr(u32)]
-// #[derive(PartialEq, Eq, TryFromPrimitive)]
-// pub enum StateTransition {
-//     StTotalAbort = 0x00000001,
-//     StNoTransition = 0x00000002,
-//     StResetPhaseToStart = 0x00000003,
-//     StResendLastMessage = 0x00000004
-// }
-
-// /// This a license preamble
-// /// All license messages are built in same way
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/73170ca2-5f82-4a2d-9d1b-b439f3d8dadc
-// fn preamble() -> Component {
-//     component![
-//         "bMsgtype" => 0 as u8,
-//         "flag" => Check::new(Preambule::PreambleVersion30 as u8),
-//         "wMsgSize" => DynOption::new(U16::LE(0), |size| MessageOption::Size("message".to_string(), size.inner() as usize - 4)),
-//         "message" => Vec::<u8>::new()
-//     ]
-// }
-
-// /// Blob use by licensing protocol
-// fn license_binary_blob() -> Component {
-//     component![
-//         "wBlobType" => U16::LE(0),
-//         "wBlobLen" => DynOption::new(U16::LE(0), | size | MessageOption::Size("blobData".to_string(), size.inner() as usize)),
-//         "blobData" => Vec::<u8>::new()
-//     ]
-// }
-
-// /// Licensing error message
-// /// use to inform state transition
-// fn licensing_error_message() -> Component {
-//     component![
-//         "dwErrorCode" => U32::LE(0),
-//         "dwStateTransition" => U32::LE(0),
-//         "blob" => license_binary_blob()
-//     ]
-// }
-
-
-// /// Parse a payload that follow an preamble
-// /// Actualle we only accept payload with type NewLicense or ErrorAlert
-// fn parse_payload(payload: &Component) -> RdpResult<LicenseMessage> {
-//     match MessageType::try_from(cast!(DataType::U8, payload["bMsgtype"])?)? {
-//         MessageType::NewLicense => Ok(LicenseMessage::NewLicense),
-//         MessageType::ErrorAlert => {
-//             let mut message = licensing_error_message();
-//             let mut stream = Cursor::new(cast!(DataType::Slice, payload["message"])?);
-//             message.read(&mut stream)?;
-//             Ok(LicenseMessage::ErrorAlert(message))
-//         }
-//         _ => Err(Error::RdpError(RdpError::new(RdpErrorKind::NotImplemented, "Licensing nego not implemented")))
-//     }
-// }
-
-// /// A license client side connect message
-// ///
-// /// Actually we only accept valid client message
-// /// without any license negotiation
-// ///
-// /// # Example
-// /// ```
-// /// ```
-// pub fn client_connect(s: &mut dyn Read) -> RdpResult<()> {
-
-//     let mut license_message = preamble();
-//     license_message.read(s)?;
-
-//     match parse_payload(&license_message)? {
-//         LicenseMessage::NewLicense => Ok(()),
-//         LicenseMessage::ErrorAlert(blob) => {
-//             if ErrorCode::try_from(cast!(DataType::U32, blob["dwErrorCode"])?)? == ErrorCode::StatusValidClient &&
-//                 StateTransition::try_from(cast!(DataType::U32, blob["dwStateTransition"])?)? == StateTransition::StNoTransition {
-//                 Ok(())
-//             } else {
-//                 Err(Error::RdpError(RdpError::new(RdpErrorKind::InvalidRespond, "Server reject license, Actually license nego is not implemented")))
-//             }
-//         }
-//     }
-// }
\ No newline at end of file
+use crate::model::data::{check_remaining, to_vec, Message};
+use crate::model::unicode::from_unicode;
+#[cfg(feature = "legacy-security")]
+use crate::core::sec::legacy::{final_hash, hash_48, mac_data, rsa_encrypt, ServerPublicKey};
+#[cfg(feature = "legacy-security")]
+use crate::model::rnd::random;
+#[cfg(feature = "legacy-security")]
+use crate::nla::rc4::Rc4;
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+#[cfg(feature = "legacy-security")]
+use md5::{Digest, Md5};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// License preambule
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/73170ca2-5f82-4a2d-9d1b-b439f3d8dadc
+#[repr(u8)]
+#[allow(dead_code)]
+pub enum Preambule {
+    PreambleVersion20 = 0x2,
+    PreambleVersion30 = 0x3,
+    ExtendedErrorMsgSupported = 0x80,
+}
+
+/// All type of message
+/// which can follow a license preamble
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/73170ca2-5f82-4a2d-9d1b-b439f3d8dadc
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum MessageType {
+    LicenseRequest = 0x01,
+    PlatformChallenge = 0x02,
+    NewLicense = 0x03,
+    UpgradeLicense = 0x04,
+    LicenseInfo = 0x12,
+    NewLicenseRequest = 0x13,
+    PlatformChallengeResponse = 0x15,
+    ErrorAlert = 0xFF,
+}
+
+/// Error code of the license automata
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/f18b6c9f-f3d8-4a0e-8398-f9b153233dca?redirectedfrom=MSDN
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, TryFromPrimitive)]
+pub enum ErrorCode {
+    ErrInvalidServerCertificate = 0x00000001,
+    ErrNoLicense = 0x00000002,
+    ErrInvalidScope = 0x00000004,
+    ErrNoLicenseServer = 0x00000006,
+    StatusValidClient = 0x00000007,
+    ErrInvalidClient = 0x00000008,
+    ErrInvalidProductid = 0x0000000B,
+    ErrInvalidMessageLen = 0x0000000C,
+    ErrInvalidMac = 0x00000003,
+}
+
+/// All valid state transition available
+/// for license automata
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/f18b6c9f-f3d8-4a0e-8398-f9b153233dca
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, TryFromPrimitive)]
+pub enum StateTransition {
+    StTotalAbort = 0x00000001,
+    StNoTransition = 0x00000002,
+    StResetPhaseToStart = 0x00000003,
+    StResendLastMessage = 0x00000004,
+}
+
+/// Type of data contained in a binary blob
+/// MS-RDPBCGR 2.2.1.12.1.2 Licensing Binary Blob (LICENSE_BINARY_BLOB)
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BlobType {
+    BbAnyBlob = 0x0000,
+    BbDataBlob = 0x0001,
+    BbRandomBlob = 0x0002,
+    BbCertificateBlob = 0x0003,
+    BbErrorBlob = 0x0004,
+    BbEncryptedDataBlob = 0x0009,
+    BbKeyExchgAlgBlob = 0x000D,
+    BbScopeBlob = 0x000E,
+    BbClientUserNameBlob = 0x000F,
+    BbClientMachineNameBlob = 0x0010,
+}
+
+/// This a license preamble
+/// All license messages are built in same way
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/73170ca2-5f82-4a2d-9d1b-b439f3d8dadc
+#[derive(Debug)]
+pub struct LicensePreamble {
+    pub msg_type: u8,
+    pub flags: u8,
+    pub msg_size: u16,
+}
+
+impl LicensePreamble {
+    /// Preamble of a message with a body of `length` bytes
+    pub fn new(msg_type: MessageType, length: usize) -> Self {
+        LicensePreamble {
+            msg_type: msg_type as u8,
+            flags: Preambule::PreambleVersion30 as u8,
+            msg_size: (length + 4) as u16,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "LICENSE: preamble")?;
+        self.msg_type = buffer.get_u8();
+        self.flags = buffer.get_u8();
+        self.msg_size = buffer.get_u16_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for LicensePreamble {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u8(self.msg_type).await?;
+        writer.write_u8(self.flags).await?;
+        writer.write_u16_le(self.msg_size).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.msg_type = reader.read_u8().await?;
+        self.flags = reader.read_u8().await?;
+        self.msg_size = reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4
+    }
+}
+
+/// Blob use by licensing protocol
+/// MS-RDPBCGR 2.2.1.12.1.2 Licensing Binary Blob (LICENSE_BINARY_BLOB)
+#[derive(Debug, Default, Clone)]
+pub struct LicenseBinaryBlob {
+    pub blob_type: u16,
+    pub data: Vec<u8>,
+}
+
+impl LicenseBinaryBlob {
+    pub fn new(blob_type: BlobType, data: Vec<u8>) -> Self {
+        LicenseBinaryBlob {
+            blob_type: blob_type as u16,
+            data,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "LICENSE: binary blob")?;
+        self.blob_type = buffer.get_u16_le();
+        let length = buffer.get_u16_le() as usize;
+        check_remaining(buffer, length, "LICENSE: binary blob")?;
+        self.data = buffer.split_to(length).to_vec();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for LicenseBinaryBlob {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.blob_type).await?;
+        writer.write_u16_le(self.data.len() as u16).await?;
+        writer.write_all(&self.data).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.blob_type = reader.read_u16_le().await?;
+        let length = reader.read_u16_le().await? as usize;
+        self.data = vec![0; length];
+        reader.read_exact(&mut self.data).await?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        4 + self.data.len()
+    }
+}
+
+/// Licensing error message
+/// use to inform state transition
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/f18b6c9f-f3d8-4a0e-8398-f9b153233dca
+#[derive(Debug, Default)]
+pub struct LicensingErrorMessage {
+    pub error_code: u32,
+    pub state_transition: u32,
+    pub error_info: LicenseBinaryBlob,
+}
+
+impl LicensingErrorMessage {
+    /// Sent by a server which doesn't
+    /// need any license negotiation
+    pub fn valid_client() -> Self {
+        LicensingErrorMessage {
+            error_code: ErrorCode::StatusValidClient as u32,
+            state_transition: StateTransition::StNoTransition as u32,
+            error_info: LicenseBinaryBlob::new(BlobType::BbErrorBlob, Vec::new()),
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 8, "LICENSE: error message")?;
+        self.error_code = buffer.get_u32_le();
+        self.state_transition = buffer.get_u32_le();
+        self.error_info.read_from_buffer(buffer)
+    }
+
+    /// The server send this message when no license
+    /// negotiation is needed
+    pub fn is_valid_client(&self) -> bool {
+        matches!(
+            ErrorCode::try_from(self.error_code),
+            Ok(ErrorCode::StatusValidClient)
+        ) && matches!(
+            StateTransition::try_from(self.state_transition),
+            Ok(StateTransition::StNoTransition)
+        )
+    }
+}
+
+#[async_trait]
+impl Message for LicensingErrorMessage {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.error_code).await?;
+        writer.write_u32_le(self.state_transition).await?;
+        self.error_info.write_to(writer).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.error_code = reader.read_u32_le().await?;
+        self.state_transition = reader.read_u32_le().await?;
+        self.error_info.read_from(reader).await
+    }
+
+    fn length(&self) -> usize {
+        8 + self.error_info.length()
+    }
+}
+
+/// Product information of the license server
+/// MS-RDPELE 2.2.2.1.1 Product Information (PRODUCT_INFO)
+#[derive(Debug, Default)]
+pub struct ProductInfo {
+    pub version: u32,
+    pub company_name: String,
+    pub product_id: String,
+}
+
+impl ProductInfo {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 8, "LICENSE: product info")?;
+        self.version = buffer.get_u32_le();
+        let length = buffer.get_u32_le() as usize;
+        check_remaining(buffer, length + 4, "LICENSE: product info")?;
+        self.company_name = from_unicode(&buffer.split_to(length));
+        let length = buffer.get_u32_le() as usize;
+        check_remaining(buffer, length, "LICENSE: product info")?;
+        self.product_id = from_unicode(&buffer.split_to(length));
+        Ok(())
+    }
+}
+
+/// First message sent by the server
+/// when a license negotiation is needed
+/// MS-RDPELE 2.2.2.1 Server License Request (SERVER_LICENSE_REQUEST)
+#[derive(Debug, Default)]
+pub struct ServerLicenseRequest {
+    pub server_random: Vec<u8>,
+    pub product_info: ProductInfo,
+    pub key_exchange_list: LicenseBinaryBlob,
+    pub server_certificate: LicenseBinaryBlob,
+    pub scope_list: Vec<LicenseBinaryBlob>,
+}
+
+impl ServerLicenseRequest {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 32, "LICENSE: license request")?;
+        self.server_random = buffer.split_to(32).to_vec();
+        self.product_info.read_from_buffer(buffer)?;
+        self.key_exchange_list.read_from_buffer(buffer)?;
+        self.server_certificate.read_from_buffer(buffer)?;
+        check_remaining(buffer, 4, "LICENSE: license request")?;
+        let scope_count = buffer.get_u32_le();
+        self.scope_list = Vec::new();
+        for _ in 0..scope_count {
+            let mut scope = LicenseBinaryBlob::default();
+            scope.read_from_buffer(buffer)?;
+            self.scope_list.push(scope);
+        }
+        Ok(())
+    }
+}
+
+/// Challenge sent by the server to check the client
+/// MS-RDPELE 2.2.2.4 Server Platform Challenge (SERVER_PLATFORM_CHALLENGE)
+#[derive(Debug, Default)]
+pub struct ServerPlatformChallenge {
+    pub connect_flags: u32,
+    pub encrypted_platform_challenge: LicenseBinaryBlob,
+    pub mac_data: [u8; 16],
+}
+
+impl ServerPlatformChallenge {
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "LICENSE: platform challenge")?;
+        self.connect_flags = buffer.get_u32_le();
+        self.encrypted_platform_challenge.read_from_buffer(buffer)?;
+        check_remaining(buffer, 16, "LICENSE: platform challenge")?;
+        buffer.copy_to_slice(&mut self.mac_data);
+        Ok(())
+    }
+}
+
+/// All license messages handled by the client
+#[derive(Debug)]
+pub enum LicenseMessage {
+    LicenseRequest(ServerLicenseRequest),
+    PlatformChallenge(ServerPlatformChallenge),
+    NewLicense,
+    UpgradeLicense,
+    ErrorAlert(LicensingErrorMessage),
+}
+
+/// Parse a license message with its preamble
+pub fn read_license_message(buffer: &mut BytesMut) -> Result<LicenseMessage> {
+    let mut preamble = LicensePreamble::new(MessageType::ErrorAlert, 0);
+    preamble.read_from_buffer(buffer)?;
+
+    match MessageType::try_from(preamble.msg_type) {
+        Ok(MessageType::LicenseRequest) => {
+            let mut request = ServerLicenseRequest::default();
+            request.read_from_buffer(buffer)?;
+            Ok(LicenseMessage::LicenseRequest(request))
+        }
+        Ok(MessageType::PlatformChallenge) => {
+            let mut challenge = ServerPlatformChallenge::default();
+            challenge.read_from_buffer(buffer)?;
+            Ok(LicenseMessage::PlatformChallenge(challenge))
+        }
+        Ok(MessageType::NewLicense) => Ok(LicenseMessage::NewLicense),
+        Ok(MessageType::UpgradeLicense) => Ok(LicenseMessage::UpgradeLicense),
+        Ok(MessageType::ErrorAlert) => {
+            let mut message = LicensingErrorMessage::default();
+            message.read_from_buffer(buffer)?;
+            Ok(LicenseMessage::ErrorAlert(message))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("LICENSE: unexpected message type {}", preamble.msg_type),
+        )),
+    }
+}
+
+/// Serialize a client license message with its preamble
+pub async fn write_license_message(
+    msg_type: MessageType,
+    message: &impl Message,
+) -> Result<Vec<u8>> {
+    let mut buffer = to_vec(&LicensePreamble::new(msg_type, message.length())).await?;
+    buffer.extend(to_vec(message).await?);
+    Ok(buffer)
+}
+
+/// Only RSA is used to exchange the premaster secret
+#[cfg(feature = "legacy-security")]
+const KEY_EXCHANGE_ALG_RSA: u32 = 0x00000001;
+
+/// Windows NT post 5.2 with a microsoft image
+#[cfg(feature = "legacy-security")]
+const PLATFORM_ID: u32 = 0x04000000 | 0x00010000;
+
+/// Client response to a license request
+/// MS-RDPELE 2.2.2.2 Client New License Request (CLIENT_NEW_LICENSE_REQUEST)
+#[cfg(feature = "legacy-security")]
+pub struct ClientNewLicenseRequest {
+    pub preferred_key_exchange_alg: u32,
+    pub platform_id: u32,
+    pub client_random: Vec<u8>,
+    pub encrypted_premaster_secret: LicenseBinaryBlob,
+    pub client_user_name: LicenseBinaryBlob,
+    pub client_machine_name: LicenseBinaryBlob,
+}
+
+#[cfg(feature = "legacy-security")]
+#[async_trait]
+impl Message for ClientNewLicenseRequest {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.preferred_key_exchange_alg).await?;
+        writer.write_u32_le(self.platform_id).await?;
+        writer.write_all(&self.client_random).await?;
+        self.encrypted_premaster_secret.write_to(writer).await?;
+        self.client_user_name.write_to(writer).await?;
+        self.client_machine_name.write_to(writer).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.preferred_key_exchange_alg = reader.read_u32_le().await?;
+        self.platform_id = reader.read_u32_le().await?;
+        self.client_random = vec![0; 32];
+        reader.read_exact(&mut self.client_random).await?;
+        self.encrypted_premaster_secret.read_from(reader).await?;
+        self.client_user_name.read_from(reader).await?;
+        self.client_machine_name.read_from(reader).await?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        8 + self.client_random.len()
+            + self.encrypted_premaster_secret.length()
+            + self.client_user_name.length()
+            + self.client_machine_name.length()
+    }
+}
+
+/// Client response to the platform challenge
+/// MS-RDPELE 2.2.2.5 Client Platform Challenge Response (CLIENT_PLATFORM_CHALLENGE_RESPONSE)
+#[cfg(feature = "legacy-security")]
+pub struct ClientPlatformChallengeResponse {
+    pub encrypted_platform_challenge_response: LicenseBinaryBlob,
+    pub encrypted_hwid: LicenseBinaryBlob,
+    pub mac_data: [u8; 16],
+}
+
+#[cfg(feature = "legacy-security")]
+#[async_trait]
+impl Message for ClientPlatformChallengeResponse {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        self.encrypted_platform_challenge_response
+            .write_to(writer)
+            .await?;
+        self.encrypted_hwid.write_to(writer).await?;
+        writer.write_all(&self.mac_data).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.encrypted_platform_challenge_response
+            .read_from(reader)
+            .await?;
+        self.encrypted_hwid.read_from(reader).await?;
+        reader.read_exact(&mut self.mac_data).await?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        self.encrypted_platform_challenge_response.length() + self.encrypted_hwid.length() + 16
+    }
+}
+
+/// Encode an ANSI null terminated string
+#[cfg(feature = "legacy-security")]
+fn ansi_null_terminated(value: &str) -> Vec<u8> {
+    let mut result = value.as_bytes().to_vec();
+    result.push(0);
+    result
+}
+
+/// Keys of the license negotiation
+/// MS-RDPELE 5.1.3 Generating Licensing Encryption and MAC Salt Keys
+#[cfg(feature = "legacy-security")]
+pub struct LicenseContext {
+    client_random: Vec<u8>,
+    premaster_secret: Vec<u8>,
+    mac_salt_key: Vec<u8>,
+    encryption_key: Vec<u8>,
+}
+
+#[cfg(feature = "legacy-security")]
+impl LicenseContext {
+    /// Derive the licensing keys from random client secrets
+    pub fn new(server_random: &[u8]) -> Self {
+        Self::from_secrets(random(32), random(48), server_random)
+    }
+
+    fn from_secrets(
+        client_random: Vec<u8>,
+        premaster_secret: Vec<u8>,
+        server_random: &[u8],
+    ) -> Self {
+        let master_secret = hash_48(&premaster_secret, b'A', &client_random, server_random);
+        // Session key blob use the server random first
+        let session_key_blob = hash_48(&master_secret, b'A', server_random, &client_random);
+
+        LicenseContext {
+            mac_salt_key: session_key_blob[..16].to_vec(),
+            encryption_key: final_hash(&session_key_blob[16..32], &client_random, server_random),
+            client_random,
+            premaster_secret,
+        }
+    }
+
+    /// Each licensing message use a new RC4 stream
+    fn rc4(&self, data: &[u8]) -> Vec<u8> {
+        let mut result = vec![0; data.len()];
+        Rc4::new(&self.encryption_key).process(data, &mut result);
+        result
+    }
+
+    /// Answer to the license request
+    pub fn new_license_request(
+        &self,
+        public_key: &ServerPublicKey,
+        user_name: &str,
+        machine_name: &str,
+    ) -> ClientNewLicenseRequest {
+        ClientNewLicenseRequest {
+            preferred_key_exchange_alg: KEY_EXCHANGE_ALG_RSA,
+            platform_id: PLATFORM_ID,
+            client_random: self.client_random.clone(),
+            encrypted_premaster_secret: LicenseBinaryBlob::new(
+                BlobType::BbRandomBlob,
+                rsa_encrypt(&self.premaster_secret, public_key),
+            ),
+            client_user_name: LicenseBinaryBlob::new(
+                BlobType::BbClientUserNameBlob,
+                ansi_null_terminated(user_name),
+            ),
+            client_machine_name: LicenseBinaryBlob::new(
+                BlobType::BbClientMachineNameBlob,
+                ansi_null_terminated(machine_name),
+            ),
+        }
+    }
+
+    /// Decrypt the platform challenge and build the response
+    /// The hardware id is derived from the machine name
+    pub fn platform_challenge_response(
+        &self,
+        challenge: &ServerPlatformChallenge,
+        machine_name: &str,
+    ) -> Result<ClientPlatformChallengeResponse> {
+        let platform_challenge = self.rc4(&challenge.encrypted_platform_challenge.data);
+        if mac_data(&self.mac_salt_key, &platform_challenge) != challenge.mac_data {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "LICENSE: invalid platform challenge MAC",
+            ));
+        }
+
+        // PLATFORM_CHALLENGE_RESPONSE_DATA
+        let mut response = Vec::new();
+        response.extend(0x0100u16.to_le_bytes());
+        response.extend(0x00ffu16.to_le_bytes());
+        response.extend(0x0003u16.to_le_bytes());
+        response.extend((platform_challenge.len() as u16).to_le_bytes());
+        response.extend(&platform_challenge);
+
+        // CLIENT_HARDWARE_ID
+        let mut hwid = PLATFORM_ID.to_le_bytes().to_vec();
+        hwid.extend_from_slice(&Md5::digest(machine_name.as_bytes()));
+
+        let mac = mac_data(&self.mac_salt_key, &[&response[..], &hwid[..]].concat());
+
+        Ok(ClientPlatformChallengeResponse {
+            encrypted_platform_challenge_response: LicenseBinaryBlob::new(
+                BlobType::BbEncryptedDataBlob,
+                self.rc4(&response),
+            ),
+            encrypted_hwid: LicenseBinaryBlob::new(BlobType::BbEncryptedDataBlob, self.rc4(&hwid)),
+            mac_data: mac,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The valid client alert is the common answer of server
+    #[test]
+    fn test_read_valid_client_error_alert() {
+        let mut buffer = BytesMut::from(
+            &[
+                0xff, 0x03, 0x10, 0x00, 0x07, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x00,
+                0x00, 0x00,
+            ][..],
+        );
+        match read_license_message(&mut buffer).unwrap() {
+            LicenseMessage::ErrorAlert(message) => assert!(message.is_valid_client()),
+            _ => panic!("expecting an error alert"),
+        }
+    }
+
+    /// Any other error must be reported
+    #[test]
+    fn test_read_invalid_client_error_alert() {
+        let mut buffer = BytesMut::from(
+            &[
+                0xff, 0x03, 0x10, 0x00, 0x08, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04, 0x00,
+                0x00, 0x00,
+            ][..],
+        );
+        match read_license_message(&mut buffer).unwrap() {
+            LicenseMessage::ErrorAlert(message) => assert!(!message.is_valid_client()),
+            _ => panic!("expecting an error alert"),
+        }
+    }
+
+    /// Test of the preamble format
+    #[tokio::test]
+    async fn test_write_license_message() {
+        let blob = LicenseBinaryBlob::new(BlobType::BbDataBlob, vec![1, 2]);
+        assert_eq!(
+            write_license_message(MessageType::NewLicenseRequest, &blob)
+                .await
+                .unwrap(),
+            [0x13, 0x03, 0x0a, 0x00, 0x01, 0x00, 0x02, 0x00, 0x01, 0x02]
+        );
+    }
+
+    /// The platform challenge must be decrypted with the licensing key
+    #[cfg(feature = "legacy-security")]
+    #[test]
+    fn test_platform_challenge_response() {
+        let context = LicenseContext::from_secrets(vec![1; 32], vec![2; 48], &[3; 32]);
+        let mut challenge = ServerPlatformChallenge::default();
+        challenge.encrypted_platform_challenge =
+            LicenseBinaryBlob::new(BlobType::BbAnyBlob, context.rc4(b"challenge"));
+        challenge.mac_data = mac_data(&context.mac_salt_key, b"challenge");
+
+        let response = context
+            .platform_challenge_response(&challenge, "rdp-rs")
+            .unwrap();
+        let data = context.rc4(&response.encrypted_platform_challenge_response.data);
+        assert_eq!(&data[8..], b"challenge");
+        assert_eq!(context.rc4(&response.encrypted_hwid.data).len(), 20);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::model::error::{RdpError, RdpErrorKind};
+
+use std::io::{Error, ErrorKind, Result};
+
+/// Largest length of a TPKT PDU
+const TPKT_MAX_SIZE: usize = 0xFFFF;
+
+/// Largest length of a fast path PDU
+const FAST_PATH_MAX_LENGTH: usize = 0x7FFF;
+
+/// Largest message reassembled from chunks or fragments
+const REASSEMBLED_MAX_SIZE: usize = 0x100_0000;
+
+/// Limits on the length fields sent by the server
+///
+/// They are checked before any allocation so a forged
+/// length can't make the client allocate large buffers
+///
+/// # Example
+/// ```
+/// use rdp::core::limits::{is_too_large, PduLimits};
+/// let limits = PduLimits {
+///     max_tpkt_size: 0x4000,
+///     ..Default::default()
+/// };
+/// let error = limits.check_tpkt_size(0x8000).unwrap_err();
+/// assert!(is_too_large(&error));
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PduLimits {
+    /// Largest TPKT PDU, header included
+    pub max_tpkt_size: usize,
+    /// Largest fast path PDU, header included
+    pub max_fast_path_length: usize,
+    /// Largest user data of an MCS send data indication
+    pub max_channel_chunk: usize,
+    /// Largest channel message or fast path update
+    /// once all its chunks are reassembled
+    pub max_reassembled_size: usize,
+}
+
+impl Default for PduLimits {
+    fn default() -> Self {
+        PduLimits {
+            max_tpkt_size: TPKT_MAX_SIZE,
+            max_fast_path_length: FAST_PATH_MAX_LENGTH,
+            max_channel_chunk: TPKT_MAX_SIZE,
+            max_reassembled_size: REASSEMBLED_MAX_SIZE,
+        }
+    }
+}
+
+impl PduLimits {
+    pub fn check_tpkt_size(&self, size: usize) -> Result<()> {
+        check_limit(size, self.max_tpkt_size, "TPKT: PDU size")
+    }
+
+    pub fn check_fast_path_length(&self, length: usize) -> Result<()> {
+        check_limit(length, self.max_fast_path_length, "TPKT: fast path length")
+    }
+
+    pub fn check_channel_chunk(&self, length: usize) -> Result<()> {
+        check_limit(length, self.max_channel_chunk, "MCS: channel chunk length")
+    }
+
+    pub fn check_reassembled_size(&self, size: usize) -> Result<()> {
+        check_limit(size, self.max_reassembled_size, "CHANNEL: reassembled message size")
+    }
+}
+
+/// Return a TooLarge error when length is above max
+pub(crate) fn check_limit(length: usize, max: usize, context: &str) -> Result<()> {
+    if length > max {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            RdpError::new(
+                RdpErrorKind::TooLarge,
+                &format!("{} {} is above the limit of {}", context, length, max),
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Check if an IO error comes from a limit
+pub fn is_too_large(error: &Error) -> bool {
+    error
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<RdpError>())
+        .map_or(false, |e| e.kind() == RdpErrorKind::TooLarge)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Lengths equal to the limit are accepted
+    #[test]
+    fn test_check_limits() {
+        let limits = PduLimits {
+            max_channel_chunk: 1600,
+            ..Default::default()
+        };
+        assert!(limits.check_channel_chunk(1600).is_ok());
+        assert!(is_too_large(&limits.check_channel_chunk(1601).unwrap_err()));
+        assert!(limits.check_fast_path_length(0x7FFF).is_ok());
+        assert!(!is_too_large(&Error::new(ErrorKind::InvalidData, "other")));
+    }
+}
@@ -0,0 +1,417 @@
+use crate::core::per;
+use crate::model::data::check_remaining;
+use crate::nla::asn1::{
+    from_ber, to_der, ASN1Type, Enumerate, ImplicitTag, Integer, OctetString, Sequence, ASN1,
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use yasna::Tag;
+
+/// MCS user id are send with
+/// this offset by the PER encoding
+pub const MCS_USERCHANNEL_BASE: u16 = 1001;
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum DomainMCSPDU {
+    ErectDomainRequest = 1,
+    DisconnectProviderUltimatum = 8,
+    AttachUserRequest = 10,
+    AttachUserConfirm = 11,
+    ChannelJoinRequest = 14,
+    ChannelJoinConfirm = 15,
+    SendDataRequest = 25,
+    SendDataIndication = 26,
+}
+
+/// ASN1 structure use by mcs layer
+/// to inform on conference capability
+fn domain_parameters(
+    max_channel_ids: u32,
+    maw_user_ids: u32,
+    max_token_ids: u32,
+    num_priorities: u32,
+    min_thoughput: u32,
+    max_height: u32,
+    max_mcs_pdu_size: u32,
+    protocol_version: u32,
+) -> Sequence {
+    sequence![
+        "maxChannelIds" => max_channel_ids,
+        "maxUserIds" => maw_user_ids,
+        "maxTokenIds" => max_token_ids,
+        "numPriorities" => num_priorities,
+        "minThoughput" => min_thoughput,
+        "maxHeight" => max_height,
+        "maxMCSPDUsize" => max_mcs_pdu_size,
+        "protocolVersion" => protocol_version
+    ]
+}
+
+/// First MCS payload send from client to server
+/// Payload send from client to server
+///
+/// http://www.itu.int/rec/T-REC-T.125-199802-I/en page 25
+fn connect_initial(user_data: Option<OctetString>) -> ImplicitTag<Sequence> {
+    ImplicitTag::new(
+        Tag::application(101),
+        sequence![
+            "callingDomainSelector" => vec![1 as u8] as OctetString,
+            "calledDomainSelector" => vec![1 as u8] as OctetString,
+            "upwardFlag" => true,
+            "targetParameters" => domain_parameters(34, 2, 0, 1, 0, 1, 0xffff, 2),
+            "minimumParameters" => domain_parameters(1, 1, 1, 1, 0, 1, 0x420, 2),
+            "maximumParameters" => domain_parameters(0xffff, 0xfc17, 0xffff, 1, 0, 1, 0xffff, 2),
+            "userData" => user_data.unwrap_or(Vec::new())
+        ],
+    )
+}
+
+/// Server response with channel capacity
+fn connect_response(user_data: Option<OctetString>) -> ImplicitTag<Sequence> {
+    ImplicitTag::new(
+        Tag::application(102),
+        sequence![
+            "result" => 0 as Enumerate,
+            "calledConnectId" => 0 as Integer,
+            "domainParameters" => domain_parameters(22, 3, 0, 1, 0, 1, 0xfff8, 2),
+            "userData" => user_data.unwrap_or(Vec::new())
+        ],
+    )
+}
+
+/// Create a basic MCS PDU header
+pub fn mcs_pdu_header(pdu: DomainMCSPDU, options: u8) -> u8 {
+    (pdu as u8) << 2 | options
+}
+
+/// Read the MCS PDU type from a header
+pub fn read_mcs_pdu_header(buffer: &mut BytesMut) -> Result<DomainMCSPDU> {
+    check_remaining(buffer, 1, "MCS: header")?;
+    DomainMCSPDU::try_from(buffer.get_u8() >> 2)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "MCS: Invalid opcode"))
+}
+
+/// Encode the connect initial PDU
+/// with the GCC conference as user data
+pub fn write_connect_initial(user_data: Vec<u8>) -> Vec<u8> {
+    to_der(&connect_initial(Some(user_data)))
+}
+
+/// Read a connect response comming from server to client
+/// Return the GCC conference create response
+pub fn read_connect_response(payload: &[u8]) -> Result<BytesMut> {
+    let mut response = connect_response(None);
+    from_ber(&mut response, payload).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("MCS: Invalid connect response {:?}", e),
+        )
+    })?;
+
+    if let ASN1Type::Enumerate(result) = response.inner["result"].visit() {
+        if result != 0 {
+            return Err(Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("MCS: Connect response rejected with result {}", result),
+            ));
+        }
+    }
+
+    match response.inner["userData"].visit() {
+        ASN1Type::OctetString(user_data) => Ok(BytesMut::from(&user_data[..])),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            "MCS: Invalid user data in connect response",
+        )),
+    }
+}
+
+/// Read a connect initial coming from client to server
+/// Return the GCC conference create request
+pub fn read_connect_initial(payload: &[u8]) -> Result<BytesMut> {
+    let mut initial = connect_initial(None);
+    from_ber(&mut initial, payload).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("MCS: Invalid connect initial {:?}", e),
+        )
+    })?;
+
+    match initial.inner["userData"].visit() {
+        ASN1Type::OctetString(user_data) => Ok(BytesMut::from(&user_data[..])),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            "MCS: Invalid user data in connect initial",
+        )),
+    }
+}
+
+/// Encode the connect response PDU
+/// with the GCC conference as user data
+pub fn write_connect_response(user_data: Vec<u8>) -> Vec<u8> {
+    to_der(&connect_response(Some(user_data)))
+}
+
+/// Create a new domain for MCS layer
+pub fn erect_domain_request() -> BytesMut {
+    let mut result = BytesMut::new();
+    result.put_u8(mcs_pdu_header(DomainMCSPDU::ErectDomainRequest, 0));
+    per::write_integer(0, &mut result);
+    per::write_integer(0, &mut result);
+    result
+}
+
+/// Create a session for the current user
+///
+/// Client -- attach_user_request -> Server
+/// Client <- attach_user_confirm -- Server
+pub fn attach_user_request() -> BytesMut {
+    let mut result = BytesMut::new();
+    result.put_u8(mcs_pdu_header(DomainMCSPDU::AttachUserRequest, 0));
+    result
+}
+
+/// Read attach user confirm
+/// Client -- attach_user_request -> Server
+/// Client <- attach_user_confirm -- Server
+pub fn read_attach_user_confirm(buffer: &mut BytesMut) -> Result<u16> {
+    if read_mcs_pdu_header(buffer)? != DomainMCSPDU::AttachUserConfirm {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "MCS: unexpected header on recv_attach_user_confirm",
+        ));
+    }
+
+    if per::read_enumerates(buffer)? != 0 {
+        return Err(Error::new(
+            ErrorKind::ConnectionRefused,
+            "MCS: recv_attach_user_confirm user rejected by server",
+        ));
+    }
+    per::read_integer_16(MCS_USERCHANNEL_BASE, buffer)
+}
+
+/// Confirm the user id to the client
+/// Client -- attach_user_request -> Server
+/// Client <- attach_user_confirm -- Server
+pub fn attach_user_confirm(user_id: u16) -> BytesMut {
+    let mut result = BytesMut::new();
+    result.put_u8(mcs_pdu_header(DomainMCSPDU::AttachUserConfirm, 2));
+    per::write_enumerates(0, &mut result);
+    per::write_integer_16(user_id, MCS_USERCHANNEL_BASE, &mut result);
+    result
+}
+
+/// Ask to join a new channel
+/// The MCS will negotiate each channel
+/// channel join confirm is sent by server
+/// to validate or not the channel requested
+/// by the client
+///
+/// Client -- channel_join_request -> Server
+/// Client <- channel_join_confirm -- Server
+pub fn channel_join_request(user_id: u16, channel_id: u16) -> BytesMut {
+    let mut result = BytesMut::new();
+    result.put_u8(mcs_pdu_header(DomainMCSPDU::ChannelJoinRequest, 0));
+    per::write_integer_16(user_id, MCS_USERCHANNEL_BASE, &mut result);
+    result.put_u16(channel_id);
+    result
+}
+
+/// Read channel join confirm
+///
+/// Client -- channel_join_request -> Server
+/// Client <- channel_join_confirm -- Server
+pub fn read_channel_join_confirm(
+    user_id: u16,
+    channel_id: u16,
+    buffer: &mut BytesMut,
+) -> Result<bool> {
+    if read_mcs_pdu_header(buffer)? != DomainMCSPDU::ChannelJoinConfirm {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "MCS: unexpected header on read_channel_join_confirm",
+        ));
+    }
+
+    let confirm = per::read_enumerates(buffer)?;
+    let confirm_user_id = per::read_integer_16(MCS_USERCHANNEL_BASE, buffer)?;
+    let confirm_channel_id = per::read_integer_16(0, buffer)?;
+
+    if user_id != confirm_user_id {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "MCS: read_channel_join_confirm invalid user id",
+        ));
+    }
+
+    if channel_id != confirm_channel_id {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "MCS: read_channel_join_confirm invalid channel_id",
+        ));
+    }
+
+    Ok(confirm == 0)
+}
+
+/// Read channel join request
+/// Return the user id and the requested channel id
+///
+/// Client -- channel_join_request -> Server
+/// Client <- channel_join_confirm -- Server
+pub fn read_channel_join_request(buffer: &mut BytesMut) -> Result<(u16, u16)> {
+    let user_id = per::read_integer_16(MCS_USERCHANNEL_BASE, buffer)?;
+    let channel_id = per::read_integer_16(0, buffer)?;
+    Ok((user_id, channel_id))
+}
+
+/// Accept a channel join request
+///
+/// Client -- channel_join_request -> Server
+/// Client <- channel_join_confirm -- Server
+pub fn channel_join_confirm(user_id: u16, channel_id: u16) -> BytesMut {
+    let mut result = BytesMut::new();
+    result.put_u8(mcs_pdu_header(DomainMCSPDU::ChannelJoinConfirm, 2));
+    per::write_enumerates(0, &mut result);
+    per::write_integer_16(user_id, MCS_USERCHANNEL_BASE, &mut result);
+    // Requested channel id then joined channel id
+    result.put_u16(channel_id);
+    result.put_u16(channel_id);
+    result
+}
+
+/// Header of a send data indication
+/// the payload follow directly
+pub fn send_data_indication(user_id: u16, channel_id: u16, length: usize) -> BytesMut {
+    let mut result = BytesMut::new();
+    result.put_u8(mcs_pdu_header(DomainMCSPDU::SendDataIndication, 0));
+    per::write_integer_16(user_id, MCS_USERCHANNEL_BASE, &mut result);
+    result.put_u16(channel_id);
+    result.put_u8(0x70);
+    per::write_length(length as u16, &mut result);
+    result
+}
+
+/// Read the header of a send data request or indication
+/// after the PDU type, return the channel id
+pub fn read_send_data_header(buffer: &mut BytesMut) -> Result<u16> {
+    per::read_integer_16(MCS_USERCHANNEL_BASE, buffer)?;
+    let channel_id = per::read_integer_16(0, buffer)?;
+    per::read_enumerates(buffer)?;
+    per::read_length(buffer)?;
+    Ok(channel_id)
+}
+
+/// Header of a send data request
+/// the payload follow directly
+pub fn send_data_request(user_id: u16, channel_id: u16, length: usize) -> BytesMut {
+    let mut result = BytesMut::new();
+    result.put_u8(mcs_pdu_header(DomainMCSPDU::SendDataRequest, 0));
+    per::write_integer_16(user_id, MCS_USERCHANNEL_BASE, &mut result);
+    result.put_u16(channel_id);
+    result.put_u8(0x70);
+    per::write_length(length as u16, &mut result);
+    result
+}
+
+/// Close the MCS session
+pub fn disconnect_provider_ultimatum() -> BytesMut {
+    let mut result = BytesMut::new();
+    result.put_u8(mcs_pdu_header(DomainMCSPDU::DisconnectProviderUltimatum, 1));
+    per::write_enumerates(0x80, &mut result);
+    result.put_slice(b"\x00\x00\x00\x00\x00\x00");
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test of read read_attach_user_confirm
+    #[test]
+    fn test_read_attach_user_confirm() {
+        assert_eq!(
+            read_attach_user_confirm(&mut BytesMut::from(&[46u8, 0, 0, 3][..])).unwrap(),
+            1004
+        )
+    }
+
+    /// Attach user request payload
+    #[test]
+    fn test_attach_user_request() {
+        assert_eq!(&attach_user_request()[..], [40])
+    }
+
+    /// Test of the new domain request
+    #[test]
+    fn test_erect_domain_request() {
+        assert_eq!(&erect_domain_request()[..], [4, 1, 0, 1, 0])
+    }
+
+    /// Test format of the channel join request
+    #[test]
+    fn test_channel_join_request() {
+        assert_eq!(&channel_join_request(1004, 1003)[..], [56, 0, 3, 3, 235])
+    }
+
+    /// Test domain parameters format
+    #[test]
+    fn test_domain_parameters() {
+        let result = to_der(&domain_parameters(1, 2, 3, 4, 5, 6, 7, 8));
+        assert_eq!(
+            result,
+            vec![48, 24, 2, 1, 1, 2, 1, 2, 2, 1, 3, 2, 1, 4, 2, 1, 5, 2, 1, 6, 2, 1, 7, 2, 1, 8]
+        )
+    }
+
+    /// Test connect initial
+    #[test]
+    fn test_connect_initial() {
+        let result = write_connect_initial(vec![1, 2, 3]);
+        assert_eq!(
+            result,
+            vec![
+                127, 101, 103, 4, 1, 1, 4, 1, 1, 1, 1, 255, 48, 26, 2, 1, 34, 2, 1, 2, 2, 1, 0, 2,
+                1, 1, 2, 1, 0, 2, 1, 1, 2, 3, 0, 255, 255, 2, 1, 2, 48, 25, 2, 1, 1, 2, 1, 1, 2, 1,
+                1, 2, 1, 1, 2, 1, 0, 2, 1, 1, 2, 2, 4, 32, 2, 1, 2, 48, 32, 2, 3, 0, 255, 255, 2,
+                3, 0, 252, 23, 2, 3, 0, 255, 255, 2, 1, 1, 2, 1, 0, 2, 1, 1, 2, 3, 0, 255, 255, 2,
+                1, 2, 4, 3, 1, 2, 3
+            ]
+        )
+    }
+
+    /// The attach user confirm is read by the client
+    #[test]
+    fn test_attach_user_confirm() {
+        assert_eq!(&attach_user_confirm(1004)[..], [46, 0, 0, 3]);
+    }
+
+    /// The channel join confirm is read by the client
+    #[test]
+    fn test_channel_join_confirm() {
+        let mut request = channel_join_request(1004, 1003);
+        read_mcs_pdu_header(&mut request).unwrap();
+        assert_eq!(read_channel_join_request(&mut request).unwrap(), (1004, 1003));
+
+        let mut confirm = channel_join_confirm(1004, 1003);
+        assert!(read_channel_join_confirm(1004, 1003, &mut confirm).unwrap());
+    }
+
+    /// Test connect initial read by the server
+    #[test]
+    fn test_read_connect_initial() {
+        let payload = write_connect_initial(vec![1, 2, 3]);
+        assert_eq!(&read_connect_initial(&payload).unwrap()[..], [1, 2, 3])
+    }
+
+    /// Test connect response
+    #[test]
+    fn test_read_connect_response() {
+        let payload = to_der(&connect_response(Some(vec![1, 2, 3])));
+        assert_eq!(&read_connect_response(&payload).unwrap()[..], [1, 2, 3])
+    }
+}
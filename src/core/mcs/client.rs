@@ -0,0 +1,360 @@
+use crate::core::gcc::{
+    client_user_data, read_conference_create_response, write_conference_create_request,
+    ChannelDef, ChannelOption, ClientCoreData, ClientData, ClientNetworkData, ClientSecurityData,
+    KeyboardLayout, ServerData, Version,
+};
+use crate::core::mcs::base::{
+    attach_user_request, channel_join_request, disconnect_provider_ultimatum,
+    erect_domain_request, read_attach_user_confirm, read_channel_join_confirm,
+    read_connect_response, read_mcs_pdu_header, send_data_request, write_connect_initial,
+    DomainMCSPDU, MCS_USERCHANNEL_BASE,
+};
+use crate::core::limits::PduLimits;
+use crate::core::metrics::Metrics;
+use crate::core::per;
+use crate::core::trace::trace_pdu;
+use crate::core::tpkt::base::Payload;
+use crate::core::tpkt::queue::WritePriority;
+use crate::core::x224::client::X224Client;
+use crate::model::data::{to_vec, Message};
+
+use bytes::BytesMut;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// MCS client
+/// Multiplex all channels over the x224 layer
+pub struct McsClient<S> {
+    /// X224 transport layer
+    x224: X224Client<S>,
+    /// Server data send during connection step
+    server_data: ServerData,
+    /// User id session negotiated by the MCS
+    user_id: u16,
+    /// Map that translate channel name to channel id
+    channel_ids: HashMap<String, u16>,
+    /// Name of the client sent in the core data
+    client_name: String,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> McsClient<S> {
+    /// Connect the MCS channel
+    /// Ask connection for each channel requested
+    /// and confirmed by server
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let mcs = McsClient::connect(x224, "mstsc-rs", 800, 600, KeyboardLayout::French).await?;
+    /// ```
+    pub async fn connect(
+        x224: X224Client<S>,
+        client_name: &str,
+        screen_width: u16,
+        screen_height: u16,
+        keyboard_layout: KeyboardLayout,
+    ) -> Result<McsClient<S>> {
+        Self::connect_with_channels(
+            x224,
+            client_name,
+            screen_width,
+            screen_height,
+            keyboard_layout,
+            &[],
+        )
+        .await
+    }
+
+    /// Connect the MCS channel and join the static virtual channels
+    /// Channel names are truncated to 7 characters
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let channels = ["rdpdr".to_string(), "cliprdr".to_string()];
+    /// let mcs = McsClient::connect_with_channels(
+    ///     x224, "mstsc-rs", 800, 600, KeyboardLayout::French, &channels
+    /// ).await?;
+    /// assert!(mcs.has_channel("cliprdr"));
+    /// ```
+    #[cfg_attr(feature = "trace", tracing::instrument(name = "mcs", skip_all))]
+    pub async fn connect_with_channels(
+        mut x224: X224Client<S>,
+        client_name: &str,
+        screen_width: u16,
+        screen_height: u16,
+        keyboard_layout: KeyboardLayout,
+        channels: &[String],
+    ) -> Result<McsClient<S>> {
+        let channel_defs: Vec<ChannelDef> = channels
+            .iter()
+            .map(|name| ChannelDef::new(name, ChannelOption::ChannelOptionInitialized as u32))
+            .collect();
+        let channel_names: Vec<String> = channel_defs.iter().map(ChannelDef::get_name).collect();
+        Self::write_connect_initial(
+            &mut x224,
+            client_name,
+            screen_width,
+            screen_height,
+            keyboard_layout,
+            channel_defs,
+        )
+        .await?;
+        let server_data = Self::read_connect_response(&mut x224).await?;
+
+        x224.write(erect_domain_request().to_vec()).await?;
+        x224.write(attach_user_request().to_vec()).await?;
+
+        let user_id = read_attach_user_confirm(&mut Self::read_raw(&mut x224).await?)?;
+
+        // Add static channel
+        let mut channel_ids = HashMap::new();
+        channel_ids.insert("global".to_string(), server_data.network.mcs_channel_id);
+        channel_ids.insert("user".to_string(), user_id);
+        if let Some(message_channel) = &server_data.message_channel {
+            channel_ids.insert("message".to_string(), message_channel.mcs_channel_id);
+        }
+        // Ids of the static channels are in the order of the request
+        let static_ids = &server_data.network.channel_id_array;
+        for (name, channel_id) in channel_names.into_iter().zip(static_ids) {
+            channel_ids.insert(name, *channel_id);
+        }
+
+        // Join all channels
+        let mut rejected = Vec::new();
+        for (name, channel_id) in &channel_ids {
+            x224.write(channel_join_request(user_id, *channel_id).to_vec())
+                .await?;
+            let mut confirm = Self::read_raw(&mut x224).await?;
+            if !read_channel_join_confirm(user_id, *channel_id, &mut confirm)? {
+                rejected.push(name.clone());
+            }
+        }
+        // A static channel can be disabled by the server policy
+        for name in rejected {
+            if name == "global" || name == "user" {
+                return Err(Error::new(
+                    ErrorKind::ConnectionRefused,
+                    format!("MCS: server rejected the {} channel", name),
+                ));
+            }
+            trace_pdu!(channel_name = %name, "MCS: server rejected channel");
+            channel_ids.remove(&name);
+        }
+
+        Ok(McsClient {
+            x224,
+            server_data,
+            user_id,
+            channel_ids,
+            client_name: client_name.to_string(),
+        })
+    }
+
+    /// Write connection initial payload
+    /// This payload include a lot of
+    /// client specific config parameters
+    async fn write_connect_initial(
+        x224: &mut X224Client<S>,
+        client_name: &str,
+        screen_width: u16,
+        screen_height: u16,
+        keyboard_layout: KeyboardLayout,
+        channel_defs: Vec<ChannelDef>,
+    ) -> Result<()> {
+        let core = ClientCoreData::new(&ClientData {
+            width: screen_width,
+            height: screen_height,
+            layout: keyboard_layout,
+            server_selected_protocol: x224.get_selected_protocols() as u32,
+            rdp_version: Version::RdpVersion5plus,
+            name: client_name.to_string(),
+        });
+        let user_data = client_user_data(
+            &core,
+            &ClientSecurityData::new(),
+            &ClientNetworkData::new(channel_defs),
+        )
+        .await?;
+        let conference = write_conference_create_request(&user_data)?;
+        x224.write(write_connect_initial(conference.to_vec())).await
+    }
+
+    /// Read a connect response comming from server to client
+    async fn read_connect_response(x224: &mut X224Client<S>) -> Result<ServerData> {
+        let payload = Self::read_raw(x224).await?;
+        read_conference_create_response(&mut read_connect_response(&payload)?)
+    }
+
+    /// Read a payload which must not be a fast path one
+    /// All connection sequence messages are sent over x224
+    async fn read_raw(x224: &mut X224Client<S>) -> Result<BytesMut> {
+        match x224.read().await? {
+            Payload::Raw(payload) => Ok(payload),
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                "MCS: Unexpected fast path payload during connection",
+            )),
+        }
+    }
+
+    /// Send a message to a connected channel
+    /// MCS stand for multi channel
+    /// Write function write a message to specific channel
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// mcs.write("global", U16::LE(0)).await?;
+    /// ```
+    pub async fn write<T>(&mut self, channel_name: &str, message: T) -> Result<()>
+    where
+        T: Message,
+    {
+        self.write_with_priority(channel_name, message, WritePriority::Low)
+            .await
+    }
+
+    /// Send a message with a priority in the write queue
+    /// The priority is chosen by the upper layers from the type of the PDU
+    pub async fn write_with_priority<T>(
+        &mut self,
+        channel_name: &str,
+        message: T,
+        priority: WritePriority,
+    ) -> Result<()>
+    where
+        T: Message,
+    {
+        let channel_id = *self.channel_ids.get(channel_name).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("MCS: unknown channel {}", channel_name),
+            )
+        })?;
+
+        trace_pdu!(channel = channel_name, size = message.length(), "MCS: sent");
+        let mut buffer = send_data_request(self.user_id, channel_id, message.length());
+        buffer.extend_from_slice(&to_vec(&message).await?);
+        self.x224
+            .write_with_priority(buffer.to_vec(), priority)
+            .await
+    }
+
+    /// Fast path payloads are dedicated to the global channel
+    /// and skip the MCS layer
+    pub async fn write_fast_path(&mut self, sec_flag: u8, payload: Vec<u8>) -> Result<()> {
+        self.x224.write_fast_path(sec_flag, payload).await
+    }
+
+    /// Receive a message for a specific channel
+    /// Actually by design you can't ask for a specific channel
+    /// the caller need to handle all channels
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let (channel_name, payload) = mcs.read().await?;
+    /// match channel_name.as_str() {
+    ///     "global" => println!("main channel"),
+    ///     ...
+    /// }
+    /// ```
+    pub async fn read(&mut self) -> Result<(String, Payload)> {
+        match self.x224.read().await? {
+            Payload::Raw(mut payload) => {
+                match read_mcs_pdu_header(&mut payload)? {
+                    DomainMCSPDU::SendDataIndication => (),
+                    DomainMCSPDU::DisconnectProviderUltimatum => {
+                        return Err(Error::new(
+                            ErrorKind::ConnectionAborted,
+                            "MCS: Disconnect Provider Ultimatum",
+                        ))
+                    }
+                    _ => {
+                        return Err(Error::new(ErrorKind::InvalidData, "MCS: Invalid opcode"))
+                    }
+                }
+
+                // Server user id
+                per::read_integer_16(MCS_USERCHANNEL_BASE, &mut payload)?;
+
+                let channel_id = per::read_integer_16(0, &mut payload)?;
+                let channel = self
+                    .channel_ids
+                    .iter()
+                    .find(|x| *x.1 == channel_id)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "MCS: unknown channel"))?;
+
+                per::read_enumerates(&mut payload)?;
+                let length = per::read_length(&mut payload)?;
+                self.x224.get_limits().check_channel_chunk(length as usize)?;
+                trace_pdu!(channel = %channel.0, size = payload.len(), "MCS: received");
+
+                Ok((channel.0.clone(), Payload::Raw(payload)))
+            }
+            // fastpath packet are dedicated to global channel
+            Payload::FastPath(sec_flag, payload) => {
+                Ok(("global".to_string(), Payload::FastPath(sec_flag, payload)))
+            }
+        }
+    }
+
+    /// Send a close event to server
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.x224
+            .write(disconnect_provider_ultimatum().to_vec())
+            .await?;
+        self.x224.shutdown(None).await
+    }
+
+    /// This function check if the client
+    /// version protocol choose is 5+
+    pub fn is_rdp_version_5_plus(&self) -> bool {
+        self.server_data.rdp_version() == Version::RdpVersion5plus
+    }
+
+    /// Change the limits on the length of received PDUs
+    pub fn set_limits(&mut self, limits: PduLimits) {
+        self.x224.set_limits(limits);
+    }
+
+    /// Limits on the length of received PDUs
+    pub fn get_limits(&self) -> PduLimits {
+        self.x224.get_limits()
+    }
+
+    /// Counters of the connection
+    pub fn get_metrics(&self) -> &Metrics {
+        self.x224.get_metrics()
+    }
+
+    /// Getter of the server data sent during connection step
+    pub fn get_server_data(&self) -> &ServerData {
+        &self.server_data
+    }
+
+    /// Getter of the client name sent during connection steps
+    pub fn get_client_name(&self) -> &str {
+        &self.client_name
+    }
+
+    /// Getter of the user id negotiated during connection steps
+    pub fn get_user_id(&self) -> u16 {
+        self.user_id
+    }
+
+    /// Getter of the global channel id
+    pub fn get_global_channel_id(&self) -> u16 {
+        self.channel_ids["global"]
+    }
+
+    /// Check if a channel was joined during connection steps
+    pub fn has_channel(&self, channel_name: &str) -> bool {
+        self.channel_ids.contains_key(channel_name)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> McsClient<S> {
+    /// Write the next PDUs from a task of their own
+    pub fn start_write_queue(&mut self, capacity: usize, prioritized: bool) {
+        self.x224.start_write_queue(capacity, prioritized);
+    }
+}
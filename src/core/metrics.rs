@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Value of the round trip time before any measure
+const RTT_UNKNOWN: u64 = u64::MAX;
+
+#[derive(Debug)]
+struct Counters {
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    fast_path_frames: AtomicU64,
+    decode_errors: AtomicU64,
+    buffer_reuses: AtomicU64,
+    buffer_allocations: AtomicU64,
+    write_queue_depth: AtomicU64,
+    rtt: AtomicU64,
+    pdus: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Counters {
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            fast_path_frames: AtomicU64::new(0),
+            decode_errors: AtomicU64::new(0),
+            buffer_reuses: AtomicU64::new(0),
+            buffer_allocations: AtomicU64::new(0),
+            write_queue_depth: AtomicU64::new(0),
+            rtt: AtomicU64::new(RTT_UNKNOWN),
+            pdus: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+/// Shared handle on the counters of a connection
+///
+/// The TPKT layer counts bytes and frames, the fast path
+/// reader counts updates and the security layer keeps the
+/// round trip time measured by the auto detection
+///
+/// # Example
+/// ```
+/// use rdp::core::metrics::Metrics;
+/// let metrics = Metrics::default();
+/// // tpkt.set_metrics(metrics.clone());
+/// let snapshot = metrics.snapshot();
+/// assert_eq!(snapshot.bytes_received, 0);
+/// assert!(snapshot.to_prometheus().contains("rdp_bytes_received_total 0"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    counters: Arc<Counters>,
+}
+
+impl Metrics {
+    pub(crate) fn add_bytes_received(&self, size: usize) {
+        self.counters.bytes_received.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes_sent(&self, size: usize) {
+        self.counters.bytes_sent.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_fast_path_frame(&self) {
+        self.counters.fast_path_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_decode_error(&self) {
+        self.counters.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_buffer_reuse(&self) {
+        self.counters.buffer_reuses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_buffer_allocation(&self) {
+        self.counters.buffer_allocations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_queued_frame(&self) {
+        self.counters.write_queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn remove_queued_frame(&self) {
+        self.counters.write_queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Count a received PDU of a type
+    pub(crate) fn add_pdu(&self, pdu_type: &str) {
+        if let Ok(mut pdus) = self.counters.pdus.lock() {
+            *pdus.entry(pdu_type.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// Round trip time in milliseconds
+    pub(crate) fn set_rtt(&self, rtt: u32) {
+        self.counters.rtt.store(rtt as u64, Ordering::Relaxed);
+    }
+
+    /// Copy of the current values
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let rtt = self.counters.rtt.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            bytes_received: self.counters.bytes_received.load(Ordering::Relaxed),
+            bytes_sent: self.counters.bytes_sent.load(Ordering::Relaxed),
+            fast_path_frames: self.counters.fast_path_frames.load(Ordering::Relaxed),
+            decode_errors: self.counters.decode_errors.load(Ordering::Relaxed),
+            buffer_reuses: self.counters.buffer_reuses.load(Ordering::Relaxed),
+            buffer_allocations: self.counters.buffer_allocations.load(Ordering::Relaxed),
+            write_queue_depth: self.counters.write_queue_depth.load(Ordering::Relaxed),
+            rtt: if rtt == RTT_UNKNOWN { None } else { Some(rtt as u32) },
+            pdus: self.counters.pdus.lock().map(|pdus| pdus.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+/// Values of the counters at a given time
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MetricsSnapshot {
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    /// Fast path PDUs received
+    pub fast_path_frames: u64,
+    /// PDUs which could not be parsed
+    pub decode_errors: u64,
+    /// Receive buffer storage reused once its PDUs were dropped
+    pub buffer_reuses: u64,
+    /// Receive buffer storage allocated since PDUs were still alive
+    /// or the storage was too small
+    pub buffer_allocations: u64,
+    /// Frames waiting in the write queue of the session
+    pub write_queue_depth: u64,
+    /// Last round trip time in milliseconds
+    pub rtt: Option<u32>,
+    /// Received PDUs by type
+    pub pdus: BTreeMap<String, u64>,
+}
+
+impl MetricsSnapshot {
+    /// Format the values in the Prometheus text format
+    pub fn to_prometheus(&self) -> String {
+        let mut result = String::new();
+        let counters = [
+            ("rdp_bytes_received_total", self.bytes_received),
+            ("rdp_bytes_sent_total", self.bytes_sent),
+            ("rdp_fast_path_frames_total", self.fast_path_frames),
+            ("rdp_decode_errors_total", self.decode_errors),
+            ("rdp_receive_buffer_reuses_total", self.buffer_reuses),
+            ("rdp_receive_buffer_allocations_total", self.buffer_allocations),
+        ];
+        for (name, value) in counters {
+            let _ = writeln!(result, "# TYPE {} counter\n{} {}", name, name, value);
+        }
+
+        let _ = writeln!(result, "# TYPE rdp_pdus_received_total counter");
+        for (pdu_type, count) in &self.pdus {
+            let _ = writeln!(result, "rdp_pdus_received_total{{type=\"{}\"}} {}", pdu_type, count);
+        }
+
+        let _ = writeln!(
+            result,
+            "# TYPE rdp_write_queue_depth gauge\nrdp_write_queue_depth {}",
+            self.write_queue_depth
+        );
+
+        if let Some(rtt) = self.rtt {
+            let _ = writeln!(result, "# TYPE rdp_rtt_milliseconds gauge\nrdp_rtt_milliseconds {}", rtt);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Clones share the same counters
+    #[test]
+    fn test_metrics_shared() {
+        let metrics = Metrics::default();
+        let handle = metrics.clone();
+        handle.add_bytes_received(10);
+        handle.add_pdu("tpkt");
+        handle.add_pdu("tpkt");
+        handle.set_rtt(25);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.bytes_received, 10);
+        assert_eq!(snapshot.pdus["tpkt"], 2);
+        assert_eq!(snapshot.rtt, Some(25));
+    }
+
+    /// Test of the Prometheus text format
+    #[test]
+    fn test_metrics_prometheus() {
+        let metrics = Metrics::default();
+        metrics.add_bytes_sent(4);
+        metrics.add_pdu("FastpathUpdatetypeBitmap");
+
+        let text = metrics.snapshot().to_prometheus();
+        assert!(text.contains("rdp_bytes_sent_total 4\n"));
+        assert!(text.contains("rdp_write_queue_depth 0\n"));
+        assert!(text.contains("rdp_pdus_received_total{type=\"FastpathUpdatetypeBitmap\"} 1\n"));
+        assert!(!text.contains("rdp_rtt_milliseconds"));
+    }
+}
@@ -8,4 +8,52 @@ pub mod sec;
 pub mod license;
 pub mod global;
 pub mod capability;
-pub mod event;
\ No newline at end of file
+pub mod event;
+pub mod session;
+pub mod error_info;
+pub mod update;
+pub mod fastpath;
+pub mod input;
+pub mod keyboard;
+pub mod pointer;
+pub mod heartbeat;
+pub mod autodetect;
+pub mod limits;
+pub mod server;
+pub mod proxy;
+pub mod record;
+#[cfg(feature = "capture")]
+pub mod capture;
+pub(crate) mod trace;
+pub mod metrics;
+pub mod runtime;
+#[cfg(feature = "compat")]
+pub mod compat;
+pub mod config;
+pub mod connector;
+pub mod preconnection;
+pub mod rdp_file;
+pub mod rdp_url;
+pub mod reconnect;
+pub mod handler;
+pub mod framebuffer;
+pub mod order;
+pub mod surface;
+pub mod egfx;
+pub mod channel;
+pub mod drdynvc;
+pub mod cliprdr;
+pub mod rdpdr;
+pub mod rdpsnd;
+pub mod audin;
+pub mod rail;
+pub mod rdpei;
+pub mod rdpedisp;
+pub mod urbdrc;
+pub mod echo;
+pub mod rdpevor;
+pub mod multitransport;
+pub mod rdpeudp;
+pub mod rdg;
+#[cfg(feature = "websocket")]
+pub mod websocket;
\ No newline at end of file
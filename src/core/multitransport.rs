@@ -0,0 +1,131 @@
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Success of a multitransport response
+pub const S_OK: u32 = 0x00000000;
+/// The client could not set up the side channel
+pub const E_ABORT: u32 = 0x80004004;
+
+/// Transport asked by the server
+/// MS-RDPBCGR 2.2.15.1 Initiate Multitransport Request PDU
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum RequestedProtocol {
+    /// Reliable UDP transport
+    InititateRequestProtocolUdpfecr = 0x0001,
+    /// Lossy UDP transport
+    InititateRequestProtocolUdpfecl = 0x0004,
+}
+
+/// Ask the client to connect a UDP side channel
+/// sent on the message channel with the SecTransportReq flag
+/// MS-RDPBCGR 2.2.15.1 Initiate Multitransport Request PDU
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MultitransportRequest {
+    pub request_id: u32,
+    pub requested_protocol: RequestedProtocol,
+    /// Sent back in the tunnel create request of the side channel
+    pub security_cookie: [u8; 16],
+}
+
+/// Read an initiate multitransport request without its security header
+pub fn read_multitransport_request(buffer: &mut BytesMut) -> Result<MultitransportRequest> {
+    check_remaining(buffer, 24, "MULTITRANSPORT: request")?;
+    let request_id = buffer.get_u32_le();
+    let requested_protocol = buffer.get_u16_le();
+    let _reserved = buffer.get_u16_le();
+    let mut security_cookie = [0u8; 16];
+    buffer.copy_to_slice(&mut security_cookie);
+    Ok(MultitransportRequest {
+        request_id,
+        requested_protocol: RequestedProtocol::try_from(requested_protocol).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "MULTITRANSPORT: unexpected requested protocol {}",
+                    requested_protocol
+                ),
+            )
+        })?,
+        security_cookie,
+    })
+}
+
+/// Result of a side channel setup
+/// sent on the message channel with the RdpSecTransportRsp flag
+/// MS-RDPBCGR 2.2.15.2 Initiate Multitransport Response PDU
+///
+/// # Example
+/// ```
+/// use rdp::core::multitransport::{MultitransportResponse, E_ABORT};
+/// // The UDP transport can't be used, the server keeps everything on TCP
+/// let response = MultitransportResponse::new(1, E_ABORT);
+/// ```
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct MultitransportResponse {
+    pub request_id: u32,
+    /// S_OK or E_ABORT
+    pub hr_response: u32,
+}
+
+impl MultitransportResponse {
+    pub fn new(request_id: u32, hr_response: u32) -> Self {
+        MultitransportResponse {
+            request_id,
+            hr_response,
+        }
+    }
+}
+
+#[async_trait]
+impl Message for MultitransportResponse {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.request_id).await?;
+        writer.write_u32_le(self.hr_response).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.request_id = reader.read_u32_le().await?;
+        self.hr_response = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Test of the multitransport request and response formats
+    #[tokio::test]
+    async fn test_multitransport() {
+        let mut buffer = BytesMut::from(
+            &[
+                2, 0, 0, 0, 1, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+            ][..],
+        );
+        assert_eq!(
+            read_multitransport_request(&mut buffer).unwrap(),
+            MultitransportRequest {
+                request_id: 2,
+                requested_protocol: RequestedProtocol::InititateRequestProtocolUdpfecr,
+                security_cookie: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+            }
+        );
+        assert_eq!(
+            to_vec(&MultitransportResponse::new(2, E_ABORT))
+                .await
+                .unwrap(),
+            [2, 0, 0, 0, 0x04, 0x40, 0x00, 0x80]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use crate::core::event::FrameAction;
+use crate::core::order::window::{read_window_order, WindowOrder};
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+
+/// Alternate secondary drawing order types
+/// The type is in the 6 high bits of the control flags
+/// MS-RDPEGDI 2.2.2.2.1.3.1.1 Alternate Secondary Drawing Order Header (ALTSEC_DRAWING_ORDER_HEADER)
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum AltSecondaryOrderType {
+    TsAltsecSwitchSurface = 0x00,
+    TsAltsecCreateOffscrBitmap = 0x01,
+    TsAltsecStreamBitmapFirst = 0x02,
+    TsAltsecStreamBitmapNext = 0x03,
+    TsAltsecCreateNinegridBitmap = 0x04,
+    TsAltsecGdipFirst = 0x05,
+    TsAltsecGdipNext = 0x06,
+    TsAltsecGdipEnd = 0x07,
+    TsAltsecGdipCacheFirst = 0x08,
+    TsAltsecGdipCacheNext = 0x09,
+    TsAltsecGdipCacheEnd = 0x0A,
+    TsAltsecWindow = 0x0B,
+    TsAltsecCompdeskFirst = 0x0C,
+    TsAltsecFrameMarker = 0x0D,
+}
+
+/// Surface id of the screen in switch surface orders
+/// MS-RDPEGDI 2.2.2.2.1.3.3 Switch Surface (SWITCH_SURFACE_ORDER)
+pub const SCREEN_BITMAP_SURFACE: u16 = 0xFFFF;
+
+/// The offscreen bitmap id is in the 15 low bits
+const OFFSCREEN_BITMAP_ID_MASK: u16 = 0x7FFF;
+/// Flag of the create offscreen bitmap order
+/// set when a delete list follows the size
+const OFFSCREEN_DELETE_LIST_PRESENT: u16 = 0x8000;
+
+/// Create an offscreen surface
+/// Surfaces of the delete list are removed first
+/// MS-RDPEGDI 2.2.2.2.1.3.2 Create Offscreen Bitmap (CREATE_OFFSCR_BITMAP_ORDER)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CreateOffscreenBitmapOrder {
+    pub id: u16,
+    pub width: u16,
+    pub height: u16,
+    pub delete_list: Vec<u16>,
+}
+
+impl CreateOffscreenBitmapOrder {
+    fn read_from_buffer(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 6, "ORDER: create offscreen bitmap")?;
+        let flags = buffer.get_u16_le();
+        let width = buffer.get_u16_le();
+        let height = buffer.get_u16_le();
+        let mut delete_list = Vec::new();
+        if flags & OFFSCREEN_DELETE_LIST_PRESENT != 0 {
+            check_remaining(buffer, 2, "ORDER: offscreen delete list")?;
+            let count = buffer.get_u16_le() as usize;
+            check_remaining(buffer, count * 2, "ORDER: offscreen delete list")?;
+            delete_list = (0..count).map(|_| buffer.get_u16_le()).collect();
+        }
+        Ok(CreateOffscreenBitmapOrder {
+            id: flags & OFFSCREEN_BITMAP_ID_MASK,
+            width,
+            height,
+            delete_list,
+        })
+    }
+}
+
+/// Alternate secondary orders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AltSecondaryOrder {
+    /// Following orders are drawn on this offscreen surface,
+    /// or on the screen for SCREEN_BITMAP_SURFACE
+    /// MS-RDPEGDI 2.2.2.2.1.3.3 Switch Surface (SWITCH_SURFACE_ORDER)
+    SwitchSurface(u16),
+    CreateOffscreenBitmap(CreateOffscreenBitmapOrder),
+    /// MS-RDPEGDI 2.2.2.2.1.3.7 Frame Marker (FRAME_MARKER)
+    FrameMarker(FrameAction),
+    /// Window, notification icon or desktop of a RemoteApp session
+    /// MS-RDPERP 2.2.1.3 Windowing Alternate Secondary Drawing Orders
+    Window(WindowOrder),
+}
+
+/// Read an alternate secondary order
+/// The other orders have no length field, so they can't be skipped
+/// Windowing orders are only sent when RemoteApp is enabled
+pub fn read_alt_secondary_order(
+    control_flags: u8,
+    buffer: &mut BytesMut,
+) -> Result<AltSecondaryOrder> {
+    let order_type = control_flags >> 2;
+    match AltSecondaryOrderType::try_from(order_type) {
+        Ok(AltSecondaryOrderType::TsAltsecSwitchSurface) => {
+            check_remaining(buffer, 2, "ORDER: switch surface")?;
+            Ok(AltSecondaryOrder::SwitchSurface(buffer.get_u16_le()))
+        }
+        Ok(AltSecondaryOrderType::TsAltsecCreateOffscrBitmap) => {
+            Ok(AltSecondaryOrder::CreateOffscreenBitmap(
+                CreateOffscreenBitmapOrder::read_from_buffer(buffer)?,
+            ))
+        }
+        Ok(AltSecondaryOrderType::TsAltsecFrameMarker) => {
+            check_remaining(buffer, 4, "ORDER: frame marker")?;
+            let action = buffer.get_u32_le();
+            FrameAction::try_from(action)
+                .map(AltSecondaryOrder::FrameMarker)
+                .map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("ORDER: invalid frame action {}", action),
+                    )
+                })
+        }
+        Ok(AltSecondaryOrderType::TsAltsecWindow) => {
+            Ok(AltSecondaryOrder::Window(read_window_order(buffer)?))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "ORDER: unsupported alternate secondary order {}",
+                order_type
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::BufMut;
+
+    /// Test the parsing of the supported alternate secondary orders
+    #[test]
+    fn test_read_alt_secondary_order() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u16_le(OFFSCREEN_DELETE_LIST_PRESENT | 3);
+        buffer.put_u16_le(64);
+        buffer.put_u16_le(32);
+        buffer.put_u16_le(2);
+        buffer.put_u16_le(1);
+        buffer.put_u16_le(2);
+        let control_flags = (AltSecondaryOrderType::TsAltsecCreateOffscrBitmap as u8) << 2;
+        assert_eq!(
+            read_alt_secondary_order(control_flags, &mut buffer).unwrap(),
+            AltSecondaryOrder::CreateOffscreenBitmap(CreateOffscreenBitmapOrder {
+                id: 3,
+                width: 64,
+                height: 32,
+                delete_list: vec![1, 2],
+            })
+        );
+
+        buffer.put_u16_le(SCREEN_BITMAP_SURFACE);
+        assert_eq!(
+            read_alt_secondary_order(0, &mut buffer).unwrap(),
+            AltSecondaryOrder::SwitchSurface(SCREEN_BITMAP_SURFACE)
+        );
+
+        buffer.put_u32_le(1);
+        let control_flags = (AltSecondaryOrderType::TsAltsecFrameMarker as u8) << 2;
+        assert_eq!(
+            read_alt_secondary_order(control_flags, &mut buffer).unwrap(),
+            AltSecondaryOrder::FrameMarker(FrameAction::End)
+        );
+
+        buffer.put_u32_le(0);
+        let control_flags = (AltSecondaryOrderType::TsAltsecCompdeskFirst as u8) << 2;
+        assert!(read_alt_secondary_order(control_flags, &mut buffer).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::core::order::altsec::{read_alt_secondary_order, AltSecondaryOrder};
+use crate::core::order::primary::{PrimaryOrder, PrimaryOrderReader};
+use crate::core::order::secondary::{read_secondary_order, SecondaryOrder};
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BytesMut};
+use std::io::Result;
+
+/// Control flags of a drawing order
+/// MS-RDPEGDI 2.2.2.2.1.1.2 Primary Drawing Order (PRIMARY_DRAWING_ORDER)
+#[repr(u8)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ControlFlag {
+    TsStandard = 0x01,
+    TsSecondary = 0x02,
+    TsBounds = 0x04,
+    TsTypeChange = 0x08,
+    TsDeltaCoordinates = 0x10,
+    TsZeroBoundsDeltas = 0x20,
+    TsZeroFieldByteBit0 = 0x40,
+    TsZeroFieldByteBit1 = 0x80,
+}
+
+/// Clipping rectangle of primary orders
+/// Bounds are inclusive and may be negative
+/// MS-RDPEGDI 2.2.2.2.1.1.1.4 Bounds (TS_BOUNDS)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Bounds {
+    pub left: i16,
+    pub top: i16,
+    pub right: i16,
+    pub bottom: i16,
+}
+
+/// A parsed drawing order
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawingOrder {
+    /// Bounds are only set when the order is clipped
+    Primary {
+        bounds: Option<Bounds>,
+        order: PrimaryOrder,
+    },
+    Secondary(SecondaryOrder),
+    AltSecondary(AltSecondaryOrder),
+}
+
+/// Read a 2 byte unsigned encoding
+/// MS-RDPEGDI 2.2.2.2.1.2.1.2 Two-Byte Unsigned Encoding (TWO_BYTE_UNSIGNED_ENCODING)
+pub(crate) fn read_2byte_unsigned(buffer: &mut BytesMut) -> Result<u16> {
+    check_remaining(buffer, 1, "ORDER: two byte unsigned")?;
+    let first = buffer.get_u8();
+    if first & 0x80 == 0 {
+        return Ok(first as u16);
+    }
+    check_remaining(buffer, 1, "ORDER: two byte unsigned")?;
+    Ok(((first as u16 & 0x7F) << 8) | buffer.get_u8() as u16)
+}
+
+/// Read a 2 byte signed encoding
+/// The second high bit is the sign
+/// MS-RDPEGDI 2.2.2.2.1.2.1.3 Two-Byte Signed Encoding (TWO_BYTE_SIGNED_ENCODING)
+pub(crate) fn read_2byte_signed(buffer: &mut BytesMut) -> Result<i16> {
+    check_remaining(buffer, 1, "ORDER: two byte signed")?;
+    let first = buffer.get_u8();
+    let mut value = (first & 0x3F) as i16;
+    if first & 0x80 != 0 {
+        check_remaining(buffer, 1, "ORDER: two byte signed")?;
+        value = (value << 8) | buffer.get_u8() as i16;
+    }
+    Ok(if first & 0x40 != 0 { -value } else { value })
+}
+
+/// Read a 4 byte unsigned encoding
+/// The 2 high bits are the number of bytes which follow
+/// MS-RDPEGDI 2.2.2.2.1.2.1.4 Four-Byte Unsigned Encoding (FOUR_BYTE_UNSIGNED_ENCODING)
+pub(crate) fn read_4byte_unsigned(buffer: &mut BytesMut) -> Result<u32> {
+    check_remaining(buffer, 1, "ORDER: four byte unsigned")?;
+    let first = buffer.get_u8();
+    let count = (first >> 6) as usize;
+    check_remaining(buffer, count, "ORDER: four byte unsigned")?;
+    Ok((0..count).fold((first & 0x3F) as u32, |value, _| {
+        (value << 8) | buffer.get_u8() as u32
+    }))
+}
+
+/// Parser of the drawing orders sent by the server
+///
+/// Primary orders only send the fields which changed
+/// since the previous order of the same type,
+/// so a single reader is used for the whole activation
+#[derive(Default)]
+pub struct OrderReader {
+    primary: PrimaryOrderReader,
+}
+
+impl OrderReader {
+    /// Read the orders of an update
+    /// MS-RDPEGDI 2.2.2.2 Orders Update (TS_UPDATE_ORDERS_PDU_DATA)
+    pub fn read(&mut self, number_orders: u16, buffer: &mut BytesMut) -> Result<Vec<DrawingOrder>> {
+        let mut orders = Vec::with_capacity(number_orders as usize);
+        for _ in 0..number_orders {
+            check_remaining(buffer, 1, "ORDER: control flags")?;
+            let control_flags = buffer.get_u8();
+            orders.push(if control_flags & ControlFlag::TsStandard as u8 == 0 {
+                DrawingOrder::AltSecondary(read_alt_secondary_order(control_flags, buffer)?)
+            } else if control_flags & ControlFlag::TsSecondary as u8 != 0 {
+                DrawingOrder::Secondary(read_secondary_order(buffer)?)
+            } else {
+                let (bounds, order) = self.primary.read(control_flags, buffer)?;
+                DrawingOrder::Primary { bounds, order }
+            });
+        }
+        Ok(orders)
+    }
+
+    /// Read the orders of a fast path update
+    /// The number of orders comes first
+    /// MS-RDPEGDI 2.2.2.2 Fast-Path Orders Update (TS_FP_UPDATE_ORDERS)
+    pub fn read_fast_path(&mut self, buffer: &mut BytesMut) -> Result<Vec<DrawingOrder>> {
+        check_remaining(buffer, 2, "ORDER: number of orders")?;
+        let number_orders = buffer.get_u16_le();
+        self.read(number_orders, buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::order::primary::MemBltOrder;
+    use crate::core::order::secondary::CacheBitmapV2Order;
+    use bytes::BufMut;
+
+    /// Variable length encodings of cache bitmap orders
+    #[test]
+    fn test_read_unsigned_encodings() {
+        let mut buffer =
+            BytesMut::from(&[0x7F, 0x81, 0x02, 0x05, 0x41, 0x02, 0xC1, 0x02, 0x03, 0x04][..]);
+        assert_eq!(read_2byte_unsigned(&mut buffer).unwrap(), 0x7F);
+        assert_eq!(read_2byte_unsigned(&mut buffer).unwrap(), 0x102);
+        assert_eq!(read_4byte_unsigned(&mut buffer).unwrap(), 0x05);
+        assert_eq!(read_4byte_unsigned(&mut buffer).unwrap(), 0x102);
+        assert_eq!(read_4byte_unsigned(&mut buffer).unwrap(), 0x1020304);
+        assert!(read_2byte_unsigned(&mut buffer).is_err());
+    }
+
+    /// Signed encoding of glyph positions
+    #[test]
+    fn test_read_signed_encoding() {
+        let mut buffer = BytesMut::from(&[0x05, 0x45, 0xC1, 0x02, 0x80][..]);
+        assert_eq!(read_2byte_signed(&mut buffer).unwrap(), 5);
+        assert_eq!(read_2byte_signed(&mut buffer).unwrap(), -5);
+        assert_eq!(read_2byte_signed(&mut buffer).unwrap(), -0x102);
+        assert!(read_2byte_signed(&mut buffer).is_err());
+    }
+
+    /// A cached bitmap then two MemBlt, the second with delta coordinates
+    #[test]
+    fn test_read_orders() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u16_le(3);
+
+        // Cache bitmap rev2, uncompressed, cache 1, 32 bpp, same height
+        buffer.put_u8(ControlFlag::TsStandard as u8 | ControlFlag::TsSecondary as u8);
+        buffer.put_u16_le(3 + 16 - 7);
+        buffer.put_u16_le(1 | (6 << 3) | (0x01 << 7));
+        buffer.put_u8(0x04);
+        buffer.put_slice(&[0x02, 0x10, 0x05]);
+        buffer.put_slice(&[0xAA; 16]);
+
+        // MemBlt, all fields
+        buffer.put_u8(ControlFlag::TsStandard as u8 | ControlFlag::TsTypeChange as u8);
+        buffer.put_u8(0x0D);
+        buffer.put_u16_le(0x01FF);
+        buffer.put_u16_le(1);
+        for value in [10i16, 20, 2, 2] {
+            buffer.put_i16_le(value);
+        }
+        buffer.put_u8(0xCC);
+        buffer.put_i16_le(0);
+        buffer.put_i16_le(0);
+        buffer.put_u16_le(5);
+
+        // MemBlt, only the left field moves by 4, with bounds
+        buffer.put_u8(
+            ControlFlag::TsStandard as u8
+                | ControlFlag::TsDeltaCoordinates as u8
+                | ControlFlag::TsBounds as u8
+                | ControlFlag::TsZeroFieldByteBit0 as u8,
+        );
+        buffer.put_u8(0x02);
+        // Absolute left and delta bottom bounds
+        buffer.put_u8(0x81);
+        buffer.put_i16_le(-2);
+        buffer.put_i8(4);
+        buffer.put_i8(4);
+
+        let mut reader = OrderReader::default();
+        let orders = reader.read_fast_path(&mut buffer).unwrap();
+        assert!(buffer.is_empty());
+        assert_eq!(
+            orders[0],
+            DrawingOrder::Secondary(SecondaryOrder::CacheBitmapV2(CacheBitmapV2Order {
+                cache_id: 1,
+                bpp: 32,
+                cache_index: 5,
+                key: None,
+                width: 2,
+                height: 2,
+                is_compress: false,
+                do_not_cache: false,
+                data: vec![0xAA; 16],
+            }))
+        );
+        let mem_blt = MemBltOrder {
+            cache_id: 1,
+            color_table_index: 0,
+            left: 10,
+            top: 20,
+            width: 2,
+            height: 2,
+            rop: 0xCC,
+            source_x: 0,
+            source_y: 0,
+            cache_index: 5,
+        };
+        assert_eq!(
+            orders[1],
+            DrawingOrder::Primary {
+                bounds: None,
+                order: PrimaryOrder::MemBlt(mem_blt)
+            }
+        );
+        assert_eq!(
+            orders[2],
+            DrawingOrder::Primary {
+                bounds: Some(Bounds {
+                    left: -2,
+                    top: 0,
+                    right: 0,
+                    bottom: 4,
+                }),
+                order: PrimaryOrder::MemBlt(MemBltOrder {
+                    left: 14,
+                    ..mem_blt
+                })
+            }
+        );
+    }
+}
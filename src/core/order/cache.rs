@@ -0,0 +1,345 @@
+use crate::core::capability::GlyphCacheCapability;
+use crate::core::event::BitmapEvent;
+use crate::core::order::gdi::decode_bitmap;
+use crate::core::order::secondary::{
+    CacheBitmapV2Order, CacheBrushOrder, CacheGlyphOrder, Glyph, SecondaryOrder,
+};
+use crate::core::update::Palette;
+
+use std::io::{Error, ErrorKind, Result};
+
+/// Index used by the server for bitmaps
+/// which are drawn without being cached
+/// MS-RDPEGDI 3.1.1.1.1 Bitmap Caches
+const BITMAPCACHE_WAITING_LIST_INDEX: u16 = 0x7FFF;
+
+/// Entries of each brush cache
+const BRUSH_CACHE_ENTRIES: usize = 64;
+
+/// Entries of the color table cache
+/// MS-RDPBCGR 2.2.7.1.6 Color Table Cache Capability Set
+const COLOR_TABLE_CACHE_ENTRIES: usize = 6;
+
+fn invalid_entry(name: &str, cache_id: usize, index: usize) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "ORDER: no {} in cache {} at index {}",
+            name, cache_id, index
+        ),
+    )
+}
+
+/// A decoded bitmap
+/// Pixels are 0xAARRGGBB, rows are top down
+#[derive(Debug, Clone)]
+pub(crate) struct CachedBitmap {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) pixels: Vec<u32>,
+}
+
+/// Client side bitmap caches
+/// Bitmaps are stored at the cache and index chosen by the server
+/// MS-RDPEGDI 3.1.1.1.1 Bitmap Caches
+pub struct BitmapCache {
+    caches: Vec<Vec<Option<CachedBitmap>>>,
+}
+
+impl BitmapCache {
+    /// Cache sizes must match the bitmap cache capability,
+    /// the persistent flag of each cache is ignored
+    pub fn new(entries: &[u32]) -> Self {
+        BitmapCache {
+            caches: entries
+                .iter()
+                .take(5)
+                .map(|entries| {
+                    let size = (entries & 0x7FFF_FFFF).min(BITMAPCACHE_WAITING_LIST_INDEX as u32);
+                    vec![None; size as usize]
+                })
+                .collect(),
+        }
+    }
+
+    /// Decode a bitmap and store it
+    /// 8 bpp bitmaps use the current palette
+    pub fn insert(&mut self, order: CacheBitmapV2Order, palette: &Palette) -> Result<()> {
+        if order.do_not_cache || order.cache_index == BITMAPCACHE_WAITING_LIST_INDEX {
+            return Ok(());
+        }
+        let entry = self
+            .caches
+            .get_mut(order.cache_id as usize)
+            .and_then(|cache| cache.get_mut(order.cache_index as usize))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "ORDER: invalid bitmap cache {} index {}",
+                        order.cache_id, order.cache_index
+                    ),
+                )
+            })?;
+
+        let bitmap = decode_bitmap(palette.apply(BitmapEvent {
+            dest_left: 0,
+            dest_top: 0,
+            dest_right: order.width.saturating_sub(1),
+            dest_bottom: order.height.saturating_sub(1),
+            width: order.width,
+            height: order.height,
+            bpp: order.bpp,
+            is_compress: order.is_compress,
+            data: order.data,
+        }))?;
+        let width = order.width as usize;
+        // Same row order as the surface
+        let pixels = if width == 0 {
+            Vec::new()
+        } else {
+            bitmap
+                .data
+                .chunks_exact(width * 4)
+                .rev()
+                .flat_map(|row| row.chunks_exact(4))
+                .map(|pixel| u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+                .collect()
+        };
+        *entry = Some(CachedBitmap {
+            width,
+            height: order.height as usize,
+            pixels,
+        });
+        Ok(())
+    }
+
+    /// A bitmap stored by a cache bitmap order
+    pub(crate) fn get(&self, cache_id: u8, cache_index: u16) -> Result<&CachedBitmap> {
+        self.caches
+            .get(cache_id as usize)
+            .and_then(|cache| cache.get(cache_index as usize))
+            .and_then(|entry| entry.as_ref())
+            .ok_or_else(|| invalid_entry("bitmap", cache_id as usize, cache_index as usize))
+    }
+}
+
+/// Glyph caches and the glyph fragment cache
+/// Sizes match the glyph cache capability
+/// MS-RDPEGDI 3.1.1.1.2 Glyph Caches
+pub struct GlyphCache {
+    caches: Vec<Vec<Option<Glyph>>>,
+    fragments: Vec<Option<Vec<u8>>>,
+}
+
+impl GlyphCache {
+    pub fn new(capability: &GlyphCacheCapability) -> Self {
+        GlyphCache {
+            caches: capability
+                .glyph_cache
+                .iter()
+                .map(|cache| vec![None; cache.cache_entries as usize])
+                .collect(),
+            fragments: vec![None; (capability.frag_cache & 0xFFFF) as usize],
+        }
+    }
+
+    /// Store a glyph at its cache index
+    pub fn put(&mut self, cache_id: u8, glyph: Glyph) -> Result<()> {
+        let index = glyph.cache_index as usize;
+        let entry = self
+            .caches
+            .get_mut(cache_id as usize)
+            .and_then(|cache| cache.get_mut(index))
+            .ok_or_else(|| invalid_entry("glyph slot", cache_id as usize, index))?;
+        *entry = Some(glyph);
+        Ok(())
+    }
+
+    pub fn insert(&mut self, order: CacheGlyphOrder) -> Result<()> {
+        for glyph in order.glyphs {
+            self.put(order.cache_id, glyph)?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, cache_id: u8, index: u8) -> Result<&Glyph> {
+        self.caches
+            .get(cache_id as usize)
+            .and_then(|cache| cache.get(index as usize))
+            .and_then(|entry| entry.as_ref())
+            .ok_or_else(|| invalid_entry("glyph", cache_id as usize, index as usize))
+    }
+
+    /// Store the glyph indices of a text fragment
+    pub fn put_fragment(&mut self, index: u8, fragment: &[u8]) -> Result<()> {
+        let entry = self
+            .fragments
+            .get_mut(index as usize)
+            .ok_or_else(|| invalid_entry("fragment slot", 0, index as usize))?;
+        *entry = Some(fragment.to_vec());
+        Ok(())
+    }
+
+    pub fn fragment(&self, index: u8) -> Result<&[u8]> {
+        self.fragments
+            .get(index as usize)
+            .and_then(|entry| entry.as_deref())
+            .ok_or_else(|| invalid_entry("fragment", 0, index as usize))
+    }
+}
+
+/// Brushes used by PatBlt orders with a cached brush style
+/// Monochrome and color brushes have their own cache
+/// MS-RDPEGDI 2.2.2.2.1.2.7 Cache Brush (CACHE_BRUSH_ORDER)
+pub struct BrushCache {
+    monochrome: Vec<Option<CacheBrushOrder>>,
+    color: Vec<Option<CacheBrushOrder>>,
+}
+
+impl Default for BrushCache {
+    fn default() -> Self {
+        BrushCache {
+            monochrome: vec![None; BRUSH_CACHE_ENTRIES],
+            color: vec![None; BRUSH_CACHE_ENTRIES],
+        }
+    }
+}
+
+impl BrushCache {
+    pub fn insert(&mut self, order: CacheBrushOrder) -> Result<()> {
+        let cache = if order.bpp == 1 {
+            &mut self.monochrome
+        } else {
+            &mut self.color
+        };
+        let index = order.cache_index as usize;
+        let entry = cache
+            .get_mut(index)
+            .ok_or_else(|| invalid_entry("brush slot", (order.bpp != 1) as usize, index))?;
+        *entry = Some(order);
+        Ok(())
+    }
+
+    pub fn get(&self, monochrome: bool, index: u8) -> Result<&CacheBrushOrder> {
+        let cache = if monochrome {
+            &self.monochrome
+        } else {
+            &self.color
+        };
+        cache
+            .get(index as usize)
+            .and_then(|entry| entry.as_ref())
+            .ok_or_else(|| invalid_entry("brush", !monochrome as usize, index as usize))
+    }
+}
+
+/// All caches filled by secondary orders
+pub struct OrderCache {
+    pub bitmaps: BitmapCache,
+    pub glyphs: GlyphCache,
+    pub brushes: BrushCache,
+    /// Palettes sent by the server in 8 bpp sessions
+    /// Cached bitmaps are decoded with the palette of the session
+    pub color_tables: Vec<Option<Palette>>,
+}
+
+impl OrderCache {
+    pub fn new(bitmap_cache_entries: &[u32], glyph_cache: &GlyphCacheCapability) -> Self {
+        OrderCache {
+            bitmaps: BitmapCache::new(bitmap_cache_entries),
+            glyphs: GlyphCache::new(glyph_cache),
+            brushes: BrushCache::default(),
+            color_tables: vec![None; COLOR_TABLE_CACHE_ENTRIES],
+        }
+    }
+
+    /// Store the content of a secondary order
+    /// Unknown orders are ignored
+    pub fn insert(&mut self, order: SecondaryOrder, palette: &Palette) -> Result<()> {
+        match order {
+            SecondaryOrder::CacheBitmapV2(order) => self.bitmaps.insert(order, palette),
+            SecondaryOrder::CacheGlyph(order) => self.glyphs.insert(order),
+            SecondaryOrder::CacheBrush(order) => self.brushes.insert(order),
+            SecondaryOrder::CacheColorTable(order) => {
+                let index = order.cache_index as usize;
+                let entry = self
+                    .color_tables
+                    .get_mut(index)
+                    .ok_or_else(|| invalid_entry("color table slot", 0, index))?;
+                *entry = Some(order.palette);
+                Ok(())
+            }
+            SecondaryOrder::Unknown(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::capability::GlyphSupportLevel;
+
+    /// Bottom up bitmaps are stored top down,
+    /// bitmaps drawn once are not stored
+    #[test]
+    fn test_insert() {
+        let mut cache = BitmapCache::new(&[10, 0x8000_0010]);
+        let order = CacheBitmapV2Order {
+            cache_id: 1,
+            bpp: 32,
+            cache_index: 3,
+            width: 1,
+            height: 2,
+            data: vec![1, 0, 0, 0xFF, 2, 0, 0, 0xFF],
+            ..Default::default()
+        };
+        cache.insert(order.clone(), &Palette::default()).unwrap();
+        assert_eq!(
+            cache.get(1, 3).unwrap().pixels,
+            vec![0xFF000002, 0xFF000001]
+        );
+
+        let order = CacheBitmapV2Order {
+            cache_index: 4,
+            do_not_cache: true,
+            ..order
+        };
+        cache.insert(order, &Palette::default()).unwrap();
+        assert!(cache.get(1, 4).is_err());
+        assert!(cache.get(2, 0).is_err());
+    }
+
+    /// Glyphs and fragments are stored at their index
+    #[test]
+    fn test_glyph_cache() {
+        let capability = GlyphCacheCapability::new(GlyphSupportLevel::GlyphSupportFull);
+        let mut cache = OrderCache::new(&[], &capability);
+        let order = CacheGlyphOrder {
+            cache_id: 9,
+            glyphs: vec![Glyph {
+                cache_index: 63,
+                width: 1,
+                height: 1,
+                data: vec![0x80, 0, 0, 0],
+                ..Default::default()
+            }],
+        };
+        cache
+            .insert(
+                SecondaryOrder::CacheGlyph(order.clone()),
+                &Palette::default(),
+            )
+            .unwrap();
+        assert_eq!(cache.glyphs.get(9, 63).unwrap(), &order.glyphs[0]);
+        assert!(cache.glyphs.get(9, 62).is_err());
+
+        let mut order = order;
+        order.glyphs[0].cache_index = 64;
+        assert!(cache.glyphs.insert(order).is_err());
+
+        cache.glyphs.put_fragment(255, &[1, 2]).unwrap();
+        assert_eq!(cache.glyphs.fragment(255).unwrap(), &[1, 2]);
+        assert!(cache.glyphs.fragment(0).is_err());
+    }
+}
@@ -0,0 +1,1393 @@
+use crate::codec::color::{bgrx_to_rgba, rgb555_to_rgba, rgb565_to_rgba};
+use crate::core::event::BitmapEvent;
+use crate::core::order::altsec::{
+    AltSecondaryOrder, CreateOffscreenBitmapOrder, SCREEN_BITMAP_SURFACE,
+};
+use crate::core::order::base::Bounds;
+use crate::core::order::cache::{BitmapCache, BrushCache, GlyphCache, OrderCache};
+use crate::core::order::primary::{
+    Brush, BrushStyle, FastGlyphOrder, GlyphIndexOrder, LineToOrder, MemBltOrder, PatBltOrder,
+    PolylineOrder, PrimaryOrder, ScrBltOrder,
+};
+use crate::core::order::secondary::Glyph;
+use crate::core::update::Palette;
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+/// Opaque black
+const BLACK: u32 = 0xFF00_0000;
+
+/// Patterns of the hatched brushes, one byte per row from the top
+/// Cleared bits are the lines, drawn with the fore color
+const HATCHED_PATTERNS: [[u8; 8]; 6] = [
+    // HS_HORIZONTAL
+    [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00],
+    // HS_VERTICAL
+    [0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0xF7],
+    // HS_FDIAGONAL
+    [0xFE, 0xFD, 0xFB, 0xF7, 0xEF, 0xDF, 0xBF, 0x7F],
+    // HS_BDIAGONAL
+    [0x7F, 0xBF, 0xDF, 0xEF, 0xF7, 0xFB, 0xFD, 0xFE],
+    // HS_CROSS
+    [0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0x00],
+    // HS_DIAGCROSS
+    [0x7E, 0xBD, 0xDB, 0xE7, 0xE7, 0xDB, 0xBD, 0x7E],
+];
+
+/// Flag of the brush style, the hatch is an index of the brush cache
+/// MS-RDPEGDI 2.2.2.2.1.1.1.5 Brush (BRUSH)
+const CACHED_BRUSH: u8 = 0x80;
+
+/// Glyphs are placed vertically
+/// MS-RDPEGDI 2.2.2.2.1.1.2.13 GlyphIndex (GLYPHINDEX_ORDER)
+const SO_VERTICAL: u8 = 0x04;
+/// Glyphs advance by their own width
+const SO_CHAR_INC_EQUAL_BM_BASE: u8 = 0x20;
+
+/// Fragment operations in the glyph indices of text orders
+/// MS-RDPEGDI 2.2.2.2.1.1.2.13 GlyphIndex (GLYPHINDEX_ORDER)
+const GLYPH_FRAGMENT_USE: u8 = 0xFE;
+const GLYPH_FRAGMENT_ADD: u8 = 0xFF;
+
+/// Raster operation which copies the pattern
+/// where the source is set, the destination elsewhere
+const ROP_PATTERN_MASK: u8 = 0xE2;
+
+/// Cache id of MemBlt orders which copy an offscreen bitmap,
+/// the cache index is then the id of the bitmap
+const OFFSCREEN_CACHE_ID: u8 = 0xFF;
+
+/// Region of a surface in signed coordinates
+/// Right and bottom are exclusive
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Area {
+    pub(crate) left: i32,
+    pub(crate) top: i32,
+    pub(crate) right: i32,
+    pub(crate) bottom: i32,
+}
+
+impl Area {
+    pub(crate) fn new(left: i16, top: i16, width: i16, height: i16) -> Self {
+        Area {
+            left: left as i32,
+            top: top as i32,
+            right: left as i32 + width as i32,
+            bottom: top as i32 + height as i32,
+        }
+    }
+
+    /// Area of inclusive bounds
+    pub(crate) fn inclusive(left: i16, top: i16, right: i16, bottom: i16) -> Self {
+        Area {
+            left: left as i32,
+            top: top as i32,
+            right: right as i32 + 1,
+            bottom: bottom as i32 + 1,
+        }
+    }
+
+    pub(crate) fn intersect(&self, other: &Area) -> Area {
+        Area {
+            left: self.left.max(other.left),
+            top: self.top.max(other.top),
+            right: self.right.min(other.right),
+            bottom: self.bottom.min(other.bottom),
+        }
+    }
+
+    /// Smallest area which contains both
+    pub(crate) fn union(&self, other: &Area) -> Area {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        Area {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.right <= self.left || self.bottom <= self.top
+    }
+}
+
+/// Ternary raster operation on packed pixels
+/// Bit i of the operation is the result for the pattern,
+/// source and destination bits given by the bits of i
+/// MS-RDPEGDI 2.2.2.2.1.1.1.7 Ternary Raster Operation Index (ROP3_OPERATION_INDEX)
+///
+/// # Example
+/// ```
+/// use rdp::core::order::gdi::rop3;
+/// // PATINVERT
+/// assert_eq!(rop3(0x5A, 0xFF00FF, 0, 0x00FFFF), 0xFFFFFF00);
+/// ```
+pub fn rop3(rop: u8, pattern: u32, source: u32, destination: u32) -> u32 {
+    let result = match rop {
+        0x00 => 0,
+        0xAA => destination,
+        0xCC => source,
+        0xF0 => pattern,
+        0xFF => !0,
+        _ => (0..8).filter(|i| rop & (1 << i) != 0).fold(0, |result, i| {
+            let pattern = if i & 4 != 0 { pattern } else { !pattern };
+            let source = if i & 2 != 0 { source } else { !source };
+            let destination = if i & 1 != 0 {
+                destination
+            } else {
+                !destination
+            };
+            result | (pattern & source & destination)
+        }),
+    };
+    result | BLACK
+}
+
+/// Ternary operation of a binary raster operation,
+/// the pen takes the place of the pattern
+/// MS-RDPEGDI 2.2.2.2.1.1.1.6 Binary Raster Operation (ROP2_OPERATION)
+fn rop2_to_rop3(rop2: u8) -> u8 {
+    let table = rop2.wrapping_sub(1) & 0x0F;
+    (0..8).fold(0, |rop, i| {
+        let index = ((i >> 2) & 1) * 2 + (i & 1);
+        rop | (((table >> index) & 1) << i)
+    })
+}
+
+/// Decode any bitmap update into an uncompressed 32 bpp bitmap
+/// Rows are bottom up as all uncompressed bitmaps,
+/// 8 bpp bitmaps must go through the palette first
+pub fn decode_bitmap(bitmap: BitmapEvent) -> Result<BitmapEvent> {
+    let (width, height) = (bitmap.width as usize, bitmap.height as usize);
+    if !bitmap.is_compress && bitmap.bpp == 32 {
+        if bitmap.data.len() < width * height * 4 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "ORDER: bitmap data is too short",
+            ));
+        }
+        return Ok(bitmap);
+    }
+    let event = BitmapEvent {
+        bpp: 32,
+        is_compress: false,
+        data: Vec::new(),
+        ..bitmap
+    };
+    let data = bitmap.decompress().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("ORDER: invalid bitmap {:?}", e),
+        )
+    })?;
+    if data.len() < width * height * 4 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "ORDER: bitmap data is too short",
+        ));
+    }
+    if width == 0 {
+        return Ok(event);
+    }
+    let mut result = vec![0; width * height * 4];
+    for (rgba, bgrx) in data[..width * height * 4]
+        .chunks_exact(width * 4)
+        .rev()
+        .zip(result.chunks_exact_mut(width * 4))
+    {
+        // Swapping red and blue again gives the order of uncompressed bitmaps
+        bgrx_to_rgba(rgba, bgrx);
+    }
+    Ok(BitmapEvent {
+        data: result,
+        ..event
+    })
+}
+
+/// Pixels drawn by the GDI
+/// Pixels are 0xAARRGGBB, from the top left corner
+#[derive(Clone, Debug)]
+pub struct Surface {
+    width: u16,
+    height: u16,
+    pixels: Vec<u32>,
+}
+
+impl Surface {
+    /// Black surface
+    pub fn new(width: u16, height: u16) -> Self {
+        Surface {
+            width,
+            height,
+            pixels: vec![BLACK; width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Color of a pixel
+    pub fn pixel(&self, x: u16, y: u16) -> Option<u32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.pixels[y as usize * self.width as usize + x as usize])
+    }
+
+    pub(crate) fn area(&self) -> Area {
+        Area {
+            left: 0,
+            top: 0,
+            right: self.width as i32,
+            bottom: self.height as i32,
+        }
+    }
+
+    /// Apply a raster operation on an area
+    /// Pattern and source are given for each destination pixel
+    pub(crate) fn blt(
+        &mut self,
+        area: Area,
+        rop: u8,
+        pattern: &dyn Fn(i32, i32) -> u32,
+        source: &dyn Fn(i32, i32) -> u32,
+    ) -> Area {
+        let area = area.intersect(&self.area());
+        if area.is_empty() {
+            return area;
+        }
+        for y in area.top..area.bottom {
+            let row = y as usize * self.width as usize;
+            for x in area.left..area.right {
+                let destination = &mut self.pixels[row + x as usize];
+                *destination = rop3(rop, pattern(x, y), source(x, y), *destination);
+            }
+        }
+        area
+    }
+
+    /// Copy pixels on an area, their alpha is kept
+    pub(crate) fn copy(&mut self, area: Area, source: &dyn Fn(i32, i32) -> u32) -> Area {
+        let area = area.intersect(&self.area());
+        for y in area.top..area.bottom {
+            let row = y as usize * self.width as usize;
+            for x in area.left..area.right {
+                self.pixels[row + x as usize] = source(x, y);
+            }
+        }
+        area
+    }
+
+    /// Copy of an area, pixels outside of the surface are black
+    pub(crate) fn copy_area(&self, area: Area) -> Vec<u32> {
+        let mut pixels = Vec::with_capacity(
+            (area.right - area.left).max(0) as usize * (area.bottom - area.top).max(0) as usize,
+        );
+        for y in area.top..area.bottom {
+            for x in area.left..area.right {
+                let inside =
+                    (0..self.width as i32).contains(&x) && (0..self.height as i32).contains(&y);
+                pixels.push(if inside {
+                    self.pixels[y as usize * self.width as usize + x as usize]
+                } else {
+                    BLACK
+                });
+            }
+        }
+        pixels
+    }
+
+    /// Copy an uncompressed 32 bpp bitmap, clipped to the surface
+    pub fn apply_bitmap(&mut self, bitmap: &BitmapEvent) {
+        let bitmap_width = bitmap.width as usize;
+        let bitmap_height = bitmap.height as usize;
+        if bitmap.is_compress
+            || bitmap.bpp != 32
+            || bitmap.data.len() < bitmap_width * bitmap_height * 4
+        {
+            return;
+        }
+        let area = Area::inclusive(
+            bitmap.dest_left as i16,
+            bitmap.dest_top as i16,
+            bitmap.dest_right as i16,
+            bitmap.dest_bottom as i16,
+        )
+        .intersect(&Area {
+            left: bitmap.dest_left as i32,
+            top: bitmap.dest_top as i32,
+            right: bitmap.dest_left as i32 + bitmap_width as i32,
+            bottom: bitmap.dest_top as i32 + bitmap_height as i32,
+        });
+        let source = |x: i32, y: i32| {
+            // Rows are bottom up
+            let row = bitmap_height - 1 - (y - bitmap.dest_top as i32) as usize;
+            let offset = (row * bitmap_width + (x - bitmap.dest_left as i32) as usize) * 4;
+            u32::from_le_bytes([
+                bitmap.data[offset],
+                bitmap.data[offset + 1],
+                bitmap.data[offset + 2],
+                bitmap.data[offset + 3],
+            ])
+        };
+        self.blt(area, 0xCC, &|_, _| 0, &source);
+    }
+
+    /// An area of the surface as an uncompressed 32 bpp bitmap
+    pub(crate) fn bitmap(&self, area: Area) -> BitmapEvent {
+        let width = (area.right - area.left) as usize;
+        let height = (area.bottom - area.top) as usize;
+        let mut data = Vec::with_capacity(width * height * 4);
+        for y in (area.top..area.bottom).rev() {
+            let offset = y as usize * self.width as usize + area.left as usize;
+            for pixel in &self.pixels[offset..offset + width] {
+                data.extend_from_slice(&pixel.to_le_bytes());
+            }
+        }
+        BitmapEvent {
+            dest_left: area.left as u16,
+            dest_top: area.top as u16,
+            dest_right: (area.right - 1) as u16,
+            dest_bottom: (area.bottom - 1) as u16,
+            width: width as u16,
+            height: height as u16,
+            bpp: 32,
+            is_compress: false,
+            data,
+        }
+    }
+}
+
+/// Position and settings of the glyphs of a text order
+struct GlyphRun {
+    cache_id: u8,
+    accel_flags: u8,
+    char_inc: u8,
+    x: i32,
+    y: i32,
+    color: u32,
+    clip: Area,
+}
+
+impl GlyphRun {
+    /// Glyphs give their offset from the previous glyph
+    fn variable_pitch(&self) -> bool {
+        self.char_inc == 0 && self.accel_flags & SO_CHAR_INC_EQUAL_BM_BASE == 0
+    }
+
+    fn advance(&mut self, offset: i32) {
+        if self.accel_flags & SO_VERTICAL != 0 {
+            self.y += offset;
+        } else {
+            self.x += offset;
+        }
+    }
+}
+
+fn invalid_offscreen(id: u16) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("ORDER: no offscreen bitmap {}", id),
+    )
+}
+
+/// Copy the pixels of a bitmap with a MemBlt order
+/// Source is given as pixels, width and height
+fn copy_pixels(
+    surface: &mut Surface,
+    order: &MemBltOrder,
+    clip: Area,
+    (pixels, width, height): (&[u32], usize, usize),
+) -> Area {
+    let (offset_x, offset_y) = (
+        order.source_x as i32 - order.left as i32,
+        order.source_y as i32 - order.top as i32,
+    );
+    // Source must stay inside the bitmap
+    let area = Area::new(order.left, order.top, order.width, order.height)
+        .intersect(&clip)
+        .intersect(&Area {
+            left: -offset_x,
+            top: -offset_y,
+            right: width as i32 - offset_x,
+            bottom: height as i32 - offset_y,
+        });
+    surface.blt(area, order.rop, &|_, _| 0, &|x, y| {
+        pixels[(y + offset_y) as usize * width + (x + offset_x) as usize]
+    })
+}
+
+/// Software GDI
+/// Primary orders are drawn on a copy of the desktop,
+/// the regions they change are given back as bitmaps
+///
+/// Bitmap updates must be applied too, as orders
+/// may read the content of the desktop
+///
+/// Orders may also be drawn on offscreen bitmaps,
+/// nothing is given back until the server switches to the screen
+///
+/// # Example
+/// ```
+/// use rdp::core::capability::GlyphCacheCapability;
+/// use rdp::core::order::cache::OrderCache;
+/// use rdp::core::order::gdi::Gdi;
+/// use rdp::core::order::primary::{OpaqueRectOrder, PrimaryOrder};
+/// use rdp::core::update::Palette;
+/// let mut gdi = Gdi::new(16, 16, 24);
+/// let mut cache = OrderCache::new(&[], &GlyphCacheCapability::default());
+/// let order = PrimaryOrder::OpaqueRect(OpaqueRectOrder {
+///     left: 2,
+///     top: 2,
+///     width: 4,
+///     height: 4,
+///     color: 0x0000FF,
+/// });
+/// let bitmap = gdi
+///     .draw(&order, None, &Palette::default(), &mut cache)
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!((bitmap.width, bitmap.height), (4, 4));
+/// assert_eq!(gdi.surface().pixel(2, 2), Some(0xFF0000FF));
+/// ```
+pub struct Gdi {
+    /// Surface the orders are drawn on
+    surface: Surface,
+    surface_id: u16,
+    /// Offscreen bitmaps, and the screen while it isn't drawn on
+    offscreen: HashMap<u16, Surface>,
+    /// Color depth of the session
+    /// Colors of the orders are encoded with it
+    bpp: u16,
+}
+
+impl Gdi {
+    pub fn new(width: u16, height: u16, bpp: u16) -> Self {
+        Gdi {
+            surface: Surface::new(width, height),
+            surface_id: SCREEN_BITMAP_SURFACE,
+            offscreen: HashMap::new(),
+            bpp,
+        }
+    }
+
+    /// Copy of the desktop
+    pub fn surface(&self) -> &Surface {
+        self.offscreen
+            .get(&SCREEN_BITMAP_SURFACE)
+            .unwrap_or(&self.surface)
+    }
+
+    /// Keep the surface in sync with bitmap updates
+    /// Bitmaps must be decoded first
+    pub fn apply_bitmap(&mut self, bitmap: &BitmapEvent) {
+        self.offscreen
+            .get_mut(&SCREEN_BITMAP_SURFACE)
+            .unwrap_or(&mut self.surface)
+            .apply_bitmap(bitmap)
+    }
+
+    /// Create and switch offscreen bitmaps
+    /// Frame markers and windowing orders don't change the surfaces
+    pub fn apply_alt_secondary(&mut self, order: &AltSecondaryOrder) -> Result<()> {
+        match order {
+            AltSecondaryOrder::SwitchSurface(id) => self.switch_surface(*id),
+            AltSecondaryOrder::CreateOffscreenBitmap(order) => {
+                self.create_offscreen(order);
+                Ok(())
+            }
+            AltSecondaryOrder::FrameMarker(_) | AltSecondaryOrder::Window(_) => Ok(()),
+        }
+    }
+
+    fn switch_surface(&mut self, id: u16) -> Result<()> {
+        if id == self.surface_id {
+            return Ok(());
+        }
+        let surface = self
+            .offscreen
+            .remove(&id)
+            .ok_or_else(|| invalid_offscreen(id))?;
+        let previous = std::mem::replace(&mut self.surface, surface);
+        self.offscreen.insert(self.surface_id, previous);
+        self.surface_id = id;
+        Ok(())
+    }
+
+    /// A new bitmap replaces the one with the same id
+    fn create_offscreen(&mut self, order: &CreateOffscreenBitmapOrder) {
+        for id in &order.delete_list {
+            if *id != SCREEN_BITMAP_SURFACE && *id != self.surface_id {
+                self.offscreen.remove(id);
+            }
+        }
+        let surface = Surface::new(order.width, order.height);
+        if order.id == self.surface_id {
+            self.surface = surface;
+        } else {
+            self.offscreen.insert(order.id, surface);
+        }
+    }
+
+    /// Pixel of a color sent in an order
+    /// MS-RDPEGDI 2.2.2.2.1.1.1.8 Color (TS_COLOR)
+    pub fn color(&self, color: u32, palette: &Palette) -> u32 {
+        let [first, second, _, _] = color.to_le_bytes();
+        let mut rgba = [0; 4];
+        match self.bpp {
+            8 => {
+                let [red, green, blue] = palette
+                    .colors
+                    .get(first as usize)
+                    .copied()
+                    .unwrap_or_default();
+                rgba = [red, green, blue, 0xFF];
+            }
+            15 => rgb555_to_rgba(&[first, second], &mut rgba),
+            16 => rgb565_to_rgba(&[first, second], &mut rgba),
+            // Blue comes first
+            _ => return (color & 0x00FF_FFFF) | BLACK,
+        }
+        u32::from_le_bytes([rgba[2], rgba[1], rgba[0], 0xFF])
+    }
+
+    /// Pattern of a brush, one pixel per position of the 8x8 pattern
+    /// None for the null brush
+    fn brush_pattern(
+        &self,
+        brush: &Brush,
+        back: u32,
+        fore: u32,
+        palette: &Palette,
+        brushes: &BrushCache,
+    ) -> Result<Option<[u32; 64]>> {
+        let rows = match brush.style {
+            style if style & CACHED_BRUSH != 0 => {
+                // Monochrome brushes have the 1 bpp format
+                let cached = brushes.get(style & 0x07 == 1, brush.hatch)?;
+                if cached.bpp == 1 {
+                    cached.data.get(..8).and_then(|rows| rows.try_into().ok())
+                } else {
+                    let pixel_size = (cached.bpp as usize + 1) / 8;
+                    let mut pattern = [BLACK; 64];
+                    for (pixel, bytes) in
+                        pattern.iter_mut().zip(cached.data.chunks_exact(pixel_size))
+                    {
+                        let mut color = [0; 4];
+                        color[..pixel_size].copy_from_slice(bytes);
+                        *pixel = self.color(u32::from_le_bytes(color), palette);
+                    }
+                    return Ok(Some(pattern));
+                }
+            }
+            style if style == BrushStyle::BsNull as u8 => return Ok(None),
+            style if style == BrushStyle::BsHatched as u8 => {
+                HATCHED_PATTERNS.get(brush.hatch as usize).copied()
+            }
+            style if style == BrushStyle::BsPattern as u8 => {
+                // The hatch is the bottom row
+                let mut rows = [brush.hatch; 8];
+                for (row, extra) in rows.iter_mut().zip(brush.extra.iter().rev()) {
+                    *row = *extra;
+                }
+                Some(rows)
+            }
+            _ => None,
+        };
+        let mut pattern = [fore; 64];
+        if let Some(rows) = rows {
+            for (index, pixel) in pattern.iter_mut().enumerate() {
+                if rows[index / 8] & (0x80 >> (index % 8)) != 0 {
+                    *pixel = back;
+                }
+            }
+        }
+        Ok(Some(pattern))
+    }
+
+    /// Area of the surface drawn by an order
+    fn clip(&self, bounds: Option<Bounds>) -> Area {
+        let area = self.surface.area();
+        match bounds {
+            Some(bounds) => area.intersect(&Area::inclusive(
+                bounds.left,
+                bounds.top,
+                bounds.right,
+                bounds.bottom,
+            )),
+            None => area,
+        }
+    }
+
+    fn pat_blt(
+        &mut self,
+        order: &PatBltOrder,
+        clip: Area,
+        palette: &Palette,
+        brushes: &BrushCache,
+    ) -> Result<Area> {
+        let back = self.color(order.back_color, palette);
+        let fore = self.color(order.fore_color, palette);
+        let pattern = match self.brush_pattern(&order.brush, back, fore, palette, brushes)? {
+            Some(pattern) => pattern,
+            None => return Ok(Area::default()),
+        };
+        let (origin_x, origin_y) = (order.brush.x as i32, order.brush.y as i32);
+        let area = Area::new(order.left, order.top, order.width, order.height).intersect(&clip);
+        Ok(self.surface.blt(
+            area,
+            order.rop,
+            &|x, y| {
+                let index = (y - origin_y).rem_euclid(8) * 8 + (x - origin_x).rem_euclid(8);
+                pattern[index as usize]
+            },
+            &|_, _| 0,
+        ))
+    }
+
+    fn scr_blt(&mut self, order: &ScrBltOrder, clip: Area) -> Area {
+        let area = Area::new(order.left, order.top, order.width, order.height).intersect(&clip);
+        if area.is_empty() {
+            return area;
+        }
+        // Source and destination may overlap
+        let (offset_x, offset_y) = (
+            order.source_x as i32 - order.left as i32,
+            order.source_y as i32 - order.top as i32,
+        );
+        let source = self.surface.copy_area(Area {
+            left: area.left + offset_x,
+            top: area.top + offset_y,
+            right: area.right + offset_x,
+            bottom: area.bottom + offset_y,
+        });
+        let width = area.right - area.left;
+        self.surface.blt(area, order.rop, &|_, _| 0, &|x, y| {
+            source[((y - area.top) * width + x - area.left) as usize]
+        })
+    }
+
+    fn mem_blt(&mut self, order: &MemBltOrder, clip: Area, cache: &BitmapCache) -> Result<Area> {
+        if order.cache_id != OFFSCREEN_CACHE_ID {
+            let bitmap = cache.get(order.cache_id, order.cache_index)?;
+            return Ok(copy_pixels(
+                &mut self.surface,
+                order,
+                clip,
+                (&bitmap.pixels, bitmap.width, bitmap.height),
+            ));
+        }
+        if order.cache_index == self.surface_id {
+            // Copy inside the same surface, the source may overlap
+            let source = self.surface.clone();
+            return Ok(copy_pixels(
+                &mut self.surface,
+                order,
+                clip,
+                (
+                    &source.pixels,
+                    source.width as usize,
+                    source.height as usize,
+                ),
+            ));
+        }
+        let source = self
+            .offscreen
+            .get(&order.cache_index)
+            .ok_or_else(|| invalid_offscreen(order.cache_index))?;
+        Ok(copy_pixels(
+            &mut self.surface,
+            order,
+            clip,
+            (
+                &source.pixels,
+                source.width as usize,
+                source.height as usize,
+            ),
+        ))
+    }
+
+    /// Bresenham line, the end point is not drawn
+    fn line(&mut self, start: (i32, i32), end: (i32, i32), rop2: u8, pen: u32, clip: Area) -> Area {
+        let rop = rop2_to_rop3(rop2);
+        let (mut x, mut y) = start;
+        let (delta_x, delta_y) = ((end.0 - x).abs(), -(end.1 - y).abs());
+        let (step_x, step_y) = ((end.0 - x).signum(), (end.1 - y).signum());
+        let mut error = delta_x + delta_y;
+        let mut drawn = Area::default();
+        while (x, y) != end {
+            let pixel = Area {
+                left: x,
+                top: y,
+                right: x + 1,
+                bottom: y + 1,
+            };
+            drawn = drawn.union(&self.surface.blt(
+                pixel.intersect(&clip),
+                rop,
+                &|_, _| pen,
+                &|_, _| 0,
+            ));
+            if 2 * error >= delta_y {
+                error += delta_y;
+                x += step_x;
+            }
+            if 2 * error <= delta_x {
+                error += delta_x;
+                y += step_y;
+            }
+        }
+        drawn
+    }
+
+    fn line_to(&mut self, order: &LineToOrder, clip: Area, palette: &Palette) -> Area {
+        let pen = self.color(order.pen_color, palette);
+        self.line(
+            (order.start_x as i32, order.start_y as i32),
+            (order.end_x as i32, order.end_y as i32),
+            order.rop2,
+            pen,
+            clip,
+        )
+    }
+
+    fn polyline(&mut self, order: &PolylineOrder, clip: Area, palette: &Palette) -> Area {
+        let pen = self.color(order.pen_color, palette);
+        let mut start = (order.start_x as i32, order.start_y as i32);
+        let mut drawn = Area::default();
+        for (delta_x, delta_y) in &order.deltas {
+            let end = (start.0 + *delta_x as i32, start.1 + *delta_y as i32);
+            drawn = drawn.union(&self.line(start, end, order.rop2, pen, clip));
+            start = end;
+        }
+        drawn
+    }
+
+    /// Fill the opaque rectangle of a text order
+    fn opaque_rect(&mut self, opaque: Area, color: u32, clip: Area) -> Area {
+        self.surface
+            .blt(opaque.intersect(&clip), 0xF0, &|_, _| color, &|_, _| 0)
+    }
+
+    /// Draw the set pixels of a glyph
+    fn draw_glyph(&mut self, glyph: &Glyph, run: &GlyphRun) -> Area {
+        let (left, top) = (run.x + glyph.x as i32, run.y + glyph.y as i32);
+        let area = Area {
+            left,
+            top,
+            right: left + glyph.width as i32,
+            bottom: top + glyph.height as i32,
+        };
+        let color = run.color;
+        self.surface.blt(
+            area.intersect(&run.clip),
+            ROP_PATTERN_MASK,
+            &|_, _| color,
+            &|x, y| {
+                if glyph.is_set((x - left) as u16, (y - top) as u16) {
+                    !0
+                } else {
+                    0
+                }
+            },
+        )
+    }
+
+    /// Draw glyph indices of the glyph cache
+    /// Each index of a variable pitch font is followed
+    /// by the offset from the previous glyph
+    fn draw_glyphs(
+        &mut self,
+        run: &mut GlyphRun,
+        data: &[u8],
+        glyphs: &GlyphCache,
+    ) -> Result<Area> {
+        let mut drawn = Area::default();
+        let mut index = 0;
+        while index < data.len() {
+            let glyph = glyphs.get(run.cache_id, data[index])?;
+            index += 1;
+            if run.variable_pitch() {
+                let offset = match data.get(index) {
+                    Some(0x80) if index + 3 <= data.len() => {
+                        i16::from_le_bytes([data[index + 1], data[index + 2]]) as i32
+                    }
+                    Some(0x80) => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            "ORDER: truncated glyph offset",
+                        ))
+                    }
+                    Some(offset) => *offset as i32,
+                    None => 0,
+                };
+                index += if data.get(index) == Some(&0x80) { 3 } else { 1 };
+                run.advance(offset);
+            }
+            drawn = drawn.union(&self.draw_glyph(glyph, run));
+            if run.char_inc != 0 {
+                run.advance(run.char_inc as i32);
+            } else if run.accel_flags & SO_CHAR_INC_EQUAL_BM_BASE != 0 {
+                run.advance(glyph.width as i32);
+            }
+        }
+        Ok(drawn)
+    }
+
+    /// Draw the glyph indices of a text order,
+    /// fragments of indices are stored and replayed from the fragment cache
+    fn draw_text(
+        &mut self,
+        run: &mut GlyphRun,
+        data: &[u8],
+        glyphs: &mut GlyphCache,
+    ) -> Result<Area> {
+        let mut drawn = Area::default();
+        let (mut start, mut index) = (0, 0);
+        while index < data.len() {
+            match data[index] {
+                GLYPH_FRAGMENT_ADD => {
+                    let (id, size) = match data.get(index + 1..index + 3) {
+                        Some(&[id, size]) if size as usize <= index - start => (id, size as usize),
+                        _ => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidData,
+                                "ORDER: invalid glyph fragment",
+                            ))
+                        }
+                    };
+                    drawn = drawn.union(&self.draw_glyphs(run, &data[start..index], glyphs)?);
+                    glyphs.put_fragment(id, &data[index - size..index])?;
+                    index += 3;
+                    start = index;
+                }
+                GLYPH_FRAGMENT_USE => {
+                    drawn = drawn.union(&self.draw_glyphs(run, &data[start..index], glyphs)?);
+                    let id = *data.get(index + 1).ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidData, "ORDER: invalid glyph fragment")
+                    })?;
+                    index += 2;
+                    if run.variable_pitch() {
+                        if let Some(offset) = data.get(index) {
+                            run.advance(*offset as i32);
+                            index += 1;
+                        }
+                    }
+                    let fragment = glyphs.fragment(id)?.to_vec();
+                    drawn = drawn.union(&self.draw_glyphs(run, &fragment, glyphs)?);
+                    start = index;
+                }
+                _ => {
+                    index += 1;
+                    if run.variable_pitch() {
+                        index += if data.get(index) == Some(&0x80) { 3 } else { 1 };
+                    }
+                }
+            }
+        }
+        let end = index.min(data.len());
+        drawn = drawn.union(&self.draw_glyphs(run, &data[start..end], glyphs)?);
+        Ok(drawn)
+    }
+
+    /// Text is clipped to the background rectangle
+    fn text_clip(clip: Area, left: i16, top: i16, right: i16, bottom: i16) -> Area {
+        if right > left {
+            clip.intersect(&Area::inclusive(left, top, right, bottom))
+        } else {
+            clip
+        }
+    }
+
+    fn glyph_index(
+        &mut self,
+        order: &GlyphIndexOrder,
+        clip: Area,
+        palette: &Palette,
+        glyphs: &mut GlyphCache,
+    ) -> Result<Area> {
+        let opaque = if order.op_redundant != 0 {
+            Area::inclusive(order.bk_left, order.bk_top, order.bk_right, order.bk_bottom)
+        } else if order.op_right > order.op_left {
+            Area::inclusive(order.op_left, order.op_top, order.op_right, order.op_bottom)
+        } else {
+            Area::default()
+        };
+        let fore = self.color(order.fore_color, palette);
+        let drawn = self.opaque_rect(opaque, fore, clip);
+
+        let mut run = GlyphRun {
+            cache_id: order.cache_id,
+            accel_flags: order.accel_flags,
+            char_inc: order.char_inc,
+            x: order.x as i32,
+            y: order.y as i32,
+            color: self.color(order.back_color, palette),
+            clip: Self::text_clip(
+                clip,
+                order.bk_left,
+                order.bk_top,
+                order.bk_right,
+                order.bk_bottom,
+            ),
+        };
+        Ok(drawn.union(&self.draw_text(&mut run, &order.data, glyphs)?))
+    }
+
+    fn fast_glyph(
+        &mut self,
+        order: &FastGlyphOrder,
+        clip: Area,
+        palette: &Palette,
+        glyphs: &mut GlyphCache,
+    ) -> Result<Area> {
+        if let Some(glyph) = &order.glyph {
+            glyphs.put(order.cache_id, glyph.clone())?;
+        }
+
+        let (mut left, mut top, mut right, mut bottom) =
+            (order.op_left, order.op_top, order.op_right, order.op_bottom);
+        // Sides flagged in the top are those of the background
+        if bottom == i16::MIN {
+            let flags = top & 0x0F;
+            bottom = if flags & 0x01 != 0 {
+                order.bk_bottom
+            } else {
+                bottom
+            };
+            right = if flags & 0x02 != 0 {
+                order.bk_right
+            } else {
+                right
+            };
+            top = if flags & 0x04 != 0 { order.bk_top } else { top };
+            left = if flags & 0x08 != 0 {
+                order.bk_left
+            } else {
+                left
+            };
+        }
+        let opaque = if right > left {
+            Area::inclusive(left, top, right, bottom)
+        } else {
+            Area::default()
+        };
+        let fore = self.color(order.fore_color, palette);
+        let drawn = self.opaque_rect(opaque, fore, clip);
+
+        let mut run = GlyphRun {
+            cache_id: order.cache_id,
+            accel_flags: order.accel_flags,
+            char_inc: order.char_inc,
+            x: order.x as i32,
+            y: order.y as i32,
+            color: self.color(order.back_color, palette),
+            clip: Self::text_clip(
+                clip,
+                order.bk_left,
+                order.bk_top,
+                order.bk_right,
+                order.bk_bottom,
+            ),
+        };
+        // A single glyph without offset
+        let data = [order.cache_index, 0];
+        Ok(drawn.union(&self.draw_glyphs(&mut run, &data, glyphs)?))
+    }
+
+    /// Draw a primary order
+    /// The region changed by the order is returned as a bitmap,
+    /// None when nothing is drawn on the screen
+    pub fn draw(
+        &mut self,
+        order: &PrimaryOrder,
+        bounds: Option<Bounds>,
+        palette: &Palette,
+        cache: &mut OrderCache,
+    ) -> Result<Option<BitmapEvent>> {
+        let clip = self.clip(bounds);
+        let area = match order {
+            PrimaryOrder::DstBlt(order) => {
+                let area = Area::new(order.left, order.top, order.width, order.height);
+                self.surface
+                    .blt(area.intersect(&clip), order.rop, &|_, _| 0, &|_, _| 0)
+            }
+            PrimaryOrder::PatBlt(order) => self.pat_blt(order, clip, palette, &cache.brushes)?,
+            PrimaryOrder::ScrBlt(order) => self.scr_blt(order, clip),
+            PrimaryOrder::OpaqueRect(order) => {
+                let area = Area::new(order.left, order.top, order.width, order.height);
+                let color = self.color(order.color, palette);
+                self.surface
+                    .blt(area.intersect(&clip), 0xF0, &|_, _| color, &|_, _| 0)
+            }
+            PrimaryOrder::MemBlt(order) => self.mem_blt(order, clip, &cache.bitmaps)?,
+            PrimaryOrder::LineTo(order) => self.line_to(order, clip, palette),
+            PrimaryOrder::Polyline(order) => self.polyline(order, clip, palette),
+            PrimaryOrder::GlyphIndex(order) => {
+                self.glyph_index(order, clip, palette, &mut cache.glyphs)?
+            }
+            PrimaryOrder::FastGlyph(order) => {
+                self.fast_glyph(order, clip, palette, &mut cache.glyphs)?
+            }
+        };
+        Ok(
+            if area.is_empty() || self.surface_id != SCREEN_BITMAP_SURFACE {
+                None
+            } else {
+                Some(self.surface.bitmap(area))
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::capability::{GlyphCacheCapability, GlyphSupportLevel};
+    use crate::core::order::primary::OpaqueRectOrder;
+    use crate::core::order::secondary::{CacheBitmapV2Order, CacheBrushOrder};
+
+    fn empty_cache() -> OrderCache {
+        OrderCache::new(
+            &[],
+            &GlyphCacheCapability::new(GlyphSupportLevel::GlyphSupportFull),
+        )
+    }
+
+    /// Binary operations are expanded with the pen as pattern
+    #[test]
+    fn test_rop2_to_rop3() {
+        // R2_BLACK, R2_NOT, R2_COPYPEN, R2_XORPEN, R2_WHITE
+        assert_eq!(rop2_to_rop3(1), 0x00);
+        assert_eq!(rop2_to_rop3(6), 0x55);
+        assert_eq!(rop2_to_rop3(13), 0xF0);
+        assert_eq!(rop2_to_rop3(7), 0x5A);
+        assert_eq!(rop2_to_rop3(16), 0xFF);
+        // DSTINVERT through the generic path
+        assert_eq!(rop3(0x55, 0, 0, 0x00123456), 0xFFEDCBA9);
+    }
+
+    /// 2x2 bitmap with a different blue value on each pixel
+    fn cache_with_bitmap() -> OrderCache {
+        let mut cache = OrderCache::new(&[10, 0x8000_0010], &GlyphCacheCapability::default());
+        let order = CacheBitmapV2Order {
+            cache_id: 1,
+            bpp: 32,
+            cache_index: 3,
+            width: 2,
+            height: 2,
+            // Bottom up rows
+            data: vec![3, 0, 0, 0xFF, 4, 0, 0, 0xFF, 1, 0, 0, 0xFF, 2, 0, 0, 0xFF],
+            ..Default::default()
+        };
+        cache.bitmaps.insert(order, &Palette::default()).unwrap();
+        cache
+    }
+
+    /// A copy of the whole bitmap keeps it unchanged
+    #[test]
+    fn test_mem_blt_copy() {
+        let mut cache = cache_with_bitmap();
+        let mut gdi = Gdi::new(32, 32, 32);
+        let order = MemBltOrder {
+            cache_id: 1,
+            cache_index: 3,
+            left: 10,
+            top: 20,
+            width: 2,
+            height: 2,
+            rop: 0xCC,
+            ..Default::default()
+        };
+        let palette = Palette::default();
+        let bitmap = gdi
+            .draw(&PrimaryOrder::MemBlt(order), None, &palette, &mut cache)
+            .unwrap()
+            .unwrap();
+        assert_eq!((bitmap.dest_left, bitmap.dest_top), (10, 20));
+        assert_eq!((bitmap.dest_right, bitmap.dest_bottom), (11, 21));
+        assert_eq!(
+            bitmap.data,
+            vec![3, 0, 0, 0xFF, 4, 0, 0, 0xFF, 1, 0, 0, 0xFF, 2, 0, 0, 0xFF]
+        );
+
+        let order = MemBltOrder {
+            cache_index: 4,
+            ..order
+        };
+        assert!(gdi
+            .draw(&PrimaryOrder::MemBlt(order), None, &palette, &mut cache)
+            .is_err());
+    }
+
+    /// Bounds and the source offset clip the destination
+    #[test]
+    fn test_mem_blt_clipped() {
+        let mut cache = cache_with_bitmap();
+        let mut gdi = Gdi::new(32, 32, 32);
+        let order = PrimaryOrder::MemBlt(MemBltOrder {
+            cache_id: 1,
+            cache_index: 3,
+            left: 10,
+            top: 20,
+            width: 8,
+            height: 8,
+            rop: 0x33,
+            source_x: 1,
+            source_y: 0,
+            ..Default::default()
+        });
+        let bounds = Bounds {
+            left: 0,
+            top: 0,
+            right: 100,
+            bottom: 20,
+        };
+        let palette = Palette::default();
+        let bitmap = gdi
+            .draw(&order, Some(bounds), &palette, &mut cache)
+            .unwrap()
+            .unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (1, 1));
+        assert_eq!(bitmap.data, vec![!2, 0xFF, 0xFF, 0xFF]);
+
+        let bounds = Bounds { right: 5, ..bounds };
+        assert!(gdi
+            .draw(&order, Some(bounds), &palette, &mut cache)
+            .unwrap()
+            .is_none());
+    }
+
+    /// Overlapping screen copy, moved one pixel right
+    #[test]
+    fn test_scr_blt_overlap() {
+        let mut gdi = Gdi::new(4, 1, 16);
+        let mut cache = empty_cache();
+        let palette = Palette::default();
+        // Pure red in RGB565
+        let red = PrimaryOrder::OpaqueRect(OpaqueRectOrder {
+            left: 0,
+            top: 0,
+            width: 2,
+            height: 1,
+            color: 0xF800,
+        });
+        gdi.draw(&red, None, &palette, &mut cache).unwrap();
+        let copy = PrimaryOrder::ScrBlt(ScrBltOrder {
+            left: 1,
+            top: 0,
+            width: 2,
+            height: 1,
+            rop: 0xCC,
+            source_x: 0,
+            source_y: 0,
+        });
+        gdi.draw(&copy, None, &palette, &mut cache).unwrap();
+        let pixels: Vec<_> = (0..4).map(|x| gdi.surface().pixel(x, 0).unwrap()).collect();
+        assert_eq!(pixels, vec![0xFFFF0000, 0xFFFF0000, 0xFFFF0000, BLACK]);
+    }
+
+    /// Hatched brush aligned on its origin
+    #[test]
+    fn test_pat_blt_hatched() {
+        let mut gdi = Gdi::new(8, 8, 24);
+        let order = PrimaryOrder::PatBlt(PatBltOrder {
+            left: 0,
+            top: 0,
+            width: 8,
+            height: 8,
+            rop: 0xF0,
+            back_color: 0xFFFFFF,
+            fore_color: 0x0000FF,
+            brush: Brush {
+                x: 1,
+                style: BrushStyle::BsHatched as u8,
+                // HS_VERTICAL, the line is the fifth column
+                hatch: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        gdi.draw(&order, None, &Palette::default(), &mut empty_cache())
+            .unwrap();
+        assert_eq!(gdi.surface().pixel(5, 3), Some(0xFF0000FF));
+        assert_eq!(gdi.surface().pixel(4, 3), Some(0xFFFFFFFF));
+    }
+
+    /// Lines don't draw their last point
+    #[test]
+    fn test_polyline() {
+        let mut gdi = Gdi::new(8, 8, 24);
+        let order = PrimaryOrder::Polyline(PolylineOrder {
+            start_x: 1,
+            start_y: 1,
+            // R2_COPYPEN
+            rop2: 13,
+            pen_color: 0x00FF00,
+            deltas: vec![(3, 0), (0, 2)],
+        });
+        let bitmap = gdi
+            .draw(&order, None, &Palette::default(), &mut empty_cache())
+            .unwrap()
+            .unwrap();
+        assert_eq!((bitmap.dest_left, bitmap.dest_top), (1, 1));
+        assert_eq!((bitmap.dest_right, bitmap.dest_bottom), (4, 2));
+        assert_eq!(gdi.surface().pixel(4, 2), Some(0xFF00FF00));
+        assert_eq!(gdi.surface().pixel(4, 3), Some(BLACK));
+        assert_eq!(gdi.surface().pixel(2, 1), Some(0xFF00FF00));
+    }
+
+    /// Cached monochrome brush, rows are top down
+    #[test]
+    fn test_pat_blt_cached_brush() {
+        let mut gdi = Gdi::new(8, 8, 24);
+        let mut cache = empty_cache();
+        let brush = CacheBrushOrder {
+            cache_index: 2,
+            bpp: 1,
+            width: 8,
+            height: 8,
+            data: vec![0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        };
+        cache.brushes.insert(brush).unwrap();
+        let order = PrimaryOrder::PatBlt(PatBltOrder {
+            width: 8,
+            height: 8,
+            rop: 0xF0,
+            back_color: 0xFFFFFF,
+            fore_color: 0x0000FF,
+            brush: Brush {
+                style: CACHED_BRUSH | 0x01,
+                hatch: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        gdi.draw(&order, None, &Palette::default(), &mut cache)
+            .unwrap();
+        assert_eq!(gdi.surface().pixel(0, 0), Some(0xFF0000FF));
+        assert_eq!(gdi.surface().pixel(1, 0), Some(0xFFFFFFFF));
+        assert_eq!(gdi.surface().pixel(0, 1), Some(0xFFFFFFFF));
+    }
+
+    /// A variable pitch text is stored as a fragment, then drawn again
+    #[test]
+    fn test_glyph_index_fragment() {
+        let mut gdi = Gdi::new(16, 4, 24);
+        let mut cache = empty_cache();
+        let glyph = Glyph {
+            cache_index: 1,
+            x: 0,
+            y: -1,
+            width: 2,
+            height: 1,
+            data: vec![0x40, 0, 0, 0],
+        };
+        cache.glyphs.put(0, glyph).unwrap();
+        let order = GlyphIndexOrder {
+            back_color: 0x00FF00,
+            bk_left: 0,
+            bk_top: 0,
+            bk_right: 15,
+            bk_bottom: 3,
+            x: 0,
+            y: 1,
+            // Two glyphs, 3 pixels apart, stored as fragment 4
+            // then the fragment drawn 8 pixels after
+            data: vec![
+                1,
+                0,
+                1,
+                3,
+                GLYPH_FRAGMENT_ADD,
+                4,
+                4,
+                GLYPH_FRAGMENT_USE,
+                4,
+                5,
+            ],
+            ..Default::default()
+        };
+        let bitmap = gdi
+            .draw(
+                &PrimaryOrder::GlyphIndex(order),
+                None,
+                &Palette::default(),
+                &mut cache,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!((bitmap.dest_left, bitmap.dest_right), (0, 12));
+        let drawn: Vec<_> = (0..16)
+            .filter(|x| gdi.surface().pixel(*x, 0) == Some(0xFF00FF00))
+            .collect();
+        assert_eq!(drawn, vec![1, 4, 9, 12]);
+    }
+
+    /// Orders drawn offscreen are only given back once copied on the screen
+    #[test]
+    fn test_offscreen_surface() {
+        let mut cache = empty_cache();
+        let mut gdi = Gdi::new(16, 16, 32);
+        let palette = Palette::default();
+        gdi.apply_alt_secondary(&AltSecondaryOrder::CreateOffscreenBitmap(
+            CreateOffscreenBitmapOrder {
+                id: 1,
+                width: 4,
+                height: 4,
+                delete_list: vec![],
+            },
+        ))
+        .unwrap();
+        gdi.apply_alt_secondary(&AltSecondaryOrder::SwitchSurface(1))
+            .unwrap();
+        let order = PrimaryOrder::OpaqueRect(OpaqueRectOrder {
+            left: 0,
+            top: 0,
+            width: 8,
+            height: 8,
+            color: 0x0000FF,
+        });
+        assert!(gdi
+            .draw(&order, None, &palette, &mut cache)
+            .unwrap()
+            .is_none());
+        assert_eq!(gdi.surface().pixel(0, 0), Some(BLACK));
+
+        gdi.apply_alt_secondary(&AltSecondaryOrder::SwitchSurface(SCREEN_BITMAP_SURFACE))
+            .unwrap();
+        let order = PrimaryOrder::MemBlt(MemBltOrder {
+            cache_id: OFFSCREEN_CACHE_ID,
+            cache_index: 1,
+            left: 10,
+            top: 10,
+            width: 8,
+            height: 8,
+            rop: 0xCC,
+            ..Default::default()
+        });
+        let bitmap = gdi
+            .draw(&order, None, &palette, &mut cache)
+            .unwrap()
+            .unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (4, 4));
+        assert_eq!(gdi.surface().pixel(13, 13), Some(0xFF0000FF));
+        assert_eq!(gdi.surface().pixel(14, 14), Some(BLACK));
+
+        assert!(gdi
+            .apply_alt_secondary(&AltSecondaryOrder::SwitchSurface(2))
+            .is_err());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod base;
+pub mod primary;
+pub mod secondary;
+pub mod cache;
+pub mod gdi;
+pub mod altsec;
+pub mod window;
\ No newline at end of file
@@ -0,0 +1,881 @@
+use crate::core::order::base::{Bounds, ControlFlag};
+use crate::core::order::secondary::Glyph;
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+
+/// Primary drawing order types
+/// MS-RDPEGDI 2.2.2.2.1.1.2 Primary Drawing Order (PRIMARY_DRAWING_ORDER)
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum PrimaryOrderType {
+    TsEncDstbltOrder = 0x00,
+    TsEncPatbltOrder = 0x01,
+    TsEncScrbltOrder = 0x02,
+    TsEncDrawninegridOrder = 0x07,
+    TsEncMultiDrawninegridOrder = 0x08,
+    TsEncLinetoOrder = 0x09,
+    TsEncOpaquerectOrder = 0x0A,
+    TsEncSavebitmapOrder = 0x0B,
+    TsEncMembltOrder = 0x0D,
+    TsEncMem3bltOrder = 0x0E,
+    TsEncMultidstbltOrder = 0x0F,
+    TsEncMultipatbltOrder = 0x10,
+    TsEncMultiscrbltOrder = 0x11,
+    TsEncMultiopaquerectOrder = 0x12,
+    TsEncFastIndexOrder = 0x13,
+    TsEncPolygonScOrder = 0x14,
+    TsEncPolygonCbOrder = 0x15,
+    TsEncPolylineOrder = 0x16,
+    TsEncFastGlyphOrder = 0x18,
+    TsEncEllipseScOrder = 0x19,
+    TsEncEllipseCbOrder = 0x1A,
+    TsEncIndexOrder = 0x1B,
+}
+
+impl PrimaryOrderType {
+    /// Number of fields of the order
+    /// which gives the size of the field flags
+    fn field_count(self) -> usize {
+        match self {
+            PrimaryOrderType::TsEncDstbltOrder => 5,
+            PrimaryOrderType::TsEncPatbltOrder => 12,
+            PrimaryOrderType::TsEncScrbltOrder => 7,
+            PrimaryOrderType::TsEncDrawninegridOrder => 5,
+            PrimaryOrderType::TsEncMultiDrawninegridOrder => 7,
+            PrimaryOrderType::TsEncLinetoOrder => 10,
+            PrimaryOrderType::TsEncOpaquerectOrder => 7,
+            PrimaryOrderType::TsEncSavebitmapOrder => 6,
+            PrimaryOrderType::TsEncMembltOrder => 9,
+            PrimaryOrderType::TsEncMem3bltOrder => 16,
+            PrimaryOrderType::TsEncMultidstbltOrder => 7,
+            PrimaryOrderType::TsEncMultipatbltOrder => 14,
+            PrimaryOrderType::TsEncMultiscrbltOrder => 9,
+            PrimaryOrderType::TsEncMultiopaquerectOrder => 9,
+            PrimaryOrderType::TsEncFastIndexOrder => 15,
+            PrimaryOrderType::TsEncPolygonScOrder => 7,
+            PrimaryOrderType::TsEncPolygonCbOrder => 13,
+            PrimaryOrderType::TsEncPolylineOrder => 7,
+            PrimaryOrderType::TsEncFastGlyphOrder => 15,
+            PrimaryOrderType::TsEncEllipseScOrder => 7,
+            PrimaryOrderType::TsEncEllipseCbOrder => 13,
+            PrimaryOrderType::TsEncIndexOrder => 22,
+        }
+    }
+}
+
+/// Fields sent with a primary order
+/// Coordinates are either absolute or deltas
+/// from the value of the previous order
+pub(crate) struct FieldFlags {
+    flags: u32,
+    delta: bool,
+}
+
+impl FieldFlags {
+    /// Fields are numbered from 1
+    pub(crate) fn has(&self, field: usize) -> bool {
+        self.flags & (1 << (field - 1)) != 0
+    }
+
+    /// Coordinate field
+    /// MS-RDPEGDI 2.2.2.2.1.1.1.1 Coord Field (COORD_FIELD)
+    pub(crate) fn read_coord(
+        &self,
+        buffer: &mut BytesMut,
+        field: usize,
+        value: &mut i16,
+    ) -> Result<()> {
+        if !self.has(field) {
+            return Ok(());
+        }
+        if self.delta {
+            check_remaining(buffer, 1, "ORDER: coordinate field")?;
+            *value = value.wrapping_add(buffer.get_i8() as i16);
+        } else {
+            check_remaining(buffer, 2, "ORDER: coordinate field")?;
+            *value = buffer.get_i16_le();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_u8(
+        &self,
+        buffer: &mut BytesMut,
+        field: usize,
+        value: &mut u8,
+    ) -> Result<()> {
+        if self.has(field) {
+            check_remaining(buffer, 1, "ORDER: byte field")?;
+            *value = buffer.get_u8();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_u16(
+        &self,
+        buffer: &mut BytesMut,
+        field: usize,
+        value: &mut u16,
+    ) -> Result<()> {
+        if self.has(field) {
+            check_remaining(buffer, 2, "ORDER: word field")?;
+            *value = buffer.get_u16_le();
+        }
+        Ok(())
+    }
+
+    /// Signed field, never sent as a delta
+    pub(crate) fn read_i16(
+        &self,
+        buffer: &mut BytesMut,
+        field: usize,
+        value: &mut i16,
+    ) -> Result<()> {
+        if self.has(field) {
+            check_remaining(buffer, 2, "ORDER: word field")?;
+            *value = buffer.get_i16_le();
+        }
+        Ok(())
+    }
+
+    /// Color in the color depth of the session
+    /// MS-RDPEGDI 2.2.2.2.1.1.1.8 Color (TS_COLOR)
+    pub(crate) fn read_color(
+        &self,
+        buffer: &mut BytesMut,
+        field: usize,
+        value: &mut u32,
+    ) -> Result<()> {
+        if self.has(field) {
+            check_remaining(buffer, 3, "ORDER: color field")?;
+            *value = buffer.get_uint_le(3) as u32;
+        }
+        Ok(())
+    }
+
+    /// Field with its size on one byte
+    /// MS-RDPEGDI 2.2.2.2.1.1.1.2 One-Byte Header Variable Field (VARIABLE1_FIELD)
+    pub(crate) fn read_variable(
+        &self,
+        buffer: &mut BytesMut,
+        field: usize,
+        value: &mut Vec<u8>,
+    ) -> Result<()> {
+        if self.has(field) {
+            check_remaining(buffer, 1, "ORDER: variable field")?;
+            let length = buffer.get_u8() as usize;
+            check_remaining(buffer, length, "ORDER: variable field")?;
+            *value = buffer.split_to(length).to_vec();
+        }
+        Ok(())
+    }
+}
+
+/// Brush styles
+/// MS-RDPEGDI 2.2.2.2.1.1.1.5 Brush (BRUSH)
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BrushStyle {
+    BsSolid = 0x00,
+    BsNull = 0x01,
+    BsHatched = 0x02,
+    BsPattern = 0x03,
+}
+
+/// Brush used to fill with a pattern
+/// The pattern is an 8x8 monochrome bitmap, one byte per row
+/// MS-RDPEGDI 2.2.2.2.1.1.1.5 Brush (BRUSH)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Brush {
+    pub x: u8,
+    pub y: u8,
+    pub style: u8,
+    /// Hatch style or bottom row of the pattern
+    pub hatch: u8,
+    /// Other rows of the pattern, from the bottom
+    pub extra: [u8; 7],
+}
+
+impl Brush {
+    /// Brush fields start at the given field number
+    fn read_fields(
+        &mut self,
+        fields: &FieldFlags,
+        buffer: &mut BytesMut,
+        first: usize,
+    ) -> Result<()> {
+        fields.read_u8(buffer, first, &mut self.x)?;
+        fields.read_u8(buffer, first + 1, &mut self.y)?;
+        fields.read_u8(buffer, first + 2, &mut self.style)?;
+        fields.read_u8(buffer, first + 3, &mut self.hatch)?;
+        if fields.has(first + 4) {
+            check_remaining(buffer, 7, "ORDER: brush extra")?;
+            buffer.copy_to_slice(&mut self.extra);
+        }
+        Ok(())
+    }
+}
+
+/// Apply a raster operation to the destination only
+/// MS-RDPEGDI 2.2.2.2.1.1.2.1 DstBlt (DSTBLT_ORDER)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DstBltOrder {
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub rop: u8,
+}
+
+impl DstBltOrder {
+    fn read_fields(&mut self, fields: &FieldFlags, buffer: &mut BytesMut) -> Result<()> {
+        fields.read_coord(buffer, 1, &mut self.left)?;
+        fields.read_coord(buffer, 2, &mut self.top)?;
+        fields.read_coord(buffer, 3, &mut self.width)?;
+        fields.read_coord(buffer, 4, &mut self.height)?;
+        fields.read_u8(buffer, 5, &mut self.rop)
+    }
+}
+
+/// Fill a rectangle with a brush and a raster operation
+/// MS-RDPEGDI 2.2.2.2.1.1.2.3 PatBlt (PATBLT_ORDER)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct PatBltOrder {
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub rop: u8,
+    pub back_color: u32,
+    pub fore_color: u32,
+    pub brush: Brush,
+}
+
+impl PatBltOrder {
+    fn read_fields(&mut self, fields: &FieldFlags, buffer: &mut BytesMut) -> Result<()> {
+        fields.read_coord(buffer, 1, &mut self.left)?;
+        fields.read_coord(buffer, 2, &mut self.top)?;
+        fields.read_coord(buffer, 3, &mut self.width)?;
+        fields.read_coord(buffer, 4, &mut self.height)?;
+        fields.read_u8(buffer, 5, &mut self.rop)?;
+        fields.read_color(buffer, 6, &mut self.back_color)?;
+        fields.read_color(buffer, 7, &mut self.fore_color)?;
+        self.brush.read_fields(fields, buffer, 8)
+    }
+}
+
+/// Copy a region of the screen
+/// MS-RDPEGDI 2.2.2.2.1.1.2.7 ScrBlt (SCRBLT_ORDER)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScrBltOrder {
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub rop: u8,
+    pub source_x: i16,
+    pub source_y: i16,
+}
+
+impl ScrBltOrder {
+    fn read_fields(&mut self, fields: &FieldFlags, buffer: &mut BytesMut) -> Result<()> {
+        fields.read_coord(buffer, 1, &mut self.left)?;
+        fields.read_coord(buffer, 2, &mut self.top)?;
+        fields.read_coord(buffer, 3, &mut self.width)?;
+        fields.read_coord(buffer, 4, &mut self.height)?;
+        fields.read_u8(buffer, 5, &mut self.rop)?;
+        fields.read_coord(buffer, 6, &mut self.source_x)?;
+        fields.read_coord(buffer, 7, &mut self.source_y)
+    }
+}
+
+/// Fill a rectangle with a color
+/// Each component of the color is a field
+/// MS-RDPEGDI 2.2.2.2.1.1.2.5 OpaqueRect (OPAQUERECT_ORDER)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct OpaqueRectOrder {
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub color: u32,
+}
+
+impl OpaqueRectOrder {
+    fn read_fields(&mut self, fields: &FieldFlags, buffer: &mut BytesMut) -> Result<()> {
+        fields.read_coord(buffer, 1, &mut self.left)?;
+        fields.read_coord(buffer, 2, &mut self.top)?;
+        fields.read_coord(buffer, 3, &mut self.width)?;
+        fields.read_coord(buffer, 4, &mut self.height)?;
+        for (index, shift) in [0, 8, 16].into_iter().enumerate() {
+            let mut component = (self.color >> shift) as u8;
+            fields.read_u8(buffer, 5 + index, &mut component)?;
+            self.color = (self.color & !(0xFF << shift)) | (component as u32) << shift;
+        }
+        Ok(())
+    }
+}
+
+/// Draw a bitmap of the bitmap caches
+/// MS-RDPEGDI 2.2.2.2.1.1.2.9 MemBlt (MEMBLT_ORDER)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct MemBltOrder {
+    pub cache_id: u8,
+    pub color_table_index: u8,
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    /// Ternary raster operation
+    pub rop: u8,
+    pub source_x: i16,
+    pub source_y: i16,
+    pub cache_index: u16,
+}
+
+impl MemBltOrder {
+    fn read_fields(&mut self, fields: &FieldFlags, buffer: &mut BytesMut) -> Result<()> {
+        if fields.has(1) {
+            check_remaining(buffer, 2, "ORDER: memblt cache id")?;
+            self.cache_id = buffer.get_u8();
+            self.color_table_index = buffer.get_u8();
+        }
+        fields.read_coord(buffer, 2, &mut self.left)?;
+        fields.read_coord(buffer, 3, &mut self.top)?;
+        fields.read_coord(buffer, 4, &mut self.width)?;
+        fields.read_coord(buffer, 5, &mut self.height)?;
+        fields.read_u8(buffer, 6, &mut self.rop)?;
+        fields.read_coord(buffer, 7, &mut self.source_x)?;
+        fields.read_coord(buffer, 8, &mut self.source_y)?;
+        fields.read_u16(buffer, 9, &mut self.cache_index)
+    }
+}
+
+/// Draw a line with a pen
+/// The last point is not drawn
+/// MS-RDPEGDI 2.2.2.2.1.1.2.11 LineTo (LINETO_ORDER)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LineToOrder {
+    pub back_mode: u16,
+    pub start_x: i16,
+    pub start_y: i16,
+    pub end_x: i16,
+    pub end_y: i16,
+    pub back_color: u32,
+    /// Binary raster operation
+    pub rop2: u8,
+    pub pen_style: u8,
+    pub pen_width: u8,
+    pub pen_color: u32,
+}
+
+impl LineToOrder {
+    fn read_fields(&mut self, fields: &FieldFlags, buffer: &mut BytesMut) -> Result<()> {
+        fields.read_u16(buffer, 1, &mut self.back_mode)?;
+        fields.read_coord(buffer, 2, &mut self.start_x)?;
+        fields.read_coord(buffer, 3, &mut self.start_y)?;
+        fields.read_coord(buffer, 4, &mut self.end_x)?;
+        fields.read_coord(buffer, 5, &mut self.end_y)?;
+        fields.read_color(buffer, 6, &mut self.back_color)?;
+        fields.read_u8(buffer, 7, &mut self.rop2)?;
+        fields.read_u8(buffer, 8, &mut self.pen_style)?;
+        fields.read_u8(buffer, 9, &mut self.pen_width)?;
+        fields.read_color(buffer, 10, &mut self.pen_color)
+    }
+}
+
+/// Read the points of a polyline
+/// Each point is a delta of the previous one,
+/// zero deltas are flagged and not sent
+/// MS-RDPEGDI 2.2.2.2.1.1.1.9 Delta-Encoded Points (DELTA_PTS_FIELD)
+fn read_delta_points(data: &[u8], number_points: usize) -> Result<Vec<(i16, i16)>> {
+    let truncated = || Error::new(ErrorKind::InvalidData, "ORDER: truncated delta points");
+    let zero_bits_size = (number_points + 3) / 4;
+    if data.len() < zero_bits_size {
+        return Err(truncated());
+    }
+    let (zero_bits, mut source) = data.split_at(zero_bits_size);
+    let mut read_u8 = || {
+        let (byte, rest) = source.split_first().ok_or_else(truncated)?;
+        source = rest;
+        Ok::<u8, Error>(*byte)
+    };
+
+    let mut points = Vec::with_capacity(number_points);
+    for index in 0..number_points {
+        let flags = zero_bits[index / 4] << ((index % 4) * 2);
+        let mut delta = [0; 2];
+        for (value, zero_bit) in delta.iter_mut().zip([0x80, 0x40]) {
+            if flags & zero_bit != 0 {
+                continue;
+            }
+            // 7 or 15 bits, signed
+            let first = read_u8()?;
+            *value = if first & 0x40 != 0 {
+                first as i16 | !0x3F
+            } else {
+                (first & 0x3F) as i16
+            };
+            if first & 0x80 != 0 {
+                *value = (*value << 8) | read_u8()? as i16;
+            }
+        }
+        points.push((delta[0], delta[1]));
+    }
+    Ok(points)
+}
+
+/// Draw connected lines with a pen
+/// MS-RDPEGDI 2.2.2.2.1.1.2.18 Polyline (POLYLINE_ORDER)
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PolylineOrder {
+    pub start_x: i16,
+    pub start_y: i16,
+    pub rop2: u8,
+    pub pen_color: u32,
+    /// Each point is relative to the previous one
+    pub deltas: Vec<(i16, i16)>,
+}
+
+impl PolylineOrder {
+    fn read_fields(&mut self, fields: &FieldFlags, buffer: &mut BytesMut) -> Result<()> {
+        let mut brush_cache_entry = 0;
+        let mut number_deltas = self.deltas.len() as u8;
+        let mut data = Vec::new();
+        fields.read_coord(buffer, 1, &mut self.start_x)?;
+        fields.read_coord(buffer, 2, &mut self.start_y)?;
+        fields.read_u8(buffer, 3, &mut self.rop2)?;
+        fields.read_u16(buffer, 4, &mut brush_cache_entry)?;
+        fields.read_color(buffer, 5, &mut self.pen_color)?;
+        fields.read_u8(buffer, 6, &mut number_deltas)?;
+        fields.read_variable(buffer, 7, &mut data)?;
+        if fields.has(7) {
+            self.deltas = read_delta_points(&data, number_deltas as usize)?;
+        }
+        Ok(())
+    }
+}
+
+/// Draw a string with glyphs of the glyph cache
+/// The back color is the color of the text,
+/// the fore color fills the opaque rectangle
+/// MS-RDPEGDI 2.2.2.2.1.1.2.13 GlyphIndex (GLYPHINDEX_ORDER)
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GlyphIndexOrder {
+    pub cache_id: u8,
+    pub accel_flags: u8,
+    /// Fixed advance between glyphs, zero when glyphs give their own
+    pub char_inc: u8,
+    /// The opaque rectangle is the background rectangle
+    pub op_redundant: u8,
+    pub back_color: u32,
+    pub fore_color: u32,
+    /// Inclusive background rectangle
+    pub bk_left: i16,
+    pub bk_top: i16,
+    pub bk_right: i16,
+    pub bk_bottom: i16,
+    /// Inclusive opaque rectangle, empty when right is zero
+    pub op_left: i16,
+    pub op_top: i16,
+    pub op_right: i16,
+    pub op_bottom: i16,
+    pub brush: Brush,
+    /// Origin of the first glyph
+    pub x: i16,
+    pub y: i16,
+    /// Glyph indices and fragments
+    pub data: Vec<u8>,
+}
+
+impl GlyphIndexOrder {
+    fn read_fields(&mut self, fields: &FieldFlags, buffer: &mut BytesMut) -> Result<()> {
+        fields.read_u8(buffer, 1, &mut self.cache_id)?;
+        fields.read_u8(buffer, 2, &mut self.accel_flags)?;
+        fields.read_u8(buffer, 3, &mut self.char_inc)?;
+        fields.read_u8(buffer, 4, &mut self.op_redundant)?;
+        fields.read_color(buffer, 5, &mut self.back_color)?;
+        fields.read_color(buffer, 6, &mut self.fore_color)?;
+        fields.read_i16(buffer, 7, &mut self.bk_left)?;
+        fields.read_i16(buffer, 8, &mut self.bk_top)?;
+        fields.read_i16(buffer, 9, &mut self.bk_right)?;
+        fields.read_i16(buffer, 10, &mut self.bk_bottom)?;
+        fields.read_i16(buffer, 11, &mut self.op_left)?;
+        fields.read_i16(buffer, 12, &mut self.op_top)?;
+        fields.read_i16(buffer, 13, &mut self.op_right)?;
+        fields.read_i16(buffer, 14, &mut self.op_bottom)?;
+        self.brush.read_fields(fields, buffer, 15)?;
+        fields.read_i16(buffer, 20, &mut self.x)?;
+        fields.read_i16(buffer, 21, &mut self.y)?;
+        fields.read_variable(buffer, 22, &mut self.data)
+    }
+}
+
+/// Draw a single glyph, which may be sent with the order
+/// MS-RDPEGDI 2.2.2.2.1.1.2.14 FastGlyph (FASTGLYPH_ORDER)
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FastGlyphOrder {
+    pub cache_id: u8,
+    pub accel_flags: u8,
+    pub char_inc: u8,
+    /// Color of the text
+    pub back_color: u32,
+    /// Color of the opaque rectangle
+    pub fore_color: u32,
+    /// Inclusive background rectangle
+    pub bk_left: i16,
+    pub bk_top: i16,
+    pub bk_right: i16,
+    pub bk_bottom: i16,
+    /// Inclusive opaque rectangle
+    /// When the bottom is -32768 the top holds the sides
+    /// which are those of the background rectangle
+    pub op_left: i16,
+    pub op_top: i16,
+    pub op_right: i16,
+    pub op_bottom: i16,
+    pub x: i16,
+    pub y: i16,
+    pub cache_index: u8,
+    /// Glyph to store in the glyph cache before drawing
+    pub glyph: Option<Glyph>,
+}
+
+impl FastGlyphOrder {
+    fn read_fields(&mut self, fields: &FieldFlags, buffer: &mut BytesMut) -> Result<()> {
+        let mut drawing = (self.char_inc as u16) << 8 | self.accel_flags as u16;
+        let mut data = Vec::new();
+        fields.read_u8(buffer, 1, &mut self.cache_id)?;
+        fields.read_u16(buffer, 2, &mut drawing)?;
+        self.accel_flags = drawing as u8;
+        self.char_inc = (drawing >> 8) as u8;
+        fields.read_color(buffer, 3, &mut self.back_color)?;
+        fields.read_color(buffer, 4, &mut self.fore_color)?;
+        fields.read_coord(buffer, 5, &mut self.bk_left)?;
+        fields.read_coord(buffer, 6, &mut self.bk_top)?;
+        fields.read_coord(buffer, 7, &mut self.bk_right)?;
+        fields.read_coord(buffer, 8, &mut self.bk_bottom)?;
+        fields.read_coord(buffer, 9, &mut self.op_left)?;
+        fields.read_coord(buffer, 10, &mut self.op_top)?;
+        fields.read_coord(buffer, 11, &mut self.op_right)?;
+        fields.read_coord(buffer, 12, &mut self.op_bottom)?;
+        fields.read_coord(buffer, 13, &mut self.x)?;
+        fields.read_coord(buffer, 14, &mut self.y)?;
+        fields.read_variable(buffer, 15, &mut data)?;
+        if fields.has(15) {
+            let (&cache_index, glyph) = data.split_first().ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, "ORDER: empty fast glyph data")
+            })?;
+            self.cache_index = cache_index;
+            self.glyph = if glyph.is_empty() {
+                None
+            } else {
+                Some(Glyph::read_fast_glyph(
+                    cache_index,
+                    &mut BytesMut::from(glyph),
+                )?)
+            };
+        }
+        Ok(())
+    }
+}
+
+/// A primary drawing order
+/// with the value of all its fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrimaryOrder {
+    DstBlt(DstBltOrder),
+    PatBlt(PatBltOrder),
+    ScrBlt(ScrBltOrder),
+    OpaqueRect(OpaqueRectOrder),
+    MemBlt(MemBltOrder),
+    LineTo(LineToOrder),
+    Polyline(PolylineOrder),
+    GlyphIndex(GlyphIndexOrder),
+    FastGlyph(FastGlyphOrder),
+}
+
+/// State kept between primary orders
+/// The type, bounds and fields of each order
+/// are those of the previous order when not sent
+pub(crate) struct PrimaryOrderReader {
+    order_type: PrimaryOrderType,
+    bounds: Bounds,
+    dst_blt: DstBltOrder,
+    pat_blt: PatBltOrder,
+    scr_blt: ScrBltOrder,
+    opaque_rect: OpaqueRectOrder,
+    mem_blt: MemBltOrder,
+    line_to: LineToOrder,
+    polyline: PolylineOrder,
+    glyph_index: GlyphIndexOrder,
+    fast_glyph: FastGlyphOrder,
+}
+
+impl Default for PrimaryOrderReader {
+    /// The initial order type is PatBlt
+    fn default() -> Self {
+        PrimaryOrderReader {
+            order_type: PrimaryOrderType::TsEncPatbltOrder,
+            bounds: Bounds::default(),
+            dst_blt: DstBltOrder::default(),
+            pat_blt: PatBltOrder::default(),
+            scr_blt: ScrBltOrder::default(),
+            opaque_rect: OpaqueRectOrder::default(),
+            mem_blt: MemBltOrder::default(),
+            line_to: LineToOrder::default(),
+            polyline: PolylineOrder::default(),
+            glyph_index: GlyphIndexOrder::default(),
+            fast_glyph: FastGlyphOrder::default(),
+        }
+    }
+}
+
+impl PrimaryOrderReader {
+    /// Bounds of the order, each side is absent,
+    /// absolute or a delta of the previous bounds
+    /// MS-RDPEGDI 2.2.2.2.1.1.1.4 Bounds (TS_BOUNDS)
+    fn read_bounds(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 1, "ORDER: bounds")?;
+        let description = buffer.get_u8();
+        let sides = [
+            &mut self.bounds.left,
+            &mut self.bounds.top,
+            &mut self.bounds.right,
+            &mut self.bounds.bottom,
+        ];
+        for (index, side) in sides.into_iter().enumerate() {
+            if description & (0x01 << index) != 0 {
+                check_remaining(buffer, 2, "ORDER: bounds")?;
+                *side = buffer.get_i16_le();
+            } else if description & (0x10 << index) != 0 {
+                check_remaining(buffer, 1, "ORDER: bounds")?;
+                *side = side.wrapping_add(buffer.get_i8() as i16);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a primary order following its control flags
+    pub(crate) fn read(
+        &mut self,
+        control_flags: u8,
+        buffer: &mut BytesMut,
+    ) -> Result<(Option<Bounds>, PrimaryOrder)> {
+        if control_flags & ControlFlag::TsTypeChange as u8 != 0 {
+            check_remaining(buffer, 1, "ORDER: primary order type")?;
+            let order_type = buffer.get_u8();
+            self.order_type = PrimaryOrderType::try_from(order_type).map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("ORDER: unknown primary order type {}", order_type),
+                )
+            })?;
+        }
+
+        // Missing high bytes of the field flags are zero
+        let mut field_bytes = (self.order_type.field_count() + 1 + 7) / 8;
+        if control_flags & ControlFlag::TsZeroFieldByteBit0 as u8 != 0 {
+            field_bytes = field_bytes.saturating_sub(1);
+        }
+        if control_flags & ControlFlag::TsZeroFieldByteBit1 as u8 != 0 {
+            field_bytes = field_bytes.saturating_sub(2);
+        }
+        check_remaining(buffer, field_bytes, "ORDER: field flags")?;
+        let fields = FieldFlags {
+            flags: (0..field_bytes).fold(0, |flags, index| {
+                flags | (buffer.get_u8() as u32) << (index * 8)
+            }),
+            delta: control_flags & ControlFlag::TsDeltaCoordinates as u8 != 0,
+        };
+
+        let bounds = if control_flags & ControlFlag::TsBounds as u8 != 0 {
+            if control_flags & ControlFlag::TsZeroBoundsDeltas as u8 == 0 {
+                self.read_bounds(buffer)?;
+            }
+            Some(self.bounds)
+        } else {
+            None
+        };
+
+        let order = match self.order_type {
+            PrimaryOrderType::TsEncDstbltOrder => {
+                self.dst_blt.read_fields(&fields, buffer)?;
+                PrimaryOrder::DstBlt(self.dst_blt)
+            }
+            PrimaryOrderType::TsEncPatbltOrder => {
+                self.pat_blt.read_fields(&fields, buffer)?;
+                PrimaryOrder::PatBlt(self.pat_blt)
+            }
+            PrimaryOrderType::TsEncScrbltOrder => {
+                self.scr_blt.read_fields(&fields, buffer)?;
+                PrimaryOrder::ScrBlt(self.scr_blt)
+            }
+            PrimaryOrderType::TsEncOpaquerectOrder => {
+                self.opaque_rect.read_fields(&fields, buffer)?;
+                PrimaryOrder::OpaqueRect(self.opaque_rect)
+            }
+            PrimaryOrderType::TsEncMembltOrder => {
+                self.mem_blt.read_fields(&fields, buffer)?;
+                PrimaryOrder::MemBlt(self.mem_blt)
+            }
+            PrimaryOrderType::TsEncLinetoOrder => {
+                self.line_to.read_fields(&fields, buffer)?;
+                PrimaryOrder::LineTo(self.line_to)
+            }
+            PrimaryOrderType::TsEncPolylineOrder => {
+                self.polyline.read_fields(&fields, buffer)?;
+                PrimaryOrder::Polyline(self.polyline.clone())
+            }
+            PrimaryOrderType::TsEncIndexOrder => {
+                self.glyph_index.read_fields(&fields, buffer)?;
+                PrimaryOrder::GlyphIndex(self.glyph_index.clone())
+            }
+            PrimaryOrderType::TsEncFastGlyphOrder => {
+                self.fast_glyph.read_fields(&fields, buffer)?;
+                PrimaryOrder::FastGlyph(self.fast_glyph.clone())
+            }
+            // Field sizes are unknown, the rest of the update can't be read
+            order_type => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("ORDER: unsupported primary order {:?}", order_type),
+                ))
+            }
+        };
+        Ok((bounds, order))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Zero field byte flags drop the high bytes of the field flags
+    #[test]
+    fn test_read_zero_field_bytes() {
+        let mut reader = PrimaryOrderReader::default();
+        let mut buffer = BytesMut::from(&[0x0D, 0x00, 0x01, 0x05, 0x00][..]);
+        let control_flags = ControlFlag::TsStandard as u8 | ControlFlag::TsTypeChange as u8;
+        let (bounds, order) = reader.read(control_flags, &mut buffer).unwrap();
+        assert_eq!(bounds, None);
+        assert_eq!(
+            order,
+            PrimaryOrder::MemBlt(MemBltOrder {
+                cache_index: 5,
+                ..Default::default()
+            })
+        );
+
+        // No field at all
+        let control_flags = ControlFlag::TsStandard as u8
+            | ControlFlag::TsZeroFieldByteBit0 as u8
+            | ControlFlag::TsZeroFieldByteBit1 as u8;
+        let (_, order) = reader.read(control_flags, &mut BytesMut::new()).unwrap();
+        assert_eq!(
+            order,
+            PrimaryOrder::MemBlt(MemBltOrder {
+                cache_index: 5,
+                ..Default::default()
+            })
+        );
+    }
+
+    /// OpaqueRect only sends the changed color components
+    #[test]
+    fn test_read_opaque_rect_color() {
+        let mut reader = PrimaryOrderReader::default();
+        let control_flags = ControlFlag::TsStandard as u8 | ControlFlag::TsTypeChange as u8;
+        let mut buffer = BytesMut::from(&[0x0A, 0x71, 0x0A, 0x00, 0x11, 0x22, 0x33][..]);
+        let (_, order) = reader.read(control_flags, &mut buffer).unwrap();
+        assert_eq!(
+            order,
+            PrimaryOrder::OpaqueRect(OpaqueRectOrder {
+                left: 10,
+                color: 0x332211,
+                ..Default::default()
+            })
+        );
+
+        let control_flags = ControlFlag::TsStandard as u8 | ControlFlag::TsDeltaCoordinates as u8;
+        let mut buffer = BytesMut::from(&[0x21, 0xFE, 0x44][..]);
+        let (_, order) = reader.read(control_flags, &mut buffer).unwrap();
+        assert_eq!(
+            order,
+            PrimaryOrder::OpaqueRect(OpaqueRectOrder {
+                left: 8,
+                color: 0x334411,
+                ..Default::default()
+            })
+        );
+    }
+
+    /// Zero deltas are flagged, others are on one or two bytes
+    #[test]
+    fn test_read_delta_points() {
+        // x of the first point and y of the second point are zero,
+        // the x of the second point is on two bytes
+        let data = [0x90, 0x05, 0x81, 0x00];
+        assert_eq!(
+            read_delta_points(&data, 2).unwrap(),
+            vec![(0, 5), (0x100, 0)]
+        );
+        assert!(read_delta_points(&data[..3], 2).is_err());
+    }
+
+    /// FastGlyph with a glyph definition,
+    /// then the same glyph from the cache
+    #[test]
+    fn test_read_fast_glyph() {
+        let mut reader = PrimaryOrderReader::default();
+        let control_flags = ControlFlag::TsStandard as u8 | ControlFlag::TsTypeChange as u8;
+        let mut buffer = BytesMut::from(
+            &[
+                0x18, 0x03, 0x70, 0x02, 0x20, 0x00, 0x0A, 0x00, 0x14, 0x00, 0x09, 0x05, 0x00, 0x48,
+                0x08, 0x02, 0xFF, 0x81, 0x00, 0x00,
+            ][..],
+        );
+        let (_, order) = reader.read(control_flags, &mut buffer).unwrap();
+        let glyph = Glyph {
+            cache_index: 5,
+            x: 0,
+            y: -8,
+            width: 8,
+            height: 2,
+            data: vec![0xFF, 0x81, 0x00, 0x00],
+        };
+        assert_eq!(
+            order,
+            PrimaryOrder::FastGlyph(FastGlyphOrder {
+                cache_id: 2,
+                accel_flags: 0x20,
+                x: 10,
+                y: 20,
+                cache_index: 5,
+                glyph: Some(glyph),
+                ..Default::default()
+            })
+        );
+
+        let mut buffer = BytesMut::from(&[0x00, 0x40, 0x01, 0x05][..]);
+        let (_, order) = reader
+            .read(ControlFlag::TsStandard as u8, &mut buffer)
+            .unwrap();
+        match order {
+            PrimaryOrder::FastGlyph(order) => assert_eq!(order.glyph, None),
+            order => panic!("unexpected order {:?}", order),
+        }
+    }
+
+    /// Unknown orders can't be skipped
+    #[test]
+    fn test_read_unknown_order() {
+        let mut reader = PrimaryOrderReader::default();
+        let mut buffer = BytesMut::from(&[0x20, 0x00][..]);
+        let control_flags = ControlFlag::TsStandard as u8 | ControlFlag::TsTypeChange as u8;
+        assert!(reader.read(control_flags, &mut buffer).is_err());
+    }
+}
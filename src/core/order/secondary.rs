@@ -0,0 +1,479 @@
+use crate::core::order::base::{read_2byte_signed, read_2byte_unsigned, read_4byte_unsigned};
+use crate::core::update::Palette;
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+
+/// Secondary drawing order types
+/// MS-RDPEGDI 2.2.2.2.1.2.1.1 Secondary Drawing Order Header (SECONDARY_DRAWING_ORDER_HEADER)
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum SecondaryOrderType {
+    TsCacheBitmapUncompressed = 0x00,
+    TsCacheColorTable = 0x01,
+    TsCacheBitmapCompressed = 0x02,
+    TsCacheGlyph = 0x03,
+    TsCacheBitmapUncompressedRev2 = 0x04,
+    TsCacheBitmapCompressedRev2 = 0x05,
+    TsCacheBrush = 0x07,
+    TsCacheBitmapCompressedRev3 = 0x08,
+}
+
+/// Flags of the cache bitmap revision 2 order
+/// MS-RDPEGDI 2.2.2.2.1.2.3 Cache Bitmap - Revision 2 (CACHE_BITMAP_REV2_ORDER)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CacheBitmapV2Flag {
+    Cbr2HeightSameAsWidth = 0x01,
+    Cbr2PersistentKeyPresent = 0x02,
+    Cbr2NoBitmapCompressionHdr = 0x08,
+    Cbr2DoNotCache = 0x10,
+}
+
+/// Size of the bitmapComprHdr field
+const BITMAP_COMPRESSION_HEADER_SIZE: usize = 8;
+
+/// Store a bitmap in a bitmap cache
+/// Data is encoded like bitmap updates
+/// MS-RDPEGDI 2.2.2.2.1.2.3 Cache Bitmap - Revision 2 (CACHE_BITMAP_REV2_ORDER)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheBitmapV2Order {
+    pub cache_id: u8,
+    pub bpp: u16,
+    pub cache_index: u16,
+    /// Key of persistent caches
+    pub key: Option<(u32, u32)>,
+    pub width: u16,
+    pub height: u16,
+    pub is_compress: bool,
+    /// The bitmap is only drawn once
+    pub do_not_cache: bool,
+    pub data: Vec<u8>,
+}
+
+impl CacheBitmapV2Order {
+    fn read_from_buffer(
+        extra_flags: u16,
+        is_compress: bool,
+        buffer: &mut BytesMut,
+    ) -> Result<Self> {
+        let bpp = match (extra_flags >> 3) & 0x0F {
+            3 => 8,
+            4 => 16,
+            5 => 24,
+            6 => 32,
+            bpp_id => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("ORDER: invalid cache bitmap bpp id {}", bpp_id),
+                ))
+            }
+        };
+        let flags = extra_flags >> 7;
+
+        let key = if flags & CacheBitmapV2Flag::Cbr2PersistentKeyPresent as u16 != 0 {
+            check_remaining(buffer, 8, "ORDER: cache bitmap key")?;
+            Some((buffer.get_u32_le(), buffer.get_u32_le()))
+        } else {
+            None
+        };
+        let width = read_2byte_unsigned(buffer)?;
+        let height = if flags & CacheBitmapV2Flag::Cbr2HeightSameAsWidth as u16 != 0 {
+            width
+        } else {
+            read_2byte_unsigned(buffer)?
+        };
+        let mut bitmap_length = read_4byte_unsigned(buffer)? as usize;
+        let cache_index = read_2byte_unsigned(buffer)?;
+
+        if is_compress && flags & CacheBitmapV2Flag::Cbr2NoBitmapCompressionHdr as u16 == 0 {
+            // TS_CD_HEADER, only the main body size is meaningful
+            check_remaining(
+                buffer,
+                BITMAP_COMPRESSION_HEADER_SIZE,
+                "ORDER: bitmap compression",
+            )?;
+            let _cb_comp_first_row_size = buffer.get_u16_le();
+            bitmap_length = buffer.get_u16_le() as usize;
+            let _cb_scan_width = buffer.get_u16_le();
+            let _cb_uncompressed_size = buffer.get_u16_le();
+        }
+
+        check_remaining(buffer, bitmap_length, "ORDER: cache bitmap data")?;
+        Ok(CacheBitmapV2Order {
+            cache_id: (extra_flags & 0x07) as u8,
+            bpp,
+            cache_index,
+            key,
+            width,
+            height,
+            is_compress,
+            do_not_cache: flags & CacheBitmapV2Flag::Cbr2DoNotCache as u16 != 0,
+            data: buffer.split_to(bitmap_length).to_vec(),
+        })
+    }
+}
+
+/// Store a palette in the color table cache
+/// MS-RDPEGDI 2.2.2.2.1.2.4 Cache Color Table (CACHE_COLOR_TABLE_ORDER)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheColorTableOrder {
+    pub cache_index: u8,
+    pub palette: Palette,
+}
+
+impl CacheColorTableOrder {
+    fn read_from_buffer(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 3, "ORDER: cache color table")?;
+        let cache_index = buffer.get_u8();
+        let number_colors = buffer.get_u16_le() as usize;
+        check_remaining(buffer, number_colors * 4, "ORDER: cache color table")?;
+        // TS_COLOR_QUAD, blue first
+        let colors = (0..number_colors)
+            .map(|_| {
+                let [blue, green, red, _] = buffer.get_u32_le().to_le_bytes();
+                [red, green, blue]
+            })
+            .collect();
+        Ok(CacheColorTableOrder {
+            cache_index,
+            palette: Palette { colors },
+        })
+    }
+}
+
+/// Flag of the glyph cache order
+/// MS-RDPEGDI 2.2.2.2.1.2.5 Cache Glyph - Revision 1 (CACHE_GLYPH_ORDER)
+const CG_GLYPH_UNICODE_PRESENT: u16 = 0x0010;
+
+/// A monochrome glyph
+/// Rows are top down and padded to a byte, the high bit is on the left
+/// MS-RDPEGDI 2.2.2.2.1.2.5.1 Cache Glyph Data (TS_CACHE_GLYPH_DATA)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Glyph {
+    pub cache_index: u16,
+    /// Origin of the glyph relative to the text position
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    pub data: Vec<u8>,
+}
+
+impl Glyph {
+    /// Size of the glyph bitmap, padded to 4 bytes
+    fn data_size(width: u16, height: u16) -> usize {
+        let size = (width as usize + 7) / 8 * height as usize;
+        (size + 3) & !3
+    }
+
+    fn read_from_buffer(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 10, "ORDER: glyph")?;
+        let cache_index = buffer.get_u16_le();
+        let x = buffer.get_i16_le();
+        let y = buffer.get_i16_le();
+        let width = buffer.get_u16_le();
+        let height = buffer.get_u16_le();
+        let size = Self::data_size(width, height);
+        check_remaining(buffer, size, "ORDER: glyph data")?;
+        Ok(Glyph {
+            cache_index,
+            x,
+            y,
+            width,
+            height,
+            data: buffer.split_to(size).to_vec(),
+        })
+    }
+
+    /// Glyph sent with a FastGlyph order, fields are variable length
+    /// MS-RDPEGDI 2.2.2.2.1.1.2.15 Fast Glyph (FASTGLYPH_ORDER)
+    pub(crate) fn read_fast_glyph(cache_index: u8, buffer: &mut BytesMut) -> Result<Self> {
+        let x = read_2byte_signed(buffer)?;
+        let y = read_2byte_signed(buffer)?;
+        let width = read_2byte_unsigned(buffer)?;
+        let height = read_2byte_unsigned(buffer)?;
+        let size = Self::data_size(width, height);
+        check_remaining(buffer, size, "ORDER: fast glyph data")?;
+        Ok(Glyph {
+            cache_index: cache_index as u16,
+            x,
+            y,
+            width,
+            height,
+            data: buffer.split_to(size).to_vec(),
+        })
+    }
+
+    /// The pixel is part of the glyph
+    pub fn is_set(&self, x: u16, y: u16) -> bool {
+        let stride = (self.width as usize + 7) / 8;
+        self.data
+            .get(y as usize * stride + x as usize / 8)
+            .map_or(false, |row| row & (0x80 >> (x % 8)) != 0)
+    }
+}
+
+/// Store glyphs in a glyph cache
+/// Only the revision 1 is used as the client doesn't
+/// advertise the glyph encode support level
+/// MS-RDPEGDI 2.2.2.2.1.2.5 Cache Glyph - Revision 1 (CACHE_GLYPH_ORDER)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheGlyphOrder {
+    pub cache_id: u8,
+    pub glyphs: Vec<Glyph>,
+}
+
+impl CacheGlyphOrder {
+    fn read_from_buffer(extra_flags: u16, buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 2, "ORDER: cache glyph")?;
+        let cache_id = buffer.get_u8();
+        let number_glyphs = buffer.get_u8();
+        let glyphs = (0..number_glyphs)
+            .map(|_| Glyph::read_from_buffer(buffer))
+            .collect::<Result<Vec<_>>>()?;
+        if extra_flags & CG_GLYPH_UNICODE_PRESENT != 0 {
+            check_remaining(buffer, glyphs.len() * 2, "ORDER: glyph characters")?;
+            buffer.advance(glyphs.len() * 2);
+        }
+        Ok(CacheGlyphOrder { cache_id, glyphs })
+    }
+}
+
+/// Store an 8x8 brush in the brush cache
+/// MS-RDPEGDI 2.2.2.2.1.2.7 Cache Brush (CACHE_BRUSH_ORDER)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheBrushOrder {
+    pub cache_index: u8,
+    /// 1 for monochrome brushes
+    pub bpp: u16,
+    pub width: u8,
+    pub height: u8,
+    /// Monochrome brushes have one byte per row,
+    /// color brushes are encoded like bitmaps of the session
+    /// Rows are top down
+    pub data: Vec<u8>,
+}
+
+impl CacheBrushOrder {
+    fn read_from_buffer(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 6, "ORDER: cache brush")?;
+        let cache_index = buffer.get_u8();
+        let bpp = match buffer.get_u8() {
+            1 => 1,
+            3 => 8,
+            4 => 16,
+            5 => 24,
+            6 => 32,
+            format => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("ORDER: invalid brush format {}", format),
+                ))
+            }
+        };
+        let width = buffer.get_u8();
+        let height = buffer.get_u8();
+        let _style = buffer.get_u8();
+        let length = buffer.get_u8() as usize;
+        check_remaining(buffer, length, "ORDER: brush data")?;
+        let data = buffer.split_to(length);
+        if width != 8 || height != 8 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "ORDER: only 8x8 brushes are supported",
+            ));
+        }
+
+        // Rows are sent bottom up
+        let pixel_size = ((bpp + 1) / 8).max(1) as usize;
+        let data: Vec<u8> = if bpp == 1 && length == 8 {
+            data.iter().rev().copied().collect()
+        } else if length == 64 * pixel_size {
+            data.chunks_exact(8 * pixel_size)
+                .rev()
+                .flatten()
+                .copied()
+                .collect()
+        } else if length == 16 + 4 * pixel_size {
+            // Compressed brush, 2 bits indices in a 4 colors palette
+            // MS-RDPEGDI 2.2.2.2.1.2.7.1 Compressed Color Brush
+            let (indices, palette) = data.split_at(16);
+            indices
+                .chunks_exact(2)
+                .rev()
+                .flat_map(|row| (0..8).map(move |x| (row[x / 4] >> ((3 - x % 4) * 2)) & 0x03))
+                .flat_map(|index| {
+                    let offset = index as usize * pixel_size;
+                    palette[offset..offset + pixel_size].iter().copied()
+                })
+                .collect()
+        } else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("ORDER: invalid brush length {}", length),
+            ));
+        };
+        Ok(CacheBrushOrder {
+            cache_index,
+            bpp,
+            width,
+            height,
+            data,
+        })
+    }
+}
+
+/// A secondary drawing order
+/// Orders not handled by rdp-rs are skipped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecondaryOrder {
+    CacheBitmapV2(CacheBitmapV2Order),
+    CacheColorTable(CacheColorTableOrder),
+    CacheGlyph(CacheGlyphOrder),
+    CacheBrush(CacheBrushOrder),
+    Unknown(u8),
+}
+
+/// Read a secondary order following its control flags
+/// The order length gives the size of the whole order, less 13 bytes
+/// MS-RDPEGDI 2.2.2.2.1.2.1.1 Secondary Drawing Order Header (SECONDARY_DRAWING_ORDER_HEADER)
+pub fn read_secondary_order(buffer: &mut BytesMut) -> Result<SecondaryOrder> {
+    check_remaining(buffer, 5, "ORDER: secondary order header")?;
+    let order_length = buffer.get_i16_le() as isize + 7;
+    let extra_flags = buffer.get_u16_le();
+    let order_type = buffer.get_u8();
+    if order_length < 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "ORDER: invalid secondary order length",
+        ));
+    }
+    check_remaining(buffer, order_length as usize, "ORDER: secondary order")?;
+    let mut body = buffer.split_to(order_length as usize);
+
+    Ok(match SecondaryOrderType::try_from(order_type) {
+        Ok(SecondaryOrderType::TsCacheBitmapUncompressedRev2) => SecondaryOrder::CacheBitmapV2(
+            CacheBitmapV2Order::read_from_buffer(extra_flags, false, &mut body)?,
+        ),
+        Ok(SecondaryOrderType::TsCacheBitmapCompressedRev2) => SecondaryOrder::CacheBitmapV2(
+            CacheBitmapV2Order::read_from_buffer(extra_flags, true, &mut body)?,
+        ),
+        Ok(SecondaryOrderType::TsCacheColorTable) => {
+            SecondaryOrder::CacheColorTable(CacheColorTableOrder::read_from_buffer(&mut body)?)
+        }
+        Ok(SecondaryOrderType::TsCacheGlyph) => {
+            SecondaryOrder::CacheGlyph(CacheGlyphOrder::read_from_buffer(extra_flags, &mut body)?)
+        }
+        Ok(SecondaryOrderType::TsCacheBrush) => {
+            SecondaryOrder::CacheBrush(CacheBrushOrder::read_from_buffer(&mut body)?)
+        }
+        _ => SecondaryOrder::Unknown(order_type),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::BufMut;
+
+    /// Compressed bitmap with a key and a compression header,
+    /// then an unknown order which is skipped
+    #[test]
+    fn test_read_secondary_order() {
+        let mut buffer = BytesMut::new();
+        buffer.put_i16_le(8 + 6 + 8 + 2 - 7);
+        buffer.put_u16_le(2 | (4 << 3) | (CacheBitmapV2Flag::Cbr2PersistentKeyPresent as u16) << 7);
+        buffer.put_u8(SecondaryOrderType::TsCacheBitmapCompressedRev2 as u8);
+        buffer.put_u32_le(0x11);
+        buffer.put_u32_le(0x22);
+        buffer.put_slice(&[0x40, 0x20, 0x0A, 0x81, 0x00]);
+        for field in [0, 2, 128, 8192] {
+            buffer.put_u16_le(field);
+        }
+        buffer.put_slice(&[0x01, 0x02]);
+        buffer.put_u8(0);
+
+        buffer.put_i16_le(3 - 7);
+        buffer.put_u16_le(0);
+        buffer.put_u8(SecondaryOrderType::TsCacheBitmapCompressedRev3 as u8);
+        buffer.put_slice(&[1, 2, 3]);
+
+        let order = read_secondary_order(&mut buffer).unwrap();
+        assert_eq!(
+            order,
+            SecondaryOrder::CacheBitmapV2(CacheBitmapV2Order {
+                cache_id: 2,
+                bpp: 16,
+                cache_index: 0x100,
+                key: Some((0x11, 0x22)),
+                width: 64,
+                height: 32,
+                is_compress: true,
+                do_not_cache: false,
+                data: vec![0x01, 0x02],
+            })
+        );
+        assert_eq!(
+            read_secondary_order(&mut buffer).unwrap(),
+            SecondaryOrder::Unknown(SecondaryOrderType::TsCacheBitmapCompressedRev3 as u8)
+        );
+        assert!(buffer.is_empty());
+    }
+
+    /// Two glyphs followed by their unicode characters
+    #[test]
+    fn test_read_cache_glyph() {
+        let mut buffer = BytesMut::new();
+        buffer.put_i16_le(2 + 2 * (10 + 4) + 4 - 7);
+        buffer.put_u16_le(CG_GLYPH_UNICODE_PRESENT);
+        buffer.put_u8(SecondaryOrderType::TsCacheGlyph as u8);
+        buffer.put_slice(&[7, 2]);
+        for index in [1u16, 2] {
+            buffer.put_u16_le(index);
+            buffer.put_i16_le(0);
+            buffer.put_i16_le(-10);
+            buffer.put_u16_le(9);
+            buffer.put_u16_le(1);
+            buffer.put_slice(&[0x80, 0x80, 0, 0]);
+        }
+        buffer.put_slice(&[b'a', 0, b'b', 0]);
+
+        let order = match read_secondary_order(&mut buffer).unwrap() {
+            SecondaryOrder::CacheGlyph(order) => order,
+            order => panic!("unexpected order {:?}", order),
+        };
+        assert!(buffer.is_empty());
+        assert_eq!(order.cache_id, 7);
+        assert_eq!(order.glyphs.len(), 2);
+        let glyph = &order.glyphs[1];
+        assert_eq!((glyph.cache_index, glyph.y, glyph.width), (2, -10, 9));
+        assert!(glyph.is_set(0, 0));
+        assert!(!glyph.is_set(1, 0));
+        assert!(glyph.is_set(8, 0));
+    }
+
+    /// Compressed 8 bpp brush, bottom row first
+    #[test]
+    fn test_read_cache_brush() {
+        let mut buffer = BytesMut::new();
+        buffer.put_i16_le(6 + 20 - 7);
+        buffer.put_u16_le(0);
+        buffer.put_u8(SecondaryOrderType::TsCacheBrush as u8);
+        buffer.put_slice(&[3, 3, 8, 8, 0, 20]);
+        buffer.put_slice(&[0x1B, 0x00]);
+        buffer.put_slice(&[0x00; 14]);
+        buffer.put_slice(&[10, 11, 12, 13]);
+
+        let order = match read_secondary_order(&mut buffer).unwrap() {
+            SecondaryOrder::CacheBrush(order) => order,
+            order => panic!("unexpected order {:?}", order),
+        };
+        assert_eq!((order.cache_index, order.bpp), (3, 8));
+        assert_eq!(order.data.len(), 64);
+        assert_eq!(&order.data[..8], &[10; 8]);
+        assert_eq!(&order.data[56..], &[10, 11, 12, 13, 10, 10, 10, 10]);
+    }
+}
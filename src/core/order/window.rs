@@ -0,0 +1,544 @@
+use crate::model::data::check_remaining;
+use crate::model::unicode::from_unicode;
+
+use bytes::{Buf, BufMut, BytesMut};
+use std::io::{Error, ErrorKind, Result};
+
+/// Size of the header of a windowing order, with the control flags
+/// MS-RDPERP 2.2.1.2 Windowing Alternate Secondary Drawing Order
+const WINDOW_ORDER_HEADER_SIZE: usize = 7;
+
+/// Kind and state of a windowing order, in the fields present flags
+pub const WINDOW_ORDER_TYPE_WINDOW: u32 = 0x01000000;
+pub const WINDOW_ORDER_TYPE_NOTIFY: u32 = 0x02000000;
+pub const WINDOW_ORDER_TYPE_DESKTOP: u32 = 0x04000000;
+pub const WINDOW_ORDER_STATE_NEW: u32 = 0x10000000;
+pub const WINDOW_ORDER_STATE_DELETED: u32 = 0x20000000;
+pub const WINDOW_ORDER_ICON: u32 = 0x40000000;
+pub const WINDOW_ORDER_CACHEDICON: u32 = 0x80000000;
+
+/// Fields of the window information order
+/// MS-RDPERP 2.2.1.3.1.2.1 New or Existing Window
+pub const WINDOW_ORDER_FIELD_OWNER: u32 = 0x00000002;
+pub const WINDOW_ORDER_FIELD_TITLE: u32 = 0x00000004;
+pub const WINDOW_ORDER_FIELD_STYLE: u32 = 0x00000008;
+pub const WINDOW_ORDER_FIELD_SHOW: u32 = 0x00000010;
+pub const WINDOW_ORDER_FIELD_RESIZE_MARGIN_X: u32 = 0x00000080;
+pub const WINDOW_ORDER_FIELD_WNDRECTS: u32 = 0x00000100;
+pub const WINDOW_ORDER_FIELD_VISIBILITY: u32 = 0x00000200;
+pub const WINDOW_ORDER_FIELD_WNDSIZE: u32 = 0x00000400;
+pub const WINDOW_ORDER_FIELD_WNDOFFSET: u32 = 0x00000800;
+pub const WINDOW_ORDER_FIELD_VISOFFSET: u32 = 0x00001000;
+pub const WINDOW_ORDER_FIELD_CLIENTAREAOFFSET: u32 = 0x00004000;
+pub const WINDOW_ORDER_FIELD_WNDCLIENTDELTA: u32 = 0x00008000;
+pub const WINDOW_ORDER_FIELD_CLIENTAREASIZE: u32 = 0x00010000;
+pub const WINDOW_ORDER_FIELD_RPCONTENT: u32 = 0x00020000;
+pub const WINDOW_ORDER_FIELD_ROOTPARENT: u32 = 0x00040000;
+pub const WINDOW_ORDER_FIELD_RESIZE_MARGIN_Y: u32 = 0x08000000;
+
+/// Fields of the notification icon order
+/// MS-RDPERP 2.2.1.3.2.2.1 New or Existing Notification Icons
+pub const WINDOW_ORDER_FIELD_NOTIFY_TIP: u32 = 0x00000001;
+pub const WINDOW_ORDER_FIELD_NOTIFY_INFO_TIP: u32 = 0x00000002;
+pub const WINDOW_ORDER_FIELD_NOTIFY_STATE: u32 = 0x00000004;
+pub const WINDOW_ORDER_FIELD_NOTIFY_VERSION: u32 = 0x00000008;
+
+/// Fields of the desktop order
+/// MS-RDPERP 2.2.1.3.3.2.1 Actively Monitored Desktop
+pub const WINDOW_ORDER_FIELD_DESKTOP_NONE: u32 = 0x00000001;
+pub const WINDOW_ORDER_FIELD_DESKTOP_HOOKED: u32 = 0x00000002;
+pub const WINDOW_ORDER_FIELD_DESKTOP_ARC_COMPLETED: u32 = 0x00000004;
+pub const WINDOW_ORDER_FIELD_DESKTOP_ARC_BEGAN: u32 = 0x00000008;
+pub const WINDOW_ORDER_FIELD_DESKTOP_ZORDER: u32 = 0x00000010;
+pub const WINDOW_ORDER_FIELD_DESKTOP_ACTIVEWND: u32 = 0x00000020;
+
+/// Rectangle of a window, relative to its offset
+/// MS-RDPERP 2.2.1.2.2 Rectangle (TS_RECTANGLE_16)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WindowRect {
+    pub left: u16,
+    pub top: u16,
+    pub right: u16,
+    pub bottom: u16,
+}
+
+impl WindowRect {
+    pub(crate) fn read(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 8, "ORDER: window rectangle")?;
+        Ok(WindowRect {
+            left: buffer.get_u16_le(),
+            top: buffer.get_u16_le(),
+            right: buffer.get_u16_le(),
+            bottom: buffer.get_u16_le(),
+        })
+    }
+
+    pub(crate) fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.put_u16_le(self.left);
+        buffer.put_u16_le(self.top);
+        buffer.put_u16_le(self.right);
+        buffer.put_u16_le(self.bottom);
+    }
+}
+
+/// Window or notification icon, with the cache entry to keep it in
+/// The mask is a 1 bit AND mask, the color table is only sent up to 8 bpp
+/// MS-RDPERP 2.2.1.2.3 Icon Info (TS_ICON_INFO)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IconInfo {
+    pub cache_entry: u16,
+    pub cache_id: u8,
+    pub bpp: u8,
+    pub width: u16,
+    pub height: u16,
+    pub bits_mask: Vec<u8>,
+    pub color_table: Vec<u8>,
+    pub bits_color: Vec<u8>,
+}
+
+impl IconInfo {
+    fn read(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 8, "ORDER: icon info")?;
+        let cache_entry = buffer.get_u16_le();
+        let cache_id = buffer.get_u8();
+        let bpp = buffer.get_u8();
+        let width = buffer.get_u16_le();
+        let height = buffer.get_u16_le();
+        let color_table_length = if matches!(bpp, 1 | 4 | 8) {
+            check_remaining(buffer, 2, "ORDER: icon color table")?;
+            buffer.get_u16_le() as usize
+        } else {
+            0
+        };
+        check_remaining(buffer, 4, "ORDER: icon info")?;
+        let mask_length = buffer.get_u16_le() as usize;
+        let color_length = buffer.get_u16_le() as usize;
+        check_remaining(
+            buffer,
+            mask_length + color_table_length + color_length,
+            "ORDER: icon bits",
+        )?;
+        Ok(IconInfo {
+            cache_entry,
+            cache_id,
+            bpp,
+            width,
+            height,
+            bits_mask: buffer.split_to(mask_length).to_vec(),
+            color_table: buffer.split_to(color_table_length).to_vec(),
+            bits_color: buffer.split_to(color_length).to_vec(),
+        })
+    }
+}
+
+/// Fields of a new or updated window
+/// Fields not sent keep their previous value, they are None here
+/// MS-RDPERP 2.2.1.3.1.2.1 New or Existing Window
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowInfo {
+    pub window_id: u32,
+    /// The window was just created
+    pub new: bool,
+    pub owner_window_id: Option<u32>,
+    /// Style and extended style, as WS_* and WS_EX_*
+    pub style: Option<(u32, u32)>,
+    /// SW_* show command
+    pub show_state: Option<u8>,
+    pub title: Option<String>,
+    pub client_offset: Option<(i32, i32)>,
+    pub client_size: Option<(u32, u32)>,
+    pub rp_content: Option<u8>,
+    pub root_parent: Option<u32>,
+    /// Position of the window on the desktop
+    pub window_offset: Option<(i32, i32)>,
+    pub client_delta: Option<(i32, i32)>,
+    pub window_size: Option<(u32, u32)>,
+    /// Shape of the window, relative to its offset
+    pub window_rects: Option<Vec<WindowRect>>,
+    pub visible_offset: Option<(i32, i32)>,
+    /// Visible region, relative to the visible offset
+    pub visibility_rects: Option<Vec<WindowRect>>,
+}
+
+/// Desktop tracked by the server
+/// MS-RDPERP 2.2.1.3.3 Desktop
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DesktopInfo {
+    /// The server stopped monitoring the desktop
+    pub none: bool,
+    pub hooked: bool,
+    /// The server started or finished sending the windows of the desktop
+    pub arc_began: bool,
+    pub arc_completed: bool,
+    pub active_window_id: Option<u32>,
+    /// Windows from the top of the Z-order
+    pub z_order: Option<Vec<u32>>,
+}
+
+/// Fields of a new or updated notification icon
+/// MS-RDPERP 2.2.1.3.2.2.1 New or Existing Notification Icons
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NotifyIconInfo {
+    pub window_id: u32,
+    pub notify_icon_id: u32,
+    pub new: bool,
+    pub version: Option<u32>,
+    pub tool_tip: Option<String>,
+    /// Balloon tooltip
+    pub info_tip: Option<InfoTip>,
+    pub state: Option<u32>,
+    pub icon: Option<IconInfo>,
+    /// Cache entry and cache id of an icon already sent
+    pub cached_icon: Option<(u16, u8)>,
+}
+
+/// Balloon tooltip of a notification icon
+/// MS-RDPERP 2.2.1.3.2.2.3 Notification Icon Balloon Tooltip (TS_NOTIFY_ICON_INFOTIP)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InfoTip {
+    pub timeout: u32,
+    pub flags: u32,
+    pub text: String,
+    pub title: String,
+}
+
+/// Windowing orders of a RemoteApp session
+/// MS-RDPERP 2.2.1.3 Windowing Alternate Secondary Drawing Orders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowOrder {
+    /// MS-RDPERP 2.2.1.3.1.2.1 New or Existing Window
+    Window(WindowInfo),
+    /// MS-RDPERP 2.2.1.3.1.2.2 Window Icon
+    WindowIcon {
+        window_id: u32,
+        icon: IconInfo,
+    },
+    /// MS-RDPERP 2.2.1.3.1.2.3 Cached Icon
+    CachedIcon {
+        window_id: u32,
+        cache_entry: u16,
+        cache_id: u8,
+    },
+    /// MS-RDPERP 2.2.1.3.1.2.4 Deleted Window
+    WindowDeleted(u32),
+    NotifyIcon(NotifyIconInfo),
+    /// MS-RDPERP 2.2.1.3.2.2.2 Deleted Notification Icons
+    NotifyIconDeleted {
+        window_id: u32,
+        notify_icon_id: u32,
+    },
+    Desktop(DesktopInfo),
+}
+
+fn read_string(buffer: &mut BytesMut) -> Result<String> {
+    check_remaining(buffer, 2, "ORDER: window string")?;
+    let length = buffer.get_u16_le() as usize;
+    check_remaining(buffer, length, "ORDER: window string")?;
+    Ok(from_unicode(&buffer.split_to(length)))
+}
+
+fn read_point(buffer: &mut BytesMut) -> Result<(i32, i32)> {
+    check_remaining(buffer, 8, "ORDER: window point")?;
+    Ok((buffer.get_i32_le(), buffer.get_i32_le()))
+}
+
+fn read_size(buffer: &mut BytesMut) -> Result<(u32, u32)> {
+    check_remaining(buffer, 8, "ORDER: window size")?;
+    Ok((buffer.get_u32_le(), buffer.get_u32_le()))
+}
+
+fn read_rects(buffer: &mut BytesMut) -> Result<Vec<WindowRect>> {
+    check_remaining(buffer, 2, "ORDER: window rectangles")?;
+    let count = buffer.get_u16_le();
+    (0..count).map(|_| WindowRect::read(buffer)).collect()
+}
+
+fn read_u32(buffer: &mut BytesMut, what: &str) -> Result<u32> {
+    check_remaining(buffer, 4, what)?;
+    Ok(buffer.get_u32_le())
+}
+
+fn read_u8(buffer: &mut BytesMut, what: &str) -> Result<u8> {
+    check_remaining(buffer, 1, what)?;
+    Ok(buffer.get_u8())
+}
+
+fn read_window_info(flags: u32, window_id: u32, buffer: &mut BytesMut) -> Result<WindowInfo> {
+    let mut info = WindowInfo {
+        window_id,
+        new: flags & WINDOW_ORDER_STATE_NEW != 0,
+        ..Default::default()
+    };
+    if flags & WINDOW_ORDER_FIELD_OWNER != 0 {
+        info.owner_window_id = Some(read_u32(buffer, "ORDER: window owner")?);
+    }
+    if flags & WINDOW_ORDER_FIELD_STYLE != 0 {
+        check_remaining(buffer, 8, "ORDER: window style")?;
+        info.style = Some((buffer.get_u32_le(), buffer.get_u32_le()));
+    }
+    if flags & WINDOW_ORDER_FIELD_SHOW != 0 {
+        info.show_state = Some(read_u8(buffer, "ORDER: window show state")?);
+    }
+    if flags & WINDOW_ORDER_FIELD_TITLE != 0 {
+        info.title = Some(read_string(buffer)?);
+    }
+    if flags & WINDOW_ORDER_FIELD_CLIENTAREAOFFSET != 0 {
+        info.client_offset = Some(read_point(buffer)?);
+    }
+    if flags & WINDOW_ORDER_FIELD_CLIENTAREASIZE != 0 {
+        info.client_size = Some(read_size(buffer)?);
+    }
+    // Resize margins are only needed to draw the frame on the server
+    if flags & WINDOW_ORDER_FIELD_RESIZE_MARGIN_X != 0 {
+        read_size(buffer)?;
+    }
+    if flags & WINDOW_ORDER_FIELD_RESIZE_MARGIN_Y != 0 {
+        read_size(buffer)?;
+    }
+    if flags & WINDOW_ORDER_FIELD_RPCONTENT != 0 {
+        info.rp_content = Some(read_u8(buffer, "ORDER: window content")?);
+    }
+    if flags & WINDOW_ORDER_FIELD_ROOTPARENT != 0 {
+        info.root_parent = Some(read_u32(buffer, "ORDER: window root parent")?);
+    }
+    if flags & WINDOW_ORDER_FIELD_WNDOFFSET != 0 {
+        info.window_offset = Some(read_point(buffer)?);
+    }
+    if flags & WINDOW_ORDER_FIELD_WNDCLIENTDELTA != 0 {
+        info.client_delta = Some(read_point(buffer)?);
+    }
+    if flags & WINDOW_ORDER_FIELD_WNDSIZE != 0 {
+        info.window_size = Some(read_size(buffer)?);
+    }
+    if flags & WINDOW_ORDER_FIELD_WNDRECTS != 0 {
+        info.window_rects = Some(read_rects(buffer)?);
+    }
+    if flags & WINDOW_ORDER_FIELD_VISOFFSET != 0 {
+        info.visible_offset = Some(read_point(buffer)?);
+    }
+    if flags & WINDOW_ORDER_FIELD_VISIBILITY != 0 {
+        info.visibility_rects = Some(read_rects(buffer)?);
+    }
+    // Overlay description, taskbar button and app bar fields are skipped with the order size
+    Ok(info)
+}
+
+fn read_notify_icon(flags: u32, buffer: &mut BytesMut) -> Result<WindowOrder> {
+    check_remaining(buffer, 8, "ORDER: notification icon")?;
+    let window_id = buffer.get_u32_le();
+    let notify_icon_id = buffer.get_u32_le();
+    if flags & WINDOW_ORDER_STATE_DELETED != 0 {
+        return Ok(WindowOrder::NotifyIconDeleted {
+            window_id,
+            notify_icon_id,
+        });
+    }
+    let mut info = NotifyIconInfo {
+        window_id,
+        notify_icon_id,
+        new: flags & WINDOW_ORDER_STATE_NEW != 0,
+        ..Default::default()
+    };
+    if flags & WINDOW_ORDER_FIELD_NOTIFY_VERSION != 0 {
+        info.version = Some(read_u32(buffer, "ORDER: notification icon version")?);
+    }
+    if flags & WINDOW_ORDER_FIELD_NOTIFY_TIP != 0 {
+        info.tool_tip = Some(read_string(buffer)?);
+    }
+    if flags & WINDOW_ORDER_FIELD_NOTIFY_INFO_TIP != 0 {
+        check_remaining(buffer, 8, "ORDER: notification icon info tip")?;
+        info.info_tip = Some(InfoTip {
+            timeout: buffer.get_u32_le(),
+            flags: buffer.get_u32_le(),
+            text: read_string(buffer)?,
+            title: read_string(buffer)?,
+        });
+    }
+    if flags & WINDOW_ORDER_FIELD_NOTIFY_STATE != 0 {
+        info.state = Some(read_u32(buffer, "ORDER: notification icon state")?);
+    }
+    if flags & WINDOW_ORDER_ICON != 0 {
+        info.icon = Some(IconInfo::read(buffer)?);
+    }
+    if flags & WINDOW_ORDER_CACHEDICON != 0 {
+        check_remaining(buffer, 3, "ORDER: cached icon")?;
+        info.cached_icon = Some((buffer.get_u16_le(), buffer.get_u8()));
+    }
+    Ok(WindowOrder::NotifyIcon(info))
+}
+
+fn read_desktop(flags: u32, buffer: &mut BytesMut) -> Result<DesktopInfo> {
+    let mut info = DesktopInfo {
+        none: flags & WINDOW_ORDER_FIELD_DESKTOP_NONE != 0,
+        hooked: flags & WINDOW_ORDER_FIELD_DESKTOP_HOOKED != 0,
+        arc_began: flags & WINDOW_ORDER_FIELD_DESKTOP_ARC_BEGAN != 0,
+        arc_completed: flags & WINDOW_ORDER_FIELD_DESKTOP_ARC_COMPLETED != 0,
+        ..Default::default()
+    };
+    if flags & WINDOW_ORDER_FIELD_DESKTOP_ACTIVEWND != 0 {
+        info.active_window_id = Some(read_u32(buffer, "ORDER: active window")?);
+    }
+    if flags & WINDOW_ORDER_FIELD_DESKTOP_ZORDER != 0 {
+        let count = read_u8(buffer, "ORDER: desktop z-order")? as usize;
+        check_remaining(buffer, count * 4, "ORDER: desktop z-order")?;
+        info.z_order = Some((0..count).map(|_| buffer.get_u32_le()).collect());
+    }
+    Ok(info)
+}
+
+/// Read a windowing order, after its control flags
+/// The whole order is consumed even if some fields are unknown
+pub fn read_window_order(buffer: &mut BytesMut) -> Result<WindowOrder> {
+    check_remaining(buffer, 6, "ORDER: window header")?;
+    let order_size = buffer.get_u16_le() as usize;
+    let flags = buffer.get_u32_le();
+    if order_size < WINDOW_ORDER_HEADER_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("ORDER: invalid window order size {}", order_size),
+        ));
+    }
+    let length = order_size - WINDOW_ORDER_HEADER_SIZE;
+    check_remaining(buffer, length, "ORDER: window order")?;
+    let mut body = buffer.split_to(length);
+    let body = &mut body;
+
+    if flags & WINDOW_ORDER_TYPE_WINDOW != 0 {
+        let window_id = read_u32(body, "ORDER: window id")?;
+        if flags & WINDOW_ORDER_STATE_DELETED != 0 {
+            Ok(WindowOrder::WindowDeleted(window_id))
+        } else if flags & WINDOW_ORDER_ICON != 0 {
+            Ok(WindowOrder::WindowIcon {
+                window_id,
+                icon: IconInfo::read(body)?,
+            })
+        } else if flags & WINDOW_ORDER_CACHEDICON != 0 {
+            check_remaining(body, 3, "ORDER: cached icon")?;
+            Ok(WindowOrder::CachedIcon {
+                window_id,
+                cache_entry: body.get_u16_le(),
+                cache_id: body.get_u8(),
+            })
+        } else {
+            Ok(WindowOrder::Window(read_window_info(
+                flags, window_id, body,
+            )?))
+        }
+    } else if flags & WINDOW_ORDER_TYPE_NOTIFY != 0 {
+        read_notify_icon(flags, body)
+    } else if flags & WINDOW_ORDER_TYPE_DESKTOP != 0 {
+        Ok(WindowOrder::Desktop(read_desktop(flags, body)?))
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("ORDER: unknown window order flags {:#x}", flags),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::unicode::Unicode;
+
+    /// Build a windowing order with its header
+    fn window_order(flags: u32, body: &[u8]) -> BytesMut {
+        let mut buffer = BytesMut::new();
+        buffer.put_u16_le((WINDOW_ORDER_HEADER_SIZE + body.len()) as u16);
+        buffer.put_u32_le(flags);
+        buffer.put_slice(body);
+        buffer
+    }
+
+    /// A new window with its title and geometry, then its deletion
+    #[test]
+    fn test_read_window_order() {
+        let mut body = BytesMut::new();
+        body.put_u32_le(42);
+        body.put_u8(5);
+        let title = "Notepad".to_unicode();
+        body.put_u16_le(title.len() as u16);
+        body.put_slice(&title);
+        body.put_i32_le(-10);
+        body.put_i32_le(20);
+        body.put_u32_le(640);
+        body.put_u32_le(480);
+        // Unknown trailing field
+        body.put_u8(1);
+        let flags = WINDOW_ORDER_TYPE_WINDOW
+            | WINDOW_ORDER_STATE_NEW
+            | WINDOW_ORDER_FIELD_SHOW
+            | WINDOW_ORDER_FIELD_TITLE
+            | WINDOW_ORDER_FIELD_WNDOFFSET
+            | WINDOW_ORDER_FIELD_WNDSIZE;
+        let mut buffer = window_order(flags, &body);
+        buffer.put_u8(0xFF);
+        assert_eq!(
+            read_window_order(&mut buffer).unwrap(),
+            WindowOrder::Window(WindowInfo {
+                window_id: 42,
+                new: true,
+                show_state: Some(5),
+                title: Some("Notepad".to_string()),
+                window_offset: Some((-10, 20)),
+                window_size: Some((640, 480)),
+                ..Default::default()
+            })
+        );
+        assert_eq!(buffer.len(), 1);
+
+        let flags = WINDOW_ORDER_TYPE_WINDOW | WINDOW_ORDER_STATE_DELETED;
+        let mut buffer = window_order(flags, &42u32.to_le_bytes());
+        assert_eq!(
+            read_window_order(&mut buffer).unwrap(),
+            WindowOrder::WindowDeleted(42)
+        );
+
+        let flags = WINDOW_ORDER_TYPE_DESKTOP
+            | WINDOW_ORDER_FIELD_DESKTOP_ACTIVEWND
+            | WINDOW_ORDER_FIELD_DESKTOP_ZORDER;
+        let mut buffer = window_order(flags, &[7, 0, 0, 0, 1, 7, 0, 0, 0]);
+        assert_eq!(
+            read_window_order(&mut buffer).unwrap(),
+            WindowOrder::Desktop(DesktopInfo {
+                active_window_id: Some(7),
+                z_order: Some(vec![7]),
+                ..Default::default()
+            })
+        );
+    }
+
+    /// Icons of a window, with the color table only at 8 bpp or less
+    #[test]
+    fn test_read_window_icon() {
+        let mut body = BytesMut::new();
+        body.put_u32_le(1);
+        body.put_u16_le(3);
+        body.put_u8(0);
+        body.put_u8(8);
+        body.put_u16_le(2);
+        body.put_u16_le(1);
+        body.put_u16_le(4);
+        body.put_u16_le(2);
+        body.put_u16_le(2);
+        body.put_slice(&[0xC0, 0x00, 1, 2, 3, 4, 5, 6]);
+        let flags = WINDOW_ORDER_TYPE_WINDOW | WINDOW_ORDER_ICON;
+        let mut buffer = window_order(flags, &body);
+        assert_eq!(
+            read_window_order(&mut buffer).unwrap(),
+            WindowOrder::WindowIcon {
+                window_id: 1,
+                icon: IconInfo {
+                    cache_entry: 3,
+                    cache_id: 0,
+                    bpp: 8,
+                    width: 2,
+                    height: 1,
+                    bits_mask: vec![0xC0, 0x00],
+                    color_table: vec![1, 2, 3, 4],
+                    bits_color: vec![5, 6],
+                }
+            }
+        );
+    }
+}
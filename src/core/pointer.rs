@@ -0,0 +1,378 @@
+use crate::core::event::{CursorEvent, PointerShape};
+use crate::core::fastpath::FastPathUpdateType;
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+
+/// Message type of a slow path pointer update
+/// MS-RDPBCGR 2.2.9.1.1.4 Server Pointer Update PDU (TS_POINTER_PDU)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum PointerMessageType {
+    TsPtrmsgtypeSystem = 0x0001,
+    TsPtrmsgtypePosition = 0x0003,
+    TsPtrmsgtypeColor = 0x0006,
+    TsPtrmsgtypeCached = 0x0007,
+    TsPtrmsgtypePointer = 0x0008,
+    TsPtrmsgtypeLarge = 0x0009,
+}
+
+/// System pointer type
+/// MS-RDPBCGR 2.2.9.1.1.4.3 System Pointer Update (TS_SYSTEMPOINTERATTRIBUTE)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SystemPointerType {
+    SysptrNull = 0x00000000,
+    SysptrDefault = 0x00007F00,
+}
+
+/// Xor mask bpp of a color pointer
+const COLOR_POINTER_XOR_BPP: u16 = 24;
+
+/// A pointer update before the cache is applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PointerUpdate {
+    Hidden,
+    Default,
+    Position { x: u16, y: u16 },
+    /// New shape to store at its cache index
+    Shape(PointerShape),
+    /// Shape stored at a cache index
+    Cached(u16),
+}
+
+/// Length of a scanline padded on two bytes
+fn scanline_length(width: usize, bpp: usize) -> usize {
+    ((width * bpp + 15) / 16) * 2
+}
+
+/// Read a bit of a monochrome scanline
+fn read_bit(scanline: &[u8], x: usize) -> bool {
+    scanline[x / 8] & (0x80 >> (x % 8)) != 0
+}
+
+/// Convert the xor and and masks into RGBA pixels
+/// Masks are stored bottom-up
+///
+/// Inverted pixels have no RGBA equivalent
+/// and are rendered as opaque black
+fn to_rgba(
+    width: usize,
+    height: usize,
+    xor_bpp: u16,
+    xor_mask: &[u8],
+    and_mask: &[u8],
+) -> Result<Vec<u8>> {
+    if !matches!(xor_bpp, 1 | 16 | 24 | 32) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("POINTER: unsupported xor bpp {}", xor_bpp),
+        ));
+    }
+
+    let xor_stride = scanline_length(width, xor_bpp as usize);
+    let and_stride = scanline_length(width, 1);
+    if xor_mask.len() < xor_stride * height {
+        return Err(Error::new(ErrorKind::InvalidData, "POINTER: xor mask is too short"));
+    }
+    // Some servers omit the and mask of alpha pointers
+    let has_and_mask = and_mask.len() >= and_stride * height;
+
+    let mut result = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let row = height - y - 1;
+        let xor_line = &xor_mask[row * xor_stride..(row + 1) * xor_stride];
+        for x in 0..width {
+            let (r, g, b, a) = match xor_bpp {
+                1 => {
+                    let c = if read_bit(xor_line, x) { 0xFF } else { 0 };
+                    (c, c, c, 0xFF)
+                }
+                16 => {
+                    let v = u16::from_le_bytes([xor_line[x * 2], xor_line[x * 2 + 1]]);
+                    let r = ((v >> 11) & 0x1F) as u8;
+                    let g = ((v >> 5) & 0x3F) as u8;
+                    let b = (v & 0x1F) as u8;
+                    (r << 3, g << 2, b << 3, 0xFF)
+                }
+                24 => (xor_line[x * 3 + 2], xor_line[x * 3 + 1], xor_line[x * 3], 0xFF),
+                _ => (
+                    xor_line[x * 4 + 2],
+                    xor_line[x * 4 + 1],
+                    xor_line[x * 4],
+                    xor_line[x * 4 + 3],
+                ),
+            };
+
+            let and_bit =
+                has_and_mask && read_bit(&and_mask[row * and_stride..(row + 1) * and_stride], x);
+            let pixel = match (and_bit, r | g | b) {
+                (false, _) => [r, g, b, a],
+                (true, 0) => [0, 0, 0, 0],
+                (true, _) => [0, 0, 0, 0xFF],
+            };
+            result.extend_from_slice(&pixel);
+        }
+    }
+    Ok(result)
+}
+
+/// Read the mask of a pointer
+fn read_mask(buffer: &mut BytesMut, length: usize, context: &str) -> Result<Vec<u8>> {
+    check_remaining(buffer, length, context)?;
+    Ok(buffer.split_to(length).to_vec())
+}
+
+/// Read a TS_COLORPOINTERATTRIBUTE structure
+/// MS-RDPBCGR 2.2.9.1.1.4.4 Color Pointer Update (TS_COLORPOINTERATTRIBUTE)
+fn read_color_pointer(buffer: &mut BytesMut, xor_bpp: u16) -> Result<PointerShape> {
+    check_remaining(buffer, 14, "POINTER: color pointer")?;
+    let cache_index = buffer.get_u16_le();
+    let hotspot_x = buffer.get_u16_le();
+    let hotspot_y = buffer.get_u16_le();
+    let width = buffer.get_u16_le();
+    let height = buffer.get_u16_le();
+    let length_and_mask = buffer.get_u16_le() as usize;
+    let length_xor_mask = buffer.get_u16_le() as usize;
+    let xor_mask = read_mask(buffer, length_xor_mask, "POINTER: xor mask")?;
+    let and_mask = read_mask(buffer, length_and_mask, "POINTER: and mask")?;
+
+    Ok(PointerShape {
+        cache_index,
+        hotspot_x,
+        hotspot_y,
+        width,
+        height,
+        rgba: to_rgba(
+            width as usize,
+            height as usize,
+            xor_bpp,
+            &xor_mask,
+            &and_mask,
+        )?,
+    })
+}
+
+/// Read a TS_POINTERATTRIBUTE structure
+/// MS-RDPBCGR 2.2.9.1.1.4.5 New Pointer Update (TS_POINTERATTRIBUTE)
+fn read_new_pointer(buffer: &mut BytesMut) -> Result<PointerShape> {
+    check_remaining(buffer, 2, "POINTER: new pointer")?;
+    let xor_bpp = buffer.get_u16_le();
+    read_color_pointer(buffer, xor_bpp)
+}
+
+/// Read a TS_LARGEPOINTERATTRIBUTE structure
+/// MS-RDPBCGR 2.2.9.1.1.4.6 Large Pointer Update (TS_LARGEPOINTERATTRIBUTE)
+fn read_large_pointer(buffer: &mut BytesMut) -> Result<PointerShape> {
+    check_remaining(buffer, 20, "POINTER: large pointer")?;
+    let xor_bpp = buffer.get_u16_le();
+    let cache_index = buffer.get_u16_le();
+    let hotspot_x = buffer.get_u16_le();
+    let hotspot_y = buffer.get_u16_le();
+    let width = buffer.get_u16_le();
+    let height = buffer.get_u16_le();
+    let length_and_mask = buffer.get_u32_le() as usize;
+    let length_xor_mask = buffer.get_u32_le() as usize;
+    let xor_mask = read_mask(buffer, length_xor_mask, "POINTER: xor mask")?;
+    let and_mask = read_mask(buffer, length_and_mask, "POINTER: and mask")?;
+
+    Ok(PointerShape {
+        cache_index,
+        hotspot_x,
+        hotspot_y,
+        width,
+        height,
+        rgba: to_rgba(
+            width as usize,
+            height as usize,
+            xor_bpp,
+            &xor_mask,
+            &and_mask,
+        )?,
+    })
+}
+
+/// Read a pointer position
+fn read_position(buffer: &mut BytesMut) -> Result<PointerUpdate> {
+    check_remaining(buffer, 4, "POINTER: position")?;
+    Ok(PointerUpdate::Position {
+        x: buffer.get_u16_le(),
+        y: buffer.get_u16_le(),
+    })
+}
+
+/// Read a cache index
+fn read_cached(buffer: &mut BytesMut) -> Result<PointerUpdate> {
+    check_remaining(buffer, 2, "POINTER: cached pointer")?;
+    Ok(PointerUpdate::Cached(buffer.get_u16_le()))
+}
+
+/// Parse the payload of a slow path pointer update PDU
+/// MS-RDPBCGR 2.2.9.1.1.4 Server Pointer Update PDU (TS_POINTER_PDU)
+pub fn read_pointer_pdu(buffer: &mut BytesMut) -> Result<PointerUpdate> {
+    check_remaining(buffer, 4, "POINTER: pointer pdu")?;
+    let message_type = buffer.get_u16_le();
+    let _pad2_octets = buffer.get_u16_le();
+
+    match PointerMessageType::try_from(message_type) {
+        Ok(PointerMessageType::TsPtrmsgtypeSystem) => {
+            check_remaining(buffer, 4, "POINTER: system pointer")?;
+            if buffer.get_u32_le() == SystemPointerType::SysptrNull as u32 {
+                Ok(PointerUpdate::Hidden)
+            } else {
+                Ok(PointerUpdate::Default)
+            }
+        }
+        Ok(PointerMessageType::TsPtrmsgtypePosition) => read_position(buffer),
+        Ok(PointerMessageType::TsPtrmsgtypeColor) => Ok(PointerUpdate::Shape(
+            read_color_pointer(buffer, COLOR_POINTER_XOR_BPP)?,
+        )),
+        Ok(PointerMessageType::TsPtrmsgtypeCached) => read_cached(buffer),
+        Ok(PointerMessageType::TsPtrmsgtypePointer) => {
+            Ok(PointerUpdate::Shape(read_new_pointer(buffer)?))
+        }
+        Ok(PointerMessageType::TsPtrmsgtypeLarge) => {
+            Ok(PointerUpdate::Shape(read_large_pointer(buffer)?))
+        }
+        Err(_) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("POINTER: unknown pointer message type {}", message_type),
+        )),
+    }
+}
+
+/// Parse a fast path pointer update
+/// MS-RDPBCGR 2.2.9.1.2.1 Fast-Path Update (TS_FP_UPDATE)
+pub fn read_fast_path_pointer(
+    update_type: FastPathUpdateType,
+    buffer: &mut BytesMut,
+) -> Result<PointerUpdate> {
+    match update_type {
+        FastPathUpdateType::FastpathUpdatetypePtrNull => Ok(PointerUpdate::Hidden),
+        FastPathUpdateType::FastpathUpdatetypePtrDefault => Ok(PointerUpdate::Default),
+        FastPathUpdateType::FastpathUpdatetypePtrPosition => read_position(buffer),
+        FastPathUpdateType::FastpathUpdatetypeColor => Ok(PointerUpdate::Shape(
+            read_color_pointer(buffer, COLOR_POINTER_XOR_BPP)?,
+        )),
+        FastPathUpdateType::FastpathUpdatetypeCached => read_cached(buffer),
+        FastPathUpdateType::FastpathUpdatetypePointer => {
+            Ok(PointerUpdate::Shape(read_new_pointer(buffer)?))
+        }
+        FastPathUpdateType::FastpathUpdatetypeLargePointer => {
+            Ok(PointerUpdate::Shape(read_large_pointer(buffer)?))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("POINTER: {:?} is not a pointer update", update_type),
+        )),
+    }
+}
+
+/// Client side pointer cache
+/// Shapes are stored at the index chosen by the server
+pub struct PointerCache {
+    entries: Vec<Option<PointerShape>>,
+}
+
+impl PointerCache {
+    /// Cache size must match the pointer capability
+    pub fn new(size: u16) -> Self {
+        PointerCache {
+            entries: vec![None; size as usize],
+        }
+    }
+
+    /// Apply an update to the cache
+    /// and return the event to render
+    pub fn update(&mut self, update: PointerUpdate) -> Result<CursorEvent> {
+        match update {
+            PointerUpdate::Hidden => Ok(CursorEvent::Hidden),
+            PointerUpdate::Default => Ok(CursorEvent::Default),
+            PointerUpdate::Position { x, y } => Ok(CursorEvent::Position { x, y }),
+            PointerUpdate::Shape(shape) => {
+                if let Some(entry) = self.entries.get_mut(shape.cache_index as usize) {
+                    *entry = Some(shape.clone());
+                }
+                Ok(CursorEvent::Shape(shape))
+            }
+            PointerUpdate::Cached(index) => self
+                .entries
+                .get(index as usize)
+                .and_then(|entry| entry.clone())
+                .map(CursorEvent::Shape)
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("POINTER: no pointer in cache at index {}", index),
+                    )
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::BufMut;
+
+    /// A 2x2 24 bpp color pointer
+    /// Top line is red and transparent
+    /// Bottom line is black and inverted
+    fn color_pointer() -> BytesMut {
+        let mut buffer = BytesMut::new();
+        buffer.put_u16_le(PointerMessageType::TsPtrmsgtypeColor as u16);
+        buffer.put_u16_le(0);
+        for field in [1, 0, 1, 2, 2, 4, 12] {
+            buffer.put_u16_le(field);
+        }
+        // Bottom-up xor mask
+        buffer.put_slice(&[0, 0, 0, 0xFF, 0xFF, 0xFF]);
+        buffer.put_slice(&[0, 0, 0xFF, 0, 0, 0]);
+        // Bottom-up and mask with lines padded to 2 bytes
+        buffer.put_slice(&[0x40, 0, 0x40, 0]);
+        buffer
+    }
+
+    /// Test the RGBA conversion of a color pointer
+    #[test]
+    fn test_read_color_pointer() {
+        let shape = match read_pointer_pdu(&mut color_pointer()).unwrap() {
+            PointerUpdate::Shape(shape) => shape,
+            _ => panic!("expected a pointer shape"),
+        };
+        assert_eq!(shape.cache_index, 1);
+        assert_eq!(shape.hotspot_y, 1);
+        assert_eq!(
+            shape.rgba,
+            vec![0xFF, 0, 0, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF]
+        );
+    }
+
+    /// Cached pointers are resolved by the cache
+    #[test]
+    fn test_pointer_cache() {
+        let mut cache = PointerCache::new(4);
+        let shape = match cache.update(read_pointer_pdu(&mut color_pointer()).unwrap()) {
+            Ok(CursorEvent::Shape(shape)) => shape,
+            _ => panic!("expected a pointer shape"),
+        };
+        assert_eq!(
+            cache.update(PointerUpdate::Cached(1)).unwrap(),
+            CursorEvent::Shape(shape)
+        );
+        assert!(cache.update(PointerUpdate::Cached(2)).is_err());
+    }
+
+    /// Test of fast path system pointers
+    #[test]
+    fn test_read_fast_path_pointer() {
+        let mut buffer = BytesMut::new();
+        assert_eq!(
+            read_fast_path_pointer(FastPathUpdateType::FastpathUpdatetypePtrNull, &mut buffer)
+                .unwrap(),
+            PointerUpdate::Hidden
+        );
+    }
+}
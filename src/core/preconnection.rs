@@ -0,0 +1,154 @@
+use crate::model::data::Message;
+use crate::model::unicode::{from_unicode, Unicode};
+
+use async_trait::async_trait;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Port of the VMConnect service of Hyper-V hosts
+pub const VMCONNECT_PORT: u16 = 2179;
+
+/// Version of the preconnection PDU
+/// MS-RDPBCGR 2.2.14.1 RDP_PRECONNECTION_PDU_V1
+const PRECONNECTION_PDU_V1: u32 = 0x00000001;
+const PRECONNECTION_PDU_V2: u32 = 0x00000002;
+
+/// Sent on the raw transport before the X224 connection request,
+/// so that a broker or a Hyper-V host can route the connection
+/// MS-RDPBCGR 2.2.14 Preconnection PDU
+///
+/// # Example
+/// ```
+/// use rdp::core::preconnection::PreconnectionPdu;
+/// let pdu = PreconnectionPdu::vmconnect("39418f90-6d03-468e-b796-91c60e430220", true);
+/// assert_eq!(
+///     pdu.blob.as_deref(),
+///     Some("39418f90-6d03-468e-b796-91c60e430220;EnhancedMode=1")
+/// );
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct PreconnectionPdu {
+    /// Identifier of the target, like a session id
+    pub id: u32,
+    /// String of the version 2, the version 1 only carries the id
+    pub blob: Option<String>,
+}
+
+impl PreconnectionPdu {
+    /// Version 1 PDU
+    pub fn new(id: u32) -> Self {
+        PreconnectionPdu { id, blob: None }
+    }
+
+    /// Version 2 PDU, with a string
+    pub fn with_blob(id: u32, blob: &str) -> Self {
+        PreconnectionPdu {
+            id,
+            blob: Some(blob.to_string()),
+        }
+    }
+
+    /// The Hyper-V host routes the connection to the VM with this id
+    /// The enhanced mode connects to the guest RDP server
+    /// instead of the console of the VM
+    pub fn vmconnect(vm_id: &str, enhanced_mode: bool) -> Self {
+        let blob = match enhanced_mode {
+            true => format!("{};EnhancedMode=1", vm_id),
+            false => vm_id.to_string(),
+        };
+        PreconnectionPdu::with_blob(0, &blob)
+    }
+
+    /// Blob encoded with its null character
+    fn unicode_blob(&self) -> Option<Vec<u8>> {
+        self.blob.as_ref().map(|blob| {
+            let mut unicode = blob.to_unicode();
+            unicode.extend_from_slice(&[0, 0]);
+            unicode
+        })
+    }
+}
+
+#[async_trait]
+impl Message for PreconnectionPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.length() as u32).await?;
+        // Flags
+        writer.write_u32_le(0).await?;
+        match self.unicode_blob() {
+            Some(blob) => {
+                writer.write_u32_le(PRECONNECTION_PDU_V2).await?;
+                writer.write_u32_le(self.id).await?;
+                writer.write_u16_le((blob.len() / 2) as u16).await?;
+                writer.write_all(&blob).await
+            }
+            None => {
+                writer.write_u32_le(PRECONNECTION_PDU_V1).await?;
+                writer.write_u32_le(self.id).await
+            }
+        }
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let size = reader.read_u32_le().await? as usize;
+        let _flags = reader.read_u32_le().await?;
+        let version = reader.read_u32_le().await?;
+        self.id = reader.read_u32_le().await?;
+        self.blob = None;
+        let mut read = 16;
+        match version {
+            PRECONNECTION_PDU_V1 => (),
+            PRECONNECTION_PDU_V2 => {
+                let count = reader.read_u16_le().await? as usize;
+                let mut blob = vec![0u8; count * 2];
+                reader.read_exact(&mut blob).await?;
+                self.blob = Some(from_unicode(&blob));
+                read += 2 + blob.len();
+            }
+            version => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("PRECONNECTION: unexpected version {}", version),
+                ))
+            }
+        }
+        // Later versions may add fields
+        let mut rest = vec![0u8; size.saturating_sub(read)];
+        reader.read_exact(&mut rest).await?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        16 + self.unicode_blob().map_or(0, |blob| 2 + blob.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Test of the layouts of both versions
+    #[tokio::test]
+    async fn test_preconnection_pdu() {
+        assert_eq!(
+            to_vec(&PreconnectionPdu::new(7)).await.unwrap(),
+            [16, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 7, 0, 0, 0]
+        );
+        let pdu = PreconnectionPdu::with_blob(0, "vm");
+        let bytes = to_vec(&pdu).await.unwrap();
+        assert_eq!(
+            bytes,
+            [24, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 3, 0, b'v', 0, b'm', 0, 0, 0]
+        );
+
+        let mut result = PreconnectionPdu::default();
+        result.read_from(&mut &bytes[..]).await.unwrap();
+        assert_eq!(result, pdu);
+    }
+}
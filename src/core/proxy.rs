@@ -0,0 +1,277 @@
+use crate::core::gcc::KeyboardLayout;
+use crate::core::mcs::client::McsClient;
+use crate::core::sec::base::{ClientInfoPdu, SecurityFlag, SecurityHeader};
+use crate::core::sec::client::SecClient;
+use crate::core::server::ServerSession;
+use crate::core::tpkt::base::Payload;
+use crate::core::tpkt::client::TpktClient;
+use crate::core::x224::base::{ConnectionRequestOptions, Protocols, SecurityPolicy};
+use crate::core::x224::client::X224Client;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::model::link::TlsAcceptor;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::nla::ntlm::{Ntlm, NtlmAcceptor};
+
+use std::io::{ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Way of a relayed PDU
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+/// Hooks called by the proxy
+/// All hooks do nothing by default
+pub trait ProxyTap: Send {
+    /// Called with the client info PDU before the connection
+    /// to the server, credentials can be replaced here
+    fn on_client_info(&mut self, _info: &mut ClientInfoPdu) {}
+
+    /// Called for each PDU before it is relayed
+    ///
+    /// Message channel payloads start with their security header
+    /// Fast path input payloads start with the number of events
+    fn on_pdu(&mut self, _direction: Direction, _channel_name: &str, _payload: &Payload) {}
+}
+
+/// Man in the middle proxy
+///
+/// The client connection is accepted by a ServerSession
+/// and a new connection to the server is made with the
+/// captured client data, channels and credentials
+/// Then all PDUs are relayed between the two connections
+///
+/// TLS and NLA are terminated by the proxy with its own
+/// acceptors and started again with the server, the password
+/// delegated by NLA is used for the server NLA
+///
+/// # Example
+/// ```rust, ignore
+/// struct Logger;
+/// impl ProxyTap for Logger {
+///     fn on_client_info(&mut self, info: &mut ClientInfoPdu) {
+///         println!("{}\\{}", info.domain, info.username);
+///     }
+/// }
+///
+/// let (client, _) = listener.accept().await?;
+/// let server = TcpStream::connect("10.0.0.1:3389").await?;
+/// RdpProxy::new(policy)
+///     .tls(TlsAcceptor::from_pem(&certificate, &key)?)
+///     .nla(NtlmAcceptor::new("DOMAIN", "PROXY").passwords(|_, _| Some(password.clone())))
+///     .tap(Logger)
+///     .relay(client, server)
+///     .await?;
+/// ```
+pub struct RdpProxy {
+    /// Security protocols accepted from the client
+    policy: SecurityPolicy,
+    /// Certificate presented to the client
+    #[cfg(not(target_arch = "wasm32"))]
+    tls: Option<TlsAcceptor>,
+    /// Authentication of the client NLA
+    #[cfg(not(target_arch = "wasm32"))]
+    nla: Option<NtlmAcceptor>,
+    /// Hooks called in order
+    taps: Vec<Box<dyn ProxyTap>>,
+}
+
+impl RdpProxy {
+    pub fn new(policy: SecurityPolicy) -> Self {
+        RdpProxy {
+            policy,
+            #[cfg(not(target_arch = "wasm32"))]
+            tls: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            nla: None,
+            taps: Vec::new(),
+        }
+    }
+
+    /// Accept SSL from the client with this certificate
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tls(mut self, acceptor: TlsAcceptor) -> Self {
+        self.tls = Some(acceptor);
+        self
+    }
+
+    /// Accept NLA from the client, SSL must be accepted too
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn nla(mut self, acceptor: NtlmAcceptor) -> Self {
+        self.nla = Some(acceptor);
+        self
+    }
+
+    /// Add a hook called after the previous ones
+    pub fn tap(mut self, tap: impl ProxyTap + 'static) -> Self {
+        self.taps.push(Box::new(tap));
+        self
+    }
+
+    /// Accept the client, connect to the server
+    /// and relay PDUs until one of them disconnects
+    pub async fn relay<C, S>(&mut self, client: C, server: S) -> Result<()>
+    where
+        C: AsyncRead + AsyncWrite + Unpin + Send,
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut session =
+            ServerSession::accept_with(client, &self.policy, self.tls.as_ref(), self.nla.as_ref())
+                .await?;
+        #[cfg(target_arch = "wasm32")]
+        let mut session = ServerSession::accept(client, &self.policy).await?;
+        let mut sec = self.connect(&session, server).await?;
+
+        // Reads are cancel safe, the pending one is restarted on the next loop
+        // The server PDUs are processed once received as it may write
+        loop {
+            tokio::select! {
+                result = session.read() => {
+                    let (channel_name, payload) = match result {
+                        Err(e) if e.kind() == ErrorKind::ConnectionAborted => {
+                            return sec.shutdown().await
+                        }
+                        result => result?,
+                    };
+                    self.on_pdu(Direction::ClientToServer, &channel_name, &payload);
+                    Self::write_server(&mut sec, &channel_name, payload).await?;
+                }
+                result = sec.receive() => {
+                    let (channel_name, payload) = match result {
+                        Err(e) if e.kind() == ErrorKind::ConnectionAborted => {
+                            return session.shutdown().await
+                        }
+                        result => {
+                            let (channel_name, payload) = result?;
+                            sec.process(channel_name, payload).await?
+                        }
+                    };
+                    self.on_pdu(Direction::ServerToClient, &channel_name, &payload);
+                    match payload {
+                        Payload::Raw(_) if !session.has_channel(&channel_name) => (),
+                        Payload::Raw(payload) => {
+                            session.write(&channel_name, payload.to_vec()).await?
+                        }
+                        Payload::FastPath(sec_flag, payload) => {
+                            session.write_fast_path(sec_flag, payload.to_vec()).await?
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Connect to the server as the client did to the proxy
+    ///
+    /// The credentials, once given to the taps, are
+    /// also the ones of the NLA with the server
+    async fn connect<C, S>(&mut self, session: &ServerSession<C>, server: S) -> Result<SecClient<S>>
+    where
+        C: AsyncRead + AsyncWrite + Unpin + Send,
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let mut info = session.get_client_info().clone();
+        // NLA clients may only give their password to CredSSP
+        if let Some(password) = session
+            .get_nla_credentials()
+            .and_then(|c| c.password.as_ref())
+        {
+            if info.password.is_empty() {
+                info.password = password.clone();
+            }
+        }
+        for tap in self.taps.iter_mut() {
+            tap.on_client_info(&mut info);
+        }
+
+        let request = session.get_x224().get_connection_request();
+        let options = ConnectionRequestOptions {
+            routing: request.routing.clone(),
+            correlation_info: request.correlation_info,
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let x224 = X224Client::connect(
+            TpktClient::new(server),
+            Protocols::ProtocolSSL as u32 | Protocols::ProtocolHybrid as u32,
+            false,
+            Some(&mut Ntlm::new(
+                info.domain.clone(),
+                info.username.clone(),
+                info.password.clone(),
+            )),
+            false,
+            false,
+            options,
+        )
+        .await?;
+        #[cfg(target_arch = "wasm32")]
+        let x224 = X224Client::connect(
+            TpktClient::new(server),
+            Protocols::ProtocolRDP as u32,
+            false,
+            None,
+            false,
+            false,
+            options,
+        )
+        .await?;
+
+        // Same static channels as the client
+        let client_data = session.get_client_data();
+        let channels: Vec<String> = client_data
+            .network
+            .iter()
+            .flat_map(|n| &n.channel_def_array)
+            .map(|c| c.get_name())
+            .collect();
+        let core = &client_data.core;
+        let mcs = McsClient::connect_with_channels(
+            x224,
+            &core.get_client_name(),
+            core.desktop_width,
+            core.desktop_height,
+            KeyboardLayout::try_from(core.keyboard_layout).unwrap_or(KeyboardLayout::US),
+            &channels,
+        )
+        .await?;
+
+        SecClient::connect(mcs, info).await
+    }
+
+    /// Relay a client PDU to the server
+    ///
+    /// The auto detect responses are dropped as they
+    /// are already sent by the proxy itself
+    async fn write_server<S>(
+        sec: &mut SecClient<S>,
+        channel_name: &str,
+        payload: Payload,
+    ) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        match payload {
+            Payload::FastPath(_, payload) => sec.write_fast_path(payload.to_vec()).await,
+            Payload::Raw(_) if !sec.get_mcs().has_channel(channel_name) => Ok(()),
+            Payload::Raw(mut payload) if channel_name == "message" => {
+                let mut header = SecurityHeader::default();
+                header.read_from_buffer(&mut payload)?;
+                if header.flags & SecurityFlag::SecAutodetectRsp as u16 != 0 {
+                    return Ok(());
+                }
+                sec.write_with_flags(channel_name, header.flags, payload.to_vec())
+                    .await
+            }
+            Payload::Raw(payload) => sec.write(channel_name, payload.to_vec()).await,
+        }
+    }
+
+    fn on_pdu(&mut self, direction: Direction, channel_name: &str, payload: &Payload) {
+        for tap in self.taps.iter_mut() {
+            tap.on_pdu(direction, channel_name, payload);
+        }
+    }
+}
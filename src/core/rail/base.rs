@@ -0,0 +1,829 @@
+use crate::core::order::window::WindowRect;
+use crate::model::data::{check_remaining, Message};
+use crate::model::unicode::{from_unicode, Unicode};
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the static virtual channel of RemoteApp
+pub const RAIL_CHANNEL_NAME: &str = "rail";
+
+/// Size of the TS_RAIL_PDU_HEADER
+const HEADER_SIZE: usize = 4;
+
+/// Size of the application id of the get application id response
+/// MS-RDPERP 2.2.2.8.1 Server Get Application ID Response PDU (TS_RAIL_ORDER_GET_APPID_RESP)
+const APPLICATION_ID_SIZE: usize = 520;
+
+/// Type of a RemoteApp PDU
+/// MS-RDPERP 2.2.2.1 Common Header (TS_RAIL_PDU_HEADER)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum RailOrderType {
+    TsRailOrderExec = 0x0001,
+    TsRailOrderActivate = 0x0002,
+    TsRailOrderSysparam = 0x0003,
+    TsRailOrderSyscommand = 0x0004,
+    TsRailOrderHandshake = 0x0005,
+    TsRailOrderNotifyEvent = 0x0006,
+    TsRailOrderWindowmove = 0x0008,
+    TsRailOrderLocalmovesize = 0x0009,
+    TsRailOrderMinmaxinfo = 0x000A,
+    TsRailOrderClientstatus = 0x000B,
+    TsRailOrderSysmenu = 0x000C,
+    TsRailOrderLangbarinfo = 0x000D,
+    TsRailOrderGetAppidReq = 0x000E,
+    TsRailOrderGetAppidResp = 0x000F,
+    TsRailOrderHandshakeEx = 0x0013,
+    TsRailOrderZorderSync = 0x0014,
+    TsRailOrderCloak = 0x0015,
+    TsRailOrderExecResult = 0x0080,
+}
+
+/// Features of the client
+/// MS-RDPERP 2.2.2.2.2 Client Information PDU (TS_RAIL_ORDER_CLIENTSTATUS)
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClientStatusFlag {
+    TsRailClientstatusAllowlocalmovesize = 0x00000001,
+    TsRailClientstatusAutoreconnect = 0x00000002,
+    TsRailClientstatusZorderSync = 0x00000004,
+    TsRailClientstatusWindowResizeMarginSupported = 0x00000010,
+    TsRailClientstatusHighDpiIconsSupported = 0x00000020,
+    TsRailClientstatusAppbarRemotingSupported = 0x00000040,
+    TsRailClientstatusPowerDisplayRequestSupported = 0x00000080,
+    TsRailClientstatusBidirectionalCloakSupported = 0x00000200,
+}
+
+/// How the program of an exec PDU is started
+/// MS-RDPERP 2.2.2.3.1 Client Execute PDU (TS_RAIL_ORDER_EXEC)
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExecFlag {
+    TsRailExecFlagExpandWorkingdirectory = 0x0001,
+    TsRailExecFlagTranslateFiles = 0x0002,
+    TsRailExecFlagFile = 0x0004,
+    TsRailExecFlagExpandArguments = 0x0008,
+    TsRailExecFlagAppUserModelId = 0x0010,
+}
+
+/// Result of an exec PDU
+/// MS-RDPERP 2.2.2.3.2 Server Execute Result PDU (TS_RAIL_ORDER_EXEC_RESULT)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum ExecResult {
+    RailExecSuccess = 0x0000,
+    RailExecErrorHookNotLoaded = 0x0001,
+    RailExecErrorDecodeFailed = 0x0002,
+    RailExecErrorNotInAllowlist = 0x0003,
+    RailExecErrorFileNotFound = 0x0005,
+    RailExecErrorFail = 0x0006,
+    RailExecErrorSessionLocked = 0x0007,
+}
+
+/// Window commands of the system menu
+/// MS-RDPERP 2.2.2.6.3 Client System Command PDU (TS_RAIL_ORDER_SYSCOMMAND)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum SysCommand {
+    ScSize = 0xF000,
+    ScMove = 0xF010,
+    ScMinimize = 0xF020,
+    ScMaximize = 0xF030,
+    ScClose = 0xF060,
+    ScKeymenu = 0xF100,
+    ScRestore = 0xF120,
+    ScDefault = 0xF160,
+}
+
+/// Ids of the system parameters
+/// MS-RDPERP 2.2.2.4.1 Client System Parameters Update PDU (TS_RAIL_ORDER_SYSPARAM)
+pub const SPI_SETMOUSEBUTTONSWAP: u32 = 0x00000021;
+pub const SPI_SETDRAGFULLWINDOWS: u32 = 0x00000025;
+pub const SPI_SETWORKAREA: u32 = 0x0000002F;
+pub const SPI_SETHIGHCONTRAST: u32 = 0x00000043;
+pub const SPI_SETKEYBOARDPREF: u32 = 0x00000045;
+pub const SPI_SETKEYBOARDCUES: u32 = 0x0000100B;
+pub const RAIL_SPI_TASKBARPOS: u32 = 0x0000F000;
+pub const RAIL_SPI_DISPLAYCHANGE: u32 = 0x0000F001;
+/// Sent by the server
+/// MS-RDPERP 2.2.2.5.1 Server System Parameters Update PDU (TS_RAIL_ORDER_SYSPARAM)
+pub const SPI_SETSCREENSAVEACTIVE: u32 = 0x00000011;
+pub const SPI_SETSCREENSAVESECURE: u32 = 0x00000077;
+
+/// Value of a system parameter
+/// Flags are sent by the parameters with a boolean value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SysParamValue {
+    Flag(bool),
+    Rect(WindowRect),
+    /// MS-RDPERP 2.2.1.2.4 High Contrast System Information Structure (TS_HIGHCONTRAST)
+    HighContrast {
+        flags: u32,
+        color_scheme: String,
+    },
+    /// Parameters unknown to the client
+    Raw(Vec<u8>),
+}
+
+impl SysParamValue {
+    fn read(param: u32, buffer: &mut BytesMut) -> Result<Self> {
+        Ok(match param {
+            SPI_SETMOUSEBUTTONSWAP
+            | SPI_SETDRAGFULLWINDOWS
+            | SPI_SETKEYBOARDPREF
+            | SPI_SETKEYBOARDCUES
+            | SPI_SETSCREENSAVEACTIVE
+            | SPI_SETSCREENSAVESECURE => {
+                check_remaining(buffer, 1, "RAIL: system parameter")?;
+                SysParamValue::Flag(buffer.get_u8() != 0)
+            }
+            SPI_SETWORKAREA | RAIL_SPI_TASKBARPOS | RAIL_SPI_DISPLAYCHANGE => {
+                SysParamValue::Rect(WindowRect::read(buffer)?)
+            }
+            SPI_SETHIGHCONTRAST => {
+                check_remaining(buffer, 8, "RAIL: high contrast")?;
+                let flags = buffer.get_u32_le();
+                let length = buffer.get_u32_le() as usize;
+                check_remaining(buffer, length, "RAIL: high contrast")?;
+                SysParamValue::HighContrast {
+                    flags,
+                    color_scheme: from_unicode(&buffer.split_to(length)),
+                }
+            }
+            _ => SysParamValue::Raw(buffer.split().to_vec()),
+        })
+    }
+
+    fn write(&self, buffer: &mut Vec<u8>) {
+        match self {
+            SysParamValue::Flag(value) => buffer.put_u8(*value as u8),
+            SysParamValue::Rect(rect) => rect.write(buffer),
+            SysParamValue::HighContrast {
+                flags,
+                color_scheme,
+            } => {
+                // The color scheme is null terminated
+                let mut name = color_scheme.to_unicode();
+                name.extend_from_slice(&[0, 0]);
+                buffer.put_u32_le(*flags);
+                buffer.put_u32_le(name.len() as u32);
+                buffer.extend_from_slice(&name);
+            }
+            SysParamValue::Raw(data) => buffer.extend_from_slice(data),
+        }
+    }
+
+    fn length(&self) -> usize {
+        match self {
+            SysParamValue::Flag(_) => 1,
+            SysParamValue::Rect(_) => 8,
+            SysParamValue::HighContrast { color_scheme, .. } => {
+                8 + color_scheme.to_unicode().len() + 2
+            }
+            SysParamValue::Raw(data) => data.len(),
+        }
+    }
+}
+
+/// PDU of the RemoteApp channel, sent by both sides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RailPdu {
+    /// Build number of the sender
+    /// MS-RDPERP 2.2.2.2.1 Handshake PDU (TS_RAIL_ORDER_HANDSHAKE)
+    Handshake(u32),
+    /// MS-RDPERP 2.2.2.2.3 HandshakeEx PDU (TS_RAIL_ORDER_HANDSHAKE_EX)
+    HandshakeEx { build_number: u32, flags: u32 },
+    /// ClientStatusFlag of the client
+    /// MS-RDPERP 2.2.2.2.2 Client Information PDU (TS_RAIL_ORDER_CLIENTSTATUS)
+    ClientStatus(u32),
+    /// MS-RDPERP 2.2.2.3.1 Client Execute PDU (TS_RAIL_ORDER_EXEC)
+    Exec {
+        flags: u16,
+        exe_or_file: String,
+        working_dir: String,
+        arguments: String,
+    },
+    /// MS-RDPERP 2.2.2.3.2 Server Execute Result PDU (TS_RAIL_ORDER_EXEC_RESULT)
+    ExecResult {
+        flags: u16,
+        result: u16,
+        raw_result: u32,
+        exe_or_file: String,
+    },
+    /// MS-RDPERP 2.2.2.4.1 Client System Parameters Update PDU (TS_RAIL_ORDER_SYSPARAM)
+    /// MS-RDPERP 2.2.2.5.1 Server System Parameters Update PDU (TS_RAIL_ORDER_SYSPARAM)
+    SysParam { param: u32, value: SysParamValue },
+    /// MS-RDPERP 2.2.2.6.1 Client Activate PDU (TS_RAIL_ORDER_ACTIVATE)
+    Activate { window_id: u32, enabled: bool },
+    /// MS-RDPERP 2.2.2.6.2 Client System Menu PDU (TS_RAIL_ORDER_SYSMENU)
+    SysMenu { window_id: u32, left: i16, top: i16 },
+    /// MS-RDPERP 2.2.2.6.3 Client System Command PDU (TS_RAIL_ORDER_SYSCOMMAND)
+    SysCommand { window_id: u32, command: u16 },
+    /// Mouse or keyboard message of a notification icon
+    /// MS-RDPERP 2.2.2.6.4 Client Notify Event PDU (TS_RAIL_ORDER_NOTIFY_EVENT)
+    NotifyEvent {
+        window_id: u32,
+        notify_icon_id: u32,
+        message: u32,
+    },
+    /// MS-RDPERP 2.2.2.6.5 Client Get Application ID PDU (TS_RAIL_ORDER_GET_APPID_REQ)
+    GetAppIdRequest(u32),
+    /// MS-RDPERP 2.2.2.8.1 Server Get Application ID Response PDU (TS_RAIL_ORDER_GET_APPID_RESP)
+    GetAppIdResponse {
+        window_id: u32,
+        application_id: String,
+    },
+    /// MS-RDPERP 2.2.2.7.1 Server Min Max Info PDU (TS_RAIL_ORDER_MINMAXINFO)
+    MinMaxInfo {
+        window_id: u32,
+        max_width: i16,
+        max_height: i16,
+        max_pos_x: i16,
+        max_pos_y: i16,
+        min_track_width: i16,
+        min_track_height: i16,
+        max_track_width: i16,
+        max_track_height: i16,
+    },
+    /// Start or end of a move or resize driven by the client
+    /// MS-RDPERP 2.2.2.7.2 Server Move/Size Start PDU (TS_RAIL_ORDER_LOCALMOVESIZE)
+    /// MS-RDPERP 2.2.2.7.3 Server Move/Size End PDU (TS_RAIL_ORDER_LOCALMOVESIZE)
+    LocalMoveSize {
+        window_id: u32,
+        start: bool,
+        move_size_type: u16,
+        x: i16,
+        y: i16,
+    },
+    /// New position of a window moved by the client
+    /// MS-RDPERP 2.2.2.7.4 Client Window Move PDU (TS_RAIL_ORDER_WINDOWMOVE)
+    WindowMove {
+        window_id: u32,
+        left: i16,
+        top: i16,
+        right: i16,
+        bottom: i16,
+    },
+    /// MS-RDPERP 2.2.2.9.1 Language Bar Information PDU (TS_RAIL_ORDER_LANGBARINFO)
+    LangBarInfo(u32),
+    /// Window above which the local windows are
+    /// MS-RDPERP 2.2.2.11.1 Server Z-Order Sync Information PDU (TS_RAIL_ORDER_ZORDER_SYNC)
+    ZOrderSync(u32),
+    /// MS-RDPERP 2.2.2.12.1 Window Cloak State Change PDU (TS_RAIL_ORDER_CLOAK)
+    Cloak { window_id: u32, cloaked: bool },
+}
+
+impl RailPdu {
+    fn order_type(&self) -> RailOrderType {
+        match self {
+            RailPdu::Handshake(_) => RailOrderType::TsRailOrderHandshake,
+            RailPdu::HandshakeEx { .. } => RailOrderType::TsRailOrderHandshakeEx,
+            RailPdu::ClientStatus(_) => RailOrderType::TsRailOrderClientstatus,
+            RailPdu::Exec { .. } => RailOrderType::TsRailOrderExec,
+            RailPdu::ExecResult { .. } => RailOrderType::TsRailOrderExecResult,
+            RailPdu::SysParam { .. } => RailOrderType::TsRailOrderSysparam,
+            RailPdu::Activate { .. } => RailOrderType::TsRailOrderActivate,
+            RailPdu::SysMenu { .. } => RailOrderType::TsRailOrderSysmenu,
+            RailPdu::SysCommand { .. } => RailOrderType::TsRailOrderSyscommand,
+            RailPdu::NotifyEvent { .. } => RailOrderType::TsRailOrderNotifyEvent,
+            RailPdu::GetAppIdRequest(_) => RailOrderType::TsRailOrderGetAppidReq,
+            RailPdu::GetAppIdResponse { .. } => RailOrderType::TsRailOrderGetAppidResp,
+            RailPdu::MinMaxInfo { .. } => RailOrderType::TsRailOrderMinmaxinfo,
+            RailPdu::LocalMoveSize { .. } => RailOrderType::TsRailOrderLocalmovesize,
+            RailPdu::WindowMove { .. } => RailOrderType::TsRailOrderWindowmove,
+            RailPdu::LangBarInfo(_) => RailOrderType::TsRailOrderLangbarinfo,
+            RailPdu::ZOrderSync(_) => RailOrderType::TsRailOrderZorderSync,
+            RailPdu::Cloak { .. } => RailOrderType::TsRailOrderCloak,
+        }
+    }
+
+    /// Body of the PDU, without the header
+    fn body(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        match self {
+            RailPdu::Handshake(value)
+            | RailPdu::ClientStatus(value)
+            | RailPdu::GetAppIdRequest(value)
+            | RailPdu::LangBarInfo(value)
+            | RailPdu::ZOrderSync(value) => buffer.put_u32_le(*value),
+            RailPdu::HandshakeEx {
+                build_number,
+                flags,
+            } => {
+                buffer.put_u32_le(*build_number);
+                buffer.put_u32_le(*flags);
+            }
+            RailPdu::Exec {
+                flags,
+                exe_or_file,
+                working_dir,
+                arguments,
+            } => {
+                let exe_or_file = exe_or_file.to_unicode();
+                let working_dir = working_dir.to_unicode();
+                let arguments = arguments.to_unicode();
+                buffer.put_u16_le(*flags);
+                buffer.put_u16_le(exe_or_file.len() as u16);
+                buffer.put_u16_le(working_dir.len() as u16);
+                buffer.put_u16_le(arguments.len() as u16);
+                buffer.extend_from_slice(&exe_or_file);
+                buffer.extend_from_slice(&working_dir);
+                buffer.extend_from_slice(&arguments);
+            }
+            RailPdu::ExecResult {
+                flags,
+                result,
+                raw_result,
+                exe_or_file,
+            } => {
+                let exe_or_file = exe_or_file.to_unicode();
+                buffer.put_u16_le(*flags);
+                buffer.put_u16_le(*result);
+                buffer.put_u32_le(*raw_result);
+                buffer.put_u16_le(0);
+                buffer.put_u16_le(exe_or_file.len() as u16);
+                buffer.extend_from_slice(&exe_or_file);
+            }
+            RailPdu::SysParam { param, value } => {
+                buffer.put_u32_le(*param);
+                value.write(&mut buffer);
+            }
+            RailPdu::Activate { window_id, enabled } => {
+                buffer.put_u32_le(*window_id);
+                buffer.put_u8(*enabled as u8);
+            }
+            RailPdu::SysMenu {
+                window_id,
+                left,
+                top,
+            } => {
+                buffer.put_u32_le(*window_id);
+                buffer.put_i16_le(*left);
+                buffer.put_i16_le(*top);
+            }
+            RailPdu::SysCommand { window_id, command } => {
+                buffer.put_u32_le(*window_id);
+                buffer.put_u16_le(*command);
+            }
+            RailPdu::NotifyEvent {
+                window_id,
+                notify_icon_id,
+                message,
+            } => {
+                buffer.put_u32_le(*window_id);
+                buffer.put_u32_le(*notify_icon_id);
+                buffer.put_u32_le(*message);
+            }
+            RailPdu::GetAppIdResponse {
+                window_id,
+                application_id,
+            } => {
+                let mut application_id = application_id.to_unicode();
+                application_id.resize(APPLICATION_ID_SIZE, 0);
+                buffer.put_u32_le(*window_id);
+                buffer.extend_from_slice(&application_id);
+            }
+            RailPdu::MinMaxInfo {
+                window_id,
+                max_width,
+                max_height,
+                max_pos_x,
+                max_pos_y,
+                min_track_width,
+                min_track_height,
+                max_track_width,
+                max_track_height,
+            } => {
+                buffer.put_u32_le(*window_id);
+                for value in [
+                    max_width,
+                    max_height,
+                    max_pos_x,
+                    max_pos_y,
+                    min_track_width,
+                    min_track_height,
+                    max_track_width,
+                    max_track_height,
+                ] {
+                    buffer.put_i16_le(*value);
+                }
+            }
+            RailPdu::LocalMoveSize {
+                window_id,
+                start,
+                move_size_type,
+                x,
+                y,
+            } => {
+                buffer.put_u32_le(*window_id);
+                buffer.put_u16_le(*start as u16);
+                buffer.put_u16_le(*move_size_type);
+                buffer.put_i16_le(*x);
+                buffer.put_i16_le(*y);
+            }
+            RailPdu::WindowMove {
+                window_id,
+                left,
+                top,
+                right,
+                bottom,
+            } => {
+                buffer.put_u32_le(*window_id);
+                buffer.put_i16_le(*left);
+                buffer.put_i16_le(*top);
+                buffer.put_i16_le(*right);
+                buffer.put_i16_le(*bottom);
+            }
+            RailPdu::Cloak { window_id, cloaked } => {
+                buffer.put_u32_le(*window_id);
+                buffer.put_u8(*cloaked as u8);
+            }
+        }
+        buffer
+    }
+
+    fn body_length(&self) -> usize {
+        match self {
+            RailPdu::Handshake(_)
+            | RailPdu::ClientStatus(_)
+            | RailPdu::GetAppIdRequest(_)
+            | RailPdu::LangBarInfo(_)
+            | RailPdu::ZOrderSync(_) => 4,
+            RailPdu::HandshakeEx { .. } => 8,
+            RailPdu::Exec {
+                exe_or_file,
+                working_dir,
+                arguments,
+                ..
+            } => {
+                8 + exe_or_file.to_unicode().len()
+                    + working_dir.to_unicode().len()
+                    + arguments.to_unicode().len()
+            }
+            RailPdu::ExecResult { exe_or_file, .. } => 12 + exe_or_file.to_unicode().len(),
+            RailPdu::SysParam { value, .. } => 4 + value.length(),
+            RailPdu::Activate { .. } | RailPdu::Cloak { .. } => 5,
+            RailPdu::SysMenu { .. } => 8,
+            RailPdu::SysCommand { .. } => 6,
+            RailPdu::NotifyEvent { .. } => 12,
+            RailPdu::GetAppIdResponse { .. } => 4 + APPLICATION_ID_SIZE,
+            RailPdu::MinMaxInfo { .. } => 20,
+            RailPdu::LocalMoveSize { .. } | RailPdu::WindowMove { .. } => 12,
+        }
+    }
+}
+
+fn read_string(buffer: &mut BytesMut, length: usize) -> Result<String> {
+    check_remaining(buffer, length, "RAIL: string")?;
+    Ok(from_unicode(&buffer.split_to(length)))
+}
+
+/// Read a RemoteApp PDU with its header
+pub fn read_rail_pdu(buffer: &mut BytesMut) -> Result<RailPdu> {
+    check_remaining(buffer, HEADER_SIZE, "RAIL: header")?;
+    let order_type = buffer.get_u16_le();
+    let order_length = buffer.get_u16_le() as usize;
+    let length = order_length.saturating_sub(HEADER_SIZE);
+    check_remaining(buffer, length, "RAIL: PDU")?;
+    let mut body = buffer.split_to(length);
+    let body = &mut body;
+
+    let order_type = RailOrderType::try_from(order_type).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("RAIL: unexpected order type {}", order_type),
+        )
+    })?;
+    let size = match order_type {
+        RailOrderType::TsRailOrderExec | RailOrderType::TsRailOrderHandshakeEx => 8,
+        RailOrderType::TsRailOrderExecResult => 12,
+        RailOrderType::TsRailOrderActivate | RailOrderType::TsRailOrderCloak => 5,
+        RailOrderType::TsRailOrderSysmenu => 8,
+        RailOrderType::TsRailOrderSyscommand => 6,
+        RailOrderType::TsRailOrderNotifyEvent => 12,
+        RailOrderType::TsRailOrderGetAppidResp => 4 + APPLICATION_ID_SIZE,
+        RailOrderType::TsRailOrderMinmaxinfo => 20,
+        RailOrderType::TsRailOrderLocalmovesize | RailOrderType::TsRailOrderWindowmove => 12,
+        _ => 4,
+    };
+    check_remaining(body, size, "RAIL: PDU")?;
+
+    Ok(match order_type {
+        RailOrderType::TsRailOrderHandshake => RailPdu::Handshake(body.get_u32_le()),
+        RailOrderType::TsRailOrderHandshakeEx => RailPdu::HandshakeEx {
+            build_number: body.get_u32_le(),
+            flags: body.get_u32_le(),
+        },
+        RailOrderType::TsRailOrderClientstatus => RailPdu::ClientStatus(body.get_u32_le()),
+        RailOrderType::TsRailOrderExec => {
+            let flags = body.get_u16_le();
+            let exe_or_file_length = body.get_u16_le() as usize;
+            let working_dir_length = body.get_u16_le() as usize;
+            let arguments_length = body.get_u16_le() as usize;
+            RailPdu::Exec {
+                flags,
+                exe_or_file: read_string(body, exe_or_file_length)?,
+                working_dir: read_string(body, working_dir_length)?,
+                arguments: read_string(body, arguments_length)?,
+            }
+        }
+        RailOrderType::TsRailOrderExecResult => {
+            let flags = body.get_u16_le();
+            let result = body.get_u16_le();
+            let raw_result = body.get_u32_le();
+            let _padding = body.get_u16_le();
+            let length = body.get_u16_le() as usize;
+            RailPdu::ExecResult {
+                flags,
+                result,
+                raw_result,
+                exe_or_file: read_string(body, length)?,
+            }
+        }
+        RailOrderType::TsRailOrderSysparam => {
+            let param = body.get_u32_le();
+            RailPdu::SysParam {
+                param,
+                value: SysParamValue::read(param, body)?,
+            }
+        }
+        RailOrderType::TsRailOrderActivate => RailPdu::Activate {
+            window_id: body.get_u32_le(),
+            enabled: body.get_u8() != 0,
+        },
+        RailOrderType::TsRailOrderSysmenu => RailPdu::SysMenu {
+            window_id: body.get_u32_le(),
+            left: body.get_i16_le(),
+            top: body.get_i16_le(),
+        },
+        RailOrderType::TsRailOrderSyscommand => RailPdu::SysCommand {
+            window_id: body.get_u32_le(),
+            command: body.get_u16_le(),
+        },
+        RailOrderType::TsRailOrderNotifyEvent => RailPdu::NotifyEvent {
+            window_id: body.get_u32_le(),
+            notify_icon_id: body.get_u32_le(),
+            message: body.get_u32_le(),
+        },
+        RailOrderType::TsRailOrderGetAppidReq => RailPdu::GetAppIdRequest(body.get_u32_le()),
+        RailOrderType::TsRailOrderGetAppidResp => RailPdu::GetAppIdResponse {
+            window_id: body.get_u32_le(),
+            application_id: read_string(body, APPLICATION_ID_SIZE)?,
+        },
+        RailOrderType::TsRailOrderMinmaxinfo => RailPdu::MinMaxInfo {
+            window_id: body.get_u32_le(),
+            max_width: body.get_i16_le(),
+            max_height: body.get_i16_le(),
+            max_pos_x: body.get_i16_le(),
+            max_pos_y: body.get_i16_le(),
+            min_track_width: body.get_i16_le(),
+            min_track_height: body.get_i16_le(),
+            max_track_width: body.get_i16_le(),
+            max_track_height: body.get_i16_le(),
+        },
+        RailOrderType::TsRailOrderLocalmovesize => RailPdu::LocalMoveSize {
+            window_id: body.get_u32_le(),
+            start: body.get_u16_le() != 0,
+            move_size_type: body.get_u16_le(),
+            x: body.get_i16_le(),
+            y: body.get_i16_le(),
+        },
+        RailOrderType::TsRailOrderWindowmove => RailPdu::WindowMove {
+            window_id: body.get_u32_le(),
+            left: body.get_i16_le(),
+            top: body.get_i16_le(),
+            right: body.get_i16_le(),
+            bottom: body.get_i16_le(),
+        },
+        RailOrderType::TsRailOrderLangbarinfo => RailPdu::LangBarInfo(body.get_u32_le()),
+        RailOrderType::TsRailOrderZorderSync => RailPdu::ZOrderSync(body.get_u32_le()),
+        RailOrderType::TsRailOrderCloak => RailPdu::Cloak {
+            window_id: body.get_u32_le(),
+            cloaked: body.get_u8() != 0,
+        },
+    })
+}
+
+#[async_trait]
+impl Message for RailPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.order_type() as u16).await?;
+        writer.write_u16_le(self.length() as u16).await?;
+        writer.write_all(&self.body()).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header).await?;
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let mut buffer = BytesMut::from(&header[..]);
+        buffer.resize(length.max(HEADER_SIZE), 0);
+        reader.read_exact(&mut buffer[HEADER_SIZE..]).await?;
+        *self = read_rail_pdu(&mut buffer)?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        HEADER_SIZE + self.body_length()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Check the encoding of a PDU both ways
+    async fn check_pdu(pdu: RailPdu, data: &[u8]) {
+        assert_eq!(pdu.length(), data.len());
+        assert_eq!(to_vec(&pdu).await.unwrap(), data);
+        assert_eq!(read_rail_pdu(&mut BytesMut::from(data)).unwrap(), pdu);
+    }
+
+    /// MS-RDPERP 2.2.2.2 Initialization Messages
+    #[tokio::test]
+    async fn test_rail_initialization_pdu() {
+        check_pdu(
+            RailPdu::Handshake(7601),
+            &[
+                0x05, 0x00, // orderType TS_RAIL_ORDER_HANDSHAKE
+                0x08, 0x00, // orderLength
+                0xB1, 0x1D, 0x00, 0x00, // buildNumber
+            ],
+        )
+        .await;
+        check_pdu(
+            RailPdu::ClientStatus(ClientStatusFlag::TsRailClientstatusAllowlocalmovesize as u32),
+            &[
+                0x0B, 0x00, // orderType TS_RAIL_ORDER_CLIENTSTATUS
+                0x08, 0x00, // orderLength
+                0x01, 0x00, 0x00, 0x00, // Flags TS_RAIL_CLIENTSTATUS_ALLOWLOCALMOVESIZE
+            ],
+        )
+        .await;
+    }
+
+    /// MS-RDPERP 2.2.2.3 Program Launching Messages
+    #[tokio::test]
+    async fn test_rail_exec_pdu() {
+        check_pdu(
+            RailPdu::Exec {
+                flags: ExecFlag::TsRailExecFlagExpandArguments as u16,
+                exe_or_file: "||notepad".to_string(),
+                working_dir: String::new(),
+                arguments: "a.txt".to_string(),
+            },
+            &[
+                0x01, 0x00, // orderType TS_RAIL_ORDER_EXEC
+                0x28, 0x00, // orderLength
+                0x08, 0x00, // Flags TS_RAIL_EXEC_FLAG_EXPAND_ARGUMENTS
+                0x12, 0x00, // ExeOrFileLength
+                0x00, 0x00, // WorkingDirLength
+                0x0A, 0x00, // ArgumentsLen
+                0x7C, 0x00, 0x7C, 0x00, 0x6E, 0x00, 0x6F, 0x00, 0x74, 0x00, // ||not
+                0x65, 0x00, 0x70, 0x00, 0x61, 0x00, 0x64, 0x00, // epad
+                0x61, 0x00, 0x2E, 0x00, 0x74, 0x00, 0x78, 0x00, 0x74, 0x00, // a.txt
+            ],
+        )
+        .await;
+        check_pdu(
+            RailPdu::ExecResult {
+                flags: 0,
+                result: ExecResult::RailExecErrorFileNotFound as u16,
+                raw_result: 2,
+                exe_or_file: "||notepad".to_string(),
+            },
+            &[
+                0x80, 0x00, // orderType TS_RAIL_ORDER_EXEC_RESULT
+                0x22, 0x00, // orderLength
+                0x00, 0x00, // Flags
+                0x05, 0x00, // ExecResult RAIL_EXEC_E_FILE_NOT_FOUND
+                0x02, 0x00, 0x00, 0x00, // RawResult
+                0x00, 0x00, // Padding
+                0x12, 0x00, // ExeOrFileLength
+                0x7C, 0x00, 0x7C, 0x00, 0x6E, 0x00, 0x6F, 0x00, 0x74, 0x00, // ||not
+                0x65, 0x00, 0x70, 0x00, 0x61, 0x00, 0x64, 0x00, // epad
+            ],
+        )
+        .await;
+    }
+
+    /// MS-RDPERP 2.2.2.4 Local Client System Parameters Update Messages
+    #[tokio::test]
+    async fn test_rail_sysparam_pdu() {
+        check_pdu(
+            RailPdu::SysParam {
+                param: SPI_SETWORKAREA,
+                value: SysParamValue::Rect(WindowRect {
+                    left: 0,
+                    top: 0,
+                    right: 1920,
+                    bottom: 1040,
+                }),
+            },
+            &[
+                0x03, 0x00, // orderType TS_RAIL_ORDER_SYSPARAM
+                0x10, 0x00, // orderLength
+                0x2F, 0x00, 0x00, 0x00, // SystemParam SPI_SETWORKAREA
+                0x00, 0x00, 0x00, 0x00, // Left, Top
+                0x80, 0x07, 0x10, 0x04, // Right, Bottom
+            ],
+        )
+        .await;
+        check_pdu(
+            RailPdu::SysParam {
+                param: SPI_SETHIGHCONTRAST,
+                value: SysParamValue::HighContrast {
+                    flags: 0x7E,
+                    color_scheme: "HC".to_string(),
+                },
+            },
+            &[
+                0x03, 0x00, // orderType TS_RAIL_ORDER_SYSPARAM
+                0x16, 0x00, // orderLength
+                0x43, 0x00, 0x00, 0x00, // SystemParam SPI_SETHIGHCONTRAST
+                0x7E, 0x00, 0x00, 0x00, // Flags
+                0x06, 0x00, 0x00, 0x00, // ColorSchemeLength
+                0x48, 0x00, 0x43, 0x00, 0x00, 0x00, // ColorScheme, null terminated
+            ],
+        )
+        .await;
+    }
+
+    /// MS-RDPERP 2.2.2.6 and 2.2.2.7 Window Messages
+    #[tokio::test]
+    async fn test_rail_window_pdu() {
+        check_pdu(
+            RailPdu::Activate {
+                window_id: 3,
+                enabled: true,
+            },
+            &[
+                0x02, 0x00, // orderType TS_RAIL_ORDER_ACTIVATE
+                0x09, 0x00, // orderLength
+                0x03, 0x00, 0x00, 0x00, // WindowId
+                0x01, // Enabled
+            ],
+        )
+        .await;
+        check_pdu(
+            RailPdu::SysCommand {
+                window_id: 3,
+                command: SysCommand::ScClose as u16,
+            },
+            &[
+                0x04, 0x00, // orderType TS_RAIL_ORDER_SYSCOMMAND
+                0x0A, 0x00, // orderLength
+                0x03, 0x00, 0x00, 0x00, // WindowId
+                0x60, 0xF0, // Command SC_CLOSE
+            ],
+        )
+        .await;
+        check_pdu(
+            RailPdu::LocalMoveSize {
+                window_id: 3,
+                start: true,
+                move_size_type: 9,
+                x: -4,
+                y: 12,
+            },
+            &[
+                0x09, 0x00, // orderType TS_RAIL_ORDER_LOCALMOVESIZE
+                0x10, 0x00, // orderLength
+                0x03, 0x00, 0x00, 0x00, // WindowId
+                0x01, 0x00, // IsMoveSizeStart
+                0x09, 0x00, // MoveSizeType RAIL_WMSZ_MOVE
+                0xFC, 0xFF, // PosX
+                0x0C, 0x00, // PosY
+            ],
+        )
+        .await;
+    }
+
+    /// The application id is padded to 260 characters
+    /// MS-RDPERP 2.2.2.8.1 Server Get Application ID Response PDU
+    #[tokio::test]
+    async fn test_rail_get_app_id_response_pdu() {
+        let mut data = vec![
+            0x0F, 0x00, // orderType TS_RAIL_ORDER_GET_APPID_RESP
+            0x10, 0x02, // orderLength
+            0x03, 0x00, 0x00, 0x00, // WindowId
+            0x61, 0x00, 0x70, 0x00, 0x70, 0x00, // app
+        ];
+        data.resize(528, 0);
+        check_pdu(
+            RailPdu::GetAppIdResponse {
+                window_id: 3,
+                application_id: "app".to_string(),
+            },
+            &data,
+        )
+        .await;
+    }
+}
@@ -0,0 +1,319 @@
+use crate::core::order::window::WindowRect;
+use crate::core::rail::base::{
+    read_rail_pdu, ClientStatusFlag, ExecFlag, RailPdu, SysCommand, SysParamValue,
+    RAIL_SPI_DISPLAYCHANGE, RAIL_SPI_TASKBARPOS, SPI_SETDRAGFULLWINDOWS, SPI_SETHIGHCONTRAST,
+    SPI_SETKEYBOARDCUES, SPI_SETKEYBOARDPREF, SPI_SETMOUSEBUTTONSWAP, SPI_SETWORKAREA,
+};
+
+use bytes::BytesMut;
+use std::io::Result;
+
+/// Build number sent in the handshake, the one of Windows 7 SP1
+const RAIL_BUILD_NUMBER: u32 = 7601;
+
+/// Height of the taskbar announced at the bottom of the desktop
+const TASKBAR_HEIGHT: u16 = 40;
+
+/// High contrast is available but not enabled
+/// MS-RDPERP 2.2.1.2.4 High Contrast System Information Structure (TS_HIGHCONTRAST)
+const HCF_AVAILABLE: u32 = 0x00000002;
+
+/// Event of the RemoteApp channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RailEvent {
+    /// The handshake is done, programs can be started
+    Ready,
+    /// Result of the start of a program, zero on success
+    ExecResult {
+        exe_or_file: String,
+        result: u16,
+        raw_result: u32,
+    },
+    /// The server starts or ends a move or resize of a window done by the client
+    /// The new position is sent with `window_move` at the end
+    LocalMoveSize {
+        window_id: u32,
+        start: bool,
+        move_size_type: u16,
+        x: i16,
+        y: i16,
+    },
+    /// Other PDUs of the server, like min max info,
+    /// system parameters, z-order sync or application ids
+    Order(RailPdu),
+}
+
+/// Client of the RemoteApp static virtual channel
+/// Programs are started once the server handshake is answered
+/// with the status and the system parameters of the client
+///
+/// Windows of the programs are sent as windowing orders,
+/// the session must be opened with `remote_app` in its capabilities
+///
+/// # Example
+/// ```
+/// use rdp::core::rail::client::RailClient;
+/// let mut client = RailClient::new(1920, 1080);
+/// assert!(client.exec("||notepad", "", "").is_none());
+/// // Handshake of the server
+/// let mut data = bytes::BytesMut::from(&[5, 0, 8, 0, 0xB1, 0x1D, 0, 0][..]);
+/// let responses = client.process(&mut data, &mut |_| {}).unwrap();
+/// // Handshake, client status, 8 system parameters and the exec
+/// assert_eq!(responses.len(), 11);
+/// ```
+pub struct RailClient {
+    /// System parameters sent after the handshake
+    sys_params: Vec<(u32, SysParamValue)>,
+    /// Programs started before the handshake
+    pending: Vec<RailPdu>,
+    ready: bool,
+}
+
+impl RailClient {
+    /// Desktop of the client, with the taskbar at the bottom
+    pub fn new(width: u16, height: u16) -> Self {
+        let desktop = WindowRect {
+            left: 0,
+            top: 0,
+            right: width,
+            bottom: height,
+        };
+        let work_area = WindowRect {
+            bottom: height.saturating_sub(TASKBAR_HEIGHT),
+            ..desktop
+        };
+        let taskbar = WindowRect {
+            top: work_area.bottom,
+            ..desktop
+        };
+        RailClient {
+            sys_params: vec![
+                (
+                    SPI_SETHIGHCONTRAST,
+                    SysParamValue::HighContrast {
+                        flags: HCF_AVAILABLE,
+                        color_scheme: String::new(),
+                    },
+                ),
+                (SPI_SETMOUSEBUTTONSWAP, SysParamValue::Flag(false)),
+                (SPI_SETKEYBOARDPREF, SysParamValue::Flag(false)),
+                (SPI_SETDRAGFULLWINDOWS, SysParamValue::Flag(false)),
+                (SPI_SETKEYBOARDCUES, SysParamValue::Flag(false)),
+                (SPI_SETWORKAREA, SysParamValue::Rect(work_area)),
+                (RAIL_SPI_DISPLAYCHANGE, SysParamValue::Rect(desktop)),
+                (RAIL_SPI_TASKBARPOS, SysParamValue::Rect(taskbar)),
+            ],
+            pending: Vec::new(),
+            ready: false,
+        }
+    }
+
+    /// Change a system parameter of the client
+    /// None while it is kept for the handshake
+    pub fn set_sys_param(&mut self, param: u32, value: SysParamValue) -> Option<RailPdu> {
+        match self.sys_params.iter_mut().find(|(id, _)| *id == param) {
+            Some((_, current)) => *current = value.clone(),
+            None => self.sys_params.push((param, value.clone())),
+        }
+        if self.ready {
+            Some(RailPdu::SysParam { param, value })
+        } else {
+            None
+        }
+    }
+
+    /// Start a program, a file or a `||alias` of a published RemoteApp
+    /// Environment variables are expanded by the server
+    /// None while it is kept for the handshake
+    pub fn exec(
+        &mut self,
+        exe_or_file: &str,
+        working_dir: &str,
+        arguments: &str,
+    ) -> Option<RailPdu> {
+        let mut flags = 0;
+        if working_dir.contains('%') {
+            flags |= ExecFlag::TsRailExecFlagExpandWorkingdirectory as u16;
+        }
+        if arguments.contains('%') {
+            flags |= ExecFlag::TsRailExecFlagExpandArguments as u16;
+        }
+        let pdu = RailPdu::Exec {
+            flags,
+            exe_or_file: exe_or_file.to_string(),
+            working_dir: working_dir.to_string(),
+            arguments: arguments.to_string(),
+        };
+        if self.ready {
+            Some(pdu)
+        } else {
+            self.pending.push(pdu);
+            None
+        }
+    }
+
+    /// Activate a window when the local one gets or loses the focus
+    pub fn activate(&self, window_id: u32, enabled: bool) -> RailPdu {
+        RailPdu::Activate { window_id, enabled }
+    }
+
+    /// Minimize, maximize, restore or close a window
+    pub fn sys_command(&self, window_id: u32, command: SysCommand) -> RailPdu {
+        RailPdu::SysCommand {
+            window_id,
+            command: command as u16,
+        }
+    }
+
+    /// Show the system menu of a window at a position of the desktop
+    pub fn sys_menu(&self, window_id: u32, left: i16, top: i16) -> RailPdu {
+        RailPdu::SysMenu {
+            window_id,
+            left,
+            top,
+        }
+    }
+
+    /// Mouse or keyboard message, as WM_*, on a notification icon
+    pub fn notify_event(&self, window_id: u32, notify_icon_id: u32, message: u32) -> RailPdu {
+        RailPdu::NotifyEvent {
+            window_id,
+            notify_icon_id,
+            message,
+        }
+    }
+
+    /// New position of a window moved or resized locally
+    pub fn window_move(
+        &self,
+        window_id: u32,
+        left: i16,
+        top: i16,
+        right: i16,
+        bottom: i16,
+    ) -> RailPdu {
+        RailPdu::WindowMove {
+            window_id,
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    /// Ask the application id of a window, to group the local windows
+    pub fn get_app_id(&self, window_id: u32) -> RailPdu {
+        RailPdu::GetAppIdRequest(window_id)
+    }
+
+    /// Answer to the handshake of the server
+    fn handshake(&mut self) -> Vec<RailPdu> {
+        let mut responses = vec![
+            RailPdu::Handshake(RAIL_BUILD_NUMBER),
+            RailPdu::ClientStatus(ClientStatusFlag::TsRailClientstatusAllowlocalmovesize as u32),
+        ];
+        responses.extend(
+            self.sys_params
+                .iter()
+                .map(|(param, value)| RailPdu::SysParam {
+                    param: *param,
+                    value: value.clone(),
+                }),
+        );
+        responses.append(&mut self.pending);
+        self.ready = true;
+        responses
+    }
+
+    /// Process a message of the channel
+    /// and build the PDUs expected by the server
+    pub fn process<T>(&mut self, buffer: &mut BytesMut, callback: &mut T) -> Result<Vec<RailPdu>>
+    where
+        T: FnMut(RailEvent),
+    {
+        let mut responses = Vec::new();
+        match read_rail_pdu(buffer)? {
+            RailPdu::Handshake(_) | RailPdu::HandshakeEx { .. } => {
+                if !self.ready {
+                    responses = self.handshake();
+                    callback(RailEvent::Ready);
+                }
+            }
+            RailPdu::ExecResult {
+                result,
+                raw_result,
+                exe_or_file,
+                ..
+            } => callback(RailEvent::ExecResult {
+                exe_or_file,
+                result,
+                raw_result,
+            }),
+            RailPdu::LocalMoveSize {
+                window_id,
+                start,
+                move_size_type,
+                x,
+                y,
+            } => callback(RailEvent::LocalMoveSize {
+                window_id,
+                start,
+                move_size_type,
+                x,
+                y,
+            }),
+            pdu => callback(RailEvent::Order(pdu)),
+        }
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::rail::base::SPI_SETSCREENSAVEACTIVE;
+
+    /// Programs started before the handshake are sent after the system parameters
+    #[test]
+    fn test_handshake() {
+        let mut client = RailClient::new(1024, 768);
+        assert!(client.exec("||calc", "", "").is_none());
+
+        let mut events = Vec::new();
+        let mut buffer = BytesMut::from(&[0x13, 0, 12, 0, 0xB1, 0x1D, 0, 0, 1, 0, 0, 0][..]);
+        let responses = client
+            .process(&mut buffer, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!(events, [RailEvent::Ready]);
+        assert_eq!(responses[0], RailPdu::Handshake(RAIL_BUILD_NUMBER));
+        assert_eq!(
+            responses[9],
+            RailPdu::SysParam {
+                param: RAIL_SPI_TASKBARPOS,
+                value: SysParamValue::Rect(WindowRect {
+                    left: 0,
+                    top: 728,
+                    right: 1024,
+                    bottom: 768,
+                }),
+            }
+        );
+        assert!(
+            matches!(&responses[10], RailPdu::Exec { exe_or_file, .. } if exe_or_file == "||calc")
+        );
+        assert!(client.exec("||calc", "", "%TEMP%").is_some());
+
+        // Server system parameter
+        let mut buffer = BytesMut::from(&[3, 0, 9, 0, 0x11, 0, 0, 0, 1][..]);
+        client
+            .process(&mut buffer, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!(
+            events[1],
+            RailEvent::Order(RailPdu::SysParam {
+                param: SPI_SETSCREENSAVEACTIVE,
+                value: SysParamValue::Flag(true),
+            })
+        );
+    }
+}
@@ -0,0 +1,573 @@
+use crate::model::data::check_remaining;
+use crate::model::unicode::{from_unicode, Unicode};
+
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::Result;
+
+/// Size of the header of all the packets
+/// MS-TSGU 2.2.10.9 HTTP_PACKET_HEADER Structure
+pub const HTTP_PACKET_HEADER_SIZE: usize = 8;
+
+/// Largest data of a data packet, its length is on 16 bits
+pub const HTTP_DATA_MAX_SIZE: usize = 0xFFFF;
+
+/// Protocol of the channel to the target
+/// MS-TSGU 2.2.10.2 HTTP_CHANNEL_PACKET Structure
+const HTTP_CHANNEL_PROTOCOL_TCP: u16 = 3;
+
+/// Success of a handshake, tunnel or channel response
+pub const STATUS_SUCCESS: u32 = 0x00000000;
+
+/// Type of the packets of the tunnel
+/// MS-TSGU 2.2.5.3.3 HTTP_PACKET_TYPE Enumeration
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum PacketType {
+    PktTypeHandshakeRequest = 0x0001,
+    PktTypeHandshakeResponse = 0x0002,
+    PktTypeExtendedAuthMsg = 0x0003,
+    PktTypeTunnelCreate = 0x0004,
+    PktTypeTunnelResponse = 0x0005,
+    PktTypeTunnelAuth = 0x0006,
+    PktTypeTunnelAuthResponse = 0x0007,
+    PktTypeChannelCreate = 0x0008,
+    PktTypeChannelResponse = 0x0009,
+    PktTypeData = 0x000A,
+    PktTypeServiceMessage = 0x000B,
+    PktTypeReauthMessage = 0x000C,
+    PktTypeKeepalive = 0x000D,
+    PktTypeCloseChannel = 0x0010,
+    PktTypeCloseChannelResponse = 0x0011,
+}
+
+/// Authentication made inside the tunnel, after the HTTP one
+/// MS-TSGU 2.2.5.3.2 HTTP_EXTENDED_AUTH Enumeration
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExtendedAuth {
+    HttpExtendedAuthNone = 0x0000,
+    HttpExtendedAuthSc = 0x0001,
+    HttpExtendedAuthPaa = 0x0002,
+    HttpExtendedAuthSspiNtlm = 0x0004,
+}
+
+/// Capabilities of the tunnel
+/// MS-TSGU 2.2.5.3.1 HTTP_CAPABILITY_TYPE Enumeration
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TunnelCapability {
+    HttpCapabilityTypeQuarSoh = 0x00000001,
+    HttpCapabilityIdleTimeout = 0x00000002,
+    HttpCapabilityMessagingConsentSign = 0x00000004,
+    HttpCapabilityMessagingServiceMsg = 0x00000008,
+    HttpCapabilityReauth = 0x00000010,
+    HttpCapabilityUdpTransport = 0x00000020,
+}
+
+/// Optional fields of the packets
+/// MS-TSGU 2.2.5.3.4 to 2.2.5.3.8
+const HTTP_TUNNEL_PACKET_FIELD_PAA_COOKIE: u16 = 0x0001;
+const HTTP_TUNNEL_RESPONSE_FIELD_TUNNEL_ID: u16 = 0x0001;
+const HTTP_TUNNEL_RESPONSE_FIELD_CAPS: u16 = 0x0002;
+const HTTP_TUNNEL_AUTH_RESPONSE_FIELD_REDIR_FLAGS: u16 = 0x0001;
+const HTTP_TUNNEL_AUTH_RESPONSE_FIELD_IDLE_TIMEOUT: u16 = 0x0002;
+const HTTP_CHANNEL_RESPONSE_FIELD_CHANNELID: u16 = 0x0001;
+const HTTP_CHANNEL_RESPONSE_FIELD_UDPPORT: u16 = 0x0004;
+
+/// Packets exchanged with the gateway
+/// once the HTTP connection is upgraded
+/// All the fields are in little endian
+///
+/// # Example
+/// ```
+/// use bytes::BytesMut;
+/// use rdp::core::rdg::base::{read_rdg_packet, RdgPacket};
+/// let bytes = RdgPacket::Data(vec![1, 2, 3]).to_bytes();
+/// assert_eq!(bytes.len(), 8 + 2 + 3);
+/// assert_eq!(
+///     read_rdg_packet(&mut BytesMut::from(&bytes[..])).unwrap(),
+///     RdgPacket::Data(vec![1, 2, 3])
+/// );
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RdgPacket {
+    /// MS-TSGU 2.2.10.10 HTTP_HANDSHAKE_REQUEST_PACKET Structure
+    HandshakeRequest {
+        /// ExtendedAuth flags supported by the client
+        extended_auth: u16,
+    },
+    /// MS-TSGU 2.2.10.11 HTTP_HANDSHAKE_RESPONSE_PACKET Structure
+    HandshakeResponse {
+        error_code: u32,
+        /// ExtendedAuth selected by the gateway
+        extended_auth: u16,
+    },
+    /// MS-TSGU 2.2.10.18 HTTP_TUNNEL_PACKET Structure
+    TunnelCreate {
+        /// TunnelCapability flags of the client
+        capabilities: u32,
+        /// Cookie of a pluggable authentication
+        paa_cookie: Option<Vec<u8>>,
+    },
+    /// MS-TSGU 2.2.10.20 HTTP_TUNNEL_RESPONSE Structure
+    TunnelResponse {
+        status_code: u32,
+        tunnel_id: Option<u32>,
+        /// TunnelCapability flags of the gateway
+        capabilities: Option<u32>,
+    },
+    /// MS-TSGU 2.2.10.14 HTTP_TUNNEL_AUTH_PACKET Structure
+    TunnelAuth {
+        client_name: String,
+    },
+    /// MS-TSGU 2.2.10.16 HTTP_TUNNEL_AUTH_RESPONSE Structure
+    TunnelAuthResponse {
+        error_code: u32,
+        /// Device redirections allowed by the gateway
+        redirection_flags: Option<u32>,
+        /// Idle time in minutes before the gateway closes the tunnel
+        idle_timeout: Option<u32>,
+    },
+    /// MS-TSGU 2.2.10.2 HTTP_CHANNEL_PACKET Structure
+    ChannelCreate {
+        /// Names of the target, the first one is tried first
+        resources: Vec<String>,
+        port: u16,
+    },
+    /// MS-TSGU 2.2.10.4 HTTP_CHANNEL_RESPONSE Structure
+    ChannelResponse {
+        error_code: u32,
+        channel_id: Option<u32>,
+        udp_port: Option<u16>,
+    },
+    /// Bytes of the RDP stream
+    /// MS-TSGU 2.2.10.6 HTTP_DATA_PACKET Structure
+    Data(Vec<u8>),
+    /// Message to show to the user
+    /// MS-TSGU 2.2.10.13 HTTP_SERVICE_MESSAGE Structure
+    ServiceMessage(String),
+    /// MS-TSGU 2.2.5.3.3 PKT_TYPE_KEEPALIVE
+    Keepalive,
+    /// MS-TSGU 2.2.10.1 HTTP_CLOSE_PACKET Structure
+    CloseChannel {
+        status_code: u32,
+    },
+    CloseChannelResponse {
+        status_code: u32,
+    },
+    /// Packets not handled by the client, skipped
+    Other(u16),
+}
+
+/// Write a unicode string with its null character,
+/// prefixed by its size in bytes
+fn put_unicode(buffer: &mut Vec<u8>, value: &str) {
+    let mut unicode = value.to_unicode();
+    unicode.extend_from_slice(&[0, 0]);
+    buffer.put_u16_le(unicode.len() as u16);
+    buffer.put_slice(&unicode);
+}
+
+fn get_unicode(buffer: &mut BytesMut, context: &str) -> Result<String> {
+    check_remaining(buffer, 2, context)?;
+    let size = buffer.get_u16_le() as usize;
+    check_remaining(buffer, size, context)?;
+    Ok(from_unicode(&buffer.split_to(size)))
+}
+
+impl RdgPacket {
+    /// PacketType of the header
+    fn packet_type(&self) -> u16 {
+        let packet_type = match self {
+            RdgPacket::HandshakeRequest { .. } => PacketType::PktTypeHandshakeRequest,
+            RdgPacket::HandshakeResponse { .. } => PacketType::PktTypeHandshakeResponse,
+            RdgPacket::TunnelCreate { .. } => PacketType::PktTypeTunnelCreate,
+            RdgPacket::TunnelResponse { .. } => PacketType::PktTypeTunnelResponse,
+            RdgPacket::TunnelAuth { .. } => PacketType::PktTypeTunnelAuth,
+            RdgPacket::TunnelAuthResponse { .. } => PacketType::PktTypeTunnelAuthResponse,
+            RdgPacket::ChannelCreate { .. } => PacketType::PktTypeChannelCreate,
+            RdgPacket::ChannelResponse { .. } => PacketType::PktTypeChannelResponse,
+            RdgPacket::Data(_) => PacketType::PktTypeData,
+            RdgPacket::ServiceMessage(_) => PacketType::PktTypeServiceMessage,
+            RdgPacket::Keepalive => PacketType::PktTypeKeepalive,
+            RdgPacket::CloseChannel { .. } => PacketType::PktTypeCloseChannel,
+            RdgPacket::CloseChannelResponse { .. } => PacketType::PktTypeCloseChannelResponse,
+            RdgPacket::Other(packet_type) => return *packet_type,
+        };
+        packet_type as u16
+    }
+
+    /// Serialize the packet with its header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        match self {
+            RdgPacket::HandshakeRequest { extended_auth } => {
+                // Version 1.0 of the protocol
+                body.put_u8(1);
+                body.put_u8(0);
+                body.put_u16_le(0);
+                body.put_u16_le(*extended_auth);
+            }
+            RdgPacket::HandshakeResponse {
+                error_code,
+                extended_auth,
+            } => {
+                body.put_u32_le(*error_code);
+                body.put_u8(1);
+                body.put_u8(0);
+                body.put_u16_le(0);
+                body.put_u16_le(*extended_auth);
+            }
+            RdgPacket::TunnelCreate {
+                capabilities,
+                paa_cookie,
+            } => {
+                body.put_u32_le(*capabilities);
+                let fields = match paa_cookie {
+                    Some(_) => HTTP_TUNNEL_PACKET_FIELD_PAA_COOKIE,
+                    None => 0,
+                };
+                body.put_u16_le(fields);
+                body.put_u16_le(0);
+                if let Some(cookie) = paa_cookie {
+                    body.put_u16_le(cookie.len() as u16);
+                    body.put_slice(cookie);
+                }
+            }
+            RdgPacket::TunnelResponse {
+                status_code,
+                tunnel_id,
+                capabilities,
+            } => {
+                let mut fields = 0;
+                if tunnel_id.is_some() {
+                    fields |= HTTP_TUNNEL_RESPONSE_FIELD_TUNNEL_ID;
+                }
+                if capabilities.is_some() {
+                    fields |= HTTP_TUNNEL_RESPONSE_FIELD_CAPS;
+                }
+                body.put_u16_le(0);
+                body.put_u32_le(*status_code);
+                body.put_u16_le(fields);
+                body.put_u16_le(0);
+                if let Some(tunnel_id) = tunnel_id {
+                    body.put_u32_le(*tunnel_id);
+                }
+                if let Some(capabilities) = capabilities {
+                    body.put_u32_le(*capabilities);
+                }
+            }
+            RdgPacket::TunnelAuth { client_name } => {
+                body.put_u16_le(0);
+                put_unicode(&mut body, client_name);
+            }
+            RdgPacket::TunnelAuthResponse {
+                error_code,
+                redirection_flags,
+                idle_timeout,
+            } => {
+                let mut fields = 0;
+                if redirection_flags.is_some() {
+                    fields |= HTTP_TUNNEL_AUTH_RESPONSE_FIELD_REDIR_FLAGS;
+                }
+                if idle_timeout.is_some() {
+                    fields |= HTTP_TUNNEL_AUTH_RESPONSE_FIELD_IDLE_TIMEOUT;
+                }
+                body.put_u32_le(*error_code);
+                body.put_u16_le(fields);
+                body.put_u16_le(0);
+                if let Some(flags) = redirection_flags {
+                    body.put_u32_le(*flags);
+                }
+                if let Some(timeout) = idle_timeout {
+                    body.put_u32_le(*timeout);
+                }
+            }
+            RdgPacket::ChannelCreate { resources, port } => {
+                body.put_u8(resources.len() as u8);
+                // No alternate resources
+                body.put_u8(0);
+                body.put_u16_le(*port);
+                body.put_u16_le(HTTP_CHANNEL_PROTOCOL_TCP);
+                for resource in resources {
+                    put_unicode(&mut body, resource);
+                }
+            }
+            RdgPacket::ChannelResponse {
+                error_code,
+                channel_id,
+                udp_port,
+            } => {
+                let mut fields = 0;
+                if channel_id.is_some() {
+                    fields |= HTTP_CHANNEL_RESPONSE_FIELD_CHANNELID;
+                }
+                if udp_port.is_some() {
+                    fields |= HTTP_CHANNEL_RESPONSE_FIELD_UDPPORT;
+                }
+                body.put_u32_le(*error_code);
+                body.put_u16_le(fields);
+                body.put_u16_le(0);
+                if let Some(channel_id) = channel_id {
+                    body.put_u32_le(*channel_id);
+                }
+                if let Some(udp_port) = udp_port {
+                    body.put_u16_le(*udp_port);
+                }
+            }
+            RdgPacket::Data(data) => {
+                body.put_u16_le(data.len() as u16);
+                body.put_slice(data);
+            }
+            RdgPacket::ServiceMessage(message) => put_unicode(&mut body, message),
+            RdgPacket::Keepalive | RdgPacket::Other(_) => (),
+            RdgPacket::CloseChannel { status_code }
+            | RdgPacket::CloseChannelResponse { status_code } => body.put_u32_le(*status_code),
+        }
+
+        let mut buffer = Vec::with_capacity(HTTP_PACKET_HEADER_SIZE + body.len());
+        buffer.put_u16_le(self.packet_type());
+        buffer.put_u16_le(0);
+        buffer.put_u32_le((HTTP_PACKET_HEADER_SIZE + body.len()) as u32);
+        buffer.put_slice(&body);
+        buffer
+    }
+}
+
+/// Length of the packet at the start of the buffer,
+/// None while its header is incomplete
+pub fn packet_length(buffer: &[u8]) -> Option<usize> {
+    if buffer.len() < HTTP_PACKET_HEADER_SIZE {
+        return None;
+    }
+    Some(u32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]) as usize)
+}
+
+/// Read a whole packet, its header included
+/// Optional fields not handled by the client are skipped
+pub fn read_rdg_packet(buffer: &mut BytesMut) -> Result<RdgPacket> {
+    check_remaining(buffer, HTTP_PACKET_HEADER_SIZE, "RDG: packet header")?;
+    let packet_type = buffer.get_u16_le();
+    let _reserved = buffer.get_u16_le();
+    let length = buffer.get_u32_le() as usize;
+    let size = length.saturating_sub(HTTP_PACKET_HEADER_SIZE);
+    check_remaining(buffer, size, "RDG: packet")?;
+    let mut body = buffer.split_to(size);
+
+    let packet_type = match PacketType::try_from(packet_type) {
+        Ok(packet_type) => packet_type,
+        // Unknown packets are skipped, like the ones of later versions
+        Err(_) => return Ok(RdgPacket::Other(packet_type)),
+    };
+    let body = &mut body;
+    Ok(match packet_type {
+        PacketType::PktTypeHandshakeResponse => {
+            check_remaining(body, 10, "RDG: handshake response")?;
+            let error_code = body.get_u32_le();
+            let _version = body.get_u32_le();
+            RdgPacket::HandshakeResponse {
+                error_code,
+                extended_auth: body.get_u16_le(),
+            }
+        }
+        PacketType::PktTypeTunnelResponse => {
+            check_remaining(body, 10, "RDG: tunnel response")?;
+            let _server_version = body.get_u16_le();
+            let status_code = body.get_u32_le();
+            let fields = body.get_u16_le();
+            let _reserved = body.get_u16_le();
+            let mut tunnel_id = None;
+            if fields & HTTP_TUNNEL_RESPONSE_FIELD_TUNNEL_ID != 0 {
+                check_remaining(body, 4, "RDG: tunnel id")?;
+                tunnel_id = Some(body.get_u32_le());
+            }
+            let mut capabilities = None;
+            if fields & HTTP_TUNNEL_RESPONSE_FIELD_CAPS != 0 {
+                check_remaining(body, 4, "RDG: tunnel capabilities")?;
+                capabilities = Some(body.get_u32_le());
+            }
+            RdgPacket::TunnelResponse {
+                status_code,
+                tunnel_id,
+                capabilities,
+            }
+        }
+        PacketType::PktTypeTunnelAuthResponse => {
+            check_remaining(body, 8, "RDG: tunnel auth response")?;
+            let error_code = body.get_u32_le();
+            let fields = body.get_u16_le();
+            let _reserved = body.get_u16_le();
+            let mut redirection_flags = None;
+            if fields & HTTP_TUNNEL_AUTH_RESPONSE_FIELD_REDIR_FLAGS != 0 {
+                check_remaining(body, 4, "RDG: redirection flags")?;
+                redirection_flags = Some(body.get_u32_le());
+            }
+            let mut idle_timeout = None;
+            if fields & HTTP_TUNNEL_AUTH_RESPONSE_FIELD_IDLE_TIMEOUT != 0 {
+                check_remaining(body, 4, "RDG: idle timeout")?;
+                idle_timeout = Some(body.get_u32_le());
+            }
+            RdgPacket::TunnelAuthResponse {
+                error_code,
+                redirection_flags,
+                idle_timeout,
+            }
+        }
+        PacketType::PktTypeChannelResponse => {
+            check_remaining(body, 8, "RDG: channel response")?;
+            let error_code = body.get_u32_le();
+            let fields = body.get_u16_le();
+            let _reserved = body.get_u16_le();
+            let mut channel_id = None;
+            if fields & HTTP_CHANNEL_RESPONSE_FIELD_CHANNELID != 0 {
+                check_remaining(body, 4, "RDG: channel id")?;
+                channel_id = Some(body.get_u32_le());
+            }
+            let mut udp_port = None;
+            if fields & HTTP_CHANNEL_RESPONSE_FIELD_UDPPORT != 0 {
+                check_remaining(body, 2, "RDG: UDP port")?;
+                udp_port = Some(body.get_u16_le());
+            }
+            RdgPacket::ChannelResponse {
+                error_code,
+                channel_id,
+                udp_port,
+            }
+        }
+        PacketType::PktTypeData => {
+            check_remaining(body, 2, "RDG: data packet")?;
+            let size = body.get_u16_le() as usize;
+            check_remaining(body, size, "RDG: data")?;
+            RdgPacket::Data(body.split_to(size).to_vec())
+        }
+        PacketType::PktTypeServiceMessage => {
+            RdgPacket::ServiceMessage(get_unicode(body, "RDG: service message")?)
+        }
+        PacketType::PktTypeKeepalive => RdgPacket::Keepalive,
+        PacketType::PktTypeCloseChannel => {
+            check_remaining(body, 4, "RDG: close channel")?;
+            RdgPacket::CloseChannel {
+                status_code: body.get_u32_le(),
+            }
+        }
+        PacketType::PktTypeCloseChannelResponse => {
+            check_remaining(body, 4, "RDG: close channel response")?;
+            RdgPacket::CloseChannelResponse {
+                status_code: body.get_u32_le(),
+            }
+        }
+        PacketType::PktTypeHandshakeRequest => {
+            check_remaining(body, 6, "RDG: handshake request")?;
+            let _version = body.get_u32_le();
+            RdgPacket::HandshakeRequest {
+                extended_auth: body.get_u16_le(),
+            }
+        }
+        PacketType::PktTypeTunnelCreate => {
+            check_remaining(body, 8, "RDG: tunnel create")?;
+            let capabilities = body.get_u32_le();
+            let fields = body.get_u16_le();
+            let _reserved = body.get_u16_le();
+            let mut paa_cookie = None;
+            if fields & HTTP_TUNNEL_PACKET_FIELD_PAA_COOKIE != 0 {
+                check_remaining(body, 2, "RDG: PAA cookie")?;
+                let size = body.get_u16_le() as usize;
+                check_remaining(body, size, "RDG: PAA cookie")?;
+                paa_cookie = Some(body.split_to(size).to_vec());
+            }
+            RdgPacket::TunnelCreate {
+                capabilities,
+                paa_cookie,
+            }
+        }
+        PacketType::PktTypeTunnelAuth => {
+            check_remaining(body, 2, "RDG: tunnel auth")?;
+            let _fields = body.get_u16_le();
+            RdgPacket::TunnelAuth {
+                client_name: get_unicode(body, "RDG: client name")?,
+            }
+        }
+        PacketType::PktTypeChannelCreate => {
+            check_remaining(body, 6, "RDG: channel create")?;
+            let count = body.get_u8();
+            let _alternate_count = body.get_u8();
+            let port = body.get_u16_le();
+            let _protocol = body.get_u16_le();
+            let mut resources = Vec::new();
+            for _ in 0..count {
+                resources.push(get_unicode(body, "RDG: resource name")?);
+            }
+            RdgPacket::ChannelCreate { resources, port }
+        }
+        packet_type => RdgPacket::Other(packet_type as u16),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Packets are written and read back
+    #[test]
+    fn test_rdg_packet() {
+        for packet in [
+            RdgPacket::HandshakeRequest {
+                extended_auth: ExtendedAuth::HttpExtendedAuthPaa as u16,
+            },
+            RdgPacket::HandshakeResponse {
+                error_code: STATUS_SUCCESS,
+                extended_auth: 0,
+            },
+            RdgPacket::TunnelCreate {
+                capabilities: TunnelCapability::HttpCapabilityIdleTimeout as u32,
+                paa_cookie: Some(vec![1, 2]),
+            },
+            RdgPacket::TunnelResponse {
+                status_code: STATUS_SUCCESS,
+                tunnel_id: Some(7),
+                capabilities: None,
+            },
+            RdgPacket::TunnelAuth {
+                client_name: "rdp-rs".to_string(),
+            },
+            RdgPacket::TunnelAuthResponse {
+                error_code: STATUS_SUCCESS,
+                redirection_flags: None,
+                idle_timeout: Some(30),
+            },
+            RdgPacket::ChannelCreate {
+                resources: vec!["server".to_string()],
+                port: 3389,
+            },
+            RdgPacket::ChannelResponse {
+                error_code: STATUS_SUCCESS,
+                channel_id: Some(1),
+                udp_port: Some(3391),
+            },
+            RdgPacket::Data(vec![3, 0, 0, 4]),
+            RdgPacket::ServiceMessage("maintenance".to_string()),
+            RdgPacket::Keepalive,
+            RdgPacket::CloseChannel { status_code: 0 },
+        ] {
+            let bytes = packet.to_bytes();
+            assert_eq!(packet_length(&bytes), Some(bytes.len()));
+            let mut buffer = BytesMut::from(&bytes[..]);
+            assert_eq!(read_rdg_packet(&mut buffer).unwrap(), packet);
+            assert!(buffer.is_empty());
+        }
+    }
+
+    /// Layout of the channel creation packet
+    #[test]
+    fn test_channel_create() {
+        let packet = RdgPacket::ChannelCreate {
+            resources: vec!["ab".to_string()],
+            port: 3389,
+        };
+        assert_eq!(
+            packet.to_bytes(),
+            [0x08, 0, 0, 0, 22, 0, 0, 0, 1, 0, 0x3D, 0x0D, 3, 0, 6, 0, b'a', 0, b'b', 0, 0, 0]
+        );
+    }
+}
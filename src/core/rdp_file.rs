@@ -0,0 +1,214 @@
+use crate::core::config::ConnectionConfig;
+use crate::core::keyboard::KeyboardHook;
+use crate::core::preconnection::PreconnectionPdu;
+
+use std::io::{Error, ErrorKind, Result};
+
+/// Gateway usage methods which bypass the gateway
+/// 0 is never use it, 4 is never use it even for local addresses
+const GATEWAY_USAGE_DIRECT: [i64; 2] = [0, 4];
+
+/// Read a connection file saved by mstsc
+/// mstsc writes them in UTF-16 with a byte order mark
+///
+/// # Example
+/// ```rust, ignore
+/// let config = read_rdp_file(&std::fs::read("server.rdp")?)?;
+/// let client = RdpClient::builder().config(config).connect().await?;
+/// ```
+pub fn read_rdp_file(content: &[u8]) -> Result<ConnectionConfig> {
+    let text = match content {
+        [0xFF, 0xFE, utf16 @ ..] => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect();
+            String::from_utf16(&units).map_err(|_| {
+                Error::new(ErrorKind::InvalidData, "RDPFILE: invalid UTF-16 content")
+            })?
+        }
+        [0xEF, 0xBB, 0xBF, utf8 @ ..] | utf8 => String::from_utf8(utf8.to_vec())
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "RDPFILE: invalid UTF-8 content"))?,
+    };
+    parse_rdp_file(&text)
+}
+
+/// Parse the `key:type:value` lines of a connection file
+/// Unknown settings are ignored
+///
+/// # Example
+/// ```
+/// use rdp::core::rdp_file::parse_rdp_file;
+/// let config = parse_rdp_file(
+///     "full address:s:192.168.0.1:3390\r\n\
+///      username:s:DOMAIN\\user\r\n\
+///      desktopwidth:i:1920\r\n\
+///      desktopheight:i:1080\r\n",
+/// ).unwrap();
+/// assert_eq!(config.target.as_deref(), Some("192.168.0.1:3390"));
+/// assert_eq!(config.domain, "DOMAIN");
+/// assert_eq!(config.username, "user");
+/// assert_eq!(config.desktop_width, 1920);
+/// ```
+pub fn parse_rdp_file(text: &str) -> Result<ConnectionConfig> {
+    let mut config = ConnectionConfig::default();
+    let mut gateway_usage = None;
+
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let mut fields = line.splitn(3, ':');
+        let (key, value) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(key), Some("s"), Some(value)) => (key, Value::String(value)),
+            (Some(key), Some("i"), Some(value)) => (key, Value::Integer(value)),
+            (Some(_), Some(_), Some(_)) => continue,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("RDPFILE: invalid line {:?}", line),
+                ))
+            }
+        };
+
+        match key.to_ascii_lowercase().as_str() {
+            "full address" => config.target = Some(value.string()?.to_string()),
+            "username" => match value.string()?.split_once('\\') {
+                Some((domain, username)) => {
+                    config.domain = domain.to_string();
+                    config.username = username.to_string();
+                }
+                None => config.username = value.string()?.to_string(),
+            },
+            "domain" => config.domain = value.string()?.to_string(),
+            "screen mode id" => config.fullscreen = value.integer()? == 2,
+            "desktopwidth" => config.desktop_width = value.integer()? as u16,
+            "desktopheight" => config.desktop_height = value.integer()? as u16,
+            "session bpp" => config.color_depth = value.integer()? as u16,
+            "keyboardhook" => {
+                config.keyboard_hook = u8::try_from(value.integer()?)
+                    .ok()
+                    .and_then(|hook| KeyboardHook::try_from(hook).ok())
+                    .ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("RDPFILE: invalid keyboard hook {:?}", line),
+                        )
+                    })?
+            }
+            "authentication level" => config.check_certificate = value.integer()? != 0,
+            "gatewayhostname" if !value.string()?.is_empty() => {
+                config.gateway = Some(value.string()?.to_string())
+            }
+            "gatewayusagemethod" => gateway_usage = Some(value.integer()?),
+            "pcb" if !value.string()?.is_empty() => {
+                config.preconnection = Some(PreconnectionPdu::with_blob(0, value.string()?))
+            }
+            "disable wallpaper" => {
+                config.performance_flags.disable_wallpaper = value.integer()? != 0
+            }
+            "disable full window drag" => {
+                config.performance_flags.disable_full_window_drag = value.integer()? != 0
+            }
+            "disable menu anims" => {
+                config.performance_flags.disable_menu_animations = value.integer()? != 0
+            }
+            "disable themes" => config.performance_flags.disable_theming = value.integer()? != 0,
+            "disable cursor setting" => {
+                config.performance_flags.disable_cursor_settings = value.integer()? != 0
+            }
+            "allow font smoothing" => {
+                config.performance_flags.enable_font_smoothing = value.integer()? != 0
+            }
+            "allow desktop composition" => {
+                config.performance_flags.enable_desktop_composition = value.integer()? != 0
+            }
+            _ => (),
+        }
+    }
+
+    if matches!(gateway_usage, Some(usage) if GATEWAY_USAGE_DIRECT.contains(&usage)) {
+        config.gateway = None;
+    }
+    Ok(config)
+}
+
+/// Value of a setting with its declared type
+enum Value<'a> {
+    String(&'a str),
+    Integer(&'a str),
+}
+
+impl<'a> Value<'a> {
+    fn string(&self) -> Result<&'a str> {
+        match self {
+            Value::String(value) => Ok(value),
+            Value::Integer(_) => Err(Error::new(
+                ErrorKind::InvalidData,
+                "RDPFILE: expecting a string setting",
+            )),
+        }
+    }
+
+    fn integer(&self) -> Result<i64> {
+        match self {
+            Value::Integer(value) => value.trim().parse().map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("RDPFILE: invalid integer {:?}", value),
+                )
+            }),
+            Value::String(_) => Err(Error::new(
+                ErrorKind::InvalidData,
+                "RDPFILE: expecting an integer setting",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test of a file saved by mstsc
+    #[test]
+    fn test_read_rdp_file_utf16() {
+        let text = "screen mode id:i:2\r\nsession bpp:i:16\r\ndisable wallpaper:i:1\r\n\
+                    keyboardhook:i:1\r\n\
+                    gatewayhostname:s:gw.example.com\r\ngatewayusagemethod:i:1\r\n";
+        let mut content = vec![0xFF, 0xFE];
+        content.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+
+        let config = read_rdp_file(&content).unwrap();
+        assert!(config.fullscreen);
+        assert_eq!(config.color_depth, 16);
+        assert_eq!(config.keyboard_hook, KeyboardHook::Remote);
+        assert!(config.performance_flags.disable_wallpaper);
+        assert_eq!(config.gateway.as_deref(), Some("gw.example.com"));
+    }
+
+    /// The gateway is ignored when the usage method bypasses it
+    #[test]
+    fn test_parse_rdp_file_gateway_bypassed() {
+        let config =
+            parse_rdp_file("gatewayhostname:s:gw.example.com\ngatewayusagemethod:i:4\n").unwrap();
+        assert_eq!(config.gateway, None);
+    }
+
+    /// The preconnection blob of Hyper-V connection files
+    #[test]
+    fn test_parse_rdp_file_pcb() {
+        let config =
+            parse_rdp_file("full address:s:host:2179\npcb:s:vm-id;EnhancedMode=1\n").unwrap();
+        assert_eq!(
+            config.preconnection,
+            Some(PreconnectionPdu::vmconnect("vm-id", true))
+        );
+    }
+
+    /// Test of a setting with an unexpected value
+    #[test]
+    fn test_parse_rdp_file_invalid_integer() {
+        let result = parse_rdp_file("desktopwidth:i:wide\n");
+        assert_eq!(result.err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+        assert!(parse_rdp_file("full address\n").is_err());
+        assert!(parse_rdp_file("keyboardhook:i:3\n").is_err());
+    }
+}
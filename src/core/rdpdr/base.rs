@@ -0,0 +1,886 @@
+use crate::model::data::{check_remaining, Message};
+use crate::model::unicode::{from_unicode, Unicode};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the static virtual channel of the device redirection
+pub const RDPDR_CHANNEL_NAME: &str = "rdpdr";
+
+/// Size of the RDPDR_HEADER
+const HEADER_SIZE: usize = 4;
+
+/// Version announced by the client
+/// MS-RDPEFS 2.2.2.3 Client Announce Reply (DR_CORE_CLIENT_ANNOUNCE_RSP)
+pub const RDPDR_VERSION_MAJOR: u16 = 0x0001;
+pub const RDPDR_VERSION_MINOR: u16 = 0x000C;
+
+/// Size of the capability header
+/// MS-RDPEFS 2.2.1.2.1 Capability Header (CAPABILITY_HEADER)
+const CAPABILITY_HEADER_SIZE: usize = 8;
+
+/// Size of the preferred DOS name of a device
+/// MS-RDPEFS 2.2.1.3 Device Announce Header (DEVICE_ANNOUNCE)
+const DOS_NAME_SIZE: usize = 8;
+
+/// Size of the device I/O request header, with the major and minor functions
+/// MS-RDPEFS 2.2.1.4 Device I/O Request (DR_DEVICE_IOREQUEST)
+const IO_REQUEST_HEADER_SIZE: usize = 20;
+
+/// Component of a RDPDR PDU
+/// MS-RDPEFS 2.2.1.1 Shared Header (RDPDR_HEADER)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum Component {
+    RdpdrCtypCore = 0x4472,
+    RdpdrCtypPrn = 0x5052,
+}
+
+/// Packet id of a RDPDR PDU
+/// MS-RDPEFS 2.2.1.1 Shared Header (RDPDR_HEADER)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum PacketId {
+    PakidCoreServerAnnounce = 0x496E,
+    PakidCoreClientidConfirm = 0x4343,
+    PakidCoreClientName = 0x434E,
+    PakidCoreDevicelistAnnounce = 0x4441,
+    PakidCoreDeviceReply = 0x6472,
+    PakidCoreDeviceIorequest = 0x4952,
+    PakidCoreDeviceIocompletion = 0x4943,
+    PakidCoreServerCapability = 0x5350,
+    PakidCoreClientCapability = 0x4350,
+    PakidCoreDevicelistRemove = 0x444D,
+    PakidPrnCacheData = 0x5043,
+    PakidCoreUserLoggedon = 0x554C,
+    PakidPrnUsingXps = 0x5543,
+}
+
+/// Type of a capability set
+/// MS-RDPEFS 2.2.1.2.1 Capability Header (CAPABILITY_HEADER)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CapabilityType {
+    CapGeneralType = 0x0001,
+    CapPrinterType = 0x0002,
+    CapPortType = 0x0003,
+    CapDriveType = 0x0004,
+    CapSmartcardType = 0x0005,
+}
+
+/// PDUs of the server announced by the general capability set
+/// MS-RDPEFS 2.2.2.7.1 General Capability Set (GENERAL_CAPS_SET)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExtendedPdu {
+    RdpdrDeviceRemovePdus = 0x00000001,
+    RdpdrClientDisplayNamePdu = 0x00000002,
+    RdpdrUserLoggedonPdu = 0x00000004,
+}
+
+/// Type of a redirected device
+/// MS-RDPEFS 2.2.1.3 Device Announce Header (DEVICE_ANNOUNCE)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum DeviceType {
+    RdpdrDtypSerial = 0x00000001,
+    RdpdrDtypParallel = 0x00000002,
+    RdpdrDtypPrint = 0x00000004,
+    RdpdrDtypFilesystem = 0x00000008,
+    RdpdrDtypSmartcard = 0x00000020,
+}
+
+/// Major function of an IRP
+/// MS-RDPEFS 2.2.1.4 Device I/O Request (DR_DEVICE_IOREQUEST)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum MajorFunction {
+    IrpMjCreate = 0x00000000,
+    IrpMjClose = 0x00000002,
+    IrpMjRead = 0x00000003,
+    IrpMjWrite = 0x00000004,
+    IrpMjDeviceControl = 0x0000000E,
+    IrpMjQueryVolumeInformation = 0x0000000A,
+    IrpMjSetVolumeInformation = 0x0000000B,
+    IrpMjQueryInformation = 0x00000005,
+    IrpMjSetInformation = 0x00000006,
+    IrpMjDirectoryControl = 0x0000000C,
+    IrpMjLockControl = 0x00000011,
+}
+
+/// Minor function of a directory control IRP
+/// MS-RDPEFS 2.2.1.4 Device I/O Request (DR_DEVICE_IOREQUEST)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MinorFunction {
+    IrpMnQueryDirectory = 0x00000001,
+    IrpMnNotifyChangeDirectory = 0x00000002,
+}
+
+/// Status of an IRP
+/// MS-ERREF 2.3.1 NTSTATUS Values
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NtStatus {
+    StatusSuccess = 0x00000000,
+    StatusUnsuccessful = 0xC0000001,
+    StatusNotImplemented = 0xC0000002,
+    StatusInvalidParameter = 0xC000000D,
+    StatusNoSuchDevice = 0xC000000E,
+    StatusNoSuchFile = 0xC000000F,
+    StatusInvalidDeviceRequest = 0xC0000010,
+    StatusAccessDenied = 0xC0000022,
+    StatusObjectNameInvalid = 0xC0000033,
+    StatusObjectNameNotFound = 0xC0000034,
+    StatusObjectNameCollision = 0xC0000035,
+    StatusFileIsADirectory = 0xC00000BA,
+    StatusNotSupported = 0xC00000BB,
+    StatusNotADirectory = 0xC0000103,
+    StatusCancelled = 0xC0000120,
+    StatusNoMoreFiles = 0x80000006,
+    StatusNotifyEnumDir = 0x0000010C,
+}
+
+/// A capability set of the client or the server
+/// MS-RDPEFS 2.2.1.2.1 Capability Header (CAPABILITY_HEADER)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capability {
+    pub capability_type: u16,
+    pub version: u32,
+    /// Data after the header
+    pub data: Vec<u8>,
+}
+
+impl Capability {
+    /// General capability set of the client
+    /// MS-RDPEFS 2.2.2.7.1 General Capability Set (GENERAL_CAPS_SET)
+    pub fn general(extended_pdu: u32) -> Self {
+        let mut data = Vec::with_capacity(36);
+        // Os type and version are ignored
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&RDPDR_VERSION_MAJOR.to_le_bytes());
+        data.extend_from_slice(&RDPDR_VERSION_MINOR.to_le_bytes());
+        // All the major functions
+        data.extend_from_slice(&0x0000FFFFu32.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&extended_pdu.to_le_bytes());
+        // Extra flags and special type device cap
+        data.extend_from_slice(&[0; 12]);
+        Capability {
+            capability_type: CapabilityType::CapGeneralType as u16,
+            version: 2,
+            data,
+        }
+    }
+
+    /// Capability set of a device type, without data
+    pub fn device(capability_type: CapabilityType, version: u32) -> Self {
+        Capability {
+            capability_type: capability_type as u16,
+            version,
+            data: Vec::new(),
+        }
+    }
+
+    /// Extended PDUs of a general capability set
+    pub fn extended_pdu(&self) -> Option<u32> {
+        if self.capability_type != CapabilityType::CapGeneralType as u16 || self.data.len() < 24 {
+            return None;
+        }
+        Some(u32::from_le_bytes(self.data[20..24].try_into().unwrap()))
+    }
+}
+
+/// A device announced by the client
+/// MS-RDPEFS 2.2.1.3 Device Announce Header (DEVICE_ANNOUNCE)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceAnnounce {
+    pub device_type: u32,
+    pub device_id: u32,
+    /// ASCII name, truncated to 7 characters
+    pub dos_name: String,
+    /// Depends on the device type
+    pub data: Vec<u8>,
+}
+
+/// IRP sent by the server to a device
+/// The parameters depend on the major function
+/// MS-RDPEFS 2.2.1.4 Device I/O Request (DR_DEVICE_IOREQUEST)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIoRequest {
+    pub device_id: u32,
+    pub file_id: u32,
+    /// Given back in the response
+    pub completion_id: u32,
+    pub major_function: u32,
+    pub minor_function: u32,
+    pub data: Vec<u8>,
+}
+
+/// Parameters of the common IRPs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IoRequest {
+    /// MS-RDPEFS 2.2.1.4.1 Device Create Request (DR_CREATE_REQ)
+    Create {
+        desired_access: u32,
+        allocation_size: u64,
+        file_attributes: u32,
+        shared_access: u32,
+        create_disposition: u32,
+        create_options: u32,
+        path: String,
+    },
+    /// MS-RDPEFS 2.2.1.4.2 Device Close Request (DR_CLOSE_REQ)
+    Close,
+    /// MS-RDPEFS 2.2.1.4.3 Device Read Request (DR_READ_REQ)
+    Read { length: u32, offset: u64 },
+    /// MS-RDPEFS 2.2.1.4.4 Device Write Request (DR_WRITE_REQ)
+    Write { offset: u64, data: Vec<u8> },
+    /// MS-RDPEFS 2.2.1.4.5 Device Control Request (DR_CONTROL_REQ)
+    DeviceControl {
+        output_length: u32,
+        io_control_code: u32,
+        input: Vec<u8>,
+    },
+    /// MS-RDPEFS 2.2.3.3.6 Server Drive Query Volume Information Request
+    QueryVolumeInformation { fs_information_class: u32 },
+    /// MS-RDPEFS 2.2.3.3.8 Server Drive Query Information Request
+    QueryInformation { fs_information_class: u32 },
+    /// MS-RDPEFS 2.2.3.3.9 Server Drive Set Information Request
+    SetInformation {
+        fs_information_class: u32,
+        data: Vec<u8>,
+    },
+    /// Path ends with the pattern of the entries
+    /// MS-RDPEFS 2.2.3.3.10 Server Drive Query Directory Request
+    QueryDirectory {
+        fs_information_class: u32,
+        initial_query: bool,
+        path: String,
+    },
+    /// MS-RDPEFS 2.2.3.3.11 Server Drive NotifyChange Directory Request
+    NotifyChangeDirectory {
+        watch_tree: bool,
+        completion_filter: u32,
+    },
+    /// MS-RDPEFS 2.2.3.3.12 Server Drive Lock Control Request
+    LockControl,
+    /// Parameters are left in the request data
+    Other,
+}
+
+impl DeviceIoRequest {
+    /// Read the parameters of the request
+    pub fn parameters(&self) -> Result<IoRequest> {
+        let mut buffer = BytesMut::from(&self.data[..]);
+        let buffer = &mut buffer;
+        Ok(match MajorFunction::try_from(self.major_function) {
+            Ok(MajorFunction::IrpMjCreate) => {
+                check_remaining(buffer, 32, "RDPDR: create request")?;
+                let desired_access = buffer.get_u32_le();
+                let allocation_size = buffer.get_u64_le();
+                let file_attributes = buffer.get_u32_le();
+                let shared_access = buffer.get_u32_le();
+                let create_disposition = buffer.get_u32_le();
+                let create_options = buffer.get_u32_le();
+                let path_length = buffer.get_u32_le() as usize;
+                check_remaining(buffer, path_length, "RDPDR: create request path")?;
+                IoRequest::Create {
+                    desired_access,
+                    allocation_size,
+                    file_attributes,
+                    shared_access,
+                    create_disposition,
+                    create_options,
+                    path: from_unicode(&buffer[..path_length]),
+                }
+            }
+            Ok(MajorFunction::IrpMjClose) => IoRequest::Close,
+            Ok(MajorFunction::IrpMjRead) => {
+                check_remaining(buffer, 12, "RDPDR: read request")?;
+                IoRequest::Read {
+                    length: buffer.get_u32_le(),
+                    offset: buffer.get_u64_le(),
+                }
+            }
+            Ok(MajorFunction::IrpMjWrite) => {
+                check_remaining(buffer, 32, "RDPDR: write request")?;
+                let length = buffer.get_u32_le() as usize;
+                let offset = buffer.get_u64_le();
+                buffer.advance(20);
+                check_remaining(buffer, length, "RDPDR: write request data")?;
+                IoRequest::Write {
+                    offset,
+                    data: buffer[..length].to_vec(),
+                }
+            }
+            Ok(MajorFunction::IrpMjDeviceControl) => {
+                check_remaining(buffer, 32, "RDPDR: device control request")?;
+                let output_length = buffer.get_u32_le();
+                let input_length = buffer.get_u32_le() as usize;
+                let io_control_code = buffer.get_u32_le();
+                buffer.advance(20);
+                check_remaining(buffer, input_length, "RDPDR: device control input")?;
+                IoRequest::DeviceControl {
+                    output_length,
+                    io_control_code,
+                    input: buffer[..input_length].to_vec(),
+                }
+            }
+            Ok(MajorFunction::IrpMjQueryVolumeInformation) => {
+                check_remaining(buffer, 4, "RDPDR: query volume information request")?;
+                IoRequest::QueryVolumeInformation {
+                    fs_information_class: buffer.get_u32_le(),
+                }
+            }
+            Ok(MajorFunction::IrpMjQueryInformation) => {
+                check_remaining(buffer, 4, "RDPDR: query information request")?;
+                IoRequest::QueryInformation {
+                    fs_information_class: buffer.get_u32_le(),
+                }
+            }
+            Ok(MajorFunction::IrpMjSetInformation) => {
+                check_remaining(buffer, 32, "RDPDR: set information request")?;
+                let fs_information_class = buffer.get_u32_le();
+                let length = buffer.get_u32_le() as usize;
+                buffer.advance(24);
+                check_remaining(buffer, length, "RDPDR: set information data")?;
+                IoRequest::SetInformation {
+                    fs_information_class,
+                    data: buffer[..length].to_vec(),
+                }
+            }
+            Ok(MajorFunction::IrpMjDirectoryControl)
+                if self.minor_function == MinorFunction::IrpMnQueryDirectory as u32 =>
+            {
+                check_remaining(buffer, 32, "RDPDR: query directory request")?;
+                let fs_information_class = buffer.get_u32_le();
+                let initial_query = buffer.get_u8() != 0;
+                let path_length = buffer.get_u32_le() as usize;
+                buffer.advance(23);
+                check_remaining(buffer, path_length, "RDPDR: query directory path")?;
+                IoRequest::QueryDirectory {
+                    fs_information_class,
+                    initial_query,
+                    path: from_unicode(&buffer[..path_length]),
+                }
+            }
+            Ok(MajorFunction::IrpMjDirectoryControl)
+                if self.minor_function == MinorFunction::IrpMnNotifyChangeDirectory as u32 =>
+            {
+                check_remaining(buffer, 5, "RDPDR: notify change directory request")?;
+                IoRequest::NotifyChangeDirectory {
+                    watch_tree: buffer.get_u8() != 0,
+                    completion_filter: buffer.get_u32_le(),
+                }
+            }
+            Ok(MajorFunction::IrpMjLockControl) => IoRequest::LockControl,
+            _ => IoRequest::Other,
+        })
+    }
+
+    /// Response to the request
+    /// The data follows the response header
+    pub fn complete(&self, io_status: u32, data: Vec<u8>) -> DeviceIoResponse {
+        DeviceIoResponse {
+            device_id: self.device_id,
+            completion_id: self.completion_id,
+            io_status,
+            data,
+        }
+    }
+
+    /// Failed response, with the empty fields expected for its major function
+    pub fn fail(&self, io_status: u32) -> DeviceIoResponse {
+        let length = match MajorFunction::try_from(self.major_function) {
+            // File id or length then padding
+            Ok(MajorFunction::IrpMjCreate)
+            | Ok(MajorFunction::IrpMjClose)
+            | Ok(MajorFunction::IrpMjWrite)
+            | Ok(MajorFunction::IrpMjSetInformation)
+            | Ok(MajorFunction::IrpMjLockControl) => 5,
+            // Length of the output
+            _ => 4,
+        };
+        self.complete(io_status, vec![0; length])
+    }
+}
+
+/// Completion of an IRP by the client
+/// MS-RDPEFS 2.2.1.5 Device I/O Response (DR_DEVICE_IOCOMPLETION)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIoResponse {
+    pub device_id: u32,
+    pub completion_id: u32,
+    /// NTSTATUS of the request
+    pub io_status: u32,
+    pub data: Vec<u8>,
+}
+
+/// Data of the response to a create request
+/// MS-RDPEFS 2.2.1.5.1 Device Create Response (DR_CREATE_RSP)
+pub fn create_response(file_id: u32, information: u8) -> Vec<u8> {
+    let mut data = file_id.to_le_bytes().to_vec();
+    data.push(information);
+    data
+}
+
+/// Data of the response to a read or device control request
+/// MS-RDPEFS 2.2.1.5.3 Device Read Response (DR_READ_RSP)
+pub fn read_response(output: &[u8]) -> Vec<u8> {
+    let mut data = (output.len() as u32).to_le_bytes().to_vec();
+    data.extend_from_slice(output);
+    data
+}
+
+/// Data of the response to a write request
+/// MS-RDPEFS 2.2.1.5.4 Device Write Response (DR_WRITE_RSP)
+pub fn write_response(length: u32) -> Vec<u8> {
+    let mut data = length.to_le_bytes().to_vec();
+    data.push(0);
+    data
+}
+
+/// PDU of the core component of the device redirection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RdpdrPdu {
+    /// MS-RDPEFS 2.2.2.2 Server Announce Request (DR_CORE_SERVER_ANNOUNCE_REQ)
+    ServerAnnounce {
+        version_minor: u16,
+        client_id: u32,
+    },
+    /// Sent by the client then confirmed by the server
+    /// MS-RDPEFS 2.2.2.3 Client Announce Reply (DR_CORE_CLIENT_ANNOUNCE_RSP)
+    ClientIdConfirm {
+        version_minor: u16,
+        client_id: u32,
+    },
+    /// Computer name of the client
+    /// MS-RDPEFS 2.2.2.4 Client Name Request (DR_CORE_CLIENT_NAME_REQ)
+    ClientName(String),
+    /// MS-RDPEFS 2.2.2.7 Server Core Capability Request (DR_CORE_CAPABILITY_REQ)
+    ServerCapability(Vec<Capability>),
+    /// MS-RDPEFS 2.2.2.8 Client Core Capability Response (DR_CORE_CAPABILITY_RSP)
+    ClientCapability(Vec<Capability>),
+    /// MS-RDPEFS 2.2.2.9 Client Device List Announce Request (DR_CORE_DEVICELIST_ANNOUNCE_REQ)
+    DeviceListAnnounce(Vec<DeviceAnnounce>),
+    /// Ids of the removed devices
+    /// MS-RDPEFS 2.2.3.2 Client Drive Device List Remove (DR_DEVICELIST_REMOVE)
+    DeviceListRemove(Vec<u32>),
+    /// MS-RDPEFS 2.2.2.1 Server Device Announce Response (DR_CORE_DEVICE_ANNOUNCE_RSP)
+    DeviceReply {
+        device_id: u32,
+        result_code: u32,
+    },
+    /// MS-RDPEFS 2.2.2.5 Server User Logged On (DR_CORE_USER_LOGGEDON)
+    UserLoggedOn,
+    DeviceIoRequest(DeviceIoRequest),
+    DeviceIoResponse(DeviceIoResponse),
+    /// Printer configuration to cache, starting with the event id
+    /// MS-RDPEPC 2.2.2.3 Add Printer Cachedata (DR_PRN_ADD_CACHEDATA)
+    PrinterCacheData(Vec<u8>),
+    /// The server prints in XPS on the printer
+    /// MS-RDPEPC 2.2.2.2 Server Printer Set XPS Mode (DR_PRN_USING_XPS)
+    PrinterUsingXps {
+        printer_id: u32,
+        flags: u32,
+    },
+}
+
+impl RdpdrPdu {
+    fn component(&self) -> Component {
+        match self {
+            RdpdrPdu::PrinterCacheData(_) | RdpdrPdu::PrinterUsingXps { .. } => {
+                Component::RdpdrCtypPrn
+            }
+            _ => Component::RdpdrCtypCore,
+        }
+    }
+
+    fn packet_id(&self) -> PacketId {
+        match self {
+            RdpdrPdu::ServerAnnounce { .. } => PacketId::PakidCoreServerAnnounce,
+            RdpdrPdu::ClientIdConfirm { .. } => PacketId::PakidCoreClientidConfirm,
+            RdpdrPdu::ClientName(_) => PacketId::PakidCoreClientName,
+            RdpdrPdu::ServerCapability(_) => PacketId::PakidCoreServerCapability,
+            RdpdrPdu::ClientCapability(_) => PacketId::PakidCoreClientCapability,
+            RdpdrPdu::DeviceListAnnounce(_) => PacketId::PakidCoreDevicelistAnnounce,
+            RdpdrPdu::DeviceListRemove(_) => PacketId::PakidCoreDevicelistRemove,
+            RdpdrPdu::DeviceReply { .. } => PacketId::PakidCoreDeviceReply,
+            RdpdrPdu::UserLoggedOn => PacketId::PakidCoreUserLoggedon,
+            RdpdrPdu::DeviceIoRequest(_) => PacketId::PakidCoreDeviceIorequest,
+            RdpdrPdu::DeviceIoResponse(_) => PacketId::PakidCoreDeviceIocompletion,
+            RdpdrPdu::PrinterCacheData(_) => PacketId::PakidPrnCacheData,
+            RdpdrPdu::PrinterUsingXps { .. } => PacketId::PakidPrnUsingXps,
+        }
+    }
+
+    fn data_length(&self) -> usize {
+        match self {
+            RdpdrPdu::ServerAnnounce { .. } | RdpdrPdu::ClientIdConfirm { .. } => 8,
+            RdpdrPdu::ClientName(name) => 12 + name.to_unicode().len() + 2,
+            RdpdrPdu::ServerCapability(capabilities) | RdpdrPdu::ClientCapability(capabilities) => {
+                4 + capabilities
+                    .iter()
+                    .map(|capability| CAPABILITY_HEADER_SIZE + capability.data.len())
+                    .sum::<usize>()
+            }
+            RdpdrPdu::DeviceListAnnounce(devices) => {
+                4 + devices
+                    .iter()
+                    .map(|device| 12 + DOS_NAME_SIZE + device.data.len())
+                    .sum::<usize>()
+            }
+            RdpdrPdu::DeviceListRemove(device_ids) => 4 + 4 * device_ids.len(),
+            RdpdrPdu::DeviceReply { .. } => 8,
+            RdpdrPdu::UserLoggedOn => 0,
+            RdpdrPdu::DeviceIoRequest(request) => IO_REQUEST_HEADER_SIZE + request.data.len(),
+            RdpdrPdu::DeviceIoResponse(response) => 12 + response.data.len(),
+            RdpdrPdu::PrinterCacheData(data) => data.len(),
+            RdpdrPdu::PrinterUsingXps { .. } => 8,
+        }
+    }
+}
+
+/// Read the capability sets of a capability PDU
+fn read_capabilities(buffer: &mut BytesMut) -> Result<Vec<Capability>> {
+    check_remaining(buffer, 4, "RDPDR: capabilities")?;
+    let count = buffer.get_u16_le();
+    let _padding = buffer.get_u16_le();
+    let mut capabilities = Vec::new();
+    for _ in 0..count {
+        check_remaining(buffer, CAPABILITY_HEADER_SIZE, "RDPDR: capability header")?;
+        let capability_type = buffer.get_u16_le();
+        let length = (buffer.get_u16_le() as usize).saturating_sub(CAPABILITY_HEADER_SIZE);
+        let version = buffer.get_u32_le();
+        check_remaining(buffer, length, "RDPDR: capability set")?;
+        capabilities.push(Capability {
+            capability_type,
+            version,
+            data: buffer.split_to(length).to_vec(),
+        });
+    }
+    Ok(capabilities)
+}
+
+/// Read a RDPDR PDU, the buffer holds the whole channel message
+pub fn read_rdpdr_pdu(buffer: &mut BytesMut) -> Result<RdpdrPdu> {
+    check_remaining(buffer, HEADER_SIZE, "RDPDR: header")?;
+    let component = buffer.get_u16_le();
+    let packet_id = buffer.get_u16_le();
+    let printer = match Component::try_from(component) {
+        Ok(Component::RdpdrCtypCore) => false,
+        Ok(Component::RdpdrCtypPrn) => true,
+        Err(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("RDPDR: unexpected component {:#x}", component),
+            ))
+        }
+    };
+
+    Ok(match PacketId::try_from(packet_id) {
+        Ok(PacketId::PakidPrnCacheData) if printer => {
+            RdpdrPdu::PrinterCacheData(buffer.split().to_vec())
+        }
+        Ok(PacketId::PakidPrnUsingXps) if printer => {
+            check_remaining(buffer, 8, "RDPDR: printer using XPS")?;
+            RdpdrPdu::PrinterUsingXps {
+                printer_id: buffer.get_u32_le(),
+                flags: buffer.get_u32_le(),
+            }
+        }
+        _ if printer => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("RDPDR: unexpected printer packet id {:#x}", packet_id),
+            ))
+        }
+        Ok(PacketId::PakidCoreServerAnnounce) | Ok(PacketId::PakidCoreClientidConfirm) => {
+            check_remaining(buffer, 8, "RDPDR: announce")?;
+            let _version_major = buffer.get_u16_le();
+            let version_minor = buffer.get_u16_le();
+            let client_id = buffer.get_u32_le();
+            if packet_id == PacketId::PakidCoreServerAnnounce as u16 {
+                RdpdrPdu::ServerAnnounce {
+                    version_minor,
+                    client_id,
+                }
+            } else {
+                RdpdrPdu::ClientIdConfirm {
+                    version_minor,
+                    client_id,
+                }
+            }
+        }
+        Ok(PacketId::PakidCoreClientName) => {
+            check_remaining(buffer, 12, "RDPDR: client name")?;
+            let unicode = buffer.get_u32_le() != 0;
+            let _code_page = buffer.get_u32_le();
+            let length = buffer.get_u32_le() as usize;
+            check_remaining(buffer, length, "RDPDR: computer name")?;
+            let name = buffer.split_to(length);
+            RdpdrPdu::ClientName(if unicode {
+                from_unicode(&name)
+            } else {
+                let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+                String::from_utf8_lossy(&name[..end]).to_string()
+            })
+        }
+        Ok(PacketId::PakidCoreServerCapability) => {
+            RdpdrPdu::ServerCapability(read_capabilities(buffer)?)
+        }
+        Ok(PacketId::PakidCoreClientCapability) => {
+            RdpdrPdu::ClientCapability(read_capabilities(buffer)?)
+        }
+        Ok(PacketId::PakidCoreDevicelistAnnounce) => {
+            check_remaining(buffer, 4, "RDPDR: device list")?;
+            let count = buffer.get_u32_le();
+            let mut devices = Vec::new();
+            for _ in 0..count {
+                check_remaining(buffer, 12 + DOS_NAME_SIZE, "RDPDR: device announce")?;
+                let device_type = buffer.get_u32_le();
+                let device_id = buffer.get_u32_le();
+                let name = buffer.split_to(DOS_NAME_SIZE);
+                let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+                let length = buffer.get_u32_le() as usize;
+                check_remaining(buffer, length, "RDPDR: device data")?;
+                devices.push(DeviceAnnounce {
+                    device_type,
+                    device_id,
+                    dos_name: String::from_utf8_lossy(&name[..end]).to_string(),
+                    data: buffer.split_to(length).to_vec(),
+                });
+            }
+            RdpdrPdu::DeviceListAnnounce(devices)
+        }
+        Ok(PacketId::PakidCoreDevicelistRemove) => {
+            check_remaining(buffer, 4, "RDPDR: device list remove")?;
+            let count = buffer.get_u32_le() as usize;
+            check_remaining(buffer, 4 * count, "RDPDR: device ids")?;
+            RdpdrPdu::DeviceListRemove((0..count).map(|_| buffer.get_u32_le()).collect())
+        }
+        Ok(PacketId::PakidCoreDeviceReply) => {
+            check_remaining(buffer, 8, "RDPDR: device reply")?;
+            RdpdrPdu::DeviceReply {
+                device_id: buffer.get_u32_le(),
+                result_code: buffer.get_u32_le(),
+            }
+        }
+        Ok(PacketId::PakidCoreUserLoggedon) => RdpdrPdu::UserLoggedOn,
+        Ok(PacketId::PakidCoreDeviceIorequest) => {
+            check_remaining(buffer, IO_REQUEST_HEADER_SIZE, "RDPDR: device I/O request")?;
+            RdpdrPdu::DeviceIoRequest(DeviceIoRequest {
+                device_id: buffer.get_u32_le(),
+                file_id: buffer.get_u32_le(),
+                completion_id: buffer.get_u32_le(),
+                major_function: buffer.get_u32_le(),
+                minor_function: buffer.get_u32_le(),
+                data: buffer.split().to_vec(),
+            })
+        }
+        Ok(PacketId::PakidCoreDeviceIocompletion) => {
+            check_remaining(buffer, 12, "RDPDR: device I/O response")?;
+            RdpdrPdu::DeviceIoResponse(DeviceIoResponse {
+                device_id: buffer.get_u32_le(),
+                completion_id: buffer.get_u32_le(),
+                io_status: buffer.get_u32_le(),
+                data: buffer.split().to_vec(),
+            })
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("RDPDR: unexpected packet id {:#x}", packet_id),
+            ))
+        }
+    })
+}
+
+#[async_trait]
+impl Message for RdpdrPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.component() as u16).await?;
+        writer.write_u16_le(self.packet_id() as u16).await?;
+        match self {
+            RdpdrPdu::ServerAnnounce {
+                version_minor,
+                client_id,
+            }
+            | RdpdrPdu::ClientIdConfirm {
+                version_minor,
+                client_id,
+            } => {
+                writer.write_u16_le(RDPDR_VERSION_MAJOR).await?;
+                writer.write_u16_le(*version_minor).await?;
+                writer.write_u32_le(*client_id).await?;
+            }
+            RdpdrPdu::ClientName(name) => {
+                let mut name = name.to_unicode();
+                name.extend_from_slice(&[0, 0]);
+                writer.write_u32_le(1).await?;
+                writer.write_u32_le(0).await?;
+                writer.write_u32_le(name.len() as u32).await?;
+                writer.write_all(&name).await?;
+            }
+            RdpdrPdu::ServerCapability(capabilities) | RdpdrPdu::ClientCapability(capabilities) => {
+                writer.write_u16_le(capabilities.len() as u16).await?;
+                writer.write_u16_le(0).await?;
+                for capability in capabilities {
+                    writer.write_u16_le(capability.capability_type).await?;
+                    writer
+                        .write_u16_le((CAPABILITY_HEADER_SIZE + capability.data.len()) as u16)
+                        .await?;
+                    writer.write_u32_le(capability.version).await?;
+                    writer.write_all(&capability.data).await?;
+                }
+            }
+            RdpdrPdu::DeviceListAnnounce(devices) => {
+                writer.write_u32_le(devices.len() as u32).await?;
+                for device in devices {
+                    writer.write_u32_le(device.device_type).await?;
+                    writer.write_u32_le(device.device_id).await?;
+                    // Null terminated
+                    let mut name = device.dos_name.as_bytes().to_vec();
+                    name.resize(DOS_NAME_SIZE - 1, 0);
+                    name.resize(DOS_NAME_SIZE, 0);
+                    writer.write_all(&name).await?;
+                    writer.write_u32_le(device.data.len() as u32).await?;
+                    writer.write_all(&device.data).await?;
+                }
+            }
+            RdpdrPdu::DeviceListRemove(device_ids) => {
+                writer.write_u32_le(device_ids.len() as u32).await?;
+                for device_id in device_ids {
+                    writer.write_u32_le(*device_id).await?;
+                }
+            }
+            RdpdrPdu::DeviceReply {
+                device_id,
+                result_code,
+            } => {
+                writer.write_u32_le(*device_id).await?;
+                writer.write_u32_le(*result_code).await?;
+            }
+            RdpdrPdu::UserLoggedOn => (),
+            RdpdrPdu::DeviceIoRequest(request) => {
+                writer.write_u32_le(request.device_id).await?;
+                writer.write_u32_le(request.file_id).await?;
+                writer.write_u32_le(request.completion_id).await?;
+                writer.write_u32_le(request.major_function).await?;
+                writer.write_u32_le(request.minor_function).await?;
+                writer.write_all(&request.data).await?;
+            }
+            RdpdrPdu::DeviceIoResponse(response) => {
+                writer.write_u32_le(response.device_id).await?;
+                writer.write_u32_le(response.completion_id).await?;
+                writer.write_u32_le(response.io_status).await?;
+                writer.write_all(&response.data).await?;
+            }
+            RdpdrPdu::PrinterCacheData(data) => writer.write_all(data).await?,
+            RdpdrPdu::PrinterUsingXps { printer_id, flags } => {
+                writer.write_u32_le(*printer_id).await?;
+                writer.write_u32_le(*flags).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The whole channel message is read, its length must be known
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        *self = read_rdpdr_pdu(&mut BytesMut::from(&data[..]))?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        HEADER_SIZE + self.data_length()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// PDUs of the initialization are written and read back
+    #[tokio::test]
+    async fn test_rdpdr_pdu() {
+        for pdu in [
+            RdpdrPdu::ClientIdConfirm {
+                version_minor: RDPDR_VERSION_MINOR,
+                client_id: 3,
+            },
+            RdpdrPdu::ClientName("desktop".to_string()),
+            RdpdrPdu::ClientCapability(vec![
+                Capability::general(ExtendedPdu::RdpdrUserLoggedonPdu as u32),
+                Capability::device(CapabilityType::CapDriveType, 2),
+            ]),
+            RdpdrPdu::DeviceListAnnounce(vec![DeviceAnnounce {
+                device_type: DeviceType::RdpdrDtypFilesystem as u32,
+                device_id: 1,
+                dos_name: "HOME".to_string(),
+                data: b"home\0".to_vec(),
+            }]),
+            RdpdrPdu::DeviceIoResponse(DeviceIoResponse {
+                device_id: 1,
+                completion_id: 7,
+                io_status: 0,
+                data: create_response(2, 0),
+            }),
+            RdpdrPdu::PrinterUsingXps {
+                printer_id: 1,
+                flags: 0,
+            },
+        ] {
+            let data = to_vec(&pdu).await.unwrap();
+            assert_eq!(data.len(), pdu.length());
+            let mut buffer = BytesMut::from(&data[..]);
+            assert_eq!(read_rdpdr_pdu(&mut buffer).unwrap(), pdu);
+        }
+    }
+
+    /// Announce of the server and parameters of a read request
+    #[test]
+    fn test_read_rdpdr_pdu() {
+        let mut buffer = BytesMut::from(
+            &[
+                0x72, 0x44, 0x6E, 0x49, 0x01, 0x00, 0x0D, 0x00, 0x05, 0x00, 0x00, 0x00,
+            ][..],
+        );
+        assert_eq!(
+            read_rdpdr_pdu(&mut buffer).unwrap(),
+            RdpdrPdu::ServerAnnounce {
+                version_minor: 0x0D,
+                client_id: 5,
+            }
+        );
+
+        let mut data = vec![0x72, 0x44, 0x52, 0x49];
+        for value in [1u32, 2, 3, MajorFunction::IrpMjRead as u32, 0, 16, 8, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[0; 20]);
+        let pdu = read_rdpdr_pdu(&mut BytesMut::from(&data[..])).unwrap();
+        let request = match pdu {
+            RdpdrPdu::DeviceIoRequest(request) => request,
+            _ => panic!("expect a device I/O request"),
+        };
+        assert_eq!(
+            request.parameters().unwrap(),
+            IoRequest::Read {
+                length: 16,
+                offset: 8
+            }
+        );
+        assert_eq!(
+            request.fail(NtStatus::StatusUnsuccessful as u32).data,
+            [0; 4]
+        );
+    }
+}
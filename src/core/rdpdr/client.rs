@@ -0,0 +1,366 @@
+use crate::core::rdpdr::base::{
+    read_rdpdr_pdu, Capability, CapabilityType, DeviceAnnounce, DeviceIoRequest, DeviceIoResponse,
+    DeviceType, ExtendedPdu, NtStatus, RdpdrPdu, RDPDR_VERSION_MINOR,
+};
+
+use bytes::BytesMut;
+use std::collections::BTreeMap;
+use std::io::Result;
+
+/// Minor version of the servers announcing all the devices before the logon
+const RDPDR_VERSION_MINOR_5: u16 = 0x0005;
+
+/// A device redirected to the server
+/// Each IRP of the server is given to the device it targets
+pub trait Device {
+    /// Type of the device, one of DeviceType
+    fn device_type(&self) -> u32;
+    /// Name of the device in the session, ASCII of 7 characters at most
+    fn dos_name(&self) -> String;
+    /// Data of the device announce, depends on the device type
+    fn device_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    /// Process a request of the server
+    /// None when the request completes later, with `poll`
+    fn process(&mut self, request: DeviceIoRequest) -> Option<DeviceIoResponse>;
+    /// Responses of the requests completed since the last call
+    fn poll(&mut self) -> Vec<DeviceIoResponse> {
+        Vec::new()
+    }
+}
+
+/// Event of the device redirection channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RdpdrEvent {
+    /// The server accepted a device, by its id
+    DeviceAdded(u32),
+    /// The server refused a device, with its NTSTATUS
+    DeviceRefused { device_id: u32, result_code: u32 },
+}
+
+/// A device with its announce state
+struct DeviceState {
+    device: Box<dyn Device + Send>,
+    announced: bool,
+}
+
+/// Client of the device redirection static virtual channel
+/// Announces the client and its devices then dispatches
+/// the IRPs of the server to the devices
+///
+/// # Example
+/// ```
+/// use rdp::core::rdpdr::client::RdpdrClient;
+/// let mut client = RdpdrClient::new("desktop");
+/// // Server announce request
+/// let mut data = bytes::BytesMut::from(&[0x72, 0x44, 0x6E, 0x49, 1, 0, 12, 0, 1, 0, 0, 0][..]);
+/// let responses = client.process(&mut data, &mut |_| {}).unwrap();
+/// // Client announce reply and client name
+/// assert_eq!(responses.len(), 2);
+/// ```
+pub struct RdpdrClient {
+    computer_name: String,
+    /// Devices by id, in their announce order
+    devices: BTreeMap<u32, DeviceState>,
+    next_device_id: u32,
+    /// Minor version of the server
+    version_minor: u16,
+    /// The client id is confirmed, devices can be announced
+    ready: bool,
+    /// The user is logged on, all the devices can be announced
+    logged_on: bool,
+}
+
+impl RdpdrClient {
+    pub fn new(computer_name: &str) -> Self {
+        RdpdrClient {
+            computer_name: computer_name.to_string(),
+            devices: BTreeMap::new(),
+            next_device_id: 1,
+            version_minor: RDPDR_VERSION_MINOR,
+            ready: false,
+            logged_on: false,
+        }
+    }
+
+    /// Redirect a device, with its new id
+    /// The returned device list must be sent when not empty,
+    /// the device is announced later when the channel isn't ready yet
+    pub fn add_device(&mut self, device: Box<dyn Device + Send>) -> (u32, Option<RdpdrPdu>) {
+        let device_id = self.next_device_id;
+        self.next_device_id += 1;
+        self.devices.insert(
+            device_id,
+            DeviceState {
+                device,
+                announced: false,
+            },
+        );
+        (device_id, self.announce())
+    }
+
+    /// Stop the redirection of a device
+    /// The returned PDU must be sent when the device was announced
+    pub fn remove_device(&mut self, device_id: u32) -> Option<RdpdrPdu> {
+        let state = self.devices.remove(&device_id)?;
+        state
+            .announced
+            .then(|| RdpdrPdu::DeviceListRemove(vec![device_id]))
+    }
+
+    /// Capabilities of the client, for all device types
+    fn capabilities(&self) -> RdpdrPdu {
+        RdpdrPdu::ClientCapability(vec![
+            Capability::general(
+                ExtendedPdu::RdpdrDeviceRemovePdus as u32
+                    | ExtendedPdu::RdpdrClientDisplayNamePdu as u32
+                    | ExtendedPdu::RdpdrUserLoggedonPdu as u32,
+            ),
+            Capability::device(CapabilityType::CapPrinterType, 1),
+            Capability::device(CapabilityType::CapPortType, 1),
+            Capability::device(CapabilityType::CapDriveType, 2),
+            Capability::device(CapabilityType::CapSmartcardType, 1),
+        ])
+    }
+
+    /// Announce the devices allowed by the state of the session
+    /// Smartcards are announced before the logon, to be used by it
+    /// MS-RDPEFS 3.2.5.1.7 Sending a Client Device List Announce Request Message
+    fn announce(&mut self) -> Option<RdpdrPdu> {
+        if !self.ready {
+            return None;
+        }
+        let all = self.logged_on || self.version_minor == RDPDR_VERSION_MINOR_5;
+        let mut devices = Vec::new();
+        for (device_id, state) in self.devices.iter_mut() {
+            let device_type = state.device.device_type();
+            if state.announced || !(all || device_type == DeviceType::RdpdrDtypSmartcard as u32) {
+                continue;
+            }
+            state.announced = true;
+            devices.push(DeviceAnnounce {
+                device_type,
+                device_id: *device_id,
+                dos_name: state.device.dos_name(),
+                data: state.device.device_data(),
+            });
+        }
+        (!devices.is_empty()).then(|| RdpdrPdu::DeviceListAnnounce(devices))
+    }
+
+    /// Give a request of the server to its device
+    fn dispatch(&mut self, request: DeviceIoRequest) -> Option<DeviceIoResponse> {
+        match self.devices.get_mut(&request.device_id) {
+            Some(state) => state.device.process(request),
+            None => Some(request.fail(NtStatus::StatusNoSuchDevice as u32)),
+        }
+    }
+
+    /// Responses of the requests completed later by the devices
+    /// Must be called regularly when devices complete requests later
+    pub fn poll(&mut self) -> Vec<RdpdrPdu> {
+        self.devices
+            .values_mut()
+            .flat_map(|state| state.device.poll())
+            .map(RdpdrPdu::DeviceIoResponse)
+            .collect()
+    }
+
+    /// Process a message of the channel
+    /// and build the PDUs expected by the server
+    pub fn process<T>(&mut self, buffer: &mut BytesMut, callback: &mut T) -> Result<Vec<RdpdrPdu>>
+    where
+        T: FnMut(RdpdrEvent),
+    {
+        let mut responses = Vec::new();
+        match read_rdpdr_pdu(buffer)? {
+            RdpdrPdu::ServerAnnounce {
+                version_minor,
+                client_id,
+            } => {
+                self.version_minor = version_minor.min(RDPDR_VERSION_MINOR);
+                responses.push(RdpdrPdu::ClientIdConfirm {
+                    version_minor: self.version_minor,
+                    client_id,
+                });
+                responses.push(RdpdrPdu::ClientName(self.computer_name.clone()));
+            }
+            RdpdrPdu::ServerCapability(capabilities) => {
+                // Without the user logged on PDU all the devices are announced at once
+                let extended_pdu = capabilities.iter().find_map(Capability::extended_pdu);
+                if extended_pdu.map_or(true, |flags| {
+                    flags & ExtendedPdu::RdpdrUserLoggedonPdu as u32 == 0
+                }) {
+                    self.logged_on = true;
+                }
+                responses.push(self.capabilities());
+            }
+            RdpdrPdu::ClientIdConfirm { .. } => {
+                self.ready = true;
+                responses.extend(self.announce());
+            }
+            RdpdrPdu::UserLoggedOn => {
+                self.logged_on = true;
+                responses.extend(self.announce());
+            }
+            RdpdrPdu::DeviceReply {
+                device_id,
+                result_code,
+            } => callback(if result_code == NtStatus::StatusSuccess as u32 {
+                RdpdrEvent::DeviceAdded(device_id)
+            } else {
+                RdpdrEvent::DeviceRefused {
+                    device_id,
+                    result_code,
+                }
+            }),
+            RdpdrPdu::DeviceIoRequest(request) => {
+                responses.extend(self.dispatch(request).map(RdpdrPdu::DeviceIoResponse));
+            }
+            // Printer configurations are not cached
+            // and XPS is announced by the printer itself
+            RdpdrPdu::PrinterCacheData(_) | RdpdrPdu::PrinterUsingXps { .. } => (),
+            // Only sent by the client
+            _ => (),
+        }
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::rdpdr::base::{read_response, IoRequest, MajorFunction};
+    use crate::model::data::to_vec;
+
+    /// Read the PDU written by the server
+    async fn server_data(pdu: &RdpdrPdu) -> BytesMut {
+        BytesMut::from(&to_vec(pdu).await.unwrap()[..])
+    }
+
+    /// Device answering read requests with their length
+    struct Echo {
+        device_type: DeviceType,
+    }
+
+    impl Device for Echo {
+        fn device_type(&self) -> u32 {
+            self.device_type as u32
+        }
+
+        fn dos_name(&self) -> String {
+            "ECHO".to_string()
+        }
+
+        fn process(&mut self, request: DeviceIoRequest) -> Option<DeviceIoResponse> {
+            match request.parameters() {
+                Ok(IoRequest::Read { length, .. }) => Some(request.complete(
+                    NtStatus::StatusSuccess as u32,
+                    read_response(&vec![7; length as usize]),
+                )),
+                _ => Some(request.fail(NtStatus::StatusNotSupported as u32)),
+            }
+        }
+    }
+
+    /// Smartcards are announced once the client id is confirmed,
+    /// the other devices once the user is logged on
+    #[tokio::test]
+    async fn test_announce_devices() {
+        let mut client = RdpdrClient::new("desktop");
+        let (drive_id, announce) = client.add_device(Box::new(Echo {
+            device_type: DeviceType::RdpdrDtypFilesystem,
+        }));
+        assert_eq!(announce, None);
+        let (smartcard_id, _) = client.add_device(Box::new(Echo {
+            device_type: DeviceType::RdpdrDtypSmartcard,
+        }));
+
+        let confirm = RdpdrPdu::ClientIdConfirm {
+            version_minor: RDPDR_VERSION_MINOR,
+            client_id: 1,
+        };
+        let responses = client
+            .process(&mut server_data(&confirm).await, &mut |_| {})
+            .unwrap();
+        let announced = |responses: &[RdpdrPdu]| -> Vec<u32> {
+            match &responses[..] {
+                [RdpdrPdu::DeviceListAnnounce(devices)] => {
+                    devices.iter().map(|device| device.device_id).collect()
+                }
+                _ => Vec::new(),
+            }
+        };
+        assert_eq!(announced(&responses), vec![smartcard_id]);
+
+        let responses = client
+            .process(&mut server_data(&RdpdrPdu::UserLoggedOn).await, &mut |_| {})
+            .unwrap();
+        assert_eq!(announced(&responses), vec![drive_id]);
+
+        let mut events = Vec::new();
+        let reply = RdpdrPdu::DeviceReply {
+            device_id: drive_id,
+            result_code: 0,
+        };
+        client
+            .process(&mut server_data(&reply).await, &mut |event| {
+                events.push(event)
+            })
+            .unwrap();
+        assert_eq!(events, vec![RdpdrEvent::DeviceAdded(drive_id)]);
+        assert_eq!(
+            client.remove_device(drive_id),
+            Some(RdpdrPdu::DeviceListRemove(vec![drive_id]))
+        );
+    }
+
+    /// Requests go to their device, unknown devices fail
+    #[tokio::test]
+    async fn test_dispatch() {
+        let mut client = RdpdrClient::new("desktop");
+        let (device_id, _) = client.add_device(Box::new(Echo {
+            device_type: DeviceType::RdpdrDtypSerial,
+        }));
+        let mut data = 3u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0; 28]);
+        let mut request = DeviceIoRequest {
+            device_id,
+            file_id: 1,
+            completion_id: 9,
+            major_function: MajorFunction::IrpMjRead as u32,
+            minor_function: 0,
+            data,
+        };
+        let responses = client
+            .process(
+                &mut server_data(&RdpdrPdu::DeviceIoRequest(request.clone())).await,
+                &mut |_| {},
+            )
+            .unwrap();
+        assert_eq!(
+            responses,
+            vec![RdpdrPdu::DeviceIoResponse(DeviceIoResponse {
+                device_id,
+                completion_id: 9,
+                io_status: 0,
+                data: vec![3, 0, 0, 0, 7, 7, 7],
+            })]
+        );
+
+        request.device_id = 42;
+        let responses = client
+            .process(
+                &mut server_data(&RdpdrPdu::DeviceIoRequest(request.clone())).await,
+                &mut |_| {},
+            )
+            .unwrap();
+        assert_eq!(
+            responses,
+            vec![RdpdrPdu::DeviceIoResponse(
+                request.fail(NtStatus::StatusNoSuchDevice as u32)
+            )]
+        );
+    }
+}
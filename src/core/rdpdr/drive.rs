@@ -0,0 +1,985 @@
+use crate::core::rdpdr::base::{
+    create_response, read_response, write_response, DeviceIoRequest, DeviceIoResponse, DeviceType,
+    IoRequest, NtStatus,
+};
+use crate::core::rdpdr::client::Device;
+use crate::model::unicode::{from_unicode, Unicode};
+
+use bytes::BufMut;
+use num_enum::TryFromPrimitive;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds between 1601 and 1970, FILETIME starts in 1601
+const FILETIME_EPOCH: u64 = 11644473600;
+
+/// Size reported by a local drive, std doesn't give the free space
+const LOCAL_VOLUME_SIZE: u64 = 1 << 40;
+
+/// Size of the allocation units of the volume
+const SECTORS_PER_UNIT: u32 = 8;
+const BYTES_PER_SECTOR: u32 = 512;
+
+/// How a file is opened
+/// MS-RDPEFS 2.2.1.4.1 Device Create Request (DR_CREATE_REQ)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum CreateDisposition {
+    FileSupersede = 0x00000000,
+    FileOpen = 0x00000001,
+    FileCreate = 0x00000002,
+    FileOpenIf = 0x00000003,
+    FileOverwrite = 0x00000004,
+    FileOverwriteIf = 0x00000005,
+}
+
+/// Options of a create request
+/// MS-SMB2 2.2.13 SMB2 CREATE Request
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CreateOption {
+    FileDirectoryFile = 0x00000001,
+    FileNonDirectoryFile = 0x00000040,
+    FileDeleteOnClose = 0x00001000,
+}
+
+/// Access rights allowing to write a file
+/// MS-SMB2 2.2.13.1.1 File_Pipe_Printer_Access_Mask
+const WRITE_ACCESS: u32 = 0x00000002 | 0x00000004 | 0x00010000 | 0x10000000 | 0x40000000;
+
+/// Result of a create request
+/// MS-RDPEFS 2.2.1.5.1 Device Create Response (DR_CREATE_RSP)
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum CreateInformation {
+    FileSuperseded = 0x00,
+    FileOpened = 0x01,
+    FileOverwritten = 0x03,
+}
+
+/// Information classes of the files
+/// MS-FSCC 2.4 File Information Classes
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum FileInformationClass {
+    FileDirectoryInformation = 1,
+    FileFullDirectoryInformation = 2,
+    FileBothDirectoryInformation = 3,
+    FileBasicInformation = 4,
+    FileStandardInformation = 5,
+    FileRenameInformation = 10,
+    FileNamesInformation = 12,
+    FileDispositionInformation = 13,
+    FileAllocationInformation = 19,
+    FileEndOfFileInformation = 20,
+    FileAttributeTagInformation = 35,
+}
+
+/// Information classes of the volume
+/// MS-FSCC 2.5 File System Information Classes
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum FsInformationClass {
+    FileFsVolumeInformation = 1,
+    FileFsSizeInformation = 3,
+    FileFsDeviceInformation = 4,
+    FileFsAttributeInformation = 5,
+    FileFsFullSizeInformation = 7,
+}
+
+/// Attributes of a file
+/// MS-FSCC 2.6 File Attributes
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FileAttribute {
+    FileAttributeReadonly = 0x00000001,
+    FileAttributeDirectory = 0x00000010,
+    FileAttributeArchive = 0x00000020,
+}
+
+/// Metadata of a file of the drive
+/// Times are FILETIME, in 100 ns since 1601
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileInformation {
+    pub size: u64,
+    pub directory: bool,
+    pub read_only: bool,
+    pub creation_time: u64,
+    pub last_access_time: u64,
+    pub last_write_time: u64,
+}
+
+impl FileInformation {
+    fn attributes(&self) -> u32 {
+        let mut attributes = if self.directory {
+            FileAttribute::FileAttributeDirectory as u32
+        } else {
+            FileAttribute::FileAttributeArchive as u32
+        };
+        if self.read_only {
+            attributes |= FileAttribute::FileAttributeReadonly as u32;
+        }
+        attributes
+    }
+
+    /// MS-FSCC 2.4.7 FileBasicInformation
+    fn write_basic(&self, data: &mut Vec<u8>) {
+        data.put_u64_le(self.creation_time);
+        data.put_u64_le(self.last_access_time);
+        data.put_u64_le(self.last_write_time);
+        // Change time
+        data.put_u64_le(self.last_write_time);
+        data.put_u32_le(self.attributes());
+    }
+}
+
+/// Size of the volume of the drive
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VolumeInformation {
+    pub label: String,
+    pub total: u64,
+    pub free: u64,
+}
+
+/// Filesystem shared by a drive
+/// Paths are relative to the root of the drive, with / separators,
+/// the root is the empty path
+pub trait RedirectedFs {
+    /// Metadata of a file or a directory
+    fn information(&mut self, path: &str) -> Result<FileInformation>;
+    /// Entries of a directory, with their name
+    fn list(&mut self, path: &str) -> Result<Vec<(String, FileInformation)>>;
+    /// Open a file, it is then read and written by the file id of the server
+    /// Directories are never opened
+    fn open(
+        &mut self,
+        file_id: u32,
+        path: &str,
+        disposition: CreateDisposition,
+        write: bool,
+    ) -> Result<()>;
+    fn close(&mut self, file_id: u32) -> Result<()>;
+    /// Read at most length bytes, less at the end of the file
+    fn read(&mut self, file_id: u32, offset: u64, length: u32) -> Result<Vec<u8>>;
+    fn write(&mut self, file_id: u32, offset: u64, data: &[u8]) -> Result<()>;
+    /// Truncate or extend an open file
+    fn set_size(&mut self, file_id: u32, size: u64) -> Result<()>;
+    fn create_dir(&mut self, path: &str) -> Result<()>;
+    /// Remove a file or an empty directory
+    fn remove(&mut self, path: &str) -> Result<()>;
+    fn rename(&mut self, from: &str, to: &str, replace: bool) -> Result<()>;
+    fn volume(&mut self) -> Result<VolumeInformation>;
+}
+
+/// FILETIME of a system time
+fn filetime(time: Result<SystemTime>) -> u64 {
+    time.ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| {
+            (duration.as_secs() + FILETIME_EPOCH) * 10_000_000
+                + duration.subsec_nanos() as u64 / 100
+        })
+}
+
+/// Filesystem of a local directory
+///
+/// # Example
+/// ```
+/// use rdp::core::rdpdr::drive::{LocalFs, RedirectedFs};
+/// let mut fs = LocalFs::new(std::env::temp_dir());
+/// assert!(fs.information("").unwrap().directory);
+/// ```
+pub struct LocalFs {
+    root: PathBuf,
+    /// Open files by file id
+    files: HashMap<u32, File>,
+}
+
+impl LocalFs {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalFs {
+            root: root.into(),
+            files: HashMap::new(),
+        }
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    fn file(&mut self, file_id: u32) -> Result<&mut File> {
+        self.files
+            .get_mut(&file_id)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "DRIVE: file is not open"))
+    }
+}
+
+impl RedirectedFs for LocalFs {
+    fn information(&mut self, path: &str) -> Result<FileInformation> {
+        let metadata = fs::metadata(self.path(path))?;
+        Ok(FileInformation {
+            size: metadata.len(),
+            directory: metadata.is_dir(),
+            read_only: metadata.permissions().readonly(),
+            creation_time: filetime(metadata.created()),
+            last_access_time: filetime(metadata.accessed()),
+            last_write_time: filetime(metadata.modified()),
+        })
+    }
+
+    fn list(&mut self, path: &str) -> Result<Vec<(String, FileInformation)>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.path(path))? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            let entry_path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", path, name)
+            };
+            // Entries removed meanwhile are skipped
+            if let Ok(information) = self.information(&entry_path) {
+                entries.push((name, information));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn open(
+        &mut self,
+        file_id: u32,
+        path: &str,
+        disposition: CreateDisposition,
+        write: bool,
+    ) -> Result<()> {
+        let mut options = OpenOptions::new();
+        options.read(true);
+        match disposition {
+            CreateDisposition::FileOpen => options.write(write),
+            CreateDisposition::FileCreate => options.write(true).create_new(true),
+            CreateDisposition::FileOpenIf => options.write(true).create(true),
+            CreateDisposition::FileOverwrite => options.write(true).truncate(true),
+            CreateDisposition::FileSupersede | CreateDisposition::FileOverwriteIf => {
+                options.write(true).create(true).truncate(true)
+            }
+        };
+        let file = options.open(self.path(path))?;
+        self.files.insert(file_id, file);
+        Ok(())
+    }
+
+    fn close(&mut self, file_id: u32) -> Result<()> {
+        self.files.remove(&file_id);
+        Ok(())
+    }
+
+    fn read(&mut self, file_id: u32, offset: u64, length: u32) -> Result<Vec<u8>> {
+        let file = self.file(file_id)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        file.take(length as u64).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write(&mut self, file_id: u32, offset: u64, data: &[u8]) -> Result<()> {
+        let file = self.file(file_id)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)
+    }
+
+    fn set_size(&mut self, file_id: u32, size: u64) -> Result<()> {
+        self.file(file_id)?.set_len(size)
+    }
+
+    fn create_dir(&mut self, path: &str) -> Result<()> {
+        fs::create_dir(self.path(path))
+    }
+
+    fn remove(&mut self, path: &str) -> Result<()> {
+        let path = self.path(path);
+        if fs::metadata(&path)?.is_dir() {
+            fs::remove_dir(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    fn rename(&mut self, from: &str, to: &str, replace: bool) -> Result<()> {
+        if !replace && fs::metadata(self.path(to)).is_ok() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "DRIVE: rename target exists",
+            ));
+        }
+        fs::rename(self.path(from), self.path(to))
+    }
+
+    fn volume(&mut self) -> Result<VolumeInformation> {
+        let label = self
+            .root
+            .file_name()
+            .map_or("".to_string(), |name| name.to_string_lossy().to_string());
+        Ok(VolumeInformation {
+            label,
+            total: LOCAL_VOLUME_SIZE,
+            free: LOCAL_VOLUME_SIZE,
+        })
+    }
+}
+
+/// NTSTATUS of a filesystem error
+fn status(error: &Error) -> u32 {
+    (match error.kind() {
+        ErrorKind::NotFound => NtStatus::StatusObjectNameNotFound,
+        ErrorKind::PermissionDenied => NtStatus::StatusAccessDenied,
+        ErrorKind::AlreadyExists => NtStatus::StatusObjectNameCollision,
+        ErrorKind::InvalidInput => NtStatus::StatusInvalidParameter,
+        ErrorKind::Unsupported => NtStatus::StatusNotSupported,
+        _ => NtStatus::StatusUnsuccessful,
+    }) as u32
+}
+
+/// Path of the drive from a path of the server
+/// None when it leaves the drive
+fn drive_path(path: &str) -> Option<String> {
+    let mut components = Vec::new();
+    for component in path.split(|c| c == '\\' || c == '/') {
+        match component {
+            "" | "." => (),
+            ".." => return None,
+            _ => components.push(component),
+        }
+    }
+    Some(components.join("/"))
+}
+
+/// Match a name against a pattern with * and ?, ignoring the case
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+        Some((c, rest)) => name.split_first().map_or(false, |(n, name)| {
+            (*c == '?' || c.to_lowercase().eq(n.to_lowercase())) && matches(rest, name)
+        }),
+    }
+}
+
+/// Entry of a directory enumeration
+/// MS-FSCC 2.4 File Information Classes
+fn directory_entry(
+    class: FileInformationClass,
+    name: &str,
+    information: &FileInformation,
+) -> Vec<u8> {
+    let name = name.to_unicode();
+    let mut data = Vec::new();
+    // Next entry offset and file index
+    data.put_u32_le(0);
+    data.put_u32_le(0);
+    if class == FileInformationClass::FileNamesInformation {
+        data.put_u32_le(name.len() as u32);
+        data.extend_from_slice(&name);
+        return data;
+    }
+    data.put_u64_le(information.creation_time);
+    data.put_u64_le(information.last_access_time);
+    data.put_u64_le(information.last_write_time);
+    data.put_u64_le(information.last_write_time);
+    data.put_u64_le(information.size);
+    data.put_u64_le(information.size);
+    data.put_u32_le(information.attributes());
+    data.put_u32_le(name.len() as u32);
+    if class != FileInformationClass::FileDirectoryInformation {
+        // Extended attributes size
+        data.put_u32_le(0);
+    }
+    if class == FileInformationClass::FileBothDirectoryInformation {
+        // No short name
+        data.put_u8(0);
+        data.put_u8(0);
+        data.extend_from_slice(&[0; 24]);
+    }
+    data.extend_from_slice(&name);
+    data
+}
+
+/// A file or a directory opened by the server
+struct DriveFile {
+    path: String,
+    directory: bool,
+    delete_on_close: bool,
+    /// Entries left of the current directory enumeration
+    entries: VecDeque<(String, FileInformation)>,
+}
+
+/// A change notification waiting for a change of its directory
+struct Notification {
+    request: DeviceIoRequest,
+    /// Entries of the directory when the request was received
+    entries: Vec<(String, FileInformation)>,
+}
+
+/// Drive redirected to the server
+/// Requests of the server are run on a RedirectedFs
+///
+/// Change notifications only watch the entries of the directory,
+/// they are completed by `RdpdrClient::poll`
+///
+/// # Example
+/// ```
+/// use rdp::core::rdpdr::client::RdpdrClient;
+/// use rdp::core::rdpdr::drive::Drive;
+/// let mut client = RdpdrClient::new("desktop");
+/// let (_device_id, _announce) = client.add_device(Box::new(Drive::local("HOME", "/home/user")));
+/// ```
+pub struct Drive {
+    name: String,
+    fs: Box<dyn RedirectedFs + Send>,
+    files: HashMap<u32, DriveFile>,
+    next_file_id: u32,
+    notifications: Vec<Notification>,
+}
+
+impl Drive {
+    pub fn new(name: &str, fs: Box<dyn RedirectedFs + Send>) -> Self {
+        Drive {
+            name: name.to_string(),
+            fs,
+            files: HashMap::new(),
+            next_file_id: 1,
+            notifications: Vec::new(),
+        }
+    }
+
+    /// Share a local directory
+    pub fn local(name: &str, root: impl Into<PathBuf>) -> Self {
+        Self::new(name, Box::new(LocalFs::new(root)))
+    }
+
+    /// Open or create a file or a directory
+    fn create(
+        &mut self,
+        path: &str,
+        desired_access: u32,
+        disposition: u32,
+        options: u32,
+    ) -> std::result::Result<(u32, u8), u32> {
+        let path = drive_path(path).ok_or(NtStatus::StatusAccessDenied as u32)?;
+        let disposition = CreateDisposition::try_from(disposition)
+            .map_err(|_| NtStatus::StatusInvalidParameter as u32)?;
+        let existing = self.fs.information(&path).ok();
+        let directory = match &existing {
+            Some(information) => information.directory,
+            None => options & CreateOption::FileDirectoryFile as u32 != 0,
+        };
+        if directory && options & CreateOption::FileNonDirectoryFile as u32 != 0 {
+            return Err(NtStatus::StatusFileIsADirectory as u32);
+        }
+        if !directory && options & CreateOption::FileDirectoryFile as u32 != 0 {
+            return Err(NtStatus::StatusNotADirectory as u32);
+        }
+
+        let file_id = self.next_file_id;
+        let information = match (&existing, disposition) {
+            (Some(_), CreateDisposition::FileCreate) => {
+                return Err(NtStatus::StatusObjectNameCollision as u32)
+            }
+            (None, CreateDisposition::FileOpen) | (None, CreateDisposition::FileOverwrite) => {
+                return Err(NtStatus::StatusObjectNameNotFound as u32)
+            }
+            (None, _) if directory => {
+                self.fs.create_dir(&path).map_err(|e| status(&e))?;
+                CreateInformation::FileSuperseded as u8
+            }
+            (Some(_), _) if directory => CreateInformation::FileOpened as u8,
+            _ => {
+                let write = desired_access & WRITE_ACCESS != 0;
+                self.fs
+                    .open(file_id, &path, disposition, write)
+                    .map_err(|e| status(&e))?;
+                match (&existing, disposition) {
+                    (None, _) => CreateInformation::FileSuperseded as u8,
+                    (Some(_), CreateDisposition::FileOpen)
+                    | (Some(_), CreateDisposition::FileOpenIf) => {
+                        CreateInformation::FileOpened as u8
+                    }
+                    _ => CreateInformation::FileOverwritten as u8,
+                }
+            }
+        };
+        self.next_file_id += 1;
+        self.files.insert(
+            file_id,
+            DriveFile {
+                path,
+                directory,
+                delete_on_close: options & CreateOption::FileDeleteOnClose as u32 != 0,
+                entries: VecDeque::new(),
+            },
+        );
+        Ok((file_id, information))
+    }
+
+    fn close(&mut self, file_id: u32) -> u32 {
+        let file = match self.files.remove(&file_id) {
+            Some(file) => file,
+            None => return NtStatus::StatusInvalidParameter as u32,
+        };
+        if !file.directory {
+            let _ = self.fs.close(file_id);
+        }
+        if file.delete_on_close {
+            if let Err(e) = self.fs.remove(&file.path) {
+                return status(&e);
+            }
+        }
+        NtStatus::StatusSuccess as u32
+    }
+
+    /// Information of a file
+    /// MS-RDPEFS 2.2.3.4.8 Client Drive Query Information Response
+    fn query_information(&mut self, path: &str, class: u32) -> std::result::Result<Vec<u8>, u32> {
+        let information = self.fs.information(path).map_err(|e| status(&e))?;
+        let mut data = Vec::new();
+        match FileInformationClass::try_from(class) {
+            Ok(FileInformationClass::FileBasicInformation) => information.write_basic(&mut data),
+            Ok(FileInformationClass::FileStandardInformation) => {
+                data.put_u64_le(information.size);
+                data.put_u64_le(information.size);
+                // Number of links, delete pending
+                data.put_u32_le(1);
+                data.put_u8(0);
+                data.put_u8(information.directory as u8);
+            }
+            Ok(FileInformationClass::FileAttributeTagInformation) => {
+                data.put_u32_le(information.attributes());
+                // No reparse tag
+                data.put_u32_le(0);
+            }
+            _ => return Err(NtStatus::StatusNotSupported as u32),
+        }
+        Ok(data)
+    }
+
+    /// Information of the volume
+    /// MS-RDPEFS 2.2.3.4.6 Client Drive Query Volume Information Response
+    fn query_volume_information(&mut self, class: u32) -> std::result::Result<Vec<u8>, u32> {
+        let volume = self.fs.volume().map_err(|e| status(&e))?;
+        let unit = (SECTORS_PER_UNIT * BYTES_PER_SECTOR) as u64;
+        let mut data = Vec::new();
+        match FsInformationClass::try_from(class) {
+            Ok(FsInformationClass::FileFsVolumeInformation) => {
+                let label = volume.label.to_unicode();
+                // Creation time and serial number
+                data.put_u64_le(0);
+                data.put_u32_le(0);
+                data.put_u32_le(label.len() as u32);
+                // Supports objects, the reserved byte is not sent
+                data.put_u8(0);
+                data.extend_from_slice(&label);
+            }
+            Ok(FsInformationClass::FileFsSizeInformation) => {
+                data.put_u64_le(volume.total / unit);
+                data.put_u64_le(volume.free / unit);
+                data.put_u32_le(SECTORS_PER_UNIT);
+                data.put_u32_le(BYTES_PER_SECTOR);
+            }
+            Ok(FsInformationClass::FileFsFullSizeInformation) => {
+                data.put_u64_le(volume.total / unit);
+                data.put_u64_le(volume.free / unit);
+                data.put_u64_le(volume.free / unit);
+                data.put_u32_le(SECTORS_PER_UNIT);
+                data.put_u32_le(BYTES_PER_SECTOR);
+            }
+            Ok(FsInformationClass::FileFsAttributeInformation) => {
+                let name = "NTFS".to_unicode();
+                // Case sensitive search, case preserved and unicode names
+                data.put_u32_le(0x00000007);
+                data.put_u32_le(255);
+                data.put_u32_le(name.len() as u32);
+                data.extend_from_slice(&name);
+            }
+            Ok(FsInformationClass::FileFsDeviceInformation) => {
+                // FILE_DEVICE_DISK without characteristics
+                data.put_u32_le(0x00000007);
+                data.put_u32_le(0);
+            }
+            Err(_) => return Err(NtStatus::StatusNotSupported as u32),
+        }
+        Ok(data)
+    }
+
+    /// Change the information of a file
+    /// MS-RDPEFS 2.2.3.3.9 Server Drive Set Information Request
+    fn set_information(&mut self, file_id: u32, class: u32, data: &[u8]) -> u32 {
+        let file = match self.files.get_mut(&file_id) {
+            Some(file) => file,
+            None => return NtStatus::StatusInvalidParameter as u32,
+        };
+        let result = match FileInformationClass::try_from(class) {
+            // Times and attributes are kept
+            Ok(FileInformationClass::FileBasicInformation)
+            | Ok(FileInformationClass::FileAllocationInformation) => Ok(()),
+            Ok(FileInformationClass::FileEndOfFileInformation) if data.len() >= 8 => {
+                let size = u64::from_le_bytes(data[..8].try_into().unwrap());
+                self.fs.set_size(file_id, size)
+            }
+            Ok(FileInformationClass::FileDispositionInformation) => {
+                file.delete_on_close = data.first().map_or(true, |pending| *pending != 0);
+                Ok(())
+            }
+            Ok(FileInformationClass::FileRenameInformation) if data.len() >= 6 => {
+                let replace = data[0] != 0;
+                let length = u32::from_le_bytes(data[2..6].try_into().unwrap()) as usize;
+                let name = data.get(6..6 + length).map(from_unicode);
+                match name.as_deref().and_then(drive_path) {
+                    Some(to) => self.fs.rename(&file.path, &to, replace).map(|_| {
+                        file.path = to;
+                    }),
+                    None => return NtStatus::StatusObjectNameInvalid as u32,
+                }
+            }
+            _ => return NtStatus::StatusNotSupported as u32,
+        };
+        match result {
+            Ok(()) => NtStatus::StatusSuccess as u32,
+            Err(e) => status(&e),
+        }
+    }
+
+    /// Next entry of a directory enumeration
+    /// MS-RDPEFS 2.2.3.4.10 Client Drive Query Directory Response
+    fn query_directory(
+        &mut self,
+        file_id: u32,
+        class: u32,
+        initial_query: bool,
+        path: &str,
+    ) -> std::result::Result<Vec<u8>, u32> {
+        let class = FileInformationClass::try_from(class)
+            .map_err(|_| NtStatus::StatusNotSupported as u32)?;
+        let file = self
+            .files
+            .get_mut(&file_id)
+            .ok_or(NtStatus::StatusInvalidParameter as u32)?;
+        if initial_query {
+            let path = drive_path(path).ok_or(NtStatus::StatusAccessDenied as u32)?;
+            let (directory, pattern) = match path.rsplit_once('/') {
+                Some((directory, pattern)) => (directory.to_string(), pattern),
+                None => (String::new(), path.as_str()),
+            };
+            let pattern: Vec<char> = pattern.chars().collect();
+            let mut entries = self.fs.list(&directory).map_err(|e| status(&e))?;
+            entries.retain(|(name, _)| matches(&pattern, &name.chars().collect::<Vec<_>>()));
+            if entries.is_empty() {
+                return Err(NtStatus::StatusNoSuchFile as u32);
+            }
+            file.entries = entries.into();
+        }
+        let (name, information) = file
+            .entries
+            .pop_front()
+            .ok_or(NtStatus::StatusNoMoreFiles as u32)?;
+        Ok(directory_entry(class, &name, &information))
+    }
+
+    /// Respond with a status and data after the length
+    fn respond(
+        request: &DeviceIoRequest,
+        result: std::result::Result<Vec<u8>, u32>,
+    ) -> DeviceIoResponse {
+        match result {
+            Ok(data) => request.complete(NtStatus::StatusSuccess as u32, read_response(&data)),
+            Err(io_status) => request.fail(io_status),
+        }
+    }
+}
+
+impl Device for Drive {
+    fn device_type(&self) -> u32 {
+        DeviceType::RdpdrDtypFilesystem as u32
+    }
+
+    fn dos_name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Full name of the drive, the DOS name is truncated
+    fn device_data(&self) -> Vec<u8> {
+        let mut data = self.name.to_unicode();
+        data.extend_from_slice(&[0, 0]);
+        data
+    }
+
+    fn process(&mut self, request: DeviceIoRequest) -> Option<DeviceIoResponse> {
+        let parameters = match request.parameters() {
+            Ok(parameters) => parameters,
+            Err(_) => return Some(request.fail(NtStatus::StatusInvalidParameter as u32)),
+        };
+        let file_id = request.file_id;
+        let path = self.files.get(&file_id).map(|file| file.path.clone());
+        Some(match (parameters, path) {
+            (
+                IoRequest::Create {
+                    desired_access,
+                    create_disposition,
+                    create_options,
+                    path,
+                    ..
+                },
+                _,
+            ) => match self.create(&path, desired_access, create_disposition, create_options) {
+                Ok((file_id, information)) => request.complete(
+                    NtStatus::StatusSuccess as u32,
+                    create_response(file_id, information),
+                ),
+                Err(io_status) => request.fail(io_status),
+            },
+            (IoRequest::Close, _) => {
+                let io_status = self.close(file_id);
+                self.notifications
+                    .retain(|notification| notification.request.file_id != file_id);
+                request.complete(io_status, vec![0; 5])
+            }
+            (IoRequest::Read { length, offset }, Some(_)) => {
+                match self.fs.read(file_id, offset, length) {
+                    Ok(data) => {
+                        request.complete(NtStatus::StatusSuccess as u32, read_response(&data))
+                    }
+                    Err(e) => request.fail(status(&e)),
+                }
+            }
+            (IoRequest::Write { offset, data }, Some(_)) => {
+                match self.fs.write(file_id, offset, &data) {
+                    Ok(()) => request.complete(
+                        NtStatus::StatusSuccess as u32,
+                        write_response(data.len() as u32),
+                    ),
+                    Err(e) => request.fail(status(&e)),
+                }
+            }
+            // No control codes are supported, the output is empty
+            (IoRequest::DeviceControl { .. }, _) => {
+                request.complete(NtStatus::StatusSuccess as u32, read_response(&[]))
+            }
+            (
+                IoRequest::QueryVolumeInformation {
+                    fs_information_class,
+                },
+                _,
+            ) => {
+                let result = self.query_volume_information(fs_information_class);
+                Self::respond(&request, result)
+            }
+            (
+                IoRequest::QueryInformation {
+                    fs_information_class,
+                },
+                Some(path),
+            ) => {
+                let result = self.query_information(&path, fs_information_class);
+                Self::respond(&request, result)
+            }
+            (
+                IoRequest::SetInformation {
+                    fs_information_class,
+                    data,
+                },
+                Some(_),
+            ) => {
+                let io_status = self.set_information(file_id, fs_information_class, &data);
+                request.complete(io_status, write_response(data.len() as u32))
+            }
+            (
+                IoRequest::QueryDirectory {
+                    fs_information_class,
+                    initial_query,
+                    path,
+                },
+                Some(_),
+            ) => {
+                let result =
+                    self.query_directory(file_id, fs_information_class, initial_query, &path);
+                Self::respond(&request, result)
+            }
+            (IoRequest::NotifyChangeDirectory { .. }, Some(path)) => {
+                let entries = self.fs.list(&path).unwrap_or_default();
+                self.notifications.push(Notification { request, entries });
+                return None;
+            }
+            // Locks are not enforced
+            (IoRequest::LockControl, Some(_)) => {
+                request.complete(NtStatus::StatusSuccess as u32, vec![0; 5])
+            }
+            (_, None) => request.fail(NtStatus::StatusInvalidParameter as u32),
+            _ => request.fail(NtStatus::StatusNotSupported as u32),
+        })
+    }
+
+    /// Complete the notifications of the directories that changed,
+    /// the server enumerates them again
+    fn poll(&mut self) -> Vec<DeviceIoResponse> {
+        let mut responses = Vec::new();
+        let files = &self.files;
+        let fs = &mut self.fs;
+        self.notifications.retain(|notification| {
+            let path = match files.get(&notification.request.file_id) {
+                Some(file) => &file.path,
+                None => return false,
+            };
+            if fs.list(path).unwrap_or_default() == notification.entries {
+                return true;
+            }
+            responses.push(
+                notification
+                    .request
+                    .complete(NtStatus::StatusNotifyEnumDir as u32, read_response(&[])),
+            );
+            false
+        });
+        responses
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::rdpdr::base::MajorFunction;
+
+    /// Empty directory of a test
+    fn test_directory(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rdp-drive-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    fn request(file_id: u32, major_function: MajorFunction, data: Vec<u8>) -> DeviceIoRequest {
+        DeviceIoRequest {
+            device_id: 1,
+            file_id,
+            completion_id: 0,
+            major_function: major_function as u32,
+            minor_function: 0,
+            data,
+        }
+    }
+
+    fn create_request(path: &str, disposition: CreateDisposition, options: u32) -> DeviceIoRequest {
+        let path = path.to_unicode();
+        let mut data = Vec::new();
+        data.put_u32_le(0x40000000);
+        data.put_u64_le(0);
+        data.put_u32_le(0);
+        data.put_u32_le(0);
+        data.put_u32_le(disposition as u32);
+        data.put_u32_le(options);
+        data.put_u32_le(path.len() as u32);
+        data.extend_from_slice(&path);
+        request(0, MajorFunction::IrpMjCreate, data)
+    }
+
+    /// Open a file and give its id
+    fn create(drive: &mut Drive, path: &str, disposition: CreateDisposition, options: u32) -> u32 {
+        let response = drive
+            .process(create_request(path, disposition, options))
+            .unwrap();
+        assert_eq!(response.io_status, 0);
+        u32::from_le_bytes(response.data[..4].try_into().unwrap())
+    }
+
+    /// Files are created, written and read back, paths can't leave the drive
+    #[test]
+    fn test_drive_read_write() {
+        let root = test_directory("read-write");
+        let mut drive = Drive::local("TEST", &root);
+        let file_id = create(&mut drive, "\\a.txt", CreateDisposition::FileCreate, 0);
+
+        let mut data = Vec::new();
+        data.put_u32_le(5);
+        data.put_u64_le(0);
+        data.extend_from_slice(&[0; 20]);
+        data.extend_from_slice(b"hello");
+        let response = drive
+            .process(request(file_id, MajorFunction::IrpMjWrite, data))
+            .unwrap();
+        assert_eq!(response.data, write_response(5));
+
+        let mut data = Vec::new();
+        data.put_u32_le(16);
+        data.put_u64_le(1);
+        data.extend_from_slice(&[0; 20]);
+        let response = drive
+            .process(request(file_id, MajorFunction::IrpMjRead, data))
+            .unwrap();
+        assert_eq!(response.data, read_response(b"ello"));
+        drive
+            .process(request(file_id, MajorFunction::IrpMjClose, vec![0; 32]))
+            .unwrap();
+        assert_eq!(fs::read(root.join("a.txt")).unwrap(), b"hello");
+
+        let response = drive
+            .process(create_request(
+                "\\..\\b.txt",
+                CreateDisposition::FileCreate,
+                0,
+            ))
+            .unwrap();
+        assert_eq!(response.io_status, NtStatus::StatusAccessDenied as u32);
+        let response = drive
+            .process(create_request("\\a.txt", CreateDisposition::FileCreate, 0))
+            .unwrap();
+        assert_eq!(
+            response.io_status,
+            NtStatus::StatusObjectNameCollision as u32
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Entries matching the pattern are enumerated one at a time
+    #[test]
+    fn test_drive_query_directory() {
+        let root = test_directory("query-directory");
+        fs::write(root.join("a.txt"), b"a").unwrap();
+        fs::write(root.join("b.log"), b"b").unwrap();
+        let mut drive = Drive::local("TEST", &root);
+        let file_id = create(
+            &mut drive,
+            "",
+            CreateDisposition::FileOpen,
+            CreateOption::FileDirectoryFile as u32,
+        );
+
+        let query = |initial_query: bool| {
+            let path = "\\*.TXT".to_unicode();
+            let mut data = Vec::new();
+            data.put_u32_le(FileInformationClass::FileNamesInformation as u32);
+            data.put_u8(initial_query as u8);
+            data.put_u32_le(path.len() as u32);
+            data.extend_from_slice(&[0; 23]);
+            data.extend_from_slice(&path);
+            let mut request = request(file_id, MajorFunction::IrpMjDirectoryControl, data);
+            request.minor_function = 1;
+            request
+        };
+        let response = drive.process(query(true)).unwrap();
+        assert_eq!(response.io_status, 0);
+        assert_eq!(&response.data[16..], &"a.txt".to_unicode()[..]);
+        let response = drive.process(query(false)).unwrap();
+        assert_eq!(response.io_status, NtStatus::StatusNoMoreFiles as u32);
+
+        // A new file completes the change notification
+        let mut notify = request(file_id, MajorFunction::IrpMjDirectoryControl, vec![0; 32]);
+        notify.minor_function = 2;
+        assert_eq!(drive.process(notify), None);
+        assert!(drive.poll().is_empty());
+        fs::write(root.join("c.txt"), b"c").unwrap();
+        assert_eq!(
+            drive.poll()[0].io_status,
+            NtStatus::StatusNotifyEnumDir as u32
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,5 @@
+pub mod base;
+pub mod client;
+pub mod drive;
+pub mod printer;
+pub mod smartcard;
\ No newline at end of file
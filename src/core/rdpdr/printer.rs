@@ -0,0 +1,320 @@
+use crate::core::rdpdr::base::{
+    create_response, read_response, write_response, DeviceIoRequest, DeviceIoResponse, DeviceType,
+    IoRequest, NtStatus,
+};
+use crate::core::rdpdr::client::Device;
+use crate::model::unicode::Unicode;
+
+use bytes::BufMut;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Result, Write};
+use std::path::PathBuf;
+
+/// Drivers of the server rendering the documents in a file format
+/// Their spool data is a PDF or XPS document
+pub const PDF_DRIVER_NAME: &str = "Microsoft Print To PDF";
+pub const XPS_DRIVER_NAME: &str = "Microsoft XPS Document Writer";
+
+/// Flags of the printer announce
+/// MS-RDPEPC 2.2.2.1 Client Device List Announce Request (DR_PRN_DEVICE_ANNOUNCE)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PrinterFlag {
+    RdpdrPrinterAnnounceFlagAscii = 0x00000001,
+    RdpdrPrinterAnnounceFlagDefaultprinter = 0x00000002,
+    RdpdrPrinterAnnounceFlagNetworkprinter = 0x00000004,
+    RdpdrPrinterAnnounceFlagTsprinter = 0x00000008,
+    RdpdrPrinterAnnounceFlagXpsformat = 0x00000010,
+}
+
+/// Metadata of a print job
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintJob {
+    /// Unique for the printer
+    pub id: u32,
+    /// Name of the printer in the session
+    pub printer: String,
+    /// Driver of the server, it gives the format of the spool data
+    pub driver: String,
+}
+
+/// Destination of the print jobs of a printer
+/// The spool data is written as rendered by the driver of the server
+pub trait PrintSink {
+    /// A document starts printing
+    fn start(&mut self, job: &PrintJob) -> Result<()>;
+    /// Next spool data of a job
+    fn write(&mut self, job_id: u32, data: &[u8]) -> Result<()>;
+    /// The document is complete, with the length of its spool data
+    fn finish(&mut self, job_id: u32, length: u64) -> Result<()>;
+}
+
+/// Writes each job in a file of a directory
+/// The file is named after the printer and the job id
+///
+/// # Example
+/// ```
+/// use rdp::core::rdpdr::printer::{DirectorySink, Printer, PDF_DRIVER_NAME};
+/// let sink = DirectorySink::new(std::env::temp_dir(), "pdf");
+/// let printer = Printer::new("Local PDF", PDF_DRIVER_NAME, Box::new(sink));
+/// ```
+pub struct DirectorySink {
+    directory: PathBuf,
+    extension: String,
+    files: HashMap<u32, File>,
+}
+
+impl DirectorySink {
+    pub fn new(directory: impl Into<PathBuf>, extension: &str) -> Self {
+        DirectorySink {
+            directory: directory.into(),
+            extension: extension.to_string(),
+            files: HashMap::new(),
+        }
+    }
+}
+
+impl PrintSink for DirectorySink {
+    fn start(&mut self, job: &PrintJob) -> Result<()> {
+        let name: String = job
+            .printer
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = self
+            .directory
+            .join(format!("{}-{}.{}", name, job.id, self.extension));
+        self.files.insert(job.id, File::create(path)?);
+        Ok(())
+    }
+
+    fn write(&mut self, job_id: u32, data: &[u8]) -> Result<()> {
+        match self.files.get_mut(&job_id) {
+            Some(file) => file.write_all(data),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self, job_id: u32, _length: u64) -> Result<()> {
+        match self.files.remove(&job_id) {
+            Some(file) => file.sync_all(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Printer redirected to the server
+/// Each print job of the session is streamed to the sink
+///
+/// # Example
+/// ```rust, ignore
+/// let sink = DirectorySink::new("/home/user/prints", "pdf");
+/// let printer = Printer::new("Local PDF", PDF_DRIVER_NAME, Box::new(sink));
+/// let (device_id, announce) = rdpdr.add_device(Box::new(printer));
+/// ```
+pub struct Printer {
+    name: String,
+    driver: String,
+    dos_name: String,
+    flags: u32,
+    sink: Box<dyn PrintSink + Send>,
+    /// Length of the spool data of the open jobs
+    jobs: HashMap<u32, u64>,
+    next_job_id: u32,
+}
+
+impl Printer {
+    pub fn new(name: &str, driver: &str, sink: Box<dyn PrintSink + Send>) -> Self {
+        let mut flags = 0;
+        if driver == XPS_DRIVER_NAME {
+            flags |= PrinterFlag::RdpdrPrinterAnnounceFlagXpsformat as u32;
+        }
+        Printer {
+            name: name.to_string(),
+            driver: driver.to_string(),
+            dos_name: "PRN1".to_string(),
+            flags,
+            sink,
+            jobs: HashMap::new(),
+            next_job_id: 1,
+        }
+    }
+
+    /// Use the printer by default in the session
+    pub fn set_default(&mut self, default: bool) {
+        if default {
+            self.flags |= PrinterFlag::RdpdrPrinterAnnounceFlagDefaultprinter as u32;
+        } else {
+            self.flags &= !(PrinterFlag::RdpdrPrinterAnnounceFlagDefaultprinter as u32);
+        }
+    }
+
+    /// DOS name of the printer, must differ for each printer
+    pub fn set_dos_name(&mut self, dos_name: &str) {
+        self.dos_name = dos_name.to_string();
+    }
+
+    /// Start a print job
+    fn create(&mut self) -> Result<u32> {
+        let id = self.next_job_id;
+        self.sink.start(&PrintJob {
+            id,
+            printer: self.name.clone(),
+            driver: self.driver.clone(),
+        })?;
+        self.next_job_id += 1;
+        self.jobs.insert(id, 0);
+        Ok(id)
+    }
+
+    fn write(&mut self, job_id: u32, data: &[u8]) -> Result<()> {
+        self.sink.write(job_id, data)?;
+        if let Some(length) = self.jobs.get_mut(&job_id) {
+            *length += data.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+impl Device for Printer {
+    fn device_type(&self) -> u32 {
+        DeviceType::RdpdrDtypPrint as u32
+    }
+
+    fn dos_name(&self) -> String {
+        self.dos_name.clone()
+    }
+
+    /// MS-RDPEPC 2.2.2.1 Client Device List Announce Request (DR_PRN_DEVICE_ANNOUNCE)
+    fn device_data(&self) -> Vec<u8> {
+        let unicode = |name: &str| {
+            let mut name = name.to_unicode();
+            name.extend_from_slice(&[0, 0]);
+            name
+        };
+        let driver = unicode(&self.driver);
+        let name = unicode(&self.name);
+        let mut data = Vec::new();
+        data.put_u32_le(self.flags);
+        // Code page
+        data.put_u32_le(0);
+        // No PnP name
+        data.put_u32_le(0);
+        data.put_u32_le(driver.len() as u32);
+        data.put_u32_le(name.len() as u32);
+        // No cached configuration
+        data.put_u32_le(0);
+        data.extend_from_slice(&driver);
+        data.extend_from_slice(&name);
+        data
+    }
+
+    fn process(&mut self, request: DeviceIoRequest) -> Option<DeviceIoResponse> {
+        let job_id = request.file_id;
+        Some(match request.parameters() {
+            Ok(IoRequest::Create { .. }) => match self.create() {
+                Ok(job_id) => {
+                    request.complete(NtStatus::StatusSuccess as u32, create_response(job_id, 0))
+                }
+                Err(_) => request.fail(NtStatus::StatusUnsuccessful as u32),
+            },
+            Ok(IoRequest::Write { data, .. }) if self.jobs.contains_key(&job_id) => {
+                match self.write(job_id, &data) {
+                    Ok(()) => request.complete(
+                        NtStatus::StatusSuccess as u32,
+                        write_response(data.len() as u32),
+                    ),
+                    Err(_) => request.fail(NtStatus::StatusUnsuccessful as u32),
+                }
+            }
+            Ok(IoRequest::Close) => {
+                let io_status = match self.jobs.remove(&job_id) {
+                    Some(length) => match self.sink.finish(job_id, length) {
+                        Ok(()) => NtStatus::StatusSuccess,
+                        Err(_) => NtStatus::StatusUnsuccessful,
+                    },
+                    None => NtStatus::StatusInvalidParameter,
+                };
+                request.complete(io_status as u32, vec![0; 5])
+            }
+            // No control codes are supported, the output is empty
+            Ok(IoRequest::DeviceControl { .. }) => {
+                request.complete(NtStatus::StatusSuccess as u32, read_response(&[]))
+            }
+            Ok(IoRequest::Write { .. }) | Err(_) => {
+                request.fail(NtStatus::StatusInvalidParameter as u32)
+            }
+            Ok(_) => request.fail(NtStatus::StatusNotSupported as u32),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::rdpdr::base::MajorFunction;
+    use std::fs;
+
+    fn request(file_id: u32, major_function: MajorFunction, data: Vec<u8>) -> DeviceIoRequest {
+        DeviceIoRequest {
+            device_id: 1,
+            file_id,
+            completion_id: 0,
+            major_function: major_function as u32,
+            minor_function: 0,
+            data,
+        }
+    }
+
+    /// A print job is streamed to a file of the directory
+    #[test]
+    fn test_print_job() {
+        let directory = std::env::temp_dir().join(format!("rdp-printer-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let sink = DirectorySink::new(&directory, "pdf");
+        let mut printer = Printer::new("Local PDF", PDF_DRIVER_NAME, Box::new(sink));
+
+        let response = printer
+            .process(request(0, MajorFunction::IrpMjCreate, vec![0; 32]))
+            .unwrap();
+        assert_eq!(response.data, create_response(1, 0));
+        for chunk in [&b"%PDF"[..], b"-1.7"] {
+            let mut data = Vec::new();
+            data.put_u32_le(chunk.len() as u32);
+            data.put_u64_le(0);
+            data.extend_from_slice(&[0; 20]);
+            data.extend_from_slice(chunk);
+            let response = printer
+                .process(request(1, MajorFunction::IrpMjWrite, data))
+                .unwrap();
+            assert_eq!(response.data, write_response(4));
+        }
+        let response = printer
+            .process(request(1, MajorFunction::IrpMjClose, vec![0; 32]))
+            .unwrap();
+        assert_eq!(response.io_status, 0);
+        assert_eq!(
+            fs::read(directory.join("Local_PDF-1.pdf")).unwrap(),
+            b"%PDF-1.7"
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    /// The announce gives the driver then the name of the printer
+    #[test]
+    fn test_printer_announce() {
+        let mut printer = Printer::new(
+            "P",
+            XPS_DRIVER_NAME,
+            Box::new(DirectorySink::new(std::env::temp_dir(), "xps")),
+        );
+        printer.set_default(true);
+        let data = printer.device_data();
+        assert_eq!(data[..4], [0x12, 0, 0, 0]);
+        let driver_length = XPS_DRIVER_NAME.len() * 2 + 2;
+        assert_eq!(data[12..16], (driver_length as u32).to_le_bytes());
+        assert_eq!(data[24 + driver_length..], [b'P', 0, 0, 0]);
+    }
+}
@@ -0,0 +1,1104 @@
+use crate::core::rdpdr::base::{
+    create_response, read_response, DeviceIoRequest, DeviceIoResponse, DeviceType, IoRequest,
+    NtStatus,
+};
+use crate::core::rdpdr::client::Device;
+use crate::model::unicode::{from_unicode, Unicode};
+
+use bytes::BufMut;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+/// Size of the type serialization headers of each call
+/// MS-RPCE 2.2.6 Type Serialization Version 1
+const NDR_HEADERS_SIZE: usize = 16;
+
+/// Size of the ATR in the reader states
+/// MS-RDPESC 2.2.1.5 ReaderState_Common_Call
+const READER_STATE_ATR_SIZE: usize = 36;
+
+/// Size of the ATR in the status return
+/// MS-RDPESC 2.2.3.10 Status_Return
+const STATUS_ATR_SIZE: usize = 32;
+
+/// Control codes of the smartcard calls
+/// MS-RDPESC 3.1.4 Message Processing Events and Sequencing Rules
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ScardIoctl {
+    ScardIoctlEstablishcontext = 0x00090014,
+    ScardIoctlReleasecontext = 0x00090018,
+    ScardIoctlIsvalidcontext = 0x0009001C,
+    ScardIoctlListreadersa = 0x00090028,
+    ScardIoctlListreadersw = 0x0009002C,
+    ScardIoctlGetstatuschangea = 0x000900A0,
+    ScardIoctlGetstatuschangew = 0x000900A4,
+    ScardIoctlCancel = 0x000900A8,
+    ScardIoctlConnecta = 0x000900AC,
+    ScardIoctlConnectw = 0x000900B0,
+    ScardIoctlReconnect = 0x000900B4,
+    ScardIoctlDisconnect = 0x000900B8,
+    ScardIoctlBegintransaction = 0x000900BC,
+    ScardIoctlEndtransaction = 0x000900C0,
+    ScardIoctlStatusa = 0x000900C8,
+    ScardIoctlStatusw = 0x000900CC,
+    ScardIoctlTransmit = 0x000900D0,
+    ScardIoctlControl = 0x000900D4,
+    ScardIoctlGetattrib = 0x000900D8,
+    ScardIoctlAccessstartedevent = 0x000900E0,
+    ScardIoctlReleasetartedevent = 0x000900E4,
+    ScardIoctlReadcachea = 0x000900F0,
+    ScardIoctlReadcachew = 0x000900F4,
+    ScardIoctlWritecachea = 0x000900F8,
+    ScardIoctlWritecachew = 0x000900FC,
+    ScardIoctlGettransmitcount = 0x00090100,
+}
+
+/// Return codes of the smartcard calls
+/// MS-ERREF 2.1 HRESULT Values
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ScardError {
+    ScardFInternalError = 0x80100001,
+    ScardECancelled = 0x80100002,
+    ScardEInvalidHandle = 0x80100003,
+    ScardEInvalidParameter = 0x80100004,
+    ScardEUnknownReader = 0x80100009,
+    ScardETimeout = 0x8010000A,
+    ScardENoService = 0x8010001D,
+    ScardEUnsupportedFeature = 0x80100022,
+    ScardENoReadersAvailable = 0x8010002E,
+    ScardWCacheItemNotFound = 0x80100070,
+}
+
+/// Result of a PC/SC call, the error is a SCARD return code
+pub type ScardResult<T> = std::result::Result<T, u32>;
+
+/// State of a reader, given and updated by get_status_change
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReaderState {
+    pub reader: String,
+    pub current_state: u32,
+    pub event_state: u32,
+    pub atr: Vec<u8>,
+}
+
+/// State of a connected card
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CardStatus {
+    pub readers: Vec<String>,
+    pub state: u32,
+    pub protocol: u32,
+    pub atr: Vec<u8>,
+}
+
+/// PC/SC of the client, called for the smartcard calls of the server
+/// Contexts and card handles are opaque values of the provider
+///
+/// get_status_change blocks on its own thread,
+/// cancel must wake it up from another thread
+pub trait PcscProvider {
+    fn establish_context(&self, scope: u32) -> ScardResult<u64>;
+    fn release_context(&self, context: u64) -> ScardResult<()>;
+    fn is_valid_context(&self, context: u64) -> ScardResult<()>;
+    fn list_readers(&self, context: u64) -> ScardResult<Vec<String>>;
+    /// Wait for a reader state to differ from its current state,
+    /// the timeout is in milliseconds
+    fn get_status_change(
+        &self,
+        context: u64,
+        timeout: u32,
+        states: &mut [ReaderState],
+    ) -> ScardResult<()>;
+    fn cancel(&self, context: u64) -> ScardResult<()>;
+    /// Card handle and active protocol
+    fn connect(
+        &self,
+        context: u64,
+        reader: &str,
+        share_mode: u32,
+        preferred_protocols: u32,
+    ) -> ScardResult<(u64, u32)>;
+    /// Active protocol
+    fn reconnect(
+        &self,
+        card: u64,
+        share_mode: u32,
+        preferred_protocols: u32,
+        initialization: u32,
+    ) -> ScardResult<u32>;
+    fn disconnect(&self, card: u64, disposition: u32) -> ScardResult<()>;
+    fn begin_transaction(&self, card: u64) -> ScardResult<()>;
+    fn end_transaction(&self, card: u64, disposition: u32) -> ScardResult<()>;
+    fn status(&self, card: u64) -> ScardResult<CardStatus>;
+    /// Response APDU of at most receive_length bytes
+    fn transmit(
+        &self,
+        card: u64,
+        protocol: u32,
+        send: &[u8],
+        receive_length: u32,
+    ) -> ScardResult<Vec<u8>>;
+    fn control(
+        &self,
+        card: u64,
+        control_code: u32,
+        input: &[u8],
+        output_length: u32,
+    ) -> ScardResult<Vec<u8>>;
+    fn get_attrib(&self, card: u64, attr_id: u32, length: u32) -> ScardResult<Vec<u8>>;
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reader of the NDR encoded calls
+/// Pointers are followed by their deferred data in the same order
+/// MS-RPCE 2.2.6 Type Serialization Version 1
+struct NdrReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> NdrReader<'a> {
+    fn new(data: &'a [u8]) -> Result<Self> {
+        if data.len() < NDR_HEADERS_SIZE {
+            return Err(invalid("SCARD: truncated type serialization headers"));
+        }
+        Ok(NdrReader {
+            data: &data[NDR_HEADERS_SIZE..],
+            position: 0,
+        })
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.data.len() < self.position + length {
+            return Err(invalid("SCARD: truncated call"));
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.position = (self.position + 3) & !3;
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    /// Referent id of a pointer, false when null
+    fn pointer(&mut self) -> Result<bool> {
+        Ok(self.u32()? != 0)
+    }
+
+    /// Conformant byte array
+    fn array(&mut self) -> Result<Vec<u8>> {
+        let count = self.u32()? as usize;
+        Ok(self.bytes(count)?.to_vec())
+    }
+
+    /// Conformant varying string, of UTF-16 or ASCII characters
+    fn string(&mut self, wide: bool) -> Result<String> {
+        let _max_count = self.u32()?;
+        let _offset = self.u32()?;
+        let count = self.u32()? as usize;
+        Ok(if wide {
+            from_unicode(self.bytes(count * 2)?)
+        } else {
+            let bytes = self.bytes(count)?;
+            let end = bytes.iter().position(|c| *c == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).to_string()
+        })
+    }
+
+    /// Context or card handle, up to 8 bytes
+    fn handle(&mut self, present: bool) -> Result<u64> {
+        if !present {
+            return Ok(0);
+        }
+        let data = self.array()?;
+        let mut handle = [0; 8];
+        let length = data.len().min(8);
+        handle[..length].copy_from_slice(&data[..length]);
+        Ok(u64::from_le_bytes(handle))
+    }
+
+    /// REDIR_SCARDCONTEXT without its deferred data
+    /// MS-RDPESC 2.2.1.1 REDIR_SCARDCONTEXT
+    fn context(&mut self) -> Result<bool> {
+        let _length = self.u32()?;
+        self.pointer()
+    }
+}
+
+/// Writer of the NDR encoded returns
+/// MS-RPCE 2.2.6 Type Serialization Version 1
+struct NdrWriter {
+    data: Vec<u8>,
+    next_referent: u32,
+}
+
+impl NdrWriter {
+    fn new(return_code: u32) -> Self {
+        let mut writer = NdrWriter {
+            data: Vec::new(),
+            next_referent: 0x00020000,
+        };
+        writer.u32(return_code);
+        writer
+    }
+
+    fn align(&mut self) {
+        while self.data.len() % 4 != 0 {
+            self.data.push(0);
+        }
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align();
+        self.data.put_u32_le(value);
+    }
+
+    fn pointer(&mut self, present: bool) {
+        if present {
+            let referent = self.next_referent;
+            self.next_referent += 4;
+            self.u32(referent);
+        } else {
+            self.u32(0);
+        }
+    }
+
+    /// Conformant byte array
+    fn array(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.data.extend_from_slice(data);
+    }
+
+    /// REDIR_SCARDCONTEXT or REDIR_SCARDHANDLE without its deferred data
+    fn handle(&mut self) {
+        self.u32(8);
+        self.pointer(true);
+    }
+
+    /// Serialized type with its headers, padded to 8 bytes
+    fn finish(mut self) -> Vec<u8> {
+        while self.data.len() % 8 != 0 {
+            self.data.push(0);
+        }
+        let mut data = vec![0x01, 0x10, 0x08, 0x00, 0xCC, 0xCC, 0xCC, 0xCC];
+        data.put_u32_le(self.data.len() as u32);
+        data.put_u32_le(0);
+        data.extend_from_slice(&self.data);
+        data
+    }
+}
+
+/// Reader names as a multi-string
+fn multi_string(names: &[String], wide: bool) -> Vec<u8> {
+    let mut data = Vec::new();
+    for name in names {
+        if wide {
+            data.extend_from_slice(&name.to_unicode());
+            data.extend_from_slice(&[0, 0]);
+        } else {
+            data.extend_from_slice(name.as_bytes());
+            data.push(0);
+        }
+    }
+    data.extend_from_slice(if wide { &[0, 0] } else { &[0] });
+    data
+}
+
+/// Return code and value of a result, the default value on errors
+fn split<T: Default>(result: ScardResult<T>) -> (u32, T) {
+    match result {
+        Ok(value) => (0, value),
+        Err(return_code) => (return_code, T::default()),
+    }
+}
+
+/// Long_Return of the calls without output
+/// MS-RDPESC 2.2.3.3 Long_Return
+fn long_return(result: ScardResult<()>) -> Vec<u8> {
+    NdrWriter::new(split(result).0).finish()
+}
+
+/// Read a GetStatusChange_Call and the reader states
+/// MS-RDPESC 2.2.2.12 GetStatusChangeW_Call
+fn read_status_change(input: &[u8], wide: bool) -> Result<(u64, u32, Vec<ReaderState>)> {
+    let mut reader = NdrReader::new(input)?;
+    let context = reader.context()?;
+    let timeout = reader.u32()?;
+    let _count = reader.u32()?;
+    let states = reader.pointer()?;
+    let context = reader.handle(context)?;
+    let mut readers = Vec::new();
+    if states {
+        let count = reader.u32()?;
+        let mut names = Vec::new();
+        for _ in 0..count {
+            names.push(reader.pointer()?);
+            let current_state = reader.u32()?;
+            let _event_state = reader.u32()?;
+            let _atr_length = reader.u32()?;
+            let _atr = reader.bytes(READER_STATE_ATR_SIZE)?;
+            readers.push(ReaderState {
+                current_state,
+                ..Default::default()
+            });
+        }
+        for (state, name) in readers.iter_mut().zip(names) {
+            if name {
+                state.reader = reader.string(wide)?;
+            }
+        }
+    }
+    Ok((context, timeout, readers))
+}
+
+/// GetStatusChange_Return
+/// MS-RDPESC 2.2.3.5 GetStatusChange_Return
+fn status_change_return(result: ScardResult<()>, states: &[ReaderState]) -> Vec<u8> {
+    let mut writer = NdrWriter::new(split(result).0);
+    writer.u32(states.len() as u32);
+    writer.pointer(true);
+    writer.u32(states.len() as u32);
+    for state in states {
+        writer.u32(state.current_state);
+        writer.u32(state.event_state);
+        let length = state.atr.len().min(READER_STATE_ATR_SIZE);
+        writer.u32(length as u32);
+        let mut atr = state.atr[..length].to_vec();
+        atr.resize(READER_STATE_ATR_SIZE, 0);
+        writer.data.extend_from_slice(&atr);
+    }
+    writer.finish()
+}
+
+/// Context and card handle of the calls on a card
+/// MS-RDPESC 2.2.1.2 REDIR_SCARDHANDLE
+fn read_card(reader: &mut NdrReader) -> Result<(bool, bool)> {
+    let context = reader.context()?;
+    let _length = reader.u32()?;
+    Ok((context, reader.pointer()?))
+}
+
+/// Run a call of the server on the provider
+/// The output is the NDR encoded return
+fn call(provider: &dyn PcscProvider, io_control_code: u32, input: &[u8]) -> Result<Vec<u8>> {
+    let wide = matches!(
+        io_control_code,
+        0x0009002C | 0x000900A4 | 0x000900B0 | 0x000900CC
+    );
+    let mut reader = NdrReader::new(input)?;
+    Ok(match io_control_code {
+        // MS-RDPESC 2.2.2.1 EstablishContext_Call
+        0x00090014 => {
+            let (return_code, context) = split(provider.establish_context(reader.u32()?));
+            let mut writer = NdrWriter::new(return_code);
+            writer.handle();
+            writer.array(&context.to_le_bytes());
+            writer.finish()
+        }
+        // MS-RDPESC 2.2.2.2 Context_Call
+        0x00090018 | 0x0009001C | 0x000900A8 => {
+            let present = reader.context()?;
+            let context = reader.handle(present)?;
+            long_return(match io_control_code {
+                0x00090018 => provider.release_context(context),
+                0x0009001C => provider.is_valid_context(context),
+                _ => provider.cancel(context),
+            })
+        }
+        // MS-RDPESC 2.2.2.4 ListReaders_Call
+        0x00090028 | 0x0009002C => {
+            let present = reader.context()?;
+            let _groups_length = reader.u32()?;
+            let groups = reader.pointer()?;
+            let readers_null = reader.u32()? != 0;
+            let _readers_length = reader.u32()?;
+            let context = reader.handle(present)?;
+            if groups {
+                reader.array()?;
+            }
+            let (return_code, names) = split(provider.list_readers(context));
+            let return_code = match (return_code, names.is_empty()) {
+                (0, true) => ScardError::ScardENoReadersAvailable as u32,
+                (return_code, _) => return_code,
+            };
+            let data = multi_string(&names, wide);
+            let mut writer = NdrWriter::new(return_code);
+            writer.u32(data.len() as u32);
+            writer.pointer(!readers_null);
+            if !readers_null {
+                writer.array(&data);
+            }
+            writer.finish()
+        }
+        // MS-RDPESC 2.2.2.6 Connect_Call
+        0x000900AC | 0x000900B0 => {
+            let name = reader.pointer()?;
+            let present = reader.context()?;
+            let share_mode = reader.u32()?;
+            let preferred_protocols = reader.u32()?;
+            let name = if name {
+                reader.string(wide)?
+            } else {
+                String::new()
+            };
+            let context = reader.handle(present)?;
+            let (return_code, (card, protocol)) =
+                split(provider.connect(context, &name, share_mode, preferred_protocols));
+            let mut writer = NdrWriter::new(return_code);
+            writer.handle();
+            writer.handle();
+            writer.u32(protocol);
+            writer.array(&context.to_le_bytes());
+            writer.array(&card.to_le_bytes());
+            writer.finish()
+        }
+        _ => return call_card(provider, io_control_code, wide, reader),
+    })
+}
+
+/// Run a call of the server on a card
+fn call_card(
+    provider: &dyn PcscProvider,
+    io_control_code: u32,
+    wide: bool,
+    mut reader: NdrReader,
+) -> Result<Vec<u8>> {
+    let (context, card) = read_card(&mut reader)?;
+    Ok(match io_control_code {
+        // MS-RDPESC 2.2.2.7 Reconnect_Call
+        0x000900B4 => {
+            let share_mode = reader.u32()?;
+            let preferred_protocols = reader.u32()?;
+            let initialization = reader.u32()?;
+            reader.handle(context)?;
+            let card = reader.handle(card)?;
+            let (return_code, protocol) =
+                split(provider.reconnect(card, share_mode, preferred_protocols, initialization));
+            let mut writer = NdrWriter::new(return_code);
+            writer.u32(protocol);
+            writer.finish()
+        }
+        // MS-RDPESC 2.2.2.8 HCardAndDisposition_Call
+        0x000900B8 | 0x000900BC | 0x000900C0 => {
+            let disposition = reader.u32()?;
+            reader.handle(context)?;
+            let card = reader.handle(card)?;
+            long_return(match io_control_code {
+                0x000900B8 => provider.disconnect(card, disposition),
+                0x000900BC => provider.begin_transaction(card),
+                _ => provider.end_transaction(card, disposition),
+            })
+        }
+        // MS-RDPESC 2.2.2.18 Status_Call
+        0x000900C8 | 0x000900CC => {
+            let names_null = reader.u32()? != 0;
+            reader.handle(context)?;
+            let card = reader.handle(card)?;
+            let (return_code, status) = split(provider.status(card));
+            let names = multi_string(&status.readers, wide);
+            let mut writer = NdrWriter::new(return_code);
+            writer.u32(names.len() as u32);
+            writer.pointer(!names_null);
+            writer.u32(status.state);
+            writer.u32(status.protocol);
+            let length = status.atr.len().min(STATUS_ATR_SIZE);
+            let mut atr = status.atr[..length].to_vec();
+            atr.resize(STATUS_ATR_SIZE, 0);
+            writer.data.extend_from_slice(&atr);
+            writer.u32(length as u32);
+            if !names_null {
+                writer.array(&names);
+            }
+            writer.finish()
+        }
+        // MS-RDPESC 2.2.2.19 Transmit_Call
+        0x000900D0 => {
+            let protocol = reader.u32()?;
+            let _extra_length = reader.u32()?;
+            let extra = reader.pointer()?;
+            let _send_length = reader.u32()?;
+            let send = reader.pointer()?;
+            let _receive_pci = reader.pointer()?;
+            let _receive_null = reader.u32()?;
+            let receive_length = reader.u32()?;
+            reader.handle(context)?;
+            let card = reader.handle(card)?;
+            if extra {
+                reader.array()?;
+            }
+            let send = if send { reader.array()? } else { Vec::new() };
+            let (return_code, received) =
+                split(provider.transmit(card, protocol, &send, receive_length));
+            let mut writer = NdrWriter::new(return_code);
+            writer.pointer(false);
+            writer.u32(received.len() as u32);
+            writer.pointer(true);
+            writer.array(&received);
+            writer.finish()
+        }
+        // MS-RDPESC 2.2.2.20 Control_Call
+        0x000900D4 => {
+            let control_code = reader.u32()?;
+            let _input_length = reader.u32()?;
+            let input = reader.pointer()?;
+            let _output_null = reader.u32()?;
+            let output_length = reader.u32()?;
+            reader.handle(context)?;
+            let card = reader.handle(card)?;
+            let input = if input { reader.array()? } else { Vec::new() };
+            let (return_code, output) =
+                split(provider.control(card, control_code, &input, output_length));
+            let mut writer = NdrWriter::new(return_code);
+            writer.u32(output.len() as u32);
+            writer.pointer(true);
+            writer.array(&output);
+            writer.finish()
+        }
+        // MS-RDPESC 2.2.2.21 GetAttrib_Call
+        0x000900D8 => {
+            let attr_id = reader.u32()?;
+            let _attr_null = reader.u32()?;
+            let length = reader.u32()?;
+            reader.handle(context)?;
+            let card = reader.handle(card)?;
+            let (return_code, attr) = split(provider.get_attrib(card, attr_id, length));
+            let mut writer = NdrWriter::new(return_code);
+            writer.u32(attr.len() as u32);
+            writer.pointer(true);
+            writer.array(&attr);
+            writer.finish()
+        }
+        _ => long_return(Err(ScardError::ScardEUnsupportedFeature as u32)),
+    })
+}
+
+/// Smartcard reader redirected to the server
+/// The PC/SC calls of the session run on the provider,
+/// so that local cards can be used to log on
+///
+/// get_status_change calls block on their own thread,
+/// they are completed by `RdpdrClient::poll`
+///
+/// # Example
+/// ```rust, ignore
+/// let smartcard = Smartcard::new(Arc::new(PcscLite));
+/// let (device_id, announce) = rdpdr.add_device(Box::new(smartcard));
+/// ```
+pub struct Smartcard {
+    provider: Arc<dyn PcscProvider + Send + Sync>,
+    /// Responses of the calls running on their own thread
+    sender: mpsc::Sender<DeviceIoResponse>,
+    completions: mpsc::Receiver<DeviceIoResponse>,
+    next_file_id: u32,
+}
+
+impl Smartcard {
+    pub fn new(provider: Arc<dyn PcscProvider + Send + Sync>) -> Self {
+        let (sender, completions) = mpsc::channel();
+        Smartcard {
+            provider,
+            sender,
+            completions,
+            next_file_id: 1,
+        }
+    }
+
+    /// Wait for a status change on another thread
+    fn get_status_change(&self, request: DeviceIoRequest, input: &[u8], wide: bool) -> Result<()> {
+        let (context, timeout, mut states) = read_status_change(input, wide)?;
+        let provider = self.provider.clone();
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            let result = provider.get_status_change(context, timeout, &mut states);
+            let output = status_change_return(result, &states);
+            let _ = sender
+                .send(request.complete(NtStatus::StatusSuccess as u32, read_response(&output)));
+        });
+        Ok(())
+    }
+}
+
+impl Device for Smartcard {
+    fn device_type(&self) -> u32 {
+        DeviceType::RdpdrDtypSmartcard as u32
+    }
+
+    fn dos_name(&self) -> String {
+        "SCARD".to_string()
+    }
+
+    fn process(&mut self, request: DeviceIoRequest) -> Option<DeviceIoResponse> {
+        let (io_control_code, input) = match request.parameters() {
+            Ok(IoRequest::DeviceControl {
+                io_control_code,
+                input,
+                ..
+            }) => (io_control_code, input),
+            Ok(IoRequest::Create { .. }) => {
+                let file_id = self.next_file_id;
+                self.next_file_id += 1;
+                return Some(
+                    request.complete(NtStatus::StatusSuccess as u32, create_response(file_id, 0)),
+                );
+            }
+            Ok(IoRequest::Close) => {
+                return Some(request.complete(NtStatus::StatusSuccess as u32, vec![0; 5]))
+            }
+            Ok(_) => return Some(request.fail(NtStatus::StatusNotSupported as u32)),
+            Err(_) => return Some(request.fail(NtStatus::StatusInvalidParameter as u32)),
+        };
+
+        let output = match io_control_code {
+            0x000900A0 | 0x000900A4 => {
+                let wide = io_control_code == ScardIoctl::ScardIoctlGetstatuschangew as u32;
+                return match self.get_status_change(request.clone(), &input, wide) {
+                    Ok(()) => None,
+                    Err(_) => Some(request.fail(NtStatus::StatusInvalidParameter as u32)),
+                };
+            }
+            // The smartcard service is always started
+            0x000900E0 | 0x000900E4 => Ok(long_return(Ok(()))),
+            // MS-RDPESC 2.2.3.1 ReadCache_Return
+            0x000900F0 | 0x000900F4 => {
+                let mut writer = NdrWriter::new(ScardError::ScardWCacheItemNotFound as u32);
+                writer.u32(0);
+                writer.pointer(false);
+                Ok(writer.finish())
+            }
+            0x000900F8 | 0x000900FC => Ok(long_return(Ok(()))),
+            // MS-RDPESC 2.2.3.13 GetTransmitCount_Return
+            0x00090100 => {
+                let mut writer = NdrWriter::new(0);
+                writer.u32(0);
+                Ok(writer.finish())
+            }
+            _ => call(self.provider.as_ref(), io_control_code, &input),
+        };
+        Some(match output {
+            Ok(output) => request.complete(NtStatus::StatusSuccess as u32, read_response(&output)),
+            Err(_) => request.fail(NtStatus::StatusInvalidParameter as u32),
+        })
+    }
+
+    fn poll(&mut self) -> Vec<DeviceIoResponse> {
+        self.completions.try_iter().collect()
+    }
+}
+
+/// PC/SC of pcsc-lite, or of WinSCard on Windows
+#[cfg(feature = "pcsc")]
+pub struct PcscLite;
+
+#[cfg(feature = "pcsc")]
+mod pcsc_lite {
+    use super::{CardStatus, PcscLite, PcscProvider, ReaderState, ScardResult};
+    use pcsc_sys::*;
+    use std::ffi::CString;
+    use std::ptr::{null, null_mut};
+
+    /// Maximum size of an ATR
+    const MAX_ATR_SIZE: usize = 33;
+
+    fn check(return_code: LONG) -> ScardResult<()> {
+        if return_code == SCARD_S_SUCCESS {
+            Ok(())
+        } else {
+            Err(return_code as u32)
+        }
+    }
+
+    /// Names of a multi-string of C characters
+    fn names(data: &[u8]) -> Vec<String> {
+        data.split(|c| *c == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).to_string())
+            .collect()
+    }
+
+    /// Control codes of Windows are converted to the ones of pcsc-lite
+    fn control_code(code: u32) -> u32 {
+        if cfg!(windows) || code >> 16 != 0x31 {
+            code
+        } else {
+            0x42000000 + ((code & 0x3FFC) >> 2)
+        }
+    }
+
+    impl PcscProvider for PcscLite {
+        fn establish_context(&self, scope: u32) -> ScardResult<u64> {
+            let mut context: SCARDCONTEXT = 0 as _;
+            check(unsafe { SCardEstablishContext(scope as _, null(), null(), &mut context) })?;
+            Ok(context as u64)
+        }
+
+        fn release_context(&self, context: u64) -> ScardResult<()> {
+            check(unsafe { SCardReleaseContext(context as _) })
+        }
+
+        fn is_valid_context(&self, context: u64) -> ScardResult<()> {
+            check(unsafe { SCardIsValidContext(context as _) })
+        }
+
+        fn list_readers(&self, context: u64) -> ScardResult<Vec<String>> {
+            let mut length: DWORD = 0;
+            check(unsafe { SCardListReaders(context as _, null(), null_mut(), &mut length) })?;
+            let mut data = vec![0u8; length as usize];
+            check(unsafe {
+                SCardListReaders(
+                    context as _,
+                    null(),
+                    data.as_mut_ptr() as *mut _,
+                    &mut length,
+                )
+            })?;
+            Ok(names(&data[..length as usize]))
+        }
+
+        fn get_status_change(
+            &self,
+            context: u64,
+            timeout: u32,
+            states: &mut [ReaderState],
+        ) -> ScardResult<()> {
+            let readers: Vec<CString> = states
+                .iter()
+                .map(|state| CString::new(state.reader.as_str()).unwrap_or_default())
+                .collect();
+            let mut native: Vec<SCARD_READERSTATE> = states
+                .iter()
+                .zip(&readers)
+                .map(|(state, reader)| {
+                    let mut native: SCARD_READERSTATE = unsafe { std::mem::zeroed() };
+                    native.szReader = reader.as_ptr();
+                    native.dwCurrentState = state.current_state as _;
+                    native
+                })
+                .collect();
+            check(unsafe {
+                SCardGetStatusChange(
+                    context as _,
+                    timeout as _,
+                    native.as_mut_ptr(),
+                    native.len() as _,
+                )
+            })?;
+            for (state, native) in states.iter_mut().zip(&native) {
+                state.event_state = native.dwEventState as u32;
+                let length = (native.cbAtr as usize).min(native.rgbAtr.len());
+                state.atr = native.rgbAtr[..length].to_vec();
+            }
+            Ok(())
+        }
+
+        fn cancel(&self, context: u64) -> ScardResult<()> {
+            check(unsafe { SCardCancel(context as _) })
+        }
+
+        fn connect(
+            &self,
+            context: u64,
+            reader: &str,
+            share_mode: u32,
+            preferred_protocols: u32,
+        ) -> ScardResult<(u64, u32)> {
+            let reader =
+                CString::new(reader).map_err(|_| super::ScardError::ScardEUnknownReader as u32)?;
+            let mut card: SCARDHANDLE = 0 as _;
+            let mut protocol: DWORD = 0;
+            check(unsafe {
+                SCardConnect(
+                    context as _,
+                    reader.as_ptr(),
+                    share_mode as _,
+                    preferred_protocols as _,
+                    &mut card,
+                    &mut protocol,
+                )
+            })?;
+            Ok((card as u64, protocol as u32))
+        }
+
+        fn reconnect(
+            &self,
+            card: u64,
+            share_mode: u32,
+            preferred_protocols: u32,
+            initialization: u32,
+        ) -> ScardResult<u32> {
+            let mut protocol: DWORD = 0;
+            check(unsafe {
+                SCardReconnect(
+                    card as _,
+                    share_mode as _,
+                    preferred_protocols as _,
+                    initialization as _,
+                    &mut protocol,
+                )
+            })?;
+            Ok(protocol as u32)
+        }
+
+        fn disconnect(&self, card: u64, disposition: u32) -> ScardResult<()> {
+            check(unsafe { SCardDisconnect(card as _, disposition as _) })
+        }
+
+        fn begin_transaction(&self, card: u64) -> ScardResult<()> {
+            check(unsafe { SCardBeginTransaction(card as _) })
+        }
+
+        fn end_transaction(&self, card: u64, disposition: u32) -> ScardResult<()> {
+            check(unsafe { SCardEndTransaction(card as _, disposition as _) })
+        }
+
+        fn status(&self, card: u64) -> ScardResult<CardStatus> {
+            let mut reader = vec![0u8; 1024];
+            let mut reader_length = reader.len() as DWORD;
+            let mut state: DWORD = 0;
+            let mut protocol: DWORD = 0;
+            let mut atr = [0u8; MAX_ATR_SIZE];
+            let mut atr_length = atr.len() as DWORD;
+            check(unsafe {
+                SCardStatus(
+                    card as _,
+                    reader.as_mut_ptr() as *mut _,
+                    &mut reader_length,
+                    &mut state,
+                    &mut protocol,
+                    atr.as_mut_ptr(),
+                    &mut atr_length,
+                )
+            })?;
+            Ok(CardStatus {
+                readers: names(&reader[..(reader_length as usize).min(reader.len())]),
+                state: state as u32,
+                protocol: protocol as u32,
+                atr: atr[..(atr_length as usize).min(MAX_ATR_SIZE)].to_vec(),
+            })
+        }
+
+        fn transmit(
+            &self,
+            card: u64,
+            protocol: u32,
+            send: &[u8],
+            receive_length: u32,
+        ) -> ScardResult<Vec<u8>> {
+            let send_pci = SCARD_IO_REQUEST {
+                dwProtocol: protocol as _,
+                cbPciLength: std::mem::size_of::<SCARD_IO_REQUEST>() as _,
+            };
+            // Extended APDUs when the length is allocated by the callee
+            let mut received = vec![0u8; (receive_length as usize).min(65538)];
+            let mut length = received.len() as DWORD;
+            check(unsafe {
+                SCardTransmit(
+                    card as _,
+                    &send_pci,
+                    send.as_ptr(),
+                    send.len() as _,
+                    null_mut(),
+                    received.as_mut_ptr(),
+                    &mut length,
+                )
+            })?;
+            received.truncate(length as usize);
+            Ok(received)
+        }
+
+        fn control(
+            &self,
+            card: u64,
+            control_code_: u32,
+            input: &[u8],
+            output_length: u32,
+        ) -> ScardResult<Vec<u8>> {
+            let mut output = vec![0u8; (output_length as usize).min(65536)];
+            let mut length: DWORD = 0;
+            check(unsafe {
+                SCardControl(
+                    card as _,
+                    control_code(control_code_) as _,
+                    input.as_ptr() as *const _,
+                    input.len() as _,
+                    output.as_mut_ptr() as *mut _,
+                    output.len() as _,
+                    &mut length,
+                )
+            })?;
+            output.truncate(length as usize);
+            Ok(output)
+        }
+
+        fn get_attrib(&self, card: u64, attr_id: u32, length: u32) -> ScardResult<Vec<u8>> {
+            let mut attr = vec![0u8; (length as usize).min(65536)];
+            let mut length = attr.len() as DWORD;
+            check(unsafe {
+                SCardGetAttrib(card as _, attr_id as _, attr.as_mut_ptr(), &mut length)
+            })?;
+            attr.truncate(length as usize);
+            Ok(attr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::rdpdr::base::MajorFunction;
+
+    /// One reader holding a card, contexts start at 1
+    struct MockProvider;
+
+    impl PcscProvider for MockProvider {
+        fn establish_context(&self, _scope: u32) -> ScardResult<u64> {
+            Ok(1)
+        }
+        fn release_context(&self, _context: u64) -> ScardResult<()> {
+            Ok(())
+        }
+        fn is_valid_context(&self, context: u64) -> ScardResult<()> {
+            if context == 1 {
+                Ok(())
+            } else {
+                Err(ScardError::ScardEInvalidHandle as u32)
+            }
+        }
+        fn list_readers(&self, _context: u64) -> ScardResult<Vec<String>> {
+            Ok(vec!["R".to_string()])
+        }
+        fn get_status_change(
+            &self,
+            _context: u64,
+            _timeout: u32,
+            states: &mut [ReaderState],
+        ) -> ScardResult<()> {
+            for state in states {
+                state.event_state = 0x22;
+                state.atr = vec![0x3B, 0x00];
+            }
+            Ok(())
+        }
+        fn cancel(&self, _context: u64) -> ScardResult<()> {
+            Ok(())
+        }
+        fn connect(&self, _: u64, _: &str, _: u32, _: u32) -> ScardResult<(u64, u32)> {
+            Ok((2, 2))
+        }
+        fn reconnect(&self, _: u64, _: u32, _: u32, _: u32) -> ScardResult<u32> {
+            Ok(2)
+        }
+        fn disconnect(&self, _card: u64, _disposition: u32) -> ScardResult<()> {
+            Ok(())
+        }
+        fn begin_transaction(&self, _card: u64) -> ScardResult<()> {
+            Ok(())
+        }
+        fn end_transaction(&self, _card: u64, _disposition: u32) -> ScardResult<()> {
+            Ok(())
+        }
+        fn status(&self, _card: u64) -> ScardResult<CardStatus> {
+            Ok(CardStatus::default())
+        }
+        fn transmit(&self, _: u64, _: u32, send: &[u8], _: u32) -> ScardResult<Vec<u8>> {
+            Ok(send.to_vec())
+        }
+        fn control(&self, _: u64, _: u32, _: &[u8], _: u32) -> ScardResult<Vec<u8>> {
+            Err(ScardError::ScardEUnsupportedFeature as u32)
+        }
+        fn get_attrib(&self, _: u64, _: u32, _: u32) -> ScardResult<Vec<u8>> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Control request with an NDR call made of the values
+    fn request(io_control_code: u32, values: &[u32]) -> DeviceIoRequest {
+        let mut input = vec![0x01, 0x10, 0x08, 0x00, 0xCC, 0xCC, 0xCC, 0xCC];
+        input.put_u32_le(values.len() as u32 * 4);
+        input.put_u32_le(0);
+        for value in values {
+            input.put_u32_le(*value);
+        }
+        let mut request_data = Vec::new();
+        request_data.put_u32_le(2048);
+        request_data.put_u32_le(input.len() as u32);
+        request_data.put_u32_le(io_control_code);
+        request_data.extend_from_slice(&[0; 20]);
+        request_data.extend_from_slice(&input);
+        DeviceIoRequest {
+            device_id: 1,
+            file_id: 1,
+            completion_id: 0,
+            major_function: MajorFunction::IrpMjDeviceControl as u32,
+            minor_function: 0,
+            data: request_data,
+        }
+    }
+
+    /// NDR data of a response, without the output length and the headers
+    fn output(response: DeviceIoResponse) -> Vec<u8> {
+        assert_eq!(response.io_status, 0);
+        response.data[4 + NDR_HEADERS_SIZE..].to_vec()
+    }
+
+    /// The context and the readers are returned in NDR
+    #[test]
+    fn test_smartcard_calls() {
+        let mut smartcard = Smartcard::new(Arc::new(MockProvider));
+        let response = smartcard
+            .process(request(ScardIoctl::ScardIoctlEstablishcontext as u32, &[2]))
+            .unwrap();
+        assert_eq!(
+            output(response),
+            [0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 2, 0, 8, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        let response = smartcard
+            .process(request(
+                ScardIoctl::ScardIoctlListreadersw as u32,
+                &[8, 0x00020000, 0, 0, 0, 0xFFFFFFFF, 8, 1, 0],
+            ))
+            .unwrap();
+        assert_eq!(
+            output(response),
+            [0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 2, 0, 6, 0, 0, 0, b'R', 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        let response = smartcard
+            .process(request(
+                ScardIoctl::ScardIoctlIsvalidcontext as u32,
+                &[8, 0x00020000, 8, 3, 0],
+            ))
+            .unwrap();
+        assert_eq!(output(response)[..4], 0x80100003u32.to_le_bytes());
+    }
+
+    /// Status changes are completed by poll
+    #[test]
+    fn test_smartcard_status_change() {
+        let mut smartcard = Smartcard::new(Arc::new(MockProvider));
+        let mut values = vec![
+            8, 0x00020000, 1000, 1, 0x00020004, 8, 1, 0, 1, 0x00020008, 0x10, 0, 0,
+        ];
+        values.extend_from_slice(&[0; 9]);
+        values.extend_from_slice(&[2, 0, 2, 'R' as u32, 0]);
+        let request = request(ScardIoctl::ScardIoctlGetstatuschangew as u32, &values);
+        assert!(smartcard.process(request).is_none());
+
+        let mut responses = Vec::new();
+        while responses.is_empty() {
+            thread::sleep(std::time::Duration::from_millis(10));
+            responses = smartcard.poll();
+        }
+        let output = output(responses.remove(0));
+        assert_eq!(output[..12], [0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 2, 0]);
+        assert_eq!(output[16..28], [0x10, 0, 0, 0, 0x22, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(output[28..30], [0x3B, 0x00]);
+    }
+}
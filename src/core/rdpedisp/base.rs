@@ -0,0 +1,255 @@
+use crate::core::gcc::Monitor;
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the dynamic virtual channel of the display control
+pub const DISPLAY_CONTROL_CHANNEL_NAME: &str = "Microsoft::Windows::RDS::DisplayControl";
+
+/// Size of the DISPLAYCONTROL_HEADER
+const HEADER_SIZE: usize = 8;
+
+/// Size of a DISPLAYCONTROL_MONITOR_LAYOUT
+const MONITOR_LAYOUT_SIZE: usize = 40;
+
+/// Bounds of the size of a monitor, the width is even
+/// MS-RDPEDISP 2.2.2.2.1 DISPLAYCONTROL_MONITOR_LAYOUT
+pub const MONITOR_MIN_SIZE: u32 = 200;
+pub const MONITOR_MAX_SIZE: u32 = 8192;
+
+/// Flag of the primary monitor
+const DISPLAYCONTROL_MONITOR_PRIMARY: u32 = 0x00000001;
+
+/// Scale factor of a monitor without scaling
+const SCALE_FACTOR_NONE: u32 = 100;
+
+/// Type of a display control PDU
+/// MS-RDPEDISP 2.2.1.1 DISPLAYCONTROL_HEADER
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum DisplayControlPduType {
+    DisplaycontrolPduTypeMonitorLayout = 0x00000002,
+    DisplaycontrolPduTypeCaps = 0x00000005,
+}
+
+/// MS-RDPEDISP 2.2.2 Message Syntax
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayControlPdu {
+    /// Limits of the layouts accepted by the server
+    /// MS-RDPEDISP 2.2.2.1 DISPLAYCONTROL_CAPS_PDU
+    Caps {
+        max_num_monitors: u32,
+        max_monitor_area_factor_a: u32,
+        max_monitor_area_factor_b: u32,
+    },
+    /// Monitors requested by the client, without physical size nor scaling
+    /// MS-RDPEDISP 2.2.2.2 DISPLAYCONTROL_MONITOR_LAYOUT_PDU
+    MonitorLayout(Vec<Monitor>),
+}
+
+impl DisplayControlPdu {
+    fn pdu_type(&self) -> DisplayControlPduType {
+        match self {
+            DisplayControlPdu::Caps { .. } => DisplayControlPduType::DisplaycontrolPduTypeCaps,
+            DisplayControlPdu::MonitorLayout(_) => {
+                DisplayControlPduType::DisplaycontrolPduTypeMonitorLayout
+            }
+        }
+    }
+
+    fn body(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        match self {
+            DisplayControlPdu::Caps {
+                max_num_monitors,
+                max_monitor_area_factor_a,
+                max_monitor_area_factor_b,
+            } => {
+                buffer.put_u32_le(*max_num_monitors);
+                buffer.put_u32_le(*max_monitor_area_factor_a);
+                buffer.put_u32_le(*max_monitor_area_factor_b);
+            }
+            DisplayControlPdu::MonitorLayout(monitors) => {
+                buffer.put_u32_le(MONITOR_LAYOUT_SIZE as u32);
+                buffer.put_u32_le(monitors.len() as u32);
+                for monitor in monitors {
+                    buffer.put_u32_le(if monitor.is_primary() {
+                        DISPLAYCONTROL_MONITOR_PRIMARY
+                    } else {
+                        0
+                    });
+                    buffer.put_i32_le(monitor.left);
+                    buffer.put_i32_le(monitor.top);
+                    buffer.put_u32_le((monitor.right - monitor.left + 1) as u32);
+                    buffer.put_u32_le((monitor.bottom - monitor.top + 1) as u32);
+                    // Physical size and orientation are unknown
+                    buffer.put_u32_le(0);
+                    buffer.put_u32_le(0);
+                    buffer.put_u32_le(0);
+                    buffer.put_u32_le(SCALE_FACTOR_NONE);
+                    buffer.put_u32_le(SCALE_FACTOR_NONE);
+                }
+            }
+        }
+        buffer
+    }
+}
+
+/// Read a display control PDU
+pub fn read_display_control_pdu(buffer: &mut BytesMut) -> Result<DisplayControlPdu> {
+    check_remaining(buffer, HEADER_SIZE, "RDPEDISP: header")?;
+    let pdu_type = buffer.get_u32_le();
+    let length = (buffer.get_u32_le() as usize).saturating_sub(HEADER_SIZE);
+    check_remaining(buffer, length, "RDPEDISP: PDU")?;
+    let mut body = buffer.split_to(length);
+    let body = &mut body;
+
+    Ok(match DisplayControlPduType::try_from(pdu_type) {
+        Ok(DisplayControlPduType::DisplaycontrolPduTypeCaps) => {
+            check_remaining(body, 12, "RDPEDISP: capabilities")?;
+            DisplayControlPdu::Caps {
+                max_num_monitors: body.get_u32_le(),
+                max_monitor_area_factor_a: body.get_u32_le(),
+                max_monitor_area_factor_b: body.get_u32_le(),
+            }
+        }
+        Ok(DisplayControlPduType::DisplaycontrolPduTypeMonitorLayout) => {
+            check_remaining(body, 8, "RDPEDISP: monitor layout")?;
+            let layout_size = body.get_u32_le() as usize;
+            let count = body.get_u32_le() as usize;
+            if layout_size < MONITOR_LAYOUT_SIZE {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("RDPEDISP: invalid monitor layout size {}", layout_size),
+                ));
+            }
+            check_remaining(body, layout_size * count, "RDPEDISP: monitors")?;
+            let mut monitors = Vec::with_capacity(count);
+            for _ in 0..count {
+                let mut layout = body.split_to(layout_size);
+                let flags = layout.get_u32_le();
+                let left = layout.get_i32_le();
+                let top = layout.get_i32_le();
+                let width = layout.get_u32_le();
+                let height = layout.get_u32_le();
+                monitors.push(Monitor::new(
+                    left,
+                    top,
+                    width,
+                    height,
+                    flags & DISPLAYCONTROL_MONITOR_PRIMARY != 0,
+                ));
+            }
+            DisplayControlPdu::MonitorLayout(monitors)
+        }
+        Err(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("RDPEDISP: unexpected PDU type {}", pdu_type),
+            ))
+        }
+    })
+}
+
+#[async_trait]
+impl Message for DisplayControlPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        let body = self.body();
+        writer.write_u32_le(self.pdu_type() as u32).await?;
+        writer
+            .write_u32_le((HEADER_SIZE + body.len()) as u32)
+            .await?;
+        writer.write_all(&body).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        *self = read_display_control_pdu(&mut BytesMut::from(&data[..]))?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        HEADER_SIZE + self.body().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Check the encoding of a PDU both ways
+    async fn check_pdu(pdu: DisplayControlPdu, data: &[u8]) {
+        assert_eq!(pdu.length(), data.len());
+        assert_eq!(to_vec(&pdu).await.unwrap(), data);
+        let mut buffer = BytesMut::from(data);
+        assert_eq!(read_display_control_pdu(&mut buffer).unwrap(), pdu);
+        assert!(buffer.is_empty());
+    }
+
+    /// MS-RDPEDISP 2.2.2.1 DISPLAYCONTROL_CAPS_PDU
+    #[tokio::test]
+    async fn test_display_control_caps_pdu() {
+        check_pdu(
+            DisplayControlPdu::Caps {
+                max_num_monitors: 16,
+                max_monitor_area_factor_a: 8192,
+                max_monitor_area_factor_b: 8192,
+            },
+            &[
+                0x05, 0x00, 0x00, 0x00, // Type DISPLAYCONTROL_PDU_TYPE_CAPS
+                0x14, 0x00, 0x00, 0x00, // Length
+                0x10, 0x00, 0x00, 0x00, // MaxNumMonitors
+                0x00, 0x20, 0x00, 0x00, // MaxMonitorAreaFactorA
+                0x00, 0x20, 0x00, 0x00, // MaxMonitorAreaFactorB
+            ],
+        )
+        .await;
+    }
+
+    /// A primary monitor with a second one on its left
+    /// MS-RDPEDISP 2.2.2.2 DISPLAYCONTROL_MONITOR_LAYOUT_PDU
+    #[tokio::test]
+    async fn test_display_control_monitor_layout_pdu() {
+        check_pdu(
+            DisplayControlPdu::MonitorLayout(vec![
+                Monitor::new(0, 0, 1920, 1080, true),
+                Monitor::new(-1280, 0, 1280, 1024, false),
+            ]),
+            &[
+                0x02, 0x00, 0x00, 0x00, // Type DISPLAYCONTROL_PDU_TYPE_MONITOR_LAYOUT
+                0x60, 0x00, 0x00, 0x00, // Length
+                0x28, 0x00, 0x00, 0x00, // MonitorLayoutSize
+                0x02, 0x00, 0x00, 0x00, // NumMonitors
+                // First monitor
+                0x01, 0x00, 0x00, 0x00, // Flags DISPLAYCONTROL_MONITOR_PRIMARY
+                0x00, 0x00, 0x00, 0x00, // Left
+                0x00, 0x00, 0x00, 0x00, // Top
+                0x80, 0x07, 0x00, 0x00, // Width
+                0x38, 0x04, 0x00, 0x00, // Height
+                0x00, 0x00, 0x00, 0x00, // PhysicalWidth
+                0x00, 0x00, 0x00, 0x00, // PhysicalHeight
+                0x00, 0x00, 0x00, 0x00, // Orientation
+                0x64, 0x00, 0x00, 0x00, // DesktopScaleFactor
+                0x64, 0x00, 0x00, 0x00, // DeviceScaleFactor
+                // Second monitor
+                0x00, 0x00, 0x00, 0x00, // Flags
+                0x00, 0xFB, 0xFF, 0xFF, // Left
+                0x00, 0x00, 0x00, 0x00, // Top
+                0x00, 0x05, 0x00, 0x00, // Width
+                0x00, 0x04, 0x00, 0x00, // Height
+                0x00, 0x00, 0x00, 0x00, // PhysicalWidth
+                0x00, 0x00, 0x00, 0x00, // PhysicalHeight
+                0x00, 0x00, 0x00, 0x00, // Orientation
+                0x64, 0x00, 0x00, 0x00, // DesktopScaleFactor
+                0x64, 0x00, 0x00, 0x00, // DeviceScaleFactor
+            ],
+        )
+        .await;
+    }
+}
@@ -0,0 +1,221 @@
+use crate::core::drdynvc::client::DynamicChannelHandler;
+use crate::core::event::RdpEvent;
+use crate::core::gcc::{check_monitor_layout, Monitor};
+use crate::core::rdpedisp::base::{
+    read_display_control_pdu, DisplayControlPdu, DISPLAY_CONTROL_CHANNEL_NAME, MONITOR_MAX_SIZE,
+    MONITOR_MIN_SIZE,
+};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use std::any::Any;
+use std::io::{Error, ErrorKind, Result};
+
+/// Client of the display control dynamic virtual channel
+/// Ask the server to change the monitors of the session
+/// when displays are plugged, unplugged or resized
+///
+/// The server opens the channel and sends its limits first,
+/// a layout is only accepted once they are known
+///
+/// # Example
+/// ```
+/// use rdp::core::gcc::Monitor;
+/// use rdp::core::rdpedisp::client::DisplayControlClient;
+/// let mut client = DisplayControlClient::new();
+/// // Capabilities PDU of the server, 2 monitors of 2560x1600 at most
+/// let mut data = bytes::BytesMut::from(
+///     &[5, 0, 0, 0, 20, 0, 0, 0, 2, 0, 0, 0, 0, 10, 0, 0, 64, 6, 0, 0][..],
+/// );
+/// client.process(&mut data).unwrap();
+/// assert!(client
+///     .set_monitor_layout(vec![
+///         Monitor::new(0, 0, 1920, 1080, true),
+///         Monitor::new(1920, 0, 1280, 1024, false),
+///     ])
+///     .is_ok());
+/// ```
+#[derive(Default)]
+pub struct DisplayControlClient {
+    /// Limits of the server once received
+    caps: Option<(u32, u32, u32)>,
+}
+
+impl DisplayControlClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Largest number of monitors of a layout, None
+    /// until the server sent its capabilities
+    pub fn get_max_num_monitors(&self) -> Option<u32> {
+        self.caps.map(|(max_num_monitors, _, _)| max_num_monitors)
+    }
+
+    /// Read the PDUs of the server, there is nothing to answer
+    pub fn process(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        while !buffer.is_empty() {
+            match read_display_control_pdu(buffer)? {
+                DisplayControlPdu::Caps {
+                    max_num_monitors,
+                    max_monitor_area_factor_a,
+                    max_monitor_area_factor_b,
+                } => {
+                    self.caps = Some((
+                        max_num_monitors,
+                        max_monitor_area_factor_a,
+                        max_monitor_area_factor_b,
+                    ))
+                }
+                DisplayControlPdu::MonitorLayout(_) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "RDPEDISP: unexpected monitor layout from the server",
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Monitor layout PDU to send on the channel
+    /// The layout is checked against the limits of the server
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let client = dynamic.handler_mut::<DisplayControlClient>().unwrap();
+    /// let layout = client.set_monitor_layout(vec![
+    ///     Monitor::new(0, 0, 1920, 1080, true),
+    ///     Monitor::new(1920, 0, 1280, 1024, false),
+    /// ])?;
+    /// let channel_id = dynamic.channel_id(DISPLAY_CONTROL_CHANNEL_NAME).unwrap();
+    /// for pdu in dynamic.write(channel_id, to_vec(&layout).await?)? {
+    ///     channel.write(to_vec(&pdu).await?).await?;
+    /// }
+    /// ```
+    pub fn set_monitor_layout(&self, monitors: Vec<Monitor>) -> Result<DisplayControlPdu> {
+        let (max_num_monitors, factor_a, factor_b) = self.caps.ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotConnected,
+                "RDPEDISP: server capabilities are not received",
+            )
+        })?;
+        check_monitor_layout(&monitors)?;
+        if monitors.len() > max_num_monitors as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "RDPEDISP: server accepts {} monitors at most",
+                    max_num_monitors
+                ),
+            ));
+        }
+
+        let mut area = 0u64;
+        for monitor in &monitors {
+            let width = (monitor.right - monitor.left + 1) as u32;
+            let height = (monitor.bottom - monitor.top + 1) as u32;
+            let valid = |size| (MONITOR_MIN_SIZE..=MONITOR_MAX_SIZE).contains(&size);
+            if !valid(width) || !valid(height) || width & 1 != 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("RDPEDISP: invalid monitor size {}x{}", width, height),
+                ));
+            }
+            area += width as u64 * height as u64;
+        }
+        // MS-RDPEDISP 2.2.2.1 the area of all the monitors is bounded
+        if area > max_num_monitors as u64 * factor_a as u64 * factor_b as u64 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "RDPEDISP: layout exceeds the area of the server",
+            ));
+        }
+        Ok(DisplayControlPdu::MonitorLayout(monitors))
+    }
+}
+
+#[async_trait]
+impl DynamicChannelHandler for DisplayControlClient {
+    fn channel_names(&self) -> Vec<&'static str> {
+        vec![DISPLAY_CONTROL_CHANNEL_NAME]
+    }
+
+    async fn process(
+        &mut self,
+        _channel_name: &str,
+        _channel_id: u32,
+        buffer: &mut BytesMut,
+        _callback: &mut (dyn FnMut(RdpEvent) + Send),
+    ) -> Result<Vec<Vec<u8>>> {
+        DisplayControlClient::process(self, buffer)?;
+        Ok(Vec::new())
+    }
+
+    fn close(&mut self, _channel_id: u32) {
+        self.caps = None;
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// A layout needs the capabilities and is bounded by them
+    #[tokio::test]
+    async fn test_monitor_layout() {
+        let mut client = DisplayControlClient::new();
+        let layout = vec![
+            Monitor::new(0, 0, 1920, 1080, true),
+            Monitor::new(1920, 0, 1280, 1024, false),
+        ];
+        assert_eq!(
+            client
+                .set_monitor_layout(layout.clone())
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotConnected
+        );
+
+        let caps = DisplayControlPdu::Caps {
+            max_num_monitors: 2,
+            max_monitor_area_factor_a: 1920,
+            max_monitor_area_factor_b: 1080,
+        };
+        let mut buffer = BytesMut::from(&to_vec(&caps).await.unwrap()[..]);
+        client.process(&mut buffer).unwrap();
+        assert_eq!(client.get_max_num_monitors(), Some(2));
+        assert_eq!(
+            client.set_monitor_layout(layout.clone()).unwrap(),
+            DisplayControlPdu::MonitorLayout(layout)
+        );
+
+        for layout in [
+            // Too many monitors
+            vec![
+                Monitor::new(0, 0, 800, 600, true),
+                Monitor::new(800, 0, 800, 600, false),
+                Monitor::new(1600, 0, 800, 600, false),
+            ],
+            // Odd width
+            vec![Monitor::new(0, 0, 1023, 768, true)],
+            // Too small
+            vec![Monitor::new(0, 0, 100, 768, true)],
+            // Larger than the area of the server
+            vec![
+                Monitor::new(0, 0, 3840, 2160, true),
+                Monitor::new(3840, 0, 3840, 2160, false),
+            ],
+        ] {
+            assert_eq!(
+                client.set_monitor_layout(layout).unwrap_err().kind(),
+                ErrorKind::InvalidInput
+            );
+        }
+    }
+}
@@ -0,0 +1,741 @@
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the dynamic virtual channel of the touch and pen input
+pub const RDPEI_CHANNEL_NAME: &str = "Microsoft::Windows::RDS::Input";
+
+/// Size of the RDPINPUT_HEADER
+const HEADER_SIZE: usize = 6;
+
+/// Versions of the protocol, pen frames are sent from version 3
+/// MS-RDPEI 2.2.3.1 RDPINPUT_SC_READY_PDU
+pub const RDPINPUT_PROTOCOL_V100: u32 = 0x00010000;
+pub const RDPINPUT_PROTOCOL_V101: u32 = 0x00010001;
+pub const RDPINPUT_PROTOCOL_V200: u32 = 0x00020000;
+pub const RDPINPUT_PROTOCOL_V300: u32 = 0x00030000;
+
+/// Type of a touch and pen input PDU
+/// MS-RDPEI 2.2.2.1 RDPINPUT_HEADER
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum RdpeiEventId {
+    EventidScReady = 0x0001,
+    EventidCsReady = 0x0002,
+    EventidTouch = 0x0003,
+    EventidSuspendInput = 0x0004,
+    EventidResumeInput = 0x0005,
+    EventidDismissHoveringContact = 0x0006,
+    EventidPen = 0x0008,
+}
+
+/// Options of the client
+/// MS-RDPEI 2.2.3.2 RDPINPUT_CS_READY_PDU
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReadyFlag {
+    ReadyFlagsShowTouchVisuals = 0x00000001,
+    ReadyFlagsDisableTimestampInjection = 0x00000002,
+    ReadyFlagsEnableMultipenInjection = 0x00000004,
+}
+
+/// State of a touch or pen contact
+/// MS-RDPEI 2.2.3.3.1.1 RDPINPUT_CONTACT_DATA
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ContactFlag {
+    ContactFlagDown = 0x0001,
+    ContactFlagUpdate = 0x0002,
+    ContactFlagUp = 0x0004,
+    ContactFlagInrange = 0x0008,
+    ContactFlagIncontact = 0x0010,
+    ContactFlagCanceled = 0x0020,
+}
+
+/// Buttons of a pen
+/// MS-RDPEI 2.2.3.7.1.1 RDPINPUT_PEN_CONTACT
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PenFlag {
+    PenFlagBarrelPressed = 0x00000001,
+    PenFlagEraserPressed = 0x00000002,
+    PenFlagInverted = 0x00000004,
+}
+
+/// Optional fields of a touch contact
+const CONTACT_DATA_CONTACTRECT_PRESENT: u16 = 0x0001;
+const CONTACT_DATA_ORIENTATION_PRESENT: u16 = 0x0002;
+const CONTACT_DATA_PRESSURE_PRESENT: u16 = 0x0004;
+
+/// Optional fields of a pen contact
+const PEN_CONTACT_PENFLAGS_PRESENT: u16 = 0x0001;
+const PEN_CONTACT_PRESSURE_PRESENT: u16 = 0x0002;
+const PEN_CONTACT_ROTATION_PRESENT: u16 = 0x0004;
+const PEN_CONTACT_TILTX_PRESENT: u16 = 0x0008;
+const PEN_CONTACT_TILTY_PRESENT: u16 = 0x0010;
+
+/// Integers encoded on a variable number of bytes
+/// The first byte holds the number of bytes, and the sign of the signed ones
+/// MS-RDPEI 2.2.2 Variable-Length Integers
+fn byte_count(value: u64, first_bits: u32) -> usize {
+    (1..8)
+        .find(|n| value >> (first_bits + 8 * (n - 1)) == 0)
+        .unwrap_or(8) as usize
+}
+
+/// Write the value on count bytes, the header bits are in the first one
+fn write_variable(buffer: &mut Vec<u8>, header: u8, count: usize, value: u64) {
+    buffer.put_u8(header | (value >> (8 * (count - 1))) as u8);
+    for i in (0..count - 1).rev() {
+        buffer.put_u8((value >> (8 * i)) as u8);
+    }
+}
+
+fn write_two_byte_unsigned(buffer: &mut Vec<u8>, value: u16) {
+    let value = value.min(0x7FFF) as u64;
+    let count = byte_count(value, 7);
+    write_variable(buffer, ((count - 1) as u8) << 7, count, value);
+}
+
+fn write_two_byte_signed(buffer: &mut Vec<u8>, value: i16) {
+    let sign = if value < 0 { 0x40 } else { 0 };
+    let magnitude = value.unsigned_abs().min(0x3FFF) as u64;
+    let count = byte_count(magnitude, 6);
+    write_variable(buffer, (((count - 1) as u8) << 7) | sign, count, magnitude);
+}
+
+fn write_four_byte_unsigned(buffer: &mut Vec<u8>, value: u32) {
+    let value = value.min(0x3FFFFFFF) as u64;
+    let count = byte_count(value, 6);
+    write_variable(buffer, ((count - 1) as u8) << 6, count, value);
+}
+
+fn write_four_byte_signed(buffer: &mut Vec<u8>, value: i32) {
+    let sign = if value < 0 { 0x20 } else { 0 };
+    let magnitude = value.unsigned_abs().min(0x1FFFFFFF) as u64;
+    let count = byte_count(magnitude, 5);
+    write_variable(buffer, (((count - 1) as u8) << 6) | sign, count, magnitude);
+}
+
+fn write_eight_byte_unsigned(buffer: &mut Vec<u8>, value: u64) {
+    let value = value.min(0x1FFFFFFFFFFFFFFF);
+    let count = byte_count(value, 5);
+    write_variable(buffer, ((count - 1) as u8) << 5, count, value);
+}
+
+/// Value of the first byte followed by count bytes
+fn read_variable(buffer: &mut BytesMut, first: u8, count: usize) -> Result<u64> {
+    check_remaining(buffer, count, "RDPEI: variable length integer")?;
+    let mut value = first as u64;
+    for _ in 0..count {
+        value = (value << 8) | buffer.get_u8() as u64;
+    }
+    Ok(value)
+}
+
+fn read_two_byte_unsigned(buffer: &mut BytesMut) -> Result<u16> {
+    check_remaining(buffer, 1, "RDPEI: variable length integer")?;
+    let first = buffer.get_u8();
+    Ok(read_variable(buffer, first & 0x7F, (first >> 7) as usize)? as u16)
+}
+
+fn read_two_byte_signed(buffer: &mut BytesMut) -> Result<i16> {
+    check_remaining(buffer, 1, "RDPEI: variable length integer")?;
+    let first = buffer.get_u8();
+    let magnitude = read_variable(buffer, first & 0x3F, (first >> 7) as usize)? as i16;
+    Ok(if first & 0x40 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    })
+}
+
+fn read_four_byte_unsigned(buffer: &mut BytesMut) -> Result<u32> {
+    check_remaining(buffer, 1, "RDPEI: variable length integer")?;
+    let first = buffer.get_u8();
+    Ok(read_variable(buffer, first & 0x3F, (first >> 6) as usize)? as u32)
+}
+
+fn read_four_byte_signed(buffer: &mut BytesMut) -> Result<i32> {
+    check_remaining(buffer, 1, "RDPEI: variable length integer")?;
+    let first = buffer.get_u8();
+    let magnitude = read_variable(buffer, first & 0x1F, (first >> 6) as usize)? as i32;
+    Ok(if first & 0x20 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    })
+}
+
+fn read_eight_byte_unsigned(buffer: &mut BytesMut) -> Result<u64> {
+    check_remaining(buffer, 1, "RDPEI: variable length integer")?;
+    let first = buffer.get_u8();
+    read_variable(buffer, first & 0x1F, (first >> 5) as usize)
+}
+
+/// A finger on the screen, in desktop coordinates
+/// The contact id is kept from the down to the up of the finger
+/// MS-RDPEI 2.2.3.3.1.1 RDPINPUT_CONTACT_DATA
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TouchContact {
+    pub contact_id: u8,
+    pub x: i32,
+    pub y: i32,
+    /// ContactFlag of the contact
+    pub flags: u32,
+    /// Left, top, right and bottom of the contact area, relative to the position
+    pub rect: Option<(i16, i16, i16, i16)>,
+    /// Angle in degrees, from 0 to 359
+    pub orientation: Option<u32>,
+    /// Pressure from 0 to 1024
+    pub pressure: Option<u32>,
+}
+
+impl TouchContact {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        let mut fields = 0;
+        if self.rect.is_some() {
+            fields |= CONTACT_DATA_CONTACTRECT_PRESENT;
+        }
+        if self.orientation.is_some() {
+            fields |= CONTACT_DATA_ORIENTATION_PRESENT;
+        }
+        if self.pressure.is_some() {
+            fields |= CONTACT_DATA_PRESSURE_PRESENT;
+        }
+        buffer.put_u8(self.contact_id);
+        write_two_byte_unsigned(buffer, fields);
+        write_four_byte_signed(buffer, self.x);
+        write_four_byte_signed(buffer, self.y);
+        write_four_byte_unsigned(buffer, self.flags);
+        if let Some((left, top, right, bottom)) = self.rect {
+            for value in [left, top, right, bottom] {
+                write_two_byte_signed(buffer, value);
+            }
+        }
+        if let Some(orientation) = self.orientation {
+            write_four_byte_unsigned(buffer, orientation);
+        }
+        if let Some(pressure) = self.pressure {
+            write_four_byte_unsigned(buffer, pressure);
+        }
+    }
+
+    fn read(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 1, "RDPEI: touch contact")?;
+        let contact_id = buffer.get_u8();
+        let fields = read_two_byte_unsigned(buffer)?;
+        let mut contact = TouchContact {
+            contact_id,
+            x: read_four_byte_signed(buffer)?,
+            y: read_four_byte_signed(buffer)?,
+            flags: read_four_byte_unsigned(buffer)?,
+            ..Default::default()
+        };
+        if fields & CONTACT_DATA_CONTACTRECT_PRESENT != 0 {
+            contact.rect = Some((
+                read_two_byte_signed(buffer)?,
+                read_two_byte_signed(buffer)?,
+                read_two_byte_signed(buffer)?,
+                read_two_byte_signed(buffer)?,
+            ));
+        }
+        if fields & CONTACT_DATA_ORIENTATION_PRESENT != 0 {
+            contact.orientation = Some(read_four_byte_unsigned(buffer)?);
+        }
+        if fields & CONTACT_DATA_PRESSURE_PRESENT != 0 {
+            contact.pressure = Some(read_four_byte_unsigned(buffer)?);
+        }
+        Ok(contact)
+    }
+}
+
+/// A pen on the screen, in desktop coordinates
+/// MS-RDPEI 2.2.3.7.1.1 RDPINPUT_PEN_CONTACT
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PenContact {
+    pub device_id: u8,
+    pub x: i32,
+    pub y: i32,
+    /// ContactFlag of the pen
+    pub flags: u32,
+    /// PenFlag of the buttons, like the eraser
+    pub pen_flags: Option<u32>,
+    /// Pressure from 0 to 1024
+    pub pressure: Option<u32>,
+    /// Angle in degrees, from 0 to 359
+    pub rotation: Option<u16>,
+    /// Tilts from -90 to 90 degrees
+    pub tilt_x: Option<i16>,
+    pub tilt_y: Option<i16>,
+}
+
+impl PenContact {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        let mut fields = 0;
+        for (present, flag) in [
+            (self.pen_flags.is_some(), PEN_CONTACT_PENFLAGS_PRESENT),
+            (self.pressure.is_some(), PEN_CONTACT_PRESSURE_PRESENT),
+            (self.rotation.is_some(), PEN_CONTACT_ROTATION_PRESENT),
+            (self.tilt_x.is_some(), PEN_CONTACT_TILTX_PRESENT),
+            (self.tilt_y.is_some(), PEN_CONTACT_TILTY_PRESENT),
+        ] {
+            if present {
+                fields |= flag;
+            }
+        }
+        buffer.put_u8(self.device_id);
+        write_two_byte_unsigned(buffer, fields);
+        write_four_byte_signed(buffer, self.x);
+        write_four_byte_signed(buffer, self.y);
+        write_four_byte_unsigned(buffer, self.flags);
+        if let Some(pen_flags) = self.pen_flags {
+            write_four_byte_unsigned(buffer, pen_flags);
+        }
+        if let Some(pressure) = self.pressure {
+            write_four_byte_unsigned(buffer, pressure);
+        }
+        if let Some(rotation) = self.rotation {
+            write_two_byte_unsigned(buffer, rotation);
+        }
+        if let Some(tilt_x) = self.tilt_x {
+            write_two_byte_signed(buffer, tilt_x);
+        }
+        if let Some(tilt_y) = self.tilt_y {
+            write_two_byte_signed(buffer, tilt_y);
+        }
+    }
+
+    fn read(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 1, "RDPEI: pen contact")?;
+        let device_id = buffer.get_u8();
+        let fields = read_two_byte_unsigned(buffer)?;
+        let mut contact = PenContact {
+            device_id,
+            x: read_four_byte_signed(buffer)?,
+            y: read_four_byte_signed(buffer)?,
+            flags: read_four_byte_unsigned(buffer)?,
+            ..Default::default()
+        };
+        if fields & PEN_CONTACT_PENFLAGS_PRESENT != 0 {
+            contact.pen_flags = Some(read_four_byte_unsigned(buffer)?);
+        }
+        if fields & PEN_CONTACT_PRESSURE_PRESENT != 0 {
+            contact.pressure = Some(read_four_byte_unsigned(buffer)?);
+        }
+        if fields & PEN_CONTACT_ROTATION_PRESENT != 0 {
+            contact.rotation = Some(read_two_byte_unsigned(buffer)?);
+        }
+        if fields & PEN_CONTACT_TILTX_PRESENT != 0 {
+            contact.tilt_x = Some(read_two_byte_signed(buffer)?);
+        }
+        if fields & PEN_CONTACT_TILTY_PRESENT != 0 {
+            contact.tilt_y = Some(read_two_byte_signed(buffer)?);
+        }
+        Ok(contact)
+    }
+}
+
+/// Contacts at the same time, the offset is in microseconds
+/// from the first frame of the PDU
+/// MS-RDPEI 2.2.3.3.1 RDPINPUT_TOUCH_FRAME
+/// MS-RDPEI 2.2.3.7.1 RDPINPUT_PEN_FRAME
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputFrame<C> {
+    pub frame_offset: u64,
+    pub contacts: Vec<C>,
+}
+
+fn write_frames<C>(
+    buffer: &mut Vec<u8>,
+    encode_time: u32,
+    frames: &[InputFrame<C>],
+    write: fn(&C, &mut Vec<u8>),
+) {
+    write_four_byte_unsigned(buffer, encode_time);
+    write_two_byte_unsigned(buffer, frames.len() as u16);
+    for frame in frames {
+        write_two_byte_unsigned(buffer, frame.contacts.len() as u16);
+        write_eight_byte_unsigned(buffer, frame.frame_offset);
+        for contact in &frame.contacts {
+            write(contact, buffer);
+        }
+    }
+}
+
+fn read_frames<C>(
+    buffer: &mut BytesMut,
+    read: fn(&mut BytesMut) -> Result<C>,
+) -> Result<(u32, Vec<InputFrame<C>>)> {
+    let encode_time = read_four_byte_unsigned(buffer)?;
+    let count = read_two_byte_unsigned(buffer)?;
+    let mut frames = Vec::new();
+    for _ in 0..count {
+        let contact_count = read_two_byte_unsigned(buffer)?;
+        let frame_offset = read_eight_byte_unsigned(buffer)?;
+        let contacts = (0..contact_count)
+            .map(|_| read(buffer))
+            .collect::<Result<Vec<C>>>()?;
+        frames.push(InputFrame {
+            frame_offset,
+            contacts,
+        });
+    }
+    Ok((encode_time, frames))
+}
+
+/// PDU of the touch and pen input channel, sent by both sides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RdpeiPdu {
+    /// Version of the server, and its features from version 3
+    /// MS-RDPEI 2.2.3.1 RDPINPUT_SC_READY_PDU
+    ScReady {
+        protocol_version: u32,
+        supported_features: Option<u32>,
+    },
+    /// MS-RDPEI 2.2.3.2 RDPINPUT_CS_READY_PDU
+    CsReady {
+        flags: u32,
+        protocol_version: u32,
+        max_touch_contacts: u16,
+    },
+    /// Milliseconds since the previous touch PDU, and its frames
+    /// MS-RDPEI 2.2.3.3 RDPINPUT_TOUCH_EVENT_PDU
+    Touch {
+        encode_time: u32,
+        frames: Vec<InputFrame<TouchContact>>,
+    },
+    /// The client must stop sending touch and pen input
+    /// MS-RDPEI 2.2.3.4 RDPINPUT_SUSPEND_INPUT_PDU
+    SuspendInput,
+    /// MS-RDPEI 2.2.3.5 RDPINPUT_RESUME_INPUT_PDU
+    ResumeInput,
+    /// Id of the contact to remove from the hovering ones
+    /// MS-RDPEI 2.2.3.6 RDPINPUT_DISMISS_HOVERING_CONTACT_PDU
+    DismissHoveringContact(u8),
+    /// MS-RDPEI 2.2.3.7 RDPINPUT_PEN_EVENT_PDU
+    Pen {
+        encode_time: u32,
+        frames: Vec<InputFrame<PenContact>>,
+    },
+}
+
+impl RdpeiPdu {
+    fn event_id(&self) -> RdpeiEventId {
+        match self {
+            RdpeiPdu::ScReady { .. } => RdpeiEventId::EventidScReady,
+            RdpeiPdu::CsReady { .. } => RdpeiEventId::EventidCsReady,
+            RdpeiPdu::Touch { .. } => RdpeiEventId::EventidTouch,
+            RdpeiPdu::SuspendInput => RdpeiEventId::EventidSuspendInput,
+            RdpeiPdu::ResumeInput => RdpeiEventId::EventidResumeInput,
+            RdpeiPdu::DismissHoveringContact(_) => RdpeiEventId::EventidDismissHoveringContact,
+            RdpeiPdu::Pen { .. } => RdpeiEventId::EventidPen,
+        }
+    }
+
+    /// Body of the PDU, without the header
+    fn body(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        match self {
+            RdpeiPdu::ScReady {
+                protocol_version,
+                supported_features,
+            } => {
+                buffer.put_u32_le(*protocol_version);
+                if let Some(features) = supported_features {
+                    buffer.put_u32_le(*features);
+                }
+            }
+            RdpeiPdu::CsReady {
+                flags,
+                protocol_version,
+                max_touch_contacts,
+            } => {
+                buffer.put_u32_le(*flags);
+                buffer.put_u32_le(*protocol_version);
+                buffer.put_u16_le(*max_touch_contacts);
+            }
+            RdpeiPdu::Touch {
+                encode_time,
+                frames,
+            } => write_frames(&mut buffer, *encode_time, frames, TouchContact::write),
+            RdpeiPdu::Pen {
+                encode_time,
+                frames,
+            } => write_frames(&mut buffer, *encode_time, frames, PenContact::write),
+            RdpeiPdu::SuspendInput | RdpeiPdu::ResumeInput => (),
+            RdpeiPdu::DismissHoveringContact(contact_id) => buffer.put_u8(*contact_id),
+        }
+        buffer
+    }
+}
+
+/// Read a touch and pen input PDU with its header
+pub fn read_rdpei_pdu(buffer: &mut BytesMut) -> Result<RdpeiPdu> {
+    check_remaining(buffer, HEADER_SIZE, "RDPEI: header")?;
+    let event_id = buffer.get_u16_le();
+    let length = (buffer.get_u32_le() as usize).saturating_sub(HEADER_SIZE);
+    check_remaining(buffer, length, "RDPEI: PDU")?;
+    let mut body = buffer.split_to(length);
+    let body = &mut body;
+
+    Ok(match RdpeiEventId::try_from(event_id) {
+        Ok(RdpeiEventId::EventidScReady) => {
+            check_remaining(body, 4, "RDPEI: server ready")?;
+            let protocol_version = body.get_u32_le();
+            let supported_features = if body.remaining() >= 4 {
+                Some(body.get_u32_le())
+            } else {
+                None
+            };
+            RdpeiPdu::ScReady {
+                protocol_version,
+                supported_features,
+            }
+        }
+        Ok(RdpeiEventId::EventidCsReady) => {
+            check_remaining(body, 10, "RDPEI: client ready")?;
+            RdpeiPdu::CsReady {
+                flags: body.get_u32_le(),
+                protocol_version: body.get_u32_le(),
+                max_touch_contacts: body.get_u16_le(),
+            }
+        }
+        Ok(RdpeiEventId::EventidTouch) => {
+            let (encode_time, frames) = read_frames(body, TouchContact::read)?;
+            RdpeiPdu::Touch {
+                encode_time,
+                frames,
+            }
+        }
+        Ok(RdpeiEventId::EventidPen) => {
+            let (encode_time, frames) = read_frames(body, PenContact::read)?;
+            RdpeiPdu::Pen {
+                encode_time,
+                frames,
+            }
+        }
+        Ok(RdpeiEventId::EventidSuspendInput) => RdpeiPdu::SuspendInput,
+        Ok(RdpeiEventId::EventidResumeInput) => RdpeiPdu::ResumeInput,
+        Ok(RdpeiEventId::EventidDismissHoveringContact) => {
+            check_remaining(body, 1, "RDPEI: dismiss hovering contact")?;
+            RdpeiPdu::DismissHoveringContact(body.get_u8())
+        }
+        Err(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("RDPEI: unexpected event id {}", event_id),
+            ))
+        }
+    })
+}
+
+#[async_trait]
+impl Message for RdpeiPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        let body = self.body();
+        writer.write_u16_le(self.event_id() as u16).await?;
+        writer
+            .write_u32_le((HEADER_SIZE + body.len()) as u32)
+            .await?;
+        writer.write_all(&body).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        *self = read_rdpei_pdu(&mut BytesMut::from(&data[..]))?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        HEADER_SIZE + self.body().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Variable length integers take the fewest bytes
+    #[test]
+    fn test_variable_length_integers() {
+        let mut buffer = Vec::new();
+        write_two_byte_unsigned(&mut buffer, 0x7F);
+        write_two_byte_unsigned(&mut buffer, 0x1234);
+        write_two_byte_signed(&mut buffer, -0x3F);
+        write_four_byte_unsigned(&mut buffer, 0x123456);
+        write_four_byte_signed(&mut buffer, -0x1FFF);
+        write_eight_byte_unsigned(&mut buffer, 0x123456789A);
+        assert_eq!(
+            buffer,
+            [0x7F, 0x92, 0x34, 0x7F, 0x92, 0x34, 0x56, 0x7F, 0xFF, 0x92, 0x34, 0x56, 0x78, 0x9A]
+        );
+        let mut buffer = BytesMut::from(&buffer[..]);
+        assert_eq!(read_two_byte_unsigned(&mut buffer).unwrap(), 0x7F);
+        assert_eq!(read_two_byte_unsigned(&mut buffer).unwrap(), 0x1234);
+        assert_eq!(read_two_byte_signed(&mut buffer).unwrap(), -0x3F);
+        assert_eq!(read_four_byte_unsigned(&mut buffer).unwrap(), 0x123456);
+        assert_eq!(read_four_byte_signed(&mut buffer).unwrap(), -0x1FFF);
+        assert_eq!(read_eight_byte_unsigned(&mut buffer).unwrap(), 0x123456789A);
+    }
+
+    /// Check the encoding of a PDU both ways
+    async fn check_pdu(pdu: RdpeiPdu, data: &[u8]) {
+        assert_eq!(pdu.length(), data.len());
+        assert_eq!(to_vec(&pdu).await.unwrap(), data);
+        assert_eq!(read_rdpei_pdu(&mut BytesMut::from(data)).unwrap(), pdu);
+    }
+
+    /// Ready PDUs of both sides
+    /// MS-RDPEI 2.2.3.1 RDPINPUT_SC_READY_PDU
+    /// MS-RDPEI 2.2.3.2 RDPINPUT_CS_READY_PDU
+    #[tokio::test]
+    async fn test_rdpei_ready_pdu() {
+        check_pdu(
+            RdpeiPdu::ScReady {
+                protocol_version: RDPINPUT_PROTOCOL_V100,
+                supported_features: None,
+            },
+            &[
+                0x01, 0x00, // eventId EVENTID_SC_READY
+                0x0A, 0x00, 0x00, 0x00, // pduLength
+                0x00, 0x00, 0x01, 0x00, // protocolVersion 1.0
+            ],
+        )
+        .await;
+        check_pdu(
+            RdpeiPdu::ScReady {
+                protocol_version: RDPINPUT_PROTOCOL_V300,
+                supported_features: Some(1),
+            },
+            &[
+                0x01, 0x00, // eventId EVENTID_SC_READY
+                0x0E, 0x00, 0x00, 0x00, // pduLength
+                0x00, 0x00, 0x03, 0x00, // protocolVersion 3.0
+                0x01, 0x00, 0x00, 0x00, // supportedFeatures multipen injection
+            ],
+        )
+        .await;
+        check_pdu(
+            RdpeiPdu::CsReady {
+                flags: ReadyFlag::ReadyFlagsShowTouchVisuals as u32,
+                protocol_version: RDPINPUT_PROTOCOL_V100,
+                max_touch_contacts: 10,
+            },
+            &[
+                0x02, 0x00, // eventId EVENTID_CS_READY
+                0x10, 0x00, 0x00, 0x00, // pduLength
+                0x01, 0x00, 0x00, 0x00, // flags READY_FLAGS_SHOW_TOUCH_VISUALS
+                0x00, 0x00, 0x01, 0x00, // protocolVersion 1.0
+                0x0A, 0x00, // maxTouchContacts
+            ],
+        )
+        .await;
+    }
+
+    /// A finger down with all optional fields
+    /// MS-RDPEI 2.2.3.3 RDPINPUT_TOUCH_EVENT_PDU
+    #[tokio::test]
+    async fn test_rdpei_touch_pdu() {
+        let flags = ContactFlag::ContactFlagDown as u32
+            | ContactFlag::ContactFlagInrange as u32
+            | ContactFlag::ContactFlagIncontact as u32;
+        check_pdu(
+            RdpeiPdu::Touch {
+                encode_time: 16,
+                frames: vec![InputFrame {
+                    frame_offset: 0,
+                    contacts: vec![TouchContact {
+                        contact_id: 1,
+                        x: 1000,
+                        y: -20,
+                        flags,
+                        rect: Some((-2, -2, 2, 2)),
+                        orientation: Some(90),
+                        pressure: Some(512),
+                    }],
+                }],
+            },
+            &[
+                0x03, 0x00, // eventId EVENTID_TOUCH
+                0x18, 0x00, 0x00, 0x00, // pduLength
+                0x10, // encodeTime
+                0x01, // frameCount
+                0x01, // contactCount
+                0x00, // frameOffset
+                0x01, // contactId
+                0x07, // fieldsPresent rect, orientation and pressure
+                0x43, 0xE8, // x 1000 on two bytes
+                0x34, // y -20
+                0x19, // contactFlags DOWN | INRANGE | INCONTACT
+                0x42, 0x42, 0x02, 0x02, // contactRectLeft, Top, Right, Bottom
+                0x40, 0x5A, // orientation 90 on two bytes
+                0x42, 0x00, // pressure 512 on two bytes
+            ],
+        )
+        .await;
+    }
+
+    /// A pen with its eraser pressed and all optional fields
+    /// MS-RDPEI 2.2.3.7 RDPINPUT_PEN_EVENT_PDU
+    #[tokio::test]
+    async fn test_rdpei_pen_pdu() {
+        let flags = ContactFlag::ContactFlagUpdate as u32
+            | ContactFlag::ContactFlagInrange as u32
+            | ContactFlag::ContactFlagIncontact as u32;
+        check_pdu(
+            RdpeiPdu::Pen {
+                encode_time: 0,
+                frames: vec![InputFrame {
+                    frame_offset: 0,
+                    contacts: vec![PenContact {
+                        device_id: 0,
+                        x: 300,
+                        y: 400,
+                        flags,
+                        pen_flags: Some(PenFlag::PenFlagEraserPressed as u32),
+                        pressure: Some(1024),
+                        rotation: Some(359),
+                        tilt_x: Some(-45),
+                        tilt_y: Some(90),
+                    }],
+                }],
+            },
+            &[
+                0x08, 0x00, // eventId EVENTID_PEN
+                0x19, 0x00, 0x00, 0x00, // pduLength
+                0x00, // encodeTime
+                0x01, // frameCount
+                0x01, // contactCount
+                0x00, // frameOffset
+                0x00, // deviceId
+                0x1F, // fieldsPresent all
+                0x41, 0x2C, // x 300 on two bytes
+                0x41, 0x90, // y 400 on two bytes
+                0x1A, // contactFlags UPDATE | INRANGE | INCONTACT
+                0x02, // penFlags PEN_FLAG_ERASER_PRESSED
+                0x44, 0x00, // pressure 1024 on two bytes
+                0x81, 0x67, // rotation 359 on two bytes
+                0x6D, // tiltX -45
+                0x80, 0x5A, // tiltY 90 on two bytes
+            ],
+        )
+        .await;
+    }
+
+    /// PDUs without a body, or a single byte
+    /// MS-RDPEI 2.2.3.4 to 2.2.3.6
+    #[tokio::test]
+    async fn test_rdpei_short_pdu() {
+        check_pdu(RdpeiPdu::SuspendInput, &[4, 0, 6, 0, 0, 0]).await;
+        check_pdu(RdpeiPdu::ResumeInput, &[5, 0, 6, 0, 0, 0]).await;
+        check_pdu(RdpeiPdu::DismissHoveringContact(3), &[6, 0, 7, 0, 0, 0, 3]).await;
+    }
+}
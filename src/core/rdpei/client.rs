@@ -0,0 +1,227 @@
+use crate::core::drdynvc::client::{to_messages, DynamicChannelHandler};
+use crate::core::event::{DynamicChannelEvent, RdpEvent};
+use crate::core::rdpei::base::{
+    read_rdpei_pdu, InputFrame, PenContact, RdpeiPdu, ReadyFlag, TouchContact,
+    RDPEI_CHANNEL_NAME, RDPINPUT_PROTOCOL_V300,
+};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use std::any::Any;
+use std::io::Result;
+use std::time::Instant;
+
+/// Event of the touch and pen input channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RdpeiEvent {
+    /// Touch frames can be sent, and pen frames from version 3
+    Ready(u32),
+    /// Frames are dropped until the input is resumed
+    Suspended,
+    Resumed,
+    /// The server removed a hovering contact
+    DismissHoveringContact(u8),
+}
+
+/// Client of the touch and pen input dynamic virtual channel
+/// Contacts are sent as frames, one frame per PDU,
+/// with the time elapsed since the previous one
+///
+/// A contact goes down with ContactFlagDown, ContactFlagInrange and ContactFlagIncontact,
+/// moves with ContactFlagUpdate and the same state flags, and ends with ContactFlagUp
+///
+/// # Example
+/// ```
+/// use rdp::core::rdpei::base::{ContactFlag, TouchContact};
+/// use rdp::core::rdpei::client::RdpeiClient;
+/// let mut client = RdpeiClient::new(10);
+/// // Ready PDU of the server, version 3
+/// let mut data = bytes::BytesMut::from(&[1, 0, 10, 0, 0, 0, 0, 0, 3, 0][..]);
+/// let responses = client.process(&mut data, &mut |_| {}).unwrap();
+/// assert_eq!(responses.len(), 1);
+/// let touch = client.touch(vec![TouchContact {
+///     contact_id: 0,
+///     x: 100,
+///     y: 200,
+///     flags: ContactFlag::ContactFlagDown as u32
+///         | ContactFlag::ContactFlagInrange as u32
+///         | ContactFlag::ContactFlagIncontact as u32,
+///     pressure: Some(512),
+///     ..Default::default()
+/// }]);
+/// assert!(touch.is_some());
+/// ```
+pub struct RdpeiClient {
+    max_touch_contacts: u16,
+    /// Version of the server once ready
+    server_version: Option<u32>,
+    suspended: bool,
+    /// Time of the previous frame
+    last_frame: Option<Instant>,
+}
+
+impl RdpeiClient {
+    /// Number of fingers the client may send at once
+    pub fn new(max_touch_contacts: u16) -> Self {
+        RdpeiClient {
+            max_touch_contacts,
+            server_version: None,
+            suspended: false,
+            last_frame: None,
+        }
+    }
+
+    /// Milliseconds since the previous frame
+    fn encode_time(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = self
+            .last_frame
+            .map(|last| now.duration_since(last).as_millis().min(u32::MAX as u128) as u32)
+            .unwrap_or(0);
+        self.last_frame = Some(now);
+        elapsed
+    }
+
+    /// Frames can be sent to the server
+    fn accepts_input(&self) -> bool {
+        self.server_version.is_some() && !self.suspended
+    }
+
+    /// Touch frame with the contacts of all the fingers on the screen
+    /// None until the server is ready, or while the input is suspended
+    pub fn touch(&mut self, contacts: Vec<TouchContact>) -> Option<RdpeiPdu> {
+        if !self.accepts_input() {
+            return None;
+        }
+        Some(RdpeiPdu::Touch {
+            encode_time: self.encode_time(),
+            frames: vec![InputFrame {
+                frame_offset: 0,
+                contacts,
+            }],
+        })
+    }
+
+    /// Pen frame, with the pressure, tilt and buttons of the pen
+    /// None when the server doesn't support the pen
+    pub fn pen(&mut self, contacts: Vec<PenContact>) -> Option<RdpeiPdu> {
+        if !self.accepts_input() || self.server_version? < RDPINPUT_PROTOCOL_V300 {
+            return None;
+        }
+        Some(RdpeiPdu::Pen {
+            encode_time: self.encode_time(),
+            frames: vec![InputFrame {
+                frame_offset: 0,
+                contacts,
+            }],
+        })
+    }
+
+    /// Process a message of the channel
+    /// and build the PDUs expected by the server
+    pub fn process<T>(&mut self, buffer: &mut BytesMut, callback: &mut T) -> Result<Vec<RdpeiPdu>>
+    where
+        T: FnMut(RdpeiEvent),
+    {
+        let mut responses = Vec::new();
+        match read_rdpei_pdu(buffer)? {
+            RdpeiPdu::ScReady {
+                protocol_version, ..
+            } => {
+                self.server_version = Some(protocol_version);
+                self.suspended = false;
+                self.last_frame = None;
+                responses.push(RdpeiPdu::CsReady {
+                    flags: ReadyFlag::ReadyFlagsShowTouchVisuals as u32,
+                    protocol_version: RDPINPUT_PROTOCOL_V300,
+                    max_touch_contacts: self.max_touch_contacts,
+                });
+                callback(RdpeiEvent::Ready(protocol_version));
+            }
+            RdpeiPdu::SuspendInput => {
+                self.suspended = true;
+                callback(RdpeiEvent::Suspended);
+            }
+            RdpeiPdu::ResumeInput => {
+                self.suspended = false;
+                callback(RdpeiEvent::Resumed);
+            }
+            RdpeiPdu::DismissHoveringContact(contact_id) => {
+                callback(RdpeiEvent::DismissHoveringContact(contact_id))
+            }
+            _ => (),
+        }
+        Ok(responses)
+    }
+}
+
+/// Frames are written with `DynamicChannelClient::write`
+#[async_trait]
+impl DynamicChannelHandler for RdpeiClient {
+    fn channel_names(&self) -> Vec<&'static str> {
+        vec![RDPEI_CHANNEL_NAME]
+    }
+
+    async fn process(
+        &mut self,
+        _channel_name: &str,
+        _channel_id: u32,
+        buffer: &mut BytesMut,
+        callback: &mut (dyn FnMut(RdpEvent) + Send),
+    ) -> Result<Vec<Vec<u8>>> {
+        let responses = RdpeiClient::process(self, buffer, &mut |event| {
+            callback(RdpEvent::Dynamic(DynamicChannelEvent::Touch(event)))
+        })?;
+        to_messages(responses).await
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::rdpei::base::RDPINPUT_PROTOCOL_V200;
+
+    /// Frames are only sent once ready and while not suspended,
+    /// pen frames need version 3
+    #[test]
+    fn test_input_state() {
+        let mut client = RdpeiClient::new(5);
+        assert!(client.touch(vec![TouchContact::default()]).is_none());
+
+        let mut events = Vec::new();
+        let mut buffer = BytesMut::from(&[1, 0, 10, 0, 0, 0, 0, 0, 2, 0][..]);
+        let responses = client
+            .process(&mut buffer, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!(
+            responses,
+            [RdpeiPdu::CsReady {
+                flags: ReadyFlag::ReadyFlagsShowTouchVisuals as u32,
+                protocol_version: RDPINPUT_PROTOCOL_V300,
+                max_touch_contacts: 5,
+            }]
+        );
+        assert!(matches!(
+            client.touch(vec![TouchContact::default()]),
+            Some(RdpeiPdu::Touch { encode_time: 0, .. })
+        ));
+        assert!(client.pen(vec![PenContact::default()]).is_none());
+
+        let mut buffer = BytesMut::from(&[4, 0, 6, 0, 0, 0][..]);
+        client
+            .process(&mut buffer, &mut |event| events.push(event))
+            .unwrap();
+        assert!(client.touch(vec![TouchContact::default()]).is_none());
+        assert_eq!(
+            events,
+            [
+                RdpeiEvent::Ready(RDPINPUT_PROTOCOL_V200),
+                RdpeiEvent::Suspended
+            ]
+        );
+    }
+}
@@ -0,0 +1,462 @@
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, BytesMut};
+use std::io::Result;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest datagram, SYN datagrams are padded to this size
+/// MS-RDPEUDP 3.1.5.1.1 Sending the SYN Datagram
+pub const RDPUDP_MTU_SIZE: u16 = 1232;
+
+/// Acknowledged sequence number of the SYN datagram
+pub const RDPUDP_INITIAL_SOURCE_ACK: u32 = 0xFFFFFFFF;
+
+/// Version of the protocol announced in the SYNEX payload
+/// MS-RDPEUDP 2.2.2.12 RDPUDP_SYNDATAEX_PAYLOAD Structure
+pub const RDPUDP_PROTOCOL_VERSION_1: u16 = 0x0001;
+pub const RDPUDP_PROTOCOL_VERSION_2: u16 = 0x0002;
+pub const RDPUDP_PROTOCOL_VERSION_3: u16 = 0x0101;
+
+/// The version field of the SYNEX payload is valid
+const RDPUDP_VERSION_INFO_VALID: u16 = 0x0001;
+
+/// State of a run of the ACK vector
+/// MS-RDPEUDP 2.2.1.1 ACK Vector Element
+const DATAGRAM_RECEIVED: u8 = 0;
+const DATAGRAM_NOT_YET_RECEIVED: u8 = 3;
+
+/// Longest run of an ACK vector element
+const ACK_VECTOR_MAX_RUN: usize = 0x3F;
+
+/// Content of a datagram, in the FEC header
+/// MS-RDPEUDP 2.2.2.1 RDPUDP_FEC_HEADER Structure
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UdpFlag {
+    RdpudpFlagSyn = 0x0001,
+    RdpudpFlagFin = 0x0002,
+    RdpudpFlagAck = 0x0004,
+    RdpudpFlagData = 0x0008,
+    RdpudpFlagFec = 0x0010,
+    RdpudpFlagCn = 0x0020,
+    RdpudpFlagCwr = 0x0040,
+    RdpudpFlagSackOption = 0x0080,
+    RdpudpFlagAckOfAcks = 0x0100,
+    RdpudpFlagSynlossy = 0x0200,
+    RdpudpFlagAckdelayed = 0x0400,
+    RdpudpFlagCorrelationId = 0x0800,
+    RdpudpFlagSynex = 0x1000,
+}
+
+/// First header of all the datagrams
+/// MS-RDPEUDP 2.2.2.1 RDPUDP_FEC_HEADER Structure
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FecHeader {
+    /// Highest sequence number described by the ACK vector
+    pub source_ack: u32,
+    /// Datagrams the sender is able to buffer
+    pub receive_window_size: u16,
+    /// UdpFlag of the datagram
+    pub flags: u16,
+}
+
+/// Parameters of the connection
+/// MS-RDPEUDP 2.2.2.5 RDPUDP_SYNDATA_PAYLOAD Structure
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SynData {
+    /// The first data datagram uses the next one
+    pub initial_sequence_number: u32,
+    pub upstream_mtu: u16,
+    pub downstream_mtu: u16,
+}
+
+/// Data of a reliable datagram, which is never coded
+/// MS-RDPEUDP 2.2.2.4 RDPUDP_SOURCE_PAYLOAD_HEADER Structure
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SourcePayload {
+    pub coded_sequence: u32,
+    pub source_start: u32,
+    pub data: Vec<u8>,
+}
+
+/// Parity of a range of source datagrams of the lossy mode
+/// MS-RDPEUDP 2.2.2.2 RDPUDP_FEC_PAYLOAD_HEADER Structure
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FecPayload {
+    pub coded_sequence: u32,
+    /// Source sequence number of the first datagram of the range
+    pub source_start: u32,
+    /// Number of source datagrams of the range
+    pub range: u8,
+    pub fec_index: u8,
+    /// Parity written by fec_encode
+    pub data: Vec<u8>,
+}
+
+/// Datagram of the UDP transport, its sections follow the flags
+/// All the fields are in network byte order
+///
+/// # Example
+/// ```
+/// use rdp::core::rdpeudp::base::{FecHeader, UdpDatagram, UdpFlag, RDPUDP_MTU_SIZE};
+/// let datagram = UdpDatagram {
+///     header: FecHeader {
+///         source_ack: 10,
+///         receive_window_size: 64,
+///         flags: UdpFlag::RdpudpFlagAck as u16,
+///     },
+///     ack_vector: Some(vec![true, false, true]),
+///     ..Default::default()
+/// };
+/// assert!(datagram.to_bytes().len() < RDPUDP_MTU_SIZE as usize);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UdpDatagram {
+    pub header: FecHeader,
+    pub syn: Option<SynData>,
+    pub correlation_id: Option<[u8; 16]>,
+    /// Version of the protocol of the SYNEX payload
+    pub synex_version: Option<u16>,
+    /// Received state of the datagrams up to the source ack
+    pub ack_vector: Option<Vec<bool>>,
+    /// The peer may forget the states up to this sequence number
+    pub ack_of_acks: Option<u32>,
+    pub payload: Option<SourcePayload>,
+    pub fec: Option<FecPayload>,
+}
+
+impl UdpDatagram {
+    /// Flags of the header with the ones of the present sections
+    pub fn flags(&self) -> u16 {
+        let mut flags = self.header.flags;
+        for (present, flag) in [
+            (self.syn.is_some(), UdpFlag::RdpudpFlagSyn),
+            (
+                self.correlation_id.is_some(),
+                UdpFlag::RdpudpFlagCorrelationId,
+            ),
+            (self.synex_version.is_some(), UdpFlag::RdpudpFlagSynex),
+            (self.ack_vector.is_some(), UdpFlag::RdpudpFlagAck),
+            (self.ack_of_acks.is_some(), UdpFlag::RdpudpFlagAckOfAcks),
+            (self.payload.is_some(), UdpFlag::RdpudpFlagData),
+            (self.fec.is_some(), UdpFlag::RdpudpFlagFec),
+        ] {
+            if present {
+                flags |= flag as u16;
+            }
+        }
+        flags
+    }
+
+    /// Serialize the datagram, padded when it is a SYN one
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.put_u32(self.header.source_ack);
+        buffer.put_u16(self.header.receive_window_size);
+        buffer.put_u16(self.flags());
+
+        if let Some(syn) = &self.syn {
+            buffer.put_u32(syn.initial_sequence_number);
+            buffer.put_u16(syn.upstream_mtu);
+            buffer.put_u16(syn.downstream_mtu);
+            if let Some(correlation_id) = &self.correlation_id {
+                buffer.put_slice(correlation_id);
+                buffer.put_slice(&[0; 16]);
+            }
+            if let Some(version) = self.synex_version {
+                buffer.put_u16(RDPUDP_VERSION_INFO_VALID);
+                buffer.put_u16(version);
+            }
+            buffer.resize(RDPUDP_MTU_SIZE as usize, 0);
+            return buffer;
+        }
+
+        if let Some(states) = &self.ack_vector {
+            let elements = write_ack_vector(states);
+            buffer.put_u16(elements.len() as u16);
+            buffer.put_slice(&elements);
+            buffer.resize(buffer.len() + (4 - (2 + elements.len()) % 4) % 4, 0);
+        }
+        if let Some(ack_of_acks) = self.ack_of_acks {
+            buffer.put_u32(ack_of_acks);
+        }
+        if let Some(payload) = &self.payload {
+            buffer.put_u32(payload.coded_sequence);
+            buffer.put_u32(payload.source_start);
+            buffer.put_slice(&payload.data);
+        }
+        if let Some(fec) = &self.fec {
+            buffer.put_u32(fec.coded_sequence);
+            buffer.put_u32(fec.source_start);
+            buffer.put_u8(fec.range);
+            buffer.put_u8(fec.fec_index);
+            buffer.put_u16(0);
+            buffer.put_slice(&fec.data);
+        }
+        buffer
+    }
+}
+
+/// Parity of the source datagrams of a range
+/// Each source is prefixed with its size, as a RDPUDP_PAYLOAD_PREFIX,
+/// and padded to the longest one
+/// MS-RDPEUDP 2.2.2.3 RDPUDP_PAYLOAD_PREFIX Structure
+pub fn fec_encode(sources: &[&[u8]]) -> Vec<u8> {
+    let mut parity = Vec::new();
+    for source in sources {
+        let mut prefixed = (source.len() as u16).to_be_bytes().to_vec();
+        prefixed.extend_from_slice(source);
+        if parity.len() < prefixed.len() {
+            parity.resize(prefixed.len(), 0);
+        }
+        for (byte, value) in parity.iter_mut().zip(prefixed) {
+            *byte ^= value;
+        }
+    }
+    parity
+}
+
+/// Rebuild the only missing source of a range
+/// from the parity and all the other sources
+pub fn fec_recover(parity: &[u8], others: &[&[u8]]) -> Option<Vec<u8>> {
+    let mut missing = fec_encode(others);
+    missing.resize(missing.len().max(parity.len()), 0);
+    for (byte, value) in missing.iter_mut().zip(parity) {
+        *byte ^= value;
+    }
+    if missing.len() < 2 {
+        return None;
+    }
+    let size = u16::from_be_bytes([missing[0], missing[1]]) as usize;
+    missing.get(2..2 + size).map(|source| source.to_vec())
+}
+
+/// Encode the states as runs of received and missing datagrams
+/// MS-RDPEUDP 2.2.1.1 ACK Vector Element
+pub fn write_ack_vector(states: &[bool]) -> Vec<u8> {
+    let mut elements = Vec::new();
+    let mut index = 0;
+    while index < states.len() {
+        let state = states[index];
+        let run = states[index..]
+            .iter()
+            .take(ACK_VECTOR_MAX_RUN)
+            .take_while(|other| **other == state)
+            .count();
+        let code = if state {
+            DATAGRAM_RECEIVED
+        } else {
+            DATAGRAM_NOT_YET_RECEIVED
+        };
+        elements.push((code << 6) | run as u8);
+        index += run;
+    }
+    elements
+}
+
+/// Decode the runs of an ACK vector
+pub fn read_ack_vector(elements: &[u8]) -> Vec<bool> {
+    elements
+        .iter()
+        .flat_map(|element| {
+            let received = element >> 6 == DATAGRAM_RECEIVED;
+            std::iter::repeat_n(received, (element & 0x3F) as usize)
+        })
+        .collect()
+}
+
+/// Read a datagram of the UDP transport
+pub fn read_udp_datagram(buffer: &mut BytesMut) -> Result<UdpDatagram> {
+    check_remaining(buffer, 8, "RDPEUDP: FEC header")?;
+    let header = FecHeader {
+        source_ack: buffer.get_u32(),
+        receive_window_size: buffer.get_u16(),
+        flags: buffer.get_u16(),
+    };
+    let has = |flag: UdpFlag| header.flags & flag as u16 != 0;
+    let mut datagram = UdpDatagram {
+        header,
+        ..Default::default()
+    };
+
+    if has(UdpFlag::RdpudpFlagSyn) {
+        check_remaining(buffer, 8, "RDPEUDP: SYN data")?;
+        datagram.syn = Some(SynData {
+            initial_sequence_number: buffer.get_u32(),
+            upstream_mtu: buffer.get_u16(),
+            downstream_mtu: buffer.get_u16(),
+        });
+        if has(UdpFlag::RdpudpFlagCorrelationId) {
+            check_remaining(buffer, 32, "RDPEUDP: correlation id")?;
+            let mut correlation_id = [0u8; 16];
+            buffer.copy_to_slice(&mut correlation_id);
+            buffer.advance(16);
+            datagram.correlation_id = Some(correlation_id);
+        }
+        if has(UdpFlag::RdpudpFlagSynex) {
+            check_remaining(buffer, 4, "RDPEUDP: SYNEX data")?;
+            let flags = buffer.get_u16();
+            let version = buffer.get_u16();
+            if flags & RDPUDP_VERSION_INFO_VALID != 0 {
+                datagram.synex_version = Some(version);
+            }
+        }
+        // The rest is padding, or the cookie hash of the version 3
+        buffer.clear();
+        return Ok(datagram);
+    }
+
+    if has(UdpFlag::RdpudpFlagAck) {
+        check_remaining(buffer, 2, "RDPEUDP: ACK vector header")?;
+        let size = buffer.get_u16() as usize;
+        let padding = (4 - (2 + size) % 4) % 4;
+        check_remaining(buffer, size + padding, "RDPEUDP: ACK vector")?;
+        datagram.ack_vector = Some(read_ack_vector(&buffer.split_to(size)));
+        buffer.advance(padding);
+    }
+    if has(UdpFlag::RdpudpFlagAckOfAcks) {
+        check_remaining(buffer, 4, "RDPEUDP: ACK of ACKs")?;
+        datagram.ack_of_acks = Some(buffer.get_u32());
+    }
+    if has(UdpFlag::RdpudpFlagData) {
+        check_remaining(buffer, 8, "RDPEUDP: source payload header")?;
+        datagram.payload = Some(SourcePayload {
+            coded_sequence: buffer.get_u32(),
+            source_start: buffer.get_u32(),
+            data: buffer.split().to_vec(),
+        });
+    }
+    if has(UdpFlag::RdpudpFlagFec) {
+        check_remaining(buffer, 12, "RDPEUDP: FEC payload header")?;
+        let coded_sequence = buffer.get_u32();
+        let source_start = buffer.get_u32();
+        let range = buffer.get_u8();
+        let fec_index = buffer.get_u8();
+        let _padding = buffer.get_u16();
+        datagram.fec = Some(FecPayload {
+            coded_sequence,
+            source_start,
+            range,
+            fec_index,
+            data: buffer.split().to_vec(),
+        });
+    }
+    Ok(datagram)
+}
+
+#[async_trait]
+impl Message for UdpDatagram {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_all(&self.to_bytes()).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        *self = read_udp_datagram(&mut BytesMut::from(&data[..]))?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        self.to_bytes().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Runs are split at the largest length of an element
+    #[test]
+    fn test_ack_vector() {
+        let mut states = vec![true; 70];
+        states.extend([false, false, true]);
+        let elements = write_ack_vector(&states);
+        assert_eq!(elements, [0x3F, 0x07, 0xC2, 0x01]);
+        assert_eq!(read_ack_vector(&elements), states);
+    }
+
+    /// A lost source is rebuilt from the parity of its range
+    #[test]
+    fn test_fec() {
+        let sources: [&[u8]; 3] = [&[1, 2, 3], &[4], &[5, 6]];
+        let parity = fec_encode(&sources);
+        assert_eq!(parity.len(), 5);
+        assert_eq!(
+            fec_recover(&parity, &[sources[0], sources[2]]),
+            Some(vec![4])
+        );
+        assert_eq!(
+            fec_recover(&parity, &[sources[1], sources[2]]),
+            Some(vec![1, 2, 3])
+        );
+    }
+
+    /// Datagrams are written and read back, SYN datagrams are padded
+    #[test]
+    fn test_udp_datagram() {
+        let syn = UdpDatagram {
+            header: FecHeader {
+                source_ack: RDPUDP_INITIAL_SOURCE_ACK,
+                receive_window_size: 64,
+                flags: 0,
+            },
+            syn: Some(SynData {
+                initial_sequence_number: 100,
+                upstream_mtu: RDPUDP_MTU_SIZE,
+                downstream_mtu: RDPUDP_MTU_SIZE,
+            }),
+            correlation_id: Some([7; 16]),
+            synex_version: Some(RDPUDP_PROTOCOL_VERSION_2),
+            ..Default::default()
+        };
+        let data = UdpDatagram {
+            header: FecHeader {
+                source_ack: 20,
+                receive_window_size: 64,
+                flags: UdpFlag::RdpudpFlagCwr as u16,
+            },
+            ack_vector: Some(vec![true, true, false, true]),
+            ack_of_acks: Some(5),
+            payload: Some(SourcePayload {
+                coded_sequence: 101,
+                source_start: 101,
+                data: vec![1, 2, 3],
+            }),
+            ..Default::default()
+        };
+        let fec = UdpDatagram {
+            header: FecHeader {
+                source_ack: 20,
+                receive_window_size: 64,
+                flags: 0,
+            },
+            ack_vector: Some(vec![true]),
+            fec: Some(FecPayload {
+                coded_sequence: 106,
+                source_start: 101,
+                range: 4,
+                fec_index: 0,
+                data: vec![9, 8, 7],
+            }),
+            ..Default::default()
+        };
+        for datagram in [syn, data, fec] {
+            let bytes = datagram.to_bytes();
+            let mut result = read_udp_datagram(&mut BytesMut::from(&bytes[..])).unwrap();
+            assert_eq!(result.header.flags, datagram.flags());
+            result.header.flags = datagram.header.flags;
+            assert_eq!(result, datagram);
+        }
+        assert_eq!(
+            UdpDatagram {
+                syn: Some(SynData::default()),
+                ..Default::default()
+            }
+            .to_bytes()
+            .len(),
+            RDPUDP_MTU_SIZE as usize
+        );
+    }
+}
@@ -0,0 +1,1017 @@
+use crate::core::multitransport::RequestedProtocol;
+use crate::core::rdpeudp::base::{
+    fec_encode, fec_recover, read_udp_datagram, FecHeader, FecPayload, SourcePayload, SynData,
+    UdpDatagram, UdpFlag, RDPUDP_INITIAL_SOURCE_ACK, RDPUDP_MTU_SIZE, RDPUDP_PROTOCOL_VERSION_2,
+};
+
+use bytes::BytesMut;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, Instant};
+
+/// Datagrams the client is able to buffer
+const RECEIVE_WINDOW_SIZE: u16 = 64;
+/// Datagrams in flight before the first acknowledgement
+const INITIAL_CONGESTION_WINDOW: u32 = 4;
+const MIN_CONGESTION_WINDOW: u32 = 2;
+/// Time before the first retransmission, until the round trip time is measured
+const INITIAL_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(1);
+const MIN_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(300);
+const MAX_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Retransmissions of a datagram before the connection is dropped
+const RETRANSMIT_LIMIT: u32 = 5;
+/// Acknowledgements of later datagrams before a missing one is resent
+const DUPLICATE_ACK_THRESHOLD: u32 = 3;
+/// Largest encoded ACK vector, the states are kept within the receive window
+const ACK_VECTOR_MAX_SIZE: usize = RECEIVE_WINDOW_SIZE as usize;
+/// FEC header, ACK vector, ACK of ACKs and FEC payload header of a datagram
+/// The payload prefix of the parity is also counted
+const DATA_OVERHEAD: usize = 8 + 4 + ACK_VECTOR_MAX_SIZE + 4 + 12 + 2;
+/// Source datagrams covered by one FEC datagram in the lossy mode
+const FEC_RANGE: usize = 4;
+/// Source datagrams buffered after a missing one before it is given up
+const LOSSY_REORDER_LIMIT: usize = 2 * FEC_RANGE;
+
+/// Sequence numbers wrap, the first one comes before the second
+/// when the distance is less than half of the range
+fn is_before(sequence: u32, other: u32) -> bool {
+    other.wrapping_sub(sequence).wrapping_sub(1) < 0x8000_0000
+}
+
+/// Profile of the connection
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UdpMode {
+    /// Every datagram is delivered, the lost ones are resent
+    Reliable,
+    /// Lost datagrams are rebuilt from the FEC datagrams or given up,
+    /// used for the graphics stream
+    Lossy,
+}
+
+impl From<RequestedProtocol> for UdpMode {
+    fn from(protocol: RequestedProtocol) -> Self {
+        match protocol {
+            RequestedProtocol::InititateRequestProtocolUdpfecr => UdpMode::Reliable,
+            RequestedProtocol::InititateRequestProtocolUdpfecl => UdpMode::Lossy,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum State {
+    Closed,
+    SynSent,
+    Established,
+}
+
+/// Data datagram waiting for its acknowledgement
+struct SentDatagram {
+    data: Vec<u8>,
+    sent: Instant,
+    retransmits: u32,
+    /// Acknowledgements which left it missing
+    missing: u32,
+}
+
+/// Event of the UDP transport
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UdpEvent {
+    /// The handshake is over, with the negotiated MTU
+    Connected {
+        mtu: u16,
+    },
+    /// Data of the server, in order
+    Data(Vec<u8>),
+    /// Datagrams of the server given up in the lossy mode
+    /// The upper layer may ask for a refresh
+    Skipped(u32),
+    Closed,
+}
+
+/// Reliable connection of the UDP transport
+///
+/// The client sends the datagrams returned by each call
+/// on its socket, and calls `poll` at `next_timeout` for the
+/// retransmissions. Data is delivered in order, acknowledged
+/// with ACK vectors, and the datagrams in flight are limited by
+/// a congestion window which shrinks on losses and congestion
+/// notifications
+///
+/// In the lossy mode nothing is resent, each range of source
+/// datagrams is followed by a FEC datagram which rebuilds one
+/// lost source, and the data is delivered with gaps
+///
+/// # Example
+/// ```
+/// use std::time::Instant;
+/// use rdp::core::rdpeudp::client::{UdpClient, UdpMode};
+/// let mut client = UdpClient::new(1000, None, UdpMode::Reliable);
+/// let syn = client.connect(Instant::now());
+/// // socket.send(&syn.to_bytes())
+/// assert!(client.send(b"hello", Instant::now()).is_err());
+/// ```
+pub struct UdpClient {
+    state: State,
+    mode: UdpMode,
+    mtu: u16,
+    correlation_id: Option<[u8; 16]>,
+    initial_sequence_number: u32,
+    /// Sequence number of the next datagram
+    next_sequence: u32,
+    /// Source sequence number of the next data datagram
+    /// Only differs from the sequence number in the lossy mode
+    next_source: u32,
+    /// Source datagrams of the current FEC range, from its first one
+    fec_sources: Vec<Vec<u8>>,
+    fec_start: u32,
+    /// Data waiting for room in the congestion window
+    queue: VecDeque<Vec<u8>>,
+    in_flight: BTreeMap<u32, SentDatagram>,
+    /// Receive window of the server
+    peer_window: u16,
+    congestion_window: u32,
+    slow_start_threshold: u32,
+    /// Acknowledgements counted toward the next congestion window increase
+    acked_in_window: u32,
+    /// The window was reduced, until this sequence number is acknowledged
+    recovery: Option<u32>,
+    /// Answer a congestion notification with the CWR flag
+    congestion_window_reduced: bool,
+    smoothed_rtt: Option<Duration>,
+    rtt_variance: Duration,
+    retransmit_timeout: Duration,
+    /// Time of the last SYN datagram and its retransmissions
+    syn_sent: Option<(Instant, u32)>,
+    /// Last source ack of the server, sent back as ACK of ACKs
+    ack_of_acks: Option<u32>,
+    /// First sequence number described by the ACK vector
+    receive_base: u32,
+    /// Received state of the datagrams of the server from the base
+    received: VecDeque<bool>,
+    /// Source sequence number of the next datagram to deliver
+    next_delivery: u32,
+    /// Data of the server by source sequence number, kept
+    /// after the delivery in the lossy mode for the FEC
+    sources: BTreeMap<u32, Vec<u8>>,
+    /// FEC datagrams of the server by first source sequence number
+    fec_payloads: BTreeMap<u32, FecPayload>,
+    /// Data of the server must be acknowledged
+    ack_pending: bool,
+}
+
+impl UdpClient {
+    /// The initial sequence number should be random
+    /// The correlation id is the one of the multitransport request
+    /// The lossy mode falls back to the reliable one if the server refuses it
+    pub fn new(
+        initial_sequence_number: u32,
+        correlation_id: Option<[u8; 16]>,
+        mode: UdpMode,
+    ) -> Self {
+        UdpClient {
+            state: State::Closed,
+            mode,
+            mtu: RDPUDP_MTU_SIZE,
+            correlation_id,
+            initial_sequence_number,
+            next_sequence: initial_sequence_number.wrapping_add(1),
+            next_source: initial_sequence_number.wrapping_add(1),
+            fec_sources: Vec::new(),
+            fec_start: 0,
+            queue: VecDeque::new(),
+            in_flight: BTreeMap::new(),
+            peer_window: RECEIVE_WINDOW_SIZE,
+            congestion_window: INITIAL_CONGESTION_WINDOW,
+            slow_start_threshold: u32::MAX,
+            acked_in_window: 0,
+            recovery: None,
+            congestion_window_reduced: false,
+            smoothed_rtt: None,
+            rtt_variance: Duration::ZERO,
+            retransmit_timeout: INITIAL_RETRANSMIT_TIMEOUT,
+            syn_sent: None,
+            ack_of_acks: None,
+            receive_base: 0,
+            received: VecDeque::new(),
+            next_delivery: 0,
+            sources: BTreeMap::new(),
+            fec_payloads: BTreeMap::new(),
+            ack_pending: false,
+        }
+    }
+
+    /// Build the SYN datagram which starts the handshake
+    pub fn connect(&mut self, now: Instant) -> UdpDatagram {
+        self.state = State::SynSent;
+        self.syn_sent = Some((now, 0));
+        self.syn()
+    }
+
+    fn syn(&self) -> UdpDatagram {
+        UdpDatagram {
+            header: FecHeader {
+                source_ack: RDPUDP_INITIAL_SOURCE_ACK,
+                receive_window_size: RECEIVE_WINDOW_SIZE,
+                flags: match self.mode {
+                    UdpMode::Reliable => 0,
+                    UdpMode::Lossy => UdpFlag::RdpudpFlagSynlossy as u16,
+                },
+            },
+            syn: Some(SynData {
+                initial_sequence_number: self.initial_sequence_number,
+                upstream_mtu: RDPUDP_MTU_SIZE,
+                downstream_mtu: RDPUDP_MTU_SIZE,
+            }),
+            correlation_id: self.correlation_id,
+            synex_version: Some(RDPUDP_PROTOCOL_VERSION_2),
+            ..Default::default()
+        }
+    }
+
+    /// Largest data of a datagram
+    fn max_payload(&self) -> usize {
+        self.mtu as usize - DATA_OVERHEAD
+    }
+
+    /// Queue data for the server, split in datagrams,
+    /// and build the ones allowed by the congestion window
+    pub fn send(&mut self, data: &[u8], now: Instant) -> Result<Vec<UdpDatagram>> {
+        if self.state != State::Established {
+            return Err(Error::new(
+                ErrorKind::NotConnected,
+                "RDPEUDP: connection is not established",
+            ));
+        }
+        for chunk in data.chunks(self.max_payload()) {
+            self.queue.push_back(chunk.to_vec());
+        }
+        Ok(self.flush(now))
+    }
+
+    /// Close the connection
+    pub fn close(&mut self) -> UdpDatagram {
+        self.state = State::Closed;
+        let mut datagram = self.acknowledgement();
+        datagram.header.flags |= UdpFlag::RdpudpFlagFin as u16;
+        datagram
+    }
+
+    /// Header and acknowledgements of the next datagram
+    /// The ACK vector covers the datagrams from the receive base
+    /// up to the source ack
+    fn acknowledgement(&mut self) -> UdpDatagram {
+        let states: Vec<bool> = self.received.iter().copied().collect();
+        let mut flags = 0;
+        if self.congestion_window_reduced {
+            flags |= UdpFlag::RdpudpFlagCwr as u16;
+            self.congestion_window_reduced = false;
+        }
+        self.ack_pending = false;
+        UdpDatagram {
+            header: FecHeader {
+                source_ack: self
+                    .receive_base
+                    .wrapping_add(states.len() as u32)
+                    .wrapping_sub(1),
+                receive_window_size: RECEIVE_WINDOW_SIZE,
+                flags,
+            },
+            ack_vector: Some(states),
+            ack_of_acks: self.ack_of_acks.take(),
+            ..Default::default()
+        }
+    }
+
+    fn data_datagram(&mut self, sequence: u32, source: u32, data: Vec<u8>) -> UdpDatagram {
+        let mut datagram = self.acknowledgement();
+        datagram.payload = Some(SourcePayload {
+            coded_sequence: sequence,
+            source_start: source,
+            data,
+        });
+        datagram
+    }
+
+    /// Datagram in flight, only the reliable ones keep their data
+    fn track(&mut self, sequence: u32, data: Vec<u8>, now: Instant) {
+        self.in_flight.insert(
+            sequence,
+            SentDatagram {
+                data,
+                sent: now,
+                retransmits: 0,
+                missing: 0,
+            },
+        );
+    }
+
+    /// Parity of the current range of source datagrams
+    fn fec_datagram(&mut self, now: Instant) -> UdpDatagram {
+        let sequence = self.next_sequence;
+        self.next_sequence = sequence.wrapping_add(1);
+        let sources: Vec<&[u8]> = self.fec_sources.iter().map(Vec::as_slice).collect();
+        let fec = FecPayload {
+            coded_sequence: sequence,
+            source_start: self.fec_start,
+            range: sources.len() as u8,
+            fec_index: 0,
+            data: fec_encode(&sources),
+        };
+        self.fec_sources.clear();
+        self.track(sequence, Vec::new(), now);
+        let mut datagram = self.acknowledgement();
+        datagram.fec = Some(fec);
+        datagram
+    }
+
+    /// Send the queued data allowed by the windows,
+    /// or a lone acknowledgement when nothing else is sent
+    fn flush(&mut self, now: Instant) -> Vec<UdpDatagram> {
+        let mut datagrams = Vec::new();
+        let window = self.congestion_window.min(self.peer_window as u32) as usize;
+        while self.in_flight.len() < window {
+            let data = match self.queue.pop_front() {
+                Some(data) => data,
+                None => break,
+            };
+            let sequence = self.next_sequence;
+            self.next_sequence = sequence.wrapping_add(1);
+            let source = self.next_source;
+            self.next_source = source.wrapping_add(1);
+            datagrams.push(self.data_datagram(sequence, source, data.clone()));
+
+            if self.mode == UdpMode::Reliable {
+                self.track(sequence, data, now);
+                continue;
+            }
+            self.track(sequence, Vec::new(), now);
+            if self.fec_sources.is_empty() {
+                self.fec_start = source;
+            }
+            self.fec_sources.push(data);
+            if self.fec_sources.len() == FEC_RANGE || self.queue.is_empty() {
+                datagrams.push(self.fec_datagram(now));
+            }
+        }
+        if datagrams.is_empty() && self.ack_pending {
+            datagrams.push(self.acknowledgement());
+        }
+        datagrams
+    }
+
+    /// Send a datagram again with fresh acknowledgements
+    fn retransmit(&mut self, sequence: u32, now: Instant) -> Result<UdpDatagram> {
+        let sent = self
+            .in_flight
+            .get_mut(&sequence)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "RDPEUDP: unknown datagram"))?;
+        sent.retransmits += 1;
+        if sent.retransmits > RETRANSMIT_LIMIT {
+            self.state = State::Closed;
+            return Err(Error::new(
+                ErrorKind::ConnectionAborted,
+                "RDPEUDP: retransmission limit reached",
+            ));
+        }
+        sent.sent = now;
+        sent.missing = 0;
+        let data = sent.data.clone();
+        Ok(self.data_datagram(sequence, sequence, data))
+    }
+
+    /// Halve the window after a loss or a congestion notification,
+    /// once per window of data
+    fn reduce_window(&mut self) {
+        if self.recovery.is_some() {
+            return;
+        }
+        self.slow_start_threshold = (self.congestion_window / 2).max(MIN_CONGESTION_WINDOW);
+        self.congestion_window = self.slow_start_threshold;
+        self.acked_in_window = 0;
+        self.recovery = Some(self.next_sequence.wrapping_sub(1));
+    }
+
+    /// Grow the window, exponentially until the threshold then linearly
+    fn grow_window(&mut self, acked: u32) {
+        if self.congestion_window < self.slow_start_threshold {
+            self.congestion_window += acked;
+            return;
+        }
+        self.acked_in_window += acked;
+        if self.acked_in_window >= self.congestion_window {
+            self.acked_in_window -= self.congestion_window;
+            self.congestion_window += 1;
+        }
+    }
+
+    /// Smoothed round trip time and retransmission timeout
+    fn update_rtt(&mut self, rtt: Duration) {
+        match self.smoothed_rtt {
+            None => {
+                self.smoothed_rtt = Some(rtt);
+                self.rtt_variance = rtt / 2;
+            }
+            Some(smoothed) => {
+                let delta = smoothed.abs_diff(rtt);
+                self.rtt_variance = (self.rtt_variance * 3 + delta) / 4;
+                self.smoothed_rtt = Some((smoothed * 7 + rtt) / 8);
+            }
+        }
+        let timeout = self.smoothed_rtt.unwrap_or_default() + self.rtt_variance * 4;
+        self.retransmit_timeout = timeout.clamp(MIN_RETRANSMIT_TIMEOUT, MAX_RETRANSMIT_TIMEOUT);
+    }
+
+    /// Acknowledgements of the server
+    /// The datagrams before its ACK vector were all received
+    fn process_ack(&mut self, header: &FecHeader, states: &[bool], now: Instant) -> Vec<u32> {
+        let start = header
+            .source_ack
+            .wrapping_sub(states.len() as u32)
+            .wrapping_add(1);
+        let is_acked = |sequence: u32| {
+            if is_before(sequence, start) {
+                return true;
+            }
+            let index = sequence.wrapping_sub(start) as usize;
+            index < states.len() && states[index]
+        };
+
+        let acked: Vec<u32> = self
+            .in_flight
+            .keys()
+            .copied()
+            .filter(|sequence| is_acked(*sequence))
+            .collect();
+        let mut rtt = None;
+        for sequence in &acked {
+            if let Some(sent) = self.in_flight.remove(sequence) {
+                // Karn's algorithm, the retransmitted ones are ambiguous
+                if sent.retransmits == 0 {
+                    rtt = Some(now.saturating_duration_since(sent.sent));
+                }
+            }
+        }
+        if let Some(rtt) = rtt {
+            self.update_rtt(rtt);
+        }
+        if let Some(recovery) = self.recovery {
+            if acked.iter().any(|sequence| !is_before(*sequence, recovery)) {
+                self.recovery = None;
+            }
+        }
+        self.grow_window(acked.len() as u32);
+
+        // Datagrams left missing by the acknowledgement of later ones
+        let mut lost = Vec::new();
+        if let Some(last) = acked.last() {
+            for (sequence, sent) in self.in_flight.iter_mut() {
+                if is_before(*sequence, *last) {
+                    sent.missing += 1;
+                    if sent.missing == DUPLICATE_ACK_THRESHOLD {
+                        lost.push(*sequence);
+                    }
+                }
+            }
+        }
+        if !lost.is_empty() {
+            self.reduce_window();
+        }
+        lost
+    }
+
+    /// Mark a datagram of the server in the ACK vector
+    /// False when it is a duplicate or out of the window
+    fn mark_received(&mut self, sequence: u32) -> bool {
+        self.ack_pending = true;
+        if is_before(sequence, self.receive_base) {
+            return false;
+        }
+        // The server counts the datagrams before the ACK vector as
+        // received, so the delivered ones may be forgotten without
+        // waiting for an ACK of ACKs, and the lost ones in the lossy mode
+        while sequence.wrapping_sub(self.receive_base) as usize >= ACK_VECTOR_MAX_SIZE {
+            match self.received.pop_front() {
+                Some(true) => (),
+                Some(false) if self.mode == UdpMode::Lossy => (),
+                None if self.mode == UdpMode::Lossy => {
+                    self.receive_base = sequence.wrapping_sub(ACK_VECTOR_MAX_SIZE as u32 - 1);
+                    break;
+                }
+                Some(state) => {
+                    self.received.push_front(state);
+                    return false;
+                }
+                None => return false,
+            }
+            self.receive_base = self.receive_base.wrapping_add(1);
+        }
+        let index = sequence.wrapping_sub(self.receive_base) as usize;
+        if self.received.len() <= index {
+            self.received.resize(index + 1, false);
+        }
+        if self.received[index] {
+            return false;
+        }
+        self.received[index] = true;
+        true
+    }
+
+    /// Rebuild the source datagrams missing alone from their range
+    fn recover(&mut self) {
+        let mut recovered = Vec::new();
+        for fec in self.fec_payloads.values() {
+            let range: Vec<u32> = (0..fec.range as u32)
+                .map(|index| fec.source_start.wrapping_add(index))
+                .collect();
+            let missing: Vec<u32> = range
+                .iter()
+                .copied()
+                .filter(|source| !self.sources.contains_key(source))
+                .collect();
+            if missing.len() != 1 || is_before(missing[0], self.next_delivery) {
+                continue;
+            }
+            let others: Vec<&[u8]> = range
+                .iter()
+                .filter_map(|source| self.sources.get(source))
+                .map(Vec::as_slice)
+                .collect();
+            if let Some(data) = fec_recover(&fec.data, &others) {
+                recovered.push((missing[0], data));
+            }
+        }
+        self.sources.extend(recovered);
+    }
+
+    /// Deliver the data of the server in order
+    /// In the lossy mode a missing datagram is given up
+    /// once too many later ones are waiting
+    fn deliver<T>(&mut self, callback: &mut T)
+    where
+        T: FnMut(UdpEvent),
+    {
+        loop {
+            loop {
+                let data = match self.mode {
+                    UdpMode::Reliable => self.sources.remove(&self.next_delivery),
+                    UdpMode::Lossy => self.sources.get(&self.next_delivery).cloned(),
+                };
+                match data {
+                    Some(data) => callback(UdpEvent::Data(data)),
+                    None => break,
+                }
+                self.next_delivery = self.next_delivery.wrapping_add(1);
+            }
+            if self.mode == UdpMode::Reliable {
+                return;
+            }
+
+            let next_delivery = self.next_delivery;
+            let waiting = self
+                .sources
+                .keys()
+                .map(|source| source.wrapping_sub(next_delivery))
+                .filter(|distance| *distance < 0x8000_0000);
+            if waiting.clone().count() <= LOSSY_REORDER_LIMIT {
+                break;
+            }
+            let skipped = waiting.min().unwrap_or(0);
+            self.next_delivery = next_delivery.wrapping_add(skipped);
+            callback(UdpEvent::Skipped(skipped));
+        }
+
+        let next_delivery = self.next_delivery;
+        let oldest = next_delivery.wrapping_sub(RECEIVE_WINDOW_SIZE as u32);
+        self.sources.retain(|source, _| !is_before(*source, oldest));
+        self.fec_payloads.retain(|_, fec| {
+            is_before(
+                next_delivery,
+                fec.source_start.wrapping_add(fec.range as u32),
+            )
+        });
+    }
+
+    /// Data of the server, delivered in order
+    fn process_source<T>(&mut self, payload: SourcePayload, callback: &mut T)
+    where
+        T: FnMut(UdpEvent),
+    {
+        if !self.mark_received(payload.coded_sequence)
+            || is_before(payload.source_start, self.next_delivery)
+        {
+            return;
+        }
+        self.sources.insert(payload.source_start, payload.data);
+        self.recover();
+        self.deliver(callback);
+    }
+
+    /// Parity of the server, in the lossy mode
+    fn process_fec<T>(&mut self, fec: FecPayload, callback: &mut T)
+    where
+        T: FnMut(UdpEvent),
+    {
+        let end = fec.source_start.wrapping_add(fec.range as u32);
+        if !self.mark_received(fec.coded_sequence) || !is_before(self.next_delivery, end) {
+            return;
+        }
+        self.fec_payloads.insert(fec.source_start, fec);
+        self.recover();
+        self.deliver(callback);
+    }
+
+    /// Forget the states the server has seen in an acknowledgement
+    fn process_ack_of_acks(&mut self, sequence: u32) {
+        while self.received.front() == Some(&true) && !is_before(sequence, self.receive_base) {
+            self.received.pop_front();
+            self.receive_base = self.receive_base.wrapping_add(1);
+        }
+    }
+
+    /// Process a datagram of the server
+    /// and build the datagrams to send back
+    pub fn process<T>(
+        &mut self,
+        buffer: &mut BytesMut,
+        now: Instant,
+        callback: &mut T,
+    ) -> Result<Vec<UdpDatagram>>
+    where
+        T: FnMut(UdpEvent),
+    {
+        let datagram = read_udp_datagram(buffer)?;
+        let has = |flag: UdpFlag| datagram.header.flags & flag as u16 != 0;
+
+        match self.state {
+            State::SynSent if has(UdpFlag::RdpudpFlagSyn) && has(UdpFlag::RdpudpFlagAck) => {
+                let syn = datagram.syn.unwrap_or_default();
+                if datagram.header.source_ack != self.initial_sequence_number {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "RDPEUDP: SYN+ACK does not acknowledge the SYN",
+                    ));
+                }
+                self.mtu = RDPUDP_MTU_SIZE
+                    .min(syn.upstream_mtu)
+                    .min(syn.downstream_mtu);
+                if (self.mtu as usize) <= DATA_OVERHEAD {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("RDPEUDP: MTU {} is too small", self.mtu),
+                    ));
+                }
+                if !has(UdpFlag::RdpudpFlagSynlossy) {
+                    self.mode = UdpMode::Reliable;
+                }
+                self.peer_window = datagram.header.receive_window_size;
+                self.receive_base = syn.initial_sequence_number.wrapping_add(1);
+                self.next_delivery = self.receive_base;
+                self.state = State::Established;
+                self.syn_sent = None;
+                callback(UdpEvent::Connected { mtu: self.mtu });
+                Ok(vec![self.acknowledgement()])
+            }
+            State::Established => {
+                if has(UdpFlag::RdpudpFlagFin) {
+                    self.state = State::Closed;
+                    callback(UdpEvent::Closed);
+                    return Ok(Vec::new());
+                }
+                // Retransmission of the SYN+ACK, the ACK was lost
+                if has(UdpFlag::RdpudpFlagSyn) {
+                    return Ok(vec![self.acknowledgement()]);
+                }
+                self.peer_window = datagram.header.receive_window_size;
+
+                let mut datagrams = Vec::new();
+                if let Some(states) = &datagram.ack_vector {
+                    let lost = self.process_ack(&datagram.header, states, now);
+                    self.ack_of_acks = Some(datagram.header.source_ack);
+                    for sequence in lost {
+                        match self.mode {
+                            UdpMode::Reliable => datagrams.push(self.retransmit(sequence, now)?),
+                            // The FEC datagrams stand for the retransmissions
+                            UdpMode::Lossy => {
+                                self.in_flight.remove(&sequence);
+                            }
+                        }
+                    }
+                }
+                if has(UdpFlag::RdpudpFlagCn) {
+                    self.reduce_window();
+                    self.congestion_window_reduced = true;
+                }
+                if let Some(sequence) = datagram.ack_of_acks {
+                    self.process_ack_of_acks(sequence);
+                }
+                if let Some(payload) = datagram.payload {
+                    self.process_source(payload, callback);
+                }
+                if let Some(fec) = datagram.fec {
+                    self.process_fec(fec, callback);
+                }
+                datagrams.extend(self.flush(now));
+                Ok(datagrams)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Time of the next retransmission
+    pub fn next_timeout(&self) -> Option<Instant> {
+        match self.state {
+            State::SynSent => self
+                .syn_sent
+                .map(|(sent, _)| sent + self.retransmit_timeout),
+            State::Established => self
+                .in_flight
+                .values()
+                .map(|sent| sent.sent + self.retransmit_timeout)
+                .min(),
+            State::Closed => None,
+        }
+    }
+
+    /// Resend the datagrams which timed out
+    /// The window falls back to one datagram and the timeout doubles
+    ///
+    /// In the lossy mode the datagrams which timed out are forgotten
+    pub fn poll(&mut self, now: Instant) -> Result<Vec<UdpDatagram>> {
+        match self.next_timeout() {
+            Some(timeout) if timeout <= now => (),
+            _ => return Ok(Vec::new()),
+        }
+        let timeout = self.retransmit_timeout;
+        self.retransmit_timeout = (timeout * 2).min(MAX_RETRANSMIT_TIMEOUT);
+
+        if let Some((_, retransmits)) = self.syn_sent {
+            if retransmits >= RETRANSMIT_LIMIT {
+                self.state = State::Closed;
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "RDPEUDP: no answer to the SYN datagram",
+                ));
+            }
+            self.syn_sent = Some((now, retransmits + 1));
+            return Ok(vec![self.syn()]);
+        }
+
+        self.slow_start_threshold = (self.in_flight.len() as u32 / 2).max(MIN_CONGESTION_WINDOW);
+        self.congestion_window = 1;
+        self.acked_in_window = 0;
+        if self.mode == UdpMode::Lossy {
+            self.in_flight.retain(|_, sent| sent.sent + timeout > now);
+            return Ok(self.flush(now));
+        }
+        let oldest = *self.in_flight.keys().next().unwrap_or(&self.next_sequence);
+        Ok(vec![self.retransmit(oldest, now)?])
+    }
+
+    /// Smoothed round trip time, None before the first acknowledgement
+    pub fn get_rtt(&self) -> Option<Duration> {
+        self.smoothed_rtt
+    }
+
+    /// Datagrams allowed in flight
+    pub fn get_congestion_window(&self) -> u32 {
+        self.congestion_window
+    }
+
+    /// Profile of the connection, once negotiated with the server
+    pub fn get_mode(&self) -> UdpMode {
+        self.mode
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state == State::Established
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn buffer(datagram: UdpDatagram) -> BytesMut {
+        BytesMut::from(&datagram.to_bytes()[..])
+    }
+
+    /// SYN+ACK of a server with 500 as initial sequence number
+    fn syn_ack() -> UdpDatagram {
+        UdpDatagram {
+            header: FecHeader {
+                source_ack: 1000,
+                receive_window_size: 64,
+                flags: UdpFlag::RdpudpFlagAck as u16,
+            },
+            syn: Some(SynData {
+                initial_sequence_number: 500,
+                upstream_mtu: 1200,
+                downstream_mtu: 1232,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn connected(now: Instant) -> UdpClient {
+        let mut client = UdpClient::new(1000, None, UdpMode::Reliable);
+        client.connect(now);
+        client
+            .process(&mut buffer(syn_ack()), now, &mut |_| {})
+            .unwrap();
+        client
+    }
+
+    /// Acknowledgement of the server, from the first data datagram
+    fn ack(source_ack: u32, states: Vec<bool>) -> UdpDatagram {
+        UdpDatagram {
+            header: FecHeader {
+                source_ack,
+                receive_window_size: 64,
+                flags: 0,
+            },
+            ack_vector: Some(states),
+            ..Default::default()
+        }
+    }
+
+    /// The handshake negotiates the MTU and acknowledges the SYN+ACK
+    #[test]
+    fn test_udp_handshake() {
+        let now = Instant::now();
+        let mut client = UdpClient::new(1000, Some([1; 16]), UdpMode::Reliable);
+        let syn = client.connect(now);
+        assert_eq!(syn.to_bytes().len(), RDPUDP_MTU_SIZE as usize);
+        assert_eq!(
+            client.next_timeout(),
+            Some(now + INITIAL_RETRANSMIT_TIMEOUT)
+        );
+        assert_eq!(
+            client.poll(now + INITIAL_RETRANSMIT_TIMEOUT).unwrap(),
+            [syn]
+        );
+
+        let mut events = Vec::new();
+        let responses = client
+            .process(&mut buffer(syn_ack()), now, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!(events, [UdpEvent::Connected { mtu: 1200 }]);
+        assert_eq!(responses[0].header.source_ack, 500);
+        assert_eq!(responses[0].ack_vector, Some(vec![]));
+        assert!(client.is_connected());
+    }
+
+    /// Data of the server is delivered in order and acknowledged
+    #[test]
+    fn test_udp_receive() {
+        let now = Instant::now();
+        let mut client = connected(now);
+        let data = |sequence: u32| UdpDatagram {
+            payload: Some(SourcePayload {
+                coded_sequence: sequence,
+                source_start: sequence,
+                data: vec![sequence as u8],
+            }),
+            ..ack(1000, vec![])
+        };
+
+        let mut events = Vec::new();
+        let responses = client
+            .process(&mut buffer(data(502)), now, &mut |event| events.push(event))
+            .unwrap();
+        assert!(events.is_empty());
+        assert_eq!(responses[0].header.source_ack, 502);
+        assert_eq!(responses[0].ack_vector, Some(vec![false, true]));
+
+        let responses = client
+            .process(&mut buffer(data(501)), now, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!(
+            events,
+            [UdpEvent::Data(vec![245]), UdpEvent::Data(vec![246])]
+        );
+        assert_eq!(responses[0].ack_vector, Some(vec![true, true]));
+
+        // The server saw the acknowledgement, the states are forgotten
+        let mut ack_of_acks = ack(1000, vec![]);
+        ack_of_acks.ack_of_acks = Some(502);
+        client
+            .process(&mut buffer(ack_of_acks), now, &mut |_| {})
+            .unwrap();
+        let datagram = client.close();
+        assert_eq!(datagram.header.source_ack, 502);
+        assert_eq!(datagram.ack_vector, Some(vec![]));
+    }
+
+    /// A datagram left missing by three acknowledgements is resent
+    /// and the congestion window is halved
+    #[test]
+    fn test_udp_loss() {
+        let now = Instant::now();
+        let mut client = connected(now);
+        let datagrams = client.send(&[0; 2000], now).unwrap();
+        assert_eq!(datagrams.len(), 2);
+        client.send(&[1; 2000], now).unwrap();
+        let window = client.get_congestion_window();
+
+        let later = now + Duration::from_millis(40);
+        let mut responses = Vec::new();
+        for count in 2..5 {
+            let mut states = vec![false];
+            states.extend(vec![true; count - 1]);
+            responses = client
+                .process(
+                    &mut buffer(ack(1000 + count as u32, states)),
+                    later,
+                    &mut |_| {},
+                )
+                .unwrap();
+        }
+        let resent = responses[0].payload.as_ref().unwrap();
+        assert_eq!(resent.coded_sequence, 1001);
+        assert_eq!(resent.data, vec![0; client.max_payload()]);
+        assert_eq!(client.get_congestion_window(), (window + 3) / 2);
+        assert_eq!(client.get_rtt(), Some(Duration::from_millis(40)));
+    }
+
+    /// Datagrams are resent on timeout, then the connection is dropped
+    #[test]
+    fn test_udp_timeout() {
+        let now = Instant::now();
+        let mut client = connected(now);
+        client.send(b"data", now).unwrap();
+
+        let mut time = now;
+        for _ in 0..RETRANSMIT_LIMIT {
+            time = client.next_timeout().unwrap();
+            let datagrams = client.poll(time).unwrap();
+            assert_eq!(datagrams[0].payload.as_ref().unwrap().data, b"data");
+            assert_eq!(client.get_congestion_window(), 1);
+        }
+        assert!(client.next_timeout().unwrap() > time);
+        let error = client.poll(client.next_timeout().unwrap()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionAborted);
+    }
+
+    /// In the lossy mode a lost datagram is rebuilt from the FEC datagram
+    /// of its range, and each range sent is followed by its parity
+    #[test]
+    fn test_udp_lossy() {
+        let now = Instant::now();
+        let mut client = UdpClient::new(1000, None, UdpMode::Lossy);
+        assert_eq!(
+            client.connect(now).header.flags & UdpFlag::RdpudpFlagSynlossy as u16,
+            UdpFlag::RdpudpFlagSynlossy as u16
+        );
+        let mut syn_ack = syn_ack();
+        syn_ack.header.flags |= UdpFlag::RdpudpFlagSynlossy as u16;
+        client
+            .process(&mut buffer(syn_ack), now, &mut |_| {})
+            .unwrap();
+        assert_eq!(client.get_mode(), UdpMode::Lossy);
+
+        let sources: Vec<Vec<u8>> = (501..505).map(|source| vec![source as u8; 3]).collect();
+        let data = |source: u32| UdpDatagram {
+            payload: Some(SourcePayload {
+                coded_sequence: source,
+                source_start: source,
+                data: sources[(source - 501) as usize].clone(),
+            }),
+            ..ack(1000, vec![])
+        };
+        let mut events = Vec::new();
+        for source in [501, 503, 504] {
+            client
+                .process(&mut buffer(data(source)), now, &mut |event| {
+                    events.push(event)
+                })
+                .unwrap();
+        }
+        assert_eq!(events, [UdpEvent::Data(sources[0].clone())]);
+
+        let parity: Vec<&[u8]> = sources.iter().map(Vec::as_slice).collect();
+        let fec = UdpDatagram {
+            fec: Some(FecPayload {
+                coded_sequence: 505,
+                source_start: 501,
+                range: 4,
+                fec_index: 0,
+                data: fec_encode(&parity),
+            }),
+            ..ack(1000, vec![])
+        };
+        client
+            .process(&mut buffer(fec), now, &mut |event| events.push(event))
+            .unwrap();
+        let expected: Vec<UdpEvent> = sources.iter().cloned().map(UdpEvent::Data).collect();
+        assert_eq!(events, expected);
+
+        let datagrams = client.send(b"data", now).unwrap();
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(datagrams[0].payload.as_ref().unwrap().source_start, 1001);
+        let fec = datagrams[1].fec.as_ref().unwrap();
+        assert_eq!(
+            (fec.coded_sequence, fec.source_start, fec.range),
+            (1002, 1001, 1)
+        );
+    }
+}
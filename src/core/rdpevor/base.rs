@@ -0,0 +1,520 @@
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the dynamic virtual channel of the presentation requests
+/// MS-RDPEVOR 2.1 Transport
+pub const VIDEO_CONTROL_CHANNEL_NAME: &str = "Microsoft::Windows::RDS::Video::Control::v08.01";
+/// Name of the dynamic virtual channel of the video samples
+pub const VIDEO_DATA_CHANNEL_NAME: &str = "Microsoft::Windows::RDS::Video::Data::v08.01";
+
+/// Size of the TSMM_VIDEO_PACKET_HEADER
+const HEADER_SIZE: usize = 8;
+
+/// Size of the framerate override of a client notification
+/// MS-RDPEVOR 2.2.1.4.1 TSMM_CLIENT_NOTIFICATION_FRAMERATE_OVERRIDE
+const FRAMERATE_OVERRIDE_SIZE: u32 = 16;
+
+/// Only version of the presentation and data PDUs
+pub const RDP_VIDEO_VERSION: u8 = 0x01;
+
+/// Video subtype of the H.264 streams, MFVideoFormat_H264
+/// {34363248-0000-0010-8000-00AA00389B71} in the GUID byte order
+pub const MFVIDEOFORMAT_H264: [u8; 16] = [
+    0x48, 0x32, 0x36, 0x34, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// Type of a video PDU
+/// MS-RDPEVOR 2.2.1.1 TSMM_VIDEO_PACKET_HEADER
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum VideoPacketType {
+    TsmmPackettypePresentationRequest = 0x00000001,
+    TsmmPackettypePresentationResponse = 0x00000002,
+    TsmmPackettypeClientNotification = 0x00000003,
+    TsmmPackettypeVideoData = 0x00000004,
+}
+
+/// Command of a presentation request
+/// MS-RDPEVOR 2.2.1.2 TSMM_PRESENTATION_REQUEST
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum PresentationCommand {
+    TsmmStartPresentation = 0x01,
+    TsmmStopPresentation = 0x02,
+}
+
+/// Content of a video data PDU
+/// MS-RDPEVOR 2.2.1.6 TSMM_VIDEO_DATA
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VideoDataFlag {
+    TsmmVideoDataFlagHasTimestamps = 0x01,
+    TsmmVideoDataFlagKeyframe = 0x02,
+    TsmmVideoDataFlagNewFramerate = 0x04,
+}
+
+/// Type of a client notification
+/// MS-RDPEVOR 2.2.1.4 TSMM_CLIENT_NOTIFICATION
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum NotificationType {
+    TsmmClientNotificationTypeNetworkError = 0x01,
+    TsmmClientNotificationTypeFramerateOverride = 0x02,
+}
+
+/// Frame rate asked by the client
+/// MS-RDPEVOR 2.2.1.4.1 TSMM_CLIENT_NOTIFICATION_FRAMERATE_OVERRIDE
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum FramerateOverrideFlag {
+    TsmmFramerateOverrideRevertToDefault = 0x00000001,
+    TsmmFramerateOverrideOverride = 0x00000002,
+}
+
+/// Start or stop of a video region
+/// Times are in units of 100 nanoseconds
+/// MS-RDPEVOR 2.2.1.2 TSMM_PRESENTATION_REQUEST
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PresentationRequest {
+    pub presentation_id: u8,
+    pub version: u8,
+    /// PresentationCommand of the request
+    pub command: u8,
+    pub frame_rate: u8,
+    pub average_bitrate_kbps: u16,
+    pub source_width: u32,
+    pub source_height: u32,
+    /// Size of the region on the screen
+    pub scaled_width: u32,
+    pub scaled_height: u32,
+    /// Timestamp of the start of the presentation
+    pub timestamp_offset: u64,
+    /// Geometry of the region, tracked by the geometry channel
+    pub geometry_mapping_id: u64,
+    pub video_subtype: [u8; 16],
+    /// Sequence and picture parameter sets of the H.264 stream
+    pub extra_data: Vec<u8>,
+}
+
+impl PresentationRequest {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.put_u8(self.presentation_id);
+        buffer.put_u8(self.version);
+        buffer.put_u8(self.command);
+        buffer.put_u8(self.frame_rate);
+        buffer.put_u16_le(self.average_bitrate_kbps);
+        buffer.put_u16_le(0);
+        buffer.put_u32_le(self.source_width);
+        buffer.put_u32_le(self.source_height);
+        buffer.put_u32_le(self.scaled_width);
+        buffer.put_u32_le(self.scaled_height);
+        buffer.put_u64_le(self.timestamp_offset);
+        buffer.put_u64_le(self.geometry_mapping_id);
+        buffer.put_slice(&self.video_subtype);
+        buffer.put_u32_le(self.extra_data.len() as u32);
+        buffer.put_slice(&self.extra_data);
+        buffer.put_u8(0);
+    }
+
+    fn read(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 60, "RDPEVOR: presentation request")?;
+        let mut request = PresentationRequest {
+            presentation_id: buffer.get_u8(),
+            version: buffer.get_u8(),
+            command: buffer.get_u8(),
+            frame_rate: buffer.get_u8(),
+            average_bitrate_kbps: buffer.get_u16_le(),
+            ..Default::default()
+        };
+        let _reserved = buffer.get_u16_le();
+        request.source_width = buffer.get_u32_le();
+        request.source_height = buffer.get_u32_le();
+        request.scaled_width = buffer.get_u32_le();
+        request.scaled_height = buffer.get_u32_le();
+        request.timestamp_offset = buffer.get_u64_le();
+        request.geometry_mapping_id = buffer.get_u64_le();
+        buffer.copy_to_slice(&mut request.video_subtype);
+        let extra_size = buffer.get_u32_le() as usize;
+        check_remaining(buffer, extra_size, "RDPEVOR: presentation extra data")?;
+        request.extra_data = buffer.split_to(extra_size).to_vec();
+        Ok(request)
+    }
+}
+
+/// Part of a sample of a presentation
+/// Times are in units of 100 nanoseconds
+/// MS-RDPEVOR 2.2.1.6 TSMM_VIDEO_DATA
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VideoData {
+    pub presentation_id: u8,
+    pub version: u8,
+    /// VideoDataFlag of the sample
+    pub flags: u8,
+    pub timestamp: u64,
+    pub duration: u64,
+    /// Index of the packet in the sample, from 1
+    pub current_packet_index: u16,
+    pub packets_in_sample: u16,
+    pub sample_number: u32,
+    pub sample: Vec<u8>,
+}
+
+impl VideoData {
+    fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.put_u8(self.presentation_id);
+        buffer.put_u8(self.version);
+        buffer.put_u8(self.flags);
+        buffer.put_u8(0);
+        buffer.put_u64_le(self.timestamp);
+        buffer.put_u64_le(self.duration);
+        buffer.put_u16_le(self.current_packet_index);
+        buffer.put_u16_le(self.packets_in_sample);
+        buffer.put_u32_le(self.sample_number);
+        buffer.put_u32_le(self.sample.len() as u32);
+        buffer.put_slice(&self.sample);
+        buffer.put_u8(0);
+    }
+
+    fn read(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 32, "RDPEVOR: video data")?;
+        let mut data = VideoData {
+            presentation_id: buffer.get_u8(),
+            version: buffer.get_u8(),
+            flags: buffer.get_u8(),
+            ..Default::default()
+        };
+        let _reserved = buffer.get_u8();
+        data.timestamp = buffer.get_u64_le();
+        data.duration = buffer.get_u64_le();
+        data.current_packet_index = buffer.get_u16_le();
+        data.packets_in_sample = buffer.get_u16_le();
+        data.sample_number = buffer.get_u32_le();
+        let sample_size = buffer.get_u32_le() as usize;
+        check_remaining(buffer, sample_size, "RDPEVOR: video sample")?;
+        data.sample = buffer.split_to(sample_size).to_vec();
+        Ok(data)
+    }
+}
+
+/// Notification of the client about a presentation
+/// MS-RDPEVOR 2.2.1.4 TSMM_CLIENT_NOTIFICATION
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientNotification {
+    /// Samples were lost, the server sends a key frame
+    NetworkError,
+    /// None reverts to the frame rate chosen by the server
+    FramerateOverride(Option<u32>),
+}
+
+/// PDU of the video channels, sent by both sides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VideoPdu {
+    PresentationRequest(PresentationRequest),
+    /// MS-RDPEVOR 2.2.1.3 TSMM_PRESENTATION_RESPONSE
+    PresentationResponse {
+        presentation_id: u8,
+    },
+    ClientNotification {
+        presentation_id: u8,
+        notification: ClientNotification,
+    },
+    VideoData(VideoData),
+}
+
+impl VideoPdu {
+    fn packet_type(&self) -> VideoPacketType {
+        match self {
+            VideoPdu::PresentationRequest(_) => VideoPacketType::TsmmPackettypePresentationRequest,
+            VideoPdu::PresentationResponse { .. } => {
+                VideoPacketType::TsmmPackettypePresentationResponse
+            }
+            VideoPdu::ClientNotification { .. } => {
+                VideoPacketType::TsmmPackettypeClientNotification
+            }
+            VideoPdu::VideoData(_) => VideoPacketType::TsmmPackettypeVideoData,
+        }
+    }
+
+    /// Body of the PDU, without the header
+    fn body(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        match self {
+            VideoPdu::PresentationRequest(request) => request.write(&mut buffer),
+            VideoPdu::PresentationResponse { presentation_id } => {
+                buffer.put_u8(*presentation_id);
+                buffer.put_u8(0);
+                buffer.put_u16_le(0);
+            }
+            VideoPdu::ClientNotification {
+                presentation_id,
+                notification,
+            } => {
+                buffer.put_u8(*presentation_id);
+                match notification {
+                    ClientNotification::NetworkError => {
+                        buffer
+                            .put_u8(NotificationType::TsmmClientNotificationTypeNetworkError as u8);
+                        buffer.put_u16_le(0);
+                        buffer.put_u32_le(0);
+                    }
+                    ClientNotification::FramerateOverride(frame_rate) => {
+                        buffer.put_u8(
+                            NotificationType::TsmmClientNotificationTypeFramerateOverride as u8,
+                        );
+                        buffer.put_u16_le(0);
+                        buffer.put_u32_le(FRAMERATE_OVERRIDE_SIZE);
+                        buffer.put_u32_le(match frame_rate {
+                            Some(_) => FramerateOverrideFlag::TsmmFramerateOverrideOverride,
+                            None => FramerateOverrideFlag::TsmmFramerateOverrideRevertToDefault,
+                        } as u32);
+                        buffer.put_u32_le(frame_rate.unwrap_or(0));
+                        buffer.put_u32_le(0);
+                        buffer.put_u32_le(0);
+                    }
+                }
+            }
+            VideoPdu::VideoData(data) => data.write(&mut buffer),
+        }
+        buffer
+    }
+}
+
+fn read_client_notification(buffer: &mut BytesMut) -> Result<VideoPdu> {
+    check_remaining(buffer, 8, "RDPEVOR: client notification")?;
+    let presentation_id = buffer.get_u8();
+    let notification_type = buffer.get_u8();
+    let _reserved = buffer.get_u16_le();
+    let size = buffer.get_u32_le() as usize;
+    check_remaining(buffer, size, "RDPEVOR: client notification data")?;
+    let mut data = buffer.split_to(size);
+
+    let notification = match NotificationType::try_from(notification_type) {
+        Ok(NotificationType::TsmmClientNotificationTypeNetworkError) => {
+            ClientNotification::NetworkError
+        }
+        Ok(NotificationType::TsmmClientNotificationTypeFramerateOverride) => {
+            check_remaining(&data, 8, "RDPEVOR: framerate override")?;
+            let flags = data.get_u32_le();
+            let frame_rate = data.get_u32_le();
+            ClientNotification::FramerateOverride(match FramerateOverrideFlag::try_from(flags) {
+                Ok(FramerateOverrideFlag::TsmmFramerateOverrideOverride) => Some(frame_rate),
+                _ => None,
+            })
+        }
+        Err(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "RDPEVOR: unexpected notification type {}",
+                    notification_type
+                ),
+            ))
+        }
+    };
+    Ok(VideoPdu::ClientNotification {
+        presentation_id,
+        notification,
+    })
+}
+
+/// Read a video PDU with its header
+pub fn read_video_pdu(buffer: &mut BytesMut) -> Result<VideoPdu> {
+    check_remaining(buffer, HEADER_SIZE, "RDPEVOR: header")?;
+    let length = (buffer.get_u32_le() as usize).saturating_sub(HEADER_SIZE);
+    let packet_type = buffer.get_u32_le();
+    check_remaining(buffer, length, "RDPEVOR: PDU")?;
+    let mut body = buffer.split_to(length);
+    let body = &mut body;
+
+    Ok(match VideoPacketType::try_from(packet_type) {
+        Ok(VideoPacketType::TsmmPackettypePresentationRequest) => {
+            VideoPdu::PresentationRequest(PresentationRequest::read(body)?)
+        }
+        Ok(VideoPacketType::TsmmPackettypePresentationResponse) => {
+            check_remaining(body, 4, "RDPEVOR: presentation response")?;
+            VideoPdu::PresentationResponse {
+                presentation_id: body.get_u8(),
+            }
+        }
+        Ok(VideoPacketType::TsmmPackettypeClientNotification) => read_client_notification(body)?,
+        Ok(VideoPacketType::TsmmPackettypeVideoData) => VideoPdu::VideoData(VideoData::read(body)?),
+        Err(_) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("RDPEVOR: unexpected packet type {}", packet_type),
+            ))
+        }
+    })
+}
+
+#[async_trait]
+impl Message for VideoPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        let body = self.body();
+        writer
+            .write_u32_le((HEADER_SIZE + body.len()) as u32)
+            .await?;
+        writer.write_u32_le(self.packet_type() as u32).await?;
+        writer.write_all(&body).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        *self = read_video_pdu(&mut BytesMut::from(&data[..]))?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        HEADER_SIZE + self.body().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Check the encoding of a PDU both ways
+    async fn check_pdu(pdu: VideoPdu, data: &[u8]) {
+        assert_eq!(pdu.length(), data.len());
+        assert_eq!(to_vec(&pdu).await.unwrap(), data);
+        assert_eq!(read_video_pdu(&mut BytesMut::from(data)).unwrap(), pdu);
+    }
+
+    /// Start of a presentation and its response
+    /// MS-RDPEVOR 2.2.1.2 TSMM_PRESENTATION_REQUEST
+    /// MS-RDPEVOR 2.2.1.3 TSMM_PRESENTATION_RESPONSE
+    #[tokio::test]
+    async fn test_video_presentation_pdu() {
+        check_pdu(
+            VideoPdu::PresentationRequest(PresentationRequest {
+                presentation_id: 3,
+                version: RDP_VIDEO_VERSION,
+                command: PresentationCommand::TsmmStartPresentation as u8,
+                frame_rate: 30,
+                average_bitrate_kbps: 2000,
+                source_width: 1280,
+                source_height: 720,
+                scaled_width: 640,
+                scaled_height: 360,
+                timestamp_offset: 10_000_000,
+                geometry_mapping_id: 0x1234,
+                video_subtype: MFVIDEOFORMAT_H264,
+                extra_data: vec![0, 0, 0, 1, 0x67],
+            }),
+            &[
+                0x4A, 0x00, 0x00, 0x00, // cbSize
+                0x01, 0x00, 0x00, 0x00, // PacketType TSMM_PACKET_TYPE_PRESENTATION_REQUEST
+                0x03, // PresentationId
+                0x01, // Version
+                0x01, // Command TSMM_START_PRESENTATION
+                0x1E, // FrameRate
+                0xD0, 0x07, // AverageBitrateKbps
+                0x00, 0x00, // Reserved
+                0x00, 0x05, 0x00, 0x00, // SourceWidth
+                0xD0, 0x02, 0x00, 0x00, // SourceHeight
+                0x80, 0x02, 0x00, 0x00, // ScaledWidth
+                0x68, 0x01, 0x00, 0x00, // ScaledHeight
+                0x80, 0x96, 0x98, 0x00, 0x00, 0x00, 0x00, 0x00, // hnsTimestampOffset
+                0x34, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // GeometryMappingId
+                0x48, 0x32, 0x36, 0x34, 0x00, 0x00, 0x10, 0x00, // VideoSubtypeId
+                0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71, // MFVideoFormat_H264
+                0x05, 0x00, 0x00, 0x00, // cbExtra
+                0x00, 0x00, 0x00, 0x01, 0x67, // pExtraData
+                0x00, // Reserved2
+            ],
+        )
+        .await;
+        check_pdu(
+            VideoPdu::PresentationResponse { presentation_id: 3 },
+            &[
+                0x0C, 0x00, 0x00, 0x00, // cbSize
+                0x02, 0x00, 0x00, 0x00, // PacketType TSMM_PACKET_TYPE_PRESENTATION_RESPONSE
+                0x03, // PresentationId
+                0x00, 0x00, 0x00, // ResponseFlags, ResultFlags
+            ],
+        )
+        .await;
+    }
+
+    /// MS-RDPEVOR 2.2.1.4 TSMM_CLIENT_NOTIFICATION
+    #[tokio::test]
+    async fn test_video_client_notification_pdu() {
+        check_pdu(
+            VideoPdu::ClientNotification {
+                presentation_id: 3,
+                notification: ClientNotification::NetworkError,
+            },
+            &[
+                0x10, 0x00, 0x00, 0x00, // cbSize
+                0x03, 0x00, 0x00, 0x00, // PacketType TSMM_PACKET_TYPE_CLIENT_NOTIFICATION
+                0x03, // PresentationId
+                0x01, // NotificationType TSMM_CLIENT_NOTIFICATION_TYPE_NETWORK_ERROR
+                0x00, 0x00, // Reserved
+                0x00, 0x00, 0x00, 0x00, // cbData
+            ],
+        )
+        .await;
+        check_pdu(
+            VideoPdu::ClientNotification {
+                presentation_id: 3,
+                notification: ClientNotification::FramerateOverride(Some(15)),
+            },
+            &[
+                0x20, 0x00, 0x00, 0x00, // cbSize
+                0x03, 0x00, 0x00, 0x00, // PacketType TSMM_PACKET_TYPE_CLIENT_NOTIFICATION
+                0x03, // PresentationId
+                0x02, // NotificationType TSMM_CLIENT_NOTIFICATION_TYPE_FRAMERATE_OVERRIDE
+                0x00, 0x00, // Reserved
+                0x10, 0x00, 0x00, 0x00, // cbData
+                0x02, 0x00, 0x00, 0x00, // Flags TSMM_FRAMERATE_FLAG_OVERRIDE
+                0x0F, 0x00, 0x00, 0x00, // DesiredFrameRate
+                0x00, 0x00, 0x00, 0x00, // Reserved1
+                0x00, 0x00, 0x00, 0x00, // Reserved2
+            ],
+        )
+        .await;
+    }
+
+    /// First packet of a key frame
+    /// MS-RDPEVOR 2.2.1.6 TSMM_VIDEO_DATA
+    #[tokio::test]
+    async fn test_video_data_pdu() {
+        check_pdu(
+            VideoPdu::VideoData(VideoData {
+                presentation_id: 3,
+                version: RDP_VIDEO_VERSION,
+                flags: VideoDataFlag::TsmmVideoDataFlagKeyframe as u8,
+                timestamp: 10_333_333,
+                duration: 333_333,
+                current_packet_index: 1,
+                packets_in_sample: 2,
+                sample_number: 1,
+                sample: vec![0, 0, 0, 1, 0x65, 0x88],
+            }),
+            &[
+                0x2F, 0x00, 0x00, 0x00, // cbSize
+                0x04, 0x00, 0x00, 0x00, // PacketType TSMM_PACKET_TYPE_VIDEO_DATA
+                0x03, // PresentationId
+                0x01, // Version
+                0x02, // Flags TSMM_VIDEO_DATA_FLAG_KEYFRAME
+                0x00, // Reserved
+                0x95, 0xAC, 0x9D, 0x00, 0x00, 0x00, 0x00, 0x00, // hnsTimestamp
+                0x15, 0x16, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, // hnsDuration
+                0x01, 0x00, // CurrentPacketIndex
+                0x02, 0x00, // PacketsInSample
+                0x01, 0x00, 0x00, 0x00, // SampleNumber
+                0x06, 0x00, 0x00, 0x00, // cbSample
+                0x00, 0x00, 0x00, 0x01, 0x65, 0x88, // pSample
+                0x00, // Reserved2
+            ],
+        )
+        .await;
+    }
+}
@@ -0,0 +1,311 @@
+use crate::core::drdynvc::client::{to_messages, DynamicChannelHandler};
+use crate::core::event::{DynamicChannelEvent, RdpEvent};
+use crate::core::rdpevor::base::{
+    read_video_pdu, ClientNotification, PresentationCommand, PresentationRequest, VideoData,
+    VideoDataFlag, VideoPdu, MFVIDEOFORMAT_H264, VIDEO_CONTROL_CHANNEL_NAME,
+    VIDEO_DATA_CHANNEL_NAME,
+};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Result;
+use std::time::Duration;
+
+/// Convert a time in units of 100 nanoseconds
+fn from_hns(value: u64) -> Duration {
+    Duration::from_nanos(value.saturating_mul(100))
+}
+
+/// Whole H.264 access unit of a presentation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoSample {
+    pub presentation_id: u8,
+    pub sample_number: u32,
+    /// Presentation time, from the start of the presentation
+    pub timestamp: Duration,
+    pub duration: Duration,
+    /// The sample can be decoded without the previous ones
+    pub keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+/// Event of the video channels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VideoEvent {
+    /// A video region is shown, the request holds its size,
+    /// its geometry mapping id and the parameter sets of the stream
+    Started(PresentationRequest),
+    /// The samples of the region are ready to decode
+    Sample(VideoSample),
+    Stopped(u8),
+}
+
+/// Sample being reassembled
+struct PendingSample {
+    header: VideoData,
+    /// Index of the next expected packet
+    next_packet: u16,
+}
+
+/// Video region started by the server
+struct Presentation {
+    timestamp_offset: u64,
+    pending: Option<PendingSample>,
+}
+
+/// Client of the video optimized remoting channels
+///
+/// The server announces the video regions on the control channel,
+/// then splits each H.264 sample in packets on the data channel.
+/// Samples are forwarded once whole, with their time relative
+/// to the start of the presentation, so the embedder can decode
+/// and present them out of the graphics pipeline
+///
+/// # Example
+/// ```
+/// use rdp::core::rdpevor::client::VideoClient;
+/// let mut client = VideoClient::default();
+/// // Samples of unknown presentations are dropped
+/// let mut data = bytes::BytesMut::from(&[
+///     41, 0, 0, 0, 4, 0, 0, 0, 1, 1, 2, 0,
+///     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+///     1, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0,
+/// ][..]);
+/// client.process_data(&mut data, &mut |_| panic!()).unwrap();
+/// ```
+#[derive(Default)]
+pub struct VideoClient {
+    presentations: HashMap<u8, Presentation>,
+}
+
+impl VideoClient {
+    /// Process a message of the control channel
+    /// and build the PDUs expected by the server
+    pub fn process_control<T>(
+        &mut self,
+        buffer: &mut BytesMut,
+        callback: &mut T,
+    ) -> Result<Vec<VideoPdu>>
+    where
+        T: FnMut(VideoEvent),
+    {
+        let mut responses = Vec::new();
+        if let VideoPdu::PresentationRequest(request) = read_video_pdu(buffer)? {
+            let presentation_id = request.presentation_id;
+            match PresentationCommand::try_from(request.command) {
+                Ok(PresentationCommand::TsmmStartPresentation)
+                    if request.video_subtype == MFVIDEOFORMAT_H264 =>
+                {
+                    self.presentations.insert(
+                        presentation_id,
+                        Presentation {
+                            timestamp_offset: request.timestamp_offset,
+                            pending: None,
+                        },
+                    );
+                    responses.push(VideoPdu::PresentationResponse { presentation_id });
+                    callback(VideoEvent::Started(request));
+                }
+                Ok(PresentationCommand::TsmmStopPresentation) => {
+                    if self.presentations.remove(&presentation_id).is_some() {
+                        callback(VideoEvent::Stopped(presentation_id));
+                    }
+                }
+                // Without a response the server keeps
+                // the region in the graphics stream
+                _ => (),
+            }
+        }
+        Ok(responses)
+    }
+
+    /// Process a message of the data channel
+    /// Incomplete samples are dropped, ask for a key
+    /// frame with network_error when the decoder needs one
+    pub fn process_data<T>(&mut self, buffer: &mut BytesMut, callback: &mut T) -> Result<()>
+    where
+        T: FnMut(VideoEvent),
+    {
+        let data = match read_video_pdu(buffer)? {
+            VideoPdu::VideoData(data) => data,
+            _ => return Ok(()),
+        };
+        let presentation = match self.presentations.get_mut(&data.presentation_id) {
+            Some(presentation) => presentation,
+            None => return Ok(()),
+        };
+
+        let mut pending = match presentation.pending.take() {
+            Some(mut pending)
+                if pending.header.sample_number == data.sample_number
+                    && pending.next_packet == data.current_packet_index =>
+            {
+                pending.header.sample.extend_from_slice(&data.sample);
+                pending
+            }
+            _ if data.current_packet_index == 1 => PendingSample {
+                header: data,
+                next_packet: 1,
+            },
+            _ => return Ok(()),
+        };
+        pending.next_packet += 1;
+
+        if pending.next_packet <= pending.header.packets_in_sample {
+            presentation.pending = Some(pending);
+            return Ok(());
+        }
+        let header = pending.header;
+        callback(VideoEvent::Sample(VideoSample {
+            presentation_id: header.presentation_id,
+            sample_number: header.sample_number,
+            timestamp: from_hns(
+                header
+                    .timestamp
+                    .saturating_sub(presentation.timestamp_offset),
+            ),
+            duration: from_hns(header.duration),
+            keyframe: header.flags & VideoDataFlag::TsmmVideoDataFlagKeyframe as u8 != 0,
+            data: header.sample,
+        }));
+        Ok(())
+    }
+
+    /// Ask the server for a new key frame after a decoding error
+    pub fn network_error(&self, presentation_id: u8) -> VideoPdu {
+        VideoPdu::ClientNotification {
+            presentation_id,
+            notification: ClientNotification::NetworkError,
+        }
+    }
+
+    /// Limit the frame rate of a presentation
+    /// None reverts to the frame rate chosen by the server
+    pub fn framerate_override(&self, presentation_id: u8, frame_rate: Option<u32>) -> VideoPdu {
+        VideoPdu::ClientNotification {
+            presentation_id,
+            notification: ClientNotification::FramerateOverride(frame_rate),
+        }
+    }
+
+    /// Presentations currently started
+    pub fn get_presentations(&self) -> Vec<u8> {
+        self.presentations.keys().copied().collect()
+    }
+}
+
+/// Serves both the control and the data channel
+#[async_trait]
+impl DynamicChannelHandler for VideoClient {
+    fn channel_names(&self) -> Vec<&'static str> {
+        vec![VIDEO_CONTROL_CHANNEL_NAME, VIDEO_DATA_CHANNEL_NAME]
+    }
+
+    async fn process(
+        &mut self,
+        channel_name: &str,
+        _channel_id: u32,
+        buffer: &mut BytesMut,
+        callback: &mut (dyn FnMut(RdpEvent) + Send),
+    ) -> Result<Vec<Vec<u8>>> {
+        let mut events = |event| callback(RdpEvent::Dynamic(DynamicChannelEvent::Video(event)));
+        if channel_name == VIDEO_CONTROL_CHANNEL_NAME {
+            let responses = self.process_control(buffer, &mut events)?;
+            return to_messages(responses).await;
+        }
+        self.process_data(buffer, &mut events)?;
+        Ok(Vec::new())
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::rdpevor::base::RDP_VIDEO_VERSION;
+    use crate::model::data::to_vec;
+
+    async fn buffer(pdu: VideoPdu) -> BytesMut {
+        BytesMut::from(&to_vec(&pdu).await.unwrap()[..])
+    }
+
+    fn packet(index: u16, sample: &[u8]) -> VideoPdu {
+        VideoPdu::VideoData(VideoData {
+            presentation_id: 1,
+            version: RDP_VIDEO_VERSION,
+            flags: VideoDataFlag::TsmmVideoDataFlagKeyframe as u8,
+            timestamp: 10_500_000,
+            duration: 400_000,
+            current_packet_index: index,
+            packets_in_sample: 2,
+            sample_number: 7,
+            sample: sample.to_vec(),
+        })
+    }
+
+    /// Samples are reassembled from their packets,
+    /// and timed from the start of the presentation
+    #[tokio::test]
+    async fn test_video_client() {
+        let mut client = VideoClient::default();
+        let mut events = Vec::new();
+        let request = PresentationRequest {
+            presentation_id: 1,
+            version: RDP_VIDEO_VERSION,
+            command: PresentationCommand::TsmmStartPresentation as u8,
+            timestamp_offset: 10_000_000,
+            video_subtype: MFVIDEOFORMAT_H264,
+            ..Default::default()
+        };
+        let responses = client
+            .process_control(
+                &mut buffer(VideoPdu::PresentationRequest(request.clone())).await,
+                &mut |event| events.push(event),
+            )
+            .unwrap();
+        assert_eq!(
+            responses,
+            [VideoPdu::PresentationResponse { presentation_id: 1 }]
+        );
+
+        // The second packet alone is dropped
+        for pdu in [packet(2, &[3]), packet(1, &[1, 2]), packet(2, &[3])] {
+            client
+                .process_data(&mut buffer(pdu).await, &mut |event| events.push(event))
+                .unwrap();
+        }
+
+        let stop = PresentationRequest {
+            presentation_id: 1,
+            command: PresentationCommand::TsmmStopPresentation as u8,
+            ..Default::default()
+        };
+        client
+            .process_control(
+                &mut buffer(VideoPdu::PresentationRequest(stop)).await,
+                &mut |event| events.push(event),
+            )
+            .unwrap();
+        assert_eq!(
+            events,
+            [
+                VideoEvent::Started(request),
+                VideoEvent::Sample(VideoSample {
+                    presentation_id: 1,
+                    sample_number: 7,
+                    timestamp: Duration::from_millis(50),
+                    duration: Duration::from_millis(40),
+                    keyframe: true,
+                    data: vec![1, 2, 3],
+                }),
+                VideoEvent::Stopped(1),
+            ]
+        );
+        assert!(client.get_presentations().is_empty());
+    }
+}
@@ -0,0 +1,419 @@
+use crate::codec::audio::AudioFormat;
+use crate::model::data::{check_remaining, Message};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the static virtual channel of the audio output
+pub const RDPSND_CHANNEL_NAME: &str = "rdpsnd";
+
+/// Size of the SNDPROLOG header
+const HEADER_SIZE: usize = 4;
+
+/// Size of the formats PDU without its formats
+/// MS-RDPEA 2.2.2.1 Server Audio Formats and Version PDU (SERVER_AUDIO_VERSION_AND_FORMATS)
+const FORMATS_SIZE: usize = 20;
+
+/// Size of the wave info PDU body
+/// MS-RDPEA 2.2.3.3 Wave Info PDU (SNDWAV_INFO)
+const WAVE_INFO_SIZE: usize = 12;
+
+/// Size of the wave2 PDU body without its audio data
+/// MS-RDPEA 2.2.3.10 Wave2 PDU (SNDWAVE2)
+const WAVE2_SIZE: usize = 12;
+
+/// Version of the client, wave2 PDUs are sent from version 8
+pub const CHANNEL_VERSION_WIN_8: u16 = 0x0008;
+
+/// Quality modes are negotiated from version 6
+pub const CHANNEL_VERSION_WIN_VISTA: u16 = 0x0006;
+
+/// Type of an audio output PDU
+/// MS-RDPEA 2.2.1 RDPSND PDU Header (SNDPROLOG)
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum SndMessageType {
+    SndcClose = 0x01,
+    SndcWave = 0x02,
+    SndcSetvolume = 0x03,
+    SndcSetpitch = 0x04,
+    SndcWaveconfirm = 0x05,
+    SndcTraining = 0x06,
+    SndcFormats = 0x07,
+    SndcCryptkey = 0x08,
+    SndcWaveencrypt = 0x09,
+    SndcUdpwave = 0x0A,
+    SndcUdpwavelast = 0x0B,
+    SndcQualitymode = 0x0C,
+    SndcWave2 = 0x0D,
+}
+
+/// Capabilities of the client
+/// MS-RDPEA 2.2.2.2 Client Audio Formats and Version PDU (CLIENT_AUDIO_VERSION_AND_FORMATS)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SndCapability {
+    TssndcapsAlive = 0x00000001,
+    TssndcapsVolume = 0x00000002,
+    TssndcapsPitch = 0x00000004,
+}
+
+/// Quality asked to the server
+/// MS-RDPEA 2.2.2.3 Quality Mode PDU
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum QualityMode {
+    DynamicQuality = 0x0000,
+    MediumQuality = 0x0001,
+    HighQuality = 0x0002,
+}
+
+/// PDU of the audio output channel, sent by both sides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RdpsndPdu {
+    /// Formats of the server, or the ones of the client it supports
+    /// MS-RDPEA 2.2.2.1 Server Audio Formats and Version PDU (SERVER_AUDIO_VERSION_AND_FORMATS)
+    /// MS-RDPEA 2.2.2.2 Client Audio Formats and Version PDU (CLIENT_AUDIO_VERSION_AND_FORMATS)
+    Formats {
+        flags: u32,
+        volume: u32,
+        pitch: u32,
+        last_block_confirmed: u8,
+        version: u16,
+        formats: Vec<AudioFormat>,
+    },
+    /// MS-RDPEA 2.2.2.3 Quality Mode PDU
+    QualityMode(u16),
+    /// Sent back by the client with the same values
+    /// MS-RDPEA 2.2.3.1 Training PDU (SNDTRAINING)
+    /// MS-RDPEA 2.2.3.2 Training Confirm PDU (SNDTRAININGCONFIRM)
+    Training { timestamp: u16, pack_size: u16 },
+    /// First 4 bytes of the audio data, the rest is in the next wave PDU
+    /// MS-RDPEA 2.2.3.3 Wave Info PDU (SNDWAV_INFO)
+    WaveInfo {
+        timestamp: u16,
+        format_no: u16,
+        block_no: u8,
+        data: [u8; 4],
+        /// Length of the whole audio data
+        length: usize,
+    },
+    /// Audio data after its first 4 bytes, the PDU has no header
+    /// MS-RDPEA 2.2.3.4 Wave PDU (SNDWAV)
+    Wave(Vec<u8>),
+    /// MS-RDPEA Wave Confirm PDU (SNDWAV_CONFIRM)
+    WaveConfirm { timestamp: u16, block_no: u8 },
+    /// MS-RDPEA 2.2.3.10 Wave2 PDU (SNDWAVE2)
+    Wave2 {
+        timestamp: u16,
+        format_no: u16,
+        block_no: u8,
+        /// Capture time of the audio, in milliseconds
+        audio_timestamp: u32,
+        data: Vec<u8>,
+    },
+    /// MS-RDPEA Close PDU (SNDCLOSE)
+    Close,
+    /// Left channel in the low word, right channel in the high word
+    /// MS-RDPEA Volume PDU (SNDVOL)
+    Volume(u32),
+    /// MS-RDPEA Pitch PDU (SNDPITCH)
+    Pitch(u32),
+}
+
+impl RdpsndPdu {
+    fn message_type(&self) -> SndMessageType {
+        match self {
+            RdpsndPdu::Formats { .. } => SndMessageType::SndcFormats,
+            RdpsndPdu::QualityMode(_) => SndMessageType::SndcQualitymode,
+            RdpsndPdu::Training { .. } => SndMessageType::SndcTraining,
+            RdpsndPdu::WaveInfo { .. } | RdpsndPdu::Wave(_) => SndMessageType::SndcWave,
+            RdpsndPdu::WaveConfirm { .. } => SndMessageType::SndcWaveconfirm,
+            RdpsndPdu::Wave2 { .. } => SndMessageType::SndcWave2,
+            RdpsndPdu::Close => SndMessageType::SndcClose,
+            RdpsndPdu::Volume(_) => SndMessageType::SndcSetvolume,
+            RdpsndPdu::Pitch(_) => SndMessageType::SndcSetpitch,
+        }
+    }
+
+    /// Value of the BodySize field
+    fn body_size(&self) -> usize {
+        match self {
+            RdpsndPdu::Formats { formats, .. } => {
+                FORMATS_SIZE + formats.iter().map(AudioFormat::length).sum::<usize>()
+            }
+            RdpsndPdu::QualityMode(_) | RdpsndPdu::Training { .. } => 4,
+            RdpsndPdu::WaveInfo { length, .. } => length + 8,
+            RdpsndPdu::Wave(data) => data.len(),
+            RdpsndPdu::WaveConfirm { .. } => 4,
+            RdpsndPdu::Wave2 { data, .. } => WAVE2_SIZE + data.len(),
+            RdpsndPdu::Close => 0,
+            RdpsndPdu::Volume(_) | RdpsndPdu::Pitch(_) => 4,
+        }
+    }
+}
+
+/// Read an audio output PDU with a header
+/// Wave PDUs are read by the client after their wave info PDU
+pub fn read_rdpsnd_pdu(buffer: &mut BytesMut) -> Result<RdpsndPdu> {
+    check_remaining(buffer, HEADER_SIZE, "RDPSND: header")?;
+    let message_type = buffer.get_u8();
+    let _pad = buffer.get_u8();
+    let body_size = buffer.get_u16_le() as usize;
+    // The wave info body size counts the audio data of the wave PDU
+    let length = match SndMessageType::try_from(message_type) {
+        Ok(SndMessageType::SndcWave) => WAVE_INFO_SIZE,
+        _ => body_size,
+    };
+    check_remaining(buffer, length, "RDPSND: PDU")?;
+    let mut body = buffer.split_to(length);
+    let body = &mut body;
+
+    Ok(match SndMessageType::try_from(message_type) {
+        Ok(SndMessageType::SndcFormats) => {
+            check_remaining(body, FORMATS_SIZE, "RDPSND: formats")?;
+            let flags = body.get_u32_le();
+            let volume = body.get_u32_le();
+            let pitch = body.get_u32_le();
+            let _dgram_port = body.get_u16_le();
+            let count = body.get_u16_le();
+            let last_block_confirmed = body.get_u8();
+            let version = body.get_u16_le();
+            let _pad = body.get_u8();
+            let mut formats = Vec::new();
+            for _ in 0..count {
+                formats.push(AudioFormat::read(body)?);
+            }
+            RdpsndPdu::Formats {
+                flags,
+                volume,
+                pitch,
+                last_block_confirmed,
+                version,
+                formats,
+            }
+        }
+        Ok(SndMessageType::SndcQualitymode) => {
+            check_remaining(body, 4, "RDPSND: quality mode")?;
+            RdpsndPdu::QualityMode(body.get_u16_le())
+        }
+        Ok(SndMessageType::SndcTraining) => {
+            check_remaining(body, 4, "RDPSND: training")?;
+            RdpsndPdu::Training {
+                timestamp: body.get_u16_le(),
+                pack_size: body.get_u16_le(),
+            }
+        }
+        Ok(SndMessageType::SndcWave) => {
+            let timestamp = body.get_u16_le();
+            let format_no = body.get_u16_le();
+            let block_no = body.get_u8();
+            body.advance(3);
+            let mut data = [0; 4];
+            body.copy_to_slice(&mut data);
+            RdpsndPdu::WaveInfo {
+                timestamp,
+                format_no,
+                block_no,
+                data,
+                length: body_size.saturating_sub(8),
+            }
+        }
+        Ok(SndMessageType::SndcWaveconfirm) => {
+            check_remaining(body, 4, "RDPSND: wave confirm")?;
+            let timestamp = body.get_u16_le();
+            RdpsndPdu::WaveConfirm {
+                timestamp,
+                block_no: body.get_u8(),
+            }
+        }
+        Ok(SndMessageType::SndcWave2) => {
+            check_remaining(body, WAVE2_SIZE, "RDPSND: wave2")?;
+            let timestamp = body.get_u16_le();
+            let format_no = body.get_u16_le();
+            let block_no = body.get_u8();
+            body.advance(3);
+            RdpsndPdu::Wave2 {
+                timestamp,
+                format_no,
+                block_no,
+                audio_timestamp: body.get_u32_le(),
+                data: body.to_vec(),
+            }
+        }
+        Ok(SndMessageType::SndcClose) => RdpsndPdu::Close,
+        Ok(SndMessageType::SndcSetvolume) => {
+            check_remaining(body, 4, "RDPSND: volume")?;
+            RdpsndPdu::Volume(body.get_u32_le())
+        }
+        Ok(SndMessageType::SndcSetpitch) => {
+            check_remaining(body, 4, "RDPSND: pitch")?;
+            RdpsndPdu::Pitch(body.get_u32_le())
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("RDPSND: unexpected message type {}", message_type),
+            ))
+        }
+    })
+}
+
+#[async_trait]
+impl Message for RdpsndPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        if let RdpsndPdu::Wave(data) = self {
+            return writer.write_all(data).await;
+        }
+        writer.write_u8(self.message_type() as u8).await?;
+        writer.write_u8(0).await?;
+        writer.write_u16_le(self.body_size() as u16).await?;
+        match self {
+            RdpsndPdu::Formats {
+                flags,
+                volume,
+                pitch,
+                last_block_confirmed,
+                version,
+                formats,
+            } => {
+                writer.write_u32_le(*flags).await?;
+                writer.write_u32_le(*volume).await?;
+                writer.write_u32_le(*pitch).await?;
+                writer.write_u16_le(0).await?;
+                writer.write_u16_le(formats.len() as u16).await?;
+                writer.write_u8(*last_block_confirmed).await?;
+                writer.write_u16_le(*version).await?;
+                writer.write_u8(0).await?;
+                let mut data = Vec::new();
+                for format in formats {
+                    format.write(&mut data);
+                }
+                writer.write_all(&data).await?;
+            }
+            RdpsndPdu::QualityMode(quality_mode) => {
+                writer.write_u16_le(*quality_mode).await?;
+                writer.write_u16_le(0).await?;
+            }
+            RdpsndPdu::Training {
+                timestamp,
+                pack_size,
+            } => {
+                writer.write_u16_le(*timestamp).await?;
+                writer.write_u16_le(*pack_size).await?;
+            }
+            RdpsndPdu::WaveInfo {
+                timestamp,
+                format_no,
+                block_no,
+                data,
+                ..
+            } => {
+                writer.write_u16_le(*timestamp).await?;
+                writer.write_u16_le(*format_no).await?;
+                writer.write_u8(*block_no).await?;
+                writer.write_all(&[0; 3]).await?;
+                writer.write_all(data).await?;
+            }
+            RdpsndPdu::WaveConfirm {
+                timestamp,
+                block_no,
+            } => {
+                writer.write_u16_le(*timestamp).await?;
+                writer.write_u8(*block_no).await?;
+                writer.write_u8(0).await?;
+            }
+            RdpsndPdu::Wave2 {
+                timestamp,
+                format_no,
+                block_no,
+                audio_timestamp,
+                data,
+            } => {
+                writer.write_u16_le(*timestamp).await?;
+                writer.write_u16_le(*format_no).await?;
+                writer.write_u8(*block_no).await?;
+                writer.write_all(&[0; 3]).await?;
+                writer.write_u32_le(*audio_timestamp).await?;
+                writer.write_all(data).await?;
+            }
+            RdpsndPdu::Volume(value) | RdpsndPdu::Pitch(value) => {
+                writer.write_u32_le(*value).await?;
+            }
+            RdpsndPdu::Close | RdpsndPdu::Wave(_) => (),
+        }
+        Ok(())
+    }
+
+    /// Wave PDUs can't be read without their wave info PDU
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header).await?;
+        let length = match SndMessageType::try_from(header[0]) {
+            Ok(SndMessageType::SndcWave) => WAVE_INFO_SIZE,
+            _ => u16::from_le_bytes([header[2], header[3]]) as usize,
+        };
+        let mut buffer = BytesMut::from(&header[..]);
+        buffer.resize(HEADER_SIZE + length, 0);
+        reader.read_exact(&mut buffer[HEADER_SIZE..]).await?;
+        *self = read_rdpsnd_pdu(&mut buffer)?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        match self {
+            RdpsndPdu::Wave(data) => data.len(),
+            RdpsndPdu::WaveInfo { .. } => HEADER_SIZE + WAVE_INFO_SIZE,
+            _ => HEADER_SIZE + self.body_size(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// PDUs with a header are written and read back
+    #[tokio::test]
+    async fn test_rdpsnd_pdu() {
+        for pdu in [
+            RdpsndPdu::Formats {
+                flags: SndCapability::TssndcapsAlive as u32,
+                volume: 0,
+                pitch: 0,
+                last_block_confirmed: 0,
+                version: CHANNEL_VERSION_WIN_8,
+                formats: vec![AudioFormat::pcm(2, 44100, 16)],
+            },
+            RdpsndPdu::Training {
+                timestamp: 0x1234,
+                pack_size: 1024,
+            },
+            RdpsndPdu::WaveInfo {
+                timestamp: 5,
+                format_no: 1,
+                block_no: 2,
+                data: [1, 2, 3, 4],
+                length: 100,
+            },
+            RdpsndPdu::Wave2 {
+                timestamp: 5,
+                format_no: 0,
+                block_no: 3,
+                audio_timestamp: 1000,
+                data: vec![0; 8],
+            },
+            RdpsndPdu::Volume(0xFFFF0000),
+        ] {
+            let data = to_vec(&pdu).await.unwrap();
+            assert_eq!(data.len(), pdu.length());
+            let mut buffer = BytesMut::from(&data[..]);
+            assert_eq!(read_rdpsnd_pdu(&mut buffer).unwrap(), pdu);
+            assert!(buffer.is_empty());
+        }
+    }
+}
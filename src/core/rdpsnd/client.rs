@@ -0,0 +1,415 @@
+use crate::codec::audio::{builtin_decoder, AudioDecoder, AudioFormat};
+use crate::core::rdpsnd::base::{
+    read_rdpsnd_pdu, QualityMode, RdpsndPdu, SndCapability, CHANNEL_VERSION_WIN_8,
+    CHANNEL_VERSION_WIN_VISTA,
+};
+
+use bytes::{Buf, BytesMut};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+/// Destination of the audio of the session
+/// Samples are given as interleaved 16 bits PCM
+pub trait AudioSink {
+    /// Channels and sample rate of the next samples
+    fn open(&mut self, channels: u16, samples_per_sec: u32) -> Result<()>;
+    /// Play samples, the timestamp is the one of the server in milliseconds
+    fn play(&mut self, samples: &[i16], timestamp: u32) -> Result<()>;
+    /// Delay in milliseconds before played samples are heard
+    fn latency(&self) -> u32 {
+        0
+    }
+    /// Volume of each channel, 0xFFFF is the loudest
+    fn set_volume(&mut self, _left: u16, _right: u16) {}
+    /// No more audio until the next open
+    fn close(&mut self) {}
+}
+
+/// Event of the audio output channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RdpsndEvent {
+    /// Formats negotiated with the server
+    Formats(Vec<AudioFormat>),
+    /// A block of audio couldn't be decoded or played
+    WaveFailed(u8),
+    Closed,
+}
+
+/// Wave info waiting for its wave PDU
+struct PendingWave {
+    timestamp: u16,
+    format_no: u16,
+    block_no: u8,
+    data: [u8; 4],
+    length: usize,
+}
+
+/// Client of the audio output static virtual channel
+/// Negotiates the formats it can decode, then plays each
+/// block of audio on the sink and confirms it to the server
+///
+/// PCM, G.711 and IMA ADPCM are decoded by default,
+/// decoders of other formats can be added
+///
+/// # Example
+/// ```
+/// use rdp::core::rdpsnd::client::{AudioSink, RdpsndClient};
+/// struct Silent;
+/// impl AudioSink for Silent {
+///     fn open(&mut self, _: u16, _: u32) -> std::io::Result<()> { Ok(()) }
+///     fn play(&mut self, _: &[i16], _: u32) -> std::io::Result<()> { Ok(()) }
+/// }
+/// let mut client = RdpsndClient::new(Box::new(Silent));
+/// // Training of the server
+/// let mut data = bytes::BytesMut::from(&[6, 0, 4, 0, 0x10, 0, 0, 4][..]);
+/// let responses = client.process(&mut data, &mut |_| {}).unwrap();
+/// // Training confirm
+/// assert_eq!(responses.len(), 1);
+/// ```
+pub struct RdpsndClient {
+    sink: Box<dyn AudioSink + Send>,
+    /// Decoders added to the builtin ones, by format tag
+    decoders: HashMap<u16, Box<dyn AudioDecoder + Send>>,
+    /// Formats of the client, indexed by the wave PDUs
+    formats: Vec<AudioFormat>,
+    quality_mode: QualityMode,
+    /// Channels and sample rate of the sink
+    opened: Option<(u16, u32)>,
+    pending: Option<PendingWave>,
+}
+
+impl RdpsndClient {
+    pub fn new(sink: Box<dyn AudioSink + Send>) -> Self {
+        RdpsndClient {
+            sink,
+            decoders: HashMap::new(),
+            formats: Vec::new(),
+            quality_mode: QualityMode::HighQuality,
+            opened: None,
+            pending: None,
+        }
+    }
+
+    /// Decode the formats of a tag, like AAC or Opus
+    /// Must be added before the formats are negotiated
+    pub fn add_decoder(&mut self, format_tag: u16, decoder: Box<dyn AudioDecoder + Send>) {
+        self.decoders.insert(format_tag, decoder);
+    }
+
+    /// Quality asked to the servers supporting it
+    pub fn set_quality_mode(&mut self, quality_mode: QualityMode) {
+        self.quality_mode = quality_mode;
+    }
+
+    fn supports(&self, format: &AudioFormat) -> bool {
+        self.decoders.contains_key(&format.format_tag) || builtin_decoder(format).is_some()
+    }
+
+    /// Keep the formats of the server the client can decode
+    fn negotiate(&mut self, formats: Vec<AudioFormat>, version: u16) -> Vec<RdpsndPdu> {
+        self.formats = formats
+            .into_iter()
+            .filter(|format| self.supports(format))
+            .collect();
+        let mut responses = vec![RdpsndPdu::Formats {
+            flags: SndCapability::TssndcapsAlive as u32 | SndCapability::TssndcapsVolume as u32,
+            volume: 0xFFFFFFFF,
+            pitch: 0x00010000,
+            last_block_confirmed: 0,
+            version: CHANNEL_VERSION_WIN_8,
+            formats: self.formats.clone(),
+        }];
+        if version >= CHANNEL_VERSION_WIN_VISTA {
+            responses.push(RdpsndPdu::QualityMode(self.quality_mode as u16));
+        }
+        responses
+    }
+
+    /// Decode a block of audio and give it to the sink
+    fn play(&mut self, format_no: u16, data: &[u8], timestamp: u32) -> Result<()> {
+        let format = match self.formats.get(format_no as usize) {
+            Some(format) => format,
+            None => return Err(Error::new(ErrorKind::InvalidData, "RDPSND: unknown format")),
+        };
+        let samples = match self.decoders.get_mut(&format.format_tag) {
+            Some(decoder) => decoder.decode(format, data)?,
+            None => match builtin_decoder(format) {
+                Some(mut decoder) => decoder.decode(format, data)?,
+                None => Vec::new(),
+            },
+        };
+        let output = (format.channels, format.samples_per_sec);
+        if self.opened != Some(output) {
+            self.sink.open(output.0, output.1)?;
+            self.opened = Some(output);
+        }
+        self.sink.play(&samples, timestamp)
+    }
+
+    /// Play a block and build its confirmation
+    /// The timestamp of the confirmation includes the latency of the sink
+    fn confirm<T>(
+        &mut self,
+        timestamp: u16,
+        format_no: u16,
+        block_no: u8,
+        data: &[u8],
+        callback: &mut T,
+    ) -> RdpsndPdu
+    where
+        T: FnMut(RdpsndEvent),
+    {
+        if self.play(format_no, data, timestamp as u32).is_err() {
+            callback(RdpsndEvent::WaveFailed(block_no));
+        }
+        RdpsndPdu::WaveConfirm {
+            timestamp: timestamp.wrapping_add(self.sink.latency() as u16),
+            block_no,
+        }
+    }
+
+    /// Process a message of the channel
+    /// and build the PDUs expected by the server
+    pub fn process<T>(&mut self, buffer: &mut BytesMut, callback: &mut T) -> Result<Vec<RdpsndPdu>>
+    where
+        T: FnMut(RdpsndEvent),
+    {
+        let mut responses = Vec::new();
+        // The wave PDU following a wave info PDU has no header
+        if let Some(pending) = self.pending.take() {
+            let length = pending.length.min(buffer.len());
+            let mut data = buffer.split_to(length).to_vec();
+            data[..4.min(length)].copy_from_slice(&pending.data[..4.min(length)]);
+            responses.push(self.confirm(
+                pending.timestamp,
+                pending.format_no,
+                pending.block_no,
+                &data,
+                callback,
+            ));
+        }
+        while buffer.has_remaining() {
+            match read_rdpsnd_pdu(buffer)? {
+                RdpsndPdu::Formats {
+                    formats, version, ..
+                } => {
+                    responses.extend(self.negotiate(formats, version));
+                    callback(RdpsndEvent::Formats(self.formats.clone()));
+                }
+                RdpsndPdu::Training {
+                    timestamp,
+                    pack_size,
+                } => {
+                    responses.push(RdpsndPdu::Training {
+                        timestamp,
+                        pack_size,
+                    });
+                }
+                RdpsndPdu::WaveInfo {
+                    timestamp,
+                    format_no,
+                    block_no,
+                    data,
+                    length,
+                } => {
+                    self.pending = Some(PendingWave {
+                        timestamp,
+                        format_no,
+                        block_no,
+                        data,
+                        length,
+                    });
+                    // The wave PDU may follow in the same message
+                    if buffer.has_remaining() {
+                        responses.extend(self.process(buffer, callback)?);
+                    }
+                }
+                RdpsndPdu::Wave2 {
+                    timestamp,
+                    format_no,
+                    block_no,
+                    data,
+                    ..
+                } => responses.push(self.confirm(timestamp, format_no, block_no, &data, callback)),
+                RdpsndPdu::Volume(volume) => {
+                    self.sink.set_volume(volume as u16, (volume >> 16) as u16);
+                }
+                RdpsndPdu::Close => {
+                    self.sink.close();
+                    self.opened = None;
+                    callback(RdpsndEvent::Closed);
+                }
+                _ => (),
+            }
+        }
+        Ok(responses)
+    }
+}
+
+/// Plays the audio on the default output device of cpal
+/// The stream runs on its own thread, fed by a queue of samples
+#[cfg(feature = "cpal")]
+pub struct CpalSink {
+    samples: std::sync::Arc<std::sync::Mutex<std::collections::VecDeque<i16>>>,
+    /// Volume of each channel, scaled by 0x10000
+    volume: std::sync::Arc<std::sync::atomic::AtomicU32>,
+    /// Stops the stream thread when dropped
+    stop: Option<std::sync::mpsc::Sender<()>>,
+}
+
+#[cfg(feature = "cpal")]
+impl CpalSink {
+    pub fn new() -> Self {
+        CpalSink {
+            samples: Default::default(),
+            volume: std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0xFFFFFFFF)),
+            stop: None,
+        }
+    }
+}
+
+#[cfg(feature = "cpal")]
+impl Default for CpalSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "cpal")]
+impl AudioSink for CpalSink {
+    fn open(&mut self, channels: u16, samples_per_sec: u32) -> Result<()> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+        use std::sync::atomic::Ordering;
+
+        self.close();
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let (ready, started) = std::sync::mpsc::channel();
+        let samples = self.samples.clone();
+        let volume = self.volume.clone();
+        std::thread::spawn(move || {
+            let stream = cpal::default_host()
+                .default_output_device()
+                .ok_or_else(|| "no output device".to_string())
+                .and_then(|device| {
+                    let config = cpal::StreamConfig {
+                        channels,
+                        sample_rate: cpal::SampleRate(samples_per_sec),
+                        buffer_size: cpal::BufferSize::Default,
+                    };
+                    device
+                        .build_output_stream(
+                            &config,
+                            move |output: &mut [i16], _: &cpal::OutputCallbackInfo| {
+                                let volume = volume.load(Ordering::Relaxed);
+                                let mut samples = samples.lock().unwrap();
+                                for (i, sample) in output.iter_mut().enumerate() {
+                                    let scale = if i % 2 == 0 {
+                                        volume & 0xFFFF
+                                    } else {
+                                        volume >> 16
+                                    };
+                                    let value = samples.pop_front().unwrap_or(0) as i32;
+                                    *sample = (value * scale as i32 / 0xFFFF) as i16;
+                                }
+                            },
+                            |_| (),
+                            None,
+                        )
+                        .map_err(|e| e.to_string())
+                })
+                .and_then(|stream| stream.play().map(|_| stream).map_err(|e| e.to_string()));
+            match stream {
+                Ok(_stream) => {
+                    let _ = ready.send(Ok(()));
+                    // Until the sink is closed or dropped
+                    let _ = stopped.recv();
+                }
+                Err(e) => {
+                    let _ = ready.send(Err(e));
+                }
+            }
+        });
+        started
+            .recv()
+            .unwrap_or_else(|_| Err("stream thread stopped".to_string()))
+            .map_err(|e| Error::new(ErrorKind::Other, format!("RDPSND: {}", e)))?;
+        self.stop = Some(stop);
+        Ok(())
+    }
+
+    fn play(&mut self, samples: &[i16], _timestamp: u32) -> Result<()> {
+        self.samples.lock().unwrap().extend(samples);
+        Ok(())
+    }
+
+    fn set_volume(&mut self, left: u16, right: u16) {
+        self.volume.store(
+            left as u32 | (right as u32) << 16,
+            std::sync::atomic::Ordering::Relaxed,
+        );
+    }
+
+    fn close(&mut self) {
+        self.stop = None;
+        self.samples.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps the played samples
+    struct Recorder(Arc<Mutex<Vec<i16>>>);
+
+    impl AudioSink for Recorder {
+        fn open(&mut self, channels: u16, samples_per_sec: u32) -> Result<()> {
+            assert_eq!((channels, samples_per_sec), (1, 8000));
+            Ok(())
+        }
+
+        fn play(&mut self, samples: &[i16], _timestamp: u32) -> Result<()> {
+            self.0.lock().unwrap().extend_from_slice(samples);
+            Ok(())
+        }
+
+        fn latency(&self) -> u32 {
+            10
+        }
+    }
+
+    /// Only the decodable formats are kept, then a wave is played and confirmed
+    #[test]
+    fn test_play_wave() {
+        let played = Arc::new(Mutex::new(Vec::new()));
+        let mut client = RdpsndClient::new(Box::new(Recorder(played.clone())));
+        let mut aac = AudioFormat::pcm(2, 44100, 16);
+        aac.format_tag = crate::codec::audio::WAVE_FORMAT_AAC_MS;
+        let responses = client.negotiate(
+            vec![aac, AudioFormat::pcm(1, 8000, 16)],
+            CHANNEL_VERSION_WIN_8,
+        );
+        assert_eq!(responses.len(), 2);
+        assert_eq!(client.formats, vec![AudioFormat::pcm(1, 8000, 16)]);
+
+        // Wave info then its wave PDU
+        let mut buffer = BytesMut::from(
+            &[
+                0x02, 0x00, 0x0E, 0x00, 0x64, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x01, 0x00,
+                0x02, 0x00,
+            ][..],
+        );
+        assert!(client.process(&mut buffer, &mut |_| {}).unwrap().is_empty());
+        let mut buffer = BytesMut::from(&[0, 0, 0, 0, 0x03, 0x00][..]);
+        let responses = client.process(&mut buffer, &mut |_| {}).unwrap();
+        assert_eq!(
+            responses,
+            vec![RdpsndPdu::WaveConfirm {
+                timestamp: 110,
+                block_no: 7
+            }]
+        );
+        assert_eq!(*played.lock().unwrap(), vec![1, 2, 3]);
+    }
+}
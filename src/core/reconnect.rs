@@ -0,0 +1,267 @@
+#[cfg(not(target_arch = "wasm32"))]
+use crate::core::client::{until_cancelled, RdpClient, RdpClientBuilder};
+use crate::core::event::SessionEnd;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::core::event::{RdpEvent, SessionEvent};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::core::input::InputEvent;
+
+use std::io::ErrorKind;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Result;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::net::TcpStream;
+
+/// How a lost session is reconnected
+///
+/// # Example
+/// ```
+/// use rdp::core::reconnect::ReconnectPolicy;
+/// use std::time::Duration;
+/// let policy = ReconnectPolicy::default();
+/// assert_eq!(policy.delay(1), Duration::from_secs(1));
+/// assert_eq!(policy.delay(3), Duration::from_secs(4));
+/// assert_eq!(policy.delay(10), Duration::from_secs(30));
+/// ```
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Attempts before giving up, 0 never reconnects
+    pub max_attempts: u32,
+    /// Wait before the first attempt
+    pub initial_delay: Duration,
+    /// The wait is doubled after each attempt up to this limit
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 5,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Wait before an attempt, starting at 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+/// Session which reconnects on its own
+/// when the connection is lost
+///
+/// The auto reconnect cookie sent by the server is
+/// kept so the new connection goes back to the same
+/// session without a new logon
+/// The whole connection sequence is run again, with the
+/// same channels and capabilities
+///
+/// Server redirection is not supported
+///
+/// # Example
+/// ```rust, ignore
+/// let builder = RdpClient::builder()
+///     .target("192.168.0.1")
+///     .credentials("domain", "username", "password");
+/// let mut client = ReconnectingClient::connect(builder, ReconnectPolicy::default()).await?;
+/// loop {
+///     client.read(|rdp_event| match rdp_event {
+///         RdpEvent::Session(SessionEvent::Reconnecting { attempt }) => {
+///             println!("Reconnecting, attempt {}", attempt)
+///         }
+///         _ => (),
+///     }).await?;
+/// }
+/// ```
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ReconnectingClient {
+    /// Settings of the first connection
+    builder: RdpClientBuilder,
+    client: RdpClient<TcpStream>,
+    policy: ReconnectPolicy,
+    /// Last cookie sent by the server
+    cookie: Option<(u32, [u8; 16])>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ReconnectingClient {
+    /// Run the first connection
+    /// A failure of this one is not retried
+    pub async fn connect(
+        builder: RdpClientBuilder,
+        policy: ReconnectPolicy,
+    ) -> Result<ReconnectingClient> {
+        let client = builder.clone().connect().await?;
+        Ok(ReconnectingClient {
+            builder,
+            client,
+            policy,
+            cookie: None,
+        })
+    }
+
+    /// Read the next payload from the server
+    ///
+    /// When the connection is lost or the server ends
+    /// the session for a recoverable reason, the
+    /// reconnection is reported by `Reconnecting` and
+    /// `Reconnected` events
+    /// The error is returned once all the attempts failed
+    pub async fn read<T>(&mut self, mut callback: T) -> Result<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let cookie = &mut self.cookie;
+        let mut end = None;
+        let result = self
+            .client
+            .read(|event| {
+                match &event {
+                    RdpEvent::Session(SessionEvent::AutoReconnectCookie { logon_id, random }) => {
+                        *cookie = Some((*logon_id, *random))
+                    }
+                    RdpEvent::Session(SessionEvent::Ended(session_end)) => end = Some(*session_end),
+                    _ => (),
+                }
+                callback(event)
+            })
+            .await;
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) if is_recoverable(end, e.kind()) => self.reconnect(e, &mut callback).await,
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Try new connections until one succeeds
+    async fn reconnect<T>(&mut self, mut error: std::io::Error, callback: &mut T) -> Result<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let cancel = self.client.get_cancel_token().clone();
+        for attempt in 1..=self.policy.max_attempts {
+            callback(RdpEvent::Session(SessionEvent::Reconnecting { attempt }));
+            let delay = self.policy.delay(attempt);
+            until_cancelled(&cancel, async {
+                tokio::time::sleep(delay).await;
+                Ok(())
+            })
+            .await?;
+
+            let mut builder = self.builder.clone();
+            if let Some((logon_id, random)) = self.cookie {
+                builder = builder.auto_reconnect_cookie(logon_id, random);
+            }
+            match builder.connect().await {
+                Ok(client) => {
+                    self.client = client;
+                    callback(RdpEvent::Session(SessionEvent::Reconnected));
+                    return Ok(());
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => return Err(e),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// Send a mouse or keyboard event to the server
+    pub async fn write(&mut self, event: RdpEvent) -> Result<()> {
+        self.client.write(event).await
+    }
+
+    /// Send several input events at once
+    pub async fn write_input(&mut self, events: &[InputEvent]) -> Result<()> {
+        self.client.write_input(events).await
+    }
+
+    /// Send the queued input events now
+    pub async fn flush_input(&mut self) -> Result<()> {
+        self.client.flush_input().await
+    }
+
+    /// Session of the current connection
+    pub fn get_client(&self) -> &RdpClient<TcpStream> {
+        &self.client
+    }
+
+    /// Mutable access to the session of the current connection
+    pub fn get_client_mut(&mut self) -> &mut RdpClient<TcpStream> {
+        &mut self.client
+    }
+
+    /// Ask the server to log off the user
+    /// A logged off session is never reconnected
+    pub async fn logoff(&mut self) -> Result<()> {
+        self.client.logoff().await
+    }
+
+    /// Send a close event to server
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.client.shutdown().await
+    }
+}
+
+/// A session can be reconnected when the connection was
+/// lost or closed by the server without logging off
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+fn is_recoverable(end: Option<SessionEnd>, kind: ErrorKind) -> bool {
+    match end {
+        Some(SessionEnd::Dropped) => true,
+        Some(SessionEnd::Disconnected(reason)) => reason.is_recoverable(),
+        Some(SessionEnd::LoggedOff) => false,
+        // Idle read timeout
+        None => kind == ErrorKind::TimedOut,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::error_info::ErrorInfo;
+
+    /// The delay is doubled up to the limit
+    #[test]
+    fn test_reconnect_policy_delay() {
+        let policy = ReconnectPolicy {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(300));
+        assert_eq!(policy.delay(64), Duration::from_millis(300));
+    }
+
+    /// Logged off and refused sessions are not reconnected
+    #[test]
+    fn test_is_recoverable() {
+        assert!(is_recoverable(
+            Some(SessionEnd::Dropped),
+            ErrorKind::UnexpectedEof
+        ));
+        assert!(is_recoverable(None, ErrorKind::TimedOut));
+        assert!(!is_recoverable(
+            Some(SessionEnd::LoggedOff),
+            ErrorKind::UnexpectedEof
+        ));
+        assert!(!is_recoverable(
+            Some(SessionEnd::Disconnected(
+                ErrorInfo::DisconnectedByOtherConnection
+            )),
+            ErrorKind::ConnectionAborted
+        ));
+        assert!(!is_recoverable(None, ErrorKind::Interrupted));
+    }
+}
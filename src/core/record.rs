@@ -0,0 +1,715 @@
+use crate::core::error_info::ErrorInfo;
+use crate::core::event::{
+    BitmapEvent, ChannelEvent, CursorEvent, FrameAction, KeyboardEvent, PointerButton,
+    PointerEvent, PointerShape, RdpEvent, SessionEnd, SessionEvent,
+};
+use crate::core::gcc::Monitor;
+use crate::core::input::InputEvent;
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+/// First bytes of a recording
+const RECORDING_MAGIC: [u8; 4] = *b"RDPR";
+
+/// Version of the recording format
+/// Records of an unknown type are skipped
+/// so new types don't need a new version
+pub const RECORDING_VERSION: u16 = 1;
+
+/// Largest record accepted by the replayer
+const RECORD_MAX_SIZE: usize = 0x4000000;
+
+/// Type of a record
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+enum RecordType {
+    Bitmap = 0x01,
+    Cursor = 0x02,
+    Session = 0x03,
+    Input = 0x04,
+    Pointer = 0x05,
+    Key = 0x06,
+    Channel = 0x07,
+}
+
+/// Content of a record
+pub enum Record {
+    /// Event given by the global client
+    Event(RdpEvent),
+    /// Input event sent to the server
+    Input(InputEvent),
+}
+
+fn put_string(buffer: &mut BytesMut, value: &str) {
+    buffer.put_u16_le(value.len() as u16);
+    buffer.put_slice(value.as_bytes());
+}
+
+fn get_string(buffer: &mut BytesMut) -> Result<String> {
+    check_remaining(buffer, 2, "RECORD: string length")?;
+    let length = buffer.get_u16_le() as usize;
+    check_remaining(buffer, length, "RECORD: string")?;
+    String::from_utf8(buffer.split_to(length).to_vec())
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "RECORD: invalid string"))
+}
+
+fn get_bytes(buffer: &mut BytesMut) -> Result<Vec<u8>> {
+    check_remaining(buffer, 4, "RECORD: data length")?;
+    let length = buffer.get_u32_le() as usize;
+    check_remaining(buffer, length, "RECORD: data")?;
+    Ok(buffer.split_to(length).to_vec())
+}
+
+fn write_bitmap(buffer: &mut BytesMut, bitmap: &BitmapEvent) {
+    for field in [
+        bitmap.dest_left,
+        bitmap.dest_top,
+        bitmap.dest_right,
+        bitmap.dest_bottom,
+        bitmap.width,
+        bitmap.height,
+        bitmap.bpp,
+    ] {
+        buffer.put_u16_le(field);
+    }
+    buffer.put_u8(bitmap.is_compress as u8);
+    buffer.put_u32_le(bitmap.data.len() as u32);
+    buffer.put_slice(&bitmap.data);
+}
+
+fn read_bitmap(buffer: &mut BytesMut) -> Result<BitmapEvent> {
+    check_remaining(buffer, 15, "RECORD: bitmap")?;
+    Ok(BitmapEvent {
+        dest_left: buffer.get_u16_le(),
+        dest_top: buffer.get_u16_le(),
+        dest_right: buffer.get_u16_le(),
+        dest_bottom: buffer.get_u16_le(),
+        width: buffer.get_u16_le(),
+        height: buffer.get_u16_le(),
+        bpp: buffer.get_u16_le(),
+        is_compress: buffer.get_u8() != 0,
+        data: get_bytes(buffer)?,
+    })
+}
+
+fn write_cursor(buffer: &mut BytesMut, cursor: &CursorEvent) {
+    match cursor {
+        CursorEvent::Hidden => buffer.put_u8(0),
+        CursorEvent::Default => buffer.put_u8(1),
+        CursorEvent::Position { x, y } => {
+            buffer.put_u8(2);
+            buffer.put_u16_le(*x);
+            buffer.put_u16_le(*y);
+        }
+        CursorEvent::Shape(shape) => {
+            buffer.put_u8(3);
+            for field in [
+                shape.cache_index,
+                shape.hotspot_x,
+                shape.hotspot_y,
+                shape.width,
+                shape.height,
+            ] {
+                buffer.put_u16_le(field);
+            }
+            buffer.put_u32_le(shape.rgba.len() as u32);
+            buffer.put_slice(&shape.rgba);
+        }
+    }
+}
+
+fn read_cursor(buffer: &mut BytesMut) -> Result<CursorEvent> {
+    check_remaining(buffer, 1, "RECORD: cursor")?;
+    match buffer.get_u8() {
+        0 => Ok(CursorEvent::Hidden),
+        1 => Ok(CursorEvent::Default),
+        2 => {
+            check_remaining(buffer, 4, "RECORD: cursor position")?;
+            Ok(CursorEvent::Position {
+                x: buffer.get_u16_le(),
+                y: buffer.get_u16_le(),
+            })
+        }
+        3 => {
+            check_remaining(buffer, 10, "RECORD: cursor shape")?;
+            Ok(CursorEvent::Shape(PointerShape {
+                cache_index: buffer.get_u16_le(),
+                hotspot_x: buffer.get_u16_le(),
+                hotspot_y: buffer.get_u16_le(),
+                width: buffer.get_u16_le(),
+                height: buffer.get_u16_le(),
+                rgba: get_bytes(buffer)?,
+            }))
+        }
+        tag => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("RECORD: invalid cursor event {}", tag),
+        )),
+    }
+}
+
+fn write_session(buffer: &mut BytesMut, event: &SessionEvent) {
+    match event {
+        SessionEvent::LoggedOn {
+            domain,
+            user,
+            session_id,
+        } => {
+            buffer.put_u8(0);
+            buffer.put_u32_le(*session_id);
+            put_string(buffer, domain);
+            put_string(buffer, user);
+        }
+        SessionEvent::LogonNotify => buffer.put_u8(1),
+        SessionEvent::AutoReconnectCookie { logon_id, random } => {
+            buffer.put_u8(2);
+            buffer.put_u32_le(*logon_id);
+            buffer.put_slice(random);
+        }
+        SessionEvent::LogonError {
+            error_type,
+            error_data,
+        } => {
+            buffer.put_u8(3);
+            buffer.put_u32_le(*error_type);
+            buffer.put_u32_le(*error_data);
+        }
+        SessionEvent::Reactivated { width, height } => {
+            buffer.put_u8(4);
+            buffer.put_u16_le(*width);
+            buffer.put_u16_le(*height);
+        }
+        SessionEvent::Terminated(reason) => {
+            buffer.put_u8(5);
+            buffer.put_u32_le(reason.code());
+        }
+        SessionEvent::MonitorLayout(monitors) => {
+            buffer.put_u8(6);
+            buffer.put_u16_le(monitors.len() as u16);
+            for monitor in monitors {
+                buffer.put_i32_le(monitor.left);
+                buffer.put_i32_le(monitor.top);
+                buffer.put_i32_le(monitor.right);
+                buffer.put_i32_le(monitor.bottom);
+                buffer.put_u32_le(monitor.flags);
+            }
+        }
+        SessionEvent::HeartbeatMissed(missed) => {
+            buffer.put_u8(7);
+            buffer.put_u8(*missed);
+        }
+        SessionEvent::LogoffDenied => buffer.put_u8(8),
+        SessionEvent::Ended(end) => {
+            buffer.put_u8(9);
+            match end {
+                SessionEnd::LoggedOff => buffer.put_u8(0),
+                SessionEnd::Disconnected(reason) => {
+                    buffer.put_u8(1);
+                    buffer.put_u32_le(reason.code());
+                }
+                SessionEnd::Dropped => buffer.put_u8(2),
+            }
+        }
+        SessionEvent::Reconnecting { attempt } => {
+            buffer.put_u8(10);
+            buffer.put_u32_le(*attempt);
+        }
+        SessionEvent::Reconnected => buffer.put_u8(11),
+        SessionEvent::Frame(action) => {
+            buffer.put_u8(12);
+            buffer.put_u8(*action as u8);
+        }
+    }
+}
+
+fn read_session(buffer: &mut BytesMut) -> Result<SessionEvent> {
+    check_remaining(buffer, 1, "RECORD: session")?;
+    match buffer.get_u8() {
+        0 => {
+            check_remaining(buffer, 4, "RECORD: logged on")?;
+            let session_id = buffer.get_u32_le();
+            Ok(SessionEvent::LoggedOn {
+                domain: get_string(buffer)?,
+                user: get_string(buffer)?,
+                session_id,
+            })
+        }
+        1 => Ok(SessionEvent::LogonNotify),
+        2 => {
+            check_remaining(buffer, 20, "RECORD: auto reconnect cookie")?;
+            let logon_id = buffer.get_u32_le();
+            let mut random = [0; 16];
+            buffer.copy_to_slice(&mut random);
+            Ok(SessionEvent::AutoReconnectCookie { logon_id, random })
+        }
+        3 => {
+            check_remaining(buffer, 8, "RECORD: logon error")?;
+            Ok(SessionEvent::LogonError {
+                error_type: buffer.get_u32_le(),
+                error_data: buffer.get_u32_le(),
+            })
+        }
+        4 => {
+            check_remaining(buffer, 4, "RECORD: reactivated")?;
+            Ok(SessionEvent::Reactivated {
+                width: buffer.get_u16_le(),
+                height: buffer.get_u16_le(),
+            })
+        }
+        5 => {
+            check_remaining(buffer, 4, "RECORD: terminated")?;
+            Ok(SessionEvent::Terminated(ErrorInfo::from(
+                buffer.get_u32_le(),
+            )))
+        }
+        6 => {
+            check_remaining(buffer, 2, "RECORD: monitor layout")?;
+            let count = buffer.get_u16_le();
+            let mut monitors = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                let mut monitor = Monitor::default();
+                monitor.read_from_buffer(buffer)?;
+                monitors.push(monitor);
+            }
+            Ok(SessionEvent::MonitorLayout(monitors))
+        }
+        7 => {
+            check_remaining(buffer, 1, "RECORD: heartbeat missed")?;
+            Ok(SessionEvent::HeartbeatMissed(buffer.get_u8()))
+        }
+        8 => Ok(SessionEvent::LogoffDenied),
+        9 => {
+            check_remaining(buffer, 1, "RECORD: session end")?;
+            let end = match buffer.get_u8() {
+                0 => SessionEnd::LoggedOff,
+                1 => {
+                    check_remaining(buffer, 4, "RECORD: session end")?;
+                    SessionEnd::Disconnected(ErrorInfo::from(buffer.get_u32_le()))
+                }
+                2 => SessionEnd::Dropped,
+                tag => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("RECORD: invalid session end {}", tag),
+                    ))
+                }
+            };
+            Ok(SessionEvent::Ended(end))
+        }
+        10 => {
+            check_remaining(buffer, 4, "RECORD: reconnecting")?;
+            Ok(SessionEvent::Reconnecting {
+                attempt: buffer.get_u32_le(),
+            })
+        }
+        11 => Ok(SessionEvent::Reconnected),
+        12 => {
+            check_remaining(buffer, 1, "RECORD: frame")?;
+            let action = buffer.get_u8();
+            FrameAction::try_from(action as u32)
+                .map(SessionEvent::Frame)
+                .map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("RECORD: invalid frame action {}", action),
+                    )
+                })
+        }
+        tag => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("RECORD: invalid session event {}", tag),
+        )),
+    }
+}
+
+fn write_input(buffer: &mut BytesMut, event: &InputEvent) {
+    match *event {
+        InputEvent::Scancode {
+            code,
+            extended,
+            down,
+        } => {
+            buffer.put_u8(0);
+            buffer.put_u8(code);
+            buffer.put_u8(extended as u8);
+            buffer.put_u8(down as u8);
+        }
+        InputEvent::Unicode { code, down } => {
+            buffer.put_u8(1);
+            buffer.put_u16_le(code);
+            buffer.put_u8(down as u8);
+        }
+        InputEvent::Mouse { flags, x, y } | InputEvent::MouseX { flags, x, y } => {
+            buffer.put_u8(if matches!(event, InputEvent::Mouse { .. }) {
+                2
+            } else {
+                3
+            });
+            buffer.put_u16_le(flags);
+            buffer.put_u16_le(x);
+            buffer.put_u16_le(y);
+        }
+        InputEvent::Sync { flags } => {
+            buffer.put_u8(4);
+            buffer.put_u32_le(flags);
+        }
+    }
+}
+
+fn read_input(buffer: &mut BytesMut) -> Result<InputEvent> {
+    check_remaining(buffer, 1, "RECORD: input")?;
+    match buffer.get_u8() {
+        0 => {
+            check_remaining(buffer, 3, "RECORD: scancode")?;
+            Ok(InputEvent::Scancode {
+                code: buffer.get_u8(),
+                extended: buffer.get_u8() != 0,
+                down: buffer.get_u8() != 0,
+            })
+        }
+        1 => {
+            check_remaining(buffer, 3, "RECORD: unicode")?;
+            Ok(InputEvent::Unicode {
+                code: buffer.get_u16_le(),
+                down: buffer.get_u8() != 0,
+            })
+        }
+        tag @ (2 | 3) => {
+            check_remaining(buffer, 6, "RECORD: mouse")?;
+            let (flags, x, y) = (
+                buffer.get_u16_le(),
+                buffer.get_u16_le(),
+                buffer.get_u16_le(),
+            );
+            Ok(match tag {
+                2 => InputEvent::Mouse { flags, x, y },
+                _ => InputEvent::MouseX { flags, x, y },
+            })
+        }
+        4 => {
+            check_remaining(buffer, 4, "RECORD: sync")?;
+            Ok(InputEvent::Sync {
+                flags: buffer.get_u32_le(),
+            })
+        }
+        tag => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("RECORD: invalid input event {}", tag),
+        )),
+    }
+}
+
+/// Record events and their timing
+///
+/// Records are kept in memory until they are flushed,
+/// so they can be added from the callback of GlobalClient::read
+/// File layout is the magic, the version and a list of records
+/// made of a timestamp in ms, a type and a length
+///
+/// # Example
+/// ```rust, ignore
+/// let mut recorder = SessionRecorder::new();
+/// let mut file = tokio::fs::File::create("session.rdpr").await?;
+/// loop {
+///     global.read(|event| {
+///         recorder.record(&event);
+///         // handle event
+///     }).await?;
+///     recorder.flush(&mut file).await?;
+/// }
+/// ```
+pub struct SessionRecorder {
+    /// Time of the first record
+    start: Instant,
+    /// Records not flushed yet
+    buffer: BytesMut,
+}
+
+impl SessionRecorder {
+    pub fn new() -> Self {
+        let mut buffer = BytesMut::new();
+        buffer.put_slice(&RECORDING_MAGIC);
+        buffer.put_u16_le(RECORDING_VERSION);
+        SessionRecorder {
+            start: Instant::now(),
+            buffer,
+        }
+    }
+
+    /// Append a record with its body
+    fn push(&mut self, record_type: RecordType, write: impl FnOnce(&mut BytesMut)) {
+        let mut body = BytesMut::new();
+        write(&mut body);
+        let timestamp = self.start.elapsed().as_millis().min(u32::MAX as u128) as u32;
+        self.buffer.put_u32_le(timestamp);
+        self.buffer.put_u8(record_type as u8);
+        self.buffer.put_u32_le(body.len() as u32);
+        self.buffer.unsplit(body);
+    }
+
+    /// Record an event given by the global client
+    pub fn record(&mut self, event: &RdpEvent) {
+        match event {
+            RdpEvent::Bitmap(bitmap) => self.push(RecordType::Bitmap, |b| write_bitmap(b, bitmap)),
+            RdpEvent::Cursor(cursor) => self.push(RecordType::Cursor, |b| write_cursor(b, cursor)),
+            RdpEvent::Session(event) => self.push(RecordType::Session, |b| write_session(b, event)),
+            RdpEvent::Pointer(pointer) => self.push(RecordType::Pointer, |b| {
+                b.put_u16_le(pointer.x);
+                b.put_u16_le(pointer.y);
+                b.put_u8(pointer.button as u8);
+                b.put_u8(pointer.down as u8);
+            }),
+            RdpEvent::Key(key) => self.push(RecordType::Key, |b| {
+                b.put_u16_le(key.code);
+                b.put_u8(key.down as u8);
+            }),
+            RdpEvent::Channel(channel) => self.push(RecordType::Channel, |b| {
+                put_string(b, &channel.name);
+                b.put_slice(&channel.data);
+            }),
+            // Windows of RemoteApp and paced frames aren't replayed
+            RdpEvent::Window(_) | RdpEvent::Damage(_) | RdpEvent::Dynamic(_) => (),
+        }
+    }
+
+    /// Record input events sent to the server
+    pub fn record_input(&mut self, events: &[InputEvent]) {
+        for event in events {
+            self.push(RecordType::Input, |b| write_input(b, event));
+        }
+    }
+
+    /// Write pending records
+    pub async fn flush(&mut self, writer: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+        writer.write_all(&self.buffer.split()).await?;
+        writer.flush().await
+    }
+}
+
+impl Default for SessionRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read a recording made by a SessionRecorder
+///
+/// # Example
+/// ```rust, ignore
+/// let file = tokio::fs::File::open("session.rdpr").await?;
+/// let mut replayer = SessionReplayer::open(file).await?;
+/// replayer.replay(true, |event| match event {
+///     RdpEvent::Bitmap(bitmap) => {
+///         // draw bitmap
+///     }
+///     _ => (),
+/// }).await?;
+/// ```
+pub struct SessionReplayer<R> {
+    reader: R,
+    /// Version of the recording
+    version: u16,
+}
+
+impl<R: AsyncRead + Unpin> SessionReplayer<R> {
+    /// Check the header of the recording
+    pub async fn open(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).await?;
+        if magic != RECORDING_MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "RECORD: not a recording",
+            ));
+        }
+
+        let version = reader.read_u16_le().await?;
+        if version > RECORDING_VERSION {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("RECORD: unsupported recording version {}", version),
+            ));
+        }
+        Ok(SessionReplayer { reader, version })
+    }
+
+    /// Version of the recording
+    pub fn get_version(&self) -> u16 {
+        self.version
+    }
+
+    /// Read the next record and its time from the start of the recording
+    /// None is returned at the end of the recording
+    pub async fn next(&mut self) -> Result<Option<(Duration, Record)>> {
+        loop {
+            let timestamp = match self.reader.read_u32_le().await {
+                Ok(timestamp) => Duration::from_millis(timestamp as u64),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let record_type = self.reader.read_u8().await?;
+            let length = self.reader.read_u32_le().await? as usize;
+            if length > RECORD_MAX_SIZE {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "RECORD: record is too large",
+                ));
+            }
+
+            let mut body = BytesMut::new();
+            body.resize(length, 0);
+            self.reader.read_exact(&mut body).await?;
+
+            let record = match RecordType::try_from(record_type) {
+                Ok(RecordType::Bitmap) => Record::Event(RdpEvent::Bitmap(read_bitmap(&mut body)?)),
+                Ok(RecordType::Cursor) => Record::Event(RdpEvent::Cursor(read_cursor(&mut body)?)),
+                Ok(RecordType::Session) => {
+                    Record::Event(RdpEvent::Session(read_session(&mut body)?))
+                }
+                Ok(RecordType::Input) => Record::Input(read_input(&mut body)?),
+                Ok(RecordType::Pointer) => {
+                    check_remaining(&body, 6, "RECORD: pointer")?;
+                    Record::Event(RdpEvent::Pointer(PointerEvent {
+                        x: body.get_u16_le(),
+                        y: body.get_u16_le(),
+                        button: PointerButton::try_from(body.get_u8()).map_err(|_| {
+                            Error::new(ErrorKind::InvalidData, "RECORD: invalid pointer button")
+                        })?,
+                        down: body.get_u8() != 0,
+                    }))
+                }
+                Ok(RecordType::Key) => {
+                    check_remaining(&body, 3, "RECORD: key")?;
+                    Record::Event(RdpEvent::Key(KeyboardEvent {
+                        code: body.get_u16_le(),
+                        down: body.get_u8() != 0,
+                    }))
+                }
+                Ok(RecordType::Channel) => Record::Event(RdpEvent::Channel(ChannelEvent {
+                    name: get_string(&mut body)?,
+                    data: body.freeze(),
+                })),
+                // Added by a newer recorder
+                Err(_) => continue,
+            };
+            return Ok(Some((timestamp, record)));
+        }
+    }
+
+    /// Feed all recorded events to the callback
+    /// Input events are skipped
+    ///
+    /// When realtime is set, events are given
+    /// with the same timing as they were recorded
+    pub async fn replay<T>(&mut self, realtime: bool, mut callback: T) -> Result<()>
+    where
+        T: FnMut(RdpEvent),
+    {
+        let start = Instant::now();
+        while let Some((timestamp, record)) = self.next().await? {
+            if let Record::Event(event) = record {
+                if realtime {
+                    tokio::time::sleep_until(start + timestamp).await;
+                }
+                callback(event);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test a recording with output and input events
+    #[tokio::test]
+    async fn test_record_replay() {
+        let mut recorder = SessionRecorder::new();
+        recorder.record(&RdpEvent::Bitmap(BitmapEvent {
+            dest_left: 0,
+            dest_top: 0,
+            dest_right: 1,
+            dest_bottom: 1,
+            width: 2,
+            height: 2,
+            bpp: 32,
+            is_compress: false,
+            data: vec![1, 2, 3],
+        }));
+        recorder.record(&RdpEvent::Session(SessionEvent::LoggedOn {
+            domain: "domain".to_string(),
+            user: "user".to_string(),
+            session_id: 2,
+        }));
+        recorder.record_input(&[InputEvent::mouse_move(10, 20)]);
+
+        let mut file = Vec::new();
+        recorder.flush(&mut file).await.unwrap();
+
+        let mut replayer = SessionReplayer::open(&file[..]).await.unwrap();
+        assert_eq!(replayer.get_version(), RECORDING_VERSION);
+        assert!(matches!(
+            replayer.next().await.unwrap(),
+            Some((_, Record::Event(RdpEvent::Bitmap(bitmap)))) if bitmap.data == vec![1, 2, 3]
+        ));
+        match replayer.next().await.unwrap() {
+            Some((_, Record::Event(RdpEvent::Session(event)))) => assert_eq!(
+                event,
+                SessionEvent::LoggedOn {
+                    domain: "domain".to_string(),
+                    user: "user".to_string(),
+                    session_id: 2,
+                }
+            ),
+            _ => panic!("expected a session event"),
+        }
+        assert!(matches!(
+            replayer.next().await.unwrap(),
+            Some((_, Record::Input(event))) if event == InputEvent::mouse_move(10, 20)
+        ));
+        assert!(replayer.next().await.unwrap().is_none());
+    }
+
+    /// Records of an unknown type are skipped
+    #[tokio::test]
+    async fn test_replay_unknown_record() {
+        let mut file = BytesMut::new();
+        file.put_slice(&RECORDING_MAGIC);
+        file.put_u16_le(RECORDING_VERSION);
+        file.put_slice(&[0, 0, 0, 0, 0xFF, 2, 0, 0, 0, 1, 2]);
+        file.put_slice(&[5, 0, 0, 0, RecordType::Cursor as u8, 1, 0, 0, 0, 0]);
+
+        let mut replayer = SessionReplayer::open(&file[..]).await.unwrap();
+        let mut events = Vec::new();
+        replayer
+            .replay(false, |event| events.push(event))
+            .await
+            .unwrap();
+        assert!(matches!(
+            events[..],
+            [RdpEvent::Cursor(CursorEvent::Hidden)]
+        ));
+    }
+
+    /// Test the encoding of a session end
+    #[test]
+    fn test_session_end_round_trip() {
+        let event = SessionEvent::Ended(SessionEnd::Disconnected(ErrorInfo::LogoffByUser));
+        let mut buffer = BytesMut::new();
+        write_session(&mut buffer, &event);
+        assert_eq!(read_session(&mut buffer).unwrap(), event);
+    }
+
+    /// Files without the magic are rejected
+    #[tokio::test]
+    async fn test_replay_invalid_magic() {
+        assert!(SessionReplayer::open(&b"RIFF\x01\x00"[..]).await.is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Future of a sleep
+/// Not Send on wasm32 where the browser timers stay on their thread
+#[cfg(not(target_arch = "wasm32"))]
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+#[cfg(target_arch = "wasm32")]
+pub type Sleep = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Timers of the connection timeouts, of the frame capture
+/// and of the heartbeats
+///
+/// The tokio timers are used by default, a browser frontend
+/// gives its own, built on setTimeout for example
+///
+/// # Example
+/// ```rust, ignore
+/// struct BrowserTimer;
+///
+/// impl Timer for BrowserTimer {
+///     fn sleep(&self, duration: Duration) -> Sleep {
+///         Box::pin(gloo_timers::future::sleep(duration))
+///     }
+/// }
+///
+/// let client = RdpClientBuilder::new()
+///     .timer(Arc::new(BrowserTimer))
+///     .connect_with(websocket)
+///     .await?;
+/// ```
+pub trait Timer: Send + Sync {
+    /// Future completed once the duration elapsed
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// Timers of the tokio runtime
+#[cfg(not(target_arch = "wasm32"))]
+pub struct TokioTimer;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Timer for TokioTimer {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Never wakes up, so the timeouts never expire
+/// Default of the targets without the tokio timers
+pub struct NoTimer;
+
+impl Timer for NoTimer {
+    fn sleep(&self, _duration: Duration) -> Sleep {
+        Box::pin(std::future::pending())
+    }
+}
+
+/// Timer used when none is given
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_timer() -> Arc<dyn Timer> {
+    Arc::new(TokioTimer)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn default_timer() -> Arc<dyn Timer> {
+    Arc::new(NoTimer)
+}
+
+/// Run a future with a time limit
+/// None once the time limit expired
+pub async fn timeout<T>(
+    timer: &dyn Timer,
+    duration: Duration,
+    future: impl Future<Output = T>,
+) -> Option<T> {
+    tokio::select! {
+        result = future => Some(result),
+        _ = timer.sleep(duration) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test of the time limit with both timers
+    #[tokio::test]
+    async fn test_timeout() {
+        let duration = Duration::from_millis(10);
+        assert_eq!(
+            timeout(&TokioTimer, duration, std::future::pending::<()>()).await,
+            None
+        );
+        assert_eq!(
+            timeout(&NoTimer, duration, std::future::ready(1)).await,
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,789 @@
+use crate::codec::bulk::CompressionType;
+use crate::model::data::{check_remaining, Message};
+use crate::model::unicode::{from_unicode, Unicode};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use std::io::Result;
+use std::net::IpAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Security flag send as header flage in core ptotocol
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/e13405c5-668b-4716-94b2-1c2654ca1ad4?redirectedfrom=MSDN
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SecurityFlag {
+    SecExchangePkt = 0x0001,
+    SecTransportReq = 0x0002,
+    RdpSecTransportRsp = 0x0004,
+    SecEncrypt = 0x0008,
+    SecResetSeqno = 0x0010,
+    SecIgnoreSeqno = 0x0020,
+    SecInfoPkt = 0x0040,
+    SecLicensePkt = 0x0080,
+    SecLicenseEncryptCs = 0x0200,
+    SecRedirectionPkt = 0x0400,
+    SecSecureChecksum = 0x0800,
+    SecAutodetectReq = 0x1000,
+    SecAutodetectRsp = 0x2000,
+    SecHeartbeat = 0x4000,
+    SecFlagshiValid = 0x8000,
+}
+
+/// RDP option someone links to capabilities
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/732394f5-e2b5-4ac5-8a0a-35345386b0d1?redirectedfrom=MSDN
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InfoFlag {
+    InfoMouse = 0x00000001,
+    InfoDisablectrlaltdel = 0x00000002,
+    InfoAutologon = 0x00000008,
+    InfoUnicode = 0x00000010,
+    InfoMaximizeshell = 0x00000020,
+    InfoLogonnotify = 0x00000040,
+    InfoCompression = 0x00000080,
+    InfoEnablewindowskey = 0x00000100,
+    InfoRemoteconsoleaudio = 0x00002000,
+    InfoForceEncryptedCsPdu = 0x00004000,
+    InfoRail = 0x00008000,
+    InfoLogonerrors = 0x00010000,
+    InfoMouseHasWheel = 0x00020000,
+    InfoPasswordIsScPin = 0x00040000,
+    InfoNoaudioplayback = 0x00080000,
+    InfoUsingSavedCreds = 0x00100000,
+    InfoAudiocapture = 0x00200000,
+    InfoVideoDisable = 0x00400000,
+    InfoHiddenf2 = 0x01000000,
+    InfoHiddenf3 = 0x02000000,
+    InfoCompressionTypeMask = 0x00001E00,
+}
+
+/// Address family of the client address
+/// in the extended info packet
+#[repr(u16)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AfInet {
+    AfInet = 0x00002,
+    AfInet6 = 0x0017,
+}
+
+/// Performance flags of the extended info packet
+/// MS-RDPBCGR 2.2.1.11.1.1.1 Extended Info Packet (TS_EXTENDED_INFO_PACKET)
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PerformanceFlag {
+    PerfDisableWallpaper = 0x00000001,
+    PerfDisableFullwindowdrag = 0x00000002,
+    PerfDisableMenuanimations = 0x00000004,
+    PerfDisableTheming = 0x00000008,
+    PerfDisableCursorShadow = 0x00000020,
+    PerfDisableCursorsettings = 0x00000040,
+    PerfEnableFontSmoothing = 0x00000080,
+    PerfEnableDesktopComposition = 0x00000100,
+}
+
+/// Typed configuration of the performance flags
+/// Allow to trim the session on low bandwidth link
+///
+/// # Example
+/// ```
+/// use rdp::core::sec::base::PerformanceFlags;
+/// let flags = PerformanceFlags {
+///     disable_wallpaper: true,
+///     enable_font_smoothing: true,
+///     ..Default::default()
+/// };
+/// assert_eq!(flags.bits(), 0x81);
+/// ```
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct PerformanceFlags {
+    pub disable_wallpaper: bool,
+    pub disable_full_window_drag: bool,
+    pub disable_menu_animations: bool,
+    pub disable_theming: bool,
+    pub disable_cursor_shadow: bool,
+    pub disable_cursor_settings: bool,
+    pub enable_font_smoothing: bool,
+    pub enable_desktop_composition: bool,
+}
+
+impl PerformanceFlags {
+    /// Disable every visual effect
+    pub fn low_bandwidth() -> Self {
+        PerformanceFlags {
+            disable_wallpaper: true,
+            disable_full_window_drag: true,
+            disable_menu_animations: true,
+            disable_theming: true,
+            disable_cursor_shadow: true,
+            disable_cursor_settings: true,
+            enable_font_smoothing: false,
+            enable_desktop_composition: false,
+        }
+    }
+
+    /// Encode the flags as expected by the extended info packet
+    pub fn bits(&self) -> u32 {
+        [
+            (self.disable_wallpaper, PerformanceFlag::PerfDisableWallpaper),
+            (self.disable_full_window_drag, PerformanceFlag::PerfDisableFullwindowdrag),
+            (self.disable_menu_animations, PerformanceFlag::PerfDisableMenuanimations),
+            (self.disable_theming, PerformanceFlag::PerfDisableTheming),
+            (self.disable_cursor_shadow, PerformanceFlag::PerfDisableCursorShadow),
+            (self.disable_cursor_settings, PerformanceFlag::PerfDisableCursorsettings),
+            (self.enable_font_smoothing, PerformanceFlag::PerfEnableFontSmoothing),
+            (self.enable_desktop_composition, PerformanceFlag::PerfEnableDesktopComposition),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .fold(0, |bits, (_, flag)| bits | *flag as u32)
+    }
+}
+
+/// Encode a string as a null terminated utf-16le string
+fn unicode_null_terminated(value: &str) -> Vec<u8> {
+    let mut result = value.to_unicode();
+    result.push(0);
+    result.push(0);
+    result
+}
+
+/// Read a utf-16le string of `size` bytes
+/// followed by its null terminator
+async fn read_unicode(reader: &mut (impl AsyncRead + Unpin + Send), size: usize) -> Result<String> {
+    let mut buffer = vec![0; size + 2];
+    reader.read_exact(&mut buffer).await?;
+    Ok(from_unicode(&buffer))
+}
+
+/// Details of the security header
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/e13405c5-668b-4716-94b2-1c2654ca1ad4
+#[derive(Debug, Default)]
+pub struct SecurityHeader {
+    pub flags: u16,
+    pub flags_hi: u16,
+}
+
+impl SecurityHeader {
+    pub fn new(flags: u16) -> Self {
+        SecurityHeader { flags, flags_hi: 0 }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> Result<()> {
+        check_remaining(buffer, 4, "SEC: security header")?;
+        self.flags = buffer.get_u16_le();
+        self.flags_hi = buffer.get_u16_le();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Message for SecurityHeader {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.flags).await?;
+        writer.write_u16_le(self.flags_hi).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.flags = reader.read_u16_le().await?;
+        self.flags_hi = reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        4
+    }
+}
+
+/// Date of a daylight saving time transition
+/// MS-RDPBCGR 2.2.1.11.1.1.1.1.1 System Time (TS_SYSTEMTIME)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SystemTime {
+    pub year: u16,
+    pub month: u16,
+    pub day_of_week: u16,
+    pub day: u16,
+    pub hour: u16,
+    pub minute: u16,
+    pub second: u16,
+    pub milliseconds: u16,
+}
+
+#[async_trait]
+impl Message for SystemTime {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u16_le(self.year).await?;
+        writer.write_u16_le(self.month).await?;
+        writer.write_u16_le(self.day_of_week).await?;
+        writer.write_u16_le(self.day).await?;
+        writer.write_u16_le(self.hour).await?;
+        writer.write_u16_le(self.minute).await?;
+        writer.write_u16_le(self.second).await?;
+        writer.write_u16_le(self.milliseconds).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.year = reader.read_u16_le().await?;
+        self.month = reader.read_u16_le().await?;
+        self.day_of_week = reader.read_u16_le().await?;
+        self.day = reader.read_u16_le().await?;
+        self.hour = reader.read_u16_le().await?;
+        self.minute = reader.read_u16_le().await?;
+        self.second = reader.read_u16_le().await?;
+        self.milliseconds = reader.read_u16_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        16
+    }
+}
+
+/// Time zone information of the client
+/// Names are encoded on 32 utf-16 characters
+///
+/// Bias are in minutes with UTC = local time + bias
+/// MS-RDPBCGR 2.2.1.11.1.1.1.1 Time Zone Information (TS_TIME_ZONE_INFORMATION)
+#[derive(Debug, Default, Clone)]
+pub struct TimeZoneInformation {
+    pub bias: i32,
+    pub standard_name: String,
+    pub standard_date: SystemTime,
+    pub standard_bias: i32,
+    pub daylight_name: String,
+    pub daylight_date: SystemTime,
+    pub daylight_bias: i32,
+}
+
+impl TimeZoneInformation {
+    /// A time zone with a fixed offset
+    /// and without daylight saving time
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::sec::base::TimeZoneInformation;
+    /// // Central European Time
+    /// let time_zone = TimeZoneInformation::new("W. Europe Standard Time", -60);
+    /// assert_eq!(time_zone.bias, -60);
+    /// ```
+    pub fn new(name: &str, bias: i32) -> Self {
+        TimeZoneInformation {
+            bias,
+            standard_name: name.to_string(),
+            daylight_name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Coordinated Universal Time
+    pub fn utc() -> Self {
+        Self::new("UTC", 0)
+    }
+
+    /// Time zone of the local system
+    ///
+    /// Transition rules are not available
+    /// so the current offset is sent as a fixed bias
+    pub fn local() -> Self {
+        let offset = chrono::Local::now().offset().local_minus_utc() / 60;
+        if offset == 0 {
+            return Self::utc();
+        }
+        let sign = if offset < 0 { '-' } else { '+' };
+        let name = format!("UTC{}{:02}:{:02}", sign, offset.abs() / 60, offset.abs() % 60);
+        Self::new(&name, -offset)
+    }
+
+    /// Names are truncated to fit in the 64 bytes field
+    fn name_field(name: &str) -> [u8; 64] {
+        let mut result = [0; 64];
+        let unicode = name.to_unicode();
+        let length = unicode.len().min(62);
+        result[..length].copy_from_slice(&unicode[..length]);
+        result
+    }
+}
+
+#[async_trait]
+impl Message for TimeZoneInformation {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_i32_le(self.bias).await?;
+        writer.write_all(&Self::name_field(&self.standard_name)).await?;
+        self.standard_date.write_to(writer).await?;
+        writer.write_i32_le(self.standard_bias).await?;
+        writer.write_all(&Self::name_field(&self.daylight_name)).await?;
+        self.daylight_date.write_to(writer).await?;
+        writer.write_i32_le(self.daylight_bias).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut name = [0; 64];
+        self.bias = reader.read_i32_le().await?;
+        reader.read_exact(&mut name).await?;
+        self.standard_name = from_unicode(&name);
+        self.standard_date.read_from(reader).await?;
+        self.standard_bias = reader.read_i32_le().await?;
+        reader.read_exact(&mut name).await?;
+        self.daylight_name = from_unicode(&name);
+        self.daylight_date.read_from(reader).await?;
+        self.daylight_bias = reader.read_i32_le().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        172
+    }
+}
+
+/// Client random used for the auto reconnect verifier
+/// when there is no standard RDP security exchange
+pub const NULL_CLIENT_RANDOM: [u8; 32] = [0; 32];
+
+/// Proof that the client owns an auto reconnect cookie
+/// MS-RDPBCGR 2.2.4.3 Client Auto-Reconnect Packet (ARC_CS_PRIVATE_PACKET)
+///
+/// # Example
+/// ```
+/// use rdp::core::sec::base::{ClientAutoReconnect, NULL_CLIENT_RANDOM};
+/// let mut cookie = ClientAutoReconnect::new(2, [1; 16]);
+/// let verifier = cookie.security_verifier;
+/// cookie.sign(&[3; 32]);
+/// assert_ne!(cookie.security_verifier, verifier);
+/// cookie.sign(&NULL_CLIENT_RANDOM);
+/// assert_eq!(cookie.security_verifier, verifier);
+/// ```
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ClientAutoReconnect {
+    pub logon_id: u32,
+    /// Random of the cookie sent by the server
+    /// Never sent back, only used as the verifier key
+    arc_random: [u8; 16],
+    pub security_verifier: [u8; 16],
+}
+
+impl ClientAutoReconnect {
+    /// Build from the cookie of the previous connection
+    /// The verifier is signed with the null client random
+    pub fn new(logon_id: u32, arc_random: [u8; 16]) -> Self {
+        let mut cookie = ClientAutoReconnect {
+            logon_id,
+            arc_random,
+            security_verifier: [0; 16],
+        };
+        cookie.sign(&NULL_CLIENT_RANDOM);
+        cookie
+    }
+
+    /// The verifier is the HMAC-MD5 of the client random
+    /// keyed by the random of the cookie
+    /// MS-RDPBCGR 5.5 Automatic Reconnection
+    pub fn sign(&mut self, client_random: &[u8]) {
+        let mut mac =
+            Hmac::<Md5>::new_from_slice(&self.arc_random).expect("HMAC accepts keys of any size");
+        mac.update(client_random);
+        self.security_verifier
+            .copy_from_slice(&mac.finalize().into_bytes());
+    }
+}
+
+#[async_trait]
+impl Message for ClientAutoReconnect {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer.write_u32_le(self.length() as u32).await?;
+        // Version 1 is the only one defined
+        writer.write_u32_le(1).await?;
+        writer.write_u32_le(self.logon_id).await?;
+        writer.write_all(&self.security_verifier).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        reader.read_u32_le().await?;
+        reader.read_u32_le().await?;
+        self.logon_id = reader.read_u32_le().await?;
+        reader.read_exact(&mut self.security_verifier).await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        28
+    }
+}
+
+/// On RDP version > 5
+/// Client have to send IP information
+/// MS-RDPBCGR 2.2.1.11.1.1.1 Extended Info Packet (TS_EXTENDED_INFO_PACKET)
+#[derive(Debug, Clone)]
+pub struct ExtendedInfoPacket {
+    pub client_address_family: u16,
+    pub client_address: String,
+    pub client_dir: String,
+    pub client_time_zone: TimeZoneInformation,
+    pub client_session_id: u32,
+    pub performance_flags: u32,
+    /// Sent when reconnecting to a previous session
+    /// Not read back by servers
+    pub auto_reconnect_cookie: Option<ClientAutoReconnect>,
+}
+
+impl ExtendedInfoPacket {
+    /// Extended info with the time zone of the local system
+    pub fn new() -> Self {
+        ExtendedInfoPacket {
+            client_address_family: AfInet::AfInet as u16,
+            client_address: String::new(),
+            client_dir: String::new(),
+            client_time_zone: TimeZoneInformation::local(),
+            client_session_id: 0,
+            performance_flags: 0,
+            auto_reconnect_cookie: None,
+        }
+    }
+}
+
+impl Default for ExtendedInfoPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Message for ExtendedInfoPacket {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        // Both size include the mandatory null terminator
+        let client_address = unicode_null_terminated(&self.client_address);
+        let client_dir = unicode_null_terminated(&self.client_dir);
+
+        writer.write_u16_le(self.client_address_family).await?;
+        writer.write_u16_le(client_address.len() as u16).await?;
+        writer.write_all(&client_address).await?;
+        writer.write_u16_le(client_dir.len() as u16).await?;
+        writer.write_all(&client_dir).await?;
+        self.client_time_zone.write_to(writer).await?;
+        writer.write_u32_le(self.client_session_id).await?;
+        writer.write_u32_le(self.performance_flags).await?;
+        if let Some(cookie) = &self.auto_reconnect_cookie {
+            writer.write_u16_le(cookie.length() as u16).await?;
+            cookie.write_to(writer).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.client_address_family = reader.read_u16_le().await?;
+        let cb_client_address = reader.read_u16_le().await? as usize;
+        self.client_address = read_unicode(reader, cb_client_address.saturating_sub(2)).await?;
+        let cb_client_dir = reader.read_u16_le().await? as usize;
+        self.client_dir = read_unicode(reader, cb_client_dir.saturating_sub(2)).await?;
+        self.client_time_zone.read_from(reader).await?;
+        self.client_session_id = reader.read_u32_le().await?;
+        self.performance_flags = reader.read_u32_le().await?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        2 + 2
+            + unicode_null_terminated(&self.client_address).len()
+            + 2
+            + unicode_null_terminated(&self.client_dir).len()
+            + self.client_time_zone.length()
+            + 4
+            + 4
+            + self
+                .auto_reconnect_cookie
+                .as_ref()
+                .map_or(0, |cookie| 2 + cookie.length())
+    }
+}
+
+/// When CSSP is not used
+/// interactive logon used credentials
+/// present in this payload
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/732394f5-e2b5-4ac5-8a0a-35345386b0d1
+#[derive(Debug, Clone)]
+pub struct ClientInfoPdu {
+    pub code_page: u32,
+    pub flags: u32,
+    pub domain: String,
+    pub username: String,
+    pub password: String,
+    pub alternate_shell: String,
+    pub working_dir: String,
+    pub extended_info: Option<ExtendedInfoPacket>,
+}
+
+impl ClientInfoPdu {
+    /// Client info with the default set of flags
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::sec::base::{ClientInfoPdu, InfoFlag};
+    /// let info = ClientInfoPdu::new("domain", "user", "password", true);
+    /// assert_ne!(info.flags & InfoFlag::InfoAutologon as u32, 0);
+    /// ```
+    pub fn new(domain: &str, username: &str, password: &str, auto_logon: bool) -> Self {
+        ClientInfoPdu {
+            code_page: 0,
+            flags: InfoFlag::InfoMouse as u32
+                | InfoFlag::InfoUnicode as u32
+                | InfoFlag::InfoLogonnotify as u32
+                | InfoFlag::InfoLogonerrors as u32
+                | InfoFlag::InfoDisablectrlaltdel as u32
+                | InfoFlag::InfoEnablewindowskey as u32
+                | if auto_logon {
+                    InfoFlag::InfoAutologon as u32
+                } else {
+                    0
+                },
+            domain: domain.to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            alternate_shell: String::new(),
+            working_dir: String::new(),
+            extended_info: None,
+        }
+    }
+
+    /// Performance flags are sent in the extended info packet
+    /// which is created if needed
+    pub fn set_performance_flags(&mut self, flags: PerformanceFlags) {
+        self.extended_info
+            .get_or_insert_with(ExtendedInfoPacket::new)
+            .performance_flags = flags.bits();
+    }
+
+    /// Bulk compression the server may use for its data
+    /// The type is the highest one supported by the client
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::codec::bulk::CompressionType;
+    /// use rdp::core::sec::base::{ClientInfoPdu, InfoFlag};
+    /// let mut info = ClientInfoPdu::new("domain", "user", "password", true);
+    /// info.set_compression(CompressionType::PacketComprType64k);
+    /// assert_eq!(info.flags & InfoFlag::InfoCompressionTypeMask as u32, 0x200);
+    /// ```
+    pub fn set_compression(&mut self, compression_type: CompressionType) {
+        self.flags &= !(InfoFlag::InfoCompressionTypeMask as u32);
+        self.flags |= InfoFlag::InfoCompression as u32 | ((compression_type as u32) << 9);
+    }
+
+    /// Time zone reported to the server
+    /// Default is the one of the local system
+    pub fn set_time_zone(&mut self, time_zone: TimeZoneInformation) {
+        self.extended_info
+            .get_or_insert_with(ExtendedInfoPacket::new)
+            .client_time_zone = time_zone;
+    }
+
+    /// Address of the client reported to the server
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::sec::base::ClientInfoPdu;
+    /// let mut info = ClientInfoPdu::new("domain", "user", "password", true);
+    /// info.set_client_address("192.168.1.10".parse().unwrap());
+    /// assert_eq!(info.extended_info.unwrap().client_address, "192.168.1.10");
+    /// ```
+    pub fn set_client_address(&mut self, address: IpAddr) {
+        let extended_info = self.extended_info.get_or_insert_with(ExtendedInfoPacket::new);
+        extended_info.client_address_family = match address {
+            IpAddr::V4(_) => AfInet::AfInet as u16,
+            IpAddr::V6(_) => AfInet::AfInet6 as u16,
+        };
+        extended_info.client_address = address.to_string();
+    }
+
+    /// Path of the client executable reported to the server
+    pub fn set_client_dir(&mut self, client_dir: &str) {
+        self.extended_info
+            .get_or_insert_with(ExtendedInfoPacket::new)
+            .client_dir = client_dir.to_string();
+    }
+
+    /// Reconnect to the session of a previous connection
+    /// The cookie is the one sent by the server in the save session info
+    pub fn set_auto_reconnect_cookie(&mut self, logon_id: u32, arc_random: [u8; 16]) {
+        self.extended_info
+            .get_or_insert_with(ExtendedInfoPacket::new)
+            .auto_reconnect_cookie = Some(ClientAutoReconnect::new(logon_id, arc_random));
+    }
+
+    /// Set or unset an info flag
+    pub fn set_flag(&mut self, flag: InfoFlag, value: bool) {
+        if value {
+            self.flags |= flag as u32;
+        } else {
+            self.flags &= !(flag as u32);
+        }
+    }
+}
+
+#[async_trait]
+impl Message for ClientInfoPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        let domain = unicode_null_terminated(&self.domain);
+        let username = unicode_null_terminated(&self.username);
+        let password = unicode_null_terminated(&self.password);
+        let alternate_shell = unicode_null_terminated(&self.alternate_shell);
+        let working_dir = unicode_null_terminated(&self.working_dir);
+
+        writer.write_u32_le(self.code_page).await?;
+        writer.write_u32_le(self.flags).await?;
+        // Size fields don't include the null terminator
+        writer.write_u16_le((domain.len() - 2) as u16).await?;
+        writer.write_u16_le((username.len() - 2) as u16).await?;
+        writer.write_u16_le((password.len() - 2) as u16).await?;
+        writer.write_u16_le((alternate_shell.len() - 2) as u16).await?;
+        writer.write_u16_le((working_dir.len() - 2) as u16).await?;
+        writer.write_all(&domain).await?;
+        writer.write_all(&username).await?;
+        writer.write_all(&password).await?;
+        writer.write_all(&alternate_shell).await?;
+        writer.write_all(&working_dir).await?;
+        if let Some(extended_info) = &self.extended_info {
+            extended_info.write_to(writer).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        self.code_page = reader.read_u32_le().await?;
+        self.flags = reader.read_u32_le().await?;
+        let cb_domain = reader.read_u16_le().await? as usize;
+        let cb_username = reader.read_u16_le().await? as usize;
+        let cb_password = reader.read_u16_le().await? as usize;
+        let cb_alternate_shell = reader.read_u16_le().await? as usize;
+        let cb_working_dir = reader.read_u16_le().await? as usize;
+        self.domain = read_unicode(reader, cb_domain).await?;
+        self.username = read_unicode(reader, cb_username).await?;
+        self.password = read_unicode(reader, cb_password).await?;
+        self.alternate_shell = read_unicode(reader, cb_alternate_shell).await?;
+        self.working_dir = read_unicode(reader, cb_working_dir).await?;
+        if let Some(extended_info) = &mut self.extended_info {
+            extended_info.read_from(reader).await?;
+        }
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        18 + unicode_null_terminated(&self.domain).len()
+            + unicode_null_terminated(&self.username).len()
+            + unicode_null_terminated(&self.password).len()
+            + unicode_null_terminated(&self.alternate_shell).len()
+            + unicode_null_terminated(&self.working_dir).len()
+            + self.extended_info.as_ref().map_or(0, |e| e.length())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Test of the client info format
+    #[tokio::test]
+    async fn test_client_info_pdu() {
+        let info = ClientInfoPdu::new("", "a", "", false);
+        let buffer = to_vec(&info).await.unwrap();
+        assert_eq!(buffer.len(), info.length());
+        assert_eq!(
+            buffer,
+            [
+                0, 0, 0, 0, 83, 1, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 97, 0, 0, 0, 0, 0, 0,
+                0, 0, 0
+            ]
+        );
+    }
+
+    /// Extended info must be a valid round trip
+    #[tokio::test]
+    async fn test_client_info_pdu_extended() {
+        let mut info = ClientInfoPdu::new("domain", "user", "password", true);
+        info.working_dir = "C:\\".to_string();
+        info.extended_info = Some(ExtendedInfoPacket::new());
+        info.extended_info.as_mut().unwrap().client_address = "127.0.0.1".to_string();
+        let buffer = to_vec(&info).await.unwrap();
+        assert_eq!(buffer.len(), info.length());
+
+        let mut result = ClientInfoPdu::new("", "", "", false);
+        result.extended_info = Some(ExtendedInfoPacket::new());
+        result.read_from(&mut &buffer[..]).await.unwrap();
+        assert_eq!(result.flags, info.flags);
+        assert_eq!(result.domain, "domain");
+        assert_eq!(result.username, "user");
+        assert_eq!(result.password, "password");
+        assert_eq!(result.working_dir, "C:\\");
+        assert_eq!(result.extended_info.unwrap().client_address, "127.0.0.1");
+    }
+
+    /// Performance flags are encoded at the end of the extended info
+    #[tokio::test]
+    async fn test_client_info_pdu_performance_flags() {
+        let mut info = ClientInfoPdu::new("", "", "", false);
+        info.set_performance_flags(PerformanceFlags::low_bandwidth());
+        let buffer = to_vec(&info).await.unwrap();
+        assert_eq!(&buffer[buffer.len() - 4..], [0x6f, 0, 0, 0]);
+    }
+
+    /// Bias is a signed value and names are null padded
+    #[tokio::test]
+    async fn test_time_zone_information() {
+        let time_zone = TimeZoneInformation::new("CET", -60);
+        let buffer = to_vec(&time_zone).await.unwrap();
+        assert_eq!(buffer.len(), 172);
+        assert_eq!(buffer[0..4], [0xc4, 0xff, 0xff, 0xff]);
+        assert_eq!(buffer[4..12], [b'C', 0, b'E', 0, b'T', 0, 0, 0]);
+
+        let mut result = TimeZoneInformation::default();
+        result.read_from(&mut &buffer[..]).await.unwrap();
+        assert_eq!(result.bias, -60);
+        assert_eq!(result.standard_name, "CET");
+        assert_eq!(result.daylight_name, "CET");
+    }
+
+    /// Address family follows the client address
+    #[test]
+    fn test_client_address_ipv6() {
+        let mut info = ClientInfoPdu::new("", "", "", false);
+        info.set_client_address("::1".parse().unwrap());
+        info.set_client_dir("C:\\mstsc.exe");
+        let extended_info = info.extended_info.unwrap();
+        assert_eq!(extended_info.client_address_family, AfInet::AfInet6 as u16);
+        assert_eq!(extended_info.client_address, "::1");
+        assert_eq!(extended_info.client_dir, "C:\\mstsc.exe");
+    }
+
+    /// The reconnect cookie is appended to the extended info
+    #[tokio::test]
+    async fn test_client_info_pdu_auto_reconnect() {
+        let mut info = ClientInfoPdu::new("", "", "", false);
+        info.set_auto_reconnect_cookie(0x1234, [1; 16]);
+        let buffer = to_vec(&info).await.unwrap();
+        assert_eq!(buffer.len(), info.length());
+        let cookie = &buffer[buffer.len() - 30..];
+        assert_eq!(
+            cookie[..14],
+            [28, 0, 28, 0, 0, 0, 1, 0, 0, 0, 0x34, 0x12, 0, 0]
+        );
+        assert_ne!(cookie[14..], [0; 16]);
+    }
+}
@@ -0,0 +1,415 @@
+use crate::core::autodetect::{read_auto_detect_request, AutoDetector, NetworkCharacteristics};
+use crate::core::license::{read_license_message, LicenseMessage};
+#[cfg(feature = "legacy-security")]
+use crate::core::license::{write_license_message, LicenseContext, MessageType};
+use crate::core::mcs::client::McsClient;
+use crate::core::sec::base::{ClientInfoPdu, ExtendedInfoPacket, SecurityFlag, SecurityHeader};
+#[cfg(feature = "legacy-security")]
+use crate::core::sec::legacy::{
+    read_server_certificate, rsa_encrypt, LegacySecurity, SecurityExchangePdu,
+    FASTPATH_INPUT_ENCRYPTED,
+};
+use crate::core::tpkt::base::Payload;
+use crate::core::tpkt::queue::WritePriority;
+use crate::model::data::{check_remaining, to_vec, Message};
+#[cfg(feature = "legacy-security")]
+use crate::model::rnd::random;
+
+use bytes::BytesMut;
+use std::io::{Error, ErrorKind, Result};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Security layer need mcs layer and send all message through
+/// the global channel
+///
+/// This layer is called sec because old RDP security
+/// was made here
+pub struct SecClient<S> {
+    /// MCS transport layer
+    mcs: McsClient<S>,
+    /// Standard RDP security context
+    /// Only set when the server asks for encryption
+    #[cfg(feature = "legacy-security")]
+    legacy: Option<LegacySecurity>,
+    /// Answer the network auto detect requests
+    auto_detector: AutoDetector,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> SecClient<S> {
+    /// Send the client info PDU which carries
+    /// the credentials used for the interactive logon
+    ///
+    /// Extended info are only sent
+    /// when the server use RDP version 5+
+    ///
+    /// When the server selects an encryption method
+    /// the security exchange is done first
+    /// which needs the `legacy-security` feature
+    ///
+    /// The connection ends with the licensing phase
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let info = ClientInfoPdu::new("domain", "username", "password", true);
+    /// let sec = SecClient::connect(mcs, info).await?;
+    /// ```
+    #[cfg_attr(feature = "trace", tracing::instrument(name = "security", skip_all))]
+    pub async fn connect(mcs: McsClient<S>, mut info: ClientInfoPdu) -> Result<SecClient<S>> {
+        if !mcs.is_rdp_version_5_plus() {
+            info.extended_info = None;
+        } else if info.extended_info.is_none() {
+            info.extended_info = Some(ExtendedInfoPacket::new());
+        }
+
+        let encryption_method = mcs.get_server_data().security.encryption_method;
+
+        #[cfg(feature = "legacy-security")]
+        let legacy = if encryption_method != 0 {
+            let (legacy, client_random) = Self::security_exchange(&mut mcs).await?;
+            // The reconnect verifier proves the cookie against this random
+            if let Some(cookie) = info
+                .extended_info
+                .as_mut()
+                .and_then(|extended_info| extended_info.auto_reconnect_cookie.as_mut())
+            {
+                cookie.sign(&client_random);
+            }
+            Some(legacy)
+        } else {
+            None
+        };
+
+        #[cfg(not(feature = "legacy-security"))]
+        if encryption_method != 0 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "SEC: standard RDP security needs the legacy-security feature",
+            ));
+        }
+
+        let mut client = SecClient {
+            mcs,
+            #[cfg(feature = "legacy-security")]
+            legacy,
+            auto_detector: AutoDetector::default(),
+        };
+        client
+            .write_with_flags("global", SecurityFlag::SecInfoPkt as u16, to_vec(&info).await?)
+            .await?;
+        client.read_license(&info.username).await?;
+
+        Ok(client)
+    }
+
+    /// Read a licensing PDU
+    /// Licensing PDUs always have a security header
+    async fn read_license_payload(&mut self) -> Result<BytesMut> {
+        let mut payload = loop {
+            match self.mcs.read().await? {
+                // Connect time auto detection runs before licensing
+                (channel_name, Payload::Raw(payload)) if channel_name == "message" => {
+                    self.read_message(payload).await?;
+                }
+                (_, Payload::Raw(payload)) => break payload,
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "SEC: unexpected fast path payload during licensing",
+                    ))
+                }
+            }
+        };
+
+        let mut header = SecurityHeader::default();
+        header.read_from_buffer(&mut payload)?;
+
+        #[cfg(feature = "legacy-security")]
+        if header.flags & SecurityFlag::SecEncrypt as u16 != 0 {
+            if let Some(legacy) = &mut self.legacy {
+                payload = legacy.decrypt(payload)?;
+            }
+        }
+
+        if header.flags & SecurityFlag::SecLicensePkt as u16 == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "SEC: Invalid Licence packet"));
+        }
+        Ok(payload)
+    }
+
+    /// Licensing PDUs sent by the client are never encrypted
+    #[cfg(feature = "legacy-security")]
+    async fn write_license(&mut self, payload: Vec<u8>) -> Result<()> {
+        let mut buffer = to_vec(&SecurityHeader::new(SecurityFlag::SecLicensePkt as u16)).await?;
+        buffer.extend(payload);
+        self.mcs.write("global", buffer).await
+    }
+
+    /// Licensing phase
+    ///
+    /// Most of the time the server send a valid client error alert
+    /// The full license negotiation needs the `legacy-security` feature
+    /// The license sent by the server is not stored
+    #[cfg_attr(not(feature = "legacy-security"), allow(unused_variables))]
+    async fn read_license(&mut self, user_name: &str) -> Result<()> {
+        #[cfg(feature = "legacy-security")]
+        let mut context: Option<LicenseContext> = None;
+
+        loop {
+            let mut payload = self.read_license_payload().await?;
+            match read_license_message(&mut payload)? {
+                LicenseMessage::NewLicense | LicenseMessage::UpgradeLicense => return Ok(()),
+                LicenseMessage::ErrorAlert(message) => {
+                    return if message.is_valid_client() {
+                        Ok(())
+                    } else {
+                        Err(Error::new(
+                            ErrorKind::PermissionDenied,
+                            format!(
+                                "SEC: Server reject license with error code {}",
+                                message.error_code
+                            ),
+                        ))
+                    }
+                }
+                #[cfg(feature = "legacy-security")]
+                LicenseMessage::LicenseRequest(request) => {
+                    // Without certificate in the request
+                    // the one of the security exchange is used
+                    let certificate = if request.server_certificate.data.is_empty() {
+                        &self.mcs.get_server_data().security.server_certificate
+                    } else {
+                        &request.server_certificate.data
+                    };
+                    let public_key = read_server_certificate(certificate)?;
+                    let license = LicenseContext::new(&request.server_random);
+                    let message = license.new_license_request(
+                        &public_key,
+                        user_name,
+                        self.mcs.get_client_name(),
+                    );
+                    self.write_license(
+                        write_license_message(MessageType::NewLicenseRequest, &message).await?,
+                    )
+                    .await?;
+                    context = Some(license);
+                }
+                #[cfg(feature = "legacy-security")]
+                LicenseMessage::PlatformChallenge(challenge) => {
+                    let license = context.as_ref().ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            "SEC: platform challenge without license request",
+                        )
+                    })?;
+                    let message = license
+                        .platform_challenge_response(&challenge, self.mcs.get_client_name())?;
+                    self.write_license(
+                        write_license_message(MessageType::PlatformChallengeResponse, &message)
+                            .await?,
+                    )
+                    .await?;
+                }
+                #[cfg(not(feature = "legacy-security"))]
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        "SEC: license negotiation needs the legacy-security feature",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Send the client random encrypted with the server public key
+    /// and derive the session keys
+    /// The client random is returned to sign the reconnect cookie
+    #[cfg(feature = "legacy-security")]
+    async fn security_exchange(mcs: &mut McsClient<S>) -> Result<(LegacySecurity, Vec<u8>)> {
+        let security = &mcs.get_server_data().security;
+        let public_key = read_server_certificate(&security.server_certificate)?;
+        let client_random = random(32);
+        let legacy = LegacySecurity::new(
+            &client_random,
+            &security.server_random,
+            security.encryption_method,
+        )?;
+
+        let exchange = SecurityExchangePdu::new(rsa_encrypt(&client_random, &public_key));
+        let mut buffer = to_vec(&SecurityHeader::new(SecurityFlag::SecExchangePkt as u16)).await?;
+        buffer.extend(to_vec(&exchange).await?);
+        mcs.write("global", buffer).await?;
+
+        Ok((legacy, client_random))
+    }
+
+    /// Send a payload with a security header
+    /// The header is omitted when no flag nor encryption is needed
+    pub async fn write_with_flags(
+        &mut self,
+        channel_name: &str,
+        flags: u16,
+        payload: Vec<u8>,
+    ) -> Result<()> {
+        self.write_with_priority(channel_name, flags, payload, WritePriority::Low)
+            .await
+    }
+
+    /// Send a payload with a priority in the write queue
+    pub async fn write_with_priority(
+        &mut self,
+        channel_name: &str,
+        flags: u16,
+        payload: Vec<u8>,
+        priority: WritePriority,
+    ) -> Result<()> {
+        #[cfg(feature = "legacy-security")]
+        if let Some(legacy) = &mut self.legacy {
+            let encrypted = legacy.encrypt(&payload);
+            let header = SecurityHeader::new(flags | SecurityFlag::SecEncrypt as u16);
+            let mut buffer = to_vec(&header).await?;
+            buffer.extend(encrypted);
+            return self.mcs.write_with_priority(channel_name, buffer, priority).await;
+        }
+
+        if flags == 0 {
+            return self.mcs.write_with_priority(channel_name, payload, priority).await;
+        }
+
+        let mut buffer = to_vec(&SecurityHeader::new(flags)).await?;
+        buffer.extend(payload);
+        self.mcs.write_with_priority(channel_name, buffer, priority).await
+    }
+
+    /// Send a message to a channel
+    /// Without legacy security no header is needed
+    pub async fn write<T>(&mut self, channel_name: &str, message: T) -> Result<()>
+    where
+        T: Message,
+    {
+        let payload = to_vec(&message).await?;
+        self.write_with_flags(channel_name, 0, payload).await
+    }
+
+    /// Send a fast path message
+    /// With legacy security the payload is signed and encrypted
+    pub async fn write_fast_path<T>(&mut self, message: T) -> Result<()>
+    where
+        T: Message,
+    {
+        let payload = to_vec(&message).await?;
+
+        #[cfg(feature = "legacy-security")]
+        if let Some(legacy) = &mut self.legacy {
+            let encrypted = legacy.encrypt(&payload);
+            return self.mcs.write_fast_path(FASTPATH_INPUT_ENCRYPTED, encrypted).await;
+        }
+
+        self.mcs.write_fast_path(0, payload).await
+    }
+
+    /// Read the next message of any channel
+    /// Payloads are decrypted when legacy security is used
+    ///
+    /// Message channel payloads keep their security header
+    /// because its flags give the type of the PDU
+    pub async fn read(&mut self) -> Result<(String, Payload)> {
+        let (channel_name, payload) = self.receive().await?;
+        self.process(channel_name, payload).await
+    }
+
+    /// Wait for the next message of any channel
+    /// Cancel safe, the message is given to `process` once received
+    pub async fn receive(&mut self) -> Result<(String, Payload)> {
+        self.mcs.read().await
+    }
+
+    /// Decrypt a received message and answer the auto detect requests
+    /// It can write to the server so it must not be cancelled
+    pub async fn process(
+        &mut self,
+        channel_name: String,
+        payload: Payload,
+    ) -> Result<(String, Payload)> {
+        let payload = match payload {
+            Payload::Raw(payload) if channel_name == "message" => {
+                return Ok((channel_name, Payload::Raw(self.read_message(payload).await?)))
+            }
+            payload => payload,
+        };
+
+        #[cfg(feature = "legacy-security")]
+        if let Some(legacy) = &mut self.legacy {
+            return Ok((channel_name, legacy.read_payload(payload)?));
+        }
+
+        Ok((channel_name, payload))
+    }
+
+    /// Decrypt a message channel payload
+    /// and answer the auto detect requests
+    ///
+    /// The security header is kept without the encrypt flag
+    async fn read_message(&mut self, mut payload: BytesMut) -> Result<BytesMut> {
+        check_remaining(&payload, 4, "SEC: security header")?;
+        let mut result = payload.split_to(4);
+        let header = SecurityHeader {
+            flags: u16::from_le_bytes([result[0], result[1]]),
+            flags_hi: u16::from_le_bytes([result[2], result[3]]),
+        };
+
+        #[cfg(feature = "legacy-security")]
+        if header.flags & SecurityFlag::SecEncrypt as u16 != 0 {
+            if let Some(legacy) = &mut self.legacy {
+                payload = legacy.decrypt(payload)?;
+            }
+        }
+
+        if header.flags & SecurityFlag::SecAutodetectReq as u16 != 0 {
+            let request = read_auto_detect_request(&mut payload.clone())?;
+            if let Some(response) = self.auto_detector.process(request, Instant::now()) {
+                let flags = SecurityFlag::SecAutodetectRsp as u16;
+                self.write_with_flags("message", flags, to_vec(&response).await?).await?;
+            }
+
+            // Average measured by the server
+            if let Some(rtt) = self.auto_detector.get_network_characteristics().average_rtt {
+                self.mcs.get_metrics().set_rtt(rtt);
+            }
+        }
+
+        // The header is rewritten in place and joined back to the payload,
+        // which is only copied when the decryption gave a new buffer
+        let flags = header.flags & !(SecurityFlag::SecEncrypt as u16);
+        result[..2].copy_from_slice(&flags.to_le_bytes());
+        result.unsplit(payload);
+        Ok(result)
+    }
+
+    /// Network characteristics measured by the server
+    pub fn get_network_characteristics(&self) -> NetworkCharacteristics {
+        self.auto_detector.get_network_characteristics()
+    }
+
+    /// Getter of the underlying MCS layer
+    pub fn get_mcs(&self) -> &McsClient<S> {
+        &self.mcs
+    }
+
+    /// Send a close event to server
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.mcs.shutdown().await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> SecClient<S> {
+    /// Write the next PDUs from a task of their own
+    /// PDUs encrypted with legacy security are all sent in order
+    pub fn start_write_queue(&mut self, capacity: usize) {
+        #[cfg(feature = "legacy-security")]
+        let prioritized = self.legacy.is_none();
+        #[cfg(not(feature = "legacy-security"))]
+        let prioritized = true;
+        self.mcs.start_write_queue(capacity, prioritized);
+    }
+}
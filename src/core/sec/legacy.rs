@@ -0,0 +1,448 @@
+use crate::core::gcc::EncryptionMethod;
+use crate::core::sec::base::{SecurityFlag, SecurityHeader};
+use crate::core::tpkt::base::Payload;
+use crate::model::data::{check_remaining, Message};
+use crate::nla::rc4::Rc4;
+
+use async_trait::async_trait;
+use bytes::{Buf, Bytes, BytesMut};
+use md5::{Digest, Md5};
+use num_bigint::BigUint;
+use sha1::Sha1;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const PAD_54: [u8; 40] = [0x36; 40];
+const PAD_92: [u8; 48] = [0x5c; 48];
+
+/// Magic of the RSA public key blob
+const RSA1_MAGIC: u32 = 0x31415352;
+
+/// Keys are renewed after this number of packets
+const KEY_UPDATE_COUNT: u32 = 4096;
+
+/// Fast path payload is encrypted
+const FASTPATH_OUTPUT_ENCRYPTED: u8 = 0x2;
+/// Fast path input is encrypted
+pub const FASTPATH_INPUT_ENCRYPTED: u8 = 0x2;
+
+/// Type of certificate sent by the server
+/// MS-RDPBCGR 2.2.1.4.3.1 Server Certificate (SERVER_CERTIFICATE)
+#[repr(u32)]
+#[allow(dead_code)]
+enum CertificateChainVersion {
+    CertChainVersion1 = 0x00000001,
+    CertChainVersion2 = 0x00000002,
+}
+
+/// RSA public key of the server
+/// Modulus is stored in little endian
+/// without the trailing padding
+#[derive(Debug, Clone)]
+pub struct ServerPublicKey {
+    pub exponent: u32,
+    pub modulus: Vec<u8>,
+}
+
+/// Read the server proprietary certificate
+/// and extract the public key
+///
+/// X.509 certificate chains are not supported
+/// The certificate signature is not checked
+/// MS-RDPBCGR 2.2.1.4.3.1.1 Server Proprietary Certificate (PROPRIETARYSERVERCERTIFICATE)
+pub fn read_server_certificate(certificate: &[u8]) -> Result<ServerPublicKey> {
+    let mut buffer = BytesMut::from(certificate);
+    check_remaining(&buffer, 4, "SEC: server certificate")?;
+    let version = buffer.get_u32_le() & 0x7fffffff;
+    if version != CertificateChainVersion::CertChainVersion1 as u32 {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "SEC: only proprietary server certificate are supported",
+        ));
+    }
+
+    // dwSigAlgId, dwKeyAlgId, wPublicKeyBlobType
+    check_remaining(&buffer, 12, "SEC: server certificate")?;
+    buffer.advance(10);
+    let blob_length = buffer.get_u16_le() as usize;
+    check_remaining(&buffer, blob_length, "SEC: server certificate")?;
+    let mut blob = buffer.split_to(blob_length);
+
+    check_remaining(&blob, 20, "SEC: server public key")?;
+    if blob.get_u32_le() != RSA1_MAGIC {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "SEC: invalid server public key magic",
+        ));
+    }
+    let key_length = blob.get_u32_le() as usize;
+    let _bit_length = blob.get_u32_le();
+    let _data_length = blob.get_u32_le();
+    let exponent = blob.get_u32_le();
+
+    if key_length < 8 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "SEC: invalid server public key length",
+        ));
+    }
+    check_remaining(&blob, key_length, "SEC: server public key")?;
+
+    Ok(ServerPublicKey {
+        exponent,
+        modulus: blob.split_to(key_length - 8).to_vec(),
+    })
+}
+
+/// Encrypt a random with the server public key
+/// The result is followed by the 8 bytes of padding
+pub fn rsa_encrypt(data: &[u8], key: &ServerPublicKey) -> Vec<u8> {
+    let modulus = BigUint::from_bytes_le(&key.modulus);
+    let mut result = BigUint::from_bytes_le(data)
+        .modpow(&BigUint::from(key.exponent), &modulus)
+        .to_bytes_le();
+    result.resize(key.modulus.len() + 8, 0);
+    result
+}
+
+/// Security exchange PDU
+/// Send the encrypted client random to the server
+/// MS-RDPBCGR 2.2.1.10.1 Security Exchange PDU Data (TS_SECURITY_PACKET)
+pub struct SecurityExchangePdu {
+    pub encrypted_client_random: Vec<u8>,
+}
+
+impl SecurityExchangePdu {
+    pub fn new(encrypted_client_random: Vec<u8>) -> Self {
+        SecurityExchangePdu {
+            encrypted_client_random,
+        }
+    }
+}
+
+#[async_trait]
+impl Message for SecurityExchangePdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        writer
+            .write_u32_le(self.encrypted_client_random.len() as u32)
+            .await?;
+        writer.write_all(&self.encrypted_client_random).await?;
+        Ok(())
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let length = reader.read_u32_le().await? as usize;
+        self.encrypted_client_random = vec![0; length];
+        reader.read_exact(&mut self.encrypted_client_random).await?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        4 + self.encrypted_client_random.len()
+    }
+}
+
+/// SaltedHash(S, I) = MD5(S + SHA(I + S + ClientRandom + ServerRandom))
+/// Licensing swap the order of the randoms
+pub(crate) fn salted_hash(
+    s: &[u8],
+    i: &[u8],
+    first_random: &[u8],
+    second_random: &[u8],
+) -> Vec<u8> {
+    let mut sha1 = Sha1::new();
+    sha1.update(i);
+    sha1.update(s);
+    sha1.update(first_random);
+    sha1.update(second_random);
+
+    let mut md5 = Md5::new();
+    md5.update(s);
+    md5.update(sha1.finalize());
+    md5.finalize().to_vec()
+}
+
+/// Concatenation of the salted hash of "A", "BB", "CCC"
+/// or "X", "YY", "ZZZ" depending of the salt
+pub(crate) fn hash_48(
+    s: &[u8],
+    salt: u8,
+    first_random: &[u8],
+    second_random: &[u8],
+) -> Vec<u8> {
+    (1..=3)
+        .flat_map(|i| {
+            let i_salt = vec![salt + i - 1; i as usize];
+            salted_hash(s, &i_salt, first_random, second_random)
+        })
+        .collect()
+}
+
+/// FinalHash(K) = MD5(K + ClientRandom + ServerRandom)
+pub(crate) fn final_hash(k: &[u8], client_random: &[u8], server_random: &[u8]) -> Vec<u8> {
+    let mut md5 = Md5::new();
+    md5.update(k);
+    md5.update(client_random);
+    md5.update(server_random);
+    md5.finalize().to_vec()
+}
+
+/// Reduce the entropy of a key
+/// depending of the negotiated encryption method
+fn reduce_key(key: &mut Vec<u8>, encryption_method: u32) {
+    if encryption_method == EncryptionMethod::EncryptionFlag40bit as u32 {
+        key.truncate(8);
+        key[..3].copy_from_slice(&[0xd1, 0x26, 0x9e]);
+    } else if encryption_method == EncryptionMethod::EncryptionFlag56bit as u32 {
+        key.truncate(8);
+        key[0] = 0xd1;
+    }
+}
+
+/// Compute the MAC of a message
+/// MS-RDPBCGR 5.3.6.1 Non-FIPS
+pub(crate) fn mac_data(mac_key: &[u8], data: &[u8]) -> [u8; 16] {
+    let mut sha1 = Sha1::new();
+    sha1.update(mac_key);
+    sha1.update(PAD_54);
+    sha1.update((data.len() as u32).to_le_bytes());
+    sha1.update(data);
+
+    let mut md5 = Md5::new();
+    md5.update(mac_key);
+    md5.update(PAD_92);
+    md5.update(sha1.finalize());
+
+    let mut result = [0; 16];
+    result.copy_from_slice(&md5.finalize());
+    result
+}
+
+/// Signature of a PDU is the first 8 bytes of the MAC
+fn mac_signature(mac_key: &[u8], data: &[u8]) -> [u8; 8] {
+    let mut signature = [0; 8];
+    signature.copy_from_slice(&mac_data(mac_key, data)[..8]);
+    signature
+}
+
+/// Session key update after 4096 packets
+/// MS-RDPBCGR 5.3.7 Session Key Updates
+fn update_key(initial_key: &[u8], current_key: &[u8], encryption_method: u32) -> Vec<u8> {
+    let mut sha1 = Sha1::new();
+    sha1.update(initial_key);
+    sha1.update(PAD_54);
+    sha1.update(current_key);
+
+    let mut md5 = Md5::new();
+    md5.update(initial_key);
+    md5.update(PAD_92);
+    md5.update(sha1.finalize());
+    let temp_key = md5.finalize()[..initial_key.len()].to_vec();
+
+    let mut result = vec![0; temp_key.len()];
+    Rc4::new(&temp_key).process(&temp_key, &mut result);
+    reduce_key(&mut result, encryption_method);
+    result
+}
+
+/// One direction of the RC4 stream
+struct Rc4Stream {
+    initial_key: Vec<u8>,
+    current_key: Vec<u8>,
+    rc4: Rc4,
+    count: u32,
+}
+
+impl Rc4Stream {
+    fn new(key: Vec<u8>) -> Self {
+        Rc4Stream {
+            rc4: Rc4::new(&key),
+            initial_key: key.clone(),
+            current_key: key,
+            count: 0,
+        }
+    }
+
+    fn process(&mut self, data: &[u8], encryption_method: u32) -> Vec<u8> {
+        if self.count == KEY_UPDATE_COUNT {
+            self.current_key = update_key(&self.initial_key, &self.current_key, encryption_method);
+            self.rc4 = Rc4::new(&self.current_key);
+            self.count = 0;
+        }
+
+        let mut result = vec![0; data.len()];
+        self.rc4.process(data, &mut result);
+        self.count += 1;
+        result
+    }
+}
+
+/// Standard RDP security context
+/// Hold the session keys derived from
+/// the client and server random
+///
+/// MS-RDPBCGR 5.3 Standard RDP Security
+pub struct LegacySecurity {
+    encryption_method: u32,
+    mac_key: Vec<u8>,
+    encrypt: Rc4Stream,
+    decrypt: Rc4Stream,
+}
+
+impl LegacySecurity {
+    /// Derive all session keys
+    /// MS-RDPBCGR 5.3.5.1 Non-FIPS
+    pub fn new(
+        client_random: &[u8],
+        server_random: &[u8],
+        encryption_method: u32,
+    ) -> Result<Self> {
+        if encryption_method != EncryptionMethod::EncryptionFlag40bit as u32
+            && encryption_method != EncryptionMethod::EncryptionFlag56bit as u32
+            && encryption_method != EncryptionMethod::EncryptionFlag128bit as u32
+        {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("SEC: unsupported encryption method {}", encryption_method),
+            ));
+        }
+
+        if client_random.len() < 24 || server_random.len() < 24 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "SEC: client and server random must be at least 24 bytes",
+            ));
+        }
+
+        let pre_master_secret = [&client_random[..24], &server_random[..24]].concat();
+        let master_secret = hash_48(&pre_master_secret, b'A', client_random, server_random);
+        let session_key_blob = hash_48(&master_secret, b'X', client_random, server_random);
+
+        let mut mac_key = session_key_blob[..16].to_vec();
+        let mut decrypt_key = final_hash(&session_key_blob[16..32], client_random, server_random);
+        let mut encrypt_key = final_hash(&session_key_blob[32..48], client_random, server_random);
+
+        reduce_key(&mut mac_key, encryption_method);
+        reduce_key(&mut decrypt_key, encryption_method);
+        reduce_key(&mut encrypt_key, encryption_method);
+
+        Ok(LegacySecurity {
+            encryption_method,
+            mac_key,
+            encrypt: Rc4Stream::new(encrypt_key),
+            decrypt: Rc4Stream::new(decrypt_key),
+        })
+    }
+
+    /// Sign then encrypt a client message
+    /// Return the signature followed by the encrypted data
+    pub fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut result = mac_signature(&self.mac_key, data).to_vec();
+        result.extend(self.encrypt.process(data, self.encryption_method));
+        result
+    }
+
+    /// Decrypt a server message and check its signature
+    /// The input start with the 8 bytes signature
+    pub fn decrypt(&mut self, mut data: BytesMut) -> Result<BytesMut> {
+        check_remaining(&data, 8, "SEC: MAC signature")?;
+        let signature = data.split_to(8);
+        let result = self.decrypt.process(&data, self.encryption_method);
+        if signature[..] != mac_signature(&self.mac_key, &result) {
+            return Err(Error::new(ErrorKind::InvalidData, "SEC: invalid MAC signature"));
+        }
+        Ok(Bytes::from(result).into())
+    }
+
+    /// Remove the security layer of a payload sent by the server
+    /// Slow path payloads always start with a security header
+    pub fn read_payload(&mut self, payload: Payload) -> Result<Payload> {
+        match payload {
+            Payload::Raw(mut payload) => {
+                let mut header = SecurityHeader::default();
+                header.read_from_buffer(&mut payload)?;
+                if header.flags & SecurityFlag::SecEncrypt as u16 != 0 {
+                    Ok(Payload::Raw(self.decrypt(payload)?))
+                } else {
+                    Ok(Payload::Raw(payload))
+                }
+            }
+            Payload::FastPath(sec_flag, payload) => {
+                if sec_flag & FASTPATH_OUTPUT_ENCRYPTED != 0 {
+                    Ok(Payload::FastPath(sec_flag, self.decrypt(payload)?))
+                } else {
+                    Ok(Payload::FastPath(sec_flag, payload))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// RSA with a tiny key
+    #[test]
+    fn test_rsa_encrypt() {
+        let key = ServerPublicKey {
+            exponent: 17,
+            modulus: vec![0xa1, 0x0c],
+        };
+        assert_eq!(
+            rsa_encrypt(&[65, 0], &key),
+            [0xe6, 0x0a, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    /// Parse of a proprietary certificate
+    #[test]
+    fn test_read_server_certificate() {
+        let mut certificate = vec![1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 6, 0, 30, 0];
+        certificate.extend(b"RSA1");
+        certificate.extend([10, 0, 0, 0, 16, 0, 0, 0, 1, 0, 0, 0, 17, 0, 0, 0]);
+        certificate.extend([0xa1, 0x0c, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let key = read_server_certificate(&certificate).unwrap();
+        assert_eq!(key.exponent, 17);
+        assert_eq!(key.modulus, [0xa1, 0x0c]);
+    }
+
+    /// 40 bits keys use a fixed prefix
+    #[test]
+    fn test_reduce_key_40_bits() {
+        let legacy = LegacySecurity::new(
+            &[1; 32],
+            &[2; 32],
+            EncryptionMethod::EncryptionFlag40bit as u32,
+        )
+        .unwrap();
+        assert_eq!(legacy.mac_key.len(), 8);
+        assert_eq!(&legacy.encrypt.initial_key[..3], [0xd1, 0x26, 0x9e]);
+    }
+
+    /// A client must be able to read what the server encrypt
+    #[test]
+    fn test_encrypt_decrypt() {
+        let mut client = LegacySecurity::new(
+            &[1; 32],
+            &[2; 32],
+            EncryptionMethod::EncryptionFlag128bit as u32,
+        )
+        .unwrap();
+        let mut server = LegacySecurity::new(
+            &[1; 32],
+            &[2; 32],
+            EncryptionMethod::EncryptionFlag128bit as u32,
+        )
+        .unwrap();
+        std::mem::swap(&mut server.encrypt, &mut server.decrypt);
+
+        let encrypted = server.encrypt(b"foo");
+        assert_ne!(&encrypted[8..], b"foo");
+        assert_eq!(
+            &client.decrypt(BytesMut::from(&encrypted[..])).unwrap()[..],
+            b"foo"
+        );
+    }
+}
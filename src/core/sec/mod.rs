@@ -0,0 +1,4 @@
+pub mod base;
+pub mod client;
+#[cfg(feature = "legacy-security")]
+pub mod legacy;
\ No newline at end of file
@@ -0,0 +1,443 @@
+use crate::core::gcc::{
+    read_conference_create_request, server_user_data, write_conference_create_response,
+    ClientUserData, ServerCoreData, ServerData, ServerMessageChannelData, ServerNetworkData,
+    Version,
+};
+use crate::core::license::{write_license_message, LicensingErrorMessage, MessageType};
+use crate::core::mcs::base::{
+    attach_user_confirm, channel_join_confirm, disconnect_provider_ultimatum,
+    read_channel_join_request, read_connect_initial, read_mcs_pdu_header, read_send_data_header,
+    send_data_indication, write_connect_response, DomainMCSPDU,
+};
+use crate::core::sec::base::{ClientInfoPdu, ExtendedInfoPacket, SecurityFlag, SecurityHeader};
+use crate::core::tpkt::base::Payload;
+use crate::core::tpkt::server::TpktServer;
+use crate::core::x224::base::{Protocols, SecurityPolicy};
+use crate::core::x224::server::X224Server;
+use crate::model::data::{to_vec, Message};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::model::link::TlsAcceptor;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::nla::cssp::read_public_key;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::nla::ntlm::NtlmAcceptor;
+use crate::nla::ntlm::NtlmCredentials;
+
+use bytes::BytesMut;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// MCS channel id of the global channel
+const GLOBAL_CHANNEL_ID: u16 = 1003;
+
+/// Accept RDP connections on a TCP socket
+///
+/// SSL is only selected with a TLS acceptor and NLA with
+/// both a TLS and an NTLM acceptor, otherwise the protocol
+/// is skipped from the policy
+/// Without NLA the credentials of the client info PDU
+/// are the ones typed by the user
+///
+/// # Example
+/// ```rust, ignore
+/// let policy = SecurityPolicy {
+///     accepted: vec![Protocols::ProtocolHybrid, Protocols::ProtocolSSL, Protocols::ProtocolRDP],
+/// };
+/// let listener = RdpListener::bind("0.0.0.0:3389", policy)
+///     .await?
+///     .tls(TlsAcceptor::from_pem(&certificate, &key)?)
+///     .nla(NtlmAcceptor::new("DOMAIN", "SERVER"));
+/// loop {
+///     match listener.accept().await {
+///         Ok((session, address)) => {
+///             let info = session.get_client_info();
+///             println!("{} {}\\{} {}", address, info.domain, info.username, info.password);
+///         }
+///         Err(e) => if let Some(credentials) = get_failed_credentials(&e) {
+///             println!("{}\\{} {:?}", credentials.domain, credentials.user, credentials.nt_challenge_response);
+///         }
+///     }
+/// }
+/// ```
+#[cfg(not(target_arch = "wasm32"))]
+pub struct RdpListener {
+    listener: TcpListener,
+    /// Security protocols accepted from clients
+    policy: SecurityPolicy,
+    /// Certificate used by SSL and NLA
+    tls: Option<TlsAcceptor>,
+    /// Authentication of NLA
+    nla: Option<NtlmAcceptor>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RdpListener {
+    pub async fn bind(address: impl ToSocketAddrs, policy: SecurityPolicy) -> Result<Self> {
+        Ok(RdpListener {
+            listener: TcpListener::bind(address).await?,
+            policy,
+            tls: None,
+            nla: None,
+        })
+    }
+
+    /// Accept SSL with this certificate
+    pub fn tls(mut self, acceptor: TlsAcceptor) -> Self {
+        self.tls = Some(acceptor);
+        self
+    }
+
+    /// Accept NLA, SSL must be accepted too
+    pub fn nla(mut self, acceptor: NtlmAcceptor) -> Self {
+        self.nla = Some(acceptor);
+        self
+    }
+
+    /// Wait for a client and run the connection sequence
+    /// up to the licensing phase
+    pub async fn accept(&self) -> Result<(ServerSession<TcpStream>, SocketAddr)> {
+        let (stream, address) = self.listener.accept().await?;
+        let session =
+            ServerSession::accept_with(stream, &self.policy, self.tls.as_ref(), self.nla.as_ref())
+                .await?;
+        Ok((session, address))
+    }
+
+    /// Address the listener is bound to
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+/// Remove the protocols the server can't run from the policy
+/// Standard RDP security is always available
+fn available_policy(policy: &SecurityPolicy, ssl: bool, hybrid: bool) -> SecurityPolicy {
+    SecurityPolicy {
+        accepted: policy
+            .accepted
+            .iter()
+            .filter(|p| match p {
+                Protocols::ProtocolRDP => true,
+                Protocols::ProtocolSSL => ssl,
+                Protocols::ProtocolHybrid => hybrid,
+                _ => false,
+            })
+            .copied()
+            .collect(),
+    }
+}
+
+/// Server side of a connected client
+pub struct ServerSession<S> {
+    /// X224 transport layer
+    x224: X224Server<S>,
+    /// Client data blocks sent in the MCS connect initial
+    client_data: ClientUserData,
+    /// Client info PDU with the credentials
+    client_info: ClientInfoPdu,
+    /// MCS user id given to the client
+    user_id: u16,
+    /// Map that translate channel name to channel id
+    channel_ids: HashMap<String, u16>,
+    /// Credentials of the client authenticated by NLA
+    nla_credentials: Option<NtlmCredentials>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> ServerSession<S> {
+    /// Run the server side of the connection sequence
+    ///
+    /// The MCS domain is built with the global channel, the static
+    /// channels and the message channel requested by the client
+    /// The client info PDU is read and a valid client license
+    /// error is sent, so the client is ready for the capabilities
+    /// exchange
+    ///
+    /// Only standard RDP security is selected
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let session = ServerSession::accept(stream, &policy).await?;
+    /// println!("{}", session.get_client_info().username);
+    /// ```
+    pub async fn accept(transport: S, policy: &SecurityPolicy) -> Result<ServerSession<S>> {
+        let policy = available_policy(policy, false, false);
+        let x224 = X224Server::accept(TpktServer::new(transport), &policy).await?;
+        Self::connect(x224, None).await
+    }
+
+    /// Same as accept, with SSL and NLA when the acceptors are given
+    ///
+    /// When NLA fails the error carries the response of the client
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let session = ServerSession::accept_with(stream, &policy, Some(&tls), Some(&ntlm)).await?;
+    /// println!("{:?}", session.get_nla_credentials());
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn accept_with(
+        transport: S,
+        policy: &SecurityPolicy,
+        tls: Option<&TlsAcceptor>,
+        nla: Option<&NtlmAcceptor>,
+    ) -> Result<ServerSession<S>> {
+        let policy = available_policy(policy, tls.is_some(), tls.is_some() && nla.is_some());
+        let mut x224 = X224Server::accept(TpktServer::new(transport), &policy).await?;
+
+        let mut nla_credentials = None;
+        match (x224.get_selected_protocols(), tls, nla) {
+            (Protocols::ProtocolSSL, Some(tls), _) => x224 = x224.accept_ssl(tls).await?,
+            (Protocols::ProtocolHybrid, Some(tls), Some(nla)) => {
+                x224 = x224.accept_ssl(tls).await?;
+                let public_key = read_public_key(tls.get_certificate())?;
+                nla_credentials = Some(x224.accept_nla(&public_key, &mut nla.start()).await?);
+            }
+            _ => (),
+        }
+        Self::connect(x224, nla_credentials).await
+    }
+
+    /// MCS connection, up to the licensing
+    async fn connect(
+        mut x224: X224Server<S>,
+        nla_credentials: Option<NtlmCredentials>,
+    ) -> Result<ServerSession<S>> {
+
+        let (pdu, payload) = Self::read_mcs(&mut x224).await?;
+        if pdu.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "SERVER: expecting a connect initial",
+            ));
+        }
+        let client_data = read_conference_create_request(&mut read_connect_initial(&payload)?)?;
+
+        // The static channels, then the message channel and the user channel
+        let mut channel_ids = HashMap::new();
+        channel_ids.insert("global".to_string(), GLOBAL_CHANNEL_ID);
+        let mut next_id = GLOBAL_CHANNEL_ID + 1;
+        let mut channel_id_array = Vec::new();
+        for channel_def in client_data
+            .network
+            .iter()
+            .flat_map(|n| &n.channel_def_array)
+        {
+            channel_ids.insert(channel_def.get_name(), next_id);
+            channel_id_array.push(next_id);
+            next_id += 1;
+        }
+        let message_channel = client_data.message_channel.as_ref().map(|_| {
+            channel_ids.insert("message".to_string(), next_id);
+            next_id += 1;
+            ServerMessageChannelData {
+                mcs_channel_id: next_id - 1,
+            }
+        });
+        let user_id = next_id;
+        channel_ids.insert("user".to_string(), user_id);
+
+        let server_data = ServerData {
+            core: ServerCoreData {
+                rdp_version: Version::RdpVersion5plus as u32,
+                client_requested_protocols: Some(
+                    x224.get_connection_request()
+                        .negotiation
+                        .map_or(0, |n| n.requested_protocols),
+                ),
+                early_capability_flags: None,
+            },
+            network: ServerNetworkData {
+                mcs_channel_id: GLOBAL_CHANNEL_ID,
+                channel_id_array,
+            },
+            message_channel,
+            ..Default::default()
+        };
+        let response = write_conference_create_response(&server_user_data(&server_data).await?)?;
+        x224.write(write_connect_response(response.to_vec()))
+            .await?;
+
+        // Domain and channels until the first data
+        let mut payload = loop {
+            match Self::read_mcs(&mut x224).await? {
+                (Some(DomainMCSPDU::ErectDomainRequest), _) => (),
+                (Some(DomainMCSPDU::AttachUserRequest), _) => {
+                    x224.write(attach_user_confirm(user_id).to_vec()).await?;
+                }
+                (Some(DomainMCSPDU::ChannelJoinRequest), mut payload) => {
+                    let (_, channel_id) = read_channel_join_request(&mut payload)?;
+                    x224.write(channel_join_confirm(user_id, channel_id).to_vec())
+                        .await?;
+                }
+                (Some(DomainMCSPDU::SendDataRequest), mut payload) => {
+                    if read_send_data_header(&mut payload)? == GLOBAL_CHANNEL_ID {
+                        break payload;
+                    }
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "SERVER: unexpected MCS PDU during connection",
+                    ))
+                }
+            }
+        };
+
+        let client_info = Self::read_client_info(&mut payload).await?;
+
+        let mut session = ServerSession {
+            x224,
+            client_data,
+            client_info,
+            user_id,
+            channel_ids,
+            nla_credentials,
+        };
+
+        // No license negotiation
+        let mut license = to_vec(&SecurityHeader::new(SecurityFlag::SecLicensePkt as u16)).await?;
+        license.extend(
+            write_license_message(
+                MessageType::ErrorAlert,
+                &LicensingErrorMessage::valid_client(),
+            )
+            .await?,
+        );
+        session.write("global", license).await?;
+
+        Ok(session)
+    }
+
+    /// Read a MCS domain PDU
+    /// None is returned for the connect initial
+    /// which is not a domain PDU
+    async fn read_mcs(x224: &mut X224Server<S>) -> Result<(Option<DomainMCSPDU>, BytesMut)> {
+        match x224.read().await? {
+            // BER application tag 101 of the connect initial
+            Payload::Raw(payload) if payload.starts_with(&[0x7F, 0x65]) => Ok((None, payload)),
+            Payload::Raw(mut payload) => Ok((Some(read_mcs_pdu_header(&mut payload)?), payload)),
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                "SERVER: unexpected fast path payload during connection",
+            )),
+        }
+    }
+
+    /// Read the client info PDU
+    /// Encryption is never selected so it comes in clear text,
+    /// or over SSL
+    async fn read_client_info(payload: &mut BytesMut) -> Result<ClientInfoPdu> {
+        let mut header = SecurityHeader::default();
+        header.read_from_buffer(payload)?;
+        if header.flags & SecurityFlag::SecInfoPkt as u16 == 0
+            || header.flags & SecurityFlag::SecEncrypt as u16 != 0
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "SERVER: expecting a clear text client info PDU",
+            ));
+        }
+
+        let mut reader = &payload[..];
+        let mut info = ClientInfoPdu::new("", "", "", false);
+        info.read_from(&mut reader).await?;
+        if !reader.is_empty() {
+            let mut extended_info = ExtendedInfoPacket::default();
+            extended_info.read_from(&mut reader).await?;
+            info.extended_info = Some(extended_info);
+        }
+        Ok(info)
+    }
+
+    /// Read the next message sent by the client
+    /// Fast path input is dedicated to the global channel
+    pub async fn read(&mut self) -> Result<(String, Payload)> {
+        match self.x224.read().await? {
+            Payload::Raw(mut payload) => match read_mcs_pdu_header(&mut payload)? {
+                DomainMCSPDU::SendDataRequest => {
+                    let channel_id = read_send_data_header(&mut payload)?;
+                    let channel = self
+                        .channel_ids
+                        .iter()
+                        .find(|x| *x.1 == channel_id)
+                        .ok_or_else(|| {
+                            Error::new(ErrorKind::InvalidData, "SERVER: unknown channel")
+                        })?;
+                    Ok((channel.0.clone(), Payload::Raw(payload)))
+                }
+                DomainMCSPDU::DisconnectProviderUltimatum => Err(Error::new(
+                    ErrorKind::ConnectionAborted,
+                    "SERVER: Disconnect Provider Ultimatum",
+                )),
+                _ => Err(Error::new(ErrorKind::InvalidData, "SERVER: Invalid opcode")),
+            },
+            Payload::FastPath(sec_flag, payload) => {
+                Ok(("global".to_string(), Payload::FastPath(sec_flag, payload)))
+            }
+        }
+    }
+
+    /// Send a message to a joined channel
+    pub async fn write<T>(&mut self, channel_name: &str, message: T) -> Result<()>
+    where
+        T: Message,
+    {
+        let channel_id = *self
+            .channel_ids
+            .get(channel_name)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "SERVER: unknown channel name"))?;
+        let payload = to_vec(&message).await?;
+        let mut buffer = send_data_indication(self.user_id, channel_id, payload.len()).to_vec();
+        buffer.extend(payload);
+        self.x224.write(buffer).await
+    }
+
+    /// Send a fast path output PDU
+    pub async fn write_fast_path(&mut self, sec_flag: u8, payload: Vec<u8>) -> Result<()> {
+        self.x224.write_fast_path(sec_flag, payload).await
+    }
+
+    /// Client info PDU with the credentials of the client
+    pub fn get_client_info(&self) -> &ClientInfoPdu {
+        &self.client_info
+    }
+
+    /// Credentials of the client when it is authenticated by NLA
+    /// with the NTLMv2 response and the password it delegates
+    pub fn get_nla_credentials(&self) -> Option<&NtlmCredentials> {
+        self.nla_credentials.as_ref()
+    }
+
+    /// Client data blocks sent in the MCS connect initial
+    pub fn get_client_data(&self) -> &ClientUserData {
+        &self.client_data
+    }
+
+    /// Getter of the underlying x224 layer
+    pub fn get_x224(&self) -> &X224Server<S> {
+        &self.x224
+    }
+
+    /// Check if a channel was joined by the client
+    pub fn has_channel(&self, channel_name: &str) -> bool {
+        self.channel_ids.contains_key(channel_name)
+    }
+
+    /// MCS user id given to the client
+    pub fn get_user_id(&self) -> u16 {
+        self.user_id
+    }
+
+    /// Send a close event to the client
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.x224
+            .write(disconnect_provider_ultimatum().to_vec())
+            .await?;
+        self.x224.shutdown(None).await
+    }
+}
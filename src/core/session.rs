@@ -0,0 +1,215 @@
+use crate::core::event::SessionEvent;
+use crate::model::data::check_remaining;
+use crate::model::unicode::from_unicode;
+
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+
+/// Type of logon information
+/// MS-RDPBCGR 2.2.10.1.1 Save Session Info PDU Data (TS_SAVE_SESSION_INFO_PDU_DATA)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum InfoType {
+    InfotypeLogon = 0x00000000,
+    InfotypeLogonLong = 0x00000001,
+    InfotypeLogonPlainnotify = 0x00000002,
+    InfotypeLogonExtendedInfo = 0x00000003,
+}
+
+/// Fields present in the extended logon info
+#[repr(u32)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LogonExFlag {
+    LogonExAutoreconnectcookie = 0x00000001,
+    LogonExLogonerrors = 0x00000002,
+}
+
+/// Notification sent in place of a logon error
+/// MS-RDPBCGR 2.2.10.1.1.4.1.1 Logon Errors Info (TS_LOGON_ERRORS_INFO)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum LogonNotificationType {
+    LogonMsgDisconnectRefused = 0xFFFFFFF9,
+    LogonMsgNoPermission = 0xFFFFFFFA,
+    LogonMsgBumpOptions = 0xFFFFFFFB,
+    LogonMsgReconnectOptions = 0xFFFFFFFC,
+    LogonMsgSessionTerminate = 0xFFFFFFFD,
+    LogonMsgSessionContinue = 0xFFFFFFFE,
+}
+
+/// Logon error codes
+/// MS-RDPBCGR 2.2.10.1.1.4.1.1 Logon Errors Info (TS_LOGON_ERRORS_INFO)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum LogonErrorType {
+    LogonFailedBadPassword = 0x00000000,
+    LogonFailedUpdatePassword = 0x00000001,
+    LogonFailedOther = 0x00000002,
+    LogonWarning = 0x00000003,
+}
+
+/// Size of the domain field of the logon info version 1
+const LOGON_INFO_DOMAIN_SIZE: usize = 52;
+/// Size of the user name field of the logon info version 1
+const LOGON_INFO_USER_NAME_SIZE: usize = 512;
+/// Padding of the logon info version 2
+const LOGON_INFO_V2_PADDING: usize = 558;
+
+/// Read a TS_LOGON_INFO structure
+/// MS-RDPBCGR 2.2.10.1.1.1 Logon Info Version 1 (TS_LOGON_INFO)
+fn read_logon_info(buffer: &mut BytesMut) -> Result<SessionEvent> {
+    check_remaining(
+        buffer,
+        4 + LOGON_INFO_DOMAIN_SIZE + 4 + LOGON_INFO_USER_NAME_SIZE + 4,
+        "SESSION: logon info",
+    )?;
+    let _cb_domain = buffer.get_u32_le();
+    let domain = from_unicode(&buffer.split_to(LOGON_INFO_DOMAIN_SIZE));
+    let _cb_user_name = buffer.get_u32_le();
+    let user = from_unicode(&buffer.split_to(LOGON_INFO_USER_NAME_SIZE));
+    let session_id = buffer.get_u32_le();
+
+    Ok(SessionEvent::LoggedOn {
+        domain,
+        user,
+        session_id,
+    })
+}
+
+/// Read a TS_LOGON_INFO_VERSION_2 structure
+/// MS-RDPBCGR 2.2.10.1.1.2 Logon Info Version 2 (TS_LOGON_INFO_VERSION_2)
+fn read_logon_info_v2(buffer: &mut BytesMut) -> Result<SessionEvent> {
+    check_remaining(buffer, 18 + LOGON_INFO_V2_PADDING, "SESSION: logon info v2")?;
+    let _version = buffer.get_u16_le();
+    let _size = buffer.get_u32_le();
+    let session_id = buffer.get_u32_le();
+    let cb_domain = buffer.get_u32_le() as usize;
+    let cb_user_name = buffer.get_u32_le() as usize;
+    buffer.advance(LOGON_INFO_V2_PADDING);
+
+    check_remaining(buffer, cb_domain + cb_user_name, "SESSION: logon info v2")?;
+    let domain = from_unicode(&buffer.split_to(cb_domain));
+    let user = from_unicode(&buffer.split_to(cb_user_name));
+
+    Ok(SessionEvent::LoggedOn {
+        domain,
+        user,
+        session_id,
+    })
+}
+
+/// Read a TS_LOGON_INFO_EXTENDED structure
+/// It can contain an auto reconnect cookie and a logon error
+/// MS-RDPBCGR 2.2.10.1.1.4 Logon Info Extended (TS_LOGON_INFO_EXTENDED)
+fn read_logon_info_extended(buffer: &mut BytesMut) -> Result<Vec<SessionEvent>> {
+    check_remaining(buffer, 6, "SESSION: logon info extended")?;
+    let _length = buffer.get_u16_le();
+    let fields_present = buffer.get_u32_le();
+
+    let mut result = Vec::new();
+    if fields_present & LogonExFlag::LogonExAutoreconnectcookie as u32 != 0 {
+        check_remaining(buffer, 4, "SESSION: auto reconnect cookie")?;
+        let cb_field_data = buffer.get_u32_le() as usize;
+        check_remaining(buffer, cb_field_data, "SESSION: auto reconnect cookie")?;
+        let mut field = buffer.split_to(cb_field_data);
+
+        // ARC_SC_PRIVATE_PACKET
+        check_remaining(&field, 28, "SESSION: auto reconnect cookie")?;
+        let _cb_len = field.get_u32_le();
+        let _version = field.get_u32_le();
+        let logon_id = field.get_u32_le();
+        let mut random = [0; 16];
+        field.copy_to_slice(&mut random);
+        result.push(SessionEvent::AutoReconnectCookie { logon_id, random });
+    }
+
+    if fields_present & LogonExFlag::LogonExLogonerrors as u32 != 0 {
+        check_remaining(buffer, 4, "SESSION: logon errors")?;
+        let cb_field_data = buffer.get_u32_le() as usize;
+        check_remaining(buffer, cb_field_data, "SESSION: logon errors")?;
+        let mut field = buffer.split_to(cb_field_data);
+
+        // TS_LOGON_ERRORS_INFO
+        check_remaining(&field, 8, "SESSION: logon errors")?;
+        result.push(SessionEvent::LogonError {
+            error_type: field.get_u32_le(),
+            error_data: field.get_u32_le(),
+        });
+    }
+
+    Ok(result)
+}
+
+/// Parse the payload of a Save Session Info PDU
+/// A single PDU can carry several events
+/// MS-RDPBCGR 2.2.10.1.1 Save Session Info PDU Data (TS_SAVE_SESSION_INFO_PDU_DATA)
+pub fn read_save_session_info(buffer: &mut BytesMut) -> Result<Vec<SessionEvent>> {
+    check_remaining(buffer, 4, "SESSION: save session info")?;
+    let info_type = buffer.get_u32_le();
+    match InfoType::try_from(info_type) {
+        Ok(InfoType::InfotypeLogon) => Ok(vec![read_logon_info(buffer)?]),
+        Ok(InfoType::InfotypeLogonLong) => Ok(vec![read_logon_info_v2(buffer)?]),
+        Ok(InfoType::InfotypeLogonPlainnotify) => Ok(vec![SessionEvent::LogonNotify]),
+        Ok(InfoType::InfotypeLogonExtendedInfo) => read_logon_info_extended(buffer),
+        Err(_) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("SESSION: unknown save session info type {}", info_type),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::unicode::Unicode;
+    use bytes::BufMut;
+
+    /// Test of the logon info version 2
+    #[test]
+    fn test_read_logon_info_v2() {
+        let domain = "DOMAIN\0".to_unicode();
+        let user = "user\0".to_unicode();
+
+        let mut buffer = BytesMut::new();
+        buffer.put_u32_le(InfoType::InfotypeLogonLong as u32);
+        buffer.put_u16_le(1);
+        buffer.put_u32_le(576);
+        buffer.put_u32_le(2);
+        buffer.put_u32_le(domain.len() as u32);
+        buffer.put_u32_le(user.len() as u32);
+        buffer.put_bytes(0, LOGON_INFO_V2_PADDING);
+        buffer.put_slice(&domain);
+        buffer.put_slice(&user);
+
+        assert_eq!(
+            read_save_session_info(&mut buffer).unwrap(),
+            vec![SessionEvent::LoggedOn {
+                domain: "DOMAIN".to_string(),
+                user: "user".to_string(),
+                session_id: 2
+            }]
+        );
+    }
+
+    /// Test of the logon errors in extended info
+    #[test]
+    fn test_read_logon_info_extended_errors() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u32_le(InfoType::InfotypeLogonExtendedInfo as u32);
+        buffer.put_u16_le(18);
+        buffer.put_u32_le(LogonExFlag::LogonExLogonerrors as u32);
+        buffer.put_u32_le(8);
+        buffer.put_u32_le(LogonNotificationType::LogonMsgSessionContinue as u32);
+        buffer.put_u32_le(0);
+
+        assert_eq!(
+            read_save_session_info(&mut buffer).unwrap(),
+            vec![SessionEvent::LogonError {
+                error_type: LogonNotificationType::LogonMsgSessionContinue as u32,
+                error_data: 0
+            }]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use crate::codec::nsc::nsc_decompress;
+use crate::core::capability::{BitmapCodecType, BitmapCodecsCapability};
+use crate::core::event::{BitmapEvent, FrameAction};
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+
+/// Surface command types
+/// MS-RDPBCGR 2.2.9.2 Surface Commands (TS_SURFCMD)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum SurfaceCommandType {
+    CmdtypeSetSurfaceBits = 0x0001,
+    CmdtypeFrameMarker = 0x0004,
+    CmdtypeStreamSurfaceBits = 0x0006,
+}
+
+/// Codec id of uncompressed surface bits
+/// Other ids are chosen in the bitmap codecs capability
+pub const CODEC_ID_NONE: u8 = 0x00;
+
+/// The extended compressed bitmap header is present
+/// MS-RDPBCGR 2.2.9.2.1.1 Extended Bitmap Data (TS_BITMAP_DATA_EX)
+const EX_COMPRESSED_BITMAP_HEADER_PRESENT: u8 = 0x01;
+/// Size of the exBitmapDataHeader field
+const EX_COMPRESSED_BITMAP_HEADER_SIZE: usize = 24;
+
+/// Bitmap sent by a set or stream surface bits command
+/// MS-RDPBCGR 2.2.9.2.1 Set Surface Bits Command (TS_SURFCMD_SET_SURF_BITS)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SurfaceBits {
+    /// Destination rectangle, right and bottom are exclusive
+    pub dest_left: u16,
+    pub dest_top: u16,
+    pub dest_right: u16,
+    pub dest_bottom: u16,
+    pub bpp: u8,
+    pub codec_id: u8,
+    pub width: u16,
+    pub height: u16,
+    /// Encoded with the codec, without the extended header
+    pub data: Vec<u8>,
+}
+
+impl SurfaceBits {
+    fn read_from_buffer(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 20, "SURFACE: surface bits")?;
+        let dest_left = buffer.get_u16_le();
+        let dest_top = buffer.get_u16_le();
+        let dest_right = buffer.get_u16_le();
+        let dest_bottom = buffer.get_u16_le();
+        let bpp = buffer.get_u8();
+        let flags = buffer.get_u8();
+        buffer.advance(1);
+        let codec_id = buffer.get_u8();
+        let width = buffer.get_u16_le();
+        let height = buffer.get_u16_le();
+        let length = buffer.get_u32_le() as usize;
+        check_remaining(buffer, length, "SURFACE: bitmap data")?;
+        let mut data = buffer.split_to(length);
+        if flags & EX_COMPRESSED_BITMAP_HEADER_PRESENT != 0 {
+            check_remaining(
+                &data,
+                EX_COMPRESSED_BITMAP_HEADER_SIZE,
+                "SURFACE: extended bitmap header",
+            )?;
+            data.advance(EX_COMPRESSED_BITMAP_HEADER_SIZE);
+        }
+        Ok(SurfaceBits {
+            dest_left,
+            dest_top,
+            dest_right,
+            dest_bottom,
+            bpp,
+            codec_id,
+            width,
+            height,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Decode the bitmap with the codec of its id
+    /// Rows are bottom up, like bitmap updates
+    pub fn into_bitmap(self, codecs: &BitmapCodecsCapability) -> Result<BitmapEvent> {
+        if self.codec_id != CODEC_ID_NONE {
+            let data = match codecs.codec(self.codec_id) {
+                Some(BitmapCodecType::NsCodec) => {
+                    nsc_decompress(&self.data, self.width, self.height)?
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!("SURFACE: unsupported codec {}", self.codec_id),
+                    ))
+                }
+            };
+            let row_size = self.width as usize * 4;
+            return SurfaceBits {
+                bpp: 32,
+                data: if row_size == 0 {
+                    data
+                } else {
+                    data.chunks_exact(row_size)
+                        .rev()
+                        .flatten()
+                        .copied()
+                        .collect()
+                },
+                codec_id: CODEC_ID_NONE,
+                ..self
+            }
+            .into_bitmap(codecs);
+        }
+        let size = self.width as usize * self.height as usize * ((self.bpp as usize + 7) / 8);
+        if self.data.len() < size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "SURFACE: bitmap data is too short",
+            ));
+        }
+        Ok(BitmapEvent {
+            dest_left: self.dest_left,
+            dest_top: self.dest_top,
+            dest_right: self.dest_right.saturating_sub(1),
+            dest_bottom: self.dest_bottom.saturating_sub(1),
+            width: self.width,
+            height: self.height,
+            bpp: self.bpp as u16,
+            is_compress: false,
+            data: self.data,
+        })
+    }
+}
+
+/// A parsed surface command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SurfaceCommand {
+    /// Set and stream surface bits only differ by their type
+    SurfaceBits(SurfaceBits),
+    /// MS-RDPBCGR 2.2.9.2.3 Frame Marker Command (TS_FRAME_MARKER)
+    FrameMarker { action: FrameAction, frame_id: u32 },
+}
+
+/// Read the commands of a surface commands update
+/// MS-RDPBCGR 2.2.9.1.2.1.10 Fast-Path Surface Commands Update (TS_FP_SURFCMDS)
+pub fn read_surface_commands(buffer: &mut BytesMut) -> Result<Vec<SurfaceCommand>> {
+    let mut commands = Vec::new();
+    while buffer.has_remaining() {
+        check_remaining(buffer, 2, "SURFACE: command type")?;
+        let command_type = buffer.get_u16_le();
+        commands.push(match SurfaceCommandType::try_from(command_type) {
+            Ok(SurfaceCommandType::CmdtypeSetSurfaceBits)
+            | Ok(SurfaceCommandType::CmdtypeStreamSurfaceBits) => {
+                SurfaceCommand::SurfaceBits(SurfaceBits::read_from_buffer(buffer)?)
+            }
+            Ok(SurfaceCommandType::CmdtypeFrameMarker) => {
+                check_remaining(buffer, 6, "SURFACE: frame marker")?;
+                let action = buffer.get_u16_le();
+                let frame_id = buffer.get_u32_le();
+                SurfaceCommand::FrameMarker {
+                    action: FrameAction::try_from(action as u32).map_err(|_| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("SURFACE: invalid frame action {}", action),
+                        )
+                    })?,
+                    frame_id,
+                }
+            }
+            // Commands have no length field, so they can't be skipped
+            Err(_) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("SURFACE: unknown command type {}", command_type),
+                ))
+            }
+        });
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::BufMut;
+
+    /// Frame markers around an uncompressed stream surface bits command
+    #[test]
+    fn test_read_surface_commands() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u16_le(SurfaceCommandType::CmdtypeFrameMarker as u16);
+        buffer.put_u16_le(0);
+        buffer.put_u32_le(7);
+        buffer.put_u16_le(SurfaceCommandType::CmdtypeStreamSurfaceBits as u16);
+        for value in [10, 20, 12, 21] {
+            buffer.put_u16_le(value);
+        }
+        buffer.put_slice(&[32, EX_COMPRESSED_BITMAP_HEADER_PRESENT, 0, CODEC_ID_NONE]);
+        buffer.put_u16_le(2);
+        buffer.put_u16_le(1);
+        buffer.put_u32_le(EX_COMPRESSED_BITMAP_HEADER_SIZE as u32 + 8);
+        buffer.put_slice(&[0; EX_COMPRESSED_BITMAP_HEADER_SIZE]);
+        buffer.put_slice(&[1, 2, 3, 0xFF, 4, 5, 6, 0xFF]);
+        buffer.put_u16_le(SurfaceCommandType::CmdtypeFrameMarker as u16);
+        buffer.put_u16_le(1);
+        buffer.put_u32_le(7);
+
+        let mut commands = read_surface_commands(&mut buffer).unwrap();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(
+            commands[2],
+            SurfaceCommand::FrameMarker {
+                action: FrameAction::End,
+                frame_id: 7
+            }
+        );
+        let bitmap = match commands.remove(1) {
+            SurfaceCommand::SurfaceBits(bits) => bits
+                .into_bitmap(&BitmapCodecsCapability::default())
+                .unwrap(),
+            _ => panic!("expected surface bits"),
+        };
+        assert_eq!((bitmap.dest_left, bitmap.dest_top), (10, 20));
+        assert_eq!((bitmap.dest_right, bitmap.dest_bottom), (11, 20));
+        assert_eq!((bitmap.width, bitmap.height, bitmap.bpp), (2, 1, 32));
+        assert_eq!(bitmap.data, vec![1, 2, 3, 0xFF, 4, 5, 6, 0xFF]);
+
+        buffer.put_u16_le(0x0002);
+        assert!(read_surface_commands(&mut buffer).is_err());
+    }
+
+    /// Codec ids are only known once announced by the client
+    #[test]
+    fn test_surface_bits_codec() {
+        let bits = SurfaceBits {
+            bpp: 32,
+            codec_id: 1,
+            width: 1,
+            height: 1,
+            data: vec![0; 20],
+            ..Default::default()
+        };
+        assert!(bits
+            .clone()
+            .into_bitmap(&BitmapCodecsCapability::default())
+            .is_err());
+        let codecs = BitmapCodecsCapability::new(&[BitmapCodecType::NsCodec]);
+        // Header without planes is not a valid NSCodec bitmap
+        assert!(matches!(
+            bits.into_bitmap(&codecs),
+            Err(e) if e.kind() == ErrorKind::InvalidData
+        ));
+    }
+}
@@ -1,9 +1,15 @@
+use crate::core::limits::PduLimits;
+use crate::core::metrics::Metrics;
+use crate::core::trace::trace_pdu;
 use crate::model::data::Message;
 
 use async_trait::async_trait;
-use bytes::BytesMut;
+use bytes::{Buf, BytesMut};
+use std::io::{self, Error, ErrorKind};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+/// Payload of a PDU, split from the receive buffer
+/// so the layers above only advance it or split it
 pub enum Payload {
     Raw(BytesMut),
     FastPath(u8, BytesMut),
@@ -60,3 +66,200 @@ impl Message for TpktHeader {
         4
     }
 }
+
+/// Build a whole TPKT PDU so it can be
+/// written with a single call to the transport
+pub(crate) async fn tpkt_frame<T: Message>(message: T) -> io::Result<Vec<u8>> {
+    let size = message.length() + 4;
+    if size > u16::MAX as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, "TPKT: payload is too large"));
+    }
+
+    let header = TpktHeader {
+        action: Action::FastPathActionX224 as u8,
+        flag: 0,
+        size: size as u16,
+    };
+
+    let mut buffer = Vec::with_capacity(size);
+    header.write_to(&mut buffer).await?;
+    message.write_to(&mut buffer).await?;
+    Ok(buffer)
+}
+
+/// Build a whole fast path PDU
+/// The header holds everything but the action code
+/// and the length is encoded on one or two bytes
+pub(crate) fn fast_path_frame(header: u8, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::with_capacity(payload.len() + 3);
+    buffer.push(Action::FastPathActionFastPath as u8 | (header & !0x3));
+
+    if payload.len() + 2 <= 0x7F {
+        buffer.push(payload.len() as u8 + 2);
+    } else if payload.len() + 3 <= 0x7FFF {
+        buffer.extend_from_slice(&(0x8000 | (payload.len() as u16 + 3)).to_be_bytes());
+    } else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "TPKT: fast path payload is too large",
+        ));
+    }
+
+    buffer.extend_from_slice(payload);
+    Ok(buffer)
+}
+
+/// Size of the TPKT header
+const TPKT_HEADER_SIZE: usize = 4;
+
+/// Free space of the receive buffer before each read of the transport
+const RECEIVE_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Split a whole TPKT or fast path PDU from the front of a buffer
+/// None is returned until the PDU is complete
+///
+/// The first byte is returned with the payload
+/// because the fast path header carries flags
+pub(crate) fn decode_frame(
+    src: &mut BytesMut,
+    limits: &PduLimits,
+) -> io::Result<Option<(u8, Payload)>> {
+    if src.is_empty() {
+        return Ok(None);
+    }
+
+    // Fast path header holds the security flags in the two high bits
+    let header = src[0];
+    let (size, header_size) = match Action::try_from(header & 0x3) {
+        Ok(Action::FastPathActionX224) => {
+            if src.len() < TPKT_HEADER_SIZE {
+                return Ok(None);
+            }
+            let size = u16::from_be_bytes([src[2], src[3]]) as usize;
+            limits.check_tpkt_size(size)?;
+            (size, TPKT_HEADER_SIZE)
+        }
+        // The length includes the header of one or two length bytes
+        Ok(Action::FastPathActionFastPath) => match src.get(1) {
+            None => return Ok(None),
+            Some(short_length) if short_length & 0x80 == 0 => (*short_length as usize, 2),
+            Some(short_length) => match src.get(2) {
+                None => return Ok(None),
+                Some(low_length) => {
+                    let length = ((short_length & !0x80) as usize) << 8;
+                    (length | *low_length as usize, 3)
+                }
+            },
+        },
+        Err(_) => return Err(Error::new(ErrorKind::InvalidData, "Invalid action code")),
+    };
+
+    if header_size != TPKT_HEADER_SIZE {
+        limits.check_fast_path_length(size)?;
+    }
+
+    if size < header_size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Invalid minimal size for TPKT",
+        ));
+    }
+
+    if src.len() < size {
+        return Ok(None);
+    }
+
+    trace_pdu!(size, fast_path = header_size != TPKT_HEADER_SIZE, "TPKT: received");
+    let mut pdu = src.split_to(size);
+    pdu.advance(header_size);
+    Ok(Some(match Action::try_from(header & 0x3) {
+        Ok(Action::FastPathActionX224) => (header, Payload::Raw(pdu)),
+        _ => (header, Payload::FastPath((header >> 6) & 0x3, pdu)),
+    }))
+}
+
+/// Read a whole TPKT or fast path PDU
+///
+/// Received bytes are kept in the buffer until the PDU is complete,
+/// so the read is cancel safe and can be used in tokio::select!
+///
+/// The PDUs are split from the front of the buffer and share its
+/// storage, which is reused once they are all dropped, so the reads
+/// of a steady session don't allocate
+pub(crate) async fn read_frame(
+    transport: &mut (impl AsyncRead + Unpin),
+    buffer: &mut BytesMut,
+    limits: &PduLimits,
+    metrics: &Metrics,
+) -> io::Result<(u8, Payload)> {
+    loop {
+        match decode_frame(buffer, limits) {
+            Ok(Some((header, payload))) => {
+                match payload {
+                    Payload::Raw(_) => metrics.add_pdu("tpkt"),
+                    Payload::FastPath(..) => metrics.add_fast_path_frame(),
+                }
+                return Ok((header, payload));
+            }
+            Ok(None) => (),
+            Err(e) => {
+                metrics.add_decode_error();
+                return Err(e);
+            }
+        }
+
+        if buffer.capacity() - buffer.len() < RECEIVE_CHUNK_SIZE {
+            if buffer.try_reclaim(RECEIVE_CHUNK_SIZE) {
+                metrics.add_buffer_reuse();
+            } else {
+                // Some PDUs are still alive or the storage is too small
+                buffer.reserve(RECEIVE_CHUNK_SIZE);
+                metrics.add_buffer_allocation();
+            }
+        }
+
+        match transport.read_buf(buffer).await? {
+            0 => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("TPKT: truncated PDU of {} bytes", buffer.len()),
+                ))
+            }
+            size => metrics.add_bytes_received(size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The storage is reused once the PDUs are dropped
+    #[tokio::test]
+    async fn test_read_frame_buffer_reuse() {
+        let data = [3, 0, 0, 7, 1, 2, 3, 3, 0, 0, 7, 4, 5, 6];
+        let mut transport = &data[..];
+        let mut buffer = BytesMut::new();
+        let limits = PduLimits::default();
+        let metrics = Metrics::default();
+
+        for expected in [[1, 2, 3], [4, 5, 6]] {
+            let (_, payload) = read_frame(&mut transport, &mut buffer, &limits, &metrics)
+                .await
+                .unwrap();
+            match payload {
+                Payload::Raw(pdu) => assert_eq!(pdu[..], expected),
+                Payload::FastPath(..) => panic!("unexpected fast path PDU"),
+            }
+        }
+        let result = read_frame(&mut transport, &mut buffer, &limits, &metrics).await;
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(ErrorKind::UnexpectedEof)
+        );
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.buffer_allocations, 1);
+        assert_eq!(snapshot.buffer_reuses, 1);
+    }
+}
@@ -1,166 +1,231 @@
 use bytes::BytesMut;
 use std::io::{self, Error, ErrorKind, Result};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf};
 
-use crate::core::tpkt::base::{Action, Payload, TpktHeader};
-use crate::model::data::{Message, U16};
-// use crate::nla::cssp::cssp_connect;
-// use crate::nla::sspi::AuthenticationProtocol;
+use crate::core::limits::PduLimits;
+use crate::core::metrics::Metrics;
+use crate::core::trace::trace_pdu;
+use crate::core::tpkt::base::{fast_path_frame, read_frame, tpkt_frame, Payload};
+use crate::core::tpkt::queue::{WritePriority, WriteQueue};
+use crate::model::data::Message;
+use crate::model::link::Stream;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::nla::cssp::{cssp_connect, read_public_key};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::nla::sspi::AuthenticationProtocol;
 
-/// TPKT must implement this two kind of payload
+/// Transport of the PDUs
+enum Transport<S> {
+    Direct(S),
+    /// Writes go through a queue and a task of their own
+    Queued(ReadHalf<S>, WriteQueue),
+    /// Only while the transport is split
+    Closed,
+}
+
+fn transport_closed() -> Error {
+    Error::new(ErrorKind::NotConnected, "TPKT: transport is closed")
+}
 
 /// Client Context of TPKT layer
 pub struct TpktClient<S> {
-    transport: S,
+    transport: Transport<Stream<S>>,
+    /// Limits on the length of received PDUs
+    limits: PduLimits,
+    /// Received bytes of the next PDU
+    buffer: BytesMut,
+    /// Counters shared with the caller
+    metrics: Metrics,
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin + Send> TpktClient<S> {
     /// Create a new Client based on a low level connection instance
     pub fn new(transport: S) -> Self {
-        TpktClient { transport }
+        TpktClient {
+            transport: Transport::Direct(Stream::Raw(transport)),
+            limits: PduLimits::default(),
+            buffer: BytesMut::new(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Change the limits on the length of received PDUs
+    pub fn set_limits(&mut self, limits: PduLimits) {
+        self.limits = limits;
+    }
+
+    /// Limits on the length of received PDUs
+    pub fn get_limits(&self) -> PduLimits {
+        self.limits
+    }
+
+    /// Share counters with the caller
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    /// Counters of the connection
+    pub fn get_metrics(&self) -> &Metrics {
+        &self.metrics
     }
 
     /// Send a message to the link layer
     /// with appropriate header
     /// Move to avoid copy
+    ///
+    /// The whole PDU is built before being sent
+    /// so it is written with a single call to the transport
     pub async fn write<T: 'static>(&mut self, message: T) -> Result<()>
     where
         T: Message,
     {
-        let header = TpktHeader {
-            action: Action::FastPathActionX224 as u8,
-            flag: 0,
-            size: (message.length() + 4) as u16,
-        };
+        self.write_with_priority(message, WritePriority::Low).await
+    }
 
-        header.write_to(&mut self.transport).await?;
-        message.write_to(&mut self.transport).await?;
-        Ok(())
+    /// Send a message, the priority is only used
+    /// once the writes are queued
+    pub async fn write_with_priority<T: 'static>(
+        &mut self,
+        message: T,
+        priority: WritePriority,
+    ) -> Result<()>
+    where
+        T: Message,
+    {
+        let buffer = tpkt_frame(message).await?;
+        trace_pdu!(size = buffer.len(), "TPKT: sent");
+        self.send(buffer, priority).await
+    }
+
+    /// Send a fast path payload
+    /// The header carries the security flags
+    /// and the length is encoded on one or two bytes
+    /// MS-RDPBCGR 2.2.8.1.2 Client Fast-Path Input Event PDU (TS_FP_INPUT_PDU)
+    pub async fn write_fast_path(&mut self, sec_flag: u8, payload: Vec<u8>) -> Result<()> {
+        let buffer = fast_path_frame((sec_flag & 0x3) << 6, &payload)?;
+        trace_pdu!(size = buffer.len(), "TPKT: sent");
+        // Only input events are sent in fast path
+        self.send(buffer, WritePriority::High).await
+    }
+
+    /// Write a whole frame on the transport or in the queue
+    async fn send(&mut self, frame: Vec<u8>, priority: WritePriority) -> Result<()> {
+        match &mut self.transport {
+            Transport::Direct(stream) => {
+                self.metrics.add_bytes_sent(frame.len());
+                stream.write_all(&frame).await
+            }
+            Transport::Queued(_, queue) => queue.send(frame, priority).await,
+            Transport::Closed => Err(transport_closed()),
+        }
     }
 
     /// Read a payload from the underlying layer
     /// Check the tpkt header and provide a well
     /// formed payload
+    ///
+    /// The whole PDU is read even when it is split
+    /// over several segments of the transport
     pub async fn read(&mut self) -> io::Result<Payload> {
-        let action = match Action::try_from(self.transport.read_u8().await?) {
-            Ok(a) => a,
-            Err(_) => return Err(Error::new(ErrorKind::InvalidData, "Invalid action code")),
-        };
-
-        match action {
-            Action::FastPathActionX224 => {
-                let _padding = self.transport.read_u8().await?;
-                let size = self.transport.read_u16().await?;
-
-                if size < 4 {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "Invalid minimal size for TPKT",
-                    ));
-                }
-
-                let mut buffer = BytesMut::with_capacity(size as usize - 4);
-                return match self.transport.read_buf(&mut buffer).await {
-                    Ok(_) => Ok(Payload::Raw(buffer)),
-                    Err(e) => Err(Error::new(
-                        ErrorKind::InvalidData,
-                        format!("Invalid minimal size for TPKT, {}", e),
-                    )),
-                };
+        let (_header, payload) = match &mut self.transport {
+            Transport::Direct(stream) => {
+                read_frame(stream, &mut self.buffer, &self.limits, &self.metrics).await?
             }
-            _ => {
-                let sec_flag = (action as u8 >> 6) & 0x3;
-                let short_length = self.transport.read_u8().await?;
-
-                match short_length & 0x80 {
-                    0 => {
-                        if short_length < 2 {
-                            return Err(Error::new(
-                                ErrorKind::InvalidData,
-                                "Invalid minimal size for TPKT",
-                            ));
-                        }
-
-                        let mut buffer = BytesMut::with_capacity(short_length as usize - 2);
-                        return match self.transport.read_buf(&mut buffer).await {
-                            Ok(_) => Ok(Payload::FastPath(sec_flag, buffer)),
-                            Err(e) => Err(Error::new(
-                                ErrorKind::InvalidData,
-                                format!("Invalid minimal size for TPKT, {}", e),
-                            )),
-                        };
-                    }
-                    _ => {
-                        let hi_length = self.transport.read_u8().await?;
-                        let length: u16 = ((short_length & !0x80) as u16) << 8;
-                        let length = length | hi_length as u16;
-
-                        if length < 3 {
-                            return Err(Error::new(
-                                ErrorKind::InvalidData,
-                                "Invalid minimal size for TPKT",
-                            ));
-                        }
-
-                        let mut buffer = BytesMut::with_capacity(length as usize - 3);
-                        return match self.transport.read_buf(&mut buffer).await {
-                            Ok(_) => Ok(Payload::FastPath(sec_flag, buffer)),
-                            Err(e) => Err(Error::new(
-                                ErrorKind::InvalidData,
-                                format!("Invalid minimal size for TPKT, {}", e),
-                            )),
-                        };
-                    }
-                };
+            Transport::Queued(reader, _) => {
+                read_frame(reader, &mut self.buffer, &self.limits, &self.metrics).await?
             }
+            Transport::Closed => return Err(transport_closed()),
         };
+        Ok(payload)
     }
 
-    // /// This function transform the link layer with
-    // /// raw data stream into a SSL data stream
-    // ///
-    // /// # Example
-    // /// ```no_run
-    // /// use std::net::{SocketAddr, TcpStream};
-    // /// use rdp::core::tpkt;
-    // /// use rdp::model::link;
-    // /// let addr = "127.0.0.1:3389".parse::<SocketAddr>().unwrap();
-    // /// let mut tcp = TcpStream::connect(&addr).unwrap();
-    // /// let mut tpkt = tpkt::Client::new(link::Link::new(link::Stream::Raw(tcp)));
-    // /// let mut tpkt_ssl = tpkt.start_ssl(false).unwrap();
-    // /// ```
-    // pub fn start_ssl(self, check_certificate: bool) -> RdpResult<Client<S>> {
-    //     Ok(Client::new(self.transport.start_ssl(check_certificate)?))
-    // }
+    /// This function transform the link layer with
+    /// raw data stream into a SSL data stream
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let mut tpkt = TpktClient::new(TcpStream::connect("127.0.0.1:3389").await?);
+    /// tpkt.start_ssl(false, "").await?;
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn start_ssl(&mut self, check_certificate: bool, server_name: &str) -> Result<()> {
+        self.transport = match std::mem::replace(&mut self.transport, Transport::Closed) {
+            Transport::Direct(stream) => {
+                Transport::Direct(stream.start_ssl(check_certificate, server_name).await?)
+            }
+            _ => return Err(transport_closed()),
+        };
+        Ok(())
+    }
 
     /// This function is used when NLA (Network Level Authentication)
-    /// Authentication is negotiated
+    /// Authentication is negotiated, once SSL is started
     ///
     /// # Example
-    /// ```no_run
-    /// use std::net::{SocketAddr, TcpStream};
-    /// use rdp::core::tpkt;
-    /// use rdp::nla::ntlm::Ntlm;
-    /// use rdp::model::link;
-    /// let addr = "127.0.0.1:3389".parse::<SocketAddr>().unwrap();
-    /// let mut tcp = TcpStream::connect(&addr).unwrap();
-    /// let mut tpkt = tpkt::Client::new(link::Link::new(link::Stream::Raw(tcp)));
-    /// let mut tpkt_nla = tpkt.start_nla(false, &mut Ntlm::new("domain".to_string(), "username".to_string(), "password".to_string()), false);
+    /// ```rust, ignore
+    /// let mut ntlm = Ntlm::new("domain".to_string(), "username".to_string(), "password".to_string());
+    /// tpkt.start_ssl(false, "").await?;
+    /// tpkt.start_nla(&mut ntlm, false).await?;
     /// ```
-    // pub fn start_nla(
-    //     self,
-    //     check_certificate: bool,
-    //     authentication_protocol: &mut dyn AuthenticationProtocol,
-    //     restricted_admin_mode: bool,
-    // ) -> RdpResult<Client<S>> {
-    //     let mut link = self.transport.start_ssl(check_certificate)?;
-    //     cssp_connect(&mut link, authentication_protocol, restricted_admin_mode)?;
-    //     Ok(Client::new(link))
-    // }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn start_nla(
+        &mut self,
+        authentication_protocol: &mut (dyn AuthenticationProtocol + Send),
+        restricted_admin_mode: bool,
+    ) -> Result<()> {
+        let certificate = self.get_peer_certificate()?.ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, "TPKT: NLA needs SSL")
+        })?;
+        let public_key = read_public_key(&certificate)?;
+        match &mut self.transport {
+            Transport::Direct(stream) => {
+                cssp_connect(
+                    stream,
+                    &public_key,
+                    authentication_protocol,
+                    restricted_admin_mode,
+                )
+                .await
+            }
+            _ => Err(transport_closed()),
+        }
+    }
+
+    /// Certificate of the server in DER, once SSL is started
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn get_peer_certificate(&self) -> Result<Option<Vec<u8>>> {
+        match &self.transport {
+            Transport::Direct(stream) => stream.get_peer_certificate(),
+            _ => Err(transport_closed()),
+        }
+    }
 
     /// Shutdown current connection
+    /// The queued PDUs are written before
     pub async fn shutdown(&mut self) -> Result<()> {
-        self.transport.shutdown().await
+        match &mut self.transport {
+            Transport::Direct(stream) => stream.shutdown().await,
+            Transport::Queued(_, queue) => queue.close().await,
+            Transport::Closed => Ok(()),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> TpktClient<S> {
+    /// Write the next PDUs from a task of their own
+    /// Each priority holds at most `capacity` PDUs,
+    /// the writes wait once it is full
+    ///
+    /// Without priority all PDUs are sent in order
+    pub fn start_write_queue(&mut self, capacity: usize, prioritized: bool) {
+        self.transport = match std::mem::replace(&mut self.transport, Transport::Closed) {
+            Transport::Direct(stream) => {
+                let (reader, writer) = tokio::io::split(stream);
+                let queue = WriteQueue::new(writer, capacity, prioritized, self.metrics.clone());
+                Transport::Queued(reader, queue)
+            }
+            transport => transport,
+        };
     }
 }
 
@@ -0,0 +1,148 @@
+use crate::core::limits::PduLimits;
+use crate::core::tpkt::base::{decode_frame, fast_path_frame, Action, Payload};
+
+use bytes::{BufMut, BytesMut};
+use std::io::{Error, ErrorKind, Result};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Size of the TPKT header
+const TPKT_HEADER_SIZE: usize = 4;
+
+/// Framing of TPKT and fast path PDUs
+/// for tokio_util Framed streams
+///
+/// Decoded payloads are the same as those
+/// returned by TpktClient::read
+///
+/// # Example
+/// ```rust, ignore
+/// use futures::{SinkExt, StreamExt};
+/// use tokio_util::codec::Framed;
+/// let mut framed = Framed::new(stream, TpktCodec::default());
+/// framed.send(Payload::Raw(x224_pdu)).await?;
+/// while let Some(payload) = framed.next().await {
+///     // dispatch payload
+/// }
+/// ```
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TpktCodec {
+    /// Limits on the length of decoded PDUs
+    limits: PduLimits,
+}
+
+impl TpktCodec {
+    pub fn new(limits: PduLimits) -> Self {
+        TpktCodec { limits }
+    }
+}
+
+impl Decoder for TpktCodec {
+    type Item = Payload;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Payload>> {
+        Ok(decode_frame(src, &self.limits)?.map(|(_header, payload)| payload))
+    }
+}
+
+impl Encoder<Payload> for TpktCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Payload, dst: &mut BytesMut) -> Result<()> {
+        match item {
+            Payload::Raw(payload) => {
+                let size = payload.len() + TPKT_HEADER_SIZE;
+                if size > u16::MAX as usize {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "TPKT: payload is too large",
+                    ));
+                }
+
+                dst.reserve(size);
+                dst.put_u8(Action::FastPathActionX224 as u8);
+                dst.put_u8(0);
+                dst.put_u16(size as u16);
+                dst.put_slice(&payload);
+            }
+            // MS-RDPBCGR 2.2.8.1.2 Client Fast-Path Input Event PDU (TS_FP_INPUT_PDU)
+            Payload::FastPath(sec_flag, payload) => {
+                dst.extend_from_slice(&fast_path_frame((sec_flag & 0x3) << 6, &payload)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::limits::is_too_large;
+
+    /// A PDU is only decoded once complete
+    #[test]
+    fn test_decode_partial_tpkt() {
+        let mut codec = TpktCodec::default();
+        let mut buffer = BytesMut::from(&[3, 0, 0][..]);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+
+        buffer.put_slice(&[6, 1]);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+
+        buffer.put_slice(&[2, 3, 0]);
+        match codec.decode(&mut buffer).unwrap() {
+            Some(Payload::Raw(payload)) => assert_eq!(payload.to_vec(), vec![1, 2]),
+            _ => panic!("expected a raw payload"),
+        }
+        assert_eq!(buffer.to_vec(), vec![3, 0]);
+    }
+
+    /// Test of a fast path PDU with a two bytes length
+    #[test]
+    fn test_decode_fast_path() {
+        let mut codec = TpktCodec::default();
+        let mut buffer = BytesMut::from(&[0x80, 0x80][..]);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+
+        buffer.put_slice(&[0x05, 1, 2]);
+        match codec.decode(&mut buffer).unwrap() {
+            Some(Payload::FastPath(sec_flag, payload)) => {
+                assert_eq!(sec_flag, 2);
+                assert_eq!(payload.to_vec(), vec![1, 2]);
+            }
+            _ => panic!("expected a fast path payload"),
+        }
+    }
+
+    /// Encoded payloads are decoded unchanged
+    #[test]
+    fn test_encode_tpkt() {
+        let mut codec = TpktCodec::default();
+        let mut buffer = BytesMut::new();
+        let payload = BytesMut::from(&[0, 0, 0, 1][..]);
+        codec.encode(Payload::Raw(payload), &mut buffer).unwrap();
+        assert_eq!(buffer.to_vec(), vec![3, 0, 0, 8, 0, 0, 0, 1]);
+
+        let payload = BytesMut::from(&[1][..]);
+        codec
+            .encode(Payload::FastPath(1, payload), &mut buffer)
+            .unwrap();
+        assert_eq!(buffer[8..].to_vec(), vec![0x40, 3, 1]);
+
+        assert!(codec.decode(&mut buffer).unwrap().is_some());
+        assert!(codec.decode(&mut buffer).unwrap().is_some());
+        assert!(buffer.is_empty());
+    }
+
+    /// A forged length is rejected before waiting for the data
+    #[test]
+    fn test_decode_too_large() {
+        let mut codec = TpktCodec::new(PduLimits {
+            max_tpkt_size: 0x100,
+            ..Default::default()
+        });
+        let mut buffer = BytesMut::from(&[3, 0, 0xFF, 0xFF][..]);
+        let error = codec.decode(&mut buffer).err().unwrap();
+        assert!(is_too_large(&error));
+    }
+}
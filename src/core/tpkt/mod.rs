@@ -1,2 +1,5 @@
 pub mod base;
-pub mod client;
\ No newline at end of file
+pub mod client;
+pub mod codec;
+pub mod queue;
+pub mod server;
\ No newline at end of file
@@ -0,0 +1,140 @@
+use crate::core::metrics::Metrics;
+
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Priority of a frame in the write queue
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WritePriority {
+    /// Input events, fast path or slow path
+    High,
+    /// Other PDUs, like acknowledgements, refreshes and virtual channel data
+    Low,
+}
+
+/// Frames written by a task of their own, so a slow network
+/// doesn't block the session until the queue is full
+///
+/// High priority frames go before the low priority ones,
+/// the frames of a priority are sent in order
+pub(crate) struct WriteQueue {
+    high: mpsc::Sender<Vec<u8>>,
+    low: mpsc::Sender<Vec<u8>>,
+    /// Frames encrypted with RC4 are all sent in order
+    prioritized: bool,
+    task: Option<JoinHandle<Result<()>>>,
+    metrics: Metrics,
+}
+
+impl WriteQueue {
+    /// Start the task writing on the transport
+    /// Each priority holds at most `capacity` frames
+    pub fn new<W>(writer: W, capacity: usize, prioritized: bool, metrics: Metrics) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (high, high_frames) = mpsc::channel(capacity.max(1));
+        let (low, low_frames) = mpsc::channel(capacity.max(1));
+        let task = tokio::spawn(write_frames(
+            writer,
+            high_frames,
+            low_frames,
+            metrics.clone(),
+        ));
+        WriteQueue {
+            high,
+            low,
+            prioritized,
+            task: Some(task),
+            metrics,
+        }
+    }
+
+    /// Queue a frame, wait while the queue of its priority is full
+    pub async fn send(&mut self, frame: Vec<u8>, priority: WritePriority) -> Result<()> {
+        let sender = match priority {
+            WritePriority::High if self.prioritized => &self.high,
+            _ => &self.low,
+        };
+        self.metrics.add_queued_frame();
+        if sender.send(frame).await.is_ok() {
+            return Ok(());
+        }
+        self.metrics.remove_queued_frame();
+        // The error of the transport ended the task
+        Err(match self.close().await {
+            Err(e) => e,
+            Ok(()) => Error::new(ErrorKind::BrokenPipe, "TPKT: write queue closed"),
+        })
+    }
+
+    /// Write the queued frames then shutdown the transport
+    pub async fn close(&mut self) -> Result<()> {
+        let (high, _) = mpsc::channel(1);
+        let (low, _) = mpsc::channel(1);
+        self.high = high;
+        self.low = low;
+        match self.task.take() {
+            Some(task) => task.await.map_err(|e| Error::new(ErrorKind::Other, e))?,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Body of the writer task, ends once both queues are closed
+async fn write_frames<W>(
+    mut writer: W,
+    mut high: mpsc::Receiver<Vec<u8>>,
+    mut low: mpsc::Receiver<Vec<u8>>,
+    metrics: Metrics,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        let frame = tokio::select! {
+            biased;
+            Some(frame) = high.recv() => frame,
+            Some(frame) = low.recv() => frame,
+            else => break,
+        };
+        metrics.remove_queued_frame();
+        metrics.add_bytes_sent(frame.len());
+        writer.write_all(&frame).await?;
+    }
+    writer.shutdown().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// High priority frames overtake the queued low priority ones
+    #[tokio::test]
+    async fn test_write_queue_priority() {
+        let (writer, mut reader) = tokio::io::duplex(2);
+        let metrics = Metrics::default();
+        let mut queue = WriteQueue::new(writer, 4, true, metrics.clone());
+
+        // The first frame fills the transport, the next ones wait in the queue
+        queue.send(vec![1, 1], WritePriority::Low).await.unwrap();
+        while metrics.snapshot().write_queue_depth > 0 {
+            tokio::task::yield_now().await;
+        }
+        queue.send(vec![2, 2], WritePriority::Low).await.unwrap();
+        queue.send(vec![3, 3], WritePriority::High).await.unwrap();
+        assert_eq!(metrics.snapshot().write_queue_depth, 2);
+
+        let reading = tokio::spawn(async move {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).await.unwrap();
+            data
+        });
+        queue.close().await.unwrap();
+        assert_eq!(reading.await.unwrap(), vec![1, 1, 3, 3, 2, 2]);
+        assert_eq!(metrics.snapshot().write_queue_depth, 0);
+    }
+}
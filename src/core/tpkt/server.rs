@@ -0,0 +1,128 @@
+use bytes::{BufMut, BytesMut};
+use std::io::Result;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::core::limits::PduLimits;
+use crate::core::metrics::Metrics;
+use crate::core::trace::trace_pdu;
+use crate::core::tpkt::base::{fast_path_frame, read_frame, tpkt_frame, Payload};
+use crate::model::data::Message;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::model::link::TlsAcceptor;
+use crate::model::link::Stream;
+
+/// Server Context of TPKT layer
+/// Read the PDUs sent by a client
+/// and write the responses
+///
+/// # Example
+/// ```rust, ignore
+/// let (stream, _) = listener.accept().await?;
+/// let mut tpkt = TpktServer::new(stream);
+/// match tpkt.read().await? {
+///     Payload::Raw(x224) => tpkt.write(response).await?,
+///     Payload::FastPath(sec_flag, input) => (),
+/// }
+/// ```
+pub struct TpktServer<S> {
+    transport: Stream<S>,
+    /// Limits on the length of received PDUs
+    limits: PduLimits,
+    /// Received bytes of the next PDU
+    buffer: BytesMut,
+    /// Counters shared with the caller
+    metrics: Metrics,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> TpktServer<S> {
+    /// Create a new Server based on an accepted connection
+    pub fn new(transport: S) -> Self {
+        TpktServer {
+            transport: Stream::Raw(transport),
+            limits: PduLimits::default(),
+            buffer: BytesMut::new(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Change the limits on the length of received PDUs
+    pub fn set_limits(&mut self, limits: PduLimits) {
+        self.limits = limits;
+    }
+
+    /// Limits on the length of received PDUs
+    pub fn get_limits(&self) -> PduLimits {
+        self.limits
+    }
+
+    /// Share counters with the caller
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    /// Counters of the connection
+    pub fn get_metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Send a message with a TPKT header
+    pub async fn write<T: 'static>(&mut self, message: T) -> Result<()>
+    where
+        T: Message,
+    {
+        let buffer = tpkt_frame(message).await?;
+        trace_pdu!(size = buffer.len(), "TPKT: sent");
+        self.metrics.add_bytes_sent(buffer.len());
+        self.transport.write_all(&buffer).await
+    }
+
+    /// Send a fast path output payload
+    /// MS-RDPBCGR 2.2.9.1.2 Server Fast-Path Update PDU (TS_FP_UPDATE_PDU)
+    pub async fn write_fast_path(&mut self, sec_flag: u8, payload: Vec<u8>) -> Result<()> {
+        let buffer = fast_path_frame((sec_flag & 0x3) << 6, &payload)?;
+        trace_pdu!(size = buffer.len(), "TPKT: sent");
+        self.metrics.add_bytes_sent(buffer.len());
+        self.transport.write_all(&buffer).await
+    }
+
+    /// Read a whole PDU sent by the client
+    ///
+    /// Clients may put the number of fast path input events
+    /// in the header, in that case it is moved in front of
+    /// the payload so it always starts with the number of events
+    /// MS-RDPBCGR 2.2.8.1.2 Client Fast-Path Input Event PDU (TS_FP_INPUT_PDU)
+    pub async fn read(&mut self) -> Result<Payload> {
+        match read_frame(&mut self.transport, &mut self.buffer, &self.limits, &self.metrics).await? {
+            (header, Payload::FastPath(sec_flag, payload)) => {
+                let number_events = (header >> 2) & 0xF;
+                if number_events == 0 {
+                    return Ok(Payload::FastPath(sec_flag, payload));
+                }
+
+                let mut buffer = BytesMut::with_capacity(payload.len() + 1);
+                buffer.put_u8(number_events);
+                buffer.unsplit(payload);
+                Ok(Payload::FastPath(sec_flag, buffer))
+            }
+            (_, payload) => Ok(payload),
+        }
+    }
+
+    /// Continue the connection over SSL
+    /// with the certificate of the acceptor
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn accept_ssl(mut self, acceptor: &TlsAcceptor) -> Result<Self> {
+        self.transport = self.transport.accept_ssl(acceptor).await?;
+        Ok(self)
+    }
+
+    /// Underlying stream, to run CSSP once SSL is started
+    pub fn get_stream(&mut self) -> &mut Stream<S> {
+        &mut self.transport
+    }
+
+    /// Shutdown current connection
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.transport.shutdown().await
+    }
+}
@@ -0,0 +1,13 @@
+/// Debug event for a sent or received PDU
+/// Compiled out without the trace feature
+///
+/// Connection phases are spans made with tracing::instrument
+/// on the connect function of each layer
+macro_rules! trace_pdu {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "trace")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+pub(crate) use trace_pdu;
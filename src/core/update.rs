@@ -0,0 +1,301 @@
+use crate::codec::interleaved::interleaved_rle_decompress;
+use crate::core::event::BitmapEvent;
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+
+/// Slow path update type
+/// MS-RDPBCGR 2.2.9.1.1.3 Server Graphics Update PDU (TS_GRAPHICS_UPDATE)
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum UpdateType {
+    UpdatetypeOrders = 0x0000,
+    UpdatetypeBitmap = 0x0001,
+    UpdatetypePalette = 0x0002,
+    UpdatetypeSynchronize = 0x0003,
+}
+
+/// Flags of a bitmap rectangle
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BitmapFlag {
+    BitmapCompression = 0x0001,
+    NoBitmapCompressionHdr = 0x0400,
+}
+
+/// Size of the bitmapComprHdr field
+const BITMAP_COMPRESSION_HEADER_SIZE: usize = 8;
+
+/// Largest number of colors in a palette
+const PALETTE_MAX_COLORS: usize = 256;
+
+/// Slow path update
+pub enum Update {
+    Bitmap(Vec<BitmapEvent>),
+    Palette(Palette),
+    Synchronize,
+    /// Number of drawing orders and their data
+    Orders(u16, BytesMut),
+    Unknown(u16, BytesMut),
+}
+
+/// Colors used by 8 bpp bitmaps
+/// An empty palette renders all pixels in black
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Palette {
+    /// RGB colors
+    pub colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    /// Convert an 8 bpp bitmap into an uncompressed 32 bpp one
+    /// Other bitmaps, and those which can't be decompressed,
+    /// are returned unchanged
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::event::BitmapEvent;
+    /// use rdp::core::update::Palette;
+    /// let palette = Palette { colors: vec![[0xFF, 0, 0]] };
+    /// let bitmap = BitmapEvent {
+    ///     dest_left: 0,
+    ///     dest_top: 0,
+    ///     dest_right: 0,
+    ///     dest_bottom: 0,
+    ///     width: 1,
+    ///     height: 1,
+    ///     bpp: 8,
+    ///     is_compress: false,
+    ///     data: vec![0],
+    /// };
+    /// assert_eq!(palette.apply(bitmap).data, vec![0, 0, 0xFF, 0xFF]);
+    /// ```
+    pub fn apply(&self, mut bitmap: BitmapEvent) -> BitmapEvent {
+        if bitmap.bpp != 8 {
+            return bitmap;
+        }
+        if bitmap.is_compress {
+            match interleaved_rle_decompress(&bitmap.data, bitmap.width, bitmap.height, 8) {
+                Ok(data) => {
+                    bitmap.data = data;
+                    bitmap.is_compress = false;
+                }
+                Err(_) => return bitmap,
+            }
+        }
+
+        // Same pixel format as 32 bpp bitmaps (BGRA)
+        bitmap.data = bitmap
+            .data
+            .iter()
+            .flat_map(|index| {
+                let [r, g, b] = self.colors.get(*index as usize).copied().unwrap_or_default();
+                [b, g, r, 0xFF]
+            })
+            .collect();
+        bitmap.bpp = 32;
+        bitmap
+    }
+}
+
+/// Read a TS_UPDATE_PALETTE_DATA structure
+/// Shared by the slow path and the fast path
+/// MS-RDPBCGR 2.2.9.1.1.3.1.1 Palette Update Data (TS_UPDATE_PALETTE_DATA)
+pub fn read_palette_update(buffer: &mut BytesMut) -> Result<Palette> {
+    check_remaining(buffer, 8, "UPDATE: palette update")?;
+    let update_type = buffer.get_u16_le();
+    if update_type != UpdateType::UpdatetypePalette as u16 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("UPDATE: unexpected palette update type {}", update_type),
+        ));
+    }
+
+    let _pad2_octets = buffer.get_u16_le();
+    let number_colors = buffer.get_u32_le() as usize;
+    if number_colors > PALETTE_MAX_COLORS {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("UPDATE: invalid number of palette colors {}", number_colors),
+        ));
+    }
+
+    check_remaining(buffer, number_colors * 3, "UPDATE: palette entries")?;
+    let colors = (0..number_colors)
+        .map(|_| [buffer.get_u8(), buffer.get_u8(), buffer.get_u8()])
+        .collect();
+    Ok(Palette { colors })
+}
+
+/// Read a TS_BITMAP_DATA structure
+/// The compression header is skipped when present
+/// MS-RDPBCGR 2.2.9.1.1.3.1.2.2 Bitmap Data (TS_BITMAP_DATA)
+fn read_bitmap_data(buffer: &mut BytesMut) -> Result<BitmapEvent> {
+    check_remaining(buffer, 18, "UPDATE: bitmap data")?;
+    let dest_left = buffer.get_u16_le();
+    let dest_top = buffer.get_u16_le();
+    let dest_right = buffer.get_u16_le();
+    let dest_bottom = buffer.get_u16_le();
+    let width = buffer.get_u16_le();
+    let height = buffer.get_u16_le();
+    let bpp = buffer.get_u16_le();
+    let flags = buffer.get_u16_le();
+    let mut bitmap_length = buffer.get_u16_le() as usize;
+
+    let is_compress = flags & BitmapFlag::BitmapCompression as u16 != 0;
+    if is_compress && flags & BitmapFlag::NoBitmapCompressionHdr as u16 == 0 {
+        // TS_CD_HEADER, only the main body size is meaningful
+        check_remaining(buffer, BITMAP_COMPRESSION_HEADER_SIZE, "UPDATE: bitmap compression")?;
+        let _cb_comp_first_row_size = buffer.get_u16_le();
+        bitmap_length = buffer.get_u16_le() as usize;
+        let _cb_scan_width = buffer.get_u16_le();
+        let _cb_uncompressed_size = buffer.get_u16_le();
+    }
+
+    check_remaining(buffer, bitmap_length, "UPDATE: bitmap data stream")?;
+    Ok(BitmapEvent {
+        dest_left,
+        dest_top,
+        dest_right,
+        dest_bottom,
+        width,
+        height,
+        bpp,
+        is_compress,
+        data: buffer.split_to(bitmap_length).to_vec(),
+    })
+}
+
+/// Read a TS_UPDATE_BITMAP_DATA structure
+/// Shared by the slow path and the fast path
+/// MS-RDPBCGR 2.2.9.1.1.3.1.2 Bitmap Update (TS_UPDATE_BITMAP_DATA)
+pub fn read_bitmap_update(buffer: &mut BytesMut) -> Result<Vec<BitmapEvent>> {
+    check_remaining(buffer, 4, "UPDATE: bitmap update")?;
+    let update_type = buffer.get_u16_le();
+    if update_type != UpdateType::UpdatetypeBitmap as u16 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("UPDATE: unexpected bitmap update type {}", update_type),
+        ));
+    }
+
+    let number_rectangles = buffer.get_u16_le();
+    (0..number_rectangles).map(|_| read_bitmap_data(buffer)).collect()
+}
+
+/// Parse the payload of a slow path update PDU
+/// MS-RDPBCGR 2.2.9.1.1.3 Server Graphics Update PDU (TS_GRAPHICS_UPDATE)
+pub fn read_update(buffer: &mut BytesMut) -> Result<Update> {
+    check_remaining(buffer, 2, "UPDATE: update type")?;
+    let update_type = u16::from_le_bytes([buffer[0], buffer[1]]);
+    match UpdateType::try_from(update_type) {
+        Ok(UpdateType::UpdatetypeBitmap) => Ok(Update::Bitmap(read_bitmap_update(buffer)?)),
+        Ok(UpdateType::UpdatetypePalette) => Ok(Update::Palette(read_palette_update(buffer)?)),
+        Ok(UpdateType::UpdatetypeSynchronize) => Ok(Update::Synchronize),
+        Ok(UpdateType::UpdatetypeOrders) => {
+            // MS-RDPEGDI 2.2.2.1 Orders Update (TS_UPDATE_ORDERS_PDU_DATA)
+            check_remaining(buffer, 8, "UPDATE: orders update")?;
+            buffer.advance(4);
+            let number_orders = buffer.get_u16_le();
+            buffer.advance(2);
+            Ok(Update::Orders(number_orders, buffer.split()))
+        }
+        _ => {
+            buffer.advance(2);
+            Ok(Update::Unknown(update_type, buffer.split()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::BufMut;
+
+    /// Write a bitmap rectangle header
+    fn put_bitmap_data(buffer: &mut BytesMut, flags: u16, length: u16) {
+        for field in [0, 0, 63, 63, 64, 64, 16, flags, length] {
+            buffer.put_u16_le(field);
+        }
+    }
+
+    /// Test a bitmap update with a raw and a compressed rectangle
+    #[test]
+    fn test_read_bitmap_update() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u16_le(UpdateType::UpdatetypeBitmap as u16);
+        buffer.put_u16_le(2);
+        put_bitmap_data(&mut buffer, 0, 4);
+        buffer.put_slice(&[1, 2, 3, 4]);
+        put_bitmap_data(&mut buffer, BitmapFlag::BitmapCompression as u16, 10);
+        for field in [0, 2, 128, 8192] {
+            buffer.put_u16_le(field);
+        }
+        buffer.put_slice(&[5, 6]);
+
+        let bitmaps = match read_update(&mut buffer).unwrap() {
+            Update::Bitmap(bitmaps) => bitmaps,
+            _ => panic!("expected a bitmap update"),
+        };
+        assert_eq!(bitmaps.len(), 2);
+        assert!(!bitmaps[0].is_compress);
+        assert_eq!(bitmaps[0].data, vec![1, 2, 3, 4]);
+        assert!(bitmaps[1].is_compress);
+        assert_eq!(bitmaps[1].data, vec![5, 6]);
+        assert_eq!(bitmaps[1].width, 64);
+        assert!(buffer.is_empty());
+    }
+
+    /// Test of a two colors palette
+    #[test]
+    fn test_read_palette_update() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u16_le(UpdateType::UpdatetypePalette as u16);
+        buffer.put_u16_le(0);
+        buffer.put_u32_le(2);
+        buffer.put_slice(&[1, 2, 3, 4, 5, 6]);
+
+        let palette = match read_update(&mut buffer).unwrap() {
+            Update::Palette(palette) => palette,
+            _ => panic!("expected a palette update"),
+        };
+        assert_eq!(palette.colors, vec![[1, 2, 3], [4, 5, 6]]);
+    }
+
+    /// Compressed 8 bpp bitmaps are decompressed before the palette
+    #[test]
+    fn test_palette_apply_compressed() {
+        let palette = Palette {
+            colors: vec![[0, 0, 0], [1, 2, 3]],
+        };
+        let bitmap = palette.apply(BitmapEvent {
+            dest_left: 0,
+            dest_top: 0,
+            dest_right: 1,
+            dest_bottom: 0,
+            width: 2,
+            height: 1,
+            bpp: 8,
+            is_compress: true,
+            // Color run of 2 pixels of index 1
+            data: vec![0x62, 0x01],
+        });
+        assert!(!bitmap.is_compress);
+        assert_eq!(bitmap.bpp, 32);
+        assert_eq!(bitmap.data, vec![3, 2, 1, 0xFF, 3, 2, 1, 0xFF]);
+    }
+
+    /// A truncated rectangle must be an error
+    #[test]
+    fn test_read_bitmap_update_truncated() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u16_le(UpdateType::UpdatetypeBitmap as u16);
+        buffer.put_u16_le(1);
+        put_bitmap_data(&mut buffer, 0, 4);
+        buffer.put_slice(&[1, 2]);
+
+        assert!(read_update(&mut buffer).is_err());
+    }
+}
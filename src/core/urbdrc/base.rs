@@ -0,0 +1,790 @@
+use crate::model::data::{check_remaining, Message};
+use crate::model::unicode::{from_unicode, Unicode};
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::TryFromPrimitive;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Name of the dynamic virtual channels of the USB redirection
+/// The first one is the control channel, then one channel per device
+pub const URBDRC_CHANNEL_NAME: &str = "URBDRC";
+
+/// Version of the capability exchange
+/// MS-RDPEUSB 2.2.3.1 Interface Manipulation Exchange Capabilities Request (RIM_EXCHANGE_CAPABILITY_REQUEST)
+pub const RIM_CAPABILITY_VERSION_01: u32 = 0x00000001;
+
+/// Interface ids of the messages
+/// Devices use the interface id chosen by the client in the add device message
+/// MS-RDPEUSB 2.2.1 Shared Message Header (SHARED_MSG_HEADER)
+pub const CAPABILITIES_NEGOTIATOR: u32 = 0x00000000;
+pub const CLIENT_DEVICE_SINK: u32 = 0x00000001;
+pub const SERVER_CHANNEL_NOTIFICATION: u32 = 0x00000002;
+pub const CLIENT_CHANNEL_NOTIFICATION: u32 = 0x00000003;
+
+/// Interface id of the first device, the next ones follow
+pub const BASE_USBDEVICE_NUM: u32 = 0x00000005;
+
+/// Size of the header of requests, responses have no function id
+const HEADER_SIZE: usize = 12;
+
+/// Size of the USB device capabilities of the add device message
+/// MS-RDPEUSB 2.2.11 USB_DEVICE_CAPABILITIES
+const USB_DEVICE_CAPABILITIES_SIZE: u32 = 28;
+
+/// Kind of message, in the two high bits of the interface id
+/// MS-RDPEUSB 2.2.1 Shared Message Header (SHARED_MSG_HEADER)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum StreamId {
+    StreamIdNone = 0x0,
+    /// Request, followed by a function id
+    StreamIdProxy = 0x1,
+    /// Response to a request, without function id
+    StreamIdStub = 0x2,
+}
+
+/// Function ids of the messages, shared between the interfaces
+/// MS-RDPEUSB 2.2.1 Shared Message Header (SHARED_MSG_HEADER)
+pub const RIMCALL_RELEASE: u32 = 0x00000001;
+pub const RIM_EXCHANGE_CAPABILITY_REQUEST: u32 = 0x00000100;
+pub const CHANNEL_CREATED: u32 = 0x00000100;
+pub const ADD_VIRTUAL_CHANNEL: u32 = 0x00000100;
+pub const ADD_DEVICE: u32 = 0x00000101;
+pub const CANCEL_REQUEST: u32 = 0x00000100;
+pub const REGISTER_REQUEST_CALLBACK: u32 = 0x00000101;
+pub const IO_CONTROL: u32 = 0x00000102;
+pub const INTERNAL_IO_CONTROL: u32 = 0x00000103;
+pub const QUERY_DEVICE_TEXT: u32 = 0x00000104;
+pub const TRANSFER_IN_REQUEST: u32 = 0x00000105;
+pub const TRANSFER_OUT_REQUEST: u32 = 0x00000106;
+pub const RETRACT_DEVICE: u32 = 0x00000107;
+pub const IOCONTROL_COMPLETION: u32 = 0x00000100;
+pub const URB_COMPLETION: u32 = 0x00000101;
+pub const URB_COMPLETION_NO_DATA: u32 = 0x00000102;
+
+/// Description of a device given to the server
+/// Strings are the ones of the Windows plug and play manager
+/// MS-RDPEUSB 2.2.4.2 Add Device Message (ADD_DEVICE)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddDevice {
+    /// Interface id of the messages of the device
+    pub usb_device: u32,
+    /// As USB\VID_xxxx&PID_xxxx\serial
+    pub device_instance_id: String,
+    pub hardware_ids: Vec<String>,
+    pub compatibility_ids: Vec<String>,
+    /// GUID grouping the functions of a physical device
+    pub container_id: String,
+    /// MS-RDPEUSB 2.2.11 USB_DEVICE_CAPABILITIES
+    pub usb_bus_interface_version: u32,
+    pub usbdi_version: u32,
+    /// As bcdUSB, 0x200 for USB 2.0
+    pub supported_usb_version: u32,
+    pub hcd_capabilities: u32,
+    pub device_is_high_speed: bool,
+    /// Zero when isochronous writes without acknowledgment are not supported
+    pub no_ack_isoch_write_jitter_buffer_size_in_ms: u32,
+}
+
+/// Message of the USB redirection channels, sent by both sides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrbdrcMessage {
+    /// Version of the capabilities of the server
+    /// MS-RDPEUSB 2.2.3.1 Interface Manipulation Exchange Capabilities Request (RIM_EXCHANGE_CAPABILITY_REQUEST)
+    CapabilityRequest(u32),
+    /// Version of the client and HRESULT
+    /// MS-RDPEUSB 2.2.3.2 Interface Manipulation Exchange Capabilities Response (RIM_EXCHANGE_CAPABILITY_RESPONSE)
+    CapabilityResponse { capability: u32, result: u32 },
+    /// Sent by the server on each new channel and answered by the client
+    /// MS-RDPEUSB 2.2.5.1 Channel Created Message (CHANNEL_CREATED)
+    ChannelCreated {
+        major_version: u32,
+        minor_version: u32,
+        capabilities: u32,
+    },
+    /// Ask the server to open a new channel for a device
+    /// MS-RDPEUSB 2.2.4.1 Add Virtual Channel Message (ADD_VIRTUAL_CHANNEL)
+    AddVirtualChannel,
+    /// MS-RDPEUSB 2.2.4.2 Add Device Message (ADD_DEVICE)
+    AddDevice(AddDevice),
+    /// Request id of a transfer to cancel
+    /// MS-RDPEUSB 2.2.6.1 Cancel Request Message (CANCEL_REQUEST)
+    CancelRequest(u32),
+    /// Interface id of the completions, none to stop them
+    /// MS-RDPEUSB 2.2.6.2 Register Request Callback Message (REGISTER_REQUEST_CALLBACK)
+    RegisterRequestCallback(Option<u32>),
+    /// MS-RDPEUSB 2.2.6.3 IO Control Message (IO_CONTROL)
+    /// MS-RDPEUSB 2.2.6.4 Internal IO Control Message (INTERNAL_IO_CONTROL)
+    IoControl {
+        internal: bool,
+        io_control_code: u32,
+        input: Vec<u8>,
+        output_buffer_size: u32,
+        request_id: u32,
+    },
+    /// MS-RDPEUSB 2.2.6.5 Query Device Text Message (QUERY_DEVICE_TEXT)
+    QueryDeviceText { text_type: u32, locale_id: u32 },
+    /// MS-RDPEUSB 2.2.6.6 Query Device Text Response Message (QUERY_DEVICE_TEXT_RSP)
+    QueryDeviceTextResponse { description: String, hresult: u32 },
+    /// TS_URB reading from the device
+    /// MS-RDPEUSB 2.2.6.7 Transfer In Request (TRANSFER_IN_REQUEST)
+    TransferIn {
+        ts_urb: Vec<u8>,
+        output_buffer_size: u32,
+    },
+    /// TS_URB writing to the device
+    /// MS-RDPEUSB 2.2.6.8 Transfer Out Request (TRANSFER_OUT_REQUEST)
+    TransferOut { ts_urb: Vec<u8>, output: Vec<u8> },
+    /// Reason of the end of the redirection of the device
+    /// MS-RDPEUSB 2.2.6.9 Retract Device (RETRACT_DEVICE)
+    RetractDevice(u32),
+    /// MS-RDPEUSB 2.2.7.1 IO Control Completion (IOCONTROL_COMPLETION)
+    IoControlCompletion {
+        request_id: u32,
+        hresult: u32,
+        information: u32,
+        output: Vec<u8>,
+    },
+    /// Result of a transfer in
+    /// MS-RDPEUSB 2.2.7.2 URB Completion (URB_COMPLETION)
+    UrbCompletion {
+        request_id: u32,
+        ts_urb_result: Vec<u8>,
+        hresult: u32,
+        output: Vec<u8>,
+    },
+    /// Result of a transfer out, with the size written
+    /// MS-RDPEUSB 2.2.7.3 URB Completion No Data (URB_COMPLETION_NO_DATA)
+    UrbCompletionNoData {
+        request_id: u32,
+        ts_urb_result: Vec<u8>,
+        hresult: u32,
+        output_buffer_size: u32,
+    },
+    /// The interface is not used anymore
+    /// MS-RDPEUSB 2.2.2 Interface Manipulation Release Message (RIMCALL_RELEASE)
+    Release,
+}
+
+/// Message with its shared header
+/// Completions can't be read back, their interface id is only known by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrbdrcPdu {
+    pub interface_id: u32,
+    pub message_id: u32,
+    pub message: UrbdrcMessage,
+}
+
+/// Write a null terminated string with its size in characters
+fn write_string(buffer: &mut Vec<u8>, value: &str) {
+    let data = value.to_unicode();
+    buffer.put_u32_le((data.len() / 2 + 1) as u32);
+    buffer.extend_from_slice(&data);
+    buffer.put_u16_le(0);
+}
+
+/// Write a list of null terminated strings, ended by an empty one
+fn write_multi_string(buffer: &mut Vec<u8>, values: &[String]) {
+    let data: Vec<u8> = values
+        .iter()
+        .flat_map(|value| {
+            let mut data = value.to_unicode();
+            data.extend_from_slice(&[0, 0]);
+            data
+        })
+        .collect();
+    buffer.put_u32_le((data.len() / 2 + 1) as u32);
+    buffer.extend_from_slice(&data);
+    buffer.put_u16_le(0);
+}
+
+/// Characters of a string or a list of strings, with their size
+fn read_chars(buffer: &mut BytesMut) -> Result<Vec<u8>> {
+    check_remaining(buffer, 4, "URBDRC: string size")?;
+    let size = buffer.get_u32_le() as usize * 2;
+    check_remaining(buffer, size, "URBDRC: string")?;
+    Ok(buffer.split_to(size).to_vec())
+}
+
+fn read_string(buffer: &mut BytesMut) -> Result<String> {
+    Ok(from_unicode(&read_chars(buffer)?))
+}
+
+fn read_multi_string(buffer: &mut BytesMut) -> Result<Vec<String>> {
+    Ok(from_unicode_list(&read_chars(buffer)?)
+        .into_iter()
+        .filter(|value| !value.is_empty())
+        .collect())
+}
+
+/// Strings of a list of null terminated strings
+fn from_unicode_list(data: &[u8]) -> Vec<String> {
+    let chars: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    chars
+        .split(|c| *c == 0)
+        .map(String::from_utf16_lossy)
+        .collect()
+}
+
+impl UrbdrcMessage {
+    /// Responses have no function id
+    fn stream_id(&self) -> StreamId {
+        match self {
+            UrbdrcMessage::CapabilityResponse { .. }
+            | UrbdrcMessage::QueryDeviceTextResponse { .. } => StreamId::StreamIdStub,
+            _ => StreamId::StreamIdProxy,
+        }
+    }
+
+    fn function_id(&self) -> Option<u32> {
+        Some(match self {
+            UrbdrcMessage::CapabilityResponse { .. }
+            | UrbdrcMessage::QueryDeviceTextResponse { .. } => return None,
+            UrbdrcMessage::CapabilityRequest(_) => RIM_EXCHANGE_CAPABILITY_REQUEST,
+            UrbdrcMessage::ChannelCreated { .. } => CHANNEL_CREATED,
+            UrbdrcMessage::AddVirtualChannel => ADD_VIRTUAL_CHANNEL,
+            UrbdrcMessage::AddDevice(_) => ADD_DEVICE,
+            UrbdrcMessage::CancelRequest(_) => CANCEL_REQUEST,
+            UrbdrcMessage::RegisterRequestCallback(_) => REGISTER_REQUEST_CALLBACK,
+            UrbdrcMessage::IoControl {
+                internal: false, ..
+            } => IO_CONTROL,
+            UrbdrcMessage::IoControl { internal: true, .. } => INTERNAL_IO_CONTROL,
+            UrbdrcMessage::QueryDeviceText { .. } => QUERY_DEVICE_TEXT,
+            UrbdrcMessage::TransferIn { .. } => TRANSFER_IN_REQUEST,
+            UrbdrcMessage::TransferOut { .. } => TRANSFER_OUT_REQUEST,
+            UrbdrcMessage::RetractDevice(_) => RETRACT_DEVICE,
+            UrbdrcMessage::IoControlCompletion { .. } => IOCONTROL_COMPLETION,
+            UrbdrcMessage::UrbCompletion { .. } => URB_COMPLETION,
+            UrbdrcMessage::UrbCompletionNoData { .. } => URB_COMPLETION_NO_DATA,
+            UrbdrcMessage::Release => RIMCALL_RELEASE,
+        })
+    }
+
+    fn body(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        match self {
+            UrbdrcMessage::CapabilityRequest(value)
+            | UrbdrcMessage::CancelRequest(value)
+            | UrbdrcMessage::RetractDevice(value) => buffer.put_u32_le(*value),
+            UrbdrcMessage::CapabilityResponse { capability, result } => {
+                buffer.put_u32_le(*capability);
+                buffer.put_u32_le(*result);
+            }
+            UrbdrcMessage::ChannelCreated {
+                major_version,
+                minor_version,
+                capabilities,
+            } => {
+                buffer.put_u32_le(*major_version);
+                buffer.put_u32_le(*minor_version);
+                buffer.put_u32_le(*capabilities);
+            }
+            UrbdrcMessage::AddVirtualChannel | UrbdrcMessage::Release => (),
+            UrbdrcMessage::AddDevice(device) => {
+                buffer.put_u32_le(1);
+                buffer.put_u32_le(device.usb_device);
+                write_string(&mut buffer, &device.device_instance_id);
+                write_multi_string(&mut buffer, &device.hardware_ids);
+                write_multi_string(&mut buffer, &device.compatibility_ids);
+                write_string(&mut buffer, &device.container_id);
+                buffer.put_u32_le(USB_DEVICE_CAPABILITIES_SIZE);
+                buffer.put_u32_le(device.usb_bus_interface_version);
+                buffer.put_u32_le(device.usbdi_version);
+                buffer.put_u32_le(device.supported_usb_version);
+                buffer.put_u32_le(device.hcd_capabilities);
+                buffer.put_u32_le(device.device_is_high_speed as u32);
+                buffer.put_u32_le(device.no_ack_isoch_write_jitter_buffer_size_in_ms);
+            }
+            UrbdrcMessage::RegisterRequestCallback(request_completion) => {
+                match request_completion {
+                    Some(interface_id) => {
+                        buffer.put_u32_le(1);
+                        buffer.put_u32_le(*interface_id);
+                    }
+                    None => buffer.put_u32_le(0),
+                }
+            }
+            UrbdrcMessage::IoControl {
+                io_control_code,
+                input,
+                output_buffer_size,
+                request_id,
+                ..
+            } => {
+                buffer.put_u32_le(*io_control_code);
+                buffer.put_u32_le(input.len() as u32);
+                buffer.extend_from_slice(input);
+                buffer.put_u32_le(*output_buffer_size);
+                buffer.put_u32_le(*request_id);
+            }
+            UrbdrcMessage::QueryDeviceText {
+                text_type,
+                locale_id,
+            } => {
+                buffer.put_u32_le(*text_type);
+                buffer.put_u32_le(*locale_id);
+            }
+            UrbdrcMessage::QueryDeviceTextResponse {
+                description,
+                hresult,
+            } => {
+                write_string(&mut buffer, description);
+                buffer.put_u32_le(*hresult);
+            }
+            UrbdrcMessage::TransferIn {
+                ts_urb,
+                output_buffer_size,
+            } => {
+                buffer.put_u32_le(ts_urb.len() as u32);
+                buffer.extend_from_slice(ts_urb);
+                buffer.put_u32_le(*output_buffer_size);
+            }
+            UrbdrcMessage::TransferOut { ts_urb, output } => {
+                buffer.put_u32_le(ts_urb.len() as u32);
+                buffer.extend_from_slice(ts_urb);
+                buffer.put_u32_le(output.len() as u32);
+                buffer.extend_from_slice(output);
+            }
+            UrbdrcMessage::IoControlCompletion {
+                request_id,
+                hresult,
+                information,
+                output,
+            } => {
+                buffer.put_u32_le(*request_id);
+                buffer.put_u32_le(*hresult);
+                buffer.put_u32_le(*information);
+                buffer.put_u32_le(output.len() as u32);
+                buffer.extend_from_slice(output);
+            }
+            UrbdrcMessage::UrbCompletion {
+                request_id,
+                ts_urb_result,
+                hresult,
+                output,
+            } => {
+                buffer.put_u32_le(*request_id);
+                buffer.put_u32_le(ts_urb_result.len() as u32);
+                buffer.extend_from_slice(ts_urb_result);
+                buffer.put_u32_le(*hresult);
+                buffer.put_u32_le(output.len() as u32);
+                buffer.extend_from_slice(output);
+            }
+            UrbdrcMessage::UrbCompletionNoData {
+                request_id,
+                ts_urb_result,
+                hresult,
+                output_buffer_size,
+            } => {
+                buffer.put_u32_le(*request_id);
+                buffer.put_u32_le(ts_urb_result.len() as u32);
+                buffer.extend_from_slice(ts_urb_result);
+                buffer.put_u32_le(*hresult);
+                buffer.put_u32_le(*output_buffer_size);
+            }
+        }
+        buffer
+    }
+}
+
+/// Bytes prefixed by their size
+fn read_sized(buffer: &mut BytesMut, context: &str) -> Result<Vec<u8>> {
+    check_remaining(buffer, 4, context)?;
+    let size = buffer.get_u32_le() as usize;
+    check_remaining(buffer, size, context)?;
+    Ok(buffer.split_to(size).to_vec())
+}
+
+/// Messages of the device sink, sent by the client
+fn read_device_sink(buffer: &mut BytesMut, function_id: u32) -> Result<UrbdrcMessage> {
+    match function_id {
+        ADD_VIRTUAL_CHANNEL => Ok(UrbdrcMessage::AddVirtualChannel),
+        ADD_DEVICE => {
+            check_remaining(buffer, 8, "URBDRC: add device")?;
+            let _num_usb_device = buffer.get_u32_le();
+            let usb_device = buffer.get_u32_le();
+            let device_instance_id = read_string(buffer)?;
+            let hardware_ids = read_multi_string(buffer)?;
+            let compatibility_ids = read_multi_string(buffer)?;
+            let container_id = read_string(buffer)?;
+            check_remaining(
+                buffer,
+                USB_DEVICE_CAPABILITIES_SIZE as usize,
+                "URBDRC: device capabilities",
+            )?;
+            let _cb_size = buffer.get_u32_le();
+            Ok(UrbdrcMessage::AddDevice(AddDevice {
+                usb_device,
+                device_instance_id,
+                hardware_ids,
+                compatibility_ids,
+                container_id,
+                usb_bus_interface_version: buffer.get_u32_le(),
+                usbdi_version: buffer.get_u32_le(),
+                supported_usb_version: buffer.get_u32_le(),
+                hcd_capabilities: buffer.get_u32_le(),
+                device_is_high_speed: buffer.get_u32_le() != 0,
+                no_ack_isoch_write_jitter_buffer_size_in_ms: buffer.get_u32_le(),
+            }))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("URBDRC: unexpected device sink function {:#x}", function_id),
+        )),
+    }
+}
+
+/// Messages of the server on the interface of a device
+fn read_device(buffer: &mut BytesMut, function_id: u32) -> Result<UrbdrcMessage> {
+    match function_id {
+        CANCEL_REQUEST => {
+            check_remaining(buffer, 4, "URBDRC: cancel request")?;
+            Ok(UrbdrcMessage::CancelRequest(buffer.get_u32_le()))
+        }
+        REGISTER_REQUEST_CALLBACK => {
+            check_remaining(buffer, 4, "URBDRC: register request callback")?;
+            if buffer.get_u32_le() == 0 {
+                return Ok(UrbdrcMessage::RegisterRequestCallback(None));
+            }
+            check_remaining(buffer, 4, "URBDRC: request completion")?;
+            Ok(UrbdrcMessage::RegisterRequestCallback(Some(
+                buffer.get_u32_le(),
+            )))
+        }
+        IO_CONTROL | INTERNAL_IO_CONTROL => {
+            check_remaining(buffer, 4, "URBDRC: io control")?;
+            let io_control_code = buffer.get_u32_le();
+            let input = read_sized(buffer, "URBDRC: io control input")?;
+            check_remaining(buffer, 8, "URBDRC: io control")?;
+            Ok(UrbdrcMessage::IoControl {
+                internal: function_id == INTERNAL_IO_CONTROL,
+                io_control_code,
+                input,
+                output_buffer_size: buffer.get_u32_le(),
+                request_id: buffer.get_u32_le(),
+            })
+        }
+        QUERY_DEVICE_TEXT => {
+            check_remaining(buffer, 8, "URBDRC: query device text")?;
+            Ok(UrbdrcMessage::QueryDeviceText {
+                text_type: buffer.get_u32_le(),
+                locale_id: buffer.get_u32_le(),
+            })
+        }
+        TRANSFER_IN_REQUEST => {
+            let ts_urb = read_sized(buffer, "URBDRC: transfer in")?;
+            check_remaining(buffer, 4, "URBDRC: transfer in")?;
+            Ok(UrbdrcMessage::TransferIn {
+                ts_urb,
+                output_buffer_size: buffer.get_u32_le(),
+            })
+        }
+        TRANSFER_OUT_REQUEST => Ok(UrbdrcMessage::TransferOut {
+            ts_urb: read_sized(buffer, "URBDRC: transfer out")?,
+            output: read_sized(buffer, "URBDRC: transfer out data")?,
+        }),
+        RETRACT_DEVICE => {
+            check_remaining(buffer, 4, "URBDRC: retract device")?;
+            Ok(UrbdrcMessage::RetractDevice(buffer.get_u32_le()))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("URBDRC: unexpected device function {:#x}", function_id),
+        )),
+    }
+}
+
+/// Read a message of the USB redirection, a whole message of a channel
+pub fn read_urbdrc_pdu(buffer: &mut BytesMut) -> Result<UrbdrcPdu> {
+    check_remaining(buffer, 8, "URBDRC: header")?;
+    let value = buffer.get_u32_le();
+    let interface_id = value & 0x3FFFFFFF;
+    let message_id = buffer.get_u32_le();
+    let message = match StreamId::try_from(value >> 30) {
+        Ok(StreamId::StreamIdStub) if interface_id == CAPABILITIES_NEGOTIATOR => {
+            check_remaining(buffer, 8, "URBDRC: capability response")?;
+            UrbdrcMessage::CapabilityResponse {
+                capability: buffer.get_u32_le(),
+                result: buffer.get_u32_le(),
+            }
+        }
+        Ok(StreamId::StreamIdStub) => {
+            let description = read_string(buffer)?;
+            check_remaining(buffer, 4, "URBDRC: query device text response")?;
+            UrbdrcMessage::QueryDeviceTextResponse {
+                description,
+                hresult: buffer.get_u32_le(),
+            }
+        }
+        _ => {
+            check_remaining(buffer, 4, "URBDRC: function id")?;
+            let function_id = buffer.get_u32_le();
+            match interface_id {
+                _ if function_id == RIMCALL_RELEASE => UrbdrcMessage::Release,
+                CAPABILITIES_NEGOTIATOR => {
+                    check_remaining(buffer, 4, "URBDRC: capability request")?;
+                    UrbdrcMessage::CapabilityRequest(buffer.get_u32_le())
+                }
+                CLIENT_DEVICE_SINK => read_device_sink(buffer, function_id)?,
+                SERVER_CHANNEL_NOTIFICATION | CLIENT_CHANNEL_NOTIFICATION => {
+                    check_remaining(buffer, 12, "URBDRC: channel created")?;
+                    UrbdrcMessage::ChannelCreated {
+                        major_version: buffer.get_u32_le(),
+                        minor_version: buffer.get_u32_le(),
+                        capabilities: buffer.get_u32_le(),
+                    }
+                }
+                _ => read_device(buffer, function_id)?,
+            }
+        }
+    };
+    buffer.clear();
+    Ok(UrbdrcPdu {
+        interface_id,
+        message_id,
+        message,
+    })
+}
+
+#[async_trait]
+impl Message for UrbdrcPdu {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> Result<()> {
+        let stream_id = self.message.stream_id() as u32;
+        writer
+            .write_u32_le(self.interface_id & 0x3FFFFFFF | stream_id << 30)
+            .await?;
+        writer.write_u32_le(self.message_id).await?;
+        if let Some(function_id) = self.message.function_id() {
+            writer.write_u32_le(function_id).await?;
+        }
+        writer.write_all(&self.message.body()).await
+    }
+
+    async fn read_from(&mut self, reader: &mut (impl AsyncRead + Unpin + Send)) -> Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await?;
+        *self = read_urbdrc_pdu(&mut BytesMut::from(&data[..]))?;
+        Ok(())
+    }
+
+    fn length(&self) -> usize {
+        let header = match self.message.function_id() {
+            Some(_) => HEADER_SIZE,
+            None => HEADER_SIZE - 4,
+        };
+        header + self.message.body().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::data::to_vec;
+
+    /// Check the encoding of a PDU both ways
+    async fn check_pdu(interface_id: u32, message_id: u32, message: UrbdrcMessage, data: &[u8]) {
+        let pdu = UrbdrcPdu {
+            interface_id,
+            message_id,
+            message,
+        };
+        assert_eq!(pdu.length(), data.len());
+        assert_eq!(to_vec(&pdu).await.unwrap(), data);
+        assert_eq!(read_urbdrc_pdu(&mut BytesMut::from(data)).unwrap(), pdu);
+    }
+
+    /// MS-RDPEUSB 2.2.3 Interface Manipulation Exchange Capabilities Interface
+    /// MS-RDPEUSB 2.2.5 Channel Notification Interface
+    #[tokio::test]
+    async fn test_urbdrc_capability_pdu() {
+        check_pdu(
+            CAPABILITIES_NEGOTIATOR,
+            0,
+            UrbdrcMessage::CapabilityRequest(RIM_CAPABILITY_VERSION_01),
+            &[
+                0x00, 0x00, 0x00, 0x40, // InterfaceId, Mask STREAM_ID_PROXY
+                0x00, 0x00, 0x00, 0x00, // MessageId
+                0x00, 0x01, 0x00, 0x00, // FunctionId RIM_EXCHANGE_CAPABILITY_REQUEST
+                0x01, 0x00, 0x00, 0x00, // CapabilityValue RIM_CAPABILITY_VERSION_01
+            ],
+        )
+        .await;
+        check_pdu(
+            CAPABILITIES_NEGOTIATOR,
+            0,
+            UrbdrcMessage::CapabilityResponse {
+                capability: RIM_CAPABILITY_VERSION_01,
+                result: 0,
+            },
+            &[
+                0x00, 0x00, 0x00, 0x80, // InterfaceId, Mask STREAM_ID_STUB
+                0x00, 0x00, 0x00, 0x00, // MessageId
+                0x01, 0x00, 0x00, 0x00, // CapabilityValue RIM_CAPABILITY_VERSION_01
+                0x00, 0x00, 0x00, 0x00, // Result S_OK
+            ],
+        )
+        .await;
+        check_pdu(
+            CLIENT_CHANNEL_NOTIFICATION,
+            1,
+            UrbdrcMessage::ChannelCreated {
+                major_version: 1,
+                minor_version: 0,
+                capabilities: 0,
+            },
+            &[
+                0x03, 0x00, 0x00, 0x40, // InterfaceId, Mask STREAM_ID_PROXY
+                0x01, 0x00, 0x00, 0x00, // MessageId
+                0x00, 0x01, 0x00, 0x00, // FunctionId CHANNEL_CREATED
+                0x01, 0x00, 0x00, 0x00, // MajorVersion
+                0x00, 0x00, 0x00, 0x00, // MinorVersion
+                0x00, 0x00, 0x00, 0x00, // Capabilities
+            ],
+        )
+        .await;
+    }
+
+    /// MS-RDPEUSB 2.2.4 Device Sink Interface
+    #[tokio::test]
+    async fn test_urbdrc_device_sink_pdu() {
+        check_pdu(
+            CLIENT_DEVICE_SINK,
+            2,
+            UrbdrcMessage::AddVirtualChannel,
+            &[
+                0x01, 0x00, 0x00, 0x40, // InterfaceId, Mask STREAM_ID_PROXY
+                0x02, 0x00, 0x00, 0x00, // MessageId
+                0x00, 0x01, 0x00, 0x00, // FunctionId ADD_VIRTUAL_CHANNEL
+            ],
+        )
+        .await;
+        check_pdu(
+            CLIENT_DEVICE_SINK,
+            3,
+            UrbdrcMessage::AddDevice(AddDevice {
+                usb_device: BASE_USBDEVICE_NUM,
+                device_instance_id: "A".to_string(),
+                hardware_ids: vec!["B".to_string()],
+                compatibility_ids: vec![],
+                container_id: "C".to_string(),
+                supported_usb_version: 0x200,
+                device_is_high_speed: true,
+                ..Default::default()
+            }),
+            &[
+                0x01, 0x00, 0x00, 0x40, // InterfaceId, Mask STREAM_ID_PROXY
+                0x03, 0x00, 0x00, 0x00, // MessageId
+                0x01, 0x01, 0x00, 0x00, // FunctionId ADD_DEVICE
+                0x01, 0x00, 0x00, 0x00, // NumUsbDevice
+                0x05, 0x00, 0x00, 0x00, // UsbDevice
+                0x02, 0x00, 0x00, 0x00, // cchDeviceInstanceId
+                0x41, 0x00, 0x00, 0x00, // DeviceInstanceId
+                0x03, 0x00, 0x00, 0x00, // cchHwIds
+                0x42, 0x00, 0x00, 0x00, 0x00, 0x00, // HardwareIds
+                0x01, 0x00, 0x00, 0x00, // cchCompatIds
+                0x00, 0x00, // CompatibilityIds
+                0x02, 0x00, 0x00, 0x00, // cchContainerId
+                0x43, 0x00, 0x00, 0x00, // ContainerId
+                0x1C, 0x00, 0x00, 0x00, // CbSize
+                0x00, 0x00, 0x00, 0x00, // UsbBusInterfaceVersion
+                0x00, 0x00, 0x00, 0x00, // USBDI_Version
+                0x00, 0x02, 0x00, 0x00, // Supported_USB_Version
+                0x00, 0x00, 0x00, 0x00, // HcdCapabilities
+                0x01, 0x00, 0x00, 0x00, // DeviceIsHighSpeed
+                0x00, 0x00, 0x00, 0x00, // NoAckIsochWriteJitterBufferSizeInMs
+            ],
+        )
+        .await;
+    }
+
+    /// MS-RDPEUSB 2.2.6 USB Device Interface
+    #[tokio::test]
+    async fn test_urbdrc_device_pdu() {
+        check_pdu(
+            BASE_USBDEVICE_NUM,
+            4,
+            UrbdrcMessage::RegisterRequestCallback(Some(0x10)),
+            &[
+                0x05, 0x00, 0x00, 0x40, // InterfaceId, Mask STREAM_ID_PROXY
+                0x04, 0x00, 0x00, 0x00, // MessageId
+                0x01, 0x01, 0x00, 0x00, // FunctionId REGISTER_REQUEST_CALLBACK
+                0x01, 0x00, 0x00, 0x00, // NumRequestCompletion
+                0x10, 0x00, 0x00, 0x00, // RequestCompletion
+            ],
+        )
+        .await;
+        check_pdu(
+            BASE_USBDEVICE_NUM,
+            5,
+            UrbdrcMessage::IoControl {
+                internal: true,
+                io_control_code: 0x00224000,
+                input: vec![],
+                output_buffer_size: 4,
+                request_id: 7,
+            },
+            &[
+                0x05, 0x00, 0x00, 0x40, // InterfaceId, Mask STREAM_ID_PROXY
+                0x05, 0x00, 0x00, 0x00, // MessageId
+                0x03, 0x01, 0x00, 0x00, // FunctionId INTERNAL_IO_CONTROL
+                0x00, 0x40, 0x22, 0x00, // IoControlCode
+                0x00, 0x00, 0x00, 0x00, // InputBufferSize
+                0x04, 0x00, 0x00, 0x00, // OutputBufferSize
+                0x07, 0x00, 0x00, 0x00, // RequestId
+            ],
+        )
+        .await;
+        check_pdu(
+            BASE_USBDEVICE_NUM,
+            6,
+            UrbdrcMessage::QueryDeviceTextResponse {
+                description: "Dev".to_string(),
+                hresult: 0,
+            },
+            &[
+                0x05, 0x00, 0x00, 0x80, // InterfaceId, Mask STREAM_ID_STUB
+                0x06, 0x00, 0x00, 0x00, // MessageId
+                0x04, 0x00, 0x00, 0x00, // cchDeviceDescription
+                0x44, 0x00, 0x65, 0x00, 0x76, 0x00, 0x00, 0x00, // DeviceDescription
+                0x00, 0x00, 0x00, 0x00, // HResult S_OK
+            ],
+        )
+        .await;
+        check_pdu(
+            BASE_USBDEVICE_NUM,
+            7,
+            UrbdrcMessage::TransferOut {
+                ts_urb: vec![1, 2, 3, 4],
+                output: vec![5, 6],
+            },
+            &[
+                0x05, 0x00, 0x00, 0x40, // InterfaceId, Mask STREAM_ID_PROXY
+                0x07, 0x00, 0x00, 0x00, // MessageId
+                0x06, 0x01, 0x00, 0x00, // FunctionId TRANSFER_OUT_REQUEST
+                0x04, 0x00, 0x00, 0x00, // CbTsUrb
+                0x01, 0x02, 0x03, 0x04, // TsUrb
+                0x02, 0x00, 0x00, 0x00, // OutputBufferSize
+                0x05, 0x06, // OutputBuffer
+            ],
+        )
+        .await;
+        check_pdu(
+            BASE_USBDEVICE_NUM,
+            8,
+            UrbdrcMessage::RetractDevice(1),
+            &[
+                0x05, 0x00, 0x00, 0x40, // InterfaceId, Mask STREAM_ID_PROXY
+                0x08, 0x00, 0x00, 0x00, // MessageId
+                0x07, 0x01, 0x00, 0x00, // FunctionId RETRACT_DEVICE
+                0x01, 0x00, 0x00, 0x00, // Reason UsbRetractReason_BlockedByPolicy
+            ],
+        )
+        .await;
+    }
+}
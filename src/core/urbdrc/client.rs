@@ -0,0 +1,894 @@
+use crate::core::drdynvc::client::{to_messages, DynamicChannelHandler};
+use crate::core::event::{DynamicChannelEvent, RdpEvent};
+use crate::core::urbdrc::base::{
+    read_urbdrc_pdu, AddDevice, UrbdrcMessage, UrbdrcPdu, BASE_USBDEVICE_NUM,
+    CAPABILITIES_NEGOTIATOR, CLIENT_DEVICE_SINK, RIM_CAPABILITY_VERSION_01,
+    SERVER_CHANNEL_NOTIFICATION, URBDRC_CHANNEL_NAME,
+};
+use crate::core::urbdrc::urb::{
+    frame_number_result, read_ts_urb, select_configuration_result, select_interface_result,
+    ts_urb_result, SetupPacket, Urb, UsbInterface, USBD_STATUS_NOT_SUPPORTED,
+    USBD_STATUS_STALL_PID, USBD_STATUS_SUCCESS,
+};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use std::any::Any;
+use std::io::Result;
+use std::time::Instant;
+
+/// Io controls of the server on a device
+/// MS-RDPEUSB 2.2.13 IOCTL_INTERNAL_USB Codes
+const IOCTL_INTERNAL_USB_RESET_PORT: u32 = 0x00220007;
+const IOCTL_INTERNAL_USB_GET_PORT_STATUS: u32 = 0x00220013;
+const IOCTL_INTERNAL_USB_CYCLE_PORT: u32 = 0x0022001F;
+const IOCTL_TSUSBGD_IOCTL_USBDI_QUERY_BUS_TIME: u32 = 0x00224000;
+
+/// The port is enabled and a device is connected
+const USBD_PORT_ENABLED_AND_CONNECTED: u32 = 0x00000003;
+
+/// HRESULT of the io controls which are not handled
+const E_NOTIMPL: u32 = 0x80004001;
+
+/// Versions of the bus interface and of USBDI announced for each device
+/// MS-RDPEUSB 2.2.11 USB_DEVICE_CAPABILITIES
+const USB_BUS_INTERFACE_VERSION: u32 = 0x00000002;
+const USBDI_VERSION: u32 = 0x00000500;
+
+/// Description of a redirected device, from its device descriptor
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbDeviceInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// As bcdDevice
+    pub device_version: u16,
+    /// As bcdUSB
+    pub usb_version: u16,
+    /// Zero for composite devices
+    pub class: u8,
+    pub sub_class: u8,
+    pub protocol: u8,
+    pub serial_number: Option<String>,
+    /// Shown by the server in its device manager
+    pub description: String,
+    pub high_speed: bool,
+}
+
+/// USB device of the client, driven by the server
+/// Transfers are synchronous, an interrupt transfer which finds no data
+/// should fail after a short timeout, the server polls it again
+pub trait UsbBackend {
+    fn info(&self) -> UsbDeviceInfo;
+    /// Set a configuration and claim its interfaces,
+    /// none unconfigures the device
+    fn select_configuration(
+        &mut self,
+        configuration_value: Option<u8>,
+    ) -> Result<Vec<UsbInterface>>;
+    /// Set the alternate setting of an interface
+    fn select_interface(&mut self, number: u8, alternate_setting: u8) -> Result<UsbInterface>;
+    /// Transfer on the default control pipe
+    /// Data read for the requests in, empty for the requests out
+    fn control_transfer(&mut self, setup: SetupPacket, data: &[u8]) -> Result<Vec<u8>>;
+    /// Bulk or interrupt transfer, up to length bytes are read from the endpoints in
+    /// and data is written to the endpoints out
+    fn transfer(&mut self, endpoint: u8, data: &[u8], length: usize) -> Result<Vec<u8>>;
+    /// Clear the halt of an endpoint
+    fn reset_endpoint(&mut self, _endpoint: u8) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Event of the USB redirection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrbdrcEvent {
+    /// The device is announced to the server on its own channel
+    DeviceAdded { channel_id: u32, usb_device: u32 },
+    /// The server stopped the redirection of a device
+    DeviceRetracted { usb_device: u32, reason: u32 },
+}
+
+/// Device with the state of its channel
+struct RedirectedDevice {
+    backend: Box<dyn UsbBackend + Send>,
+    /// Interface id of the messages of the device
+    usb_device: u32,
+    /// Asked with an add virtual channel message
+    announced: bool,
+    channel_id: Option<u32>,
+    /// Interface id of the completions
+    request_completion: Option<u32>,
+}
+
+/// Client of the USB redirection dynamic virtual channels
+/// The first channel is the control channel, where the client asks
+/// a new channel for each device with an add virtual channel message
+///
+/// Each message is processed with the id of its channel,
+/// responses are sent on the same channel
+///
+/// # Example
+/// ```
+/// use rdp::core::urbdrc::client::UrbdrcClient;
+/// let mut client = UrbdrcClient::new();
+/// // Capability request of the server on the control channel
+/// let mut data = bytes::BytesMut::from(&[0, 0, 0, 0x40, 0, 0, 0, 0, 0, 1, 0, 0, 1, 0, 0, 0][..]);
+/// let responses = client.process(1, &mut data, &mut |_| {}).unwrap();
+/// assert_eq!(responses.len(), 1);
+/// ```
+pub struct UrbdrcClient {
+    devices: Vec<RedirectedDevice>,
+    control_channel: Option<u32>,
+    /// The control channel is created, devices can be announced
+    ready: bool,
+    next_usb_device: u32,
+    message_id: u32,
+    /// Origin of the frame numbers, one per millisecond
+    started: Instant,
+}
+
+impl Default for UrbdrcClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UrbdrcClient {
+    pub fn new() -> Self {
+        UrbdrcClient {
+            devices: Vec::new(),
+            control_channel: None,
+            ready: false,
+            next_usb_device: BASE_USBDEVICE_NUM,
+            message_id: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Channel of the capability exchange and of the device announces
+    pub fn control_channel(&self) -> Option<u32> {
+        self.control_channel
+    }
+
+    fn next_message_id(&mut self) -> u32 {
+        self.message_id = self.message_id.wrapping_add(1);
+        self.message_id
+    }
+
+    fn add_virtual_channel(&mut self) -> UrbdrcPdu {
+        UrbdrcPdu {
+            interface_id: CLIENT_DEVICE_SINK,
+            message_id: self.next_message_id(),
+            message: UrbdrcMessage::AddVirtualChannel,
+        }
+    }
+
+    /// Redirect a device, the message is sent on the control channel
+    /// None while it is kept until the control channel is created
+    pub fn add_device(&mut self, backend: Box<dyn UsbBackend + Send>) -> Option<UrbdrcPdu> {
+        let usb_device = self.next_usb_device;
+        self.next_usb_device += 1;
+        self.devices.push(RedirectedDevice {
+            backend,
+            usb_device,
+            announced: self.ready,
+            channel_id: None,
+            request_completion: None,
+        });
+        if self.ready {
+            Some(self.add_virtual_channel())
+        } else {
+            None
+        }
+    }
+
+    /// Forget the device of a closed channel,
+    /// or all of them when the control channel is closed
+    pub fn close_channel(&mut self, channel_id: u32) {
+        if self.control_channel == Some(channel_id) {
+            self.devices.clear();
+            self.control_channel = None;
+            self.ready = false;
+        } else {
+            self.devices
+                .retain(|device| device.channel_id != Some(channel_id));
+        }
+    }
+
+    /// Description of the device given to the server
+    fn describe(device: &RedirectedDevice) -> AddDevice {
+        let info = device.backend.info();
+        let ids = format!(
+            "USB\\VID_{:04X}&PID_{:04X}",
+            info.vendor_id, info.product_id
+        );
+        let instance = info
+            .serial_number
+            .clone()
+            .filter(|serial| !serial.is_empty())
+            .unwrap_or_else(|| device.usb_device.to_string());
+        let compatibility_ids = if info.class == 0 {
+            vec![
+                "USB\\DevClass_00&SubClass_00&Prot_00".to_string(),
+                "USB\\DevClass_00&SubClass_00".to_string(),
+                "USB\\DevClass_00".to_string(),
+                "USB\\COMPOSITE".to_string(),
+            ]
+        } else {
+            let class = format!("USB\\Class_{:02X}", info.class);
+            let sub_class = format!("{}&SubClass_{:02X}", class, info.sub_class);
+            vec![
+                format!("{}&Prot_{:02X}", sub_class, info.protocol),
+                sub_class,
+                class,
+            ]
+        };
+        AddDevice {
+            usb_device: device.usb_device,
+            device_instance_id: format!("{}\\{}", ids, instance),
+            hardware_ids: vec![format!("{}&REV_{:04X}", ids, info.device_version), ids],
+            compatibility_ids,
+            container_id: format!(
+                "{{{:04X}{:04X}-0000-0000-0000-{:012X}}}",
+                info.vendor_id, info.product_id, device.usb_device
+            ),
+            usb_bus_interface_version: USB_BUS_INTERFACE_VERSION,
+            usbdi_version: USBDI_VERSION,
+            supported_usb_version: info.usb_version as u32,
+            hcd_capabilities: 0,
+            device_is_high_speed: info.high_speed,
+            no_ack_isoch_write_jitter_buffer_size_in_ms: 0,
+        }
+    }
+
+    /// Process a message of a channel
+    /// and build the PDUs expected by the server on the same channel
+    pub fn process<T>(
+        &mut self,
+        channel_id: u32,
+        buffer: &mut BytesMut,
+        callback: &mut T,
+    ) -> Result<Vec<UrbdrcPdu>>
+    where
+        T: FnMut(UrbdrcEvent),
+    {
+        let control_channel = *self.control_channel.get_or_insert(channel_id);
+        let pdu = read_urbdrc_pdu(buffer)?;
+        let mut responses = Vec::new();
+        match pdu.message {
+            UrbdrcMessage::CapabilityRequest(_) => responses.push(UrbdrcPdu {
+                interface_id: CAPABILITIES_NEGOTIATOR,
+                message_id: pdu.message_id,
+                message: UrbdrcMessage::CapabilityResponse {
+                    capability: RIM_CAPABILITY_VERSION_01,
+                    result: 0,
+                },
+            }),
+            UrbdrcMessage::ChannelCreated { .. } => {
+                responses.push(UrbdrcPdu {
+                    interface_id: SERVER_CHANNEL_NOTIFICATION,
+                    message_id: self.next_message_id(),
+                    message: UrbdrcMessage::ChannelCreated {
+                        major_version: 1,
+                        minor_version: 0,
+                        capabilities: 0,
+                    },
+                });
+                if channel_id == control_channel {
+                    self.ready = true;
+                    let mut waiting = 0;
+                    for device in self.devices.iter_mut().filter(|device| !device.announced) {
+                        device.announced = true;
+                        waiting += 1;
+                    }
+                    for _ in 0..waiting {
+                        let pdu = self.add_virtual_channel();
+                        responses.push(pdu);
+                    }
+                } else if let Some(index) = self
+                    .devices
+                    .iter()
+                    .position(|device| device.announced && device.channel_id.is_none())
+                {
+                    self.devices[index].channel_id = Some(channel_id);
+                    let message_id = self.next_message_id();
+                    responses.push(UrbdrcPdu {
+                        interface_id: CLIENT_DEVICE_SINK,
+                        message_id,
+                        message: UrbdrcMessage::AddDevice(Self::describe(&self.devices[index])),
+                    });
+                    callback(UrbdrcEvent::DeviceAdded {
+                        channel_id,
+                        usb_device: self.devices[index].usb_device,
+                    });
+                }
+            }
+            message => {
+                if let Some(index) = self
+                    .devices
+                    .iter()
+                    .position(|device| device.usb_device == pdu.interface_id)
+                {
+                    if let UrbdrcMessage::RetractDevice(reason) = message {
+                        let device = self.devices.remove(index);
+                        callback(UrbdrcEvent::DeviceRetracted {
+                            usb_device: device.usb_device,
+                            reason,
+                        });
+                    } else {
+                        let frame_number = self.frame_number();
+                        responses.extend(self.devices[index].process(
+                            pdu.interface_id,
+                            pdu.message_id,
+                            message,
+                            frame_number,
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(responses)
+    }
+
+    /// Current USB frame number
+    fn frame_number(&self) -> u32 {
+        self.started.elapsed().as_millis() as u32
+    }
+}
+
+impl RedirectedDevice {
+    /// Answer a message of the server on the interface of the device
+    fn process(
+        &mut self,
+        interface_id: u32,
+        message_id: u32,
+        message: UrbdrcMessage,
+        frame_number: u32,
+    ) -> Option<UrbdrcPdu> {
+        let message = match message {
+            UrbdrcMessage::RegisterRequestCallback(request_completion) => {
+                self.request_completion = request_completion;
+                return None;
+            }
+            UrbdrcMessage::QueryDeviceText { .. } => {
+                return Some(UrbdrcPdu {
+                    interface_id,
+                    message_id,
+                    message: UrbdrcMessage::QueryDeviceTextResponse {
+                        description: self.backend.info().description,
+                        hresult: 0,
+                    },
+                })
+            }
+            UrbdrcMessage::IoControl {
+                io_control_code,
+                output_buffer_size,
+                request_id,
+                ..
+            } => {
+                let (hresult, output) = match io_control_code {
+                    IOCTL_INTERNAL_USB_GET_PORT_STATUS => {
+                        (0, USBD_PORT_ENABLED_AND_CONNECTED.to_le_bytes().to_vec())
+                    }
+                    IOCTL_TSUSBGD_IOCTL_USBDI_QUERY_BUS_TIME => {
+                        (0, frame_number.to_le_bytes().to_vec())
+                    }
+                    IOCTL_INTERNAL_USB_RESET_PORT | IOCTL_INTERNAL_USB_CYCLE_PORT => {
+                        (0, Vec::new())
+                    }
+                    _ => (E_NOTIMPL, Vec::new()),
+                };
+                let output = output
+                    .into_iter()
+                    .take(output_buffer_size as usize)
+                    .collect::<Vec<_>>();
+                UrbdrcMessage::IoControlCompletion {
+                    request_id,
+                    hresult,
+                    information: output.len() as u32,
+                    output,
+                }
+            }
+            UrbdrcMessage::TransferIn {
+                ts_urb,
+                output_buffer_size,
+            } => self.transfer(&ts_urb, &[], output_buffer_size, frame_number)?,
+            UrbdrcMessage::TransferOut { ts_urb, output } => {
+                self.transfer(&ts_urb, &output, output.len() as u32, frame_number)?
+            }
+            // Transfers are done before the next message
+            _ => return None,
+        };
+        Some(UrbdrcPdu {
+            interface_id: self.request_completion?,
+            message_id,
+            message,
+        })
+    }
+
+    /// Run a TS_URB on the backend and build its completion
+    /// None for the transfers out without acknowledgment
+    fn transfer(
+        &mut self,
+        ts_urb: &[u8],
+        data: &[u8],
+        length: u32,
+        frame_number: u32,
+    ) -> Option<UrbdrcMessage> {
+        let ts_urb = match read_ts_urb(&mut BytesMut::from(ts_urb), length) {
+            Ok(ts_urb) => ts_urb,
+            Err(_) => return None,
+        };
+        let (result, output) = match ts_urb.urb {
+            Urb::SelectConfiguration {
+                configuration_value,
+                interfaces,
+            } => match self.backend.select_configuration(configuration_value) {
+                Ok(selected) => {
+                    let interfaces: Vec<_> = selected
+                        .into_iter()
+                        .map(|interface| {
+                            let selection = interfaces
+                                .iter()
+                                .find(|selection| selection.number == interface.number)
+                                .cloned()
+                                .unwrap_or_default();
+                            (interface, selection)
+                        })
+                        .collect();
+                    let handle = configuration_value.unwrap_or(0) as u32;
+                    (select_configuration_result(handle, &interfaces), Vec::new())
+                }
+                Err(_) => (ts_urb_result(USBD_STATUS_STALL_PID), Vec::new()),
+            },
+            Urb::SelectInterface { interface, .. } => match self
+                .backend
+                .select_interface(interface.number, interface.alternate_setting)
+            {
+                Ok(selected) => (select_interface_result(&selected, &interface), Vec::new()),
+                Err(_) => (ts_urb_result(USBD_STATUS_STALL_PID), Vec::new()),
+            },
+            Urb::PipeRequest { endpoint, .. } => match self.backend.reset_endpoint(endpoint) {
+                Ok(()) => (ts_urb_result(USBD_STATUS_SUCCESS), Vec::new()),
+                Err(_) => (ts_urb_result(USBD_STATUS_STALL_PID), Vec::new()),
+            },
+            Urb::GetCurrentFrameNumber => (frame_number_result(frame_number), Vec::new()),
+            Urb::Control(setup) => match self.backend.control_transfer(setup, data) {
+                Ok(output) => (ts_urb_result(USBD_STATUS_SUCCESS), output),
+                Err(_) => (ts_urb_result(USBD_STATUS_STALL_PID), Vec::new()),
+            },
+            Urb::BulkOrInterrupt { endpoint, length } => {
+                match self.backend.transfer(endpoint, data, length as usize) {
+                    Ok(output) => (ts_urb_result(USBD_STATUS_SUCCESS), output),
+                    Err(_) => (ts_urb_result(USBD_STATUS_STALL_PID), Vec::new()),
+                }
+            }
+            Urb::Unsupported => (ts_urb_result(USBD_STATUS_NOT_SUPPORTED), Vec::new()),
+        };
+
+        if !data.is_empty() || output.is_empty() {
+            if ts_urb.no_ack {
+                return None;
+            }
+            Some(UrbdrcMessage::UrbCompletionNoData {
+                request_id: ts_urb.request_id,
+                ts_urb_result: result,
+                hresult: 0,
+                output_buffer_size: data.len() as u32,
+            })
+        } else {
+            Some(UrbdrcMessage::UrbCompletion {
+                request_id: ts_urb.request_id,
+                ts_urb_result: result,
+                hresult: 0,
+                output,
+            })
+        }
+    }
+}
+
+/// Device of the client opened with rusb, on libusb
+#[cfg(feature = "rusb")]
+pub struct RusbBackend {
+    handle: rusb::DeviceHandle<rusb::GlobalContext>,
+    info: UsbDeviceInfo,
+    /// Interrupt endpoints of the selected configuration,
+    /// the other ones are bulk endpoints
+    interrupt_endpoints: Vec<u8>,
+    /// Interfaces claimed for the selected configuration
+    claimed: Vec<u8>,
+}
+
+#[cfg(feature = "rusb")]
+mod rusb_backend {
+    use super::{RusbBackend, UsbBackend, UsbDeviceInfo};
+    use crate::core::urbdrc::urb::{SetupPacket, UsbEndpoint, UsbInterface};
+    use std::io::{Error, ErrorKind, Result};
+    use std::time::Duration;
+
+    /// Time limit of the control and bulk transfers
+    const TRANSFER_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// An interrupt transfer without data fails quickly,
+    /// the server polls it again
+    const INTERRUPT_TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn error(e: rusb::Error) -> Error {
+        let kind = match e {
+            rusb::Error::Timeout => ErrorKind::TimedOut,
+            rusb::Error::NoDevice | rusb::Error::NotFound => ErrorKind::NotFound,
+            rusb::Error::Access => ErrorKind::PermissionDenied,
+            _ => ErrorKind::Other,
+        };
+        Error::new(kind, format!("URBDRC: {}", e))
+    }
+
+    /// Binary coded decimal of a version
+    fn bcd(version: rusb::Version) -> u16 {
+        (version.major() as u16) << 8 | (version.minor() as u16) << 4 | version.sub_minor() as u16
+    }
+
+    fn interface(descriptor: &rusb::InterfaceDescriptor) -> UsbInterface {
+        UsbInterface {
+            number: descriptor.interface_number(),
+            alternate_setting: descriptor.setting_number(),
+            class: descriptor.class_code(),
+            sub_class: descriptor.sub_class_code(),
+            protocol: descriptor.protocol_code(),
+            endpoints: descriptor
+                .endpoint_descriptors()
+                .map(|endpoint| UsbEndpoint {
+                    address: endpoint.address(),
+                    attributes: match endpoint.transfer_type() {
+                        rusb::TransferType::Control => 0,
+                        rusb::TransferType::Isochronous => 1,
+                        rusb::TransferType::Bulk => 2,
+                        rusb::TransferType::Interrupt => 3,
+                    },
+                    max_packet_size: endpoint.max_packet_size(),
+                    interval: endpoint.interval(),
+                })
+                .collect(),
+        }
+    }
+
+    impl RusbBackend {
+        /// Open the first device with a vendor and product id
+        pub fn open(vendor_id: u16, product_id: u16) -> Result<Self> {
+            let mut handle = rusb::open_device_with_vid_pid(vendor_id, product_id)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, "URBDRC: no device with this id"))?;
+            let device = handle.device();
+            let descriptor = device.device_descriptor().map_err(error)?;
+            // Not supported on all platforms
+            let _ = handle.set_auto_detach_kernel_driver(true);
+            let info = UsbDeviceInfo {
+                vendor_id,
+                product_id,
+                device_version: bcd(descriptor.device_version()),
+                usb_version: bcd(descriptor.usb_version()),
+                class: descriptor.class_code(),
+                sub_class: descriptor.sub_class_code(),
+                protocol: descriptor.protocol_code(),
+                serial_number: handle.read_serial_number_string_ascii(&descriptor).ok(),
+                description: handle
+                    .read_product_string_ascii(&descriptor)
+                    .unwrap_or_else(|_| format!("USB device {:04x}:{:04x}", vendor_id, product_id)),
+                high_speed: matches!(
+                    device.speed(),
+                    rusb::Speed::High | rusb::Speed::Super | rusb::Speed::SuperPlus
+                ),
+            };
+            Ok(RusbBackend {
+                handle,
+                info,
+                interrupt_endpoints: Vec::new(),
+                claimed: Vec::new(),
+            })
+        }
+
+        fn release(&mut self) {
+            for number in self.claimed.drain(..) {
+                let _ = self.handle.release_interface(number);
+            }
+            self.interrupt_endpoints.clear();
+        }
+    }
+
+    impl Drop for RusbBackend {
+        fn drop(&mut self) {
+            self.release();
+        }
+    }
+
+    impl UsbBackend for RusbBackend {
+        fn info(&self) -> UsbDeviceInfo {
+            self.info.clone()
+        }
+
+        fn select_configuration(
+            &mut self,
+            configuration_value: Option<u8>,
+        ) -> Result<Vec<UsbInterface>> {
+            self.release();
+            let value = match configuration_value {
+                Some(value) => value,
+                None => return Ok(Vec::new()),
+            };
+            self.handle.set_active_configuration(value).map_err(error)?;
+            let config = self
+                .handle
+                .device()
+                .active_config_descriptor()
+                .map_err(error)?;
+            let mut interfaces = Vec::new();
+            for descriptor in config
+                .interfaces()
+                .filter_map(|interface| interface.descriptors().next())
+            {
+                self.handle
+                    .claim_interface(descriptor.interface_number())
+                    .map_err(error)?;
+                self.claimed.push(descriptor.interface_number());
+                interfaces.push(interface(&descriptor));
+            }
+            self.interrupt_endpoints = interfaces
+                .iter()
+                .flat_map(|interface| interface.endpoints.iter())
+                .filter(|endpoint| endpoint.attributes == 3)
+                .map(|endpoint| endpoint.address)
+                .collect();
+            Ok(interfaces)
+        }
+
+        fn select_interface(&mut self, number: u8, alternate_setting: u8) -> Result<UsbInterface> {
+            self.handle
+                .set_alternate_setting(number, alternate_setting)
+                .map_err(error)?;
+            let config = self
+                .handle
+                .device()
+                .active_config_descriptor()
+                .map_err(error)?;
+            let selected = config
+                .interfaces()
+                .flat_map(|interface| interface.descriptors())
+                .find(|descriptor| {
+                    descriptor.interface_number() == number
+                        && descriptor.setting_number() == alternate_setting
+                })
+                .map(|descriptor| interface(&descriptor))
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::NotFound, "URBDRC: unknown alternate setting")
+                })?;
+            for endpoint in &selected.endpoints {
+                if endpoint.attributes == 3 && !self.interrupt_endpoints.contains(&endpoint.address)
+                {
+                    self.interrupt_endpoints.push(endpoint.address);
+                }
+            }
+            Ok(selected)
+        }
+
+        fn control_transfer(&mut self, setup: SetupPacket, data: &[u8]) -> Result<Vec<u8>> {
+            if setup.is_in() {
+                let mut buffer = vec![0; setup.length as usize];
+                let size = self
+                    .handle
+                    .read_control(
+                        setup.request_type,
+                        setup.request,
+                        setup.value,
+                        setup.index,
+                        &mut buffer,
+                        TRANSFER_TIMEOUT,
+                    )
+                    .map_err(error)?;
+                buffer.truncate(size);
+                Ok(buffer)
+            } else {
+                self.handle
+                    .write_control(
+                        setup.request_type,
+                        setup.request,
+                        setup.value,
+                        setup.index,
+                        data,
+                        TRANSFER_TIMEOUT,
+                    )
+                    .map_err(error)?;
+                Ok(Vec::new())
+            }
+        }
+
+        fn transfer(&mut self, endpoint: u8, data: &[u8], length: usize) -> Result<Vec<u8>> {
+            let interrupt = self.interrupt_endpoints.contains(&endpoint);
+            if endpoint & 0x80 != 0 {
+                let mut buffer = vec![0; length];
+                let size = if interrupt {
+                    self.handle
+                        .read_interrupt(endpoint, &mut buffer, INTERRUPT_TIMEOUT)
+                } else {
+                    self.handle
+                        .read_bulk(endpoint, &mut buffer, TRANSFER_TIMEOUT)
+                }
+                .map_err(error)?;
+                buffer.truncate(size);
+                Ok(buffer)
+            } else {
+                let written = if interrupt {
+                    self.handle
+                        .write_interrupt(endpoint, data, TRANSFER_TIMEOUT)
+                } else {
+                    self.handle.write_bulk(endpoint, data, TRANSFER_TIMEOUT)
+                };
+                written.map_err(error)?;
+                Ok(Vec::new())
+            }
+        }
+
+        fn reset_endpoint(&mut self, endpoint: u8) -> Result<()> {
+            self.handle.clear_halt(endpoint).map_err(error)
+        }
+    }
+}
+
+/// The control channel and the channel of each device share the same name
+#[async_trait]
+impl DynamicChannelHandler for UrbdrcClient {
+    fn channel_names(&self) -> Vec<&'static str> {
+        vec![URBDRC_CHANNEL_NAME]
+    }
+
+    async fn process(
+        &mut self,
+        _channel_name: &str,
+        channel_id: u32,
+        buffer: &mut BytesMut,
+        callback: &mut (dyn FnMut(RdpEvent) + Send),
+    ) -> Result<Vec<Vec<u8>>> {
+        let responses = UrbdrcClient::process(self, channel_id, buffer, &mut |event| {
+            callback(RdpEvent::Dynamic(DynamicChannelEvent::Usb(event)))
+        })?;
+        to_messages(responses).await
+    }
+
+    fn close(&mut self, channel_id: u32) {
+        self.close_channel(channel_id)
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::urbdrc::base::CLIENT_CHANNEL_NOTIFICATION;
+    use crate::model::data::to_vec;
+
+    /// Device answering its descriptor
+    struct Loopback;
+
+    impl UsbBackend for Loopback {
+        fn info(&self) -> UsbDeviceInfo {
+            UsbDeviceInfo {
+                vendor_id: 0x1234,
+                product_id: 0x5678,
+                class: 0xFF,
+                description: "Loopback".to_string(),
+                ..Default::default()
+            }
+        }
+
+        fn select_configuration(&mut self, _: Option<u8>) -> Result<Vec<UsbInterface>> {
+            Ok(Vec::new())
+        }
+
+        fn select_interface(&mut self, number: u8, _: u8) -> Result<UsbInterface> {
+            Ok(UsbInterface {
+                number,
+                ..Default::default()
+            })
+        }
+
+        fn control_transfer(&mut self, setup: SetupPacket, _: &[u8]) -> Result<Vec<u8>> {
+            Ok(vec![0x12; setup.length as usize])
+        }
+
+        fn transfer(&mut self, _: u8, data: &[u8], _: usize) -> Result<Vec<u8>> {
+            Ok(data.to_vec())
+        }
+    }
+
+    /// Data of a message of the server
+    async fn server_message(interface_id: u32, message: UrbdrcMessage) -> BytesMut {
+        let pdu = UrbdrcPdu {
+            interface_id,
+            message_id: 9,
+            message,
+        };
+        BytesMut::from(&to_vec(&pdu).await.unwrap()[..])
+    }
+
+    /// A device is announced on the control channel, then added on its own channel
+    /// where its transfers are completed
+    #[tokio::test]
+    async fn test_device_redirection() {
+        let mut client = UrbdrcClient::new();
+        assert!(client.add_device(Box::new(Loopback)).is_none());
+
+        let channel_created = UrbdrcMessage::ChannelCreated {
+            major_version: 1,
+            minor_version: 0,
+            capabilities: 0,
+        };
+        let mut buffer = server_message(CLIENT_CHANNEL_NOTIFICATION, channel_created.clone()).await;
+        let responses = client.process(1, &mut buffer, &mut |_| {}).unwrap();
+        assert_eq!(client.control_channel(), Some(1));
+        assert_eq!(responses[1].message, UrbdrcMessage::AddVirtualChannel);
+
+        let mut events = Vec::new();
+        let mut buffer = server_message(CLIENT_CHANNEL_NOTIFICATION, channel_created).await;
+        let responses = client
+            .process(2, &mut buffer, &mut |event| events.push(event))
+            .unwrap();
+        assert!(matches!(
+            &responses[1].message,
+            UrbdrcMessage::AddDevice(device) if device.hardware_ids[1] == "USB\\VID_1234&PID_5678"
+        ));
+        assert_eq!(
+            events,
+            [UrbdrcEvent::DeviceAdded {
+                channel_id: 2,
+                usb_device: BASE_USBDEVICE_NUM
+            }]
+        );
+
+        let mut buffer = server_message(
+            BASE_USBDEVICE_NUM,
+            UrbdrcMessage::RegisterRequestCallback(Some(0x10)),
+        )
+        .await;
+        assert!(client
+            .process(2, &mut buffer, &mut |_| {})
+            .unwrap()
+            .is_empty());
+
+        // Device descriptor
+        let mut buffer = server_message(
+            BASE_USBDEVICE_NUM,
+            UrbdrcMessage::TransferIn {
+                ts_urb: vec![12, 0, 0x0B, 0, 3, 0, 0, 0, 0, 1, 0, 0],
+                output_buffer_size: 18,
+            },
+        )
+        .await;
+        let responses = client.process(2, &mut buffer, &mut |_| {}).unwrap();
+        assert_eq!(
+            responses,
+            [UrbdrcPdu {
+                interface_id: 0x10,
+                message_id: 9,
+                message: UrbdrcMessage::UrbCompletion {
+                    request_id: 3,
+                    ts_urb_result: ts_urb_result(USBD_STATUS_SUCCESS),
+                    hresult: 0,
+                    output: vec![0x12; 18],
+                },
+            }]
+        );
+
+        let mut buffer = server_message(BASE_USBDEVICE_NUM, UrbdrcMessage::RetractDevice(1)).await;
+        client
+            .process(2, &mut buffer, &mut |event| events.push(event))
+            .unwrap();
+        assert_eq!(
+            events[1],
+            UrbdrcEvent::DeviceRetracted {
+                usb_device: BASE_USBDEVICE_NUM,
+                reason: 1
+            }
+        );
+    }
+}
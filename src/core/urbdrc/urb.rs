@@ -0,0 +1,592 @@
+use crate::model::data::check_remaining;
+
+use bytes::{Buf, BufMut, BytesMut};
+use std::io::{Error, ErrorKind, Result};
+
+/// URB functions of the TS_URB header
+/// Descriptor, feature, status, vendor and class requests
+/// are all converted into control transfers
+/// MS-RDPEUSB 2.2.9.1.1 TS_URB_HEADER
+pub const URB_FUNCTION_SELECT_CONFIGURATION: u16 = 0x0000;
+pub const URB_FUNCTION_SELECT_INTERFACE: u16 = 0x0001;
+pub const URB_FUNCTION_ABORT_PIPE: u16 = 0x0002;
+pub const URB_FUNCTION_GET_CURRENT_FRAME_NUMBER: u16 = 0x0007;
+pub const URB_FUNCTION_CONTROL_TRANSFER: u16 = 0x0008;
+pub const URB_FUNCTION_BULK_OR_INTERRUPT_TRANSFER: u16 = 0x0009;
+pub const URB_FUNCTION_ISOCH_TRANSFER: u16 = 0x000A;
+pub const URB_FUNCTION_GET_DESCRIPTOR_FROM_DEVICE: u16 = 0x000B;
+pub const URB_FUNCTION_SET_DESCRIPTOR_TO_DEVICE: u16 = 0x000C;
+pub const URB_FUNCTION_SET_FEATURE_TO_DEVICE: u16 = 0x000D;
+pub const URB_FUNCTION_SET_FEATURE_TO_INTERFACE: u16 = 0x000E;
+pub const URB_FUNCTION_SET_FEATURE_TO_ENDPOINT: u16 = 0x000F;
+pub const URB_FUNCTION_CLEAR_FEATURE_TO_DEVICE: u16 = 0x0010;
+pub const URB_FUNCTION_CLEAR_FEATURE_TO_INTERFACE: u16 = 0x0011;
+pub const URB_FUNCTION_CLEAR_FEATURE_TO_ENDPOINT: u16 = 0x0012;
+pub const URB_FUNCTION_GET_STATUS_FROM_DEVICE: u16 = 0x0013;
+pub const URB_FUNCTION_GET_STATUS_FROM_INTERFACE: u16 = 0x0014;
+pub const URB_FUNCTION_GET_STATUS_FROM_ENDPOINT: u16 = 0x0015;
+pub const URB_FUNCTION_VENDOR_DEVICE: u16 = 0x0017;
+pub const URB_FUNCTION_VENDOR_INTERFACE: u16 = 0x0018;
+pub const URB_FUNCTION_VENDOR_ENDPOINT: u16 = 0x0019;
+pub const URB_FUNCTION_CLASS_DEVICE: u16 = 0x001A;
+pub const URB_FUNCTION_CLASS_INTERFACE: u16 = 0x001B;
+pub const URB_FUNCTION_CLASS_ENDPOINT: u16 = 0x001C;
+pub const URB_FUNCTION_SYNC_RESET_PIPE_AND_CLEAR_STALL: u16 = 0x001E;
+pub const URB_FUNCTION_CLASS_OTHER: u16 = 0x001F;
+pub const URB_FUNCTION_VENDOR_OTHER: u16 = 0x0020;
+pub const URB_FUNCTION_GET_STATUS_FROM_OTHER: u16 = 0x0021;
+pub const URB_FUNCTION_CLEAR_FEATURE_TO_OTHER: u16 = 0x0022;
+pub const URB_FUNCTION_SET_FEATURE_TO_OTHER: u16 = 0x0023;
+pub const URB_FUNCTION_GET_DESCRIPTOR_FROM_ENDPOINT: u16 = 0x0024;
+pub const URB_FUNCTION_SET_DESCRIPTOR_TO_ENDPOINT: u16 = 0x0025;
+pub const URB_FUNCTION_GET_CONFIGURATION: u16 = 0x0026;
+pub const URB_FUNCTION_GET_INTERFACE: u16 = 0x0027;
+pub const URB_FUNCTION_GET_DESCRIPTOR_FROM_INTERFACE: u16 = 0x0028;
+pub const URB_FUNCTION_SET_DESCRIPTOR_TO_INTERFACE: u16 = 0x0029;
+pub const URB_FUNCTION_SYNC_RESET_PIPE: u16 = 0x0030;
+pub const URB_FUNCTION_SYNC_CLEAR_STALL: u16 = 0x0031;
+pub const URB_FUNCTION_CONTROL_TRANSFER_EX: u16 = 0x0032;
+
+/// Status of a transfer in the TS_URB result
+/// MS-RDPEUSB 2.2.10.1.1 TS_URB_RESULT_HEADER
+pub const USBD_STATUS_SUCCESS: u32 = 0x00000000;
+pub const USBD_STATUS_STALL_PID: u32 = 0xC0000004;
+pub const USBD_STATUS_INVALID_PARAMETER: u32 = 0x80000300;
+pub const USBD_STATUS_NOT_SUPPORTED: u32 = 0xC0000E00;
+
+/// The transfer reads from the device
+pub const USBD_TRANSFER_DIRECTION_IN: u32 = 0x00000001;
+
+/// Size of the TS_URB header and of the result header
+const HEADER_SIZE: usize = 8;
+
+/// Size of an interface and of a pipe of the selection results
+/// MS-RDPEUSB 2.2.10.1.2 TS_USBD_INTERFACE_INFORMATION_RESULT
+const INTERFACE_RESULT_SIZE: usize = 16;
+const PIPE_RESULT_SIZE: usize = 20;
+
+/// Pipe handles carry the endpoint address in their low byte
+const PIPE_HANDLE_BASE: u32 = 0x00010000;
+
+/// Standard requests of the setup packets
+/// USB 2.0 9.4 Standard Device Requests
+const GET_STATUS: u8 = 0x00;
+const CLEAR_FEATURE: u8 = 0x01;
+const SET_FEATURE: u8 = 0x03;
+const GET_DESCRIPTOR: u8 = 0x06;
+const SET_DESCRIPTOR: u8 = 0x07;
+const GET_CONFIGURATION: u8 = 0x08;
+const GET_INTERFACE: u8 = 0x0A;
+
+/// Setup packet of a control transfer
+/// USB 2.0 9.3 USB Device Requests
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl SetupPacket {
+    /// Data goes from the device to the host
+    pub fn is_in(&self) -> bool {
+        self.request_type & 0x80 != 0
+    }
+
+    fn read(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 8, "URBDRC: setup packet")?;
+        Ok(SetupPacket {
+            request_type: buffer.get_u8(),
+            request: buffer.get_u8(),
+            value: buffer.get_u16_le(),
+            index: buffer.get_u16_le(),
+            length: buffer.get_u16_le(),
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; 8] {
+        let mut data = [0; 8];
+        data[0] = self.request_type;
+        data[1] = self.request;
+        data[2..4].copy_from_slice(&self.value.to_le_bytes());
+        data[4..6].copy_from_slice(&self.index.to_le_bytes());
+        data[6..8].copy_from_slice(&self.length.to_le_bytes());
+        data
+    }
+}
+
+/// Endpoint of an interface, as in its descriptor
+/// USB 2.0 9.6.6 Endpoint
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct UsbEndpoint {
+    /// Direction in the high bit
+    pub address: u8,
+    /// Transfer type in the two low bits
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+/// Alternate setting of an interface with its endpoints
+/// USB 2.0 9.6.5 Interface
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbInterface {
+    pub number: u8,
+    pub alternate_setting: u8,
+    pub class: u8,
+    pub sub_class: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<UsbEndpoint>,
+}
+
+/// Interface asked by the server, with the settings of its pipes
+/// MS-RDPEUSB 2.2.9.2.1 TS_USBD_INTERFACE_INFORMATION
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceSelection {
+    pub number: u8,
+    pub alternate_setting: u8,
+    /// Maximum transfer size and flags of each pipe
+    pub pipes: Vec<(u32, u32)>,
+}
+
+impl InterfaceSelection {
+    fn read(buffer: &mut BytesMut) -> Result<Self> {
+        check_remaining(buffer, 12, "URBDRC: interface information")?;
+        let length = buffer.get_u16_le() as usize;
+        let _number_of_pipes_expected = buffer.get_u16_le();
+        let number = buffer.get_u8();
+        let alternate_setting = buffer.get_u8();
+        buffer.advance(2);
+        let number_of_pipes = buffer.get_u32_le() as usize;
+        check_remaining(buffer, number_of_pipes * 12, "URBDRC: pipe information")?;
+        let mut pipes = Vec::new();
+        for _ in 0..number_of_pipes {
+            let _maximum_packet_size = buffer.get_u16_le();
+            buffer.advance(2);
+            pipes.push((buffer.get_u32_le(), buffer.get_u32_le()));
+        }
+        // Trailing data announced by the length
+        let read = 12 + number_of_pipes * 12;
+        if length > read {
+            check_remaining(buffer, length - read, "URBDRC: interface information")?;
+            buffer.advance(length - read);
+        }
+        Ok(InterfaceSelection {
+            number,
+            alternate_setting,
+            pipes,
+        })
+    }
+}
+
+/// Request of a TS_URB, in the form the backend runs it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Urb {
+    /// None unconfigures the device
+    /// MS-RDPEUSB 2.2.9.2 TS_URB_SELECT_CONFIGURATION
+    SelectConfiguration {
+        configuration_value: Option<u8>,
+        interfaces: Vec<InterfaceSelection>,
+    },
+    /// MS-RDPEUSB 2.2.9.3 TS_URB_SELECT_INTERFACE
+    SelectInterface {
+        configuration_handle: u32,
+        interface: InterfaceSelection,
+    },
+    /// Abort or reset of a pipe
+    /// MS-RDPEUSB 2.2.9.4 TS_URB_PIPE_REQUEST
+    PipeRequest { function: u16, endpoint: u8 },
+    /// MS-RDPEUSB 2.2.9.5 TS_URB_GET_CURRENT_FRAME_NUMBER
+    GetCurrentFrameNumber,
+    /// Transfer on the default control pipe
+    /// MS-RDPEUSB 2.2.9.6 TS_URB_CONTROL_TRANSFER
+    Control(SetupPacket),
+    /// MS-RDPEUSB 2.2.9.7 TS_URB_BULK_OR_INTERRUPT_TRANSFER
+    BulkOrInterrupt { endpoint: u8, length: u32 },
+    /// Isochronous transfers and OS descriptors are not handled
+    Unsupported,
+}
+
+/// TS_URB of a transfer request
+/// MS-RDPEUSB 2.2.9.1.1 TS_URB_HEADER
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TsUrb {
+    pub function: u16,
+    pub request_id: u32,
+    /// No completion is expected for a transfer out
+    pub no_ack: bool,
+    pub urb: Urb,
+}
+
+/// Pipe handle of an endpoint given in the selection results
+pub fn pipe_handle(endpoint: u8) -> u32 {
+    PIPE_HANDLE_BASE | endpoint as u32
+}
+
+/// Request type of a standard request from the recipient of the URB function
+/// 0 is the device, 1 the interface, 2 the endpoint and 3 other
+fn standard(
+    direction_in: bool,
+    recipient: u16,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u32,
+) -> Urb {
+    Urb::Control(SetupPacket {
+        request_type: (if direction_in { 0x80 } else { 0 }) | recipient as u8,
+        request,
+        value,
+        index,
+        length: length.min(u16::MAX as u32) as u16,
+    })
+}
+
+/// Read a TS_URB of a transfer request
+/// The length is the size of the output buffer for a transfer in,
+/// or the size of the data for a transfer out
+pub fn read_ts_urb(buffer: &mut BytesMut, length: u32) -> Result<TsUrb> {
+    check_remaining(buffer, HEADER_SIZE, "URBDRC: urb header")?;
+    let _size = buffer.get_u16_le();
+    let function = buffer.get_u16_le();
+    let value = buffer.get_u32_le();
+
+    let urb = match function {
+        URB_FUNCTION_SELECT_CONFIGURATION => {
+            check_remaining(buffer, 4, "URBDRC: select configuration")?;
+            let num_interfaces = buffer.get_u8();
+            let descriptor_is_valid = buffer.get_u8() != 0;
+            buffer.advance(2);
+            let mut interfaces = Vec::new();
+            for _ in 0..num_interfaces {
+                interfaces.push(InterfaceSelection::read(buffer)?);
+            }
+            let configuration_value = if descriptor_is_valid {
+                check_remaining(buffer, 9, "URBDRC: configuration descriptor")?;
+                Some(buffer[5])
+            } else {
+                None
+            };
+            Urb::SelectConfiguration {
+                configuration_value,
+                interfaces,
+            }
+        }
+        URB_FUNCTION_SELECT_INTERFACE => {
+            check_remaining(buffer, 4, "URBDRC: select interface")?;
+            Urb::SelectInterface {
+                configuration_handle: buffer.get_u32_le(),
+                interface: InterfaceSelection::read(buffer)?,
+            }
+        }
+        URB_FUNCTION_ABORT_PIPE
+        | URB_FUNCTION_SYNC_RESET_PIPE_AND_CLEAR_STALL
+        | URB_FUNCTION_SYNC_RESET_PIPE
+        | URB_FUNCTION_SYNC_CLEAR_STALL => {
+            check_remaining(buffer, 4, "URBDRC: pipe request")?;
+            Urb::PipeRequest {
+                function,
+                endpoint: buffer.get_u32_le() as u8,
+            }
+        }
+        URB_FUNCTION_GET_CURRENT_FRAME_NUMBER => Urb::GetCurrentFrameNumber,
+        URB_FUNCTION_CONTROL_TRANSFER | URB_FUNCTION_CONTROL_TRANSFER_EX => {
+            let size = if function == URB_FUNCTION_CONTROL_TRANSFER {
+                8
+            } else {
+                12
+            };
+            check_remaining(buffer, size, "URBDRC: control transfer")?;
+            buffer.advance(size);
+            Urb::Control(SetupPacket::read(buffer)?)
+        }
+        URB_FUNCTION_BULK_OR_INTERRUPT_TRANSFER => {
+            check_remaining(buffer, 8, "URBDRC: bulk or interrupt transfer")?;
+            Urb::BulkOrInterrupt {
+                endpoint: buffer.get_u32_le() as u8,
+                length,
+            }
+        }
+        URB_FUNCTION_GET_DESCRIPTOR_FROM_DEVICE
+        | URB_FUNCTION_GET_DESCRIPTOR_FROM_INTERFACE
+        | URB_FUNCTION_GET_DESCRIPTOR_FROM_ENDPOINT
+        | URB_FUNCTION_SET_DESCRIPTOR_TO_DEVICE
+        | URB_FUNCTION_SET_DESCRIPTOR_TO_INTERFACE
+        | URB_FUNCTION_SET_DESCRIPTOR_TO_ENDPOINT => {
+            check_remaining(buffer, 4, "URBDRC: descriptor request")?;
+            let index = buffer.get_u8();
+            let descriptor_type = buffer.get_u8();
+            let language_id = buffer.get_u16_le();
+            let (direction_in, recipient) = match function {
+                URB_FUNCTION_GET_DESCRIPTOR_FROM_DEVICE => (true, 0),
+                URB_FUNCTION_GET_DESCRIPTOR_FROM_INTERFACE => (true, 1),
+                URB_FUNCTION_GET_DESCRIPTOR_FROM_ENDPOINT => (true, 2),
+                URB_FUNCTION_SET_DESCRIPTOR_TO_DEVICE => (false, 0),
+                URB_FUNCTION_SET_DESCRIPTOR_TO_INTERFACE => (false, 1),
+                _ => (false, 2),
+            };
+            standard(
+                direction_in,
+                recipient,
+                if direction_in {
+                    GET_DESCRIPTOR
+                } else {
+                    SET_DESCRIPTOR
+                },
+                (descriptor_type as u16) << 8 | index as u16,
+                language_id,
+                length,
+            )
+        }
+        URB_FUNCTION_SET_FEATURE_TO_DEVICE..=URB_FUNCTION_CLEAR_FEATURE_TO_ENDPOINT
+        | URB_FUNCTION_CLEAR_FEATURE_TO_OTHER
+        | URB_FUNCTION_SET_FEATURE_TO_OTHER => {
+            check_remaining(buffer, 4, "URBDRC: feature request")?;
+            let feature_selector = buffer.get_u16_le();
+            let index = buffer.get_u16_le();
+            let (request, recipient) = match function {
+                URB_FUNCTION_CLEAR_FEATURE_TO_OTHER => (CLEAR_FEATURE, 3),
+                URB_FUNCTION_SET_FEATURE_TO_OTHER => (SET_FEATURE, 3),
+                URB_FUNCTION_SET_FEATURE_TO_DEVICE..=URB_FUNCTION_SET_FEATURE_TO_ENDPOINT => {
+                    (SET_FEATURE, function - URB_FUNCTION_SET_FEATURE_TO_DEVICE)
+                }
+                _ => (
+                    CLEAR_FEATURE,
+                    function - URB_FUNCTION_CLEAR_FEATURE_TO_DEVICE,
+                ),
+            };
+            standard(false, recipient, request, feature_selector, index, 0)
+        }
+        URB_FUNCTION_GET_STATUS_FROM_DEVICE..=URB_FUNCTION_GET_STATUS_FROM_ENDPOINT
+        | URB_FUNCTION_GET_STATUS_FROM_OTHER => {
+            check_remaining(buffer, 4, "URBDRC: get status")?;
+            let index = buffer.get_u16_le();
+            let recipient = match function {
+                URB_FUNCTION_GET_STATUS_FROM_OTHER => 3,
+                _ => function - URB_FUNCTION_GET_STATUS_FROM_DEVICE,
+            };
+            standard(true, recipient, GET_STATUS, 0, index, 2)
+        }
+        URB_FUNCTION_VENDOR_DEVICE..=URB_FUNCTION_CLASS_ENDPOINT
+        | URB_FUNCTION_CLASS_OTHER
+        | URB_FUNCTION_VENDOR_OTHER => {
+            check_remaining(buffer, 12, "URBDRC: vendor or class request")?;
+            let transfer_flags = buffer.get_u32_le();
+            let reserved_bits = buffer.get_u8();
+            let request = buffer.get_u8();
+            let value = buffer.get_u16_le();
+            let index = buffer.get_u16_le();
+            let (kind, recipient) = match function {
+                URB_FUNCTION_CLASS_OTHER => (0x20, 3),
+                URB_FUNCTION_VENDOR_OTHER => (0x40, 3),
+                URB_FUNCTION_VENDOR_DEVICE..=URB_FUNCTION_VENDOR_ENDPOINT => {
+                    (0x40, function - URB_FUNCTION_VENDOR_DEVICE)
+                }
+                _ => (0x20, function - URB_FUNCTION_CLASS_DEVICE),
+            };
+            let direction = if transfer_flags & USBD_TRANSFER_DIRECTION_IN != 0 {
+                0x80
+            } else {
+                0
+            };
+            Urb::Control(SetupPacket {
+                request_type: direction | kind | reserved_bits | recipient as u8,
+                request,
+                value,
+                index,
+                length: length.min(u16::MAX as u32) as u16,
+            })
+        }
+        URB_FUNCTION_GET_CONFIGURATION => standard(true, 0, GET_CONFIGURATION, 0, 0, 1),
+        URB_FUNCTION_GET_INTERFACE => {
+            check_remaining(buffer, 4, "URBDRC: get interface")?;
+            let interface = buffer.get_u16_le();
+            standard(true, 1, GET_INTERFACE, 0, interface, 1)
+        }
+        _ => Urb::Unsupported,
+    };
+    Ok(TsUrb {
+        function,
+        request_id: value & 0x7FFFFFFF,
+        no_ack: value & 0x80000000 != 0,
+        urb,
+    })
+}
+
+/// Interface selected by the backend, with a handle for each pipe
+fn write_interface_result(
+    buffer: &mut Vec<u8>,
+    interface: &UsbInterface,
+    selection: &InterfaceSelection,
+) {
+    buffer
+        .put_u16_le((INTERFACE_RESULT_SIZE + interface.endpoints.len() * PIPE_RESULT_SIZE) as u16);
+    buffer.put_u8(interface.number);
+    buffer.put_u8(interface.alternate_setting);
+    buffer.put_u8(interface.class);
+    buffer.put_u8(interface.sub_class);
+    buffer.put_u8(interface.protocol);
+    buffer.put_u8(0);
+    buffer.put_u32_le((interface.number as u32) << 8 | interface.alternate_setting as u32);
+    buffer.put_u32_le(interface.endpoints.len() as u32);
+    for (index, endpoint) in interface.endpoints.iter().enumerate() {
+        let (max_transfer_size, pipe_flags) =
+            selection.pipes.get(index).copied().unwrap_or((u32::MAX, 0));
+        buffer.put_u16_le(endpoint.max_packet_size);
+        buffer.put_u8(endpoint.address);
+        buffer.put_u8(endpoint.interval);
+        buffer.put_u32_le((endpoint.attributes & 0x03) as u32);
+        buffer.put_u32_le(pipe_handle(endpoint.address));
+        buffer.put_u32_le(max_transfer_size);
+        buffer.put_u32_le(pipe_flags);
+    }
+}
+
+/// Result of the transfers without specific result
+/// MS-RDPEUSB 2.2.10.1.1 TS_URB_RESULT_HEADER
+pub fn ts_urb_result(usbd_status: u32) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.put_u16_le(HEADER_SIZE as u16);
+    buffer.put_u16_le(0);
+    buffer.put_u32_le(usbd_status);
+    buffer
+}
+
+/// Interfaces of the selected configuration
+/// MS-RDPEUSB 2.2.10.2 TS_URB_SELECT_CONFIGURATION_RESULT
+pub fn select_configuration_result(
+    configuration_handle: u32,
+    interfaces: &[(UsbInterface, InterfaceSelection)],
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.put_u32_le(configuration_handle);
+    body.put_u32_le(interfaces.len() as u32);
+    for (interface, selection) in interfaces {
+        write_interface_result(&mut body, interface, selection);
+    }
+    let mut buffer = Vec::new();
+    buffer.put_u16_le((HEADER_SIZE + body.len()) as u16);
+    buffer.put_u16_le(0);
+    buffer.put_u32_le(USBD_STATUS_SUCCESS);
+    buffer.extend_from_slice(&body);
+    buffer
+}
+
+/// Alternate setting of the selected interface
+/// MS-RDPEUSB 2.2.10.3 TS_URB_SELECT_INTERFACE_RESULT
+pub fn select_interface_result(
+    interface: &UsbInterface,
+    selection: &InterfaceSelection,
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_interface_result(&mut body, interface, selection);
+    let mut buffer = Vec::new();
+    buffer.put_u16_le((HEADER_SIZE + body.len()) as u16);
+    buffer.put_u16_le(0);
+    buffer.put_u32_le(USBD_STATUS_SUCCESS);
+    buffer.extend_from_slice(&body);
+    buffer
+}
+
+/// MS-RDPEUSB 2.2.10.4 TS_URB_GET_CURRENT_FRAME_NUMBER_RESULT
+pub fn frame_number_result(frame_number: u32) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.put_u16_le(HEADER_SIZE as u16 + 4);
+    buffer.put_u16_le(0);
+    buffer.put_u32_le(USBD_STATUS_SUCCESS);
+    buffer.put_u32_le(frame_number);
+    buffer
+}
+
+/// Status of a result, to check the completions
+pub fn read_usbd_status(data: &[u8]) -> Result<u32> {
+    data.get(4..8)
+        .map(|status| u32::from_le_bytes([status[0], status[1], status[2], status[3]]))
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "URBDRC: invalid urb result"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Standard and vendor requests become control transfers
+    #[test]
+    fn test_read_ts_urb_control() {
+        // Device descriptor, request 3 with acknowledgment
+        let mut buffer = BytesMut::from(&[12, 0, 0x0B, 0, 3, 0, 0, 0, 0, 1, 0, 0][..]);
+        let urb = read_ts_urb(&mut buffer, 18).unwrap();
+        assert_eq!(urb.request_id, 3);
+        assert!(!urb.no_ack);
+        assert_eq!(
+            urb.urb,
+            Urb::Control(SetupPacket {
+                request_type: 0x80,
+                request: GET_DESCRIPTOR,
+                value: 0x0100,
+                index: 0,
+                length: 18,
+            })
+        );
+
+        // Vendor request out to an interface, without acknowledgment
+        let mut buffer = BytesMut::from(
+            &[
+                20, 0, 0x18, 0, 4, 0, 0, 0x80, 0, 0, 0, 0, 0, 0x42, 1, 0, 2, 0, 0, 0,
+            ][..],
+        );
+        let urb = read_ts_urb(&mut buffer, 4).unwrap();
+        assert!(urb.no_ack);
+        assert_eq!(
+            urb.urb,
+            Urb::Control(SetupPacket {
+                request_type: 0x41,
+                request: 0x42,
+                value: 1,
+                index: 2,
+                length: 4,
+            })
+        );
+    }
+
+    /// The selection result gives a handle to each pipe
+    #[test]
+    fn test_select_configuration() {
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(&[0, 0, 0, 0, 1, 0, 0, 0]);
+        buffer.extend_from_slice(&[1, 1, 0, 0]);
+        buffer.extend_from_slice(&[24, 0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+        buffer.extend_from_slice(&[0, 2, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0]);
+        buffer.extend_from_slice(&[9, 2, 32, 0, 1, 1, 0, 0x80, 50]);
+        let urb = read_ts_urb(&mut buffer, 0).unwrap();
+        let selection = InterfaceSelection {
+            number: 0,
+            alternate_setting: 0,
+            pipes: vec![(0x10000, 0)],
+        };
+        assert_eq!(
+            urb.urb,
+            Urb::SelectConfiguration {
+                configuration_value: Some(1),
+                interfaces: vec![selection.clone()],
+            }
+        );
+
+        let interface = UsbInterface {
+            class: 0xFF,
+            endpoints: vec![UsbEndpoint {
+                address: 0x81,
+                attributes: 0x02,
+                max_packet_size: 512,
+                interval: 0,
+            }],
+            ..Default::default()
+        };
+        let result = select_configuration_result(1, &[(interface, selection)]);
+        assert_eq!(result.len(), 16 + INTERFACE_RESULT_SIZE + PIPE_RESULT_SIZE);
+        assert_eq!(result[0] as usize, result.len());
+        assert_eq!(read_usbd_status(&result).unwrap(), USBD_STATUS_SUCCESS);
+        assert_eq!(&result[40..44], &pipe_handle(0x81).to_le_bytes());
+    }
+}
@@ -0,0 +1,159 @@
+use bytes::{Buf, Bytes};
+use futures_util::{Sink, Stream};
+use std::io::{Error, ErrorKind, Result};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use tokio_tungstenite::WebSocketStream;
+
+/// Open the websocket on an opened stream, like a TLS stream
+/// to a gateway which terminates the websocket and forwards
+/// the bytes to the RDP server
+///
+/// # Example
+/// ```rust, ignore
+/// let stream = TcpStream::connect("gateway.example.com:80").await?;
+/// let transport = websocket::connect("ws://gateway.example.com/rdp", stream).await?;
+/// let client = RdpClient::builder()
+///     .username("user")
+///     .password("password")
+///     .connect_with(transport)
+///     .await?;
+/// ```
+pub async fn connect<S>(url: &str, stream: S) -> Result<WebSocketTransport<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (stream, _) = tokio_tungstenite::client_async(url, stream)
+        .await
+        .map_err(ws_error)?;
+    Ok(WebSocketTransport::new(stream))
+}
+
+/// Byte stream over the binary frames of a websocket
+/// Each write is sent as a binary frame, and the frames are read
+/// back to back since a gateway may split or merge the TPKT packets
+pub struct WebSocketTransport<S> {
+    stream: WebSocketStream<S>,
+    /// Rest of the last binary frame
+    pending: Bytes,
+}
+
+impl<S> WebSocketTransport<S> {
+    /// Wrap a websocket already opened, by a server
+    /// or with its own handshake
+    pub fn new(stream: WebSocketStream<S>) -> Self {
+        WebSocketTransport {
+            stream,
+            pending: Bytes::new(),
+        }
+    }
+
+    /// Give back the websocket, the bytes of a partially read
+    /// frame are lost
+    pub fn into_inner(self) -> WebSocketStream<S> {
+        self.stream
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketTransport<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        while self.pending.is_empty() {
+            match Pin::new(&mut self.stream).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Ok(WsMessage::Binary(data)))) => self.pending = Bytes::from(data),
+                Poll::Ready(Some(Ok(WsMessage::Text(_)))) => {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "WEBSOCKET: unexpected text frame",
+                    )))
+                }
+                // The pings are answered by the websocket itself
+                Poll::Ready(Some(Ok(WsMessage::Ping(_) | WsMessage::Pong(_)))) => (),
+                Poll::Ready(Some(Ok(_))) | Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Ready(Some(Err(WsError::ConnectionClosed))) => return Poll::Ready(Ok(())),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(ws_error(e))),
+            }
+        }
+        let size = self.pending.len().min(buf.remaining());
+        buf.put_slice(&self.pending[..size]);
+        self.pending.advance(size);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketTransport<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        match Pin::new(&mut self.stream).poll_ready(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(result) => result.map_err(ws_error)?,
+        }
+        Pin::new(&mut self.stream)
+            .start_send(WsMessage::Binary(buf.to_vec()))
+            .map_err(ws_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx).map_err(ws_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.stream).poll_close(cx).map_err(ws_error)
+    }
+}
+
+fn ws_error(e: WsError) -> Error {
+    match e {
+        WsError::Io(e) => e,
+        WsError::ConnectionClosed | WsError::AlreadyClosed => {
+            Error::new(ErrorKind::BrokenPipe, "WEBSOCKET: connection closed")
+        }
+        e => Error::new(ErrorKind::Other, format!("WEBSOCKET: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    /// Writes are binary frames, and the frames are read as a stream
+    #[tokio::test]
+    async fn test_websocket_transport() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut transport = WebSocketTransport::new(
+            WebSocketStream::from_raw_socket(client, Role::Client, None).await,
+        );
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+
+        transport.write_all(&[3, 0, 0, 5, 1]).await.unwrap();
+        transport.flush().await.unwrap();
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            WsMessage::Binary(vec![3, 0, 0, 5, 1])
+        );
+
+        // A TPKT packet split over two frames
+        server.send(WsMessage::Binary(vec![3, 0])).await.unwrap();
+        server.send(WsMessage::Ping(vec![])).await.unwrap();
+        server.send(WsMessage::Binary(vec![0, 5, 2])).await.unwrap();
+        let mut packet = [0u8; 5];
+        transport.read_exact(&mut packet).await.unwrap();
+        assert_eq!(packet, [3, 0, 0, 5, 2]);
+
+        server.close(None).await.unwrap();
+        assert_eq!(transport.read(&mut packet).await.unwrap(), 0);
+    }
+}
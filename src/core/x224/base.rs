@@ -1,6 +1,7 @@
 use std::io::Read;
 
-use crate::model::data::{Message, U16, U32};
+use crate::model::data::{check_remaining, Message, U16, U32};
+use crate::model::rnd::random;
 
 use async_trait::async_trait;
 use bytes::{Buf, BytesMut};
@@ -20,10 +21,14 @@ pub enum NegotiationType {
     /// Send when security level are not expected
     /// Server ask for NLA and client doesn't support it
     TypeRDPNegFailure = 0x03,
+    /// Correlation info
+    /// Send from client to server after the request
+    TypeRDPCorrelationInfo = 0x06,
 }
 
 #[repr(u32)]
-#[derive(Copy, Clone, Debug, TryFromPrimitive)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Protocols {
     /// Basic RDP security
     /// Not supported by rdp-rs
@@ -36,6 +41,19 @@ pub enum Protocols {
     ProtocolHybridEx = 0x08,
 }
 
+/// Reason sent by the server in a negotiation failure
+/// MS-RDPBCGR 2.2.1.2.2 RDP Negotiation Failure (RDP_NEG_FAILURE)
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum NegotiationFailureCode {
+    SslRequiredByServer = 0x01,
+    SslNotAllowedByServer = 0x02,
+    SslCertNotOnServer = 0x03,
+    InconsistentFlags = 0x04,
+    HybridRequiredByServer = 0x05,
+    SslWithUserAuthRequiredByServer = 0x06,
+}
+
 #[derive(Copy, Clone)]
 pub enum MessageType {
     X224TPDUConnectionRequest = 0xE0,
@@ -45,6 +63,26 @@ pub enum MessageType {
     X224TPDUError = 0x70,
 }
 
+/// Reason of a disconnect request
+/// ITU-T X.224 13.5.3 Disconnect Request (DR) TPDU
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, TryFromPrimitive)]
+pub enum DisconnectReason {
+    NotSpecified = 0,
+    Congestion = 1,
+    SessionEntityNotAttached = 2,
+    AddressUnknown = 3,
+    NormalDisconnect = 128,
+    RemoteCongestion = 129,
+    NegotiationFailed = 130,
+    DuplicateSourceReference = 131,
+    MismatchedReferences = 132,
+    ProtocolError = 133,
+    ReferenceOverflow = 135,
+    ConnectionRefused = 136,
+    InvalidHeaderLength = 138,
+}
+
 /// Credential mode
 #[repr(u8)]
 pub enum RequestMode {
@@ -58,6 +96,346 @@ pub enum RequestMode {
     CorrelationInfoPresent = 0x08,
 }
 
+/// Close the transport connection
+/// ITU-T X.224 13.5 Disconnect Request (DR) TPDU
+pub struct X224DisconnectRequest {
+    pub reason: u8,
+}
+
+impl X224DisconnectRequest {
+    pub fn new(reason: DisconnectReason) -> Self {
+        X224DisconnectRequest {
+            reason: reason as u8,
+        }
+    }
+
+    pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> std::io::Result<()> {
+        check_remaining(buffer, 7, "X224: disconnect request")?;
+        let _length_indicator = buffer.get_u8();
+        let _code = buffer.get_u8();
+        let _dst_ref = buffer.get_u16();
+        let _src_ref = buffer.get_u16();
+        self.reason = buffer.get_u8();
+        Ok(())
+    }
+
+    /// Typed reason if it is a known one
+    pub fn get_reason(&self) -> Option<DisconnectReason> {
+        DisconnectReason::try_from(self.reason).ok()
+    }
+}
+
+#[async_trait]
+impl Message for X224DisconnectRequest {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> std::io::Result<()> {
+        writer.write_u8(self.length() as u8 - 1).await?;
+        writer.write_u8(MessageType::X224TPDUDisconnectRequest as u8).await?;
+        writer.write_u16(0).await?;
+        writer.write_u16(0).await?;
+        writer.write_u8(self.reason).await?;
+        Ok(())
+    }
+
+    async fn read_from(
+        &mut self,
+        reader: &mut (impl AsyncRead + Unpin + Send),
+    ) -> std::io::Result<()> {
+        let _length_indicator = reader.read_u8().await?;
+        let _code = reader.read_u8().await?;
+        let _dst_ref = reader.read_u16().await?;
+        let _src_ref = reader.read_u16().await?;
+        self.reason = reader.read_u8().await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        7
+    }
+}
+
+/// Data sent before the negotiation request
+/// Connection brokers and load balancers use it
+/// to route the connection to the right host
+/// MS-RDPBCGR 2.2.1.1 Client X.224 Connection Request PDU
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RoutingInfo {
+    /// Sent as `Cookie: mstshash=<identifier>`
+    /// The identifier is usually the user name
+    Cookie(String),
+    /// Opaque token given by a load balancer
+    Token(Vec<u8>),
+}
+
+impl RoutingInfo {
+    /// Serialized field terminated by CR LF
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::x224::base::RoutingInfo;
+    /// let cookie = RoutingInfo::Cookie("admin".to_string());
+    /// assert_eq!(cookie.to_bytes(), b"Cookie: mstshash=admin\r\n".to_vec());
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = match self {
+            RoutingInfo::Cookie(identifier) => {
+                format!("Cookie: mstshash={}", identifier).into_bytes()
+            }
+            RoutingInfo::Token(token) => token.clone(),
+        };
+        if !result.ends_with(b"\r\n") {
+            result.extend_from_slice(b"\r\n");
+        }
+        result
+    }
+}
+
+/// Id used to trace a connection in the server event logs
+/// MS-RDPBCGR 2.2.1.1.2 RDP Correlation Info (RDP_NEG_CORRELATION_INFO)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CorrelationInfo {
+    pub correlation_id: [u8; 16],
+}
+
+impl CorrelationInfo {
+    /// Check the rules of the protocol on the id
+    /// The first byte must not be 0x00 or 0xF4
+    /// and no byte can be 0x0D
+    pub fn new(correlation_id: [u8; 16]) -> std::io::Result<Self> {
+        if correlation_id[0] == 0x00
+            || correlation_id[0] == 0xF4
+            || correlation_id.contains(&0x0D)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "X224: invalid correlation id",
+            ));
+        }
+        Ok(CorrelationInfo { correlation_id })
+    }
+
+    /// Random id which follows the rules of the protocol
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::core::x224::base::CorrelationInfo;
+    /// let info = CorrelationInfo::generate();
+    /// assert!(CorrelationInfo::new(info.correlation_id).is_ok());
+    /// ```
+    pub fn generate() -> Self {
+        let mut correlation_id = [0u8; 16];
+        correlation_id.copy_from_slice(&random(16));
+        for byte in correlation_id.iter_mut().filter(|byte| **byte == 0x0D) {
+            *byte = 0x0E;
+        }
+        if correlation_id[0] == 0x00 || correlation_id[0] == 0xF4 {
+            correlation_id[0] = 0x01;
+        }
+        CorrelationInfo { correlation_id }
+    }
+}
+
+#[async_trait]
+impl Message for CorrelationInfo {
+    async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> std::io::Result<()> {
+        writer.write_u8(NegotiationType::TypeRDPCorrelationInfo as u8).await?;
+        writer.write_u8(0).await?;
+        writer.write_u16_le(self.length() as u16).await?;
+        writer.write_all(&self.correlation_id).await?;
+        writer.write_all(&[0; 16]).await?;
+        Ok(())
+    }
+
+    async fn read_from(
+        &mut self,
+        reader: &mut (impl AsyncRead + Unpin + Send),
+    ) -> std::io::Result<()> {
+        let _type = reader.read_u8().await?;
+        let _flags = reader.read_u8().await?;
+        let _length = reader.read_u16_le().await?;
+        reader.read_exact(&mut self.correlation_id).await?;
+        let mut reserved = [0; 16];
+        reader.read_exact(&mut reserved).await?;
+        Ok(())
+    }
+
+    #[inline]
+    fn length(&self) -> usize {
+        36
+    }
+}
+
+/// Optional fields of the connection request
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionRequestOptions {
+    /// Cookie or routing token for load balancers
+    pub routing: Option<RoutingInfo>,
+    /// Sent after the negotiation request
+    pub correlation_info: Option<CorrelationInfo>,
+}
+
+/// Security protocols a server accepts
+/// in order of preference
+///
+/// # Example
+/// ```
+/// use rdp::core::x224::base::{NegotiationFailureCode, Protocols, SecurityPolicy};
+/// let policy = SecurityPolicy {
+///     accepted: vec![Protocols::ProtocolHybrid, Protocols::ProtocolSSL],
+/// };
+/// let requested = Protocols::ProtocolSSL as u32 | Protocols::ProtocolHybrid as u32;
+/// assert_eq!(policy.select(requested), Ok(Protocols::ProtocolHybrid));
+/// assert_eq!(
+///     policy.select(Protocols::ProtocolRDP as u32),
+///     Err(NegotiationFailureCode::HybridRequiredByServer)
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct SecurityPolicy {
+    pub accepted: Vec<Protocols>,
+}
+
+impl SecurityPolicy {
+    /// Pick the first accepted protocol requested by the client
+    /// Standard RDP security is always requested
+    /// The failure code tells the client what the server expects
+    pub fn select(&self, requested: u32) -> Result<Protocols, NegotiationFailureCode> {
+        let is_requested = |protocol: &Protocols| {
+            *protocol == Protocols::ProtocolRDP || requested & *protocol as u32 != 0
+        };
+        if let Some(protocol) = self.accepted.iter().find(|p| is_requested(p)) {
+            return Ok(*protocol);
+        }
+
+        Err(match self.accepted.first() {
+            Some(Protocols::ProtocolSSL) => NegotiationFailureCode::SslRequiredByServer,
+            Some(Protocols::ProtocolHybrid) | Some(Protocols::ProtocolHybridEx) => {
+                NegotiationFailureCode::HybridRequiredByServer
+            }
+            _ => NegotiationFailureCode::SslNotAllowedByServer,
+        })
+    }
+}
+
+/// Connection request received by a server
+/// MS-RDPBCGR 2.2.1.1 Client X.224 Connection Request PDU
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionRequest {
+    /// Cookie or routing token for load balancers
+    pub routing: Option<RoutingInfo>,
+    /// None when the client doesn't send a negotiation request
+    pub negotiation: Option<ClientNegotiation>,
+    /// Sent after the negotiation request
+    pub correlation_info: Option<CorrelationInfo>,
+}
+
+/// Content of the RDP_NEG_REQ structure
+/// MS-RDPBCGR 2.2.1.1.1 RDP Negotiation Request (RDP_NEG_REQ)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClientNegotiation {
+    /// Mix of RequestMode
+    pub flags: u8,
+    /// Mix of Protocols
+    pub requested_protocols: u32,
+}
+
+/// Read the routing field terminated by CR LF
+fn read_routing_info(buffer: &mut BytesMut) -> Option<RoutingInfo> {
+    const COOKIE: &[u8] = b"Cookie: mstshash=";
+    if !buffer.starts_with(b"Cookie: ") {
+        return None;
+    }
+
+    let end = buffer.windows(2).position(|window| window == b"\r\n")?;
+    let line = buffer.split_to(end + 2);
+    let line = &line[..end];
+    Some(match line.strip_prefix(COOKIE) {
+        Some(identifier) => RoutingInfo::Cookie(String::from_utf8_lossy(identifier).to_string()),
+        None => RoutingInfo::Token(line.to_vec()),
+    })
+}
+
+/// Parse a connection request sent by a client
+/// The buffer starts after the TPKT header
+///
+/// # Example
+/// ```
+/// use bytes::BytesMut;
+/// use rdp::core::x224::base::{read_connection_request, RoutingInfo};
+/// let mut buffer = BytesMut::from(&[34, 0xE0, 0, 0, 0, 0, 0][..]);
+/// buffer.extend_from_slice(b"Cookie: mstshash=a\r\n");
+/// buffer.extend_from_slice(&[1, 0, 8, 0, 1, 0, 0, 0]);
+/// let request = read_connection_request(&mut buffer).unwrap();
+/// assert_eq!(request.routing, Some(RoutingInfo::Cookie("a".to_string())));
+/// assert_eq!(request.negotiation.unwrap().requested_protocols, 1);
+/// ```
+pub fn read_connection_request(buffer: &mut BytesMut) -> std::io::Result<ConnectionRequest> {
+    check_remaining(buffer, 7, "X224: connection request")?;
+    let length_indicator = buffer.get_u8() as usize;
+    let code = buffer.get_u8();
+    if code & 0xF0 != MessageType::X224TPDUConnectionRequest as u8 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("X224: unexpected TPDU code {}", code),
+        ));
+    }
+    let _dst_ref = buffer.get_u16();
+    let _src_ref = buffer.get_u16();
+    let _class = buffer.get_u8();
+
+    // The length indicator doesn't count itself
+    check_remaining(buffer, length_indicator.saturating_sub(6), "X224: connection request")?;
+    let mut variable = buffer.split_to(length_indicator.saturating_sub(6));
+
+    let mut request = ConnectionRequest {
+        routing: read_routing_info(&mut variable),
+        ..Default::default()
+    };
+
+    if variable.remaining() >= 8 && variable[0] == NegotiationType::TypeRDPNegReq as u8 {
+        let mut negotiation = RdpNegRequest::new(None, None, None);
+        negotiation.read_from_buffer(&mut variable)?;
+        request.negotiation = Some(ClientNegotiation {
+            flags: negotiation.flags,
+            requested_protocols: negotiation.protocols.inner(),
+        });
+
+        if negotiation.flags & RequestMode::CorrelationInfoPresent as u8 != 0 {
+            check_remaining(&variable, 36, "X224: correlation info")?;
+            let _type = variable.get_u8();
+            let _flags = variable.get_u8();
+            let _length = variable.get_u16_le();
+            let mut correlation_id = [0; 16];
+            variable.copy_to_slice(&mut correlation_id);
+            request.correlation_info = Some(CorrelationInfo { correlation_id });
+        }
+    }
+
+    Ok(request)
+}
+
+/// Read the header of a data TPDU
+/// A disconnect request is returned as an error
+pub fn read_data_header(payload: &mut BytesMut) -> std::io::Result<()> {
+    check_remaining(payload, 3, "X224: data header")?;
+    if payload[1] == MessageType::X224TPDUDisconnectRequest as u8 {
+        let mut request = X224DisconnectRequest { reason: 0 };
+        request.read_from_buffer(payload)?;
+        let reason = match request.get_reason() {
+            Some(reason) => format!("{:?}", reason),
+            None => request.reason.to_string(),
+        };
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            format!("X224: disconnect request with reason {}", reason),
+        ));
+    }
+
+    // Skip the X224Header
+    payload.advance(3);
+    Ok(())
+}
+
 pub struct X224Header {
     header: u8,
     messageType: u8,
@@ -95,7 +473,7 @@ impl Message for X224Header {
 
     #[inline]
     fn length(&self) -> usize {
-        4
+        3
     }
 }
 
@@ -217,17 +595,43 @@ impl Message for RdpNegRequest {
 // }
 pub struct X224ConnectionPDU {
     pub header: X224CRQ,
+    /// Only sent by the client
+    pub routing: Option<RoutingInfo>,
     pub negotiation: RdpNegRequest,
+    /// Only sent by the client
+    pub correlation_info: Option<CorrelationInfo>,
 }
 
 impl X224ConnectionPDU {
     pub fn new() -> Self {
         Self {
             header: X224CRQ::new(0, MessageType::X224TPDUConnectionConfirm),
+            routing: None,
             negotiation: RdpNegRequest::new(None, None, None),
+            correlation_info: None,
         }
     }
 
+    /// Connection request sent by the client
+    /// The header length covers the optional fields
+    pub fn request(options: ConnectionRequestOptions, mut negotiation: RdpNegRequest) -> Self {
+        if options.correlation_info.is_some() {
+            negotiation.flags |= RequestMode::CorrelationInfoPresent as u8;
+        }
+
+        let mut pdu = Self {
+            header: X224CRQ::new(0, MessageType::X224TPDUConnectionRequest),
+            routing: options.routing,
+            negotiation,
+            correlation_info: options.correlation_info,
+        };
+        pdu.header = X224CRQ::new(
+            (pdu.length() - pdu.header.length()) as u8,
+            MessageType::X224TPDUConnectionRequest,
+        );
+        pdu
+    }
+
     pub fn read_from_buffer(&mut self, buffer: &mut BytesMut) -> std::io::Result<()> {
         self.header.read_from_buffer(buffer)?;
         self.negotiation.read_from_buffer(buffer)?;
@@ -239,7 +643,13 @@ impl X224ConnectionPDU {
 impl Message for X224ConnectionPDU {
     async fn write_to(&self, writer: &mut (impl AsyncWrite + Unpin + Send)) -> std::io::Result<()> {
         self.header.write_to(writer).await?;
+        if let Some(routing) = &self.routing {
+            writer.write_all(&routing.to_bytes()).await?;
+        }
         self.negotiation.write_to(writer).await?;
+        if let Some(correlation_info) = &self.correlation_info {
+            correlation_info.write_to(writer).await?;
+        }
         Ok(())
     }
 
@@ -254,6 +664,8 @@ impl Message for X224ConnectionPDU {
 
     #[inline]
     fn length(&self) -> usize {
-        self.header.length() + self.negotiation.length()
+        let routing_length = self.routing.as_ref().map_or(0, |routing| routing.to_bytes().len());
+        let correlation_length = self.correlation_info.map_or(0, |info| info.length());
+        self.header.length() + routing_length + self.negotiation.length() + correlation_length
     }
 }
@@ -1,13 +1,15 @@
+use crate::core::limits::PduLimits;
+use crate::core::metrics::Metrics;
 use crate::core::tpkt::base::Payload;
 use crate::core::tpkt::client::TpktClient;
+use crate::core::tpkt::queue::WritePriority;
 use crate::core::x224::base::{
-    MessageType, NegotiationType, Protocols, RdpNegRequest, RequestMode, X224ConnectionPDU,
-    X224Header, X224CRQ,
+    read_data_header, ConnectionRequestOptions, DisconnectReason, NegotiationType, Protocols,
+    RdpNegRequest, RequestMode, X224ConnectionPDU, X224DisconnectRequest, X224Header,
 };
 use crate::model::data::{Message, U16, U32};
 use crate::nla::sspi::AuthenticationProtocol;
 
-use bytes::Buf;
 use std::convert::TryFrom;
 use std::io::{Error, ErrorKind, Result};
 use std::option::Option;
@@ -36,11 +38,28 @@ impl<S: AsyncRead + AsyncWrite + Unpin + Send> X224Client<S> {
     where
         T: Message,
     {
-        let header = X224Header::new();
-        self.transport.write(header).await?;
-        self.transport.write(message).await?;
-        Ok(())
-        // self.transport.write(trame![x224_header(), message])
+        self.write_with_priority(message, WritePriority::Low).await
+    }
+
+    /// Send a message with a priority in the write queue
+    pub async fn write_with_priority<T: 'static>(
+        &mut self,
+        message: T,
+        priority: WritePriority,
+    ) -> Result<()>
+    where
+        T: Message,
+    {
+        // Header and message must be sent in the same TPKT frame
+        let mut buffer = Vec::with_capacity(X224Header::new().length() + message.length());
+        X224Header::new().write_to(&mut buffer).await?;
+        message.write_to(&mut buffer).await?;
+        self.transport.write_with_priority(buffer, priority).await
+    }
+
+    /// Fast path payloads skip the x224 header
+    pub async fn write_fast_path(&mut self, sec_flag: u8, payload: Vec<u8>) -> Result<()> {
+        self.transport.write_fast_path(sec_flag, payload).await
     }
 
     /// Start reading an entire X224 paylaod
@@ -50,8 +69,7 @@ impl<S: AsyncRead + AsyncWrite + Unpin + Send> X224Client<S> {
         let s = self.transport.read().await?;
         match s {
             Payload::Raw(mut payload) => {
-                // Skip 4 bytes for X224Header
-                payload.get_u32();
+                read_data_header(&mut payload)?;
                 Ok(Payload::Raw(payload))
             }
             Payload::FastPath(flag, payload) => Ok(Payload::FastPath(flag, payload)),
@@ -63,37 +81,114 @@ impl<S: AsyncRead + AsyncWrite + Unpin + Send> X224Client<S> {
     /// At the end it will produce a valid x224 layer
     ///
     /// security_protocols is a valid mix of Protocols
-    /// RDP -> Protocols::ProtocolRDP as u32 NOT implemented
+    /// RDP -> Protocols::ProtocolRDP as u32
     /// SSL -> Protocols::ProtocolSSL as u32
-    /// NLA -> Protocols::ProtocolSSL as u32 Protocols::Hybrid as u32
+    /// NLA -> Protocols::ProtocolSSL as u32 | Protocols::ProtocolHybrid as u32
     ///
     /// If NLA we need to provide an authentication protocol
     ///
+    /// Options add optional fields to the connection request
+    /// like the load balancing cookie
+    ///
     /// # Example
     /// ```rust, ignore
     /// // SSL Security layer
-    /// x224::Connector::connect(
+    /// X224Client::connect(
     ///     tpkt,
     ///     Protocols::ProtocolSSL as u32,
+    ///     false,
     ///     None,
-    ///     false
-    /// ).unwrap();
+    ///     false,
+    ///     false,
+    ///     ConnectionRequestOptions::default(),
+    /// ).await?;
+    ///
+    /// // Behind a load balancer
+    /// X224Client::connect(
+    ///     tpkt,
+    ///     Protocols::ProtocolSSL as u32,
+    ///     false,
+    ///     None,
+    ///     false,
+    ///     false,
+    ///     ConnectionRequestOptions {
+    ///         routing: Some(RoutingInfo::Cookie("admin".to_string())),
+    ///         correlation_info: Some(CorrelationInfo::generate()),
+    ///     },
+    /// ).await?;
     ///
     /// // NLA security Layer
-    /// x224::Client::connect(
+    /// X224Client::connect(
     ///     tpkt,
-    ///     Protocols::ProtocolSSL as u32 Protocols::Hybrid as u32,
-    ///     Some(&mut Ntlm::new("domain".to_string(), "username".to_string(), "password".to_string()),
-    ///     false
-    /// ).unwrap()
+    ///     Protocols::ProtocolSSL as u32 | Protocols::ProtocolHybrid as u32,
+    ///     false,
+    ///     Some(&mut Ntlm::new("domain".to_string(), "username".to_string(), "password".to_string())),
+    ///     false,
+    ///     false,
+    ///     ConnectionRequestOptions::default(),
+    /// ).await?;
     /// ```
+    #[cfg_attr(feature = "trace", tracing::instrument(name = "x224", skip_all))]
     pub async fn connect(
-        mut client: TpktClient<S>,
+        client: TpktClient<S>,
         security_protocols: u32,
         check_certificate: bool,
-        authentication_protocol: Option<&mut dyn AuthenticationProtocol>,
+        authentication_protocol: Option<&mut (dyn AuthenticationProtocol + Send)>,
         restricted_admin_mode: bool,
         blank_creds: bool,
+        options: ConnectionRequestOptions,
+    ) -> Result<X224Client<S>> {
+        let mut x224 =
+            Self::negotiate(client, security_protocols, restricted_admin_mode, options).await?;
+        // SSL isn't available on wasm
+        #[cfg(target_arch = "wasm32")]
+        let _ = (check_certificate, authentication_protocol, blank_creds);
+        match x224.selected_protocol {
+            Protocols::ProtocolRDP => (),
+            #[cfg(not(target_arch = "wasm32"))]
+            Protocols::ProtocolSSL => x224.start_ssl(check_certificate, "").await?,
+            #[cfg(not(target_arch = "wasm32"))]
+            Protocols::ProtocolHybrid => {
+                let authentication_protocol = authentication_protocol.ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        "X224: NLA needs an authentication protocol",
+                    )
+                })?;
+                x224.start_ssl(check_certificate, "").await?;
+                x224.start_nla(
+                    authentication_protocol,
+                    restricted_admin_mode || blank_creds,
+                )
+                .await?;
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "Security protocol not handled",
+                ))
+            }
+        }
+        Ok(x224)
+    }
+
+    /// Negotiation of the security protocol only
+    ///
+    /// SSL and NLA are left to the caller, with start_ssl
+    /// and start_nla, according to the selected protocol
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let mut x224 = X224Client::negotiate(tpkt, Protocols::ProtocolSSL as u32, false, options).await?;
+    /// if x224.get_selected_protocols() == Protocols::ProtocolSSL {
+    ///     x224.start_ssl(false, "").await?;
+    /// }
+    /// ```
+    pub async fn negotiate(
+        mut client: TpktClient<S>,
+        security_protocols: u32,
+        restricted_admin_mode: bool,
+        options: ConnectionRequestOptions,
     ) -> Result<X224Client<S>> {
         Self::write_connection_request(
             &mut client,
@@ -103,28 +198,34 @@ impl<S: AsyncRead + AsyncWrite + Unpin + Send> X224Client<S> {
             } else {
                 0
             }),
+            options,
         )
         .await?;
 
-        match Self::read_connection_confirm(&mut client).await? {
-            // Protocols::ProtocolHybrid => Ok(Client::new(
-            //     tpkt.start_nla(
-            //         check_certificate,
-            //         authentication_protocol.unwrap(),
-            //         restricted_admin_mode || blank_creds,
-            //     )?,
-            //     Protocols::ProtocolHybrid,
-            // )),
-            // Protocols::ProtocolSSL => Ok(Client::new(
-            //     tpkt.start_ssl(check_certificate)?,
-            //     Protocols::ProtocolSSL,
-            // )),
-            Protocols::ProtocolRDP => Ok(X224Client::new(client, Protocols::ProtocolRDP)),
-            _ => Err(Error::new(
-                ErrorKind::PermissionDenied,
-                "Security protocol not handled",
-            )),
-        }
+        let selected_protocol = Self::read_connection_confirm(&mut client).await?;
+        Ok(X224Client::new(client, selected_protocol))
+    }
+
+    /// Start SSL once it is selected by the server
+    /// The name is used for SNI and checked with the certificate
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn start_ssl(&mut self, check_certificate: bool, server_name: &str) -> Result<()> {
+        self.transport.start_ssl(check_certificate, server_name).await
+    }
+
+    /// Authenticate with CSSP once SSL is started
+    ///
+    /// In restricted admin mode the credentials
+    /// are not delegated to the server
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn start_nla(
+        &mut self,
+        authentication_protocol: &mut (dyn AuthenticationProtocol + Send),
+        restricted_admin_mode: bool,
+    ) -> Result<()> {
+        self.transport
+            .start_nla(authentication_protocol, restricted_admin_mode)
+            .await
     }
 
     /// Send connection request
@@ -132,18 +233,14 @@ impl<S: AsyncRead + AsyncWrite + Unpin + Send> X224Client<S> {
         client: &mut TpktClient<S>,
         security_protocols: u32,
         mode: Option<u8>,
+        options: ConnectionRequestOptions,
     ) -> std::io::Result<()> {
         let body = RdpNegRequest::new(
             Some(NegotiationType::TypeRDPNegReq),
             mode,
             Some(security_protocols),
         );
-        let header = X224CRQ::new(body.length() as u8, MessageType::X224TPDUConnectionRequest);
-        let pdu = X224ConnectionPDU {
-            header,
-            negotiation: body,
-        };
-        client.write(pdu).await
+        client.write(X224ConnectionPDU::request(options, body)).await
     }
 
     /// Expect a connection confirm payload
@@ -169,7 +266,13 @@ impl<S: AsyncRead + AsyncWrite + Unpin + Send> X224Client<S> {
             }
         }
 
-        return match NegotiationType::try_from(pdu.negotiation.tpe).unwrap() {
+        let negotiation_type = NegotiationType::try_from(pdu.negotiation.tpe).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Unknown negotiation type {}", pdu.negotiation.tpe),
+            )
+        })?;
+        return match negotiation_type {
             NegotiationType::TypeRDPNegFailure => Err(Error::new(
                 ErrorKind::ConnectionReset,
                 "Error during negotiation step",
@@ -178,6 +281,10 @@ impl<S: AsyncRead + AsyncWrite + Unpin + Send> X224Client<S> {
                 ErrorKind::ConnectionRefused,
                 "Server reject security protocols",
             )),
+            NegotiationType::TypeRDPCorrelationInfo => Err(Error::new(
+                ErrorKind::InvalidData,
+                "Unexpected correlation info in the connection confirm",
+            )),
             NegotiationType::TypeRDPNegRsp => Ok(
                 match Protocols::try_from(pdu.negotiation.protocols.inner()) {
                     Ok(p) => p,
@@ -197,12 +304,43 @@ impl<S: AsyncRead + AsyncWrite + Unpin + Send> X224Client<S> {
         self.selected_protocol
     }
 
-    #[inline]
-    pub async fn shutdown(&mut self) -> std::io::Result<()> {
+    /// Change the limits on the length of received PDUs
+    pub fn set_limits(&mut self, limits: PduLimits) {
+        self.transport.set_limits(limits);
+    }
+
+    /// Limits on the length of received PDUs
+    pub fn get_limits(&self) -> PduLimits {
+        self.transport.get_limits()
+    }
+
+    /// Counters of the connection
+    pub fn get_metrics(&self) -> &Metrics {
+        self.transport.get_metrics()
+    }
+
+    /// Close the connection
+    /// A disconnect request is sent first when a reason is given
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// x224.shutdown(Some(DisconnectReason::NormalDisconnect)).await?;
+    /// ```
+    pub async fn shutdown(&mut self, reason: Option<DisconnectReason>) -> Result<()> {
+        if let Some(reason) = reason {
+            self.transport.write(X224DisconnectRequest::new(reason)).await?;
+        }
         self.transport.shutdown().await
     }
 }
 
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> X224Client<S> {
+    /// Write the next PDUs from a task of their own
+    pub fn start_write_queue(&mut self, capacity: usize, prioritized: bool) {
+        self.transport.start_write_queue(capacity, prioritized);
+    }
+}
+
 #[cfg(test)]
 mod test {
     // use super::*;
@@ -0,0 +1,194 @@
+use crate::core::limits::PduLimits;
+use crate::core::metrics::Metrics;
+use crate::core::tpkt::base::Payload;
+use crate::core::tpkt::server::TpktServer;
+use crate::core::x224::base::{
+    read_connection_request, read_data_header, ConnectionRequest, DisconnectReason,
+    MessageType, NegotiationType, Protocols, RdpNegRequest, SecurityPolicy, X224CRQ,
+    X224DisconnectRequest, X224Header,
+};
+use crate::model::data::{to_vec, Message};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::model::link::TlsAcceptor;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::nla::cssp::cssp_accept;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::nla::ntlm::{NtlmCredentials, NtlmServer};
+
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// x224 server
+pub struct X224Server<S> {
+    /// Transport layer, x224 use a tpkt
+    transport: TpktServer<S>,
+    /// Security protocol selected from the policy
+    selected_protocol: Protocols,
+    /// Connection request sent by the client
+    request: ConnectionRequest,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> X224Server<S> {
+    /// Accept the connection request of a client
+    ///
+    /// The protocol is chosen from the policy and sent back in
+    /// a negotiation response, if none of the requested protocols
+    /// is accepted a negotiation failure is sent instead
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let policy = SecurityPolicy {
+    ///     accepted: vec![Protocols::ProtocolSSL, Protocols::ProtocolRDP],
+    /// };
+    /// let x224 = X224Server::accept(TpktServer::new(stream), &policy).await?;
+    /// ```
+    pub async fn accept(
+        mut transport: TpktServer<S>,
+        policy: &SecurityPolicy,
+    ) -> Result<X224Server<S>> {
+        let request = match transport.read().await? {
+            Payload::Raw(mut payload) => read_connection_request(&mut payload)?,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "X224: unexpected fast path payload during connection",
+                ))
+            }
+        };
+
+        // Old clients without negotiation only support standard RDP security
+        let requested_protocols = request
+            .negotiation
+            .map_or(Protocols::ProtocolRDP as u32, |n| n.requested_protocols);
+
+        match policy.select(requested_protocols) {
+            Ok(protocol) => {
+                let negotiation = RdpNegRequest::new(
+                    Some(NegotiationType::TypeRDPNegRsp),
+                    None,
+                    Some(protocol as u32),
+                );
+                Self::write_connection_confirm(&mut transport, &request, negotiation).await?;
+                Ok(X224Server {
+                    transport,
+                    selected_protocol: protocol,
+                    request,
+                })
+            }
+            Err(code) => {
+                let negotiation = RdpNegRequest::new(
+                    Some(NegotiationType::TypeRDPNegFailure),
+                    None,
+                    Some(code as u32),
+                );
+                Self::write_connection_confirm(&mut transport, &request, negotiation).await?;
+                Err(Error::new(
+                    ErrorKind::ConnectionRefused,
+                    format!("X224: negotiation failure {:?}", code),
+                ))
+            }
+        }
+    }
+
+    /// Send the connection confirm
+    /// The negotiation is only sent when the client asked for it
+    /// MS-RDPBCGR 2.2.1.2 Server X.224 Connection Confirm PDU
+    async fn write_connection_confirm(
+        transport: &mut TpktServer<S>,
+        request: &ConnectionRequest,
+        negotiation: RdpNegRequest,
+    ) -> Result<()> {
+        if request.negotiation.is_none() {
+            let header = X224CRQ::new(0, MessageType::X224TPDUConnectionConfirm);
+            return transport.write(to_vec(&header).await?).await;
+        }
+
+        let header = X224CRQ::new(
+            negotiation.length() as u8,
+            MessageType::X224TPDUConnectionConfirm,
+        );
+        let mut buffer = to_vec(&header).await?;
+        buffer.extend(to_vec(&negotiation).await?);
+        transport.write(buffer).await
+    }
+
+    /// Continue over SSL once it is selected
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn accept_ssl(mut self, acceptor: &TlsAcceptor) -> Result<Self> {
+        self.transport = self.transport.accept_ssl(acceptor).await?;
+        Ok(self)
+    }
+
+    /// Authenticate the client with CSSP once SSL is started
+    /// The public key is the one of the SSL certificate
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn accept_nla(
+        &mut self,
+        public_key: &[u8],
+        server: &mut NtlmServer,
+    ) -> Result<NtlmCredentials> {
+        cssp_accept(self.transport.get_stream(), public_key, server).await
+    }
+
+    /// Send a new x224 formated message
+    pub async fn write<T: 'static>(&mut self, message: T) -> Result<()>
+    where
+        T: Message,
+    {
+        // Header and message must be sent in the same TPKT frame
+        let mut buffer = Vec::with_capacity(X224Header::new().length() + message.length());
+        X224Header::new().write_to(&mut buffer).await?;
+        message.write_to(&mut buffer).await?;
+        self.transport.write(buffer).await
+    }
+
+    /// Fast path payloads skip the x224 header
+    pub async fn write_fast_path(&mut self, sec_flag: u8, payload: Vec<u8>) -> Result<()> {
+        self.transport.write_fast_path(sec_flag, payload).await
+    }
+
+    /// Read a x224 payload or a fast path input payload
+    pub async fn read(&mut self) -> Result<Payload> {
+        match self.transport.read().await? {
+            Payload::Raw(mut payload) => {
+                read_data_header(&mut payload)?;
+                Ok(Payload::Raw(payload))
+            }
+            payload => Ok(payload),
+        }
+    }
+
+    /// Getter for the selected protocol
+    pub fn get_selected_protocols(&self) -> Protocols {
+        self.selected_protocol
+    }
+
+    /// Connection request sent by the client
+    pub fn get_connection_request(&self) -> &ConnectionRequest {
+        &self.request
+    }
+
+    /// Change the limits on the length of received PDUs
+    pub fn set_limits(&mut self, limits: PduLimits) {
+        self.transport.set_limits(limits);
+    }
+
+    /// Limits on the length of received PDUs
+    pub fn get_limits(&self) -> PduLimits {
+        self.transport.get_limits()
+    }
+
+    /// Counters of the connection
+    pub fn get_metrics(&self) -> &Metrics {
+        self.transport.get_metrics()
+    }
+
+    /// Close the connection
+    /// A disconnect request is sent first when a reason is given
+    pub async fn shutdown(&mut self, reason: Option<DisconnectReason>) -> Result<()> {
+        if let Some(reason) = reason {
+            self.transport.write(X224DisconnectRequest::new(reason)).await?;
+        }
+        self.transport.shutdown().await
+    }
+}
@@ -0,0 +1,482 @@
+//! C interface of the client, for the applications
+//! written in C, C++, Go or Python
+//!
+//! The header is generated by cbindgen in the `OUT_DIR` of the build,
+//! `include/rdp.h` is refreshed when `RDP_UPDATE_HEADER` is set,
+//! and the library is built with
+//! `cargo rustc --release --features ffi --crate-type cdylib`
+//!
+//! Each session runs on its own single thread runtime,
+//! driven by the calls of the application
+//!
+//! ```c
+//! RdpConfig *config = rdp_config_new();
+//! rdp_config_set_target(config, "192.168.0.1");
+//! rdp_config_set_credentials(config, "domain", "username", "password");
+//! RdpSession *session = rdp_connect(config);
+//! rdp_config_free(config);
+//! if (session == NULL) {
+//!     fprintf(stderr, "%s\n", rdp_last_error());
+//!     return 1;
+//! }
+//! RdpCEvent event;
+//! while (rdp_poll_event(session, &event, 100) >= 0) {
+//!     if (event.kind == RDP_EVENT_KIND_BITMAP) {
+//!         // draw event.data at event.left, event.top
+//!     }
+//! }
+//! rdp_disconnect(session);
+//! ```
+use crate::core::client::{RdpClient, RdpClientBuilder};
+use crate::core::event::{PointerButton, RdpEvent, SessionEvent};
+use crate::core::input::InputEvent;
+
+use num_enum::TryFromPrimitive;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::io::{Error, ErrorKind, Result};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+/// The call succeeded
+pub const RDP_OK: c_int = 0;
+/// The call failed, the reason is given by rdp_last_error
+pub const RDP_ERROR: c_int = -1;
+
+thread_local! {
+    /// Last error of the calls made by this thread
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// Settings of a connection, opaque to C
+pub struct RdpConfig {
+    builder: RdpClientBuilder,
+}
+
+/// Connected session, opaque to C
+pub struct RdpSession {
+    client: RdpClient<TcpStream>,
+    /// Events read from the server and not yet polled
+    events: VecDeque<RdpEvent>,
+    /// Pixels of the last polled bitmap,
+    /// kept alive until the next poll
+    pixels: Vec<u8>,
+    /// Dropped after the client which uses it
+    runtime: Runtime,
+}
+
+/// Kind of a polled event
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RdpEventKind {
+    /// Pixels to draw on the desktop
+    Bitmap = 0,
+    /// The user is logged on
+    LoggedOn = 1,
+    /// New size of the desktop
+    Resized = 2,
+    /// The server ends the session
+    Terminated = 3,
+    /// Events not exposed to C
+    Other = 4,
+}
+
+/// Event filled by rdp_poll_event
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RdpCEvent {
+    pub kind: RdpEventKind,
+    /// Destination of a bitmap on the desktop, right and bottom included
+    pub left: u16,
+    pub top: u16,
+    pub right: u16,
+    pub bottom: u16,
+    /// Size of the bitmap pixels, or of the resized desktop
+    pub width: u16,
+    pub height: u16,
+    /// Bits per pixel of the bitmap pixels
+    pub bpp: u16,
+    /// RGBA bitmap pixels, valid until the next poll
+    pub data: *const u8,
+    pub data_len: usize,
+}
+
+impl RdpCEvent {
+    fn new(kind: RdpEventKind) -> Self {
+        RdpCEvent {
+            kind,
+            left: 0,
+            top: 0,
+            right: 0,
+            bottom: 0,
+            width: 0,
+            height: 0,
+            bpp: 0,
+            data: ptr::null(),
+            data_len: 0,
+        }
+    }
+}
+
+impl RdpSession {
+    /// Convert an event, the pixels are kept by the session
+    fn c_event(&mut self, event: RdpEvent) -> Result<RdpCEvent> {
+        Ok(match event {
+            RdpEvent::Bitmap(bitmap) => {
+                let mut c_event = RdpCEvent::new(RdpEventKind::Bitmap);
+                c_event.left = bitmap.dest_left;
+                c_event.top = bitmap.dest_top;
+                c_event.right = bitmap.dest_right;
+                c_event.bottom = bitmap.dest_bottom;
+                c_event.width = bitmap.width;
+                c_event.height = bitmap.height;
+                c_event.bpp = bitmap.bpp;
+                self.pixels = bitmap.decompress().map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("FFI: invalid bitmap {:?}", e),
+                    )
+                })?;
+                c_event.data = self.pixels.as_ptr();
+                c_event.data_len = self.pixels.len();
+                c_event
+            }
+            RdpEvent::Session(SessionEvent::LoggedOn { .. }) => {
+                RdpCEvent::new(RdpEventKind::LoggedOn)
+            }
+            RdpEvent::Session(SessionEvent::Reactivated { width, height }) => {
+                let mut c_event = RdpCEvent::new(RdpEventKind::Resized);
+                c_event.width = width;
+                c_event.height = height;
+                c_event
+            }
+            RdpEvent::Session(SessionEvent::Terminated(_)) => {
+                RdpCEvent::new(RdpEventKind::Terminated)
+            }
+            _ => RdpCEvent::new(RdpEventKind::Other),
+        })
+    }
+
+    fn write_input(&mut self, events: &[InputEvent]) -> Result<()> {
+        self.runtime.block_on(self.client.write_input(events))
+    }
+}
+
+/// Keep the error for rdp_last_error
+fn set_last_error(e: Error) {
+    let message =
+        CString::new(e.to_string().replace('\0', "")).expect("FFI: null characters are removed");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Status code of a call
+fn status(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => RDP_OK,
+        Err(e) => {
+            set_last_error(e);
+            RDP_ERROR
+        }
+    }
+}
+
+/// Borrow a C string, an error if null or not UTF-8
+unsafe fn c_str<'a>(value: *const c_char, name: &str) -> Result<&'a str> {
+    if value.is_null() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("FFI: {} is null", name),
+        ));
+    }
+    CStr::from_ptr(value).to_str().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("FFI: {} is not UTF-8", name),
+        )
+    })
+}
+
+/// Borrow an object given by C, an error if null
+unsafe fn c_ref<'a, T>(value: *mut T, name: &str) -> Result<&'a mut T> {
+    value
+        .as_mut()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("FFI: {} is null", name)))
+}
+
+/// Replace the builder of a config
+unsafe fn update_config(
+    config: *mut RdpConfig,
+    update: impl FnOnce(RdpClientBuilder) -> Result<RdpClientBuilder>,
+) -> Result<()> {
+    let config = c_ref(config, "config")?;
+    config.builder = update(config.builder.clone())?;
+    Ok(())
+}
+
+/// Message of the last error of this thread
+/// Null if no call failed, valid until the next failure
+#[no_mangle]
+pub extern "C" fn rdp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// New configuration, freed by rdp_config_free
+#[no_mangle]
+pub extern "C" fn rdp_config_new() -> *mut RdpConfig {
+    Box::into_raw(Box::new(RdpConfig {
+        builder: RdpClientBuilder::new(),
+    }))
+}
+
+/// Free a configuration
+///
+/// # Safety
+/// The config comes from rdp_config_new and is not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn rdp_config_free(config: *mut RdpConfig) {
+    if !config.is_null() {
+        drop(Box::from_raw(config));
+    }
+}
+
+/// Host name or address with an optional port
+///
+/// # Safety
+/// The config comes from rdp_config_new,
+/// the target is a null terminated string
+#[no_mangle]
+pub unsafe extern "C" fn rdp_config_set_target(
+    config: *mut RdpConfig,
+    target: *const c_char,
+) -> c_int {
+    status(update_config(config, |builder| {
+        Ok(builder.target(c_str(target, "target")?))
+    }))
+}
+
+/// Credentials of the logon
+///
+/// # Safety
+/// The config comes from rdp_config_new,
+/// the strings are null terminated
+#[no_mangle]
+pub unsafe extern "C" fn rdp_config_set_credentials(
+    config: *mut RdpConfig,
+    domain: *const c_char,
+    username: *const c_char,
+    password: *const c_char,
+) -> c_int {
+    status(update_config(config, |builder| {
+        Ok(builder.credentials(
+            c_str(domain, "domain")?,
+            c_str(username, "username")?,
+            c_str(password, "password")?,
+        ))
+    }))
+}
+
+/// Size of the desktop
+///
+/// # Safety
+/// The config comes from rdp_config_new
+#[no_mangle]
+pub unsafe extern "C" fn rdp_config_set_screen(
+    config: *mut RdpConfig,
+    width: u16,
+    height: u16,
+) -> c_int {
+    status(update_config(config, |builder| {
+        Ok(builder.screen(width, height))
+    }))
+}
+
+/// Run the whole connection sequence
+/// Null on failure, freed by rdp_disconnect
+/// The config can be freed or reused afterwards
+///
+/// # Safety
+/// The config comes from rdp_config_new
+#[no_mangle]
+pub unsafe extern "C" fn rdp_connect(config: *const RdpConfig) -> *mut RdpSession {
+    match connect(config) {
+        Ok(session) => Box::into_raw(Box::new(session)),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe fn connect(config: *const RdpConfig) -> Result<RdpSession> {
+    let config = c_ref(config as *mut RdpConfig, "config")?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let client = runtime.block_on(config.builder.clone().connect())?;
+    Ok(RdpSession {
+        client,
+        events: VecDeque::new(),
+        pixels: Vec::new(),
+        runtime,
+    })
+}
+
+/// Wait up to timeout_ms for the next event
+/// Returns 1 once the event is filled, 0 if none came in time
+/// and RDP_ERROR once the session is closed
+///
+/// # Safety
+/// The session comes from rdp_connect,
+/// the event points to a writable RdpCEvent
+#[no_mangle]
+pub unsafe extern "C" fn rdp_poll_event(
+    session: *mut RdpSession,
+    event: *mut RdpCEvent,
+    timeout_ms: u32,
+) -> c_int {
+    match poll_event(session, event, timeout_ms) {
+        Ok(polled) => polled,
+        Err(e) => {
+            set_last_error(e);
+            RDP_ERROR
+        }
+    }
+}
+
+unsafe fn poll_event(
+    session: *mut RdpSession,
+    event: *mut RdpCEvent,
+    timeout_ms: u32,
+) -> Result<c_int> {
+    let session = c_ref(session, "session")?;
+    let event = c_ref(event, "event")?;
+    if session.events.is_empty() {
+        let RdpSession {
+            client,
+            events,
+            runtime,
+            ..
+        } = &mut *session;
+        let read = client.read(|event| events.push_back(event));
+        let timeout = Duration::from_millis(timeout_ms as u64);
+        // Reads are cancel safe
+        if let Ok(result) = runtime.block_on(async { tokio::time::timeout(timeout, read).await }) {
+            result?;
+        }
+    }
+    match session.events.pop_front() {
+        Some(next) => {
+            *event = session.c_event(next)?;
+            Ok(1)
+        }
+        None => Ok(0),
+    }
+}
+
+/// Press or release a key
+///
+/// # Safety
+/// The session comes from rdp_connect
+#[no_mangle]
+pub unsafe extern "C" fn rdp_send_key(
+    session: *mut RdpSession,
+    scancode: u8,
+    extended: bool,
+    down: bool,
+) -> c_int {
+    status(c_ref(session, "session").and_then(|session| {
+        session.write_input(&[InputEvent::Scancode {
+            code: scancode,
+            extended,
+            down,
+        }])
+    }))
+}
+
+/// Move the mouse
+///
+/// # Safety
+/// The session comes from rdp_connect
+#[no_mangle]
+pub unsafe extern "C" fn rdp_send_mouse_move(session: *mut RdpSession, x: u16, y: u16) -> c_int {
+    status(
+        c_ref(session, "session")
+            .and_then(|session| session.write_input(&[InputEvent::mouse_move(x, y)])),
+    )
+}
+
+/// Press or release a mouse button
+/// 1 is the left button, 2 the right one, 3 the middle one,
+/// 4 and 5 the extended ones
+///
+/// # Safety
+/// The session comes from rdp_connect
+#[no_mangle]
+pub unsafe extern "C" fn rdp_send_mouse_button(
+    session: *mut RdpSession,
+    button: u8,
+    down: bool,
+    x: u16,
+    y: u16,
+) -> c_int {
+    let button = match PointerButton::try_from_primitive(button) {
+        Ok(button) => button,
+        Err(_) => {
+            return status(Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("FFI: unknown mouse button {}", button),
+            )))
+        }
+    };
+    status(
+        c_ref(session, "session").and_then(|session| {
+            session.write_input(&[InputEvent::mouse_button(button, down, x, y)])
+        }),
+    )
+}
+
+/// Close the session and free it
+///
+/// # Safety
+/// The session comes from rdp_connect and is not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn rdp_disconnect(session: *mut RdpSession) {
+    if !session.is_null() {
+        let mut session = Box::from_raw(session);
+        // The server may already be gone
+        let _ = session.runtime.block_on(session.client.shutdown());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Failures are reported by rdp_last_error
+    #[test]
+    fn test_ffi_connect_error() {
+        unsafe {
+            let config = rdp_config_new();
+            assert_eq!(rdp_config_set_target(config, ptr::null()), RDP_ERROR);
+            assert_eq!(
+                CStr::from_ptr(rdp_last_error()).to_str().unwrap(),
+                "FFI: target is null"
+            );
+
+            // No target was set
+            assert!(rdp_connect(config).is_null());
+            assert_eq!(
+                CStr::from_ptr(rdp_last_error()).to_str().unwrap(),
+                "RDPCLIENT: no target to connect to"
+            );
+            rdp_config_free(config);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 extern crate byteorder;
 extern crate indexmap;
 extern crate yasna;
+#[cfg(not(target_arch = "wasm32"))]
 extern crate native_tls;
 extern crate md4;
 extern crate hmac;
@@ -24,3 +25,5 @@ pub mod model;
 pub mod nla;
 pub mod core;
 pub mod codec;
+#[cfg(feature = "ffi")]
+pub mod ffi;
@@ -0,0 +1,63 @@
+/// Standard alphabet of RFC 4648, used by the HTTP authentication headers
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode with padding
+///
+/// # Example
+/// ```
+/// use rdp::model::base64::base64_encode;
+/// assert_eq!(base64_encode(b"foo"), "Zm9v");
+/// ```
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded
+                    .push(BASE64_ALPHABET[((value >> (18 - 6 * index)) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// None when a character is out of the alphabet
+pub fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut value = 0u32;
+    let mut bits = 0;
+    for c in encoded.trim_end_matches('=').bytes() {
+        let index = BASE64_ALPHABET.iter().position(|a| *a == c)? as u32;
+        value = (value << 6) | index;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((value >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test of the encoding with and without padding
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64_encode(b"user:password"), "dXNlcjpwYXNzd29yZA==");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_decode("Zm9vYg==").unwrap(), b"foob");
+        assert_eq!(base64_decode("Zm9vYmFy").unwrap(), b"foobar");
+        assert_eq!(base64_decode("Zm9v*"), None);
+    }
+}
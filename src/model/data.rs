@@ -1,5 +1,6 @@
 use async_trait::async_trait;
-use std::io::Result;
+use bytes::Buf;
+use std::io::{Error, ErrorKind, Result};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// All data type used
@@ -200,6 +201,39 @@ impl Message for Vec<u8> {
     }
 }
 
+/// Serialize a message into a new vector
+///
+/// Use to build a complete payload before
+/// handing it to the underlying layer
+///
+/// # Example
+/// ```
+/// # use rdp::model::data::{to_vec, U32};
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// assert_eq!(to_vec(&U32::LE(1)).await.unwrap(), [1, 0, 0, 0]);
+/// # }
+/// ```
+pub async fn to_vec(message: &impl Message) -> Result<Vec<u8>> {
+    let mut buffer = Vec::with_capacity(message.length());
+    message.write_to(&mut buffer).await?;
+    Ok(buffer)
+}
+
+/// Check that a buffer still hold at least size bytes
+///
+/// Buf getters panic on short buffer
+/// so every parser check before reading
+pub fn check_remaining(buffer: &impl Buf, size: usize, context: &str) -> Result<()> {
+    if buffer.remaining() < size {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("{}: expect {} bytes, got {}", context, size, buffer.remaining()),
+        ));
+    }
+    Ok(())
+}
+
 // /// Add dynamic filtering capability for parent Node
 // ///
 // /// Use by component node to create a filtering relationship
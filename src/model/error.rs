@@ -1,9 +1,13 @@
+#[cfg(not(target_arch = "wasm32"))]
 extern crate native_tls;
 
+#[cfg(not(target_arch = "wasm32"))]
 use self::native_tls::Error as SslError;
+#[cfg(not(target_arch = "wasm32"))]
 use self::native_tls::HandshakeError;
 use num_enum::{TryFromPrimitive, TryFromPrimitiveError};
 use std::io::Error as IoError;
+#[cfg(not(target_arch = "wasm32"))]
 use std::io::{Read, Write};
 use std::string::String;
 use yasna::ASN1Error;
@@ -49,6 +53,8 @@ pub enum RdpErrorKind {
     /// Indicate an unknown field
     Unknown,
     UnexpectedType,
+    /// A length field is above the configured limit
+    TooLarge,
 }
 
 #[derive(Debug)]
@@ -86,6 +92,15 @@ impl RdpError {
     }
 }
 
+impl std::fmt::Display for RdpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+/// Allow an RdpError to be carried by an IO error
+impl std::error::Error for RdpError {}
+
 #[derive(Debug)]
 pub enum Error {
     /// RDP error
@@ -95,6 +110,7 @@ pub enum Error {
     /// SSL handshake error
     SslHandshakeError,
     /// SSL error
+    #[cfg(not(target_arch = "wasm32"))]
     SslError(SslError),
     /// ASN1 parser error
     ASN1Error(ASN1Error),
@@ -109,12 +125,14 @@ impl From<IoError> for Error {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl<S: Read + Write> From<HandshakeError<S>> for Error {
     fn from(_: HandshakeError<S>) -> Error {
         Error::SslHandshakeError
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl From<SslError> for Error {
     fn from(e: SslError) -> Error {
         Error::SslError(e)
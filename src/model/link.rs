@@ -0,0 +1,328 @@
+use std::io::Result;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+#[cfg(not(target_arch = "wasm32"))]
+use native_tls::{HandshakeError, Identity, TlsConnector};
+#[cfg(not(target_arch = "wasm32"))]
+use std::future::poll_fn;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{Error, ErrorKind, Read, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::task::Waker;
+
+/// Transport under the TPKT layer
+/// TLS is started once the security protocol is negotiated
+pub enum Stream<S> {
+    /// Bytes as they are on the transport
+    Raw(S),
+    /// TLS over the transport
+    #[cfg(not(target_arch = "wasm32"))]
+    Ssl(Box<TlsStream<S>>),
+}
+
+impl<S> Stream<S> {
+    /// Check if the TLS layer is started
+    pub fn is_ssl(&self) -> bool {
+        !matches!(self, Stream::Raw(_))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream<S> {
+    /// Start TLS as a client
+    ///
+    /// Without check, any certificate is accepted as most
+    /// RDP servers use a self signed one
+    /// The server name is only checked when not empty
+    ///
+    /// # Example
+    /// ```rust, ignore
+    /// let stream = Stream::Raw(tcp).start_ssl(true, "server.example.com").await?;
+    /// ```
+    pub async fn start_ssl(self, check_certificate: bool, server_name: &str) -> Result<Stream<S>> {
+        let transport = match self {
+            Stream::Raw(transport) => transport,
+            Stream::Ssl(_) => return Err(ssl_started()),
+        };
+        let connector = TlsConnector::builder()
+            .danger_accept_invalid_certs(!check_certificate)
+            .danger_accept_invalid_hostnames(!check_certificate || server_name.is_empty())
+            .use_sni(!server_name.is_empty())
+            .build()
+            .map_err(ssl_error)?;
+        let stream = handshake(transport, |stream| connector.connect(server_name, stream)).await?;
+        Ok(Stream::Ssl(Box::new(stream)))
+    }
+
+    /// Start TLS as a server
+    pub async fn accept_ssl(self, acceptor: &TlsAcceptor) -> Result<Stream<S>> {
+        let transport = match self {
+            Stream::Raw(transport) => transport,
+            Stream::Ssl(_) => return Err(ssl_started()),
+        };
+        let stream = handshake(transport, |stream| acceptor.acceptor.accept(stream)).await?;
+        Ok(Stream::Ssl(Box::new(stream)))
+    }
+
+    /// DER certificate of the peer, none before TLS is started
+    pub fn get_peer_certificate(&self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Stream::Raw(_) => Ok(None),
+            Stream::Ssl(stream) => match stream.tls.peer_certificate().map_err(ssl_error)? {
+                Some(certificate) => Ok(Some(certificate.to_der().map_err(ssl_error)?)),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for Stream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        match self.get_mut() {
+            Stream::Raw(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::Ssl(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for Stream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        match self.get_mut() {
+            Stream::Raw(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::Ssl(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match self.get_mut() {
+            Stream::Raw(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::Ssl(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match self.get_mut() {
+            Stream::Raw(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(not(target_arch = "wasm32"))]
+            Stream::Ssl(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Certificate and key of the TLS servers
+///
+/// # Example
+/// ```rust, ignore
+/// let acceptor = TlsAcceptor::from_pem(
+///     &std::fs::read("cert.pem")?,
+///     &std::fs::read("key.pem")?,
+/// )?;
+/// ```
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: native_tls::TlsAcceptor,
+    /// DER certificate sent to the clients
+    certificate: Vec<u8>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TlsAcceptor {
+    /// Load a PEM certificate and its PKCS #8 PEM key
+    pub fn from_pem(certificate: &[u8], key: &[u8]) -> Result<Self> {
+        let (_, pem) = x509_parser::pem::parse_x509_pem(certificate).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("TLS: invalid PEM certificate, {:?}", e),
+            )
+        })?;
+        let identity = Identity::from_pkcs8(certificate, key).map_err(ssl_error)?;
+        Ok(TlsAcceptor {
+            acceptor: native_tls::TlsAcceptor::new(identity).map_err(ssl_error)?,
+            certificate: pem.contents,
+        })
+    }
+
+    /// DER certificate sent to the clients
+    pub fn get_certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+}
+
+/// TLS over an async transport
+///
+/// native-tls works on blocking streams, so the transport is
+/// polled from the blocking calls with the waker of the
+/// current task, a pending poll is seen as WouldBlock
+#[cfg(not(target_arch = "wasm32"))]
+pub struct TlsStream<S> {
+    tls: native_tls::TlsStream<AllowStd<S>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S: AsyncRead + AsyncWrite + Unpin> TlsStream<S> {
+    /// Run a blocking call of native-tls from a poll
+    fn with_context<F, R>(&mut self, cx: &mut Context<'_>, f: F) -> Poll<Result<R>>
+    where
+        F: FnOnce(&mut native_tls::TlsStream<AllowStd<S>>) -> Result<R>,
+    {
+        self.tls.get_mut().set_waker(cx);
+        match f(&mut self.tls) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Poll::Pending,
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        self.get_mut().with_context(cx, |tls| {
+            let size = tls.read(buf.initialize_unfilled())?;
+            buf.advance(size);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().with_context(cx, |tls| tls.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().with_context(cx, |tls| tls.flush())
+    }
+
+    /// Send the close notify alert then close the transport
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        match this.with_context(cx, |tls| tls.shutdown()) {
+            Poll::Ready(Ok(())) => (),
+            // The peer may have closed first
+            Poll::Ready(Err(e)) if e.kind() != ErrorKind::NotConnected => {
+                return Poll::Ready(Err(e))
+            }
+            Poll::Ready(Err(_)) => (),
+            Poll::Pending => return Poll::Pending,
+        }
+        Pin::new(&mut this.tls.get_mut().transport).poll_shutdown(cx)
+    }
+}
+
+/// Blocking view of an async transport for native-tls
+#[cfg(not(target_arch = "wasm32"))]
+struct AllowStd<S> {
+    transport: S,
+    /// Waker of the task polling the TLS stream
+    waker: Option<Waker>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S> AllowStd<S> {
+    fn set_waker(&mut self, cx: &Context<'_>) {
+        match &self.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => (),
+            _ => self.waker = Some(cx.waker().clone()),
+        }
+    }
+
+    /// Poll the transport, pending becomes WouldBlock
+    fn poll<F, R>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(Pin<&mut S>, &mut Context<'_>) -> Poll<Result<R>>,
+        S: Unpin,
+    {
+        let waker = match &self.waker {
+            Some(waker) => waker.clone(),
+            None => return Err(ErrorKind::WouldBlock.into()),
+        };
+        let mut cx = Context::from_waker(&waker);
+        match f(Pin::new(&mut self.transport), &mut cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S: AsyncRead + Unpin> Read for AllowStd<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut buf = ReadBuf::new(buf);
+        self.poll(|transport, cx| transport.poll_read(cx, &mut buf))?;
+        Ok(buf.filled().len())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<S: AsyncWrite + Unpin> Write for AllowStd<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.poll(|transport, cx| transport.poll_write(cx, buf))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.poll(|transport, cx| transport.poll_flush(cx))
+    }
+}
+
+/// Drive a TLS handshake until it is done
+#[cfg(not(target_arch = "wasm32"))]
+async fn handshake<S, F>(transport: S, start: F) -> Result<TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: FnOnce(
+        AllowStd<S>,
+    ) -> std::result::Result<
+        native_tls::TlsStream<AllowStd<S>>,
+        HandshakeError<AllowStd<S>>,
+    >,
+{
+    let mut start = Some((start, transport));
+    let mut pending: Option<native_tls::MidHandshakeTlsStream<AllowStd<S>>> = None;
+    poll_fn(|cx| {
+        let result = match (start.take(), pending.take()) {
+            (Some((start, transport)), _) => start(AllowStd {
+                transport,
+                waker: Some(cx.waker().clone()),
+            }),
+            (None, Some(mut stream)) => {
+                stream.get_mut().set_waker(cx);
+                stream.handshake()
+            }
+            (None, None) => return Poll::Ready(Err(ssl_started())),
+        };
+        match result {
+            Ok(tls) => Poll::Ready(Ok(TlsStream { tls })),
+            Err(HandshakeError::WouldBlock(stream)) => {
+                pending = Some(stream);
+                Poll::Pending
+            }
+            Err(HandshakeError::Failure(e)) => Poll::Ready(Err(ssl_error(e))),
+        }
+    })
+    .await
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn ssl_error(e: native_tls::Error) -> Error {
+    Error::new(ErrorKind::ConnectionAborted, format!("TLS: {}", e))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn ssl_started() -> Error {
+    Error::new(ErrorKind::InvalidInput, "TLS: already started")
+}
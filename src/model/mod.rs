@@ -3,4 +3,6 @@ pub mod data;
 #[macro_use]
 pub mod error;
 pub mod rnd;
-pub mod unicode;
\ No newline at end of file
+pub mod unicode;
+pub mod base64;
+pub mod link;
\ No newline at end of file
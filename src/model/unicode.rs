@@ -1,27 +1,35 @@
-// use crate::model::data::{Message, U16};
+/// Use to to_unicode function for String
+pub trait Unicode {
+    fn to_unicode(&self) -> Vec<u8>;
+}
 
-// use std::io::Cursor;
+impl Unicode for str {
+    /// Convert any string into utf-16le string
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::model::unicode::Unicode;
+    /// let s = "foo".to_string();
+    /// assert_eq!(s.to_unicode(), [102, 0, 111, 0, 111, 0])
+    /// ```
+    fn to_unicode(&self) -> Vec<u8> {
+        self.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+    }
+}
 
-// /// Use to to_unicode function for String
-// pub trait Unicode {
-//     fn to_unicode(&self) -> Vec<u8>;
-// }
-
-// impl Unicode for String {
-//     /// Convert any string into utf-16le string
-//     ///
-//     /// # Example
-//     /// ```
-//     /// use rdp::model::unicode::Unicode;
-//     /// let s = "foo".to_string();
-//     /// assert_eq!(s.to_unicode(), [102, 0, 111, 0, 111, 0])
-//     /// ```
-//     fn to_unicode(&self) -> Vec<u8> {
-//         let mut result = Cursor::new(Vec::new());
-//         for c in self.encode_utf16() {
-//             let encode_char = U16::LE(c);
-//             encode_char.write(&mut result).unwrap();
-//         }
-//         return result.into_inner();
-//     }
-// }
+/// Decode an utf-16le buffer
+/// Stop at the first null character if any
+///
+/// # Example
+/// ```
+/// use rdp::model::unicode::from_unicode;
+/// assert_eq!(from_unicode(&[102, 0, 111, 0, 111, 0, 0, 0, 1, 0]), "foo")
+/// ```
+pub fn from_unicode(buffer: &[u8]) -> String {
+    let chars: Vec<u16> = buffer
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    String::from_utf16_lossy(&chars)
+}
@@ -1,282 +1,612 @@
-// use crate::model::error::{Error, RdpError, RdpErrorKind, RdpResult};
-// use crate::nla::asn1::{
-//     to_der, ASN1Type, ExplicitTag, Integer, OctetString, Sequence, SequenceOf, ASN1,
-// };
-// use crate::nla::sspi::AuthenticationProtocol;
-
-// use num_bigint::BigUint;
-// use std::io::{Read, Write};
-// use x509_parser::prelude::*;
-// use yasna::Tag;
-
-// /// Create a ts request as expected by the specification
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-cssp/6aac4dea-08ef-47a6-8747-22ea7f6d8685?redirectedfrom=MSDN
-// ///
-// /// This is the first payload sent from client to server
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::nla::cssp::create_ts_request;
-// /// let payload = create_ts_request(vec![0, 1, 2]);
-// /// assert_eq!(payload, [48, 18, 160, 3, 2, 1, 2, 161, 11, 48, 9, 48, 7, 160, 5, 4, 3, 0, 1, 2])
-// /// ```
-// pub fn create_ts_request(nego: Vec<u8>) -> Vec<u8> {
-//     let ts_request = sequence![
-//         "version" => ExplicitTag::new(Tag::context(0), 2 as u32),
-//         "negoTokens" => ExplicitTag::new(Tag::context(1),
-//             sequence_of![
-//                 sequence![
-//                     "negoToken" => ExplicitTag::new(Tag::context(0), nego)
-//                 ]
-//             ])
-//     ];
-//     to_der(&ts_request)
-// }
-
-// /// This is the second step in CSSP handshake
-// /// this is the challenge message from server to client
-// ///
-// /// This function will parse the request and extract the negoToken
-// /// which use as payload field
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-cssp/6aac4dea-08ef-47a6-8747-22ea7f6d8685?redirectedfrom=MSDN
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-cssp/9664994d-0784-4659-b85b-83b8d54c2336
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::nla::cssp::read_ts_server_challenge;
-// /// let challenge = [48, 18, 160, 3, 2, 1, 2, 161, 11, 48, 9, 48, 7, 160, 5, 4, 3, 0, 1, 2];
-// /// let payload = read_ts_server_challenge(&challenge).unwrap();
-// /// assert_eq!(payload, [0, 1, 2])
-// /// ```
-// pub fn read_ts_server_challenge(stream: &[u8]) -> RdpResult<Vec<u8>> {
-//     let mut ts_request = sequence![
-//         "version" => ExplicitTag::new(Tag::context(0), 2 as Integer),
-//         "negoTokens" => ExplicitTag::new(Tag::context(1),
-//             SequenceOf::reader(|| {
-//                 Box::new(sequence![
-//                     "negoToken" => ExplicitTag::new(Tag::context(0), OctetString::new())
-//                 ])
-//             })
-//          )
-//     ];
-
-//     yasna::parse_der(stream, |reader| {
-//         if let Err(Error::ASN1Error(e)) = ts_request.read_asn1(reader) {
-//             return Err(e);
-//         }
-//         Ok(())
-//     })?;
-
-//     let nego_tokens = cast!(ASN1Type::SequenceOf, ts_request["negoTokens"]).unwrap();
-//     let first_nego_tokens = cast!(ASN1Type::Sequence, nego_tokens.inner[0]).unwrap();
-//     let nego_token = cast!(ASN1Type::OctetString, first_nego_tokens["negoToken"]).unwrap();
-//     Ok(nego_token.to_vec())
-// }
-
-// /// This the third step in CSSP Handshake
-// /// Send the pubKey of server encoded with negotiated key
-// /// to protect agains MITM attack
-// ///
-// /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-cssp/6aac4dea-08ef-47a6-8747-22ea7f6d8685?redirectedfrom=MSDN
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::nla::cssp::create_ts_authenticate;
-// /// let payload = create_ts_authenticate(vec![0, 1, 2], vec![0, 1, 2]);
-// /// assert_eq!(payload, [48, 25, 160, 3, 2, 1, 2, 161, 11, 48, 9, 48, 7, 160, 5, 4, 3, 0, 1, 2, 163, 5, 4, 3, 0, 1, 2])
-// /// ```
-// pub fn create_ts_authenticate(nego: Vec<u8>, pub_key_auth: Vec<u8>) -> Vec<u8> {
-//     let ts_challenge = sequence![
-//         "version" => ExplicitTag::new(Tag::context(0), 2 as Integer),
-//         "negoTokens" => ExplicitTag::new(Tag::context(1),
-//             sequence_of![
-//                 sequence![
-//                     "negoToken" => ExplicitTag::new(Tag::context(0), nego as OctetString)
-//                 ]
-//             ]),
-//         "pubKeyAuth" => ExplicitTag::new(Tag::context(3), pub_key_auth as OctetString)
-//     ];
-
-//     to_der(&ts_challenge)
-// }
-
-// /// Helper function to read the certificate
-// pub fn read_public_certificate(stream: &[u8]) -> RdpResult<X509Certificate> {
-//     match parse_x509_certificate(stream) {
-//         Ok((_, cert)) => Ok(cert),
-//         Err(e) => Err(Error::RdpError(RdpError::new(
-//             RdpErrorKind::InvalidProtocol,
-//             e.to_string().as_str(),
-//         ))),
-//     }
-// }
-
-// /// read ts validate
-// /// This is the last step in cssp handshake
-// /// Server must send its public key incremented by one
-// /// and cyphered with the authentication protocol
-// /// Parse the ts message and extract the public key
-// ///
-// /// # Example
-// /// ```
-// /// use rdp::nla::cssp::read_ts_validate;
-// /// let pub_key = read_ts_validate(&[48, 12, 160, 3, 2, 1, 2, 163, 5, 4, 3, 0, 1, 2]).unwrap();
-// /// assert_eq!(pub_key, [0, 1, 2])
-// /// ```
-// pub fn read_ts_validate(request: &[u8]) -> RdpResult<Vec<u8>> {
-//     let mut ts_challenge = sequence![
-//         "version" => ExplicitTag::new(Tag::context(0), 2 as Integer),
-//         "pubKeyAuth" => ExplicitTag::new(Tag::context(3), OctetString::new())
-//     ];
-
-//     yasna::parse_der(request, |reader| {
-//         if let Err(Error::ASN1Error(e)) = ts_challenge.read_asn1(reader) {
-//             return Err(e);
-//         }
-//         Ok(())
-//     })?;
-//     let pubkey = cast!(ASN1Type::OctetString, ts_challenge["pubKeyAuth"])?;
-//     Ok(pubkey.to_vec())
-// }
-
-// fn create_ts_credentials(domain: Vec<u8>, user: Vec<u8>, password: Vec<u8>) -> Vec<u8> {
-//     let ts_password_creds = sequence![
-//         "domainName" => ExplicitTag::new(Tag::context(0), domain as OctetString),
-//         "userName" => ExplicitTag::new(Tag::context(1), user as OctetString),
-//         "password" => ExplicitTag::new(Tag::context(2), password as OctetString)
-//     ];
-
-//     let ts_password_cred_encoded = yasna::construct_der(|writer| {
-//         ts_password_creds.write_asn1(writer).unwrap();
-//     });
-
-//     let ts_credentials = sequence![
-//         "credType" => ExplicitTag::new(Tag::context(0), 1 as Integer),
-//         "credentials" => ExplicitTag::new(Tag::context(1), ts_password_cred_encoded as OctetString)
-//     ];
-
-//     to_der(&ts_credentials)
-// }
-
-// fn create_ts_authinfo(auth_info: Vec<u8>) -> Vec<u8> {
-//     let ts_authinfo = sequence![
-//         "version" => ExplicitTag::new(Tag::context(0), 2 as Integer),
-//         "authInfo" => ExplicitTag::new(Tag::context(2), auth_info)
-//     ];
-
-//     to_der(&ts_authinfo)
-// }
-
-// /// This the main function for CSSP protocol
-// /// It will use the raw link layer and the selected authenticate protocol
-// /// to perform the NLA authenticate
-// pub fn cssp_connect<S: Read + Write>(
-//     link: &mut Link<S>,
-//     authentication_protocol: &mut dyn AuthenticationProtocol,
-//     restricted_admin_mode: bool,
-// ) -> RdpResult<()> {
-//     // first step is to send the negotiate message from authentication protocol
-//     let negotiate_message = create_ts_request(authentication_protocol.create_negotiate_message()?);
-//     link.write(&negotiate_message)?;
-
-//     // now receive server challenge
-//     let server_challenge = read_ts_server_challenge(&(link.read(0)?))?;
-
-//     // now ask for to authenticate protocol
-//     let client_challenge = authentication_protocol.read_challenge_message(&server_challenge)?;
-
-//     // now we need to build the security interface for auth protocol
-//     let mut security_interface = authentication_protocol.build_security_interface();
-
-//     // Get the peer public certificate
-//     let certificate_der = try_option!(
-//         link.get_peer_certificate()?,
-//         "No public certificate available"
-//     )?
-//     .to_der()?;
-//     let certificate = read_public_certificate(&certificate_der)?;
-
-//     // Now we can send back our challenge payload wit the public key encoded
-//     let challenge = create_ts_authenticate(
-//         client_challenge,
-//         security_interface.gss_wrapex(
-//             certificate
-//                 .tbs_certificate
-//                 .subject_pki
-//                 .subject_public_key
-//                 .data,
-//         )?,
-//     );
-//     link.write(&challenge)?;
-
-//     // now server respond normally with the original public key incremented by one
-//     let inc_pub_key = security_interface.gss_unwrapex(&(read_ts_validate(&(link.read(0)?))?))?;
-
-//     // Check possible man in the middle using cssp
-//     if BigUint::from_bytes_le(&inc_pub_key)
-//         != BigUint::from_bytes_le(
-//             certificate
-//                 .tbs_certificate
-//                 .subject_pki
-//                 .subject_public_key
-//                 .data,
-//         ) + BigUint::new(vec![1])
-//     {
-//         return Err(Error::RdpError(RdpError::new(
-//             RdpErrorKind::PossibleMITM,
-//             "Man in the middle detected",
-//         )));
-//     }
-
-//     // compute the last message with encoded credentials
-
-//     let domain = if restricted_admin_mode {
-//         vec![]
-//     } else {
-//         authentication_protocol.get_domain_name()
-//     };
-//     let user = if restricted_admin_mode {
-//         vec![]
-//     } else {
-//         authentication_protocol.get_user_name()
-//     };
-//     let password = if restricted_admin_mode {
-//         vec![]
-//     } else {
-//         authentication_protocol.get_password()
-//     };
-
-//     let credentials = create_ts_authinfo(
-//         security_interface.gss_wrapex(&create_ts_credentials(domain, user, password))?,
-//     );
-//     link.write(&credentials)?;
-
-//     Ok(())
-// }
-
-// #[cfg(test)]
-// mod test {
-//     use super::*;
-
-//     #[test]
-//     fn test_create_ts_credentials() {
-//         let credentials =
-//             create_ts_credentials(b"domain".to_vec(), b"user".to_vec(), b"password".to_vec());
-//         let result = [
-//             48, 41, 160, 3, 2, 1, 1, 161, 34, 4, 32, 48, 30, 160, 8, 4, 6, 100, 111, 109, 97, 105,
-//             110, 161, 6, 4, 4, 117, 115, 101, 114, 162, 10, 4, 8, 112, 97, 115, 115, 119, 111, 114,
-//             100,
-//         ];
-//         assert_eq!(credentials[0..32], result[0..32]);
-//         assert_eq!(credentials[33..43], result[33..43]);
-//     }
-
-//     #[test]
-//     fn test_create_ts_authinfo() {
-//         assert_eq!(
-//             create_ts_authinfo(b"foo".to_vec()),
-//             [48, 12, 160, 3, 2, 1, 2, 162, 5, 4, 3, 102, 111, 111]
-//         )
-//     }
-// }
+use crate::core::limits::check_limit;
+use crate::model::unicode::from_unicode;
+use crate::nla::ntlm::{NtlmCredentials, NtlmServer};
+use crate::nla::sspi::AuthenticationProtocol;
+
+use num_bigint::BigUint;
+use std::io::{Error, ErrorKind, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use x509_parser::prelude::*;
+use yasna::{DERWriter, Tag};
+
+/// Version of CSSP, the later ones hash the public key with SHA-256
+const CSSP_VERSION: u32 = 2;
+
+/// Largest TSRequest accepted from the peer
+const TS_REQUEST_MAX_SIZE: usize = 0x10000;
+
+/// TSCredentials of a password
+const CRED_TYPE_PASSWORD: u32 = 1;
+
+/// MS-CSSP 2.2.1 TSRequest
+///
+/// Only the first of the negoTokens is used
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TsRequest {
+    pub version: u32,
+    pub nego_token: Option<Vec<u8>>,
+    pub auth_info: Option<Vec<u8>>,
+    pub pub_key_auth: Option<Vec<u8>>,
+    pub error_code: Option<u32>,
+}
+
+impl TsRequest {
+    fn new() -> Self {
+        TsRequest {
+            version: CSSP_VERSION,
+            ..Default::default()
+        }
+    }
+
+    /// Encode the request as expected by the specification
+    /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-cssp/6aac4dea-08ef-47a6-8747-22ea7f6d8685?redirectedfrom=MSDN
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::nla::cssp::TsRequest;
+    /// let request = TsRequest {
+    ///     version: 2,
+    ///     nego_token: Some(vec![0, 1, 2]),
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(request.to_der(), [48, 18, 160, 3, 2, 1, 2, 161, 11, 48, 9, 48, 7, 160, 5, 4, 3, 0, 1, 2])
+    /// ```
+    pub fn to_der(&self) -> Vec<u8> {
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|sequence| {
+                sequence
+                    .next()
+                    .write_tagged(Tag::context(0), |writer| writer.write_u32(self.version));
+                if let Some(nego_token) = &self.nego_token {
+                    sequence.next().write_tagged(Tag::context(1), |writer| {
+                        writer.write_sequence(|nego_tokens| {
+                            nego_tokens.next().write_sequence(|nego_data| {
+                                write_octets(nego_data.next(), 0, nego_token)
+                            })
+                        })
+                    });
+                }
+                if let Some(auth_info) = &self.auth_info {
+                    write_octets(sequence.next(), 2, auth_info);
+                }
+                if let Some(pub_key_auth) = &self.pub_key_auth {
+                    write_octets(sequence.next(), 3, pub_key_auth);
+                }
+                if let Some(error_code) = self.error_code {
+                    sequence
+                        .next()
+                        .write_tagged(Tag::context(4), |writer| writer.write_u32(error_code));
+                }
+            })
+        })
+    }
+
+    /// Decode a request sent by the peer
+    ///
+    /// # Example
+    /// ```
+    /// use rdp::nla::cssp::TsRequest;
+    /// let request = TsRequest::from_der(&[48, 12, 160, 3, 2, 1, 2, 163, 5, 4, 3, 0, 1, 2]).unwrap();
+    /// assert_eq!(request.pub_key_auth.unwrap(), [0, 1, 2])
+    /// ```
+    pub fn from_der(payload: &[u8]) -> Result<Self> {
+        yasna::parse_der(payload, |reader| {
+            reader.read_sequence(|sequence| {
+                let version = sequence
+                    .next()
+                    .read_tagged(Tag::context(0), |reader| reader.read_u32())?;
+                let nego_token = sequence.read_optional(|reader| {
+                    reader.read_tagged(Tag::context(1), |reader| {
+                        reader.read_sequence(|nego_tokens| {
+                            nego_tokens.next().read_sequence(|nego_data| {
+                                nego_data
+                                    .next()
+                                    .read_tagged(Tag::context(0), |reader| reader.read_bytes())
+                            })
+                        })
+                    })
+                })?;
+                let auth_info = sequence.read_optional(|reader| {
+                    reader.read_tagged(Tag::context(2), |reader| reader.read_bytes())
+                })?;
+                let pub_key_auth = sequence.read_optional(|reader| {
+                    reader.read_tagged(Tag::context(3), |reader| reader.read_bytes())
+                })?;
+                let error_code = sequence.read_optional(|reader| {
+                    reader.read_tagged(Tag::context(4), |reader| reader.read_u32())
+                })?;
+                // The client nonce is only used by the later versions
+                sequence.read_optional(|reader| {
+                    reader.read_tagged(Tag::context(5), |reader| reader.read_bytes())
+                })?;
+                Ok(TsRequest {
+                    version,
+                    nego_token,
+                    auth_info,
+                    pub_key_auth,
+                    error_code,
+                })
+            })
+        })
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("CSSP: Invalid TSRequest {:?}", e),
+            )
+        })
+    }
+}
+
+fn write_octets(writer: DERWriter, tag: u64, data: &[u8]) {
+    writer.write_tagged(Tag::context(tag), |writer| writer.write_bytes(data))
+}
+
+/// MS-CSSP 2.2.1.2 TSCredentials of a password
+/// All the fields are encoded in UTF-16
+fn create_ts_credentials(domain: &[u8], user: &[u8], password: &[u8]) -> Vec<u8> {
+    let ts_password_creds = yasna::construct_der(|writer| {
+        writer.write_sequence(|sequence| {
+            write_octets(sequence.next(), 0, domain);
+            write_octets(sequence.next(), 1, user);
+            write_octets(sequence.next(), 2, password);
+        })
+    });
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|sequence| {
+            sequence.next().write_tagged(Tag::context(0), |writer| {
+                writer.write_u32(CRED_TYPE_PASSWORD)
+            });
+            write_octets(sequence.next(), 1, &ts_password_creds);
+        })
+    })
+}
+
+/// Read the domain, the user and the password of TSCredentials
+fn read_ts_credentials(payload: &[u8]) -> Result<(String, String, String)> {
+    let invalid = |e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("CSSP: Invalid TSCredentials {:?}", e),
+        )
+    };
+    let (cred_type, credentials) = yasna::parse_der(payload, |reader| {
+        reader.read_sequence(|sequence| {
+            let cred_type = sequence
+                .next()
+                .read_tagged(Tag::context(0), |reader| reader.read_u32())?;
+            let credentials = sequence
+                .next()
+                .read_tagged(Tag::context(1), |reader| reader.read_bytes())?;
+            Ok((cred_type, credentials))
+        })
+    })
+    .map_err(invalid)?;
+
+    if cred_type != CRED_TYPE_PASSWORD {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "CSSP: only password credentials are supported",
+        ));
+    }
+
+    yasna::parse_der(&credentials, |reader| {
+        reader.read_sequence(|sequence| {
+            let mut read_field = |tag| {
+                sequence
+                    .next()
+                    .read_tagged(Tag::context(tag), |reader| reader.read_bytes())
+                    .map(|field| from_unicode(&field))
+            };
+            Ok((read_field(0)?, read_field(1)?, read_field(2)?))
+        })
+    })
+    .map_err(invalid)
+}
+
+/// Read a whole TSRequest from the peer
+///
+/// The length is the one of the DER sequence
+async fn read_ts_request<S: AsyncRead + Unpin>(stream: &mut S) -> Result<TsRequest> {
+    let mut header = vec![0; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != 0x30 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "CSSP: Invalid TSRequest",
+        ));
+    }
+
+    let length = if header[1] & 0x80 == 0 {
+        header[1] as usize
+    } else {
+        let size = (header[1] & 0x7F) as usize;
+        if size == 0 || size > 4 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "CSSP: Invalid TSRequest length",
+            ));
+        }
+        let mut length = vec![0; size];
+        stream.read_exact(&mut length).await?;
+        header.extend(&length);
+        length
+            .iter()
+            .fold(0, |length, byte| (length << 8) | *byte as usize)
+    };
+    check_limit(length, TS_REQUEST_MAX_SIZE, "CSSP: TSRequest length")?;
+
+    let mut payload = header;
+    let offset = payload.len();
+    payload.resize(offset + length, 0);
+    stream.read_exact(&mut payload[offset..]).await?;
+    TsRequest::from_der(&payload)
+}
+
+async fn write_ts_request<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    request: &TsRequest,
+) -> Result<()> {
+    stream.write_all(&request.to_der()).await?;
+    stream.flush().await
+}
+
+fn expect_field(field: Option<Vec<u8>>, name: &str) -> Result<Vec<u8>> {
+    field.ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("CSSP: {} is missing", name)))
+}
+
+/// The NTLM layer reports errors with its own type
+fn auth_error(e: crate::model::error::Error) -> Error {
+    Error::new(ErrorKind::PermissionDenied, format!("CSSP: {:?}", e))
+}
+
+/// Public key of the certificate sent during the TLS handshake
+///
+/// The key is sent back ciphered by the authentication
+/// protocol so both sides know there is no man in the middle
+pub fn read_public_key(certificate: &[u8]) -> Result<Vec<u8>> {
+    match parse_x509_certificate(certificate) {
+        Ok((_, certificate)) => Ok(certificate
+            .tbs_certificate
+            .subject_pki
+            .subject_public_key
+            .data
+            .to_vec()),
+        Err(e) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("CSSP: Invalid certificate {:?}", e),
+        )),
+    }
+}
+
+/// The server proves it has the key by sending it back incremented by one
+fn increment(public_key: &[u8]) -> Vec<u8> {
+    let mut result = (BigUint::from_bytes_le(public_key) + 1u32).to_bytes_le();
+    result.resize(public_key.len().max(result.len()), 0);
+    result
+}
+
+/// This the main function for CSSP protocol
+/// It will use the TLS link and the selected authenticate protocol
+/// to perform the NLA authenticate
+///
+/// # Example
+/// ```rust, ignore
+/// let public_key = read_public_key(&stream.get_peer_certificate()?.unwrap())?;
+/// let mut ntlm = Ntlm::new("domain".to_string(), "user".to_string(), "password".to_string());
+/// cssp_connect(&mut stream, &public_key, &mut ntlm, false).await?;
+/// ```
+pub async fn cssp_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    public_key: &[u8],
+    authentication_protocol: &mut (dyn AuthenticationProtocol + Send),
+    restricted_admin_mode: bool,
+) -> Result<()> {
+    // first step is to send the negotiate message from authentication protocol
+    let mut negotiate = TsRequest::new();
+    negotiate.nego_token = Some(
+        authentication_protocol
+            .create_negotiate_message()
+            .map_err(auth_error)?,
+    );
+    write_ts_request(stream, &negotiate).await?;
+
+    // now receive server challenge
+    let challenge = read_ts_request(stream).await?;
+    let client_challenge = authentication_protocol
+        .read_challenge_message(&expect_field(challenge.nego_token, "negoToken")?)
+        .map_err(auth_error)?;
+
+    // now we need to build the security interface for auth protocol
+    let mut security_interface = authentication_protocol.build_security_interface();
+
+    // Now we can send back our challenge payload with the public key encoded
+    let mut authenticate = TsRequest::new();
+    authenticate.nego_token = Some(client_challenge);
+    authenticate.pub_key_auth = Some(
+        security_interface
+            .gss_wrapex(public_key)
+            .map_err(auth_error)?,
+    );
+    write_ts_request(stream, &authenticate).await?;
+
+    // now server respond normally with the original public key incremented by one
+    let validate = read_ts_request(stream).await?;
+    if let Some(error_code) = validate.error_code {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("CSSP: authentication failed with {:#x}", error_code),
+        ));
+    }
+    let inc_pub_key = security_interface
+        .gss_unwrapex(&expect_field(validate.pub_key_auth, "pubKeyAuth")?)
+        .map_err(auth_error)?;
+
+    // Check possible man in the middle using cssp
+    if inc_pub_key != increment(public_key) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "CSSP: Man in the middle detected",
+        ));
+    }
+
+    // compute the last message with encoded credentials
+    let credentials = if restricted_admin_mode {
+        create_ts_credentials(&[], &[], &[])
+    } else {
+        create_ts_credentials(
+            &authentication_protocol.get_domain_name(),
+            &authentication_protocol.get_user_name(),
+            &authentication_protocol.get_password(),
+        )
+    };
+    let mut auth_info = TsRequest::new();
+    auth_info.auth_info = Some(
+        security_interface
+            .gss_wrapex(&credentials)
+            .map_err(auth_error)?,
+    );
+    write_ts_request(stream, &auth_info).await
+}
+
+/// A client failed to authenticate
+///
+/// Its response is kept so the caller can log it
+#[derive(Debug)]
+pub struct AuthenticationFailure {
+    credentials: NtlmCredentials,
+    message: String,
+}
+
+impl std::fmt::Display for AuthenticationFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "CSSP: authentication of {}\\{} failed, {}",
+            self.credentials.domain, self.credentials.user, self.message
+        )
+    }
+}
+
+impl std::error::Error for AuthenticationFailure {}
+
+/// Credentials sent by a client whose authentication failed
+///
+/// # Example
+/// ```rust, ignore
+/// if let Err(e) = cssp_accept(&mut stream, &public_key, &mut acceptor.start()).await {
+///     if let Some(credentials) = get_failed_credentials(&e) {
+///         println!("{} failed to log in", credentials.user);
+///     }
+/// }
+/// ```
+pub fn get_failed_credentials(error: &Error) -> Option<&NtlmCredentials> {
+    error
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<AuthenticationFailure>())
+        .map(|e| &e.credentials)
+}
+
+/// Server side of CSSP
+///
+/// Authenticate the client with NTLM then read
+/// the password it delegates to the server
+///
+/// # Example
+/// ```rust, ignore
+/// let public_key = read_public_key(acceptor.get_certificate())?;
+/// let credentials = cssp_accept(&mut stream, &public_key, &mut ntlm.start()).await?;
+/// ```
+pub async fn cssp_accept<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    public_key: &[u8],
+    server: &mut NtlmServer,
+) -> Result<NtlmCredentials> {
+    let negotiate = read_ts_request(stream).await?;
+    let mut challenge = TsRequest::new();
+    challenge.nego_token = Some(
+        server
+            .read_negotiate_message(&expect_field(negotiate.nego_token, "negoToken")?)
+            .map_err(auth_error)?,
+    );
+    write_ts_request(stream, &challenge).await?;
+
+    let authenticate = read_ts_request(stream).await?;
+    let authenticate_message = expect_field(authenticate.nego_token, "negoToken")?;
+    if let Err(e) = server.read_authenticate_message(&authenticate_message) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            AuthenticationFailure {
+                credentials: server.get_credentials().clone(),
+                message: format!("{:?}", e),
+            },
+        ));
+    }
+
+    let mut security_interface = server.build_security_interface();
+    let client_public_key = security_interface
+        .gss_unwrapex(&expect_field(authenticate.pub_key_auth, "pubKeyAuth")?)
+        .map_err(auth_error)?;
+    if client_public_key != public_key {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "CSSP: Man in the middle detected",
+        ));
+    }
+
+    let mut validate = TsRequest::new();
+    validate.pub_key_auth = Some(
+        security_interface
+            .gss_wrapex(&increment(public_key))
+            .map_err(auth_error)?,
+    );
+    write_ts_request(stream, &validate).await?;
+
+    let auth_info = read_ts_request(stream).await?;
+    let credentials = security_interface
+        .gss_unwrapex(&expect_field(auth_info.auth_info, "authInfo")?)
+        .map_err(auth_error)?;
+    let (_, _, password) = read_ts_credentials(&credentials)?;
+
+    let mut credentials = server.get_credentials().clone();
+    credentials.password = Some(password);
+    Ok(credentials)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::unicode::Unicode;
+    use crate::nla::ntlm::{Ntlm, NtlmAcceptor};
+
+    /// Test format of the first request of the client
+    #[test]
+    fn test_create_ts_request() {
+        let mut request = TsRequest::new();
+        request.nego_token = Some(vec![0, 1, 2]);
+        assert_eq!(
+            request.to_der(),
+            [48, 18, 160, 3, 2, 1, 2, 161, 11, 48, 9, 48, 7, 160, 5, 4, 3, 0, 1, 2]
+        );
+        assert_eq!(TsRequest::from_der(&request.to_der()).unwrap(), request);
+    }
+
+    /// Test format of the request carrying the public key
+    #[test]
+    fn test_create_ts_authenticate() {
+        let mut request = TsRequest::new();
+        request.nego_token = Some(vec![0, 1, 2]);
+        request.pub_key_auth = Some(vec![0, 1, 2]);
+        assert_eq!(
+            request.to_der(),
+            [
+                48, 25, 160, 3, 2, 1, 2, 161, 11, 48, 9, 48, 7, 160, 5, 4, 3, 0, 1, 2, 163, 5, 4,
+                3, 0, 1, 2
+            ]
+        );
+    }
+
+    #[test]
+    fn test_create_ts_credentials() {
+        let credentials = create_ts_credentials(b"domain", b"user", b"password");
+        let result = [
+            48, 41, 160, 3, 2, 1, 1, 161, 34, 4, 32, 48, 30, 160, 8, 4, 6, 100, 111, 109, 97, 105,
+            110, 161, 6, 4, 4, 117, 115, 101, 114, 162, 10, 4, 8, 112, 97, 115, 115, 119, 111, 114,
+            100,
+        ];
+        assert_eq!(credentials, result);
+    }
+
+    #[test]
+    fn test_create_ts_authinfo() {
+        let mut request = TsRequest::new();
+        request.auth_info = Some(b"foo".to_vec());
+        assert_eq!(
+            request.to_der(),
+            [48, 12, 160, 3, 2, 1, 2, 162, 5, 4, 3, 102, 111, 111]
+        )
+    }
+
+    /// The credentials are read back in UTF-16
+    #[test]
+    fn test_read_ts_credentials() {
+        let credentials = create_ts_credentials(
+            &"domain".to_unicode(),
+            &"user".to_unicode(),
+            &"password".to_unicode(),
+        );
+        assert_eq!(
+            read_ts_credentials(&credentials).unwrap(),
+            (
+                "domain".to_string(),
+                "user".to_string(),
+                "password".to_string()
+            )
+        );
+    }
+
+    /// The carry goes through the little endian key
+    #[test]
+    fn test_increment() {
+        assert_eq!(increment(&[0xff, 0xff, 0]), [0, 0, 1]);
+        assert_eq!(increment(&[0xff]), [0, 1]);
+    }
+
+    /// A forged length is refused before any allocation
+    #[tokio::test]
+    async fn test_read_ts_request_too_large() {
+        let mut stream: &[u8] = &[48, 0x84, 0x10, 0, 0, 0];
+        let error = read_ts_request(&mut stream).await.unwrap_err();
+        assert!(crate::core::limits::is_too_large(&error));
+    }
+
+    /// The client gives its password to a server that knows it
+    #[tokio::test]
+    async fn test_cssp_accept() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let public_key = vec![1, 2, 3, 4];
+        let acceptor =
+            NtlmAcceptor::new("DOMAIN", "SERVER").passwords(|_, _| Some("password".to_string()));
+
+        let client_key = public_key.clone();
+        let connect = tokio::spawn(async move {
+            let mut ntlm = Ntlm::new(
+                "domain".to_string(),
+                "user".to_string(),
+                "password".to_string(),
+            );
+            cssp_connect(&mut client, &client_key, &mut ntlm, false).await
+        });
+        let credentials = cssp_accept(&mut server, &public_key, &mut acceptor.start())
+            .await
+            .unwrap();
+        connect.await.unwrap().unwrap();
+
+        assert_eq!(credentials.user, "user");
+        assert_eq!(credentials.password, Some("password".to_string()));
+    }
+
+    /// The response of a client with a wrong password is kept in the error
+    #[tokio::test]
+    async fn test_cssp_accept_failure() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let acceptor = NtlmAcceptor::new("DOMAIN", "SERVER");
+
+        let connect = tokio::spawn(async move {
+            let mut ntlm = Ntlm::new(
+                "domain".to_string(),
+                "user".to_string(),
+                "password".to_string(),
+            );
+            cssp_connect(&mut client, &[1, 2, 3, 4], &mut ntlm, false).await
+        });
+        let error = cssp_accept(&mut server, &[1, 2, 3, 4], &mut acceptor.start())
+            .await
+            .unwrap_err();
+        drop(server);
+        assert!(connect.await.unwrap().is_err());
+
+        let credentials = get_failed_credentials(&error).unwrap();
+        assert_eq!(credentials.user, "user");
+        assert!(!credentials.nt_challenge_response.is_empty());
+    }
+}
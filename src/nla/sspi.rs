@@ -26,7 +26,7 @@ pub trait AuthenticationProtocol {
     /// Once the two first step are done
     /// We can built the associated security interface
     /// to the underlying authenticate protocole
-    fn build_security_interface(&self) -> Box<dyn GenericSecurityService>;
+    fn build_security_interface(&self) -> Box<dyn GenericSecurityService + Send>;
 
     /// Get domain name encoded as expected in the negotiated payload
     fn get_domain_name(&self) -> Vec<u8>;
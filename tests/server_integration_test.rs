@@ -0,0 +1,642 @@
+use bytes::BytesMut;
+use rdp::core::client::{ConnectionPhase, RdpClientBuilder};
+use rdp::core::event::{RdpEvent, SessionEvent};
+use rdp::core::gcc::KeyboardLayout;
+use rdp::core::global::base::{
+    Action, ControlPdu, PDUType, PDUType2, ShareControlHeader, ShareDataHeader, SynchronizePdu,
+};
+use rdp::core::input::InputEvent;
+use rdp::core::mcs::client::McsClient;
+use rdp::core::proxy::{Direction, ProxyTap, RdpProxy};
+use rdp::core::rdp_url::parse_rdp_url;
+use rdp::core::sec::base::ClientInfoPdu;
+use rdp::core::sec::client::SecClient;
+use rdp::core::server::ServerSession;
+use rdp::core::tpkt::base::Payload;
+use rdp::core::tpkt::client::TpktClient;
+use rdp::core::x224::base::{ConnectionRequestOptions, Protocols, SecurityPolicy};
+use rdp::core::x224::client::X224Client;
+use rdp::model::data::{to_vec, Message};
+use rdp::model::link::TlsAcceptor;
+use rdp::nla::cssp::get_failed_credentials;
+use rdp::nla::ntlm::{Ntlm, NtlmAcceptor};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::DuplexStream;
+
+/// Share id of the activations of the test server
+const SHARE_ID: u32 = 0x103ea;
+
+/// Connect the client stack to a server session
+async fn connect_client(stream: DuplexStream) -> std::io::Result<SecClient<DuplexStream>> {
+    connect_client_with(stream, Protocols::ProtocolRDP as u32, None, &[]).await
+}
+
+/// Connect with the security protocols requested by the client
+async fn connect_client_with(
+    stream: DuplexStream,
+    security_protocols: u32,
+    authentication_protocol: Option<&mut Ntlm>,
+    channels: &[String],
+) -> std::io::Result<SecClient<DuplexStream>> {
+    let x224 = X224Client::connect(
+        TpktClient::new(stream),
+        security_protocols,
+        false,
+        authentication_protocol.map(|a| a as _),
+        false,
+        false,
+        ConnectionRequestOptions::default(),
+    )
+    .await?;
+    let mcs =
+        McsClient::connect_with_channels(x224, "rdp-rs", 800, 600, KeyboardLayout::US, channels)
+            .await?;
+    SecClient::connect(mcs, ClientInfoPdu::new("domain", "user", "password", true)).await
+}
+
+/// Send a share control PDU on the global channel
+async fn write_global(session: &mut ServerSession<DuplexStream>, pdu_type: PDUType, body: Vec<u8>) {
+    let header = ShareControlHeader::new(pdu_type, session.get_user_id(), body.len());
+    let mut payload = to_vec(&header).await.unwrap();
+    payload.extend(body);
+    session.write("global", payload).await.unwrap();
+}
+
+/// Send a data PDU on the global channel
+async fn write_data(
+    session: &mut ServerSession<DuplexStream>,
+    pdu_type_2: PDUType2,
+    message: &impl Message,
+) {
+    let header = ShareDataHeader::new(SHARE_ID, pdu_type_2, message.length());
+    let mut body = to_vec(&header).await.unwrap();
+    body.extend(to_vec(message).await.unwrap());
+    write_global(session, PDUType::PdutypeDatapdu, body).await;
+}
+
+/// Read the next data PDU of the global channel
+/// Messages of the other channels are skipped
+async fn read_data(session: &mut ServerSession<DuplexStream>) -> (u8, BytesMut) {
+    loop {
+        if let (channel_name, Payload::Raw(mut payload)) = session.read().await.unwrap() {
+            if channel_name == "global" {
+                ShareControlHeader::default()
+                    .read_from_buffer(&mut payload)
+                    .unwrap();
+                let mut header = ShareDataHeader::default();
+                header.read_from_buffer(&mut payload).unwrap();
+                return (header.pdu_type_2, payload);
+            }
+        }
+    }
+}
+
+/// Capability exchange without any capability
+/// then the server side of the finalization
+async fn activate(session: &mut ServerSession<DuplexStream>) {
+    let mut demand_active = SHARE_ID.to_le_bytes().to_vec();
+    // Lengths of the source descriptor and of the capabilities
+    demand_active.extend([4, 0, 4, 0]);
+    demand_active.extend(b"RDP\0");
+    // No capability set, then the session id
+    demand_active.extend([0; 8]);
+    write_global(session, PDUType::PdutypeDemandactivepdu, demand_active).await;
+
+    // Nothing else is written before the confirm active
+    match session.read().await.unwrap() {
+        (channel_name, Payload::Raw(mut payload)) if channel_name == "global" => {
+            let mut header = ShareControlHeader::default();
+            header.read_from_buffer(&mut payload).unwrap();
+            assert_eq!(
+                header.get_pdu_type(),
+                Some(PDUType::PdutypeConfirmactivepdu)
+            );
+        }
+        (channel_name, _) => panic!("write on {} during activation", channel_name),
+    }
+
+    let user_id = session.get_user_id();
+    write_data(
+        session,
+        PDUType2::Pdutype2Synchronize,
+        &SynchronizePdu::new(user_id),
+    )
+    .await;
+    for action in [
+        Action::CtrlactionCooperate,
+        Action::CtrlactionGrantedControl,
+    ] {
+        write_data(session, PDUType2::Pdutype2Control, &ControlPdu::new(action)).await;
+    }
+    write_data(session, PDUType2::Pdutype2Fontmap, &vec![0u8; 8]).await;
+
+    // Client finalization until the toggle keys
+    while read_data(session).await.0 != PDUType2::Pdutype2Input as u8 {}
+}
+
+/// Deactivate all PDU, then a new activation once
+/// the client had the time to queue a write
+async fn reactivate(session: &mut ServerSession<DuplexStream>) {
+    let mut deactivate_all = SHARE_ID.to_le_bytes().to_vec();
+    deactivate_all.extend([1, 0, 0]);
+    write_global(session, PDUType::PdutypeDeactivateallpdu, deactivate_all).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    activate(session).await;
+}
+
+/// The server captures the credentials of the client info PDU
+#[tokio::test]
+async fn test_server_session_accept() {
+    let (server, client) = tokio::io::duplex(4096);
+    let policy = SecurityPolicy {
+        accepted: vec![Protocols::ProtocolRDP],
+    };
+
+    let (session, client) = tokio::join!(
+        ServerSession::accept(server, &policy),
+        connect_client(client)
+    );
+    client.unwrap();
+    let session = session.unwrap();
+
+    let info = session.get_client_info();
+    assert_eq!(info.domain, "domain");
+    assert_eq!(info.username, "user");
+    assert_eq!(info.password, "password");
+    assert!(info.extended_info.is_some());
+    assert_eq!(session.get_client_data().core.get_client_name(), "rdp-rs");
+}
+
+/// Certificate and key of the test server
+fn tls_acceptor() -> TlsAcceptor {
+    TlsAcceptor::from_pem(
+        include_bytes!("data/certificate.pem"),
+        include_bytes!("data/key.pem"),
+    )
+    .unwrap()
+}
+
+/// Every protocol of the policy and of the client
+const ALL_PROTOCOLS: u32 = Protocols::ProtocolSSL as u32 | Protocols::ProtocolHybrid as u32;
+
+fn all_protocols_policy() -> SecurityPolicy {
+    SecurityPolicy {
+        accepted: vec![
+            Protocols::ProtocolHybrid,
+            Protocols::ProtocolSSL,
+            Protocols::ProtocolRDP,
+        ],
+    }
+}
+
+/// Without acceptor, TLS and NLA are skipped from the policy
+#[tokio::test]
+async fn test_server_session_tls_policy() {
+    let (server, client) = tokio::io::duplex(4096);
+    let policy = all_protocols_policy();
+
+    let (session, client) = tokio::join!(
+        ServerSession::accept(server, &policy),
+        connect_client_with(client, ALL_PROTOCOLS, None, &[])
+    );
+    client.unwrap();
+    let session = session.unwrap();
+    assert_eq!(
+        session.get_x224().get_selected_protocols(),
+        Protocols::ProtocolRDP
+    );
+    assert!(session.get_nla_credentials().is_none());
+}
+
+/// The session continues over TLS
+#[tokio::test]
+async fn test_server_session_accept_ssl() {
+    let (server, client) = tokio::io::duplex(4096);
+    let policy = all_protocols_policy();
+    let tls = tls_acceptor();
+
+    let (session, client) = tokio::join!(
+        ServerSession::accept_with(server, &policy, Some(&tls), None),
+        connect_client_with(client, ALL_PROTOCOLS, None, &[])
+    );
+    client.unwrap();
+    let session = session.unwrap();
+    assert_eq!(
+        session.get_x224().get_selected_protocols(),
+        Protocols::ProtocolSSL
+    );
+    assert_eq!(session.get_client_info().password, "password");
+}
+
+/// NLA gives the NTLMv2 response and the delegated password
+#[tokio::test]
+async fn test_server_session_accept_nla() {
+    let (server, client) = tokio::io::duplex(4096);
+    let policy = all_protocols_policy();
+    let tls = tls_acceptor();
+    let nla = NtlmAcceptor::new("DOMAIN", "SERVER").passwords(|_, user| {
+        if user == "user" {
+            Some("password".to_string())
+        } else {
+            None
+        }
+    });
+    let mut ntlm = Ntlm::new(
+        "domain".to_string(),
+        "user".to_string(),
+        "password".to_string(),
+    );
+
+    let (session, client) = tokio::join!(
+        ServerSession::accept_with(server, &policy, Some(&tls), Some(&nla)),
+        connect_client_with(client, ALL_PROTOCOLS, Some(&mut ntlm), &[])
+    );
+    client.unwrap();
+    let session = session.unwrap();
+    assert_eq!(
+        session.get_x224().get_selected_protocols(),
+        Protocols::ProtocolHybrid
+    );
+    let credentials = session.get_nla_credentials().unwrap();
+    assert_eq!(credentials.domain, "domain");
+    assert_eq!(credentials.user, "user");
+    assert_eq!(credentials.server_challenge.len(), 8);
+    assert!(credentials.nt_challenge_response.len() > 16);
+    assert_eq!(credentials.password, Some("password".to_string()));
+}
+
+/// The client of a `nla=1` URL authenticates through NLA
+#[tokio::test]
+async fn test_client_connect_nla_url() {
+    let (server, client) = tokio::io::duplex(0x10000);
+    let policy = all_protocols_policy();
+    let tls = tls_acceptor();
+    let nla = NtlmAcceptor::new("DOMAIN", "SERVER").passwords(|_, user| {
+        if user == "user" {
+            Some("password".to_string())
+        } else {
+            None
+        }
+    });
+    let mut config = parse_rdp_url("rdp://CORP%5Cuser@server/?nla=1").unwrap();
+    config.password = "password".to_string();
+
+    let server = async {
+        let mut session = ServerSession::accept_with(server, &policy, Some(&tls), Some(&nla))
+            .await
+            .unwrap();
+        activate(&mut session).await;
+        session
+    };
+
+    let phases = Arc::new(Mutex::new(Vec::new()));
+    let progress = phases.clone();
+
+    // The whole client stack is too large for the stack of the test
+    let client = Box::pin(async {
+        RdpClientBuilder::new()
+            .config(config)
+            .progress(move |phase| progress.lock().unwrap().push(phase))
+            .connect_with(client)
+            .await
+            .unwrap()
+    });
+
+    let test = async { tokio::join!(server, client) };
+    let (session, _client) = tokio::time::timeout(Duration::from_secs(5), test)
+        .await
+        .expect("connection was interrupted");
+    assert_eq!(
+        session.get_x224().get_selected_protocols(),
+        Protocols::ProtocolHybrid
+    );
+    let credentials = session.get_nla_credentials().unwrap();
+    assert_eq!(credentials.domain, "CORP");
+    assert_eq!(credentials.user, "user");
+    assert_eq!(credentials.password, Some("password".to_string()));
+    assert_eq!(
+        *phases.lock().unwrap(),
+        vec![
+            ConnectionPhase::NegotiationDone(Protocols::ProtocolHybrid),
+            ConnectionPhase::TlsEstablished,
+            ConnectionPhase::NlaDone,
+            ConnectionPhase::McsConnected,
+            ConnectionPhase::Licensed,
+            ConnectionPhase::Activated,
+        ]
+    );
+}
+
+/// The response of an unknown user is kept in the error
+#[tokio::test]
+async fn test_server_session_nla_failure() {
+    let (server, client) = tokio::io::duplex(4096);
+    let policy = all_protocols_policy();
+    let tls = tls_acceptor();
+    let nla = NtlmAcceptor::new("DOMAIN", "SERVER");
+    let mut ntlm = Ntlm::new(
+        "domain".to_string(),
+        "user".to_string(),
+        "password".to_string(),
+    );
+
+    let (session, client) = tokio::join!(
+        async {
+            // The client waits for the server until it is closed
+            let session = ServerSession::accept_with(server, &policy, Some(&tls), Some(&nla)).await;
+            session.err().unwrap()
+        },
+        connect_client_with(client, ALL_PROTOCOLS, Some(&mut ntlm), &[])
+    );
+    assert!(client.is_err());
+    let credentials = get_failed_credentials(&session).unwrap();
+    assert_eq!(credentials.user, "user");
+    assert!(credentials.nt_challenge_response.len() > 16);
+    assert_eq!(credentials.password, None);
+}
+
+/// Record the relayed PDUs and replace the password
+struct Recorder(Arc<Mutex<Vec<(Direction, String)>>>);
+
+impl ProxyTap for Recorder {
+    fn on_client_info(&mut self, info: &mut ClientInfoPdu) {
+        info.password = "secret".to_string();
+    }
+
+    fn on_pdu(&mut self, direction: Direction, channel_name: &str, _payload: &Payload) {
+        self.0
+            .lock()
+            .unwrap()
+            .push((direction, channel_name.to_string()));
+    }
+}
+
+/// PDUs are relayed through the proxy and seen by the taps
+#[tokio::test]
+async fn test_proxy_relay() {
+    let (client, proxy_client) = tokio::io::duplex(4096);
+    let (proxy_server, server) = tokio::io::duplex(4096);
+    let policy = SecurityPolicy {
+        accepted: vec![Protocols::ProtocolRDP],
+    };
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut proxy = RdpProxy::new(policy.clone()).tap(Recorder(log.clone()));
+
+    let test = async {
+        let (session, client) = tokio::join!(
+            ServerSession::accept(server, &policy),
+            connect_client(client)
+        );
+        let mut session = session.unwrap();
+        let mut client = client.unwrap();
+        assert_eq!(session.get_client_info().username, "user");
+        assert_eq!(session.get_client_info().password, "secret");
+
+        client.write("global", vec![1u8, 2, 3]).await.unwrap();
+        match session.read().await.unwrap() {
+            (channel_name, Payload::Raw(payload)) => {
+                assert_eq!(channel_name, "global");
+                assert_eq!(payload.to_vec(), vec![1, 2, 3]);
+            }
+            _ => panic!("expected a raw payload"),
+        }
+    };
+
+    tokio::select! {
+        result = proxy.relay(proxy_client, proxy_server) => panic!("relay ended {:?}", result.err()),
+        _ = test => (),
+    }
+    assert_eq!(
+        log.lock().unwrap().as_slice(),
+        &[(Direction::ClientToServer, "global".to_string())]
+    );
+}
+
+/// TLS and NLA are terminated by the proxy and started again
+/// with the server, static channels are relayed both ways
+#[tokio::test]
+async fn test_proxy_relay_nla() {
+    let (client, proxy_client) = tokio::io::duplex(4096);
+    let (proxy_server, server) = tokio::io::duplex(4096);
+    let policy = all_protocols_policy();
+    let tls = tls_acceptor();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut proxy = RdpProxy::new(policy.clone())
+        .tls(tls.clone())
+        .nla(NtlmAcceptor::new("PROXY", "PROXY").passwords(|_, _| Some("password".to_string())))
+        .tap(Recorder(log.clone()));
+    // The tap replaces the password given to the server
+    let nla = NtlmAcceptor::new("DOMAIN", "SERVER").passwords(|_, _| Some("secret".to_string()));
+    let mut ntlm = Ntlm::new(
+        "domain".to_string(),
+        "user".to_string(),
+        "password".to_string(),
+    );
+    let channels = ["cliprdr".to_string()];
+
+    let test = async {
+        let (session, client) = tokio::join!(
+            ServerSession::accept_with(server, &policy, Some(&tls), Some(&nla)),
+            connect_client_with(client, ALL_PROTOCOLS, Some(&mut ntlm), &channels)
+        );
+        let mut session = session.unwrap();
+        let mut client = client.unwrap();
+        assert_eq!(
+            session.get_x224().get_selected_protocols(),
+            Protocols::ProtocolHybrid
+        );
+        let credentials = session.get_nla_credentials().unwrap();
+        assert_eq!(credentials.user, "user");
+        assert_eq!(credentials.password, Some("secret".to_string()));
+        assert!(session.has_channel("cliprdr"));
+
+        client.write("cliprdr", vec![1u8, 2, 3]).await.unwrap();
+        match session.read().await.unwrap() {
+            (channel_name, Payload::Raw(payload)) => {
+                assert_eq!(channel_name, "cliprdr");
+                assert_eq!(payload.to_vec(), vec![1, 2, 3]);
+            }
+            _ => panic!("expected a raw payload"),
+        }
+
+        session.write("cliprdr", vec![4u8, 5]).await.unwrap();
+        match client.read().await.unwrap() {
+            (channel_name, Payload::Raw(payload)) => {
+                assert_eq!(channel_name, "cliprdr");
+                assert_eq!(payload.to_vec(), vec![4, 5]);
+            }
+            _ => panic!("expected a raw payload"),
+        }
+    };
+
+    tokio::select! {
+        result = proxy.relay(proxy_client, proxy_server) => panic!("relay ended {:?}", result.err()),
+        _ = test => (),
+    }
+    assert_eq!(
+        log.lock().unwrap().as_slice(),
+        &[
+            (Direction::ClientToServer, "cliprdr".to_string()),
+            (Direction::ServerToClient, "cliprdr".to_string())
+        ]
+    );
+}
+
+/// A channel message queued during a reactivation
+/// is written once the reactivation is done
+#[tokio::test]
+async fn test_reactivation_with_channel_write() {
+    let (server, client) = tokio::io::duplex(0x10000);
+    let policy = SecurityPolicy {
+        accepted: vec![Protocols::ProtocolRDP],
+    };
+
+    let server = async {
+        let mut session = ServerSession::accept(server, &policy).await.unwrap();
+        activate(&mut session).await;
+        reactivate(&mut session).await;
+
+        loop {
+            match session.read().await.unwrap() {
+                (channel_name, Payload::Raw(payload)) if channel_name == "MYCHAN" => {
+                    assert!(payload.ends_with(b"ping"));
+                    break;
+                }
+                _ => continue,
+            }
+        }
+        session.shutdown().await.unwrap();
+    };
+
+    // The whole client stack is too large for the stack of the test
+    let client = Box::pin(async {
+        let mut client = RdpClientBuilder::new()
+            .security(Protocols::ProtocolRDP as u32)
+            .channels(&["MYCHAN"])
+            .connect_with(client)
+            .await
+            .unwrap();
+        let channel = client.open_static_channel("MYCHAN").unwrap();
+
+        let write = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            channel.write(b"ping".to_vec()).await.unwrap();
+        };
+        let read = async {
+            let mut events = Vec::new();
+            while client.read(|event| events.push(event)).await.is_ok() {}
+            events
+        };
+        tokio::join!(write, read).1
+    });
+
+    let test = async { tokio::join!(server, client) };
+    let (_, events) = tokio::time::timeout(Duration::from_secs(5), test)
+        .await
+        .expect("reactivation was interrupted");
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, RdpEvent::Session(SessionEvent::Reactivated { .. }))));
+}
+
+/// An input queued with a latency during a reactivation
+/// is sent once the reactivation is done
+#[tokio::test]
+async fn test_reactivation_with_input_deadline() {
+    let (server, client) = tokio::io::duplex(0x10000);
+    let policy = SecurityPolicy {
+        accepted: vec![Protocols::ProtocolRDP],
+    };
+
+    let server = async {
+        let mut session = ServerSession::accept(server, &policy).await.unwrap();
+        activate(&mut session).await;
+        reactivate(&mut session).await;
+
+        let (pdu_type_2, payload) = read_data(&mut session).await;
+        assert_eq!(pdu_type_2, PDUType2::Pdutype2Input as u8);
+        // Message type of the first event is a mouse event
+        assert_eq!(payload[8..10], [0x01, 0x80]);
+        session.shutdown().await.unwrap();
+    };
+
+    let client = Box::pin(async {
+        let mut client = RdpClientBuilder::new()
+            .security(Protocols::ProtocolRDP as u32)
+            .input_latency(Duration::from_millis(50))
+            .connect_with(client)
+            .await
+            .unwrap();
+        client
+            .write_input(&[InputEvent::mouse_move(10, 10)])
+            .await
+            .unwrap();
+
+        let mut events = Vec::new();
+        while client.read(|event| events.push(event)).await.is_ok() {}
+        events
+    });
+
+    let test = async { tokio::join!(server, client) };
+    let (_, events) = tokio::time::timeout(Duration::from_secs(5), test)
+        .await
+        .expect("reactivation was interrupted");
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, RdpEvent::Session(SessionEvent::Reactivated { .. }))));
+}
+
+/// Inputs of a split session written during a reactivation
+/// are sent once the reactivation is done
+#[tokio::test]
+async fn test_split_session_reactivation() {
+    let (server, client) = tokio::io::duplex(0x10000);
+    let policy = SecurityPolicy {
+        accepted: vec![Protocols::ProtocolRDP],
+    };
+
+    let server = async {
+        let mut session = ServerSession::accept(server, &policy).await.unwrap();
+        activate(&mut session).await;
+        reactivate(&mut session).await;
+
+        let (pdu_type_2, payload) = read_data(&mut session).await;
+        assert_eq!(pdu_type_2, PDUType2::Pdutype2Input as u8);
+        assert_eq!(payload[8..10], [0x01, 0x80]);
+        session.shutdown().await.unwrap();
+    };
+
+    let client = Box::pin(async {
+        let client = RdpClientBuilder::new()
+            .security(Protocols::ProtocolRDP as u32)
+            .connect_with(client)
+            .await
+            .unwrap();
+        let (mut reader, writer) = client.split();
+
+        let write = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            writer
+                .write_input(&[InputEvent::mouse_move(10, 10)])
+                .await
+                .unwrap();
+        };
+        let read = async {
+            let mut events = Vec::new();
+            while let Some(Ok(event)) = reader.next().await {
+                events.push(event);
+            }
+            events
+        };
+        tokio::join!(write, read).1
+    });
+
+    let test = async { tokio::join!(server, client) };
+    let (_, events) = tokio::time::timeout(Duration::from_secs(5), test)
+        .await
+        .expect("reactivation was interrupted");
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, RdpEvent::Session(SessionEvent::Reactivated { .. }))));
+}
@@ -1,7 +1,67 @@
+use rdp::core::limits::{is_too_large, PduLimits};
+use rdp::core::metrics::Metrics;
 use rdp::core::tpkt::base::Payload;
 use rdp::core::tpkt::client::TpktClient;
+use rdp::core::tpkt::server::TpktServer;
 use rdp::model::data::U32;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// Transport which delivers its data
+/// a few bytes at a time and records each write
+struct ChunkedTransport {
+    data: Vec<u8>,
+    position: usize,
+    chunk_size: usize,
+    writes: Vec<Vec<u8>>,
+}
+
+impl ChunkedTransport {
+    fn new(data: Vec<u8>, chunk_size: usize) -> Self {
+        ChunkedTransport {
+            data,
+            position: 0,
+            chunk_size,
+            writes: Vec::new(),
+        }
+    }
+}
+
+impl AsyncRead for ChunkedTransport {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let end = (self.position + self.chunk_size)
+            .min(self.data.len())
+            .min(self.position + buf.remaining());
+        buf.put_slice(&self.data[self.position..end]);
+        self.position = end;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ChunkedTransport {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.writes.push(buf.to_vec());
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
 
 #[tokio::test]
 async fn test_tpkt_client_write() {
@@ -17,6 +77,21 @@ async fn test_tpkt_client_write() {
     assert_eq!(buf, [3, 0, 0, 8, 0, 0, 0, 1]);
 }
 
+#[tokio::test]
+async fn test_tpkt_client_write_single_segment() {
+    let mut transport = ChunkedTransport::new(Vec::new(), 1);
+    let mut client = TpktClient::new(&mut transport);
+
+    client.write(vec![1u8; 32]).await.unwrap();
+    client.write_fast_path(0, vec![2; 200]).await.unwrap();
+    // The transport is borrowed until the client is dropped
+    drop(client);
+
+    assert_eq!(transport.writes.len(), 2);
+    assert_eq!(transport.writes[0].len(), 36);
+    assert_eq!(transport.writes[1][..3], [0, 0x80, 203]);
+}
+
 #[tokio::test]
 async fn test_tpkt_client_read() {
     let (mut server, client) = tokio::io::duplex(128);
@@ -32,3 +107,119 @@ async fn test_tpkt_client_read() {
         _ => assert!(false),
     }
 }
+
+#[tokio::test]
+async fn test_tpkt_client_read_chunked() {
+    let mut data = vec![3, 0, 0x10, 0x04];
+    data.extend((0..0x1000).map(|i| i as u8));
+    data.extend([3, 0, 0, 5, 0xAA]);
+    let mut client = TpktClient::new(ChunkedTransport::new(data, 3));
+
+    match client.read().await.unwrap() {
+        Payload::Raw(data) => {
+            assert_eq!(data.len(), 0x1000);
+            assert_eq!(data[0x0FFF], 0xFF);
+        }
+        _ => panic!("expected a raw payload"),
+    }
+
+    match client.read().await.unwrap() {
+        Payload::Raw(data) => assert_eq!(data.to_vec(), vec![0xAA]),
+        _ => panic!("expected a raw payload"),
+    }
+}
+
+#[tokio::test]
+async fn test_tpkt_client_read_fast_path_chunked() {
+    let mut data = vec![0x80, 0x81, 0x03];
+    data.extend(vec![7; 0x100]);
+    let mut client = TpktClient::new(ChunkedTransport::new(data, 1));
+
+    match client.read().await.unwrap() {
+        Payload::FastPath(sec_flag, data) => {
+            assert_eq!(sec_flag, 2);
+            assert_eq!(data.to_vec(), vec![7; 0x100]);
+        }
+        _ => panic!("expected a fast path payload"),
+    }
+}
+
+#[tokio::test]
+async fn test_tpkt_client_read_truncated() {
+    let data = vec![3, 0, 0, 8, 0, 0];
+    let mut client = TpktClient::new(ChunkedTransport::new(data, 2));
+
+    let error = client.read().await.err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn test_tpkt_client_read_too_large() {
+    let data = vec![3, 0, 0x80, 0, 0, 0];
+    let mut client = TpktClient::new(ChunkedTransport::new(data, 6));
+    client.set_limits(PduLimits {
+        max_tpkt_size: 0x1000,
+        ..Default::default()
+    });
+
+    let error = client.read().await.err().unwrap();
+    assert!(is_too_large(&error));
+}
+
+/// Bytes and frames are counted in the shared metrics
+#[tokio::test]
+async fn test_tpkt_client_metrics() {
+    let data = vec![3, 0, 0, 5, 1, 0x00, 0x03, 2, 0x01];
+    let mut client = TpktClient::new(ChunkedTransport::new(data, 4));
+    let metrics = Metrics::default();
+    client.set_metrics(metrics.clone());
+
+    client.write(U32::LE(1)).await.unwrap();
+    client.read().await.unwrap();
+    client.read().await.unwrap();
+    assert!(client.read().await.is_err());
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.bytes_sent, 8);
+    assert_eq!(snapshot.bytes_received, 9);
+    assert_eq!(snapshot.fast_path_frames, 1);
+    assert_eq!(snapshot.pdus["tpkt"], 1);
+    assert_eq!(snapshot.decode_errors, 1);
+}
+
+#[tokio::test]
+async fn test_tpkt_server_read_client_write() {
+    let (server, client) = tokio::io::duplex(128);
+    let mut server = TpktServer::new(server);
+    let mut client = TpktClient::new(client);
+
+    client.write(U32::BE(1)).await.unwrap();
+    match server.read().await.unwrap() {
+        Payload::Raw(data) => assert_eq!(data.to_vec(), vec![0, 0, 0, 1]),
+        _ => panic!("expected a raw payload"),
+    }
+
+    server.write_fast_path(2, vec![1, 2, 3]).await.unwrap();
+    match client.read().await.unwrap() {
+        Payload::FastPath(sec_flag, data) => {
+            assert_eq!(sec_flag, 2);
+            assert_eq!(data.to_vec(), vec![1, 2, 3]);
+        }
+        _ => panic!("expected a fast path payload"),
+    }
+}
+
+#[tokio::test]
+async fn test_tpkt_server_read_fast_path_events_in_header() {
+    // Two events in the header and no number of events in the payload
+    let data = vec![0x08, 0x05, 0xA, 0xB, 0xC];
+    let mut server = TpktServer::new(ChunkedTransport::new(data, 2));
+
+    match server.read().await.unwrap() {
+        Payload::FastPath(sec_flag, data) => {
+            assert_eq!(sec_flag, 0);
+            assert_eq!(data.to_vec(), vec![2, 0xA, 0xB, 0xC]);
+        }
+        _ => panic!("expected a fast path payload"),
+    }
+}
@@ -1,6 +1,15 @@
 use bytes::{Buf, BufMut, BytesMut};
-use rdp::core::x224::base::{NegotiationType, Protocols, RdpNegRequest, X224ConnectionPDU};
+use rdp::core::tpkt::base::Payload;
+use rdp::core::tpkt::client::TpktClient;
+use rdp::core::tpkt::server::TpktServer;
+use rdp::core::x224::base::{
+    ConnectionRequestOptions, CorrelationInfo, DisconnectReason, NegotiationType, Protocols,
+    RdpNegRequest, RoutingInfo, SecurityPolicy, X224ConnectionPDU, X224DisconnectRequest,
+};
+use rdp::core::x224::client::X224Client;
+use rdp::core::x224::server::X224Server;
 use rdp::model::data::{Message, U32};
+use std::io::ErrorKind;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 #[tokio::test]
@@ -19,6 +28,148 @@ async fn test_x224_request() {
     assert_eq!(buf, [1, 0, 8, 0, 1, 0, 0, 0]);
 }
 
+/// The cookie is written between the header and the negotiation request
+#[tokio::test]
+async fn test_x224_request_with_cookie() {
+    let negotiation = RdpNegRequest::new(
+        Some(NegotiationType::TypeRDPNegReq),
+        None,
+        Some(Protocols::ProtocolSSL as u32),
+    );
+    let options = ConnectionRequestOptions {
+        routing: Some(RoutingInfo::Cookie("a".to_string())),
+        correlation_info: None,
+    };
+    let pdu = X224ConnectionPDU::request(options, negotiation);
+
+    let mut buffer = Vec::new();
+    pdu.write_to(&mut buffer).await.unwrap();
+    assert_eq!(buffer.len(), pdu.length());
+    assert_eq!(&buffer[..2], [34, 0xE0]);
+    assert_eq!(&buffer[7..27], b"Cookie: mstshash=a\r\n");
+    assert_eq!(&buffer[27..], [1, 0, 8, 0, 1, 0, 0, 0]);
+}
+
+/// Correlation info follows the negotiation request and sets its flag
+#[tokio::test]
+async fn test_x224_request_with_correlation_info() {
+    let negotiation = RdpNegRequest::new(
+        Some(NegotiationType::TypeRDPNegReq),
+        None,
+        Some(Protocols::ProtocolSSL as u32),
+    );
+    let options = ConnectionRequestOptions {
+        routing: None,
+        correlation_info: Some(CorrelationInfo::new([1; 16]).unwrap()),
+    };
+    let pdu = X224ConnectionPDU::request(options, negotiation);
+
+    let mut buffer = Vec::new();
+    pdu.write_to(&mut buffer).await.unwrap();
+    assert_eq!(buffer.len(), 51);
+    assert_eq!(buffer[0], 50);
+    assert_eq!(&buffer[7..11], [1, 0x08, 8, 0]);
+    assert_eq!(&buffer[15..19], [6, 0, 36, 0]);
+    assert_eq!(&buffer[19..35], [1; 16]);
+    assert!(CorrelationInfo::new([0xF4; 16]).is_err());
+}
+
+/// Test of the disconnect request format
+#[tokio::test]
+async fn test_x224_disconnect_request() {
+    let mut buffer = Vec::new();
+    X224DisconnectRequest::new(DisconnectReason::NormalDisconnect)
+        .write_to(&mut buffer)
+        .await
+        .unwrap();
+    assert_eq!(buffer, [6, 0x80, 0, 0, 0, 0, 128]);
+
+    let mut request = X224DisconnectRequest { reason: 0 };
+    request.read_from_buffer(&mut BytesMut::from(&buffer[..])).unwrap();
+    assert_eq!(request.get_reason(), Some(DisconnectReason::NormalDisconnect));
+}
+
+/// The server selects the protocol and keeps the cookie
+#[tokio::test]
+async fn test_x224_server_accept() {
+    let (server, client) = tokio::io::duplex(256);
+    let policy = SecurityPolicy {
+        accepted: vec![Protocols::ProtocolSSL, Protocols::ProtocolRDP],
+    };
+    let options = ConnectionRequestOptions {
+        routing: Some(RoutingInfo::Cookie("admin".to_string())),
+        correlation_info: Some(CorrelationInfo::new([1; 16]).unwrap()),
+    };
+
+    let (server, client) = tokio::join!(
+        X224Server::accept(TpktServer::new(server), &policy),
+        X224Client::connect(TpktClient::new(client), 0, false, None, false, false, options)
+    );
+    let mut server = server.unwrap();
+    let mut client = client.unwrap();
+
+    assert_eq!(server.get_selected_protocols(), Protocols::ProtocolRDP);
+    let request = server.get_connection_request();
+    assert_eq!(request.routing, Some(RoutingInfo::Cookie("admin".to_string())));
+    assert_eq!(request.correlation_info.unwrap().correlation_id, [1; 16]);
+
+    client.write(U32::LE(7)).await.unwrap();
+    match server.read().await.unwrap() {
+        Payload::Raw(payload) => assert_eq!(payload.to_vec(), vec![7, 0, 0, 0]),
+        _ => panic!("expected a raw payload"),
+    }
+}
+
+/// A negotiation failure is sent when no requested protocol is accepted
+#[tokio::test]
+async fn test_x224_server_negotiation_failure() {
+    let (server, client) = tokio::io::duplex(256);
+    let policy = SecurityPolicy {
+        accepted: vec![Protocols::ProtocolHybrid],
+    };
+
+    let (server, client) = tokio::join!(
+        X224Server::accept(TpktServer::new(server), &policy),
+        X224Client::connect(
+            TpktClient::new(client),
+            Protocols::ProtocolSSL as u32,
+            false,
+            None,
+            false,
+            false,
+            ConnectionRequestOptions::default()
+        )
+    );
+    assert_eq!(server.err().unwrap().kind(), ErrorKind::ConnectionRefused);
+    assert_eq!(client.err().unwrap().kind(), ErrorKind::ConnectionReset);
+}
+
+/// An unknown negotiation type in the confirm is an error, not a panic
+#[tokio::test]
+async fn test_x224_confirm_unknown_negotiation_type() {
+    let (client, mut server) = tokio::io::duplex(1024);
+    server
+        .write_all(&[
+            3, 0, 0, 19, 14, 0xD0, 0, 0, 0, 0, 0, 0x09, 0, 8, 0, 1, 0, 0, 0,
+        ])
+        .await
+        .unwrap();
+    let result = X224Client::connect(
+        TpktClient::new(client),
+        Protocols::ProtocolRDP as u32,
+        false,
+        None,
+        false,
+        false,
+        ConnectionRequestOptions::default(),
+    )
+    .await;
+    assert_eq!(
+        result.err().map(|e| e.kind()),
+        Some(std::io::ErrorKind::InvalidData)
+    );
+}
+
 // #[tokio::test]
 // async fn test_tpkt_client_response() {
 //     // let (mut server, mut client) = tokio::io::duplex(128);